@@ -27,6 +27,8 @@ fn create_hpc_config() -> MultiCoreConfig {
             load_balance_interval: 500,
             enable_cpu_affinity: true,
             enable_load_balancing: true,
+            enable_tracing: false,
+            enable_latency_tracking: false,
         },
         multicore_config: MulticoreConfig {
             max_cpus: 256,
@@ -44,6 +46,9 @@ fn create_hpc_config() -> MultiCoreConfig {
             cache_line_size: 64,
             enable_monitoring: true,
             monitoring_interval: 100,
+            enable_tickless: true,
+            tickless: TicklessConfig::default(),
+            isolated_cpus: 0,
         },
         performance_config: PerformanceConfig {
             enable_hardware_counters: true,
@@ -82,6 +87,8 @@ fn create_realtime_config() -> MultiCoreConfig {
             load_balance_interval: 1000, // Less frequent balancing
             enable_cpu_affinity: true,
             enable_load_balancing: true,
+            enable_tracing: true, // Capture wakeup latency traces
+            enable_latency_tracking: true, // Validate rt_deadline_us per priority class
         },
         multicore_config: MulticoreConfig {
             max_cpus: 64,
@@ -99,6 +106,8 @@ fn create_realtime_config() -> MultiCoreConfig {
             cache_line_size: 64,
             enable_monitoring: true,
             monitoring_interval: 50, // Higher frequency monitoring
+            enable_tickless: false, // Keep a fixed tick for determinism
+            isolated_cpus: 0,
         },
         performance_config: PerformanceConfig {
             enable_hardware_counters: true,
@@ -2,12 +2,52 @@
 //! 
 //! Supports USB audio devices like speakers, microphones, audio interfaces, and headphones.
 //! Implements USB Audio Class specifications for digital audio streaming and control.
+//!
+//! `AudioDevice` parses the UAC 1.0 or 2.0 descriptors of a configuration,
+//! negotiates an alternate setting and sample rate for each direction, and
+//! streams PCM through isochronous packets queued on an `AudioTransport`.
+//! Callers exchange PCM with the device through ring buffers, filled and
+//! drained by `service()`.
 
 use crate::*;
+use crate::host::xhci::{XhciController, XhciTransferHandle};
+use alloc::collections::VecDeque;
 
 #[cfg(feature = "std")]
 use std::collections::BTreeMap;
 
+/// Class-specific descriptor types
+pub const UAC_CS_INTERFACE: u8 = 0x24;
+pub const UAC_CS_ENDPOINT: u8 = 0x25;
+
+/// Audio interface subclasses
+pub const UAC_SUBCLASS_AUDIOCONTROL: u8 = 0x01;
+pub const UAC_SUBCLASS_AUDIOSTREAMING: u8 = 0x02;
+
+/// AudioControl interface descriptor subtypes
+const UAC_AC_HEADER: u8 = 0x01;
+const UAC_AC_INPUT_TERMINAL: u8 = 0x02;
+const UAC_AC_OUTPUT_TERMINAL: u8 = 0x03;
+const UAC_AC_FEATURE_UNIT: u8 = 0x06;
+const UAC2_AC_CLOCK_SOURCE: u8 = 0x0A;
+
+/// AudioStreaming interface descriptor subtypes
+const UAC_AS_GENERAL: u8 = 0x01;
+const UAC_AS_FORMAT_TYPE: u8 = 0x02;
+const UAC_FORMAT_TYPE_I: u8 = 0x01;
+
+/// Class-specific requests (UAC 1.0 SET_CUR, UAC 2.0 CUR and RANGE)
+const UAC_REQUEST_CUR: u8 = 0x01;
+const UAC2_REQUEST_RANGE: u8 = 0x02;
+/// Sampling frequency control selector, on the endpoint (UAC 1.0) or
+/// the clock source (UAC 2.0)
+const UAC_SAMPLING_FREQ_CONTROL: u16 = 0x01;
+
+/// Isochronous data packets kept queued ahead of the device
+pub const AUDIO_PACKETS_IN_FLIGHT: usize = 8;
+/// PCM ring buffer size in milliseconds of audio
+pub const AUDIO_RING_MS: usize = 200;
+
 /// Audio Class Version
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum AudioClassVersion {
@@ -182,7 +222,11 @@ pub struct AudioStreamFormat {
 }
 
 /// Audio Stream Interface
-#[derive(Debug)]
+///
+/// One alternate setting of an AudioStreaming interface. `formats` holds
+/// its Type I format with the highest supported rate; `sample_rates` lists
+/// every rate it accepts.
+#[derive(Debug, Clone)]
 pub struct AudioStreamInterface {
     pub interface_number: u8,
     pub alternate_setting: u8,
@@ -192,6 +236,96 @@ pub struct AudioStreamInterface {
     pub iso_endpoint_type: IsoEndpointType,
     pub delay: u8,
     pub active: bool,
+    /// Terminal this interface streams to or from
+    pub terminal_link: u8,
+    /// Bytes per sample slot, which may exceed the bit resolution
+    pub subslot_size: u8,
+    pub sample_rates: Vec<AudioRateRange>,
+    /// Isochronous synchronization type from the endpoint attributes
+    pub sync_type: IsoSyncType,
+    pub max_packet_size: u16,
+    pub interval: u8,
+    /// Explicit feedback endpoint of an asynchronous OUT endpoint
+    pub feedback_endpoint: Option<u8>,
+    pub feedback_interval: u8,
+    /// UAC 1.0 endpoint accepts a sampling frequency request
+    pub rate_control: bool,
+}
+
+impl AudioStreamInterface {
+    /// Whether this setting carries data from the device
+    pub fn is_capture(&self) -> bool {
+        self.endpoint_address & 0x80 != 0
+    }
+
+    /// Bytes one service interval may carry, including extra transactions
+    /// of high-bandwidth endpoints
+    pub fn max_payload(&self) -> usize {
+        let transactions = ((self.max_packet_size >> 11) & 0x3) as usize + 1;
+        (self.max_packet_size & 0x7FF) as usize * transactions
+    }
+
+    /// Supported rate closest to `rate`
+    pub fn nearest_rate(&self, rate: u32) -> Option<u32> {
+        self.sample_rates.iter()
+            .map(|range| range.nearest(rate))
+            .min_by_key(|&candidate| candidate.abs_diff(rate))
+    }
+}
+
+/// Sample rates accepted by a stream, a single rate when `min == max`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioRateRange {
+    pub min: u32,
+    pub max: u32,
+    /// Step between supported rates; zero for a continuous range
+    pub resolution: u32,
+}
+
+impl AudioRateRange {
+    pub fn discrete(rate: u32) -> Self {
+        Self { min: rate, max: rate, resolution: 0 }
+    }
+
+    /// Supported rate of this range closest to `rate`
+    pub fn nearest(&self, rate: u32) -> u32 {
+        let clamped = rate.clamp(self.min, self.max);
+        if self.resolution == 0 {
+            return clamped;
+        }
+        let steps = (clamped - self.min + self.resolution / 2) / self.resolution;
+        (self.min + steps * self.resolution).min(self.max)
+    }
+}
+
+/// Isochronous endpoint synchronization types
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IsoSyncType {
+    None = 0x00,
+    Asynchronous = 0x01,
+    Adaptive = 0x02,
+    Synchronous = 0x03,
+}
+
+impl IsoSyncType {
+    pub fn from_u8(value: u8) -> Self {
+        match value & 0x03 {
+            0x01 => IsoSyncType::Asynchronous,
+            0x02 => IsoSyncType::Adaptive,
+            0x03 => IsoSyncType::Synchronous,
+            _ => IsoSyncType::None,
+        }
+    }
+}
+
+/// Input or output terminal of an audio function
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioTerminal {
+    pub id: u8,
+    pub terminal_type: u16,
+    pub input: bool,
+    /// Clock source of the terminal (UAC 2.0)
+    pub clock_source: u8,
 }
 
 /// ISO Endpoint Types
@@ -218,6 +352,10 @@ pub struct AudioDeviceInfo {
     pub bit_depth: u8,
     pub channels: u8,
     pub sync_endpoint_address: Option<u8>,
+    /// AudioControl interface number
+    pub control_interface: u8,
+    pub terminals: Vec<AudioTerminal>,
+    pub clock_sources: Vec<u8>,
 }
 
 /// Audio Data Block
@@ -232,6 +370,8 @@ pub struct AudioDataBlock {
 }
 
 /// Audio Buffer
+///
+/// Byte ring shared between the PCM API and the isochronous stream.
 #[derive(Debug)]
 pub struct AudioBuffer {
     pub data: Vec<u8>,
@@ -242,6 +382,70 @@ pub struct AudioBuffer {
     pub empty: bool,
 }
 
+impl AudioBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            data: vec![0u8; capacity],
+            capacity,
+            write_position: 0,
+            read_position: 0,
+            filled: false,
+            empty: true,
+        }
+    }
+
+    /// Bytes waiting to be read
+    pub fn available(&self) -> usize {
+        if self.filled {
+            self.capacity
+        } else if self.capacity == 0 {
+            0
+        } else {
+            (self.write_position + self.capacity - self.read_position) % self.capacity
+        }
+    }
+
+    /// Bytes that can be written before the buffer is full
+    pub fn space(&self) -> usize {
+        self.capacity - self.available()
+    }
+
+    /// Append up to `data.len()` bytes; returns the number written
+    pub fn write(&mut self, data: &[u8]) -> usize {
+        let count = data.len().min(self.space());
+        let first = count.min(self.capacity - self.write_position);
+        self.data[self.write_position..self.write_position + first].copy_from_slice(&data[..first]);
+        self.data[..count - first].copy_from_slice(&data[first..count]);
+        if count > 0 {
+            self.write_position = (self.write_position + count) % self.capacity;
+            self.empty = false;
+            self.filled = self.write_position == self.read_position;
+        }
+        count
+    }
+
+    /// Remove up to `out.len()` bytes; returns the number read
+    pub fn read(&mut self, out: &mut [u8]) -> usize {
+        let count = out.len().min(self.available());
+        let first = count.min(self.capacity - self.read_position);
+        out[..first].copy_from_slice(&self.data[self.read_position..self.read_position + first]);
+        out[first..count].copy_from_slice(&self.data[..count - first]);
+        if count > 0 {
+            self.read_position = (self.read_position + count) % self.capacity;
+            self.filled = false;
+            self.empty = self.read_position == self.write_position;
+        }
+        count
+    }
+
+    pub fn clear(&mut self) {
+        self.write_position = 0;
+        self.read_position = 0;
+        self.filled = false;
+        self.empty = true;
+    }
+}
+
 /// Audio Stream State
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum AudioStreamState {
@@ -290,6 +494,9 @@ impl AudioDriver {
                 bit_depth: 16,
                 channels: 2,
                 sync_endpoint_address: None,
+                control_interface: 0,
+                terminals: Vec::new(),
+                clock_sources: Vec::new(),
             },
             stream_state: AudioStreamState::Stopped,
             current_format: None,
@@ -339,10 +546,24 @@ impl AudioDriver {
         Ok(())
     }
 
+    /// Load the audio function from a raw configuration descriptor
+    pub fn load_descriptors(&mut self, configuration: &[u8]) -> UsbResult<()> {
+        let vendor_id = self.device_info.vendor_id;
+        let product_id = self.device_info.product_id;
+        self.device_info = parse_audio_function(configuration)?;
+        self.device_info.vendor_id = vendor_id;
+        self.device_info.product_id = product_id;
+        Ok(())
+    }
+
     /// Discover audio stream interfaces
     fn discover_stream_interfaces(&mut self) -> UsbResult<()> {
-        // This would parse USB descriptors to find audio stream interfaces
-        // For now, create a default stereo interface
+        if !self.device_info.stream_interfaces.is_empty() {
+            return Ok(());
+        }
+
+        // Without descriptors from `load_descriptors()`, assume a plain
+        // stereo interface
         let default_format = AudioStreamFormat {
             format_type: AudioDataFormat::PCM,
             channels: 2,
@@ -361,6 +582,15 @@ impl AudioDriver {
             iso_endpoint_type: IsoEndpointType::Data,
             delay: 0,
             active: false,
+            terminal_link: 0,
+            subslot_size: 2,
+            sample_rates: vec![AudioRateRange::discrete(44100)],
+            sync_type: IsoSyncType::Adaptive,
+            max_packet_size: 192,
+            interval: 1,
+            feedback_endpoint: None,
+            feedback_interval: 1,
+            rate_control: false,
         };
 
         self.device_info.stream_interfaces.push(stream_interface);
@@ -381,23 +611,9 @@ impl AudioDriver {
                                (self.device_info.bit_depth as usize / 8);
         let buffer_size = bytes_per_second;
 
-        self.audio_buffer = AudioBuffer {
-            data: vec![0u8; buffer_size],
-            capacity: buffer_size,
-            write_position: 0,
-            read_position: 0,
-            filled: false,
-            empty: true,
-        };
-
-        self.sync_endpoint_buffer = AudioBuffer {
-            data: vec![0u8; 256], // Small buffer for sync data
-            capacity: 256,
-            write_position: 0,
-            read_position: 0,
-            filled: false,
-            empty: true,
-        };
+        self.audio_buffer = AudioBuffer::new(buffer_size);
+        // Small buffer for sync data
+        self.sync_endpoint_buffer = AudioBuffer::new(256);
 
         log::info!("Audio buffer initialized: {} bytes", buffer_size);
         Ok(())
@@ -438,8 +654,7 @@ impl AudioDriver {
         }
 
         self.stream_state = AudioStreamState::Playing;
-        self.audio_buffer.empty = true;
-        self.audio_buffer.filled = false;
+        self.audio_buffer.clear();
 
         log::info!("Started audio playback");
         Ok(())
@@ -452,8 +667,7 @@ impl AudioDriver {
         }
 
         self.stream_state = AudioStreamState::Recording;
-        self.audio_buffer.empty = true;
-        self.audio_buffer.filled = false;
+        self.audio_buffer.clear();
 
         log::info!("Started audio recording");
         Ok(())
@@ -462,8 +676,7 @@ impl AudioDriver {
     /// Stop audio streaming
     pub fn stop_streaming(&mut self) -> UsbResult<()> {
         self.stream_state = AudioStreamState::Stopped;
-        self.audio_buffer.empty = true;
-        self.audio_buffer.filled = false;
+        self.audio_buffer.clear();
 
         log::info!("Stopped audio streaming");
         Ok(())
@@ -475,40 +688,11 @@ impl AudioDriver {
             return Err(UsbDriverError::InvalidConfiguration);
         }
 
-        let format = match &self.current_format {
-            Some(f) => f,
-            None => return Err(UsbDriverError::InvalidConfiguration),
-        };
-
-        // Calculate how much data can be written
-        let bytes_available = self.audio_buffer.capacity - 
-                              ((self.audio_buffer.write_position - self.audio_buffer.read_position + 
-                                self.audio_buffer.capacity) % self.audio_buffer.capacity);
-
-        let bytes_to_write = data.len().min(bytes_available);
-        if bytes_to_write == 0 {
-            return Ok(0);
-        }
-
-        // Write data in two parts if it wraps around the buffer
-        if self.audio_buffer.write_position + bytes_to_write > self.audio_buffer.capacity {
-            let first_part = self.audio_buffer.capacity - self.audio_buffer.write_position;
-            let second_part = bytes_to_write - first_part;
-
-            self.audio_buffer.data[self.audio_buffer.write_position..].copy_from_slice(&data[..first_part]);
-            self.audio_buffer.data[..second_part].copy_from_slice(&data[first_part..]);
-
-            self.audio_buffer.write_position = second_part;
-        } else {
-            self.audio_buffer.data[self.audio_buffer.write_position..self.audio_buffer.write_position + bytes_to_write]
-                .copy_from_slice(&data);
-            self.audio_buffer.write_position += bytes_to_write;
+        if self.current_format.is_none() {
+            return Err(UsbDriverError::InvalidConfiguration);
         }
 
-        self.audio_buffer.empty = false;
-        if self.audio_buffer.write_position == self.audio_buffer.read_position {
-            self.audio_buffer.filled = true;
-        }
+        let bytes_to_write = self.audio_buffer.write(data);
 
         log::debug!("Wrote {} bytes to audio buffer", bytes_to_write);
         Ok(bytes_to_write)
@@ -520,39 +704,7 @@ impl AudioDriver {
             return Err(UsbDriverError::InvalidConfiguration);
         }
 
-        // Calculate how much data is available
-        let bytes_available = if self.audio_buffer.filled {
-            self.audio_buffer.capacity
-        } else {
-            (self.audio_buffer.write_position - self.audio_buffer.read_position + 
-             self.audio_buffer.capacity) % self.audio_buffer.capacity
-        };
-
-        let bytes_to_read = buffer.len().min(bytes_available);
-        if bytes_to_read == 0 {
-            return Ok(0);
-        }
-
-        // Read data in two parts if it wraps around the buffer
-        if self.audio_buffer.read_position + bytes_to_read > self.audio_buffer.capacity {
-            let first_part = self.audio_buffer.capacity - self.audio_buffer.read_position;
-            let second_part = bytes_to_read - first_part;
-
-            buffer[..first_part].copy_from_slice(&self.audio_buffer.data[self.audio_buffer.read_position..]);
-            buffer[first_part..].copy_from_slice(&self.audio_buffer.data[..second_part]);
-
-            self.audio_buffer.read_position = second_part;
-        } else {
-            buffer[..bytes_to_read].copy_from_slice(
-                &self.audio_buffer.data[self.audio_buffer.read_position..self.audio_buffer.read_position + bytes_to_read]
-            );
-            self.audio_buffer.read_position += bytes_to_read;
-        }
-
-        self.audio_buffer.filled = false;
-        if self.audio_buffer.read_position == self.audio_buffer.write_position {
-            self.audio_buffer.empty = true;
-        }
+        let bytes_to_read = self.audio_buffer.read(buffer);
 
         log::debug!("Read {} bytes from audio buffer", bytes_to_read);
         Ok(bytes_to_read)
@@ -702,14 +854,7 @@ impl AudioDriver {
 
     /// Get audio buffer status
     pub fn get_buffer_status(&self) -> (usize, usize) {
-        let bytes_used = if self.audio_buffer.filled {
-            self.audio_buffer.capacity
-        } else {
-            (self.audio_buffer.write_position - self.audio_buffer.read_position + 
-             self.audio_buffer.capacity) % self.audio_buffer.capacity
-        };
-
-        (bytes_used, self.audio_buffer.capacity)
+        (self.audio_buffer.available(), self.audio_buffer.capacity)
     }
 
     /// Set endpoints
@@ -728,6 +873,760 @@ impl AudioDriver {
     }
 }
 
+/// Direction of an audio stream, seen from the host
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioDirection {
+    Playback,
+    Capture,
+}
+
+/// Parse the audio function of a raw configuration descriptor
+///
+/// Collects the AudioControl terminals, feature units and clock sources and
+/// one `AudioStreamInterface` per streaming alternate setting. UAC 2.0
+/// devices report their rates through clock source RANGE requests rather
+/// than descriptors, so their settings come back with empty `sample_rates`.
+pub fn parse_audio_function(configuration: &[u8]) -> UsbResult<AudioDeviceInfo> {
+    let mut info = AudioDeviceInfo {
+        vendor_id: 0,
+        product_id: 0,
+        class_version: AudioClassVersion::Unknown,
+        stream_interface_count: 0,
+        stream_interfaces: Vec::new(),
+        terminal_types: Vec::new(),
+        processing_units: Vec::new(),
+        feature_units: Vec::new(),
+        endpoint_controls: Vec::new(),
+        sample_rate: 0,
+        bit_depth: 0,
+        channels: 0,
+        sync_endpoint_address: None,
+        control_interface: 0,
+        terminals: Vec::new(),
+        clock_sources: Vec::new(),
+    };
+    let mut uac2 = false;
+    let mut subclass = 0u8;
+    let mut stream: Option<AudioStreamInterface> = None;
+    let mut offset = 0;
+
+    while offset + 2 <= configuration.len() {
+        let length = configuration[offset] as usize;
+        if length < 2 || offset + length > configuration.len() {
+            return Err(UsbDriverError::InvalidConfiguration);
+        }
+        let d = &configuration[offset..offset + length];
+        offset += length;
+
+        match d[1] {
+            t if t == UsbDescriptorType::Interface as u8 && length >= 9 => {
+                if let Some(done) = stream.take() {
+                    push_stream_interface(&mut info, done);
+                }
+                subclass = if d[5] == UsbClass::Audio as u8 { d[6] } else { 0 };
+                if subclass == UAC_SUBCLASS_AUDIOCONTROL {
+                    info.control_interface = d[2];
+                    uac2 = d[7] == 0x20;
+                } else if subclass == UAC_SUBCLASS_AUDIOSTREAMING && d[3] != 0 {
+                    stream = Some(AudioStreamInterface {
+                        interface_number: d[2],
+                        alternate_setting: d[3],
+                        format_count: 0,
+                        formats: Vec::new(),
+                        endpoint_address: 0,
+                        iso_endpoint_type: IsoEndpointType::Data,
+                        delay: 0,
+                        active: false,
+                        terminal_link: 0,
+                        subslot_size: 0,
+                        sample_rates: Vec::new(),
+                        sync_type: IsoSyncType::None,
+                        max_packet_size: 0,
+                        interval: 1,
+                        feedback_endpoint: None,
+                        feedback_interval: 1,
+                        rate_control: false,
+                    });
+                }
+            }
+            UAC_CS_INTERFACE if subclass == UAC_SUBCLASS_AUDIOCONTROL && length >= 3 => {
+                parse_control_descriptor(&mut info, d, uac2);
+            }
+            UAC_CS_INTERFACE if length >= 3 => {
+                if let Some(stream) = stream.as_mut() {
+                    parse_streaming_descriptor(stream, d, uac2);
+                }
+            }
+            t if t == UsbDescriptorType::Endpoint as u8 && length >= 7 => {
+                let Some(stream) = stream.as_mut() else { continue };
+                if d[3] & 0x03 != UsbTransferType::Isochronous as u8 {
+                    continue;
+                }
+                let max_packet_size = u16::from_le_bytes([d[4], d[5]]);
+                // UAC 1.0 sync endpoints predate the usage bits and are
+                // only known through the data endpoint's bSynchAddress
+                let usage = if stream.endpoint_address != 0 && stream.feedback_endpoint == Some(d[2]) {
+                    0x01
+                } else {
+                    (d[3] >> 4) & 0x03
+                };
+                match usage {
+                    0x01 => {
+                        stream.feedback_endpoint = Some(d[2]);
+                        stream.feedback_interval = d[6];
+                        info.sync_endpoint_address = Some(d[2]);
+                    }
+                    usage => {
+                        stream.endpoint_address = d[2];
+                        stream.max_packet_size = max_packet_size;
+                        stream.interval = d[6];
+                        stream.sync_type = IsoSyncType::from_u8(d[3] >> 2);
+                        if usage == 0x02 {
+                            stream.iso_endpoint_type = IsoEndpointType::Implicit;
+                        }
+                        // UAC 1.0 names the feedback endpoint in bSynchAddress
+                        if length >= 9 && d[8] != 0 {
+                            stream.feedback_endpoint = Some(d[8] | 0x80);
+                        }
+                    }
+                }
+            }
+            UAC_CS_ENDPOINT if length >= 4 => {
+                // UAC 1.0 EP_GENERAL bit 0: sampling frequency control
+                if let Some(stream) = stream.as_mut() {
+                    if !uac2 && d[2] == UAC_AS_GENERAL {
+                        stream.rate_control = d[3] & 0x01 != 0;
+                        info.endpoint_controls.push(d[3]);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    if let Some(done) = stream.take() {
+        push_stream_interface(&mut info, done);
+    }
+
+    if info.stream_interfaces.is_empty() {
+        return Err(UsbDriverError::UnsupportedFeature);
+    }
+    if uac2 {
+        info.class_version = AudioClassVersion::Version2;
+    }
+    info.stream_interface_count = info.stream_interfaces.len() as u8;
+    Ok(info)
+}
+
+fn parse_control_descriptor(info: &mut AudioDeviceInfo, d: &[u8], uac2: bool) {
+    match d[2] {
+        UAC_AC_HEADER if d.len() >= 5 => {
+            info.class_version = match u16::from_le_bytes([d[3], d[4]]) {
+                0x0100 => AudioClassVersion::Version1,
+                0x0200 => AudioClassVersion::Version2,
+                0x0300 => AudioClassVersion::Version3,
+                _ => AudioClassVersion::Unknown,
+            };
+        }
+        UAC_AC_INPUT_TERMINAL | UAC_AC_OUTPUT_TERMINAL if d.len() >= 8 => {
+            let input = d[2] == UAC_AC_INPUT_TERMINAL;
+            let terminal_type = u16::from_le_bytes([d[4], d[5]]);
+            // bCSourceID follows bAssocTerminal on input terminals and
+            // bSourceID on output terminals
+            let clock_index = if input { 7 } else { 8 };
+            let clock_source = if uac2 { d.get(clock_index).copied().unwrap_or(0) } else { 0 };
+            info.terminals.push(AudioTerminal { id: d[3], terminal_type, input, clock_source });
+            info.terminal_types.push(AudioTerminalType::from_u16(terminal_type));
+        }
+        UAC_AC_FEATURE_UNIT if d.len() >= 4 => info.feature_units.push(d[3]),
+        UAC2_AC_CLOCK_SOURCE if uac2 && d.len() >= 4 => info.clock_sources.push(d[3]),
+        _ => {}
+    }
+}
+
+fn parse_streaming_descriptor(stream: &mut AudioStreamInterface, d: &[u8], uac2: bool) {
+    match d[2] {
+        UAC_AS_GENERAL if uac2 && d.len() >= 11 => {
+            // bmFormats bit n stands for format tag n + 1
+            let formats = u32::from_le_bytes([d[6], d[7], d[8], d[9]]);
+            let format_type = match formats.trailing_zeros() {
+                32 => AudioDataFormat::Undefined,
+                bit => AudioDataFormat::from_u8(bit as u8 + 1),
+            };
+            stream.terminal_link = d[3];
+            stream.formats.push(AudioStreamFormat {
+                format_type,
+                channels: d[10],
+                bits_per_sample: 0,
+                sample_rate: 0,
+                bit_rate: 0,
+                sync_frames: 0,
+            });
+        }
+        UAC_AS_GENERAL if d.len() >= 7 => {
+            stream.terminal_link = d[3];
+            stream.delay = d[4];
+            let tag = u16::from_le_bytes([d[5], d[6]]);
+            stream.formats.push(AudioStreamFormat {
+                format_type: AudioDataFormat::from_u8(tag as u8),
+                channels: 0,
+                bits_per_sample: 0,
+                sample_rate: 0,
+                bit_rate: 0,
+                sync_frames: 0,
+            });
+        }
+        UAC_AS_FORMAT_TYPE if d.len() >= 6 && d[3] == UAC_FORMAT_TYPE_I => {
+            let Some(format) = stream.formats.last_mut() else { return };
+            if uac2 {
+                stream.subslot_size = d[4];
+                format.bits_per_sample = d[5];
+                return;
+            }
+            if d.len() < 8 {
+                return;
+            }
+            format.channels = d[4];
+            stream.subslot_size = d[5];
+            format.bits_per_sample = d[6];
+            let rate = |i: usize| d.get(8 + i * 3..11 + i * 3)
+                .map(|b| u32::from_le_bytes([b[0], b[1], b[2], 0]));
+            if d[7] == 0 {
+                if let (Some(min), Some(max)) = (rate(0), rate(1)) {
+                    stream.sample_rates.push(AudioRateRange { min, max, resolution: 0 });
+                }
+            } else {
+                stream.sample_rates.extend((0..d[7] as usize).filter_map(rate).map(AudioRateRange::discrete));
+            }
+        }
+        _ => {}
+    }
+}
+
+fn push_stream_interface(info: &mut AudioDeviceInfo, mut stream: AudioStreamInterface) {
+    if stream.endpoint_address == 0 || stream.formats.is_empty() {
+        return;
+    }
+    let max_rate = stream.sample_rates.iter().map(|range| range.max).max().unwrap_or(0);
+    for format in &mut stream.formats {
+        format.sample_rate = max_rate;
+        format.bit_rate = max_rate * format.channels as u32 * format.bits_per_sample as u32;
+    }
+    stream.format_count = stream.formats.len() as u8;
+    if let Some(format) = stream.formats.first() {
+        if format.channels > info.channels {
+            info.channels = format.channels;
+            info.bit_depth = format.bits_per_sample;
+            info.sample_rate = format.sample_rate;
+        }
+    }
+    info.stream_interfaces.push(stream);
+}
+
+/// Completed isochronous packet
+#[derive(Debug, Clone)]
+pub struct AudioPacketResult {
+    pub status: UsbTransferStatus,
+    pub actual_length: usize,
+    /// Received bytes of an IN packet
+    pub data: Vec<u8>,
+}
+
+/// Transfers an audio device needs from its host controller
+pub trait AudioTransport {
+    /// Control transfer on the default pipe; returns bytes moved
+    fn control(&mut self, setup: &UsbSetupPacket, data: &mut [u8]) -> UsbResult<usize>;
+
+    /// Set up the isochronous endpoints of a newly selected alternate setting
+    fn configure_endpoints(&mut self, endpoints: &[UsbEndpointDescriptor]) -> UsbResult<()>;
+
+    /// Queue one packet for the service interval after those already
+    /// queued; IN packets receive up to `data.len()` bytes. Returns a token
+    /// for `poll_isoch()`.
+    fn submit_isoch(&mut self, endpoint: u8, data: &[u8]) -> UsbResult<u64>;
+
+    /// Result of a queued packet, or `None` while it is pending
+    fn poll_isoch(&mut self, token: u64) -> Option<AudioPacketResult>;
+}
+
+/// Isochronous audio transport over a device slot of an xHCI controller
+pub struct XhciAudioTransport<'a> {
+    controller: &'a mut XhciController,
+    slot_id: u8,
+}
+
+impl<'a> XhciAudioTransport<'a> {
+    pub fn new(controller: &'a mut XhciController, slot_id: u8) -> Self {
+        Self { controller, slot_id }
+    }
+
+    pub fn slot_id(&self) -> u8 {
+        self.slot_id
+    }
+}
+
+impl<'a> AudioTransport for XhciAudioTransport<'a> {
+    fn control(&mut self, setup: &UsbSetupPacket, data: &mut [u8]) -> UsbResult<usize> {
+        self.controller.control_transfer(self.slot_id, setup, data)
+    }
+
+    fn configure_endpoints(&mut self, endpoints: &[UsbEndpointDescriptor]) -> UsbResult<()> {
+        self.controller.configure_endpoints(self.slot_id, endpoints)
+    }
+
+    fn submit_isoch(&mut self, endpoint: u8, data: &[u8]) -> UsbResult<u64> {
+        self.controller.submit_isoch(self.slot_id, endpoint, data, None).map(|handle| handle.0)
+    }
+
+    fn poll_isoch(&mut self, token: u64) -> Option<AudioPacketResult> {
+        let handle = XhciTransferHandle(token);
+        let result = match self.controller.take_transfer(handle) {
+            Some(result) => result,
+            None => {
+                self.controller.poll_transfers();
+                self.controller.take_transfer(handle)?
+            }
+        };
+        Some(AudioPacketResult {
+            status: result.status,
+            actual_length: result.actual_length,
+            data: result.data,
+        })
+    }
+}
+
+/// Counters of one audio stream
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AudioStreamStats {
+    pub packets_submitted: u64,
+    pub packets_completed: u64,
+    pub packet_errors: u64,
+    pub bytes_transferred: u64,
+    /// Playback packets padded with silence for lack of PCM
+    pub underruns: u32,
+    /// Capture bytes dropped because the ring was full
+    pub overruns: u32,
+    pub feedback_updates: u32,
+}
+
+/// Open stream of one direction
+struct AudioStream {
+    setting: AudioStreamInterface,
+    format: AudioStreamFormat,
+    frame_bytes: usize,
+    ring: AudioBuffer,
+    /// Nominal frames per service interval, 16.16 fixed point
+    nominal_frames: u32,
+    /// Frames per service interval in use, adjusted by explicit feedback
+    frames_per_packet: u32,
+    accumulator: u32,
+    inflight: VecDeque<u64>,
+    feedback_inflight: Option<u64>,
+    running: bool,
+    stats: AudioStreamStats,
+}
+
+impl AudioStream {
+    /// Frames to send in the next playback packet
+    fn next_packet_frames(&mut self) -> usize {
+        self.accumulator += self.frames_per_packet;
+        let frames = self.accumulator >> 16;
+        self.accumulator &= 0xFFFF;
+        frames as usize
+    }
+
+    fn silence(&self) -> u8 {
+        if self.format.format_type == AudioDataFormat::PCM8 { 0x80 } else { 0x00 }
+    }
+}
+
+/// USB audio function streaming PCM over isochronous endpoints
+///
+/// `open_stream()` picks the alternate setting closest to the requested
+/// format and programs its sample rate. PCM moves through per-direction
+/// rings with `write_pcm()` and `read_pcm()`; `service()` must run at least
+/// every `AUDIO_PACKETS_IN_FLIGHT` service intervals to keep packets queued.
+pub struct AudioDevice<T: AudioTransport> {
+    transport: T,
+    speed: UsbSpeed,
+    info: AudioDeviceInfo,
+    playback: Option<AudioStream>,
+    capture: Option<AudioStream>,
+}
+
+impl<T: AudioTransport> AudioDevice<T> {
+    /// Bind to the audio function described by a raw configuration descriptor
+    pub fn new(transport: T, speed: UsbSpeed, configuration: &[u8]) -> UsbResult<Self> {
+        let info = parse_audio_function(configuration)?;
+        log::info!("USB audio function: {:?}, {} streaming settings",
+                   info.class_version, info.stream_interface_count);
+        Ok(Self { transport, speed, info, playback: None, capture: None })
+    }
+
+    pub fn info(&self) -> &AudioDeviceInfo {
+        &self.info
+    }
+
+    pub fn transport(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    /// Negotiated format of an open stream
+    pub fn format(&self, direction: AudioDirection) -> Option<&AudioStreamFormat> {
+        self.stream(direction).map(|stream| &stream.format)
+    }
+
+    pub fn stream_stats(&self, direction: AudioDirection) -> Option<AudioStreamStats> {
+        self.stream(direction).map(|stream| stream.stats)
+    }
+
+    /// Select the setting closest to `wanted` and program its sample rate
+    ///
+    /// Format type comes first, then the sample rate, channel count and bit
+    /// depth. Returns the format actually in use.
+    pub fn open_stream(&mut self, direction: AudioDirection, wanted: &AudioStreamFormat) -> UsbResult<AudioStreamFormat> {
+        let capture = direction == AudioDirection::Capture;
+        if self.info.class_version == AudioClassVersion::Version2 {
+            self.query_clock_rates(capture)?;
+        }
+        if self.stream(direction).is_some() {
+            self.close_stream(direction)?;
+        }
+
+        let speed = self.speed;
+        let (setting, rate) = self.info.stream_interfaces.iter()
+            .filter(|setting| setting.is_capture() == capture && !setting.formats.is_empty())
+            .filter_map(|setting| {
+                let rate = setting.nearest_rate(wanted.sample_rate)?;
+                let format = &setting.formats[0];
+                let frame_bytes = setting.subslot_size as usize * format.channels as usize;
+                let fits = frame_bytes > 0 && rate > 0
+                    && max_packet_frames(speed, setting.interval, rate) * frame_bytes <= setting.max_payload();
+                fits.then_some((setting, rate))
+            })
+            .min_by_key(|(setting, rate)| {
+                let format = &setting.formats[0];
+                (format.format_type != wanted.format_type,
+                 rate.abs_diff(wanted.sample_rate),
+                 format.channels.abs_diff(wanted.channels),
+                 format.bits_per_sample.abs_diff(wanted.bits_per_sample))
+            })
+            .ok_or(UsbDriverError::UnsupportedFeature)?;
+        let setting = setting.clone();
+
+        let mut format = setting.formats[0].clone();
+        format.sample_rate = rate;
+        format.bit_rate = rate * format.channels as u32 * format.bits_per_sample as u32;
+        let frame_bytes = setting.subslot_size as usize * format.channels as usize;
+
+        self.set_interface(setting.interface_number, setting.alternate_setting)?;
+        self.set_sample_rate(&setting, rate)?;
+
+        let mut endpoints = vec![UsbEndpointDescriptor {
+            bLength: 7,
+            bDescriptorType: UsbDescriptorType::Endpoint as u8,
+            bEndpointAddress: setting.endpoint_address,
+            bmAttributes: UsbTransferType::Isochronous as u8 | (setting.sync_type as u8) << 2,
+            wMaxPacketSize: setting.max_packet_size,
+            bInterval: setting.interval,
+        }];
+        if let Some(feedback) = setting.feedback_endpoint {
+            endpoints.push(UsbEndpointDescriptor {
+                bLength: 7,
+                bDescriptorType: UsbDescriptorType::Endpoint as u8,
+                bEndpointAddress: feedback,
+                bmAttributes: UsbTransferType::Isochronous as u8 | 0x10,
+                wMaxPacketSize: 4,
+                bInterval: setting.feedback_interval,
+            });
+        }
+        self.transport.configure_endpoints(&endpoints)?;
+
+        let ring_frames = (rate as usize * AUDIO_RING_MS / 1000).max(1);
+        let nominal_frames = (((rate as u64) << 16) / packets_per_second(speed, setting.interval) as u64) as u32;
+        for candidate in &mut self.info.stream_interfaces {
+            if candidate.interface_number == setting.interface_number {
+                candidate.active = candidate.alternate_setting == setting.alternate_setting;
+            }
+        }
+        log::info!("Opened {:?} stream on interface {} alt {}: {} Hz, {} bit, {} channels",
+                   direction, setting.interface_number, setting.alternate_setting,
+                   rate, format.bits_per_sample, format.channels);
+
+        let stream = AudioStream {
+            setting,
+            format: format.clone(),
+            frame_bytes,
+            ring: AudioBuffer::new(ring_frames * frame_bytes),
+            nominal_frames,
+            frames_per_packet: nominal_frames,
+            accumulator: 0,
+            inflight: VecDeque::new(),
+            feedback_inflight: None,
+            running: false,
+            stats: AudioStreamStats::default(),
+        };
+        *self.stream_slot(direction) = Some(stream);
+        Ok(format)
+    }
+
+    /// Drop a stream and return its interface to the zero-bandwidth setting
+    pub fn close_stream(&mut self, direction: AudioDirection) -> UsbResult<()> {
+        let Some(stream) = self.stream_slot(direction).take() else { return Ok(()) };
+        let interface = stream.setting.interface_number;
+        for setting in &mut self.info.stream_interfaces {
+            if setting.interface_number == interface {
+                setting.active = false;
+            }
+        }
+        self.set_interface(interface, 0)
+    }
+
+    /// Start queueing packets; playback sends whatever the ring holds
+    pub fn start(&mut self, direction: AudioDirection) -> UsbResult<()> {
+        let stream = self.stream_slot(direction).as_mut().ok_or(UsbDriverError::InvalidConfiguration)?;
+        stream.running = true;
+        stream.accumulator = 0;
+        self.service()
+    }
+
+    /// Stop queueing packets and discard buffered PCM; packets already
+    /// queued are still reaped by `service()`
+    pub fn stop(&mut self, direction: AudioDirection) -> UsbResult<()> {
+        let stream = self.stream_slot(direction).as_mut().ok_or(UsbDriverError::InvalidConfiguration)?;
+        stream.running = false;
+        stream.ring.clear();
+        stream.frames_per_packet = stream.nominal_frames;
+        Ok(())
+    }
+
+    pub fn is_running(&self, direction: AudioDirection) -> bool {
+        self.stream(direction).map_or(false, |stream| stream.running)
+    }
+
+    /// Queue playback PCM; returns the bytes accepted, whole frames only
+    pub fn write_pcm(&mut self, data: &[u8]) -> UsbResult<usize> {
+        let stream = self.playback.as_mut().ok_or(UsbDriverError::InvalidConfiguration)?;
+        let count = data.len().min(stream.ring.space());
+        Ok(stream.ring.write(&data[..count - count % stream.frame_bytes]))
+    }
+
+    /// Take captured PCM; returns the bytes read, whole frames only
+    pub fn read_pcm(&mut self, out: &mut [u8]) -> UsbResult<usize> {
+        let stream = self.capture.as_mut().ok_or(UsbDriverError::InvalidConfiguration)?;
+        let count = out.len().min(stream.ring.available());
+        Ok(stream.ring.read(&mut out[..count - count % stream.frame_bytes]))
+    }
+
+    /// Bytes queued for playback or waiting to be read from capture
+    pub fn pcm_available(&self, direction: AudioDirection) -> usize {
+        self.stream(direction).map_or(0, |stream| stream.ring.available())
+    }
+
+    /// Reap completed packets in order and keep running streams topped up
+    pub fn service(&mut self) -> UsbResult<()> {
+        let speed = self.speed;
+        for direction in [AudioDirection::Playback, AudioDirection::Capture] {
+            let Some(stream) = (match direction {
+                AudioDirection::Playback => self.playback.as_mut(),
+                AudioDirection::Capture => self.capture.as_mut(),
+            }) else { continue };
+            Self::reap(&mut self.transport, stream, speed);
+            if stream.running {
+                Self::top_up(&mut self.transport, stream)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn reap(transport: &mut T, stream: &mut AudioStream, speed: UsbSpeed) {
+        while let Some(&token) = stream.inflight.front() {
+            let Some(result) = transport.poll_isoch(token) else { break };
+            stream.inflight.pop_front();
+            stream.stats.packets_completed += 1;
+            if !matches!(result.status, UsbTransferStatus::Success | UsbTransferStatus::ShortPacket) {
+                stream.stats.packet_errors += 1;
+                continue;
+            }
+            stream.stats.bytes_transferred += result.actual_length as u64;
+            if stream.setting.is_capture() && stream.running {
+                let received = &result.data[..result.actual_length.min(result.data.len())];
+                if stream.ring.write(received) < received.len() {
+                    stream.stats.overruns += 1;
+                }
+            }
+        }
+
+        if let Some(token) = stream.feedback_inflight {
+            if let Some(result) = transport.poll_isoch(token) {
+                stream.feedback_inflight = None;
+                if result.status == UsbTransferStatus::Success {
+                    Self::apply_feedback(stream, speed, &result.data[..result.actual_length.min(result.data.len())]);
+                }
+            }
+        }
+    }
+
+    /// Adopt the device's rate from an explicit feedback packet: 10.14
+    /// frames per frame in three bytes at full speed, 16.16 frames per
+    /// microframe in four bytes at high speed
+    fn apply_feedback(stream: &mut AudioStream, speed: UsbSpeed, data: &[u8]) {
+        let per_interval = match *data {
+            [a, b, c] => u32::from_le_bytes([a, b, c, 0]) << 2,
+            [a, b, c, d, ..] => u32::from_le_bytes([a, b, c, d]),
+            _ => return,
+        };
+        let intervals = packets_per_second(speed, 1) / packets_per_second(speed, stream.setting.interval);
+        let frames = per_interval.saturating_mul(intervals);
+        // Ignore values more than a quarter away from the nominal rate
+        let tolerance = stream.nominal_frames / 4;
+        if frames.abs_diff(stream.nominal_frames) <= tolerance {
+            stream.frames_per_packet = frames;
+            stream.stats.feedback_updates += 1;
+        }
+    }
+
+    fn top_up(transport: &mut T, stream: &mut AudioStream) -> UsbResult<()> {
+        let capture = stream.setting.is_capture();
+        while stream.inflight.len() < AUDIO_PACKETS_IN_FLIGHT {
+            let packet = if capture {
+                vec![0u8; stream.setting.max_payload()]
+            } else {
+                let bytes = (stream.next_packet_frames() * stream.frame_bytes).min(stream.setting.max_payload());
+                let mut packet = vec![stream.silence(); bytes];
+                if stream.ring.read(&mut packet) < bytes {
+                    stream.stats.underruns += 1;
+                }
+                packet
+            };
+            if packet.is_empty() {
+                continue;
+            }
+            let token = transport.submit_isoch(stream.setting.endpoint_address, &packet)?;
+            stream.inflight.push_back(token);
+            stream.stats.packets_submitted += 1;
+        }
+
+        if let (Some(feedback), None) = (stream.setting.feedback_endpoint, stream.feedback_inflight) {
+            if !capture {
+                stream.feedback_inflight = Some(transport.submit_isoch(feedback, &[0u8; 4])?);
+            }
+        }
+        Ok(())
+    }
+
+    fn set_interface(&mut self, interface: u8, alternate_setting: u8) -> UsbResult<()> {
+        let setup = UsbSetupPacket {
+            bmRequestType: UsbRequestType::Standard as u8 | UsbRecipient::Interface as u8,
+            bRequest: UsbStandardRequest::SET_INTERFACE as u8,
+            wValue: alternate_setting as u16,
+            wIndex: interface as u16,
+            wLength: 0,
+        };
+        self.transport.control(&setup, &mut []).map(|_| ())
+    }
+
+    /// UAC 1.0 programs the rate on the data endpoint, UAC 2.0 on the clock
+    /// source feeding the stream's terminal
+    fn set_sample_rate(&mut self, setting: &AudioStreamInterface, rate: u32) -> UsbResult<()> {
+        if self.info.class_version == AudioClassVersion::Version2 {
+            let Some(clock) = self.clock_for(setting.terminal_link) else { return Ok(()) };
+            let mut data = rate.to_le_bytes();
+            let setup = UsbSetupPacket {
+                bmRequestType: UsbRequestType::Class as u8 | UsbRecipient::Interface as u8,
+                bRequest: UAC_REQUEST_CUR,
+                wValue: UAC_SAMPLING_FREQ_CONTROL << 8,
+                wIndex: (clock as u16) << 8 | self.info.control_interface as u16,
+                wLength: 4,
+            };
+            return self.transport.control(&setup, &mut data).map(|_| ());
+        }
+        if !setting.rate_control {
+            return Ok(());
+        }
+        let mut data = [rate as u8, (rate >> 8) as u8, (rate >> 16) as u8];
+        let setup = UsbSetupPacket {
+            bmRequestType: UsbRequestType::Class as u8 | UsbRecipient::Endpoint as u8,
+            bRequest: UAC_REQUEST_CUR,
+            wValue: UAC_SAMPLING_FREQ_CONTROL << 8,
+            wIndex: setting.endpoint_address as u16,
+            wLength: 3,
+        };
+        self.transport.control(&setup, &mut data).map(|_| ())
+    }
+
+    /// Fill in UAC 2.0 rates from the RANGE of each setting's clock source
+    fn query_clock_rates(&mut self, capture: bool) -> UsbResult<()> {
+        for index in 0..self.info.stream_interfaces.len() {
+            let setting = &self.info.stream_interfaces[index];
+            if setting.is_capture() != capture || !setting.sample_rates.is_empty() {
+                continue;
+            }
+            let Some(clock) = self.clock_for(setting.terminal_link) else { continue };
+
+            let mut data = [0u8; 2 + 12 * 16];
+            let setup = UsbSetupPacket {
+                bmRequestType: 0x80 | UsbRequestType::Class as u8 | UsbRecipient::Interface as u8,
+                bRequest: UAC2_REQUEST_RANGE,
+                wValue: UAC_SAMPLING_FREQ_CONTROL << 8,
+                wIndex: (clock as u16) << 8 | self.info.control_interface as u16,
+                wLength: data.len() as u16,
+            };
+            let length = self.transport.control(&setup, &mut data)?;
+            let count = u16::from_le_bytes([data[0], data[1]]) as usize;
+            let field = |at: usize| u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]]);
+            let ranges: Vec<AudioRateRange> = (0..count)
+                .map(|i| 2 + i * 12)
+                .take_while(|&at| at + 12 <= length.min(data.len()))
+                .map(|at| AudioRateRange { min: field(at), max: field(at + 4), resolution: field(at + 8) })
+                .collect();
+
+            let setting = &mut self.info.stream_interfaces[index];
+            setting.sample_rates = ranges;
+            let max_rate = setting.sample_rates.iter().map(|range| range.max).max().unwrap_or(0);
+            for format in &mut setting.formats {
+                format.sample_rate = max_rate;
+                format.bit_rate = max_rate * format.channels as u32 * format.bits_per_sample as u32;
+            }
+        }
+        Ok(())
+    }
+
+    fn clock_for(&self, terminal: u8) -> Option<u8> {
+        self.info.terminals.iter()
+            .find(|candidate| candidate.id == terminal && candidate.clock_source != 0)
+            .map(|candidate| candidate.clock_source)
+            .or_else(|| self.info.clock_sources.first().copied())
+    }
+
+    fn stream(&self, direction: AudioDirection) -> Option<&AudioStream> {
+        match direction {
+            AudioDirection::Playback => self.playback.as_ref(),
+            AudioDirection::Capture => self.capture.as_ref(),
+        }
+    }
+
+    fn stream_slot(&mut self, direction: AudioDirection) -> &mut Option<AudioStream> {
+        match direction {
+            AudioDirection::Playback => &mut self.playback,
+            AudioDirection::Capture => &mut self.capture,
+        }
+    }
+}
+
+/// Service intervals per second of an isochronous endpoint
+fn packets_per_second(speed: UsbSpeed, interval: u8) -> u32 {
+    let base = match speed {
+        UsbSpeed::Low | UsbSpeed::Full => 1000,
+        _ => 8000,
+    };
+    base >> (interval.clamp(1, 16) - 1).min(12)
+}
+
+/// Largest frame count one service interval carries at `rate`
+fn max_packet_frames(speed: UsbSpeed, interval: u8, rate: u32) -> usize {
+    (rate as usize).div_ceil(packets_per_second(speed, interval).max(1) as usize)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -783,8 +1682,261 @@ mod tests {
         assert_eq!(AudioTerminalType::from_u16(0x0400), AudioTerminalType::Speaker);
         assert_eq!(AudioTerminalType::from_u16(0x0000), AudioTerminalType::Undefined);
     }
+
+    #[derive(Default)]
+    struct MockAudioTransport {
+        controls: Vec<(UsbSetupPacket, Vec<u8>)>,
+        /// Data returned by IN control requests, in order
+        responses: Vec<Vec<u8>>,
+        configured: Vec<UsbEndpointDescriptor>,
+        submitted: Vec<(u64, u8, Vec<u8>)>,
+        completions: BTreeMap<u64, AudioPacketResult>,
+        next_token: u64,
+    }
+
+    impl MockAudioTransport {
+        fn complete(&mut self, token: u64, status: UsbTransferStatus, data: Vec<u8>) {
+            let actual_length = data.len();
+            self.completions.insert(token, AudioPacketResult { status, actual_length, data });
+        }
+
+        fn packets_to(&self, endpoint: u8) -> Vec<&(u64, u8, Vec<u8>)> {
+            self.submitted.iter().filter(|packet| packet.1 == endpoint).collect()
+        }
+    }
+
+    impl AudioTransport for MockAudioTransport {
+        fn control(&mut self, setup: &UsbSetupPacket, data: &mut [u8]) -> UsbResult<usize> {
+            let mut length = data.len();
+            if setup.bmRequestType & 0x80 != 0 {
+                let response = self.responses.remove(0);
+                length = response.len().min(data.len());
+                data[..length].copy_from_slice(&response[..length]);
+            }
+            self.controls.push((*setup, data[..length].to_vec()));
+            Ok(length)
+        }
+
+        fn configure_endpoints(&mut self, endpoints: &[UsbEndpointDescriptor]) -> UsbResult<()> {
+            self.configured.extend_from_slice(endpoints);
+            Ok(())
+        }
+
+        fn submit_isoch(&mut self, endpoint: u8, data: &[u8]) -> UsbResult<u64> {
+            self.next_token += 1;
+            self.submitted.push((self.next_token, endpoint, data.to_vec()));
+            Ok(self.next_token)
+        }
+
+        fn poll_isoch(&mut self, token: u64) -> Option<AudioPacketResult> {
+            self.completions.remove(&token)
+        }
+    }
+
+    /// UAC 1.0 full-speed speaker: 16-bit stereo at 44.1 or 48 kHz on an
+    /// asynchronous endpoint with a feedback endpoint
+    fn uac1_speaker() -> Vec<u8> {
+        let mut config = vec![9, 0x02, 0, 0, 2, 1, 0, 0x80, 50];
+        config.extend_from_slice(&[9, 0x04, 0, 0, 0, 0x01, 0x01, 0x00, 0]);
+        config.extend_from_slice(&[9, 0x24, 0x01, 0x00, 0x01, 39, 0, 1, 1]);
+        config.extend_from_slice(&[12, 0x24, 0x02, 1, 0x01, 0x01, 0, 2, 0x03, 0, 0, 0]);
+        config.extend_from_slice(&[9, 0x24, 0x03, 3, 0x01, 0x03, 0, 2, 0]);
+        config.extend_from_slice(&[9, 0x24, 0x06, 2, 1, 1, 0x03, 0x00, 0]);
+        config.extend_from_slice(&[9, 0x04, 1, 0, 0, 0x01, 0x02, 0x00, 0]);
+        config.extend_from_slice(&[9, 0x04, 1, 1, 2, 0x01, 0x02, 0x00, 0]);
+        config.extend_from_slice(&[7, 0x24, 0x01, 1, 1, 0x01, 0x00]);
+        config.extend_from_slice(&[14, 0x24, 0x02, 0x01, 2, 2, 16, 2, 0x44, 0xAC, 0x00, 0x80, 0xBB, 0x00]);
+        config.extend_from_slice(&[9, 0x05, 0x01, 0x05, 0xC8, 0x00, 1, 0, 0x82]);
+        config.extend_from_slice(&[7, 0x25, 0x01, 0x01, 0, 0, 0]);
+        config.extend_from_slice(&[9, 0x05, 0x82, 0x01, 3, 0, 1, 2, 0]);
+        let total = config.len() as u16;
+        config[2..4].copy_from_slice(&total.to_le_bytes());
+        config
+    }
+
+    /// UAC 2.0 high-speed microphone: 24-bit stereo in 3-byte subslots,
+    /// rates reported by clock source 0x10
+    fn uac2_microphone() -> Vec<u8> {
+        let mut config = vec![9, 0x02, 0, 0, 2, 1, 0, 0x80, 50];
+        config.extend_from_slice(&[9, 0x04, 0, 0, 0, 0x01, 0x01, 0x20, 0]);
+        config.extend_from_slice(&[9, 0x24, 0x01, 0x00, 0x02, 0x03, 46, 0, 0]);
+        config.extend_from_slice(&[8, 0x24, 0x0A, 0x10, 0x03, 0x07, 0, 0]);
+        config.extend_from_slice(&[17, 0x24, 0x02, 1, 0x01, 0x02, 0, 0x10, 2, 0, 0, 0, 0, 0, 0, 0, 0]);
+        config.extend_from_slice(&[12, 0x24, 0x03, 2, 0x01, 0x01, 0, 1, 0x10, 0, 0, 0]);
+        config.extend_from_slice(&[9, 0x04, 1, 0, 0, 0x01, 0x02, 0x20, 0]);
+        config.extend_from_slice(&[9, 0x04, 1, 1, 1, 0x01, 0x02, 0x20, 0]);
+        config.extend_from_slice(&[16, 0x24, 0x01, 2, 0, 0x01, 0x01, 0, 0, 0, 2, 0x03, 0, 0, 0, 0]);
+        config.extend_from_slice(&[6, 0x24, 0x02, 0x01, 3, 24]);
+        config.extend_from_slice(&[7, 0x05, 0x81, 0x05, 0x60, 0x00, 1]);
+        config.extend_from_slice(&[8, 0x25, 0x01, 0, 0, 0, 0, 0]);
+        let total = config.len() as u16;
+        config[2..4].copy_from_slice(&total.to_le_bytes());
+        config
+    }
+
+    fn pcm_format(sample_rate: u32, channels: u8, bits_per_sample: u8) -> AudioStreamFormat {
+        AudioStreamFormat {
+            format_type: AudioDataFormat::PCM,
+            channels,
+            bits_per_sample,
+            sample_rate,
+            bit_rate: 0,
+            sync_frames: 0,
+        }
+    }
+
+    #[test]
+    fn test_audio_buffer_wraps() {
+        let mut buffer = AudioBuffer::new(8);
+        assert_eq!(buffer.write(&[1, 2, 3, 4, 5, 6]), 6);
+        let mut out = [0u8; 4];
+        assert_eq!(buffer.read(&mut out), 4);
+        assert_eq!(buffer.write(&[7, 8, 9, 10, 11, 12]), 6);
+        assert!(buffer.filled);
+        assert_eq!(buffer.write(&[13]), 0);
+        let mut out = [0u8; 8];
+        assert_eq!(buffer.read(&mut out), 8);
+        assert_eq!(out, [5, 6, 7, 8, 9, 10, 11, 12]);
+        assert!(buffer.empty);
+    }
+
+    #[test]
+    fn test_parse_uac1_function() {
+        let info = parse_audio_function(&uac1_speaker()).unwrap();
+        assert_eq!(info.class_version, AudioClassVersion::Version1);
+        assert_eq!(info.terminals.len(), 2);
+        assert_eq!(info.terminals[1].terminal_type, 0x0301);
+        assert_eq!(info.feature_units, vec![2]);
+        assert_eq!(info.stream_interface_count, 1);
+
+        let stream = &info.stream_interfaces[0];
+        assert_eq!((stream.interface_number, stream.alternate_setting), (1, 1));
+        assert_eq!(stream.endpoint_address, 0x01);
+        assert_eq!(stream.sync_type, IsoSyncType::Asynchronous);
+        assert_eq!(stream.feedback_endpoint, Some(0x82));
+        assert!(stream.rate_control);
+        assert_eq!(stream.sample_rates, vec![AudioRateRange::discrete(44100), AudioRateRange::discrete(48000)]);
+        assert_eq!(stream.formats[0].channels, 2);
+        assert_eq!(stream.formats[0].sample_rate, 48000);
+    }
+
+    #[test]
+    fn test_uac1_playback_packetizes_44k1() {
+        let mut device = AudioDevice::new(MockAudioTransport::default(), UsbSpeed::Full, &uac1_speaker()).unwrap();
+        let format = device.open_stream(AudioDirection::Playback, &pcm_format(44000, 2, 16)).unwrap();
+        assert_eq!(format.sample_rate, 44100);
+
+        let controls = &device.transport().controls;
+        assert_eq!((controls[0].0.bRequest, controls[0].0.wValue, controls[0].0.wIndex), (0x0B, 1, 1));
+        assert_eq!((controls[1].0.bmRequestType, controls[1].0.wValue, controls[1].0.wIndex), (0x22, 0x0100, 0x01));
+        assert_eq!(controls[1].1, vec![0x44, 0xAC, 0x00]);
+        assert_eq!(device.transport().configured.len(), 2);
+
+        // 10 ms of audio plus a partial frame, which is refused
+        let pcm = vec![0x11u8; 441 * 4 + 3];
+        assert_eq!(device.write_pcm(&pcm).unwrap(), 441 * 4);
+        device.start(AudioDirection::Playback).unwrap();
+
+        let packets: Vec<usize> = device.transport().packets_to(0x01).iter().map(|packet| packet.2.len()).collect();
+        assert_eq!(packets, vec![176; AUDIO_PACKETS_IN_FLIGHT]);
+        assert_eq!(device.transport().packets_to(0x82).len(), 1);
+        assert_eq!(device.pcm_available(AudioDirection::Playback), 441 * 4 - 8 * 176);
+
+        let tokens: Vec<u64> = device.transport().packets_to(0x01).iter().map(|packet| packet.0).collect();
+        for token in tokens {
+            device.transport().complete(token, UsbTransferStatus::Success, Vec::new());
+        }
+        device.service().unwrap();
+
+        // The eleventh packet carries 45 frames and the ring runs dry there
+        let packets: Vec<usize> = device.transport().packets_to(0x01).iter().map(|packet| packet.2.len()).collect();
+        assert_eq!(packets.len(), 2 * AUDIO_PACKETS_IN_FLIGHT);
+        assert_eq!(packets[10], 180);
+        let eleventh = &device.transport().packets_to(0x01)[10].2;
+        assert_eq!(&eleventh[..4], &[0x11; 4]);
+        assert!(eleventh[4..].iter().all(|&byte| byte == 0));
+        let stats = device.stream_stats(AudioDirection::Playback).unwrap();
+        assert_eq!(stats.packets_completed, 8);
+        assert_eq!(stats.underruns, 6);
+    }
+
+    #[test]
+    fn test_explicit_feedback_adjusts_packet_size() {
+        let mut device = AudioDevice::new(MockAudioTransport::default(), UsbSpeed::Full, &uac1_speaker()).unwrap();
+        device.open_stream(AudioDirection::Playback, &pcm_format(48000, 2, 16)).unwrap();
+        device.start(AudioDirection::Playback).unwrap();
+
+        // 48.5 frames per frame in 10.14
+        let feedback = device.transport().packets_to(0x82)[0].0;
+        let value = (48u32 << 14) | (1 << 13);
+        device.transport().complete(feedback, UsbTransferStatus::Success, value.to_le_bytes()[..3].to_vec());
+        let first = device.transport().packets_to(0x01)[0].0;
+        device.transport().complete(first, UsbTransferStatus::Success, Vec::new());
+        device.service().unwrap();
+
+        let stats = device.stream_stats(AudioDirection::Playback).unwrap();
+        assert_eq!(stats.feedback_updates, 1);
+        let sizes: Vec<usize> = device.transport().packets_to(0x01).iter().map(|packet| packet.2.len()).collect();
+        assert_eq!(sizes[8], 48 * 4);
+        assert_eq!(device.transport().packets_to(0x82).len(), 2);
+
+        // Values far from the nominal rate are ignored
+        let feedback = device.transport().packets_to(0x82)[1].0;
+        device.transport().complete(feedback, UsbTransferStatus::Success, vec![0, 0, 0x08]);
+        device.service().unwrap();
+        assert_eq!(device.stream_stats(AudioDirection::Playback).unwrap().feedback_updates, 1);
+    }
+
+    #[test]
+    fn test_uac2_capture_uses_clock_range() {
+        let mut transport = MockAudioTransport::default();
+        let mut range = vec![2, 0];
+        for value in [44100u32, 44100, 0, 48000, 96000, 48000] {
+            range.extend_from_slice(&value.to_le_bytes());
+        }
+        transport.responses.push(range);
+        let mut device = AudioDevice::new(transport, UsbSpeed::High, &uac2_microphone()).unwrap();
+        assert_eq!(device.info().class_version, AudioClassVersion::Version2);
+        assert_eq!(device.info().clock_sources, vec![0x10]);
+        assert_eq!(device.info().terminals[0].clock_source, 0x10);
+
+        let format = device.open_stream(AudioDirection::Capture, &pcm_format(50000, 2, 24)).unwrap();
+        assert_eq!(format.sample_rate, 48000);
+        assert_eq!(format.bits_per_sample, 24);
+
+        let controls = &device.transport().controls;
+        assert_eq!((controls[0].0.bmRequestType, controls[0].0.bRequest, controls[0].0.wIndex), (0xA1, 0x02, 0x1000));
+        assert_eq!((controls[1].0.bRequest, controls[1].0.wValue), (0x0B, 1));
+        assert_eq!((controls[2].0.bmRequestType, controls[2].0.bRequest, controls[2].0.wIndex), (0x21, 0x01, 0x1000));
+        assert_eq!(controls[2].1, 48000u32.to_le_bytes().to_vec());
+        assert!(device.write_pcm(&[0; 6]).is_err());
+
+        device.start(AudioDirection::Capture).unwrap();
+        let tokens: Vec<u64> = device.transport().packets_to(0x81).iter().map(|packet| packet.0).collect();
+        assert_eq!(tokens.len(), AUDIO_PACKETS_IN_FLIGHT);
+        assert_eq!(device.transport().packets_to(0x81)[0].2.len(), 96);
+
+        device.transport().complete(tokens[0], UsbTransferStatus::Success, vec![1; 36]);
+        device.transport().complete(tokens[1], UsbTransferStatus::Success, vec![2; 36]);
+        device.transport().complete(tokens[2], UsbTransferStatus::Stalled, Vec::new());
+        device.service().unwrap();
+
+        let mut out = [0u8; 100];
+        assert_eq!(device.read_pcm(&mut out).unwrap(), 72);
+        assert_eq!(&out[35..37], &[1, 2]);
+        let stats = device.stream_stats(AudioDirection::Capture).unwrap();
+        assert_eq!(stats.packets_completed, 3);
+        assert_eq!(stats.packet_errors, 1);
+        assert_eq!(device.transport().packets_to(0x81).len(), AUDIO_PACKETS_IN_FLIGHT + 3);
+
+        device.close_stream(AudioDirection::Capture).unwrap();
+        let last = device.transport().controls.last().unwrap().0;
+        assert_eq!((last.bRequest, last.wValue, last.wIndex), (0x0B, 0, 1));
+        assert!(device.info().stream_interfaces.iter().all(|setting| !setting.active));
+    }
 }
 
+
 // Add missing trait implementations
 impl From<u8> for AudioDataFormat {
     fn from(value: u8) -> Self {
@@ -874,4 +2026,16 @@ impl From<u16> for AudioTerminalType {
             _ => AudioTerminalType::Undefined,
         }
     }
-}
\ No newline at end of file
+}
+
+impl AudioDataFormat {
+    pub fn from_u8(value: u8) -> Self {
+        Self::from(value)
+    }
+}
+
+impl AudioTerminalType {
+    pub fn from_u16(value: u16) -> Self {
+        Self::from(value)
+    }
+}
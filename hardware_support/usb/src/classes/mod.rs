@@ -13,9 +13,9 @@ pub mod audio;
 
 // Re-export classes for easy access
 pub use hid::{HidDriver, HidEvent, HidUsagePage, HidGenericDesktopUsage};
-pub use msc::{MscDriver, MscCommandResult, ScsiOperationCode, ScsiResponseCode};
+pub use msc::{MscDriver, MscDevice, MscTransport, XhciMscTransport, UsbBlockDevice, MscCommandResult, ScsiOperationCode, ScsiResponseCode};
 pub use cdc::{CdcDriver, CdcAcmDriver, CdcNcmDriver, CdcSubclass, CdcProtocol};
-pub use audio::{AudioDriver, AudioStreamFormat, AudioDataFormat, AudioTerminalType};
+pub use audio::{AudioDriver, AudioDevice, AudioTransport, XhciAudioTransport, AudioDirection, AudioStreamFormat, AudioDataFormat, AudioTerminalType};
 
 use crate::*;
 
@@ -171,7 +171,8 @@ impl UsbClassManager {
                 e
             })?;
 
-        let driver_id = self.drivers.len() as u8;
+        // Ids of removed drivers are not reused while later ones exist
+        let driver_id = self.drivers.keys().next_back().map_or(0, |&id| id.wrapping_add(1));
         self.drivers.insert(driver_id, driver);
         self.device_addresses.insert(device_address, driver_id);
 
@@ -225,7 +226,7 @@ impl UsbClassManager {
 
     /// Remove driver
     pub fn remove_driver(&mut self, driver_id: u8) -> UsbResult<()> {
-        let driver = self.drivers.remove(&driver_id)
+        let mut driver = self.drivers.remove(&driver_id)
             .ok_or(UsbDriverError::DeviceNotFound { address: driver_id })?;
 
         driver.cleanup();
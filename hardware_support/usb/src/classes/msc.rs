@@ -1,13 +1,57 @@
 //! USB MSC (Mass Storage Class) Driver
-//! 
+//!
 //! Supports USB mass storage devices like flash drives, external hard drives, and memory cards.
 //! Implements SCSI commands, bulk-only transport protocol, and file system mounting.
+//!
+//! `MscDevice` drives one bulk-only interface through an `MscTransport`:
+//! every command is a 31-byte CBW on the bulk-out pipe, an optional data
+//! phase, and a 13-byte CSW on the bulk-in pipe. Stalled phases are cleared
+//! and the CSW retried; an invalid CSW or a phase error triggers the
+//! bulk-only reset recovery. The device is exposed to the storage layer and
+//! the hypervisor disk backends through `UsbBlockDevice`.
 
 use crate::*;
+use crate::host::xhci::XhciController;
 
 #[cfg(feature = "std")]
 use std::collections::BTreeMap;
 
+/// CBW signature, "USBC" in little endian
+pub const MSC_CBW_SIGNATURE: u32 = 0x43425355;
+/// CSW signature, "USBS" in little endian
+pub const MSC_CSW_SIGNATURE: u32 = 0x53425355;
+pub const MSC_CBW_LENGTH: usize = 31;
+pub const MSC_CSW_LENGTH: usize = 13;
+
+/// CBW direction flag for device-to-host data
+pub const MSC_CBW_FLAG_DATA_IN: u8 = 0x80;
+
+/// CSW status values
+pub const MSC_CSW_STATUS_PASSED: u8 = 0x00;
+pub const MSC_CSW_STATUS_FAILED: u8 = 0x01;
+pub const MSC_CSW_STATUS_PHASE_ERROR: u8 = 0x02;
+
+/// Bulk-only class requests
+pub const MSC_REQUEST_RESET: u8 = 0xFF;
+pub const MSC_REQUEST_GET_MAX_LUN: u8 = 0xFE;
+
+/// Interface subclass and protocol of a SCSI bulk-only device
+pub const MSC_SUBCLASS_SCSI: u8 = 0x06;
+pub const MSC_PROTOCOL_BULK_ONLY: u8 = 0x50;
+
+/// Standard INQUIRY and fixed-format sense data lengths
+pub const SCSI_INQUIRY_LENGTH: usize = 36;
+pub const SCSI_SENSE_LENGTH: usize = 18;
+
+/// Blocks moved by one READ(10)/WRITE(10) unless changed
+pub const MSC_DEFAULT_MAX_TRANSFER_BLOCKS: u16 = 128;
+
+/// Times a command is repeated after UNIT ATTENTION or a recovered reset
+const MSC_COMMAND_RETRIES: u32 = 3;
+
+/// TEST UNIT READY attempts while the medium spins up
+const MSC_READY_RETRIES: u32 = 10;
+
 /// SCSI Command Operation Codes
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
@@ -15,7 +59,6 @@ pub enum ScsiOperationCode {
     TestUnitReady = 0x00,
     RequestSense = 0x03,
     Inquiry = 0x12,
-    ModeSense = 0x1A,
     StartStopUnit = 0x1B,
     MediaRemoval = 0x1E,
     ReadCapacity10 = 0x25,
@@ -223,6 +266,311 @@ pub enum MscCommandResult {
     Unknown,
 }
 
+impl ScsiOperationCode {
+    pub fn from_u8(value: u8) -> Self {
+        Self::from(value)
+    }
+}
+
+impl ScsiResponseCode {
+    pub fn from_u8(value: u8) -> Self {
+        Self::from(value)
+    }
+}
+
+impl ScsiCBW {
+    /// Wrap a command block for `lun`
+    pub fn new(tag: u32, data_length: u32, data_in: bool, lun: u8, command: &[u8]) -> Self {
+        let mut cb = [0u8; 16];
+        let length = command.len().min(16);
+        cb[..length].copy_from_slice(&command[..length]);
+        Self {
+            dSignature: MSC_CBW_SIGNATURE,
+            dTag: tag,
+            dDataTransferLength: data_length,
+            bmFlags: if data_in { MSC_CBW_FLAG_DATA_IN } else { 0 },
+            bLUN: lun & 0x0F,
+            bCDBLength: length as u8,
+            CB: cb,
+        }
+    }
+
+    /// Wire format sent on the bulk-out pipe
+    pub fn to_bytes(&self) -> [u8; MSC_CBW_LENGTH] {
+        let mut bytes = [0u8; MSC_CBW_LENGTH];
+        bytes[0..4].copy_from_slice(&self.dSignature.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.dTag.to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.dDataTransferLength.to_le_bytes());
+        bytes[12] = self.bmFlags;
+        bytes[13] = self.bLUN;
+        bytes[14] = self.bCDBLength;
+        bytes[15..31].copy_from_slice(&self.CB);
+        bytes
+    }
+
+    /// Decode a CBW; `None` if it is not a valid, meaningful CBW
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        if data.len() != MSC_CBW_LENGTH {
+            return None;
+        }
+        let mut cb = [0u8; 16];
+        cb.copy_from_slice(&data[15..31]);
+        let cbw = Self {
+            dSignature: le32(data, 0),
+            dTag: le32(data, 4),
+            dDataTransferLength: le32(data, 8),
+            bmFlags: data[12],
+            bLUN: data[13] & 0x0F,
+            bCDBLength: data[14] & 0x1F,
+            CB: cb,
+        };
+        let meaningful = cbw.bCDBLength >= 1 && cbw.bCDBLength <= 16;
+        (cbw.dSignature == MSC_CBW_SIGNATURE && meaningful).then_some(cbw)
+    }
+
+    pub fn is_data_in(&self) -> bool {
+        self.bmFlags & MSC_CBW_FLAG_DATA_IN != 0
+    }
+}
+
+impl ScsiCSW {
+    /// Decode a CSW; `None` if it is not 13 bytes with the CSW signature
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        if data.len() != MSC_CSW_LENGTH {
+            return None;
+        }
+        let csw = Self {
+            dSignature: le32(data, 0),
+            dTag: le32(data, 4),
+            dDataResidue: le32(data, 8),
+            bStatus: data[12],
+            reserved: [0; 3],
+        };
+        (csw.dSignature == MSC_CSW_SIGNATURE).then_some(csw)
+    }
+
+    /// Wire format returned on the bulk-in pipe
+    pub fn to_bytes(&self) -> [u8; MSC_CSW_LENGTH] {
+        let mut bytes = [0u8; MSC_CSW_LENGTH];
+        bytes[0..4].copy_from_slice(&self.dSignature.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.dTag.to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.dDataResidue.to_le_bytes());
+        bytes[12] = self.bStatus;
+        bytes
+    }
+
+    /// A CSW answers the CBW with `tag` and carries a status the host can act on
+    pub fn is_meaningful_for(&self, tag: u32, data_length: u32) -> bool {
+        self.dTag == tag && self.bStatus <= MSC_CSW_STATUS_PHASE_ERROR &&
+            (self.bStatus == MSC_CSW_STATUS_PHASE_ERROR || self.dDataResidue <= data_length)
+    }
+}
+
+impl ScsiCDB {
+    /// Build a command without extra parameters
+    pub fn new(operation_code: ScsiOperationCode, logical_block_address: u32, transfer_length: u32) -> Self {
+        Self { operation_code, logical_block_address, transfer_length, parameters: Vec::new() }
+    }
+
+    /// Encode the command block carried in the CBW
+    pub fn encode(&self) -> [u8; 16] {
+        let mut cdb = [0u8; 16];
+        cdb[0] = self.operation_code as u8;
+
+        match self.operation_code {
+            ScsiOperationCode::Read10 | ScsiOperationCode::Write10 |
+            ScsiOperationCode::Verify10 | ScsiOperationCode::WriteAndVerify10 => {
+                cdb[2..6].copy_from_slice(&self.logical_block_address.to_be_bytes());
+                cdb[7..9].copy_from_slice(&(self.transfer_length as u16).to_be_bytes());
+            }
+            ScsiOperationCode::SynchronizeCache10 => {
+                cdb[2..6].copy_from_slice(&self.logical_block_address.to_be_bytes());
+                cdb[7..9].copy_from_slice(&(self.transfer_length as u16).to_be_bytes());
+            }
+            ScsiOperationCode::ReadCapacity10 | ScsiOperationCode::TestUnitReady => {}
+            ScsiOperationCode::RequestSense => {
+                cdb[4] = self.transfer_length as u8;
+            }
+            ScsiOperationCode::Inquiry => {
+                if self.parameters.len() >= 2 {
+                    cdb[1] = self.parameters[0]; // EVPD
+                    cdb[2] = self.parameters[1]; // Page code
+                }
+                cdb[3..5].copy_from_slice(&(self.transfer_length as u16).to_be_bytes());
+            }
+            ScsiOperationCode::ModeSense6 => {
+                if !self.parameters.is_empty() {
+                    cdb[2] = self.parameters[0]; // Page control and code
+                }
+                cdb[4] = self.transfer_length as u8;
+            }
+            _ => {
+                // For other commands, copy parameters as available
+                for (i, &param) in self.parameters.iter().enumerate().take(15) {
+                    cdb[i + 1] = param;
+                }
+            }
+        }
+        cdb
+    }
+
+    /// Length of the command block for this operation
+    pub fn cdb_length(&self) -> u8 {
+        match self.operation_code {
+            ScsiOperationCode::ModeSelect6 | ScsiOperationCode::ModeSense6 |
+            ScsiOperationCode::MediaRemoval | ScsiOperationCode::FormatUnit => 6,
+            ScsiOperationCode::Read10 | ScsiOperationCode::Write10 |
+            ScsiOperationCode::ReadCapacity10 |
+            ScsiOperationCode::WriteAndVerify10 | ScsiOperationCode::Verify10 |
+            ScsiOperationCode::ModeSelect10 | ScsiOperationCode::ModeSense10 |
+            ScsiOperationCode::Seek10 |
+            ScsiOperationCode::SynchronizeCache10 | ScsiOperationCode::LockUnlockCache |
+            ScsiOperationCode::ReadDefectData | ScsiOperationCode::ReadToc |
+            ScsiOperationCode::ReadHeader | ScsiOperationCode::PlayAudio10 |
+            ScsiOperationCode::PlayAudioMSF | ScsiOperationCode::PlayAudioTrackIndex |
+            ScsiOperationCode::PlayTrackRelative10 | ScsiOperationCode::PauseResume |
+            ScsiOperationCode::StopPlayLoad | ScsiOperationCode::ReadDiscInformation |
+            ScsiOperationCode::ReadTrackInformation | ScsiOperationCode::ReserveTrack |
+            ScsiOperationCode::SendCommandInformation => 10,
+            ScsiOperationCode::Read12 | ScsiOperationCode::Write12 |
+            ScsiOperationCode::SynchronizeCache12 | ScsiOperationCode::ModeSelect2 => 12,
+            _ => 6, // Default to 6-byte CDB
+        }
+    }
+
+    /// Check if command receives data (IN transfer)
+    pub fn is_data_in(&self) -> bool {
+        matches!(self.operation_code,
+            ScsiOperationCode::Read10 | ScsiOperationCode::Read12 |
+            ScsiOperationCode::ReadCapacity10 | ScsiOperationCode::Inquiry |
+            ScsiOperationCode::RequestSense | ScsiOperationCode::ModeSense6 |
+            ScsiOperationCode::ModeSense10 | ScsiOperationCode::ReadToc |
+            ScsiOperationCode::ReadHeader | ScsiOperationCode::ReadDefectData |
+            ScsiOperationCode::ReadDiscInformation | ScsiOperationCode::ReadTrackInformation)
+    }
+}
+
+impl ScsiSenseData {
+    /// Decode fixed-format sense data (response codes 0x70 and 0x71)
+    pub fn parse(data: &[u8]) -> Self {
+        if data.len() < 14 {
+            return Self::no_sense();
+        }
+
+        let mut information = [0u8; 4];
+        let mut command_specific_information = [0u8; 4];
+        information.copy_from_slice(&data[3..7]);
+        command_specific_information.copy_from_slice(&data[8..12]);
+
+        Self {
+            valid: data[0] >> 7,
+            response_code: data[0] & 0x7F,
+            obsolete: data[1],
+            sense_key: ScsiSenseKey::from(data[2] & 0x0F),
+            information,
+            additional_sense_length: data[7],
+            command_specific_information,
+            additional_sense_code: data[12],
+            additional_sense_qualifier: data[13],
+        }
+    }
+
+    pub fn no_sense() -> Self {
+        Self {
+            valid: 0,
+            response_code: 0,
+            obsolete: 0,
+            sense_key: ScsiSenseKey::NoSense,
+            information: [0; 4],
+            additional_sense_length: 0,
+            command_specific_information: [0; 4],
+            additional_sense_code: 0,
+            additional_sense_qualifier: 0,
+        }
+    }
+
+    /// Additional sense code and qualifier
+    pub fn asc_ascq(&self) -> (u8, u8) {
+        (self.additional_sense_code, self.additional_sense_qualifier)
+    }
+}
+
+impl ScsiDeviceInfo {
+    /// Decode standard INQUIRY data
+    pub fn parse(data: &[u8]) -> Self {
+        if data.len() < SCSI_INQUIRY_LENGTH {
+            return Self {
+                device_type: "Unknown".to_string(),
+                vendor_id: [0; 8],
+                product_id: [0; 16],
+                product_revision: [0; 4],
+                serial_number: Vec::new(),
+                removable_media: false,
+                write_protected: false,
+                command_queue_support: false,
+                characteristics: Vec::new(),
+            };
+        }
+
+        let mut vendor_id = [0u8; 8];
+        let mut product_id = [0u8; 16];
+        let mut product_revision = [0u8; 4];
+
+        vendor_id.copy_from_slice(&data[8..16]);
+        product_id.copy_from_slice(&data[16..32]);
+        product_revision.copy_from_slice(&data[32..36]);
+
+        let device_type = match data[0] & 0x1F {
+            0x00 => "Disk".to_string(),
+            0x05 => "CD/DVD".to_string(),
+            0x07 => "Optical".to_string(),
+            0x0E => "Simplified Disk".to_string(),
+            other => format!("Type {}", other),
+        };
+
+        Self {
+            device_type,
+            vendor_id,
+            product_id,
+            product_revision,
+            serial_number: Vec::new(),
+            removable_media: (data[1] & 0x80) != 0,
+            write_protected: false, // Reported by MODE SENSE
+            command_queue_support: (data[7] & 0x02) != 0,
+            characteristics: Vec::new(),
+        }
+    }
+
+    pub fn vendor_string(&self) -> String {
+        inquiry_string(&self.vendor_id)
+    }
+
+    pub fn product_string(&self) -> String {
+        inquiry_string(&self.product_id)
+    }
+
+    pub fn revision_string(&self) -> String {
+        inquiry_string(&self.product_revision)
+    }
+}
+
+fn le32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]])
+}
+
+fn be32(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]])
+}
+
+/// INQUIRY identification fields are space padded ASCII
+fn inquiry_string(field: &[u8]) -> String {
+    field.iter()
+        .map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { ' ' })
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
 impl MscDriver {
     /// Create a new MSC driver instance
     pub fn new(device_address: u8) -> Self {
@@ -230,22 +578,12 @@ impl MscDriver {
             device_info: MscDeviceInfo {
                 vendor_id: 0,
                 product_id: 0,
-                scsi_info: ScsiDeviceInfo {
-                    device_type: "Unknown".to_string(),
-                    vendor_id: [0; 8],
-                    product_id: [0; 16],
-                    product_revision: [0; 4],
-                    serial_number: Vec::new(),
-                    removable_media: false,
-                    write_protected: false,
-                    command_queue_support: false,
-                    characteristics: Vec::new(),
-                },
+                scsi_info: ScsiDeviceInfo::parse(&[]),
                 logical_unit_count: 1,
                 max_lun: 0,
                 bulk_only: true,
                 control_transport: false,
-                max_sense_length: 18,
+                max_sense_length: SCSI_SENSE_LENGTH as u8,
             },
             bulk_endpoint_in: None,
             bulk_endpoint_out: None,
@@ -299,36 +637,24 @@ impl MscDriver {
 
     /// Get device capacity
     pub fn get_capacity(&mut self) -> UsbResult<()> {
-        let capacity_cmd = ScsiCDB {
-            operation_code: ScsiOperationCode::ReadCapacity10,
-            logical_block_address: 0,
-            transfer_length: 0,
-            parameters: Vec::new(),
-        };
+        let capacity_cmd = ScsiCDB::new(ScsiOperationCode::ReadCapacity10, 0, 0);
 
         let mut capacity_data = [0u8; 8];
         let result = self.execute_command(&capacity_cmd, &mut capacity_data)?;
 
         match result {
-            MscCommandResult::Success(data) => {
-                capacity_data.copy_from_slice(&data);
-                
-                self.total_blocks = ((capacity_data[0] as u32) << 24) |
-                                 ((capacity_data[1] as u32) << 16) |
-                                 ((capacity_data[2] as u32) << 8) |
-                                 (capacity_data[3] as u32);
-                self.block_size = ((capacity_data[4] as u32) << 24) |
-                                ((capacity_data[5] as u32) << 16) |
-                                ((capacity_data[6] as u32) << 8) |
-                                (capacity_data[7] as u32);
-
-                log::info!("MSC capacity: {} blocks of {} bytes", 
+            MscCommandResult::Success(data) if data.len() >= 8 => {
+                let (total_blocks, block_size) = parse_read_capacity10(&data);
+                self.total_blocks = total_blocks.min(u32::MAX as u64) as u32;
+                self.block_size = block_size;
+
+                log::info!("MSC capacity: {} blocks of {} bytes",
                           self.total_blocks, self.block_size);
             }
             _ => {
                 log::warn!("Failed to get MSC capacity");
-                return Err(UsbDriverError::TransferFailed { 
-                    status: UsbTransferStatus::Stalled 
+                return Err(UsbDriverError::TransferFailed {
+                    status: UsbTransferStatus::Stalled
                 });
             }
         }
@@ -343,26 +669,19 @@ impl MscDriver {
         }
 
         // Prepare CBW
-        let mut cbw = ScsiCBW {
-            dSignature: 0x43425355, // "USBC"
-            dTag: self.generate_tag(),
-            dDataTransferLength: data_buffer.len() as u32,
-            bmFlags: 0, // Will be set based on command
-            bLUN: 0,
-            bCDBLength: self.get_cdb_length(cdb.operation_code),
-            CB: [0; 16],
-        };
-
-        // Fill CBW CB field with SCSI command
-        self.fill_cdb(&cbw.CB, cdb);
+        let is_data_in = cdb.is_data_in();
+        let command = cdb.encode();
+        let cbw = ScsiCBW::new(
+            self.generate_tag(),
+            data_buffer.len() as u32,
+            is_data_in,
+            0,
+            &command[..cdb.cdb_length() as usize],
+        );
 
         // Send CBW
         self.send_cbw(&cbw)?;
 
-        // Determine transfer direction
-        let is_data_in = self.is_data_in_command(cdb.operation_code);
-        cbw.bmFlags = if is_data_in { 0x80 } else { 0x00 };
-
         // Send or receive data based on command
         if data_buffer.len() > 0 {
             if is_data_in {
@@ -377,65 +696,48 @@ impl MscDriver {
 
         // Check CSW status
         match csw.bStatus {
-            0x00 => Ok(MscCommandResult::Success(data_buffer.to_vec())),
-            0x02 => {
-                // Check Condition - get sense data
+            MSC_CSW_STATUS_PASSED => Ok(MscCommandResult::Success(data_buffer.to_vec())),
+            MSC_CSW_STATUS_FAILED if cdb.operation_code != ScsiOperationCode::RequestSense => {
+                // Command failed - get sense data
                 let sense = self.get_sense_data()?;
                 Ok(MscCommandResult::CheckCondition(sense))
             }
-            0x08 => Ok(MscCommandResult::Busy),
-            0x18 => Ok(MscCommandResult::ReservationConflict),
             _ => Ok(MscCommandResult::Unknown),
         }
     }
 
     /// Read data blocks
     pub fn read_blocks(&mut self, lba: u32, block_count: u32, buffer: &mut [u8]) -> UsbResult<()> {
-        let read_cmd = ScsiCDB {
-            operation_code: ScsiOperationCode::Read10,
-            logical_block_address: lba,
-            transfer_length: block_count,
-            parameters: Vec::new(),
-        };
+        let read_cmd = ScsiCDB::new(ScsiOperationCode::Read10, lba, block_count);
 
         let result = self.execute_command(&read_cmd, buffer)?;
-        
+
         match result {
             MscCommandResult::Success(_) => Ok(()),
-            _ => Err(UsbDriverError::TransferFailed { 
-                status: UsbTransferStatus::Stalled 
+            _ => Err(UsbDriverError::TransferFailed {
+                status: UsbTransferStatus::Stalled
             }),
         }
     }
 
     /// Write data blocks
     pub fn write_blocks(&mut self, lba: u32, block_count: u32, buffer: &[u8]) -> UsbResult<()> {
-        let write_cmd = ScsiCDB {
-            operation_code: ScsiOperationCode::Write10,
-            logical_block_address: lba,
-            transfer_length: block_count,
-            parameters: Vec::new(),
-        };
+        let write_cmd = ScsiCDB::new(ScsiOperationCode::Write10, lba, block_count);
 
         let mut write_buffer = buffer.to_vec();
         let result = self.execute_command(&write_cmd, &mut write_buffer)?;
-        
+
         match result {
             MscCommandResult::Success(_) => Ok(()),
-            _ => Err(UsbDriverError::TransferFailed { 
-                status: UsbTransferStatus::Stalled 
+            _ => Err(UsbDriverError::TransferFailed {
+                status: UsbTransferStatus::Stalled
             }),
         }
     }
 
     /// Test if unit is ready
     pub fn test_unit_ready(&mut self) -> UsbResult<bool> {
-        let test_cmd = ScsiCDB {
-            operation_code: ScsiOperationCode::TestUnitReady,
-            logical_block_address: 0,
-            transfer_length: 0,
-            parameters: Vec::new(),
-        };
+        let test_cmd = ScsiCDB::new(ScsiOperationCode::TestUnitReady, 0, 0);
 
         let mut dummy_buffer = [0u8; 0];
         let result = self.execute_command(&test_cmd, &mut dummy_buffer)?;
@@ -451,9 +753,9 @@ impl MscDriver {
     fn send_cbw(&mut self, cbw: &ScsiCBW) -> UsbResult<()> {
         // Implementation would send CBW through bulk-out endpoint
         // For now, just log the operation
-        log::debug!("Sending CBW: tag={}, length={}, direction={}", 
-                   cbw.dTag, cbw.dDataTransferLength, 
-                   if (cbw.bmFlags & 0x80) != 0 { "IN" } else { "OUT" });
+        log::debug!("Sending CBW: tag={}, length={}, direction={}",
+                   cbw.dTag, cbw.dDataTransferLength,
+                   if cbw.is_data_in() { "IN" } else { "OUT" });
         Ok(())
     }
 
@@ -473,11 +775,11 @@ impl MscDriver {
 
     /// Receive CSW from device
     fn receive_csw(&mut self) -> UsbResult<ScsiCSW> {
-        let mut csw = ScsiCSW {
-            dSignature: 0,
+        let csw = ScsiCSW {
+            dSignature: MSC_CSW_SIGNATURE,
             dTag: 0,
             dDataResidue: 0,
-            bStatus: 0,
+            bStatus: MSC_CSW_STATUS_PASSED,
             reserved: [0; 3],
         };
 
@@ -488,221 +790,665 @@ impl MscDriver {
 
     /// Get sense data
     fn get_sense_data(&mut self) -> UsbResult<ScsiSenseData> {
-        let sense_cmd = ScsiCDB {
-            operation_code: ScsiOperationCode::RequestSense,
-            logical_block_address: 0,
-            transfer_length: self.device_info.max_sense_length as u32,
-            parameters: Vec::new(),
-        };
+        let sense_cmd = ScsiCDB::new(ScsiOperationCode::RequestSense, 0, self.device_info.max_sense_length as u32);
 
         let mut sense_buffer = vec![0u8; self.device_info.max_sense_length as usize];
         let result = self.execute_command(&sense_cmd, &mut sense_buffer)?;
 
         match result {
-            MscCommandResult::Success(data) => {
-                Ok(self.parse_sense_data(&data))
-            }
-            _ => Err(UsbDriverError::TransferFailed { 
-                status: UsbTransferStatus::Stalled 
+            MscCommandResult::Success(data) => Ok(ScsiSenseData::parse(&data)),
+            _ => Err(UsbDriverError::TransferFailed {
+                status: UsbTransferStatus::Stalled
             }),
         }
     }
 
-    /// Parse sense data
-    fn parse_sense_data(&self, data: &[u8]) -> ScsiSenseData {
-        if data.len() < 14 {
-            return ScsiSenseData {
-                valid: 0,
-                response_code: 0,
-                obsolete: 0,
-                sense_key: ScsiSenseKey::NoSense,
-                information: [0; 4],
-                additional_sense_length: 0,
-                command_specific_information: [0; 4],
-                additional_sense_code: 0,
-                additional_sense_qualifier: 0,
-            };
+    /// Get inquiry information
+    pub fn get_inquiry_info(&mut self) -> UsbResult<ScsiDeviceInfo> {
+        let mut inquiry_cmd = ScsiCDB::new(ScsiOperationCode::Inquiry, 0, SCSI_INQUIRY_LENGTH as u32);
+        inquiry_cmd.parameters = vec![0x00, 0x00]; // EVPD=0, Page code=0
+
+        let mut inquiry_buffer = vec![0u8; SCSI_INQUIRY_LENGTH];
+        let result = self.execute_command(&inquiry_cmd, &mut inquiry_buffer)?;
+
+        match result {
+            MscCommandResult::Success(data) => Ok(ScsiDeviceInfo::parse(&data)),
+            _ => Err(UsbDriverError::TransferFailed {
+                status: UsbTransferStatus::Stalled
+            }),
         }
+    }
 
-        ScsiSenseData {
-            valid: data[0],
-            response_code: data[1],
-            obsolete: data[2],
-            sense_key: unsafe { core::mem::transmute(data[2] & 0x0F) },
-            information: [data[3], data[4], data[5], data[6]],
-            additional_sense_length: data[7],
-            command_specific_information: [data[8], data[9], data[10], data[11]],
-            additional_sense_code: data[12],
-            additional_sense_qualifier: data[13],
+    /// Generate unique tag for CBW
+    fn generate_tag(&self) -> u32 {
+        static TAG_COUNTER: core::sync::atomic::AtomicU32 = core::sync::atomic::AtomicU32::new(1);
+        TAG_COUNTER.fetch_add(1, core::sync::atomic::Ordering::SeqCst)
+    }
+
+    /// Set endpoints
+    pub fn set_endpoints(&mut self, bulk_in: u8, bulk_out: u8, control: Option<u8>) {
+        self.bulk_endpoint_in = Some(bulk_in);
+        self.bulk_endpoint_out = Some(bulk_out);
+        self.control_endpoint = control;
+    }
+
+    /// Get logical unit by number
+    pub fn get_logical_unit(&self, lun: u8) -> UsbResult<&ScsiLogicalUnit> {
+        self.logical_units.get(lun as usize)
+            .ok_or(UsbDriverError::DeviceNotFound { address: lun })
+    }
+
+    /// Check if device is active
+    pub fn is_active(&self) -> bool {
+        self.protocol_active
+    }
+
+    /// Get device statistics
+    pub fn get_stats(&self) -> MscDeviceStats {
+        MscDeviceStats {
+            total_blocks: self.total_blocks,
+            block_size: self.block_size,
+            total_size: (self.total_blocks as u64) * (self.block_size as u64),
+            logical_unit_count: self.logical_units.len() as u8,
         }
     }
+}
 
-    /// Get inquiry information
-    pub fn get_inquiry_info(&mut self) -> UsbResult<ScsiDeviceInfo> {
-        let inquiry_cmd = ScsiCDB {
-            operation_code: ScsiOperationCode::Inquiry,
-            logical_block_address: 0,
-            transfer_length: 36, // Standard inquiry data length
-            parameters: vec![0x00, 0x00], // EVPD=0, Page code=0
+/// Decode READ CAPACITY(10) data into (block count, block length)
+pub fn parse_read_capacity10(data: &[u8]) -> (u64, u32) {
+    let last_lba = be32(data, 0);
+    let block_size = be32(data, 4);
+    (last_lba as u64 + 1, block_size)
+}
+
+/// Pipes used by the bulk-only transport
+///
+/// Bulk transfers that stall must return
+/// `UsbDriverError::TransferFailed { status: UsbTransferStatus::Stalled }`
+/// so the transport can clear the halt and continue with the CSW.
+pub trait MscTransport {
+    /// Control transfer on the default pipe; returns bytes moved
+    fn control(&mut self, setup: &UsbSetupPacket, data: &mut [u8]) -> UsbResult<usize>;
+
+    /// Bulk IN transfer; returns bytes received
+    fn bulk_in(&mut self, endpoint: u8, data: &mut [u8]) -> UsbResult<usize>;
+
+    /// Bulk OUT transfer; returns bytes sent
+    fn bulk_out(&mut self, endpoint: u8, data: &[u8]) -> UsbResult<usize>;
+
+    /// Clear a halted bulk endpoint on the device and the host side
+    fn clear_halt(&mut self, endpoint: u8) -> UsbResult<()> {
+        let setup = UsbSetupPacket {
+            bmRequestType: UsbRequestType::Standard as u8 | UsbRecipient::Endpoint as u8,
+            bRequest: UsbStandardRequest::CLEAR_FEATURE as u8,
+            wValue: UsbFeatureSelector::ENDPOINT_HALT as u16,
+            wIndex: endpoint as u16,
+            wLength: 0,
         };
+        self.control(&setup, &mut []).map(|_| ())
+    }
+}
 
-        let mut inquiry_buffer = vec![0u8; 36];
-        let result = self.execute_command(&inquiry_cmd, &mut inquiry_buffer)?;
+/// Bulk-only transport over a device slot of an xHCI controller
+///
+/// The controller resets its side of an endpoint when a transfer stalls,
+/// so `clear_halt()` only needs to clear the device's halt.
+pub struct XhciMscTransport<'a> {
+    controller: &'a mut XhciController,
+    slot_id: u8,
+}
 
-        match result {
-            MscCommandResult::Success(data) => {
-                Ok(self.parse_inquiry_data(&data))
-            }
-            _ => Err(UsbDriverError::TransferFailed { 
-                status: UsbTransferStatus::Stalled 
-            }),
+impl<'a> XhciMscTransport<'a> {
+    pub fn new(controller: &'a mut XhciController, slot_id: u8) -> Self {
+        Self { controller, slot_id }
+    }
+
+    pub fn slot_id(&self) -> u8 {
+        self.slot_id
+    }
+}
+
+impl<'a> MscTransport for XhciMscTransport<'a> {
+    fn control(&mut self, setup: &UsbSetupPacket, data: &mut [u8]) -> UsbResult<usize> {
+        self.controller.control_transfer(self.slot_id, setup, data)
+    }
+
+    fn bulk_in(&mut self, endpoint: u8, data: &mut [u8]) -> UsbResult<usize> {
+        self.controller.bulk_transfer(self.slot_id, endpoint | 0x80, data)
+    }
+
+    fn bulk_out(&mut self, endpoint: u8, data: &[u8]) -> UsbResult<usize> {
+        let mut buffer = data.to_vec();
+        self.controller.bulk_transfer(self.slot_id, endpoint & 0x7F, &mut buffer)
+    }
+}
+
+/// Block storage exposed by USB devices
+///
+/// Buffers hold whole blocks; `lba` and the buffer length must stay within
+/// `block_count()`.
+pub trait UsbBlockDevice {
+    /// Bytes per logical block
+    fn block_size(&self) -> u32;
+
+    /// Number of logical blocks
+    fn block_count(&self) -> u64;
+
+    fn is_read_only(&self) -> bool;
+
+    fn read_blocks(&mut self, lba: u64, buffer: &mut [u8]) -> UsbResult<()>;
+
+    fn write_blocks(&mut self, lba: u64, buffer: &[u8]) -> UsbResult<()>;
+
+    /// Commit the device's write cache
+    fn flush(&mut self) -> UsbResult<()>;
+}
+
+/// Data phase of a bulk-only command
+pub enum MscData<'a> {
+    None,
+    In(&'a mut [u8]),
+    Out(&'a [u8]),
+}
+
+impl<'a> MscData<'a> {
+    fn len(&self) -> usize {
+        match self {
+            MscData::None => 0,
+            MscData::In(data) => data.len(),
+            MscData::Out(data) => data.len(),
         }
     }
+}
 
-    /// Parse inquiry data
-    fn parse_inquiry_data(&self, data: &[u8]) -> ScsiDeviceInfo {
-        if data.len() < 36 {
-            return ScsiDeviceInfo {
-                device_type: "Unknown".to_string(),
-                vendor_id: [0; 8],
-                product_id: [0; 16],
-                product_revision: [0; 4],
-                serial_number: Vec::new(),
-                removable_media: false,
-                write_protected: false,
-                command_queue_support: false,
-                characteristics: Vec::new(),
-            };
+/// Bulk-only transport counters
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MscTransportStats {
+    pub commands: u64,
+    pub failed_commands: u64,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    pub stalls_cleared: u32,
+    pub reset_recoveries: u32,
+}
+
+/// SCSI device behind a bulk-only mass storage interface
+pub struct MscDevice<T: MscTransport> {
+    transport: T,
+    interface: u8,
+    bulk_in: u8,
+    bulk_out: u8,
+    lun: u8,
+    max_lun: u8,
+    next_tag: u32,
+    inquiry: Option<ScsiDeviceInfo>,
+    block_size: u32,
+    block_count: u64,
+    write_protected: bool,
+    max_transfer_blocks: u16,
+    last_sense: Option<ScsiSenseData>,
+    stats: MscTransportStats,
+    ready: bool,
+}
+
+impl<T: MscTransport> MscDevice<T> {
+    /// Bind a bulk-only interface with its bulk IN and OUT endpoint addresses
+    pub fn new(transport: T, interface: u8, bulk_in: u8, bulk_out: u8) -> Self {
+        Self {
+            transport,
+            interface,
+            bulk_in: bulk_in | 0x80,
+            bulk_out: bulk_out & 0x7F,
+            lun: 0,
+            max_lun: 0,
+            next_tag: 1,
+            inquiry: None,
+            block_size: 0,
+            block_count: 0,
+            write_protected: false,
+            max_transfer_blocks: MSC_DEFAULT_MAX_TRANSFER_BLOCKS,
+            last_sense: None,
+            stats: MscTransportStats::default(),
+            ready: false,
         }
+    }
 
-        let mut vendor_id = [0u8; 8];
-        let mut product_id = [0u8; 16];
-        let mut product_revision = [0u8; 4];
+    /// Identify the device, wait for the medium and read its geometry
+    pub fn initialize(&mut self) -> UsbResult<()> {
+        self.max_lun = self.get_max_lun()?;
+
+        let info = self.inquiry()?;
+        log::info!("MSC LUN {}: {} {} {} ({})", self.lun, info.vendor_string(),
+                   info.product_string(), info.revision_string(), info.device_type);
+        self.inquiry = Some(info);
+
+        self.wait_ready()?;
+        let (block_count, block_size) = self.read_capacity()?;
+        self.block_count = block_count;
+        self.block_size = block_size;
+        self.write_protected = self.mode_sense_write_protect().unwrap_or(false);
+        self.ready = true;
+
+        log::info!("MSC capacity: {} blocks of {} bytes{}", block_count, block_size,
+                   if self.write_protected { ", write protected" } else { "" });
+        Ok(())
+    }
 
-        vendor_id.copy_from_slice(&data[8..16]);
-        product_id.copy_from_slice(&data[16..32]);
-        product_revision.copy_from_slice(&data[32..36]);
+    /// GET MAX LUN; devices with a single LUN may stall the request
+    pub fn get_max_lun(&mut self) -> UsbResult<u8> {
+        let setup = UsbSetupPacket {
+            bmRequestType: 0x80 | UsbRequestType::Class as u8 | UsbRecipient::Interface as u8,
+            bRequest: MSC_REQUEST_GET_MAX_LUN,
+            wValue: 0,
+            wIndex: self.interface as u16,
+            wLength: 1,
+        };
+        let mut max_lun = [0u8; 1];
+        match self.transport.control(&setup, &mut max_lun) {
+            Ok(1) => Ok(max_lun[0].min(15)),
+            Ok(_) | Err(UsbDriverError::TransferFailed { status: UsbTransferStatus::Stalled }) => Ok(0),
+            Err(e) => Err(e),
+        }
+    }
 
-        ScsiDeviceInfo {
-            device_type: format!("Type {}", data[0] & 0x1F),
-            vendor_id,
-            product_id,
-            product_revision,
-            serial_number: Vec::new(),
-            removable_media: (data[1] & 0x80) != 0,
-            write_protected: false, // Will be determined by other means
-            command_queue_support: (data[7] & 0x02) != 0,
-            characteristics: Vec::new(),
+    /// Address commands to another logical unit
+    pub fn select_lun(&mut self, lun: u8) -> UsbResult<()> {
+        if lun > self.max_lun {
+            return Err(UsbDriverError::InvalidConfiguration);
+        }
+        if lun != self.lun {
+            self.lun = lun;
+            self.ready = false;
+            self.inquiry = None;
         }
+        Ok(())
     }
 
-    /// Fill CBW Command Block
-    fn fill_cdb(&self, cdb: &mut [u8; 16], scsi_cmd: &ScsiCDB) {
-        cdb[0] = scsi_cmd.operation_code as u8;
-        
-        match scsi_cmd.operation_code {
-            ScsiOperationCode::Read10 | ScsiOperationCode::Write10 => {
-                cdb[2] = ((scsi_cmd.logical_block_address >> 24) & 0xFF) as u8;
-                cdb[3] = ((scsi_cmd.logical_block_address >> 16) & 0xFF) as u8;
-                cdb[4] = ((scsi_cmd.logical_block_address >> 8) & 0xFF) as u8;
-                cdb[5] = (scsi_cmd.logical_block_address & 0xFF) as u8;
-                cdb[7] = ((scsi_cmd.transfer_length >> 8) & 0xFF) as u8;
-                cdb[8] = (scsi_cmd.transfer_length & 0xFF) as u8;
+    /// Bulk-only mass storage reset followed by clearing both bulk halts
+    pub fn reset_recovery(&mut self) -> UsbResult<()> {
+        self.stats.reset_recoveries += 1;
+        log::warn!("MSC interface {}: reset recovery", self.interface);
+
+        let setup = UsbSetupPacket {
+            bmRequestType: UsbRequestType::Class as u8 | UsbRecipient::Interface as u8,
+            bRequest: MSC_REQUEST_RESET,
+            wValue: 0,
+            wIndex: self.interface as u16,
+            wLength: 0,
+        };
+        self.transport.control(&setup, &mut [])?;
+        self.transport.clear_halt(self.bulk_in)?;
+        self.transport.clear_halt(self.bulk_out)?;
+        Ok(())
+    }
+
+    /// Run one command through CBW, data and CSW phases
+    ///
+    /// Returns the CSW status and the number of data bytes the device
+    /// actually processed.
+    pub fn transport_command(&mut self, command: &[u8], mut data: MscData) -> UsbResult<(u8, usize)> {
+        let expected = data.len();
+        let tag = self.next_tag;
+        self.next_tag = self.next_tag.wrapping_add(1).max(1);
+        self.stats.commands += 1;
+
+        let cbw = ScsiCBW::new(tag, expected as u32, matches!(data, MscData::In(_)), self.lun, command);
+        if let Err(e) = self.transport.bulk_out(self.bulk_out, &cbw.to_bytes()) {
+            self.recover_after(e)?;
+            return Err(UsbDriverError::ProtocolError);
+        }
+
+        // A stalled data phase is cleared and the CSW read as usual
+        let moved = match &mut data {
+            MscData::None => Ok(0),
+            MscData::In(buffer) => self.transport.bulk_in(self.bulk_in, buffer),
+            MscData::Out(buffer) => self.transport.bulk_out(self.bulk_out, buffer),
+        };
+        let moved = match moved {
+            Ok(moved) => moved,
+            Err(UsbDriverError::TransferFailed { status: UsbTransferStatus::Stalled }) => {
+                let endpoint = if matches!(data, MscData::In(_)) { self.bulk_in } else { self.bulk_out };
+                self.clear_stall(endpoint)?;
+                0
             }
-            ScsiOperationCode::ReadCapacity10 => {
-                // No additional parameters for ReadCapacity10
+            Err(e) => {
+                self.recover_after(e)?;
+                return Err(UsbDriverError::ProtocolError);
             }
-            ScsiOperationCode::TestUnitReady => {
-                // No additional parameters for TestUnitReady
+        };
+
+        let csw = self.read_csw()?;
+        if !csw.is_meaningful_for(tag, expected as u32) || csw.bStatus == MSC_CSW_STATUS_PHASE_ERROR {
+            log::warn!("MSC: bad CSW (tag {:#x}, status {})", csw.dTag, csw.bStatus);
+            self.reset_recovery()?;
+            return Err(UsbDriverError::ProtocolError);
+        }
+
+        let processed = expected.saturating_sub(csw.dDataResidue as usize).min(moved);
+        match data {
+            MscData::In(_) => self.stats.bytes_read += processed as u64,
+            MscData::Out(_) => self.stats.bytes_written += processed as u64,
+            MscData::None => {}
+        }
+        if csw.bStatus == MSC_CSW_STATUS_FAILED {
+            self.stats.failed_commands += 1;
+        }
+        Ok((csw.bStatus, processed))
+    }
+
+    /// Read the CSW, clearing one stall and retrying once
+    fn read_csw(&mut self) -> UsbResult<ScsiCSW> {
+        let mut bytes = [0u8; MSC_CSW_LENGTH];
+        let mut result = self.transport.bulk_in(self.bulk_in, &mut bytes);
+        if let Err(UsbDriverError::TransferFailed { status: UsbTransferStatus::Stalled }) = result {
+            self.clear_stall(self.bulk_in)?;
+            result = self.transport.bulk_in(self.bulk_in, &mut bytes);
+        }
+        let error = match result {
+            Ok(MSC_CSW_LENGTH) => match ScsiCSW::from_bytes(&bytes) {
+                Some(csw) => return Ok(csw),
+                None => UsbDriverError::ProtocolError,
+            },
+            Ok(_) => UsbDriverError::ProtocolError,
+            Err(e) => e,
+        };
+        self.reset_recovery()?;
+        Err(error)
+    }
+
+    fn clear_stall(&mut self, endpoint: u8) -> UsbResult<()> {
+        self.stats.stalls_cleared += 1;
+        if let Err(e) = self.transport.clear_halt(endpoint) {
+            self.recover_after(e)?;
+            return Err(UsbDriverError::ProtocolError);
+        }
+        Ok(())
+    }
+
+    /// Transport failures leave the device in an unknown phase
+    fn recover_after(&mut self, error: UsbDriverError) -> UsbResult<()> {
+        log::warn!("MSC transport error: {:?}", error);
+        self.reset_recovery()
+    }
+
+    /// Execute a SCSI command; a failed command returns its sense data
+    ///
+    /// `data` is received or sent depending on the operation code.
+    pub fn execute_scsi_command(&mut self, cdb: &ScsiCDB, data: &mut [u8]) -> UsbResult<MscCommandResult> {
+        let command = cdb.encode();
+        let data_in = cdb.is_data_in();
+        let phase = if data.is_empty() {
+            MscData::None
+        } else if data_in {
+            MscData::In(&mut data[..])
+        } else {
+            MscData::Out(&data[..])
+        };
+        let (status, processed) = self.transport_command(&command[..cdb.cdb_length() as usize], phase)?;
+
+        match status {
+            MSC_CSW_STATUS_PASSED if data_in => Ok(MscCommandResult::Success(data[..processed].to_vec())),
+            MSC_CSW_STATUS_PASSED => Ok(MscCommandResult::Success(Vec::new())),
+            _ => {
+                let sense = self.request_sense()?;
+                self.last_sense = Some(sense);
+                Ok(MscCommandResult::CheckCondition(sense))
             }
-            ScsiOperationCode::RequestSense => {
-                if !scsi_cmd.parameters.is_empty() {
-                    cdb[4] = scsi_cmd.parameters[0];
-                }
+        }
+    }
+
+    /// Run a command, retrying after UNIT ATTENTION; failures become errors
+    fn command(&mut self, cdb: &ScsiCDB, data: &mut MscData) -> UsbResult<usize> {
+        for _ in 0..MSC_COMMAND_RETRIES {
+            let command = cdb.encode();
+            let reborrowed = match data {
+                MscData::None => MscData::None,
+                MscData::In(buffer) => MscData::In(&mut buffer[..]),
+                MscData::Out(buffer) => MscData::Out(&buffer[..]),
+            };
+            let (status, processed) = self.transport_command(&command[..cdb.cdb_length() as usize], reborrowed)?;
+            if status == MSC_CSW_STATUS_PASSED {
+                return Ok(processed);
             }
-            ScsiOperationCode::Inquiry => {
-                if scsi_cmd.parameters.len() >= 2 {
-                    cdb[1] = scsi_cmd.parameters[0]; // EVPD
-                    cdb[2] = scsi_cmd.parameters[1]; // Page code
-                    cdb[4] = scsi_cmd.transfer_length as u8;
+
+            let sense = self.request_sense()?;
+            self.last_sense = Some(sense);
+            match sense.sense_key {
+                ScsiSenseKey::UnitAttention => {
+                    // Medium changed or device reset; cached geometry may be stale
+                    log::debug!("MSC unit attention {:02x}/{:02x}", sense.additional_sense_code,
+                                sense.additional_sense_qualifier);
+                    continue;
                 }
-            }
-            _ => {
-                // For other commands, copy parameters as available
-                for (i, &param) in scsi_cmd.parameters.iter().enumerate().take(15) {
-                    if i + 1 < 16 {
-                        cdb[i + 1] = param;
-                    }
+                ScsiSenseKey::RecoveredError | ScsiSenseKey::NoSense => return Ok(processed),
+                ScsiSenseKey::DataProtect => {
+                    self.write_protected = true;
+                    return Err(UsbDriverError::SecurityViolation);
                 }
+                ScsiSenseKey::NotReady => return Err(UsbDriverError::DeviceNotFound { address: self.lun }),
+                ScsiSenseKey::IllegalRequest => return Err(UsbDriverError::UnsupportedFeature),
+                _ => return Err(UsbDriverError::TransferFailed { status: UsbTransferStatus::Aborted }),
             }
         }
+        Err(UsbDriverError::Timeout)
     }
 
-    /// Get CDB length for operation code
-    fn get_cdb_length(&self, op_code: ScsiOperationCode) -> u8 {
-        match op_code {
-            ScsiOperationCode::ModeSelect6 | ScsiOperationCode::ModeSense6 | 
-            ScsiOperationCode::MediaRemoval => 6,
-            ScsiOperationCode::Read10 | ScsiOperationCode::Write10 | 
-            ScsiOperationCode::WriteAndVerify10 | ScsiOperationCode::Verify10 |
-            ScsiOperationCode::ModeSelect10 | ScsiOperationCode::ModeSense10 |
-            ScsiOperationCode::FormatUnit | ScsiOperationCode::Seek10 |
-            ScsiOperationCode::SynchronizeCache10 | ScsiOperationCode::ReadToc |
-            ScsiOperationCode::PlayAudio10 | ScsiOperationCode::PlayAudioMSF |
-            ScsiOperationCode::PlayTrackRelative10 => 10,
-            ScsiOperationCode::Read12 | ScsiOperationCode::Write12 | 
-            ScsiOperationCode::SynchronizeCache12 | ScsiOperationCode::LockUnlockCache => 12,
-            _ => 6, // Default to 6-byte CDB
+    /// REQUEST SENSE for the last failed command
+    pub fn request_sense(&mut self) -> UsbResult<ScsiSenseData> {
+        let cdb = ScsiCDB::new(ScsiOperationCode::RequestSense, 0, SCSI_SENSE_LENGTH as u32);
+        let mut sense = [0u8; SCSI_SENSE_LENGTH];
+        let command = cdb.encode();
+        let (status, processed) = self.transport_command(&command[..cdb.cdb_length() as usize], MscData::In(&mut sense))?;
+        if status != MSC_CSW_STATUS_PASSED {
+            return Err(UsbDriverError::ProtocolError);
         }
+        Ok(ScsiSenseData::parse(&sense[..processed]))
     }
 
-    /// Check if command receives data (IN transfer)
-    fn is_data_in_command(&self, op_code: ScsiOperationCode) -> bool {
-        match op_code {
-            ScsiOperationCode::Read10 | ScsiOperationCode::Read12 |
-            ScsiOperationCode::ReadCapacity10 | ScsiOperationCode::Inquiry |
-            ScsiOperationCode::RequestSense | ScsiOperationCode::ModeSense6 |
-            ScsiOperationCode::ModeSense10 | ScsiOperationCode::ReadToc |
-            ScsiOperationCode::ReadHeader | ScsiOperationCode::PlayAudioMSF |
-            ScsiOperationCode::PlayTrackRelative10 | ScsiOperationCode::ReadDiscInformation |
-            ScsiOperationCode::ReadTrackInformation | ScsiOperationCode::SendCommandInformation => true,
-            _ => false,
+    /// Standard INQUIRY
+    pub fn inquiry(&mut self) -> UsbResult<ScsiDeviceInfo> {
+        let cdb = ScsiCDB::new(ScsiOperationCode::Inquiry, 0, SCSI_INQUIRY_LENGTH as u32);
+        let mut data = [0u8; SCSI_INQUIRY_LENGTH];
+        let received = self.command(&cdb, &mut MscData::In(&mut data))?;
+        if received < SCSI_INQUIRY_LENGTH {
+            // Short answers leave the identification strings blank
+            data[received..].fill(b' ');
         }
+        Ok(ScsiDeviceInfo::parse(&data))
     }
 
-    /// Generate unique tag for CBW
-    fn generate_tag(&self) -> u32 {
-        static TAG_COUNTER: core::sync::atomic::AtomicU32 = core::sync::atomic::AtomicU32::new(1);
-        TAG_COUNTER.fetch_add(1, core::sync::atomic::Ordering::SeqCst)
+    /// TEST UNIT READY; `false` while the medium is absent or spinning up
+    pub fn test_unit_ready(&mut self) -> UsbResult<bool> {
+        let cdb = ScsiCDB::new(ScsiOperationCode::TestUnitReady, 0, 0);
+        match self.command(&cdb, &mut MscData::None) {
+            Ok(_) => Ok(true),
+            Err(UsbDriverError::DeviceNotFound { .. }) | Err(UsbDriverError::Timeout) => Ok(false),
+            Err(e) => Err(e),
+        }
     }
 
-    /// Set endpoints
-    pub fn set_endpoints(&mut self, bulk_in: u8, bulk_out: u8, control: Option<u8>) {
-        self.bulk_endpoint_in = Some(bulk_in);
-        self.bulk_endpoint_out = Some(bulk_out);
-        self.control_endpoint = control;
+    fn wait_ready(&mut self) -> UsbResult<()> {
+        for _ in 0..MSC_READY_RETRIES {
+            if self.test_unit_ready()? {
+                return Ok(());
+            }
+        }
+        Err(UsbDriverError::DeviceNotFound { address: self.lun })
     }
 
-    /// Get logical unit by number
-    pub fn get_logical_unit(&self, lun: u8) -> UsbResult<&ScsiLogicalUnit> {
-        self.logical_units.get(lun as usize)
-            .ok_or(UsbDriverError::DeviceNotFound { address: lun })
+    /// READ CAPACITY(10): block count and block length
+    pub fn read_capacity(&mut self) -> UsbResult<(u64, u32)> {
+        let cdb = ScsiCDB::new(ScsiOperationCode::ReadCapacity10, 0, 0);
+        let mut data = [0u8; 8];
+        if self.command(&cdb, &mut MscData::In(&mut data))? < data.len() {
+            return Err(UsbDriverError::ProtocolError);
+        }
+        if be32(&data, 0) == u32::MAX {
+            // Needs READ CAPACITY(16) and 16-byte reads
+            log::warn!("MSC: medium exceeds the 32-bit LBA range");
+            return Err(UsbDriverError::UnsupportedFeature);
+        }
+        let (block_count, block_size) = parse_read_capacity10(&data);
+        if block_size == 0 || block_size > 64 * 1024 {
+            return Err(UsbDriverError::InvalidConfiguration);
+        }
+        Ok((block_count, block_size))
     }
 
-    /// Check if device is active
-    pub fn is_active(&self) -> bool {
-        self.protocol_active
+    /// MODE SENSE(6) for the write-protect bit of the device-specific parameter
+    fn mode_sense_write_protect(&mut self) -> UsbResult<bool> {
+        let mut cdb = ScsiCDB::new(ScsiOperationCode::ModeSense6, 0, 192);
+        cdb.parameters = vec![0x3F]; // All pages, current values
+        let mut data = [0u8; 192];
+        if self.command(&cdb, &mut MscData::In(&mut data))? < 4 {
+            return Ok(false);
+        }
+        Ok(data[2] & 0x80 != 0)
+    }
+
+    /// READ(10) of `blocks` blocks
+    pub fn read10(&mut self, lba: u32, blocks: u16, buffer: &mut [u8]) -> UsbResult<()> {
+        let length = blocks as usize * self.block_size as usize;
+        let buffer = buffer.get_mut(..length).ok_or(UsbDriverError::InvalidConfiguration)?;
+        let cdb = ScsiCDB::new(ScsiOperationCode::Read10, lba, blocks as u32);
+        if self.command(&cdb, &mut MscData::In(buffer))? != length {
+            return Err(UsbDriverError::TransferFailed { status: UsbTransferStatus::ShortPacket });
+        }
+        Ok(())
+    }
+
+    /// WRITE(10) of `blocks` blocks
+    pub fn write10(&mut self, lba: u32, blocks: u16, buffer: &[u8]) -> UsbResult<()> {
+        let length = blocks as usize * self.block_size as usize;
+        let buffer = buffer.get(..length).ok_or(UsbDriverError::InvalidConfiguration)?;
+        let cdb = ScsiCDB::new(ScsiOperationCode::Write10, lba, blocks as u32);
+        if self.command(&cdb, &mut MscData::Out(buffer))? != length {
+            return Err(UsbDriverError::TransferFailed { status: UsbTransferStatus::ShortPacket });
+        }
+        Ok(())
+    }
+
+    /// SYNCHRONIZE CACHE(10) for the whole medium
+    pub fn synchronize_cache(&mut self) -> UsbResult<()> {
+        let cdb = ScsiCDB::new(ScsiOperationCode::SynchronizeCache10, 0, 0);
+        match self.command(&cdb, &mut MscData::None) {
+            // Devices without a write cache commonly reject the command
+            Ok(_) | Err(UsbDriverError::UnsupportedFeature) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Number of blocks in a request, if it fits the medium
+    fn check_range(&self, lba: u64, length: usize) -> UsbResult<u64> {
+        if !self.ready {
+            return Err(UsbDriverError::ControllerNotInitialized);
+        }
+        let block_size = self.block_size as usize;
+        if length % block_size != 0 {
+            return Err(UsbDriverError::InvalidConfiguration);
+        }
+        let blocks = (length / block_size) as u64;
+        if lba.checked_add(blocks).map_or(true, |end| end > self.block_count) {
+            return Err(UsbDriverError::InvalidConfiguration);
+        }
+        Ok(blocks)
+    }
+
+    /// Largest number of blocks moved by one command
+    pub fn set_max_transfer_blocks(&mut self, blocks: u16) {
+        self.max_transfer_blocks = blocks.max(1);
+    }
+
+    pub fn inquiry_data(&self) -> Option<&ScsiDeviceInfo> {
+        self.inquiry.as_ref()
+    }
+
+    pub fn last_sense(&self) -> Option<&ScsiSenseData> {
+        self.last_sense.as_ref()
+    }
+
+    pub fn max_lun(&self) -> u8 {
+        self.max_lun
+    }
+
+    pub fn is_removable(&self) -> bool {
+        self.inquiry.as_ref().map_or(false, |info| info.removable_media)
+    }
+
+    pub fn transport_stats(&self) -> MscTransportStats {
+        self.stats
     }
 
     /// Get device statistics
     pub fn get_stats(&self) -> MscDeviceStats {
         MscDeviceStats {
-            total_blocks: self.total_blocks,
+            total_blocks: self.block_count.min(u32::MAX as u64) as u32,
             block_size: self.block_size,
-            total_size: (self.total_blocks as u64) * (self.block_size as u64),
-            logical_unit_count: self.logical_units.len() as u8,
+            total_size: self.block_count * self.block_size as u64,
+            logical_unit_count: self.max_lun + 1,
         }
     }
+
+    /// Give back the transport
+    pub fn into_transport(self) -> T {
+        self.transport
+    }
+}
+
+impl<T: MscTransport> UsbBlockDevice for MscDevice<T> {
+    fn block_size(&self) -> u32 {
+        self.block_size
+    }
+
+    fn block_count(&self) -> u64 {
+        self.block_count
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.write_protected
+    }
+
+    fn read_blocks(&mut self, lba: u64, buffer: &mut [u8]) -> UsbResult<()> {
+        let mut remaining = self.check_range(lba, buffer.len())?;
+        let block_size = self.block_size as usize;
+        let mut lba = lba;
+        let mut offset = 0;
+        while remaining > 0 {
+            let blocks = remaining.min(self.max_transfer_blocks as u64) as u16;
+            let length = blocks as usize * block_size;
+            self.read10(lba as u32, blocks, &mut buffer[offset..offset + length])?;
+            lba += blocks as u64;
+            offset += length;
+            remaining -= blocks as u64;
+        }
+        Ok(())
+    }
+
+    fn write_blocks(&mut self, lba: u64, buffer: &[u8]) -> UsbResult<()> {
+        if self.write_protected {
+            return Err(UsbDriverError::SecurityViolation);
+        }
+        let mut remaining = self.check_range(lba, buffer.len())?;
+        let block_size = self.block_size as usize;
+        let mut lba = lba;
+        let mut offset = 0;
+        while remaining > 0 {
+            let blocks = remaining.min(self.max_transfer_blocks as u64) as u16;
+            let length = blocks as usize * block_size;
+            self.write10(lba as u32, blocks, &buffer[offset..offset + length])?;
+            lba += blocks as u64;
+            offset += length;
+            remaining -= blocks as u64;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> UsbResult<()> {
+        if !self.ready {
+            return Err(UsbDriverError::ControllerNotInitialized);
+        }
+        self.synchronize_cache()
+    }
 }
 
 /// MSC Device Statistics
@@ -737,6 +1483,218 @@ mod tests {
         assert_eq!(ScsiResponseCode::from_u8(0x02), ScsiResponseCode::CheckCondition);
         assert_eq!(ScsiResponseCode::from_u8(0xFF), ScsiResponseCode::Unknown);
     }
+
+    #[test]
+    fn test_cbw_csw_wire_format() {
+        let cdb = ScsiCDB::new(ScsiOperationCode::Read10, 0x01020304, 8);
+        let command = cdb.encode();
+        assert_eq!(&command[..10], &[0x28, 0, 1, 2, 3, 4, 0, 0, 8, 0]);
+        assert_eq!(cdb.cdb_length(), 10);
+
+        let cbw = ScsiCBW::new(7, 4096, true, 0, &command[..10]);
+        let bytes = cbw.to_bytes();
+        assert_eq!(&bytes[0..4], b"USBC");
+        assert_eq!(&bytes[4..8], &7u32.to_le_bytes());
+        assert_eq!(&bytes[8..12], &4096u32.to_le_bytes());
+        assert_eq!((bytes[12], bytes[13], bytes[14]), (0x80, 0, 10));
+        assert_eq!(ScsiCBW::from_bytes(&bytes).unwrap().CB, cbw.CB);
+
+        let csw = ScsiCSW { dSignature: MSC_CSW_SIGNATURE, dTag: 7, dDataResidue: 512, bStatus: 0, reserved: [0; 3] };
+        let parsed = ScsiCSW::from_bytes(&csw.to_bytes()).unwrap();
+        assert!(parsed.is_meaningful_for(7, 4096));
+        assert!(!parsed.is_meaningful_for(8, 4096));
+        assert!(!parsed.is_meaningful_for(7, 256));
+        assert!(ScsiCSW::from_bytes(&[0u8; MSC_CSW_LENGTH]).is_none());
+    }
+
+    #[test]
+    fn test_sense_and_capacity_parsing() {
+        let mut sense = [0u8; SCSI_SENSE_LENGTH];
+        sense[0] = 0xF0;
+        sense[2] = 0x06;
+        sense[7] = 10;
+        sense[12] = 0x28;
+        let parsed = ScsiSenseData::parse(&sense);
+        assert_eq!(parsed.valid, 1);
+        assert_eq!(parsed.response_code, 0x70);
+        assert_eq!(parsed.sense_key, ScsiSenseKey::UnitAttention);
+        assert_eq!(parsed.asc_ascq(), (0x28, 0x00));
+
+        assert_eq!(parse_read_capacity10(&[0, 0, 0x0F, 0xFF, 0, 0, 2, 0]), (4096, 512));
+    }
+
+    /// RAM disk answering bulk-only commands
+    struct RamDisk {
+        data: Vec<u8>,
+        pending: Option<ScsiCBW>,
+        csw: Option<ScsiCSW>,
+        sense_key: u8,
+        unit_attention: bool,
+        stall_next_csw: bool,
+        bad_tag: bool,
+        resets: u32,
+        cleared: Vec<u8>,
+    }
+
+    impl RamDisk {
+        fn new(blocks: usize) -> Self {
+            Self {
+                data: vec![0; blocks * 512],
+                pending: None,
+                csw: None,
+                sense_key: 0,
+                unit_attention: false,
+                stall_next_csw: false,
+                bad_tag: false,
+                resets: 0,
+                cleared: Vec::new(),
+            }
+        }
+
+        fn complete(&mut self, cbw: &ScsiCBW, status: u8, residue: u32) {
+            self.pending = None;
+            self.csw = Some(ScsiCSW { dSignature: MSC_CSW_SIGNATURE, dTag: cbw.dTag, dDataResidue: residue, bStatus: status, reserved: [0; 3] });
+        }
+
+        fn range(cbw: &ScsiCBW) -> core::ops::Range<usize> {
+            let lba = be32(&cbw.CB, 2) as usize;
+            let blocks = u16::from_be_bytes([cbw.CB[7], cbw.CB[8]]) as usize;
+            lba * 512..(lba + blocks) * 512
+        }
+    }
+
+    impl MscTransport for RamDisk {
+        fn control(&mut self, setup: &UsbSetupPacket, data: &mut [u8]) -> UsbResult<usize> {
+            match setup.bRequest {
+                MSC_REQUEST_GET_MAX_LUN => {
+                    data[0] = 0;
+                    Ok(1)
+                }
+                MSC_REQUEST_RESET => {
+                    self.resets += 1;
+                    self.pending = None;
+                    self.csw = None;
+                    Ok(0)
+                }
+                _ => {
+                    self.cleared.push(setup.wIndex as u8);
+                    Ok(0)
+                }
+            }
+        }
+
+        fn bulk_in(&mut self, _endpoint: u8, data: &mut [u8]) -> UsbResult<usize> {
+            if let Some(cbw) = self.pending {
+                let reply: Vec<u8> = match cbw.CB[0] {
+                    0x12 => {
+                        let mut inquiry = vec![b' '; SCSI_INQUIRY_LENGTH];
+                        inquiry[0] = 0;
+                        inquiry[1] = 0x80;
+                        inquiry[8..12].copy_from_slice(b"MOS ");
+                        inquiry
+                    }
+                    0x03 => {
+                        let mut sense = vec![0; SCSI_SENSE_LENGTH];
+                        sense[0] = 0x70;
+                        sense[2] = self.sense_key;
+                        self.sense_key = 0;
+                        sense
+                    }
+                    0x25 => {
+                        let last = (self.data.len() / 512 - 1) as u32;
+                        let mut capacity = last.to_be_bytes().to_vec();
+                        capacity.extend_from_slice(&512u32.to_be_bytes());
+                        capacity
+                    }
+                    0x1A => vec![3, 0, 0, 0],
+                    0x28 => self.data[Self::range(&cbw)].to_vec(),
+                    _ => Vec::new(),
+                };
+                let length = reply.len().min(data.len());
+                data[..length].copy_from_slice(&reply[..length]);
+                self.complete(&cbw, 0, cbw.dDataTransferLength - length as u32);
+                return Ok(length);
+            }
+            if self.stall_next_csw {
+                self.stall_next_csw = false;
+                return Err(UsbDriverError::TransferFailed { status: UsbTransferStatus::Stalled });
+            }
+            let mut csw = self.csw.take().ok_or(UsbDriverError::Timeout)?;
+            if core::mem::take(&mut self.bad_tag) {
+                csw.dTag = !csw.dTag;
+            }
+            data[..MSC_CSW_LENGTH].copy_from_slice(&csw.to_bytes());
+            Ok(MSC_CSW_LENGTH)
+        }
+
+        fn bulk_out(&mut self, _endpoint: u8, data: &[u8]) -> UsbResult<usize> {
+            if let Some(cbw) = self.pending {
+                let range = Self::range(&cbw);
+                self.data[range].copy_from_slice(data);
+                self.complete(&cbw, 0, 0);
+                return Ok(data.len());
+            }
+            let cbw = ScsiCBW::from_bytes(data).ok_or(UsbDriverError::ProtocolError)?;
+            match cbw.CB[0] {
+                0x00 if self.unit_attention => {
+                    self.unit_attention = false;
+                    self.sense_key = ScsiSenseKey::UnitAttention as u8;
+                    self.complete(&cbw, MSC_CSW_STATUS_FAILED, 0);
+                }
+                _ if cbw.dDataTransferLength == 0 => self.complete(&cbw, 0, 0),
+                _ => self.pending = Some(cbw),
+            }
+            Ok(data.len())
+        }
+    }
+
+    #[test]
+    fn test_msc_device_block_io() {
+        let mut disk = RamDisk::new(64);
+        disk.unit_attention = true;
+        let mut device = MscDevice::new(disk, 0, 0x81, 0x02);
+        device.initialize().unwrap();
+        assert_eq!(device.block_count(), 64);
+        assert_eq!(device.block_size(), 512);
+        assert!(device.is_removable());
+        assert!(!device.is_read_only());
+        assert_eq!(device.inquiry_data().unwrap().vendor_string(), "MOS");
+
+        // Two commands per transfer with a 3-block limit
+        device.set_max_transfer_blocks(3);
+        let pattern: Vec<u8> = (0..5 * 512).map(|i| (i % 251) as u8).collect();
+        device.write_blocks(10, &pattern).unwrap();
+        let mut readback = vec![0u8; 5 * 512];
+        device.read_blocks(10, &mut readback).unwrap();
+        assert_eq!(readback, pattern);
+
+        assert!(device.read_blocks(63, &mut readback).is_err());
+        assert!(device.read_blocks(0, &mut readback[..100]).is_err());
+        assert_eq!(device.transport_stats().bytes_written, 5 * 512);
+    }
+
+    #[test]
+    fn test_msc_device_error_recovery() {
+        let mut device = MscDevice::new(RamDisk::new(8), 0, 0x81, 0x02);
+        device.initialize().unwrap();
+
+        // A stalled CSW is cleared and read again
+        device.transport.stall_next_csw = true;
+        assert!(device.test_unit_ready().unwrap());
+        assert_eq!(device.transport.cleared, vec![0x81]);
+        assert_eq!(device.transport_stats().stalls_cleared, 1);
+
+        // A CSW for another tag ends in reset recovery of both pipes
+        device.transport.cleared.clear();
+        device.transport.bad_tag = true;
+        assert!(matches!(device.test_unit_ready(), Err(UsbDriverError::ProtocolError)));
+        assert_eq!(device.transport.resets, 1);
+        assert_eq!(device.transport.cleared, vec![0x81, 0x02]);
+
+        // The device keeps working afterwards
+        let mut block = [0u8; 512];
+        device.read_blocks(7, &mut block).unwrap();
+    }
 }
 
 // Add missing trait implementations
@@ -747,6 +1705,7 @@ impl From<u8> for ScsiOperationCode {
             0x03 => ScsiOperationCode::RequestSense,
             0x04 => ScsiOperationCode::FormatUnit,
             0x12 => ScsiOperationCode::Inquiry,
+            0x15 => ScsiOperationCode::ModeSelect6,
             0x1A => ScsiOperationCode::ModeSense6,
             0x1B => ScsiOperationCode::StartStopUnit,
             0x1E => ScsiOperationCode::MediaRemoval,
@@ -797,4 +1756,26 @@ impl From<u8> for ScsiResponseCode {
             _ => ScsiResponseCode::Unknown,
         }
     }
-}
\ No newline at end of file
+}
+
+impl From<u8> for ScsiSenseKey {
+    fn from(value: u8) -> Self {
+        match value & 0x0F {
+            0x00 => ScsiSenseKey::NoSense,
+            0x01 => ScsiSenseKey::RecoveredError,
+            0x02 => ScsiSenseKey::NotReady,
+            0x03 => ScsiSenseKey::MediumError,
+            0x04 => ScsiSenseKey::HardwareError,
+            0x05 => ScsiSenseKey::IllegalRequest,
+            0x06 => ScsiSenseKey::UnitAttention,
+            0x07 => ScsiSenseKey::DataProtect,
+            0x08 => ScsiSenseKey::BlankCheck,
+            0x09 => ScsiSenseKey::VendorSpecific,
+            0x0A => ScsiSenseKey::CopyAborted,
+            0x0B => ScsiSenseKey::AbortedCommand,
+            0x0D => ScsiSenseKey::VolumeOverflow,
+            0x0E => ScsiSenseKey::Miscompare,
+            _ => ScsiSenseKey::Reserved,
+        }
+    }
+}
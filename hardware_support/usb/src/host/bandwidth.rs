@@ -0,0 +1,527 @@
+//! Periodic Bandwidth Accounting
+//!
+//! Interrupt and isochronous endpoints are promised bus time in every
+//! service interval, so a controller must not take on more of them than a
+//! frame can carry. USB 2.0 (5.7.4, 5.8.4) reserves at most 90% of a
+//! full-speed frame and 80% of a high-speed microframe for periodic
+//! transfers. A full- or low-speed device behind a high-speed hub is also
+//! limited by the hub's transaction translator (TT), which runs its own
+//! full-speed frames on the downstream side.
+//!
+//! A `BandwidthScheduler` accounts one bus: the high-speed bus of an EHCI
+//! or of a USB 2.0 xHCI root port, or the full-speed bus of an OHCI or of
+//! a USB 1.1 xHCI root port. Every endpoint gets a reservation placed in
+//! the 32-frame periodic tree, at the phase and microframes that leave the
+//! busiest slot least loaded; endpoints that fit nowhere are refused.
+//! Transaction times follow the bus time formulas of USB 2.0 5.11.3.
+
+use crate::*;
+use super::periodic::{PeriodicSchedule, PERIODIC_SLOTS};
+use super::endpoint_transfer_type;
+
+#[cfg(feature = "std")]
+use std::collections::BTreeMap;
+
+/// Periodic time per high-speed microframe: 80% of 125 µs
+pub const HS_MICROFRAME_BUDGET_NS: u32 = 100_000;
+/// Periodic time per full-speed frame: 90% of 1 ms
+pub const FS_FRAME_BUDGET_NS: u32 = 900_000;
+
+/// Host controller turnaround, full/low speed
+const BW_HOST_DELAY_NS: u64 = 1000;
+/// Hub low-speed setup time
+const BW_HUB_LS_SETUP_NS: u64 = 333;
+/// Host controller turnaround, high speed
+const HS_HOST_DELAY_NS: u64 = 5;
+
+/// Microframes per frame
+const MICROFRAMES: usize = 8;
+/// Microframes of a split transaction: start in 0, complete in 2 to 4,
+/// as the EHCI queue heads schedule them
+const SPLIT_MICROFRAMES: u8 = 0x1D;
+
+/// Bits on the wire for `bytes` of data with worst-case bit stuffing
+fn bit_time(bytes: u64) -> u64 {
+    7 * 8 * bytes / 6
+}
+
+/// Worst-case bus time of one transaction carrying `bytes` of data
+pub fn usb_bus_time_ns(speed: UsbSpeed, transfer_type: UsbTransferType, direction_in: bool, bytes: u32) -> u32 {
+    let bytes = bytes as u64;
+    let isochronous = transfer_type == UsbTransferType::Isochronous;
+    let ns = match speed {
+        UsbSpeed::Low => {
+            if direction_in {
+                64060 + 2 * BW_HUB_LS_SETUP_NS + BW_HOST_DELAY_NS + 67667 * (31 + 10 * bit_time(bytes)) / 1000
+            } else {
+                64107 + 2 * BW_HUB_LS_SETUP_NS + BW_HOST_DELAY_NS + 66700 * (31 + 10 * bit_time(bytes)) / 1000
+            }
+        }
+        UsbSpeed::Full => {
+            let overhead = match (isochronous, direction_in) {
+                (true, true) => 7268,
+                (true, false) => 6265,
+                (false, _) => 9107,
+            };
+            overhead + BW_HOST_DELAY_NS + 8354 * (31 + 10 * bit_time(bytes)) / 1000
+        }
+        _ => {
+            let overhead_bytes = if isochronous { 38 } else { 55 };
+            (overhead_bytes * 8 * 2083 + 2083 * (3 + bit_time(bytes))) / 1000 + HS_HOST_DELAY_NS
+        }
+    };
+    ns as u32
+}
+
+/// Transaction translator: hub address and, for a multi-TT hub, port
+pub type TtKey = (u8, u8);
+
+/// Periodic endpoint asking for bus time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BandwidthRequest {
+    /// Device address, or slot ID on xHCI
+    pub address: u8,
+    pub endpoint: u8,
+    pub speed: UsbSpeed,
+    pub transfer_type: UsbTransferType,
+    /// wMaxPacketSize, including the additional transactions of
+    /// high-bandwidth endpoints
+    pub max_packet_size: u16,
+    pub interval: u8,
+    /// Hub address and port of the TT serving a full- or low-speed device
+    /// on a high-speed bus
+    pub transaction_translator: Option<(u8, u8)>,
+}
+
+impl BandwidthRequest {
+    /// Request of an endpoint descriptor; `None` for control and bulk
+    /// endpoints, which use whatever time periodic transfers leave
+    pub fn from_endpoint(address: u8, speed: UsbSpeed, transaction_translator: Option<(u8, u8)>, endpoint: &UsbEndpointDescriptor) -> Option<Self> {
+        let transfer_type = endpoint_transfer_type(endpoint);
+        if !matches!(transfer_type, UsbTransferType::Interrupt | UsbTransferType::Isochronous) {
+            return None;
+        }
+        Some(Self {
+            address,
+            endpoint: endpoint.bEndpointAddress,
+            speed,
+            transfer_type,
+            max_packet_size: endpoint.wMaxPacketSize,
+            interval: endpoint.bInterval,
+            transaction_translator,
+        })
+    }
+
+    /// Transactions per service interval
+    pub fn transactions(&self) -> u32 {
+        if self.speed == UsbSpeed::High {
+            ((self.max_packet_size >> 11) & 0x3) as u32 + 1
+        } else {
+            1
+        }
+    }
+
+    /// Largest payload of one transaction
+    pub fn payload(&self) -> u32 {
+        (self.max_packet_size & 0x7FF) as u32
+    }
+
+    /// Service interval in microframes
+    pub fn interval_microframes(&self) -> u32 {
+        match (self.speed, self.transfer_type) {
+            // bInterval is an exponent: 2^(bInterval-1) (micro)frames
+            (UsbSpeed::High, _) => 1 << (self.interval.clamp(1, 16) - 1),
+            (_, UsbTransferType::Isochronous) => (1 << (self.interval.clamp(1, 16) - 1)) * MICROFRAMES as u32,
+            _ => self.interval.max(1) as u32 * MICROFRAMES as u32,
+        }
+    }
+
+    fn direction_in(&self) -> bool {
+        self.endpoint & 0x80 != 0
+    }
+}
+
+/// Bus time held by one endpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BandwidthReservation {
+    pub address: u8,
+    pub endpoint: u8,
+    /// Frames between service intervals, a power of two up to 32
+    pub period: u16,
+    /// First frame served, below `period`
+    pub phase: u16,
+    /// Microframes used in each frame served; all of them on a
+    /// full-speed bus
+    pub microframes: u8,
+    /// Time taken in each microframe used, or in each frame served on a
+    /// full-speed bus
+    pub bus_time_ns: u32,
+    /// TT the endpoint's split transactions go through
+    pub transaction_translator: Option<TtKey>,
+    /// Full-speed time taken on the TT's downstream bus per frame served
+    pub tt_time_ns: u32,
+}
+
+impl BandwidthReservation {
+    pub fn serves(&self, frame: usize) -> bool {
+        frame % self.period as usize == self.phase as usize
+    }
+}
+
+/// Periodic load of a bus or TT
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BandwidthUsage {
+    /// Periodic time available per (micro)frame
+    pub budget_ns: u32,
+    /// Load of the busiest (micro)frame
+    pub peak_ns: u32,
+    /// Load averaged over the periodic tree
+    pub average_ns: u32,
+    /// Endpoints holding time
+    pub endpoints: usize,
+}
+
+impl BandwidthUsage {
+    /// Busiest (micro)frame as a percentage of the budget
+    pub fn peak_percent(&self) -> u32 {
+        if self.budget_ns == 0 {
+            return 0;
+        }
+        (self.peak_ns as u64 * 100 / self.budget_ns as u64) as u32
+    }
+
+    pub fn average_percent(&self) -> u32 {
+        if self.budget_ns == 0 {
+            return 0;
+        }
+        (self.average_ns as u64 * 100 / self.budget_ns as u64) as u32
+    }
+}
+
+/// Periodic bandwidth of one bus and the TTs of its high-speed hubs
+#[derive(Debug, Clone)]
+pub struct BandwidthScheduler {
+    /// High for a high-speed bus, Full for a full-speed one
+    bus_speed: UsbSpeed,
+    reservations: Vec<BandwidthReservation>,
+    /// Hubs with one TT per port
+    multi_tt: Vec<u8>,
+}
+
+impl BandwidthScheduler {
+    pub fn new(bus_speed: UsbSpeed) -> Self {
+        let bus_speed = if bus_speed == UsbSpeed::Low { UsbSpeed::Full } else { bus_speed };
+        Self { bus_speed, reservations: Vec::new(), multi_tt: Vec::new() }
+    }
+
+    pub fn bus_speed(&self) -> UsbSpeed {
+        self.bus_speed
+    }
+
+    fn high_speed_bus(&self) -> bool {
+        self.bus_speed == UsbSpeed::High
+    }
+
+    /// Periodic time available in a slot: a microframe on a high-speed bus,
+    /// a frame on a full-speed one
+    pub fn budget_ns(&self) -> u32 {
+        if self.high_speed_bus() { HS_MICROFRAME_BUDGET_NS } else { FS_FRAME_BUDGET_NS }
+    }
+
+    /// Record whether a hub has a TT per port; single-TT hubs share one
+    /// downstream frame between all their ports
+    pub fn set_multi_tt(&mut self, hub: u8, multi_tt: bool) {
+        self.multi_tt.retain(|&other| other != hub);
+        if multi_tt {
+            self.multi_tt.push(hub);
+        }
+    }
+
+    fn tt_key(&self, transaction_translator: (u8, u8)) -> TtKey {
+        let (hub, port) = transaction_translator;
+        if self.multi_tt.contains(&hub) { (hub, port) } else { (hub, 0) }
+    }
+
+    /// Load of microframe `microframe` of `frame`; on a full-speed bus the
+    /// load of the whole frame
+    pub fn slot_load(&self, frame: usize, microframe: usize) -> u32 {
+        let frame = frame % PERIODIC_SLOTS;
+        self.reservations
+            .iter()
+            .filter(|reservation| reservation.serves(frame))
+            .filter(|reservation| !self.high_speed_bus() || reservation.microframes & (1 << microframe) != 0)
+            .map(|reservation| reservation.bus_time_ns)
+            .sum()
+    }
+
+    /// Downstream load of a TT in `frame`
+    pub fn tt_load(&self, tt: TtKey, frame: usize) -> u32 {
+        let frame = frame % PERIODIC_SLOTS;
+        self.reservations
+            .iter()
+            .filter(|reservation| reservation.transaction_translator == Some(tt) && reservation.serves(frame))
+            .map(|reservation| reservation.tt_time_ns)
+            .sum()
+    }
+
+    /// Microframe patterns an endpoint may use in each frame it is served
+    fn microframe_candidates(&self, request: &BandwidthRequest, interval_microframes: u32) -> Vec<u8> {
+        if !self.high_speed_bus() {
+            return vec![0xFF];
+        }
+        if request.speed != UsbSpeed::High {
+            return vec![SPLIT_MICROFRAMES];
+        }
+        match interval_microframes {
+            0 | 1 => vec![0xFF],
+            2 => vec![0x55, 0xAA],
+            3..=7 => (0..4).map(|shift| 0x11 << shift).collect(),
+            _ => (0..MICROFRAMES).map(|microframe| 1 << microframe).collect(),
+        }
+    }
+
+    /// Place an endpoint where it leaves the busiest slot least loaded
+    fn place(&self, request: &BandwidthRequest) -> UsbResult<BandwidthReservation> {
+        if request.speed != UsbSpeed::High && self.high_speed_bus() && request.transaction_translator.is_none() {
+            return Err(UsbDriverError::InvalidConfiguration);
+        }
+        let transactions = request.transactions();
+        let direction_in = request.direction_in();
+        let bus_speed = if self.high_speed_bus() { UsbSpeed::High } else { request.speed };
+        let bus_time_ns = usb_bus_time_ns(bus_speed, request.transfer_type, direction_in, request.payload()) * transactions;
+        let transaction_translator = match (self.high_speed_bus(), request.speed) {
+            (true, UsbSpeed::Low | UsbSpeed::Full) => request.transaction_translator.map(|tt| self.tt_key(tt)),
+            _ => None,
+        };
+        let tt_time_ns = match transaction_translator {
+            Some(_) => usb_bus_time_ns(request.speed, request.transfer_type, direction_in, request.payload()),
+            None => 0,
+        };
+
+        let interval = request.interval_microframes();
+        let period = PeriodicSchedule::<u8>::period_for(interval / MICROFRAMES as u32);
+        let microframe_candidates = self.microframe_candidates(request, interval);
+
+        let mut best: Option<(u32, BandwidthReservation)> = None;
+        for phase in 0..period {
+            for &microframes in &microframe_candidates {
+                let candidate = BandwidthReservation {
+                    address: request.address,
+                    endpoint: request.endpoint,
+                    period,
+                    phase,
+                    microframes,
+                    bus_time_ns,
+                    transaction_translator,
+                    tt_time_ns,
+                };
+                if let Some(peak) = self.peak_with(&candidate) {
+                    if best.map_or(true, |(best_peak, _)| peak < best_peak) {
+                        best = Some((peak, candidate));
+                    }
+                }
+            }
+        }
+
+        best.map(|(_, reservation)| reservation).ok_or_else(|| {
+            log::warn!("USB bandwidth: no room for device {} endpoint {:#04x} ({} ns every {} microframes)",
+                      request.address, request.endpoint, bus_time_ns, interval);
+            UsbDriverError::InvalidConfiguration
+        })
+    }
+
+    /// Busiest slot the candidate is served in once added, or `None` if
+    /// it overruns the bus or TT budget
+    fn peak_with(&self, candidate: &BandwidthReservation) -> Option<u32> {
+        let mut peak = 0;
+        for frame in (candidate.phase as usize..PERIODIC_SLOTS).step_by(candidate.period as usize) {
+            if let Some(tt) = candidate.transaction_translator {
+                if self.tt_load(tt, frame) + candidate.tt_time_ns > FS_FRAME_BUDGET_NS {
+                    return None;
+                }
+            }
+            let microframes = if self.high_speed_bus() { MICROFRAMES } else { 1 };
+            for microframe in 0..microframes {
+                if self.high_speed_bus() && candidate.microframes & (1 << microframe) == 0 {
+                    continue;
+                }
+                let load = self.slot_load(frame, microframe) + candidate.bus_time_ns;
+                if load > self.budget_ns() {
+                    return None;
+                }
+                peak = peak.max(load);
+            }
+        }
+        Some(peak)
+    }
+
+    /// Reserve time for an endpoint, replacing its previous reservation
+    pub fn reserve(&mut self, request: &BandwidthRequest) -> UsbResult<BandwidthReservation> {
+        let previous = self.release(request.address, request.endpoint);
+        match self.place(request) {
+            Ok(reservation) => {
+                self.reservations.push(reservation);
+                Ok(reservation)
+            }
+            Err(err) => {
+                self.reservations.extend(previous);
+                Err(err)
+            }
+        }
+    }
+
+    /// Reserve time for every periodic endpoint of a configuration, or for
+    /// none of them
+    pub fn reserve_all(&mut self, requests: &[BandwidthRequest]) -> UsbResult<Vec<BandwidthReservation>> {
+        let saved = self.reservations.clone();
+        let mut reserved = Vec::with_capacity(requests.len());
+        for request in requests {
+            match self.reserve(request) {
+                Ok(reservation) => reserved.push(reservation),
+                Err(err) => {
+                    self.reservations = saved;
+                    return Err(err);
+                }
+            }
+        }
+        Ok(reserved)
+    }
+
+    /// Give up an endpoint's time
+    pub fn release(&mut self, address: u8, endpoint: u8) -> Option<BandwidthReservation> {
+        let position = self.reservations
+            .iter()
+            .position(|reservation| reservation.address == address && reservation.endpoint == endpoint)?;
+        Some(self.reservations.remove(position))
+    }
+
+    /// Give up the time of all of a device's endpoints
+    pub fn release_device(&mut self, address: u8) {
+        self.reservations.retain(|reservation| reservation.address != address);
+    }
+
+    pub fn reservation(&self, address: u8, endpoint: u8) -> Option<&BandwidthReservation> {
+        self.reservations
+            .iter()
+            .find(|reservation| reservation.address == address && reservation.endpoint == endpoint)
+    }
+
+    pub fn reservations(&self) -> &[BandwidthReservation] {
+        &self.reservations
+    }
+
+    /// Current load of the bus
+    pub fn usage(&self) -> BandwidthUsage {
+        let microframes = if self.high_speed_bus() { MICROFRAMES } else { 1 };
+        let mut peak = 0;
+        let mut total = 0u64;
+        for frame in 0..PERIODIC_SLOTS {
+            for microframe in 0..microframes {
+                let load = self.slot_load(frame, microframe);
+                peak = peak.max(load);
+                total += load as u64;
+            }
+        }
+        BandwidthUsage {
+            budget_ns: self.budget_ns(),
+            peak_ns: peak,
+            average_ns: (total / (PERIODIC_SLOTS * microframes) as u64) as u32,
+            endpoints: self.reservations.len(),
+        }
+    }
+
+    /// Current downstream load of every TT in use
+    pub fn tt_usage(&self) -> BTreeMap<TtKey, BandwidthUsage> {
+        let mut usage = BTreeMap::new();
+        for reservation in &self.reservations {
+            if let Some(tt) = reservation.transaction_translator {
+                usage.entry(tt).or_insert(BandwidthUsage { budget_ns: FS_FRAME_BUDGET_NS, ..Default::default() }).endpoints += 1;
+            }
+        }
+        for (&tt, tt_usage) in usage.iter_mut() {
+            let loads: Vec<u32> = (0..PERIODIC_SLOTS).map(|frame| self.tt_load(tt, frame)).collect();
+            tt_usage.peak_ns = loads.iter().copied().max().unwrap_or(0);
+            tt_usage.average_ns = (loads.iter().map(|&load| load as u64).sum::<u64>() / PERIODIC_SLOTS as u64) as u32;
+        }
+        usage
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(address: u8, endpoint: u8, speed: UsbSpeed, transfer_type: UsbTransferType, max_packet_size: u16, interval: u8) -> BandwidthRequest {
+        BandwidthRequest { address, endpoint, speed, transfer_type, max_packet_size, interval, transaction_translator: None }
+    }
+
+    #[test]
+    fn test_bus_time() {
+        // Full-speed isochronous IN of 1023 bytes takes most of a frame
+        let fs = usb_bus_time_ns(UsbSpeed::Full, UsbTransferType::Isochronous, true, 1023);
+        assert!(fs > 800_000 && fs < FS_FRAME_BUDGET_NS);
+        let hs = usb_bus_time_ns(UsbSpeed::High, UsbTransferType::Isochronous, true, 1024);
+        assert!(hs > 20_000 && hs < 21_000);
+        assert!(usb_bus_time_ns(UsbSpeed::Low, UsbTransferType::Interrupt, true, 8) > usb_bus_time_ns(UsbSpeed::Full, UsbTransferType::Interrupt, true, 8));
+    }
+
+    #[test]
+    fn test_high_speed_budget() {
+        let mut scheduler = BandwidthScheduler::new(UsbSpeed::High);
+        // Three 1024-byte transactions every microframe
+        let video = request(1, 0x81, UsbSpeed::High, UsbTransferType::Isochronous, 1024 | 2 << 11, 1);
+        let reservation = scheduler.reserve(&video).unwrap();
+        assert_eq!(reservation.microframes, 0xFF);
+        assert!(scheduler.reserve(&request(2, 0x81, UsbSpeed::High, UsbTransferType::Isochronous, 1024 | 2 << 11, 1)).is_err());
+        assert!(scheduler.usage().peak_percent() > 50);
+
+        // Replacing a reservation does not count it twice
+        assert!(scheduler.reserve(&video).is_ok());
+        assert_eq!(scheduler.usage().endpoints, 1);
+
+        // Slower endpoints spread over the microframes
+        let a = scheduler.reserve(&request(3, 0x81, UsbSpeed::High, UsbTransferType::Interrupt, 64, 4)).unwrap();
+        let b = scheduler.reserve(&request(3, 0x82, UsbSpeed::High, UsbTransferType::Interrupt, 64, 4)).unwrap();
+        assert_ne!(a.microframes, b.microframes);
+        assert_eq!(a.period, 1);
+    }
+
+    #[test]
+    fn test_full_speed_budget_and_rollback() {
+        let mut scheduler = BandwidthScheduler::new(UsbSpeed::Full);
+        let mouse = request(1, 0x81, UsbSpeed::Full, UsbTransferType::Interrupt, 8, 10);
+        let playback = request(1, 0x02, UsbSpeed::Full, UsbTransferType::Isochronous, 1023, 1);
+        let capture = request(1, 0x83, UsbSpeed::Full, UsbTransferType::Isochronous, 1023, 1);
+        assert!(scheduler.reserve_all(&[mouse, playback, capture]).is_err());
+        assert!(scheduler.reservations().is_empty());
+
+        assert_eq!(scheduler.reserve_all(&[mouse, playback]).unwrap().len(), 2);
+        assert!(scheduler.reserve(&capture).is_err());
+        scheduler.release_device(1);
+        assert_eq!(scheduler.usage().peak_ns, 0);
+    }
+
+    #[test]
+    fn test_transaction_translator_budget() {
+        let mut scheduler = BandwidthScheduler::new(UsbSpeed::High);
+        let mut first = request(4, 0x01, UsbSpeed::Full, UsbTransferType::Isochronous, 1023, 1);
+        first.transaction_translator = Some((2, 1));
+        let mut second = request(5, 0x01, UsbSpeed::Full, UsbTransferType::Isochronous, 1023, 1);
+        second.transaction_translator = Some((2, 3));
+
+        // A single-TT hub has one downstream frame for all its ports
+        scheduler.reserve(&first).unwrap();
+        assert!(scheduler.reserve(&second).is_err());
+
+        scheduler.set_multi_tt(2, true);
+        scheduler.release_device(4);
+        scheduler.reserve(&first).unwrap();
+        let reservation = scheduler.reserve(&second).unwrap();
+        assert_eq!(reservation.transaction_translator, Some((2, 3)));
+        assert_eq!(reservation.microframes, SPLIT_MICROFRAMES);
+        assert_eq!(scheduler.tt_usage().len(), 2);
+
+        // Full-speed devices on a high-speed bus need a TT
+        assert!(scheduler.reserve(&request(6, 0x81, UsbSpeed::Full, UsbTransferType::Interrupt, 8, 1)).is_err());
+    }
+}
@@ -1,149 +1,217 @@
 //! EHCI (Enhanced Host Controller Interface) Driver
-//! 
+//!
 //! Supports USB 2.0 high-speed hosts with features like:
 //! - High-speed USB 2.0 support
 //! - Asynchronous scheduling
-//! - Isochronous scheduling
+//! - Interrupt scheduling, with split transactions behind hubs
 //! - Support for companion controllers
-
+//!
+//! Control and bulk endpoints are queue heads on the asynchronous
+//! schedule, a ring that starts at an empty head queue head. Interrupt
+//! endpoints are queue heads in the periodic frame list, linked as the
+//! tree kept by `PeriodicSchedule`. A queue head runs one transfer, a
+//! chain of qTDs, at a time; later transfers wait in software until it
+//! is idle again.
+//!
+//! Only high-speed devices stay on the EHCI. Setting CONFIGFLAG routes
+//! every port to it; a port whose device turns out to be full or low
+//! speed is released to a companion controller, which enumerates the
+//! device itself. The controller is driven with 32-bit addresses, so all
+//! memory it reads sits below 4 GiB.
+
+use core::mem;
+use core::sync::atomic::{fence, Ordering};
+use alloc::collections::VecDeque;
 use crate::*;
+use crate::hotplug::{
+    UsbPortHost, USB_PORT_STAT_CONNECTION, USB_PORT_STAT_ENABLE, USB_PORT_STAT_HIGH_SPEED, USB_PORT_STAT_OVERCURRENT,
+    USB_PORT_STAT_POWER, USB_PORT_STAT_RESET, USB_PORT_STAT_SUSPEND,
+};
+use super::ohci::OhciController;
+use super::bandwidth::{BandwidthRequest, BandwidthReservation, BandwidthScheduler, BandwidthUsage, TtKey};
+use super::periodic::{PeriodicSchedule, PERIODIC_SLOTS};
+use super::xhci::{XhciDmaBuffer, XhciTransferResult};
+use super::{
+    addressed_device, default_max_packet_size0, endpoint_transfer_type, get_device_descriptor, parse_device_descriptor,
+    set_address_request,
+};
 
 #[cfg(feature = "std")]
 use std::collections::BTreeMap;
 
-/// EHCI Capability Registers
-const EHCI_CAPLENGTH: u32 = 0x00;
-const EHCI_HCIVERSION: u32 = 0x02;
-const EHCI_HCSPARAMS: u32 = 0x04;
-const EHCI_HCCPARAMS: u32 = 0x08;
-const EHCI_DBOFF: u32 = 0x0C;
-const EHCI_DTI: u32 = 0x10;
-
-/// EHCI Operational Registers
-const EHCI_USBCMD: u32 = 0x20;
-const EHCI_USBSTS: u32 = 0x24;
-const EHCI_USBINTR: u32 = 0x28;
-const EHCI_FRINDEX: u32 = 0x2C;
-const EHCI_CTRLDSSYNPAGE: u32 = 0x30;
-const EHCI_PERIODICLISTBASE: u32 = 0x34;
-const EHCI_ASYNCLISTADDR: u32 = 0x38;
-const EHCI_ASYNCTHHRESHOLD: u32 = 0x3C;
-
-/// EHCI Port Status and Control Register
-const EHCI_PORTSC1: u32 = 0x44;
+/// EHCI Capability Register Offsets
+const EHCI_CAPLENGTH: usize = 0x00;
+const EHCI_HCIVERSION: usize = 0x02;
+const EHCI_HCSPARAMS: usize = 0x04;
+const EHCI_HCCPARAMS: usize = 0x08;
+const EHCI_HCSP_PORTROUTE: usize = 0x0C;
+
+/// EHCI Operational Register Offsets (from CAPLENGTH)
+const EHCI_USBCMD: usize = 0x00;
+const EHCI_USBSTS: usize = 0x04;
+const EHCI_USBINTR: usize = 0x08;
+const EHCI_FRINDEX: usize = 0x0C;
+const EHCI_CTRLDSSEGMENT: usize = 0x10;
+const EHCI_PERIODICLISTBASE: usize = 0x14;
+const EHCI_ASYNCLISTADDR: usize = 0x18;
+const EHCI_CONFIGFLAG: usize = 0x40;
+const EHCI_PORTSC_BASE: usize = 0x44;
+
+/// Structural parameters (HCSPARAMS) fields
+const EHCI_HCS_N_PORTS_MASK: u32 = 0xF;
+const EHCI_HCS_PPC: u32 = 1 << 4;          // Port Power Control
+const EHCI_HCS_PRR: u32 = 1 << 7;          // Port Routing Rules
+const EHCI_HCS_N_PCC_SHIFT: u32 = 8;       // Ports per companion
+const EHCI_HCS_N_CC_SHIFT: u32 = 12;       // Number of companions
+
+/// Capability parameters (HCCPARAMS) fields
+const EHCI_HCC_64BIT: u32 = 1 << 0;
 
 /// EHCI Command Register (USBCMD) bit fields
-const EHCI_CMD_ITC_MASKS: u32 = 0x000000FF;
+const EHCI_CMD_RS: u32 = 1 << 0;           // Run/Stop
+const EHCI_CMD_HCRESET: u32 = 1 << 1;      // Host Controller Reset
+const EHCI_CMD_PSE: u32 = 1 << 4;          // Periodic Schedule Enable
+const EHCI_CMD_ASE: u32 = 1 << 5;          // Asynchronous Schedule Enable
+const EHCI_CMD_IAAD: u32 = 1 << 6;         // Interrupt on Async Advance Doorbell
 const EHCI_CMD_ITC_SHIFT: u32 = 16;
-const EHCI_CMD_IAADB: u32 = 0x00000080;
-const EHCI_CMD_ASE: u32 = 0x00000040;
-const EHCI_CMD_PSE: u32 = 0x00000020;
-const EHCI_CMD_FLS: u32 = 0x0000000C;
-const EHCI_CMD_LHCRT: u32 = 0x00000008;
-const EHCI_CMD_C_SSS: u32 = 0x00000004;
-const EHCI_CMD_C_RSS: u32 = 0x00000002;
-const EHCI_CMD_RS: u32 = 0x00000001;
-const EHCI_CMD_HCRESET: u32 = 0x00000002;
-const EHCI_CMD_HLE: u32 = 0x00000100;
-const EHCI_CMD_PPCEE: u32 = 0x00000200;
-const EHCI_CMD_FS1: u32 = 0x00000400;
-const EHCI_CMD_ATDTW: u32 = 0x00000800;
-const EHCI_CMD_ASYPCE: u32 = 0x00001000;
-const EHCI_CMD_LPM: u32 = 0x00002000;
+/// Interrupt threshold in microframes (1 ms)
+const EHCI_INTERRUPT_THRESHOLD: u32 = 8;
 
 /// EHCI Status Register (USBSTS) bit fields
-const EHCI_STS_ASS: u32 = 0x00008000;
-const EHCI_STS_PSS: u32 = 0x00004000;
-const EHCI_STS_RECL: u32 = 0x00002000;
-const EHCI_STS_HCH: u32 = 0x00001000;
-const EHCI_STS_AAI: u32 = 0x00000800;
-const EHCI_STS_NAK: u32 = 0x00000400;
-const EHCI_STS_PCD: u32 = 0x00000200;
-const EHCI_STS_FLR: u32 = 0x00000100;
-const EHCI_STS_PCI: u32 = 0x00000080;
-const EHCI_STS_ERRINT: u32 = 0x00000040;
-const EHCI_STS_INT: u32 = 0x00000020;
-const EHCI_STS_HSE: u32 = 0x00000010;
-const EHCI_STS_ASR: u32 = 0x00000008;
-const EHCI_STS_PSSR: u32 = 0x00000004;
-const EHCI_STS_RHSC: u32 = 0x00000002;
-const EHCI_STS_HCINTERRUPTS: u32 = 0x00000001;
-
-/// EHCI Port Status Register (PORTSC1) bit fields
-const EHCI_PORT_W1C_BITS: u32 = 0xFF007FFF;
-const EHCI_PORT_CONNECT: u32 = 0x00000001;
-const EHCI_PORT_PEC: u32 = 0x00000002;
-const EHCI_PORT_PEDC: u32 = 0x00000004;
-const EHCI_PORT_OCA: u32 = 0x00000008;
-const EHCI_PORT_OCC: u32 = 0x00000010;
-const EHCI_PORT_FPR: u32 = 0x00000020;
-const EHCI_PORT_SUSP: u32 = 0x00000040;
-const EHCI_PORT_RPR: u32 = 0x00000080;
-const EHCI_PORT_LS: u32 = 0x00000100;
-const EHCI_PORT_PP: u32 = 0x00001000;
-const EHCI_PORT_PIC: u32 = 0x00006000;
-const EHCI_PORT_POWNER: u32 = 0x20000000;
-const EHCI_PORT_PPOWER: u32 = 0x40000000;
-const EHCI_PORT_READONLY: u32 = 0x80000000;
-
-/// EHCI Transfer Descriptor (qTD) structure
-#[derive(Debug, Clone, Copy)]
-#[repr(C)]
-pub struct EhciQTD {
-    pub next_qtd: u32,           // Next qTD pointer
-    pub alternate_next_qtd: u32, // Alternate next qTD pointer
-    pub token: u32,              // Status and token fields
-    pub buffer_page0: u32,       // Buffer page 0
-    pub buffer_page1: u32,       // Buffer page 1
-    pub buffer_page2: u32,       // Buffer page 2
-    pub buffer_page3: u32,       // Buffer page 3
-    pub buffer_page4: u32,       // Buffer page 4
-    pub extended_buffer: u32,    // Extended buffer (for large transfers)
-}
-
-/// EHCI Transfer Descriptor Token bit fields
-const EHCI_QTD_TOKENS_ACTIVE: u32 = 0x00000080;
-const EHCI_QTD_TOKENS_HALT: u32 = 0x00000040;
-const EHCI_QTD_TOKENS_DATAGERR: u32 = 0x00000020;
-const EHCI_QTD_TOKENS_BABBLED: u32 = 0x00000010;
-const EHCI_QTD_TOKENS_XACT_ERROR: u32 = 0x00000008;
-const EHCI_QTD_TOKENS_MISSED_FRAME: u32 = 0x00000004;
-const EHCI_QTD_TOKENS_SPLIT: u32 = 0x00000002;
-const EHCI_QTD_TOKENS_PING_STATE: u32 = 0x00000001;
-const EHCI_QTD_TOKENS_IOC: u32 = 0x00008000;
-const EHCI_QTD_TOKENS_CPAGE_SHIFT: u32 = 12;
-const EHCI_QTD_TOKENS_CPAGE_MASK: u32 = 0x00007000;
-
-/// EHCI Transfer Queue Head (qH) structure
-#[derive(Debug, Clone, Copy)]
-#[repr(C)]
-pub struct EhciQH {
-    pub link: u32,                   // Link to next QH or itd
-    pub ep_capabilities: u32,        // Endpoint characteristics
-    pub current_qtd: u32,            // Current qTD pointer
-    pub next_qtd: u32,               // Next qTD pointer
-    pub altnext_qtd: u32,            // Alternate next qTD pointer
-    pub token: u32,                  // Status and token fields
-    pub buffer_page0: u32,           // Buffer page 0
-    pub buffer_page1: u32,           // Buffer page 1
-    pub buffer_page2: u32,           // Buffer page 2
-    pub buffer_page3: u32,           // Buffer page 3
-    pub buffer_page4: u32,           // Buffer page 4
-}
+const EHCI_STS_USBINT: u32 = 1 << 0;       // Transfer completed
+const EHCI_STS_ERRINT: u32 = 1 << 1;       // Transfer error
+const EHCI_STS_PCD: u32 = 1 << 2;          // Port Change Detect
+const EHCI_STS_FLR: u32 = 1 << 3;          // Frame List Rollover
+const EHCI_STS_HSE: u32 = 1 << 4;          // Host System Error
+const EHCI_STS_IAA: u32 = 1 << 5;          // Interrupt on Async Advance
+const EHCI_STS_HCH: u32 = 1 << 12;         // Host Controller Halted
+const EHCI_STS_PSS: u32 = 1 << 14;         // Periodic Schedule Status
+const EHCI_STS_ASS: u32 = 1 << 15;         // Asynchronous Schedule Status
+/// Write-1-to-clear interrupt bits, acknowledged by the interrupt handler
+/// except IAA, which the async unlink handshake waits for itself
+const EHCI_STS_ACK_MASK: u32 = EHCI_STS_USBINT | EHCI_STS_ERRINT | EHCI_STS_PCD | EHCI_STS_FLR | EHCI_STS_HSE;
+
+/// Interrupts enabled in USBINTR
+const EHCI_INTR_MASK: u32 = EHCI_STS_USBINT | EHCI_STS_ERRINT | EHCI_STS_PCD | EHCI_STS_HSE;
+
+/// Port Status and Control Register (PORTSC) bit fields
+const EHCI_PORT_CCS: u32 = 1 << 0;         // Current Connect Status
+const EHCI_PORT_CSC: u32 = 1 << 1;         // Connect Status Change
+const EHCI_PORT_PE: u32 = 1 << 2;          // Port Enabled
+const EHCI_PORT_PEC: u32 = 1 << 3;         // Port Enable Change
+const EHCI_PORT_OCA: u32 = 1 << 4;         // Over-current Active
+const EHCI_PORT_OCC: u32 = 1 << 5;         // Over-current Change
+const EHCI_PORT_SUSP: u32 = 1 << 7;        // Suspend
+const EHCI_PORT_PR: u32 = 1 << 8;          // Port Reset
+const EHCI_PORT_LS_SHIFT: u32 = 10;        // Line Status
+const EHCI_PORT_LS_MASK: u32 = 0x3;
+const EHCI_LINE_STATUS_K: u32 = 0x1;       // K-state: a low-speed device
+const EHCI_PORT_PP: u32 = 1 << 12;         // Port Power
+const EHCI_PORT_OWNER: u32 = 1 << 13;      // Port owned by a companion
+/// Write-1-to-clear change bits, masked out when writing PORTSC back
+const EHCI_PORT_CHANGE_MASK: u32 = EHCI_PORT_CSC | EHCI_PORT_PEC | EHCI_PORT_OCC;
+
+/// Link pointer bits
+const EHCI_LINK_TERMINATE: u32 = 1 << 0;
+const EHCI_LINK_TYPE_QH: u32 = 1 << 1;
+
+/// Queue head layout
+const EHCI_QH_LINK: usize = 0x00;
+const EHCI_QH_CHARACTERISTICS: usize = 0x04;
+const EHCI_QH_CAPABILITIES: usize = 0x08;
+/// Transfer overlay: a qTD the controller works on
+const EHCI_QH_OVERLAY: usize = 0x10;
+const EHCI_QH_SIZE: usize = 0x60;
+
+/// qTD layout
+const EHCI_QTD_NEXT: usize = 0x00;
+const EHCI_QTD_ALT_NEXT: usize = 0x04;
+const EHCI_QTD_TOKEN: usize = 0x08;
+const EHCI_QTD_BUFFER: usize = 0x0C;
+const EHCI_QTD_BUFFER_HI: usize = 0x20;
+const EHCI_QTD_SIZE: usize = 0x40;
+const EHCI_QTD_BUFFER_PAGES: usize = 5;
+/// Queue heads and qTDs must not cross a 4 KiB boundary
+const EHCI_DESCRIPTOR_ALIGN: usize = 64;
+const EHCI_PAGE_SIZE: usize = 4096;
+
+/// Queue head endpoint characteristics
+const EHCI_QH_ENDPOINT_SHIFT: u32 = 8;
+const EHCI_QH_EPS_SHIFT: u32 = 12;
+const EHCI_QH_DTC: u32 = 1 << 14;          // Data toggle from the qTD
+const EHCI_QH_HEAD: u32 = 1 << 15;         // Head of the async ring
+const EHCI_QH_MPS_SHIFT: u32 = 16;
+const EHCI_QH_CONTROL_ENDPOINT: u32 = 1 << 27;
+const EHCI_QH_RL_SHIFT: u32 = 28;
+const EHCI_QH_NAK_RELOAD: u32 = 4;
+
+/// Queue head endpoint capabilities
+const EHCI_QH_CMASK_SHIFT: u32 = 8;
+const EHCI_QH_HUB_SHIFT: u32 = 16;
+const EHCI_QH_PORT_SHIFT: u32 = 23;
+const EHCI_QH_MULT_SHIFT: u32 = 30;
+/// Split transactions start in microframe 0 and complete in 2 to 4
+const EHCI_SPLIT_SMASK: u32 = 0x01;
+const EHCI_SPLIT_CMASK: u32 = 0x1C;
+
+/// Endpoint speed (EPS) encodings
+const EHCI_EPS_FULL: u32 = 0;
+const EHCI_EPS_LOW: u32 = 1;
+const EHCI_EPS_HIGH: u32 = 2;
+
+/// qTD token bit fields
+const EHCI_QTD_ACTIVE: u32 = 1 << 7;
+const EHCI_QTD_HALTED: u32 = 1 << 6;
+const EHCI_QTD_BUFFER_ERROR: u32 = 1 << 5;
+const EHCI_QTD_BABBLE: u32 = 1 << 4;
+const EHCI_QTD_XACT_ERROR: u32 = 1 << 3;
+const EHCI_QTD_MISSED_MICROFRAME: u32 = 1 << 2;
+const EHCI_QTD_PID_SHIFT: u32 = 8;
+const EHCI_QTD_CERR_SHIFT: u32 = 10;
+const EHCI_QTD_IOC: u32 = 1 << 15;
+const EHCI_QTD_BYTES_SHIFT: u32 = 16;
+const EHCI_QTD_BYTES_MASK: u32 = 0x7FFF;
+const EHCI_QTD_TOGGLE: u32 = 1 << 31;
+
+/// qTD PID codes
+const EHCI_PID_OUT: u32 = 0;
+const EHCI_PID_IN: u32 = 1;
+const EHCI_PID_SETUP: u32 = 2;
+
+/// Bytes one qTD moves. Five pages of a page-aligned buffer hold 20 KiB;
+/// 16 KiB keeps every qTD but the last a whole number of packets.
+const EHCI_QTD_MAX_LENGTH: usize = 16 * 1024;
+/// Entries of the periodic frame list (FLS = 0)
+const EHCI_FRAME_LIST_ENTRIES: usize = 1024;
+/// FRINDEX wraps at this many microframes with a 1024-entry frame list
+const EHCI_FRINDEX_MASK: u32 = 0x3FFF;
+/// Root port reset duration in microframes (50 ms)
+const EHCI_PORT_RESET_MICROFRAMES: u32 = 50 * 8;
+/// Port power good time in microframes (20 ms)
+const EHCI_POWER_GOOD_MICROFRAMES: u32 = 20 * 8;
+/// SET_ADDRESS recovery in microframes (2 ms)
+const EHCI_SET_ADDRESS_MICROFRAMES: u32 = 2 * 8;
+/// Polling iterations before a register wait or transfer gives up
+const EHCI_TIMEOUT_SPINS: u32 = 1_000_000;
+
+/// Platform MMIO base of the first EHCI controller
+pub const EHCI_DEFAULT_BASE_ADDRESS: u64 = 0xFEC00000;
+/// Platform MMIO bases of its OHCI companions, by PCI function
+pub const EHCI_DEFAULT_COMPANIONS: [u64; 2] = [0xFEC10000, 0xFEC20000];
 
 /// EHCI Host Controller capability parameters
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Default)]
 pub struct EhciCapabilityParams {
     pub cap_length: u8,
     pub hc_interface_version: u16,
     pub hcs_params: u32,
     pub hcc_params: u32,
-    pub doorbell_offset: u32,
-    pub usb_dccparams: u32,
+    /// HCSP-PORTROUTE, four bits of companion number per port
+    pub port_route: u64,
 }
 
 /// EHCI Controller State
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum EhciControllerState {
     Uninitialized,
     Initialized,
@@ -165,614 +233,1644 @@ pub struct EhciPort {
     pub is_connected_to_companion: bool,
 }
 
-/// EHCI Queue Head Pool
+/// Controller that drives the device on a port after its reset
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EhciPortRoute {
+    /// A high-speed device, enabled on the EHCI
+    HighSpeed,
+    /// A full- or low-speed device, released to port `port` of companion
+    /// `companion`
+    Companion { companion: usize, port: u8 },
+}
+
+/// Handle of a submitted transfer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct EhciTransferHandle(pub u64);
+
+/// Queue head of one endpoint
+#[derive(Debug)]
+struct EhciEndpoint {
+    qh: XhciDmaBuffer,
+    transfer_type: UsbTransferType,
+    /// Transfer the queue head is running
+    active: Option<EhciTransferHandle>,
+    /// Transfers waiting for the queue head, oldest first
+    queued: VecDeque<EhciTransferHandle>,
+}
+
+/// Device addressed on the EHCI
+#[derive(Debug)]
+pub struct EhciDevice {
+    pub address: u8,
+    pub port: u8,
+    pub speed: UsbSpeed,
+    /// Hub address and port of the transaction translator serving a full-
+    /// or low-speed device behind a high-speed hub
+    pub transaction_translator: Option<(u8, u8)>,
+    pub max_packet_size0: u16,
+    /// Queue heads by endpoint address; EP0 is 0 in both directions
+    endpoints: BTreeMap<u8, EhciEndpoint>,
+}
+
+/// One qTD of a transfer
 #[derive(Debug)]
-pub struct EhciQueueHeadPool {
-    pub base: *mut u8,
-    pub size: usize,
-    pub queue_heads: BTreeMap<u8, *mut EhciQH>,
+struct EhciQtd {
+    qtd: XhciDmaBuffer,
+    offset: usize,
+    length: usize,
+    /// Part of the data stage rather than setup or status
+    data: bool,
 }
 
-/// EHCI Transfer Descriptor Pool
+/// Transfer waiting for its queue head or running on it
 #[derive(Debug)]
-pub struct EhciQtdPool {
-    pub base: *mut u8,
-    pub size: usize,
-    pub qtds: Vec<*mut EhciQTD>,
+struct EhciInflight {
+    address: u8,
+    endpoint: u8,
+    control: bool,
+    direction_in: bool,
+    setup: Option<XhciDmaBuffer>,
+    buffer: Option<XhciDmaBuffer>,
+    qtds: Vec<EhciQtd>,
+}
+
+/// Address a controller without 64-bit addressing can use
+fn dma32(address: u64) -> UsbResult<u32> {
+    u32::try_from(address).map_err(|_| UsbDriverError::UnsupportedFeature)
+}
+
+/// Token of an active qTD allowed three retries
+pub fn ehci_qtd_token(pid: u32, length: usize, toggle: bool, ioc: bool) -> u32 {
+    let mut token = EHCI_QTD_ACTIVE
+        | pid << EHCI_QTD_PID_SHIFT
+        | 3 << EHCI_QTD_CERR_SHIFT
+        | (length as u32 & EHCI_QTD_BYTES_MASK) << EHCI_QTD_BYTES_SHIFT;
+    if ioc {
+        token |= EHCI_QTD_IOC;
+    }
+    if toggle {
+        token |= EHCI_QTD_TOGGLE;
+    }
+    token
+}
+
+/// Map a retired qTD's token to the transfer status it reports
+pub fn ehci_transfer_status(token: u32) -> UsbTransferStatus {
+    if token & EHCI_QTD_BABBLE != 0 {
+        UsbTransferStatus::BabbleDetected
+    } else if token & EHCI_QTD_BUFFER_ERROR != 0 {
+        if (token >> EHCI_QTD_PID_SHIFT) & 0x3 == EHCI_PID_IN {
+            UsbTransferStatus::BufferOverrun
+        } else {
+            UsbTransferStatus::BufferUnderrun
+        }
+    } else if token & (EHCI_QTD_XACT_ERROR | EHCI_QTD_MISSED_MICROFRAME) != 0 {
+        UsbTransferStatus::NotAccessed
+    } else if token & EHCI_QTD_HALTED != 0 {
+        UsbTransferStatus::Stalled
+    } else {
+        UsbTransferStatus::Success
+    }
+}
+
+/// Endpoint characteristics word of a queue head
+pub fn ehci_qh_characteristics(address: u8, endpoint: u8, speed: UsbSpeed, max_packet: u16, transfer_type: UsbTransferType) -> u32 {
+    let eps = match speed {
+        UsbSpeed::Low => EHCI_EPS_LOW,
+        UsbSpeed::Full => EHCI_EPS_FULL,
+        _ => EHCI_EPS_HIGH,
+    };
+    let mut characteristics = (address as u32 & 0x7F)
+        | ((endpoint & 0x0F) as u32) << EHCI_QH_ENDPOINT_SHIFT
+        | eps << EHCI_QH_EPS_SHIFT
+        | ((max_packet & 0x7FF) as u32) << EHCI_QH_MPS_SHIFT;
+    if transfer_type == UsbTransferType::Control {
+        // SETUP must go out as DATA0 whatever the toggle was
+        characteristics |= EHCI_QH_DTC;
+        if eps != EHCI_EPS_HIGH {
+            characteristics |= EHCI_QH_CONTROL_ENDPOINT;
+        }
+    }
+    // Periodic queue heads must not use NAK reload
+    if transfer_type != UsbTransferType::Interrupt {
+        characteristics |= EHCI_QH_NAK_RELOAD << EHCI_QH_RL_SHIFT;
+    }
+    characteristics
+}
+
+/// Endpoint capabilities word of a queue head
+///
+/// High-speed interrupt endpoints are polled in the microframes of
+/// `smask` within each frame they are scheduled in; full- and low-speed
+/// ones behind a transaction translator use split transactions.
+pub fn ehci_qh_capabilities(speed: UsbSpeed, transaction_translator: Option<(u8, u8)>, transfer_type: UsbTransferType, smask: u8, mult: u32) -> u32 {
+    let mut capabilities = mult.clamp(1, 3) << EHCI_QH_MULT_SHIFT;
+    let split = speed != UsbSpeed::High;
+    if let Some((hub, port)) = transaction_translator.filter(|_| split) {
+        capabilities |= (hub as u32 & 0x7F) << EHCI_QH_HUB_SHIFT | (port as u32 & 0x7F) << EHCI_QH_PORT_SHIFT;
+    }
+    if transfer_type == UsbTransferType::Interrupt {
+        capabilities |= if split {
+            EHCI_SPLIT_SMASK | EHCI_SPLIT_CMASK << EHCI_QH_CMASK_SHIFT
+        } else {
+            smask as u32
+        };
+    }
+    capabilities
+}
+
+/// Companion controller, and its port number, behind an EHCI port
+///
+/// Without port routing rules each companion takes the next N_PCC ports;
+/// with them HCSP-PORTROUTE names the companion of every port. Companions
+/// count their ports in the order of the EHCI ports routed to them.
+pub fn ehci_companion_port(hcs_params: u32, port_route: u64, port: u8) -> Option<(usize, u8)> {
+    let ports = (hcs_params & EHCI_HCS_N_PORTS_MASK) as u8;
+    let ports_per_companion = ((hcs_params >> EHCI_HCS_N_PCC_SHIFT) & 0xF) as u8;
+    let companions = ((hcs_params >> EHCI_HCS_N_CC_SHIFT) & 0xF) as usize;
+    if companions == 0 || port == 0 || port > ports {
+        return None;
+    }
+
+    let companion_of = |port: u8| -> usize {
+        if hcs_params & EHCI_HCS_PRR != 0 {
+            ((port_route >> ((port as u64 - 1) * 4)) & 0xF) as usize
+        } else {
+            ((port - 1) / ports_per_companion.max(1)) as usize
+        }
+    };
+    let companion = companion_of(port);
+    if companion >= companions {
+        return None;
+    }
+    let companion_port = (1..port).filter(|&other| companion_of(other) == companion).count() as u8 + 1;
+    Some((companion, companion_port))
 }
 
 /// EHCI Controller Implementation
+#[derive(Debug)]
 pub struct EhciController {
     pub base_address: u64,
     pub capability_params: EhciCapabilityParams,
     pub state: EhciControllerState,
-    pub doorbell_array: *mut u32,
-    pub periodic_frame_list: *mut u32,
-    pub async_list_head: *mut EhciQH,
-    pub queue_head_pool: Option<EhciQueueHeadPool>,
-    pub qtd_pool: Option<EhciQtdPool>,
     pub ports: Vec<EhciPort>,
     pub max_ports: u8,
-    pub frame_number: u32,
+    pub companion_controllers: u8,
+    pub ports_per_companion: u8,
     pub frame_list_size: usize,
-    pub interrupt_threshold: u32,
-    pub companion_required: bool,
-    pub port_routing_enabled: bool,
+    frame_list: Option<XhciDmaBuffer>,
+    async_head: Option<XhciDmaBuffer>,
+    /// Async queue heads in ring order after the head
+    async_order: Vec<(u8, u8)>,
+    periodic: PeriodicSchedule<(u8, u8)>,
+    /// Periodic time of the high-speed bus and the TTs behind it
+    bandwidth: BandwidthScheduler,
+    devices: BTreeMap<u8, EhciDevice>,
+    /// FRINDEX at the start of each port reset in progress
+    port_resets: BTreeMap<u8, u32>,
+    next_handle: u64,
+    inflight: BTreeMap<EhciTransferHandle, EhciInflight>,
+    completed: BTreeMap<EhciTransferHandle, XhciTransferResult>,
+    events: Vec<UsbEvent>,
+    stats: UsbControllerStats,
 }
 
 impl EhciController {
     /// Create a new EHCI controller instance
+    ///
+    /// No register is touched until `initialize()`.
     pub fn new(base_address: u64) -> Self {
-        let mut controller = Self {
+        Self {
             base_address,
-            capability_params: EhciCapabilityParams {
-                cap_length: 0,
-                hc_interface_version: 0,
-                hcs_params: 0,
-                hcc_params: 0,
-                doorbell_offset: 0,
-                usb_dccparams: 0,
-            },
+            capability_params: EhciCapabilityParams::default(),
             state: EhciControllerState::Uninitialized,
-            doorbell_array: core::ptr::null_mut(),
-            periodic_frame_list: core::ptr::null_mut(),
-            async_list_head: core::ptr::null_mut(),
-            queue_head_pool: None,
-            qtd_pool: None,
             ports: Vec::new(),
             max_ports: 0,
-            frame_number: 0,
-            frame_list_size: 0,
-            interrupt_threshold: 0x08,
-            companion_required: false,
-            port_routing_enabled: false,
-        };
+            companion_controllers: 0,
+            ports_per_companion: 0,
+            frame_list_size: EHCI_FRAME_LIST_ENTRIES,
+            frame_list: None,
+            async_head: None,
+            async_order: Vec::new(),
+            periodic: PeriodicSchedule::new(),
+            bandwidth: BandwidthScheduler::new(UsbSpeed::High),
+            devices: BTreeMap::new(),
+            port_resets: BTreeMap::new(),
+            next_handle: 1,
+            inflight: BTreeMap::new(),
+            completed: BTreeMap::new(),
+            events: Vec::new(),
+            stats: UsbControllerStats {
+                total_transactions: 0,
+                successful_transactions: 0,
+                failed_transactions: 0,
+                bytes_transferred: 0,
+                error_count: 0,
+                last_error: None,
+            },
+        }
+    }
+
+    fn read32(&self, offset: usize) -> u32 {
+        unsafe { core::ptr::read_volatile((self.base_address as usize + offset) as *const u32) }
+    }
+
+    fn write32(&self, offset: usize, value: u32) {
+        unsafe { core::ptr::write_volatile((self.base_address as usize + offset) as *mut u32, value) }
+    }
+
+    fn op_read(&self, offset: usize) -> u32 {
+        self.read32(self.capability_params.cap_length as usize + offset)
+    }
 
-        // Read capability parameters
-        controller.read_capability_params();
+    fn op_write(&self, offset: usize, value: u32) {
+        self.write32(self.capability_params.cap_length as usize + offset, value)
+    }
+
+    fn portsc_offset(&self, port_number: u8) -> usize {
+        EHCI_PORTSC_BASE + (port_number as usize - 1) * mem::size_of::<u32>()
+    }
+
+    /// Spin until `(USBSTS & mask) == expected`
+    fn wait_status(&self, mask: u32, expected: u32) -> UsbResult<()> {
+        for _ in 0..EHCI_TIMEOUT_SPINS {
+            if self.op_read(EHCI_USBSTS) & mask == expected {
+                return Ok(());
+            }
+            core::hint::spin_loop();
+        }
+        Err(UsbDriverError::Timeout)
+    }
 
-        controller
+    /// Microframes since FRINDEX read `since`
+    fn microframes_since(&self, since: u32) -> u32 {
+        self.op_read(EHCI_FRINDEX).wrapping_sub(since) & EHCI_FRINDEX_MASK
+    }
+
+    /// Wait `microframes` on the running controller's frame counter
+    fn wait_microframes(&self, microframes: u32) -> UsbResult<()> {
+        if self.state != EhciControllerState::Running {
+            return Err(UsbDriverError::ControllerNotInitialized);
+        }
+        let start = self.op_read(EHCI_FRINDEX);
+        for _ in 0..EHCI_TIMEOUT_SPINS {
+            if self.microframes_since(start) >= microframes {
+                return Ok(());
+            }
+            core::hint::spin_loop();
+        }
+        Err(UsbDriverError::Timeout)
     }
 
     /// Read EHCI capability parameters from registers
     pub fn read_capability_params(&mut self) {
-        unsafe {
-            let cap_base = self.base_address as *const u8;
-            
-            self.capability_params.cap_length = *cap_base;
-            self.capability_params.hc_interface_version = core::ptr::read_unaligned(
-                cap_base.add(2) as *const u16
-            ) as u16;
-            self.capability_params.hcs_params = core::ptr::read_unaligned(
-                self.base_address.add(EHCI_HCSPARAMS as usize) as *const u32
-            );
-            self.capability_params.hcc_params = core::ptr::read_unaligned(
-                self.base_address.add(EHCI_HCCPARAMS as usize) as *const u32
-            );
-            self.capability_params.doorbell_offset = core::ptr::read_unaligned(
-                self.base_address.add(EHCI_DBOFF as usize) as *const u32
-            );
-            self.capability_params.usb_dccparams = core::ptr::read_unaligned(
-                self.base_address.add(EHCI_DTI as usize) as *const u32
-            );
-
-            // Extract important parameters
-            self.max_ports = (self.capability_params.hcs_params & 0x0F) as u8;
-            self.companion_required = (self.capability_params.hcs_params & (1 << 4)) != 0;
-            self.port_routing_enabled = (self.capability_params.hcs_params & (1 << 5)) != 0;
-
-            // Determine frame list size
-            let fls = (self.capability_params.hcc_params >> 2) & 0x3;
-            self.frame_list_size = match fls {
-                0 => 1024,
-                1 => 512,
-                2 => 256,
-                _ => 1024,
-            };
-        }
+        let cap_header = self.read32(EHCI_CAPLENGTH);
+        let port_route = self.read32(EHCI_HCSP_PORTROUTE) as u64 | (self.read32(EHCI_HCSP_PORTROUTE + 4) as u64) << 32;
+        self.capability_params = EhciCapabilityParams {
+            cap_length: cap_header as u8,
+            hc_interface_version: (cap_header >> (EHCI_HCIVERSION * 8)) as u16,
+            hcs_params: self.read32(EHCI_HCSPARAMS),
+            hcc_params: self.read32(EHCI_HCCPARAMS),
+            port_route,
+        };
+
+        let hcs_params = self.capability_params.hcs_params;
+        self.max_ports = (hcs_params & EHCI_HCS_N_PORTS_MASK) as u8;
+        self.ports_per_companion = ((hcs_params >> EHCI_HCS_N_PCC_SHIFT) & 0xF) as u8;
+        self.companion_controllers = ((hcs_params >> EHCI_HCS_N_CC_SHIFT) & 0xF) as u8;
 
         log::info!("EHCI Controller initialized:");
         log::info!("  Max Ports: {}", self.max_ports);
-        log::info!("  Companion Required: {}", self.companion_required);
-        log::info!("  Frame List Size: {}", self.frame_list_size);
-        log::info!("  HC Version: {:#06x}", self.capability_params.hc_interface_version);
+        log::info!("  Companion Controllers: {} ({} ports each)", self.companion_controllers, self.ports_per_companion);
+        log::info!("  HCI Version: {:#06x}", self.capability_params.hc_interface_version);
     }
 
     /// Initialize EHCI controller
+    ///
+    /// Resets the controller, installs the periodic frame list and the
+    /// async ring, starts it and routes every port to it.
     pub fn initialize(&mut self) -> UsbResult<()> {
-        if self.state == EhciControllerState::Initialized {
+        if self.state == EhciControllerState::Running {
             return Ok(());
         }
 
-        // Reset the controller
+        self.read_capability_params();
+        if self.max_ports == 0 {
+            return Err(UsbDriverError::ControllerNotInitialized);
+        }
+
         self.reset()?;
 
-        // Enable controller
-        self.enable()?;
+        if self.capability_params.hcc_params & EHCI_HCC_64BIT != 0 {
+            self.op_write(EHCI_CTRLDSSEGMENT, 0);
+        }
+        self.initialize_frame_list()?;
+        self.initialize_async_head()?;
+        self.op_write(EHCI_USBINTR, EHCI_INTR_MASK);
+        self.state = EhciControllerState::Initialized;
 
-        // Initialize frame lists
-        self.initialize_frame_lists()?;
+        self.enable()?;
 
-        // Initialize queue head and qTD pools
-        self.initialize_pools()?;
+        // Ports belong to the companions until this is set; from now on
+        // they are released one by one
+        self.op_write(EHCI_CONFIGFLAG, 1);
+        self.power_ports()?;
 
-        // Discover ports
+        self.enable_periodic_schedule()?;
+        self.enable_async_schedule()?;
         self.discover_ports()?;
 
-        self.state = EhciControllerState::Initialized;
         log::info!("EHCI controller initialized successfully");
-
         Ok(())
     }
 
-    /// Reset the EHCI controller
+    /// Halt and reset the EHCI controller
+    ///
+    /// Resetting clears CONFIGFLAG, which hands every port back to the
+    /// companions.
     pub fn reset(&mut self) -> UsbResult<()> {
-        unsafe {
-            let cmd_reg = (self.base_address + EHCI_USBCMD as u64) as *mut u32;
-            let status_reg = (self.base_address + EHCI_USBSTS as u64) as *mut u32;
-
-            // Reset the controller
-            *cmd_reg = EHCI_CMD_HCRESET;
-            
-            // Wait for reset to complete
-            for _ in 0..10000 {
-                let status = *status_reg;
-                if status & EHCI_STS_HCH == 0 {
-                    break;
-                }
-            }
-
-            // Check if reset was successful
-            let status = *status_reg;
-            if status & EHCI_STS_HCH == 0 {
-                return Err(UsbDriverError::ControllerNotInitialized);
+        let cmd = self.op_read(EHCI_USBCMD);
+        self.op_write(EHCI_USBCMD, cmd & !EHCI_CMD_RS);
+        self.wait_status(EHCI_STS_HCH, EHCI_STS_HCH)?;
+
+        self.op_write(EHCI_USBCMD, EHCI_CMD_HCRESET);
+        let mut reset_done = false;
+        for _ in 0..EHCI_TIMEOUT_SPINS {
+            if self.op_read(EHCI_USBCMD) & EHCI_CMD_HCRESET == 0 {
+                reset_done = true;
+                break;
             }
-
-            self.state = EhciControllerState::Uninitialized;
+            core::hint::spin_loop();
         }
+        if !reset_done {
+            return Err(UsbDriverError::Timeout);
+        }
+
+        // Everything the controller knew about is gone
+        self.state = EhciControllerState::Uninitialized;
+        self.abandon_transfers();
+        self.devices.clear();
+        self.async_order.clear();
+        self.periodic = PeriodicSchedule::new();
+        self.bandwidth = BandwidthScheduler::new(UsbSpeed::High);
+        self.port_resets.clear();
+        self.frame_list = None;
+        self.async_head = None;
 
         log::info!("EHCI controller reset completed");
         Ok(())
     }
 
-    /// Enable the EHCI controller
+    /// Start the EHCI controller
     pub fn enable(&mut self) -> UsbResult<()> {
-        unsafe {
-            let cmd_reg = (self.base_address + EHCI_USBCMD as u64) as *mut u32;
-            let mut cmd = *cmd_reg;
-            
-            // Set Run/Stop bit
-            cmd |= EHCI_CMD_RS;
-            *cmd_reg = cmd;
-
-            // Enable interrupts
-            cmd |= EHCI_CMD_PPCEE; // Port Power Control Enable
-            *cmd_reg = cmd;
-
-            // Wait for controller to start
-            for _ in 0..10000 {
-                let status = *(self.base_address + EHCI_USBSTS as u64) as *const u32;
-                if status & EHCI_STS_HCH == 0 {
-                    break;
-                }
-            }
+        if self.frame_list.is_none() || self.async_head.is_none() {
+            return Err(UsbDriverError::ControllerNotInitialized);
+        }
 
-            let status = *(self.base_address + EHCI_USBSTS as u64) as *const u32;
-            if status & EHCI_STS_HCH != 0 {
-                return Err(UsbDriverError::ControllerNotInitialized);
-            }
+        let cmd = self.op_read(EHCI_USBCMD) & !(0xFF << EHCI_CMD_ITC_SHIFT);
+        self.op_write(EHCI_USBCMD, cmd | EHCI_CMD_RS | EHCI_INTERRUPT_THRESHOLD << EHCI_CMD_ITC_SHIFT);
+        self.wait_status(EHCI_STS_HCH, 0)?;
+
+        self.state = EhciControllerState::Running;
+        log::info!("EHCI controller enabled and running");
+        Ok(())
+    }
+
+    /// Halt the controller
+    pub fn halt(&mut self) -> UsbResult<()> {
+        let cmd = self.op_read(EHCI_USBCMD);
+        self.op_write(EHCI_USBCMD, cmd & !EHCI_CMD_RS);
+        self.wait_status(EHCI_STS_HCH, EHCI_STS_HCH)?;
+        self.state = EhciControllerState::Initialized;
+        Ok(())
+    }
 
-            self.state = EhciControllerState::Running;
+    /// Install an empty periodic frame list
+    pub fn initialize_frame_list(&mut self) -> UsbResult<()> {
+        let mut frame_list = XhciDmaBuffer::new(EHCI_FRAME_LIST_ENTRIES * mem::size_of::<u32>(), EHCI_PAGE_SIZE)?;
+        for frame in 0..EHCI_FRAME_LIST_ENTRIES {
+            frame_list.write_u32(frame * mem::size_of::<u32>(), EHCI_LINK_TERMINATE);
         }
+        self.op_write(EHCI_PERIODICLISTBASE, dma32(frame_list.address())?);
+        self.frame_list = Some(frame_list);
+        Ok(())
+    }
 
-        log::info!("EHCI controller enabled and running");
+    /// Install the async ring's head: an idle queue head linked to itself
+    pub fn initialize_async_head(&mut self) -> UsbResult<()> {
+        let mut head = XhciDmaBuffer::new(EHCI_QH_SIZE, EHCI_DESCRIPTOR_ALIGN)?;
+        let address = dma32(head.address())?;
+        head.write_u32(EHCI_QH_LINK, address | EHCI_LINK_TYPE_QH);
+        head.write_u32(EHCI_QH_CHARACTERISTICS, EHCI_QH_HEAD | EHCI_EPS_HIGH << EHCI_QH_EPS_SHIFT);
+        head.write_u32(EHCI_QH_CAPABILITIES, 1 << EHCI_QH_MULT_SHIFT);
+        head.write_u32(EHCI_QH_OVERLAY + EHCI_QTD_NEXT, EHCI_LINK_TERMINATE);
+        head.write_u32(EHCI_QH_OVERLAY + EHCI_QTD_ALT_NEXT, EHCI_LINK_TERMINATE);
+        head.write_u32(EHCI_QH_OVERLAY + EHCI_QTD_TOKEN, EHCI_QTD_HALTED);
+        self.op_write(EHCI_ASYNCLISTADDR, address);
+        self.async_head = Some(head);
         Ok(())
     }
 
-    /// Initialize frame lists (periodic and asynchronous)
-    pub fn initialize_frame_lists(&mut self) -> UsbResult<()> {
-        unsafe {
-            // Allocate periodic frame list
-            let frame_list_size = self.frame_list_size * mem::size_of::<u32>();
-            let frame_list = alloc::alloc::alloc(alloc::alloc::Layout::from_size_align(frame_list_size, 4096)?);
-            if frame_list.is_null() {
-                return Err(UsbDriverError::UnsupportedFeature);
-            }
+    /// Power every port if the controller switches port power
+    fn power_ports(&mut self) -> UsbResult<()> {
+        if self.capability_params.hcs_params & EHCI_HCS_PPC == 0 {
+            return Ok(());
+        }
+        for port_number in 1..=self.max_ports {
+            let portsc = self.op_read(self.portsc_offset(port_number));
+            self.write_portsc(port_number, portsc, EHCI_PORT_PP);
+        }
+        self.wait_microframes(EHCI_POWER_GOOD_MICROFRAMES)
+    }
 
-            self.periodic_frame_list = frame_list as *mut u32;
+    /// Enable the periodic schedule
+    pub fn enable_periodic_schedule(&mut self) -> UsbResult<()> {
+        self.set_schedule(EHCI_CMD_PSE, EHCI_STS_PSS, true)
+    }
 
-            // Zero out frame list
-            core::ptr::write_bytes(self.periodic_frame_list, 0, self.frame_list_size);
+    /// Enable the asynchronous schedule
+    pub fn enable_async_schedule(&mut self) -> UsbResult<()> {
+        self.set_schedule(EHCI_CMD_ASE, EHCI_STS_ASS, true)
+    }
 
-            // Allocate async list head
-            let async_head_size = mem::size_of::<EhciQH>();
-            let async_head = alloc::alloc::alloc(alloc::alloc::Layout::from_size_align(async_head_size, 64)?);
-            if async_head.is_null() {
-                return Err(UsbDriverError::UnsupportedFeature);
-            }
+    /// Disable the periodic schedule
+    pub fn disable_periodic_schedule(&mut self) -> UsbResult<()> {
+        self.set_schedule(EHCI_CMD_PSE, EHCI_STS_PSS, false)
+    }
 
-            self.async_list_head = async_head as *mut EhciQH;
+    /// Disable the asynchronous schedule
+    pub fn disable_async_schedule(&mut self) -> UsbResult<()> {
+        self.set_schedule(EHCI_CMD_ASE, EHCI_STS_ASS, false)
+    }
 
-            // Initialize async list head
-            let qh = &mut *self.async_list_head;
-            qh.link = (self.async_list_head as u32) | 0x00000001; // Terminate bit
-            qh.ep_capabilities = 0x80000001; // Head of reclamation list
-            qh.current_qtd = 0;
-            qh.next_qtd = 0x00000001; // Terminate bit
-            qh.altnext_qtd = 0x00000001; // Terminate bit
-            qh.token = 0x40000000; // Device address 0
-            qh.buffer_page0 = 0;
-            qh.buffer_page1 = 0;
-            qh.buffer_page2 = 0;
-            qh.buffer_page3 = 0;
-            qh.buffer_page4 = 0;
+    /// Switch a schedule and wait for its status to follow, as the
+    /// enable bit may only change while the two agree
+    fn set_schedule(&mut self, enable: u32, status: u32, on: bool) -> UsbResult<()> {
+        if self.state != EhciControllerState::Running {
+            return Err(UsbDriverError::ControllerNotInitialized);
+        }
+        let cmd = self.op_read(EHCI_USBCMD);
+        self.wait_status(status, if cmd & enable != 0 { status } else { 0 })?;
+        self.op_write(EHCI_USBCMD, if on { cmd | enable } else { cmd & !enable });
+        self.wait_status(status, if on { status } else { 0 })
+    }
 
-            // Write frame list base addresses to registers
-            let periodic_reg = (self.base_address + EHCI_PERIODICLISTBASE as u64) as *mut u32;
-            let async_reg = (self.base_address + EHCI_ASYNCLISTADDR as u64) as *mut u32;
+    /// Discover and initialize ports
+    pub fn discover_ports(&mut self) -> UsbResult<()> {
+        self.ports.clear();
 
-            *periodic_reg = self.periodic_frame_list as u32;
-            *async_reg = self.async_list_head as u32;
+        for port_number in 1..=self.max_ports {
+            let portsc = self.op_read(self.portsc_offset(port_number));
+            self.ports.push(EhciPort {
+                port_number,
+                speed: UsbSpeed::High,
+                status: portsc,
+                connection_status: portsc & EHCI_PORT_CCS != 0,
+                device_attached: portsc & EHCI_PORT_CCS != 0,
+                power_state: if portsc & EHCI_PORT_PP != 0 { UsbPowerState::Active } else { UsbPowerState::Off },
+                is_oc_enabled: portsc & EHCI_PORT_OCA != 0,
+                is_connected_to_companion: portsc & EHCI_PORT_OWNER != 0,
+            });
         }
 
-        log::info!("Frame lists initialized:");
-        log::info!("  Periodic frame list: {:#x}", self.periodic_frame_list as u64);
-        log::info!("  Async list head: {:#x}", self.async_list_head as u64);
+        log::info!("Discovered {} EHCI ports", self.ports.len());
         Ok(())
     }
 
-    /// Initialize queue head and qTD pools
-    pub fn initialize_pools(&mut self) -> UsbResult<()> {
-        let num_queue_heads = self.max_ports as usize * 32; // 32 endpoints per port
-        let num_qtds = num_queue_heads * 4; // 4 qTDs per endpoint
+    /// Get port status (PORTSC)
+    pub fn get_port_status(&mut self, port_number: u8) -> UsbResult<u32> {
+        if port_number == 0 || port_number > self.max_ports {
+            return Err(UsbDriverError::DeviceNotFound { address: port_number });
+        }
 
-        // Initialize queue head pool
-        unsafe {
-            let qh_pool_size = num_queue_heads * mem::size_of::<EhciQH>();
-            let qh_pool_base = alloc::alloc::alloc(alloc::alloc::Layout::from_size_align(qh_pool_size, 64)?);
-            if qh_pool_base.is_null() {
-                return Err(UsbDriverError::UnsupportedFeature);
+        let portsc = self.op_read(self.portsc_offset(port_number));
+        if let Some(port) = self.ports.get_mut(port_number as usize - 1) {
+            port.status = portsc;
+            port.connection_status = portsc & EHCI_PORT_CCS != 0;
+            port.device_attached = portsc & EHCI_PORT_CCS != 0;
+            port.is_oc_enabled = portsc & EHCI_PORT_OCA != 0;
+            port.is_connected_to_companion = portsc & EHCI_PORT_OWNER != 0;
+            if portsc & EHCI_PORT_PE != 0 {
+                port.speed = UsbSpeed::High;
+            } else if (portsc >> EHCI_PORT_LS_SHIFT) & EHCI_PORT_LS_MASK == EHCI_LINE_STATUS_K {
+                port.speed = UsbSpeed::Low;
             }
+        }
+        Ok(portsc)
+    }
 
-            let qh_pool = EhciQueueHeadPool {
-                base: qh_pool_base,
-                size: qh_pool_size,
-                queue_heads: BTreeMap::new(),
-            };
+    /// Write PORTSC, leaving the change bits not in `set` untouched
+    fn write_portsc(&self, port_number: u8, portsc: u32, set: u32) {
+        self.op_write(self.portsc_offset(port_number), (portsc & !EHCI_PORT_CHANGE_MASK) | set);
+    }
 
-            self.queue_head_pool = Some(qh_pool);
-        }
+    /// Companion controller and port behind an EHCI port
+    pub fn companion_port(&self, port_number: u8) -> Option<(usize, u8)> {
+        ehci_companion_port(self.capability_params.hcs_params, self.capability_params.port_route, port_number)
+    }
+
+    /// EHCI port behind a companion's port
+    pub fn port_for_companion(&self, companion: usize, companion_port: u8) -> Option<u8> {
+        (1..=self.max_ports).find(|&port| self.companion_port(port) == Some((companion, companion_port)))
+    }
 
-        // Initialize qTD pool
-        unsafe {
-            let qtd_pool_size = num_qtds * mem::size_of::<EhciQTD>();
-            let qtd_pool_base = alloc::alloc::alloc(alloc::alloc::Layout::from_size_align(qtd_pool_size, 64)?);
-            if qtd_pool_base.is_null() {
+    /// Whether a companion drives the port
+    pub fn port_owned_by_companion(&self, port_number: u8) -> bool {
+        port_number >= 1 && port_number <= self.max_ports
+            && self.op_read(self.portsc_offset(port_number)) & EHCI_PORT_OWNER != 0
+    }
+
+    /// Release a port's full- or low-speed device to its companion
+    pub fn release_port(&mut self, port_number: u8) -> UsbResult<EhciPortRoute> {
+        let (companion, port) = match self.companion_port(port_number) {
+            Some(route) => route,
+            None => {
+                log::warn!("EHCI port {}: full/low-speed device but no companion controller", port_number);
                 return Err(UsbDriverError::UnsupportedFeature);
             }
+        };
+        let portsc = self.get_port_status(port_number)?;
+        self.write_portsc(port_number, portsc & !EHCI_PORT_PE, EHCI_PORT_OWNER);
+        self.port_resets.remove(&port_number);
+        self.get_port_status(port_number)?;
+        log::info!("EHCI port {} routed to companion {} port {}", port_number, companion, port);
+        Ok(EhciPortRoute::Companion { companion, port })
+    }
+
+    /// Take a port back from its companion once its device is gone
+    pub fn reclaim_port(&mut self, port_number: u8) -> UsbResult<()> {
+        let portsc = self.get_port_status(port_number)?;
+        if portsc & EHCI_PORT_OWNER != 0 {
+            self.write_portsc(port_number, portsc & !EHCI_PORT_OWNER, 0);
+            self.get_port_status(port_number)?;
+        }
+        Ok(())
+    }
+
+    /// Begin a port reset, or release the port straight away if the line
+    /// state shows a low-speed device
+    fn begin_port_reset(&mut self, port_number: u8) -> UsbResult<Option<EhciPortRoute>> {
+        let portsc = self.get_port_status(port_number)?;
+        if portsc & EHCI_PORT_CCS == 0 || portsc & EHCI_PORT_OWNER != 0 {
+            return Err(UsbDriverError::DeviceNotFound { address: port_number });
+        }
+        if (portsc >> EHCI_PORT_LS_SHIFT) & EHCI_PORT_LS_MASK == EHCI_LINE_STATUS_K {
+            return self.release_port(port_number).map(Some);
+        }
+
+        // PE must be written as zero together with PR
+        self.write_portsc(port_number, portsc & !EHCI_PORT_PE, EHCI_PORT_PR);
+        let started = self.op_read(EHCI_FRINDEX);
+        self.port_resets.insert(port_number, started);
+        Ok(None)
+    }
 
-            let mut qtds = Vec::new();
-            for i in 0..num_qtds {
-                let qtd_ptr = qtd_pool_base.add(i * mem::size_of::<EhciQTD>()) as *mut EhciQTD;
-                qtds.push(qtd_ptr);
+    /// End a port reset: a device that chirped is high speed and enabled;
+    /// anything else goes to the companion
+    fn finish_port_reset(&mut self, port_number: u8) -> UsbResult<EhciPortRoute> {
+        self.port_resets.remove(&port_number);
+        let offset = self.portsc_offset(port_number);
+        let portsc = self.op_read(offset);
+        self.write_portsc(port_number, portsc & !EHCI_PORT_PR, 0);
+
+        let mut portsc = self.op_read(offset);
+        for _ in 0..EHCI_TIMEOUT_SPINS {
+            if portsc & EHCI_PORT_PR == 0 {
+                break;
             }
+            core::hint::spin_loop();
+            portsc = self.op_read(offset);
+        }
+        if portsc & EHCI_PORT_PR != 0 {
+            return Err(UsbDriverError::Timeout);
+        }
+        if portsc & EHCI_PORT_CCS == 0 {
+            return Err(UsbDriverError::DeviceNotFound { address: port_number });
+        }
 
-            let qtd_pool = EhciQtdPool {
-                base: qtd_pool_base,
-                size: qtd_pool_size,
-                qtds,
-            };
+        self.get_port_status(port_number)?;
+        if portsc & EHCI_PORT_PE != 0 {
+            Ok(EhciPortRoute::HighSpeed)
+        } else {
+            self.release_port(port_number)
+        }
+    }
 
-            self.qtd_pool = Some(qtd_pool);
+    /// Reset a port and decide which controller drives its device
+    pub fn reset_port(&mut self, port_number: u8) -> UsbResult<EhciPortRoute> {
+        if let Some(route) = self.begin_port_reset(port_number)? {
+            return Ok(route);
         }
+        self.wait_microframes(EHCI_PORT_RESET_MICROFRAMES)?;
+        self.finish_port_reset(port_number)
+    }
+
+    /// Current 1 ms frame
+    pub fn get_frame_number(&self) -> u32 {
+        (self.op_read(EHCI_FRINDEX) & EHCI_FRINDEX_MASK) >> 3
+    }
+
+    fn async_head_mut(&mut self) -> UsbResult<&mut XhciDmaBuffer> {
+        self.async_head.as_mut().ok_or(UsbDriverError::ControllerNotInitialized)
+    }
 
-        log::info!("Queue pools initialized:");
-        log::info!("  Queue heads: {}", num_queue_heads);
-        log::info!("  qTDs: {}", num_qtds);
+    fn queue_head(&mut self, key: (u8, u8)) -> UsbResult<&mut XhciDmaBuffer> {
+        self.devices
+            .get_mut(&key.0)
+            .and_then(|device| device.endpoints.get_mut(&key.1))
+            .map(|endpoint| &mut endpoint.qh)
+            .ok_or(UsbDriverError::DeviceNotFound { address: key.0 })
+    }
+
+    fn queue_head_link(&self, key: (u8, u8)) -> UsbResult<u32> {
+        let qh = self.devices
+            .get(&key.0)
+            .and_then(|device| device.endpoints.get(&key.1))
+            .ok_or(UsbDriverError::DeviceNotFound { address: key.0 })?;
+        Ok(dma32(qh.qh.address())? | EHCI_LINK_TYPE_QH)
+    }
+
+    /// Link a queue head into the async ring right after the head
+    fn link_async(&mut self, key: (u8, u8)) -> UsbResult<()> {
+        let link = self.queue_head_link(key)?;
+        let next = self.async_head_mut()?.read_u32(EHCI_QH_LINK);
+        self.queue_head(key)?.write_u32(EHCI_QH_LINK, next);
+        fence(Ordering::SeqCst);
+        self.async_head_mut()?.write_u32(EHCI_QH_LINK, link);
+        self.async_order.insert(0, key);
         Ok(())
     }
 
-    /// Discover and initialize ports
-    pub fn discover_ports(&mut self) -> UsbResult<()> {
-        self.ports.clear();
+    /// Take a queue head off the async ring; once this returns the
+    /// controller holds no reference to it
+    fn unlink_async(&mut self, key: (u8, u8)) -> UsbResult<()> {
+        let position = match self.async_order.iter().position(|&other| other == key) {
+            Some(position) => position,
+            None => return Ok(()),
+        };
+        let next = self.queue_head(key)?.read_u32(EHCI_QH_LINK);
+        if position == 0 {
+            self.async_head_mut()?.write_u32(EHCI_QH_LINK, next);
+        } else {
+            let previous = self.async_order[position - 1];
+            self.queue_head(previous)?.write_u32(EHCI_QH_LINK, next);
+        }
+        self.async_order.remove(position);
+        self.async_advance()
+    }
 
-        for port_num in 1..=self.max_ports {
-            let port = EhciPort {
-                port_number: port_num,
-                speed: UsbSpeed::High,
-                status: 0,
-                connection_status: false,
-                device_attached: false,
-                power_state: UsbPowerState::Active,
-                is_oc_enabled: false,
-                is_connected_to_companion: false,
-            };
+    /// Async advance doorbell handshake: once the controller answers, it
+    /// has dropped every cached pointer to unlinked queue heads
+    fn async_advance(&mut self) -> UsbResult<()> {
+        if self.state != EhciControllerState::Running || self.op_read(EHCI_USBSTS) & EHCI_STS_ASS == 0 {
+            return Ok(());
+        }
+        fence(Ordering::SeqCst);
+        self.op_write(EHCI_USBSTS, EHCI_STS_IAA);
+        let cmd = self.op_read(EHCI_USBCMD);
+        self.op_write(EHCI_USBCMD, cmd | EHCI_CMD_IAAD);
+        self.wait_status(EHCI_STS_IAA, EHCI_STS_IAA)?;
+        self.op_write(EHCI_USBSTS, EHCI_STS_IAA);
+        Ok(())
+    }
 
-            self.ports.push(port);
+    /// Rewrite the periodic tree: queue head links first, so every frame
+    /// list entry written afterwards leads through up-to-date links
+    fn rebuild_periodic(&mut self) -> UsbResult<()> {
+        let keys: Vec<(u8, u8)> = self.periodic.entries().iter().map(|entry| entry.key).collect();
+        for key in keys {
+            let link = match self.periodic.successor(key) {
+                Some(next) => self.queue_head_link(next)?,
+                None => EHCI_LINK_TERMINATE,
+            };
+            self.queue_head(key)?.write_u32(EHCI_QH_LINK, link);
         }
+        fence(Ordering::SeqCst);
 
-        log::info!("Discovered {} EHCI ports", self.ports.len());
+        let mut heads = [EHCI_LINK_TERMINATE; PERIODIC_SLOTS];
+        for (frame, head) in heads.iter_mut().enumerate() {
+            if let Some(key) = self.periodic.head(frame) {
+                *head = self.queue_head_link(key)?;
+            }
+        }
+        let frame_list = self.frame_list.as_mut().ok_or(UsbDriverError::ControllerNotInitialized)?;
+        for frame in 0..EHCI_FRAME_LIST_ENTRIES {
+            frame_list.write_u32(frame * mem::size_of::<u32>(), heads[frame % PERIODIC_SLOTS]);
+        }
         Ok(())
     }
 
-    /// Get port status
-    pub fn get_port_status(&mut self, port_number: u8) -> UsbResult<u32> {
-        if port_number == 0 || port_number > self.max_ports {
-            return Err(UsbDriverError::DeviceNotFound { address: port_number });
+    /// Take a queue head out of the periodic tree and wait until no frame
+    /// can still be walking through it
+    fn unlink_periodic(&mut self, key: (u8, u8)) -> UsbResult<()> {
+        if self.periodic.remove(key).is_none() {
+            return Ok(());
+        }
+        self.rebuild_periodic()?;
+        if self.state == EhciControllerState::Running {
+            self.wait_microframes(2 * 8)?;
         }
+        Ok(())
+    }
 
-        unsafe {
-            let portsc_offset = EHCI_PORTSC1 as u64 + ((port_number as u64 - 1) * 0x04);
-            let portsc_reg = (self.base_address + portsc_offset) as *const u32;
-            let status = *portsc_reg;
-
-            // Update port information
-            if port_number as usize <= self.ports.len() {
-                let port = &mut self.ports[port_number as usize - 1];
-                port.status = status;
-                port.connection_status = (status & EHCI_PORT_CONNECT) != 0;
-                port.device_attached = (status & EHCI_PORT_CONNECT) != 0;
-                port.is_connected_to_companion = (status & EHCI_PORT_POWNER) != 0;
-                
-                // Determine port speed from status
-                let line_status = (status & EHCI_PORT_LS) >> 10;
-                port.speed = match line_status {
-                    0 => UsbSpeed::Full,  // Full speed
-                    1 => UsbSpeed::Low,   // Low speed
-                    2 => UsbSpeed::High,  // High speed
-                    _ => UsbSpeed::Full,  // Default
-                };
+    /// Set up a queue head for an endpoint and put it on its schedule;
+    /// interrupt endpoints are polled as their bandwidth reservation says
+    fn add_endpoint(&mut self, address: u8, endpoint: u8, transfer_type: UsbTransferType, max_packet: u16, reservation: Option<BandwidthReservation>) -> UsbResult<()> {
+        let device = self.devices.get(&address).ok_or(UsbDriverError::DeviceNotFound { address })?;
+        let speed = device.speed;
+        let transaction_translator = device.transaction_translator;
+        let mult = if speed == UsbSpeed::High { ((max_packet >> 11) & 0x3) as u32 + 1 } else { 1 };
+        let smask = reservation.map_or(0x01, |reservation| reservation.microframes);
+
+        let mut qh = XhciDmaBuffer::new(EHCI_QH_SIZE, EHCI_DESCRIPTOR_ALIGN)?;
+        dma32(qh.address())?;
+        qh.write_u32(EHCI_QH_LINK, EHCI_LINK_TERMINATE);
+        qh.write_u32(EHCI_QH_CHARACTERISTICS, ehci_qh_characteristics(address, endpoint, speed, max_packet, transfer_type));
+        qh.write_u32(EHCI_QH_CAPABILITIES, ehci_qh_capabilities(speed, transaction_translator, transfer_type, smask, mult));
+        qh.write_u32(EHCI_QH_OVERLAY + EHCI_QTD_NEXT, EHCI_LINK_TERMINATE);
+        qh.write_u32(EHCI_QH_OVERLAY + EHCI_QTD_ALT_NEXT, EHCI_LINK_TERMINATE);
+
+        self.unschedule_endpoint(address, endpoint)?;
+        let device = self.devices.get_mut(&address).ok_or(UsbDriverError::DeviceNotFound { address })?;
+        device.endpoints.insert(endpoint, EhciEndpoint { qh, transfer_type, active: None, queued: VecDeque::new() });
+
+        match (transfer_type, reservation) {
+            (UsbTransferType::Interrupt, Some(reservation)) => {
+                let load = (max_packet & 0x7FF) as u32 * mult;
+                self.periodic.insert_at((address, endpoint), reservation.period, reservation.phase, load);
+                self.rebuild_periodic()
             }
+            (UsbTransferType::Interrupt, None) => Err(UsbDriverError::InvalidConfiguration),
+            _ => self.link_async((address, endpoint)),
+        }
+    }
+
+    /// Unschedule an endpoint's queue head, cancel its transfers, free it
+    /// and give up its bus time
+    fn remove_endpoint(&mut self, address: u8, endpoint: u8) -> UsbResult<()> {
+        self.bandwidth.release(address, endpoint);
+        self.unschedule_endpoint(address, endpoint)
+    }
 
-            Ok(status)
+    fn unschedule_endpoint(&mut self, address: u8, endpoint: u8) -> UsbResult<()> {
+        let transfer_type = match self.devices.get(&address).and_then(|device| device.endpoints.get(&endpoint)) {
+            Some(ep) => ep.transfer_type,
+            None => return Ok(()),
+        };
+        let result = match transfer_type {
+            UsbTransferType::Interrupt => self.unlink_periodic((address, endpoint)),
+            _ => self.unlink_async((address, endpoint)),
+        };
+        self.cancel_transfers(address, Some(endpoint));
+        if let Some(device) = self.devices.get_mut(&address) {
+            device.endpoints.remove(&endpoint);
         }
+        result
     }
 
-    /// Set port feature
-    pub fn set_port_feature(&mut self, port_number: u8, feature: u32) -> UsbResult<()> {
-        if port_number == 0 || port_number > self.max_ports {
-            return Err(UsbDriverError::DeviceNotFound { address: port_number });
+    /// Set up EP0 of a device still at the default address and move it to
+    /// `address`
+    pub fn address_device(&mut self, port_number: u8, speed: UsbSpeed, transaction_translator: Option<(u8, u8)>, address: u8) -> UsbResult<u8> {
+        if address == 0 || address > 127 || self.devices.contains_key(&address) {
+            return Err(UsbDriverError::InvalidConfiguration);
+        }
+        if self.devices.contains_key(&0) {
+            // Another device is being enumerated
+            return Err(UsbDriverError::DeviceNotFound { address: 0 });
         }
 
-        unsafe {
-            let portsc_offset = EHCI_PORTSC1 as u64 + ((port_number as u64 - 1) * 0x04);
-            let portsc_reg = (self.base_address + portsc_offset) as *mut u32;
-            let mut status = *portsc_reg;
+        let max_packet_size0 = default_max_packet_size0(speed);
+        self.devices.insert(0, EhciDevice {
+            address: 0,
+            port: port_number,
+            speed,
+            transaction_translator,
+            max_packet_size0,
+            endpoints: BTreeMap::new(),
+        });
+
+        let result = self.add_endpoint(0, 0, UsbTransferType::Control, max_packet_size0, None)
+            .and_then(|_| self.control_transfer(0, &set_address_request(address), &mut []));
+        if let Err(err) = result {
+            let _ = self.release_device(0);
+            return Err(err);
+        }
 
-            match feature {
-                // USB Port Power Control
-                0x0008 => {
-                    status |= EHCI_PORT_PPOWER;
-                }
-                // USB Port Reset
-                0x0004 => {
-                    status |= EHCI_PORT_FPR;
-                }
-                // USB Port Suspend
-                0x0005 => {
-                    status |= EHCI_PORT_SUSP;
-                }
-                _ => {
-                    log::warn!("Unsupported port feature: {:#x}", feature);
-                    return Err(UsbDriverError::UnsupportedFeature);
+        // The queue head keeps serving the device at its new address
+        let mut device = self.devices.remove(&0).expect("device at default address");
+        device.address = address;
+        if let Some(ep0) = device.endpoints.get_mut(&0) {
+            let characteristics = ep0.qh.read_u32(EHCI_QH_CHARACTERISTICS);
+            ep0.qh.write_u32(EHCI_QH_CHARACTERISTICS, (characteristics & !0x7F) | address as u32);
+        }
+        if let Some(position) = self.async_order.iter().position(|&key| key == (0, 0)) {
+            self.async_order[position] = (address, 0);
+        }
+        self.devices.insert(address, device);
+        if self.state == EhciControllerState::Running {
+            self.wait_microframes(EHCI_SET_ADDRESS_MICROFRAMES)?;
+        }
+
+        log::info!("EHCI port {}: device addressed as {}", port_number, address);
+        Ok(address)
+    }
+
+    /// Lowest address no device uses
+    pub fn next_free_address(&self) -> Option<u8> {
+        (1..=127).find(|address| !self.devices.contains_key(address))
+    }
+
+    /// Update the control endpoint's max packet size after reading the
+    /// device descriptor
+    pub fn set_max_packet_size0(&mut self, address: u8, max_packet_size: u16) -> UsbResult<()> {
+        let device = self.devices.get_mut(&address).ok_or(UsbDriverError::DeviceNotFound { address })?;
+        if device.max_packet_size0 == max_packet_size {
+            return Ok(());
+        }
+        let ep0 = device.endpoints.get_mut(&0).ok_or(UsbDriverError::DeviceNotFound { address })?;
+        if ep0.active.is_some() {
+            return Err(UsbDriverError::InvalidConfiguration);
+        }
+        let characteristics = ep0.qh.read_u32(EHCI_QH_CHARACTERISTICS) & !(0x7FF << EHCI_QH_MPS_SHIFT);
+        ep0.qh.write_u32(EHCI_QH_CHARACTERISTICS, characteristics | ((max_packet_size & 0x7FF) as u32) << EHCI_QH_MPS_SHIFT);
+        device.max_packet_size0 = max_packet_size;
+        Ok(())
+    }
+
+    /// Add the endpoints of a configuration to a device
+    ///
+    /// The periodic endpoints must all fit the bus and TT budgets, or
+    /// none of the endpoints is added.
+    pub fn configure_endpoints(&mut self, address: u8, endpoints: &[UsbEndpointDescriptor]) -> UsbResult<()> {
+        let device = self.devices.get(&address).ok_or(UsbDriverError::DeviceNotFound { address })?;
+        let (speed, transaction_translator) = (device.speed, device.transaction_translator);
+        let mut requests = Vec::new();
+        for endpoint in endpoints {
+            if endpoint_transfer_type(endpoint) == UsbTransferType::Isochronous {
+                log::warn!("EHCI device {}: isochronous endpoint {:#04x} not supported", address, endpoint.bEndpointAddress);
+                return Err(UsbDriverError::UnsupportedFeature);
+            }
+            if endpoint.bEndpointAddress & 0x0F == 0 {
+                return Err(UsbDriverError::InvalidConfiguration);
+            }
+            requests.extend(BandwidthRequest::from_endpoint(address, speed, transaction_translator, endpoint));
+        }
+        self.bandwidth.reserve_all(&requests)?;
+
+        for endpoint in endpoints {
+            let reservation = self.bandwidth.reservation(address, endpoint.bEndpointAddress).copied();
+            let transfer_type = endpoint_transfer_type(endpoint);
+            if let Err(err) = self.add_endpoint(address, endpoint.bEndpointAddress, transfer_type, endpoint.wMaxPacketSize, reservation) {
+                for endpoint in endpoints {
+                    let _ = self.remove_endpoint(address, endpoint.bEndpointAddress);
                 }
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Periodic load of the high-speed bus
+    pub fn bandwidth_usage(&self) -> BandwidthUsage {
+        self.bandwidth.usage()
+    }
+
+    /// Periodic load of every TT serving full- and low-speed devices
+    pub fn tt_bandwidth_usage(&self) -> BTreeMap<TtKey, BandwidthUsage> {
+        self.bandwidth.tt_usage()
+    }
+
+    /// Record whether a high-speed hub has a TT per port
+    pub fn set_hub_multi_tt(&mut self, hub: u8, multi_tt: bool) {
+        self.bandwidth.set_multi_tt(hub, multi_tt);
+    }
+
+    /// Forget a device and free its queue heads
+    pub fn release_device(&mut self, address: u8) -> UsbResult<()> {
+        let endpoints: Vec<u8> = match self.devices.get(&address) {
+            Some(device) => device.endpoints.keys().copied().collect(),
+            None => return Ok(()),
+        };
+        let mut result = Ok(());
+        for endpoint in endpoints {
+            if let Err(err) = self.remove_endpoint(address, endpoint) {
+                result = Err(err);
             }
+        }
+        self.devices.remove(&address);
+        result
+    }
 
-            *portsc_reg = status;
+    pub fn device(&self, address: u8) -> Option<&EhciDevice> {
+        self.devices.get(&address)
+    }
+
+    /// Address of the device on a root port
+    pub fn address_for_port(&self, port_number: u8) -> Option<u8> {
+        self.devices.values()
+            .find(|device| device.port == port_number && device.transaction_translator.is_none() && device.address != 0)
+            .map(|device| device.address)
+    }
+
+    /// Allocate and fill a qTD moving `length` bytes at `address`, which
+    /// may span five pages
+    fn new_qtd(pid: u32, address: u64, length: usize, toggle: bool, ioc: bool) -> UsbResult<XhciDmaBuffer> {
+        let mut qtd = XhciDmaBuffer::new(EHCI_QTD_SIZE, EHCI_DESCRIPTOR_ALIGN)?;
+        dma32(qtd.address())?;
+        let start = if length > 0 { dma32(address)? } else { 0 };
+        qtd.write_u32(EHCI_QTD_NEXT, EHCI_LINK_TERMINATE);
+        qtd.write_u32(EHCI_QTD_ALT_NEXT, EHCI_LINK_TERMINATE);
+        for page in 0..EHCI_QTD_BUFFER_PAGES {
+            let pointer = match page {
+                0 => start,
+                _ => (start & !(EHCI_PAGE_SIZE as u32 - 1)).wrapping_add((page * EHCI_PAGE_SIZE) as u32),
+            };
+            qtd.write_u32(EHCI_QTD_BUFFER + page * mem::size_of::<u32>(), if length > 0 { pointer } else { 0 });
+            qtd.write_u32(EHCI_QTD_BUFFER_HI + page * mem::size_of::<u32>(), 0);
         }
+        qtd.write_u32(EHCI_QTD_TOKEN, ehci_qtd_token(pid, length, toggle, ioc));
+        Ok(qtd)
+    }
 
+    /// Data-stage qTDs for `length` bytes of `buffer`; control transfers
+    /// start at DATA1 and track the toggle, the others leave it to the
+    /// queue head
+    fn data_qtds(buffer: &XhciDmaBuffer, pid: u32, length: usize, max_packet: usize, ioc_last: bool) -> UsbResult<Vec<EhciQtd>> {
+        let chunks = length.div_ceil(EHCI_QTD_MAX_LENGTH).max(1);
+        let mut toggle = true;
+        let mut qtds = Vec::with_capacity(chunks);
+        for chunk in 0..chunks {
+            let offset = chunk * EHCI_QTD_MAX_LENGTH;
+            let chunk_length = (length - offset).min(EHCI_QTD_MAX_LENGTH);
+            let ioc = ioc_last && chunk + 1 == chunks;
+            let qtd = Self::new_qtd(pid, buffer.address() + offset as u64, chunk_length, toggle, ioc)?;
+            if chunk_length.div_ceil(max_packet.max(1)) % 2 == 1 {
+                toggle = !toggle;
+            }
+            qtds.push(EhciQtd { qtd, offset, length: chunk_length, data: true });
+        }
+        Ok(qtds)
+    }
+
+    /// Chain the qTDs of a transfer; short data stages of control
+    /// transfers skip ahead to the status stage
+    fn link_qtds(qtds: &mut [EhciQtd], control: bool) -> UsbResult<()> {
+        let status = if control { Some(dma32(qtds[qtds.len() - 1].qtd.address())?) } else { None };
+        for index in 0..qtds.len() {
+            let next = match qtds.get(index + 1) {
+                Some(next) => dma32(next.qtd.address())?,
+                None => EHCI_LINK_TERMINATE,
+            };
+            let qtd = &mut qtds[index];
+            qtd.qtd.write_u32(EHCI_QTD_NEXT, next);
+            if let (true, Some(status)) = (qtd.data, status) {
+                qtd.qtd.write_u32(EHCI_QTD_ALT_NEXT, status);
+            }
+        }
         Ok(())
     }
 
-    /// Clear port feature
-    pub fn clear_port_feature(&mut self, port_number: u8, feature: u32) -> UsbResult<()> {
-        if port_number == 0 || port_number > self.max_ports {
-            return Err(UsbDriverError::DeviceNotFound { address: port_number });
+    /// Queue a control transfer on EP0
+    ///
+    /// For OUT requests `data` is sent; for IN requests up to `wLength`
+    /// bytes are received and returned by `take_transfer()`.
+    pub fn submit_control(&mut self, address: u8, setup: &UsbSetupPacket, data: &[u8]) -> UsbResult<EhciTransferHandle> {
+        let direction_in = setup.bmRequestType & 0x80 != 0;
+        let length = setup.wLength as usize;
+        if !direction_in && data.len() < length {
+            return Err(UsbDriverError::InvalidConfiguration);
         }
+        let max_packet = self.devices.get(&address)
+            .filter(|device| device.endpoints.contains_key(&0))
+            .map(|device| device.max_packet_size0 as usize)
+            .ok_or(UsbDriverError::DeviceNotFound { address })?;
+
+        let mut setup_buffer = XhciDmaBuffer::new(8, EHCI_DESCRIPTOR_ALIGN)?;
+        let mut bytes = [0u8; 8];
+        bytes[0] = setup.bmRequestType;
+        bytes[1] = setup.bRequest;
+        bytes[2..4].copy_from_slice(&setup.wValue.to_le_bytes());
+        bytes[4..6].copy_from_slice(&setup.wIndex.to_le_bytes());
+        bytes[6..8].copy_from_slice(&setup.wLength.to_le_bytes());
+        setup_buffer.copy_from(0, &bytes);
+
+        let mut qtds = Vec::new();
+        qtds.push(EhciQtd {
+            qtd: Self::new_qtd(EHCI_PID_SETUP, setup_buffer.address(), 8, false, false)?,
+            offset: 0,
+            length: 0,
+            data: false,
+        });
+
+        let buffer = if length > 0 {
+            let mut buffer = XhciDmaBuffer::new(length, EHCI_PAGE_SIZE)?;
+            if !direction_in {
+                buffer.copy_from(0, &data[..length]);
+            }
+            let pid = if direction_in { EHCI_PID_IN } else { EHCI_PID_OUT };
+            qtds.extend(Self::data_qtds(&buffer, pid, length, max_packet, false)?);
+            Some(buffer)
+        } else {
+            None
+        };
 
-        unsafe {
-            let portsc_offset = EHCI_PORTSC1 as u64 + ((port_number as u64 - 1) * 0x04);
-            let portsc_reg = (self.base_address + portsc_offset) as *mut u32;
-            let mut status = *portsc_reg;
+        // The status stage runs opposite to the data stage, IN without one
+        let status_pid = if length == 0 || !direction_in { EHCI_PID_IN } else { EHCI_PID_OUT };
+        qtds.push(EhciQtd { qtd: Self::new_qtd(status_pid, 0, 0, true, true)?, offset: length, length: 0, data: false });
+        Self::link_qtds(&mut qtds, true)?;
 
-            match feature {
-                // USB Port Enable/Disable Change
-                0x0004 => {
-                    status &= !EHCI_PORT_PEC;
-                    status &= !EHCI_PORT_PEDC;
-                }
-                // USB Port Connection Status Change
-                0x0008 => {
-                    status &= !EHCI_PORT_OCC;
+        self.queue_transfer(EhciInflight { address, endpoint: 0, control: true, direction_in, setup: Some(setup_buffer), buffer, qtds })
+    }
+
+    /// Queue a bulk or interrupt transfer
+    ///
+    /// The direction comes from `endpoint`. OUT transfers send `data`; IN
+    /// transfers receive up to `data.len()` bytes, returned by
+    /// `take_transfer()`.
+    pub fn submit_transfer(&mut self, address: u8, endpoint: u8, data: &[u8]) -> UsbResult<EhciTransferHandle> {
+        let direction_in = endpoint & 0x80 != 0;
+        if endpoint & 0x0F == 0 {
+            return Err(UsbDriverError::InvalidConfiguration);
+        }
+        if !self.devices.get(&address).map_or(false, |device| device.endpoints.contains_key(&endpoint)) {
+            return Err(UsbDriverError::InvalidConfiguration);
+        }
+
+        let mut buffer = XhciDmaBuffer::new(data.len(), EHCI_PAGE_SIZE)?;
+        if !direction_in {
+            buffer.copy_from(0, data);
+        }
+        let pid = if direction_in { EHCI_PID_IN } else { EHCI_PID_OUT };
+        let mut qtds = Self::data_qtds(&buffer, pid, data.len(), 1, true)?;
+        Self::link_qtds(&mut qtds, false)?;
+
+        self.queue_transfer(EhciInflight { address, endpoint, control: false, direction_in, setup: None, buffer: Some(buffer), qtds })
+    }
+
+    fn queue_transfer(&mut self, inflight: EhciInflight) -> UsbResult<EhciTransferHandle> {
+        let handle = EhciTransferHandle(self.next_handle);
+        self.next_handle += 1;
+        let (address, endpoint) = (inflight.address, inflight.endpoint);
+        self.inflight.insert(handle, inflight);
+
+        let ep = self.devices.get_mut(&address)
+            .and_then(|device| device.endpoints.get_mut(&endpoint))
+            .ok_or(UsbDriverError::DeviceNotFound { address })?;
+        ep.queued.push_back(handle);
+        if ep.active.is_none() {
+            self.start_next(address, endpoint)?;
+        }
+        Ok(handle)
+    }
+
+    /// Hand the next waiting transfer to an idle queue head
+    fn start_next(&mut self, address: u8, endpoint: u8) -> UsbResult<()> {
+        let ep = match self.devices.get_mut(&address).and_then(|device| device.endpoints.get_mut(&endpoint)) {
+            Some(ep) => ep,
+            None => return Ok(()),
+        };
+        let handle = match ep.queued.pop_front() {
+            Some(handle) => handle,
+            None => return Ok(()),
+        };
+        let first = match self.inflight.get(&handle) {
+            Some(inflight) => dma32(inflight.qtds[0].qtd.address())?,
+            None => return Ok(()),
+        };
+
+        // The overlay is idle: clear its status, keep the toggle of
+        // endpoints that track it, and point it at the first qTD last
+        let token = ep.qh.read_u32(EHCI_QH_OVERLAY + EHCI_QTD_TOKEN) & EHCI_QTD_TOGGLE;
+        ep.qh.write_u32(EHCI_QH_OVERLAY + EHCI_QTD_TOKEN, token);
+        ep.qh.write_u32(EHCI_QH_OVERLAY + EHCI_QTD_ALT_NEXT, EHCI_LINK_TERMINATE);
+        fence(Ordering::SeqCst);
+        ep.qh.write_u32(EHCI_QH_OVERLAY + EHCI_QTD_NEXT, first);
+        ep.active = Some(handle);
+        Ok(())
+    }
+
+    /// Outcome of a running transfer once the controller is done with it
+    fn transfer_outcome(inflight: &EhciInflight) -> Option<(UsbTransferStatus, usize)> {
+        let mut actual = 0;
+        let mut short = false;
+        let mut index = 0;
+        while index < inflight.qtds.len() {
+            let qtd = &inflight.qtds[index];
+            let token = qtd.qtd.read_u32(EHCI_QTD_TOKEN);
+            if token & EHCI_QTD_ACTIVE != 0 {
+                return None;
+            }
+            if token & EHCI_QTD_HALTED != 0 {
+                return Some((ehci_transfer_status(token), actual));
+            }
+            if qtd.data {
+                let residue = ((token >> EHCI_QTD_BYTES_SHIFT) & EHCI_QTD_BYTES_MASK) as usize;
+                actual += qtd.length - residue.min(qtd.length);
+                if residue > 0 {
+                    short = true;
+                    if !inflight.control {
+                        break;
+                    }
+                    // The controller went on with the status stage
+                    index = inflight.qtds.len() - 1;
+                    continue;
                 }
-                // USB Port Power
-                0x0008 => {
-                    status &= !EHCI_PORT_PPOWER;
+            }
+            index += 1;
+        }
+        fence(Ordering::Acquire);
+        let status = if short { UsbTransferStatus::ShortPacket } else { UsbTransferStatus::Success };
+        Some((status, actual))
+    }
+
+    /// Retire finished transfers and start the ones waiting behind them
+    fn scan_transfers(&mut self) {
+        let mut finished = Vec::new();
+        for device in self.devices.values() {
+            for (&endpoint, ep) in &device.endpoints {
+                if let Some(handle) = ep.active {
+                    if let Some(outcome) = self.inflight.get(&handle).and_then(Self::transfer_outcome) {
+                        finished.push((device.address, endpoint, handle, outcome));
+                    }
                 }
-                // USB Port Reset Change
-                0x0007 => {
-                    status &= !EHCI_PORT_RPR;
+            }
+        }
+
+        for (address, endpoint, handle, (status, actual)) in finished {
+            if let Some(ep) = self.devices.get_mut(&address).and_then(|device| device.endpoints.get_mut(&endpoint)) {
+                ep.active = None;
+                if !matches!(status, UsbTransferStatus::Success | UsbTransferStatus::ShortPacket) {
+                    // Clear the halt; a stall also resets the toggle
+                    ep.qh.write_u32(EHCI_QH_OVERLAY + EHCI_QTD_TOKEN, 0);
                 }
-                _ => {
-                    log::warn!("Unsupported port feature: {:#x}", feature);
-                    return Err(UsbDriverError::UnsupportedFeature);
+                ep.qh.write_u32(EHCI_QH_OVERLAY + EHCI_QTD_NEXT, EHCI_LINK_TERMINATE);
+            }
+            if let Some(inflight) = self.inflight.remove(&handle) {
+                self.complete_transfer(handle, inflight, status, actual);
+            }
+            let _ = self.start_next(address, endpoint);
+        }
+    }
+
+    /// Record the outcome of a transfer and report it as an event
+    fn complete_transfer(&mut self, handle: EhciTransferHandle, inflight: EhciInflight, status: UsbTransferStatus, actual: usize) {
+        let mut data = Vec::new();
+        if inflight.direction_in {
+            if let Some(buffer) = &inflight.buffer {
+                data.resize(actual.min(buffer.len()), 0);
+                buffer.copy_to(0, &mut data);
+            }
+        }
+
+        let (address, endpoint) = (inflight.address, inflight.endpoint);
+        self.stats.total_transactions += 1;
+        if matches!(status, UsbTransferStatus::Success | UsbTransferStatus::ShortPacket) {
+            self.stats.successful_transactions += 1;
+            self.stats.bytes_transferred += actual as u64;
+            self.events.push(UsbEvent::TransferCompleted { address, endpoint, status });
+        } else {
+            self.stats.failed_transactions += 1;
+            self.stats.error_count += 1;
+            self.stats.last_error = Some(format!("device {} endpoint {:#04x}: {:?}", address, endpoint, status));
+            self.events.push(UsbEvent::TransferError { address, endpoint, error: status });
+        }
+
+        self.completed.insert(handle, XhciTransferResult { status, actual_length: actual, data });
+    }
+
+    /// Drop the transfers of a device's endpoint, or of all its endpoints,
+    /// after their queue heads were unscheduled
+    fn cancel_transfers(&mut self, address: u8, endpoint: Option<u8>) {
+        let handles: Vec<EhciTransferHandle> = self.inflight.iter()
+            .filter(|(_, inflight)| inflight.address == address)
+            .filter(|(_, inflight)| endpoint.map_or(true, |endpoint| inflight.endpoint == endpoint))
+            .map(|(&handle, _)| handle)
+            .collect();
+        for handle in handles {
+            let inflight = self.inflight.remove(&handle).expect("inflight transfer");
+            self.complete_transfer(handle, inflight, UsbTransferStatus::Cancelled, 0);
+        }
+        if let Some(device) = self.devices.get_mut(&address) {
+            for (&key, ep) in device.endpoints.iter_mut() {
+                if endpoint.map_or(true, |endpoint| endpoint == key) {
+                    ep.active = None;
+                    ep.queued.clear();
                 }
             }
+        }
+    }
 
-            *portsc_reg = status;
+    fn abandon_transfers(&mut self) {
+        let addresses: Vec<u8> = self.devices.keys().copied().collect();
+        for address in addresses {
+            self.cancel_transfers(address, None);
         }
+    }
 
-        Ok(())
+    /// Take the result of a completed transfer
+    pub fn take_transfer(&mut self, handle: EhciTransferHandle) -> Option<XhciTransferResult> {
+        self.completed.remove(&handle)
     }
 
-    /// Enable periodic schedule
-    pub fn enable_periodic_schedule(&mut self) -> UsbResult<()> {
-        unsafe {
-            let cmd_reg = (self.base_address + EHCI_USBCMD as u64) as *mut u32;
-            let mut cmd = *cmd_reg;
-            
-            cmd |= EHCI_CMD_PSE;
-            *cmd_reg = cmd;
+    /// Retire finished transfers so they can be taken, leaving the events
+    /// they report for `process_events()`
+    pub fn poll_transfers(&mut self) {
+        self.scan_transfers();
+    }
+
+    fn wait_transfer(&mut self, handle: EhciTransferHandle) -> UsbResult<XhciTransferResult> {
+        for _ in 0..EHCI_TIMEOUT_SPINS {
+            self.scan_transfers();
+            if let Some(result) = self.completed.remove(&handle) {
+                return match result.status {
+                    UsbTransferStatus::Success | UsbTransferStatus::ShortPacket => Ok(result),
+                    status => Err(UsbDriverError::TransferFailed { status }),
+                };
+            }
+            core::hint::spin_loop();
         }
+        Err(UsbDriverError::Timeout)
+    }
 
-        log::info!("Periodic schedule enabled");
-        Ok(())
+    /// Perform a control transfer and return the number of bytes moved
+    pub fn control_transfer(&mut self, address: u8, setup: &UsbSetupPacket, data: &mut [u8]) -> UsbResult<usize> {
+        if data.len() < setup.wLength as usize {
+            return Err(UsbDriverError::InvalidConfiguration);
+        }
+        let handle = self.submit_control(address, setup, data)?;
+        let result = self.wait_transfer(handle)?;
+        if setup.bmRequestType & 0x80 != 0 {
+            data[..result.data.len()].copy_from_slice(&result.data);
+        }
+        Ok(result.actual_length)
     }
 
-    /// Enable asynchronous schedule
-    pub fn enable_async_schedule(&mut self) -> UsbResult<()> {
-        unsafe {
-            let cmd_reg = (self.base_address + EHCI_USBCMD as u64) as *mut u32;
-            let mut cmd = *cmd_reg;
-            
-            cmd |= EHCI_CMD_ASE;
-            *cmd_reg = cmd;
+    /// Perform a bulk transfer and return the number of bytes moved
+    pub fn bulk_transfer(&mut self, address: u8, endpoint: u8, data: &mut [u8]) -> UsbResult<usize> {
+        self.normal_transfer(address, endpoint, data, UsbTransferType::Bulk)
+    }
+
+    /// Perform an interrupt transfer and return the number of bytes moved
+    pub fn interrupt_transfer(&mut self, address: u8, endpoint: u8, data: &mut [u8]) -> UsbResult<usize> {
+        self.normal_transfer(address, endpoint, data, UsbTransferType::Interrupt)
+    }
+
+    fn normal_transfer(&mut self, address: u8, endpoint: u8, data: &mut [u8], transfer_type: UsbTransferType) -> UsbResult<usize> {
+        let configured = self.devices.get(&address)
+            .and_then(|device| device.endpoints.get(&endpoint))
+            .map(|ep| ep.transfer_type);
+        if configured != Some(transfer_type) {
+            return Err(UsbDriverError::InvalidConfiguration);
+        }
+        let handle = self.submit_transfer(address, endpoint, data)?;
+        let result = self.wait_transfer(handle)?;
+        if endpoint & 0x80 != 0 {
+            data[..result.data.len()].copy_from_slice(&result.data);
         }
+        Ok(result.actual_length)
+    }
 
-        log::info!("Asynchronous schedule enabled");
-        Ok(())
+    /// Address the high-speed device on a reset port and read its device
+    /// descriptor
+    pub fn describe_device(&mut self, port_number: u8, speed: UsbSpeed, transaction_translator: Option<(u8, u8)>) -> UsbResult<UsbDevice> {
+        let address = self.next_free_address().ok_or(UsbDriverError::InvalidConfiguration)?;
+        self.address_device(port_number, speed, transaction_translator, address)?;
+
+        let result = self.read_device_descriptor(address, speed);
+        if result.is_err() {
+            let _ = self.release_device(address);
+        }
+        result
     }
 
-    /// Disable periodic schedule
-    pub fn disable_periodic_schedule(&mut self) -> UsbResult<()> {
-        unsafe {
-            let cmd_reg = (self.base_address + EHCI_USBCMD as u64) as *mut u32;
-            let mut cmd = *cmd_reg;
-            
-            cmd &= !EHCI_CMD_PSE;
-            *cmd_reg = cmd;
+    fn read_device_descriptor(&mut self, address: u8, speed: UsbSpeed) -> UsbResult<UsbDevice> {
+        // The first 8 bytes hold bMaxPacketSize0
+        let mut descriptor = [0u8; 18];
+        self.control_transfer(address, &get_device_descriptor(8), &mut descriptor[..8])?;
+        if descriptor[7] >= 8 {
+            self.set_max_packet_size0(address, descriptor[7] as u16)?;
         }
 
-        log::info!("Periodic schedule disabled");
-        Ok(())
+        let length = self.control_transfer(address, &get_device_descriptor(18), &mut descriptor)?;
+        if length < descriptor.len() {
+            return Err(UsbDriverError::ProtocolError);
+        }
+        let descriptor = parse_device_descriptor(&descriptor);
+        log::info!("EHCI device {:04x}:{:04x} at address {}", descriptor.idVendor, descriptor.idProduct, address);
+        Ok(addressed_device(address, speed, descriptor))
     }
 
-    /// Disable asynchronous schedule
-    pub fn disable_async_schedule(&mut self) -> UsbResult<()> {
-        unsafe {
-            let cmd_reg = (self.base_address + EHCI_USBCMD as u64) as *mut u32;
-            let mut cmd = *cmd_reg;
-            
-            cmd &= !EHCI_CMD_ASE;
-            *cmd_reg = cmd;
+    /// Reset the device on a port, address it and read its device
+    /// descriptor; fails with `DeviceNotFound` once the port went to a
+    /// companion
+    pub fn enumerate_port(&mut self, port_number: u8) -> UsbResult<UsbDevice> {
+        match self.reset_port(port_number)? {
+            EhciPortRoute::HighSpeed => self.describe_device(port_number, UsbSpeed::High, None),
+            EhciPortRoute::Companion { .. } => Err(UsbDriverError::DeviceNotFound { address: port_number }),
         }
+    }
 
-        log::info!("Asynchronous schedule disabled");
-        Ok(())
+    /// Report connection changes on ports the EHCI owns
+    fn scan_ports(&mut self) {
+        for port_number in 1..=self.max_ports {
+            let portsc = self.op_read(self.portsc_offset(port_number));
+            if portsc & EHCI_PORT_CHANGE_MASK == 0 {
+                continue;
+            }
+            self.write_portsc(port_number, portsc, portsc & EHCI_PORT_CHANGE_MASK);
+            let _ = self.get_port_status(port_number);
+            if portsc & EHCI_PORT_OWNER != 0 || portsc & EHCI_PORT_CSC == 0 {
+                continue;
+            }
+
+            if portsc & EHCI_PORT_CCS != 0 {
+                let speed = if (portsc >> EHCI_PORT_LS_SHIFT) & EHCI_PORT_LS_MASK == EHCI_LINE_STATUS_K {
+                    UsbSpeed::Low
+                } else {
+                    UsbSpeed::High
+                };
+                self.events.push(UsbEvent::DeviceConnected { port: port_number, speed });
+            } else {
+                self.port_resets.remove(&port_number);
+                if let Some(address) = self.address_for_port(port_number) {
+                    self.events.push(UsbEvent::DeviceDisconnected { address });
+                }
+            }
+        }
+    }
+
+    /// Process completed transfers and port changes
+    ///
+    /// Returns the connection changes and transfer completions seen since
+    /// the last call.
+    pub fn process_events(&mut self) -> UsbResult<Vec<UsbEvent>> {
+        self.scan_ports();
+        self.scan_transfers();
+        Ok(mem::take(&mut self.events))
+    }
+
+    /// Service a controller interrupt
+    pub fn handle_interrupt(&mut self) -> UsbResult<Vec<UsbEvent>> {
+        let status = self.op_read(EHCI_USBSTS);
+        if status & EHCI_STS_ACK_MASK != 0 {
+            self.op_write(EHCI_USBSTS, status & EHCI_STS_ACK_MASK);
+        }
+        if status & EHCI_STS_HSE != 0 {
+            log::error!("EHCI host system error");
+            self.stats.error_count += 1;
+            self.state = EhciControllerState::Error;
+            return Err(UsbDriverError::ControllerNotInitialized);
+        }
+        self.process_events()
     }
 
     /// Get controller statistics
     pub fn get_stats(&self) -> UsbControllerStats {
-        UsbControllerStats {
-            total_transactions: 0,
-            successful_transactions: 0,
-            failed_transactions: 0,
-            bytes_transferred: 0,
-            error_count: 0,
-            last_error: None,
-        }
+        self.stats.clone()
     }
+}
 
-    /// Get current frame number
-    pub fn get_frame_number(&self) -> u32 {
-        unsafe {
-            let frindex_reg = (self.base_address + EHCI_FRINDEX as u64) as *const u32;
-            *frindex_reg
-        }
-    }
-
-    /// Create a queue head for an endpoint
-    pub fn create_queue_head(&mut self, device_address: u8, endpoint_number: u8, 
-                           direction: UsbDirection, speed: UsbSpeed) -> UsbResult<*mut EhciQH> {
-        let queue_heads = self.queue_head_pool.as_mut().ok_or(UsbDriverError::UnsupportedFeature)?;
-        
-        unsafe {
-            // Find free queue head
-            for qh_base in core::iter::Step::new(queue_heads.base, queue_heads.size, mem::size_of::<EhciQH>()) {
-                let qh_ptr = qh_base as *mut EhciQH;
-                let qh = &mut *qh_ptr;
-                
-                // Check if queue head is unused (token == 0)
-                if qh.token == 0 {
-                    // Initialize queue head
-                    qh.link = 0x00000001; // Terminate bit
-                    qh.ep_capabilities = (endpoint_number as u32) << 8 | (device_address as u32);
-                    
-                    // Set endpoint characteristics based on speed
-                    match speed {
-                        UsbSpeed::Low | UsbSpeed::Full => {
-                            qh.ep_capabilities |= 1 << 12; // Device is a low/full speed device
-                        }
-                        UsbSpeed::High => {
-                            qh.ep_capabilities |= 2 << 12; // Device is a high speed device
-                        }
-                        _ => {}
-                    }
-                    
-                    qh.current_qtd = 0;
-                    qh.next_qtd = 0x00000001; // Terminate bit
-                    qh.altnext_qtd = 0x00000001; // Terminate bit
-                    qh.token = 0x40000000; // Device address 0
-                    qh.buffer_page0 = 0;
-                    qh.buffer_page1 = 0;
-                    qh.buffer_page2 = 0;
-                    qh.buffer_page3 = 0;
-                    qh.buffer_page4 = 0;
-                    
-                    // Store queue head reference
-                    queue_heads.queue_heads.insert(device_address, qh_ptr);
-                    
-                    return Ok(qh_ptr);
-                }
+/// Root ports driven by the hotplug attach sequence
+///
+/// Ports released to a companion read as disconnected here; the companion
+/// reports their device. A reset ends after 50 ms on the frame counter,
+/// on the first status read after that.
+impl UsbPortHost for EhciController {
+    fn port_status(&mut self, port: u8) -> UsbResult<u32> {
+        if let Some(&started) = self.port_resets.get(&port) {
+            if self.microframes_since(started) >= EHCI_PORT_RESET_MICROFRAMES {
+                self.finish_port_reset(port)?;
             }
-            
-            Err(UsbDriverError::UnsupportedFeature)
         }
+
+        let portsc = self.get_port_status(port)?;
+        if portsc & EHCI_PORT_OWNER != 0 {
+            return Ok(0);
+        }
+        let mut status = 0;
+        if portsc & EHCI_PORT_CCS != 0 {
+            status |= USB_PORT_STAT_CONNECTION;
+        }
+        if portsc & EHCI_PORT_PE != 0 {
+            status |= USB_PORT_STAT_ENABLE | USB_PORT_STAT_HIGH_SPEED;
+        }
+        if portsc & EHCI_PORT_OCA != 0 {
+            status |= USB_PORT_STAT_OVERCURRENT;
+        }
+        if portsc & EHCI_PORT_PR != 0 {
+            status |= USB_PORT_STAT_RESET;
+        }
+        if portsc & EHCI_PORT_PP != 0 || self.capability_params.hcs_params & EHCI_HCS_PPC == 0 {
+            status |= USB_PORT_STAT_POWER;
+        }
+        if portsc & EHCI_PORT_SUSP != 0 {
+            status |= USB_PORT_STAT_SUSPEND;
+        }
+        Ok(status)
+    }
+
+    fn start_port_reset(&mut self, port: u8) -> UsbResult<()> {
+        self.begin_port_reset(port).map(|_| ())
+    }
+
+    fn disable_port(&mut self, port: u8) -> UsbResult<()> {
+        let portsc = self.get_port_status(port)?;
+        self.write_portsc(port, portsc & !EHCI_PORT_PE, 0);
+        Ok(())
+    }
+
+    fn set_address(&mut self, port: u8, speed: UsbSpeed, address: u8) -> UsbResult<u8> {
+        let address = if address != 0 && !self.devices.contains_key(&address) {
+            address
+        } else {
+            self.next_free_address().ok_or(UsbDriverError::InvalidConfiguration)?
+        };
+        self.address_device(port, speed, None, address)
+    }
+
+    fn control_transfer(&mut self, address: u8, setup: &UsbSetupPacket, data: &mut [u8]) -> UsbResult<usize> {
+        EhciController::control_transfer(self, address, setup, data)
+    }
+
+    fn set_max_packet_size0(&mut self, address: u8, max_packet_size: u16) -> UsbResult<()> {
+        EhciController::set_max_packet_size0(self, address, max_packet_size)
+    }
+
+    fn configure_endpoints(&mut self, address: u8, endpoints: &[UsbEndpointDescriptor]) -> UsbResult<()> {
+        EhciController::configure_endpoints(self, address, endpoints)
     }
 
-    /// Get queue head for device
-    pub fn get_queue_head(&self, device_address: u8) -> UsbResult<*mut EhciQH> {
-        let queue_heads = self.queue_head_pool.as_ref().ok_or(UsbDriverError::UnsupportedFeature)?;
-        queue_heads.queue_heads.get(&device_address)
-            .copied()
-            .ok_or(UsbDriverError::DeviceNotFound { address: device_address })
+    fn release_address(&mut self, address: u8) -> UsbResult<()> {
+        self.release_device(address)
     }
 }
 
 impl Drop for EhciController {
     fn drop(&mut self) {
-        unsafe {
-            // Clean up allocated memory
-            if !self.periodic_frame_list.is_null() {
-                let frame_list_size = self.frame_list_size * mem::size_of::<u32>();
-                alloc::alloc::dealloc(
-                    self.periodic_frame_list as *mut u8,
-                    alloc::alloc::Layout::from_size_align(frame_list_size, 4096).unwrap(),
-                );
+        // Stop DMA and give the ports back before the schedules are freed
+        if self.state == EhciControllerState::Running {
+            self.op_write(EHCI_CONFIGFLAG, 0);
+            let _ = self.halt();
+        }
+    }
+}
+
+/// EHCI host: the controller, its companions and the devices on both
+///
+/// High-speed devices are enumerated on the EHCI. Ports with full- or
+/// low-speed devices are released to the companion their routing names,
+/// which enumerates the device; when it is unplugged the port is taken
+/// back so the next device is offered to the EHCI first.
+#[derive(Debug)]
+pub struct EhciHost {
+    pub controller: EhciController,
+    /// Companion controllers, in PCI function order
+    pub companions: Vec<OhciController>,
+    /// High-speed devices by address
+    pub devices: BTreeMap<u8, UsbDevice>,
+    /// Full- and low-speed devices by companion and address
+    pub companion_devices: BTreeMap<(usize, u8), UsbDevice>,
+}
+
+impl EhciHost {
+    /// Bring up the platform's EHCI controller and its companions
+    pub fn new() -> UsbResult<Self> {
+        Self::with_companions(EHCI_DEFAULT_BASE_ADDRESS, &EHCI_DEFAULT_COMPANIONS)
+    }
+
+    /// Bring up the controller at `base_address` without companions;
+    /// full- and low-speed devices on its ports are not usable
+    pub fn with_base_address(base_address: u64) -> UsbResult<Self> {
+        Self::with_companions(base_address, &[])
+    }
+
+    /// Bring up the controller at `base_address` and the companions at
+    /// `companion_addresses`, then enumerate the devices already connected
+    pub fn with_companions(base_address: u64, companion_addresses: &[u64]) -> UsbResult<Self> {
+        // Companions first: they own every port until CONFIGFLAG is set
+        let mut companions = Vec::new();
+        for &companion_address in companion_addresses {
+            let mut companion = OhciController::new(companion_address);
+            match companion.initialize() {
+                Ok(()) => companions.push(companion),
+                Err(err) => log::warn!("EHCI companion at {:#x} failed to initialize: {:?}", companion_address, err),
             }
+        }
+
+        let mut controller = EhciController::new(base_address);
+        controller.initialize()?;
+        if (controller.companion_controllers as usize) > companions.len() {
+            log::warn!("EHCI reports {} companion controllers, {} available",
+                      controller.companion_controllers, companions.len());
+        }
+
+        let mut host = Self { controller, companions, devices: BTreeMap::new(), companion_devices: BTreeMap::new() };
+        host.enumerate()?;
+        Ok(host)
+    }
 
-            if !self.async_list_head.is_null() {
-                let async_head_size = mem::size_of::<EhciQH>();
-                alloc::alloc::dealloc(
-                    self.async_list_head as *mut u8,
-                    alloc::alloc::Layout::from_size_align(async_head_size, 64).unwrap(),
-                );
+    /// Enumerate every connected port without a device; returns how many
+    /// devices were added
+    pub fn enumerate(&mut self) -> UsbResult<usize> {
+        let mut added = 0;
+        for port_number in 1..=self.controller.max_ports {
+            if self.controller.address_for_port(port_number).is_some()
+                || self.controller.port_owned_by_companion(port_number) {
+                continue;
+            }
+            if self.controller.get_port_status(port_number)? & EHCI_PORT_CCS == 0 {
+                continue;
             }
+            if self.attach(port_number) {
+                added += 1;
+            }
+        }
+        Ok(added)
+    }
 
-            if let Some(qh_pool) = &self.queue_head_pool {
-                alloc::alloc::dealloc(
-                    qh_pool.base as *mut u8,
-                    alloc::alloc::Layout::from_size_align(qh_pool.size, 64).unwrap(),
-                );
+    fn attach(&mut self, port_number: u8) -> bool {
+        let result = match self.controller.reset_port(port_number) {
+            Ok(EhciPortRoute::HighSpeed) => self.controller
+                .describe_device(port_number, UsbSpeed::High, None)
+                .map(|device| {
+                    self.devices.insert(device.address, device);
+                }),
+            Ok(EhciPortRoute::Companion { companion, port }) => self.attach_companion(companion, port),
+            Err(err) => Err(err),
+        };
+        match result {
+            Ok(()) => true,
+            Err(err) => {
+                log::warn!("EHCI port {}: enumeration failed: {:?}", port_number, err);
+                false
             }
+        }
+    }
 
-            if let Some(qtd_pool) = &self.qtd_pool {
-                alloc::alloc::dealloc(
-                    qtd_pool.base as *mut u8,
-                    alloc::alloc::Layout::from_size_align(qtd_pool.size, 64).unwrap(),
-                );
+    fn attach_companion(&mut self, companion: usize, port: u8) -> UsbResult<()> {
+        let controller = self.companions.get_mut(companion).ok_or(UsbDriverError::UnsupportedFeature)?;
+        // The companion sees the device connect only after the handoff
+        if !controller.wait_for_connect(port) {
+            return Err(UsbDriverError::DeviceNotFound { address: port });
+        }
+        let device = controller.enumerate_port(port)?;
+        self.companion_devices.insert((companion, device.address), device);
+        Ok(())
+    }
+
+    /// Service the EHCI and companion interrupts, enumerating new devices
+    /// and releasing removed ones
+    pub fn handle_interrupt(&mut self) -> UsbResult<Vec<UsbEvent>> {
+        let mut events = self.controller.handle_interrupt()?;
+        for event in &events {
+            match *event {
+                UsbEvent::DeviceConnected { port, .. } => {
+                    if self.controller.address_for_port(port).is_none() {
+                        self.attach(port);
+                    }
+                }
+                UsbEvent::DeviceDisconnected { address } => {
+                    self.devices.remove(&address);
+                    let _ = self.controller.release_device(address);
+                }
+                _ => {}
             }
         }
+
+        for companion in 0..self.companions.len() {
+            let companion_events = match self.companions[companion].handle_interrupt() {
+                Ok(companion_events) => companion_events,
+                Err(err) => {
+                    log::warn!("EHCI companion {}: {:?}", companion, err);
+                    continue;
+                }
+            };
+            for event in &companion_events {
+                if let UsbEvent::DeviceDisconnected { address } = *event {
+                    let port = self.companions[companion].device(address).map(|device| device.port);
+                    self.companion_devices.remove(&(companion, address));
+                    let _ = self.companions[companion].release_device(address);
+                    if let Some(ehci_port) = port.and_then(|port| self.controller.port_for_companion(companion, port)) {
+                        let _ = self.controller.reclaim_port(ehci_port);
+                    }
+                }
+            }
+            // Connects on released ports were enumerated when released
+            events.extend(companion_events.into_iter().filter(|event| match *event {
+                UsbEvent::DeviceConnected { port, .. } => self.companions[companion].address_for_port(port).is_none(),
+                _ => true,
+            }));
+        }
+        Ok(events)
+    }
+
+    /// High-speed device at an address
+    pub fn device(&self, address: u8) -> Option<&UsbDevice> {
+        self.devices.get(&address)
+    }
+
+    pub fn port_count(&self) -> u8 {
+        self.controller.max_ports
     }
 }
 
@@ -804,4 +1902,61 @@ mod tests {
         assert_eq!(port.speed, UsbSpeed::High);
         assert!(!port.connection_status);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_qtd_token_encoding() {
+        let token = ehci_qtd_token(EHCI_PID_IN, 18, true, true);
+        assert_eq!(token & EHCI_QTD_ACTIVE, EHCI_QTD_ACTIVE);
+        assert_eq!((token >> EHCI_QTD_PID_SHIFT) & 0x3, EHCI_PID_IN);
+        assert_eq!((token >> EHCI_QTD_CERR_SHIFT) & 0x3, 3);
+        assert_eq!((token >> EHCI_QTD_BYTES_SHIFT) & EHCI_QTD_BYTES_MASK, 18);
+        assert_ne!(token & EHCI_QTD_IOC, 0);
+        assert_ne!(token & EHCI_QTD_TOGGLE, 0);
+
+        assert_eq!(ehci_transfer_status(EHCI_QTD_HALTED), UsbTransferStatus::Stalled);
+        assert_eq!(ehci_transfer_status(EHCI_QTD_HALTED | EHCI_QTD_BABBLE), UsbTransferStatus::BabbleDetected);
+        assert_eq!(ehci_transfer_status(EHCI_QTD_HALTED | EHCI_QTD_XACT_ERROR), UsbTransferStatus::NotAccessed);
+    }
+
+    #[test]
+    fn test_queue_head_encoding() {
+        let characteristics = ehci_qh_characteristics(5, 0, UsbSpeed::Full, 8, UsbTransferType::Control);
+        assert_eq!(characteristics & 0x7F, 5);
+        assert_eq!((characteristics >> EHCI_QH_EPS_SHIFT) & 0x3, EHCI_EPS_FULL);
+        assert_ne!(characteristics & EHCI_QH_DTC, 0);
+        assert_ne!(characteristics & EHCI_QH_CONTROL_ENDPOINT, 0);
+        assert_eq!((characteristics >> EHCI_QH_MPS_SHIFT) & 0x7FF, 8);
+
+        let characteristics = ehci_qh_characteristics(2, 1, UsbSpeed::High, 64, UsbTransferType::Interrupt);
+        assert_eq!(characteristics >> EHCI_QH_RL_SHIFT, 0);
+        assert_eq!(characteristics & EHCI_QH_CONTROL_ENDPOINT, 0);
+
+        // Full speed behind a hub: split transaction masks and the TT
+        let capabilities = ehci_qh_capabilities(UsbSpeed::Full, Some((3, 2)), UsbTransferType::Interrupt, 0x01, 1);
+        assert_eq!(capabilities & 0xFF, EHCI_SPLIT_SMASK);
+        assert_eq!((capabilities >> EHCI_QH_CMASK_SHIFT) & 0xFF, EHCI_SPLIT_CMASK);
+        assert_eq!((capabilities >> EHCI_QH_HUB_SHIFT) & 0x7F, 3);
+        assert_eq!((capabilities >> EHCI_QH_PORT_SHIFT) & 0x7F, 2);
+
+        let capabilities = ehci_qh_capabilities(UsbSpeed::High, None, UsbTransferType::Interrupt, 0x55, 1);
+        assert_eq!(capabilities & 0xFF, 0x55);
+    }
+
+    #[test]
+    fn test_companion_port_routing() {
+        // Four ports, two per companion, two companions
+        let hcs_params = 4 | 2 << EHCI_HCS_N_PCC_SHIFT | 2 << EHCI_HCS_N_CC_SHIFT;
+        assert_eq!(ehci_companion_port(hcs_params, 0, 1), Some((0, 1)));
+        assert_eq!(ehci_companion_port(hcs_params, 0, 2), Some((0, 2)));
+        assert_eq!(ehci_companion_port(hcs_params, 0, 3), Some((1, 1)));
+        assert_eq!(ehci_companion_port(hcs_params, 0, 5), None);
+
+        // Routing rules interleave the ports
+        let route = 0x1010;
+        assert_eq!(ehci_companion_port(hcs_params | EHCI_HCS_PRR, route, 2), Some((1, 1)));
+        assert_eq!(ehci_companion_port(hcs_params | EHCI_HCS_PRR, route, 3), Some((0, 2)));
+
+        // No companions: nowhere to send full/low-speed devices
+        assert_eq!(ehci_companion_port(4, 0, 1), None);
+    }
+}
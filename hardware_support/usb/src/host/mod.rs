@@ -9,6 +9,18 @@
 
 use crate::*;
 
+pub mod xhci;
+pub mod ehci;
+pub mod ohci;
+pub mod periodic;
+pub mod bandwidth;
+
+pub use xhci::{XhciController, XhciHost};
+pub use ehci::{EhciController, EhciHost, EhciPortRoute};
+pub use ohci::{OhciController, OhciHost};
+pub use periodic::{PeriodicSchedule, PeriodicEntry};
+pub use bandwidth::{BandwidthScheduler, BandwidthRequest, BandwidthReservation, BandwidthUsage};
+
 #[cfg(feature = "std")]
 use std::collections::BTreeMap;
 
@@ -418,6 +430,81 @@ impl UsbHostControllerManager {
     }
 }
 
+/// GET_DESCRIPTOR(DEVICE) request for `length` bytes
+pub(crate) fn get_device_descriptor(length: u16) -> UsbSetupPacket {
+    UsbSetupPacket {
+        bmRequestType: 0x80,
+        bRequest: UsbStandardRequest::GET_DESCRIPTOR as u8,
+        wValue: (UsbDescriptorType::Device as u16) << 8,
+        wIndex: 0,
+        wLength: length,
+    }
+}
+
+/// SET_ADDRESS request
+pub(crate) fn set_address_request(address: u8) -> UsbSetupPacket {
+    UsbSetupPacket {
+        bmRequestType: 0x00,
+        bRequest: UsbStandardRequest::SET_ADDRESS as u8,
+        wValue: address as u16,
+        wIndex: 0,
+        wLength: 0,
+    }
+}
+
+/// Decode a device descriptor
+pub(crate) fn parse_device_descriptor(descriptor: &[u8; 18]) -> UsbDeviceDescriptor {
+    UsbDeviceDescriptor {
+        bLength: descriptor[0],
+        bDescriptorType: descriptor[1],
+        bcdUSB: u16::from_le_bytes([descriptor[2], descriptor[3]]),
+        bDeviceClass: descriptor[4],
+        bDeviceSubClass: descriptor[5],
+        bDeviceProtocol: descriptor[6],
+        bMaxPacketSize0: descriptor[7],
+        idVendor: u16::from_le_bytes([descriptor[8], descriptor[9]]),
+        idProduct: u16::from_le_bytes([descriptor[10], descriptor[11]]),
+        bcdDevice: u16::from_le_bytes([descriptor[12], descriptor[13]]),
+        iManufacturer: descriptor[14],
+        iProduct: descriptor[15],
+        iSerialNumber: descriptor[16],
+        bNumConfigurations: descriptor[17],
+    }
+}
+
+/// Device freshly moved to `address`, before it is configured
+pub(crate) fn addressed_device(address: u8, speed: UsbSpeed, descriptor: UsbDeviceDescriptor) -> UsbDevice {
+    UsbDevice {
+        address,
+        vendor_id: descriptor.idVendor,
+        product_id: descriptor.idProduct,
+        speed,
+        state: UsbDeviceState::Address,
+        configuration: 0,
+        interfaces: Vec::new(),
+        descriptor: Some(descriptor),
+    }
+}
+
+/// Default control endpoint packet size before the device descriptor is read
+pub(crate) fn default_max_packet_size0(speed: UsbSpeed) -> u16 {
+    match speed {
+        UsbSpeed::Low | UsbSpeed::Full => 8,
+        UsbSpeed::High => 64,
+        UsbSpeed::Super | UsbSpeed::SuperPlus => 512,
+    }
+}
+
+/// Transfer type of an endpoint descriptor
+pub(crate) fn endpoint_transfer_type(endpoint: &UsbEndpointDescriptor) -> UsbTransferType {
+    match endpoint.bmAttributes & 0x3 {
+        0 => UsbTransferType::Control,
+        1 => UsbTransferType::Isochronous,
+        2 => UsbTransferType::Bulk,
+        _ => UsbTransferType::Interrupt,
+    }
+}
+
 impl Default for UsbHostControllerManager {
     fn default() -> Self {
         Self::new()
@@ -1,59 +1,82 @@
 //! OHCI (Open Host Controller Interface) Driver
-//! 
+//!
 //! Supports USB 1.0/1.1 legacy hosts with features like:
 //! - Low and Full speed USB support
 //! - Simple interrupt-driven architecture
 //! - ED (Endpoint Descriptor) and TD (Transfer Descriptor) based
 //! - Legacy system support for older hardware
-
+//!
+//! Every endpoint has an ED on the control or bulk list, or in the
+//! interrupt tree hanging off the HCCA. An ED's TD queue always ends in an
+//! empty dummy TD: a transfer is written into the dummy, a new dummy is
+//! appended, and moving TailP hands the transfer to the controller.
+//! Retired TDs come back on the done queue, newest first.
+//!
+//! OHCI controllers also serve as EHCI companions, driving the full- and
+//! low-speed devices the EHCI releases to them.
+
+use core::mem;
+use core::sync::atomic::{fence, Ordering};
 use crate::*;
+use crate::hotplug::{
+    UsbPortHost, USB_PORT_STAT_CONNECTION, USB_PORT_STAT_ENABLE, USB_PORT_STAT_LOW_SPEED, USB_PORT_STAT_OVERCURRENT,
+    USB_PORT_STAT_POWER, USB_PORT_STAT_RESET, USB_PORT_STAT_SUSPEND,
+};
+use super::bandwidth::{BandwidthRequest, BandwidthReservation, BandwidthScheduler, BandwidthUsage};
+use super::periodic::{PeriodicSchedule, PERIODIC_SLOTS};
+use super::xhci::{XhciDmaBuffer, XhciTransferResult};
+use super::{
+    addressed_device, default_max_packet_size0, endpoint_transfer_type, get_device_descriptor, parse_device_descriptor,
+    set_address_request,
+};
 
 #[cfg(feature = "std")]
 use std::collections::BTreeMap;
 
 /// OHCI Host Controller Registers
-const OHCI_HCREVISION: u32 = 0x00;
-const OHCI_HCCONTROL: u32 = 0x04;
-const OHCI_HCCOMMANDSTATUS: u32 = 0x08;
-const OHCI_HCINTERRUPTSTATUS: u32 = 0x0C;
-const OHCI_HCINTERRUPTENABLE: u32 = 0x10;
-const OHCI_HCINTERRUPTDISABLE: u32 = 0x14;
-const OHCI_HCHCCA: u32 = 0x18;
-const OHCI_HCPERIODCURRENTED: u32 = 0x1C;
-const OHCI_HCCTRLHEADED: u32 = 0x20;
-const OHCI_HCCTRLCURRENTED: u32 = 0x24;
-const OHCI_HCBULKHEADED: u32 = 0x28;
-const OHCI_HCBULKCURRENTED: u32 = 0x2C;
-const OHCI_HCDONEHEAD: u32 = 0x30;
-const OHCI_HCFMINTERVAL: u32 = 0x34;
-const OHCI_HCFMREMAINING: u32 = 0x38;
-const OHCI_HCFMRNUMBER: u32 = 0x3C;
-const OHCI_HCFSMDYNAMICLOAD: u32 = 0x40;
-const OHCI_HCCTRLRDCOMPLETION: u32 = 0x44;
-const OHCI_HCPORTSTATUS1: u32 = 0x64;
-const OHCI_HCPORTSTATUS2: u32 = 0x68;
+const OHCI_HCREVISION: usize = 0x00;
+const OHCI_HCCONTROL: usize = 0x04;
+const OHCI_HCCOMMANDSTATUS: usize = 0x08;
+const OHCI_HCINTERRUPTSTATUS: usize = 0x0C;
+const OHCI_HCINTERRUPTENABLE: usize = 0x10;
+const OHCI_HCINTERRUPTDISABLE: usize = 0x14;
+const OHCI_HCHCCA: usize = 0x18;
+const OHCI_HCCTRLHEADED: usize = 0x20;
+const OHCI_HCCTRLCURRENTED: usize = 0x24;
+const OHCI_HCBULKHEADED: usize = 0x28;
+const OHCI_HCBULKCURRENTED: usize = 0x2C;
+const OHCI_HCFMINTERVAL: usize = 0x34;
+const OHCI_HCFMNUMBER: usize = 0x3C;
+const OHCI_HCPERIODICSTART: usize = 0x40;
+const OHCI_HCLSTHRESHOLD: usize = 0x44;
+const OHCI_HCRHDESCRIPTORA: usize = 0x48;
+const OHCI_HCRHDESCRIPTORB: usize = 0x4C;
+const OHCI_HCRHSTATUS: usize = 0x50;
+const OHCI_HCRHPORTSTATUS_BASE: usize = 0x54;
 
 /// OHCI Frame Interval
 const OHCI_FR_INTERVAL: u32 = 0x2EDF; // Frame interval for 12MHz clock
-const OHCI_FR_NUMBER_MASK: u32 = 0x3FFF;
-const OHCI_FR_ADJUST_MASK: u32 = 0x1F;
+const OHCI_FR_INTERVAL_MASK: u32 = 0x3FFF;
+const OHCI_FR_FSMPS_SHIFT: u32 = 16;
+const OHCI_FR_FIT: u32 = 1 << 31;
+const OHCI_FR_NUMBER_MASK: u32 = 0xFFFF;
+/// Low-speed threshold recommended by the specification
+const OHCI_LS_THRESHOLD: u32 = 0x628;
 
 /// OHCI Control Register bit fields
 const OHCI_CTRL_CBSR_MASK: u32 = 0x00000003;
-const OHCI_CTRL_CBSR_SHIFT: u32 = 0;
-const OHCI_CTRL_HCFS_MASK: u32 = 0x0000001C;
-const OHCI_CTRL_HCFS_SHIFT: u32 = 2;
+const OHCI_CTRL_PLE: u32 = 0x00000004;
+const OHCI_CTRL_CLE: u32 = 0x00000010;
 const OHCI_CTRL_BLE: u32 = 0x00000020;
-const OHCI_CTRL_CLE: u32 = 0x00000040;
-const OHCI_CTRL_IE: u32 = 0x00000080;
-const OHCI_CTRL_RWE: u32 = 0x00000100;
+const OHCI_CTRL_HCFS_MASK: u32 = 0x000000C0;
+const OHCI_CTRL_IR: u32 = 0x00000100;
 const OHCI_CTRL_RWC: u32 = 0x00000200;
-const OHCI_CTRL_IR: u32 = 0x00000400;
 
 /// OHCI Host Controller Functional States
 const OHCI_HCFS_USBRESET: u32 = 0x00000000;
-const OHCI_HCFS_USBOPERATIONAL: u32 = 0x00000008;
-const OHCI_HCFS_USBSUSPEND: u32 = 0x0000000C;
+const OHCI_HCFS_USBRESUME: u32 = 0x00000040;
+const OHCI_HCFS_USBOPERATIONAL: u32 = 0x00000080;
+const OHCI_HCFS_USBSUSPEND: u32 = 0x000000C0;
 
 /// OHCI Command Status Register bit fields
 const OHCI_CMD_HCR: u32 = 0x00000001;
@@ -71,101 +94,134 @@ const OHCI_INT_FNO: u32 = 0x00000020;
 const OHCI_INT_RHSC: u32 = 0x00000040;
 const OHCI_INT_OC: u32 = 0x40000000;
 const OHCI_INT_MIE: u32 = 0x80000000;
-
-/// OHCI Port Status Register bit fields
+const OHCI_INT_ALL: u32 = OHCI_INT_SO | OHCI_INT_WDH | OHCI_INT_SF | OHCI_INT_RD | OHCI_INT_UE | OHCI_INT_FNO
+    | OHCI_INT_RHSC | OHCI_INT_OC;
+/// Interrupts the driver services
+const OHCI_INT_ENABLED: u32 = OHCI_INT_SO | OHCI_INT_WDH | OHCI_INT_UE | OHCI_INT_RHSC;
+
+/// Root hub descriptor A fields
+const OHCI_RH_NDP_MASK: u32 = 0xFF;
+const OHCI_RH_PSM: u32 = 1 << 8;           // Ports powered individually
+const OHCI_RH_NPS: u32 = 1 << 9;           // No power switching
+const OHCI_RH_POTPGT_SHIFT: u32 = 24;      // Power on to power good, 2 ms units
+/// Root hub status: write to switch on global power
+const OHCI_RH_LPSC: u32 = 1 << 16;
+
+/// OHCI Port Status Register bit fields, as read
 const OHCI_PORT_CCS: u32 = 0x00000001;
 const OHCI_PORT_PES: u32 = 0x00000002;
 const OHCI_PORT_PSS: u32 = 0x00000004;
 const OHCI_PORT_POCI: u32 = 0x00000008;
-const OHCI_PORT_PRSC: u32 = 0x00000010;
-const OHCI_PORT_LSDA: u32 = 0x00000100;
-const OHCI_PORT_CSC: u32 = 0x00000001;
-const OHCI_PORT_PESC: u32 = 0x00000002;
-const OHCI_PORT_PSSC: u32 = 0x00000004;
-const OHCI_PORT_OCIC: u32 = 0x00000008;
-
-/// OHCI Transfer Descriptor (TD) structure
-#[derive(Debug, Clone, Copy)]
-#[repr(C)]
-pub struct OhciTD {
-    pub next_td: u32,            // Link to next TD
-    pub alt_next_td: u32,        // Alternate next TD
-    pub cbp: u32,                // Current buffer pointer
-    pub td_control: u32,         // TD control fields
-}
-
-/// OHCI Transfer Descriptor Control bit fields
-const OHCI_TD_CC_MASK: u32 = 0xF0000000;
-const OHCI_TD_CC_SHIFT: u32 = 28;
-const OHCI_TD_EC_MASK: u32 = 0x0C000000;
-const OHCI_TD_EC_SHIFT: u32 = 26;
-const OHCI_TD_T_MASK: u32 = 0x03000000;
-const OHCI_TD_T_SHIFT: u32 = 24;
-const OHCI_TD_DI_MASK: u32 = 0x00E00000;
-const OHCI_TD_DI_SHIFT: u32 = 21;
-const OHCI_TD_DP_MASK: u32 = 0x00180000;
-const OHCI_TD_DP_SHIFT: u32 = 19;
-const OHCI_TD_CBP_MASK: u32 = 0x001FFFFF;
-
-/// OHCI Completion Codes
-const OHCI_CC_NO_ERROR: u32 = 0x00000000;
-const OHCI_CC_CRC: u32 = 0x00000001;
-const OHCI_CC_BIT_STUFFING: u32 = 0x00000002;
-const OHCI_CC_DATA_TOGGLE_MISMATCH: u32 = 0x00000003;
-const OHCI_CC_STALL: u32 = 0x00000004;
-const OHCI_CC_DEVICE_NOT_RESPONDING: u32 = 0x00000005;
-const OHCI_CC_PID_CHECK_FAILURE: u32 = 0x00000006;
-const OHCI_CC_PID_UNEXPECTED: u32 = 0x00000007;
-const OHCI_CC_DATA_UNDERUN: u32 = 0x00000008;
-const OHCI_CC_DATA_OVERRUN: u32 = 0x00000009;
-const OHCI_CC_BUFFER_OVERRUN: u32 = 0x0000000A;
-const OHCI_CC_BUFFER_UNDERRUN: u32 = 0x0000000B;
-const OHCI_CC_NOT_ACCESSED: u32 = 0x0000000C;
-
-/// OHCI Endpoint Descriptor (ED) structure
-#[derive(Debug, Clone, Copy)]
-#[repr(C)]
-pub struct OhciED {
-    pub next_ed: u32,            // Link to next ED
-    pub td_control: u32,         // ED control fields
-    pub current_td: u32,         // Current TD pointer
-    pub tail_td: u32,            // Tail TD pointer
-}
+const OHCI_PORT_PRS: u32 = 0x00000010;
+const OHCI_PORT_PPS: u32 = 0x00000100;
+const OHCI_PORT_LSDA: u32 = 0x00000200;
+const OHCI_PORT_CSC: u32 = 0x00010000;
+const OHCI_PORT_PESC: u32 = 0x00020000;
+const OHCI_PORT_PSSC: u32 = 0x00040000;
+const OHCI_PORT_OCIC: u32 = 0x00080000;
+const OHCI_PORT_PRSC: u32 = 0x00100000;
+const OHCI_PORT_CHANGE_MASK: u32 = OHCI_PORT_CSC | OHCI_PORT_PESC | OHCI_PORT_PSSC | OHCI_PORT_OCIC | OHCI_PORT_PRSC;
+
+/// OHCI Port Status Register commands: writing 1 acts, 0 does nothing
+const OHCI_PORT_CLEAR_ENABLE: u32 = 0x00000001;
+const OHCI_PORT_SET_RESET: u32 = 0x00000010;
+const OHCI_PORT_SET_POWER: u32 = 0x00000100;
+
+/// HCCA layout
+const OHCI_HCCA_SIZE: usize = 256;
+const OHCI_HCCA_INTERRUPT_TABLE: usize = 0x00;
+const OHCI_HCCA_FRAME_NUMBER: usize = 0x80;
+const OHCI_HCCA_DONE_HEAD: usize = 0x84;
+
+/// OHCI Endpoint Descriptor (ED) layout
+const OHCI_ED_CONTROL: usize = 0x00;
+const OHCI_ED_TAIL: usize = 0x04;
+const OHCI_ED_HEAD: usize = 0x08;
+const OHCI_ED_NEXT: usize = 0x0C;
+const OHCI_ED_SIZE: usize = 16;
 
 /// OHCI Endpoint Descriptor Control bit fields
-const OHCI_ED_FA_MASK: u32 = 0x0000007F;
-const OHCI_ED_FA_SHIFT: u32 = 0;
-const OHCI_ED_EN_MASK: u32 = 0x00000780;
 const OHCI_ED_EN_SHIFT: u32 = 7;
-const OHCI_ED_D_MASK: u32 = 0x00001800;
 const OHCI_ED_D_SHIFT: u32 = 11;
-const OHCI_ED_S_MASK: u32 = 0x00006000;
-const OHCI_ED_S_SHIFT: u32 = 13;
-const OHCI_ED_K_MASK: u32 = 0x00008000;
-const OHCI_ED_F_MASK: u32 = 0x00010000;
-const OHCI_ED_MPS_MASK: u32 = 0x07FF0000;
+const OHCI_ED_S: u32 = 1 << 13;            // Low speed
+const OHCI_ED_K: u32 = 1 << 14;            // Skip
 const OHCI_ED_MPS_SHIFT: u32 = 16;
+/// HeadP flags
+const OHCI_ED_HALTED: u32 = 1 << 0;
+const OHCI_ED_CARRY: u32 = 1 << 1;
+const OHCI_ED_POINTER_MASK: u32 = !0xF;
+
+/// OHCI Endpoint Direction codes (ED D field)
+const OHCI_ED_DIR_TD: u32 = 0;
+const OHCI_ED_DIR_OUT: u32 = 1;
+const OHCI_ED_DIR_IN: u32 = 2;
+
+/// OHCI Transfer Descriptor (TD) layout
+const OHCI_TD_CONTROL: usize = 0x00;
+const OHCI_TD_CBP: usize = 0x04;
+const OHCI_TD_NEXT: usize = 0x08;
+const OHCI_TD_BE: usize = 0x0C;
+const OHCI_TD_SIZE: usize = 16;
+const OHCI_DESCRIPTOR_ALIGN: usize = 16;
+const OHCI_PAGE_SIZE: usize = 4096;
+
+/// OHCI Transfer Descriptor Control bit fields
+const OHCI_TD_R: u32 = 1 << 18;            // Buffer rounding: short packets are fine
+const OHCI_TD_DP_SHIFT: u32 = 19;
+const OHCI_TD_DI_SHIFT: u32 = 21;
+const OHCI_TD_DI_NONE: u32 = 7;
+const OHCI_TD_T_SHIFT: u32 = 24;
+const OHCI_TD_T_CARRY: u32 = 0;
+const OHCI_TD_T_DATA0: u32 = 2;
+const OHCI_TD_T_DATA1: u32 = 3;
+const OHCI_TD_CC_SHIFT: u32 = 28;
 
-/// OHCI Endpoint Direction codes
-const OHCI_ED_DIR_OUT: u32 = 0x00000000;
-const OHCI_ED_DIR_IN: u32 = 0x00000800;
-const OHCI_ED_DIR_SETUP: u32 = 0x00001000;
+/// OHCI TD PID codes (DP field)
+const OHCI_TD_PID_SETUP: u32 = 0;
+const OHCI_TD_PID_OUT: u32 = 1;
+const OHCI_TD_PID_IN: u32 = 2;
 
-/// OHCI Endpoint Speed codes
-const OHCI_ED_SPEED_FULL: u32 = 0x00000000;
-const OHCI_ED_SPEED_LOW: u32 = 0x00002000;
+/// OHCI Completion Codes
+const OHCI_CC_NO_ERROR: u32 = 0x0;
+const OHCI_CC_CRC: u32 = 0x1;
+const OHCI_CC_BIT_STUFFING: u32 = 0x2;
+const OHCI_CC_DATA_TOGGLE_MISMATCH: u32 = 0x3;
+const OHCI_CC_STALL: u32 = 0x4;
+const OHCI_CC_DEVICE_NOT_RESPONDING: u32 = 0x5;
+const OHCI_CC_PID_CHECK_FAILURE: u32 = 0x6;
+const OHCI_CC_PID_UNEXPECTED: u32 = 0x7;
+const OHCI_CC_DATA_OVERRUN: u32 = 0x8;
+const OHCI_CC_DATA_UNDERRUN: u32 = 0x9;
+const OHCI_CC_BUFFER_OVERRUN: u32 = 0xC;
+const OHCI_CC_BUFFER_UNDERRUN: u32 = 0xD;
+const OHCI_CC_NOT_ACCESSED: u32 = 0xF;
+
+/// Bytes one TD moves: two pages of a page-aligned buffer
+const OHCI_TD_MAX_LENGTH: usize = 8 * 1024;
+/// Root port reset completion wait in frames
+const OHCI_PORT_RESET_FRAMES: u32 = 20;
+/// Frames to wait for a port handed over by the EHCI to show its device
+const OHCI_CONNECT_FRAMES: u32 = 100;
+/// SET_ADDRESS recovery in frames
+const OHCI_SET_ADDRESS_FRAMES: u32 = 2;
+/// Polling iterations before a register wait or transfer gives up
+const OHCI_TIMEOUT_SPINS: u32 = 1_000_000;
+
+/// Platform MMIO base of the first OHCI controller
+pub const OHCI_DEFAULT_BASE_ADDRESS: u64 = 0xFEC10000;
 
 /// OHCI Host Controller capability parameters
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Default)]
 pub struct OhciCapabilityParams {
     pub hc_revision: u8,
-    pub hc_control_hc_bulk_ep_ratios: u8,
-    pub hc_hardware_support: u8,
-    pub hc_reset_recovery_time: u8,
+    /// HcRhDescriptorA
+    pub rh_descriptor_a: u32,
+    /// HcRhDescriptorB
+    pub rh_descriptor_b: u32,
 }
 
 /// OHCI Controller State
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum OhciControllerState {
     Uninitialized,
     Initialized,
@@ -189,532 +245,1332 @@ pub struct OhciPort {
     pub low_speed_device: bool,
 }
 
-/// OHCI Transfer Pool
+/// Handle of a submitted transfer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct OhciTransferHandle(pub u64);
+
+/// ED of one endpoint
 #[derive(Debug)]
-pub struct OhciTransferPool {
-    pub ed_base: *mut u8,
-    pub td_base: *mut u8,
-    pub eds: Vec<*mut OhciED>,
-    pub tds: Vec<*mut OhciTD>,
-    pub ed_count: usize,
-    pub td_count: usize,
+struct OhciEndpoint {
+    ed: XhciDmaBuffer,
+    /// Dummy TD at the end of the queue
+    tail: XhciDmaBuffer,
+    transfer_type: UsbTransferType,
+}
+
+/// Device addressed on the controller
+#[derive(Debug)]
+pub struct OhciDevice {
+    pub address: u8,
+    pub port: u8,
+    pub speed: UsbSpeed,
+    pub max_packet_size0: u16,
+    /// EDs by endpoint address; EP0 is 0 in both directions
+    endpoints: BTreeMap<u8, OhciEndpoint>,
+}
+
+/// One TD of a transfer
+#[derive(Debug)]
+struct OhciTd {
+    td: XhciDmaBuffer,
+    offset: usize,
+    length: usize,
+    /// Part of the data stage rather than setup or status
+    data: bool,
+}
+
+/// Transfer queued on an ED
+#[derive(Debug)]
+struct OhciInflight {
+    address: u8,
+    endpoint: u8,
+    control: bool,
+    direction_in: bool,
+    setup: Option<XhciDmaBuffer>,
+    buffer: Option<XhciDmaBuffer>,
+    tds: Vec<OhciTd>,
+    /// TDs the controller has not retired yet
+    pending: usize,
+    /// TD after the transfer's last one: the next transfer or the dummy
+    next_td: u32,
+    status: UsbTransferStatus,
+    actual: usize,
+}
+
+/// Address the controller can use; OHCI is 32-bit only
+fn dma32(address: u64) -> UsbResult<u32> {
+    u32::try_from(address).map_err(|_| UsbDriverError::UnsupportedFeature)
+}
+
+/// Control word of a fresh TD, not yet accessed
+pub fn ohci_td_control(pid: u32, toggle: u32, rounding: bool, interrupt: bool) -> u32 {
+    let delay = if interrupt { 0 } else { OHCI_TD_DI_NONE };
+    let mut control = pid << OHCI_TD_DP_SHIFT
+        | delay << OHCI_TD_DI_SHIFT
+        | toggle << OHCI_TD_T_SHIFT
+        | OHCI_CC_NOT_ACCESSED << OHCI_TD_CC_SHIFT;
+    if rounding {
+        control |= OHCI_TD_R;
+    }
+    control
+}
+
+/// Control word of an ED
+pub fn ohci_ed_control(address: u8, endpoint: u8, speed: UsbSpeed, max_packet: u16, transfer_type: UsbTransferType) -> u32 {
+    let direction = match transfer_type {
+        UsbTransferType::Control => OHCI_ED_DIR_TD,
+        _ if endpoint & 0x80 != 0 => OHCI_ED_DIR_IN,
+        _ => OHCI_ED_DIR_OUT,
+    };
+    let mut control = (address as u32 & 0x7F)
+        | ((endpoint & 0x0F) as u32) << OHCI_ED_EN_SHIFT
+        | direction << OHCI_ED_D_SHIFT
+        | ((max_packet & 0x7FF) as u32) << OHCI_ED_MPS_SHIFT;
+    if speed == UsbSpeed::Low {
+        control |= OHCI_ED_S;
+    }
+    control
+}
+
+/// Map a completion code to the transfer status it reports
+pub fn ohci_transfer_status(code: u32) -> UsbTransferStatus {
+    match code {
+        OHCI_CC_NO_ERROR => UsbTransferStatus::Success,
+        OHCI_CC_DATA_UNDERRUN => UsbTransferStatus::ShortPacket,
+        OHCI_CC_STALL => UsbTransferStatus::Stalled,
+        OHCI_CC_DATA_OVERRUN => UsbTransferStatus::BabbleDetected,
+        OHCI_CC_BUFFER_OVERRUN => UsbTransferStatus::BufferOverrun,
+        OHCI_CC_BUFFER_UNDERRUN => UsbTransferStatus::BufferUnderrun,
+        OHCI_CC_CRC | OHCI_CC_BIT_STUFFING | OHCI_CC_DATA_TOGGLE_MISMATCH | OHCI_CC_DEVICE_NOT_RESPONDING
+        | OHCI_CC_PID_CHECK_FAILURE | OHCI_CC_PID_UNEXPECTED => UsbTransferStatus::NotAccessed,
+        _ => UsbTransferStatus::Aborted,
+    }
 }
 
 /// OHCI Controller Implementation
+#[derive(Debug)]
 pub struct OhciController {
     pub base_address: u64,
     pub capability_params: OhciCapabilityParams,
     pub state: OhciControllerState,
-    pub hcca: *mut u8,
-    pub control_head: *mut OhciED,
-    pub control_current: *mut OhciED,
-    pub bulk_head: *mut OhciED,
-    pub bulk_current: *mut OhciED,
-    pub done_head: *mut u32,
-    pub transfer_pool: Option<OhciTransferPool>,
     pub ports: Vec<OhciPort>,
     pub max_ports: u8,
     pub frame_interval: u32,
-    pub interrupt_threshold: u32,
     pub periodic_schedule_enabled: bool,
     pub control_schedule_enabled: bool,
     pub bulk_schedule_enabled: bool,
+    hcca: Option<XhciDmaBuffer>,
+    /// EDs of the control and bulk lists, in list order
+    control_order: Vec<(u8, u8)>,
+    bulk_order: Vec<(u8, u8)>,
+    periodic: PeriodicSchedule<(u8, u8)>,
+    /// Periodic time of the full-speed bus
+    bandwidth: BandwidthScheduler,
+    devices: BTreeMap<u8, OhciDevice>,
+    next_handle: u64,
+    inflight: BTreeMap<OhciTransferHandle, OhciInflight>,
+    /// Transfer and TD index of each queued TD, by TD address
+    td_owners: BTreeMap<u32, (OhciTransferHandle, usize)>,
+    completed: BTreeMap<OhciTransferHandle, XhciTransferResult>,
+    events: Vec<UsbEvent>,
+    stats: UsbControllerStats,
 }
 
 impl OhciController {
     /// Create a new OHCI controller instance
+    ///
+    /// No register is touched until `initialize()`.
     pub fn new(base_address: u64) -> Self {
-        let mut controller = Self {
+        Self {
             base_address,
-            capability_params: OhciCapabilityParams {
-                hc_revision: 0,
-                hc_control_hc_bulk_ep_ratios: 0,
-                hc_hardware_support: 0,
-                hc_reset_recovery_time: 0,
-            },
+            capability_params: OhciCapabilityParams::default(),
             state: OhciControllerState::Uninitialized,
-            hcca: core::ptr::null_mut(),
-            control_head: core::ptr::null_mut(),
-            control_current: core::ptr::null_mut(),
-            bulk_head: core::ptr::null_mut(),
-            bulk_current: core::ptr::null_mut(),
-            done_head: core::ptr::null_mut(),
-            transfer_pool: None,
             ports: Vec::new(),
             max_ports: 0,
-            frame_interval: 0,
-            interrupt_threshold: 0,
+            frame_interval: OHCI_FR_INTERVAL,
             periodic_schedule_enabled: false,
             control_schedule_enabled: false,
             bulk_schedule_enabled: false,
-        };
+            hcca: None,
+            control_order: Vec::new(),
+            bulk_order: Vec::new(),
+            periodic: PeriodicSchedule::new(),
+            bandwidth: BandwidthScheduler::new(UsbSpeed::Full),
+            devices: BTreeMap::new(),
+            next_handle: 1,
+            inflight: BTreeMap::new(),
+            td_owners: BTreeMap::new(),
+            completed: BTreeMap::new(),
+            events: Vec::new(),
+            stats: UsbControllerStats {
+                total_transactions: 0,
+                successful_transactions: 0,
+                failed_transactions: 0,
+                bytes_transferred: 0,
+                error_count: 0,
+                last_error: None,
+            },
+        }
+    }
 
-        // Read capability parameters
-        controller.read_capability_params();
+    fn read32(&self, offset: usize) -> u32 {
+        unsafe { core::ptr::read_volatile((self.base_address as usize + offset) as *const u32) }
+    }
 
-        controller
+    fn write32(&self, offset: usize, value: u32) {
+        unsafe { core::ptr::write_volatile((self.base_address as usize + offset) as *mut u32, value) }
     }
 
-    /// Read OHCI capability parameters from registers
-    pub fn read_capability_params(&mut self) {
-        unsafe {
-            let cap_base = self.base_address as *const u8;
-            
-            self.capability_params.hc_revision = *cap_base;
-            self.capability_params.hc_control_hc_bulk_ep_ratios = *(cap_base.add(1));
-            self.capability_params.hc_hardware_support = *(cap_base.add(2));
-            self.capability_params.hc_reset_recovery_time = *(cap_base.add(3));
+    fn port_offset(&self, port_number: u8) -> usize {
+        OHCI_HCRHPORTSTATUS_BASE + (port_number as usize - 1) * mem::size_of::<u32>()
+    }
 
-            // Read max ports from frame length register
-            let fminterval_reg = (self.base_address + OHCI_HCFMINTERVAL as u64) as *const u32;
-            self.frame_interval = *fminterval_reg & 0x3FFF;
+    /// Frames since HcFmNumber read `since`
+    fn frames_since(&self, since: u32) -> u32 {
+        self.read32(OHCI_HCFMNUMBER).wrapping_sub(since) & OHCI_FR_NUMBER_MASK
+    }
 
-            // Determine max ports (typically 2 for UHCI compatible, 15 for others)
-            self.max_ports = 2; // Conservative default for legacy systems
+    /// Wait `frames` on the operational controller's frame counter
+    fn wait_frames(&self, frames: u32) -> UsbResult<()> {
+        if self.state != OhciControllerState::Operational {
+            return Err(UsbDriverError::ControllerNotInitialized);
+        }
+        let start = self.read32(OHCI_HCFMNUMBER);
+        for _ in 0..OHCI_TIMEOUT_SPINS {
+            if self.frames_since(start) >= frames {
+                return Ok(());
+            }
+            core::hint::spin_loop();
         }
+        Err(UsbDriverError::Timeout)
+    }
+
+    /// Read OHCI capability parameters from registers
+    pub fn read_capability_params(&mut self) {
+        self.capability_params = OhciCapabilityParams {
+            hc_revision: self.read32(OHCI_HCREVISION) as u8,
+            rh_descriptor_a: self.read32(OHCI_HCRHDESCRIPTORA),
+            rh_descriptor_b: self.read32(OHCI_HCRHDESCRIPTORB),
+        };
+        self.max_ports = (self.capability_params.rh_descriptor_a & OHCI_RH_NDP_MASK).min(15) as u8;
 
         log::info!("OHCI Controller initialized:");
-        log::info!("  HC Revision: {}", self.capability_params.hc_revision);
+        log::info!("  Revision: {:#04x}", self.capability_params.hc_revision);
         log::info!("  Max Ports: {}", self.max_ports);
-        log::info!("  Frame Interval: {}", self.frame_interval);
     }
 
     /// Initialize OHCI controller
+    ///
+    /// Takes the controller over from firmware, resets it, installs the
+    /// HCCA and makes it operational with all three lists enabled.
     pub fn initialize(&mut self) -> UsbResult<()> {
-        if self.state == OhciControllerState::Initialized {
+        if self.state == OhciControllerState::Operational {
             return Ok(());
         }
 
-        // Reset the controller
-        self.reset()?;
+        self.read_capability_params();
+        if self.capability_params.hc_revision & 0xF0 != 0x10 || self.max_ports == 0 {
+            return Err(UsbDriverError::ControllerNotInitialized);
+        }
 
-        // Initialize memory structures
+        self.take_ownership()?;
+        self.reset()?;
         self.initialize_memory()?;
-
-        // Configure the controller
         self.configure_controller()?;
-
-        // Discover ports
+        self.power_ports()?;
         self.discover_ports()?;
 
-        self.state = OhciControllerState::Initialized;
         log::info!("OHCI controller initialized successfully");
+        Ok(())
+    }
 
+    /// Claim the controller from SMM firmware emulating legacy devices
+    fn take_ownership(&mut self) -> UsbResult<()> {
+        let control = self.read32(OHCI_HCCONTROL);
+        if control & OHCI_CTRL_IR != 0 {
+            self.write32(OHCI_HCCOMMANDSTATUS, OHCI_CMD_OCR);
+            for _ in 0..OHCI_TIMEOUT_SPINS {
+                if self.read32(OHCI_HCCONTROL) & OHCI_CTRL_IR == 0 {
+                    log::info!("OHCI: took controller over from SMM");
+                    return Ok(());
+                }
+                core::hint::spin_loop();
+            }
+            return Err(UsbDriverError::Timeout);
+        }
+        if control & OHCI_CTRL_HCFS_MASK != OHCI_HCFS_USBRESET {
+            log::info!("OHCI: taking controller over from firmware");
+        }
         Ok(())
     }
 
     /// Reset the OHCI controller
+    ///
+    /// The controller comes out of a software reset suspended; it has
+    /// 2 ms to be made operational.
     pub fn reset(&mut self) -> UsbResult<()> {
-        unsafe {
-            let cmd_reg = (self.base_address + OHCI_HCCOMMANDSTATUS as u64) as *mut u32;
-            let control_reg = (self.base_address + OHCI_HCCONTROL as u64) as *mut u32;
-
-            // Reset the controller
-            *cmd_reg = OHCI_CMD_HCR;
-            
-            // Wait for reset to complete
-            for _ in 0..10000 {
-                let cmd_status = *cmd_reg;
-                if cmd_status & OHCI_CMD_HCR == 0 {
-                    break;
-                }
-            }
+        self.write32(OHCI_HCINTERRUPTDISABLE, OHCI_INT_ALL | OHCI_INT_MIE);
 
-            // Set controller to reset state
-            let mut control = *control_reg;
-            control &= !OHCI_CTRL_HCFS_MASK;
-            control |= OHCI_HCFS_USBRESET;
-            *control_reg = control;
+        let interval = self.read32(OHCI_HCFMINTERVAL) & OHCI_FR_INTERVAL_MASK;
+        if interval != 0 {
+            self.frame_interval = interval;
+        }
 
-            self.state = OhciControllerState::Reset;
+        self.write32(OHCI_HCCOMMANDSTATUS, OHCI_CMD_HCR);
+        let mut reset_done = false;
+        for _ in 0..OHCI_TIMEOUT_SPINS {
+            if self.read32(OHCI_HCCOMMANDSTATUS) & OHCI_CMD_HCR == 0 {
+                reset_done = true;
+                break;
+            }
+            core::hint::spin_loop();
+        }
+        if !reset_done {
+            self.state = OhciControllerState::Error;
+            return Err(UsbDriverError::Timeout);
         }
 
+        // Everything the controller knew about is gone
+        self.state = OhciControllerState::Reset;
+        self.abandon_transfers();
+        self.devices.clear();
+        self.control_order.clear();
+        self.bulk_order.clear();
+        self.periodic = PeriodicSchedule::new();
+        self.bandwidth = BandwidthScheduler::new(UsbSpeed::Full);
+        self.hcca = None;
+        self.periodic_schedule_enabled = false;
+        self.control_schedule_enabled = false;
+        self.bulk_schedule_enabled = false;
+
         log::info!("OHCI controller reset completed");
         Ok(())
     }
 
-    /// Initialize OHCI memory structures (HCCA, ED/TD pools)
+    /// Install an empty HCCA and empty control and bulk lists
     pub fn initialize_memory(&mut self) -> UsbResult<()> {
-        unsafe {
-            // Allocate HCCA (Host Controller Communications Area)
-            let hcca_size = 256; // 256 bytes as per OHCI spec
-            let hcca_base = alloc::alloc::alloc(alloc::alloc::Layout::from_size_align(hcca_size, 256)?);
-            if hcca_base.is_null() {
-                return Err(UsbDriverError::UnsupportedFeature);
-            }
-            self.hcca = hcca_base;
-
-            // Zero out HCCA
-            core::ptr::write_bytes(self.hcca, 0, hcca_size);
+        let hcca = XhciDmaBuffer::new(OHCI_HCCA_SIZE, OHCI_HCCA_SIZE)?;
+        self.write32(OHCI_HCHCCA, dma32(hcca.address())?);
+        self.write32(OHCI_HCCTRLHEADED, 0);
+        self.write32(OHCI_HCCTRLCURRENTED, 0);
+        self.write32(OHCI_HCBULKHEADED, 0);
+        self.write32(OHCI_HCBULKCURRENTED, 0);
+        self.hcca = Some(hcca);
+        self.state = OhciControllerState::Initialized;
+        Ok(())
+    }
 
-            // Initialize ED and TD pools
-            let num_eds = 32;
-            let num_tds = 128;
-            let ed_size = num_eds * mem::size_of::<OhciED>();
-            let td_size = num_tds * mem::size_of::<OhciTD>();
+    /// Program frame timing and interrupts and make the controller
+    /// operational
+    pub fn configure_controller(&mut self) -> UsbResult<()> {
+        if self.hcca.is_none() {
+            return Err(UsbDriverError::ControllerNotInitialized);
+        }
 
-            let ed_base = alloc::alloc::alloc(alloc::alloc::Layout::from_size_align(ed_size, 32)?);
-            let td_base = alloc::alloc::alloc(alloc::alloc::Layout::from_size_align(td_size, 32)?);
+        // Largest full-speed data packet that still fits a frame
+        let fs_max_packet = (self.frame_interval - 210) * 6 / 7;
+        let toggle = !self.read32(OHCI_HCFMINTERVAL) & OHCI_FR_FIT;
+        self.write32(OHCI_HCFMINTERVAL, self.frame_interval | fs_max_packet << OHCI_FR_FSMPS_SHIFT | toggle);
+        self.write32(OHCI_HCPERIODICSTART, self.frame_interval * 9 / 10);
+        self.write32(OHCI_HCLSTHRESHOLD, OHCI_LS_THRESHOLD);
+
+        self.write32(OHCI_HCINTERRUPTSTATUS, OHCI_INT_ALL);
+        self.write32(OHCI_HCINTERRUPTENABLE, OHCI_INT_ENABLED | OHCI_INT_MIE);
+
+        let control = self.read32(OHCI_HCCONTROL) & OHCI_CTRL_RWC;
+        self.write32(OHCI_HCCONTROL, control
+            | OHCI_CTRL_CBSR_MASK
+            | OHCI_CTRL_PLE
+            | OHCI_CTRL_CLE
+            | OHCI_CTRL_BLE
+            | OHCI_HCFS_USBOPERATIONAL);
+        self.state = OhciControllerState::Operational;
+        self.periodic_schedule_enabled = true;
+        self.control_schedule_enabled = true;
+        self.bulk_schedule_enabled = true;
+        Ok(())
+    }
 
-            if ed_base.is_null() || td_base.is_null() {
-                return Err(UsbDriverError::UnsupportedFeature);
+    /// Power the root hub ports and wait for power to be good
+    fn power_ports(&mut self) -> UsbResult<()> {
+        let descriptor_a = self.capability_params.rh_descriptor_a;
+        if descriptor_a & OHCI_RH_NPS != 0 {
+            return Ok(());
+        }
+        if descriptor_a & OHCI_RH_PSM != 0 {
+            for port_number in 1..=self.max_ports {
+                self.write32(self.port_offset(port_number), OHCI_PORT_SET_POWER);
             }
+        } else {
+            self.write32(OHCI_HCRHSTATUS, OHCI_RH_LPSC);
+        }
+        let power_good = (descriptor_a >> OHCI_RH_POTPGT_SHIFT) * 2;
+        self.wait_frames(power_good.max(20))
+    }
 
-            let mut eds = Vec::new();
-            for i in 0..num_eds {
-                let ed_ptr = ed_base.add(i * mem::size_of::<OhciED>()) as *mut OhciED;
-                eds.push(ed_ptr);
-            }
+    /// Discover and initialize ports
+    pub fn discover_ports(&mut self) -> UsbResult<()> {
+        self.ports.clear();
+        for port_number in 1..=self.max_ports {
+            self.ports.push(OhciPort {
+                port_number,
+                speed: UsbSpeed::Full,
+                status: 0,
+                change_status: 0,
+                connection_status: false,
+                device_attached: false,
+                power_state: UsbPowerState::Off,
+                port_enabled: false,
+                low_speed_device: false,
+            });
+            self.get_port_status(port_number)?;
+        }
 
-            let mut tds = Vec::new();
-            for i in 0..num_tds {
-                let td_ptr = td_base.add(i * mem::size_of::<OhciTD>()) as *mut OhciTD;
-                tds.push(td_ptr);
-            }
+        log::info!("Discovered {} OHCI ports", self.ports.len());
+        Ok(())
+    }
 
-            let pool = OhciTransferPool {
-                ed_base,
-                td_base,
-                eds,
-                tds,
-                ed_count: num_eds,
-                td_count: num_tds,
+    /// Get port status (HcRhPortStatus)
+    pub fn get_port_status(&mut self, port_number: u8) -> UsbResult<u32> {
+        if port_number == 0 || port_number > self.max_ports {
+            return Err(UsbDriverError::DeviceNotFound { address: port_number });
+        }
+
+        let status = self.read32(self.port_offset(port_number));
+        if let Some(port) = self.ports.get_mut(port_number as usize - 1) {
+            port.status = status & !OHCI_PORT_CHANGE_MASK;
+            port.change_status = status & OHCI_PORT_CHANGE_MASK;
+            port.connection_status = status & OHCI_PORT_CCS != 0;
+            port.device_attached = status & OHCI_PORT_CCS != 0;
+            port.port_enabled = status & OHCI_PORT_PES != 0;
+            port.low_speed_device = status & OHCI_PORT_LSDA != 0;
+            port.speed = if port.low_speed_device { UsbSpeed::Low } else { UsbSpeed::Full };
+            port.power_state = if status & OHCI_PORT_PSS != 0 {
+                UsbPowerState::Suspended
+            } else if status & OHCI_PORT_PPS != 0 {
+                UsbPowerState::Active
+            } else {
+                UsbPowerState::Off
             };
+        }
+        Ok(status)
+    }
 
-            self.transfer_pool = Some(pool);
+    /// Write a port command; only the bits set in `command` act
+    pub fn set_port_feature(&mut self, port_number: u8, command: u32) -> UsbResult<()> {
+        if port_number == 0 || port_number > self.max_ports {
+            return Err(UsbDriverError::DeviceNotFound { address: port_number });
+        }
+        self.write32(self.port_offset(port_number), command);
+        Ok(())
+    }
 
-            // Initialize done head
-            self.done_head = core::ptr::null_mut();
+    /// Clear port change bits
+    pub fn clear_port_feature(&mut self, port_number: u8, changes: u32) -> UsbResult<()> {
+        self.set_port_feature(port_number, changes & OHCI_PORT_CHANGE_MASK)
+    }
 
-            // Write HCCA base address to controller
-            let hcca_reg = (self.base_address + OHCI_HCHCCA as u64) as *mut u32;
-            *hcca_reg = self.hcca as u32;
+    /// Reset a port and return the speed of the enabled device
+    pub fn reset_port(&mut self, port_number: u8) -> UsbResult<UsbSpeed> {
+        let status = self.get_port_status(port_number)?;
+        if status & OHCI_PORT_CCS == 0 {
+            return Err(UsbDriverError::DeviceNotFound { address: port_number });
+        }
+        self.set_port_feature(port_number, OHCI_PORT_SET_RESET)?;
+
+        // The root hub times the reset itself
+        let start = self.read32(OHCI_HCFMNUMBER);
+        let mut status = self.get_port_status(port_number)?;
+        for _ in 0..OHCI_TIMEOUT_SPINS {
+            if status & OHCI_PORT_PRSC != 0 || self.frames_since(start) > OHCI_PORT_RESET_FRAMES {
+                break;
+            }
+            core::hint::spin_loop();
+            status = self.get_port_status(port_number)?;
+        }
+        if status & OHCI_PORT_PRSC == 0 {
+            return Err(UsbDriverError::Timeout);
+        }
+        self.clear_port_feature(port_number, OHCI_PORT_PRSC)?;
+        if status & OHCI_PORT_PES == 0 {
+            return Err(UsbDriverError::DeviceNotFound { address: port_number });
         }
+        Ok(if status & OHCI_PORT_LSDA != 0 { UsbSpeed::Low } else { UsbSpeed::Full })
+    }
 
-        log::info!("OHCI memory structures initialized:");
-        log::info!("  HCCA: {:#x}", self.hcca as u64);
-        if let Some(pool) = &self.transfer_pool {
-            log::info!("  EDs: {}", pool.ed_count);
-            log::info!("  TDs: {}", pool.td_count);
+    /// Wait for a device to show up on a port, e.g. one the EHCI just
+    /// released
+    pub fn wait_for_connect(&mut self, port_number: u8) -> bool {
+        if self.state != OhciControllerState::Operational {
+            return false;
+        }
+        let start = self.read32(OHCI_HCFMNUMBER);
+        for _ in 0..OHCI_TIMEOUT_SPINS {
+            match self.get_port_status(port_number) {
+                Ok(status) if status & OHCI_PORT_CCS != 0 => return true,
+                Ok(_) => {}
+                Err(_) => return false,
+            }
+            if self.frames_since(start) > OHCI_CONNECT_FRAMES {
+                return false;
+            }
+            core::hint::spin_loop();
         }
+        false
+    }
+
+    /// Enable the periodic (interrupt) list
+    pub fn enable_periodic_schedule(&mut self) -> UsbResult<()> {
+        self.set_list(OHCI_CTRL_PLE, true)?;
+        self.periodic_schedule_enabled = true;
         Ok(())
     }
 
-    /// Configure OHCI controller operational parameters
-    pub fn configure_controller(&mut self) -> UsbResult<()> {
-        unsafe {
-            let control_reg = (self.base_address + OHCI_HCCONTROL as u64) as *mut u32;
-            let mut control = *control_reg;
-
-            // Set frame interval
-            let fminterval_reg = (self.base_address + OHCI_HCFMINTERVAL as u64) as *mut u32;
-            *fminterval_reg = OHCI_FR_INTERVAL | (31 << 16); // 12MHz clock, 31 frame adjust
-
-            // Configure control register
-            control &= !OHCI_CTRL_HCFS_MASK;
-            control |= OHCI_HCFS_USBOPERATIONAL; // Set to operational state
-            control |= OHCI_CTRL_IE; // Enable interrupts
-            control |= OHCI_CTRL_RWE; // Enable remote wakeup
-
-            *control_reg = control;
-
-            // Initialize head pointers for control and bulk lists
-            if let Some(pool) = &self.transfer_pool {
-                if !pool.eds.is_empty() {
-                    self.control_head = pool.eds[0];
-                    self.bulk_head = pool.eds[1 % pool.ed_count];
-                }
-            }
+    /// Enable the control list
+    pub fn enable_control_schedule(&mut self) -> UsbResult<()> {
+        self.set_list(OHCI_CTRL_CLE, true)?;
+        self.control_schedule_enabled = true;
+        Ok(())
+    }
 
-            // Clear interrupt status
-            let intr_stat_reg = (self.base_address + OHCI_HCINTERRUPTSTATUS as u64) as *mut u32;
-            *intr_stat_reg = 0xFFFFFFFF;
+    /// Enable the bulk list
+    pub fn enable_bulk_schedule(&mut self) -> UsbResult<()> {
+        self.set_list(OHCI_CTRL_BLE, true)?;
+        self.bulk_schedule_enabled = true;
+        Ok(())
+    }
+
+    fn set_list(&mut self, enable: u32, on: bool) -> UsbResult<()> {
+        if self.state != OhciControllerState::Operational {
+            return Err(UsbDriverError::ControllerNotInitialized);
         }
+        let control = self.read32(OHCI_HCCONTROL);
+        self.write32(OHCI_HCCONTROL, if on { control | enable } else { control & !enable });
+        Ok(())
+    }
 
+    /// Suspend the bus
+    pub fn suspend(&mut self) -> UsbResult<()> {
+        self.set_functional_state(OHCI_HCFS_USBSUSPEND)?;
+        self.state = OhciControllerState::Suspended;
+        Ok(())
+    }
+
+    /// Resume a suspended bus
+    pub fn resume(&mut self) -> UsbResult<()> {
+        if self.state != OhciControllerState::Suspended {
+            return Ok(());
+        }
+        self.set_functional_state(OHCI_HCFS_USBRESUME)?;
+        // Resume signalling must last 20 ms; frames do not run yet
+        for _ in 0..OHCI_TIMEOUT_SPINS {
+            core::hint::spin_loop();
+        }
+        self.set_functional_state(OHCI_HCFS_USBOPERATIONAL)?;
         self.state = OhciControllerState::Operational;
-        log::info!("OHCI controller configured for operational state");
         Ok(())
     }
 
-    /// Discover and initialize ports
-    pub fn discover_ports(&mut self) -> UsbResult<()> {
-        self.ports.clear();
+    fn set_functional_state(&mut self, state: u32) -> UsbResult<()> {
+        if self.hcca.is_none() {
+            return Err(UsbDriverError::ControllerNotInitialized);
+        }
+        let control = self.read32(OHCI_HCCONTROL) & !OHCI_CTRL_HCFS_MASK;
+        self.write32(OHCI_HCCONTROL, control | state);
+        Ok(())
+    }
 
-        // Start with 2 ports (UHCI compatible), will be discovered dynamically
-        for port_num in 1..=self.max_ports {
-            let port = OhciPort {
-                port_number: port_num,
-                speed: UsbSpeed::Full,
-                status: 0,
-                change_status: 0,
-                connection_status: false,
-                device_attached: false,
-                power_state: UsbPowerState::Active,
-                port_enabled: false,
-                low_speed_device: false,
-            };
+    /// Get current frame number
+    pub fn get_frame_number(&self) -> u32 {
+        match &self.hcca {
+            Some(hcca) => hcca.read_u32(OHCI_HCCA_FRAME_NUMBER) & OHCI_FR_NUMBER_MASK,
+            None => self.read32(OHCI_HCFMNUMBER) & OHCI_FR_NUMBER_MASK,
+        }
+    }
+
+    fn endpoint(&self, key: (u8, u8)) -> UsbResult<&OhciEndpoint> {
+        self.devices
+            .get(&key.0)
+            .and_then(|device| device.endpoints.get(&key.1))
+            .ok_or(UsbDriverError::DeviceNotFound { address: key.0 })
+    }
+
+    fn endpoint_mut(&mut self, key: (u8, u8)) -> UsbResult<&mut OhciEndpoint> {
+        self.devices
+            .get_mut(&key.0)
+            .and_then(|device| device.endpoints.get_mut(&key.1))
+            .ok_or(UsbDriverError::DeviceNotFound { address: key.0 })
+    }
 
-            self.ports.push(port);
+    fn ed_address(&self, key: (u8, u8)) -> UsbResult<u32> {
+        dma32(self.endpoint(key)?.ed.address())
+    }
+
+    fn list_registers(transfer_type: UsbTransferType) -> (usize, usize, u32) {
+        match transfer_type {
+            UsbTransferType::Control => (OHCI_HCCTRLHEADED, OHCI_HCCTRLCURRENTED, OHCI_CMD_CLF),
+            _ => (OHCI_HCBULKHEADED, OHCI_HCBULKCURRENTED, OHCI_CMD_BLF),
         }
+    }
 
-        log::info!("Discovered {} OHCI ports", self.ports.len());
+    /// Put an ED at the front of the control or bulk list
+    fn link_async(&mut self, key: (u8, u8), transfer_type: UsbTransferType) -> UsbResult<()> {
+        let (head_register, _, _) = Self::list_registers(transfer_type);
+        let address = self.ed_address(key)?;
+        let head = self.read32(head_register);
+        self.endpoint_mut(key)?.ed.write_u32(OHCI_ED_NEXT, head);
+        fence(Ordering::SeqCst);
+        self.write32(head_register, address);
+        match transfer_type {
+            UsbTransferType::Control => self.control_order.insert(0, key),
+            _ => self.bulk_order.insert(0, key),
+        }
         Ok(())
     }
 
-    /// Get port status
-    pub fn get_port_status(&mut self, port_number: u8) -> UsbResult<u32> {
-        if port_number == 0 || port_number > self.max_ports {
-            return Err(UsbDriverError::DeviceNotFound { address: port_number });
+    /// Take an ED off its list; the controller may still be reading it
+    /// until the next frame starts
+    fn unlink_async(&mut self, key: (u8, u8), transfer_type: UsbTransferType) -> UsbResult<()> {
+        let (head_register, current_register, _) = Self::list_registers(transfer_type);
+        let order = match transfer_type {
+            UsbTransferType::Control => &self.control_order,
+            _ => &self.bulk_order,
+        };
+        let position = match order.iter().position(|&other| other == key) {
+            Some(position) => position,
+            None => return Ok(()),
+        };
+        let previous = position.checked_sub(1).map(|position| order[position]);
+
+        let address = self.ed_address(key)?;
+        let next = self.endpoint(key)?.ed.read_u32(OHCI_ED_NEXT);
+        match previous {
+            Some(previous) => self.endpoint_mut(previous)?.ed.write_u32(OHCI_ED_NEXT, next),
+            None => self.write32(head_register, next),
+        }
+        if self.read32(current_register) & OHCI_ED_POINTER_MASK == address {
+            self.write32(current_register, next);
         }
+        match transfer_type {
+            UsbTransferType::Control => self.control_order.remove(position),
+            _ => self.bulk_order.remove(position),
+        };
+        Ok(())
+    }
 
-        unsafe {
-            let port_offset = match port_number {
-                1 => OHCI_HCPORTSTATUS1,
-                2 => OHCI_HCPORTSTATUS2,
-                _ => OHCI_HCPORTSTATUS1 + ((port_number - 1) * 0x04),
+    /// Rewrite the interrupt tree: ED links first, so every HCCA entry
+    /// written afterwards leads through up-to-date links
+    fn rebuild_periodic(&mut self) -> UsbResult<()> {
+        let keys: Vec<(u8, u8)> = self.periodic.entries().iter().map(|entry| entry.key).collect();
+        for key in keys {
+            let link = match self.periodic.successor(key) {
+                Some(next) => self.ed_address(next)?,
+                None => 0,
             };
+            self.endpoint_mut(key)?.ed.write_u32(OHCI_ED_NEXT, link);
+        }
+        fence(Ordering::SeqCst);
 
-            let portsc_reg = (self.base_address + port_offset as u64) as *const u32;
-            let status = *portsc_reg;
-
-            // Update port information
-            if port_number as usize <= self.ports.len() {
-                let port = &mut self.ports[port_number as usize - 1];
-                port.status = status;
-                port.connection_status = (status & OHCI_PORT_CCS) != 0;
-                port.port_enabled = (status & OHCI_PORT_PES) != 0;
-                port.low_speed_device = (status & OHCI_PORT_LSDA) != 0;
-                port.device_attached = port.connection_status;
-                port.speed = if port.low_speed_device { 
-                    UsbSpeed::Low 
-                } else { 
-                    UsbSpeed::Full 
-                };
+        let mut heads = [0u32; PERIODIC_SLOTS];
+        for (frame, head) in heads.iter_mut().enumerate() {
+            if let Some(key) = self.periodic.head(frame) {
+                *head = self.ed_address(key)?;
             }
+        }
+        let hcca = self.hcca.as_mut().ok_or(UsbDriverError::ControllerNotInitialized)?;
+        for (frame, head) in heads.iter().enumerate() {
+            hcca.write_u32(OHCI_HCCA_INTERRUPT_TABLE + frame * mem::size_of::<u32>(), *head);
+        }
+        Ok(())
+    }
 
-            Ok(status)
+    /// Set up an ED for an endpoint and put it on its list; interrupt EDs
+    /// go into the frames their bandwidth reservation says
+    fn add_endpoint(&mut self, address: u8, endpoint: u8, transfer_type: UsbTransferType, max_packet: u16, reservation: Option<BandwidthReservation>) -> UsbResult<()> {
+        let speed = self.devices.get(&address).ok_or(UsbDriverError::DeviceNotFound { address })?.speed;
+
+        let tail = XhciDmaBuffer::new(OHCI_TD_SIZE, OHCI_DESCRIPTOR_ALIGN)?;
+        let tail_address = dma32(tail.address())?;
+        let mut ed = XhciDmaBuffer::new(OHCI_ED_SIZE, OHCI_DESCRIPTOR_ALIGN)?;
+        dma32(ed.address())?;
+        ed.write_u32(OHCI_ED_CONTROL, ohci_ed_control(address, endpoint, speed, max_packet, transfer_type));
+        ed.write_u32(OHCI_ED_TAIL, tail_address);
+        ed.write_u32(OHCI_ED_HEAD, tail_address);
+        ed.write_u32(OHCI_ED_NEXT, 0);
+
+        self.unschedule_endpoint(address, endpoint)?;
+        let device = self.devices.get_mut(&address).ok_or(UsbDriverError::DeviceNotFound { address })?;
+        device.endpoints.insert(endpoint, OhciEndpoint { ed, tail, transfer_type });
+
+        match (transfer_type, reservation) {
+            (UsbTransferType::Interrupt, Some(reservation)) => {
+                self.periodic.insert_at((address, endpoint), reservation.period, reservation.phase, (max_packet & 0x7FF) as u32);
+                self.rebuild_periodic()
+            }
+            (UsbTransferType::Interrupt, None) => Err(UsbDriverError::InvalidConfiguration),
+            _ => self.link_async((address, endpoint), transfer_type),
         }
     }
 
-    /// Set port feature
-    pub fn set_port_feature(&mut self, port_number: u8, feature: u32) -> UsbResult<()> {
-        if port_number == 0 || port_number > self.max_ports {
-            return Err(UsbDriverError::DeviceNotFound { address: port_number });
+    /// Unschedule an endpoint's ED, cancel its transfers, free it and give
+    /// up its bus time
+    fn remove_endpoint(&mut self, address: u8, endpoint: u8) -> UsbResult<()> {
+        self.bandwidth.release(address, endpoint);
+        self.unschedule_endpoint(address, endpoint)
+    }
+
+    fn unschedule_endpoint(&mut self, address: u8, endpoint: u8) -> UsbResult<()> {
+        let key = (address, endpoint);
+        let transfer_type = match self.endpoint(key) {
+            Ok(ep) => ep.transfer_type,
+            Err(_) => return Ok(()),
+        };
+
+        let ep = self.endpoint_mut(key)?;
+        let control = ep.ed.read_u32(OHCI_ED_CONTROL);
+        ep.ed.write_u32(OHCI_ED_CONTROL, control | OHCI_ED_K);
+        let mut result = match transfer_type {
+            UsbTransferType::Interrupt => {
+                self.periodic.remove(key);
+                self.rebuild_periodic()
+            }
+            _ => self.unlink_async(key, transfer_type),
+        };
+        // Let the controller finish any frame that still reaches the ED
+        if self.state == OhciControllerState::Operational {
+            result = result.and(self.wait_frames(2));
+        }
+        self.cancel_transfers(address, Some(endpoint));
+        if let Some(device) = self.devices.get_mut(&address) {
+            device.endpoints.remove(&endpoint);
         }
+        result
+    }
 
-        unsafe {
-            let port_offset = match port_number {
-                1 => OHCI_HCPORTSTATUS1,
-                2 => OHCI_HCPORTSTATUS2,
-                _ => OHCI_HCPORTSTATUS1 + ((port_number - 1) * 0x04),
-            };
+    /// Set up EP0 of a device still at the default address and move it to
+    /// `address`
+    pub fn address_device(&mut self, port_number: u8, speed: UsbSpeed, address: u8) -> UsbResult<u8> {
+        if address == 0 || address > 127 || self.devices.contains_key(&address) {
+            return Err(UsbDriverError::InvalidConfiguration);
+        }
+        if self.devices.contains_key(&0) {
+            // Another device is being enumerated
+            return Err(UsbDriverError::DeviceNotFound { address: 0 });
+        }
 
-            let portsc_reg = (self.base_address + port_offset as u64) as *mut u32;
-            let mut status = *portsc_reg;
+        let max_packet_size0 = default_max_packet_size0(speed);
+        self.devices.insert(0, OhciDevice { address: 0, port: port_number, speed, max_packet_size0, endpoints: BTreeMap::new() });
 
-            match feature {
-                // USB Port Enable
-                0x0004 => {
-                    status |= OHCI_PORT_PES;
-                }
-                // USB Port Suspend
-                0x0005 => {
-                    status |= OHCI_PORT_PSS;
-                }
-                // USB Port Reset
-                0x0004 => {
-                    status |= OHCI_PORT_PRSC;
-                }
-                // USB Port Power
-                0x0008 => {
-                    status |= OHCI_PORT_PPOWER;
-                }
-                _ => {
-                    log::warn!("Unsupported OHCI port feature: {:#x}", feature);
-                    return Err(UsbDriverError::UnsupportedFeature);
+        let result = self.add_endpoint(0, 0, UsbTransferType::Control, max_packet_size0, None)
+            .and_then(|_| self.control_transfer(0, &set_address_request(address), &mut []));
+        if let Err(err) = result {
+            let _ = self.release_device(0);
+            return Err(err);
+        }
+
+        // The ED keeps serving the device at its new address
+        let mut device = self.devices.remove(&0).expect("device at default address");
+        device.address = address;
+        if let Some(ep0) = device.endpoints.get_mut(&0) {
+            let control = ep0.ed.read_u32(OHCI_ED_CONTROL);
+            ep0.ed.write_u32(OHCI_ED_CONTROL, (control & !0x7F) | address as u32);
+        }
+        if let Some(position) = self.control_order.iter().position(|&key| key == (0, 0)) {
+            self.control_order[position] = (address, 0);
+        }
+        self.devices.insert(address, device);
+        self.wait_frames(OHCI_SET_ADDRESS_FRAMES)?;
+
+        log::info!("OHCI port {}: device addressed as {}", port_number, address);
+        Ok(address)
+    }
+
+    /// Lowest address no device uses
+    pub fn next_free_address(&self) -> Option<u8> {
+        (1..=127).find(|address| !self.devices.contains_key(address))
+    }
+
+    /// Update the control endpoint's max packet size after reading the
+    /// device descriptor
+    pub fn set_max_packet_size0(&mut self, address: u8, max_packet_size: u16) -> UsbResult<()> {
+        let device = self.devices.get_mut(&address).ok_or(UsbDriverError::DeviceNotFound { address })?;
+        let ep0 = device.endpoints.get_mut(&0).ok_or(UsbDriverError::DeviceNotFound { address })?;
+        let control = ep0.ed.read_u32(OHCI_ED_CONTROL) & !(0x7FF << OHCI_ED_MPS_SHIFT);
+        ep0.ed.write_u32(OHCI_ED_CONTROL, control | ((max_packet_size & 0x7FF) as u32) << OHCI_ED_MPS_SHIFT);
+        device.max_packet_size0 = max_packet_size;
+        Ok(())
+    }
+
+    /// Add the endpoints of a configuration to a device
+    ///
+    /// The periodic endpoints must all fit the frame budget, or none of
+    /// the endpoints is added.
+    pub fn configure_endpoints(&mut self, address: u8, endpoints: &[UsbEndpointDescriptor]) -> UsbResult<()> {
+        let speed = self.devices.get(&address).ok_or(UsbDriverError::DeviceNotFound { address })?.speed;
+        let mut requests = Vec::new();
+        for endpoint in endpoints {
+            if endpoint_transfer_type(endpoint) == UsbTransferType::Isochronous {
+                log::warn!("OHCI device {}: isochronous endpoint {:#04x} not supported", address, endpoint.bEndpointAddress);
+                return Err(UsbDriverError::UnsupportedFeature);
+            }
+            if endpoint.bEndpointAddress & 0x0F == 0 {
+                return Err(UsbDriverError::InvalidConfiguration);
+            }
+            requests.extend(BandwidthRequest::from_endpoint(address, speed, None, endpoint));
+        }
+        self.bandwidth.reserve_all(&requests)?;
+
+        for endpoint in endpoints {
+            let reservation = self.bandwidth.reservation(address, endpoint.bEndpointAddress).copied();
+            let transfer_type = endpoint_transfer_type(endpoint);
+            if let Err(err) = self.add_endpoint(address, endpoint.bEndpointAddress, transfer_type, endpoint.wMaxPacketSize, reservation) {
+                for endpoint in endpoints {
+                    let _ = self.remove_endpoint(address, endpoint.bEndpointAddress);
                 }
+                return Err(err);
             }
+        }
+        Ok(())
+    }
 
-            *portsc_reg = status;
+    /// Periodic load of the full-speed bus
+    pub fn bandwidth_usage(&self) -> BandwidthUsage {
+        self.bandwidth.usage()
+    }
+
+    /// Forget a device and free its EDs
+    pub fn release_device(&mut self, address: u8) -> UsbResult<()> {
+        let endpoints: Vec<u8> = match self.devices.get(&address) {
+            Some(device) => device.endpoints.keys().copied().collect(),
+            None => return Ok(()),
+        };
+        let mut result = Ok(());
+        for endpoint in endpoints {
+            if let Err(err) = self.remove_endpoint(address, endpoint) {
+                result = Err(err);
+            }
         }
+        self.devices.remove(&address);
+        result
+    }
+
+    pub fn device(&self, address: u8) -> Option<&OhciDevice> {
+        self.devices.get(&address)
+    }
 
+    /// Address of the device on a root port
+    pub fn address_for_port(&self, port_number: u8) -> Option<u8> {
+        self.devices.values()
+            .find(|device| device.port == port_number && device.address != 0)
+            .map(|device| device.address)
+    }
+
+    /// Fill a TD moving `length` bytes at `address`
+    fn fill_td(td: &mut XhciDmaBuffer, control: u32, address: u64, length: usize) -> UsbResult<()> {
+        let (start, end) = if length > 0 {
+            (dma32(address)?, dma32(address + length as u64 - 1)?)
+        } else {
+            (0, 0)
+        };
+        td.write_u32(OHCI_TD_CONTROL, control);
+        td.write_u32(OHCI_TD_CBP, start);
+        td.write_u32(OHCI_TD_NEXT, 0);
+        td.write_u32(OHCI_TD_BE, end);
         Ok(())
     }
 
-    /// Clear port feature
-    pub fn clear_port_feature(&mut self, port_number: u8, feature: u32) -> UsbResult<()> {
-        if port_number == 0 || port_number > self.max_ports {
-            return Err(UsbDriverError::DeviceNotFound { address: port_number });
+    fn new_td(control: u32, address: u64, length: usize) -> UsbResult<XhciDmaBuffer> {
+        let mut td = XhciDmaBuffer::new(OHCI_TD_SIZE, OHCI_DESCRIPTOR_ALIGN)?;
+        dma32(td.address())?;
+        Self::fill_td(&mut td, control, address, length)?;
+        Ok(td)
+    }
+
+    /// Data-stage TDs for `length` bytes of `buffer`
+    ///
+    /// Every TD but the last refuses short packets, so one halts the ED
+    /// with a data underrun instead of starting the next TD; the last
+    /// accepts them.
+    fn data_tds(buffer: &XhciDmaBuffer, pid: u32, length: usize, first_toggle: u32, interrupt_last: bool) -> UsbResult<Vec<OhciTd>> {
+        let chunks = length.div_ceil(OHCI_TD_MAX_LENGTH).max(1);
+        let mut tds = Vec::with_capacity(chunks);
+        for chunk in 0..chunks {
+            let offset = chunk * OHCI_TD_MAX_LENGTH;
+            let chunk_length = (length - offset).min(OHCI_TD_MAX_LENGTH);
+            let last = chunk + 1 == chunks;
+            let toggle = if chunk == 0 { first_toggle } else { OHCI_TD_T_CARRY };
+            let control = ohci_td_control(pid, toggle, last, last && interrupt_last);
+            let td = Self::new_td(control, buffer.address() + offset as u64, chunk_length)?;
+            tds.push(OhciTd { td, offset, length: chunk_length, data: true });
+        }
+        Ok(tds)
+    }
+
+    /// Queue a control transfer on EP0
+    ///
+    /// For OUT requests `data` is sent; for IN requests up to `wLength`
+    /// bytes are received and returned by `take_transfer()`.
+    pub fn submit_control(&mut self, address: u8, setup: &UsbSetupPacket, data: &[u8]) -> UsbResult<OhciTransferHandle> {
+        let direction_in = setup.bmRequestType & 0x80 != 0;
+        let length = setup.wLength as usize;
+        if !direction_in && data.len() < length {
+            return Err(UsbDriverError::InvalidConfiguration);
         }
+        self.endpoint((address, 0))?;
+
+        let mut setup_buffer = XhciDmaBuffer::new(8, OHCI_DESCRIPTOR_ALIGN)?;
+        let mut bytes = [0u8; 8];
+        bytes[0] = setup.bmRequestType;
+        bytes[1] = setup.bRequest;
+        bytes[2..4].copy_from_slice(&setup.wValue.to_le_bytes());
+        bytes[4..6].copy_from_slice(&setup.wIndex.to_le_bytes());
+        bytes[6..8].copy_from_slice(&setup.wLength.to_le_bytes());
+        setup_buffer.copy_from(0, &bytes);
+
+        let mut tds = Vec::new();
+        tds.push(OhciTd {
+            td: Self::new_td(ohci_td_control(OHCI_TD_PID_SETUP, OHCI_TD_T_DATA0, false, false), setup_buffer.address(), 8)?,
+            offset: 0,
+            length: 0,
+            data: false,
+        });
+
+        let buffer = if length > 0 {
+            let mut buffer = XhciDmaBuffer::new(length, OHCI_PAGE_SIZE)?;
+            if !direction_in {
+                buffer.copy_from(0, &data[..length]);
+            }
+            let pid = if direction_in { OHCI_TD_PID_IN } else { OHCI_TD_PID_OUT };
+            tds.extend(Self::data_tds(&buffer, pid, length, OHCI_TD_T_DATA1, false)?);
+            Some(buffer)
+        } else {
+            None
+        };
 
-        unsafe {
-            let port_offset = match port_number {
-                1 => OHCI_HCPORTSTATUS1,
-                2 => OHCI_HCPORTSTATUS2,
-                _ => OHCI_HCPORTSTATUS1 + ((port_number - 1) * 0x04),
+        // The status stage runs opposite to the data stage, IN without one
+        let status_pid = if length == 0 || !direction_in { OHCI_TD_PID_IN } else { OHCI_TD_PID_OUT };
+        tds.push(OhciTd {
+            td: Self::new_td(ohci_td_control(status_pid, OHCI_TD_T_DATA1, false, true), 0, 0)?,
+            offset: length,
+            length: 0,
+            data: false,
+        });
+
+        self.queue_transfer(OhciInflight {
+            address,
+            endpoint: 0,
+            control: true,
+            direction_in,
+            setup: Some(setup_buffer),
+            buffer,
+            pending: tds.len(),
+            tds,
+            next_td: 0,
+            status: UsbTransferStatus::Success,
+            actual: 0,
+        })
+    }
+
+    /// Queue a bulk or interrupt transfer
+    ///
+    /// The direction comes from `endpoint`. OUT transfers send `data`; IN
+    /// transfers receive up to `data.len()` bytes, returned by
+    /// `take_transfer()`.
+    pub fn submit_transfer(&mut self, address: u8, endpoint: u8, data: &[u8]) -> UsbResult<OhciTransferHandle> {
+        let direction_in = endpoint & 0x80 != 0;
+        if endpoint & 0x0F == 0 || self.endpoint((address, endpoint)).is_err() {
+            return Err(UsbDriverError::InvalidConfiguration);
+        }
+
+        let mut buffer = XhciDmaBuffer::new(data.len(), OHCI_PAGE_SIZE)?;
+        if !direction_in {
+            buffer.copy_from(0, data);
+        }
+        let pid = if direction_in { OHCI_TD_PID_IN } else { OHCI_TD_PID_OUT };
+        let tds = Self::data_tds(&buffer, pid, data.len(), OHCI_TD_T_CARRY, true)?;
+
+        self.queue_transfer(OhciInflight {
+            address,
+            endpoint,
+            control: false,
+            direction_in,
+            setup: None,
+            buffer: Some(buffer),
+            pending: tds.len(),
+            tds,
+            next_td: 0,
+            status: UsbTransferStatus::Success,
+            actual: 0,
+        })
+    }
+
+    /// Hand a transfer to the controller: its first TD is written over the
+    /// ED's dummy, a new dummy ends the queue, and TailP moves to it
+    fn queue_transfer(&mut self, mut inflight: OhciInflight) -> UsbResult<OhciTransferHandle> {
+        let key = (inflight.address, inflight.endpoint);
+        let new_tail = XhciDmaBuffer::new(OHCI_TD_SIZE, OHCI_DESCRIPTOR_ALIGN)?;
+        let new_tail_address = dma32(new_tail.address())?;
+
+        let ep = self.endpoint_mut(key)?;
+        let old_tail = mem::replace(&mut ep.tail, new_tail);
+        let first = &mut inflight.tds[0];
+        let mut td = old_tail;
+        for offset in [OHCI_TD_CONTROL, OHCI_TD_CBP, OHCI_TD_BE] {
+            td.write_u32(offset, first.td.read_u32(offset));
+        }
+        first.td = td;
+
+        for index in 0..inflight.tds.len() {
+            let next = match inflight.tds.get(index + 1) {
+                Some(next) => dma32(next.td.address())?,
+                None => new_tail_address,
             };
+            inflight.tds[index].td.write_u32(OHCI_TD_NEXT, next);
+        }
+        inflight.next_td = new_tail_address;
 
-            let portsc_reg = (self.base_address + port_offset as u64) as *mut u32;
-            let mut status = *portsc_reg;
+        let handle = OhciTransferHandle(self.next_handle);
+        self.next_handle += 1;
+        for (index, td) in inflight.tds.iter().enumerate() {
+            self.td_owners.insert(dma32(td.td.address())?, (handle, index));
+        }
+        let transfer_type = self.endpoint(key)?.transfer_type;
+        self.inflight.insert(handle, inflight);
+
+        fence(Ordering::SeqCst);
+        self.endpoint_mut(key)?.ed.write_u32(OHCI_ED_TAIL, new_tail_address);
+        match transfer_type {
+            UsbTransferType::Control => self.write32(OHCI_HCCOMMANDSTATUS, OHCI_CMD_CLF),
+            UsbTransferType::Bulk => self.write32(OHCI_HCCOMMANDSTATUS, OHCI_CMD_BLF),
+            _ => {}
+        }
+        Ok(handle)
+    }
 
-            match feature {
-                // USB Port Enable Disable Change
-                0x0004 => {
-                    status &= !OHCI_PORT_PESC;
-                }
-                // USB Port Suspend Status Change
-                0x0008 => {
-                    status &= !OHCI_PORT_PSSC;
-                }
-                // USB Port Reset Status Change
-                0x000A => {
-                    status &= !OHCI_PORT_PRSC;
-                }
-                // USB Port Connection Status Change
-                0x0008 => {
-                    status &= !OHCI_PORT_CSC;
-                }
-                // USB Port Over Current Indicator Change
-                0x000C => {
-                    status &= !OHCI_PORT_OCIC;
+    /// Take the done queue from the HCCA, oldest TD first
+    fn take_done_queue(&mut self) -> Vec<u32> {
+        let hcca = match self.hcca.as_mut() {
+            Some(hcca) => hcca,
+            None => return Vec::new(),
+        };
+        fence(Ordering::Acquire);
+        let mut next = hcca.read_u32(OHCI_HCCA_DONE_HEAD) & OHCI_ED_POINTER_MASK;
+        hcca.write_u32(OHCI_HCCA_DONE_HEAD, 0);
+        // Let the controller write the next done queue
+        self.write32(OHCI_HCINTERRUPTSTATUS, OHCI_INT_WDH);
+
+        let mut done = Vec::new();
+        while next != 0 && done.len() < self.td_owners.len() {
+            let (handle, index) = match self.td_owners.get(&next) {
+                Some(&owner) => owner,
+                None => break,
+            };
+            done.push(next);
+            next = match self.inflight.get(&handle) {
+                Some(inflight) => inflight.tds[index].td.read_u32(OHCI_TD_NEXT) & OHCI_ED_POINTER_MASK,
+                None => 0,
+            };
+        }
+        done.reverse();
+        done
+    }
+
+    /// Account for retired TDs and complete the transfers they finish
+    fn scan_done_queue(&mut self) {
+        for td_address in self.take_done_queue() {
+            let (handle, index) = match self.td_owners.remove(&td_address) {
+                Some(owner) => owner,
+                None => continue,
+            };
+            let inflight = match self.inflight.get_mut(&handle) {
+                Some(inflight) => inflight,
+                None => continue,
+            };
+            inflight.pending -= 1;
+
+            let td = &inflight.tds[index];
+            let control = td.td.read_u32(OHCI_TD_CONTROL);
+            let code = control >> OHCI_TD_CC_SHIFT;
+            if td.data {
+                let start = inflight.buffer.as_ref().map_or(0, |buffer| buffer.address()) + td.offset as u64;
+                let cbp = td.td.read_u32(OHCI_TD_CBP) as u64;
+                inflight.actual += if cbp == 0 { td.length } else { (cbp - start) as usize };
+                if code == OHCI_CC_NO_ERROR && cbp != 0 {
+                    inflight.status = UsbTransferStatus::ShortPacket;
                 }
-                _ => {
-                    log::warn!("Unsupported OHCI port feature: {:#x}", feature);
-                    return Err(UsbDriverError::UnsupportedFeature);
+            }
+
+            if code != OHCI_CC_NO_ERROR {
+                let status = ohci_transfer_status(code);
+                if status == UsbTransferStatus::ShortPacket && inflight.control {
+                    // Go on with the status stage
+                    inflight.status = status;
+                    let status_stage = inflight.tds.len() - 1;
+                    let status_address = inflight.tds[status_stage].td.address() as u32;
+                    self.skip_tds(handle, index + 1..status_stage);
+                    let _ = self.restart_endpoint(handle, status_address, false);
+                    continue;
                 }
+                inflight.status = status;
+                let next_td = inflight.next_td;
+                let remaining = index + 1..inflight.tds.len();
+                self.skip_tds(handle, remaining);
+                // A stall resets the data toggle; a short packet does not
+                let _ = self.restart_endpoint(handle, next_td, status == UsbTransferStatus::ShortPacket);
+            }
+
+            if self.inflight.get(&handle).map_or(false, |inflight| inflight.pending == 0) {
+                let inflight = self.inflight.remove(&handle).expect("inflight transfer");
+                let (status, actual) = (inflight.status, inflight.actual);
+                self.complete_transfer(handle, inflight, status, actual);
             }
+        }
+    }
 
-            *portsc_reg = status;
+    /// Forget TDs the controller will not retire because the ED was moved
+    /// past them
+    fn skip_tds(&mut self, handle: OhciTransferHandle, range: core::ops::Range<usize>) {
+        let inflight = match self.inflight.get_mut(&handle) {
+            Some(inflight) => inflight,
+            None => return,
+        };
+        for index in range {
+            if let Ok(address) = dma32(inflight.tds[index].td.address()) {
+                if self.td_owners.remove(&address).is_some() {
+                    inflight.pending -= 1;
+                }
+            }
         }
+    }
 
+    /// Point a halted ED at `next_td` and let the controller go on
+    fn restart_endpoint(&mut self, handle: OhciTransferHandle, next_td: u32, keep_toggle: bool) -> UsbResult<()> {
+        let key = match self.inflight.get(&handle) {
+            Some(inflight) => (inflight.address, inflight.endpoint),
+            None => return Ok(()),
+        };
+        let ep = self.endpoint_mut(key)?;
+        let head = ep.ed.read_u32(OHCI_ED_HEAD);
+        let carry = if keep_toggle { head & OHCI_ED_CARRY } else { 0 };
+        ep.ed.write_u32(OHCI_ED_HEAD, next_td | carry);
+        let transfer_type = ep.transfer_type;
+        if transfer_type != UsbTransferType::Interrupt {
+            let (_, _, filled) = Self::list_registers(transfer_type);
+            self.write32(OHCI_HCCOMMANDSTATUS, filled);
+        }
         Ok(())
     }
 
-    /// Enable periodic schedule
-    pub fn enable_periodic_schedule(&mut self) -> UsbResult<()> {
-        unsafe {
-            let control_reg = (self.base_address + OHCI_HCCONTROL as u64) as *mut u32;
-            let mut control = *control_reg;
-            
-            control |= OHCI_CTRL_PLE;
-            *control_reg = control;
-            self.periodic_schedule_enabled = true;
+    /// Record the outcome of a transfer and report it as an event
+    fn complete_transfer(&mut self, handle: OhciTransferHandle, inflight: OhciInflight, status: UsbTransferStatus, actual: usize) {
+        let mut data = Vec::new();
+        if inflight.direction_in {
+            if let Some(buffer) = &inflight.buffer {
+                data.resize(actual.min(buffer.len()), 0);
+                buffer.copy_to(0, &mut data);
+            }
         }
 
-        log::info!("OHCI periodic schedule enabled");
-        Ok(())
+        let (address, endpoint) = (inflight.address, inflight.endpoint);
+        self.stats.total_transactions += 1;
+        if matches!(status, UsbTransferStatus::Success | UsbTransferStatus::ShortPacket) {
+            self.stats.successful_transactions += 1;
+            self.stats.bytes_transferred += actual as u64;
+            self.events.push(UsbEvent::TransferCompleted { address, endpoint, status });
+        } else {
+            self.stats.failed_transactions += 1;
+            self.stats.error_count += 1;
+            self.stats.last_error = Some(format!("device {} endpoint {:#04x}: {:?}", address, endpoint, status));
+            self.events.push(UsbEvent::TransferError { address, endpoint, error: status });
+        }
+
+        self.completed.insert(handle, XhciTransferResult { status, actual_length: actual, data });
     }
 
-    /// Enable control schedule
-    pub fn enable_control_schedule(&mut self) -> UsbResult<()> {
-        unsafe {
-            let control_reg = (self.base_address + OHCI_HCCONTROL as u64) as *mut u32;
-            let mut control = *control_reg;
-            
-            control |= OHCI_CTRL_CLE;
-            *control_reg = control;
-            self.control_schedule_enabled = true;
+    /// Drop the transfers of a device's endpoint, or of all its endpoints,
+    /// after their EDs were unscheduled
+    fn cancel_transfers(&mut self, address: u8, endpoint: Option<u8>) {
+        let handles: Vec<OhciTransferHandle> = self.inflight.iter()
+            .filter(|(_, inflight)| inflight.address == address)
+            .filter(|(_, inflight)| endpoint.map_or(true, |endpoint| inflight.endpoint == endpoint))
+            .map(|(&handle, _)| handle)
+            .collect();
+        for handle in handles {
+            let inflight = self.inflight.remove(&handle).expect("inflight transfer");
+            self.td_owners.retain(|_, (owner, _)| *owner != handle);
+            self.complete_transfer(handle, inflight, UsbTransferStatus::Cancelled, 0);
         }
-
-        log::info!("OHCI control schedule enabled");
-        Ok(())
     }
 
-    /// Enable bulk schedule
-    pub fn enable_bulk_schedule(&mut self) -> UsbResult<()> {
-        unsafe {
-            let control_reg = (self.base_address + OHCI_HCCONTROL as u64) as *mut u32;
-            let mut control = *control_reg;
-            
-            control |= OHCI_CTRL_BLE;
-            *control_reg = control;
-            self.bulk_schedule_enabled = true;
+    fn abandon_transfers(&mut self) {
+        let addresses: Vec<u8> = self.devices.keys().copied().collect();
+        for address in addresses {
+            self.cancel_transfers(address, None);
         }
+        self.td_owners.clear();
+    }
 
-        log::info!("OHCI bulk schedule enabled");
-        Ok(())
+    /// Take the result of a completed transfer
+    pub fn take_transfer(&mut self, handle: OhciTransferHandle) -> Option<XhciTransferResult> {
+        self.completed.remove(&handle)
     }
 
-    /// Get current frame number
-    pub fn get_frame_number(&self) -> u32 {
-        unsafe {
-            let fmnumber_reg = (self.base_address + OHCI_HCFMRNUMBER as u64) as *const u32;
-            *fmnumber_reg & OHCI_FR_NUMBER_MASK
-        }
-    }
-
-    /// Create endpoint descriptor
-    pub fn create_endpoint_descriptor(&mut self, device_address: u8, endpoint_number: u8, 
-                                    direction: UsbDirection, speed: UsbSpeed, 
-                                    packet_size: u16) -> UsbResult<*mut OhciED> {
-        let pool = self.transfer_pool.as_mut().ok_or(UsbDriverError::UnsupportedFeature)?;
-        
-        // Find free ED
-        for &ed_ptr in &pool.eds {
-            unsafe {
-                let ed = &mut *ed_ptr;
-                if ed.td_control == 0 { // Unused ED
-                    // Initialize ED
-                    ed.next_ed = 0x00000001; // Terminate bit
-                    ed.td_control = (device_address as u32) |
-                                   (endpoint_number as u32) << OHCI_ED_EN_SHIFT |
-                                   (match direction {
-                                       UsbDirection::Out => OHCI_ED_DIR_OUT,
-                                       UsbDirection::In => OHCI_ED_DIR_IN,
-                                   }) |
-                                   (match speed {
-                                       UsbSpeed::Low => OHCI_ED_SPEED_LOW,
-                                       UsbSpeed::Full => OHCI_ED_SPEED_FULL,
-                                       _ => OHCI_ED_SPEED_FULL, // Default
-                                   }) |
-                                   (packet_size as u32) << OHCI_ED_MPS_SHIFT;
-                    
-                    ed.current_td = 0x00000001; // Terminate
-                    ed.tail_td = 0x00000001; // Terminate
-                    
-                    return Ok(ed_ptr);
-                }
+    /// Retire finished transfers so they can be taken, leaving the events
+    /// they report for `process_events()`
+    pub fn poll_transfers(&mut self) {
+        if self.read32(OHCI_HCINTERRUPTSTATUS) & OHCI_INT_WDH != 0 {
+            self.scan_done_queue();
+        }
+    }
+
+    fn wait_transfer(&mut self, handle: OhciTransferHandle) -> UsbResult<OhciTransferHandle> {
+        for _ in 0..OHCI_TIMEOUT_SPINS {
+            self.poll_transfers();
+            if self.completed.contains_key(&handle) {
+                return Ok(handle);
             }
+            core::hint::spin_loop();
         }
-        
-        Err(UsbDriverError::UnsupportedFeature)
-    }
-
-    /// Create transfer descriptor
-    pub fn create_transfer_descriptor(&mut self, data: &[u8], toggle_bit: bool) -> UsbResult<*mut OhciTD> {
-        let pool = self.transfer_pool.as_mut().ok_or(UsbDriverError::UnsupportedFeature)?;
-        
-        // Find free TD
-        for &td_ptr in &pool.tds {
-            unsafe {
-                let td = &mut *td_ptr;
-                if td.td_control == 0 { // Unused TD
-                    // Initialize TD
-                    td.next_td = 0x00000001; // Terminate bit
-                    td.alt_next_td = 0x00000001; // Terminate bit
-                    td.cbp = data.as_ptr() as u32;
-                    td.td_control = OHCI_CC_NOT_ACCESSED << OHCI_TD_CC_SHIFT |
-                                   (if toggle_bit { 1 } else { 0 }) << OHCI_TD_T_SHIFT |
-                                   (data.len() as u32 & OHCI_TD_CBP_MASK);
-                    
-                    return Ok(td_ptr);
-                }
+        Err(UsbDriverError::Timeout)
+    }
+
+    fn finished_transfer(&mut self, handle: OhciTransferHandle) -> UsbResult<XhciTransferResult> {
+        let handle = self.wait_transfer(handle)?;
+        let result = self.completed.remove(&handle).ok_or(UsbDriverError::Timeout)?;
+        match result.status {
+            UsbTransferStatus::Success | UsbTransferStatus::ShortPacket => Ok(result),
+            status => Err(UsbDriverError::TransferFailed { status }),
+        }
+    }
+
+    /// Perform a control transfer and return the number of bytes moved
+    pub fn control_transfer(&mut self, address: u8, setup: &UsbSetupPacket, data: &mut [u8]) -> UsbResult<usize> {
+        if data.len() < setup.wLength as usize {
+            return Err(UsbDriverError::InvalidConfiguration);
+        }
+        let handle = self.submit_control(address, setup, data)?;
+        let result = self.finished_transfer(handle)?;
+        if setup.bmRequestType & 0x80 != 0 {
+            data[..result.data.len()].copy_from_slice(&result.data);
+        }
+        Ok(result.actual_length)
+    }
+
+    /// Perform a bulk transfer and return the number of bytes moved
+    pub fn bulk_transfer(&mut self, address: u8, endpoint: u8, data: &mut [u8]) -> UsbResult<usize> {
+        self.normal_transfer(address, endpoint, data, UsbTransferType::Bulk)
+    }
+
+    /// Perform an interrupt transfer and return the number of bytes moved
+    pub fn interrupt_transfer(&mut self, address: u8, endpoint: u8, data: &mut [u8]) -> UsbResult<usize> {
+        self.normal_transfer(address, endpoint, data, UsbTransferType::Interrupt)
+    }
+
+    fn normal_transfer(&mut self, address: u8, endpoint: u8, data: &mut [u8], transfer_type: UsbTransferType) -> UsbResult<usize> {
+        if self.endpoint((address, endpoint)).map(|ep| ep.transfer_type).ok() != Some(transfer_type) {
+            return Err(UsbDriverError::InvalidConfiguration);
+        }
+        let handle = self.submit_transfer(address, endpoint, data)?;
+        let result = self.finished_transfer(handle)?;
+        if endpoint & 0x80 != 0 {
+            data[..result.data.len()].copy_from_slice(&result.data);
+        }
+        Ok(result.actual_length)
+    }
+
+    /// Address the device on a reset port and read its device descriptor
+    pub fn describe_device(&mut self, port_number: u8, speed: UsbSpeed) -> UsbResult<UsbDevice> {
+        let address = self.next_free_address().ok_or(UsbDriverError::InvalidConfiguration)?;
+        self.address_device(port_number, speed, address)?;
+
+        let result = self.read_device_descriptor(address, speed);
+        if result.is_err() {
+            let _ = self.release_device(address);
+        }
+        result
+    }
+
+    fn read_device_descriptor(&mut self, address: u8, speed: UsbSpeed) -> UsbResult<UsbDevice> {
+        // The first 8 bytes hold bMaxPacketSize0
+        let mut descriptor = [0u8; 18];
+        self.control_transfer(address, &get_device_descriptor(8), &mut descriptor[..8])?;
+        if descriptor[7] >= 8 {
+            self.set_max_packet_size0(address, descriptor[7] as u16)?;
+        }
+
+        let length = self.control_transfer(address, &get_device_descriptor(18), &mut descriptor)?;
+        if length < descriptor.len() {
+            return Err(UsbDriverError::ProtocolError);
+        }
+        let descriptor = parse_device_descriptor(&descriptor);
+        log::info!("OHCI device {:04x}:{:04x} at address {}", descriptor.idVendor, descriptor.idProduct, address);
+        Ok(addressed_device(address, speed, descriptor))
+    }
+
+    /// Reset the device on a port, address it and read its device
+    /// descriptor
+    pub fn enumerate_port(&mut self, port_number: u8) -> UsbResult<UsbDevice> {
+        let speed = self.reset_port(port_number)?;
+        self.describe_device(port_number, speed)
+    }
+
+    /// Report connection changes on the root hub ports
+    fn scan_ports(&mut self) {
+        for port_number in 1..=self.max_ports {
+            let status = self.read32(self.port_offset(port_number));
+            if status & OHCI_PORT_CHANGE_MASK == 0 {
+                continue;
+            }
+            self.write32(self.port_offset(port_number), status & OHCI_PORT_CHANGE_MASK);
+            let _ = self.get_port_status(port_number);
+            if status & OHCI_PORT_CSC == 0 {
+                continue;
+            }
+
+            if status & OHCI_PORT_CCS != 0 {
+                let speed = if status & OHCI_PORT_LSDA != 0 { UsbSpeed::Low } else { UsbSpeed::Full };
+                self.events.push(UsbEvent::DeviceConnected { port: port_number, speed });
+            } else if let Some(address) = self.address_for_port(port_number) {
+                self.events.push(UsbEvent::DeviceDisconnected { address });
             }
         }
-        
-        Err(UsbDriverError::UnsupportedFeature)
+    }
+
+    /// Process completed transfers and port changes
+    ///
+    /// Returns the connection changes and transfer completions seen since
+    /// the last call.
+    pub fn process_events(&mut self) -> UsbResult<Vec<UsbEvent>> {
+        self.scan_ports();
+        self.poll_transfers();
+        Ok(mem::take(&mut self.events))
+    }
+
+    /// Service a controller interrupt
+    pub fn handle_interrupt(&mut self) -> UsbResult<Vec<UsbEvent>> {
+        if self.state != OhciControllerState::Operational {
+            return Ok(Vec::new());
+        }
+        let status = self.read32(OHCI_HCINTERRUPTSTATUS) & self.read32(OHCI_HCINTERRUPTENABLE);
+        if status & OHCI_INT_UE != 0 {
+            log::error!("OHCI unrecoverable error");
+            self.write32(OHCI_HCINTERRUPTSTATUS, OHCI_INT_UE);
+            self.stats.error_count += 1;
+            self.state = OhciControllerState::Error;
+            return Err(UsbDriverError::ControllerNotInitialized);
+        }
+        if status & OHCI_INT_SO != 0 {
+            log::warn!("OHCI scheduling overrun");
+            self.stats.error_count += 1;
+        }
+        // WDH is acknowledged once the done queue is taken
+        self.write32(OHCI_HCINTERRUPTSTATUS, status & !OHCI_INT_WDH & OHCI_INT_ALL);
+        self.process_events()
     }
 
     /// Get controller statistics
     pub fn get_stats(&self) -> UsbControllerStats {
-        UsbControllerStats {
-            total_transactions: 0,
-            successful_transactions: 0,
-            failed_transactions: 0,
-            bytes_transferred: 0,
-            error_count: 0,
-            last_error: None,
-        }
+        self.stats.clone()
     }
 
     /// Check if controller is operational
@@ -723,30 +1579,171 @@ impl OhciController {
     }
 }
 
+/// Root ports driven by the hotplug attach sequence
+///
+/// The root hub times port resets itself; a status read after the reset
+/// completed acknowledges it.
+impl UsbPortHost for OhciController {
+    fn port_status(&mut self, port: u8) -> UsbResult<u32> {
+        let raw = self.get_port_status(port)?;
+        if raw & OHCI_PORT_PRSC != 0 {
+            self.clear_port_feature(port, OHCI_PORT_PRSC)?;
+        }
+
+        let mut status = 0;
+        if raw & OHCI_PORT_CCS != 0 {
+            status |= USB_PORT_STAT_CONNECTION;
+        }
+        if raw & OHCI_PORT_PES != 0 {
+            status |= USB_PORT_STAT_ENABLE;
+        }
+        if raw & OHCI_PORT_LSDA != 0 {
+            status |= USB_PORT_STAT_LOW_SPEED;
+        }
+        if raw & OHCI_PORT_POCI != 0 {
+            status |= USB_PORT_STAT_OVERCURRENT;
+        }
+        if raw & OHCI_PORT_PRS != 0 {
+            status |= USB_PORT_STAT_RESET;
+        }
+        if raw & OHCI_PORT_PPS != 0 || self.capability_params.rh_descriptor_a & OHCI_RH_NPS != 0 {
+            status |= USB_PORT_STAT_POWER;
+        }
+        if raw & OHCI_PORT_PSS != 0 {
+            status |= USB_PORT_STAT_SUSPEND;
+        }
+        Ok(status)
+    }
+
+    fn start_port_reset(&mut self, port: u8) -> UsbResult<()> {
+        self.set_port_feature(port, OHCI_PORT_SET_RESET)
+    }
+
+    fn disable_port(&mut self, port: u8) -> UsbResult<()> {
+        self.set_port_feature(port, OHCI_PORT_CLEAR_ENABLE)
+    }
+
+    fn set_address(&mut self, port: u8, speed: UsbSpeed, address: u8) -> UsbResult<u8> {
+        let address = if address != 0 && !self.devices.contains_key(&address) {
+            address
+        } else {
+            self.next_free_address().ok_or(UsbDriverError::InvalidConfiguration)?
+        };
+        self.address_device(port, speed, address)
+    }
+
+    fn control_transfer(&mut self, address: u8, setup: &UsbSetupPacket, data: &mut [u8]) -> UsbResult<usize> {
+        OhciController::control_transfer(self, address, setup, data)
+    }
+
+    fn set_max_packet_size0(&mut self, address: u8, max_packet_size: u16) -> UsbResult<()> {
+        OhciController::set_max_packet_size0(self, address, max_packet_size)
+    }
+
+    fn configure_endpoints(&mut self, address: u8, endpoints: &[UsbEndpointDescriptor]) -> UsbResult<()> {
+        OhciController::configure_endpoints(self, address, endpoints)
+    }
+
+    fn release_address(&mut self, address: u8) -> UsbResult<()> {
+        self.release_device(address)
+    }
+}
+
 impl Drop for OhciController {
     fn drop(&mut self) {
-        unsafe {
-            // Clean up allocated memory
-            if !self.hcca.is_null() {
-                let hcca_size = 256;
-                alloc::alloc::dealloc(
-                    self.hcca as *mut u8,
-                    alloc::alloc::Layout::from_size_align(hcca_size, 256).unwrap(),
-                );
+        // Stop DMA before the HCCA and descriptors are freed
+        if self.hcca.is_some() {
+            self.write32(OHCI_HCINTERRUPTDISABLE, OHCI_INT_ALL | OHCI_INT_MIE);
+            let control = self.read32(OHCI_HCCONTROL) & OHCI_CTRL_RWC;
+            self.write32(OHCI_HCCONTROL, control | OHCI_HCFS_USBRESET);
+        }
+    }
+}
+
+/// OHCI host: a standalone controller and its devices
+#[derive(Debug)]
+pub struct OhciHost {
+    pub controller: OhciController,
+    /// Enumerated devices by address
+    pub devices: BTreeMap<u8, UsbDevice>,
+}
+
+impl OhciHost {
+    /// Bring up the platform's OHCI controller
+    pub fn new() -> UsbResult<Self> {
+        Self::with_base_address(OHCI_DEFAULT_BASE_ADDRESS)
+    }
+
+    /// Bring up the controller at `base_address` and enumerate the devices
+    /// already connected
+    pub fn with_base_address(base_address: u64) -> UsbResult<Self> {
+        let mut controller = OhciController::new(base_address);
+        controller.initialize()?;
+        let mut host = Self { controller, devices: BTreeMap::new() };
+        host.enumerate()?;
+        Ok(host)
+    }
+
+    /// Enumerate every connected port without a device; returns how many
+    /// devices were added
+    pub fn enumerate(&mut self) -> UsbResult<usize> {
+        let mut added = 0;
+        for port_number in 1..=self.controller.max_ports {
+            if self.controller.address_for_port(port_number).is_some() {
+                continue;
+            }
+            if self.controller.get_port_status(port_number)? & OHCI_PORT_CCS == 0 {
+                continue;
             }
+            if self.attach(port_number) {
+                added += 1;
+            }
+        }
+        Ok(added)
+    }
 
-            if let Some(pool) = &self.transfer_pool {
-                alloc::alloc::dealloc(
-                    pool.ed_base as *mut u8,
-                    alloc::alloc::Layout::from_size_align(pool.ed_count * mem::size_of::<OhciED>(), 32).unwrap(),
-                );
-                alloc::alloc::dealloc(
-                    pool.td_base as *mut u8,
-                    alloc::alloc::Layout::from_size_align(pool.td_count * mem::size_of::<OhciTD>(), 32).unwrap(),
-                );
+    fn attach(&mut self, port_number: u8) -> bool {
+        match self.controller.enumerate_port(port_number) {
+            Ok(device) => {
+                self.devices.insert(device.address, device);
+                true
+            }
+            Err(err) => {
+                log::warn!("OHCI port {}: enumeration failed: {:?}", port_number, err);
+                false
             }
         }
     }
+
+    /// Service a controller interrupt, enumerating new devices and
+    /// releasing removed ones
+    pub fn handle_interrupt(&mut self) -> UsbResult<Vec<UsbEvent>> {
+        let events = self.controller.handle_interrupt()?;
+        for event in &events {
+            match *event {
+                UsbEvent::DeviceConnected { port, .. } => {
+                    if self.controller.address_for_port(port).is_none() {
+                        self.attach(port);
+                    }
+                }
+                UsbEvent::DeviceDisconnected { address } => {
+                    self.devices.remove(&address);
+                    let _ = self.controller.release_device(address);
+                }
+                _ => {}
+            }
+        }
+        Ok(events)
+    }
+
+    /// Device at an address
+    pub fn device(&self, address: u8) -> Option<&UsbDevice> {
+        self.devices.get(&address)
+    }
+
+    pub fn port_count(&self) -> u8 {
+        self.controller.max_ports
+    }
 }
 
 #[cfg(test)]
@@ -778,4 +1775,33 @@ mod tests {
         assert_eq!(port.speed, UsbSpeed::Full);
         assert!(!port.connection_status);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_descriptor_encoding() {
+        let control = ohci_td_control(OHCI_TD_PID_IN, OHCI_TD_T_DATA1, true, false);
+        assert_eq!((control >> OHCI_TD_DP_SHIFT) & 0x3, OHCI_TD_PID_IN);
+        assert_eq!((control >> OHCI_TD_T_SHIFT) & 0x3, OHCI_TD_T_DATA1);
+        assert_eq!((control >> OHCI_TD_DI_SHIFT) & 0x7, OHCI_TD_DI_NONE);
+        assert_eq!(control >> OHCI_TD_CC_SHIFT, OHCI_CC_NOT_ACCESSED);
+        assert_ne!(control & OHCI_TD_R, 0);
+
+        let control = ohci_ed_control(9, 0x81, UsbSpeed::Low, 8, UsbTransferType::Interrupt);
+        assert_eq!(control & 0x7F, 9);
+        assert_eq!((control >> OHCI_ED_EN_SHIFT) & 0xF, 1);
+        assert_eq!((control >> OHCI_ED_D_SHIFT) & 0x3, OHCI_ED_DIR_IN);
+        assert_ne!(control & OHCI_ED_S, 0);
+        assert_eq!((control >> OHCI_ED_MPS_SHIFT) & 0x7FF, 8);
+
+        let control = ohci_ed_control(1, 0, UsbSpeed::Full, 64, UsbTransferType::Control);
+        assert_eq!((control >> OHCI_ED_D_SHIFT) & 0x3, OHCI_ED_DIR_TD);
+    }
+
+    #[test]
+    fn test_completion_codes() {
+        assert_eq!(ohci_transfer_status(OHCI_CC_NO_ERROR), UsbTransferStatus::Success);
+        assert_eq!(ohci_transfer_status(OHCI_CC_STALL), UsbTransferStatus::Stalled);
+        assert_eq!(ohci_transfer_status(OHCI_CC_DATA_UNDERRUN), UsbTransferStatus::ShortPacket);
+        assert_eq!(ohci_transfer_status(OHCI_CC_DEVICE_NOT_RESPONDING), UsbTransferStatus::NotAccessed);
+        assert_eq!(ohci_transfer_status(OHCI_CC_NOT_ACCESSED), UsbTransferStatus::Aborted);
+    }
+}
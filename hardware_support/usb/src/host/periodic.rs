@@ -0,0 +1,155 @@
+//! Periodic Schedule Tree
+//!
+//! EHCI and OHCI poll interrupt endpoints from a table of per-frame lists.
+//! Every endpoint is given a power-of-two period of at most
+//! `PERIODIC_SLOTS` frames and a phase within it, and is linked into the
+//! list of every frame `f` with `f % period == phase`. Lists are kept
+//! sorted by period, longest first, so the queue heads of a short period
+//! are shared as the tail of every longer one and each frame's list is a
+//! path through one tree.
+
+use crate::*;
+
+/// Frames of the periodic tree; the frame list repeats it
+pub const PERIODIC_SLOTS: usize = 32;
+
+/// An endpoint's place in the periodic tree
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeriodicEntry<K> {
+    pub key: K,
+    /// Frames between polls, a power of two
+    pub period: u16,
+    /// First frame polled, below `period`
+    pub phase: u16,
+    /// Bytes per poll, used to balance frames
+    pub load: u32,
+}
+
+impl<K> PeriodicEntry<K> {
+    pub fn polls(&self, frame: usize) -> bool {
+        frame % self.period as usize == self.phase as usize
+    }
+}
+
+/// Interrupt endpoints of a controller, ordered by descending period
+#[derive(Debug, Clone)]
+pub struct PeriodicSchedule<K> {
+    entries: Vec<PeriodicEntry<K>>,
+}
+
+impl<K: Copy + PartialEq> PeriodicSchedule<K> {
+    pub fn new() -> Self {
+        Self { entries: Vec::new() }
+    }
+
+    /// Period for an endpoint that wants polling every `interval` frames:
+    /// the largest power of two not above it
+    pub fn period_for(interval: u32) -> u16 {
+        let interval = interval.clamp(1, PERIODIC_SLOTS as u32);
+        1 << (31 - interval.leading_zeros())
+    }
+
+    /// Bytes polled in a frame
+    pub fn frame_load(&self, frame: usize) -> u32 {
+        self.entries.iter().filter(|entry| entry.polls(frame)).map(|entry| entry.load).sum()
+    }
+
+    /// Add an endpoint at the phase whose busiest frame is least loaded
+    pub fn insert(&mut self, key: K, interval: u32, load: u32) -> PeriodicEntry<K> {
+        self.remove(key);
+        let period = Self::period_for(interval);
+        let phase = (0..period)
+            .min_by_key(|&phase| {
+                (phase as usize..PERIODIC_SLOTS)
+                    .step_by(period as usize)
+                    .map(|frame| self.frame_load(frame))
+                    .max()
+                    .unwrap_or(0)
+            })
+            .unwrap_or(0);
+
+        self.insert_at(key, period, phase, load)
+    }
+
+    /// Add an endpoint at a phase chosen elsewhere, e.g. by bandwidth
+    /// accounting
+    pub fn insert_at(&mut self, key: K, period: u16, phase: u16, load: u32) -> PeriodicEntry<K> {
+        self.remove(key);
+        let period = Self::period_for(period as u32);
+        let entry = PeriodicEntry { key, period, phase: phase % period, load };
+        let position = self.entries.iter().position(|other| other.period < period).unwrap_or(self.entries.len());
+        self.entries.insert(position, entry);
+        entry
+    }
+
+    pub fn remove(&mut self, key: K) -> Option<PeriodicEntry<K>> {
+        let position = self.entries.iter().position(|entry| entry.key == key)?;
+        Some(self.entries.remove(position))
+    }
+
+    pub fn get(&self, key: K) -> Option<&PeriodicEntry<K>> {
+        self.entries.iter().find(|entry| entry.key == key)
+    }
+
+    /// First endpoint polled in `frame`
+    pub fn head(&self, frame: usize) -> Option<K> {
+        let frame = frame % PERIODIC_SLOTS;
+        self.entries.iter().find(|entry| entry.polls(frame)).map(|entry| entry.key)
+    }
+
+    /// Endpoint polled after `key` in each of its frames
+    pub fn successor(&self, key: K) -> Option<K> {
+        let position = self.entries.iter().position(|entry| entry.key == key)?;
+        let phase = self.entries[position].phase as usize;
+        self.entries[position + 1..]
+            .iter()
+            .find(|entry| entry.polls(phase))
+            .map(|entry| entry.key)
+    }
+
+    pub fn entries(&self) -> &[PeriodicEntry<K>] {
+        &self.entries
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl<K: Copy + PartialEq> Default for PeriodicSchedule<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_period_rounds_down_to_power_of_two() {
+        assert_eq!(PeriodicSchedule::<u8>::period_for(0), 1);
+        assert_eq!(PeriodicSchedule::<u8>::period_for(10), 8);
+        assert_eq!(PeriodicSchedule::<u8>::period_for(255), 32);
+    }
+
+    #[test]
+    fn test_phases_balance_and_lists_form_a_tree() {
+        let mut schedule = PeriodicSchedule::new();
+        let a = schedule.insert(1u8, 8, 64);
+        let b = schedule.insert(2u8, 8, 64);
+        let c = schedule.insert(3u8, 2, 8);
+        assert_ne!(a.phase, b.phase);
+        assert_eq!(c.period, 2);
+
+        // Every frame list ends in the short-period endpoint polling it
+        let frame = a.phase as usize;
+        assert_eq!(schedule.head(frame), Some(1));
+        let next = schedule.successor(1);
+        assert_eq!(next, if frame % 2 == c.phase as usize { Some(3) } else { None });
+        assert_eq!(schedule.successor(3), None);
+
+        schedule.remove(1);
+        assert_eq!(schedule.frame_load(frame), if frame % 2 == c.phase as usize { 8 } else { 0 });
+    }
+}
@@ -1,53 +1,74 @@
 //! xHCI (eXtensible Host Controller Interface) Driver
-//! 
+//!
 //! Supports USB 3.0+ hosts with modern features like:
 //! - SuperSpeed support
 //! - Stream mode
 //! - Multiple transfer rings
 //! - USB 3.0 features
+//!
+//! Software and controller talk through TRB rings in host memory:
+//! - the command ring, produced by software and consumed by the controller
+//! - one transfer ring per endpoint, likewise produced by software
+//! - the event ring of interrupter 0, produced by the controller
+//!
+//! Ownership of every TRB is decided by its cycle bit, which flips each
+//! time a ring wraps. Host memory is identity mapped, so buffer addresses
+//! are handed to the controller as they are.
 
 use core::mem;
+use core::ptr;
+use core::sync::atomic::{fence, Ordering};
+use alloc::alloc::{alloc_zeroed, dealloc, Layout};
 use crate::*;
+use crate::power::{UsbLinkPowerState, UsbPowerHost};
+use super::{addressed_device, default_max_packet_size0, endpoint_transfer_type, get_device_descriptor, parse_device_descriptor};
+use super::bandwidth::{BandwidthRequest, BandwidthScheduler, BandwidthUsage};
+use crate::hotplug::{
+    UsbPortHost, USB_PORT_STAT_CONNECTION, USB_PORT_STAT_ENABLE, USB_PORT_STAT_HIGH_SPEED, USB_PORT_STAT_LOW_SPEED,
+    USB_PORT_STAT_OVERCURRENT, USB_PORT_STAT_POWER, USB_PORT_STAT_RESET, USB_PORT_STAT_SUPER_SPEED, USB_PORT_STAT_SUSPEND,
+};
 
 #[cfg(feature = "std")]
 use std::collections::BTreeMap;
 
-/// xHCI Registers (Memory Mapped I/O)
-const XHCI_BASE_OFFSET: usize = 0x0;
-const XHCI_CAP_LENGTH: usize = 0x0;
-const XHCI_HCSPARAMS1: usize = 0x4;
-const XHCI_HCSPARAMS2: usize = 0x8;
-const XHCI_HCSPARAMS3: usize = 0xC;
+/// xHCI Capability Register Offsets
+const XHCI_CAPLENGTH: usize = 0x00;
+const XHCI_HCIVERSION: usize = 0x02;
+const XHCI_HCSPARAMS1: usize = 0x04;
+const XHCI_HCSPARAMS2: usize = 0x08;
+const XHCI_HCSPARAMS3: usize = 0x0C;
 const XHCI_HCCPARAMS1: usize = 0x10;
-const XHCI_HCCPARAMS2: usize = 0x14;
-const XHCI_DBOFF: usize = 0x18;
-const XHCI_RTORS_BASE: usize = 0x1C;
-
-const XHCI_USBCMD_OFFSET: usize = 0x20;
-const XHCI_USBSTS_OFFSET: usize = 0x24;
-const XHCI_PAGESIZE_OFFSET: usize = 0x28;
-const XHCI_DNCTRL_OFFSET: usize = 0x34;
-const XHCI_DCR_OFFSET: usize = 0x40;
-const XHCI_IMOD_OFFSET: usize = 0x44;
-
-/// xHCI Operational Register Offsets
-const XHCI_MFINDEX_OFFSET: usize = 0x0;
-const XHCI_CRCR_OFFSET: usize = 0x4;
-const XHCI_DCBAAP_OFFSET: usize = 0x8;
-const XHCI_CONFIG_OFFSET: usize = 0x24;
-
-/// xHCI Extended Capability Register Offsets
-const XHCI_USBLEGSUP_OFFSET: usize = 0x0;
-const XHCI_USBLEGCTLSTS_OFFSET: usize = 0x4;
-const XHCI_DOE_OFFSET: usize = 0x8;
-const XHCI_SP_OFFSET: usize = 0xC;
+const XHCI_DBOFF: usize = 0x14;
+const XHCI_RTSOFF: usize = 0x18;
+const XHCI_HCCPARAMS2: usize = 0x1C;
+
+/// xHCI Operational Register Offsets (from CAPLENGTH)
+const XHCI_USBCMD: usize = 0x00;
+const XHCI_USBSTS: usize = 0x04;
+const XHCI_PAGESIZE: usize = 0x08;
+const XHCI_DNCTRL: usize = 0x14;
+const XHCI_CRCR: usize = 0x18;
+const XHCI_DCBAAP: usize = 0x30;
+const XHCI_CONFIG: usize = 0x38;
+const XHCI_PORTSC_BASE: usize = 0x400;
+const XHCI_PORT_STRIDE: usize = 0x10;
+
+/// xHCI Runtime Register Offsets (from RTSOFF)
+const XHCI_INTERRUPTER_BASE: usize = 0x20;
+const XHCI_INTERRUPTER_STRIDE: usize = 0x20;
+const XHCI_IMAN: usize = 0x00;
+const XHCI_IMOD: usize = 0x04;
+const XHCI_ERSTSZ: usize = 0x08;
+const XHCI_ERSTBA: usize = 0x10;
+const XHCI_ERDP: usize = 0x18;
+const XHCI_MFINDEX: usize = 0x00;
 
 /// xHCI Command Register (USBCMD) bit fields
 const XHCI_CMD_RS: u32 = 1 << 0;     // Run/Stop
 const XHCI_CMD_HCRST: u32 = 1 << 1;  // Host Controller Reset
 const XHCI_CMD_INTE: u32 = 1 << 2;   // Interrupter Enable
-const XHCI_CMD_HSEE: u32 = 1 << 3;   // Hardware SRE Enable
-const XHCI_CMD_LHCRT: u32 = 1 << 4;  // Light HC Reset
+const XHCI_CMD_HSEE: u32 = 1 << 3;   // Host System Error Enable
+const XHCI_CMD_LHCRST: u32 = 1 << 7; // Light HC Reset
 const XHCI_CMD_CSS: u32 = 1 << 8;    // Controller Save State
 const XHCI_CMD_CRS: u32 = 1 << 9;    // Controller Restore State
 const XHCI_CMD_EWE: u32 = 1 << 10;   // Enable Wrap Event
@@ -56,7 +77,7 @@ const XHCI_CMD_EU3S: u32 = 1 << 11;  // Enable U3 MFINDEX Stop
 /// xHCI Status Register (USBSTS) bit fields
 const XHCI_STS_HCH: u32 = 1 << 0;    // Host Controller Halted
 const XHCI_STS_HSE: u32 = 1 << 2;    // Host System Error
-const XHCI_STS_EINT: u32 = 1 << 0;   // Event Interrupt
+const XHCI_STS_EINT: u32 = 1 << 3;   // Event Interrupt
 const XHCI_STS_PCD: u32 = 1 << 4;    // Port Change Detect
 const XHCI_STS_SSS: u32 = 1 << 8;    // Save State Status
 const XHCI_STS_RSS: u32 = 1 << 9;    // Restore State Status
@@ -64,31 +85,134 @@ const XHCI_STS_SRE: u32 = 1 << 10;   // Save Restore Error
 const XHCI_STS_CNR: u32 = 1 << 11;   // Controller Not Ready
 const XHCI_STS_HCE: u32 = 1 << 12;   // Host Controller Error
 
+/// Interrupter Management Register (IMAN) bit fields
+const XHCI_IMAN_IP: u32 = 1 << 0;    // Interrupt Pending
+const XHCI_IMAN_IE: u32 = 1 << 1;    // Interrupt Enable
+
+/// Event Handler Busy, cleared by writing ERDP
+const XHCI_ERDP_EHB: u64 = 1 << 3;
+
+/// Interrupt moderation interval in 250 ns units (1 ms)
+const XHCI_IMOD_INTERVAL: u32 = 4000;
+
+/// Port Status and Control Register (PORTSC) bit fields
+const XHCI_PORTSC_CCS: u32 = 1 << 0;   // Current Connect Status
+const XHCI_PORTSC_PED: u32 = 1 << 1;   // Port Enabled/Disabled
+const XHCI_PORTSC_OCA: u32 = 1 << 3;   // Over-current Active
+const XHCI_PORTSC_PR: u32 = 1 << 4;    // Port Reset
+const XHCI_PORTSC_PLS_SHIFT: u32 = 5;
+const XHCI_PORTSC_PLS_MASK: u32 = 0xF;
+const XHCI_PLS_U0: u32 = 0;             // Active link state
+const XHCI_PLS_U3: u32 = 3;             // Suspended link state
+const XHCI_PORTSC_LWS: u32 = 1 << 16;  // Port Link State Write Strobe
+const XHCI_PORTSC_PP: u32 = 1 << 9;    // Port Power
+const XHCI_PORTSC_SPEED_SHIFT: u32 = 10;
+const XHCI_PORTSC_SPEED_MASK: u32 = 0xF;
+const XHCI_PORTSC_CSC: u32 = 1 << 17;  // Connect Status Change
+const XHCI_PORTSC_PEC: u32 = 1 << 18;  // Port Enabled/Disabled Change
+const XHCI_PORTSC_WRC: u32 = 1 << 19;  // Warm Port Reset Change
+const XHCI_PORTSC_OCC: u32 = 1 << 20;  // Over-current Change
+const XHCI_PORTSC_PRC: u32 = 1 << 21;  // Port Reset Change
+const XHCI_PORTSC_PLC: u32 = 1 << 22;  // Port Link State Change
+const XHCI_PORTSC_CEC: u32 = 1 << 23;  // Port Config Error Change
+const XHCI_PORTSC_CHANGE_MASK: u32 = XHCI_PORTSC_CSC | XHCI_PORTSC_PEC | XHCI_PORTSC_WRC
+    | XHCI_PORTSC_OCC | XHCI_PORTSC_PRC | XHCI_PORTSC_PLC | XHCI_PORTSC_CEC;
+/// Bits written back unchanged; the rest are write-1-to-clear or strobes
+/// PORTPMSC fields, USB3 ports
+const XHCI_PORTPMSC_U1_TIMEOUT_MASK: u32 = 0xFF;
+const XHCI_PORTPMSC_U2_TIMEOUT_SHIFT: u32 = 8;
+const XHCI_PORTPMSC_U2_TIMEOUT_MASK: u32 = 0xFF << 8;
+/// PORTPMSC fields, USB2 ports
+const XHCI_PORTPMSC_HLE: u32 = 1 << 16; // Hardware LPM Enable
+/// U1 inactivity timeout in microseconds (max 127)
+const XHCI_U1_TIMEOUT_US: u32 = 127;
+/// U2 inactivity timeout in 256us units (max 0xFE)
+const XHCI_U2_TIMEOUT_UNITS: u32 = 0x10;
+
+const XHCI_PORTSC_PRESERVE: u32 = XHCI_PORTSC_PP | (0x3 << 14) | (0x7 << 25);
+
 /// xHCI TRB Types
-const XHCI_TRB_RESERVED: u32 = 0;
 const XHCI_TRB_NORMAL: u32 = 1;
 const XHCI_TRB_SETUP: u32 = 2;
 const XHCI_TRB_DATA: u32 = 3;
 const XHCI_TRB_STATUS: u32 = 4;
 const XHCI_TRB_ISOCH: u32 = 5;
 const XHCI_TRB_LINK: u32 = 6;
-const XHCI_TRB_EVENT_DATA: u7 << 1 = 7;
+const XHCI_TRB_EVENT_DATA: u32 = 7;
 const XHCI_TRB_NO_OP: u32 = 8;
+const XHCI_TRB_ENABLE_SLOT: u32 = 9;
+const XHCI_TRB_DISABLE_SLOT: u32 = 10;
+const XHCI_TRB_ADDRESS_DEVICE: u32 = 11;
+const XHCI_TRB_CONFIGURE_ENDPOINT: u32 = 12;
+const XHCI_TRB_EVALUATE_CONTEXT: u32 = 13;
+const XHCI_TRB_RESET_ENDPOINT: u32 = 14;
+const XHCI_TRB_STOP_ENDPOINT: u32 = 15;
+const XHCI_TRB_SET_TR_DEQUEUE: u32 = 16;
+const XHCI_TRB_RESET_DEVICE: u32 = 17;
+const XHCI_TRB_NO_OP_COMMAND: u32 = 23;
+const XHCI_TRB_TRANSFER_EVENT: u32 = 32;
+const XHCI_TRB_COMMAND_COMPLETION: u32 = 33;
+const XHCI_TRB_PORT_STATUS_CHANGE: u32 = 34;
+const XHCI_TRB_HOST_CONTROLLER_EVENT: u32 = 37;
+
+/// TRB control field bits
+const XHCI_TRB_CYCLE: u32 = 1 << 0;
+const XHCI_TRB_TOGGLE_CYCLE: u32 = 1 << 1; // Link TRBs only
+const XHCI_TRB_ISP: u32 = 1 << 2;          // Interrupt on Short Packet
+const XHCI_TRB_CHAIN: u32 = 1 << 4;
+const XHCI_TRB_IOC: u32 = 1 << 5;          // Interrupt On Completion
+const XHCI_TRB_IDT: u32 = 1 << 6;          // Immediate Data
+const XHCI_TRB_BSR: u32 = 1 << 9;          // Block Set Address Request
+const XHCI_TRB_TYPE_SHIFT: u32 = 10;
+const XHCI_TRB_DIR_IN: u32 = 1 << 16;
+const XHCI_TRB_TRT_SHIFT: u32 = 16;        // Setup TRB transfer type
+const XHCI_TRB_TBC_SHIFT: u32 = 7;         // Isoch TRB transfer burst count
+const XHCI_TRB_TLBPC_SHIFT: u32 = 16;      // Isoch TRB last burst packet count
+const XHCI_TRB_FRAME_ID_SHIFT: u32 = 20;
+const XHCI_TRB_SIA: u32 = 1 << 31;         // Start Isoch ASAP
+const XHCI_TRB_ENDPOINT_SHIFT: u32 = 16;
+const XHCI_TRB_SLOT_SHIFT: u32 = 24;
+
+/// Setup TRB transfer types
+const XHCI_TRT_NO_DATA: u32 = 0;
+const XHCI_TRT_OUT_DATA: u32 = 2;
+const XHCI_TRT_IN_DATA: u32 = 3;
 
 /// xHCI TRB Completion Codes
 const XHCI_CC_SUCCESS: u32 = 1;
-const XHCI_CC_DATA_BUFFER_ERROR: u32 = 4;
-const XHCI_CC_BABBLE_DETECTED: u32 = 5;
-const XHCI_CC_USB_TRANSACTION_ERROR: u32 = 6;
-const XHCI_CC_TRB_ERROR: u32 = 7;
-const XHCI_CC_STALL_ERROR: u32 = 14;
-const XHCI_CC_INVALID_STREAM_TYPE: u32 = 21;
-const XHCI_CC_INVALID_EP_STATE: u32 = 22;
-const XHCI_CC_RING_OVERRUN: u32 = 27;
-const XHCI_CC_RING_STOPPED: u32 = 28;
-const XHCI_CC_LINK_LOST: u32 = 29;
+const XHCI_CC_DATA_BUFFER_ERROR: u32 = 2;
+const XHCI_CC_BABBLE_DETECTED: u32 = 3;
+const XHCI_CC_USB_TRANSACTION_ERROR: u32 = 4;
+const XHCI_CC_TRB_ERROR: u32 = 5;
+const XHCI_CC_STALL_ERROR: u32 = 6;
+const XHCI_CC_RESOURCE_ERROR: u32 = 7;
+const XHCI_CC_BANDWIDTH_ERROR: u32 = 8;
+const XHCI_CC_NO_SLOTS_AVAILABLE: u32 = 9;
+const XHCI_CC_SHORT_PACKET: u32 = 13;
+const XHCI_CC_RING_UNDERRUN: u32 = 14;
+const XHCI_CC_RING_OVERRUN: u32 = 15;
+const XHCI_CC_CONTEXT_STATE_ERROR: u32 = 19;
+const XHCI_CC_COMMAND_ABORTED: u32 = 25;
+const XHCI_CC_STOPPED: u32 = 26;
+const XHCI_CC_STOPPED_LENGTH_INVALID: u32 = 27;
+const XHCI_CC_STOPPED_SHORT_PACKET: u32 = 28;
 const XHCI_CC_ISOCH_BUFFER_OVERRUN: u32 = 31;
-const XHCI_CC_ISOCH_BUFFER_UNDERRUN: u32 = 32;
+
+/// TRBs per command and transfer ring, including the link TRB
+const XHCI_RING_TRBS: usize = 256;
+/// TRBs in the single event ring segment
+const XHCI_EVENT_RING_TRBS: usize = 256;
+/// Size of one TRB in bytes
+const XHCI_TRB_SIZE: usize = 16;
+/// Rings and contexts are page aligned so they never cross a 64 KiB boundary
+const XHCI_PAGE_ALIGN: usize = 4096;
+/// Largest buffer a single Normal or Data TRB can describe
+const XHCI_TRB_MAX_LENGTH: usize = 64 * 1024;
+/// Polling iterations before a register wait or command gives up
+const XHCI_TIMEOUT_SPINS: u32 = 1_000_000;
+
+/// Platform MMIO base of the first xHCI controller
+pub const XHCI_DEFAULT_BASE_ADDRESS: u64 = 0xFED90000;
 
 /// xHCI Endpoint Types
 #[repr(u8)]
@@ -104,92 +228,289 @@ pub enum XhciEndpointType {
     InterruptIn = 7,
 }
 
-/// xHCI Slot Context
-#[derive(Debug, Clone, Copy)]
-#[repr(C)]
-pub struct XhciSlotContext {
-    pub route_string: u32,
-    pub speed: u8,
-    pub mtt: u8,
-    pub hub: u8,
-    pub context_entries: u8,
-    pub max_exit_latency: u16,
-    pub root_hub_port_number: u8,
-    pub number_of_ports: u8,
-    pub parent_hub_slot_id: u8,
-    pub parent_hub_port_number: u8,
-    pub ttt: u8,
-    pub u1_device_exit_latency: u8,
-    pub reserved: u8,
-    pub u2_device_exit_latency: u16,
-    pub slot_state: u8,
-    pub reserved2: [u8; 3],
-}
-
-/// xHCI Device Context Array Entry
-#[derive(Debug, Clone, Copy)]
-#[repr(C)]
-pub struct XhciDeviceContextArray {
-    pub input_control_context: u32,
-    pub slot_context: u32,
-    pub ep_context_1: u32,
-    pub ep_context_2: u32,
-    pub ep_context_3: u32,
-    pub ep_context_4: u32,
-    pub ep_context_5: u32,
-    pub ep_context_6: u32,
-    pub ep_context_7: u32,
-    pub ep_context_8: u32,
-    pub ep_context_9: u32,
-    pub ep_context_10: u32,
-    pub ep_context_11: u32,
-    pub ep_context_12: u32,
-    pub ep_context_13: u32,
-    pub ep_context_14: u32,
-    pub ep_context_15: u32,
-    pub ep_context_16: u32,
-    pub ep_context_17: u32,
-    pub ep_context_18: u32,
-    pub ep_context_19: u32,
-    pub ep_context_20: u32,
-    pub ep_context_21: u32,
-    pub ep_context_22: u32,
-    pub ep_context_23: u32,
-    pub ep_context_24: u32,
-    pub ep_context_25: u32,
-    pub ep_context_26: u32,
-    pub ep_context_27: u32,
-    pub ep_context_28: u32,
-    pub ep_context_29: u32,
-    pub ep_context_30: u32,
-    pub ep_context_31: u32,
-}
-
-/// xHCI Transfer Ring
+impl XhciEndpointType {
+    /// Endpoint context type of an endpoint descriptor's transfer type
+    pub fn from_transfer(transfer_type: UsbTransferType, direction_in: bool) -> Self {
+        match (transfer_type, direction_in) {
+            (UsbTransferType::Control, _) => XhciEndpointType::Control,
+            (UsbTransferType::Isochronous, false) => XhciEndpointType::IsochronousOut,
+            (UsbTransferType::Isochronous, true) => XhciEndpointType::IsochronousIn,
+            (UsbTransferType::Bulk, false) => XhciEndpointType::BulkOut,
+            (UsbTransferType::Bulk, true) => XhciEndpointType::BulkIn,
+            (UsbTransferType::Interrupt, false) => XhciEndpointType::InterruptOut,
+            (UsbTransferType::Interrupt, true) => XhciEndpointType::InterruptIn,
+        }
+    }
+}
+
+/// Zeroed, aligned host memory shared with the controller
 #[derive(Debug)]
-pub struct XhciTransferRing {
-    pub base: *mut u8,
-    pub enqueue_ptr: *mut u32,
-    pub dequeue_ptr: *mut u32,
-    pub ring_size: usize,
-    pub consumer_cycle_state: bool,
-    pub pcs_supported: bool,
+pub struct XhciDmaBuffer {
+    ptr: *mut u8,
+    layout: Layout,
 }
 
-/// xHCI Event Ring
+impl XhciDmaBuffer {
+    /// Allocate `size` zeroed bytes aligned to `align`
+    pub fn new(size: usize, align: usize) -> UsbResult<Self> {
+        let layout = Layout::from_size_align(size.max(1), align)
+            .map_err(|_| UsbDriverError::InvalidConfiguration)?;
+        let ptr = unsafe { alloc_zeroed(layout) };
+        if ptr.is_null() {
+            return Err(UsbDriverError::UnsupportedFeature);
+        }
+        Ok(Self { ptr, layout })
+    }
+
+    /// Address the controller uses for this buffer
+    pub fn address(&self) -> u64 {
+        self.ptr as u64
+    }
+
+    pub fn len(&self) -> usize {
+        self.layout.size()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layout.size() == 0
+    }
+
+    pub fn read_u32(&self, offset: usize) -> u32 {
+        assert!(offset + 4 <= self.len());
+        unsafe { ptr::read_volatile(self.ptr.add(offset) as *const u32) }
+    }
+
+    pub fn write_u32(&mut self, offset: usize, value: u32) {
+        assert!(offset + 4 <= self.len());
+        unsafe { ptr::write_volatile(self.ptr.add(offset) as *mut u32, value) }
+    }
+
+    pub fn write_u64(&mut self, offset: usize, value: u64) {
+        self.write_u32(offset, value as u32);
+        self.write_u32(offset + 4, (value >> 32) as u32);
+    }
+
+    /// Zero `len` bytes starting at `offset`
+    pub fn clear(&mut self, offset: usize, len: usize) {
+        assert!(offset + len <= self.len());
+        unsafe { ptr::write_bytes(self.ptr.add(offset), 0, len) }
+    }
+
+    pub fn copy_from(&mut self, offset: usize, data: &[u8]) {
+        assert!(offset + data.len() <= self.len());
+        unsafe { ptr::copy_nonoverlapping(data.as_ptr(), self.ptr.add(offset), data.len()) }
+    }
+
+    pub fn copy_to(&self, offset: usize, out: &mut [u8]) {
+        assert!(offset + out.len() <= self.len());
+        unsafe { ptr::copy_nonoverlapping(self.ptr.add(offset), out.as_mut_ptr(), out.len()) }
+    }
+
+    /// Read the TRB at `index`
+    pub fn read_trb(&self, index: usize) -> UsbTRB {
+        let offset = index * XHCI_TRB_SIZE;
+        UsbTRB {
+            ptr_low: self.read_u32(offset),
+            ptr_high: self.read_u32(offset + 4),
+            status: self.read_u32(offset + 8),
+            control: self.read_u32(offset + 12),
+        }
+    }
+
+    /// Write the TRB at `index`, making the control word (and with it the
+    /// cycle bit) visible last
+    pub fn write_trb(&mut self, index: usize, trb: UsbTRB) {
+        let offset = index * XHCI_TRB_SIZE;
+        self.write_u32(offset, trb.ptr_low);
+        self.write_u32(offset + 4, trb.ptr_high);
+        self.write_u32(offset + 8, trb.status);
+        fence(Ordering::Release);
+        self.write_u32(offset + 12, trb.control);
+    }
+}
+
+impl Drop for XhciDmaBuffer {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.ptr, self.layout) }
+    }
+}
+
+/// Build a TRB from its pointer, status and control fields
+fn make_trb(pointer: u64, status: u32, control: u32) -> UsbTRB {
+    UsbTRB {
+        ptr_low: pointer as u32,
+        ptr_high: (pointer >> 32) as u32,
+        status,
+        control,
+    }
+}
+
+fn trb_type(trb: &UsbTRB) -> u32 {
+    (trb.control >> XHCI_TRB_TYPE_SHIFT) & 0x3F
+}
+
+fn trb_pointer(trb: &UsbTRB) -> u64 {
+    ((trb.ptr_high as u64) << 32) | trb.ptr_low as u64
+}
+
+fn completion_code(trb: &UsbTRB) -> u32 {
+    trb.status >> 24
+}
+
+/// Producer ring: the command ring and every transfer ring
+///
+/// The last TRB is a link back to the start that toggles the cycle bit.
+#[derive(Debug)]
+pub struct XhciRing {
+    buffer: XhciDmaBuffer,
+    trbs: usize,
+    enqueue: usize,
+    /// First TRB the controller has not reported as consumed
+    dequeue: usize,
+    cycle: bool,
+}
+
+impl XhciRing {
+    /// Allocate a ring of `trbs` TRBs, the last of which is the link TRB
+    pub fn new(trbs: usize) -> UsbResult<Self> {
+        if trbs < 2 {
+            return Err(UsbDriverError::InvalidConfiguration);
+        }
+        let mut buffer = XhciDmaBuffer::new(trbs * XHCI_TRB_SIZE, XHCI_PAGE_ALIGN)?;
+        let link = make_trb(buffer.address(), 0, (XHCI_TRB_LINK << XHCI_TRB_TYPE_SHIFT) | XHCI_TRB_TOGGLE_CYCLE);
+        buffer.write_trb(trbs - 1, link);
+        Ok(Self { buffer, trbs, enqueue: 0, dequeue: 0, cycle: true })
+    }
+
+    pub fn address(&self) -> u64 {
+        self.buffer.address()
+    }
+
+    /// Producer cycle state
+    pub fn cycle(&self) -> bool {
+        self.cycle
+    }
+
+    /// Enqueue pointer with the cycle state in bit 0, as CRCR and the TR
+    /// dequeue pointer of endpoint contexts expect it
+    pub fn dequeue_pointer(&self) -> u64 {
+        self.trb_address(self.enqueue) | self.cycle as u64
+    }
+
+    fn trb_address(&self, index: usize) -> u64 {
+        self.buffer.address() + (index * XHCI_TRB_SIZE) as u64
+    }
+
+    fn index_of(&self, address: u64) -> Option<usize> {
+        let offset = address.checked_sub(self.buffer.address())? as usize;
+        if offset % XHCI_TRB_SIZE != 0 || offset / XHCI_TRB_SIZE >= self.trbs - 1 {
+            return None;
+        }
+        Some(offset / XHCI_TRB_SIZE)
+    }
+
+    /// Whether `address` is a TRB of this ring
+    pub fn contains(&self, address: u64) -> bool {
+        self.index_of(address).is_some()
+    }
+
+    /// TRBs that can be enqueued before the ring is full
+    pub fn free_trbs(&self) -> usize {
+        let usable = self.trbs - 1;
+        let used = (self.enqueue + usable - self.dequeue) % usable;
+        usable - 1 - used
+    }
+
+    /// Enqueue a TRB, handing it to the controller, and return its address
+    ///
+    /// Callers queueing a multi-TRB TD check `free_trbs()` first so the TD
+    /// is never split by a full ring.
+    pub fn push(&mut self, trb: UsbTRB) -> UsbResult<u64> {
+        if self.free_trbs() == 0 {
+            return Err(UsbDriverError::TransferFailed { status: UsbTransferStatus::BufferOverrun });
+        }
+        let address = self.trb_address(self.enqueue);
+        let control = (trb.control & !XHCI_TRB_CYCLE) | self.cycle as u32;
+        self.buffer.write_trb(self.enqueue, UsbTRB { control, ..trb });
+
+        self.enqueue += 1;
+        if self.enqueue == self.trbs - 1 {
+            // Pass the link TRB over too; it keeps the chain bit so a TD
+            // can continue across the wrap
+            let link = make_trb(
+                self.buffer.address(),
+                0,
+                (XHCI_TRB_LINK << XHCI_TRB_TYPE_SHIFT) | XHCI_TRB_TOGGLE_CYCLE
+                    | (trb.control & XHCI_TRB_CHAIN) | self.cycle as u32,
+            );
+            self.buffer.write_trb(self.trbs - 1, link);
+            self.enqueue = 0;
+            self.cycle = !self.cycle;
+        }
+        Ok(address)
+    }
+
+    /// Record that the controller has consumed the TRB at `address`
+    pub fn consumed(&mut self, address: u64) {
+        if let Some(index) = self.index_of(address) {
+            self.dequeue = (index + 1) % (self.trbs - 1);
+        }
+    }
+
+    /// Forget every queued TRB, after the controller was pointed past them
+    pub fn skip_to_enqueue(&mut self) {
+        self.dequeue = self.enqueue;
+    }
+}
+
+/// Event ring of an interrupter, with its one-entry segment table
 #[derive(Debug)]
 pub struct XhciEventRing {
-    pub base: *mut u32,
-    pub enqueue_ptr: *mut u32,
-    pub dequeue_ptr: *mut u32,
-    pub ring_size: usize,
-    pub segment_size: usize,
-    pub producer_cycle_state: bool,
-    pub pcs_supported: bool,
+    segment: XhciDmaBuffer,
+    segment_table: XhciDmaBuffer,
+    trbs: usize,
+    dequeue: usize,
+    cycle: bool,
+}
+
+impl XhciEventRing {
+    pub fn new(trbs: usize) -> UsbResult<Self> {
+        let segment = XhciDmaBuffer::new(trbs * XHCI_TRB_SIZE, XHCI_PAGE_ALIGN)?;
+        let mut segment_table = XhciDmaBuffer::new(16, 64)?;
+        segment_table.write_u64(0, segment.address());
+        segment_table.write_u32(8, trbs as u32);
+        Ok(Self { segment, segment_table, trbs, dequeue: 0, cycle: true })
+    }
+
+    /// Address of the event ring segment table (ERSTBA)
+    pub fn segment_table_address(&self) -> u64 {
+        self.segment_table.address()
+    }
+
+    /// Current dequeue pointer (ERDP)
+    pub fn dequeue_pointer(&self) -> u64 {
+        self.segment.address() + (self.dequeue * XHCI_TRB_SIZE) as u64
+    }
+
+    /// Take the next event the controller has written, if any
+    pub fn pop(&mut self) -> Option<UsbTRB> {
+        let control = self.segment.read_u32(self.dequeue * XHCI_TRB_SIZE + 12);
+        if (control & XHCI_TRB_CYCLE != 0) != self.cycle {
+            return None;
+        }
+        fence(Ordering::Acquire);
+        let trb = self.segment.read_trb(self.dequeue);
+        self.dequeue += 1;
+        if self.dequeue == self.trbs {
+            self.dequeue = 0;
+            self.cycle = !self.cycle;
+        }
+        Some(trb)
+    }
 }
 
 /// xHCI Capability Parameters
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Default)]
 pub struct XhciCapabilityParams {
     pub usb_cap_length: u8,
     pub hci_version: u16,
@@ -200,11 +521,10 @@ pub struct XhciCapabilityParams {
     pub hcc_params2: u32,
     pub doorbell_offset: u32,
     pub rtors_base: u32,
-    pub hcc_params_ext: u32,
 }
 
 /// xHCI Controller State
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum XhciControllerState {
     Uninitialized,
     Initialized,
@@ -213,12 +533,6 @@ pub enum XhciControllerState {
     Error,
 }
 
-/// xHCI Doorbell Register
-#[derive(Debug)]
-pub struct XhciDoorbell {
-    pub address: *mut u32,
-}
-
 /// xHCI Port Status and Control
 #[derive(Debug, Clone, Copy)]
 pub struct XhciPortStatus {
@@ -228,16 +542,6 @@ pub struct XhciPortStatus {
     pub porthlpmc: u32,
 }
 
-/// xHCI Command Ring
-#[derive(Debug)]
-pub struct XhciCommandRing {
-    pub base: *mut u32,
-    pub enqueue_ptr: *mut u32,
-    pub dequeue_ptr: *mut u32,
-    pub ring_size: usize,
-    pub cycle_state: bool,
-}
-
 /// xHCI Port Information
 #[derive(Debug)]
 pub struct XhciPort {
@@ -249,91 +553,288 @@ pub struct XhciPort {
     pub power_state: UsbPowerState,
 }
 
+/// Result of a command reported on the event ring
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct XhciCommandCompletion {
+    pub code: u32,
+    pub slot_id: u8,
+}
+
+/// Device slot: the contexts and transfer rings of one attached device
+#[derive(Debug)]
+pub struct XhciSlot {
+    pub slot_id: u8,
+    pub port: u8,
+    pub speed: UsbSpeed,
+    /// Address assigned by the Address Device command
+    pub address: u8,
+    pub max_packet_size0: u16,
+    /// Protocol speed ID reported in PORTSC
+    speed_id: u32,
+    output_context: XhciDmaBuffer,
+    input_context: XhciDmaBuffer,
+    /// Transfer rings by device context index
+    rings: BTreeMap<u8, XhciRing>,
+}
+
+/// Handle of a submitted transfer: the address of its last TRB
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct XhciTransferHandle(pub u64);
+
+/// Outcome of a completed transfer
+#[derive(Debug, Clone)]
+pub struct XhciTransferResult {
+    pub status: UsbTransferStatus,
+    pub actual_length: usize,
+    /// Received bytes of an IN transfer
+    pub data: Vec<u8>,
+}
+
+/// Transfer descriptor waiting for its completion event
+#[derive(Debug)]
+struct XhciInflight {
+    slot_id: u8,
+    dci: u8,
+    control: bool,
+    direction_in: bool,
+    buffer: Option<XhciDmaBuffer>,
+    /// TRBs of the TD: address, offset into the buffer, length
+    trbs: Vec<(u64, usize, u32)>,
+    /// Bytes moved when a short packet ended the data stage early
+    actual: Option<usize>,
+}
+
+/// Map a completion code to the transfer status it reports
+pub fn xhci_transfer_status(code: u32) -> UsbTransferStatus {
+    match code {
+        XHCI_CC_SUCCESS => UsbTransferStatus::Success,
+        XHCI_CC_SHORT_PACKET => UsbTransferStatus::ShortPacket,
+        XHCI_CC_STALL_ERROR => UsbTransferStatus::Stalled,
+        XHCI_CC_BABBLE_DETECTED => UsbTransferStatus::BabbleDetected,
+        XHCI_CC_DATA_BUFFER_ERROR | XHCI_CC_RING_OVERRUN | XHCI_CC_ISOCH_BUFFER_OVERRUN => UsbTransferStatus::BufferOverrun,
+        XHCI_CC_RING_UNDERRUN => UsbTransferStatus::BufferUnderrun,
+        XHCI_CC_USB_TRANSACTION_ERROR => UsbTransferStatus::NotAccessed,
+        XHCI_CC_STOPPED | XHCI_CC_STOPPED_LENGTH_INVALID | XHCI_CC_STOPPED_SHORT_PACKET => UsbTransferStatus::Cancelled,
+        _ => UsbTransferStatus::Aborted,
+    }
+}
+
+/// Map a failed command's completion code to a driver error
+fn command_error(code: u32) -> UsbDriverError {
+    match code {
+        XHCI_CC_NO_SLOTS_AVAILABLE | XHCI_CC_RESOURCE_ERROR | XHCI_CC_BANDWIDTH_ERROR => UsbDriverError::InvalidConfiguration,
+        XHCI_CC_USB_TRANSACTION_ERROR => UsbDriverError::TransferFailed { status: UsbTransferStatus::NotAccessed },
+        XHCI_CC_STALL_ERROR => UsbDriverError::TransferFailed { status: UsbTransferStatus::Stalled },
+        _ => UsbDriverError::ProtocolError,
+    }
+}
+
+/// Device context index of an endpoint address (EP0 is 1)
+pub fn xhci_endpoint_dci(endpoint_address: u8) -> u8 {
+    let number = endpoint_address & 0x0F;
+    if number == 0 {
+        1
+    } else {
+        number * 2 + (endpoint_address >> 7)
+    }
+}
+
+/// Endpoint address of a device context index
+pub fn xhci_dci_endpoint(dci: u8) -> u8 {
+    if dci <= 1 {
+        0
+    } else {
+        (dci / 2) | if dci & 1 != 0 { 0x80 } else { 0 }
+    }
+}
+
+/// Speed of a PORTSC protocol speed ID
+fn port_speed(speed_id: u32) -> UsbSpeed {
+    match speed_id {
+        1 => UsbSpeed::Full,
+        2 => UsbSpeed::Low,
+        3 => UsbSpeed::High,
+        4 => UsbSpeed::Super,
+        _ => UsbSpeed::SuperPlus,
+    }
+}
+
+/// Endpoint context interval: service period as 2^interval * 125 us
+pub fn xhci_endpoint_interval(speed: UsbSpeed, transfer_type: UsbTransferType, b_interval: u8) -> u8 {
+    match (transfer_type, speed) {
+        (UsbTransferType::Control, _) | (UsbTransferType::Bulk, _) => 0,
+        // bInterval is already an exponent of 2^(bInterval-1) (micro)frames
+        (_, UsbSpeed::High) | (_, UsbSpeed::Super) | (_, UsbSpeed::SuperPlus) => b_interval.clamp(1, 16) - 1,
+        (UsbTransferType::Isochronous, _) => (b_interval.clamp(1, 16) - 1 + 3).min(15),
+        // Full/low speed interrupt bInterval counts 1 ms frames
+        (UsbTransferType::Interrupt, _) => {
+            let microframes = b_interval.max(1) as u32 * 8;
+            ((31 - microframes.leading_zeros()) as u8).clamp(3, 10)
+        }
+    }
+}
+
+/// Encode a setup packet as the immediate data of a Setup TRB
+fn setup_trb(setup: &UsbSetupPacket) -> UsbTRB {
+    let ptr_low = setup.bmRequestType as u32
+        | (setup.bRequest as u32) << 8
+        | (setup.wValue as u32) << 16;
+    let ptr_high = setup.wIndex as u32 | (setup.wLength as u32) << 16;
+    let transfer_type = if setup.wLength == 0 {
+        XHCI_TRT_NO_DATA
+    } else if setup.bmRequestType & 0x80 != 0 {
+        XHCI_TRT_IN_DATA
+    } else {
+        XHCI_TRT_OUT_DATA
+    };
+    UsbTRB {
+        ptr_low,
+        ptr_high,
+        status: 8,
+        control: (XHCI_TRB_SETUP << XHCI_TRB_TYPE_SHIFT) | XHCI_TRB_IDT | (transfer_type << XHCI_TRB_TRT_SHIFT),
+    }
+}
+
 /// xHCI Controller Implementation
+#[derive(Debug)]
 pub struct XhciController {
     pub base_address: u64,
     pub capability_params: XhciCapabilityParams,
     pub state: XhciControllerState,
-    pub device_context_array: *mut u32,
-    pub command_ring: Option<XhciCommandRing>,
-    pub event_ring: Option<XhciEventRing>,
-    pub doorbell_array: *mut u32,
-    pub slots: BTreeMap<u8, XhciSlotContext>,
-    pub transfer_rings: BTreeMap<u32, XhciTransferRing>,
     pub ports: Vec<XhciPort>,
+    pub slots: BTreeMap<u8, XhciSlot>,
     pub interrupt_vector: u8,
     pub max_slots: u8,
     pub max_ports: u8,
     pub max_streams: u8,
-    pub max_intr_interrupts: u8,
+    pub max_intr_interrupts: u16,
     pub extended_capabilities_offset: u32,
+    /// Context size in bytes (32, or 64 when HCCPARAMS1.CSZ is set)
+    pub context_size: usize,
+    device_context_array: Option<XhciDmaBuffer>,
+    scratchpad_array: Option<XhciDmaBuffer>,
+    scratchpad_pages: Vec<XhciDmaBuffer>,
+    command_ring: Option<XhciRing>,
+    event_ring: Option<XhciEventRing>,
+    command_completions: BTreeMap<u64, XhciCommandCompletion>,
+    inflight: BTreeMap<XhciTransferHandle, XhciInflight>,
+    /// Owning transfer of every queued TRB
+    trb_owners: BTreeMap<u64, XhciTransferHandle>,
+    completed: BTreeMap<XhciTransferHandle, XhciTransferResult>,
+    /// Periodic time of each root port's USB 2.0 bus, keyed by slot;
+    /// SuperSpeed budgets are left to the xHC
+    bandwidth: BTreeMap<u8, BandwidthScheduler>,
+    events: Vec<UsbEvent>,
+    stats: UsbControllerStats,
 }
 
 impl XhciController {
     /// Create a new xHCI controller instance
+    ///
+    /// No register is touched until `initialize()`.
     pub fn new(base_address: u64) -> Self {
-        let mut controller = Self {
+        Self {
             base_address,
-            capability_params: XhciCapabilityParams {
-                usb_cap_length: 0,
-                hci_version: 0,
-                hcs_params1: 0,
-                hcs_params2: 0,
-                hcs_params3: 0,
-                hcc_params1: 0,
-                hcc_params2: 0,
-                doorbell_offset: 0,
-                rtors_base: 0,
-                hcc_params_ext: 0,
-            },
+            capability_params: XhciCapabilityParams::default(),
             state: XhciControllerState::Uninitialized,
-            device_context_array: core::ptr::null_mut(),
-            command_ring: None,
-            event_ring: None,
-            doorbell_array: core::ptr::null_mut(),
-            slots: BTreeMap::new(),
-            transfer_rings: BTreeMap::new(),
             ports: Vec::new(),
+            slots: BTreeMap::new(),
             interrupt_vector: 0,
             max_slots: 0,
             max_ports: 0,
             max_streams: 0,
             max_intr_interrupts: 0,
             extended_capabilities_offset: 0,
-        };
+            context_size: 32,
+            device_context_array: None,
+            scratchpad_array: None,
+            scratchpad_pages: Vec::new(),
+            command_ring: None,
+            event_ring: None,
+            command_completions: BTreeMap::new(),
+            inflight: BTreeMap::new(),
+            trb_owners: BTreeMap::new(),
+            completed: BTreeMap::new(),
+            bandwidth: BTreeMap::new(),
+            events: Vec::new(),
+            stats: UsbControllerStats {
+                total_transactions: 0,
+                successful_transactions: 0,
+                failed_transactions: 0,
+                bytes_transferred: 0,
+                error_count: 0,
+                last_error: None,
+            },
+        }
+    }
+
+    fn read32(&self, offset: usize) -> u32 {
+        unsafe { ptr::read_volatile((self.base_address as usize + offset) as *const u32) }
+    }
+
+    fn write32(&self, offset: usize, value: u32) {
+        unsafe { ptr::write_volatile((self.base_address as usize + offset) as *mut u32, value) }
+    }
+
+    fn write64(&self, offset: usize, value: u64) {
+        self.write32(offset, value as u32);
+        self.write32(offset + 4, (value >> 32) as u32);
+    }
+
+    fn op_read(&self, offset: usize) -> u32 {
+        self.read32(self.capability_params.usb_cap_length as usize + offset)
+    }
+
+    fn op_write(&self, offset: usize, value: u32) {
+        self.write32(self.capability_params.usb_cap_length as usize + offset, value)
+    }
+
+    fn op_write64(&self, offset: usize, value: u64) {
+        self.write64(self.capability_params.usb_cap_length as usize + offset, value)
+    }
+
+    fn interrupter_offset(&self, interrupter: usize, register: usize) -> usize {
+        self.capability_params.rtors_base as usize + XHCI_INTERRUPTER_BASE + interrupter * XHCI_INTERRUPTER_STRIDE + register
+    }
 
-        // Read capability parameters
-        controller.read_capability_params();
+    fn portsc_offset(&self, port_number: u8) -> usize {
+        self.capability_params.usb_cap_length as usize + XHCI_PORTSC_BASE + (port_number as usize - 1) * XHCI_PORT_STRIDE
+    }
 
-        controller
+    /// Spin until `(USBSTS & mask) == expected`
+    fn wait_status(&self, mask: u32, expected: u32) -> UsbResult<()> {
+        for _ in 0..XHCI_TIMEOUT_SPINS {
+            if self.op_read(XHCI_USBSTS) & mask == expected {
+                return Ok(());
+            }
+            core::hint::spin_loop();
+        }
+        Err(UsbDriverError::Timeout)
     }
 
     /// Read xHCI capability parameters from registers
     pub fn read_capability_params(&mut self) {
-        unsafe {
-            let cap_base = self.base_address as *const u32;
-            
-            self.capability_params.usb_cap_length = (*cap_base.offset(XHCI_CAP_LENGTH as isize)) as u8;
-            self.capability_params.hci_version = ((*cap_base.offset(XHCI_CAP_LENGTH as isize)) >> 16) as u16;
-            self.capability_params.hcs_params1 = *cap_base.offset(XHCI_HCSPARAMS1 as isize);
-            self.capability_params.hcs_params2 = *cap_base.offset(XHCI_HCSPARAMS2 as isize);
-            self.capability_params.hcs_params3 = *cap_base.offset(XHCI_HCSPARAMS3 as isize);
-            self.capability_params.hcc_params1 = *cap_base.offset(XHCI_HCCPARAMS1 as isize);
-            self.capability_params.hcc_params2 = *cap_base.offset(XHCI_HCCPARAMS2 as isize);
-            self.capability_params.doorbell_offset = *cap_base.offset(XHCI_DBOFF as isize);
-            self.capability_params.rtors_base = *cap_base.offset(XHCI_RTORS_BASE as isize);
-            self.capability_params.hcc_params_ext = *cap_base.offset(0x20); // Extended capability offset
-
-            // Extract important parameters
-            self.max_ports = (self.capability_params.hcs_params1 & 0xFF) as u8;
-            self.max_slots = ((self.capability_params.hcs_params1 >> 8) & 0xFF) as u8;
-            self.max_streams = ((self.capability_params.hcs_params1 >> 16) & 0x1F) as u8;
-            self.max_intr_interrupts = ((self.capability_params.hcs_params1 >> 8) & 0x1FF) as u8;
-
-            // Get extended capabilities offset
-            let hcc_params1 = self.capability_params.hcc_params1;
-            self.extended_capabilities_offset = ((hcc_params1 & 0xFF) << 2) & 0xFFFFFC;
-        }
+        let cap_header = self.read32(XHCI_CAPLENGTH);
+        self.capability_params = XhciCapabilityParams {
+            usb_cap_length: cap_header as u8,
+            hci_version: (cap_header >> (XHCI_HCIVERSION * 8)) as u16,
+            hcs_params1: self.read32(XHCI_HCSPARAMS1),
+            hcs_params2: self.read32(XHCI_HCSPARAMS2),
+            hcs_params3: self.read32(XHCI_HCSPARAMS3),
+            hcc_params1: self.read32(XHCI_HCCPARAMS1),
+            hcc_params2: self.read32(XHCI_HCCPARAMS2),
+            doorbell_offset: self.read32(XHCI_DBOFF) & !0x3,
+            rtors_base: self.read32(XHCI_RTSOFF) & !0x1F,
+        };
+
+        let hcs_params1 = self.capability_params.hcs_params1;
+        let hcc_params1 = self.capability_params.hcc_params1;
+        self.max_slots = hcs_params1 as u8;
+        self.max_intr_interrupts = ((hcs_params1 >> 8) & 0x7FF) as u16;
+        self.max_ports = (hcs_params1 >> 24) as u8;
+        self.max_streams = ((hcc_params1 >> 12) & 0xF) as u8;
+        self.context_size = if hcc_params1 & (1 << 2) != 0 { 64 } else { 32 };
+        self.extended_capabilities_offset = (hcc_params1 >> 16) << 2;
 
         log::info!("xHCI Controller initialized:");
         log::info!("  Max Ports: {}", self.max_ports);
@@ -343,16 +844,23 @@ impl XhciController {
     }
 
     /// Initialize xHCI controller
+    ///
+    /// Resets the controller, sets up the device context array, scratchpad,
+    /// command ring and the event ring of interrupter 0, then starts it.
     pub fn initialize(&mut self) -> UsbResult<()> {
-        if self.state == XhciControllerState::Initialized {
+        if self.state == XhciControllerState::Running {
             return Ok(());
         }
 
+        self.read_capability_params();
+        if self.max_slots == 0 || self.max_ports == 0 {
+            return Err(UsbDriverError::ControllerNotInitialized);
+        }
+
         // Reset the controller
         self.reset()?;
 
-        // Enable controller
-        self.enable()?;
+        self.op_write(XHCI_CONFIG, self.max_slots as u32);
 
         // Initialize device context array
         self.initialize_device_context_array()?;
@@ -363,207 +871,154 @@ impl XhciController {
         // Initialize event ring
         self.initialize_event_ring()?;
 
-        // Initialize doorbell array
-        self.initialize_doorbell_array()?;
+        self.state = XhciControllerState::Initialized;
+
+        // Enable controller
+        self.enable()?;
 
         // Discover ports
         self.discover_ports()?;
 
-        self.state = XhciControllerState::Initialized;
         log::info!("xHCI controller initialized successfully");
-
         Ok(())
     }
 
-    /// Reset the xHCI controller
+    /// Halt and reset the xHCI controller
     pub fn reset(&mut self) -> UsbResult<()> {
-        unsafe {
-            let cmd_reg = (self.base_address + 0x20) as *mut u32;
-            let status_reg = (self.base_address + 0x24) as *mut u32;
-
-            // Reset the controller
-            *cmd_reg = XHCI_CMD_HCRST;
-            
-            // Wait for reset to complete
-            for _ in 0..10000 {
-                let status = *status_reg;
-                if status & XHCI_STS_CNR == 0 {
-                    break;
-                }
-            }
-
-            // Check if reset was successful
-            let status = *status_reg;
-            if status & XHCI_STS_CNR != 0 {
-                return Err(UsbDriverError::ControllerNotInitialized);
+        self.wait_status(XHCI_STS_CNR, 0)?;
+
+        // The controller must be halted before it is reset
+        let cmd = self.op_read(XHCI_USBCMD);
+        self.op_write(XHCI_USBCMD, cmd & !XHCI_CMD_RS);
+        self.wait_status(XHCI_STS_HCH, XHCI_STS_HCH)?;
+
+        self.op_write(XHCI_USBCMD, XHCI_CMD_HCRST);
+        let mut reset_done = false;
+        for _ in 0..XHCI_TIMEOUT_SPINS {
+            if self.op_read(XHCI_USBCMD) & XHCI_CMD_HCRST == 0 {
+                reset_done = true;
+                break;
             }
-
-            self.state = XhciControllerState::Uninitialized;
+            core::hint::spin_loop();
+        }
+        if !reset_done {
+            return Err(UsbDriverError::Timeout);
         }
+        self.wait_status(XHCI_STS_CNR, 0)?;
+
+        // Everything the controller knew about is gone
+        self.abandon_transfers();
+        self.slots.clear();
+        self.bandwidth.clear();
+        self.command_completions.clear();
+        self.state = XhciControllerState::Uninitialized;
 
         log::info!("xHCI controller reset completed");
         Ok(())
     }
 
-    /// Enable the xHCI controller
+    /// Start the xHCI controller with interrupts enabled
     pub fn enable(&mut self) -> UsbResult<()> {
-        unsafe {
-            let cmd_reg = (self.base_address + 0x20) as *mut u32;
-            let mut cmd = *cmd_reg;
-            
-            // Set Run/Stop bit
-            cmd |= XHCI_CMD_RS;
-            *cmd_reg = cmd;
-
-            // Enable interrupts
-            cmd |= XHCI_CMD_INTE;
-            *cmd_reg = cmd;
-
-            // Wait for controller to start
-            for _ in 0..10000 {
-                let status = *(self.base_address + 0x24) as *const u32;
-                if status & XHCI_STS_HCH == 0 {
-                    break;
-                }
-            }
-
-            let status = *(self.base_address + 0x24) as *const u32;
-            if status & XHCI_STS_HCH != 0 {
-                return Err(UsbDriverError::ControllerNotInitialized);
-            }
-
-            self.state = XhciControllerState::Running;
+        if self.command_ring.is_none() || self.event_ring.is_none() {
+            return Err(UsbDriverError::ControllerNotInitialized);
         }
 
+        let cmd = self.op_read(XHCI_USBCMD);
+        self.op_write(XHCI_USBCMD, cmd | XHCI_CMD_RS | XHCI_CMD_INTE | XHCI_CMD_HSEE);
+        self.wait_status(XHCI_STS_HCH, 0)?;
+
+        self.state = XhciControllerState::Running;
         log::info!("xHCI controller enabled and running");
         Ok(())
     }
 
-    /// Initialize device context array
-    pub fn initialize_device_context_array(&mut self) -> UsbResult<()> {
-        let size = (self.max_slots as usize + 1) * mem::size_of::<u32>();
-        
-        unsafe {
-            self.device_context_array = {
-                let alloc = alloc::alloc::alloc(alloc::alloc::Layout::from_size_align(size, 64)?);
-                if alloc.is_null() {
-                    return Err(UsbDriverError::UnsupportedFeature);
-                }
-                alloc as *mut u32
-            };
+    /// Halt the controller
+    pub fn halt(&mut self) -> UsbResult<()> {
+        let cmd = self.op_read(XHCI_USBCMD);
+        self.op_write(XHCI_USBCMD, cmd & !(XHCI_CMD_RS | XHCI_CMD_INTE));
+        self.wait_status(XHCI_STS_HCH, XHCI_STS_HCH)?;
+        self.state = XhciControllerState::Initialized;
+        Ok(())
+    }
 
-            // Zero out the device context array
-            core::ptr::write_bytes(self.device_context_array, 0, size / mem::size_of::<u32>());
-        }
+    /// xHCI schedules every transfer type from its rings; nothing to enable
+    pub fn enable_periodic_schedule(&mut self) -> UsbResult<()> {
+        Ok(())
+    }
+
+    /// xHCI schedules every transfer type from its rings; nothing to enable
+    pub fn enable_async_schedule(&mut self) -> UsbResult<()> {
+        Ok(())
+    }
 
-        // Write DCBAAP register
-        unsafe {
-            let dcbaa_reg = (self.base_address + 0x8) as *mut u64;
-            *dcbaa_reg = self.device_context_array as u64;
+    /// Initialize the device context base address array and scratchpad
+    pub fn initialize_device_context_array(&mut self) -> UsbResult<()> {
+        let mut dcbaa = XhciDmaBuffer::new((self.max_slots as usize + 1) * mem::size_of::<u64>(), 64)?;
+
+        // Scratchpad buffers the controller asked for, referenced by entry 0
+        let hcs_params2 = self.capability_params.hcs_params2;
+        let scratchpads = ((((hcs_params2 >> 21) & 0x1F) << 5) | ((hcs_params2 >> 27) & 0x1F)) as usize;
+        self.scratchpad_pages.clear();
+        self.scratchpad_array = None;
+        if scratchpads > 0 {
+            let page_size = ((self.op_read(XHCI_PAGESIZE) & 0xFFFF).trailing_zeros() as usize + 12).min(27);
+            let page_size = 1usize << page_size;
+            let mut array = XhciDmaBuffer::new(scratchpads * mem::size_of::<u64>(), 64)?;
+            for index in 0..scratchpads {
+                let page = XhciDmaBuffer::new(page_size, page_size)?;
+                array.write_u64(index * mem::size_of::<u64>(), page.address());
+                self.scratchpad_pages.push(page);
+            }
+            dcbaa.write_u64(0, array.address());
+            self.scratchpad_array = Some(array);
         }
 
-        log::info!("Device context array initialized at {:#x}", self.device_context_array as u64);
+        self.op_write64(XHCI_DCBAAP, dcbaa.address());
+        log::info!("Device context array initialized at {:#x}", dcbaa.address());
+        self.device_context_array = Some(dcbaa);
         Ok(())
     }
 
     /// Initialize command ring
     pub fn initialize_command_ring(&mut self) -> UsbResult<()> {
-        let ring_size = 256; // 256 TRBs
-        let trb_size = mem::size_of::<u32>();
-        let total_size = ring_size * trb_size;
-
-        unsafe {
-            let ring_base = alloc::alloc::alloc(alloc::alloc::Layout::from_size_align(total_size, 64)?);
-            if ring_base.is_null() {
-                return Err(UsbDriverError::UnsupportedFeature);
-            }
-
-            let command_ring = XhciCommandRing {
-                base: ring_base as *mut u32,
-                enqueue_ptr: ring_base as *mut u32,
-                dequeue_ptr: ring_base as *mut u32,
-                ring_size,
-                cycle_state: true,
-            };
-
-            self.command_ring = Some(command_ring);
-
-            // Write CRCR register
-            let crcr_reg = (self.base_address + 0x4) as *mut u64;
-            let mut crcr_value = self.command_ring.as_ref().unwrap().base as u64;
-            crcr_value |= 1; // Ring cycle state bit
-            *crcr_reg = crcr_value;
-        }
+        let command_ring = XhciRing::new(XHCI_RING_TRBS)?;
+        self.op_write64(XHCI_CRCR, command_ring.dequeue_pointer());
+        self.command_ring = Some(command_ring);
 
         log::info!("Command ring initialized");
         Ok(())
     }
 
-    /// Initialize event ring
+    /// Initialize the event ring of interrupter 0
     pub fn initialize_event_ring(&mut self) -> UsbResult<()> {
-        let ring_size = 256; // 256 event TRBs
-        let trb_size = mem::size_of::<u32>();
-        let total_size = ring_size * trb_size;
-
-        unsafe {
-            let ring_base = alloc::alloc::alloc(alloc::alloc::Layout::from_size_align(total_size, 64)?);
-            if ring_base.is_null() {
-                return Err(UsbDriverError::UnsupportedFeature);
-            }
+        let event_ring = XhciEventRing::new(XHCI_EVENT_RING_TRBS)?;
 
-            let event_ring = XhciEventRing {
-                base: ring_base as *mut u32,
-                enqueue_ptr: ring_base as *mut u32,
-                dequeue_ptr: ring_base as *mut u32,
-                ring_size,
-                segment_size: 256,
-                producer_cycle_state: true,
-                pcs_supported: true,
-            };
-
-            self.event_ring = Some(event_ring);
-        }
+        self.write32(self.interrupter_offset(0, XHCI_ERSTSZ), 1);
+        self.write64(self.interrupter_offset(0, XHCI_ERDP), event_ring.dequeue_pointer());
+        // Writing ERSTBA enables the event ring, so it goes last
+        self.write64(self.interrupter_offset(0, XHCI_ERSTBA), event_ring.segment_table_address());
+        self.write32(self.interrupter_offset(0, XHCI_IMOD), XHCI_IMOD_INTERVAL);
+        self.write32(self.interrupter_offset(0, XHCI_IMAN), XHCI_IMAN_IP | XHCI_IMAN_IE);
+        self.event_ring = Some(event_ring);
 
         log::info!("Event ring initialized");
         Ok(())
     }
 
-    /// Initialize doorbell array
-    pub fn initialize_doorbell_array(&mut self) -> UsbResult<()> {
-        self.doorbell_array = unsafe {
-            let size = (self.max_slots as usize + 1) * mem::size_of::<u32>();
-            let alloc = alloc::alloc::alloc(alloc::alloc::Layout::from_size_align(size, 64)?);
-            if alloc.is_null() {
-                return Err(UsbDriverError::UnsupportedFeature);
-            }
-            core::ptr::write_bytes(alloc, 0, size / mem::size_of::<u32>());
-            alloc as *mut u32
-        };
-
-        log::info!("Doorbell array initialized");
-        Ok(())
-    }
-
     /// Discover and initialize ports
     pub fn discover_ports(&mut self) -> UsbResult<()> {
         self.ports.clear();
 
-        for port_num in 1..=self.max_ports {
-            let port = XhciPort {
-                port_number: port_num,
-                speed: UsbSpeed::High,
-                status: XhciPortStatus {
-                    portsc: 0,
-                    portpmsc: 0,
-                    portli: 0,
-                    porthlpmc: 0,
-                },
-                connection_status: false,
-                device_attached: false,
-                power_state: UsbPowerState::Active,
-            };
-
-            self.ports.push(port);
+        for port_number in 1..=self.max_ports {
+            let portsc = self.read32(self.portsc_offset(port_number));
+            self.ports.push(XhciPort {
+                port_number,
+                speed: port_speed((portsc >> XHCI_PORTSC_SPEED_SHIFT) & XHCI_PORTSC_SPEED_MASK),
+                status: XhciPortStatus { portsc, portpmsc: 0, portli: 0, porthlpmc: 0 },
+                connection_status: portsc & XHCI_PORTSC_CCS != 0,
+                device_attached: portsc & XHCI_PORTSC_CCS != 0,
+                power_state: if portsc & XHCI_PORTSC_PP != 0 { UsbPowerState::Active } else { UsbPowerState::Off },
+            });
         }
 
         log::info!("Discovered {} xHCI ports", self.ports.len());
@@ -576,137 +1031,1060 @@ impl XhciController {
             return Err(UsbDriverError::DeviceNotFound { address: port_number });
         }
 
-        unsafe {
-            let portsc_reg = (self.base_address + 0x400 + ((port_number as usize - 1) * 0x10)) as *const u32;
-            let portpmsc_reg = (self.base_address + 0x404 + ((port_number as usize - 1) * 0x10)) as *const u32;
-            let portli_reg = (self.base_address + 0x408 + ((port_number as usize - 1) * 0x10)) as *const u32;
-            let porthlpmc_reg = (self.base_address + 0x40C + ((port_number as usize - 1) * 0x10)) as *const u32;
+        let offset = self.portsc_offset(port_number);
+        let status = XhciPortStatus {
+            portsc: self.read32(offset),
+            portpmsc: self.read32(offset + 0x4),
+            portli: self.read32(offset + 0x8),
+            porthlpmc: self.read32(offset + 0xC),
+        };
 
-            let status = XhciPortStatus {
-                portsc: *portsc_reg,
-                portpmsc: *portpmsc_reg,
-                portli: *portli_reg,
-                porthlpmc: *porthlpmc_reg,
-            };
+        // Update port information
+        if let Some(port) = self.ports.get_mut(port_number as usize - 1) {
+            port.status = status;
+            port.connection_status = status.portsc & XHCI_PORTSC_CCS != 0;
+            port.device_attached = status.portsc & XHCI_PORTSC_CCS != 0;
+            port.speed = port_speed((status.portsc >> XHCI_PORTSC_SPEED_SHIFT) & XHCI_PORTSC_SPEED_MASK);
+        }
 
-            // Update port information
-            if port_number as usize <= self.ports.len() {
-                let port = &mut self.ports[port_number as usize - 1];
-                port.status = status;
-                port.connection_status = (status.portsc & 1) != 0;
-                port.device_attached = (status.portsc & (1 << 9)) != 0;
-            }
+        Ok(status)
+    }
 
-            Ok(status)
+    /// Write PORTSC, leaving the change bits not in `set` untouched
+    fn write_portsc(&self, port_number: u8, portsc: u32, set: u32) {
+        self.write32(self.portsc_offset(port_number), (portsc & XHCI_PORTSC_PRESERVE) | set);
+    }
+
+    /// Reset a port and return the speed the device came up at
+    pub fn reset_port(&mut self, port_number: u8) -> UsbResult<UsbSpeed> {
+        let portsc = self.get_port_status(port_number)?.portsc;
+        if portsc & XHCI_PORTSC_CCS == 0 {
+            return Err(UsbDriverError::DeviceNotFound { address: port_number });
+        }
+
+        self.write_portsc(port_number, portsc, XHCI_PORTSC_PR);
+        let offset = self.portsc_offset(port_number);
+        for _ in 0..XHCI_TIMEOUT_SPINS {
+            let portsc = self.read32(offset);
+            if portsc & XHCI_PORTSC_PRC != 0 {
+                self.write_portsc(port_number, portsc, XHCI_PORTSC_PRC);
+                if portsc & XHCI_PORTSC_PED == 0 {
+                    return Err(UsbDriverError::DeviceNotFound { address: port_number });
+                }
+                let speed = port_speed((portsc >> XHCI_PORTSC_SPEED_SHIFT) & XHCI_PORTSC_SPEED_MASK);
+                self.get_port_status(port_number)?;
+                return Ok(speed);
+            }
+            core::hint::spin_loop();
         }
+        Err(UsbDriverError::Timeout)
     }
 
-    /// Ring the doorbell for a device slot
+    /// Ring a doorbell: slot 0 with target 0 for the command ring, or a
+    /// device slot with the device context index of an endpoint
     pub fn ring_doorbell(&mut self, slot_id: u8, target: u32) -> UsbResult<()> {
-        unsafe {
-            if slot_id as usize > self.max_slots as usize {
-                return Err(UsbDriverError::DeviceNotFound { address: slot_id });
+        if slot_id > self.max_slots {
+            return Err(UsbDriverError::DeviceNotFound { address: slot_id });
+        }
+
+        fence(Ordering::SeqCst);
+        let offset = self.capability_params.doorbell_offset as usize + slot_id as usize * mem::size_of::<u32>();
+        self.write32(offset, target);
+        Ok(())
+    }
+
+    /// Queue a command TRB and ring the command doorbell
+    fn submit_command(&mut self, trb: UsbTRB) -> UsbResult<u64> {
+        if self.state != XhciControllerState::Running {
+            return Err(UsbDriverError::ControllerNotInitialized);
+        }
+        let ring = self.command_ring.as_mut().ok_or(UsbDriverError::ControllerNotInitialized)?;
+        let address = ring.push(trb)?;
+        self.ring_doorbell(0, 0)?;
+        Ok(address)
+    }
+
+    /// Run a command to completion
+    fn execute_command(&mut self, trb: UsbTRB) -> UsbResult<XhciCommandCompletion> {
+        let address = self.submit_command(trb)?;
+        for _ in 0..XHCI_TIMEOUT_SPINS {
+            self.poll_event_ring();
+            if let Some(completion) = self.command_completions.remove(&address) {
+                if completion.code != XHCI_CC_SUCCESS {
+                    log::warn!("xHCI command {} failed with completion code {}",
+                              (trb.control >> XHCI_TRB_TYPE_SHIFT) & 0x3F, completion.code);
+                    return Err(command_error(completion.code));
+                }
+                return Ok(completion);
             }
+            core::hint::spin_loop();
+        }
+        Err(UsbDriverError::Timeout)
+    }
 
-            let doorbell_offset = self.capability_params.doorbell_offset as usize;
-            let doorbell_addr = self.base_address as *mut u32;
-            let doorbell_ptr = doorbell_addr.add(doorbell_offset / mem::size_of::<u32>() + slot_id as usize);
-            
-            *doorbell_ptr = target;
+    /// Take every pending event off the event ring
+    fn poll_event_ring(&mut self) -> usize {
+        let mut handled = 0;
+        while let Some(event) = self.event_ring.as_mut().and_then(|ring| ring.pop()) {
+            self.handle_event(event);
+            handled += 1;
         }
+        if handled > 0 {
+            if let Some(ring) = &self.event_ring {
+                self.write64(self.interrupter_offset(0, XHCI_ERDP), ring.dequeue_pointer() | XHCI_ERDP_EHB);
+            }
+        }
+        handled
+    }
 
-        Ok(())
+    fn handle_event(&mut self, event: UsbTRB) {
+        match trb_type(&event) {
+            XHCI_TRB_TRANSFER_EVENT => self.handle_transfer_event(event),
+            XHCI_TRB_COMMAND_COMPLETION => {
+                let address = trb_pointer(&event);
+                if let Some(ring) = self.command_ring.as_mut() {
+                    ring.consumed(address);
+                }
+                self.command_completions.insert(address, XhciCommandCompletion {
+                    code: completion_code(&event),
+                    slot_id: (event.control >> XHCI_TRB_SLOT_SHIFT) as u8,
+                });
+            }
+            XHCI_TRB_PORT_STATUS_CHANGE => self.handle_port_change((event.ptr_low >> 24) as u8),
+            XHCI_TRB_HOST_CONTROLLER_EVENT => {
+                log::error!("xHCI host controller event, completion code {}", completion_code(&event));
+                self.stats.error_count += 1;
+                if self.op_read(XHCI_USBSTS) & (XHCI_STS_HSE | XHCI_STS_HCE) != 0 {
+                    self.state = XhciControllerState::Error;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_port_change(&mut self, port_number: u8) {
+        if port_number == 0 || port_number > self.max_ports {
+            return;
+        }
+        let portsc = self.read32(self.portsc_offset(port_number));
+        self.write_portsc(port_number, portsc, portsc & XHCI_PORTSC_CHANGE_MASK);
+        let _ = self.get_port_status(port_number);
+
+        if portsc & XHCI_PORTSC_CSC != 0 {
+            if portsc & XHCI_PORTSC_CCS != 0 {
+                let speed = port_speed((portsc >> XHCI_PORTSC_SPEED_SHIFT) & XHCI_PORTSC_SPEED_MASK);
+                self.events.push(UsbEvent::DeviceConnected { port: port_number, speed });
+            } else if let Some(slot) = self.slots.values().find(|slot| slot.port == port_number) {
+                self.events.push(UsbEvent::DeviceDisconnected { address: slot.address });
+            }
+        }
+    }
+
+    fn handle_transfer_event(&mut self, event: UsbTRB) {
+        let address = trb_pointer(&event);
+        let code = completion_code(&event);
+        let residue = (event.status & 0xFF_FFFF) as usize;
+        let slot_id = (event.control >> XHCI_TRB_SLOT_SHIFT) as u8;
+        let dci = ((event.control >> XHCI_TRB_ENDPOINT_SHIFT) & 0x1F) as u8;
+
+        if let Some(ring) = self.slots.get_mut(&slot_id).and_then(|slot| slot.rings.get_mut(&dci)) {
+            ring.consumed(address);
+        }
+
+        let handle = match self.trb_owners.get(&address) {
+            Some(&handle) => handle,
+            None => return,
+        };
+        let inflight = match self.inflight.get_mut(&handle) {
+            Some(inflight) => inflight,
+            None => return,
+        };
+        let (offset, length) = match inflight.trbs.iter().find(|trb| trb.0 == address) {
+            Some(&(_, offset, length)) => (offset, length as usize),
+            None => return,
+        };
+
+        let status = xhci_transfer_status(code);
+        let is_last = address == handle.0;
+        let finished = match status {
+            UsbTransferStatus::Success => is_last,
+            UsbTransferStatus::ShortPacket => {
+                inflight.actual = Some(offset + length - residue.min(length));
+                // A short data stage still runs the status stage
+                !inflight.control || is_last
+            }
+            _ => true,
+        };
+        if !finished {
+            return;
+        }
+
+        let inflight = self.inflight.remove(&handle).expect("inflight transfer");
+        for (trb, _, _) in &inflight.trbs {
+            self.trb_owners.remove(trb);
+        }
+        let actual = inflight.actual.unwrap_or(offset + length - residue.min(length));
+        let status = match status {
+            UsbTransferStatus::Success if inflight.actual.is_some() => UsbTransferStatus::ShortPacket,
+            status => status,
+        };
+        self.complete_transfer(handle, inflight, status, actual);
+    }
+
+    /// Record the outcome of a transfer and report it as an event
+    fn complete_transfer(&mut self, handle: XhciTransferHandle, inflight: XhciInflight, status: UsbTransferStatus, actual: usize) {
+        let mut data = Vec::new();
+        if inflight.direction_in {
+            if let Some(buffer) = &inflight.buffer {
+                data.resize(actual.min(buffer.len()), 0);
+                buffer.copy_to(0, &mut data);
+            }
+        }
+
+        let address = self.slots.get(&inflight.slot_id).map_or(0, |slot| slot.address);
+        let endpoint = xhci_dci_endpoint(inflight.dci);
+        self.stats.total_transactions += 1;
+        if matches!(status, UsbTransferStatus::Success | UsbTransferStatus::ShortPacket) {
+            self.stats.successful_transactions += 1;
+            self.stats.bytes_transferred += actual as u64;
+            self.events.push(UsbEvent::TransferCompleted { address, endpoint, status });
+        } else {
+            self.stats.failed_transactions += 1;
+            self.stats.error_count += 1;
+            self.stats.last_error = Some(format!("slot {} endpoint {:#04x}: {:?}", inflight.slot_id, endpoint, status));
+            self.events.push(UsbEvent::TransferError { address, endpoint, error: status });
+        }
+
+        self.completed.insert(handle, XhciTransferResult { status, actual_length: actual, data });
+    }
+
+    /// Drop every outstanding transfer of a slot, or of all slots
+    fn cancel_transfers(&mut self, slot_id: Option<u8>, dci: Option<u8>) {
+        let handles: Vec<XhciTransferHandle> = self.inflight.iter()
+            .filter(|(_, inflight)| slot_id.map_or(true, |slot| inflight.slot_id == slot))
+            .filter(|(_, inflight)| dci.map_or(true, |dci| inflight.dci == dci))
+            .map(|(&handle, _)| handle)
+            .collect();
+        for handle in handles {
+            let inflight = self.inflight.remove(&handle).expect("inflight transfer");
+            for (trb, _, _) in &inflight.trbs {
+                self.trb_owners.remove(trb);
+            }
+            self.complete_transfer(handle, inflight, UsbTransferStatus::Cancelled, 0);
+        }
+    }
+
+    fn abandon_transfers(&mut self) {
+        self.cancel_transfers(None, None);
     }
 
     /// Process event ring
+    ///
+    /// Returns the connection changes and transfer completions seen since
+    /// the last call.
     pub fn process_events(&mut self) -> UsbResult<Vec<UsbEvent>> {
-        let mut events = Vec::new();
+        self.poll_event_ring();
+        Ok(mem::take(&mut self.events))
+    }
 
-        // Implementation would process event ring and generate USB events
-        // For now, return empty vector
+    /// Service an interrupt from interrupter 0
+    pub fn handle_interrupt(&mut self) -> UsbResult<Vec<UsbEvent>> {
+        let iman_offset = self.interrupter_offset(0, XHCI_IMAN);
+        let iman = self.read32(iman_offset);
+        if iman & XHCI_IMAN_IP != 0 {
+            self.write32(iman_offset, iman | XHCI_IMAN_IP);
+        }
+        let status = self.op_read(XHCI_USBSTS);
+        if status & XHCI_STS_EINT != 0 {
+            self.op_write(XHCI_USBSTS, XHCI_STS_EINT);
+        }
+        if status & (XHCI_STS_HSE | XHCI_STS_HCE) != 0 {
+            self.state = XhciControllerState::Error;
+            return Err(UsbDriverError::ControllerNotInitialized);
+        }
+        self.process_events()
+    }
 
-        Ok(events)
+    fn dcbaa_mut(&mut self) -> UsbResult<&mut XhciDmaBuffer> {
+        self.device_context_array.as_mut().ok_or(UsbDriverError::ControllerNotInitialized)
+    }
+
+    /// Enable a device slot and return its ID
+    pub fn enable_slot(&mut self) -> UsbResult<u8> {
+        let completion = self.execute_command(make_trb(0, 0, XHCI_TRB_ENABLE_SLOT << XHCI_TRB_TYPE_SHIFT))?;
+        if completion.slot_id == 0 || completion.slot_id > self.max_slots {
+            return Err(UsbDriverError::ProtocolError);
+        }
+        Ok(completion.slot_id)
     }
 
-    /// Enable slot (initialize device context)
-    pub fn enable_slot(&mut self, slot_id: u8) -> UsbResult<()> {
-        if slot_id as usize > self.max_slots as usize {
+    /// Disable a device slot and release its contexts and rings
+    pub fn disable_slot(&mut self, slot_id: u8) -> UsbResult<()> {
+        if slot_id == 0 || slot_id > self.max_slots {
             return Err(UsbDriverError::DeviceNotFound { address: slot_id });
         }
 
-        self.ring_doorbell(slot_id, 1)?; // Target 1 = Enable Slot
-        Ok(())
+        let result = self.execute_command(make_trb(0, 0,
+            (XHCI_TRB_DISABLE_SLOT << XHCI_TRB_TYPE_SHIFT) | (slot_id as u32) << XHCI_TRB_SLOT_SHIFT));
+        self.cancel_transfers(Some(slot_id), None);
+        if let Some(slot) = self.slots.remove(&slot_id) {
+            // The next device on the port may run at another speed
+            if let Some(bandwidth) = self.bandwidth.get_mut(&slot.port) {
+                bandwidth.release_device(slot_id);
+                if bandwidth.reservations().is_empty() {
+                    self.bandwidth.remove(&slot.port);
+                }
+            }
+            self.dcbaa_mut()?.write_u64(slot_id as usize * mem::size_of::<u64>(), 0);
+        }
+        result.map(|_| ())
     }
 
-    /// Disable slot
-    pub fn disable_slot(&mut self, slot_id: u8) -> UsbResult<()> {
-        if slot_id as usize > self.max_slots as usize {
+    /// Set up the contexts of an enabled slot and address the device on
+    /// `port_number`; returns the address the controller assigned
+    pub fn address_device(&mut self, slot_id: u8, port_number: u8, speed: UsbSpeed) -> UsbResult<u8> {
+        if slot_id == 0 || slot_id > self.max_slots {
             return Err(UsbDriverError::DeviceNotFound { address: slot_id });
         }
+        let speed_id = (self.get_port_status(port_number)?.portsc >> XHCI_PORTSC_SPEED_SHIFT) & XHCI_PORTSC_SPEED_MASK;
+        let ctx = self.context_size;
+
+        let output_context = XhciDmaBuffer::new(32 * ctx, XHCI_PAGE_ALIGN)?;
+        let mut input_context = XhciDmaBuffer::new(33 * ctx, XHCI_PAGE_ALIGN)?;
+        let ep0_ring = XhciRing::new(XHCI_RING_TRBS)?;
+        let max_packet_size0 = default_max_packet_size0(speed);
+
+        // Add the slot context and EP0
+        input_context.write_u32(4, 0b11);
+        input_context.write_u32(ctx, speed_id << 20 | 1 << 27);
+        input_context.write_u32(ctx + 4, (port_number as u32) << 16);
+        write_endpoint_context(&mut input_context, 2 * ctx, XhciEndpointType::Control, max_packet_size0, 0, 0, ep0_ring.dequeue_pointer(), 8);
+
+        self.dcbaa_mut()?.write_u64(slot_id as usize * mem::size_of::<u64>(), output_context.address());
+
+        let mut rings = BTreeMap::new();
+        rings.insert(1, ep0_ring);
+        let input_address = input_context.address();
+        self.slots.insert(slot_id, XhciSlot {
+            slot_id,
+            port: port_number,
+            speed,
+            address: 0,
+            max_packet_size0,
+            speed_id,
+            output_context,
+            input_context,
+            rings,
+        });
+
+        let result = self.execute_command(make_trb(input_address, 0,
+            (XHCI_TRB_ADDRESS_DEVICE << XHCI_TRB_TYPE_SHIFT) | (slot_id as u32) << XHCI_TRB_SLOT_SHIFT));
+        if let Err(err) = result {
+            self.slots.remove(&slot_id);
+            self.dcbaa_mut()?.write_u64(slot_id as usize * mem::size_of::<u64>(), 0);
+            return Err(err);
+        }
 
-        self.ring_doorbell(slot_id, 2)?; // Target 2 = Disable Slot
+        let slot = self.slots.get_mut(&slot_id).expect("slot exists");
+        slot.address = slot.output_context.read_u32(12) as u8;
+        log::info!("xHCI slot {} on port {} addressed as device {}", slot_id, port_number, slot.address);
+        Ok(slot.address)
+    }
+
+    /// Update the control endpoint's max packet size after reading the
+    /// device descriptor
+    pub fn set_max_packet_size0(&mut self, slot_id: u8, max_packet_size: u16) -> UsbResult<()> {
+        let ctx = self.context_size;
+        let slot = self.slots.get_mut(&slot_id).ok_or(UsbDriverError::DeviceNotFound { address: slot_id })?;
+        if slot.max_packet_size0 == max_packet_size {
+            return Ok(());
+        }
+
+        let input = &mut slot.input_context;
+        input.clear(0, 33 * ctx);
+        input.write_u32(4, 1 << 1);
+        input.write_u32(2 * ctx + 4, (max_packet_size as u32) << 16);
+        let input_address = input.address();
+
+        self.execute_command(make_trb(input_address, 0,
+            (XHCI_TRB_EVALUATE_CONTEXT << XHCI_TRB_TYPE_SHIFT) | (slot_id as u32) << XHCI_TRB_SLOT_SHIFT))?;
+        if let Some(slot) = self.slots.get_mut(&slot_id) {
+            slot.max_packet_size0 = max_packet_size;
+        }
         Ok(())
     }
 
     /// Configure endpoint
-    pub fn configure_endpoint(&mut self, slot_id: u8, endpoint_id: u8) -> UsbResult<()> {
-        if slot_id as usize > self.max_slots as usize {
+    pub fn configure_endpoint(&mut self, slot_id: u8, endpoint: &UsbEndpointDescriptor) -> UsbResult<u8> {
+        self.configure_endpoints(slot_id, core::slice::from_ref(endpoint))?;
+        Ok(xhci_endpoint_dci(endpoint.bEndpointAddress))
+    }
+
+    /// Add the endpoints of a configuration to a slot with one Configure
+    /// Endpoint command
+    ///
+    /// Periodic endpoints of USB 2.0 devices are checked against their
+    /// port's budget before the command is issued.
+    pub fn configure_endpoints(&mut self, slot_id: u8, endpoints: &[UsbEndpointDescriptor]) -> UsbResult<()> {
+        let ctx = self.context_size;
+        let slot = self.slots.get_mut(&slot_id).ok_or(UsbDriverError::DeviceNotFound { address: slot_id })?;
+
+        let (port, speed) = (slot.port, slot.speed);
+        let mut saved_bandwidth = None;
+        if matches!(speed, UsbSpeed::Low | UsbSpeed::Full | UsbSpeed::High) {
+            let requests: Vec<_> = endpoints
+                .iter()
+                .filter_map(|endpoint| BandwidthRequest::from_endpoint(slot_id, speed, None, endpoint))
+                .collect();
+            let bandwidth = self.bandwidth.entry(port).or_insert_with(|| BandwidthScheduler::new(speed));
+            saved_bandwidth = Some(bandwidth.clone());
+            bandwidth.reserve_all(&requests)?;
+        }
+        let result = self.configure_slot_endpoints(slot_id, endpoints, ctx);
+        if let (Err(_), Some(saved)) = (&result, saved_bandwidth) {
+            self.bandwidth.insert(port, saved);
+        }
+        result
+    }
+
+    fn configure_slot_endpoints(&mut self, slot_id: u8, endpoints: &[UsbEndpointDescriptor], ctx: usize) -> UsbResult<()> {
+        let slot = self.slots.get_mut(&slot_id).ok_or(UsbDriverError::DeviceNotFound { address: slot_id })?;
+
+        let mut rings = Vec::new();
+        let mut add_flags = 1u32;
+        let mut last_dci = (slot.output_context.read_u32(0) >> 27) as u8;
+        slot.input_context.clear(0, 33 * ctx);
+
+        for endpoint in endpoints {
+            let dci = xhci_endpoint_dci(endpoint.bEndpointAddress);
+            if dci < 2 {
+                return Err(UsbDriverError::InvalidConfiguration);
+            }
+            let transfer_type = endpoint_transfer_type(endpoint);
+            let ep_type = XhciEndpointType::from_transfer(transfer_type, endpoint.bEndpointAddress & 0x80 != 0);
+            let max_packet = endpoint.wMaxPacketSize & 0x7FF;
+            let max_burst = match (slot.speed, transfer_type) {
+                (UsbSpeed::High, UsbTransferType::Isochronous) | (UsbSpeed::High, UsbTransferType::Interrupt) => {
+                    ((endpoint.wMaxPacketSize >> 11) & 0x3) as u8
+                }
+                _ => 0,
+            };
+            let interval = xhci_endpoint_interval(slot.speed, transfer_type, endpoint.bInterval);
+            let average_trb_length = match transfer_type {
+                UsbTransferType::Control => 8,
+                UsbTransferType::Bulk => 3072,
+                _ => max_packet * (max_burst as u16 + 1),
+            };
+
+            let ring = XhciRing::new(XHCI_RING_TRBS)?;
+            write_endpoint_context(&mut slot.input_context, (dci as usize + 1) * ctx, ep_type, max_packet, max_burst, interval, ring.dequeue_pointer(), average_trb_length);
+            add_flags |= 1 << dci;
+            last_dci = last_dci.max(dci);
+            rings.push((dci, ring));
+        }
+
+        // The slot context carries the new last valid endpoint
+        let slot_dw0 = slot.output_context.read_u32(0);
+        let slot_dw1 = slot.output_context.read_u32(4);
+        let slot_dw2 = slot.output_context.read_u32(8);
+        slot.input_context.write_u32(4, add_flags);
+        slot.input_context.write_u32(ctx, (slot_dw0 & !(0x1F << 27)) | (last_dci as u32) << 27);
+        slot.input_context.write_u32(ctx + 4, slot_dw1);
+        slot.input_context.write_u32(ctx + 8, slot_dw2);
+        let input_address = slot.input_context.address();
+
+        self.execute_command(make_trb(input_address, 0,
+            (XHCI_TRB_CONFIGURE_ENDPOINT << XHCI_TRB_TYPE_SHIFT) | (slot_id as u32) << XHCI_TRB_SLOT_SHIFT))?;
+
+        let slot = self.slots.get_mut(&slot_id).ok_or(UsbDriverError::DeviceNotFound { address: slot_id })?;
+        for (dci, ring) in rings {
+            slot.rings.insert(dci, ring);
+        }
+        Ok(())
+    }
+
+    /// Periodic load of a root port's USB 2.0 bus
+    pub fn bandwidth_usage(&self, port: u8) -> Option<BandwidthUsage> {
+        self.bandwidth.get(&port).map(BandwidthScheduler::usage)
+    }
+
+    /// Clear a halted endpoint and move its ring past the failed transfer
+    pub fn reset_endpoint(&mut self, slot_id: u8, endpoint: u8) -> UsbResult<()> {
+        let dci = xhci_endpoint_dci(endpoint);
+        let target = (slot_id as u32) << XHCI_TRB_SLOT_SHIFT | (dci as u32) << XHCI_TRB_ENDPOINT_SHIFT;
+        if !self.slots.get(&slot_id).map_or(false, |slot| slot.rings.contains_key(&dci)) {
             return Err(UsbDriverError::DeviceNotFound { address: slot_id });
         }
 
-        self.ring_doorbell(slot_id, endpoint_id + 1)?;
+        self.execute_command(make_trb(0, 0, (XHCI_TRB_RESET_ENDPOINT << XHCI_TRB_TYPE_SHIFT) | target))?;
+
+        let ring = self.slots.get_mut(&slot_id)
+            .and_then(|slot| slot.rings.get_mut(&dci))
+            .ok_or(UsbDriverError::DeviceNotFound { address: slot_id })?;
+        let dequeue = ring.dequeue_pointer();
+        ring.skip_to_enqueue();
+        self.cancel_transfers(Some(slot_id), Some(dci));
+
+        self.execute_command(make_trb(dequeue, 0, (XHCI_TRB_SET_TR_DEQUEUE << XHCI_TRB_TYPE_SHIFT) | target))?;
         Ok(())
     }
 
+    /// Queue a control transfer on EP0
+    ///
+    /// For OUT requests `data` is sent; for IN requests up to `wLength`
+    /// bytes are received and returned by `take_transfer()`.
+    pub fn submit_control(&mut self, slot_id: u8, setup: &UsbSetupPacket, data: &[u8]) -> UsbResult<XhciTransferHandle> {
+        let direction_in = setup.bmRequestType & 0x80 != 0;
+        let length = setup.wLength as usize;
+        if !direction_in && data.len() < length {
+            return Err(UsbDriverError::InvalidConfiguration);
+        }
+
+        let buffer = if length > 0 {
+            let mut buffer = XhciDmaBuffer::new(length, 64)?;
+            if !direction_in {
+                buffer.copy_from(0, &data[..length]);
+            }
+            Some(buffer)
+        } else {
+            None
+        };
+
+        let slot = self.slots.get_mut(&slot_id).ok_or(UsbDriverError::DeviceNotFound { address: slot_id })?;
+        let ring = slot.rings.get_mut(&1).ok_or(UsbDriverError::DeviceNotFound { address: slot_id })?;
+        if ring.free_trbs() < 3 {
+            return Err(UsbDriverError::TransferFailed { status: UsbTransferStatus::BufferOverrun });
+        }
+
+        let mut trbs = Vec::new();
+        trbs.push((ring.push(setup_trb(setup))?, 0, 0));
+        if let Some(buffer) = &buffer {
+            let mut control = (XHCI_TRB_DATA << XHCI_TRB_TYPE_SHIFT) | XHCI_TRB_ISP;
+            if direction_in {
+                control |= XHCI_TRB_DIR_IN;
+            }
+            trbs.push((ring.push(make_trb(buffer.address(), length as u32, control))?, 0, length as u32));
+        }
+        // The status stage runs opposite to the data stage, IN without one
+        let mut control = (XHCI_TRB_STATUS << XHCI_TRB_TYPE_SHIFT) | XHCI_TRB_IOC;
+        if length == 0 || !direction_in {
+            control |= XHCI_TRB_DIR_IN;
+        }
+        let last = ring.push(make_trb(0, 0, control))?;
+        trbs.push((last, length, 0));
+
+        let handle = XhciTransferHandle(last);
+        self.track(handle, XhciInflight { slot_id, dci: 1, control: true, direction_in, buffer, trbs, actual: None });
+        self.ring_doorbell(slot_id, 1)?;
+        Ok(handle)
+    }
+
+    /// Queue a bulk or interrupt transfer
+    ///
+    /// The direction comes from `endpoint`. OUT transfers send `data`; IN
+    /// transfers receive up to `data.len()` bytes, returned by
+    /// `take_transfer()`.
+    pub fn submit_transfer(&mut self, slot_id: u8, endpoint: u8, data: &[u8]) -> UsbResult<XhciTransferHandle> {
+        let direction_in = endpoint & 0x80 != 0;
+        let dci = xhci_endpoint_dci(endpoint);
+        if dci < 2 {
+            return Err(UsbDriverError::InvalidConfiguration);
+        }
+
+        let mut buffer = XhciDmaBuffer::new(data.len(), XHCI_PAGE_ALIGN)?;
+        if !direction_in {
+            buffer.copy_from(0, data);
+        }
+
+        let slot = self.slots.get_mut(&slot_id).ok_or(UsbDriverError::DeviceNotFound { address: slot_id })?;
+        let ring = slot.rings.get_mut(&dci).ok_or(UsbDriverError::InvalidConfiguration)?;
+
+        // Page-aligned buffers split at 64 KiB never cross a 64 KiB boundary
+        let chunks = data.len().div_ceil(XHCI_TRB_MAX_LENGTH).max(1);
+        if ring.free_trbs() < chunks {
+            return Err(UsbDriverError::TransferFailed { status: UsbTransferStatus::BufferOverrun });
+        }
+
+        let mut trbs = Vec::new();
+        for chunk in 0..chunks {
+            let offset = chunk * XHCI_TRB_MAX_LENGTH;
+            let length = (data.len() - offset).min(XHCI_TRB_MAX_LENGTH);
+            let remaining_packets = (chunks - chunk - 1).min(31) as u32;
+            let mut control = (XHCI_TRB_NORMAL << XHCI_TRB_TYPE_SHIFT) | XHCI_TRB_ISP;
+            control |= if chunk + 1 == chunks { XHCI_TRB_IOC } else { XHCI_TRB_CHAIN };
+            let trb = make_trb(buffer.address() + offset as u64, length as u32 | remaining_packets << 17, control);
+            trbs.push((ring.push(trb)?, offset, length as u32));
+        }
+
+        let handle = XhciTransferHandle(trbs[trbs.len() - 1].0);
+        self.track(handle, XhciInflight { slot_id, dci, control: false, direction_in, buffer: Some(buffer), trbs, actual: None });
+        self.ring_doorbell(slot_id, dci as u32)?;
+        Ok(handle)
+    }
+
+    /// Queue one isochronous TD, the data of one service interval
+    ///
+    /// `frame` schedules the TD for a 1 ms frame (the low 11 bits of
+    /// `current_frame()`); `None` runs it in the interval after the TDs
+    /// already queued. IN transfers receive up to `data.len()` bytes,
+    /// returned by `take_transfer()`.
+    pub fn submit_isoch(&mut self, slot_id: u8, endpoint: u8, data: &[u8], frame: Option<u16>) -> UsbResult<XhciTransferHandle> {
+        let direction_in = endpoint & 0x80 != 0;
+        let expected = if direction_in { XhciEndpointType::IsochronousIn } else { XhciEndpointType::IsochronousOut };
+        if self.endpoint_type(slot_id, endpoint) != Some(expected) {
+            return Err(UsbDriverError::InvalidConfiguration);
+        }
+        if data.is_empty() || data.len() > XHCI_TRB_MAX_LENGTH {
+            return Err(UsbDriverError::InvalidConfiguration);
+        }
+
+        let dci = xhci_endpoint_dci(endpoint);
+        let ctx = self.context_size;
+        let mut buffer = XhciDmaBuffer::new(data.len(), XHCI_PAGE_ALIGN)?;
+        if !direction_in {
+            buffer.copy_from(0, data);
+        }
+
+        let slot = self.slots.get_mut(&slot_id).ok_or(UsbDriverError::DeviceNotFound { address: slot_id })?;
+        let dw1 = slot.output_context.read_u32(dci as usize * ctx + 4);
+        let max_packet = ((dw1 >> 16) as usize).max(1);
+        let burst = ((dw1 >> 8) & 0xFF) as usize + 1;
+        let packets = data.len().div_ceil(max_packet);
+        let burst_count = (packets.div_ceil(burst) - 1) as u32;
+        let last_burst_packets = ((packets - 1) % burst) as u32;
+
+        let ring = slot.rings.get_mut(&dci).ok_or(UsbDriverError::InvalidConfiguration)?;
+        if ring.free_trbs() < 1 {
+            return Err(UsbDriverError::TransferFailed { status: UsbTransferStatus::BufferOverrun });
+        }
+        let mut control = (XHCI_TRB_ISOCH << XHCI_TRB_TYPE_SHIFT) | XHCI_TRB_ISP | XHCI_TRB_IOC
+            | burst_count << XHCI_TRB_TBC_SHIFT
+            | last_burst_packets << XHCI_TRB_TLBPC_SHIFT;
+        control |= match frame {
+            Some(frame) => (frame as u32 & 0x7FF) << XHCI_TRB_FRAME_ID_SHIFT,
+            None => XHCI_TRB_SIA,
+        };
+        let address = ring.push(make_trb(buffer.address(), data.len() as u32, control))?;
+
+        let handle = XhciTransferHandle(address);
+        let trbs = vec![(address, 0, data.len() as u32)];
+        self.track(handle, XhciInflight { slot_id, dci, control: false, direction_in, buffer: Some(buffer), trbs, actual: None });
+        self.ring_doorbell(slot_id, dci as u32)?;
+        Ok(handle)
+    }
+
+    /// Current 1 ms frame from the microframe index register
+    pub fn current_frame(&self) -> u16 {
+        let mfindex = self.read32(self.capability_params.rtors_base as usize + XHCI_MFINDEX);
+        ((mfindex & 0x3FFF) >> 3) as u16
+    }
+
+    /// Consume pending events so finished transfers can be taken, leaving
+    /// the events they report for `process_events()`
+    pub fn poll_transfers(&mut self) -> usize {
+        self.poll_event_ring()
+    }
+
+    fn track(&mut self, handle: XhciTransferHandle, inflight: XhciInflight) {
+        for (trb, _, _) in &inflight.trbs {
+            self.trb_owners.insert(*trb, handle);
+        }
+        self.inflight.insert(handle, inflight);
+    }
+
+    /// Take the result of a completed transfer
+    pub fn take_transfer(&mut self, handle: XhciTransferHandle) -> Option<XhciTransferResult> {
+        self.completed.remove(&handle)
+    }
+
+    /// Wait for a transfer, clearing the endpoint halt if it stalled
+    fn wait_transfer(&mut self, slot_id: u8, endpoint: u8, handle: XhciTransferHandle) -> UsbResult<XhciTransferResult> {
+        for _ in 0..XHCI_TIMEOUT_SPINS {
+            self.poll_event_ring();
+            if let Some(result) = self.completed.remove(&handle) {
+                return match result.status {
+                    UsbTransferStatus::Success | UsbTransferStatus::ShortPacket => Ok(result),
+                    UsbTransferStatus::Stalled => {
+                        self.reset_endpoint(slot_id, endpoint)?;
+                        Err(UsbDriverError::TransferFailed { status: UsbTransferStatus::Stalled })
+                    }
+                    status => Err(UsbDriverError::TransferFailed { status }),
+                };
+            }
+            core::hint::spin_loop();
+        }
+        Err(UsbDriverError::Timeout)
+    }
+
+    /// Perform a control transfer and return the number of bytes moved
+    pub fn control_transfer(&mut self, slot_id: u8, setup: &UsbSetupPacket, data: &mut [u8]) -> UsbResult<usize> {
+        if data.len() < setup.wLength as usize {
+            return Err(UsbDriverError::InvalidConfiguration);
+        }
+        let handle = self.submit_control(slot_id, setup, data)?;
+        let result = self.wait_transfer(slot_id, 0, handle)?;
+        if setup.bmRequestType & 0x80 != 0 {
+            data[..result.data.len()].copy_from_slice(&result.data);
+        }
+        Ok(result.actual_length)
+    }
+
+    /// Perform a bulk transfer and return the number of bytes moved
+    pub fn bulk_transfer(&mut self, slot_id: u8, endpoint: u8, data: &mut [u8]) -> UsbResult<usize> {
+        self.normal_transfer(slot_id, endpoint, data, XhciEndpointType::BulkOut, XhciEndpointType::BulkIn)
+    }
+
+    /// Perform an interrupt transfer and return the number of bytes moved
+    pub fn interrupt_transfer(&mut self, slot_id: u8, endpoint: u8, data: &mut [u8]) -> UsbResult<usize> {
+        self.normal_transfer(slot_id, endpoint, data, XhciEndpointType::InterruptOut, XhciEndpointType::InterruptIn)
+    }
+
+    fn normal_transfer(&mut self, slot_id: u8, endpoint: u8, data: &mut [u8], out_type: XhciEndpointType, in_type: XhciEndpointType) -> UsbResult<usize> {
+        let expected = if endpoint & 0x80 != 0 { in_type } else { out_type };
+        if self.endpoint_type(slot_id, endpoint) != Some(expected) {
+            return Err(UsbDriverError::InvalidConfiguration);
+        }
+        let handle = self.submit_transfer(slot_id, endpoint, data)?;
+        let result = self.wait_transfer(slot_id, endpoint, handle)?;
+        if endpoint & 0x80 != 0 {
+            data[..result.data.len()].copy_from_slice(&result.data);
+        }
+        Ok(result.actual_length)
+    }
+
+    /// Endpoint type configured in the controller's output context
+    pub fn endpoint_type(&self, slot_id: u8, endpoint: u8) -> Option<XhciEndpointType> {
+        let slot = self.slots.get(&slot_id)?;
+        let dci = xhci_endpoint_dci(endpoint);
+        if !slot.rings.contains_key(&dci) {
+            return None;
+        }
+        let dw1 = slot.output_context.read_u32(dci as usize * self.context_size + 4);
+        Some(match (dw1 >> 3) & 0x7 {
+            1 => XhciEndpointType::IsochronousOut,
+            2 => XhciEndpointType::BulkOut,
+            3 => XhciEndpointType::InterruptOut,
+            4 => XhciEndpointType::Control,
+            5 => XhciEndpointType::IsochronousIn,
+            6 => XhciEndpointType::BulkIn,
+            7 => XhciEndpointType::InterruptIn,
+            _ => XhciEndpointType::NotConfigured,
+        })
+    }
+
+    /// Slot of the device on a root hub port
+    pub fn slot_for_port(&self, port_number: u8) -> Option<u8> {
+        self.slots.values().find(|slot| slot.port == port_number).map(|slot| slot.slot_id)
+    }
+
+    /// Slot of the device with a USB address
+    pub fn slot_for_address(&self, address: u8) -> Option<u8> {
+        self.slots.values().find(|slot| slot.address == address).map(|slot| slot.slot_id)
+    }
+
+    /// Reset the device on a port, address it and read its device
+    /// descriptor
+    pub fn enumerate_port(&mut self, port_number: u8) -> UsbResult<UsbDevice> {
+        let speed = self.reset_port(port_number)?;
+        let slot_id = self.enable_slot()?;
+
+        match self.address_and_describe(slot_id, port_number, speed) {
+            Ok(device) => Ok(device),
+            Err(err) => {
+                let _ = self.disable_slot(slot_id);
+                Err(err)
+            }
+        }
+    }
+
+    fn address_and_describe(&mut self, slot_id: u8, port_number: u8, speed: UsbSpeed) -> UsbResult<UsbDevice> {
+        let address = self.address_device(slot_id, port_number, speed)?;
+
+        // The first 8 bytes hold bMaxPacketSize0, which full-speed devices
+        // may set to anything from 8 to 64
+        let mut descriptor = [0u8; 18];
+        self.control_transfer(slot_id, &get_device_descriptor(8), &mut descriptor[..8])?;
+        let max_packet_size0 = match speed {
+            UsbSpeed::Super | UsbSpeed::SuperPlus => 1u16 << descriptor[7].min(9),
+            _ => descriptor[7] as u16,
+        };
+        if max_packet_size0 >= 8 {
+            self.set_max_packet_size0(slot_id, max_packet_size0)?;
+        }
+
+        let length = self.control_transfer(slot_id, &get_device_descriptor(18), &mut descriptor)?;
+        if length < descriptor.len() {
+            return Err(UsbDriverError::ProtocolError);
+        }
+        let descriptor = parse_device_descriptor(&descriptor);
+
+        log::info!("xHCI port {}: device {:04x}:{:04x} at address {}",
+                  port_number, descriptor.idVendor, descriptor.idProduct, address);
+        Ok(addressed_device(address, speed, descriptor))
+    }
+
     /// Get controller statistics
     pub fn get_stats(&self) -> UsbControllerStats {
-        UsbControllerStats {
-            total_transactions: 0,
-            successful_transactions: 0,
-            failed_transactions: 0,
-            bytes_transferred: 0,
-            error_count: 0,
-            last_error: None,
+        self.stats.clone()
+    }
+}
+
+/// Root ports driven by the hotplug attach sequence
+///
+/// The controller chooses device addresses itself, so `set_address()`
+/// enables a slot and returns the address from the slot context.
+impl UsbPortHost for XhciController {
+    fn port_status(&mut self, port: u8) -> UsbResult<u32> {
+        let portsc = self.get_port_status(port)?.portsc;
+        let mut status = 0;
+        if portsc & XHCI_PORTSC_CCS != 0 {
+            status |= USB_PORT_STAT_CONNECTION;
+        }
+        if portsc & XHCI_PORTSC_PED != 0 {
+            status |= USB_PORT_STAT_ENABLE;
+        }
+        if portsc & XHCI_PORTSC_OCA != 0 {
+            status |= USB_PORT_STAT_OVERCURRENT;
+        }
+        if portsc & XHCI_PORTSC_PR != 0 {
+            status |= USB_PORT_STAT_RESET;
+        }
+        if portsc & XHCI_PORTSC_PP != 0 {
+            status |= USB_PORT_STAT_POWER;
+        }
+        if (portsc >> XHCI_PORTSC_PLS_SHIFT) & XHCI_PORTSC_PLS_MASK == XHCI_PLS_U3 {
+            status |= USB_PORT_STAT_SUSPEND;
+        }
+        if portsc & XHCI_PORTSC_CCS != 0 {
+            status |= match port_speed((portsc >> XHCI_PORTSC_SPEED_SHIFT) & XHCI_PORTSC_SPEED_MASK) {
+                UsbSpeed::Low => USB_PORT_STAT_LOW_SPEED,
+                UsbSpeed::High => USB_PORT_STAT_HIGH_SPEED,
+                UsbSpeed::Super | UsbSpeed::SuperPlus => USB_PORT_STAT_SUPER_SPEED,
+                UsbSpeed::Full => 0,
+            };
+        }
+        Ok(status)
+    }
+
+    fn start_port_reset(&mut self, port: u8) -> UsbResult<()> {
+        let portsc = self.get_port_status(port)?.portsc;
+        if portsc & XHCI_PORTSC_CCS == 0 {
+            return Err(UsbDriverError::DeviceNotFound { address: port });
+        }
+        // Clear a stale reset change so the next one is this reset's
+        self.write_portsc(port, portsc, XHCI_PORTSC_PR | XHCI_PORTSC_PRC);
+        Ok(())
+    }
+
+    fn disable_port(&mut self, port: u8) -> UsbResult<()> {
+        let portsc = self.get_port_status(port)?.portsc;
+        // PED is cleared by writing one
+        self.write_portsc(port, portsc, XHCI_PORTSC_PED);
+        Ok(())
+    }
+
+    fn set_address(&mut self, port: u8, speed: UsbSpeed, _address: u8) -> UsbResult<u8> {
+        let slot_id = self.enable_slot()?;
+        self.address_device(slot_id, port, speed).map_err(|err| {
+            let _ = self.disable_slot(slot_id);
+            err
+        })
+    }
+
+    fn control_transfer(&mut self, address: u8, setup: &UsbSetupPacket, data: &mut [u8]) -> UsbResult<usize> {
+        let slot_id = self.slot_for_address(address).ok_or(UsbDriverError::DeviceNotFound { address })?;
+        XhciController::control_transfer(self, slot_id, setup, data)
+    }
+
+    fn set_max_packet_size0(&mut self, address: u8, max_packet_size: u16) -> UsbResult<()> {
+        let slot_id = self.slot_for_address(address).ok_or(UsbDriverError::DeviceNotFound { address })?;
+        XhciController::set_max_packet_size0(self, slot_id, max_packet_size)
+    }
+
+    fn configure_endpoints(&mut self, address: u8, endpoints: &[UsbEndpointDescriptor]) -> UsbResult<()> {
+        let slot_id = self.slot_for_address(address).ok_or(UsbDriverError::DeviceNotFound { address })?;
+        XhciController::configure_endpoints(self, slot_id, endpoints)
+    }
+
+    fn release_address(&mut self, address: u8) -> UsbResult<()> {
+        match self.slot_for_address(address) {
+            Some(slot_id) => self.disable_slot(slot_id),
+            None => Ok(()),
         }
     }
 }
 
+/// Link power management on root ports
+///
+/// Suspend and resume are software-directed link state writes. U1/U2 and
+/// L1 are entered by the port itself once the link idles, so those states
+/// program the PORTPMSC inactivity timeouts or hardware LPM enable.
+impl UsbPowerHost for XhciController {
+    fn set_link_state(&mut self, port: u8, state: UsbLinkPowerState) -> UsbResult<()> {
+        let status = self.get_port_status(port)?;
+        if status.portsc & XHCI_PORTSC_PED == 0 {
+            return Err(UsbDriverError::DeviceNotFound { address: port });
+        }
+        let pmsc_offset = self.portsc_offset(port) + 0x4;
+
+        match state {
+            UsbLinkPowerState::U0 | UsbLinkPowerState::L0 => {
+                let pmsc = if state == UsbLinkPowerState::U0 {
+                    status.portpmsc & !(XHCI_PORTPMSC_U1_TIMEOUT_MASK | XHCI_PORTPMSC_U2_TIMEOUT_MASK)
+                } else {
+                    status.portpmsc & !XHCI_PORTPMSC_HLE
+                };
+                self.write32(pmsc_offset, pmsc);
+                if (status.portsc >> XHCI_PORTSC_PLS_SHIFT) & XHCI_PORTSC_PLS_MASK != XHCI_PLS_U0 {
+                    self.write_portsc(port, status.portsc, XHCI_PORTSC_LWS | (XHCI_PLS_U0 << XHCI_PORTSC_PLS_SHIFT));
+                }
+            }
+            UsbLinkPowerState::U3 | UsbLinkPowerState::L2 => {
+                self.write_portsc(port, status.portsc, XHCI_PORTSC_LWS | (XHCI_PLS_U3 << XHCI_PORTSC_PLS_SHIFT));
+            }
+            UsbLinkPowerState::U1 => {
+                let pmsc = (status.portpmsc & !XHCI_PORTPMSC_U1_TIMEOUT_MASK) | XHCI_U1_TIMEOUT_US;
+                self.write32(pmsc_offset, pmsc);
+            }
+            UsbLinkPowerState::U2 => {
+                let pmsc = (status.portpmsc & !XHCI_PORTPMSC_U2_TIMEOUT_MASK)
+                    | (XHCI_U2_TIMEOUT_UNITS << XHCI_PORTPMSC_U2_TIMEOUT_SHIFT);
+                self.write32(pmsc_offset, pmsc);
+            }
+            UsbLinkPowerState::L1 => {
+                self.write32(pmsc_offset, status.portpmsc | XHCI_PORTPMSC_HLE);
+            }
+        }
+        Ok(())
+    }
+
+    fn control_transfer(&mut self, address: u8, setup: &UsbSetupPacket, data: &mut [u8]) -> UsbResult<usize> {
+        let slot_id = self.slot_for_address(address).ok_or(UsbDriverError::DeviceNotFound { address })?;
+        XhciController::control_transfer(self, slot_id, setup, data)
+    }
+}
+
+/// Fill the endpoint context at `offset` of a context buffer
+#[allow(clippy::too_many_arguments)]
+fn write_endpoint_context(
+    context: &mut XhciDmaBuffer,
+    offset: usize,
+    ep_type: XhciEndpointType,
+    max_packet_size: u16,
+    max_burst: u8,
+    interval: u8,
+    dequeue_pointer: u64,
+    average_trb_length: u16,
+) {
+    let periodic = matches!(ep_type,
+        XhciEndpointType::IsochronousOut | XhciEndpointType::IsochronousIn
+            | XhciEndpointType::InterruptOut | XhciEndpointType::InterruptIn);
+    // Isochronous endpoints are never retried
+    let error_count = if matches!(ep_type, XhciEndpointType::IsochronousOut | XhciEndpointType::IsochronousIn) { 0 } else { 3 };
+    let max_esit_payload = if periodic { max_packet_size as u32 * (max_burst as u32 + 1) } else { 0 };
+
+    context.write_u32(offset, (interval as u32) << 16);
+    context.write_u32(offset + 4, error_count << 1 | (ep_type as u32) << 3 | (max_burst as u32) << 8 | (max_packet_size as u32) << 16);
+    context.write_u64(offset + 8, dequeue_pointer);
+    context.write_u32(offset + 16, average_trb_length as u32 | (max_esit_payload & 0xFFFF) << 16);
+}
+
 impl Drop for XhciController {
     fn drop(&mut self) {
-        unsafe {
-            // Clean up allocated memory
-            if !self.device_context_array.is_null() {
-                let size = (self.max_slots as usize + 1) * mem::size_of::<u32>();
-                alloc::alloc::dealloc(
-                    self.device_context_array as *mut u8,
-                    alloc::alloc::Layout::from_size_align(size, 64).unwrap(),
-                );
-            }
+        // Stop DMA before the rings and contexts are freed
+        if self.state == XhciControllerState::Running {
+            let _ = self.halt();
+        }
+    }
+}
+
+/// xHCI host: a controller and the devices enumerated on its ports
+#[derive(Debug)]
+pub struct XhciHost {
+    pub controller: XhciController,
+    /// Enumerated devices by slot ID
+    pub devices: BTreeMap<u8, UsbDevice>,
+}
+
+impl XhciHost {
+    /// Bring up the platform's xHCI controller
+    pub fn new() -> UsbResult<Self> {
+        Self::with_base_address(XHCI_DEFAULT_BASE_ADDRESS)
+    }
+
+    /// Bring up the controller at `base_address` and enumerate the devices
+    /// already connected
+    pub fn with_base_address(base_address: u64) -> UsbResult<Self> {
+        let mut controller = XhciController::new(base_address);
+        controller.initialize()?;
+        let mut host = Self { controller, devices: BTreeMap::new() };
+        host.enumerate()?;
+        Ok(host)
+    }
 
-            if let Some(command_ring) = &self.command_ring {
-                let size = command_ring.ring_size * mem::size_of::<u32>();
-                alloc::alloc::dealloc(
-                    command_ring.base as *mut u8,
-                    alloc::alloc::Layout::from_size_align(size, 64).unwrap(),
-                );
+    /// Enumerate every connected port without a device; returns how many
+    /// devices were added
+    pub fn enumerate(&mut self) -> UsbResult<usize> {
+        let mut added = 0;
+        for port_number in 1..=self.controller.max_ports {
+            if self.controller.slot_for_port(port_number).is_some() {
+                continue;
             }
+            if self.controller.get_port_status(port_number)?.portsc & XHCI_PORTSC_CCS == 0 {
+                continue;
+            }
+            if self.attach(port_number) {
+                added += 1;
+            }
+        }
+        Ok(added)
+    }
 
-            if let Some(event_ring) = &self.event_ring {
-                let size = event_ring.ring_size * mem::size_of::<u32>();
-                alloc::alloc::dealloc(
-                    event_ring.base as *mut u8,
-                    alloc::alloc::Layout::from_size_align(size, 64).unwrap(),
-                );
+    fn attach(&mut self, port_number: u8) -> bool {
+        match self.controller.enumerate_port(port_number) {
+            Ok(device) => {
+                if let Some(slot_id) = self.controller.slot_for_port(port_number) {
+                    self.devices.insert(slot_id, device);
+                }
+                true
+            }
+            Err(err) => {
+                log::warn!("xHCI port {}: enumeration failed: {:?}", port_number, err);
+                false
             }
+        }
+    }
 
-            if !self.doorbell_array.is_null() {
-                let size = (self.max_slots as usize + 1) * mem::size_of::<u32>();
-                alloc::alloc::dealloc(
-                    self.doorbell_array as *mut u8,
-                    alloc::alloc::Layout::from_size_align(size, 64).unwrap(),
-                );
+    /// Service a controller interrupt, enumerating new devices and
+    /// releasing removed ones
+    pub fn handle_interrupt(&mut self) -> UsbResult<Vec<UsbEvent>> {
+        let events = self.controller.handle_interrupt()?;
+        for event in &events {
+            match *event {
+                UsbEvent::DeviceConnected { port, .. } => {
+                    if self.controller.slot_for_port(port).is_none() {
+                        self.attach(port);
+                    }
+                }
+                UsbEvent::DeviceDisconnected { address } => {
+                    if let Some(slot_id) = self.controller.slot_for_address(address) {
+                        self.devices.remove(&slot_id);
+                        let _ = self.controller.disable_slot(slot_id);
+                    }
+                }
+                _ => {}
             }
         }
+        Ok(events)
+    }
+
+    /// Device in a slot
+    pub fn device(&self, slot_id: u8) -> Option<&UsbDevice> {
+        self.devices.get(&slot_id)
+    }
+
+    pub fn port_count(&self) -> u8 {
+        self.controller.max_ports
     }
 }
 
@@ -741,4 +2119,81 @@ mod tests {
         assert_eq!(port.speed, UsbSpeed::Super);
         assert!(!port.connection_status);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_ring_wraps_through_link_trb() {
+        let mut ring = XhciRing::new(4).unwrap();
+        assert_eq!(ring.free_trbs(), 2);
+        assert_eq!(ring.dequeue_pointer(), ring.address() | 1);
+
+        let first = ring.push(make_trb(0, 0, XHCI_TRB_NORMAL << XHCI_TRB_TYPE_SHIFT)).unwrap();
+        let second = ring.push(make_trb(0, 0, XHCI_TRB_NORMAL << XHCI_TRB_TYPE_SHIFT)).unwrap();
+        assert!(ring.push(make_trb(0, 0, 0)).is_err());
+        assert_eq!(ring.buffer.read_trb(0).control & XHCI_TRB_CYCLE, 1);
+
+        ring.consumed(first);
+        ring.consumed(second);
+        ring.push(make_trb(0, 0, XHCI_TRB_NORMAL << XHCI_TRB_TYPE_SHIFT)).unwrap();
+
+        // The third TRB filled the last slot, so the link TRB was handed
+        // over and the producer cycle flipped
+        let link = ring.buffer.read_trb(3);
+        assert_eq!(trb_type(&link), XHCI_TRB_LINK);
+        assert_eq!(link.control & XHCI_TRB_CYCLE, 1);
+        assert!(!ring.cycle());
+        assert_eq!(ring.dequeue_pointer(), ring.address());
+        assert!(!ring.contains(ring.address() + 3 * XHCI_TRB_SIZE as u64));
+    }
+
+    #[test]
+    fn test_event_ring_follows_cycle_bit() {
+        let mut events = XhciEventRing::new(2).unwrap();
+        assert!(events.pop().is_none());
+
+        let event = make_trb(0x1000, XHCI_CC_SUCCESS << 24, (XHCI_TRB_COMMAND_COMPLETION << XHCI_TRB_TYPE_SHIFT) | 5 << 24 | 1);
+        events.segment.write_trb(0, event);
+        events.segment.write_trb(1, event);
+        assert_eq!(trb_pointer(&events.pop().unwrap()), 0x1000);
+        assert!(events.pop().is_some());
+
+        // Wrapped: entries still carrying cycle 1 are stale
+        assert!(events.pop().is_none());
+        events.segment.write_trb(0, UsbTRB { control: event.control & !XHCI_TRB_CYCLE, ..event });
+        assert_eq!(completion_code(&events.pop().unwrap()), XHCI_CC_SUCCESS);
+        assert_eq!(events.dequeue_pointer(), events.segment.address() + XHCI_TRB_SIZE as u64);
+    }
+
+    #[test]
+    fn test_setup_trb_encoding() {
+        let trb = setup_trb(&get_device_descriptor(18));
+        assert_eq!(trb.ptr_low, 0x0100_0680);
+        assert_eq!(trb.ptr_high, 18 << 16);
+        assert_eq!(trb.status, 8);
+        assert_eq!(trb_type(&trb), XHCI_TRB_SETUP);
+        assert_eq!((trb.control >> XHCI_TRB_TRT_SHIFT) & 0x3, XHCI_TRT_IN_DATA);
+        assert_ne!(trb.control & XHCI_TRB_IDT, 0);
+    }
+
+    #[test]
+    fn test_endpoint_indices_and_intervals() {
+        assert_eq!(xhci_endpoint_dci(0x00), 1);
+        assert_eq!(xhci_endpoint_dci(0x81), 3);
+        assert_eq!(xhci_endpoint_dci(0x02), 4);
+        assert_eq!(xhci_dci_endpoint(3), 0x81);
+        assert_eq!(xhci_dci_endpoint(4), 0x02);
+
+        assert_eq!(xhci_endpoint_interval(UsbSpeed::High, UsbTransferType::Interrupt, 4), 3);
+        assert_eq!(xhci_endpoint_interval(UsbSpeed::Full, UsbTransferType::Interrupt, 10), 6);
+        assert_eq!(xhci_endpoint_interval(UsbSpeed::Full, UsbTransferType::Isochronous, 1), 3);
+        assert_eq!(xhci_endpoint_interval(UsbSpeed::Super, UsbTransferType::Bulk, 0), 0);
+    }
+
+    #[test]
+    fn test_completion_codes() {
+        assert_eq!(xhci_transfer_status(XHCI_CC_SUCCESS), UsbTransferStatus::Success);
+        assert_eq!(xhci_transfer_status(XHCI_CC_SHORT_PACKET), UsbTransferStatus::ShortPacket);
+        assert_eq!(xhci_transfer_status(XHCI_CC_STALL_ERROR), UsbTransferStatus::Stalled);
+        assert_eq!(xhci_transfer_status(XHCI_CC_STOPPED), UsbTransferStatus::Cancelled);
+        assert!(matches!(command_error(XHCI_CC_NO_SLOTS_AVAILABLE), UsbDriverError::InvalidConfiguration));
+    }
+}
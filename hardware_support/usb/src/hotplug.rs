@@ -6,12 +6,43 @@
 //! - Port polling and interrupt handling
 //! - Device state management
 //! - Automatic driver binding
+//!
+//! `HotplugDetector` runs the attach sequence of each root port: debounce,
+//! port reset, reset recovery, SET_ADDRESS, descriptor reads, configuration
+//! selection and class driver binding. Failed attempts are retried with an
+//! exponential backoff until the port is given up on.
 
 use crate::*;
+use crate::classes::UsbClassManager;
 
 #[cfg(feature = "std")]
 use std::collections::BTreeMap;
 
+/// Port status bits reported by `UsbPortHost::port_status()`
+pub const USB_PORT_STAT_CONNECTION: u32 = 0x0001;
+pub const USB_PORT_STAT_ENABLE: u32 = 0x0002;
+pub const USB_PORT_STAT_OVERCURRENT: u32 = 0x0008;
+pub const USB_PORT_STAT_SUSPEND: u32 = 0x0040;
+pub const USB_PORT_STAT_RESET: u32 = 0x0080;
+pub const USB_PORT_STAT_LOW_SPEED: u32 = 0x0100;
+pub const USB_PORT_STAT_HIGH_SPEED: u32 = 0x0400;
+pub const USB_PORT_STAT_SUPER_SPEED: u32 = 0x0800;
+pub const USB_PORT_STAT_POWER: u32 = 0x1000;
+
+/// Connection must stay stable this long before reset (tATTDB)
+pub const USB_ATTACH_DEBOUNCE_MS: u64 = 100;
+/// Longest a port reset may take before the attempt fails
+pub const USB_PORT_RESET_TIMEOUT_MS: u64 = 500;
+/// Idle time after reset before the first request (tRSTRCY)
+pub const USB_RESET_RECOVERY_MS: u64 = 10;
+/// Idle time after SET_ADDRESS (tDSETADDR)
+pub const USB_SET_ADDRESS_RECOVERY_MS: u64 = 2;
+/// Delay before the first retry; doubled for each further one
+pub const USB_ATTACH_BACKOFF_MS: u64 = 100;
+
+/// Configurations read from a device at most
+const USB_MAX_CONFIGURATIONS: u8 = 8;
+
 /// USB Hotplug Event Types
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum UsbHotplugEventType {
@@ -87,7 +118,7 @@ pub struct UsbEnumerationTimeout {
 /// USB Hotplug Manager
 pub struct UsbHotplugManager {
     pub device_connections: BTreeMap<u8, UsbDeviceConnection>,
-    pub port_monitors: BTreeMap<u8, UsbPortMonitor>, // Key: (hub_address << 8) | port_number
+    pub port_monitors: BTreeMap<u16, UsbPortMonitor>, // Key: (hub_address << 8) | port_number
     pub enumeration_timeouts: BTreeMap<u8, UsbEnumerationTimeout>,
     pub event_callbacks: Vec<fn(UsbHotplugEventType, UsbDeviceConnection)>,
     pub polling_enabled: bool,
@@ -399,7 +430,7 @@ impl UsbHotplugManager {
 
     /// Register port for monitoring
     pub fn register_port(&mut self, hub_address: u8, port_number: u8) -> UsbResult<()> {
-        let key = (hub_address << 8) | port_number;
+        let key = port_key(hub_address, port_number);
         
         if self.port_monitors.contains_key(&key) {
             return Err(UsbDriverError::DeviceNotFound { address: port_number });
@@ -414,7 +445,7 @@ impl UsbHotplugManager {
 
     /// Start monitoring port
     pub fn start_port_monitoring(&mut self, hub_address: u8, port_number: u8) -> UsbResult<()> {
-        let key = (hub_address << 8) | port_number;
+        let key = port_key(hub_address, port_number);
         
         let monitor = self.port_monitors.get_mut(&key)
             .ok_or(UsbDriverError::DeviceNotFound { address: port_number })?;
@@ -429,7 +460,7 @@ impl UsbHotplugManager {
 
     /// Update port status and handle changes
     pub fn update_port_status(&mut self, hub_address: u8, port_number: u8, status: u32) -> UsbResult<UsbPortStatusChange> {
-        let key = (hub_address << 8) | port_number;
+        let key = port_key(hub_address, port_number);
         
         let monitor = self.port_monitors.get_mut(&key)
             .ok_or(UsbDriverError::DeviceNotFound { address: port_number })?;
@@ -444,7 +475,7 @@ impl UsbHotplugManager {
 
     /// Handle port status changes
     fn handle_port_changes(&mut self, change: &UsbPortStatusChange) -> UsbResult<()> {
-        let key = (change.hub_address << 8) | change.port_number;
+        let key = port_key(change.hub_address, change.port_number);
         let monitor = self.port_monitors.get(&key)
             .ok_or(UsbDriverError::DeviceNotFound { address: change.port_number })?;
 
@@ -502,7 +533,7 @@ impl UsbHotplugManager {
         self.device_connections.insert(device_address, device);
 
         // Update port monitor
-        let key = (hub_address << 8) | port_number;
+        let key = port_key(hub_address, port_number);
         if let Some(monitor) = self.port_monitors.get_mut(&key) {
             monitor.devices_connected.push(device_address);
         }
@@ -556,7 +587,7 @@ impl UsbHotplugManager {
         }
 
         // Update port monitor
-        let key = (hub_address << 8) | port_number;
+        let key = port_key(hub_address, port_number);
         if let Some(monitor) = self.port_monitors.get_mut(&key) {
             monitor.devices_connected.clear();
         }
@@ -580,7 +611,8 @@ impl UsbHotplugManager {
         for device_address in reset_device_addresses {
             if let Some(device) = self.device_connections.get_mut(&device_address) {
                 device.enumeration_state = UsbEnumerationState::Reset;
-                self.trigger_event_callback(UsbHotplugEventType::DeviceReset, device.clone());
+                let device = device.clone();
+                self.trigger_event_callback(UsbHotplugEventType::DeviceReset, device);
             }
         }
 
@@ -592,7 +624,7 @@ impl UsbHotplugManager {
         log::info!("Port enabled on hub {} port {}", hub_address, port_number);
 
         // Update device enumeration state
-        let key = (hub_address << 8) | port_number;
+        let key = port_key(hub_address, port_number);
         if let Some(monitor) = self.port_monitors.get(&key) {
             for &device_address in &monitor.devices_connected {
                 if let Some(device) = self.device_connections.get_mut(&device_address) {
@@ -663,7 +695,7 @@ impl UsbHotplugManager {
 
         for (&device_address, timeout) in &self.enumeration_timeouts {
             let elapsed = current_time - timeout.start_time;
-            if elapsed > timeout.timeout_ms {
+            if elapsed > timeout.timeout_ms as u64 {
                 expired_devices.push(device_address);
             }
         }
@@ -715,7 +747,7 @@ impl UsbHotplugManager {
 
     /// Get port monitor
     pub fn get_port_monitor(&self, hub_address: u8, port_number: u8) -> UsbResult<&UsbPortMonitor> {
-        let key = (hub_address << 8) | port_number;
+        let key = port_key(hub_address, port_number);
         self.port_monitors.get(&key)
             .ok_or(UsbDriverError::DeviceNotFound { address: port_number })
     }
@@ -771,6 +803,11 @@ impl UsbHotplugManager {
     }
 }
 
+/// Key of a port in `UsbHotplugManager::port_monitors`
+fn port_key(hub_address: u8, port_number: u8) -> u16 {
+    (hub_address as u16) << 8 | port_number as u16
+}
+
 /// USB Hotplug Statistics
 #[derive(Debug, Clone)]
 pub struct UsbHotplugStats {
@@ -788,6 +825,681 @@ impl Default for UsbHotplugManager {
     }
 }
 
+/// Port operations the attach sequence needs from a host controller or hub
+///
+/// `port_status()` reports `USB_PORT_STAT_*` bits. A reset started with
+/// `start_port_reset()` has finished once the port shows enabled and no
+/// longer in reset.
+pub trait UsbPortHost {
+    fn port_status(&mut self, port: u8) -> UsbResult<u32>;
+
+    fn start_port_reset(&mut self, port: u8) -> UsbResult<()>;
+
+    fn disable_port(&mut self, port: u8) -> UsbResult<()>;
+
+    /// Move the device on `port` from the default address to `address`;
+    /// returns the address in use, which hosts that assign addresses
+    /// themselves may choose differently
+    fn set_address(&mut self, port: u8, speed: UsbSpeed, address: u8) -> UsbResult<u8>;
+
+    /// Control transfer to an addressed device; returns bytes moved
+    fn control_transfer(&mut self, address: u8, setup: &UsbSetupPacket, data: &mut [u8]) -> UsbResult<usize>;
+
+    /// Update EP0 after the device descriptor revealed its packet size
+    fn set_max_packet_size0(&mut self, _address: u8, _max_packet_size: u16) -> UsbResult<()> {
+        Ok(())
+    }
+
+    /// Set up host resources for the endpoints of the chosen configuration
+    fn configure_endpoints(&mut self, _address: u8, _endpoints: &[UsbEndpointDescriptor]) -> UsbResult<()> {
+        Ok(())
+    }
+
+    /// Forget a device that was detached or failed to enumerate
+    fn release_address(&mut self, _address: u8) -> UsbResult<()> {
+        Ok(())
+    }
+}
+
+/// Step of a port's attach sequence
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UsbAttachStep {
+    Disconnected,
+    Debounce,
+    Reset,
+    ResetRecovery,
+    SetAddress,
+    AddressRecovery,
+    ReadDescriptors,
+    SetConfiguration,
+    BindDrivers,
+    Ready,
+    Backoff,
+    Failed,
+}
+
+/// A configuration descriptor with its interfaces
+#[derive(Debug, Clone)]
+pub struct UsbParsedConfiguration {
+    pub descriptor: UsbConfigDescriptor,
+    pub interfaces: Vec<UsbInterface>,
+}
+
+/// Attach progress of one port
+#[derive(Debug, Clone)]
+pub struct UsbPortAttach {
+    pub port: u8,
+    pub step: UsbAttachStep,
+    /// Time the current step waits for, in milliseconds
+    pub deadline: u64,
+    pub speed: UsbSpeed,
+    pub address: u8,
+    pub attempts: u8,
+    pub descriptor: Option<UsbDeviceDescriptor>,
+    pub configuration: Option<UsbParsedConfiguration>,
+    pub drivers: Vec<u8>,
+}
+
+impl UsbPortAttach {
+    fn new(port: u8) -> Self {
+        Self {
+            port,
+            step: UsbAttachStep::Disconnected,
+            deadline: 0,
+            speed: UsbSpeed::Full,
+            address: 0,
+            attempts: 0,
+            descriptor: None,
+            configuration: None,
+            drivers: Vec::new(),
+        }
+    }
+
+    fn wait(&mut self, step: UsbAttachStep, until: u64) {
+        self.step = step;
+        self.deadline = until;
+    }
+}
+
+/// Root port hotplug detection and device enumeration
+pub struct HotplugDetector {
+    pub ports: BTreeMap<u8, UsbPortAttach>,
+    pub monitors: BTreeMap<u8, UsbPortMonitor>,
+    pub devices: BTreeMap<u8, UsbDeviceConnection>,
+    pub event_callbacks: Vec<fn(UsbHotplugEventType, UsbDeviceConnection)>,
+    pub max_retries: u8,
+    pub backoff_ms: u64,
+    /// Addresses 1-127 in use
+    addresses: u128,
+}
+
+impl HotplugDetector {
+    pub fn new() -> Self {
+        Self {
+            ports: BTreeMap::new(),
+            monitors: BTreeMap::new(),
+            devices: BTreeMap::new(),
+            event_callbacks: Vec::new(),
+            max_retries: 3,
+            backoff_ms: USB_ATTACH_BACKOFF_MS,
+            addresses: 1, // Address 0 is the default address
+        }
+    }
+
+    /// Add event callback
+    pub fn add_event_callback(&mut self, callback: fn(UsbHotplugEventType, UsbDeviceConnection)) {
+        self.event_callbacks.push(callback);
+    }
+
+    /// Watch a root port
+    pub fn register_port(&mut self, port: u8) {
+        self.ports.entry(port).or_insert_with(|| UsbPortAttach::new(port));
+        self.monitors.entry(port).or_insert_with(|| UsbPortMonitor::new(0, port));
+    }
+
+    /// Port status change interrupt: read the port and start or tear down
+    /// the attach sequence
+    pub fn handle_port_change<H: UsbPortHost>(&mut self, host: &mut H, classes: &mut UsbClassManager,
+                                              port: u8, now: u64) -> UsbResult<()> {
+        self.register_port(port);
+        let status = host.port_status(port)?;
+        let change = match self.monitors.get_mut(&port) {
+            Some(monitor) => monitor.update_status(status),
+            None => return Ok(()),
+        };
+
+        if change.changes.overcurrent_status_changed && status & USB_PORT_STAT_OVERCURRENT != 0 {
+            log::error!("Overcurrent on port {}", port);
+            self.detach(host, classes, port);
+            let _ = host.disable_port(port);
+            if let Some(attach) = self.ports.get_mut(&port) {
+                attach.step = UsbAttachStep::Failed;
+            }
+            self.trigger_event_callback(UsbHotplugEventType::PortOvercurrent, UsbDeviceConnection::new(0));
+            return Ok(());
+        }
+
+        if change.changes.connection_status_changed {
+            if status & USB_PORT_STAT_CONNECTION != 0 {
+                // A new connection restarts the sequence, even after a failure
+                self.detach(host, classes, port);
+                if let Some(attach) = self.ports.get_mut(&port) {
+                    attach.attempts = 0;
+                    attach.wait(UsbAttachStep::Debounce, now + USB_ATTACH_DEBOUNCE_MS);
+                }
+                log::info!("Connection on port {}", port);
+            } else {
+                log::info!("Disconnection on port {}", port);
+                self.detach(host, classes, port);
+            }
+        }
+        Ok(())
+    }
+
+    /// Feed an event reported by the host controller
+    pub fn handle_event<H: UsbPortHost>(&mut self, host: &mut H, classes: &mut UsbClassManager,
+                                        event: &UsbEvent, now: u64) -> UsbResult<()> {
+        match *event {
+            UsbEvent::DeviceConnected { port, .. } => self.handle_port_change(host, classes, port, now),
+            UsbEvent::DeviceDisconnected { address } => {
+                if let Some(port) = self.port_of(address) {
+                    self.handle_port_change(host, classes, port, now)?;
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Advance the attach sequence of every port
+    pub fn poll<H: UsbPortHost>(&mut self, host: &mut H, classes: &mut UsbClassManager, now: u64) -> UsbResult<()> {
+        let ports: Vec<u8> = self.ports.keys().copied().collect();
+        for port in ports {
+            // Run steps back to back until one has to wait
+            loop {
+                let step = match self.ports.get(&port) {
+                    Some(attach) => attach.step,
+                    None => break,
+                };
+                match self.advance(host, classes, port, now) {
+                    Ok(()) => {}
+                    Err(err) => self.attempt_failed(host, classes, port, now, err),
+                }
+                if self.ports.get(&port).map_or(true, |attach| attach.step == step) {
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Perform the current step of `port` if it is due
+    fn advance<H: UsbPortHost>(&mut self, host: &mut H, classes: &mut UsbClassManager, port: u8, now: u64) -> UsbResult<()> {
+        let attach = match self.ports.get(&port) {
+            Some(attach) => attach.clone(),
+            None => return Ok(()),
+        };
+
+        match attach.step {
+            UsbAttachStep::Disconnected | UsbAttachStep::Ready | UsbAttachStep::Failed => {}
+            UsbAttachStep::Debounce | UsbAttachStep::Backoff => {
+                if now < attach.deadline {
+                    return Ok(());
+                }
+                if host.port_status(port)? & USB_PORT_STAT_CONNECTION == 0 {
+                    // Bounced away; the disconnect change will follow
+                    self.set_step(port, UsbAttachStep::Disconnected, now);
+                    return Ok(());
+                }
+                host.start_port_reset(port)?;
+                self.set_step(port, UsbAttachStep::Reset, now + USB_PORT_RESET_TIMEOUT_MS);
+            }
+            UsbAttachStep::Reset => {
+                let status = host.port_status(port)?;
+                if status & USB_PORT_STAT_CONNECTION == 0 {
+                    return Err(UsbDriverError::DeviceNotFound { address: port });
+                }
+                if status & USB_PORT_STAT_RESET == 0 && status & USB_PORT_STAT_ENABLE != 0 {
+                    if let Some(attach) = self.ports.get_mut(&port) {
+                        attach.speed = port_status_speed(status);
+                        attach.wait(UsbAttachStep::ResetRecovery, now + USB_RESET_RECOVERY_MS);
+                    }
+                } else if now >= attach.deadline {
+                    return Err(UsbDriverError::Timeout);
+                }
+            }
+            UsbAttachStep::ResetRecovery | UsbAttachStep::AddressRecovery => {
+                if now >= attach.deadline {
+                    let next = if attach.step == UsbAttachStep::ResetRecovery {
+                        UsbAttachStep::SetAddress
+                    } else {
+                        UsbAttachStep::ReadDescriptors
+                    };
+                    self.set_step(port, next, now);
+                }
+            }
+            UsbAttachStep::SetAddress => {
+                let requested = self.allocate_address().ok_or(UsbDriverError::InvalidConfiguration)?;
+                let address = match host.set_address(port, attach.speed, requested) {
+                    Ok(address) => address,
+                    Err(err) => {
+                        self.free_address(requested);
+                        return Err(err);
+                    }
+                };
+                if address != requested {
+                    self.free_address(requested);
+                    self.reserve_address(address);
+                }
+
+                let mut device = UsbDeviceConnection::new(address);
+                device.start_enumeration()?;
+                device.complete_reset(attach.speed)?;
+                device.set_address(address)?;
+                device.connection_timestamp = now;
+                device.last_activity = now;
+                self.devices.insert(address, device);
+
+                if let Some(attach) = self.ports.get_mut(&port) {
+                    attach.address = address;
+                    attach.wait(UsbAttachStep::AddressRecovery, now + USB_SET_ADDRESS_RECOVERY_MS);
+                }
+            }
+            UsbAttachStep::ReadDescriptors => {
+                let descriptor = read_device_descriptor(host, attach.address, attach.speed)?;
+                let configuration = select_configuration(host, attach.address, &descriptor)?;
+                if let Some(device) = self.devices.get_mut(&attach.address) {
+                    device.vendor_id = descriptor.idVendor;
+                    device.product_id = descriptor.idProduct;
+                    device.descriptor = Some(descriptor);
+                    device.last_activity = now;
+                }
+                if let Some(attach) = self.ports.get_mut(&port) {
+                    attach.descriptor = Some(descriptor);
+                    attach.configuration = Some(configuration);
+                    attach.step = UsbAttachStep::SetConfiguration;
+                }
+                self.trigger_device_event(attach.address, UsbHotplugEventType::DeviceEnumerated);
+            }
+            UsbAttachStep::SetConfiguration => {
+                let configuration = attach.configuration.as_ref().ok_or(UsbDriverError::InvalidConfiguration)?;
+                let endpoints: Vec<UsbEndpointDescriptor> = configuration.interfaces.iter()
+                    .filter(|interface| interface.alternate_setting == 0)
+                    .flat_map(|interface| interface.endpoints.iter().filter_map(|endpoint| endpoint.descriptor))
+                    .collect();
+                host.configure_endpoints(attach.address, &endpoints)?;
+
+                let value = configuration.descriptor.bConfigurationValue;
+                let setup = UsbSetupPacket {
+                    bmRequestType: 0x00,
+                    bRequest: UsbStandardRequest::SET_CONFIGURATION as u8,
+                    wValue: value as u16,
+                    wIndex: 0,
+                    wLength: 0,
+                };
+                host.control_transfer(attach.address, &setup, &mut [])?;
+
+                if let Some(device) = self.devices.get_mut(&attach.address) {
+                    device.configure_device(value)?;
+                    device.interfaces = configuration.interfaces.clone();
+                    device.last_activity = now;
+                }
+                self.set_step(port, UsbAttachStep::BindDrivers, now);
+                self.trigger_device_event(attach.address, UsbHotplugEventType::DeviceConfigured);
+            }
+            UsbAttachStep::BindDrivers => {
+                let drivers = bind_drivers(classes, attach.address, attach.descriptor.as_ref(), attach.configuration.as_ref());
+                if let Some(device) = self.devices.get_mut(&attach.address) {
+                    device.complete_enumeration()?;
+                    device.mark_ready();
+                    device.reset_error_count();
+                }
+                if let Some(attach) = self.ports.get_mut(&port) {
+                    attach.drivers = drivers;
+                    attach.attempts = 0;
+                    attach.step = UsbAttachStep::Ready;
+                }
+                self.trigger_device_event(attach.address, UsbHotplugEventType::DeviceConnected);
+            }
+        }
+        Ok(())
+    }
+
+    /// Undo a failed attempt and retry after a backoff, or give up on the port
+    fn attempt_failed<H: UsbPortHost>(&mut self, host: &mut H, classes: &mut UsbClassManager,
+                                      port: u8, now: u64, error: UsbDriverError) {
+        let (step, address) = match self.ports.get(&port) {
+            Some(attach) => (attach.step, attach.address),
+            None => return,
+        };
+        log::warn!("Attach of port {} failed during {:?}: {:?}", port, step, error);
+
+        let failed_device = self.devices.get(&address).cloned();
+        self.detach(host, classes, port);
+        let attach = match self.ports.get_mut(&port) {
+            Some(attach) => attach,
+            None => return,
+        };
+        attach.attempts += 1;
+
+        if attach.attempts >= self.max_retries {
+            log::error!("Giving up on port {} after {} attempts", port, attach.attempts);
+            attach.step = UsbAttachStep::Failed;
+            let _ = host.disable_port(port);
+        } else {
+            let backoff = self.backoff_ms << (attach.attempts - 1);
+            attach.wait(UsbAttachStep::Backoff, now + backoff);
+        }
+
+        let mut device = failed_device.unwrap_or_else(|| UsbDeviceConnection::new(0));
+        device.enumeration_state = UsbEnumerationState::Error;
+        device.error_count = self.ports.get(&port).map_or(0, |attach| attach.attempts);
+        self.trigger_event_callback(UsbHotplugEventType::DeviceError, device);
+    }
+
+    /// Release the device on `port`, its drivers and its address
+    fn detach<H: UsbPortHost>(&mut self, host: &mut H, classes: &mut UsbClassManager, port: u8) {
+        let attach = match self.ports.get_mut(&port) {
+            Some(attach) => attach,
+            None => return,
+        };
+        let address = core::mem::take(&mut attach.address);
+        let drivers = core::mem::take(&mut attach.drivers);
+        let was_ready = attach.step == UsbAttachStep::Ready;
+        attach.step = UsbAttachStep::Disconnected;
+        attach.descriptor = None;
+        attach.configuration = None;
+
+        for driver_id in drivers {
+            let _ = classes.remove_driver(driver_id);
+        }
+        if address == 0 {
+            return;
+        }
+        if let Err(err) = host.release_address(address) {
+            log::warn!("Releasing address {} failed: {:?}", address, err);
+        }
+        self.free_address(address);
+
+        if let Some(mut device) = self.devices.remove(&address) {
+            device.enumeration_state = UsbEnumerationState::Removed;
+            device.active = false;
+            if was_ready {
+                log::info!("Device {} removed from port {}", address, port);
+                self.trigger_event_callback(UsbHotplugEventType::DeviceDisconnected, device);
+            }
+        }
+    }
+
+    fn set_step(&mut self, port: u8, step: UsbAttachStep, until: u64) {
+        if let Some(attach) = self.ports.get_mut(&port) {
+            attach.wait(step, until);
+        }
+    }
+
+    fn allocate_address(&mut self) -> Option<u8> {
+        let address = (!self.addresses).trailing_zeros();
+        if address > 127 {
+            return None;
+        }
+        self.addresses |= 1 << address;
+        Some(address as u8)
+    }
+
+    fn reserve_address(&mut self, address: u8) {
+        self.addresses |= 1 << (address & 0x7F);
+    }
+
+    fn free_address(&mut self, address: u8) {
+        if address != 0 {
+            self.addresses &= !(1 << (address & 0x7F));
+        }
+    }
+
+    fn port_of(&self, address: u8) -> Option<u8> {
+        self.ports.values()
+            .find(|attach| attach.address == address && address != 0)
+            .map(|attach| attach.port)
+    }
+
+    fn trigger_device_event(&self, address: u8, event_type: UsbHotplugEventType) {
+        if let Some(device) = self.devices.get(&address) {
+            self.trigger_event_callback(event_type, device.clone());
+        }
+    }
+
+    /// Trigger event callbacks
+    fn trigger_event_callback(&self, event_type: UsbHotplugEventType, device: UsbDeviceConnection) {
+        for callback in &self.event_callbacks {
+            callback(event_type, device.clone());
+        }
+    }
+
+    /// Attach step of a port
+    pub fn port_step(&self, port: u8) -> Option<UsbAttachStep> {
+        self.ports.get(&port).map(|attach| attach.step)
+    }
+
+    /// Get device by address
+    pub fn get_device(&self, address: u8) -> UsbResult<&UsbDeviceConnection> {
+        self.devices.get(&address)
+            .ok_or(UsbDriverError::DeviceNotFound { address })
+    }
+
+    /// Devices that finished enumeration
+    pub fn ready_devices(&self) -> Vec<&UsbDeviceConnection> {
+        self.devices.values().filter(|device| device.is_ready()).collect()
+    }
+}
+
+impl Default for HotplugDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Device speed encoded in `USB_PORT_STAT_*` bits
+pub fn port_status_speed(status: u32) -> UsbSpeed {
+    if status & USB_PORT_STAT_SUPER_SPEED != 0 {
+        UsbSpeed::Super
+    } else if status & USB_PORT_STAT_HIGH_SPEED != 0 {
+        UsbSpeed::High
+    } else if status & USB_PORT_STAT_LOW_SPEED != 0 {
+        UsbSpeed::Low
+    } else {
+        UsbSpeed::Full
+    }
+}
+
+fn get_descriptor(descriptor_type: UsbDescriptorType, index: u8, length: u16) -> UsbSetupPacket {
+    UsbSetupPacket {
+        bmRequestType: 0x80,
+        bRequest: UsbStandardRequest::GET_DESCRIPTOR as u8,
+        wValue: (descriptor_type as u16) << 8 | index as u16,
+        wIndex: 0,
+        wLength: length,
+    }
+}
+
+/// Read the device descriptor, fixing up EP0 after the first 8 bytes
+fn read_device_descriptor<H: UsbPortHost>(host: &mut H, address: u8, speed: UsbSpeed) -> UsbResult<UsbDeviceDescriptor> {
+    let mut data = [0u8; 18];
+    if host.control_transfer(address, &get_descriptor(UsbDescriptorType::Device, 0, 8), &mut data[..8])? < 8 {
+        return Err(UsbDriverError::ProtocolError);
+    }
+    let max_packet_size0 = match speed {
+        UsbSpeed::Super | UsbSpeed::SuperPlus => 1u16 << data[7].min(9),
+        _ => data[7] as u16,
+    };
+    if !matches!(max_packet_size0, 8 | 16 | 32 | 64 | 512) {
+        return Err(UsbDriverError::ProtocolError);
+    }
+    host.set_max_packet_size0(address, max_packet_size0)?;
+
+    if host.control_transfer(address, &get_descriptor(UsbDescriptorType::Device, 0, 18), &mut data)? < 18
+        || data[1] != UsbDescriptorType::Device as u8 {
+        return Err(UsbDriverError::ProtocolError);
+    }
+    Ok(UsbDeviceDescriptor {
+        bLength: data[0],
+        bDescriptorType: data[1],
+        bcdUSB: u16::from_le_bytes([data[2], data[3]]),
+        bDeviceClass: data[4],
+        bDeviceSubClass: data[5],
+        bDeviceProtocol: data[6],
+        bMaxPacketSize0: data[7],
+        idVendor: u16::from_le_bytes([data[8], data[9]]),
+        idProduct: u16::from_le_bytes([data[10], data[11]]),
+        bcdDevice: u16::from_le_bytes([data[12], data[13]]),
+        iManufacturer: data[14],
+        iProduct: data[15],
+        iSerialNumber: data[16],
+        bNumConfigurations: data[17],
+    })
+}
+
+/// Read every configuration and pick the first one with an interface of a
+/// standard class, falling back to the first configuration
+fn select_configuration<H: UsbPortHost>(host: &mut H, address: u8, descriptor: &UsbDeviceDescriptor) -> UsbResult<UsbParsedConfiguration> {
+    let mut first = None;
+    for index in 0..descriptor.bNumConfigurations.clamp(1, USB_MAX_CONFIGURATIONS) {
+        let mut header = [0u8; 9];
+        if host.control_transfer(address, &get_descriptor(UsbDescriptorType::Configuration, index, 9), &mut header)? < 9 {
+            return Err(UsbDriverError::ProtocolError);
+        }
+        let total_length = u16::from_le_bytes([header[2], header[3]]).max(9);
+        let mut data = vec![0u8; total_length as usize];
+        let length = host.control_transfer(address, &get_descriptor(UsbDescriptorType::Configuration, index, total_length), &mut data)?;
+        let configuration = parse_configuration(&data[..length])?;
+
+        let standard = configuration.interfaces.iter()
+            .any(|interface| !matches!(interface.class, UsbClass::VendorSpecific | UsbClass::ApplicationSpecific));
+        if standard {
+            return Ok(configuration);
+        }
+        first.get_or_insert(configuration);
+    }
+    first.ok_or(UsbDriverError::InvalidConfiguration)
+}
+
+/// Decode a configuration descriptor and the interface and endpoint
+/// descriptors that follow it
+pub fn parse_configuration(data: &[u8]) -> UsbResult<UsbParsedConfiguration> {
+    if data.len() < 9 || data[1] != UsbDescriptorType::Configuration as u8 {
+        return Err(UsbDriverError::ProtocolError);
+    }
+    let descriptor = UsbConfigDescriptor {
+        bLength: data[0],
+        bDescriptorType: data[1],
+        wTotalLength: u16::from_le_bytes([data[2], data[3]]),
+        bNumInterfaces: data[4],
+        bConfigurationValue: data[5],
+        iConfiguration: data[6],
+        bmAttributes: data[7],
+        bMaxPower: data[8],
+    };
+
+    let mut interfaces: Vec<UsbInterface> = Vec::new();
+    let mut offset = data[0] as usize;
+    while offset + 2 <= data.len() {
+        let length = data[offset] as usize;
+        if length < 2 || offset + length > data.len() {
+            return Err(UsbDriverError::ProtocolError);
+        }
+        let body = &data[offset..offset + length];
+        match body[1] {
+            t if t == UsbDescriptorType::Interface as u8 && length >= 9 => {
+                let interface = UsbInterfaceDescriptor {
+                    bLength: body[0],
+                    bDescriptorType: body[1],
+                    bInterfaceNumber: body[2],
+                    bAlternateSetting: body[3],
+                    bNumEndpoints: body[4],
+                    bInterfaceClass: body[5],
+                    bInterfaceSubClass: body[6],
+                    bInterfaceProtocol: body[7],
+                    iInterface: body[8],
+                };
+                interfaces.push(UsbInterface {
+                    number: interface.bInterfaceNumber,
+                    alternate_setting: interface.bAlternateSetting,
+                    class: UsbClass::from(interface.bInterfaceClass),
+                    subclass: interface.bInterfaceSubClass,
+                    protocol: interface.bInterfaceProtocol,
+                    endpoints: Vec::new(),
+                    descriptor: Some(interface),
+                });
+            }
+            t if t == UsbDescriptorType::Endpoint as u8 && length >= 7 => {
+                let endpoint = UsbEndpointDescriptor {
+                    bLength: body[0],
+                    bDescriptorType: body[1],
+                    bEndpointAddress: body[2],
+                    bmAttributes: body[3],
+                    wMaxPacketSize: u16::from_le_bytes([body[4], body[5]]),
+                    bInterval: body[6],
+                };
+                let interface = interfaces.last_mut().ok_or(UsbDriverError::ProtocolError)?;
+                interface.endpoints.push(UsbEndpoint {
+                    address: endpoint.bEndpointAddress,
+                    direction: if endpoint.bEndpointAddress & 0x80 != 0 { UsbDirection::In } else { UsbDirection::Out },
+                    transfer_type: match endpoint.bmAttributes & 0x03 {
+                        0 => UsbTransferType::Control,
+                        1 => UsbTransferType::Isochronous,
+                        2 => UsbTransferType::Bulk,
+                        _ => UsbTransferType::Interrupt,
+                    },
+                    max_packet_size: endpoint.wMaxPacketSize,
+                    interval: endpoint.bInterval,
+                    descriptor: Some(endpoint),
+                });
+            }
+            // Class-specific and companion descriptors are left to the drivers
+            _ => {}
+        }
+        offset += length;
+    }
+    Ok(UsbParsedConfiguration { descriptor, interfaces })
+}
+
+/// Create and start class drivers for the device-level class or for each
+/// interface; interfaces without a driver are skipped
+fn bind_drivers(classes: &mut UsbClassManager, address: u8, descriptor: Option<&UsbDeviceDescriptor>,
+                configuration: Option<&UsbParsedConfiguration>) -> Vec<u8> {
+    let mut functions: Vec<(UsbClass, u8, u8)> = Vec::new();
+    match descriptor {
+        Some(device) if !matches!(device.bDeviceClass, 0x00 | 0xEF | 0xFF) => {
+            functions.push((UsbClass::from(device.bDeviceClass), device.bDeviceSubClass, device.bDeviceProtocol));
+        }
+        _ => {
+            if let Some(configuration) = configuration {
+                functions.extend(configuration.interfaces.iter()
+                    .filter(|interface| interface.alternate_setting == 0)
+                    .map(|interface| (interface.class, interface.subclass, interface.protocol)));
+            }
+        }
+    }
+
+    let mut drivers = Vec::new();
+    for (class, subclass, protocol) in functions {
+        let driver_id = match classes.create_driver(address, class, subclass, protocol) {
+            Ok(driver_id) => driver_id,
+            Err(_) => {
+                log::info!("Device {}: no driver for class {:?}", address, class);
+                continue;
+            }
+        };
+        match classes.initialize_driver(driver_id) {
+            Ok(()) => drivers.push(driver_id),
+            Err(err) => {
+                log::warn!("Device {}: {:?} driver failed to start: {:?}", address, class, err);
+                let _ = classes.remove_driver(driver_id);
+            }
+        }
+    }
+    drivers
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -848,4 +1560,193 @@ mod tests {
         device.error_count = 3;
         assert!(!device.should_retry_enumeration(3));
     }
-}
\ No newline at end of file
+
+    /// Configuration with one bulk-only mass storage interface
+    const MSC_CONFIGURATION: [u8; 32] = [
+        9, 2, 32, 0, 1, 1, 0, 0x80, 50,
+        9, 4, 0, 0, 2, 0x08, 0x06, 0x50, 0,
+        7, 5, 0x81, 2, 0x00, 0x02, 0,
+        7, 5, 0x02, 2, 0x00, 0x02, 0,
+    ];
+
+    /// Root hub with one port and a high-speed device behind it
+    struct MockPortHost {
+        status: u32,
+        reset_polls: u32,
+        fail_set_address: bool,
+        address: u8,
+        max_packet_size0: u16,
+        configuration: u8,
+        released: Vec<u8>,
+        disabled: bool,
+    }
+
+    impl MockPortHost {
+        fn new() -> Self {
+            Self {
+                status: USB_PORT_STAT_POWER,
+                reset_polls: 0,
+                fail_set_address: false,
+                address: 0,
+                max_packet_size0: 0,
+                configuration: 0,
+                released: Vec::new(),
+                disabled: false,
+            }
+        }
+    }
+
+    impl UsbPortHost for MockPortHost {
+        fn port_status(&mut self, _port: u8) -> UsbResult<u32> {
+            if self.status & USB_PORT_STAT_RESET != 0 {
+                if self.reset_polls == 0 {
+                    self.status = (self.status & !USB_PORT_STAT_RESET) | USB_PORT_STAT_ENABLE | USB_PORT_STAT_HIGH_SPEED;
+                } else {
+                    self.reset_polls -= 1;
+                }
+            }
+            Ok(self.status)
+        }
+
+        fn start_port_reset(&mut self, _port: u8) -> UsbResult<()> {
+            self.status |= USB_PORT_STAT_RESET;
+            self.reset_polls = 1;
+            Ok(())
+        }
+
+        fn disable_port(&mut self, _port: u8) -> UsbResult<()> {
+            self.status &= !USB_PORT_STAT_ENABLE;
+            self.disabled = true;
+            Ok(())
+        }
+
+        fn set_address(&mut self, _port: u8, _speed: UsbSpeed, address: u8) -> UsbResult<u8> {
+            if self.fail_set_address {
+                return Err(UsbDriverError::TransferFailed { status: UsbTransferStatus::Stalled });
+            }
+            self.address = address;
+            Ok(address)
+        }
+
+        fn control_transfer(&mut self, address: u8, setup: &UsbSetupPacket, data: &mut [u8]) -> UsbResult<usize> {
+            assert_eq!(address, self.address);
+            let reply: &[u8] = match (setup.bRequest, setup.wValue >> 8) {
+                (0x06, 1) => &[18, 1, 0x00, 0x02, 0, 0, 0, 64, 0x81, 0x07, 0x81, 0x55, 0x00, 0x01, 1, 2, 3, 1],
+                (0x06, 2) => &MSC_CONFIGURATION,
+                (0x09, _) => {
+                    self.configuration = setup.wValue as u8;
+                    &[]
+                }
+                _ => return Err(UsbDriverError::TransferFailed { status: UsbTransferStatus::Stalled }),
+            };
+            let length = reply.len().min(setup.wLength as usize);
+            data[..length].copy_from_slice(&reply[..length]);
+            Ok(length)
+        }
+
+        fn set_max_packet_size0(&mut self, _address: u8, max_packet_size: u16) -> UsbResult<()> {
+            self.max_packet_size0 = max_packet_size;
+            Ok(())
+        }
+
+        fn release_address(&mut self, address: u8) -> UsbResult<()> {
+            self.released.push(address);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_parse_configuration() {
+        let configuration = parse_configuration(&MSC_CONFIGURATION).unwrap();
+        assert_eq!(configuration.descriptor.bConfigurationValue, 1);
+        assert_eq!(configuration.interfaces.len(), 1);
+        let interface = &configuration.interfaces[0];
+        assert_eq!(interface.class, UsbClass::MassStorage);
+        assert_eq!((interface.subclass, interface.protocol), (0x06, 0x50));
+        assert_eq!(interface.endpoints.len(), 2);
+        assert_eq!(interface.endpoints[0].direction, UsbDirection::In);
+        assert_eq!(interface.endpoints[1].max_packet_size, 512);
+
+        assert!(parse_configuration(&MSC_CONFIGURATION[..20]).is_err());
+    }
+
+    #[test]
+    fn test_attach_sequence_timing() {
+        let mut host = MockPortHost::new();
+        let mut classes = UsbClassManager::new();
+        let mut detector = HotplugDetector::new();
+
+        host.status |= USB_PORT_STAT_CONNECTION;
+        detector.handle_port_change(&mut host, &mut classes, 1, 0).unwrap();
+        assert_eq!(detector.port_step(1), Some(UsbAttachStep::Debounce));
+
+        detector.poll(&mut host, &mut classes, 50).unwrap();
+        assert_eq!(detector.port_step(1), Some(UsbAttachStep::Debounce));
+
+        detector.poll(&mut host, &mut classes, USB_ATTACH_DEBOUNCE_MS).unwrap();
+        assert_eq!(detector.port_step(1), Some(UsbAttachStep::Reset));
+
+        detector.poll(&mut host, &mut classes, 105).unwrap();
+        assert_eq!(detector.port_step(1), Some(UsbAttachStep::ResetRecovery));
+
+        detector.poll(&mut host, &mut classes, 115).unwrap();
+        assert_eq!(detector.port_step(1), Some(UsbAttachStep::AddressRecovery));
+        assert_eq!(host.address, 1);
+
+        detector.poll(&mut host, &mut classes, 117).unwrap();
+        assert_eq!(detector.port_step(1), Some(UsbAttachStep::Ready));
+        assert_eq!(host.max_packet_size0, 64);
+        assert_eq!(host.configuration, 1);
+
+        let device = detector.get_device(1).unwrap();
+        assert!(device.is_ready());
+        assert_eq!((device.vendor_id, device.product_id), (0x0781, 0x5581));
+        assert_eq!(device.speed, UsbSpeed::High);
+        assert_eq!(device.interfaces.len(), 1);
+
+        // Unplugging releases the device and its address
+        host.status = USB_PORT_STAT_POWER;
+        detector.handle_port_change(&mut host, &mut classes, 1, 200).unwrap();
+        assert_eq!(detector.port_step(1), Some(UsbAttachStep::Disconnected));
+        assert!(detector.get_device(1).is_err());
+        assert_eq!(host.released, vec![1]);
+    }
+
+    #[test]
+    fn test_attach_retry_backoff() {
+        let mut host = MockPortHost::new();
+        let mut classes = UsbClassManager::new();
+        let mut detector = HotplugDetector::new();
+        host.fail_set_address = true;
+
+        host.status |= USB_PORT_STAT_CONNECTION;
+        detector.handle_port_change(&mut host, &mut classes, 1, 0).unwrap();
+
+        let mut now = 0;
+        let mut backoffs = Vec::new();
+        let mut last_deadline = 0;
+        while detector.port_step(1) != Some(UsbAttachStep::Failed) && now < 10_000 {
+            detector.poll(&mut host, &mut classes, now).unwrap();
+            let attach = &detector.ports[&1];
+            if attach.step == UsbAttachStep::Backoff && attach.deadline != last_deadline {
+                backoffs.push(attach.deadline - now);
+                last_deadline = attach.deadline;
+            }
+            now += 1;
+        }
+
+        assert_eq!(detector.port_step(1), Some(UsbAttachStep::Failed));
+        assert_eq!(backoffs, vec![USB_ATTACH_BACKOFF_MS, 2 * USB_ATTACH_BACKOFF_MS]);
+        assert!(host.disabled);
+        // The address reserved for each attempt was given back
+        assert_eq!(detector.allocate_address(), Some(1));
+
+        // Replugging starts over
+        host.fail_set_address = false;
+        host.status = USB_PORT_STAT_POWER;
+        detector.handle_port_change(&mut host, &mut classes, 1, now).unwrap();
+        host.status |= USB_PORT_STAT_CONNECTION;
+        detector.handle_port_change(&mut host, &mut classes, 1, now).unwrap();
+        assert_eq!(detector.port_step(1), Some(UsbAttachStep::Debounce));
+    }
+}
@@ -32,13 +32,13 @@ pub mod protocol_analyzer;
 pub mod tests;
 
 // Re-export commonly used types
-pub use host::{XhciHost, EhciHost, OhciHost};
+pub use host::{XhciHost, XhciController, EhciHost, EhciController, OhciHost, OhciController};
 pub use classes::{HidDevice, MscDevice, CdcDevice, AudioDevice, UsbDeviceClass};
 pub use hub::UsbHub;
 pub use hotplug::HotplugDetector;
 pub use power::{UsbPowerManager, PowerState};
-pub use security::{SecurityManager, SecurityLevel, DeviceFingerprint, TrustState};
-pub use protocol_analyzer::{ProtocolAnalyzer, DescriptorDecoder};
+pub use security::{SecurityManager, SecurityLevel, DeviceFingerprint, TrustState, SuspiciousIndicator, QuarantineEntry};
+pub use protocol_analyzer::{ProtocolAnalyzer, DescriptorDecoder, CaptureFilter, CaptureSubscriber};
 pub use tests::{TestSuite, TestResult, run_comprehensive_tests, quick_validation_test, benchmark_framework};
 
 /// USB Version constants
@@ -79,6 +79,36 @@ pub enum UsbClass {
     VendorSpecific = 0xFF,
 }
 
+impl From<u8> for UsbClass {
+    fn from(code: u8) -> Self {
+        match code {
+            0x00 => UsbClass::None,
+            0x01 => UsbClass::Audio,
+            0x02 => UsbClass::Communications,
+            0x03 => UsbClass::HID,
+            0x05 => UsbClass::Physical,
+            0x06 => UsbClass::Image,
+            0x07 => UsbClass::Printer,
+            0x08 => UsbClass::MassStorage,
+            0x09 => UsbClass::Hub,
+            0x0A => UsbClass::CDCData,
+            0x0B => UsbClass::SmartCard,
+            0x0D => UsbClass::ContentSecurity,
+            0x0E => UsbClass::Video,
+            0x0F => UsbClass::PersonalHealthcare,
+            0x10 => UsbClass::AudioVideo,
+            0x11 => UsbClass::Billboard,
+            0x12 => UsbClass::USBTypeCBridge,
+            0x13 => UsbClass::Matter,
+            0xDC => UsbClass::Diagnostic,
+            0xE0 => UsbClass::WirelessController,
+            0xEF => UsbClass::Miscellaneous,
+            0xFE => UsbClass::ApplicationSpecific,
+            _ => UsbClass::VendorSpecific,
+        }
+    }
+}
+
 /// USB Device Speed
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -411,7 +441,7 @@ pub struct UsbEndpoint {
 }
 
 /// USB Host Controller
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub enum UsbHostController {
     XHCI(XhciController),
     EHCI(EhciController),
@@ -437,6 +467,13 @@ pub struct UsbPowerInfo {
     pub max_power_ma: u32,
     pub port_power_managed: bool,
     pub wakeup_enabled: bool,
+    pub suspend_count: u32,
+    pub resume_count: u32,
+    pub autosuspend_count: u32,
+    pub remote_wakeup_count: u32,
+    pub link_transitions: u32,
+    pub budget_denials: u32,
+    pub time_suspended_ms: u64,
 }
 
 /// USB Security Context
@@ -542,6 +579,44 @@ pub struct UsbDevicePolicy {
     pub quarantine_days: u8,
 }
 
+impl UsbSecurityManager {
+    /// Check whether `operation` is permitted on a device and return the
+    /// security level it runs under
+    ///
+    /// Quarantined devices are always refused. A device with its own policy
+    /// must list the operation; devices without one are only allowed while
+    /// the global level is `Basic` or lower.
+    pub fn authorize(&self, vendor_id: u16, product_id: u16, operation: &str) -> UsbResult<UsbSecurityLevel> {
+        let quarantined = self.quarantine_list.iter().any(|entry| {
+            let mut ids = entry.split(':').map(|part| u16::from_str_radix(part.trim(), 16).ok());
+            matches!((ids.next(), ids.next(), ids.next()), (Some(Some(v)), Some(Some(p)), None) if v == vendor_id && p == product_id)
+        });
+        if quarantined {
+            log::warn!("USB device {:04x}:{:04x} is quarantined, refusing {}", vendor_id, product_id, operation);
+            return Err(UsbDriverError::SecurityViolation);
+        }
+
+        let policy = self.device_policies.iter()
+            .find(|policy| policy.vendor_id == vendor_id && policy.product_id == product_id);
+        match policy {
+            Some(policy) if policy.allowed_operations.iter().any(|op| op == operation) => {
+                Ok(policy.security_level)
+            }
+            Some(_) => {
+                log::warn!("USB device {:04x}:{:04x} policy does not allow {}", vendor_id, product_id, operation);
+                Err(UsbDriverError::SecurityViolation)
+            }
+            None if (self.global_security_level as u8) <= (UsbSecurityLevel::Basic as u8) => {
+                Ok(self.global_security_level)
+            }
+            None => {
+                log::warn!("USB device {:04x}:{:04x} has no policy for {}", vendor_id, product_id, operation);
+                Err(UsbDriverError::SecurityViolation)
+            }
+        }
+    }
+}
+
 /// USB Driver Error Types
 #[derive(Debug, Clone)]
 pub enum UsbDriverError {
@@ -7,6 +7,8 @@
 //! - Power consumption tracking
 //! - System sleep/wake handling
 //! - Per-device and global power policies
+//! - Autosuspend timers and link power management (LPM, U1/U2)
+//! - Per-port power budgeting against hub capabilities
 
 use crate::*;
 
@@ -44,6 +46,163 @@ pub enum UsbPowerPolicy {
     Custom,           // Custom policy
 }
 
+/// Current a root port supplies to a USB 2.0 device
+pub const USB2_ROOT_PORT_MA: u32 = 500;
+/// Current a root port supplies to a SuperSpeed device
+pub const USB3_ROOT_PORT_MA: u32 = 900;
+/// One unit load on a bus-powered USB 2.0 hub port
+pub const USB2_UNIT_LOAD_MA: u32 = 100;
+/// One unit load on a bus-powered SuperSpeed hub port
+pub const USB3_UNIT_LOAD_MA: u32 = 150;
+
+/// Link power state of the port a device is attached to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UsbLinkPowerState {
+    /// USB 2.0 link on
+    L0,
+    /// USB 2.0 LPM sleep
+    L1,
+    /// USB 2.0 suspend
+    L2,
+    /// SuperSpeed link active
+    U0,
+    /// SuperSpeed standby with fast exit
+    U1,
+    /// SuperSpeed standby with slower exit
+    U2,
+    /// SuperSpeed suspend
+    U3,
+}
+
+impl UsbLinkPowerState {
+    /// Active link state for a device speed
+    pub fn active_for(speed: UsbSpeed) -> Self {
+        if is_superspeed(speed) { UsbLinkPowerState::U0 } else { UsbLinkPowerState::L0 }
+    }
+
+    /// Suspended link state for a device speed
+    pub fn suspended_for(speed: UsbSpeed) -> Self {
+        if is_superspeed(speed) { UsbLinkPowerState::U3 } else { UsbLinkPowerState::L2 }
+    }
+
+    /// Whether this is a SuperSpeed link state
+    pub fn is_superspeed(self) -> bool {
+        matches!(self, UsbLinkPowerState::U0 | UsbLinkPowerState::U1 | UsbLinkPowerState::U2 | UsbLinkPowerState::U3)
+    }
+
+    /// Whether the link is suspended rather than merely idle
+    pub fn is_suspended(self) -> bool {
+        matches!(self, UsbLinkPowerState::L2 | UsbLinkPowerState::U3)
+    }
+
+    /// Whether the link may move directly to `next`
+    ///
+    /// Suspended links only resume to the active state, and U1/U2/L1 can
+    /// only be left for the active or a deeper state.
+    pub fn can_transition_to(self, next: UsbLinkPowerState) -> bool {
+        use UsbLinkPowerState::*;
+        if self == next {
+            return true;
+        }
+        match (self, next) {
+            (L0, L1) | (L0, L2) | (L1, L0) | (L1, L2) | (L2, L0) => true,
+            (U0, U1) | (U0, U2) | (U0, U3) => true,
+            (U1, U0) | (U1, U2) | (U1, U3) => true,
+            (U2, U0) | (U2, U3) => true,
+            (U3, U0) => true,
+            _ => false,
+        }
+    }
+}
+
+fn is_superspeed(speed: UsbSpeed) -> bool {
+    matches!(speed, UsbSpeed::Super | UsbSpeed::SuperPlus)
+}
+
+/// Hardware operations the power manager drives
+pub trait UsbPowerHost {
+    /// Move a port's link to `state`
+    ///
+    /// U1, U2 and L1 are entered by hardware once the link idles; setting
+    /// them arms that autonomous entry, and U0/L0 disarms it again.
+    fn set_link_state(&mut self, port: u8, state: UsbLinkPowerState) -> UsbResult<()>;
+
+    /// Run a control transfer on a device
+    fn control_transfer(&mut self, address: u8, setup: &UsbSetupPacket, data: &mut [u8]) -> UsbResult<usize>;
+
+    /// Arm or disarm remote wakeup on a device
+    ///
+    /// USB 2.0 devices use the DEVICE_REMOTE_WAKEUP feature; SuperSpeed
+    /// devices use FUNCTION_SUSPEND on their first interface.
+    fn set_remote_wakeup(&mut self, address: u8, superspeed: bool, enable: bool) -> UsbResult<()> {
+        let request = if enable { UsbStandardRequest::SET_FEATURE } else { UsbStandardRequest::CLEAR_FEATURE };
+        let setup = if superspeed {
+            UsbSetupPacket {
+                bmRequestType: UsbRecipient::Interface as u8,
+                bRequest: UsbStandardRequest::SET_FEATURE as u8,
+                wValue: USB_FEATURE_FUNCTION_SUSPEND,
+                // Low power suspend plus the remote wake enable bit
+                wIndex: if enable { 0x0300 } else { 0x0000 },
+                wLength: 0,
+            }
+        } else {
+            UsbSetupPacket {
+                bmRequestType: UsbRecipient::Device as u8,
+                bRequest: request as u8,
+                wValue: UsbFeatureSelector::DEVICE_REMOTE_WAKEUP as u16,
+                wIndex: 0,
+                wLength: 0,
+            }
+        };
+        self.control_transfer(address, &setup, &mut []).map(|_| ())
+    }
+}
+
+/// FUNCTION_SUSPEND feature selector (USB 3.x)
+const USB_FEATURE_FUNCTION_SUSPEND: u16 = 0x0000;
+
+/// Power capability and allocation of one downstream port
+#[derive(Debug, Clone)]
+pub struct UsbPortPowerBudget {
+    pub hub_address: u8,
+    pub port: u8,
+    pub max_current_ma: u32,
+    pub allocated_ma: u32,
+    pub device_address: Option<u8>,
+    pub power_switching: bool,
+}
+
+/// Power budget of a hub feeding its downstream ports
+#[derive(Debug, Clone)]
+pub struct UsbHubPowerBudget {
+    pub hub_address: u8,
+    pub self_powered: bool,
+    /// Current available to all downstream ports together
+    pub total_ma: u32,
+    pub allocated_ma: u32,
+}
+
+/// Port attachment and link state of a device
+#[derive(Debug, Clone)]
+pub struct UsbDeviceLink {
+    pub hub_address: u8,
+    pub port: u8,
+    pub speed: UsbSpeed,
+    pub link_state: UsbLinkPowerState,
+    /// USB 2.0 LPM or SuperSpeed U1/U2 support from the BOS descriptor
+    pub lpm_capable: bool,
+    pub remote_wakeup_armed: bool,
+}
+
+/// Autosuspend bookkeeping for one device
+#[derive(Debug, Clone, Copy)]
+pub struct UsbAutosuspendTimer {
+    pub last_activity_ms: u64,
+    /// Outstanding users; the device is never autosuspended while non-zero
+    pub usage_count: u32,
+    pub state_since_ms: u64,
+}
+
 /// USB Power Event Type
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum UsbPowerEvent {
@@ -57,6 +216,8 @@ pub enum UsbPowerEvent {
     OvercurrentDetected,
     VoltageAnomalyDetected,
     ThermalLimitReached,
+    PowerBudgetDenied,
+    LinkStateChanged,
 }
 
 /// USB Device Power Configuration
@@ -112,6 +273,9 @@ pub struct UsbPowerStats {
     pub resume_count: u32,
     pub remote_wakeup_count: u32,
     pub power_policy_changes: u32,
+    pub autosuspend_count: u32,
+    pub link_transitions: u32,
+    pub budget_denials: u32,
 }
 
 /// USB Power Manager
@@ -127,6 +291,12 @@ pub struct UsbPowerManager {
     pub current_temperature_c: f32,
     pub power_limit_enforced: bool,
     pub idle_timeout_active: bool,
+    pub port_budgets: BTreeMap<(u8, u8), UsbPortPowerBudget>,
+    pub hub_budgets: BTreeMap<u8, UsbHubPowerBudget>,
+    pub device_links: BTreeMap<u8, UsbDeviceLink>,
+    pub autosuspend_timers: BTreeMap<u8, UsbAutosuspendTimer>,
+    /// Time of the last `poll`, in milliseconds
+    pub now_ms: u64,
 }
 
 /// USB Power Policy Manager
@@ -247,6 +417,11 @@ impl UsbPowerManager {
             current_temperature_c: 25.0,
             power_limit_enforced: false,
             idle_timeout_active: false,
+            port_budgets: BTreeMap::new(),
+            hub_budgets: BTreeMap::new(),
+            device_links: BTreeMap::new(),
+            autosuspend_timers: BTreeMap::new(),
+            now_ms: 0,
         }
     }
 
@@ -385,6 +560,14 @@ impl UsbPowerManager {
             resume_count: 0,
             remote_wakeup_count: 0,
             power_policy_changes: 0,
+            autosuspend_count: 0,
+            link_transitions: 0,
+            budget_denials: 0,
+        });
+        self.autosuspend_timers.insert(device_address, UsbAutosuspendTimer {
+            last_activity_ms: self.now_ms,
+            usage_count: 0,
+            state_since_ms: self.now_ms,
         });
 
         log::info!("Device {} registered for power management (budget: {} mA)", 
@@ -526,6 +709,7 @@ impl UsbPowerManager {
         self.device_states.insert(device_address, UsbPowerManagementState::Suspended);
 
         // Update statistics
+        self.update_state_statistics(device_address, current_state, UsbPowerManagementState::Suspended);
         if let Some(stats) = self.power_stats.get_mut(&device_address) {
             stats.suspend_count += 1;
         }
@@ -553,9 +737,13 @@ impl UsbPowerManager {
         self.device_states.insert(device_address, UsbPowerManagementState::Active);
 
         // Update statistics
+        self.update_state_statistics(device_address, current_state, UsbPowerManagementState::Active);
         if let Some(stats) = self.power_stats.get_mut(&device_address) {
             stats.resume_count += 1;
         }
+        if let Some(timer) = self.autosuspend_timers.get_mut(&device_address) {
+            timer.last_activity_ms = self.now_ms;
+        }
 
         // Trigger event
         self.trigger_power_event(UsbPowerEvent::DeviceResumed, device_address);
@@ -615,18 +803,26 @@ impl UsbPowerManager {
 
     /// Update statistics for state transition
     fn update_state_statistics(&mut self, device_address: u8, old_state: UsbPowerManagementState, new_state: UsbPowerManagementState) {
-        if let Some(stats) = self.power_stats.get_mut(&device_address) {
-            let current_time = 0; // TODO: Add timestamp
+        let now = self.now_ms;
+        let elapsed = match self.autosuspend_timers.get_mut(&device_address) {
+            Some(timer) => {
+                let elapsed = now.saturating_sub(timer.state_since_ms);
+                timer.state_since_ms = now;
+                elapsed
+            }
+            None => 0,
+        };
 
+        if let Some(stats) = self.power_stats.get_mut(&device_address) {
             match old_state {
                 UsbPowerManagementState::Active => {
-                    stats.time_in_active_ms += current_time;
+                    stats.time_in_active_ms += elapsed;
                 }
                 UsbPowerManagementState::Suspended | UsbPowerManagementState::SelectiveSuspended => {
-                    stats.time_in_suspended_ms += current_time;
+                    stats.time_in_suspended_ms += elapsed;
                 }
                 UsbPowerManagementState::PoweredDown => {
-                    stats.time_in_powered_down_ms += current_time;
+                    stats.time_in_powered_down_ms += elapsed;
                 }
                 _ => {}
             }
@@ -662,8 +858,9 @@ impl UsbPowerManager {
         self.system_config.global_power_policy = policy;
 
         // Apply policy to all devices
-        for (device_address, config) in &mut self.device_configs {
-            self.apply_power_policy(*device_address, policy)?;
+        let devices: Vec<u8> = self.device_configs.keys().copied().collect();
+        for device_address in devices {
+            self.apply_power_policy(device_address, policy)?;
         }
 
         // Trigger event
@@ -679,6 +876,9 @@ impl UsbPowerManager {
             .ok_or(UsbDriverError::DeviceNotFound { address: device_address })?;
 
         config.power_policy = policy;
+        if let Some(stats) = self.power_stats.get_mut(&device_address) {
+            stats.power_policy_changes += 1;
+        }
 
         // Update device parameters based on policy
         match policy {
@@ -717,10 +917,14 @@ impl UsbPowerManager {
         }
 
         // Check if device has been idle based on timeout
-        let current_time = 0; // TODO: Add timestamp
-        let idle_timeout = config.idle_timeout_ms;
+        let idle_timeout = config.idle_timeout_ms as u64;
+        let idle = match self.autosuspend_timers.get(&device_address) {
+            Some(timer) => timer.usage_count == 0
+                && self.now_ms.saturating_sub(timer.last_activity_ms) >= idle_timeout,
+            None => false,
+        };
 
-        if current_time > idle_timeout {
+        if idle {
             self.trigger_power_event(UsbPowerEvent::IdleTimeoutReached, device_address);
             return Ok(true);
         }
@@ -741,6 +945,9 @@ impl UsbPowerManager {
         if current_state != UsbPowerManagementState::Active {
             self.resume_device(device_address)?;
         }
+        if let Some(timer) = self.autosuspend_timers.get_mut(&device_address) {
+            timer.last_activity_ms = self.now_ms;
+        }
 
         log::debug!("Device {} activity detected", device_address);
         Ok(())
@@ -801,8 +1008,12 @@ impl UsbPowerManager {
             self.power_budget.available_power_ma = self.power_budget.total_power_ma - self.power_budget.allocated_power_ma;
         }
 
+        // Release the port it was drawing from
+        self.detach_device(device_address);
+
         // Remove statistics
         self.power_stats.remove(&device_address);
+        self.autosuspend_timers.remove(&device_address);
 
         log::info!("Device {} unregistered from power management", device_address);
         Ok(())
@@ -872,6 +1083,365 @@ impl UsbPowerManager {
         log::info!("Power budget changed: {} mA -> {} mA", old_total, total_ma);
         Ok(())
     }
+
+    /// Register a root hub port
+    pub fn register_root_port(&mut self, port: u8, superspeed: bool) {
+        let max_current_ma = if superspeed { USB3_ROOT_PORT_MA } else { USB2_ROOT_PORT_MA };
+        self.port_budgets.insert((0, port), UsbPortPowerBudget {
+            hub_address: 0,
+            port,
+            max_current_ma,
+            allocated_ma: 0,
+            device_address: None,
+            power_switching: true,
+        });
+    }
+
+    /// Register the downstream ports of an external hub
+    ///
+    /// Self-powered hubs supply a full port's worth of current to every port.
+    /// Bus-powered hubs supply one unit load per port, and all ports share
+    /// what the hub draws upstream minus its own controller current.
+    pub fn register_hub_ports(
+        &mut self,
+        hub_address: u8,
+        descriptor: &UsbHubDescriptor,
+        self_powered: bool,
+        superspeed: bool,
+        upstream_ma: u32,
+    ) {
+        let per_port_ma = match (self_powered, superspeed) {
+            (true, true) => USB3_ROOT_PORT_MA,
+            (true, false) => USB2_ROOT_PORT_MA,
+            (false, true) => USB3_UNIT_LOAD_MA,
+            (false, false) => USB2_UNIT_LOAD_MA,
+        };
+        let total_ma = if self_powered {
+            per_port_ma * descriptor.bNbrPorts as u32
+        } else {
+            upstream_ma.saturating_sub(descriptor.bHubContrCurrent as u32)
+        };
+        // Logical power switching mode: 00 ganged, 01 per-port
+        let power_switching = descriptor.wHubCharacteristics & 0x3 == 0x1;
+
+        self.hub_budgets.insert(hub_address, UsbHubPowerBudget {
+            hub_address,
+            self_powered,
+            total_ma,
+            allocated_ma: 0,
+        });
+        for port in 1..=descriptor.bNbrPorts {
+            self.port_budgets.insert((hub_address, port), UsbPortPowerBudget {
+                hub_address,
+                port,
+                max_current_ma: per_port_ma,
+                allocated_ma: 0,
+                device_address: None,
+                power_switching,
+            });
+        }
+
+        log::info!("Hub {} power: {} ports at {} mA ({} mA total, {})",
+                  hub_address, descriptor.bNbrPorts, per_port_ma, total_ma,
+                  if self_powered { "self-powered" } else { "bus-powered" });
+    }
+
+    /// Attach a registered device to a port, charging its configuration's
+    /// current against the port and hub budgets
+    ///
+    /// A device that asks for more than the port can supply must be left
+    /// unconfigured; the denial is counted and reported as an event.
+    pub fn attach_device(
+        &mut self,
+        device_address: u8,
+        hub_address: u8,
+        port: u8,
+        speed: UsbSpeed,
+        requested_ma: u32,
+        lpm_capable: bool,
+    ) -> UsbResult<()> {
+        if !self.device_configs.contains_key(&device_address) {
+            return Err(UsbDriverError::DeviceNotFound { address: device_address });
+        }
+
+        let budget = self.port_budgets.get(&(hub_address, port))
+            .ok_or(UsbDriverError::InvalidConfiguration)?;
+        if budget.device_address.is_some_and(|other| other != device_address) {
+            return Err(UsbDriverError::InvalidConfiguration);
+        }
+
+        let hub_available = self.hub_budgets.get(&hub_address)
+            .map(|hub| hub.total_ma.saturating_sub(hub.allocated_ma))
+            .unwrap_or(u32::MAX);
+        if requested_ma > budget.max_current_ma || requested_ma > hub_available {
+            log::warn!("Device {} needs {} mA but hub {} port {} can supply {} mA",
+                      device_address, requested_ma, hub_address, port,
+                      budget.max_current_ma.min(hub_available));
+            if let Some(stats) = self.power_stats.get_mut(&device_address) {
+                stats.budget_denials += 1;
+            }
+            self.trigger_power_event(UsbPowerEvent::PowerBudgetDenied, device_address);
+            return Err(UsbDriverError::PowerManagementError);
+        }
+
+        if let Some(budget) = self.port_budgets.get_mut(&(hub_address, port)) {
+            budget.allocated_ma = requested_ma;
+            budget.device_address = Some(device_address);
+        }
+        if let Some(hub) = self.hub_budgets.get_mut(&hub_address) {
+            hub.allocated_ma += requested_ma;
+        }
+        self.device_links.insert(device_address, UsbDeviceLink {
+            hub_address,
+            port,
+            speed,
+            link_state: UsbLinkPowerState::active_for(speed),
+            lpm_capable,
+            remote_wakeup_armed: false,
+        });
+
+        log::info!("Device {} attached to hub {} port {} drawing {} mA",
+                  device_address, hub_address, port, requested_ma);
+        Ok(())
+    }
+
+    /// Release the port a device was attached to
+    pub fn detach_device(&mut self, device_address: u8) {
+        let link = match self.device_links.remove(&device_address) {
+            Some(link) => link,
+            None => return,
+        };
+        if let Some(budget) = self.port_budgets.get_mut(&(link.hub_address, link.port)) {
+            if let Some(hub) = self.hub_budgets.get_mut(&link.hub_address) {
+                hub.allocated_ma = hub.allocated_ma.saturating_sub(budget.allocated_ma);
+            }
+            budget.allocated_ma = 0;
+            budget.device_address = None;
+        }
+    }
+
+    /// Take an autosuspend reference; the device stays active until it is
+    /// released with `autosuspend_put`
+    pub fn autosuspend_get<H: UsbPowerHost>(&mut self, host: &mut H, device_address: u8) -> UsbResult<()> {
+        let timer = self.autosuspend_timers.get_mut(&device_address)
+            .ok_or(UsbDriverError::DeviceNotFound { address: device_address })?;
+        timer.usage_count += 1;
+
+        if self.get_device_power_state(device_address)? != UsbPowerManagementState::Active {
+            self.resume_link(host, device_address)?;
+        }
+        Ok(())
+    }
+
+    /// Drop an autosuspend reference and restart the idle timer
+    pub fn autosuspend_put(&mut self, device_address: u8) -> UsbResult<()> {
+        let timer = self.autosuspend_timers.get_mut(&device_address)
+            .ok_or(UsbDriverError::DeviceNotFound { address: device_address })?;
+        timer.usage_count = timer.usage_count.saturating_sub(1);
+        timer.last_activity_ms = self.now_ms;
+        Ok(())
+    }
+
+    /// Advance the clock and selectively suspend devices whose idle timer
+    /// has expired; returns the devices suspended by this call
+    pub fn poll<H: UsbPowerHost>(&mut self, host: &mut H, now_ms: u64) -> UsbResult<Vec<u8>> {
+        self.now_ms = now_ms;
+        if !self.system_config.enable_selective_suspend {
+            return Ok(Vec::new());
+        }
+
+        let expired: Vec<u8> = self.device_configs.iter()
+            .filter(|(address, config)| {
+                config.allow_selective_suspend
+                    && config.idle_timeout_ms != u32::MAX
+                    && self.device_states.get(*address) == Some(&UsbPowerManagementState::Active)
+                    && self.autosuspend_timers.get(*address).is_some_and(|timer| {
+                        timer.usage_count == 0
+                            && now_ms.saturating_sub(timer.last_activity_ms) >= config.idle_timeout_ms as u64
+                    })
+            })
+            .map(|(address, _)| *address)
+            .collect();
+
+        let mut suspended = Vec::new();
+        for device_address in expired {
+            self.trigger_power_event(UsbPowerEvent::IdleTimeoutReached, device_address);
+            match self.suspend_link(host, device_address) {
+                Ok(()) => {
+                    if let Some(stats) = self.power_stats.get_mut(&device_address) {
+                        stats.autosuspend_count += 1;
+                    }
+                    suspended.push(device_address);
+                }
+                Err(err) => log::warn!("Autosuspend of device {} failed: {:?}", device_address, err),
+            }
+        }
+        Ok(suspended)
+    }
+
+    /// Selectively suspend a device's link, arming remote wakeup first
+    pub fn suspend_link<H: UsbPowerHost>(&mut self, host: &mut H, device_address: u8) -> UsbResult<()> {
+        let wakeup = self.device_configs.get(&device_address)
+            .map(|config| config.remote_wakeup_enabled && self.system_config.enable_remote_wakeup)
+            .ok_or(UsbDriverError::DeviceNotFound { address: device_address })?;
+        let old_state = self.get_device_power_state(device_address)?;
+        if matches!(old_state, UsbPowerManagementState::SelectiveSuspended | UsbPowerManagementState::Suspended) {
+            return Ok(());
+        }
+
+        if let Some(link) = self.device_links.get(&device_address).cloned() {
+            let superspeed = is_superspeed(link.speed);
+            if wakeup {
+                host.set_remote_wakeup(device_address, superspeed, true)?;
+            }
+            self.set_link_state_unchecked(host, device_address, UsbLinkPowerState::suspended_for(link.speed))?;
+            if let Some(link) = self.device_links.get_mut(&device_address) {
+                link.remote_wakeup_armed = wakeup;
+            }
+        }
+
+        self.device_states.insert(device_address, UsbPowerManagementState::SelectiveSuspended);
+        self.update_state_statistics(device_address, old_state, UsbPowerManagementState::SelectiveSuspended);
+        if let Some(stats) = self.power_stats.get_mut(&device_address) {
+            stats.suspend_count += 1;
+        }
+        self.trigger_power_event(UsbPowerEvent::DeviceSuspended, device_address);
+
+        log::info!("Device {} selectively suspended", device_address);
+        Ok(())
+    }
+
+    /// Resume a suspended device's link and restart its idle timer
+    pub fn resume_link<H: UsbPowerHost>(&mut self, host: &mut H, device_address: u8) -> UsbResult<()> {
+        let old_state = self.get_device_power_state(device_address)?;
+        if old_state == UsbPowerManagementState::Active {
+            return Ok(());
+        }
+
+        if let Some(link) = self.device_links.get(&device_address).cloned() {
+            self.set_link_state_unchecked(host, device_address, UsbLinkPowerState::active_for(link.speed))?;
+            if link.remote_wakeup_armed {
+                host.set_remote_wakeup(device_address, is_superspeed(link.speed), false)?;
+                if let Some(link) = self.device_links.get_mut(&device_address) {
+                    link.remote_wakeup_armed = false;
+                }
+            }
+        }
+
+        self.device_states.insert(device_address, UsbPowerManagementState::Active);
+        self.update_state_statistics(device_address, old_state, UsbPowerManagementState::Active);
+        if let Some(stats) = self.power_stats.get_mut(&device_address) {
+            stats.resume_count += 1;
+        }
+        if let Some(timer) = self.autosuspend_timers.get_mut(&device_address) {
+            timer.last_activity_ms = self.now_ms;
+        }
+        self.trigger_power_event(UsbPowerEvent::DeviceResumed, device_address);
+
+        log::info!("Device {} resumed", device_address);
+        Ok(())
+    }
+
+    /// Handle a resume signalled by the device itself
+    ///
+    /// Returns false if the device had not been allowed to wake the host,
+    /// in which case the wakeup is ignored.
+    pub fn handle_remote_wakeup<H: UsbPowerHost>(&mut self, host: &mut H, device_address: u8) -> UsbResult<bool> {
+        let armed = match self.device_links.get(&device_address) {
+            Some(link) => link.remote_wakeup_armed,
+            None => self.get_device_config(device_address)?.remote_wakeup_enabled,
+        };
+        if !armed {
+            log::warn!("Ignoring remote wakeup from device {} (not armed)", device_address);
+            return Ok(false);
+        }
+
+        self.resume_link(host, device_address)?;
+        if let Some(stats) = self.power_stats.get_mut(&device_address) {
+            stats.remote_wakeup_count += 1;
+        }
+        self.trigger_power_event(UsbPowerEvent::RemoteWakeupTriggered, device_address);
+        Ok(true)
+    }
+
+    /// Enter an LPM state (L1, U1 or U2) or return the link to L0/U0
+    ///
+    /// Suspend and resume go through `suspend_link`/`resume_link`; this is
+    /// for the idle states an active device may drop into between transfers.
+    pub fn set_link_power_state<H: UsbPowerHost>(&mut self, host: &mut H, device_address: u8, state: UsbLinkPowerState) -> UsbResult<()> {
+        let link = self.device_links.get(&device_address)
+            .ok_or(UsbDriverError::DeviceNotFound { address: device_address })?;
+
+        if state.is_suspended() || link.link_state.is_suspended() {
+            return Err(UsbDriverError::PowerManagementError);
+        }
+        if state.is_superspeed() != is_superspeed(link.speed) {
+            return Err(UsbDriverError::UnsupportedFeature);
+        }
+        let entering_lpm = state != UsbLinkPowerState::active_for(link.speed);
+        if entering_lpm && !link.lpm_capable {
+            return Err(UsbDriverError::UnsupportedFeature);
+        }
+        if !link.link_state.can_transition_to(state) {
+            return Err(UsbDriverError::PowerManagementError);
+        }
+
+        self.set_link_state_unchecked(host, device_address, state)
+    }
+
+    fn set_link_state_unchecked<H: UsbPowerHost>(&mut self, host: &mut H, device_address: u8, state: UsbLinkPowerState) -> UsbResult<()> {
+        let link = self.device_links.get_mut(&device_address)
+            .ok_or(UsbDriverError::DeviceNotFound { address: device_address })?;
+        if link.link_state == state {
+            return Ok(());
+        }
+
+        host.set_link_state(link.port, state)?;
+        log::debug!("Device {} link {:?} -> {:?}", device_address, link.link_state, state);
+        link.link_state = state;
+
+        if let Some(stats) = self.power_stats.get_mut(&device_address) {
+            stats.link_transitions += 1;
+        }
+        self.trigger_power_event(UsbPowerEvent::LinkStateChanged, device_address);
+        Ok(())
+    }
+
+    /// Summarize a device's power state and counters
+    pub fn power_info(&self, device_address: u8) -> UsbResult<UsbPowerInfo> {
+        let config = self.get_device_config(device_address)?;
+        let stats = self.get_device_power_stats(device_address)?;
+        let link = self.device_links.get(&device_address);
+
+        let state = match self.get_device_power_state(device_address)? {
+            UsbPowerManagementState::Active | UsbPowerManagementState::Idle | UsbPowerManagementState::TestMode => UsbPowerState::Active,
+            UsbPowerManagementState::Suspended
+            | UsbPowerManagementState::SelectiveSuspended
+            | UsbPowerManagementState::SystemSuspended => UsbPowerState::Suspended,
+            UsbPowerManagementState::PoweredDown => UsbPowerState::PoweredDown,
+            UsbPowerManagementState::Error => UsbPowerState::Off,
+        };
+        let current_draw_ma = link
+            .and_then(|link| self.port_budgets.get(&(link.hub_address, link.port)))
+            .map(|budget| budget.allocated_ma)
+            .or_else(|| self.power_budget.device_budgets.get(&device_address).copied())
+            .unwrap_or(0);
+
+        Ok(UsbPowerInfo {
+            state,
+            current_draw_ma,
+            max_power_ma: config.max_power_ma,
+            port_power_managed: link.is_some(),
+            wakeup_enabled: config.remote_wakeup_enabled,
+            suspend_count: stats.suspend_count,
+            resume_count: stats.resume_count,
+            autosuspend_count: stats.autosuspend_count,
+            remote_wakeup_count: stats.remote_wakeup_count,
+            link_transitions: stats.link_transitions,
+            budget_denials: stats.budget_denials,
+            time_suspended_ms: stats.time_in_suspended_ms,
+        })
+    }
 }
 
 /// USB System Power Statistics
@@ -962,4 +1532,130 @@ mod tests {
         assert_eq!(stats.active_devices, 0);
         assert_eq!(stats.current_power_ma, 0);
     }
-}
\ No newline at end of file
+
+    #[derive(Default)]
+    struct MockPowerHost {
+        links: Vec<(u8, UsbLinkPowerState)>,
+        requests: Vec<(u8, u8, u16)>,
+    }
+
+    impl UsbPowerHost for MockPowerHost {
+        fn set_link_state(&mut self, port: u8, state: UsbLinkPowerState) -> UsbResult<()> {
+            self.links.push((port, state));
+            Ok(())
+        }
+
+        fn control_transfer(&mut self, address: u8, setup: &UsbSetupPacket, _data: &mut [u8]) -> UsbResult<usize> {
+            self.requests.push((address, setup.bRequest, setup.wValue));
+            Ok(0)
+        }
+    }
+
+    fn hub_descriptor(ports: u8) -> UsbHubDescriptor {
+        UsbHubDescriptor {
+            bLength: 9,
+            bDescriptorType: 0x29,
+            bNbrPorts: ports,
+            wHubCharacteristics: 0x0001,
+            bPwrOn2PwrGood: 50,
+            bHubContrCurrent: 100,
+            deviceRemovable: 0,
+            portPwrCtrlMask: 0xFF,
+        }
+    }
+
+    #[test]
+    fn test_autosuspend_and_remote_wakeup() {
+        let mut manager = UsbPowerManager::new();
+        let mut host = MockPowerHost::default();
+        manager.register_root_port(1, false);
+        manager.register_device(1, UsbClass::HID).unwrap();
+        manager.attach_device(1, 0, 1, UsbSpeed::Full, 100, false).unwrap();
+
+        // A held reference blocks autosuspend past the idle timeout
+        manager.autosuspend_get(&mut host, 1).unwrap();
+        assert!(manager.poll(&mut host, 5000).unwrap().is_empty());
+        manager.autosuspend_put(1).unwrap();
+        assert!(manager.poll(&mut host, 6000).unwrap().is_empty());
+
+        assert_eq!(manager.poll(&mut host, 7000).unwrap(), vec![1]);
+        assert_eq!(manager.get_device_power_state(1).unwrap(), UsbPowerManagementState::SelectiveSuspended);
+        assert_eq!(host.links, vec![(1, UsbLinkPowerState::L2)]);
+        assert_eq!(host.requests, vec![(1, UsbStandardRequest::SET_FEATURE as u8, 1)]);
+
+        manager.now_ms = 9000;
+        assert!(manager.handle_remote_wakeup(&mut host, 1).unwrap());
+        assert_eq!(host.links.last(), Some(&(1, UsbLinkPowerState::L0)));
+        assert_eq!(host.requests.last(), Some(&(1, UsbStandardRequest::CLEAR_FEATURE as u8, 1)));
+        // Disarmed again, so a spurious wakeup is ignored
+        assert!(!manager.handle_remote_wakeup(&mut host, 1).unwrap());
+
+        let info = manager.power_info(1).unwrap();
+        assert_eq!(info.state, UsbPowerState::Active);
+        assert_eq!(info.current_draw_ma, 100);
+        assert_eq!((info.suspend_count, info.resume_count), (1, 1));
+        assert_eq!((info.autosuspend_count, info.remote_wakeup_count), (1, 1));
+        assert_eq!(info.link_transitions, 2);
+        assert_eq!(info.time_suspended_ms, 2000);
+    }
+
+    #[test]
+    fn test_link_power_management() {
+        let mut manager = UsbPowerManager::new();
+        let mut host = MockPowerHost::default();
+        manager.register_root_port(1, true);
+        manager.register_root_port(2, false);
+        manager.register_device(1, UsbClass::MassStorage).unwrap();
+        manager.register_device(2, UsbClass::HID).unwrap();
+        manager.attach_device(1, 0, 1, UsbSpeed::Super, 896, true).unwrap();
+        manager.attach_device(2, 0, 2, UsbSpeed::High, 100, false).unwrap();
+
+        manager.set_link_power_state(&mut host, 1, UsbLinkPowerState::U1).unwrap();
+        manager.set_link_power_state(&mut host, 1, UsbLinkPowerState::U2).unwrap();
+        // U2 cannot step back up to U1
+        assert!(matches!(manager.set_link_power_state(&mut host, 1, UsbLinkPowerState::U1),
+            Err(UsbDriverError::PowerManagementError)));
+        manager.set_link_power_state(&mut host, 1, UsbLinkPowerState::U0).unwrap();
+        // USB 2.0 state on a SuperSpeed link
+        assert!(matches!(manager.set_link_power_state(&mut host, 1, UsbLinkPowerState::L1),
+            Err(UsbDriverError::UnsupportedFeature)));
+        // Device without LPM support
+        assert!(matches!(manager.set_link_power_state(&mut host, 2, UsbLinkPowerState::L1),
+            Err(UsbDriverError::UnsupportedFeature)));
+
+        assert_eq!(host.links, vec![
+            (1, UsbLinkPowerState::U1),
+            (1, UsbLinkPowerState::U2),
+            (1, UsbLinkPowerState::U0),
+        ]);
+        assert_eq!(manager.power_info(1).unwrap().link_transitions, 3);
+    }
+
+    #[test]
+    fn test_port_power_budget() {
+        let mut manager = UsbPowerManager::new();
+        // Bus-powered hub drawing 500 mA, 100 mA for itself
+        manager.register_hub_ports(5, &hub_descriptor(4), false, false, 500);
+        for address in 1..=4 {
+            manager.register_device(address, UsbClass::HID).unwrap();
+        }
+        manager.register_device(6, UsbClass::MassStorage).unwrap();
+
+        // 500 mA device on a one-unit-load port
+        assert!(matches!(manager.attach_device(6, 5, 1, UsbSpeed::High, 500, false),
+            Err(UsbDriverError::PowerManagementError)));
+        assert_eq!(manager.power_info(6).unwrap().budget_denials, 1);
+
+        for address in 1..=4 {
+            manager.attach_device(address, 5, address, UsbSpeed::Full, 100, false).unwrap();
+        }
+        assert_eq!(manager.hub_budgets[&5].allocated_ma, 400);
+        // Port is taken
+        assert!(manager.attach_device(6, 5, 1, UsbSpeed::Full, 100, false).is_err());
+
+        manager.unregister_device(4).unwrap();
+        assert_eq!(manager.hub_budgets[&5].allocated_ma, 300);
+        assert_eq!(manager.port_budgets[&(5, 4)].device_address, None);
+        manager.attach_device(6, 5, 4, UsbSpeed::Full, 100, false).unwrap();
+    }
+}
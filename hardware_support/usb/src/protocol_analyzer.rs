@@ -5,12 +5,39 @@
 //! and educational explanations of USB concepts.
 
 use core::fmt;
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
 use alloc::vec::Vec;
 use alloc::string::String;
-use crate::UsbResult;
+use crate::{UsbResult, UsbDriverError, UsbPacketFilter, UsbTransactionType};
+
+/// pcapng link-layer type for Linux usbmon records with the 64-byte header
+pub const LINKTYPE_USB_LINUX_MMAPPED: u16 = 220;
+
+/// Length of a usbmon binary record header
+pub const USBMON_HEADER_LEN: usize = 64;
+
+/// Default cap on captured payload bytes held in memory
+pub const DEFAULT_MAX_CAPTURE_BYTES: usize = 4 * 1024 * 1024;
+
+const PCAPNG_SHB: u32 = 0x0A0D_0D0A;
+const PCAPNG_IDB: u32 = 0x0000_0001;
+const PCAPNG_EPB: u32 = 0x0000_0006;
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+const PCAPNG_OPT_ENDOFOPT: u16 = 0;
+const PCAPNG_OPT_IF_TSRESOL: u16 = 9;
+const PCAPNG_SNAPLEN: u32 = 0x0004_0000;
+
+const USB_PID_OUT: u8 = 0xE1;
+const USB_PID_IN: u8 = 0x69;
+const USB_PID_SETUP: u8 = 0x2D;
+const USB_PID_STALL: u8 = 0x1E;
+
+const EINPROGRESS: i32 = 115;
+const EPIPE: i32 = 32;
 
 /// USB transfer types
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum TransferType {
     Control,
     Interrupt,
@@ -322,14 +349,14 @@ impl DescriptorDecoder {
     /// Decode USB descriptor data
     pub fn decode_descriptor(&self, descriptor_data: &[u8]) -> UsbResult<String> {
         if descriptor_data.len() < 2 {
-            return Err(UsbResult::InvalidBuffer);
+            return Err(UsbDriverError::ProtocolError);
         }
 
         let descriptor_type = DescriptorType::from_u8(descriptor_data[1]);
         let descriptor_length = descriptor_data[0] as usize;
 
         if descriptor_data.len() < descriptor_length {
-            return Err(UsbResult::InvalidBuffer);
+            return Err(UsbDriverError::ProtocolError);
         }
 
         let mut result = String::new();
@@ -381,6 +408,7 @@ impl DescriptorDecoder {
         // bDeviceClass
         let device_class = data[4];
         result.push_str(&format!("bDeviceClass: 0x{:02X} ", device_class));
+        let unknown_class = format!("(Reserved/Unknown class: 0x{:02X})", device_class);
         result.push_str(&match device_class {
             0x00 => "(Defined at interface level)",
             0x01 => "(Audio class)",
@@ -391,7 +419,7 @@ impl DescriptorDecoder {
             0x0E => "(Video class)",
             0x0D => "(Content security class)",
             0x11 => "(Billboard class)",
-            other => &format!("(Reserved/Unknown class: 0x{:02X})", other),
+            _ => &unknown_class,
         });
         result.push_str("\n");
 
@@ -510,6 +538,7 @@ impl DescriptorDecoder {
         // bInterfaceClass
         let interface_class = data[5];
         result.push_str(&format!("bInterfaceClass: 0x{:02X} ", interface_class));
+        let vendor_class = format!("(Vendor specific class: 0x{:02X})", interface_class);
         result.push_str(&match interface_class {
             0x00 => "(Device class)",
             0x01 => "(Audio class)",
@@ -532,7 +561,7 @@ impl DescriptorDecoder {
             0x14 => "(Miscellaneous class)",
             0x16 => "(Application specific)",
             0x1B => "(USB Type-C Bridge class)",
-            other => &format!("(Vendor specific class: 0x{:02X})", other),
+            _ => &vendor_class,
         });
         result.push_str("\n");
 
@@ -616,7 +645,7 @@ impl DescriptorDecoder {
         for i in (2..data.len()).step_by(2) {
             if i + 1 < data.len() {
                 let char = (data[i + 1] as u16) << 8 | data[i] as u16;
-                string_data.push(char as char);
+                string_data.push(char::from_u32(char as u32).unwrap_or(char::REPLACEMENT_CHARACTER));
             }
         }
 
@@ -701,27 +730,129 @@ impl DescriptorDecoder {
     }
 }
 
+/// Receiver for transactions as they are captured
+pub trait CaptureSubscriber: Send {
+    /// Called for every transaction that passes the capture filter
+    fn on_transaction(&mut self, transaction: &UsbTransaction);
+}
+
+/// Handle returned by `ProtocolAnalyzer::subscribe`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SubscriberId(u32);
+
+/// One compiled filter rule; every field that is set must match
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FilterRule {
+    device_address: Option<u8>,
+    class: Option<u8>,
+    endpoint: Option<u8>,
+    transaction: Option<UsbTransactionType>,
+}
+
+/// Capture filter compiled from `UsbPacketFilter`s
+///
+/// A transaction is kept if it matches any rule; an empty filter keeps
+/// everything.
+#[derive(Debug, Clone, Default)]
+pub struct CaptureFilter {
+    rules: Vec<FilterRule>,
+}
+
+impl CaptureFilter {
+    /// Compile a set of packet filters
+    ///
+    /// `device_filter` carries the bus number in its high byte and the
+    /// device address in its low byte; a zero bus matches any bus.
+    pub fn compile(filters: &[UsbPacketFilter]) -> Self {
+        let rules = filters.iter()
+            .map(|filter| FilterRule {
+                device_address: filter.device_filter.map(|device| (device & 0x7F) as u8),
+                class: filter.class_filter,
+                endpoint: filter.endpoint_filter.map(|endpoint| endpoint & 0x0F),
+                transaction: filter.transaction_filter,
+            })
+            .collect();
+        Self { rules }
+    }
+
+    /// Whether the filter keeps every transaction
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Check a transaction against the filter
+    ///
+    /// `device_class` looks up the interface class recorded for a device.
+    pub fn matches(&self, transaction: &UsbTransaction, device_class: impl Fn(u8) -> Option<u8>) -> bool {
+        if self.rules.is_empty() {
+            return true;
+        }
+        self.rules.iter().any(|rule| {
+            rule.device_address.map_or(true, |address| address == transaction.device_address)
+                && rule.endpoint.map_or(true, |endpoint| endpoint == transaction.endpoint_number & 0x0F)
+                && rule.class.map_or(true, |class| device_class(transaction.device_address) == Some(class))
+                && rule.transaction.map_or(true, |kind| {
+                    transaction.packets.iter().any(|packet| packet_matches(packet, kind))
+                })
+        })
+    }
+}
+
+/// Whether a packet belongs to a transaction phase
+fn packet_matches(packet: &UsbPacket, kind: UsbTransactionType) -> bool {
+    match (kind, packet) {
+        (UsbTransactionType::Setup, UsbPacket::Setup { .. }) => true,
+        (UsbTransactionType::Data, UsbPacket::Data { data }) => !data.data.is_empty(),
+        // The status stage is a zero-length data packet
+        (UsbTransactionType::Status, UsbPacket::Data { data }) => data.data.is_empty(),
+        (UsbTransactionType::Token, UsbPacket::Token { .. }) => true,
+        (UsbTransactionType::Handshake, UsbPacket::Handshake { .. }) => true,
+        (UsbTransactionType::Split, UsbPacket::Custom { packet_type, .. }) => packet_type == "SPLIT",
+        _ => false,
+    }
+}
+
+/// Capture buffer counters
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CaptureStats {
+    /// Transactions accepted into the buffer
+    pub captured: u64,
+    /// Transactions evicted to stay within the buffer limits
+    pub evicted: u64,
+    /// Transactions rejected by the capture filter
+    pub filtered: u64,
+    /// Deliveries made to live subscribers
+    pub delivered: u64,
+}
+
 /// USB protocol analyzer for capturing and analyzing USB traffic
 pub struct ProtocolAnalyzer {
     /// Enable capture mode
     capture_enabled: bool,
-    /// Captured transactions
-    transactions: Vec<UsbTransaction>,
+    /// Captured transactions, oldest first
+    transactions: VecDeque<UsbTransaction>,
     /// Max transactions to keep
     max_transactions: usize,
+    /// Max payload bytes to keep
+    max_capture_bytes: usize,
+    /// Payload bytes currently buffered
+    captured_bytes: usize,
     /// Enable educational mode
     educational_mode: bool,
+    /// Active capture filter
+    filter: CaptureFilter,
+    /// Interface class per device address, for class filters
+    device_classes: alloc::collections::BTreeMap<u8, u8>,
+    /// Live capture subscribers
+    subscribers: Vec<(SubscriberId, Box<dyn CaptureSubscriber>)>,
+    next_subscriber: u32,
+    stats: CaptureStats,
 }
 
 impl ProtocolAnalyzer {
     /// Create a new protocol analyzer
     pub fn new() -> Self {
-        Self {
-            capture_enabled: false,
-            transactions: Vec::new(),
-            max_transactions: 1000,
-            educational_mode: true,
-        }
+        Self::new_with_config(false, 1000, true)
     }
 
     /// Create protocol analyzer with custom settings
@@ -732,9 +863,16 @@ impl ProtocolAnalyzer {
     ) -> Self {
         Self {
             capture_enabled: enable_capture,
-            transactions: Vec::with_capacity(max_transactions),
+            transactions: VecDeque::with_capacity(max_transactions),
             max_transactions,
+            max_capture_bytes: DEFAULT_MAX_CAPTURE_BYTES,
+            captured_bytes: 0,
             educational_mode,
+            filter: CaptureFilter::default(),
+            device_classes: alloc::collections::BTreeMap::new(),
+            subscribers: Vec::new(),
+            next_subscriber: 0,
+            stats: CaptureStats::default(),
         }
     }
 
@@ -748,28 +886,88 @@ impl ProtocolAnalyzer {
         self.educational_mode = enabled;
     }
 
+    /// Bound the capture buffer by transaction count and payload bytes
+    pub fn set_capture_limits(&mut self, max_transactions: usize, max_capture_bytes: usize) {
+        self.max_transactions = max_transactions;
+        self.max_capture_bytes = max_capture_bytes;
+        self.enforce_limits();
+    }
+
+    /// Replace the capture filter
+    pub fn set_filters(&mut self, filters: &[UsbPacketFilter]) {
+        self.filter = CaptureFilter::compile(filters);
+    }
+
+    /// Record a device's interface class so class filters can match it
+    pub fn set_device_class(&mut self, device_address: u8, class: u8) {
+        self.device_classes.insert(device_address, class);
+    }
+
+    /// Stream captured transactions to `subscriber` as they arrive
+    pub fn subscribe(&mut self, subscriber: Box<dyn CaptureSubscriber>) -> SubscriberId {
+        let id = SubscriberId(self.next_subscriber);
+        self.next_subscriber = self.next_subscriber.wrapping_add(1);
+        self.subscribers.push((id, subscriber));
+        id
+    }
+
+    /// Stop streaming to a subscriber
+    pub fn unsubscribe(&mut self, id: SubscriberId) -> Option<Box<dyn CaptureSubscriber>> {
+        let index = self.subscribers.iter().position(|(sub_id, _)| *sub_id == id)?;
+        Some(self.subscribers.remove(index).1)
+    }
+
+    /// Capture buffer counters
+    pub fn capture_stats(&self) -> CaptureStats {
+        self.stats
+    }
+
     /// Add a transaction to the analyzer
+    ///
+    /// Transactions that pass the filter are streamed to subscribers and
+    /// buffered; the oldest ones are evicted once the buffer is full.
     pub fn add_transaction(&mut self, transaction: UsbTransaction) {
         if !self.capture_enabled {
             return;
         }
 
-        self.transactions.push(transaction);
+        let classes = &self.device_classes;
+        if !self.filter.matches(&transaction, |address| classes.get(&address).copied()) {
+            self.stats.filtered += 1;
+            return;
+        }
 
-        // Limit transaction count
-        if self.transactions.len() > self.max_transactions {
-            self.transactions.remove(0);
+        for (_, subscriber) in self.subscribers.iter_mut() {
+            subscriber.on_transaction(&transaction);
+            self.stats.delivered += 1;
         }
+
+        self.captured_bytes += transaction_payload_len(&transaction);
+        self.transactions.push_back(transaction);
+        self.stats.captured += 1;
+        self.enforce_limits();
     }
 
-    /// Get captured transactions
-    pub fn get_transactions(&self) -> &[UsbTransaction] {
-        &self.transactions
+    fn enforce_limits(&mut self) {
+        while self.transactions.len() > self.max_transactions
+            || (self.captured_bytes > self.max_capture_bytes && !self.transactions.is_empty())
+        {
+            if let Some(evicted) = self.transactions.pop_front() {
+                self.captured_bytes -= transaction_payload_len(&evicted);
+                self.stats.evicted += 1;
+            }
+        }
+    }
+
+    /// Get captured transactions, oldest first
+    pub fn get_transactions(&self) -> impl Iterator<Item = &UsbTransaction> {
+        self.transactions.iter()
     }
 
     /// Clear captured transactions
     pub fn clear_transactions(&mut self) {
         self.transactions.clear();
+        self.captured_bytes = 0;
     }
 
     /// Analyze captured transactions and generate report
@@ -829,7 +1027,7 @@ impl ProtocolAnalyzer {
     /// Decode SETUP packet
     pub fn decode_setup_packet(&self, setup_data: &[u8]) -> UsbResult<String> {
         if setup_data.len() != 8 {
-            return Err(UsbResult::InvalidBuffer);
+            return Err(UsbDriverError::ProtocolError);
         }
 
         let mut result = String::new();
@@ -996,6 +1194,59 @@ impl ProtocolAnalyzer {
         export
     }
 
+    /// Export the capture as pcapng with usbmon records
+    ///
+    /// Each transaction becomes a submit and a completion record on bus 1,
+    /// the pairing Wireshark expects. Timestamps are nanoseconds.
+    pub fn export_pcapng(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+
+        // Section header block
+        let shb_start = pcapng_begin_block(&mut out, PCAPNG_SHB);
+        out.extend_from_slice(&PCAPNG_BYTE_ORDER_MAGIC.to_le_bytes());
+        out.extend_from_slice(&1u16.to_le_bytes());
+        out.extend_from_slice(&0u16.to_le_bytes());
+        out.extend_from_slice(&(-1i64).to_le_bytes());
+        pcapng_end_block(&mut out, shb_start);
+
+        // Interface description block with nanosecond timestamps
+        let idb_start = pcapng_begin_block(&mut out, PCAPNG_IDB);
+        out.extend_from_slice(&LINKTYPE_USB_LINUX_MMAPPED.to_le_bytes());
+        out.extend_from_slice(&0u16.to_le_bytes());
+        out.extend_from_slice(&PCAPNG_SNAPLEN.to_le_bytes());
+        out.extend_from_slice(&PCAPNG_OPT_IF_TSRESOL.to_le_bytes());
+        out.extend_from_slice(&1u16.to_le_bytes());
+        out.extend_from_slice(&[9, 0, 0, 0]);
+        out.extend_from_slice(&PCAPNG_OPT_ENDOFOPT.to_le_bytes());
+        out.extend_from_slice(&0u16.to_le_bytes());
+        pcapng_end_block(&mut out, idb_start);
+
+        for (id, transaction) in self.transactions.iter().enumerate() {
+            for submit in [true, false] {
+                let record = usbmon_record(id as u64, transaction, submit);
+                let timestamp = if submit {
+                    transaction.timestamp
+                } else {
+                    transaction.timestamp + transaction.duration_ns
+                };
+
+                let epb_start = pcapng_begin_block(&mut out, PCAPNG_EPB);
+                out.extend_from_slice(&0u32.to_le_bytes());
+                out.extend_from_slice(&((timestamp >> 32) as u32).to_le_bytes());
+                out.extend_from_slice(&(timestamp as u32).to_le_bytes());
+                out.extend_from_slice(&(record.len() as u32).to_le_bytes());
+                out.extend_from_slice(&(record.len() as u32).to_le_bytes());
+                out.extend_from_slice(&record);
+                while out.len() % 4 != 0 {
+                    out.push(0);
+                }
+                pcapng_end_block(&mut out, epb_start);
+            }
+        }
+
+        out
+    }
+
     /// Generate educational tutorial content
     pub fn generate_tutorial(&self, topic: &str) -> String {
         match topic.to_lowercase().as_str() {
@@ -1186,8 +1437,247 @@ impl ProtocolAnalyzer {
     }
 }
 
+/// Start a pcapng block, leaving its length to be patched in
+fn pcapng_begin_block(out: &mut Vec<u8>, block_type: u32) -> usize {
+    let start = out.len();
+    out.extend_from_slice(&block_type.to_le_bytes());
+    out.extend_from_slice(&0u32.to_le_bytes());
+    start
+}
+
+/// Close a pcapng block with its trailing length and patch the leading one
+fn pcapng_end_block(out: &mut Vec<u8>, start: usize) {
+    let length = (out.len() - start + 4) as u32;
+    out.extend_from_slice(&length.to_le_bytes());
+    out[start + 4..start + 8].copy_from_slice(&length.to_le_bytes());
+}
+
+/// Payload bytes carried by a transaction's data packets
+fn transaction_payload_len(transaction: &UsbTransaction) -> usize {
+    transaction.packets.iter()
+        .map(|packet| match packet {
+            UsbPacket::Data { data } => data.data.len(),
+            UsbPacket::Custom { data, .. } => data.len(),
+            _ => 0,
+        })
+        .sum()
+}
+
+/// Direction of a transaction: the setup request direction for control
+/// transfers, otherwise the token PID or the endpoint direction bit
+fn transaction_is_in(transaction: &UsbTransaction) -> bool {
+    for packet in &transaction.packets {
+        match packet {
+            UsbPacket::Setup { setup } => return setup.request_type & 0x80 != 0,
+            UsbPacket::Token { token } if token.pid == USB_PID_IN => return true,
+            UsbPacket::Token { token } if token.pid == USB_PID_OUT || token.pid == USB_PID_SETUP => return false,
+            _ => {}
+        }
+    }
+    transaction.endpoint_number & 0x80 != 0
+}
+
+/// Build one usbmon binary record (header plus captured data)
+fn usbmon_record(id: u64, transaction: &UsbTransaction, submit: bool) -> Vec<u8> {
+    let direction_in = transaction_is_in(transaction);
+    let setup = transaction.packets.iter().find_map(|packet| match packet {
+        UsbPacket::Setup { setup } => Some(setup),
+        _ => None,
+    });
+    let stalled = transaction.packets.iter().any(|packet| {
+        matches!(packet, UsbPacket::Handshake { handshake } if handshake.pid == USB_PID_STALL)
+    });
+    let mut payload = Vec::new();
+    for packet in &transaction.packets {
+        if let UsbPacket::Data { data } = packet {
+            payload.extend_from_slice(&data.data);
+        }
+    }
+
+    // OUT data travels with the submission, IN data with the completion
+    let carries_data = !payload.is_empty() && (submit != direction_in);
+    let length = setup.map_or(payload.len(), |setup| setup.length as usize);
+    let timestamp = if submit { transaction.timestamp } else { transaction.timestamp + transaction.duration_ns };
+    let status = match (submit, stalled) {
+        (true, _) => -EINPROGRESS,
+        (false, true) => -EPIPE,
+        (false, false) => 0,
+    };
+    let xfer_type: u8 = match transaction.transfer_type {
+        TransferType::Isochronous => 0,
+        TransferType::Interrupt => 1,
+        TransferType::Control => 2,
+        TransferType::Bulk => 3,
+    };
+    let flag_data = if carries_data {
+        0
+    } else if direction_in {
+        b'<'
+    } else {
+        b'>'
+    };
+
+    let mut record = Vec::with_capacity(USBMON_HEADER_LEN + payload.len());
+    record.extend_from_slice(&id.to_le_bytes());
+    record.push(if submit { b'S' } else { b'C' });
+    record.push(xfer_type);
+    record.push((transaction.endpoint_number & 0x0F) | if direction_in { 0x80 } else { 0 });
+    record.push(transaction.device_address);
+    record.extend_from_slice(&1u16.to_le_bytes());
+    record.push(if submit && setup.is_some() { 0 } else { b'-' });
+    record.push(flag_data);
+    record.extend_from_slice(&((timestamp / 1_000_000_000) as i64).to_le_bytes());
+    record.extend_from_slice(&(((timestamp % 1_000_000_000) / 1_000) as i32).to_le_bytes());
+    record.extend_from_slice(&status.to_le_bytes());
+    record.extend_from_slice(&(if submit { length } else { payload.len() } as u32).to_le_bytes());
+    record.extend_from_slice(&(if carries_data { payload.len() } else { 0 } as u32).to_le_bytes());
+    match setup {
+        Some(setup) if submit => {
+            record.push(setup.request_type);
+            record.push(setup.request);
+            record.extend_from_slice(&setup.value.to_le_bytes());
+            record.extend_from_slice(&setup.index.to_le_bytes());
+            record.extend_from_slice(&setup.length.to_le_bytes());
+        }
+        _ => record.extend_from_slice(&[0; 8]),
+    }
+    // interval, start_frame, xfer_flags, ndesc
+    record.extend_from_slice(&[0; 16]);
+    if carries_data {
+        record.extend_from_slice(&payload);
+    }
+    record
+}
+
 impl Default for ProtocolAnalyzer {
     fn default() -> Self {
         Self::new()
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::sync::Arc;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    fn control_in(timestamp: u64, address: u8) -> UsbTransaction {
+        UsbTransaction {
+            timestamp,
+            device_address: address,
+            endpoint_number: 0,
+            transfer_type: TransferType::Control,
+            packets: vec![
+                UsbPacket::Setup {
+                    setup: SetupPacket { request_type: 0x80, request: 0x06, value: 0x0100, index: 0, length: 18 },
+                },
+                UsbPacket::Data { data: DataPacket { data: vec![0x12; 18], pid: 0x4B } },
+                UsbPacket::Data { data: DataPacket { data: Vec::new(), pid: 0x4B } },
+            ],
+            duration_ns: 2_000,
+        }
+    }
+
+    fn bulk_out(address: u8, len: usize) -> UsbTransaction {
+        UsbTransaction {
+            timestamp: 0,
+            device_address: address,
+            endpoint_number: 2,
+            transfer_type: TransferType::Bulk,
+            packets: vec![
+                UsbPacket::Token { token: TokenPacket { pid: USB_PID_OUT, address, endpoint: 2 } },
+                UsbPacket::Data { data: DataPacket { data: vec![0; len], pid: 0xC3 } },
+            ],
+            duration_ns: 0,
+        }
+    }
+
+    struct CountingSubscriber(Arc<AtomicUsize>);
+
+    impl CaptureSubscriber for CountingSubscriber {
+        fn on_transaction(&mut self, _transaction: &UsbTransaction) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn test_capture_limits() {
+        let mut analyzer = ProtocolAnalyzer::new_with_config(true, 3, false);
+        for address in 1..=5 {
+            analyzer.add_transaction(bulk_out(address, 16));
+        }
+        let kept: Vec<u8> = analyzer.get_transactions().map(|t| t.device_address).collect();
+        assert_eq!(kept, vec![3, 4, 5]);
+        assert_eq!(analyzer.capture_stats().evicted, 2);
+
+        analyzer.set_capture_limits(3, 40);
+        assert_eq!(analyzer.get_transactions().count(), 2);
+        analyzer.add_transaction(bulk_out(6, 100));
+        assert_eq!(analyzer.get_transactions().count(), 0);
+    }
+
+    #[test]
+    fn test_filters_and_subscribers() {
+        let mut analyzer = ProtocolAnalyzer::new_with_config(true, 100, false);
+        let seen = Arc::new(AtomicUsize::new(0));
+        let id = analyzer.subscribe(Box::new(CountingSubscriber(seen.clone())));
+
+        analyzer.set_device_class(4, 0x08);
+        analyzer.set_filters(&[
+            UsbPacketFilter { device_filter: Some(1), class_filter: None, endpoint_filter: None, transaction_filter: Some(UsbTransactionType::Setup) },
+            UsbPacketFilter { device_filter: None, class_filter: Some(0x08), endpoint_filter: Some(0x02), transaction_filter: None },
+        ]);
+
+        analyzer.add_transaction(control_in(0, 1));
+        analyzer.add_transaction(control_in(0, 2));
+        analyzer.add_transaction(bulk_out(4, 8));
+        analyzer.add_transaction(bulk_out(5, 8));
+
+        assert_eq!(analyzer.get_transactions().count(), 2);
+        assert_eq!(seen.load(Ordering::SeqCst), 2);
+        assert_eq!(analyzer.capture_stats().filtered, 2);
+
+        assert!(analyzer.unsubscribe(id).is_some());
+        analyzer.add_transaction(control_in(0, 1));
+        assert_eq!(seen.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_pcapng_export() {
+        let mut analyzer = ProtocolAnalyzer::new_with_config(true, 10, false);
+        analyzer.add_transaction(control_in(1_500_000_000, 3));
+        let capture = analyzer.export_pcapng();
+
+        let u32_at = |offset: usize| u32::from_le_bytes([capture[offset], capture[offset + 1], capture[offset + 2], capture[offset + 3]]);
+        assert_eq!(u32_at(0), PCAPNG_SHB);
+        assert_eq!(u32_at(8), PCAPNG_BYTE_ORDER_MAGIC);
+        let shb_len = u32_at(4) as usize;
+        assert_eq!(u32_at(shb_len - 4) as usize, shb_len);
+
+        let idb = shb_len;
+        assert_eq!(u32_at(idb), PCAPNG_IDB);
+        assert_eq!(u16::from_le_bytes([capture[idb + 8], capture[idb + 9]]), LINKTYPE_USB_LINUX_MMAPPED);
+
+        // Submit record: setup present, no data for an IN request
+        let submit = idb + u32_at(idb + 4) as usize;
+        assert_eq!(u32_at(submit), PCAPNG_EPB);
+        assert_eq!(u32_at(submit + 20) as usize, USBMON_HEADER_LEN);
+        let header = submit + 28;
+        assert_eq!(capture[header + 8], b'S');
+        assert_eq!(capture[header + 9], 2);
+        assert_eq!(capture[header + 10], 0x80);
+        assert_eq!(capture[header + 11], 3);
+        assert_eq!(capture[header + 14], 0);
+        assert_eq!(capture[header + 16], 1);
+        assert_eq!(&capture[header + 40..header + 42], &[0x80, 0x06]);
+
+        // Completion record carries the descriptor bytes
+        let complete = submit + u32_at(submit + 4) as usize;
+        assert_eq!(u32_at(complete + 20) as usize, USBMON_HEADER_LEN + 18);
+        let header = complete + 28;
+        assert_eq!(capture[header + 8], b'C');
+        assert_eq!(capture[header + 15], 0);
+        assert_eq!(u32_at(header + 36), 18);
+        assert_eq!(capture[header + USBMON_HEADER_LEN], 0x12);
+        assert_eq!(complete + u32_at(complete + 4) as usize, capture.len());
+    }
+}
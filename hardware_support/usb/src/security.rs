@@ -3,14 +3,25 @@
 //! This module provides comprehensive security isolation for USB devices,
 //! including device fingerprinting, permission management, attack detection,
 //! and security policy enforcement.
+//!
+//! Enumerated devices are checked against BadUSB heuristics: a keyboard
+//! appearing on a storage device, rapid re-enumeration and interface class
+//! changes. Suspicious devices are quarantined until a user approves or
+//! rejects them.
 
 use core::fmt;
 use alloc::vec::Vec;
 use alloc::string::String;
-use crate::UsbResult;
+use crate::{UsbAuditEntry, UsbClass, UsbDeviceDescriptor, UsbDriverError, UsbResult, UsbTransferStatus};
+use crate::hotplug::UsbParsedConfiguration;
+
+/// Enumerations of one device allowed inside `REENUMERATION_WINDOW_MS`
+const REENUMERATION_LIMIT: usize = 3;
+/// Window for counting re-enumerations, in milliseconds
+const REENUMERATION_WINDOW_MS: u64 = 10_000;
 
 /// Security isolation levels
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum SecurityLevel {
     /// No security restrictions - full access
     None,
@@ -37,7 +48,7 @@ impl fmt::Display for SecurityLevel {
 }
 
 /// Device trust state
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TrustState {
     /// Device is explicitly trusted
     Trusted,
@@ -49,6 +60,8 @@ pub enum TrustState {
     Blocked,
     /// Device failed security checks
     Failed,
+    /// Device is held back until a user approves it
+    Quarantined,
 }
 
 /// USB device fingerprint for security purposes
@@ -68,6 +81,8 @@ pub struct DeviceFingerprint {
     pub class: (u8, u8, u8),
     /// Device configuration hash
     pub config_hash: u64,
+    /// Class, subclass and protocol of every interface
+    pub interfaces: Vec<(u8, u8, u8)>,
     /// Additional device-specific data
     pub custom_data: Vec<u8>,
 }
@@ -87,10 +102,72 @@ impl DeviceFingerprint {
             product: None,
             class,
             config_hash: 0,
+            interfaces: Vec::new(),
             custom_data: Vec::new(),
         }
     }
 
+    /// Fingerprint a device from its device descriptor and active configuration
+    ///
+    /// The configuration hash covers every interface and endpoint, so a
+    /// device that changes its topology under the same IDs gets a new hash.
+    pub fn from_descriptors(
+        device: &UsbDeviceDescriptor,
+        configuration: &UsbParsedConfiguration,
+        serial: Option<String>,
+    ) -> Self {
+        let mut fingerprint = Self::new(
+            device.idVendor,
+            device.idProduct,
+            (device.bDeviceClass, device.bDeviceSubClass, device.bDeviceProtocol),
+        );
+        fingerprint.serial = serial;
+
+        // FNV-1a over the interface topology
+        let mut hash = 0xcbf29ce484222325u64;
+        let mut mix = |byte: u8| {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        };
+        mix(configuration.descriptor.bNumInterfaces);
+        mix(configuration.descriptor.bmAttributes);
+        for interface in &configuration.interfaces {
+            let class = (interface.class as u8, interface.subclass, interface.protocol);
+            for byte in [interface.number, interface.alternate_setting, class.0, class.1, class.2] {
+                mix(byte);
+            }
+            for endpoint in &interface.endpoints {
+                for byte in [endpoint.address, endpoint.transfer_type as u8] {
+                    mix(byte);
+                }
+                for byte in endpoint.max_packet_size.to_le_bytes() {
+                    mix(byte);
+                }
+            }
+            if interface.alternate_setting == 0 {
+                fingerprint.interfaces.push(class);
+            }
+        }
+        fingerprint.config_hash = hash;
+        fingerprint
+    }
+
+    /// Device ID in the `vvvv:pppp` form used by quarantine lists and audit entries
+    pub fn device_id(&self) -> String {
+        format!("{:04x}:{:04x}", self.vendor_id, self.product_id)
+    }
+
+    /// Whether the device or any of its interfaces has class `class`
+    pub fn has_class(&self, class: UsbClass) -> bool {
+        self.class.0 == class as u8 || self.interfaces.iter().any(|interface| interface.0 == class as u8)
+    }
+
+    /// Whether every interface is mass storage
+    pub fn is_storage_only(&self) -> bool {
+        !self.interfaces.is_empty()
+            && self.interfaces.iter().all(|interface| interface.0 == UsbClass::MassStorage as u8)
+    }
+
     /// Calculate a unique hash for this device fingerprint
     pub fn hash(&self) -> u64 {
         let mut hash = 0x5bd1e995u64;
@@ -123,6 +200,12 @@ impl DeviceFingerprint {
             }
         }
         
+        // Add interface topology
+        for &byte in &self.config_hash.to_le_bytes() {
+            hash = hash.wrapping_mul(0x85ebca6b);
+            hash ^= byte as u64;
+        }
+
         // Add custom data
         for &byte in &self.custom_data {
             hash = hash.wrapping_mul(0x85ebca6b);
@@ -258,6 +341,55 @@ pub enum SecurityEvent {
     UnknownDevice { fingerprint: DeviceFingerprint },
     /// Suspicious device behavior
     SuspiciousBehavior { fingerprint: DeviceFingerprint, behavior: String },
+    /// Device held back pending user approval
+    DeviceQuarantined { fingerprint: DeviceFingerprint, indicators: Vec<SuspiciousIndicator> },
+    /// Quarantined device approved by the user
+    DeviceApproved { fingerprint: DeviceFingerprint },
+}
+
+/// BadUSB indicator raised while evaluating an enumeration
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SuspiciousIndicator {
+    /// HID interface next to mass storage, or on a device last seen as storage only
+    HidOnStorageDevice,
+    /// Device enumerated `count` times inside the re-enumeration window
+    RapidReenumeration { count: usize },
+    /// Same device identity presenting a different interface topology
+    InterfaceClassChange { previous: Vec<(u8, u8, u8)>, current: Vec<(u8, u8, u8)> },
+}
+
+impl fmt::Display for SuspiciousIndicator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SuspiciousIndicator::HidOnStorageDevice => write!(f, "HID interface on a storage device"),
+            SuspiciousIndicator::RapidReenumeration { count } => {
+                write!(f, "{} enumerations within {} ms", count, REENUMERATION_WINDOW_MS)
+            }
+            SuspiciousIndicator::InterfaceClassChange { previous, current } => {
+                write!(f, "interface classes changed from {:02x?} to {:02x?}",
+                    previous.iter().map(|interface| interface.0).collect::<Vec<_>>(),
+                    current.iter().map(|interface| interface.0).collect::<Vec<_>>())
+            }
+        }
+    }
+}
+
+/// Device held back until a user approves or rejects it
+#[derive(Debug, Clone)]
+pub struct QuarantineEntry {
+    /// Handle passed to `approve_device`/`reject_device`
+    pub id: u32,
+    pub fingerprint: DeviceFingerprint,
+    pub indicators: Vec<SuspiciousIndicator>,
+    /// Time the device was quarantined, in milliseconds
+    pub since_ms: u64,
+}
+
+/// Recent enumerations of one device identity
+#[derive(Debug, Clone)]
+struct EnumerationRecord {
+    fingerprint: DeviceFingerprint,
+    timestamps: Vec<u64>,
 }
 
 /// Security event handler
@@ -282,6 +414,13 @@ pub struct SecurityManager {
     audit_log: Vec<SecurityEvent>,
     /// Maximum audit log entries
     max_audit_entries: usize,
+    /// Per-device audit trail
+    usb_audit: Vec<UsbAuditEntry>,
+    /// Enumeration history used by the BadUSB heuristics
+    enumeration_history: Vec<EnumerationRecord>,
+    /// Devices awaiting user approval
+    quarantine: Vec<QuarantineEntry>,
+    next_quarantine_id: u32,
 }
 
 impl SecurityManager {
@@ -295,6 +434,10 @@ impl SecurityManager {
             monitoring_enabled: true,
             audit_log: Vec::new(),
             max_audit_entries: 1000,
+            usb_audit: Vec::new(),
+            enumeration_history: Vec::new(),
+            quarantine: Vec::new(),
+            next_quarantine_id: 1,
         };
 
         // Add default policy
@@ -336,13 +479,11 @@ impl SecurityManager {
     /// Check if a device is allowed to connect
     pub fn check_device_access(&mut self, fingerprint: &DeviceFingerprint) -> UsbResult<TrustState> {
         // Check cache first
-        for (cached_fp, trust_state) in &self.device_cache {
-            if self.devices_match(fingerprint, cached_fp) {
-                self.log_event(SecurityEvent::DeviceConnected { 
-                    fingerprint: fingerprint.clone() 
-                });
-                return Ok(*trust_state);
-            }
+        if let Some(trust_state) = self.cached_trust(fingerprint) {
+            self.log_event(SecurityEvent::DeviceConnected { 
+                fingerprint: fingerprint.clone() 
+            });
+            return Ok(trust_state);
         }
 
         // Apply security policies
@@ -398,16 +539,7 @@ impl SecurityManager {
 
     /// Mark a device as trusted
     pub fn trust_device(&mut self, fingerprint: &DeviceFingerprint) {
-        // Update cache
-        for (cached_fp, trust_state) in &mut self.device_cache {
-            if self.devices_match(fingerprint, cached_fp) {
-                *trust_state = TrustState::Trusted;
-                break;
-            }
-        }
-
-        // Add to cache if not present
-        self.device_cache.push((fingerprint.clone(), TrustState::Trusted));
+        self.set_trust(fingerprint, TrustState::Trusted);
 
         self.log_event(SecurityEvent::DeviceConnected { 
             fingerprint: fingerprint.clone() 
@@ -416,16 +548,7 @@ impl SecurityManager {
 
     /// Block a device
     pub fn block_device(&mut self, fingerprint: &DeviceFingerprint) {
-        // Update cache
-        for (cached_fp, trust_state) in &mut self.device_cache {
-            if self.devices_match(fingerprint, cached_fp) {
-                *trust_state = TrustState::Blocked;
-                break;
-            }
-        }
-
-        // Add to cache if not present
-        self.device_cache.push((fingerprint.clone(), TrustState::Blocked));
+        self.set_trust(fingerprint, TrustState::Blocked);
 
         self.log_event(SecurityEvent::AccessDenied {
             fingerprint: fingerprint.clone(),
@@ -497,6 +620,216 @@ impl SecurityManager {
         });
     }
 
+    /// Evaluate a freshly enumerated device
+    ///
+    /// Runs the BadUSB heuristics before the policy check. Below `Medium`
+    /// indicators are only logged; from `Medium` up the device is
+    /// quarantined until `approve_device` or `reject_device` is called.
+    /// A device approved with the same topology is not flagged again for
+    /// it, but rapid re-enumeration still quarantines it.
+    pub fn evaluate_enumeration(&mut self, fingerprint: &DeviceFingerprint, now_ms: u64) -> UsbResult<TrustState> {
+        let mut indicators = self.record_enumeration(fingerprint, now_ms);
+
+        let approved = self.device_cache.iter().any(|(cached_fp, trust_state)| {
+            *trust_state == TrustState::Trusted
+                && cached_fp.config_hash == fingerprint.config_hash
+                && self.devices_match(fingerprint, cached_fp)
+        });
+        if approved {
+            indicators.retain(|indicator| matches!(indicator, SuspiciousIndicator::RapidReenumeration { .. }));
+        }
+
+        if self.level == SecurityLevel::None {
+            indicators.clear();
+        }
+        for indicator in &indicators {
+            self.log_event(SecurityEvent::SuspiciousBehavior {
+                fingerprint: fingerprint.clone(),
+                behavior: indicator.to_string(),
+            });
+        }
+
+        let pending = self.quarantine.iter().any(|entry| self.devices_match(fingerprint, &entry.fingerprint));
+        if pending || (!indicators.is_empty() && self.level >= SecurityLevel::Medium) {
+            self.quarantine_device(fingerprint, indicators, now_ms);
+            self.audit(fingerprint, "enumerate", UsbTransferStatus::Aborted, now_ms);
+            return Ok(TrustState::Quarantined);
+        }
+
+        let trust_state = self.check_device_access(fingerprint)?;
+        let result = match trust_state {
+            TrustState::Trusted | TrustState::Verified => UsbTransferStatus::Success,
+            _ => UsbTransferStatus::Aborted,
+        };
+        self.audit(fingerprint, "enumerate", result, now_ms);
+        Ok(trust_state)
+    }
+
+    /// Devices waiting for user approval
+    pub fn pending_approvals(&self) -> &[QuarantineEntry] {
+        &self.quarantine
+    }
+
+    /// Release a quarantined device and trust its current topology
+    pub fn approve_device(&mut self, id: u32, now_ms: u64) -> UsbResult<()> {
+        let entry = self.take_quarantine_entry(id)?;
+        self.set_trust(&entry.fingerprint, TrustState::Trusted);
+        self.log_event(SecurityEvent::DeviceApproved { fingerprint: entry.fingerprint.clone() });
+        self.audit(&entry.fingerprint, "approve", UsbTransferStatus::Success, now_ms);
+        Ok(())
+    }
+
+    /// Reject a quarantined device and block it
+    pub fn reject_device(&mut self, id: u32, now_ms: u64) -> UsbResult<()> {
+        let entry = self.take_quarantine_entry(id)?;
+        self.set_trust(&entry.fingerprint, TrustState::Blocked);
+        self.log_event(SecurityEvent::AccessDenied {
+            fingerprint: entry.fingerprint.clone(),
+            reason: "Quarantined device rejected by user".to_string(),
+        });
+        self.audit(&entry.fingerprint, "reject", UsbTransferStatus::Aborted, now_ms);
+        Ok(())
+    }
+
+    /// Trust state of the devices enumerated as `vendor_id:product_id`
+    ///
+    /// A pending quarantine entry wins, then the most restrictive cached
+    /// state across serial numbers. Devices never evaluated are `Unknown`.
+    pub fn device_trust(&self, vendor_id: u16, product_id: u16) -> TrustState {
+        let same_ids = |fingerprint: &DeviceFingerprint| {
+            fingerprint.vendor_id == vendor_id && fingerprint.product_id == product_id
+        };
+        if self.quarantine.iter().any(|entry| same_ids(&entry.fingerprint)) {
+            return TrustState::Quarantined;
+        }
+
+        let states: Vec<TrustState> = self.device_cache.iter()
+            .filter(|(fingerprint, _)| same_ids(fingerprint))
+            .map(|(_, trust_state)| *trust_state)
+            .collect();
+        [TrustState::Quarantined, TrustState::Blocked, TrustState::Failed, TrustState::Unknown, TrustState::Verified, TrustState::Trusted]
+            .into_iter()
+            .find(|state| states.contains(state))
+            .unwrap_or(TrustState::Unknown)
+    }
+
+    /// Per-device audit trail
+    pub fn get_usb_audit_log(&self) -> &[UsbAuditEntry] {
+        &self.usb_audit
+    }
+
+    /// Record an enumeration and return the indicators it raises
+    fn record_enumeration(&mut self, fingerprint: &DeviceFingerprint, now_ms: u64) -> Vec<SuspiciousIndicator> {
+        let mut indicators = Vec::new();
+        if fingerprint.has_class(UsbClass::MassStorage) && fingerprint.has_class(UsbClass::HID) {
+            indicators.push(SuspiciousIndicator::HidOnStorageDevice);
+        }
+
+        let index = self.enumeration_history.iter()
+            .position(|record| self.devices_match(fingerprint, &record.fingerprint));
+        let record = match index {
+            Some(index) => &mut self.enumeration_history[index],
+            None => {
+                self.enumeration_history.push(EnumerationRecord {
+                    fingerprint: fingerprint.clone(),
+                    timestamps: vec![now_ms],
+                });
+                return indicators;
+            }
+        };
+
+        record.timestamps.retain(|&time| now_ms.saturating_sub(time) < REENUMERATION_WINDOW_MS);
+        record.timestamps.push(now_ms);
+        if record.timestamps.len() > REENUMERATION_LIMIT {
+            indicators.push(SuspiciousIndicator::RapidReenumeration { count: record.timestamps.len() });
+        }
+
+        if record.fingerprint.interfaces != fingerprint.interfaces {
+            if record.fingerprint.is_storage_only()
+                && fingerprint.has_class(UsbClass::HID)
+                && !indicators.contains(&SuspiciousIndicator::HidOnStorageDevice) {
+                indicators.push(SuspiciousIndicator::HidOnStorageDevice);
+            }
+            indicators.push(SuspiciousIndicator::InterfaceClassChange {
+                previous: record.fingerprint.interfaces.clone(),
+                current: fingerprint.interfaces.clone(),
+            });
+        }
+        record.fingerprint = fingerprint.clone();
+        indicators
+    }
+
+    /// Hold a device for approval, merging with an existing entry for it
+    fn quarantine_device(&mut self, fingerprint: &DeviceFingerprint, indicators: Vec<SuspiciousIndicator>, now_ms: u64) {
+        let existing = self.quarantine.iter()
+            .position(|entry| self.devices_match(fingerprint, &entry.fingerprint));
+        match existing {
+            Some(index) => {
+                let entry = &mut self.quarantine[index];
+                entry.fingerprint = fingerprint.clone();
+                for indicator in indicators.iter() {
+                    if !entry.indicators.contains(indicator) {
+                        entry.indicators.push(indicator.clone());
+                    }
+                }
+            }
+            None => {
+                let id = self.next_quarantine_id;
+                self.next_quarantine_id = self.next_quarantine_id.wrapping_add(1);
+                self.quarantine.push(QuarantineEntry {
+                    id,
+                    fingerprint: fingerprint.clone(),
+                    indicators: indicators.clone(),
+                    since_ms: now_ms,
+                });
+            }
+        }
+
+        self.set_trust(fingerprint, TrustState::Quarantined);
+        self.log_event(SecurityEvent::DeviceQuarantined {
+            fingerprint: fingerprint.clone(),
+            indicators,
+        });
+    }
+
+    fn take_quarantine_entry(&mut self, id: u32) -> UsbResult<QuarantineEntry> {
+        let index = self.quarantine.iter()
+            .position(|entry| entry.id == id)
+            .ok_or(UsbDriverError::InvalidConfiguration)?;
+        Ok(self.quarantine.remove(index))
+    }
+
+    /// Append to the per-device audit trail
+    fn audit(&mut self, fingerprint: &DeviceFingerprint, action: &str, result: UsbTransferStatus, now_ms: u64) {
+        self.usb_audit.push(UsbAuditEntry {
+            timestamp: now_ms,
+            device_id: fingerprint.device_id(),
+            action: action.to_string(),
+            result,
+            data_size: 0,
+        });
+        if self.usb_audit.len() > self.max_audit_entries {
+            self.usb_audit.remove(0);
+        }
+    }
+
+    /// Cached trust state of a device
+    fn cached_trust(&self, fingerprint: &DeviceFingerprint) -> Option<TrustState> {
+        self.device_cache.iter()
+            .find(|(cached_fp, _)| self.devices_match(fingerprint, cached_fp))
+            .map(|(_, trust_state)| *trust_state)
+    }
+
+    /// Update or insert a device's cached trust state
+    fn set_trust(&mut self, fingerprint: &DeviceFingerprint, state: TrustState) {
+        let index = self.device_cache.iter()
+            .position(|(cached_fp, _)| self.devices_match(fingerprint, cached_fp));
+        match index {
+            Some(index) => self.device_cache[index] = (fingerprint.clone(), state),
+            None => self.device_cache.push((fingerprint.clone(), state)),
+        }
+    }
+
     /// Check if vendor ID is allowed
     fn is_allowed_vendor(&self, vendor_id: u16) -> bool {
         // List of common, trusted USB vendors
@@ -561,7 +894,7 @@ impl SecurityManager {
     pub fn get_trusted_devices(&self) -> Vec<&DeviceFingerprint> {
         self.device_cache
             .iter()
-            .filter(|(_, trust_state)| **trust_state == TrustState::Trusted)
+            .filter(|(_, trust_state)| *trust_state == TrustState::Trusted)
             .map(|(fingerprint, _)| fingerprint)
             .collect()
     }
@@ -570,7 +903,7 @@ impl SecurityManager {
     pub fn get_blocked_devices(&self) -> Vec<&DeviceFingerprint> {
         self.device_cache
             .iter()
-            .filter(|(_, trust_state)| **trust_state == TrustState::Blocked)
+            .filter(|(_, trust_state)| *trust_state == TrustState::Blocked)
             .map(|(fingerprint, _)| fingerprint)
             .collect()
     }
@@ -589,17 +922,18 @@ impl SecurityManager {
         report.push_str("Device Statistics:\n");
         report.push_str("------------------\n");
         
-        let trusted = self.device_cache.iter().filter(|(_, state)| **state == TrustState::Trusted).count();
-        let verified = self.device_cache.iter().filter(|(_, state)| **state == TrustState::Verified).count();
-        let unknown = self.device_cache.iter().filter(|(_, state)| **state == TrustState::Unknown).count();
-        let blocked = self.device_cache.iter().filter(|(|_, state)| **state == TrustState::Blocked).count();
-        let failed = self.device_cache.iter().filter(|(_, state)| **state == TrustState::Failed).count();
+        let trusted = self.device_cache.iter().filter(|(_, state)| *state == TrustState::Trusted).count();
+        let verified = self.device_cache.iter().filter(|(_, state)| *state == TrustState::Verified).count();
+        let unknown = self.device_cache.iter().filter(|(_, state)| *state == TrustState::Unknown).count();
+        let blocked = self.device_cache.iter().filter(|(_, state)| *state == TrustState::Blocked).count();
+        let failed = self.device_cache.iter().filter(|(_, state)| *state == TrustState::Failed).count();
         
         report.push_str(&format!("Trusted: {}\n", trusted));
         report.push_str(&format!("Verified: {}\n", verified));
         report.push_str(&format!("Unknown: {}\n", unknown));
         report.push_str(&format!("Blocked: {}\n", blocked));
-        report.push_str(&format!("Failed: {}\n\n", failed));
+        report.push_str(&format!("Failed: {}\n", failed));
+        report.push_str(&format!("Quarantined: {}\n\n", self.quarantine.len()));
         
         if !self.audit_log.is_empty() {
             report.push_str(&format!("Recent Events: {} entries\n", self.audit_log.len()));
@@ -644,7 +978,7 @@ impl SecurityManager {
                             "Medium" => SecurityLevel::Medium,
                             "High" => SecurityLevel::High,
                             "Maximum" => SecurityLevel::Maximum,
-                            _ => return Err(UsbResult::InvalidArgument),
+                            _ => return Err(UsbDriverError::InvalidConfiguration),
                         };
                     }
                     "monitoring_enabled" => {
@@ -703,6 +1037,14 @@ impl SecurityEventHandler for DefaultSecurityHandler {
                 println!("USB Security: Suspicious behavior - VID:{:04X}, PID:{:04X} - {}", 
                     fingerprint.vendor_id, fingerprint.product_id, behavior);
             }
+            SecurityEvent::DeviceQuarantined { fingerprint, indicators } => {
+                println!("USB Security: Device quarantined - VID:{:04X}, PID:{:04X} - {} indicator(s)", 
+                    fingerprint.vendor_id, fingerprint.product_id, indicators.len());
+            }
+            SecurityEvent::DeviceApproved { fingerprint } => {
+                println!("USB Security: Device approved - VID:{:04X}, PID:{:04X}", 
+                    fingerprint.vendor_id, fingerprint.product_id);
+            }
         }
     }
 }
@@ -743,4 +1085,116 @@ impl Default for MemorySecurityHandler {
     fn default() -> Self {
         Self::new()
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hotplug::parse_configuration;
+
+    fn device_descriptor() -> UsbDeviceDescriptor {
+        UsbDeviceDescriptor {
+            bLength: 18,
+            bDescriptorType: 0x01,
+            bcdUSB: 0x0200,
+            bDeviceClass: 0,
+            bDeviceSubClass: 0,
+            bDeviceProtocol: 0,
+            bMaxPacketSize0: 64,
+            idVendor: 0x0951,
+            idProduct: 0x1666,
+            bcdDevice: 0x0100,
+            iManufacturer: 1,
+            iProduct: 2,
+            iSerialNumber: 3,
+            bNumConfigurations: 1,
+        }
+    }
+
+    /// Configuration with one interface per (class, subclass, protocol)
+    fn fingerprint(interfaces: &[(u8, u8, u8)]) -> DeviceFingerprint {
+        let mut data = vec![9, 0x02, 0, 0, interfaces.len() as u8, 1, 0, 0x80, 50];
+        for (number, &(class, subclass, protocol)) in interfaces.iter().enumerate() {
+            data.extend_from_slice(&[9, 0x04, number as u8, 0, 1, class, subclass, protocol, 0]);
+            data.extend_from_slice(&[7, 0x05, 0x81 + number as u8, 0x02, 64, 0, 0]);
+        }
+        let length = data.len() as u16;
+        data[2..4].copy_from_slice(&length.to_le_bytes());
+        let configuration = parse_configuration(&data).unwrap();
+        DeviceFingerprint::from_descriptors(&device_descriptor(), &configuration, Some("0001".to_string()))
+    }
+
+    const STORAGE: (u8, u8, u8) = (0x08, 0x06, 0x50);
+    const KEYBOARD: (u8, u8, u8) = (0x03, 0x01, 0x01);
+
+    #[test]
+    fn test_fingerprint_topology() {
+        let storage = fingerprint(&[STORAGE]);
+        let composite = fingerprint(&[STORAGE, KEYBOARD]);
+        assert_eq!(storage.interfaces, vec![STORAGE]);
+        assert!(storage.is_storage_only());
+        assert!(composite.has_class(UsbClass::HID));
+        assert_ne!(storage.config_hash, composite.config_hash);
+        assert_ne!(storage.hash(), composite.hash());
+        assert_eq!(storage.device_id(), "0951:1666");
+    }
+
+    #[test]
+    fn test_hid_on_storage_quarantine() {
+        let mut manager = SecurityManager::new(SecurityLevel::Medium);
+        let storage = fingerprint(&[STORAGE]);
+        assert_eq!(manager.evaluate_enumeration(&storage, 0).unwrap(), TrustState::Trusted);
+
+        // Same stick comes back with a keyboard interface
+        let badusb = fingerprint(&[STORAGE, KEYBOARD]);
+        assert_eq!(manager.evaluate_enumeration(&badusb, 60_000).unwrap(), TrustState::Quarantined);
+        let pending = manager.pending_approvals();
+        assert_eq!(pending.len(), 1);
+        assert!(pending[0].indicators.contains(&SuspiciousIndicator::HidOnStorageDevice));
+        assert!(pending[0].indicators.iter()
+            .any(|indicator| matches!(indicator, SuspiciousIndicator::InterfaceClassChange { .. })));
+
+        // Replugging does not get around the quarantine
+        assert_eq!(manager.evaluate_enumeration(&badusb, 120_000).unwrap(), TrustState::Quarantined);
+        assert_eq!(manager.pending_approvals().len(), 1);
+
+        let id = manager.pending_approvals()[0].id;
+        manager.approve_device(id, 130_000).unwrap();
+        assert!(manager.pending_approvals().is_empty());
+        assert_eq!(manager.evaluate_enumeration(&badusb, 180_000).unwrap(), TrustState::Trusted);
+        assert!(manager.approve_device(id, 190_000).is_err());
+
+        let audit: Vec<(&str, UsbTransferStatus)> = manager.get_usb_audit_log().iter()
+            .map(|entry| (entry.action.as_str(), entry.result))
+            .collect();
+        assert_eq!(audit, vec![
+            ("enumerate", UsbTransferStatus::Success),
+            ("enumerate", UsbTransferStatus::Aborted),
+            ("enumerate", UsbTransferStatus::Aborted),
+            ("approve", UsbTransferStatus::Success),
+            ("enumerate", UsbTransferStatus::Success),
+        ]);
+        assert!(manager.get_usb_audit_log().iter().all(|entry| entry.device_id == "0951:1666"));
+    }
+
+    #[test]
+    fn test_rapid_reenumeration() {
+        let mut manager = SecurityManager::new(SecurityLevel::Basic);
+        let storage = fingerprint(&[STORAGE]);
+        // Below Medium indicators are logged but the device is allowed
+        for time in [0, 1000, 2000, 3000] {
+            assert_eq!(manager.evaluate_enumeration(&storage, time).unwrap(), TrustState::Trusted);
+        }
+        assert!(manager.get_audit_log().iter()
+            .any(|event| matches!(event, SecurityEvent::SuspiciousBehavior { .. })));
+
+        manager.set_security_level(SecurityLevel::High);
+        assert_eq!(manager.evaluate_enumeration(&storage, 4000).unwrap(), TrustState::Quarantined);
+        let id = manager.pending_approvals()[0].id;
+        assert_eq!(manager.pending_approvals()[0].indicators,
+            vec![SuspiciousIndicator::RapidReenumeration { count: 5 }]);
+        manager.reject_device(id, 5000).unwrap();
+        assert_eq!(manager.get_blocked_devices().len(), 1);
+        // Outside the window the device is no longer suspicious, but stays blocked
+        assert_eq!(manager.evaluate_enumeration(&storage, 60_000).unwrap(), TrustState::Blocked);
+    }
+}
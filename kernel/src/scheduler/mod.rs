@@ -80,6 +80,9 @@ pub fn timer_interrupt_occurred() {
     // Release the lock before potentially blocking
     drop(state);
     
+    // Wake futex waiters whose timeout has passed
+    multios_scheduler::futex_expire(crate::services::time_service::get_uptime_ns());
+    
     // Check if we need to switch threads
     check_thread_switch();
 }
@@ -163,6 +166,11 @@ pub struct ThreadControlBlock {
     pub last_scheduled: u64,
 }
 
+/// Thread currently running, if the scheduler has picked one
+pub fn current_thread_id() -> Option<ThreadId> {
+    SCHEDULER_STATE.lock().as_ref().and_then(|scheduler| scheduler.current_thread)
+}
+
 /// Yield current thread and schedule next
 pub fn yield_current_thread() {
     info!("Yielding current thread");
@@ -132,6 +132,7 @@ impl SyscallDispatcher {
             syscall_numbers::THREAD_GETTID => self.handle_thread_gettid(&params),
             syscall_numbers::THREAD_SET_PRIORITY => self.handle_thread_set_priority(&params),
             syscall_numbers::THREAD_GET_PRIORITY => self.handle_thread_get_priority(&params),
+            syscall_numbers::THREAD_FUTEX => self.handle_thread_futex(&params),
             
             // Memory management
             syscall_numbers::VIRTUAL_ALLOC => self.handle_virtual_alloc(&params),
@@ -417,6 +418,75 @@ impl SyscallDispatcher {
         }
     }
 
+    /// Handle futex(uaddr, op, val, timeout, uaddr2, val3)
+    ///
+    /// On failure `return_value` carries the errno, so callers can tell a
+    /// changed futex word (EAGAIN) from an expired timeout (ETIMEDOUT).
+    fn handle_thread_futex(&mut self, params: &SystemCallParams) -> SystemCallResult {
+        use core::sync::atomic::AtomicU32;
+        use multios_scheduler::{sys_futex, FutexOp, THREAD_MANAGER};
+        
+        let failed = |error_code: InterruptError| SystemCallResult { return_value: 0, error_code };
+        
+        if let Err(err) = self.validator.validate_pointer(params.arg0, 4) {
+            return failed(err.into());
+        }
+        let cmd = match FutexOp::decode(params.arg1 as u32) {
+            Ok((cmd, _)) => cmd,
+            Err(_) => return failed(InterruptError::ParameterValidationFailed),
+        };
+        
+        // Requeue passes its limit in the timeout slot; otherwise it points at a timespec
+        let timeout_ns = match cmd {
+            FutexOp::Requeue | FutexOp::CmpRequeue => Some(params.arg3 as u64),
+            _ if params.arg3 == 0 => None,
+            _ => {
+                if let Err(err) = self.validator.validate_pointer(params.arg3, 16) {
+                    return failed(err.into());
+                }
+                let [sec, nsec] = unsafe { core::ptr::read_unaligned(params.arg3 as *const [u64; 2]) };
+                if nsec >= 1_000_000_000 {
+                    return failed(InterruptError::ParameterValidationFailed);
+                }
+                Some(sec.saturating_mul(1_000_000_000).saturating_add(nsec))
+            }
+        };
+        let uaddr2 = if params.arg4 == 0 {
+            None
+        } else {
+            if let Err(err) = self.validator.validate_pointer(params.arg4, 4) {
+                return failed(err.into());
+            }
+            Some(unsafe { &*(params.arg4 as *const AtomicU32) })
+        };
+        let uaddr = unsafe { &*(params.arg0 as *const AtomicU32) };
+        
+        let caller = match scheduler::current_thread_id().and_then(|tid| THREAD_MANAGER.get_thread(tid).ok()) {
+            Some(thread) => thread,
+            None => return failed(InterruptError::SystemCallInvalid),
+        };
+        let (thread_id, process_id, priority) = {
+            let tcb = caller.lock();
+            (tcb.thread_id, tcb.process_id, tcb.priority)
+        };
+        let now_ns = crate::services::time_service::get_uptime_ns();
+        
+        match sys_futex(process_id, thread_id, priority, uaddr, params.arg1 as u32, params.arg2 as u32,
+                        timeout_ns, uaddr2, params.arg5 as u32, now_ns) {
+            Ok(count) => SystemCallResult {
+                return_value: count,
+                error_code: InterruptError::SystemCallInvalid,
+            },
+            Err(err) => {
+                debug!("Futex operation failed: {:?}", err);
+                SystemCallResult {
+                    return_value: err.errno() as usize,
+                    error_code: InterruptError::ParameterValidationFailed,
+                }
+            }
+        }
+    }
+
     /// Handle time get
     fn handle_time_get(&mut self, params: &SystemCallParams) -> SystemCallResult {
         use crate::bootstrap::get_boot_time;
@@ -84,6 +84,8 @@ pub mod syscall_numbers {
     pub const THREAD_BARRIER_DESTROY: usize = 32;
     /// Wait at thread barrier
     pub const THREAD_BARRIER_WAIT: usize = 33;
+    /// Wait on, wake or requeue waiters of a futex word
+    pub const THREAD_FUTEX: usize = 34;
 
     // ========================================
     // Memory Management System Calls (40-79)
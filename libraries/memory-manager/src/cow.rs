@@ -0,0 +1,309 @@
+//! Copy-on-Write Address Spaces
+//!
+//! This module backs `fork()` with copy-on-write paging:
+//! - Per-address-space page tables of user mappings
+//! - Reference-counted physical frames shared between parent and child
+//! - Writable pages are downgraded to read-only + `COPY_ON_WRITE` on fork
+//! - Write faults on shared pages copy the frame; the last owner reuses it
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use crate::memory_types::*;
+use crate::{MemoryError, MemoryResult};
+
+/// Address space identifier
+pub type AddressSpaceId = usize;
+
+/// Source of physical frames for CoW copies
+pub trait FrameSource {
+    /// Allocate a zeroed 4 KiB frame
+    fn allocate_frame(&mut self) -> Option<PhysAddr>;
+    /// Return a frame that is no longer referenced
+    fn free_frame(&mut self, frame: PhysAddr);
+    /// Copy one 4 KiB frame to another
+    fn copy_frame(&mut self, src: PhysAddr, dst: PhysAddr);
+}
+
+/// Page table entry of a CoW address space
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CowPage {
+    /// Backing physical frame
+    pub frame: PhysAddr,
+    /// Effective protection flags
+    pub flags: MemoryFlags,
+}
+
+/// How a write fault was resolved
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CowFaultOutcome {
+    /// The frame was shared: a private copy was made
+    Copied { from: PhysAddr, to: PhysAddr },
+    /// The faulting space was the last owner: write access restored in place
+    Reused(PhysAddr),
+}
+
+/// Copy-on-write statistics
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CowStats {
+    pub forks: u64,
+    pub pages_shared: u64,
+    pub pages_copied: u64,
+    pub pages_reused: u64,
+    pub frames_freed: u64,
+}
+
+/// Address space page table keyed by virtual page number
+#[derive(Debug, Clone, Default)]
+struct CowAddressSpace {
+    pages: BTreeMap<u64, CowPage>,
+}
+
+/// Copy-on-write address space manager
+#[derive(Debug, Default)]
+pub struct CowManager {
+    spaces: BTreeMap<AddressSpaceId, CowAddressSpace>,
+    /// Number of address spaces referencing each frame
+    frame_refs: BTreeMap<u64, usize>,
+    next_id: AddressSpaceId,
+    stats: CowStats,
+}
+
+const PAGE_SIZE: PageSize = PageSize::Size4K;
+
+fn page_number(addr: VirtAddr) -> u64 {
+    addr.page_number(PAGE_SIZE) as u64
+}
+
+impl CowManager {
+    /// Create an empty manager
+    pub fn new() -> Self {
+        Self {
+            next_id: 1,
+            ..Default::default()
+        }
+    }
+
+    /// Create an empty address space
+    pub fn create_space(&mut self) -> AddressSpaceId {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.spaces.insert(id, CowAddressSpace::default());
+        id
+    }
+
+    /// Map a frame at a page-aligned virtual address
+    pub fn map_page(&mut self, space: AddressSpaceId, virt: VirtAddr, frame: PhysAddr, flags: MemoryFlags) -> MemoryResult<()> {
+        if !virt.is_aligned(PAGE_SIZE) || !frame.is_aligned(PAGE_SIZE) {
+            return Err(MemoryError::InvalidAddress);
+        }
+        let pages = &mut self.spaces.get_mut(&space).ok_or(MemoryError::InvalidAddress)?.pages;
+        if pages.contains_key(&page_number(virt)) {
+            return Err(MemoryError::InvalidAddress);
+        }
+
+        pages.insert(page_number(virt), CowPage { frame, flags });
+        *self.frame_refs.entry(frame.as_u64()).or_insert(0) += 1;
+        Ok(())
+    }
+
+    /// Look up the page mapped at a virtual address
+    pub fn translate(&self, space: AddressSpaceId, virt: VirtAddr) -> Option<CowPage> {
+        self.spaces.get(&space)?.pages.get(&page_number(virt)).copied()
+    }
+
+    /// Number of address spaces sharing a frame
+    pub fn frame_refcount(&self, frame: PhysAddr) -> usize {
+        self.frame_refs.get(&frame.as_u64()).copied().unwrap_or(0)
+    }
+
+    /// Duplicate an address space for `fork()`.
+    ///
+    /// Every page is shared with the child. Writable pages become read-only
+    /// with `COPY_ON_WRITE` in both spaces so the first write faults.
+    pub fn fork_space(&mut self, parent: AddressSpaceId) -> MemoryResult<AddressSpaceId> {
+        let parent_space = self.spaces.get_mut(&parent).ok_or(MemoryError::InvalidAddress)?;
+
+        for page in parent_space.pages.values_mut() {
+            if page.flags.is_writable() {
+                page.flags.remove(MemoryFlags::WRITE);
+                page.flags.insert(MemoryFlags::COPY_ON_WRITE);
+            }
+        }
+        let child_space = parent_space.clone();
+
+        for page in child_space.pages.values() {
+            *self.frame_refs.entry(page.frame.as_u64()).or_insert(0) += 1;
+        }
+        self.stats.pages_shared += child_space.pages.len() as u64;
+        self.stats.forks += 1;
+
+        let child = self.create_space();
+        self.spaces.insert(child, child_space);
+        Ok(child)
+    }
+
+    /// Resolve a page fault in `space`.
+    ///
+    /// Only write faults on present `COPY_ON_WRITE` pages are handled; any
+    /// other fault is a genuine protection violation.
+    pub fn handle_fault(&mut self, space: AddressSpaceId, fault: &PageFaultInfo, frames: &mut dyn FrameSource) -> MemoryResult<CowFaultOutcome> {
+        if fault.error_code.not_present() || !fault.error_code.write_access() {
+            return Err(MemoryError::PageFault);
+        }
+
+        let vpn = page_number(fault.fault_addr);
+        let page = self.spaces.get(&space)
+            .and_then(|s| s.pages.get(&vpn).copied())
+            .ok_or(MemoryError::PageFault)?;
+        if !page.flags.contains(MemoryFlags::COPY_ON_WRITE) {
+            return Err(MemoryError::PageFault);
+        }
+
+        let mut flags = page.flags;
+        flags.remove(MemoryFlags::COPY_ON_WRITE);
+        flags.insert(MemoryFlags::WRITE);
+
+        let outcome = if self.frame_refcount(page.frame) <= 1 {
+            self.stats.pages_reused += 1;
+            CowFaultOutcome::Reused(page.frame)
+        } else {
+            let copy = frames.allocate_frame().ok_or(MemoryError::OutOfMemory)?;
+            frames.copy_frame(page.frame, copy);
+            self.release_frame(page.frame, frames);
+            *self.frame_refs.entry(copy.as_u64()).or_insert(0) += 1;
+            self.stats.pages_copied += 1;
+            CowFaultOutcome::Copied { from: page.frame, to: copy }
+        };
+
+        let frame = match outcome {
+            CowFaultOutcome::Copied { to, .. } => to,
+            CowFaultOutcome::Reused(frame) => frame,
+        };
+        if let Some(s) = self.spaces.get_mut(&space) {
+            s.pages.insert(vpn, CowPage { frame, flags });
+        }
+        Ok(outcome)
+    }
+
+    /// Tear down an address space, freeing frames no other space references
+    pub fn destroy_space(&mut self, space: AddressSpaceId, frames: &mut dyn FrameSource) -> MemoryResult<usize> {
+        let removed = self.spaces.remove(&space).ok_or(MemoryError::InvalidAddress)?;
+        let before = self.stats.frames_freed;
+        for page in removed.pages.values() {
+            self.release_frame(page.frame, frames);
+        }
+        Ok((self.stats.frames_freed - before) as usize)
+    }
+
+    /// Pages mapped in an address space, in address order
+    pub fn pages(&self, space: AddressSpaceId) -> Vec<(VirtAddr, CowPage)> {
+        self.spaces.get(&space).map_or_else(Vec::new, |s| {
+            s.pages.iter()
+                .map(|(&vpn, &page)| (VirtAddr::new(vpn * PAGE_SIZE.as_usize() as u64), page))
+                .collect()
+        })
+    }
+
+    /// Get copy-on-write statistics
+    pub fn get_stats(&self) -> CowStats {
+        self.stats
+    }
+
+    fn release_frame(&mut self, frame: PhysAddr, frames: &mut dyn FrameSource) {
+        let key = frame.as_u64();
+        if let Some(refs) = self.frame_refs.get_mut(&key) {
+            *refs -= 1;
+            if *refs == 0 {
+                self.frame_refs.remove(&key);
+                frames.free_frame(frame);
+                self.stats.frames_freed += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct TestFrames {
+        next: u64,
+        freed: Vec<PhysAddr>,
+        copies: Vec<(PhysAddr, PhysAddr)>,
+    }
+
+    impl FrameSource for TestFrames {
+        fn allocate_frame(&mut self) -> Option<PhysAddr> {
+            self.next += 1;
+            Some(PhysAddr::new(0x100_0000 + self.next * 0x1000))
+        }
+
+        fn free_frame(&mut self, frame: PhysAddr) {
+            self.freed.push(frame);
+        }
+
+        fn copy_frame(&mut self, src: PhysAddr, dst: PhysAddr) {
+            self.copies.push((src, dst));
+        }
+    }
+
+    fn write_fault(addr: u64) -> PageFaultInfo {
+        PageFaultInfo {
+            fault_addr: VirtAddr::new(addr),
+            // present | write
+            error_code: PageFaultError(0x3),
+            instruction_ptr: VirtAddr::new(0),
+        }
+    }
+
+    #[test]
+    fn test_fork_shares_then_copies_on_write() {
+        let mut cow = CowManager::new();
+        let mut frames = TestFrames::default();
+        let parent = cow.create_space();
+        cow.map_page(parent, VirtAddr::new(0x4000), PhysAddr::new(0x8000), MemoryFlags::user_rw()).unwrap();
+        cow.map_page(parent, VirtAddr::new(0x5000), PhysAddr::new(0x9000), MemoryFlags::user_ro()).unwrap();
+
+        let child = cow.fork_space(parent).unwrap();
+        let shared = cow.translate(child, VirtAddr::new(0x4000)).unwrap();
+        assert!(!shared.flags.is_writable());
+        assert!(shared.flags.contains(MemoryFlags::COPY_ON_WRITE));
+        assert_eq!(cow.frame_refcount(PhysAddr::new(0x8000)), 2);
+
+        // Child writes: gets a private copy
+        let outcome = cow.handle_fault(child, &write_fault(0x4010), &mut frames).unwrap();
+        let copy = match outcome {
+            CowFaultOutcome::Copied { from, to } => {
+                assert_eq!(from, PhysAddr::new(0x8000));
+                to
+            }
+            other => panic!("unexpected {:?}", other),
+        };
+        assert_eq!(frames.copies, vec![(PhysAddr::new(0x8000), copy)]);
+        assert!(cow.translate(child, VirtAddr::new(0x4000)).unwrap().flags.is_writable());
+
+        // Parent is now the sole owner and reuses its frame
+        assert_eq!(
+            cow.handle_fault(parent, &write_fault(0x4000), &mut frames),
+            Ok(CowFaultOutcome::Reused(PhysAddr::new(0x8000)))
+        );
+
+        // Read-only pages stay protected
+        assert!(cow.handle_fault(child, &write_fault(0x5000), &mut frames).is_err());
+    }
+
+    #[test]
+    fn test_destroy_frees_unshared_frames() {
+        let mut cow = CowManager::new();
+        let mut frames = TestFrames::default();
+        let parent = cow.create_space();
+        cow.map_page(parent, VirtAddr::new(0x1000), PhysAddr::new(0x2000), MemoryFlags::user_rw()).unwrap();
+        let child = cow.fork_space(parent).unwrap();
+
+        assert_eq!(cow.destroy_space(child, &mut frames), Ok(0));
+        assert_eq!(cow.destroy_space(parent, &mut frames), Ok(1));
+        assert_eq!(frames.freed, vec![PhysAddr::new(0x2000)]);
+    }
+}
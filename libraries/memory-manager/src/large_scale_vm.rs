@@ -10,7 +10,9 @@
 //! - Advanced page fault handling and demand paging
 //! - Memory overcommitment and ballooning
 //! - Huge page defragmentation and consolidation
+//! - Reverse mapping from physical frames to the VMAs/VMs mapping them
 
+use alloc::collections::BTreeMap;
 use alloc::vec::Vec;
 use spin::Mutex;
 use bitflags::bitflags;
@@ -18,6 +20,7 @@ use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use core::ops::Range;
 
 use crate::{PhysAddr, VirtAddr, PageSize, MemoryFlags, MemoryError, MemoryResult};
+use crate::cow::AddressSpaceId;
 
 /// Maximum virtual address space (1 Exabyte)
 const MAX_VIRTUAL_ADDRESS_SPACE: usize = 1usize << 60;
@@ -313,6 +316,8 @@ pub struct LargeScaleVirtualMemory {
     pub mapping_lock: spin::Mutex<()>,
     /// VMA sequence number
     pub vma_sequence: AtomicU64,
+    /// Reverse mapping of physical frames
+    pub rmap: ReverseMap,
 }
 
 /// Huge page allocation policies
@@ -466,6 +471,7 @@ impl LargeScaleVirtualMemory {
             pressure_manager: MemoryPressureManager::new(),
             mapping_lock: spin::Mutex::new(()),
             vma_sequence: AtomicU64::new(0),
+            rmap: ReverseMap::new(),
         }
     }
 
@@ -804,6 +810,22 @@ impl LargeScaleVirtualMemory {
         self.stats.clone()
     }
 
+    /// Record that `owner` maps `frame` at `address`, inside one of its VMAs
+    pub fn map_frame(&mut self, owner: AddressSpaceId, address: VirtAddr, frame: PhysAddr) -> MemoryResult<()> {
+        let vma_sequence = self.find_vma(address).ok_or(MemoryError::InvalidAddress)?.mmap_sequence;
+        self.rmap.add(RmapEntry { owner, vma_sequence, address }, frame)
+    }
+
+    /// Drop the mapping of `address` in `owner`, returning the frame it mapped
+    pub fn unmap_frame(&mut self, owner: AddressSpaceId, address: VirtAddr) -> Option<PhysAddr> {
+        self.rmap.remove(owner, address)
+    }
+
+    /// Visit every mapping of a physical frame
+    pub fn for_each_mapping<F: FnMut(RmapEntry)>(&self, frame: PhysAddr, f: F) {
+        self.rmap.for_each_mapping(frame, f)
+    }
+
     /// Get current timestamp
     fn get_current_time(&self) -> u64 {
         // Placeholder - would use high-resolution timer
@@ -820,6 +842,200 @@ pub struct AccessResponse {
     pub page_cached: bool,
 }
 
+/// One mapping of a physical frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RmapEntry {
+    /// VM or address space owning the mapping
+    pub owner: AddressSpaceId,
+    /// `mmap_sequence` of the VMA containing the mapping
+    pub vma_sequence: u64,
+    /// Page-aligned virtual address the frame is mapped at
+    pub address: VirtAddr,
+}
+
+/// Mappings of one frame
+///
+/// Most frames are mapped once, so that case is stored inline. Shared
+/// frames (dedup, CoW clones) fan out by owner, then by VMA.
+#[derive(Debug, Clone)]
+enum FrameMappings {
+    Single(RmapEntry),
+    Shared(BTreeMap<AddressSpaceId, BTreeMap<u64, Vec<VirtAddr>>>),
+}
+
+/// Reverse mapping statistics
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RmapStats {
+    /// Frames with at least one mapping
+    pub mapped_frames: usize,
+    /// Frames with more than one mapping
+    pub shared_frames: usize,
+    /// Total mappings
+    pub mappings: usize,
+}
+
+/// Reverse map from physical frames to the VMAs/VMs mapping them
+///
+/// Lets dedup, CoW clones and ballooning find every mapping of a frame
+/// without scanning page tables.
+#[derive(Debug, Default)]
+pub struct ReverseMap {
+    /// Mappings keyed by physical frame number
+    frames: BTreeMap<u64, FrameMappings>,
+    /// Frame number mapped at each (owner, virtual page)
+    mapped: BTreeMap<(AddressSpaceId, u64), u64>,
+    shared_frames: usize,
+}
+
+const RMAP_PAGE_SIZE: PageSize = PageSize::Size4K;
+
+impl ReverseMap {
+    /// Create an empty reverse map
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a mapping of `frame`
+    pub fn add(&mut self, entry: RmapEntry, frame: PhysAddr) -> MemoryResult<()> {
+        if !entry.address.is_aligned(RMAP_PAGE_SIZE) || !frame.is_aligned(RMAP_PAGE_SIZE) {
+            return Err(MemoryError::InvalidAddress);
+        }
+        let key = (entry.owner, entry.address.as_u64());
+        if self.mapped.contains_key(&key) {
+            return Err(MemoryError::InvalidAddress);
+        }
+
+        let frame_number = frame.as_u64() / RMAP_PAGE_SIZE.as_usize() as u64;
+        match self.frames.remove(&frame_number) {
+            None => {
+                self.frames.insert(frame_number, FrameMappings::Single(entry));
+            }
+            Some(FrameMappings::Single(existing)) => {
+                let mut shared = BTreeMap::new();
+                Self::insert_shared(&mut shared, existing);
+                Self::insert_shared(&mut shared, entry);
+                self.frames.insert(frame_number, FrameMappings::Shared(shared));
+                self.shared_frames += 1;
+            }
+            Some(FrameMappings::Shared(mut shared)) => {
+                Self::insert_shared(&mut shared, entry);
+                self.frames.insert(frame_number, FrameMappings::Shared(shared));
+            }
+        }
+        self.mapped.insert(key, frame_number);
+        Ok(())
+    }
+
+    /// Remove the mapping of `address` in `owner`, returning the frame it mapped
+    pub fn remove(&mut self, owner: AddressSpaceId, address: VirtAddr) -> Option<PhysAddr> {
+        let frame_number = self.mapped.remove(&(owner, address.as_u64()))?;
+
+        let mut remaining = None;
+        match self.frames.get_mut(&frame_number) {
+            Some(FrameMappings::Single(_)) => {
+                self.frames.remove(&frame_number);
+            }
+            Some(FrameMappings::Shared(shared)) => {
+                if let Some(vmas) = shared.get_mut(&owner) {
+                    for addresses in vmas.values_mut() {
+                        addresses.retain(|&mapped| mapped != address);
+                    }
+                    vmas.retain(|_, addresses| !addresses.is_empty());
+                    if vmas.is_empty() {
+                        shared.remove(&owner);
+                    }
+                }
+                let mut entries = Self::shared_entries(shared);
+                if entries.len() == 1 {
+                    remaining = entries.pop();
+                }
+            }
+            None => {}
+        }
+
+        // Back to a single mapping: store it inline again
+        if let Some(entry) = remaining {
+            self.frames.insert(frame_number, FrameMappings::Single(entry));
+            self.shared_frames -= 1;
+        }
+        Some(PhysAddr::new(frame_number * RMAP_PAGE_SIZE.as_usize() as u64))
+    }
+
+    /// Remove every mapping of an owner, e.g. when a VM is destroyed
+    pub fn remove_owner(&mut self, owner: AddressSpaceId) -> usize {
+        let addresses: Vec<u64> = self
+            .mapped
+            .range((owner, 0)..=(owner, u64::MAX))
+            .map(|(&(_, address), _)| address)
+            .collect();
+        for &address in &addresses {
+            self.remove(owner, VirtAddr::new(address));
+        }
+        addresses.len()
+    }
+
+    /// Frame mapped at `address` in `owner`
+    pub fn frame_at(&self, owner: AddressSpaceId, address: VirtAddr) -> Option<PhysAddr> {
+        self.mapped
+            .get(&(owner, address.as_u64()))
+            .map(|&frame_number| PhysAddr::new(frame_number * RMAP_PAGE_SIZE.as_usize() as u64))
+    }
+
+    /// Number of mappings of a frame
+    pub fn mapping_count(&self, frame: PhysAddr) -> usize {
+        let mut count = 0;
+        self.for_each_mapping(frame, |_| count += 1);
+        count
+    }
+
+    /// Visit every mapping of a frame, grouped by owner and then by VMA
+    pub fn for_each_mapping<F: FnMut(RmapEntry)>(&self, frame: PhysAddr, mut f: F) {
+        let frame_number = frame.as_u64() / RMAP_PAGE_SIZE.as_usize() as u64;
+        match self.frames.get(&frame_number) {
+            Some(FrameMappings::Single(entry)) => f(*entry),
+            Some(FrameMappings::Shared(shared)) => {
+                for (&owner, vmas) in shared {
+                    for (&vma_sequence, addresses) in vmas {
+                        for &address in addresses {
+                            f(RmapEntry { owner, vma_sequence, address });
+                        }
+                    }
+                }
+            }
+            None => {}
+        }
+    }
+
+    /// Get reverse mapping statistics
+    pub fn stats(&self) -> RmapStats {
+        RmapStats {
+            mapped_frames: self.frames.len(),
+            shared_frames: self.shared_frames,
+            mappings: self.mapped.len(),
+        }
+    }
+
+    fn insert_shared(shared: &mut BTreeMap<AddressSpaceId, BTreeMap<u64, Vec<VirtAddr>>>, entry: RmapEntry) {
+        shared
+            .entry(entry.owner)
+            .or_insert_with(BTreeMap::new)
+            .entry(entry.vma_sequence)
+            .or_insert_with(Vec::new)
+            .push(entry.address);
+    }
+
+    fn shared_entries(shared: &BTreeMap<AddressSpaceId, BTreeMap<u64, Vec<VirtAddr>>>) -> Vec<RmapEntry> {
+        shared
+            .iter()
+            .flat_map(|(&owner, vmas)| {
+                vmas.iter().flat_map(move |(&vma_sequence, addresses)| {
+                    addresses.iter().map(move |&address| RmapEntry { owner, vma_sequence, address })
+                })
+            })
+            .collect()
+    }
+}
+
 // Implementation details for supporting structures
 
 impl HugePageManager {
@@ -1099,6 +1315,45 @@ mod tests {
         assert_eq!(vm.get_stats().mapped_memory, 4096);
     }
 
+    #[test]
+    fn test_rmap_shared_frame() {
+        let mut rmap = ReverseMap::new();
+        let frame = PhysAddr::new(0x200000);
+        let first = RmapEntry { owner: 1, vma_sequence: 0, address: VirtAddr::new(0x1000) };
+        let second = RmapEntry { owner: 2, vma_sequence: 3, address: VirtAddr::new(0x7000) };
+
+        assert!(rmap.add(first, frame).is_ok());
+        assert!(rmap.add(second, frame).is_ok());
+        assert!(rmap.add(first, frame).is_err());
+        assert_eq!(rmap.stats().shared_frames, 1);
+
+        let mut seen = Vec::new();
+        rmap.for_each_mapping(frame, |entry| seen.push(entry));
+        assert_eq!(seen, vec![first, second]);
+
+        assert_eq!(rmap.remove(1, VirtAddr::new(0x1000)), Some(frame));
+        assert_eq!(rmap.mapping_count(frame), 1);
+        assert_eq!(rmap.stats().shared_frames, 0);
+
+        assert_eq!(rmap.remove_owner(2), 1);
+        assert_eq!(rmap.mapping_count(frame), 0);
+        assert_eq!(rmap.stats().mapped_frames, 0);
+    }
+
+    #[test]
+    fn test_map_frame_requires_vma() {
+        let mut vm = LargeScaleVirtualMemory::new(1 << 40);
+        assert!(vm.init().is_ok());
+        let frame = PhysAddr::new(0x5000);
+
+        assert!(vm.map_frame(1, VirtAddr::new(0x1000), frame).is_err());
+
+        vm.map_virtual_extended(VirtAddr::new(0x1000), 4096, VmaFlags::READABLE, VmaBacking::Anonymous, false).unwrap();
+        assert!(vm.map_frame(1, VirtAddr::new(0x1000), frame).is_ok());
+        assert_eq!(vm.rmap.frame_at(1, VirtAddr::new(0x1000)), Some(frame));
+        assert_eq!(vm.unmap_frame(1, VirtAddr::new(0x1000)), Some(frame));
+    }
+
     #[test]
     fn test_cache_aligned_data() {
         use crate::cache_coherency::CacheAligned;
@@ -65,6 +65,7 @@ pub mod arch_specific;
 pub mod numa;
 pub mod cache_coherency;
 pub mod large_scale_vm;
+pub mod cow;
 
 #[cfg(test)]
 pub mod tests;
@@ -78,6 +79,7 @@ pub use arch_specific::*;
 pub use numa::*;
 pub use cache_coherency::*;
 pub use large_scale_vm::*;
+pub use cow::*;
 
 use log::{info, debug, warn, error};
 
@@ -0,0 +1,319 @@
+//! Seasonal Anomaly Detection for MultiOS
+//!
+//! This module learns what "normal" looks like for CPU, memory and I/O metrics
+//! and flags samples that deviate from it:
+//! - Seasonal baseline: EWMA per time-of-day and time-of-week slot, falling
+//!   back to an overall EWMA level for slots not seen yet
+//! - EWMA of absolute residuals as a robust, sqrt-free spread estimate
+//! - Chebyshev-bounded confidence so no distribution has to be assumed
+//! - Anomalous samples barely move the baseline, so a spike does not shift
+//!   it but a recurring change is eventually learned
+//! - Findings convert into `PerformanceAlert`s of the anomaly category
+
+use alloc::vec::Vec;
+
+use crate::performance_monitor::{
+    AlertAction, AlertCategory, AlertSeverity, ComparisonOperator, PerfCounterType, PerformanceAlert,
+};
+
+/// Seconds per day
+const SECS_PER_DAY: u64 = 86_400;
+
+/// Ratio of standard deviation to mean absolute deviation for normal data
+const MAD_TO_SIGMA: f32 = 1.2533;
+
+/// Alert IDs at and above this value come from the anomaly detector
+pub const ANOMALY_ALERT_ID_BASE: u32 = 0x1000;
+
+/// Anomaly detector configuration
+#[derive(Debug, Clone)]
+pub struct AnomalyConfig {
+    /// Length of one seasonal slot (seconds)
+    pub slot_secs: u64,
+    /// Smoothing factor for the overall level
+    pub level_alpha: f32,
+    /// Smoothing factor for time-of-day and time-of-week slots
+    pub seasonal_gamma: f32,
+    /// Smoothing factor with which anomalous samples still reach the
+    /// time-of-week slot, so recurring changes are eventually learned
+    pub anomaly_gamma: f32,
+    /// Smoothing factor for the residual spread
+    pub deviation_beta: f32,
+    /// Deviation (in sigmas) that counts as an anomaly
+    pub threshold_sigma: f32,
+    /// Samples to learn before anything is flagged
+    pub warmup_samples: u32,
+    /// Lower bound on the spread so flat metrics do not flag noise
+    pub min_deviation: f32,
+}
+
+impl Default for AnomalyConfig {
+    fn default() -> Self {
+        Self {
+            slot_secs: 3600,
+            level_alpha: 0.05,
+            seasonal_gamma: 0.3,
+            anomaly_gamma: 0.05,
+            deviation_beta: 0.05,
+            threshold_sigma: 4.0,
+            warmup_samples: 48,
+            min_deviation: 0.5,
+        }
+    }
+}
+
+/// Learned baseline of one metric.
+///
+/// The expected value comes from the time-of-week slot once a week has been
+/// seen, from the time-of-day slot before that, and from the overall level
+/// until the slot has been seen at all.
+#[derive(Debug, Clone)]
+pub struct SeasonalBaseline {
+    pub level: f32,
+    /// EWMA per time-of-day slot
+    pub daily: Vec<Option<f32>>,
+    /// EWMA per time-of-week slot
+    pub weekly: Vec<Option<f32>>,
+    /// EWMA of absolute residuals
+    pub deviation: f32,
+    pub samples: u32,
+}
+
+fn ewma(current: Option<f32>, value: f32, factor: f32) -> Option<f32> {
+    Some(current.map_or(value, |current| current + factor * (value - current)))
+}
+
+impl SeasonalBaseline {
+    fn new(slots_per_day: usize) -> Self {
+        Self {
+            level: 0.0,
+            daily: alloc::vec![None; slots_per_day],
+            weekly: alloc::vec![None; slots_per_day * 7],
+            deviation: 0.0,
+            samples: 0,
+        }
+    }
+
+    fn slots(&self, timestamp_secs: u64) -> (usize, usize) {
+        let slot_secs = SECS_PER_DAY / self.daily.len() as u64;
+        let slot = ((timestamp_secs % SECS_PER_DAY) / slot_secs) as usize;
+        let day = ((timestamp_secs / SECS_PER_DAY) % 7) as usize;
+        (slot, day * self.daily.len() + slot)
+    }
+
+    /// Expected value at a point in time
+    pub fn expected(&self, timestamp_secs: u64) -> f32 {
+        let (slot, week_slot) = self.slots(timestamp_secs);
+        self.weekly[week_slot].or(self.daily[slot]).unwrap_or(self.level)
+    }
+
+    fn learn(&mut self, config: &AnomalyConfig, timestamp_secs: u64, value: f32) {
+        let (slot, week_slot) = self.slots(timestamp_secs);
+        self.level = if self.samples == 0 {
+            value
+        } else {
+            self.level + config.level_alpha * (value - self.level)
+        };
+        self.daily[slot] = ewma(self.daily[slot], value, config.seasonal_gamma);
+        self.weekly[week_slot] = ewma(self.weekly[week_slot], value, config.seasonal_gamma);
+    }
+
+    fn learn_anomaly(&mut self, config: &AnomalyConfig, timestamp_secs: u64, value: f32) {
+        let (_, week_slot) = self.slots(timestamp_secs);
+        self.weekly[week_slot] = ewma(self.weekly[week_slot], value, config.anomaly_gamma);
+    }
+}
+
+/// A sample that deviated from its baseline
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AnomalyEvent {
+    pub metric: PerfCounterType,
+    pub timestamp_secs: u64,
+    pub observed: f32,
+    pub expected: f32,
+    /// Deviation in estimated sigmas
+    pub score: f32,
+    /// Lower bound on the probability that this is not noise
+    pub confidence: f32,
+    pub severity: AlertSeverity,
+}
+
+impl AnomalyEvent {
+    /// Alert describing this anomaly; `threshold_value` is the expected value
+    pub fn to_alert(&self, alert_id: u32) -> PerformanceAlert {
+        PerformanceAlert {
+            alert_id,
+            metric_type: self.metric,
+            threshold_value: self.expected,
+            comparison_operator: if self.observed > self.expected {
+                ComparisonOperator::GreaterThan
+            } else {
+                ComparisonOperator::LessThan
+            },
+            severity: self.severity,
+            duration_seconds: 0,
+            enabled: true,
+            action: AlertAction::LogAndAlert,
+            category: AlertCategory::Anomaly,
+        }
+    }
+}
+
+/// Online anomaly detector over any number of metrics
+#[derive(Debug)]
+pub struct AnomalyDetector {
+    config: AnomalyConfig,
+    baselines: Vec<(PerfCounterType, SeasonalBaseline)>,
+    anomalies: u64,
+}
+
+impl AnomalyDetector {
+    /// Create a detector
+    pub fn new(config: AnomalyConfig) -> Self {
+        Self {
+            config,
+            baselines: Vec::new(),
+            anomalies: 0,
+        }
+    }
+
+    /// Feed a sample; returns an anomaly if it deviates from the baseline
+    pub fn observe(&mut self, metric: PerfCounterType, value: f32, timestamp_secs: u64) -> Option<AnomalyEvent> {
+        let slots_per_day = (SECS_PER_DAY / self.config.slot_secs.clamp(1, SECS_PER_DAY)) as usize;
+        let index = match self.baselines.iter().position(|(m, _)| *m == metric) {
+            Some(index) => index,
+            None => {
+                self.baselines.push((metric, SeasonalBaseline::new(slots_per_day)));
+                self.baselines.len() - 1
+            }
+        };
+        let config = &self.config;
+        let baseline = &mut self.baselines[index].1;
+
+        let expected = baseline.expected(timestamp_secs);
+        let residual = value - expected;
+        let sigma = baseline.deviation.max(config.min_deviation) * MAD_TO_SIGMA;
+        let score = residual.abs() / sigma;
+        let warmed_up = baseline.samples >= config.warmup_samples;
+
+        if warmed_up && score > config.threshold_sigma {
+            // Keep the spike out of the level, daily slots and spread; only
+            // the time-of-week slot sees it, so a new weekly habit is learned
+            baseline.learn_anomaly(config, timestamp_secs, value);
+            self.anomalies += 1;
+
+            let confidence = 1.0 - 1.0 / (score * score);
+            return Some(AnomalyEvent {
+                metric,
+                timestamp_secs,
+                observed: value,
+                expected,
+                score,
+                confidence,
+                severity: match confidence {
+                    c if c >= 0.99 => AlertSeverity::Critical,
+                    c if c >= 0.95 => AlertSeverity::Warning,
+                    _ => AlertSeverity::Info,
+                },
+            });
+        }
+
+        if baseline.samples > 0 {
+            baseline.deviation += config.deviation_beta * (residual.abs() - baseline.deviation);
+        }
+        baseline.learn(config, timestamp_secs, value);
+        baseline.samples = baseline.samples.saturating_add(1);
+        None
+    }
+
+    /// Expected value of a metric at a point in time, once it has been seen
+    pub fn expected(&self, metric: PerfCounterType, timestamp_secs: u64) -> Option<f32> {
+        self.baseline(metric).map(|baseline| baseline.expected(timestamp_secs))
+    }
+
+    /// Learned baseline of a metric
+    pub fn baseline(&self, metric: PerfCounterType) -> Option<&SeasonalBaseline> {
+        self.baselines.iter().find(|(m, _)| *m == metric).map(|(_, baseline)| baseline)
+    }
+
+    /// Forget a metric's baseline, e.g. after a hardware change
+    pub fn reset(&mut self, metric: PerfCounterType) {
+        self.baselines.retain(|(m, _)| *m != metric);
+    }
+
+    /// Anomalies flagged so far
+    pub fn anomaly_count(&self) -> u64 {
+        self.anomalies
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Busy 09:00-17:00 at 80%, idle at 20% otherwise
+    fn office_load(timestamp_secs: u64) -> f32 {
+        let hour = (timestamp_secs % SECS_PER_DAY) / 3600;
+        if (9..17).contains(&hour) { 80.0 } else { 20.0 }
+    }
+
+    #[test]
+    fn test_learns_daily_pattern() {
+        let mut detector = AnomalyDetector::new(AnomalyConfig::default());
+        for hour in 0..24 * 14 {
+            let ts = hour * 3600;
+            detector.observe(PerfCounterType::CpuUtilization, office_load(ts), ts);
+        }
+
+        let ts = 24 * 14 * 3600;
+        let busy = detector.expected(PerfCounterType::CpuUtilization, ts + 10 * 3600).unwrap();
+        let idle = detector.expected(PerfCounterType::CpuUtilization, ts + 2 * 3600).unwrap();
+        assert!((busy - 80.0).abs() < 5.0);
+        assert!((idle - 20.0).abs() < 5.0);
+
+        // Expected busy period is not anomalous, a busy night is
+        assert!(detector.observe(PerfCounterType::CpuUtilization, 80.0, ts + 10 * 3600).is_none());
+        let anomaly = detector.observe(PerfCounterType::CpuUtilization, 80.0, ts + 26 * 3600).unwrap();
+        assert!(anomaly.confidence > 0.9);
+        assert_eq!(detector.anomaly_count(), 1);
+
+        let alert = anomaly.to_alert(ANOMALY_ALERT_ID_BASE);
+        assert_eq!(alert.category, AlertCategory::Anomaly);
+        assert_eq!(alert.comparison_operator, ComparisonOperator::GreaterThan);
+    }
+
+    #[test]
+    fn test_learns_weekly_pattern() {
+        let mut detector = AnomalyDetector::new(AnomalyConfig::default());
+        let mut anomalous_days = Vec::new();
+        for hour in 0..24 * 7 * 3 {
+            let ts = hour * 3600;
+            // Day 0 is a Thursday; days 2 and 3 of each week are a quiet weekend
+            let weekend = matches!((ts / SECS_PER_DAY) % 7, 2 | 3);
+            let load = if weekend { 20.0 } else { office_load(ts) };
+            if detector.observe(PerfCounterType::CpuUtilization, load, ts).is_some() {
+                anomalous_days.push(ts / SECS_PER_DAY);
+            }
+        }
+        // Only the first, never-seen weekend stands out
+        assert!(!anomalous_days.is_empty());
+        assert!(anomalous_days.iter().all(|&day| day == 2 || day == 3));
+    }
+
+    #[test]
+    fn test_warmup_and_independent_metrics() {
+        let config = AnomalyConfig { warmup_samples: 10, ..Default::default() };
+        let mut detector = AnomalyDetector::new(config);
+        for i in 0..5 {
+            assert!(detector.observe(PerfCounterType::MemoryBandwidth, 1000.0 * i as f32, i * 60).is_none());
+        }
+        for i in 0..20 {
+            detector.observe(PerfCounterType::IoThroughput, 50.0, i * 60);
+        }
+        assert!(detector.observe(PerfCounterType::IoThroughput, 500.0, 20 * 60).is_some());
+        assert_eq!(detector.baseline(PerfCounterType::MemoryBandwidth).unwrap().samples, 5);
+
+        detector.reset(PerfCounterType::IoThroughput);
+        assert!(detector.baseline(PerfCounterType::IoThroughput).is_none());
+    }
+}
@@ -0,0 +1,382 @@
+//! Pluggable Load Balancing Policies for MultiOS
+//!
+//! The multi-core scheduler asks its active `BalancePolicy` where to place
+//! threads and which CPUs to migrate between when a domain is balanced:
+//! - `LoadBasedPolicy` evens out load, counting thermal pressure
+//! - `NumaAwarePolicy` keeps threads on their home node unless remote CPUs
+//!   are clearly less loaded
+//! - `PowerPackingPolicy` packs threads onto as few CPUs as possible so the
+//!   rest can stay in deep idle states
+//! - `CacheAffinePolicy` keeps threads on their last CPU or its domain while
+//!   the imbalance does not justify losing cache contents
+//!
+//! Policies can be switched at runtime; migration statistics are kept per
+//! policy so they can be compared on the same workload.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::multicore::BalanceAlgorithm;
+use crate::scheduler_algo::CpuId;
+
+/// Load of one CPU as seen by a policy
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CpuLoadInfo {
+    pub cpu_id: CpuId,
+    pub online: bool,
+    /// Runnable load
+    pub load: f32,
+    /// Load including thermal pressure
+    pub effective_load: f32,
+    /// NUMA node of the CPU
+    pub node: usize,
+    /// Leaf scheduling domain, whose CPUs share a cache
+    pub domain: Option<usize>,
+}
+
+/// Input to a placement or balancing decision
+#[derive(Debug, Clone, Copy)]
+pub struct BalanceContext<'a> {
+    /// Indexed by CPU ID
+    pub cpus: &'a [CpuLoadInfo],
+    /// Load difference worth a migration
+    pub imbalance_threshold: f32,
+    /// CPU the thread last ran on, for placement
+    pub previous_cpu: Option<CpuId>,
+}
+
+impl<'a> BalanceContext<'a> {
+    fn cpu(&self, cpu_id: CpuId) -> Option<&'a CpuLoadInfo> {
+        self.cpus.get(cpu_id).filter(|cpu| cpu.online)
+    }
+
+    fn online<'b>(&'b self, cpu_ids: &'b [CpuId]) -> impl Iterator<Item = &'a CpuLoadInfo> + 'b {
+        cpu_ids.iter().filter_map(move |&cpu_id| self.cpu(cpu_id))
+    }
+}
+
+/// Load balancing strategy of the multi-core scheduler
+pub trait BalancePolicy: Send {
+    /// Algorithm the policy implements
+    fn algorithm(&self) -> BalanceAlgorithm;
+
+    /// CPU to place a new or waking thread on, out of `candidates`
+    fn select_cpu(&self, ctx: &BalanceContext, candidates: &[CpuId]) -> Option<CpuId>;
+
+    /// Source and target CPU of the migration that best balances the
+    /// domain's CPUs, if one is worth doing
+    fn find_migration(&self, ctx: &BalanceContext, domain_cpus: &[CpuId]) -> Option<(CpuId, CpuId)>;
+}
+
+impl fmt::Debug for dyn BalancePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "BalancePolicy({:?})", self.algorithm())
+    }
+}
+
+/// Policy implementing `algorithm`. Weighted and ML-based balancing fall
+/// back to load-based balancing, memory-aware balancing to NUMA-aware.
+pub fn policy_for(algorithm: BalanceAlgorithm) -> Box<dyn BalancePolicy> {
+    match algorithm {
+        BalanceAlgorithm::NumaAware | BalanceAlgorithm::MemoryAware => Box::new(NumaAwarePolicy::default()),
+        BalanceAlgorithm::CacheAware => Box::new(CacheAffinePolicy::default()),
+        BalanceAlgorithm::PowerPacking => Box::new(PowerPackingPolicy::default()),
+        BalanceAlgorithm::LoadBased | BalanceAlgorithm::WeightedLoad | BalanceAlgorithm::MLBased => {
+            Box::new(LoadBasedPolicy)
+        }
+    }
+}
+
+/// Least and most loaded of `cpus` by `score`
+fn extremes<'a>(cpus: impl Iterator<Item = &'a CpuLoadInfo>, score: impl Fn(&CpuLoadInfo) -> f32)
+    -> Option<(&'a CpuLoadInfo, &'a CpuLoadInfo)> {
+    let mut lightest: Option<&CpuLoadInfo> = None;
+    let mut heaviest: Option<&CpuLoadInfo> = None;
+    for cpu in cpus {
+        if lightest.map_or(true, |light| score(cpu) < score(light)) {
+            lightest = Some(cpu);
+        }
+        if heaviest.map_or(true, |heavy| score(cpu) > score(heavy)) {
+            heaviest = Some(cpu);
+        }
+    }
+    Some((lightest?, heaviest?))
+}
+
+/// Move load from the heaviest to the lightest CPU once they differ by
+/// more than the threshold
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LoadBasedPolicy;
+
+impl BalancePolicy for LoadBasedPolicy {
+    fn algorithm(&self) -> BalanceAlgorithm {
+        BalanceAlgorithm::LoadBased
+    }
+
+    fn select_cpu(&self, ctx: &BalanceContext, candidates: &[CpuId]) -> Option<CpuId> {
+        extremes(ctx.online(candidates), |cpu| cpu.effective_load).map(|(lightest, _)| lightest.cpu_id)
+    }
+
+    fn find_migration(&self, ctx: &BalanceContext, domain_cpus: &[CpuId]) -> Option<(CpuId, CpuId)> {
+        let (lightest, heaviest) = extremes(ctx.online(domain_cpus), |cpu| cpu.effective_load)?;
+        (heaviest.effective_load - lightest.effective_load > ctx.imbalance_threshold)
+            .then_some((heaviest.cpu_id, lightest.cpu_id))
+    }
+}
+
+/// Charge CPUs off the thread's home node a penalty, so threads only leave
+/// their node for a clearly lighter CPU
+#[derive(Debug, Clone, Copy)]
+pub struct NumaAwarePolicy {
+    /// Extra load counted for a CPU on another node
+    pub remote_penalty: f32,
+}
+
+impl Default for NumaAwarePolicy {
+    fn default() -> Self {
+        Self { remote_penalty: 0.2 }
+    }
+}
+
+impl NumaAwarePolicy {
+    fn score(&self, cpu: &CpuLoadInfo, home_node: usize) -> f32 {
+        cpu.effective_load + if cpu.node == home_node { 0.0 } else { self.remote_penalty }
+    }
+}
+
+impl BalancePolicy for NumaAwarePolicy {
+    fn algorithm(&self) -> BalanceAlgorithm {
+        BalanceAlgorithm::NumaAware
+    }
+
+    fn select_cpu(&self, ctx: &BalanceContext, candidates: &[CpuId]) -> Option<CpuId> {
+        let home_node = ctx.previous_cpu.and_then(|cpu_id| ctx.cpus.get(cpu_id)).map_or(0, |cpu| cpu.node);
+        extremes(ctx.online(candidates), |cpu| self.score(cpu, home_node)).map(|(lightest, _)| lightest.cpu_id)
+    }
+
+    fn find_migration(&self, ctx: &BalanceContext, domain_cpus: &[CpuId]) -> Option<(CpuId, CpuId)> {
+        let (_, heaviest) = extremes(ctx.online(domain_cpus), |cpu| cpu.effective_load)?;
+        // Threads on the busiest CPU have their memory on its node
+        let (lightest, _) = extremes(ctx.online(domain_cpus), |cpu| self.score(cpu, heaviest.node))?;
+        (heaviest.effective_load - self.score(lightest, heaviest.node) > ctx.imbalance_threshold)
+            .then_some((heaviest.cpu_id, lightest.cpu_id))
+    }
+}
+
+/// Fill busy CPUs up to `capacity` before waking idle ones, and drain
+/// lightly loaded CPUs into busier ones that have room
+#[derive(Debug, Clone, Copy)]
+pub struct PowerPackingPolicy {
+    /// Load a CPU takes before threads spill to another one
+    pub capacity: f32,
+}
+
+impl Default for PowerPackingPolicy {
+    fn default() -> Self {
+        Self { capacity: 4.0 }
+    }
+}
+
+impl PowerPackingPolicy {
+    /// Busiest CPU that still has room for `load`, other than `except`
+    fn fullest_with_room<'a>(&self, cpus: impl Iterator<Item = &'a CpuLoadInfo>, load: f32, except: Option<CpuId>)
+        -> Option<&'a CpuLoadInfo> {
+        cpus.filter(|cpu| Some(cpu.cpu_id) != except && cpu.effective_load + load <= self.capacity)
+            .fold(None, |best: Option<&CpuLoadInfo>, cpu| match best {
+                Some(best) if best.effective_load >= cpu.effective_load => Some(best),
+                _ => Some(cpu),
+            })
+    }
+}
+
+impl BalancePolicy for PowerPackingPolicy {
+    fn algorithm(&self) -> BalanceAlgorithm {
+        BalanceAlgorithm::PowerPacking
+    }
+
+    fn select_cpu(&self, ctx: &BalanceContext, candidates: &[CpuId]) -> Option<CpuId> {
+        self.fullest_with_room(ctx.online(candidates), 1.0, None)
+            .or_else(|| extremes(ctx.online(candidates), |cpu| cpu.effective_load).map(|(lightest, _)| lightest))
+            .map(|cpu| cpu.cpu_id)
+    }
+
+    fn find_migration(&self, ctx: &BalanceContext, domain_cpus: &[CpuId]) -> Option<(CpuId, CpuId)> {
+        let (lightest, heaviest) = extremes(ctx.online(domain_cpus), |cpu| cpu.effective_load)?;
+        // An overloaded CPU sheds load to the lightest one
+        if heaviest.effective_load > self.capacity {
+            return (heaviest.effective_load - lightest.effective_load > ctx.imbalance_threshold)
+                .then_some((heaviest.cpu_id, lightest.cpu_id));
+        }
+        // Otherwise empty the least loaded busy CPU so it can go idle
+        let source = ctx.online(domain_cpus)
+            .filter(|cpu| cpu.load >= 1.0)
+            .min_by(|a, b| a.effective_load.total_cmp(&b.effective_load))?;
+        let target = self.fullest_with_room(ctx.online(domain_cpus), 1.0, Some(source.cpu_id))?;
+        (target.effective_load >= source.effective_load).then_some((source.cpu_id, target.cpu_id))
+    }
+}
+
+/// Keep threads where their cache is warm: on the previous CPU if it is not
+/// much busier than the best one, then within its cache domain, and only
+/// migrate across domains for a large imbalance
+#[derive(Debug, Clone, Copy)]
+pub struct CacheAffinePolicy {
+    /// Extra load tolerated to stay on the previous CPU
+    pub affinity_bonus: f32,
+    /// Multiple of the imbalance threshold that justifies leaving a domain
+    pub cross_domain_factor: f32,
+}
+
+impl Default for CacheAffinePolicy {
+    fn default() -> Self {
+        Self { affinity_bonus: 0.5, cross_domain_factor: 2.0 }
+    }
+}
+
+impl BalancePolicy for CacheAffinePolicy {
+    fn algorithm(&self) -> BalanceAlgorithm {
+        BalanceAlgorithm::CacheAware
+    }
+
+    fn select_cpu(&self, ctx: &BalanceContext, candidates: &[CpuId]) -> Option<CpuId> {
+        let (lightest, _) = extremes(ctx.online(candidates), |cpu| cpu.effective_load)?;
+        let Some(previous) = ctx.previous_cpu.filter(|cpu_id| candidates.contains(cpu_id)).and_then(|cpu_id| ctx.cpu(cpu_id)) else {
+            return Some(lightest.cpu_id);
+        };
+        if previous.effective_load <= lightest.effective_load + self.affinity_bonus {
+            return Some(previous.cpu_id);
+        }
+        // Stay within the previous CPU's cache domain if that is close enough
+        let sibling = extremes(
+            ctx.online(candidates).filter(|cpu| cpu.domain.is_some() && cpu.domain == previous.domain),
+            |cpu| cpu.effective_load,
+        ).map(|(lightest, _)| lightest);
+        match sibling {
+            Some(sibling) if sibling.effective_load <= lightest.effective_load + self.affinity_bonus => Some(sibling.cpu_id),
+            _ => Some(lightest.cpu_id),
+        }
+    }
+
+    fn find_migration(&self, ctx: &BalanceContext, domain_cpus: &[CpuId]) -> Option<(CpuId, CpuId)> {
+        let (_, heaviest) = extremes(ctx.online(domain_cpus), |cpu| cpu.effective_load)?;
+        let imbalance = |target: &CpuLoadInfo| heaviest.effective_load - target.effective_load;
+
+        let local: Vec<&CpuLoadInfo> = ctx.online(domain_cpus)
+            .filter(|cpu| cpu.domain.is_some() && cpu.domain == heaviest.domain)
+            .collect();
+        if let Some((lightest, _)) = extremes(local.into_iter(), |cpu| cpu.effective_load) {
+            if imbalance(lightest) > ctx.imbalance_threshold {
+                return Some((heaviest.cpu_id, lightest.cpu_id));
+            }
+        }
+        let (lightest, _) = extremes(ctx.online(domain_cpus), |cpu| cpu.effective_load)?;
+        (imbalance(lightest) > ctx.imbalance_threshold * self.cross_domain_factor)
+            .then_some((heaviest.cpu_id, lightest.cpu_id))
+    }
+}
+
+/// Placement and migration counts of one policy
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PolicyStats {
+    /// Threads placed while the policy was active
+    pub placements: u64,
+    /// Balancing passes run
+    pub balance_runs: u64,
+    /// Threads migrated by balancing
+    pub migrations: u64,
+    /// Times the policy was switched to
+    pub activations: u64,
+}
+
+/// Per-policy statistics of a load balancer
+#[derive(Debug, Clone, Default)]
+pub struct BalanceStats {
+    entries: Vec<(BalanceAlgorithm, PolicyStats)>,
+}
+
+impl BalanceStats {
+    pub fn get(&self, algorithm: BalanceAlgorithm) -> PolicyStats {
+        self.entries.iter()
+            .find(|(entry, _)| *entry == algorithm)
+            .map(|(_, stats)| *stats)
+            .unwrap_or_default()
+    }
+
+    pub fn get_mut(&mut self, algorithm: BalanceAlgorithm) -> &mut PolicyStats {
+        let index = match self.entries.iter().position(|(entry, _)| *entry == algorithm) {
+            Some(index) => index,
+            None => {
+                self.entries.push((algorithm, PolicyStats::default()));
+                self.entries.len() - 1
+            }
+        };
+        &mut self.entries[index].1
+    }
+
+    /// Statistics of every policy that was active at some point
+    pub fn iter(&self) -> impl Iterator<Item = (BalanceAlgorithm, PolicyStats)> + '_ {
+        self.entries.iter().copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    fn cpu(cpu_id: CpuId, load: f32, node: usize, domain: usize) -> CpuLoadInfo {
+        CpuLoadInfo { cpu_id, online: true, load, effective_load: load, node, domain: Some(domain) }
+    }
+
+    fn context(cpus: &[CpuLoadInfo], previous_cpu: Option<CpuId>) -> BalanceContext<'_> {
+        BalanceContext { cpus, imbalance_threshold: 0.5, previous_cpu }
+    }
+
+    #[test]
+    fn test_load_based_evens_out_load() {
+        let cpus = vec![cpu(0, 3.0, 0, 0), cpu(1, 1.0, 0, 0), cpu(2, 2.0, 0, 0)];
+        let ctx = context(&cpus, None);
+        assert_eq!(LoadBasedPolicy.select_cpu(&ctx, &[0, 1, 2]), Some(1));
+        assert_eq!(LoadBasedPolicy.find_migration(&ctx, &[0, 1, 2]), Some((0, 1)));
+        assert_eq!(LoadBasedPolicy.find_migration(&ctx, &[0, 2]), Some((0, 2)));
+    }
+
+    #[test]
+    fn test_numa_aware_prefers_home_node() {
+        let cpus = vec![cpu(0, 1.0, 0, 0), cpu(1, 0.9, 1, 1)];
+        let policy = NumaAwarePolicy::default();
+        assert_eq!(policy.select_cpu(&context(&cpus, Some(0)), &[0, 1]), Some(0));
+        assert_eq!(policy.select_cpu(&context(&cpus, Some(1)), &[0, 1]), Some(1));
+    }
+
+    #[test]
+    fn test_power_packing_fills_busy_cpus() {
+        let cpus = vec![cpu(0, 2.0, 0, 0), cpu(1, 1.0, 0, 0), cpu(2, 0.0, 0, 0)];
+        let policy = PowerPackingPolicy::default();
+        let ctx = context(&cpus, None);
+        assert_eq!(policy.select_cpu(&ctx, &[0, 1, 2]), Some(0));
+        // CPU 1 is drained into CPU 0 so it can idle
+        assert_eq!(policy.find_migration(&ctx, &[0, 1, 2]), Some((1, 0)));
+    }
+
+    #[test]
+    fn test_cache_affine_stays_in_domain() {
+        let cpus = vec![cpu(0, 3.0, 0, 0), cpu(1, 2.0, 0, 0), cpu(2, 1.5, 0, 1)];
+        let policy = CacheAffinePolicy::default();
+        let ctx = context(&cpus, Some(0));
+        // CPU 1 shares CPU 0's cache and is within the affinity bonus of CPU 2
+        assert_eq!(policy.select_cpu(&ctx, &[0, 1, 2]), Some(1));
+        assert_eq!(policy.find_migration(&ctx, &[0, 1, 2]), Some((0, 1)));
+    }
+
+    #[test]
+    fn test_stats_are_kept_per_policy() {
+        let mut stats = BalanceStats::default();
+        stats.get_mut(BalanceAlgorithm::LoadBased).migrations += 2;
+        stats.get_mut(BalanceAlgorithm::PowerPacking).migrations += 1;
+        assert_eq!(stats.get(BalanceAlgorithm::LoadBased).migrations, 2);
+        assert_eq!(stats.get(BalanceAlgorithm::PowerPacking).migrations, 1);
+        assert_eq!(stats.get(BalanceAlgorithm::CacheAware), PolicyStats::default());
+    }
+}
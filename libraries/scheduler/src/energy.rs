@@ -0,0 +1,268 @@
+//! Energy-Aware Scheduling for MultiOS
+//!
+//! This module provides an energy model for heterogeneous (big.LITTLE style)
+//! and homogeneous systems:
+//! - Per-CPU operating performance points with capacity and power cost
+//! - Energy estimation for a given utilization
+//! - Energy-aware wakeup placement that packs light tasks on efficient cores
+//! - Energy consumption accounting exported to performance statistics
+
+use alloc::vec::Vec;
+
+use crate::Priority;
+use crate::scheduler_algo::CpuId;
+
+/// Capacity of the biggest CPU at its highest frequency
+pub const SCHED_CAPACITY_SCALE: u32 = 1024;
+
+/// Utilization headroom kept free on a CPU (percent of capacity)
+const CAPACITY_MARGIN_PERCENT: u32 = 20;
+
+/// Estimated utilization of a newly woken task, by priority
+pub fn estimated_task_util(priority: Priority) -> u32 {
+    match priority {
+        Priority::Idle => 32,
+        Priority::Low => 64,
+        Priority::Normal => 128,
+        Priority::High => 256,
+        Priority::Critical => SCHED_CAPACITY_SCALE,
+    }
+}
+
+/// Core micro-architecture class
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoreType {
+    /// Efficient in-order/low-power core
+    Little,
+    /// High-performance core
+    Big,
+}
+
+/// Operating performance point (frequency/voltage pair)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OperatingPoint {
+    pub frequency_mhz: u32,
+    /// Compute capacity at this frequency (0..=SCHED_CAPACITY_SCALE)
+    pub capacity: u32,
+    /// Active power at full utilization (milliwatts)
+    pub power_mw: u32,
+}
+
+/// Energy profile of a single CPU
+#[derive(Debug, Clone)]
+pub struct CpuEnergyProfile {
+    pub cpu_id: CpuId,
+    pub core_type: CoreType,
+    /// Operating points sorted by ascending frequency
+    pub opps: Vec<OperatingPoint>,
+    /// Idle power (milliwatts)
+    pub idle_power_mw: u32,
+}
+
+impl CpuEnergyProfile {
+    /// Maximum capacity of this CPU
+    pub fn max_capacity(&self) -> u32 {
+        self.opps.last().map_or(0, |opp| opp.capacity)
+    }
+
+    /// Lowest operating point able to serve `util`
+    pub fn opp_for_util(&self, util: u32) -> Option<&OperatingPoint> {
+        self.opps.iter().find(|opp| opp.capacity >= util).or(self.opps.last())
+    }
+
+    /// Estimated power (milliwatts) when running at `util`
+    pub fn estimate_power_mw(&self, util: u32) -> f32 {
+        if util == 0 {
+            return self.idle_power_mw as f32;
+        }
+        match self.opp_for_util(util) {
+            Some(opp) => {
+                let busy = (util as f32 / opp.capacity.max(1) as f32).min(1.0);
+                opp.power_mw as f32 * busy + self.idle_power_mw as f32 * (1.0 - busy)
+            }
+            None => 0.0,
+        }
+    }
+}
+
+/// Energy accounting statistics
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EnergyStats {
+    /// Total estimated energy consumed (millijoules)
+    pub total_energy_mj: f64,
+    /// Estimated current power draw (milliwatts)
+    pub current_power_mw: f32,
+    /// Wakeups placed by the energy-aware path
+    pub eas_placements: u64,
+    /// Wakeups that fell back to performance placement
+    pub fallback_placements: u64,
+    /// Placements that landed on little cores
+    pub little_core_placements: u64,
+}
+
+/// System energy model
+#[derive(Debug, Clone)]
+pub struct EnergyModel {
+    profiles: Vec<CpuEnergyProfile>,
+    /// Total utilization above which energy-aware placement is disabled (percent)
+    pub overutilized_percent: u32,
+    stats: EnergyStats,
+}
+
+impl EnergyModel {
+    /// Create a model from per-CPU profiles
+    pub fn new(profiles: Vec<CpuEnergyProfile>) -> Self {
+        Self {
+            profiles,
+            overutilized_percent: 80,
+            stats: EnergyStats::default(),
+        }
+    }
+
+    /// Build a simple big.LITTLE model: `little` efficient cores then `big` cores
+    pub fn big_little(little: usize, big: usize) -> Self {
+        let little_opps = vec![
+            OperatingPoint { frequency_mhz: 500, capacity: 150, power_mw: 60 },
+            OperatingPoint { frequency_mhz: 1000, capacity: 300, power_mw: 150 },
+            OperatingPoint { frequency_mhz: 1400, capacity: 420, power_mw: 280 },
+        ];
+        let big_opps = vec![
+            OperatingPoint { frequency_mhz: 1000, capacity: 400, power_mw: 400 },
+            OperatingPoint { frequency_mhz: 2000, capacity: 750, power_mw: 1200 },
+            OperatingPoint { frequency_mhz: 2800, capacity: SCHED_CAPACITY_SCALE, power_mw: 2600 },
+        ];
+
+        let profiles = (0..little + big).map(|cpu_id| {
+            let is_little = cpu_id < little;
+            CpuEnergyProfile {
+                cpu_id,
+                core_type: if is_little { CoreType::Little } else { CoreType::Big },
+                opps: if is_little { little_opps.clone() } else { big_opps.clone() },
+                idle_power_mw: if is_little { 5 } else { 20 },
+            }
+        }).collect();
+
+        Self::new(profiles)
+    }
+
+    /// Get a CPU's energy profile
+    pub fn profile(&self, cpu_id: CpuId) -> Option<&CpuEnergyProfile> {
+        self.profiles.get(cpu_id)
+    }
+
+    /// Estimate total system power for a utilization vector
+    pub fn estimate_power_mw(&self, utils: &[u32]) -> f32 {
+        self.profiles.iter()
+            .map(|p| p.estimate_power_mw(utils.get(p.cpu_id).copied().unwrap_or(0)))
+            .sum()
+    }
+
+    /// Whether the system is too busy for energy-aware placement
+    pub fn is_overutilized(&self, utils: &[u32]) -> bool {
+        self.profiles.iter().any(|p| {
+            let util = utils.get(p.cpu_id).copied().unwrap_or(0);
+            util * 100 > p.max_capacity() * self.overutilized_percent
+        })
+    }
+
+    /// Energy-aware wakeup placement.
+    ///
+    /// Returns the candidate CPU whose estimated power grows least
+    /// after adding `task_util`, or `None` when the system is overutilized or
+    /// no candidate has room, in which case the caller should fall back to the
+    /// performance-oriented load balancer.
+    pub fn find_energy_efficient_cpu(&mut self, utils: &[u32], candidates: &[CpuId], task_util: u32) -> Option<CpuId> {
+        if self.is_overutilized(utils) {
+            self.stats.fallback_placements += 1;
+            return None;
+        }
+
+        let mut best: Option<(CpuId, f32)> = None;
+
+        for &cpu_id in candidates {
+            let profile = match self.profiles.get(cpu_id) {
+                Some(profile) => profile,
+                None => continue,
+            };
+            let current = utils.get(cpu_id).copied().unwrap_or(0);
+            let new_util = current + task_util;
+            let limit = profile.max_capacity() * (100 - CAPACITY_MARGIN_PERCENT) / 100;
+            if new_util > limit {
+                continue;
+            }
+
+            // Only the chosen CPU's power changes, so compare per-CPU deltas
+            let delta = profile.estimate_power_mw(new_util) - profile.estimate_power_mw(current);
+            if best.map_or(true, |(_, best_delta)| delta < best_delta) {
+                best = Some((cpu_id, delta));
+            }
+        }
+
+        match best {
+            Some((cpu_id, _)) => {
+                self.stats.eas_placements += 1;
+                if self.profiles[cpu_id].core_type == CoreType::Little {
+                    self.stats.little_core_placements += 1;
+                }
+                Some(cpu_id)
+            }
+            None => {
+                self.stats.fallback_placements += 1;
+                None
+            }
+        }
+    }
+
+    /// Accumulate energy consumed over `elapsed_ns` at the given utilizations
+    pub fn account_energy(&mut self, utils: &[u32], elapsed_ns: u64) {
+        let power = self.estimate_power_mw(utils);
+        self.stats.current_power_mw = power;
+        // mW * s = mJ
+        self.stats.total_energy_mj += power as f64 * elapsed_ns as f64 / 1_000_000_000.0;
+    }
+
+    /// Get energy statistics
+    pub fn get_stats(&self) -> EnergyStats {
+        self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_light_task_packs_on_little_core() {
+        let mut model = EnergyModel::big_little(2, 2);
+        let utils = [50, 0, 0, 0];
+
+        let cpu = model.find_energy_efficient_cpu(&utils, &[0, 1, 2, 3], 100).unwrap();
+        assert_eq!(model.profile(cpu).unwrap().core_type, CoreType::Little);
+        assert_eq!(model.get_stats().little_core_placements, 1);
+    }
+
+    #[test]
+    fn test_heavy_task_goes_to_big_core() {
+        let mut model = EnergyModel::big_little(2, 2);
+        let utils = [0, 0, 0, 0];
+
+        let cpu = model.find_energy_efficient_cpu(&utils, &[0, 1, 2, 3], 500).unwrap();
+        assert_eq!(model.profile(cpu).unwrap().core_type, CoreType::Big);
+    }
+
+    #[test]
+    fn test_overutilized_falls_back() {
+        let mut model = EnergyModel::big_little(1, 1);
+        let utils = [400, 900];
+
+        assert_eq!(model.find_energy_efficient_cpu(&utils, &[0, 1], 10), None);
+        assert_eq!(model.get_stats().fallback_placements, 1);
+    }
+
+    #[test]
+    fn test_energy_accounting() {
+        let mut model = EnergyModel::big_little(1, 0);
+        model.account_energy(&[0], 1_000_000_000);
+        assert!((model.get_stats().total_energy_mj - 5.0).abs() < 1e-6);
+    }
+}
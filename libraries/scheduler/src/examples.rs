@@ -457,6 +457,8 @@ pub fn example_custom_scheduler() {
         load_balance_interval: 50,
         enable_cpu_affinity: true,
         enable_load_balancing: true,
+        enable_tracing: false,
+        enable_latency_tracking: false,
     };
 
     // Initialize with custom config
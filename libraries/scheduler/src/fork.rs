@@ -0,0 +1,207 @@
+//! Process Duplication State for MultiOS
+//!
+//! This module holds the per-process state that `fork()`, `vfork()` and
+//! `posix_spawn()` propagate or rewrite:
+//! - File descriptor tables (shared open file handles, close-on-exec)
+//! - Signal dispositions (handlers reset on exec, ignores preserved)
+//! - The interface to the memory manager's copy-on-write address spaces
+//! - Spawn file actions and attributes
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use crate::process::{ProcessError, ProcessId, ProcessResult};
+
+/// Address space identifier handed out by the memory manager
+pub type AddressSpaceId = usize;
+
+/// Memory manager operations needed to duplicate a process
+pub trait AddressSpaceOps {
+    /// Create an empty address space for a freshly exec'd image
+    fn create_space(&mut self) -> ProcessResult<AddressSpaceId>;
+    /// Share all pages of `parent` copy-on-write with a new address space
+    fn fork_space(&mut self, parent: AddressSpaceId) -> ProcessResult<AddressSpaceId>;
+    /// Release an address space no process uses any more
+    fn destroy_space(&mut self, space: AddressSpaceId);
+}
+
+/// Close this descriptor on exec
+pub const FD_CLOEXEC: u32 = 1;
+
+/// Number of signals tracked per process
+pub const NSIG: usize = 64;
+
+/// File descriptor table entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileDescriptor {
+    /// Open file description handle; shared between forked processes
+    pub handle: usize,
+    /// Per-descriptor flags (`FD_CLOEXEC`)
+    pub fd_flags: u32,
+}
+
+/// Per-process file descriptor table
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileDescriptorTable {
+    entries: BTreeMap<i32, FileDescriptor>,
+}
+
+impl FileDescriptorTable {
+    /// Create an empty table
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Install a descriptor, replacing any previous one
+    pub fn insert(&mut self, fd: i32, descriptor: FileDescriptor) {
+        self.entries.insert(fd, descriptor);
+    }
+
+    /// Look up a descriptor
+    pub fn get(&self, fd: i32) -> Option<FileDescriptor> {
+        self.entries.get(&fd).copied()
+    }
+
+    /// Close a descriptor
+    pub fn close(&mut self, fd: i32) -> Option<FileDescriptor> {
+        self.entries.remove(&fd)
+    }
+
+    /// Duplicate `old_fd` onto `new_fd`; the copy does not inherit `FD_CLOEXEC`
+    pub fn dup2(&mut self, old_fd: i32, new_fd: i32) -> ProcessResult<()> {
+        let descriptor = self.get(old_fd).ok_or(ProcessError::InvalidParameter)?;
+        if old_fd != new_fd {
+            self.entries.insert(new_fd, FileDescriptor { fd_flags: 0, ..descriptor });
+        }
+        Ok(())
+    }
+
+    /// Drop every descriptor marked `FD_CLOEXEC`
+    pub fn close_on_exec(&mut self) {
+        self.entries.retain(|_, descriptor| descriptor.fd_flags & FD_CLOEXEC == 0);
+    }
+
+    /// Open descriptor numbers in ascending order
+    pub fn fds(&self) -> Vec<i32> {
+        self.entries.keys().copied().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// Action taken when a signal is delivered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignalDisposition {
+    Default,
+    Ignore,
+    /// User handler entry point
+    Handler(usize),
+}
+
+/// Per-process signal dispositions and blocked mask
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignalDispositions {
+    actions: [SignalDisposition; NSIG],
+    /// Blocked signal bitmask (bit N = signal N)
+    pub blocked: u64,
+}
+
+impl Default for SignalDispositions {
+    fn default() -> Self {
+        Self {
+            actions: [SignalDisposition::Default; NSIG],
+            blocked: 0,
+        }
+    }
+}
+
+impl SignalDispositions {
+    /// Disposition of a signal
+    pub fn get(&self, signal: u32) -> SignalDisposition {
+        self.actions.get(signal as usize).copied().unwrap_or(SignalDisposition::Default)
+    }
+
+    /// Change the disposition of a signal, returning the previous one
+    pub fn set(&mut self, signal: u32, disposition: SignalDisposition) -> ProcessResult<SignalDisposition> {
+        let slot = match signal as usize {
+            0 => return Err(ProcessError::InvalidParameter),
+            index => self.actions.get_mut(index).ok_or(ProcessError::InvalidParameter)?,
+        };
+        Ok(core::mem::replace(slot, disposition))
+    }
+
+    /// Exec semantics: caught signals revert to default, ignored stay ignored
+    pub fn reset_on_exec(&mut self) {
+        for action in self.actions.iter_mut() {
+            if let SignalDisposition::Handler(_) = action {
+                *action = SignalDisposition::Default;
+            }
+        }
+    }
+
+    /// Reset the given signals to their default action
+    pub fn reset_to_default(&mut self, mask: u64) {
+        for (signal, action) in self.actions.iter_mut().enumerate() {
+            if mask & (1 << signal) != 0 {
+                *action = SignalDisposition::Default;
+            }
+        }
+    }
+}
+
+/// File action applied in the child by `posix_spawn`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpawnFileAction {
+    Close(i32),
+    Dup2 { old_fd: i32, new_fd: i32 },
+}
+
+/// Attributes applied in the child by `posix_spawn`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SpawnAttributes {
+    /// Move the child into this process group (0 = its own)
+    pub pgroup: Option<ProcessId>,
+    /// Start a new session
+    pub setsid: bool,
+    /// Signals reset to their default action
+    pub sigdefault: u64,
+    /// Initial blocked signal mask
+    pub sigmask: Option<u64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fd_table_dup_and_cloexec() {
+        let mut table = FileDescriptorTable::new();
+        table.insert(0, FileDescriptor { handle: 10, fd_flags: 0 });
+        table.insert(3, FileDescriptor { handle: 11, fd_flags: FD_CLOEXEC });
+
+        table.dup2(3, 5).unwrap();
+        assert_eq!(table.get(5), Some(FileDescriptor { handle: 11, fd_flags: 0 }));
+        assert!(table.dup2(7, 8).is_err());
+
+        table.close_on_exec();
+        assert_eq!(table.fds(), vec![0, 5]);
+    }
+
+    #[test]
+    fn test_signal_dispositions_reset_on_exec() {
+        let mut signals = SignalDispositions::default();
+        signals.set(2, SignalDisposition::Handler(0x4000)).unwrap();
+        signals.set(13, SignalDisposition::Ignore).unwrap();
+        assert!(signals.set(0, SignalDisposition::Ignore).is_err());
+
+        signals.reset_on_exec();
+        assert_eq!(signals.get(2), SignalDisposition::Default);
+        assert_eq!(signals.get(13), SignalDisposition::Ignore);
+    }
+}
@@ -0,0 +1,742 @@
+//! Futex (Fast Userspace Mutex) Support for MultiOS
+//!
+//! This module provides the kernel side of the FUTEX system call used by the
+//! POSIX threading layer to block and wake threads:
+//! - Wait on an address with an atomic value check
+//! - Wake up to N waiters, requeue waiters onto another address
+//! - Relative timeouts handled by the timer path
+//! - Priority inheritance for PI lock/unlock, backed by the kernel PI
+//!   mutexes of `wait_queue`
+
+use alloc::collections::{BTreeMap, BTreeSet, VecDeque};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use spin::Mutex;
+
+use crate::Priority;
+use crate::thread::{ThreadId, THREAD_MANAGER};
+use crate::wait_queue::{apply_priority_changes, PiLockOutcome, PiMutexError, PiMutexId, PriorityChange, PI_MUTEX_MANAGER};
+
+/// Futex operation codes (Linux-compatible numbering)
+pub const FUTEX_WAIT: u32 = 0;
+pub const FUTEX_WAKE: u32 = 1;
+pub const FUTEX_REQUEUE: u32 = 3;
+pub const FUTEX_CMP_REQUEUE: u32 = 4;
+pub const FUTEX_LOCK_PI: u32 = 6;
+pub const FUTEX_UNLOCK_PI: u32 = 7;
+pub const FUTEX_PRIVATE_FLAG: u32 = 128;
+const FUTEX_CMD_MASK: u32 = !FUTEX_PRIVATE_FLAG;
+
+/// PI futex word: waiters present
+pub const FUTEX_WAITERS: u32 = 0x8000_0000;
+/// PI futex word: owner thread ID mask
+pub const FUTEX_TID_MASK: u32 = 0x3fff_ffff;
+
+/// Futex result type
+pub type FutexResult<T> = Result<T, FutexError>;
+
+/// Futex errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FutexError {
+    /// Futex word did not hold the expected value (EAGAIN)
+    WouldBlock,
+    /// Timeout expired before a wakeup (ETIMEDOUT)
+    TimedOut,
+    /// Bad operation or argument (EINVAL)
+    InvalidArgument,
+    /// Caller does not own the PI futex (EPERM)
+    NotOwner,
+    /// Caller already owns the PI futex (EDEADLK)
+    Deadlock,
+    /// The TID in a PI futex word names no thread (ESRCH)
+    OwnerNotFound,
+}
+
+impl FutexError {
+    /// Errno value reported to user space
+    pub fn errno(self) -> i32 {
+        match self {
+            FutexError::WouldBlock => 11,
+            FutexError::TimedOut => 110,
+            FutexError::InvalidArgument => 22,
+            FutexError::NotOwner => 1,
+            FutexError::Deadlock => 35,
+            FutexError::OwnerNotFound => 3,
+        }
+    }
+}
+
+/// Futex decoded operation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FutexOp {
+    Wait,
+    Wake,
+    Requeue,
+    CmpRequeue,
+    LockPi,
+    UnlockPi,
+}
+
+impl FutexOp {
+    /// Decode a raw futex op, returning the command and the private flag
+    pub fn decode(op: u32) -> FutexResult<(FutexOp, bool)> {
+        let private = op & FUTEX_PRIVATE_FLAG != 0;
+        let cmd = match op & FUTEX_CMD_MASK {
+            FUTEX_WAIT => FutexOp::Wait,
+            FUTEX_WAKE => FutexOp::Wake,
+            FUTEX_REQUEUE => FutexOp::Requeue,
+            FUTEX_CMP_REQUEUE => FutexOp::CmpRequeue,
+            FUTEX_LOCK_PI => FutexOp::LockPi,
+            FUTEX_UNLOCK_PI => FutexOp::UnlockPi,
+            _ => return Err(FutexError::InvalidArgument),
+        };
+        Ok((cmd, private))
+    }
+}
+
+/// Futex hash key
+///
+/// Private futexes are keyed by (process, address); shared futexes use the
+/// address alone (which the caller resolves to a physical/shared mapping).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FutexKey {
+    pub process_id: Option<usize>,
+    pub address: usize,
+}
+
+impl FutexKey {
+    pub fn private(process_id: usize, address: usize) -> Self {
+        Self { process_id: Some(process_id), address }
+    }
+
+    pub fn shared(address: usize) -> Self {
+        Self { process_id: None, address }
+    }
+}
+
+/// A thread blocked on a futex
+#[derive(Debug, Clone, Copy)]
+pub struct FutexWaiter {
+    pub thread_id: ThreadId,
+    pub priority: Priority,
+    /// Absolute timeout (nanoseconds)
+    pub deadline_ns: Option<u64>,
+}
+
+/// Futex statistics
+#[derive(Debug, Default)]
+pub struct FutexStats {
+    pub waits: AtomicU64,
+    pub wakes: AtomicU64,
+    pub requeues: AtomicU64,
+    pub timeouts: AtomicU64,
+    pub value_mismatches: AtomicU64,
+    pub pi_boosts: AtomicU64,
+}
+
+/// Futex statistics snapshot
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FutexStatsSnapshot {
+    pub waits: u64,
+    pub wakes: u64,
+    pub requeues: u64,
+    pub timeouts: u64,
+    pub value_mismatches: u64,
+    pub pi_boosts: u64,
+}
+
+impl FutexStats {
+    const fn new() -> Self {
+        Self {
+            waits: AtomicU64::new(0),
+            wakes: AtomicU64::new(0),
+            requeues: AtomicU64::new(0),
+            timeouts: AtomicU64::new(0),
+            value_mismatches: AtomicU64::new(0),
+            pi_boosts: AtomicU64::new(0),
+        }
+    }
+}
+
+/// Futex wait-queue manager
+///
+/// Lock order: queues, then PI mutexes, then the thread manager.
+#[derive(Debug)]
+pub struct FutexManager {
+    queues: Mutex<BTreeMap<FutexKey, VecDeque<FutexWaiter>>>,
+    /// Kernel PI mutex of each contended PI futex
+    pi_mutexes: Mutex<BTreeMap<FutexKey, PiMutexId>>,
+    /// Waiters that left their queue by deadline rather than a wake
+    timed_out: Mutex<BTreeSet<ThreadId>>,
+    stats: FutexStats,
+}
+
+impl FutexManager {
+    /// Create an empty futex manager
+    pub const fn new() -> Self {
+        Self {
+            queues: Mutex::new(BTreeMap::new()),
+            pi_mutexes: Mutex::new(BTreeMap::new()),
+            timed_out: Mutex::new(BTreeSet::new()),
+            stats: FutexStats::new(),
+        }
+    }
+
+    /// Block `waiter` on `key` if `*uaddr == expected`.
+    ///
+    /// The value check, enqueue and parking of the thread all happen under
+    /// the queue lock, so a concurrent wake cannot be lost.
+    pub fn wait(&self, key: FutexKey, uaddr: &AtomicU32, expected: u32, waiter: FutexWaiter) -> FutexResult<()> {
+        let mut queues = self.queues.lock();
+
+        if uaddr.load(Ordering::SeqCst) != expected {
+            self.stats.value_mismatches.fetch_add(1, Ordering::Relaxed);
+            return Err(FutexError::WouldBlock);
+        }
+
+        self.park(&mut queues, key, waiter);
+        self.stats.waits.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Queue a waiter and mark its thread Waiting
+    fn park(&self, queues: &mut BTreeMap<FutexKey, VecDeque<FutexWaiter>>, key: FutexKey, waiter: FutexWaiter) {
+        self.timed_out.lock().remove(&waiter.thread_id);
+        let queue = queues.entry(key).or_insert_with(VecDeque::new);
+        if self.pi_mutexes.lock().contains_key(&key) {
+            // Keep PI queues ordered by priority like the kernel mutex's
+            let pos = queue.iter().position(|w| w.priority < waiter.priority).unwrap_or(queue.len());
+            queue.insert(pos, waiter);
+        } else {
+            queue.push_back(waiter);
+        }
+        let _ = THREAD_MANAGER.sleep_thread(waiter.thread_id, 0);
+    }
+
+    /// Wake up to `count` waiters on `key`, returning the woken threads
+    pub fn wake(&self, key: FutexKey, count: usize) -> Vec<ThreadId> {
+        let mut queues = self.queues.lock();
+        let woken = Self::dequeue(&mut queues, key, count);
+        self.stats.wakes.fetch_add(woken.len() as u64, Ordering::Relaxed);
+        woken
+    }
+
+    /// Wake `wake_count` waiters on `from` and move up to `requeue_count`
+    /// of the remaining waiters to `to`.
+    ///
+    /// If `check` is given (CMP_REQUEUE) the operation fails with
+    /// `WouldBlock` when `*uaddr` no longer matches.
+    pub fn requeue(
+        &self,
+        from: FutexKey,
+        to: FutexKey,
+        wake_count: usize,
+        requeue_count: usize,
+        check: Option<(&AtomicU32, u32)>,
+    ) -> FutexResult<(Vec<ThreadId>, usize)> {
+        let mut queues = self.queues.lock();
+
+        if let Some((uaddr, expected)) = check {
+            if uaddr.load(Ordering::SeqCst) != expected {
+                self.stats.value_mismatches.fetch_add(1, Ordering::Relaxed);
+                return Err(FutexError::WouldBlock);
+            }
+        }
+
+        let woken = Self::dequeue(&mut queues, from, wake_count);
+
+        let mut moved = Vec::new();
+        if let Some(queue) = queues.get_mut(&from) {
+            while moved.len() < requeue_count {
+                match queue.pop_front() {
+                    Some(waiter) => moved.push(waiter),
+                    None => break,
+                }
+            }
+            if queue.is_empty() {
+                queues.remove(&from);
+            }
+        }
+
+        let moved_count = moved.len();
+        if moved_count > 0 {
+            queues.entry(to).or_insert_with(VecDeque::new).extend(moved);
+        }
+
+        self.stats.wakes.fetch_add(woken.len() as u64, Ordering::Relaxed);
+        self.stats.requeues.fetch_add(moved_count as u64, Ordering::Relaxed);
+        Ok((woken, moved_count))
+    }
+
+    /// Remove a waiter (signal delivery or cancellation)
+    pub fn cancel_wait(&self, key: FutexKey, thread_id: ThreadId) -> bool {
+        let mut queues = self.queues.lock();
+        let mut removed = false;
+
+        if let Some(queue) = queues.get_mut(&key) {
+            let before = queue.len();
+            queue.retain(|w| w.thread_id != thread_id);
+            removed = queue.len() != before;
+            if queue.is_empty() {
+                queues.remove(&key);
+            }
+        }
+        if removed {
+            self.cancel_pi_wait(key, thread_id);
+        }
+
+        removed
+    }
+
+    /// Expire waiters whose deadline has passed, returning the timed-out threads
+    ///
+    /// Each is remembered so `take_timeout()` reports it once the thread runs.
+    pub fn expire_timeouts(&self, now_ns: u64) -> Vec<ThreadId> {
+        let mut queues = self.queues.lock();
+        let mut expired = Vec::new();
+
+        for (&key, queue) in queues.iter_mut() {
+            queue.retain(|w| match w.deadline_ns {
+                Some(deadline) if deadline <= now_ns => {
+                    expired.push((key, w.thread_id));
+                    false
+                }
+                _ => true,
+            });
+        }
+        queues.retain(|_, queue| !queue.is_empty());
+
+        let mut timed_out = self.timed_out.lock();
+        for &(key, thread_id) in &expired {
+            timed_out.insert(thread_id);
+            self.cancel_pi_wait(key, thread_id);
+        }
+
+        self.stats.timeouts.fetch_add(expired.len() as u64, Ordering::Relaxed);
+        expired.into_iter().map(|(_, thread_id)| thread_id).collect()
+    }
+
+    /// Whether the thread's last wait ended by timeout, clearing the mark
+    pub fn take_timeout(&self, thread_id: ThreadId) -> bool {
+        self.timed_out.lock().remove(&thread_id)
+    }
+
+    /// Acquire a PI futex.
+    ///
+    /// Returns `Ok(true)` if the lock was taken immediately, or `Ok(false)` if
+    /// the caller was queued and parked. The owner, and every owner it is in
+    /// turn blocked behind, inherits the waiter's priority.
+    pub fn lock_pi(&self, key: FutexKey, uaddr: &AtomicU32, waiter: FutexWaiter) -> FutexResult<bool> {
+        let mut queues = self.queues.lock();
+        let tid = waiter.thread_id as u32 & FUTEX_TID_MASK;
+
+        // The owner can release the word in user space while we look at it;
+        // retry until it is either ours or marked as having waiters
+        let owner = loop {
+            let current = uaddr.load(Ordering::SeqCst);
+            let owner = current & FUTEX_TID_MASK;
+            if owner == tid {
+                return Err(FutexError::Deadlock);
+            }
+            if owner == 0 {
+                let taken = tid | (current & FUTEX_WAITERS);
+                if uaddr.compare_exchange(current, taken, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+                    return Ok(true);
+                }
+                continue;
+            }
+            if current & FUTEX_WAITERS != 0
+                || uaddr.compare_exchange(current, current | FUTEX_WAITERS, Ordering::SeqCst, Ordering::SeqCst).is_ok()
+            {
+                break owner as ThreadId;
+            }
+        };
+
+        let id = self.pi_mutex(key, owner)?;
+        let changes = match PI_MUTEX_MANAGER.lock(id, waiter.thread_id, waiter.priority) {
+            Ok(PiLockOutcome::Blocked { changes, .. }) => changes,
+            Err(PiMutexError::Deadlock) => return Err(FutexError::Deadlock),
+            // The owner was just recorded, so the mutex cannot have been free
+            Ok(PiLockOutcome::Acquired) | Err(_) => return Err(FutexError::InvalidArgument),
+        };
+
+        self.park(&mut queues, key, waiter);
+        self.apply_pi_changes(&changes);
+        self.stats.waits.fetch_add(1, Ordering::Relaxed);
+        Ok(false)
+    }
+
+    /// Release a PI futex, handing it to the highest-priority waiter.
+    ///
+    /// Returns the new owner, if any.
+    pub fn unlock_pi(&self, key: FutexKey, uaddr: &AtomicU32, owner: ThreadId) -> FutexResult<Option<ThreadId>> {
+        let mut queues = self.queues.lock();
+        let current = uaddr.load(Ordering::SeqCst);
+
+        if (current & FUTEX_TID_MASK) as ThreadId != owner {
+            return Err(FutexError::NotOwner);
+        }
+
+        let id = match self.pi_mutexes.lock().get(&key).copied() {
+            Some(id) => id,
+            None => {
+                uaddr.store(0, Ordering::SeqCst);
+                return Ok(None);
+            }
+        };
+        let outcome = PI_MUTEX_MANAGER.unlock(id, owner).map_err(|_| FutexError::NotOwner)?;
+        let mut changes = outcome.changes;
+
+        let word = match outcome.next_owner {
+            Some(next) => {
+                if let Some(queue) = queues.get_mut(&key) {
+                    queue.retain(|w| w.thread_id != next);
+                    if queue.is_empty() {
+                        queues.remove(&key);
+                    }
+                }
+                self.stats.wakes.fetch_add(1, Ordering::Relaxed);
+
+                let tid = next as u32 & FUTEX_TID_MASK;
+                if PI_MUTEX_MANAGER.waiter_count(id) > 0 {
+                    tid | FUTEX_WAITERS
+                } else {
+                    // Uncontended again: the new owner releases it in user space
+                    if let Ok(handoff) = PI_MUTEX_MANAGER.unlock(id, next) {
+                        changes.extend(handoff.changes);
+                    }
+                    self.forget_pi_mutex(key, id);
+                    tid
+                }
+            }
+            None => {
+                self.forget_pi_mutex(key, id);
+                0
+            }
+        };
+        uaddr.store(word, Ordering::SeqCst);
+        self.apply_pi_changes(&changes);
+        Ok(outcome.next_owner)
+    }
+
+    /// Priority a PI futex owner has inherited from its waiters
+    pub fn inherited_priority(&self, owner: ThreadId) -> Option<Priority> {
+        PI_MUTEX_MANAGER.effective_priority(owner)
+    }
+
+    /// Kernel PI mutex of `key`, held by `owner`
+    ///
+    /// Uncontended PI futexes are taken in user space, so the kernel learns
+    /// the owner only when the first waiter arrives.
+    fn pi_mutex(&self, key: FutexKey, owner: ThreadId) -> FutexResult<PiMutexId> {
+        let mut pi_mutexes = self.pi_mutexes.lock();
+        let id = *pi_mutexes.entry(key).or_insert_with(|| PI_MUTEX_MANAGER.create());
+        match PI_MUTEX_MANAGER.owner(id) {
+            Some(current) if current == owner => Ok(id),
+            Some(_) => Err(FutexError::InvalidArgument),
+            None => {
+                let priority = THREAD_MANAGER
+                    .get_thread_stats(owner)
+                    .map_err(|_| FutexError::OwnerNotFound)?
+                    .priority;
+                match PI_MUTEX_MANAGER.try_lock(id, owner, priority) {
+                    Ok(true) => Ok(id),
+                    _ => Err(FutexError::InvalidArgument),
+                }
+            }
+        }
+    }
+
+    fn forget_pi_mutex(&self, key: FutexKey, id: PiMutexId) {
+        self.pi_mutexes.lock().remove(&key);
+        let _ = PI_MUTEX_MANAGER.destroy(id);
+    }
+
+    /// Undo the boosts of a PI waiter that stopped waiting
+    fn cancel_pi_wait(&self, key: FutexKey, thread_id: ThreadId) {
+        let id = self.pi_mutexes.lock().get(&key).copied();
+        if let Some(id) = id {
+            if let Ok(changes) = PI_MUTEX_MANAGER.cancel_wait(id, thread_id) {
+                self.apply_pi_changes(&changes);
+            }
+        }
+    }
+
+    fn apply_pi_changes(&self, changes: &[PriorityChange]) {
+        let boosts = changes.iter().filter(|change| change.to > change.from).count();
+        self.stats.pi_boosts.fetch_add(boosts as u64, Ordering::Relaxed);
+        apply_priority_changes(changes);
+    }
+
+    /// Earliest timeout among all queued waiters
+    pub fn next_deadline(&self) -> Option<u64> {
+        let queues = self.queues.lock();
+        queues.values().flat_map(|queue| queue.iter()).filter_map(|w| w.deadline_ns).min()
+    }
+
+    /// Number of threads waiting on `key`
+    pub fn waiter_count(&self, key: FutexKey) -> usize {
+        self.queues.lock().get(&key).map_or(0, |q| q.len())
+    }
+
+    /// Get futex statistics
+    pub fn get_stats(&self) -> FutexStatsSnapshot {
+        FutexStatsSnapshot {
+            waits: self.stats.waits.load(Ordering::Relaxed),
+            wakes: self.stats.wakes.load(Ordering::Relaxed),
+            requeues: self.stats.requeues.load(Ordering::Relaxed),
+            timeouts: self.stats.timeouts.load(Ordering::Relaxed),
+            value_mismatches: self.stats.value_mismatches.load(Ordering::Relaxed),
+            pi_boosts: self.stats.pi_boosts.load(Ordering::Relaxed),
+        }
+    }
+
+    fn dequeue(queues: &mut BTreeMap<FutexKey, VecDeque<FutexWaiter>>, key: FutexKey, count: usize) -> Vec<ThreadId> {
+        let mut woken = Vec::new();
+        if let Some(queue) = queues.get_mut(&key) {
+            while woken.len() < count {
+                match queue.pop_front() {
+                    Some(waiter) => woken.push(waiter.thread_id),
+                    None => break,
+                }
+            }
+            if queue.is_empty() {
+                queues.remove(&key);
+            }
+        }
+        woken
+    }
+}
+
+/// Global futex manager instance
+pub static FUTEX_MANAGER: FutexManager = FutexManager::new();
+
+/// FUTEX system call entry point.
+///
+/// `uaddr`/`uaddr2` have already been translated and validated by the syscall
+/// layer. Returns the number of threads woken (or 0 for wait/lock).
+pub fn sys_futex(
+    process_id: usize,
+    thread_id: ThreadId,
+    priority: Priority,
+    uaddr: &AtomicU32,
+    op: u32,
+    val: u32,
+    timeout_ns: Option<u64>,
+    uaddr2: Option<&AtomicU32>,
+    val3: u32,
+    now_ns: u64,
+) -> FutexResult<usize> {
+    let (cmd, private) = FutexOp::decode(op)?;
+    let key_of = |addr: &AtomicU32| {
+        let address = addr as *const AtomicU32 as usize;
+        if private { FutexKey::private(process_id, address) } else { FutexKey::shared(address) }
+    };
+    let key = key_of(uaddr);
+    let waiter = FutexWaiter {
+        thread_id,
+        priority,
+        deadline_ns: timeout_ns.map(|t| now_ns.saturating_add(t)),
+    };
+
+    match cmd {
+        FutexOp::Wait => {
+            if timeout_ns == Some(0) {
+                let current = uaddr.load(Ordering::SeqCst);
+                return Err(if current == val { FutexError::TimedOut } else { FutexError::WouldBlock });
+            }
+            FUTEX_MANAGER.wait(key, uaddr, val, waiter)?;
+            Ok(0)
+        }
+        FutexOp::Wake => {
+            let woken = FUTEX_MANAGER.wake(key, val as usize);
+            for &tid in &woken {
+                let _ = THREAD_MANAGER.wake_thread(tid);
+            }
+            Ok(woken.len())
+        }
+        FutexOp::Requeue | FutexOp::CmpRequeue => {
+            let target = uaddr2.ok_or(FutexError::InvalidArgument)?;
+            let check = if cmd == FutexOp::CmpRequeue { Some((uaddr, val3)) } else { None };
+            // The requeue limit travels in the timeout argument slot
+            let requeue_count = timeout_ns.unwrap_or(u32::MAX as u64) as usize;
+            let (woken, moved) = FUTEX_MANAGER.requeue(key, key_of(target), val as usize, requeue_count, check)?;
+            for &tid in &woken {
+                let _ = THREAD_MANAGER.wake_thread(tid);
+            }
+            Ok(woken.len() + moved)
+        }
+        FutexOp::LockPi => {
+            FUTEX_MANAGER.lock_pi(key, uaddr, waiter)?;
+            Ok(0)
+        }
+        FutexOp::UnlockPi => {
+            match FUTEX_MANAGER.unlock_pi(key, uaddr, thread_id)? {
+                Some(next) => {
+                    let _ = THREAD_MANAGER.wake_thread(next);
+                    Ok(1)
+                }
+                None => Ok(0),
+            }
+        }
+    }
+}
+
+/// Timer path: wake futex waiters whose deadline has passed
+///
+/// Returns the number of threads woken.
+pub fn futex_expire(now_ns: u64) -> usize {
+    let expired = FUTEX_MANAGER.expire_timeouts(now_ns);
+    for &tid in &expired {
+        let _ = THREAD_MANAGER.wake_thread(tid);
+    }
+    expired.len()
+}
+
+/// Result of a blocking FUTEX_WAIT or FUTEX_LOCK_PI, taken when the thread
+/// runs again: `TimedOut` if its deadline woke it rather than a waker
+pub fn futex_wait_result(thread_id: ThreadId) -> FutexResult<usize> {
+    if FUTEX_MANAGER.take_timeout(thread_id) {
+        Err(FutexError::TimedOut)
+    } else {
+        Ok(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::thread::ThreadParams;
+
+    /// TIDs far past any thread the tests create, so parking them is a no-op
+    const GHOST: ThreadId = 1 << 20;
+
+    fn waiter(thread_id: ThreadId, priority: Priority) -> FutexWaiter {
+        FutexWaiter { thread_id, priority, deadline_ns: None }
+    }
+
+    fn spawn(priority: Priority) -> ThreadId {
+        let params = ThreadParams { stack_size: 4096, priority, detached: false, inherit_priority: false };
+        let handle = THREAD_MANAGER.create_thread(1, b"futex".to_vec(), None, params).unwrap();
+        let thread_id = handle.lock().thread_id;
+        thread_id
+    }
+
+    #[test]
+    fn test_wait_checks_value() {
+        let manager = FutexManager::new();
+        let word = AtomicU32::new(1);
+        let key = FutexKey::private(1, 0x1000);
+
+        assert_eq!(manager.wait(key, &word, 0, waiter(GHOST, Priority::Normal)), Err(FutexError::WouldBlock));
+        assert!(manager.wait(key, &word, 1, waiter(GHOST, Priority::Normal)).is_ok());
+        assert_eq!(manager.waiter_count(key), 1);
+    }
+
+    #[test]
+    fn test_wake_n_fifo() {
+        let manager = FutexManager::new();
+        let word = AtomicU32::new(0);
+        let key = FutexKey::shared(0x2000);
+
+        for tid in GHOST..GHOST + 3 {
+            manager.wait(key, &word, 0, waiter(tid, Priority::Normal)).unwrap();
+        }
+
+        assert_eq!(manager.wake(key, 2), vec![GHOST, GHOST + 1]);
+        assert_eq!(manager.waiter_count(key), 1);
+    }
+
+    #[test]
+    fn test_requeue_moves_waiters() {
+        let manager = FutexManager::new();
+        let word = AtomicU32::new(0);
+        let from = FutexKey::private(1, 0x10);
+        let to = FutexKey::private(1, 0x20);
+
+        for tid in GHOST..GHOST + 4 {
+            manager.wait(from, &word, 0, waiter(tid, Priority::Normal)).unwrap();
+        }
+
+        let (woken, moved) = manager.requeue(from, to, 1, 10, Some((&word, 0))).unwrap();
+        assert_eq!(woken, vec![GHOST]);
+        assert_eq!(moved, 3);
+        assert_eq!(manager.waiter_count(to), 3);
+        assert!(manager.requeue(from, to, 1, 1, Some((&word, 5))).is_err());
+    }
+
+    #[test]
+    fn test_wait_parks_before_a_wake_can_run() {
+        let manager = FutexManager::new();
+        let word = AtomicU32::new(0);
+        let key = FutexKey::private(1, 0x50);
+        let thread = spawn(Priority::Normal);
+
+        manager.wait(key, &word, 0, waiter(thread, Priority::Normal)).unwrap();
+        assert_eq!(THREAD_MANAGER.is_thread_running(thread), Ok(false));
+
+        for tid in manager.wake(key, 1) {
+            THREAD_MANAGER.wake_thread(tid).unwrap();
+        }
+        assert_eq!(THREAD_MANAGER.is_thread_running(thread), Ok(true));
+    }
+
+    #[test]
+    fn test_timeouts_expire() {
+        let manager = FutexManager::new();
+        let word = AtomicU32::new(0);
+        let key = FutexKey::shared(0x30);
+
+        manager.wait(key, &word, 0, FutexWaiter { thread_id: GHOST, priority: Priority::Low, deadline_ns: Some(100) }).unwrap();
+        assert_eq!(manager.next_deadline(), Some(100));
+        assert!(manager.expire_timeouts(50).is_empty());
+        assert_eq!(manager.expire_timeouts(100), vec![GHOST]);
+        assert_eq!(manager.get_stats().timeouts, 1);
+        assert_eq!(manager.next_deadline(), None);
+
+        // Reported once, and not carried into the next wait
+        assert!(manager.take_timeout(GHOST));
+        assert!(!manager.take_timeout(GHOST));
+    }
+
+    #[test]
+    fn test_pi_lock_boosts_owner_and_hands_off() {
+        let manager = FutexManager::new();
+        let word = AtomicU32::new(0);
+        let key = FutexKey::private(1, 0x40);
+        let owner = spawn(Priority::Low);
+        let contender = spawn(Priority::High);
+
+        assert_eq!(manager.lock_pi(key, &word, waiter(owner, Priority::Low)), Ok(true));
+        assert_eq!(manager.lock_pi(key, &word, waiter(owner, Priority::Low)), Err(FutexError::Deadlock));
+        assert_eq!(manager.lock_pi(key, &word, waiter(contender, Priority::High)), Ok(false));
+        assert_eq!(manager.inherited_priority(owner), Some(Priority::High));
+        assert_eq!(THREAD_MANAGER.get_thread_stats(owner).unwrap().priority, Priority::High);
+        assert_eq!(THREAD_MANAGER.is_thread_running(contender), Ok(false));
+
+        assert_eq!(manager.unlock_pi(key, &word, contender), Err(FutexError::NotOwner));
+        assert_eq!(manager.unlock_pi(key, &word, owner), Ok(Some(contender)));
+        assert_eq!(word.load(Ordering::SeqCst), contender as u32);
+        assert_eq!(manager.inherited_priority(owner), None);
+        assert_eq!(THREAD_MANAGER.get_thread_stats(owner).unwrap().priority, Priority::Low);
+    }
+
+    #[test]
+    fn test_pi_lock_takes_word_released_with_waiters_bit() {
+        let manager = FutexManager::new();
+        let word = AtomicU32::new(FUTEX_WAITERS);
+        let key = FutexKey::private(1, 0x60);
+        let thread = spawn(Priority::Normal);
+
+        // No owner to boost: the word is free apart from the stale bit
+        assert_eq!(manager.lock_pi(key, &word, waiter(thread, Priority::Normal)), Ok(true));
+        assert_eq!(word.load(Ordering::SeqCst), thread as u32 | FUTEX_WAITERS);
+        assert_eq!(manager.get_stats().pi_boosts, 0);
+    }
+
+    #[test]
+    fn test_decode_op() {
+        assert_eq!(FutexOp::decode(FUTEX_WAKE | FUTEX_PRIVATE_FLAG), Ok((FutexOp::Wake, true)));
+        assert_eq!(FutexOp::decode(42), Err(FutexError::InvalidArgument));
+    }
+}
@@ -0,0 +1,280 @@
+//! CPU Hotplug State Machine and Notifier Chain for MultiOS
+//!
+//! This module lets subsystems react to CPUs coming and going:
+//! - Per-CPU hotplug state machine: DEAD -> PREPARE -> ONLINE -> OFFLINE -> DEAD
+//! - Callbacks registered with a priority and run in order
+//!   (highest priority first on bring-up, reverse order on tear-down)
+//! - Failed transitions are rolled back on the callbacks already notified
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use crate::{SchedulerError, SchedulerResult};
+use crate::scheduler_algo::CpuId;
+
+/// Priority of the scheduler's own hotplug handling
+pub const HOTPLUG_PRIO_SCHED: i32 = 100;
+/// Priority for NUMA topology updates
+pub const HOTPLUG_PRIO_NUMA: i32 = 80;
+/// Priority for performance monitoring counters
+pub const HOTPLUG_PRIO_PERF: i32 = 60;
+/// Priority for hypervisor VCPU rebalancing
+pub const HOTPLUG_PRIO_VCPU: i32 = 40;
+
+/// CPU hotplug state
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HotplugState {
+    /// CPU is powered off and unknown to subsystems
+    Dead,
+    /// Subsystems allocate per-CPU resources before the CPU runs
+    Prepare,
+    /// CPU is running and schedulable
+    Online,
+    /// CPU stopped taking work; subsystems migrate state off it
+    Offline,
+}
+
+/// Hotplug notifier callback, invoked with the CPU and the state being entered
+pub type HotplugCallback = Box<dyn Fn(CpuId, HotplugState) -> SchedulerResult<()> + Send + Sync>;
+
+/// Handle identifying a registered callback
+pub type NotifierId = usize;
+
+/// Registered notifier
+struct Notifier {
+    id: NotifierId,
+    name: &'static str,
+    priority: i32,
+    callback: HotplugCallback,
+}
+
+/// Hotplug statistics
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HotplugStats {
+    pub cpus_onlined: u64,
+    pub cpus_offlined: u64,
+    pub callbacks_run: u64,
+    pub rollbacks: u64,
+}
+
+/// Ordered CPU hotplug notifier chain
+pub struct HotplugNotifier {
+    notifiers: Vec<Notifier>,
+    states: Vec<HotplugState>,
+    next_id: NotifierId,
+    stats: HotplugStats,
+}
+
+impl HotplugNotifier {
+    /// Create a chain for `cpu_count` CPUs, all initially online
+    pub fn new(cpu_count: usize) -> Self {
+        Self {
+            notifiers: Vec::new(),
+            states: vec![HotplugState::Online; cpu_count],
+            next_id: 1,
+            stats: HotplugStats::default(),
+        }
+    }
+
+    /// Register a callback; higher priorities run first on bring-up
+    pub fn register(&mut self, name: &'static str, priority: i32, callback: HotplugCallback) -> NotifierId {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let index = self.notifiers.iter()
+            .position(|notifier| notifier.priority < priority)
+            .unwrap_or(self.notifiers.len());
+        self.notifiers.insert(index, Notifier { id, name, priority, callback });
+        id
+    }
+
+    /// Remove a previously registered callback
+    pub fn unregister(&mut self, id: NotifierId) -> bool {
+        let before = self.notifiers.len();
+        self.notifiers.retain(|notifier| notifier.id != id);
+        self.notifiers.len() != before
+    }
+
+    /// Names of registered callbacks in bring-up order
+    pub fn registered(&self) -> Vec<&'static str> {
+        self.notifiers.iter().map(|notifier| notifier.name).collect()
+    }
+
+    /// Current hotplug state of a CPU
+    pub fn state(&self, cpu_id: CpuId) -> Option<HotplugState> {
+        self.states.get(cpu_id).copied()
+    }
+
+    /// Run PREPARE then ONLINE callbacks for a CPU.
+    ///
+    /// If any callback fails, the callbacks already notified are walked back
+    /// through OFFLINE/DEAD and the CPU stays dead.
+    pub fn bring_up(&mut self, cpu_id: CpuId) -> SchedulerResult<()> {
+        match self.state(cpu_id) {
+            Some(HotplugState::Dead) => {}
+            Some(HotplugState::Online) => return Ok(()),
+            _ => return Err(SchedulerError::InvalidThreadId),
+        }
+
+        self.transition(cpu_id, HotplugState::Prepare, HotplugState::Dead, false)?;
+        if let Err(error) = self.transition(cpu_id, HotplugState::Online, HotplugState::Offline, false) {
+            self.run_all(cpu_id, HotplugState::Dead, true);
+            self.states[cpu_id] = HotplugState::Dead;
+            return Err(error);
+        }
+
+        self.stats.cpus_onlined += 1;
+        Ok(())
+    }
+
+    /// Run OFFLINE then DEAD callbacks for a CPU.
+    ///
+    /// An OFFLINE callback may veto the removal, in which case the callbacks
+    /// already notified are told the CPU is online again.
+    pub fn tear_down(&mut self, cpu_id: CpuId) -> SchedulerResult<()> {
+        if self.state(cpu_id) == Some(HotplugState::Dead) {
+            return Ok(());
+        }
+        self.offline(cpu_id)?;
+        self.dead(cpu_id)
+    }
+
+    /// Run OFFLINE callbacks for an online CPU, the first half of `tear_down`.
+    ///
+    /// The caller empties the CPU next and then calls `dead`, or
+    /// `cancel_offline` if it could not.
+    pub fn offline(&mut self, cpu_id: CpuId) -> SchedulerResult<()> {
+        if self.state(cpu_id) != Some(HotplugState::Online) {
+            return Err(SchedulerError::InvalidThreadId);
+        }
+        self.transition(cpu_id, HotplugState::Offline, HotplugState::Online, true)
+    }
+
+    /// Tell every callback an offlined CPU is online again
+    pub fn cancel_offline(&mut self, cpu_id: CpuId) -> SchedulerResult<()> {
+        if self.state(cpu_id) != Some(HotplugState::Offline) {
+            return Err(SchedulerError::InvalidThreadId);
+        }
+        self.stats.rollbacks += 1;
+        self.run_all(cpu_id, HotplugState::Online, false);
+        self.states[cpu_id] = HotplugState::Online;
+        Ok(())
+    }
+
+    /// Run DEAD callbacks for a CPU that went through `offline`
+    pub fn dead(&mut self, cpu_id: CpuId) -> SchedulerResult<()> {
+        if self.state(cpu_id) != Some(HotplugState::Offline) {
+            return Err(SchedulerError::InvalidThreadId);
+        }
+        // DEAD cannot be vetoed: the CPU is already gone
+        self.run_all(cpu_id, HotplugState::Dead, true);
+        self.states[cpu_id] = HotplugState::Dead;
+
+        self.stats.cpus_offlined += 1;
+        Ok(())
+    }
+
+    /// Get hotplug statistics
+    pub fn get_stats(&self) -> HotplugStats {
+        self.stats
+    }
+
+    /// Enter `state`, rolling notified callbacks back to `rollback` on failure
+    fn transition(&mut self, cpu_id: CpuId, state: HotplugState, rollback: HotplugState, reverse: bool) -> SchedulerResult<()> {
+        let order = self.order(reverse);
+
+        for (done, &index) in order.iter().enumerate() {
+            self.stats.callbacks_run += 1;
+            if let Err(error) = (self.notifiers[index].callback)(cpu_id, state) {
+                self.stats.rollbacks += 1;
+                for &undo in order[..done].iter().rev() {
+                    let _ = (self.notifiers[undo].callback)(cpu_id, rollback);
+                }
+                return Err(error);
+            }
+        }
+
+        self.states[cpu_id] = state;
+        Ok(())
+    }
+
+    /// Notify every callback, ignoring errors
+    fn run_all(&mut self, cpu_id: CpuId, state: HotplugState, reverse: bool) {
+        for index in self.order(reverse) {
+            self.stats.callbacks_run += 1;
+            let _ = (self.notifiers[index].callback)(cpu_id, state);
+        }
+    }
+
+    fn order(&self, reverse: bool) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..self.notifiers.len()).collect();
+        if reverse {
+            order.reverse();
+        }
+        order
+    }
+}
+
+impl core::fmt::Debug for HotplugNotifier {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("HotplugNotifier")
+            .field("notifiers", &self.registered())
+            .field("states", &self.states)
+            .field("stats", &self.stats)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::sync::Arc;
+    use spin::Mutex;
+
+    type Log = Arc<Mutex<Vec<(&'static str, HotplugState)>>>;
+
+    fn logger(log: &Log, name: &'static str) -> HotplugCallback {
+        let log = log.clone();
+        Box::new(move |_, state| {
+            log.lock().push((name, state));
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_callbacks_run_in_priority_order() {
+        let log: Log = Arc::new(Mutex::new(Vec::new()));
+        let mut chain = HotplugNotifier::new(2);
+        chain.register("perf", HOTPLUG_PRIO_PERF, logger(&log, "perf"));
+        chain.register("sched", HOTPLUG_PRIO_SCHED, logger(&log, "sched"));
+        assert_eq!(chain.registered(), vec!["sched", "perf"]);
+
+        chain.tear_down(1).unwrap();
+        assert_eq!(chain.state(1), Some(HotplugState::Dead));
+        chain.bring_up(1).unwrap();
+        assert_eq!(chain.state(1), Some(HotplugState::Online));
+
+        assert_eq!(*log.lock(), vec![
+            ("perf", HotplugState::Offline), ("sched", HotplugState::Offline),
+            ("perf", HotplugState::Dead), ("sched", HotplugState::Dead),
+            ("sched", HotplugState::Prepare), ("perf", HotplugState::Prepare),
+            ("sched", HotplugState::Online), ("perf", HotplugState::Online),
+        ]);
+    }
+
+    #[test]
+    fn test_offline_veto_rolls_back() {
+        let log: Log = Arc::new(Mutex::new(Vec::new()));
+        let mut chain = HotplugNotifier::new(1);
+        chain.register("vcpu", HOTPLUG_PRIO_VCPU, logger(&log, "vcpu"));
+        chain.register("sched", HOTPLUG_PRIO_SCHED, Box::new(|_, state| match state {
+            HotplugState::Offline => Err(SchedulerError::NoRunnableThreads),
+            _ => Ok(()),
+        }));
+
+        assert!(chain.tear_down(0).is_err());
+        assert_eq!(chain.state(0), Some(HotplugState::Online));
+        assert_eq!(*log.lock(), vec![("vcpu", HotplugState::Offline), ("vcpu", HotplugState::Online)]);
+        assert_eq!(chain.get_stats().rollbacks, 1);
+    }
+}
@@ -23,6 +23,21 @@ pub mod thread;
 pub mod scheduler_algo;
 pub mod multicore;
 pub mod performance_monitor;
+pub mod tickless;
+pub mod task_group;
+pub mod futex;
+pub mod numa_balancing;
+pub mod energy;
+pub mod thermal;
+pub mod lockfree;
+pub mod sched_trace;
+pub mod sched_latency;
+pub mod perf_events;
+pub mod anomaly;
+pub mod hotplug;
+pub mod fork;
+pub mod wait_queue;
+pub mod balance_policy;
 
 #[cfg(feature = "examples")]
 pub mod examples;
@@ -53,7 +68,7 @@ pub use multicore::{
     PerformanceMonitor, PerformanceConfig,
     CacheCoherencyMonitor, CacheProtocol, CacheState,
     LockFreeQueue, LockFreeStack, LockFreeCounter,
-    MemoryBarriers, CpuGovernor, ThermalAction,
+    MemoryBarriers, CpuGovernor, ThermalAction, TimerTick,
 };
 
 pub use performance_monitor::{
@@ -61,6 +76,79 @@ pub use performance_monitor::{
     AlertSeverity, AlertAction, PerformancePredictor,
     OptimizationObjective, PerformanceRegression,
     ResourceContentionAnalyzer, ContentionAnalysis,
+    TaskStats, TaskMetric, TaskAccounting, AlertCategory,
+};
+
+pub use anomaly::{
+    AnomalyDetector, AnomalyConfig, AnomalyEvent, SeasonalBaseline, ANOMALY_ALERT_ID_BASE,
+};
+
+pub use tickless::{
+    TickController, TicklessConfig, TickMode, TickStatsSnapshot, OneShotTimer,
+};
+
+pub use task_group::{
+    TaskGroupManager, TaskGroup, TaskGroupId, TaskGroupStats, CpuBandwidth,
+    ROOT_TASK_GROUP, DEFAULT_CPU_SHARES, with_task_groups,
+};
+
+pub use futex::{
+    FutexManager, FutexKey, FutexOp, FutexWaiter, FutexError, FutexResult,
+    FutexStatsSnapshot, FUTEX_MANAGER, sys_futex, futex_expire, futex_wait_result,
+};
+
+pub use numa_balancing::{
+    NumaBalancer, NumaBalancingConfig, NumaBalancingStats, NumaFaultSample,
+    MigrationCandidate, PageMigrator,
+};
+
+pub use energy::{
+    EnergyModel, EnergyStats, CpuEnergyProfile, OperatingPoint, CoreType,
+    SCHED_CAPACITY_SCALE,
+};
+
+pub use thermal::{
+    ThermalGovernor, ThermalGovernorConfig, ThermalGovernorStats, ThermalDecision,
+    CpuThermalState, TemperatureSensor, SimulatedSensor,
+};
+
+pub use lockfree::{
+    BoundedMpmcQueue, CachePadded, EpochCollector, EpochHandle, EpochGuard, EpochStats,
+};
+
+pub use sched_trace::{
+    SchedTracer, SchedEvent, SchedEventKind, SchedTraceStats, TraceFormat,
+    export_trace, wakeup_latencies,
+};
+
+pub use perf_events::{
+    PerfEventManager, PerfEventId, PerfGroupId, PerfEventValue, PerfEventError, PerfEventResult,
+    PerfTarget, PmuCounters, is_hardware_event,
+};
+
+pub use sched_latency::{
+    LatencyTracker, LatencyReport, LatencyHistogramSnapshot, LATENCY_BUCKETS, PRIORITY_CLASSES,
+};
+
+pub use hotplug::{
+    HotplugNotifier, HotplugState, HotplugCallback, HotplugStats, NotifierId,
+    HOTPLUG_PRIO_SCHED, HOTPLUG_PRIO_NUMA, HOTPLUG_PRIO_PERF, HOTPLUG_PRIO_VCPU,
+};
+
+pub use fork::{
+    AddressSpaceId, AddressSpaceOps, FileDescriptor, FileDescriptorTable,
+    SignalDisposition, SignalDispositions, SpawnAttributes, SpawnFileAction, FD_CLOEXEC,
+};
+
+pub use wait_queue::{
+    WaitQueue, WaitQueueEntry, PiMutexManager, PiMutexId, PiMutexError, PiMutexResult,
+    PiLockOutcome, PiUnlockOutcome, PriorityChange, PiMutexStatsSnapshot, PI_MUTEX_MANAGER,
+    MAX_PI_CHAIN_DEPTH, apply_priority_changes,
+};
+
+pub use balance_policy::{
+    BalancePolicy, BalanceContext, BalanceStats, PolicyStats, CpuLoadInfo, LoadBasedPolicy,
+    NumaAwarePolicy, PowerPackingPolicy, CacheAffinePolicy, policy_for,
 };
 
 pub use thread::THREAD_MANAGER;
@@ -152,6 +240,12 @@ pub fn init_multicore_system(config: MultiCoreConfig) -> MultiCoreResult<()> {
     info!("Initializing multi-core scheduler...");
     system.scheduler.init()?;
 
+    // Initialize thermal governor
+    if config.enable_thermal_management {
+        info!("Enabling thermal governor...");
+        system.scheduler.enable_thermal_management(true, thermal::ThermalGovernorConfig::default().throttle_temp);
+    }
+
     // Initialize performance monitoring
     if config.enable_performance_monitoring {
         info!("Starting performance monitoring...");
@@ -278,7 +372,7 @@ pub fn enable_cpu_hotplug(cpu_id: usize, enabled: bool) -> MultiCoreResult<()> {
         sys.scheduler.set_cpu_enabled(cpu_id, enabled)?;
         
         // Update performance monitoring
-        sys.performance_monitor.set_counter_enabled(0, enabled)?;
+        sys.performance_monitor.set_cpu_online(cpu_id, enabled);
         
         Ok(())
     } else {
@@ -286,6 +380,77 @@ pub fn enable_cpu_hotplug(cpu_id: usize, enabled: bool) -> MultiCoreResult<()> {
     }
 }
 
+/// Register a CPU hotplug notifier (e.g. for hypervisor VCPU rebalancing)
+pub fn register_hotplug_callback(name: &'static str, priority: i32, callback: hotplug::HotplugCallback) -> MultiCoreResult<hotplug::NotifierId> {
+    let system = get_multicore_system()?;
+    let mut guard = system.lock();
+    
+    if let Some(sys) = guard.as_mut() {
+        if !sys.config.enable_hotplug {
+            return Err(MultiCoreError::UnsupportedFeature);
+        }
+        Ok(sys.scheduler.register_hotplug_callback(name, priority, callback))
+    } else {
+        Err(MultiCoreError::NotInitialized)
+    }
+}
+
+/// Run automatic NUMA page migration against the system NUMA manager
+pub fn run_numa_balancing(now_ns: u64) -> MultiCoreResult<usize> {
+    let system = get_multicore_system()?;
+    let mut guard = system.lock();
+    
+    if let Some(sys) = guard.as_mut() {
+        match &mut sys.numa_manager {
+            Some(numa_manager) => Ok(sys.scheduler.run_numa_balancing(numa_manager, now_ns)),
+            None => Err(MultiCoreError::UnsupportedFeature),
+        }
+    } else {
+        Err(MultiCoreError::NotInitialized)
+    }
+}
+
+/// Per-CPU timer interrupt entry point
+pub fn timer_tick(cpu_id: usize, runnable: usize, now_ns: u64) -> MultiCoreResult<multicore::TimerTick> {
+    let system = get_multicore_system()?;
+    let mut guard = system.lock();
+    
+    if let Some(sys) = guard.as_mut() {
+        sys.scheduler.timer_tick(cpu_id, runnable, now_ns)
+            .map_err(|_| MultiCoreError::InvalidConfiguration)
+    } else {
+        Err(MultiCoreError::NotInitialized)
+    }
+}
+
+/// Install an energy model for energy-aware wakeup placement
+pub fn set_energy_model(model: energy::EnergyModel) -> MultiCoreResult<()> {
+    let system = get_multicore_system()?;
+    let mut guard = system.lock();
+    
+    if let Some(sys) = guard.as_mut() {
+        sys.scheduler.set_energy_model(model);
+        Ok(())
+    } else {
+        Err(MultiCoreError::NotInitialized)
+    }
+}
+
+/// Account estimated energy over the last interval and publish it to `PerformanceStats`
+pub fn update_energy_accounting(elapsed_ns: u64) -> MultiCoreResult<energy::EnergyStats> {
+    let system = get_multicore_system()?;
+    let mut guard = system.lock();
+    
+    if let Some(sys) = guard.as_mut() {
+        let stats = sys.scheduler.account_energy(elapsed_ns)
+            .ok_or(MultiCoreError::UnsupportedFeature)?;
+        sys.performance_monitor.update_energy_stats(&stats);
+        Ok(stats)
+    } else {
+        Err(MultiCoreError::NotInitialized)
+    }
+}
+
 /// Optimize memory allocation for NUMA
 pub fn allocate_memory_numa_aware(size: usize, policy: memory_manager::numa::NumaPolicy) -> MultiCoreResult<Vec<memory_manager::PhysAddr>> {
     let system = get_multicore_system()?;
@@ -484,8 +649,8 @@ pub fn configure_power_management(policy: multicore::CpuGovernor, scaling_enable
     let mut guard = system.lock();
     
     if let Some(sys) = guard.as_mut() {
-        // Update power management configuration
-        // This would update CPU frequency governors and power policies
+        // Update CPU frequency governors and power policies
+        sys.scheduler.set_governor(policy);
         
         Ok(())
     } else {
@@ -499,8 +664,9 @@ pub fn enable_thermal_management(enable: bool, throttle_temp: u8) -> MultiCoreRe
     let mut guard = system.lock();
     
     if let Some(sys) = guard.as_mut() {
-        // Configure thermal management
-        // This would set thermal thresholds and response actions
+        // Configure thermal thresholds and response actions
+        sys.scheduler.enable_thermal_management(enable, throttle_temp);
+        sys.config.enable_thermal_management = enable;
         
         Ok(())
     } else {
@@ -508,6 +674,19 @@ pub fn enable_thermal_management(enable: bool, throttle_temp: u8) -> MultiCoreRe
     }
 }
 
+/// Sample temperature sensors and apply thermal throttling to the scheduler
+pub fn update_thermal_state(sensor: &mut dyn thermal::TemperatureSensor) -> MultiCoreResult<usize> {
+    let system = get_multicore_system()?;
+    let mut guard = system.lock();
+    
+    if let Some(sys) = guard.as_mut() {
+        sys.scheduler.update_thermal(sensor)
+            .map_err(|_| MultiCoreError::ResourceUnavailable)
+    } else {
+        Err(MultiCoreError::NotInitialized)
+    }
+}
+
 /// Export comprehensive performance report
 pub fn export_performance_report(format: performance_monitor::ExportFormat) -> MultiCoreResult<Vec<u8>> {
     let system = get_multicore_system()?;
@@ -551,6 +730,8 @@ pub fn create_optimized_config(
             load_balance_interval: 500,
             enable_cpu_affinity: true,
             enable_load_balancing: true,
+            enable_tracing: false,
+            enable_latency_tracking: false,
         },
         multicore_config: MulticoreConfig {
             max_cpus: cpu_count,
@@ -572,6 +753,9 @@ pub fn create_optimized_config(
             cache_line_size: 64,
             enable_monitoring: true,
             monitoring_interval: 100,
+            enable_tickless: true,
+            tickless: TicklessConfig::default(),
+            isolated_cpus: 0,
         },
         performance_config: PerformanceConfig {
             enable_hardware_counters: enable_advanced_features,
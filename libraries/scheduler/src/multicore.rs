@@ -9,6 +9,8 @@
 //! - NUMA-aware scheduling for multi-socket systems
 //! - Performance monitoring and optimization
 
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
 use alloc::vec::Vec;
 use spin::Mutex;
 use bitflags::bitflags;
@@ -18,7 +20,14 @@ use core::time::Duration;
 use crate::{
     Priority, ThreadState, SchedulerError, SchedulerResult,
     thread::{ThreadHandle, ThreadId, ThreadManager, ThreadControlBlock},
-    scheduler_algo::{CpuId, CpuAffinity, SchedulingAlgorithm, CpuState}
+    scheduler_algo::{CpuId, CpuAffinity, SchedulingAlgorithm, CpuState},
+    tickless::{TickController, TicklessConfig, TickStatsSnapshot, OneShotTimer},
+    numa_balancing::{NumaBalancer, NumaBalancingConfig, NumaBalancingStats, NumaFaultSample, PageMigrator},
+    energy::{EnergyModel, EnergyStats, estimated_task_util},
+    thermal::{ThermalGovernor, ThermalGovernorConfig, ThermalGovernorStats, TemperatureSensor},
+    hotplug::{HotplugNotifier, HotplugCallback, HotplugState, NotifierId},
+    futex::{futex_expire, FUTEX_MANAGER},
+    balance_policy::{BalancePolicy, BalanceContext, BalanceStats, CpuLoadInfo, policy_for},
 };
 
 /// Maximum number of CPUs supported
@@ -27,6 +36,9 @@ const MAX_CPUS: usize = 1024;
 /// Maximum scheduling domains
 const MAX_SCHED_DOMAINS: usize = 16;
 
+/// Extra load units charged to a fully throttled CPU when placing threads
+const THERMAL_LOAD_WEIGHT: f32 = 4.0;
+
 /// CPU power states for energy management
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CpuPowerState {
@@ -90,7 +102,7 @@ pub struct SchedDomain {
     pub parent_domain: Option<usize>,
     /// Child domains
     pub child_domains: Vec<usize>,
-    /// Load balancing algorithm
+    /// Load balancing algorithm, following the active policy
     pub balance_algorithm: BalanceAlgorithm,
     /// Balancing interval in milliseconds
     pub balance_interval: u64,
@@ -116,6 +128,8 @@ pub enum BalanceAlgorithm {
     NumaAware,
     /// Machine learning based balancing
     MLBased,
+    /// Pack threads onto few CPUs so the others can idle
+    PowerPacking,
 }
 
 /// Domain-level statistics
@@ -146,6 +160,8 @@ pub struct CpuHotplugManager {
     pub operation_in_progress: bool,
     /// Callbacks for CPU state changes
     pub callbacks: Vec<CpuStateCallback>,
+    /// Ordered PREPARE/ONLINE/OFFLINE/DEAD notifier chain
+    pub notifier: HotplugNotifier,
 }
 
 /// CPU state change callback
@@ -174,6 +190,16 @@ pub struct MulticoreScheduler {
     power_manager: PowerManager,
     /// Multi-core synchronization
     sync_manager: SyncManager,
+    /// Dynamic tick controller
+    tick_controller: Option<TickController>,
+    /// Automatic NUMA page migration
+    numa_balancer: Option<NumaBalancer>,
+    /// Thermal throttling feedback loop
+    thermal_governor: Option<ThermalGovernor>,
+    /// CPUs kept free of housekeeping and balanced threads
+    isolated_cpus: CpuMask,
+    /// CPU affinity each placed thread was added with
+    thread_affinity: BTreeMap<ThreadId, CpuAffinity>,
 }
 
 /// Multi-core scheduler configuration
@@ -189,7 +215,7 @@ pub struct MulticoreConfig {
     pub domain_size: usize,
     /// Enable load balancing
     pub enable_balancing: bool,
-    /// Balancing algorithm
+    /// Initial balancing algorithm; see `set_balance_policy`
     pub balance_algorithm: BalanceAlgorithm,
     /// Enable power management
     pub enable_power_mgmt: bool,
@@ -209,6 +235,13 @@ pub struct MulticoreConfig {
     pub enable_monitoring: bool,
     /// Monitoring interval (milliseconds)
     pub monitoring_interval: u64,
+    /// Enable tickless idle / adaptive tick
+    pub enable_tickless: bool,
+    /// Tick mode and idle limits used when `enable_tickless` is set
+    pub tickless: TicklessConfig,
+    /// Isolated (nohz_full) CPUs: no housekeeping, timers or load-balanced
+    /// threads unless a thread is explicitly affined to them
+    pub isolated_cpus: CpuMask,
 }
 
 impl Default for MulticoreConfig {
@@ -229,6 +262,9 @@ impl Default for MulticoreConfig {
             cache_line_size: 64,
             enable_monitoring: true,
             monitoring_interval: 100,
+            enable_tickless: true,
+            tickless: TicklessConfig::default(),
+            isolated_cpus: 0,
         }
     }
 }
@@ -244,10 +280,20 @@ pub struct CpuState {
     pub sched_domain: Option<usize>,
     pub current_thread: Option<ThreadId>,
     pub load: f32,
+    /// Thermal pressure (percent of capacity lost to throttling)
     pub thermal_state: u8,
     pub frequency_scaling: bool,
 }
 
+/// Result of a per-CPU timer tick
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimerTick {
+    /// Threads woken by expired futex timeouts
+    pub woken: usize,
+    /// Delay until the next tick should fire (nanoseconds)
+    pub next_tick_ns: u64,
+}
+
 /// NUMA-aware scheduler extension
 #[derive(Debug)]
 pub struct NumaScheduler {
@@ -347,8 +393,10 @@ pub struct ThermalMonitor {
 /// CPU load balancing engine
 #[derive(Debug)]
 pub struct LoadBalancer {
-    /// Active load balancing algorithms
-    pub algorithms: Vec<BalanceAlgorithm>,
+    /// Active balancing policy
+    pub policy: Box<dyn BalancePolicy>,
+    /// Placement and migration counts per policy
+    pub stats: BalanceStats,
     /// Balancing thresholds
     pub thresholds: LoadThresholds,
     /// Migration history for optimization
@@ -393,6 +441,10 @@ pub struct PowerManager {
     pub idle_manager: IdleManager,
     /// Thermal management
     pub thermal_manager: ThermalManager,
+    /// Active frequency governor
+    pub governor: CpuGovernor,
+    /// Per-CPU energy model used by the energy-aware governor
+    pub energy_model: Option<EnergyModel>,
 }
 
 /// CPU frequency scaling policy
@@ -413,6 +465,8 @@ pub enum CpuGovernor {
     OnDemand,
     Conservative,
     Schedutil,
+    /// Schedutil with energy-aware wakeup placement
+    EnergyAware,
 }
 
 /// CPU idle state manager
@@ -571,6 +625,7 @@ impl MulticoreScheduler {
                 hotplug_capable_cpus: Vec::new(),
                 operation_in_progress: false,
                 callbacks: Vec::new(),
+                notifier: HotplugNotifier::new(config.max_cpus),
             },
             numa_scheduler: if config.enable_numa {
                 Some(NumaScheduler {
@@ -596,6 +651,15 @@ impl MulticoreScheduler {
             load_balancer: LoadBalancer::new(&config),
             power_manager: PowerManager::new(&config),
             sync_manager: SyncManager::new(&config),
+            tick_controller: if config.enable_tickless {
+                Some(TickController::new(config.tickless.clone(), cpu_count))
+            } else {
+                None
+            },
+            numa_balancer: None,
+            thermal_governor: None,
+            isolated_cpus: 0,
+            thread_affinity: BTreeMap::new(),
         }
     }
 
@@ -609,6 +673,9 @@ impl MulticoreScheduler {
         // Initialize NUMA scheduler
         if let Some(numa_sched) = &mut self.numa_scheduler {
             numa_sched.init();
+
+            let cpu_to_node = numa_sched.numa_topology.cpu_to_node[..self.config.max_cpus.min(MAX_CPUS)].to_vec();
+            self.numa_balancer = Some(NumaBalancer::new(NumaBalancingConfig::default(), cpu_to_node));
         }
 
         // Initialize real-time scheduler
@@ -629,6 +696,11 @@ impl MulticoreScheduler {
         // Initialize synchronization optimizations
         self.sync_manager.init();
 
+        // Apply boot-time CPU isolation
+        if self.config.isolated_cpus != 0 {
+            self.set_isolated_cpus(self.config.isolated_cpus)?;
+        }
+
         Ok(())
     }
 
@@ -724,11 +796,18 @@ impl MulticoreScheduler {
             cpu_state.current_thread = Some(thread_id);
         }
 
+        self.thread_affinity.insert(thread_id, cpu_affinity);
+
         // Update real-time scheduler if applicable
         if let Some(rt_sched) = &mut self.rt_scheduler {
             rt_sched.add_realtime_task(thread_id, priority);
         }
 
+        // Track home node for automatic NUMA balancing
+        if let Some(balancer) = &mut self.numa_balancer {
+            balancer.set_thread_affinity(thread_id, cpu_affinity);
+        }
+
         // Update performance counters
         self.perf_monitor.record_thread_placement(target_cpu, thread_id);
 
@@ -736,7 +815,7 @@ impl MulticoreScheduler {
     }
 
     /// Select optimal CPU for thread using advanced algorithms
-    fn select_optimal_cpu(&self, thread_handle: &ThreadHandle, affinity: CpuAffinity, priority: Priority) -> SchedulerResult<CpuId> {
+    fn select_optimal_cpu(&mut self, thread_handle: &ThreadHandle, affinity: CpuAffinity, priority: Priority) -> SchedulerResult<CpuId> {
         let mut candidates = Vec::new();
         
         // Build candidate CPU list based on affinity
@@ -747,22 +826,164 @@ impl MulticoreScheduler {
             }
         }
 
+        // Isolated CPUs only take threads explicitly affined to them
+        if !self.affined_to_isolated(affinity) {
+            candidates.retain(|&cpu_id| !self.is_cpu_isolated(cpu_id));
+        }
+
         if candidates.is_empty() {
-            // No affinity specified, consider all online CPUs
+            // No affinity specified, consider all online housekeeping CPUs
             for cpu_id in 0..self.config.max_cpus {
-                if self.cpu_states[cpu_id].state == CpuState::Online {
+                if self.cpu_states[cpu_id].state == CpuState::Online && !self.is_cpu_isolated(cpu_id) {
                     candidates.push(cpu_id);
                 }
             }
         }
 
-        // Apply NUMA-aware selection
-        if let Some(numa_sched) = &self.numa_scheduler {
-            return numa_sched.select_cpu_with_numa_awareness(&self.cpu_states, &candidates, priority);
+        // Prefer the most energy-efficient CPU while the system is lightly loaded
+        if self.power_manager.energy_aware() && priority != Priority::Critical {
+            let utils = self.cpu_utilizations();
+            let task_util = estimated_task_util(priority);
+            if let Some(model) = &mut self.power_manager.energy_model {
+                if let Some(cpu_id) = model.find_energy_efficient_cpu(&utils, &candidates, task_util) {
+                    return Ok(cpu_id);
+                }
+            }
+        }
+
+        let previous_cpu = thread_handle.lock().sched_params.last_cpu;
+        let cpus = self.cpu_load_info();
+        let ctx = BalanceContext {
+            cpus: &cpus,
+            imbalance_threshold: self.load_balancer.thresholds.imbalance_threshold,
+            previous_cpu: Some(previous_cpu),
+        };
+        let cpu_id = self.load_balancer.policy.select_cpu(&ctx, &candidates).unwrap_or(candidates[0]);
+        let algorithm = self.load_balancer.policy.algorithm();
+        self.load_balancer.stats.get_mut(algorithm).placements += 1;
+        Ok(cpu_id)
+    }
+
+    /// Switch the load balancing policy; placement and balancing use it from
+    /// the next decision on
+    pub fn set_balance_policy(&mut self, policy: Box<dyn BalancePolicy>) {
+        let algorithm = policy.algorithm();
+        for domain in &mut self.sched_domains {
+            domain.balance_algorithm = algorithm;
+        }
+        self.load_balancer.policy = policy;
+        self.load_balancer.stats.get_mut(algorithm).activations += 1;
+    }
+
+    /// Switch to the built-in policy for `algorithm`
+    pub fn set_balance_algorithm(&mut self, algorithm: BalanceAlgorithm) {
+        self.set_balance_policy(policy_for(algorithm));
+    }
+
+    pub fn balance_algorithm(&self) -> BalanceAlgorithm {
+        self.load_balancer.policy.algorithm()
+    }
+
+    /// Placement and migration statistics per policy
+    pub fn get_balance_stats(&self) -> &BalanceStats {
+        &self.load_balancer.stats
+    }
+
+    /// Per-CPU load, node and domain as policies see them
+    fn cpu_load_info(&self) -> Vec<CpuLoadInfo> {
+        self.cpu_states.iter().map(|cpu_state| CpuLoadInfo {
+            cpu_id: cpu_state.cpu_id,
+            online: cpu_state.state == CpuState::Online,
+            load: cpu_state.load,
+            effective_load: thermal_adjusted_load(cpu_state),
+            node: self.numa_scheduler.as_ref()
+                .and_then(|numa_sched| numa_sched.numa_topology.cpu_to_node.get(cpu_state.cpu_id).copied())
+                .unwrap_or(0),
+            domain: cpu_state.sched_domain,
+        }).collect()
+    }
+
+    /// Isolate `cpus` from housekeeping, timers and load balancing.
+    ///
+    /// Threads on newly isolated CPUs move to a housekeeping CPU unless they
+    /// are explicitly affined to isolated CPUs. At least one online
+    /// housekeeping CPU must remain.
+    pub fn set_isolated_cpus(&mut self, cpus: CpuMask) -> SchedulerResult<()> {
+        let max_cpus = self.config.max_cpus.min(CpuMask::BITS as usize);
+        if max_cpus < CpuMask::BITS as usize && cpus >> max_cpus != 0 {
+            return Err(SchedulerError::InvalidThreadId);
+        }
+        let has_housekeeping = (0..max_cpus).any(|cpu_id| {
+            cpus & (1 << cpu_id) == 0 && self.cpu_states[cpu_id].state == CpuState::Online
+        });
+        if !has_housekeeping {
+            return Err(SchedulerError::InvalidThreadId);
+        }
+
+        let newly_isolated = cpus & !self.isolated_cpus;
+        self.isolated_cpus = cpus;
+
+        if let Some(controller) = &mut self.tick_controller {
+            for cpu_id in 0..max_cpus {
+                controller.set_nohz_full(cpu_id, cpus & (1 << cpu_id) != 0)?;
+            }
+        }
+
+        for cpu_id in (0..max_cpus).filter(|&cpu_id| newly_isolated & (1 << cpu_id) != 0) {
+            let Some(thread_id) = self.cpu_states[cpu_id].current_thread else {
+                continue;
+            };
+            let affinity = self.thread_affinity.get(&thread_id).copied().unwrap_or(0);
+            if self.affined_to_isolated(affinity) {
+                continue;
+            }
+
+            let target_cpu = self.find_migration_target(cpu_id, thread_id)?;
+            if target_cpu != cpu_id {
+                self.migrate_thread(thread_id, cpu_id, target_cpu)?;
+                self.cpu_states[cpu_id].current_thread = None;
+                self.cpu_states[target_cpu].current_thread.get_or_insert(thread_id);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Get the isolated CPU mask
+    pub fn isolated_cpus(&self) -> CpuMask {
+        self.isolated_cpus
+    }
+
+    /// Check whether a CPU is isolated
+    pub fn is_cpu_isolated(&self, cpu_id: CpuId) -> bool {
+        cpu_id < CpuMask::BITS as usize && self.isolated_cpus & (1 << cpu_id) != 0
+    }
+
+    /// Online CPUs that run housekeeping work
+    pub fn housekeeping_cpus(&self) -> Vec<CpuId> {
+        (0..self.config.max_cpus)
+            .filter(|&cpu_id| self.cpu_states[cpu_id].state == CpuState::Online && !self.is_cpu_isolated(cpu_id))
+            .collect()
+    }
+
+    /// CPU that should run timers and deferred work raised on `cpu_id`;
+    /// isolated CPUs hand it to the least loaded housekeeping CPU
+    pub fn housekeeping_target(&self, cpu_id: CpuId) -> Option<CpuId> {
+        if !self.is_cpu_isolated(cpu_id) && self.cpu_states.get(cpu_id)?.state == CpuState::Online {
+            return Some(cpu_id);
         }
+        self.housekeeping_cpus().into_iter().min_by(|&a, &b| {
+            thermal_adjusted_load(&self.cpu_states[a])
+                .partial_cmp(&thermal_adjusted_load(&self.cpu_states[b]))
+                .unwrap_or(core::cmp::Ordering::Equal)
+        })
+    }
 
-        // Apply load balancing
-        self.load_balancer.select_best_cpu(&self.cpu_states, &candidates, priority)
+    /// Whether `affinity` names only isolated CPUs
+    fn affined_to_isolated(&self, affinity: CpuAffinity) -> bool {
+        affinity != 0 && (0..CpuAffinity::BITS as usize)
+            .filter(|&cpu_id| affinity & (1 << cpu_id) != 0)
+            .all(|cpu_id| self.is_cpu_isolated(cpu_id))
     }
 
     /// Handle CPU hot-plug event
@@ -772,40 +993,101 @@ impl MulticoreScheduler {
         }
 
         self.hotplug_manager.operation_in_progress = true;
+        let result = if online {
+            self.cpu_up(cpu_id)
+        } else {
+            self.cpu_down(cpu_id)
+        };
+        self.hotplug_manager.operation_in_progress = false;
 
-        if online {
-            // Bringing CPU online
-            let cpu_state = &mut self.cpu_states[cpu_id];
-            cpu_state.state = CpuState::Online;
-            
-            // Initialize CPU-specific structures
-            self.initialize_cpu(cpu_id)?;
-            
-            // Notify callbacks
-            for callback in &self.hotplug_manager.callbacks {
-                callback(cpu_id, cpu_state.power_state)?;
+        result
+    }
+
+    /// Bring a CPU online and run the PREPARE/ONLINE notifier chain
+    fn cpu_up(&mut self, cpu_id: CpuId) -> SchedulerResult<()> {
+        self.hotplug_manager.notifier.bring_up(cpu_id)?;
+
+        // Bringing CPU online
+        self.cpu_states[cpu_id].state = CpuState::Online;
+        self.hotplug_manager.offline_cpus.retain(|&cpu| cpu != cpu_id);
+        if let Some(numa_sched) = &mut self.numa_scheduler {
+            let node = numa_sched.numa_topology.cpu_to_node[cpu_id];
+            if let Some(cpus) = numa_sched.node_cpu_mapping.get_mut(node) {
+                if !cpus.contains(&cpu_id) {
+                    cpus.push(cpu_id);
+                }
             }
-        } else {
-            // Taking CPU offline
-            let cpu_state = &self.cpu_states[cpu_id];
-            
-            // Migrate threads from offline CPU
-            self.migrate_threads_from_cpu(cpu_id)?;
-            
-            // Mark CPU as offline
-            let cpu_state = &mut self.cpu_states[cpu_id];
-            cpu_state.state = CpuState::Offline;
-            
-            // Notify callbacks
-            for callback in &self.hotplug_manager.callbacks {
-                callback(cpu_id, cpu_state.power_state)?;
+        }
+
+        // Initialize CPU-specific structures
+        self.initialize_cpu(cpu_id)?;
+
+        // Notify callbacks
+        let cpu_state = &self.cpu_states[cpu_id];
+        for callback in &self.hotplug_manager.callbacks {
+            callback(cpu_id, cpu_state.power_state)?;
+        }
+
+        Ok(())
+    }
+
+    /// Run the OFFLINE/DEAD notifier chain and take a CPU offline
+    fn cpu_down(&mut self, cpu_id: CpuId) -> SchedulerResult<()> {
+        // Never remove the last housekeeping CPU
+        if self.housekeeping_cpus() == [cpu_id] {
+            return Err(SchedulerError::InvalidThreadId);
+        }
+
+        // OFFLINE: subsystems stop using the CPU and may veto the removal
+        // before any state is touched
+        self.hotplug_manager.notifier.offline(cpu_id)?;
+
+        // Migrate threads from offline CPU; if one cannot leave, the CPU
+        // stays and the notified subsystems are told it is online again
+        if let Err(error) = self.migrate_threads_from_cpu(cpu_id) {
+            let _ = self.hotplug_manager.notifier.cancel_offline(cpu_id);
+            return Err(error);
+        }
+
+        // DEAD: the CPU is empty
+        self.hotplug_manager.notifier.dead(cpu_id)?;
+
+        // Mark CPU as offline
+        self.cpu_states[cpu_id].state = CpuState::Offline;
+        if !self.hotplug_manager.offline_cpus.contains(&cpu_id) {
+            self.hotplug_manager.offline_cpus.push(cpu_id);
+        }
+        if let Some(numa_sched) = &mut self.numa_scheduler {
+            for cpus in &mut numa_sched.node_cpu_mapping {
+                cpus.retain(|&cpu| cpu != cpu_id);
             }
         }
 
-        self.hotplug_manager.operation_in_progress = false;
+        // Notify callbacks
+        let cpu_state = &self.cpu_states[cpu_id];
+        for callback in &self.hotplug_manager.callbacks {
+            callback(cpu_id, cpu_state.power_state)?;
+        }
+
         Ok(())
     }
 
+    /// Register a hotplug notifier; higher priorities run first on bring-up
+    /// and last on tear-down
+    pub fn register_hotplug_callback(&mut self, name: &'static str, priority: i32, callback: HotplugCallback) -> NotifierId {
+        self.hotplug_manager.notifier.register(name, priority, callback)
+    }
+
+    /// Remove a hotplug notifier
+    pub fn unregister_hotplug_callback(&mut self, id: NotifierId) -> bool {
+        self.hotplug_manager.notifier.unregister(id)
+    }
+
+    /// Get the hotplug state of a CPU
+    pub fn hotplug_state(&self, cpu_id: CpuId) -> Option<HotplugState> {
+        self.hotplug_manager.notifier.state(cpu_id)
+    }
+
     /// Initialize a CPU after hot-plug
     fn initialize_cpu(&mut self, cpu_id: CpuId) -> SchedulerResult<()> {
         // Initialize idle states
@@ -839,9 +1121,15 @@ impl MulticoreScheduler {
         // Find migration targets
         for thread_id in threads_to_migrate {
             let target_cpu = self.find_migration_target(cpu_id, thread_id)?;
-            
+            if target_cpu == cpu_id {
+                // Pinned to this CPU with no other CPU allowed
+                return Err(SchedulerError::InvalidThreadId);
+            }
+
             // Perform migration
             self.migrate_thread(thread_id, cpu_id, target_cpu)?;
+            self.cpu_states[cpu_id].current_thread = None;
+            self.cpu_states[target_cpu].current_thread.get_or_insert(thread_id);
         }
 
         Ok(())
@@ -851,18 +1139,27 @@ impl MulticoreScheduler {
     fn find_migration_target(&self, source_cpu: CpuId, thread_id: ThreadId) -> SchedulerResult<CpuId> {
         let mut best_cpu = source_cpu;
         let mut best_score = f32::MAX;
+        // An empty mask means no affinity was given
+        let affinity = self.thread_affinity.get(&thread_id).copied().filter(|&affinity| affinity != 0);
 
         for cpu_id in 0..self.config.max_cpus {
             if cpu_id == source_cpu {
                 continue;
             }
 
-            if self.cpu_states[cpu_id].state != CpuState::Online {
+            let allowed = affinity.map_or(true, |affinity| {
+                cpu_id < CpuAffinity::BITS as usize && affinity & (1 << cpu_id) != 0
+            });
+            if !allowed {
+                continue;
+            }
+
+            if self.cpu_states[cpu_id].state != CpuState::Online || self.is_cpu_isolated(cpu_id) {
                 continue;
             }
 
             let cpu_state = &self.cpu_states[cpu_id];
-            let score = cpu_state.load + self.calculate_migration_cost(source_cpu, cpu_id);
+            let score = thermal_adjusted_load(cpu_state) + self.calculate_migration_cost(source_cpu, cpu_id);
             
             if score < best_score {
                 best_score = score;
@@ -933,59 +1230,40 @@ impl MulticoreScheduler {
     /// Balance load within a scheduling domain
     fn balance_domain(&mut self, domain_id: usize) -> SchedulerResult<()> {
         let domain = &self.sched_domains[domain_id];
-        
-        // Find heaviest and lightest loaded CPUs in domain
-        let mut heaviest_cpu = None;
-        let mut lightest_cpu = None;
-        let mut heaviest_load = 0.0;
-        let mut lightest_load = f32::MAX;
-
-        // Collect CPUs in this domain
         let domain_cpus: Vec<CpuId> = (0..self.config.max_cpus)
-            .filter(|&cpu_id| domain.cpu_mask & (1 << cpu_id) != 0)
+            .filter(|&cpu_id| domain.cpu_mask & (1 << cpu_id) != 0 && !self.is_cpu_isolated(cpu_id))
             .collect();
 
-        for &cpu_id in &domain_cpus {
-            if self.cpu_states[cpu_id].state != CpuState::Online {
-                continue;
-            }
-
-            let load = self.cpu_states[cpu_id].load;
-            
-            if load > heaviest_load {
-                heaviest_load = load;
-                heaviest_cpu = Some(cpu_id);
-            }
-            
-            if load < lightest_load {
-                lightest_load = load;
-                lightest_cpu = Some(cpu_id);
-            }
-        }
+        let cpus = self.cpu_load_info();
+        let ctx = BalanceContext {
+            cpus: &cpus,
+            imbalance_threshold: self.load_balancer.thresholds.imbalance_threshold,
+            previous_cpu: None,
+        };
+        let migration = self.load_balancer.policy.find_migration(&ctx, &domain_cpus);
+        let algorithm = self.load_balancer.policy.algorithm();
+        self.load_balancer.stats.get_mut(algorithm).balance_runs += 1;
 
-        // Check if balancing is needed
-        if let (Some(heavy_cpu), Some(light_cpu)) = (heaviest_cpu, lightest_cpu) {
-            let imbalance = heaviest_load - lightest_load;
-            
-            if imbalance > self.load_balancer.thresholds.imbalance_threshold {
-                // Perform migration
-                self.migrate_between_cpus(heavy_cpu, light_cpu)?;
+        if let Some((from_cpu, to_cpu)) = migration {
+            if self.migrate_between_cpus(from_cpu, to_cpu)? {
+                self.load_balancer.stats.get_mut(algorithm).migrations += 1;
             }
         }
 
         Ok(())
     }
 
-    /// Migrate threads between specific CPUs
-    fn migrate_between_cpus(&mut self, from_cpu: CpuId, to_cpu: CpuId) -> SchedulerResult<()> {
+    /// Migrate a thread between specific CPUs; returns whether one moved
+    fn migrate_between_cpus(&mut self, from_cpu: CpuId, to_cpu: CpuId) -> SchedulerResult<bool> {
         // Find migratable thread from heavy CPU
         let migratable_thread = self.find_migratable_thread(from_cpu)?;
         
         if let Some(thread_id) = migratable_thread {
             self.migrate_thread(thread_id, from_cpu, to_cpu)?;
+            return Ok(true);
         }
 
-        Ok(())
+        Ok(false)
     }
 
     /// Find a thread suitable for migration
@@ -1033,6 +1311,10 @@ impl MulticoreScheduler {
     /// Set CPU frequency
     fn set_cpu_frequency(&mut self, cpu_id: CpuId, frequency_mhz: u32) -> SchedulerResult<()> {
         let cpu_state = &mut self.cpu_states[cpu_id];
+        let frequency_mhz = match &self.thermal_governor {
+            Some(governor) => frequency_mhz.min(governor.capped_frequency(cpu_id, cpu_state.perf_info.max_frequency)),
+            None => frequency_mhz,
+        };
         
         if cpu_state.frequency_scaling {
             cpu_state.perf_info.current_frequency = frequency_mhz;
@@ -1066,6 +1348,188 @@ impl MulticoreScheduler {
         self.handle_cpu_hotplug(cpu_id, enabled)
     }
 
+    /// Notify the scheduler that a CPU is entering idle.
+    ///
+    /// Returns the one-shot timer to program when the periodic tick is stopped.
+    pub fn enter_idle(&mut self, cpu_id: CpuId, now_ns: u64) -> SchedulerResult<Option<OneShotTimer>> {
+        let busy = self.cpu_states.get(cpu_id)
+            .ok_or(SchedulerError::InvalidThreadId)?
+            .current_thread
+            .is_some();
+        match &mut self.tick_controller {
+            Some(controller) if !busy => {
+                // Sleep no longer than the first futex timeout
+                controller.set_next_deadline(cpu_id, FUTEX_MANAGER.next_deadline())?;
+                controller.enter_idle(cpu_id, now_ns)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Notify the scheduler that a CPU has left idle
+    pub fn exit_idle(&mut self, cpu_id: CpuId, now_ns: u64) -> SchedulerResult<()> {
+        if let Some(controller) = &mut self.tick_controller {
+            controller.exit_idle(cpu_id, now_ns)?;
+        }
+        Ok(())
+    }
+
+    /// Per-CPU timer interrupt, with `runnable` threads queued on the CPU.
+    ///
+    /// Wakes futex waiters whose timeout has passed, accounts the tick and
+    /// picks the delay until the next one.
+    pub fn timer_tick(&mut self, cpu_id: CpuId, runnable: usize, now_ns: u64) -> SchedulerResult<TimerTick> {
+        if cpu_id >= self.cpu_states.len() {
+            return Err(SchedulerError::InvalidThreadId);
+        }
+        let woken = futex_expire(now_ns);
+
+        let next_tick_ns = match &mut self.tick_controller {
+            Some(controller) => {
+                controller.on_tick(cpu_id, now_ns)?;
+                controller.set_next_deadline(cpu_id, FUTEX_MANAGER.next_deadline())?;
+                controller.adapt_tick(cpu_id, runnable, now_ns)?
+            }
+            None => self.config.tickless.tick_period_ns,
+        };
+        Ok(TimerTick { woken, next_tick_ns })
+    }
+
+    /// Get tick reduction statistics
+    pub fn get_tick_stats(&self) -> Option<TickStatsSnapshot> {
+        self.tick_controller.as_ref().map(|controller| controller.get_stats())
+    }
+
+    /// Record a sampled NUMA hinting fault; samples from unknown CPUs are dropped
+    pub fn record_numa_fault(&mut self, sample: NumaFaultSample) {
+        if sample.cpu_id >= self.cpu_states.len() {
+            return;
+        }
+        let cpu_node = match &self.numa_scheduler {
+            Some(numa) => match numa.numa_topology.cpu_to_node.get(sample.cpu_id) {
+                Some(&node) => node,
+                None => return,
+            },
+            None => 0,
+        };
+        self.perf_monitor.memory_patterns.record_access(sample.cpu_id, sample.page_node != cpu_node);
+
+        if let Some(balancer) = &mut self.numa_balancer {
+            balancer.record_fault(sample);
+        }
+    }
+
+    /// Run a NUMA balancing scan, migrating hot pages toward their threads
+    pub fn run_numa_balancing(&mut self, migrator: &mut dyn PageMigrator, now_ns: u64) -> usize {
+        let migrated = match &mut self.numa_balancer {
+            Some(balancer) => balancer.scan(migrator, now_ns),
+            None => 0,
+        };
+        self.perf_monitor.memory_patterns.numa_migrations.fetch_add(migrated as u64, Ordering::SeqCst);
+        migrated
+    }
+
+    /// Get NUMA balancing statistics
+    pub fn get_numa_balancing_stats(&self) -> Option<NumaBalancingStats> {
+        self.numa_balancer.as_ref().map(|balancer| balancer.get_stats())
+    }
+
+    /// Enable or disable the thermal governor
+    pub fn enable_thermal_management(&mut self, enable: bool, throttle_temp: u8) {
+        if !enable {
+            self.thermal_governor = None;
+            for cpu_state in &mut self.cpu_states {
+                cpu_state.thermal_state = 0;
+                cpu_state.perf_info.current_frequency = cpu_state.perf_info.max_frequency;
+            }
+            return;
+        }
+
+        let config = ThermalGovernorConfig::with_throttle_temp(throttle_temp);
+        self.thermal_governor = Some(ThermalGovernor::new(config, self.cpu_states.len()));
+    }
+
+    /// Sample temperature sensors and feed thermal actions back into scheduling.
+    ///
+    /// Caps the frequency of hot CPUs, raises their thermal pressure so the
+    /// load balancer places fewer threads on them, and migrates runnable load
+    /// off throttled CPUs. Returns the number of CPUs that shed load.
+    pub fn update_thermal(&mut self, sensor: &mut dyn TemperatureSensor) -> SchedulerResult<usize> {
+        let decisions = match &mut self.thermal_governor {
+            Some(governor) => governor.update(sensor),
+            None => return Ok(0),
+        };
+
+        let governor = self.thermal_governor.as_ref().unwrap();
+        for cpu_state in &mut self.cpu_states {
+            let thermal = match governor.get_cpu_state(cpu_state.cpu_id) {
+                Some(thermal) => thermal,
+                None => continue,
+            };
+            cpu_state.thermal_state = (thermal.pressure() * 100.0) as u8;
+            cpu_state.perf_info.temperature = Some(thermal.temperature);
+            // The cap rises again as the CPU cools, up to the full frequency
+            cpu_state.perf_info.current_frequency = governor.capped_frequency(cpu_state.cpu_id, cpu_state.perf_info.max_frequency);
+            self.perf_monitor.thermal_monitor.cpu_temperatures[cpu_state.cpu_id]
+                .store(thermal.temperature as u32, Ordering::SeqCst);
+        }
+
+        let mut shed = 0;
+        for decision in decisions {
+            self.perf_monitor.thermal_monitor.cooling_actions.fetch_add(1, Ordering::SeqCst);
+            if decision.migrate_load {
+                self.perf_monitor.thermal_monitor.thermal_throttling_events.fetch_add(1, Ordering::SeqCst);
+                if self.cpu_states[decision.cpu_id].load > 0.0 {
+                    self.migrate_threads_from_cpu(decision.cpu_id)?;
+                    shed += 1;
+                }
+            }
+        }
+
+        Ok(shed)
+    }
+
+    /// Get thermal governor statistics
+    pub fn get_thermal_governor_stats(&self) -> Option<ThermalGovernorStats> {
+        self.thermal_governor.as_ref().map(|governor| governor.get_stats())
+    }
+
+    /// Switch the CPU frequency governor
+    pub fn set_governor(&mut self, governor: CpuGovernor) {
+        self.power_manager.set_governor(governor);
+    }
+
+    /// Install the per-CPU energy model used by `CpuGovernor::EnergyAware`
+    pub fn set_energy_model(&mut self, model: EnergyModel) {
+        self.power_manager.energy_model = Some(model);
+    }
+
+    /// Accumulate estimated energy for the last `elapsed_ns` of execution
+    pub fn account_energy(&mut self, elapsed_ns: u64) -> Option<EnergyStats> {
+        let utils = self.cpu_utilizations();
+        let model = self.power_manager.energy_model.as_mut()?;
+        model.account_energy(&utils, elapsed_ns);
+        Some(model.get_stats())
+    }
+
+    /// Get energy model statistics
+    pub fn get_energy_stats(&self) -> Option<EnergyStats> {
+        self.power_manager.energy_model.as_ref().map(|model| model.get_stats())
+    }
+
+    /// Per-CPU utilization in energy model capacity units
+    fn cpu_utilizations(&self) -> Vec<u32> {
+        let model = match &self.power_manager.energy_model {
+            Some(model) => model,
+            None => return Vec::new(),
+        };
+
+        self.cpu_states.iter().map(|cpu| {
+            let capacity = model.profile(cpu.cpu_id).map_or(0, |profile| profile.max_capacity());
+            cpu.perf_info.utilization.min(100) * capacity / 100
+        }).collect()
+    }
+
     /// Get CPU state information
     pub fn get_cpu_state(&self, cpu_id: CpuId) -> Option<CpuState> {
         if cpu_id < self.cpu_states.len() {
@@ -1077,6 +1541,11 @@ impl MulticoreScheduler {
 }
 
 // Implementation details for supporting structures
+/// CPU load including thermal pressure, so hot cores attract fewer threads
+fn thermal_adjusted_load(cpu_state: &CpuState) -> f32 {
+    cpu_state.load + cpu_state.thermal_state as f32 / 100.0 * THERMAL_LOAD_WEIGHT
+}
+
 impl UtilizationTracker {
     fn new(cpu_count: usize) -> Self {
         let mut counters = [AtomicU32::new(0); MAX_CPUS];
@@ -1126,6 +1595,13 @@ impl PerfMonitor {
 }
 
 impl MemoryPatternTracker {
+    fn record_access(&self, cpu_id: CpuId, remote: bool) {
+        let counters = if remote { &self.remote_accesses } else { &self.local_accesses };
+        if let Some(counter) = counters.get(cpu_id) {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
     fn new(cpu_count: usize) -> Self {
         let remote_accesses = (0..cpu_count).map(|_| AtomicU64::new(0)).collect();
         let local_accesses = (0..cpu_count).map(|_| AtomicU64::new(0)).collect();
@@ -1152,8 +1628,11 @@ impl ThermalMonitor {
 
 impl LoadBalancer {
     fn new(config: &MulticoreConfig) -> Self {
+        let mut stats = BalanceStats::default();
+        stats.get_mut(config.balance_algorithm).activations += 1;
         Self {
-            algorithms: vec![config.balance_algorithm],
+            policy: policy_for(config.balance_algorithm),
+            stats,
             thresholds: LoadThresholds {
                 imbalance_threshold: 0.5,
                 migration_threshold: 0.3,
@@ -1171,26 +1650,22 @@ impl LoadBalancer {
     fn start(&self) {
         // Start load balancing thread
     }
+}
 
-    fn select_best_cpu(&self, cpu_states: &[CpuState], candidates: &[CpuId], priority: Priority) -> SchedulerResult<CpuId> {
-        let mut best_cpu = candidates[0];
-        let mut best_load = f32::MAX;
+impl PowerManager {
+    /// Whether wakeups should go through the energy model
+    fn energy_aware(&self) -> bool {
+        self.governor == CpuGovernor::EnergyAware && self.energy_model.is_some()
+    }
 
-        for &cpu_id in candidates {
-            if cpu_states[cpu_id].state == CpuState::Online {
-                let load = cpu_states[cpu_id].load;
-                if load < best_load {
-                    best_load = load;
-                    best_cpu = cpu_id;
-                }
-            }
+    /// Switch the governor on every CPU frequency policy
+    fn set_governor(&mut self, governor: CpuGovernor) {
+        self.governor = governor;
+        for policy in &mut self.freq_policies {
+            policy.governor = governor;
         }
-
-        Ok(best_cpu)
     }
-}
 
-impl PowerManager {
     fn new(config: &MulticoreConfig) -> Self {
         Self {
             freq_policies: Vec::new(),
@@ -1204,6 +1679,8 @@ impl PowerManager {
                 thermal_zones: Vec::new(),
                 throttle_events: Vec::new(),
             },
+            governor: CpuGovernor::OnDemand,
+            energy_model: None,
         }
     }
 
@@ -1223,7 +1700,7 @@ impl PowerManager {
     fn get_frequency_policy(&self, _cpu_id: CpuId) -> SchedulerResult<FrequencyPolicy> {
         Ok(FrequencyPolicy {
             cpu_id: 0,
-            governor: CpuGovernor::OnDemand,
+            governor: self.governor,
             min_freq_mhz: 800,
             max_freq_mhz: 3000,
             current_freq_mhz: 2000,
@@ -1272,38 +1749,6 @@ impl NumaScheduler {
             self.numa_topology.cpu_to_node[i] = 0;
         }
     }
-
-    fn select_cpu_with_numa_awareness(
-        &self,
-        cpu_states: &[CpuState],
-        candidates: &[CpuId],
-        priority: Priority,
-    ) -> SchedulerResult<CpuId> {
-        let mut best_cpu = candidates[0];
-        let mut best_score = f32::MAX;
-
-        for &cpu_id in candidates {
-            if cpu_states[cpu_id].state == CpuState::Online {
-                let load = cpu_states[cpu_id].load;
-                // Add NUMA penalty for non-local nodes
-                let numa_penalty = self.calculate_numa_penalty(cpu_id);
-                let score = load + numa_penalty;
-                
-                if score < best_score {
-                    best_score = score;
-                    best_cpu = cpu_id;
-                }
-            }
-        }
-
-        Ok(best_cpu)
-    }
-
-    fn calculate_numa_penalty(&self, cpu_id: CpuId) -> f32 {
-        let node_id = self.numa_topology.cpu_to_node[cpu_id];
-        // Return penalty for accessing remote memory
-        if node_id == 0 { 0.0 } else { 0.2 }
-    }
 }
 
 impl RealtimeScheduler {
@@ -1360,42 +1805,187 @@ mod tests {
 
     #[test]
     fn test_numa_cpu_selection() {
-        let numa_sched = NumaScheduler {
-            numa_topology: NumaTopology::default(),
-            node_cpu_mapping: vec![vec![0, 1, 2, 3]],
-            balancing_enabled: true,
-            remote_access_penalty: 100,
-        };
-        
-        let cpu_states = vec![
-            CpuState {
-                cpu_id: 0,
-                state: CpuState::Online,
-                power_state: CpuPowerState::Performance,
-                perf_info: CpuPerfInfo::default(),
-                idle_states: Vec::new(),
-                sched_domain: None,
-                current_thread: None,
-                load: 0.5,
-                thermal_state: 0,
-                frequency_scaling: true,
-            },
-            CpuState {
-                cpu_id: 1,
-                state: CpuState::Online,
-                power_state: CpuPowerState::Performance,
-                perf_info: CpuPerfInfo::default(),
-                idle_states: Vec::new(),
-                sched_domain: None,
-                current_thread: None,
-                load: 0.1,
-                thermal_state: 0,
-                frequency_scaling: true,
-            },
+        let cpus = vec![
+            CpuLoadInfo { cpu_id: 0, online: true, load: 0.5, effective_load: 0.5, node: 0, domain: None },
+            CpuLoadInfo { cpu_id: 1, online: true, load: 0.1, effective_load: 0.1, node: 0, domain: None },
         ];
-        
-        let candidates = vec![0, 1];
-        let selected_cpu = numa_sched.select_cpu_with_numa_awareness(&cpu_states, &candidates, Priority::Normal).unwrap();
+        let ctx = BalanceContext { cpus: &cpus, imbalance_threshold: 0.5, previous_cpu: None };
+        let selected_cpu = policy_for(BalanceAlgorithm::NumaAware).select_cpu(&ctx, &[0, 1]).unwrap();
         assert_eq!(selected_cpu, 1); // Should select the less loaded CPU
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_balance_policy_switch() {
+        let config = MulticoreConfig { max_cpus: 4, enable_numa: false, ..Default::default() };
+        let mut scheduler = MulticoreScheduler::new(config);
+        assert_eq!(scheduler.balance_algorithm(), BalanceAlgorithm::NumaAware);
+
+        scheduler.set_balance_algorithm(BalanceAlgorithm::PowerPacking);
+        assert_eq!(scheduler.balance_algorithm(), BalanceAlgorithm::PowerPacking);
+        assert!(scheduler.sched_domains.iter().all(|domain| domain.balance_algorithm == BalanceAlgorithm::PowerPacking));
+
+        scheduler.cpu_states[0].load = 3.0;
+        scheduler.cpu_states[0].current_thread = Some(7);
+        scheduler.cpu_states[1].load = 2.0;
+        scheduler.cpu_states[1].current_thread = Some(8);
+        scheduler.perform_advanced_balancing().unwrap();
+
+        let stats = scheduler.get_balance_stats();
+        assert_eq!(stats.get(BalanceAlgorithm::PowerPacking).activations, 1);
+        assert_eq!(stats.get(BalanceAlgorithm::PowerPacking).balance_runs, 1);
+        assert_eq!(stats.get(BalanceAlgorithm::PowerPacking).migrations, 1);
+        assert_eq!(stats.get(BalanceAlgorithm::NumaAware).migrations, 0);
+    }
+
+    #[test]
+    fn test_isolated_cpus() {
+        let config = MulticoreConfig { max_cpus: 4, enable_numa: false, ..Default::default() };
+        let mut scheduler = MulticoreScheduler::new(config);
+        scheduler.cpu_states[2].load = 1.0;
+        scheduler.cpu_states[2].current_thread = Some(5);
+        scheduler.thread_affinity.insert(5, 0xFFFFFFFF);
+        scheduler.cpu_states[3].load = 1.0;
+        scheduler.cpu_states[3].current_thread = Some(6);
+        scheduler.thread_affinity.insert(6, 1 << 3);
+
+        scheduler.set_isolated_cpus(0b1100).unwrap();
+        assert_eq!(scheduler.housekeeping_cpus(), vec![0, 1]);
+        assert_eq!(scheduler.cpu_states[2].current_thread, None);
+        assert_eq!(scheduler.cpu_states[3].current_thread, Some(6));
+        assert!(scheduler.tick_controller.as_ref().unwrap().is_nohz_full(3));
+        assert!(matches!(scheduler.housekeeping_target(3), Some(0) | Some(1)));
+
+        assert!(!scheduler.affined_to_isolated(0xFFFFFFFF));
+        assert!(scheduler.affined_to_isolated(0b1000));
+        assert!(scheduler.set_isolated_cpus(0b1111).is_err());
+    }
+
+    #[test]
+    fn test_thermal_cap_lifts_after_cooling() {
+        struct FixedSensor(u8);
+
+        impl TemperatureSensor for FixedSensor {
+            fn read_celsius(&mut self, _cpu_id: CpuId) -> Option<u8> {
+                Some(self.0)
+            }
+        }
+
+        let config = MulticoreConfig { max_cpus: 2, enable_numa: false, ..Default::default() };
+        let mut scheduler = MulticoreScheduler::new(config);
+        scheduler.enable_thermal_management(true, 85);
+        let max_frequency = scheduler.cpu_states[0].perf_info.max_frequency;
+
+        let mut sensor = FixedSensor(95);
+        for _ in 0..3 {
+            scheduler.update_thermal(&mut sensor).unwrap();
+        }
+        let hot_frequency = scheduler.cpu_states[0].perf_info.current_frequency;
+        assert!(hot_frequency < max_frequency);
+
+        sensor.0 = 40;
+        scheduler.update_thermal(&mut sensor).unwrap();
+        assert!(scheduler.cpu_states[0].perf_info.current_frequency > hot_frequency);
+        for _ in 0..20 {
+            scheduler.update_thermal(&mut sensor).unwrap();
+        }
+        assert_eq!(scheduler.cpu_states[0].perf_info.current_frequency, max_frequency);
+        assert_eq!(scheduler.cpu_states[0].thermal_state, 0);
+    }
+
+    #[test]
+    fn test_enter_idle_uses_configured_tickless_limits() {
+        let tickless = TicklessConfig { max_idle_ns: 10_000_000, ..Default::default() };
+        let config = MulticoreConfig { max_cpus: 2, enable_numa: false, tickless, ..Default::default() };
+        let mut scheduler = MulticoreScheduler::new(config);
+
+        let timer = scheduler.enter_idle(1, 0).unwrap().unwrap();
+        assert_eq!(timer.expires_ns, 10_000_000);
+        assert!(scheduler.enter_idle(2, 0).is_err());
+    }
+
+    #[test]
+    fn test_timer_tick_expires_futex_timeouts() {
+        use crate::futex::{sys_futex, futex_wait_result, FutexError, FUTEX_WAIT, FUTEX_PRIVATE_FLAG};
+
+        // Far past any real thread, so parking it is a no-op
+        const WAITER: ThreadId = 1 << 21;
+        let config = MulticoreConfig { max_cpus: 2, enable_numa: false, ..Default::default() };
+        let mut scheduler = MulticoreScheduler::new(config);
+        let word = AtomicU32::new(0);
+        let start_ns = 1 << 40;
+
+        let op = FUTEX_WAIT | FUTEX_PRIVATE_FLAG;
+        sys_futex(1, WAITER, Priority::Normal, &word, op, 0, Some(500), None, 0, start_ns).unwrap();
+
+        assert_eq!(scheduler.timer_tick(1, 0, start_ns + 499).unwrap().woken, 0);
+        assert_eq!(scheduler.timer_tick(1, 0, start_ns + 500).unwrap().woken, 1);
+        assert_eq!(futex_wait_result(WAITER), Err(FutexError::TimedOut));
+        assert!(scheduler.timer_tick(2, 0, start_ns + 500).is_err());
+    }
+
+    #[test]
+    fn test_timer_tick_drives_adaptive_tick() {
+        use crate::tickless::TickMode;
+
+        let tickless = TicklessConfig { mode: TickMode::Adaptive, ..Default::default() };
+        let base = tickless.tick_period_ns;
+        let config = MulticoreConfig { max_cpus: 2, enable_numa: false, tickless, ..Default::default() };
+        let mut scheduler = MulticoreScheduler::new(config);
+        let start_ns = 1 << 30;
+
+        // One runnable thread stretches the tick; more bring it back
+        assert_eq!(scheduler.timer_tick(0, 1, start_ns).unwrap().next_tick_ns, base * 8);
+        assert_eq!(scheduler.timer_tick(0, 2, start_ns + base * 8).unwrap().next_tick_ns, base);
+
+        let stats = scheduler.get_tick_stats().unwrap();
+        assert_eq!(stats.ticks_delivered, 2);
+        assert_eq!(stats.ticks_skipped, 7);
+    }
+
+    #[test]
+    fn test_numa_fault_from_unknown_cpu_is_dropped() {
+        let config = MulticoreConfig { max_cpus: 4, ..Default::default() };
+        let mut scheduler = MulticoreScheduler::new(config);
+        let sample = |cpu_id| NumaFaultSample { thread_id: 1, page: memory_manager::PhysAddr::new(0x1000), page_node: 1, cpu_id };
+
+        scheduler.record_numa_fault(sample(MAX_CPUS + 1));
+        scheduler.record_numa_fault(sample(4));
+        scheduler.record_numa_fault(sample(3));
+
+        let patterns = &scheduler.perf_monitor.memory_patterns;
+        let remote: u64 = patterns.remote_accesses.iter().map(|counter| counter.load(Ordering::Relaxed)).sum();
+        assert_eq!(remote, 1);
+        assert_eq!(scheduler.cpu_load_info().len(), 4);
+    }
+
+    #[test]
+    fn test_cpu_down_orders_notifiers_around_migration() {
+        use alloc::sync::Arc;
+
+        let config = MulticoreConfig { max_cpus: 4, enable_numa: false, ..Default::default() };
+        let mut scheduler = MulticoreScheduler::new(config);
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let sink = log.clone();
+        scheduler.register_hotplug_callback("test", crate::hotplug::HOTPLUG_PRIO_SCHED, Box::new(move |cpu_id, state| {
+            sink.lock().push((cpu_id, state));
+            Ok(())
+        }));
+
+        // A thread pinned to the CPU cannot migrate: OFFLINE is rolled back
+        scheduler.cpu_states[2].current_thread = Some(5);
+        scheduler.thread_affinity.insert(5, 1 << 2);
+        assert!(scheduler.handle_cpu_hotplug(2, false).is_err());
+        assert_eq!(scheduler.hotplug_state(2), Some(HotplugState::Online));
+        assert_eq!(scheduler.cpu_states[2].state, CpuState::Online);
+        assert_eq!(*log.lock(), vec![(2, HotplugState::Offline), (2, HotplugState::Online)]);
+
+        // Once it may move, DEAD runs after it left the CPU
+        log.lock().clear();
+        scheduler.thread_affinity.insert(5, 0b0101);
+        scheduler.handle_cpu_hotplug(2, false).unwrap();
+        assert_eq!(scheduler.hotplug_state(2), Some(HotplugState::Dead));
+        assert_eq!(scheduler.cpu_states[2].current_thread, None);
+        assert_eq!(scheduler.cpu_states[0].current_thread, Some(5));
+        assert_eq!(*log.lock(), vec![(2, HotplugState::Offline), (2, HotplugState::Dead)]);
+    }
+}
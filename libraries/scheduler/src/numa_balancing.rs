@@ -0,0 +1,366 @@
+//! Automatic NUMA Page Migration for MultiOS
+//!
+//! This module closes the loop between the NUMA-aware scheduler and the
+//! memory manager:
+//! - Periodic NUMA hinting fault sampling per thread
+//! - Scoring of hot pages that live on a node remote to the thread's CPUs
+//! - Rate-limited migration requests to `memory_manager::numa`
+//! - Statistics on migrations and locality improvements
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use memory_manager::PhysAddr;
+use memory_manager::numa::{NumaManager, NumaNodeId};
+
+use crate::thread::ThreadId;
+use crate::scheduler_algo::{CpuAffinity, CpuId};
+
+/// Sink for page migration requests.
+///
+/// Implemented by the memory manager's `NumaManager`; tests and simulators can
+/// provide their own implementation.
+pub trait PageMigrator {
+    /// Migrate pages to `target_node`
+    fn migrate(&mut self, pages: &[PhysAddr], target_node: NumaNodeId) -> Result<(), ()>;
+    /// Relative distance between nodes (10 = local)
+    fn node_distance(&self, from: NumaNodeId, to: NumaNodeId) -> u8;
+}
+
+impl PageMigrator for NumaManager {
+    fn migrate(&mut self, pages: &[PhysAddr], target_node: NumaNodeId) -> Result<(), ()> {
+        self.migrate_pages(pages, target_node).map_err(|_| ())
+    }
+
+    fn node_distance(&self, from: NumaNodeId, to: NumaNodeId) -> u8 {
+        self.get_distance(from, to)
+    }
+}
+
+/// NUMA balancing configuration
+#[derive(Debug, Clone)]
+pub struct NumaBalancingConfig {
+    /// Interval between scans (nanoseconds)
+    pub scan_period_ns: u64,
+    /// Minimum faults on a page before it is considered hot
+    pub min_page_faults: u32,
+    /// Maximum pages migrated per scan
+    pub max_migrations_per_scan: usize,
+    /// Minimum score for a page to be migrated
+    pub score_threshold: f32,
+    /// Fault count decay applied after each scan (0.0 - 1.0)
+    pub decay_factor: f32,
+}
+
+impl Default for NumaBalancingConfig {
+    fn default() -> Self {
+        Self {
+            scan_period_ns: 1_000_000_000,
+            min_page_faults: 4,
+            max_migrations_per_scan: 256,
+            score_threshold: 1.0,
+            decay_factor: 0.5,
+        }
+    }
+}
+
+/// A sampled NUMA hinting fault
+#[derive(Debug, Clone, Copy)]
+pub struct NumaFaultSample {
+    pub thread_id: ThreadId,
+    pub page: PhysAddr,
+    /// Node the page currently lives on
+    pub page_node: NumaNodeId,
+    /// CPU that took the fault
+    pub cpu_id: CpuId,
+}
+
+/// Per-page fault history for a thread
+#[derive(Debug, Clone, Copy)]
+struct PageHotness {
+    node: NumaNodeId,
+    faults: u32,
+}
+
+/// Per-thread fault history
+#[derive(Debug, Default, Clone)]
+struct ThreadFaults {
+    pages: BTreeMap<PhysAddr, PageHotness>,
+    local_faults: u64,
+    remote_faults: u64,
+}
+
+/// Page migration candidate
+#[derive(Debug, Clone, Copy)]
+pub struct MigrationCandidate {
+    pub thread_id: ThreadId,
+    pub page: PhysAddr,
+    pub from_node: NumaNodeId,
+    pub to_node: NumaNodeId,
+    pub score: f32,
+}
+
+/// NUMA balancing statistics
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NumaBalancingStats {
+    pub scans: u64,
+    pub faults_sampled: u64,
+    pub local_faults: u64,
+    pub remote_faults: u64,
+    pub candidates_scored: u64,
+    pub pages_migrated: u64,
+    pub migrations_failed: u64,
+    /// Local fault ratio measured in the previous scan window
+    pub locality_before: f32,
+    /// Local fault ratio measured in the latest scan window
+    pub locality_after: f32,
+}
+
+/// NUMA fault sampler and migration driver
+#[derive(Debug)]
+pub struct NumaBalancer {
+    config: NumaBalancingConfig,
+    cpu_to_node: Vec<NumaNodeId>,
+    threads: BTreeMap<ThreadId, ThreadFaults>,
+    affinities: BTreeMap<ThreadId, CpuAffinity>,
+    last_scan_ns: u64,
+    stats: NumaBalancingStats,
+}
+
+impl NumaBalancer {
+    /// Create a balancer for the given CPU-to-node mapping
+    pub fn new(config: NumaBalancingConfig, cpu_to_node: Vec<NumaNodeId>) -> Self {
+        Self {
+            config,
+            cpu_to_node,
+            threads: BTreeMap::new(),
+            affinities: BTreeMap::new(),
+            last_scan_ns: 0,
+            stats: NumaBalancingStats::default(),
+        }
+    }
+
+    /// Record a thread's CPU affinity (used to pick its home node)
+    pub fn set_thread_affinity(&mut self, thread_id: ThreadId, affinity: CpuAffinity) {
+        self.affinities.insert(thread_id, affinity);
+    }
+
+    /// Forget a thread that exited
+    pub fn remove_thread(&mut self, thread_id: ThreadId) {
+        self.threads.remove(&thread_id);
+        self.affinities.remove(&thread_id);
+    }
+
+    /// Record a NUMA hinting fault
+    pub fn record_fault(&mut self, sample: NumaFaultSample) {
+        let cpu_node = self.node_of_cpu(sample.cpu_id);
+        let entry = self.threads.entry(sample.thread_id).or_default();
+
+        let page = entry.pages.entry(sample.page).or_insert(PageHotness {
+            node: sample.page_node,
+            faults: 0,
+        });
+        page.node = sample.page_node;
+        page.faults = page.faults.saturating_add(1);
+
+        if sample.page_node == cpu_node {
+            entry.local_faults += 1;
+            self.stats.local_faults += 1;
+        } else {
+            entry.remote_faults += 1;
+            self.stats.remote_faults += 1;
+        }
+        self.stats.faults_sampled += 1;
+    }
+
+    /// Home node for a thread: the node holding most of its allowed CPUs
+    pub fn preferred_node(&self, thread_id: ThreadId) -> Option<NumaNodeId> {
+        let affinity = *self.affinities.get(&thread_id)?;
+        let mut counts: BTreeMap<NumaNodeId, usize> = BTreeMap::new();
+
+        for (cpu_id, &node) in self.cpu_to_node.iter().enumerate() {
+            if cpu_id < 32 && affinity & (1 << cpu_id) != 0 {
+                *counts.entry(node).or_insert(0) += 1;
+            }
+        }
+
+        counts.into_iter().max_by_key(|&(node, count)| (count, core::cmp::Reverse(node))).map(|(node, _)| node)
+    }
+
+    /// Score migration candidates for all sampled threads
+    pub fn score_candidates(&mut self, migrator: &dyn PageMigrator) -> Vec<MigrationCandidate> {
+        let mut candidates = Vec::new();
+
+        for (&thread_id, faults) in &self.threads {
+            let target = match self.preferred_node(thread_id) {
+                Some(node) => node,
+                None => continue,
+            };
+
+            // Threads already mostly local gain little from migration
+            let total = faults.local_faults + faults.remote_faults;
+            let remote_ratio = if total == 0 { 0.0 } else { faults.remote_faults as f32 / total as f32 };
+
+            for (&page, hotness) in &faults.pages {
+                if hotness.node == target || hotness.faults < self.config.min_page_faults {
+                    continue;
+                }
+
+                let distance = migrator.node_distance(hotness.node, target).max(10) as f32 / 10.0;
+                let score = hotness.faults as f32 * distance * (0.5 + remote_ratio) / self.config.min_page_faults.max(1) as f32;
+
+                if score >= self.config.score_threshold {
+                    candidates.push(MigrationCandidate {
+                        thread_id,
+                        page,
+                        from_node: hotness.node,
+                        to_node: target,
+                        score,
+                    });
+                }
+            }
+        }
+
+        candidates.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(core::cmp::Ordering::Equal));
+        candidates.truncate(self.config.max_migrations_per_scan);
+        self.stats.candidates_scored += candidates.len() as u64;
+        candidates
+    }
+
+    /// Run a balancing scan if the scan period has elapsed.
+    ///
+    /// Returns the number of pages migrated.
+    pub fn scan(&mut self, migrator: &mut dyn PageMigrator, now_ns: u64) -> usize {
+        if now_ns.saturating_sub(self.last_scan_ns) < self.config.scan_period_ns {
+            return 0;
+        }
+        self.last_scan_ns = now_ns;
+        self.stats.scans += 1;
+
+        let candidates = self.score_candidates(migrator);
+
+        // Batch pages per target node
+        let mut batches: BTreeMap<NumaNodeId, Vec<MigrationCandidate>> = BTreeMap::new();
+        for candidate in candidates {
+            batches.entry(candidate.to_node).or_default().push(candidate);
+        }
+
+        let mut migrated = 0;
+        for (target, batch) in batches {
+            let pages: Vec<PhysAddr> = batch.iter().map(|c| c.page).collect();
+            match migrator.migrate(&pages, target) {
+                Ok(()) => {
+                    migrated += pages.len();
+                    for candidate in &batch {
+                        if let Some(hot) = self.threads.get_mut(&candidate.thread_id)
+                            .and_then(|t| t.pages.get_mut(&candidate.page))
+                        {
+                            hot.node = target;
+                        }
+                    }
+                }
+                Err(()) => self.stats.migrations_failed += pages.len() as u64,
+            }
+        }
+        self.stats.pages_migrated += migrated as u64;
+
+        self.update_locality();
+        self.decay();
+        migrated
+    }
+
+    /// Get NUMA balancing statistics
+    pub fn get_stats(&self) -> NumaBalancingStats {
+        self.stats
+    }
+
+    fn node_of_cpu(&self, cpu_id: CpuId) -> NumaNodeId {
+        self.cpu_to_node.get(cpu_id).copied().unwrap_or(0)
+    }
+
+    fn update_locality(&mut self) {
+        let (local, remote) = self.threads.values()
+            .fold((0u64, 0u64), |(l, r), t| (l + t.local_faults, r + t.remote_faults));
+        let total = local + remote;
+        self.stats.locality_before = self.stats.locality_after;
+        self.stats.locality_after = if total == 0 { 1.0 } else { local as f32 / total as f32 };
+    }
+
+    fn decay(&mut self) {
+        let factor = self.config.decay_factor.clamp(0.0, 1.0);
+        for thread in self.threads.values_mut() {
+            thread.local_faults = 0;
+            thread.remote_faults = 0;
+            thread.pages.retain(|_, page| {
+                page.faults = (page.faults as f32 * factor) as u32;
+                page.faults > 0
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct MockMigrator {
+        migrated: Vec<(PhysAddr, NumaNodeId)>,
+    }
+
+    impl PageMigrator for MockMigrator {
+        fn migrate(&mut self, pages: &[PhysAddr], target_node: NumaNodeId) -> Result<(), ()> {
+            self.migrated.extend(pages.iter().map(|&p| (p, target_node)));
+            Ok(())
+        }
+
+        fn node_distance(&self, from: NumaNodeId, to: NumaNodeId) -> u8 {
+            if from == to { 10 } else { 20 }
+        }
+    }
+
+    fn balancer() -> NumaBalancer {
+        // CPUs 0-1 on node 0, CPUs 2-3 on node 1
+        NumaBalancer::new(NumaBalancingConfig::default(), vec![0, 0, 1, 1])
+    }
+
+    #[test]
+    fn test_preferred_node_from_affinity() {
+        let mut balancer = balancer();
+        balancer.set_thread_affinity(1, 0b1100);
+        assert_eq!(balancer.preferred_node(1), Some(1));
+        assert_eq!(balancer.preferred_node(2), None);
+    }
+
+    #[test]
+    fn test_hot_remote_page_is_migrated() {
+        let mut balancer = balancer();
+        let mut migrator = MockMigrator { migrated: Vec::new() };
+        balancer.set_thread_affinity(1, 0b1100);
+
+        let page = PhysAddr::new(0x1000);
+        for _ in 0..8 {
+            balancer.record_fault(NumaFaultSample { thread_id: 1, page, page_node: 0, cpu_id: 2 });
+        }
+
+        assert_eq!(balancer.scan(&mut migrator, 1_000_000_000), 1);
+        assert_eq!(migrator.migrated, vec![(page, 1)]);
+
+        let stats = balancer.get_stats();
+        assert_eq!(stats.pages_migrated, 1);
+        assert_eq!(stats.remote_faults, 8);
+    }
+
+    #[test]
+    fn test_cold_pages_and_scan_period() {
+        let mut balancer = balancer();
+        let mut migrator = MockMigrator { migrated: Vec::new() };
+        balancer.set_thread_affinity(1, 0b0011);
+
+        balancer.record_fault(NumaFaultSample { thread_id: 1, page: PhysAddr::new(0x2000), page_node: 1, cpu_id: 0 });
+        assert_eq!(balancer.scan(&mut migrator, 10), 0);
+        assert_eq!(balancer.get_stats().scans, 0);
+
+        assert_eq!(balancer.scan(&mut migrator, 2_000_000_000), 0);
+        assert!(migrator.migrated.is_empty());
+    }
+}
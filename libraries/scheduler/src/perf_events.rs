@@ -0,0 +1,432 @@
+//! Hardware Performance Events for MultiOS
+//!
+//! This module provides the programming model behind `enable_hardware_counters`:
+//! - Events (cycles, instructions, cache and branch misses) opened per CPU or
+//!   per thread
+//! - Event groups whose members are always scheduled onto the PMU together
+//! - Counter multiplexing: when more groups are attached to a CPU than it has
+//!   physical counters, flexible groups rotate on every tick and counts are
+//!   scaled by `time_enabled / time_running`
+//! - Pinned groups that are never rotated out
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use crate::performance_monitor::PerfCounterType;
+use crate::scheduler_algo::CpuId;
+use crate::thread::ThreadId;
+
+/// Perf event identifier
+pub type PerfEventId = u32;
+
+/// Perf event group identifier
+pub type PerfGroupId = u32;
+
+/// Perf event result type
+pub type PerfEventResult<T> = Result<T, PerfEventError>;
+
+/// Perf event errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PerfEventError {
+    /// Event is not counted by the PMU
+    NotHardwareEvent,
+    /// CPU is out of range
+    InvalidCpu,
+    /// Group would need more counters than the PMU provides
+    GroupTooLarge,
+    /// Unknown event or group
+    NotFound,
+}
+
+/// What an event counts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PerfTarget {
+    /// Everything that runs on a CPU
+    Cpu(CpuId),
+    /// A thread, on whichever CPU it runs
+    Thread(ThreadId),
+}
+
+/// Physical counters of the per-CPU PMUs
+pub trait PmuCounters {
+    /// Number of general-purpose counters on a CPU
+    fn num_counters(&self, cpu_id: CpuId) -> usize;
+    /// Program and start a counter
+    fn program(&mut self, cpu_id: CpuId, counter: usize, event: PerfCounterType);
+    /// Read a counter's raw value
+    fn read(&mut self, cpu_id: CpuId, counter: usize) -> u64;
+    /// Stop a counter
+    fn stop(&mut self, cpu_id: CpuId, counter: usize);
+}
+
+/// Whether the PMU can count an event
+pub fn is_hardware_event(event: PerfCounterType) -> bool {
+    matches!(
+        event,
+        PerfCounterType::Cycles
+            | PerfCounterType::Instructions
+            | PerfCounterType::CacheReferences
+            | PerfCounterType::CacheMisses
+            | PerfCounterType::BranchInstructions
+            | PerfCounterType::BranchMisses
+    )
+}
+
+/// Event value with multiplexing times
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PerfEventValue {
+    /// Events counted while on a physical counter
+    pub raw: u64,
+    /// Time the event was eligible to count
+    pub time_enabled_ns: u64,
+    /// Time the event actually held a counter
+    pub time_running_ns: u64,
+}
+
+impl PerfEventValue {
+    /// Count extrapolated over the whole enabled time
+    pub fn scaled(&self) -> u64 {
+        if self.time_running_ns == 0 || self.time_running_ns >= self.time_enabled_ns {
+            return self.raw;
+        }
+        (self.raw as u128 * self.time_enabled_ns as u128 / self.time_running_ns as u128) as u64
+    }
+
+    /// Whether the event shared its counter with others
+    pub fn multiplexed(&self) -> bool {
+        self.time_running_ns < self.time_enabled_ns
+    }
+}
+
+#[derive(Debug)]
+struct PerfEvent {
+    event: PerfCounterType,
+    group: PerfGroupId,
+    value: PerfEventValue,
+    /// Counter currently holding the event and its last raw reading
+    counter: Option<(CpuId, usize, u64)>,
+}
+
+#[derive(Debug)]
+struct PerfGroup {
+    target: PerfTarget,
+    pinned: bool,
+    members: Vec<PerfEventId>,
+    /// CPU the group is scheduled on
+    active_on: Option<CpuId>,
+}
+
+#[derive(Debug, Default)]
+struct CpuPmuState {
+    current_thread: Option<ThreadId>,
+    /// Rotation offset into the flexible groups
+    rotation: usize,
+    /// Whether the last schedule left flexible groups without counters
+    oversubscribed: bool,
+    last_update_ns: u64,
+}
+
+/// Perf event scheduler: opens events and multiplexes them onto the PMU
+#[derive(Debug)]
+pub struct PerfEventManager {
+    cpus: Vec<CpuPmuState>,
+    events: BTreeMap<PerfEventId, PerfEvent>,
+    groups: BTreeMap<PerfGroupId, PerfGroup>,
+    next_id: u32,
+    rotations: u64,
+}
+
+impl PerfEventManager {
+    /// Create a manager for `cpu_count` CPUs
+    pub fn new(cpu_count: usize) -> Self {
+        Self {
+            cpus: (0..cpu_count).map(|_| CpuPmuState::default()).collect(),
+            events: BTreeMap::new(),
+            groups: BTreeMap::new(),
+            next_id: 1,
+            rotations: 0,
+        }
+    }
+
+    /// Open an event as the leader of a new group
+    pub fn open_group(&mut self, event: PerfCounterType, target: PerfTarget, pinned: bool) -> PerfEventResult<(PerfGroupId, PerfEventId)> {
+        if !is_hardware_event(event) {
+            return Err(PerfEventError::NotHardwareEvent);
+        }
+        if let PerfTarget::Cpu(cpu_id) = target {
+            if cpu_id >= self.cpus.len() {
+                return Err(PerfEventError::InvalidCpu);
+            }
+        }
+
+        let group_id = self.alloc_id();
+        self.groups.insert(group_id, PerfGroup { target, pinned, members: Vec::new(), active_on: None });
+        let event_id = self.add_member(group_id, event);
+        Ok((group_id, event_id))
+    }
+
+    /// Open a single ungrouped, flexible event
+    pub fn open(&mut self, event: PerfCounterType, target: PerfTarget) -> PerfEventResult<PerfEventId> {
+        self.open_group(event, target, false).map(|(_, event_id)| event_id)
+    }
+
+    /// Add an event to an existing group; it is scheduled together with the
+    /// leader from the next schedule on
+    pub fn add_to_group(&mut self, group_id: PerfGroupId, event: PerfCounterType, pmu: &dyn PmuCounters) -> PerfEventResult<PerfEventId> {
+        if !is_hardware_event(event) {
+            return Err(PerfEventError::NotHardwareEvent);
+        }
+        let group = self.groups.get(&group_id).ok_or(PerfEventError::NotFound)?;
+        let max_counters = match group.target {
+            PerfTarget::Cpu(cpu_id) => pmu.num_counters(cpu_id),
+            PerfTarget::Thread(_) => (0..self.cpus.len()).map(|cpu_id| pmu.num_counters(cpu_id)).min().unwrap_or(0),
+        };
+        if group.members.len() >= max_counters {
+            return Err(PerfEventError::GroupTooLarge);
+        }
+        Ok(self.add_member(group_id, event))
+    }
+
+    /// Close an event; closing a group leader closes the whole group
+    pub fn close(&mut self, event_id: PerfEventId, pmu: &mut dyn PmuCounters) -> PerfEventResult<()> {
+        let group_id = self.events.get(&event_id).ok_or(PerfEventError::NotFound)?.group;
+        let leader = self.groups[&group_id].members[0];
+
+        let closing: Vec<PerfEventId> = if leader == event_id {
+            self.groups.remove(&group_id).map(|group| group.members).unwrap_or_default()
+        } else {
+            self.groups.get_mut(&group_id).unwrap().members.retain(|&member| member != event_id);
+            alloc::vec![event_id]
+        };
+
+        for id in closing {
+            if let Some(PerfEvent { counter: Some((cpu_id, counter, _)), .. }) = self.events.remove(&id) {
+                pmu.stop(cpu_id, counter);
+            }
+        }
+        Ok(())
+    }
+
+    /// Read an event's value
+    pub fn read(&self, event_id: PerfEventId) -> PerfEventResult<PerfEventValue> {
+        self.events.get(&event_id).map(|event| event.value).ok_or(PerfEventError::NotFound)
+    }
+
+    /// Read all members of a group, leader first
+    pub fn read_group(&self, group_id: PerfGroupId) -> PerfEventResult<Vec<(PerfCounterType, PerfEventValue)>> {
+        let group = self.groups.get(&group_id).ok_or(PerfEventError::NotFound)?;
+        Ok(group.members.iter()
+            .map(|id| (self.events[id].event, self.events[id].value))
+            .collect())
+    }
+
+    /// Context switch on a CPU: thread-attached groups follow their thread
+    pub fn switch_thread(&mut self, cpu_id: CpuId, next: Option<ThreadId>, pmu: &mut dyn PmuCounters, now_ns: u64) -> PerfEventResult<()> {
+        self.update(cpu_id, pmu, now_ns)?;
+        self.cpus[cpu_id].current_thread = next;
+        self.reschedule(cpu_id, pmu);
+        Ok(())
+    }
+
+    /// Scheduler tick: accumulate counts and rotate flexible groups when the
+    /// CPU is oversubscribed
+    pub fn tick(&mut self, cpu_id: CpuId, pmu: &mut dyn PmuCounters, now_ns: u64) -> PerfEventResult<()> {
+        self.update(cpu_id, pmu, now_ns)?;
+        if self.cpus[cpu_id].oversubscribed {
+            self.cpus[cpu_id].rotation = self.cpus[cpu_id].rotation.wrapping_add(1);
+            self.rotations += 1;
+        }
+        self.reschedule(cpu_id, pmu);
+        Ok(())
+    }
+
+    /// Number of multiplexing rotations performed
+    pub fn rotations(&self) -> u64 {
+        self.rotations
+    }
+
+    /// Groups that hold counters on a CPU
+    pub fn active_groups(&self, cpu_id: CpuId) -> Vec<PerfGroupId> {
+        self.groups.iter()
+            .filter(|(_, group)| group.active_on == Some(cpu_id))
+            .map(|(&group_id, _)| group_id)
+            .collect()
+    }
+
+    fn alloc_id(&mut self) -> u32 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    fn add_member(&mut self, group_id: PerfGroupId, event: PerfCounterType) -> PerfEventId {
+        let event_id = self.alloc_id();
+        self.events.insert(event_id, PerfEvent { event, group: group_id, value: PerfEventValue::default(), counter: None });
+        self.groups.get_mut(&group_id).unwrap().members.push(event_id);
+        event_id
+    }
+
+    fn eligible(&self, group: &PerfGroup, cpu_id: CpuId) -> bool {
+        match group.target {
+            PerfTarget::Cpu(target) => target == cpu_id,
+            PerfTarget::Thread(thread_id) => self.cpus[cpu_id].current_thread == Some(thread_id),
+        }
+    }
+
+    /// Fold elapsed time and counter deltas into the groups eligible on a CPU
+    fn update(&mut self, cpu_id: CpuId, pmu: &mut dyn PmuCounters, now_ns: u64) -> PerfEventResult<()> {
+        let cpu = self.cpus.get_mut(cpu_id).ok_or(PerfEventError::InvalidCpu)?;
+        let elapsed = now_ns.saturating_sub(cpu.last_update_ns);
+        cpu.last_update_ns = now_ns;
+
+        let eligible: Vec<PerfGroupId> = self.groups.iter()
+            .filter(|(_, group)| self.eligible(group, cpu_id))
+            .map(|(&group_id, _)| group_id)
+            .collect();
+
+        for group_id in eligible {
+            for event_id in &self.groups[&group_id].members {
+                let event = self.events.get_mut(event_id).unwrap();
+                event.value.time_enabled_ns += elapsed;
+                let Some((cpu, counter, last)) = &mut event.counter else {
+                    continue;
+                };
+                let raw = pmu.read(*cpu, *counter);
+                event.value.raw += raw.wrapping_sub(*last);
+                event.value.time_running_ns += elapsed;
+                *last = raw;
+            }
+        }
+        Ok(())
+    }
+
+    /// Take every group off a CPU's counters and schedule eligible groups
+    /// back on: pinned first, then flexible ones from the rotation offset
+    fn reschedule(&mut self, cpu_id: CpuId, pmu: &mut dyn PmuCounters) {
+        for group in self.groups.values_mut().filter(|group| group.active_on == Some(cpu_id)) {
+            group.active_on = None;
+            for event_id in &group.members {
+                if let Some((cpu, counter, _)) = self.events.get_mut(event_id).unwrap().counter.take() {
+                    pmu.stop(cpu, counter);
+                }
+            }
+        }
+
+        let (pinned, flexible): (Vec<PerfGroupId>, Vec<PerfGroupId>) = self.groups.iter()
+            .filter(|(_, group)| self.eligible(group, cpu_id))
+            .map(|(&group_id, _)| group_id)
+            .partition(|group_id| self.groups[group_id].pinned);
+
+        let rotation = if flexible.is_empty() { 0 } else { self.cpus[cpu_id].rotation % flexible.len() };
+        let order = pinned.iter().chain(flexible[rotation..].iter()).chain(flexible[..rotation].iter());
+
+        let capacity = pmu.num_counters(cpu_id);
+        let mut next_counter = 0;
+        let mut oversubscribed = false;
+        for &group_id in order {
+            let group = self.groups.get_mut(&group_id).unwrap();
+            if next_counter + group.members.len() > capacity {
+                oversubscribed |= !group.pinned;
+                continue;
+            }
+            group.active_on = Some(cpu_id);
+            for event_id in &group.members {
+                let event = self.events.get_mut(event_id).unwrap();
+                pmu.program(cpu_id, next_counter, event.event);
+                let raw = pmu.read(cpu_id, next_counter);
+                event.counter = Some((cpu_id, next_counter, raw));
+                next_counter += 1;
+            }
+        }
+        self.cpus[cpu_id].oversubscribed = oversubscribed;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two counters per CPU that advance by one per read
+    struct TestPmu {
+        counters: Vec<[Option<u64>; 2]>,
+    }
+
+    impl PmuCounters for TestPmu {
+        fn num_counters(&self, _cpu_id: CpuId) -> usize {
+            2
+        }
+
+        fn program(&mut self, cpu_id: CpuId, counter: usize, _event: PerfCounterType) {
+            self.counters[cpu_id][counter] = Some(0);
+        }
+
+        fn read(&mut self, cpu_id: CpuId, counter: usize) -> u64 {
+            let value = self.counters[cpu_id][counter].as_mut().unwrap();
+            *value += 1;
+            *value
+        }
+
+        fn stop(&mut self, cpu_id: CpuId, counter: usize) {
+            self.counters[cpu_id][counter] = None;
+        }
+    }
+
+    #[test]
+    fn test_group_limits_and_software_events() {
+        let pmu = TestPmu { counters: alloc::vec![[None; 2]; 1] };
+        let mut manager = PerfEventManager::new(1);
+
+        assert_eq!(manager.open(PerfCounterType::PageFaults, PerfTarget::Cpu(0)), Err(PerfEventError::NotHardwareEvent));
+        assert_eq!(manager.open(PerfCounterType::Cycles, PerfTarget::Cpu(4)), Err(PerfEventError::InvalidCpu));
+
+        let (group, _) = manager.open_group(PerfCounterType::Cycles, PerfTarget::Cpu(0), false).unwrap();
+        manager.add_to_group(group, PerfCounterType::Instructions, &pmu).unwrap();
+        assert_eq!(manager.add_to_group(group, PerfCounterType::CacheMisses, &pmu), Err(PerfEventError::GroupTooLarge));
+    }
+
+    #[test]
+    fn test_multiplexing_rotates_and_scales() {
+        let mut pmu = TestPmu { counters: alloc::vec![[None; 2]; 1] };
+        let mut manager = PerfEventManager::new(1);
+
+        let (pinned, _) = manager.open_group(PerfCounterType::Cycles, PerfTarget::Cpu(0), true).unwrap();
+        let misses = manager.open(PerfCounterType::CacheMisses, PerfTarget::Cpu(0)).unwrap();
+        let branches = manager.open(PerfCounterType::BranchMisses, PerfTarget::Cpu(0)).unwrap();
+
+        manager.tick(0, &mut pmu, 0).unwrap();
+        for step in 1..=4 {
+            assert!(manager.active_groups(0).contains(&pinned));
+            assert_eq!(manager.active_groups(0).len(), 2);
+            manager.tick(0, &mut pmu, step * 1000).unwrap();
+        }
+        assert_eq!(manager.rotations(), 4);
+
+        for event_id in [misses, branches] {
+            let value = manager.read(event_id).unwrap();
+            assert_eq!(value.time_enabled_ns, 4000);
+            assert_eq!(value.time_running_ns, 2000);
+            assert!(value.multiplexed());
+            assert_eq!(value.scaled(), value.raw * 2);
+        }
+    }
+
+    #[test]
+    fn test_thread_events_follow_thread() {
+        let mut pmu = TestPmu { counters: alloc::vec![[None; 2]; 2] };
+        let mut manager = PerfEventManager::new(2);
+        let event = manager.open(PerfCounterType::Instructions, PerfTarget::Thread(7)).unwrap();
+
+        manager.switch_thread(0, Some(7), &mut pmu, 0).unwrap();
+        assert_eq!(manager.active_groups(0).len(), 1);
+        manager.switch_thread(0, None, &mut pmu, 100).unwrap();
+        assert!(manager.active_groups(0).is_empty());
+        manager.switch_thread(1, Some(7), &mut pmu, 200).unwrap();
+        manager.tick(1, &mut pmu, 300).unwrap();
+
+        let value = manager.read(event).unwrap();
+        assert_eq!(value.time_running_ns, 200);
+        assert!(!value.multiplexed());
+        manager.close(event, &mut pmu).unwrap();
+        assert_eq!(manager.read(event), Err(PerfEventError::NotFound));
+    }
+}
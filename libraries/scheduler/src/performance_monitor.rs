@@ -5,14 +5,16 @@
 //! - Real-time performance metrics collection
 //! - CPU utilization and load analysis
 //! - Memory access pattern analysis
-! - Cache coherency monitoring
+//! - Cache coherency monitoring
 //! - NUMA performance optimization
 //! - Thermal and power management
 //! - Predictive performance modeling
 //! - Automatic performance tuning
 //! - Performance regression detection
+//! - Seasonal anomaly detection
 //! - Resource contention analysis
 
+use alloc::collections::BTreeMap;
 use alloc::vec::Vec;
 use spin::Mutex;
 use bitflags::bitflags;
@@ -22,6 +24,11 @@ use core::time::Duration;
 use crate::{
     multicore::{CpuId, CpuState, CpuPerfInfo, MulticoreScheduler},
     scheduler_algo::{Scheduler, SchedulerStats},
+    process::ProcessId,
+    thread::ThreadId,
+    energy::EnergyStats,
+    perf_events::PerfEventManager,
+    anomaly::{AnomalyConfig, AnomalyDetector, AnomalyEvent, ANOMALY_ALERT_ID_BASE},
 };
 
 /// Maximum number of CPUs to monitor
@@ -69,6 +76,8 @@ pub enum PerfCounterType {
     ThermalThrottles,
     /// NUMA remote accesses
     NumaRemoteAccesses,
+    /// I/O throughput
+    IoThroughput,
 }
 
 /// Hardware performance counter
@@ -199,6 +208,10 @@ pub struct PowerPerformanceStats {
     pub power_efficiency_score: f32,
     pub c_state_residency_percent: f32,
     pub frequency_scaling_events: u32,
+    /// Estimated energy consumed since boot (from the energy model)
+    pub estimated_energy_joules: f64,
+    pub energy_aware_placements: u64,
+    pub little_core_placements: u64,
 }
 
 /// Performance alert configuration
@@ -212,6 +225,16 @@ pub struct PerformanceAlert {
     pub duration_seconds: u32,
     pub enabled: bool,
     pub action: AlertAction,
+    pub category: AlertCategory,
+}
+
+/// What raised a performance alert
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlertCategory {
+    /// A metric crossed a fixed threshold
+    Threshold,
+    /// A metric deviated from its learned seasonal baseline
+    Anomaly,
 }
 
 /// Performance alert comparison operators
@@ -252,6 +275,7 @@ pub struct PerformancePredictor {
     pub prediction_horizon: Duration,
     pub confidence_threshold: f32,
     pub accuracy_score: f32,
+    pub anomaly_detector: AnomalyDetector,
 }
 
 /// Types of prediction models
@@ -484,6 +508,97 @@ pub struct NetworkContention {
     pub packet_loss_rate: f32,
 }
 
+/// Per-thread runtime accounting
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct TaskStats {
+    pub thread_id: ThreadId,
+    pub process_id: ProcessId,
+    /// Time spent in user mode (nanoseconds)
+    pub user_time_ns: u64,
+    /// Time spent in kernel mode (nanoseconds)
+    pub sys_time_ns: u64,
+    pub voluntary_switches: u64,
+    pub involuntary_switches: u64,
+    pub migrations: u64,
+    /// Instructions retired (hardware counters only)
+    pub instructions: Option<u64>,
+    /// Cache misses (hardware counters only)
+    pub cache_misses: Option<u64>,
+}
+
+impl TaskStats {
+    /// Total CPU time (nanoseconds)
+    pub fn total_time_ns(&self) -> u64 {
+        self.user_time_ns + self.sys_time_ns
+    }
+
+    /// Cache misses per thousand instructions, when counters are available
+    pub fn cache_mpki(&self) -> Option<f32> {
+        match (self.cache_misses, self.instructions) {
+            (Some(misses), Some(instructions)) if instructions > 0 => {
+                Some(misses as f32 * 1000.0 / instructions as f32)
+            }
+            _ => None,
+        }
+    }
+
+    fn merge(&mut self, other: &TaskStats) {
+        self.user_time_ns += other.user_time_ns;
+        self.sys_time_ns += other.sys_time_ns;
+        self.voluntary_switches += other.voluntary_switches;
+        self.involuntary_switches += other.involuntary_switches;
+        self.migrations += other.migrations;
+        self.instructions = add_optional(self.instructions, other.instructions);
+        self.cache_misses = add_optional(self.cache_misses, other.cache_misses);
+    }
+}
+
+fn add_optional(a: Option<u64>, b: Option<u64>) -> Option<u64> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a + b),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+/// Metric used to rank top consumers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskMetric {
+    CpuTime,
+    UserTime,
+    SysTime,
+    ContextSwitches,
+    CacheMisses,
+}
+
+/// Per-thread and per-process accounting table
+#[derive(Debug, Default)]
+pub struct TaskAccounting {
+    threads: BTreeMap<ThreadId, TaskStats>,
+    /// Totals of threads that have exited, folded into their process
+    exited: BTreeMap<ProcessId, TaskStats>,
+}
+
+impl TaskAccounting {
+    fn entry(&mut self, process_id: ProcessId, thread_id: ThreadId) -> &mut TaskStats {
+        self.threads.entry(thread_id).or_insert_with(|| TaskStats {
+            thread_id,
+            process_id,
+            ..TaskStats::default()
+        })
+    }
+
+    fn metric(stats: &TaskStats, metric: TaskMetric) -> u64 {
+        match metric {
+            TaskMetric::CpuTime => stats.total_time_ns(),
+            TaskMetric::UserTime => stats.user_time_ns,
+            TaskMetric::SysTime => stats.sys_time_ns,
+            TaskMetric::ContextSwitches => stats.voluntary_switches + stats.involuntary_switches,
+            TaskMetric::CacheMisses => stats.cache_misses.unwrap_or(0),
+        }
+    }
+}
+
 /// Main performance monitoring system
 #[derive(Debug)]
 pub struct PerformanceMonitor {
@@ -499,6 +614,9 @@ pub struct PerformanceMonitor {
     pub monitoring_active: AtomicUsize,
     pub sample_buffer: Vec<PerformanceSample>,
     pub alert_callbacks: Vec<AlertCallback>,
+    pub task_accounting: Mutex<TaskAccounting>,
+    /// Event groups multiplexed onto the PMU (hardware counters only)
+    pub perf_events: Option<Mutex<PerfEventManager>>,
 }
 
 /// Alert callback function
@@ -534,6 +652,12 @@ impl PerformanceMonitor {
             monitoring_active: AtomicUsize::new(0),
             sample_buffer: Vec::with_capacity(config.max_history_size),
             alert_callbacks: Vec::new(),
+            task_accounting: Mutex::new(TaskAccounting::default()),
+            perf_events: if config.enable_hardware_counters {
+                Some(Mutex::new(PerfEventManager::new(cpu_count.min(MAX_MONITORED_CPUS))))
+            } else {
+                None
+            },
         }
     }
 
@@ -577,6 +701,7 @@ impl PerformanceMonitor {
                 duration_seconds: 60,
                 enabled: true,
                 action: AlertAction::Log,
+                category: AlertCategory::Threshold,
             },
             PerformanceAlert {
                 alert_id: 2,
@@ -587,6 +712,7 @@ impl PerformanceMonitor {
                 duration_seconds: 10,
                 enabled: true,
                 action: AlertAction::ThrottleCPU,
+                category: AlertCategory::Threshold,
             },
             PerformanceAlert {
                 alert_id: 3,
@@ -597,6 +723,7 @@ impl PerformanceMonitor {
                 duration_seconds: 30,
                 enabled: true,
                 action: AlertAction::LogAndAlert,
+                category: AlertCategory::Threshold,
             },
         ]
     }
@@ -785,6 +912,29 @@ impl PerformanceMonitor {
         }
     }
 
+    /// Feed a CPU, memory or I/O metric sample to the anomaly detector.
+    ///
+    /// A deviation from the learned baseline is raised as an anomaly-category
+    /// alert through the registered callbacks. Requires `enable_prediction`.
+    pub fn observe_metric(&mut self, metric: PerfCounterType, value: f32, timestamp_secs: u64) -> Option<AnomalyEvent> {
+        let detector = &mut self.predictor.as_mut()?.anomaly_detector;
+        let anomaly = detector.observe(metric, value, timestamp_secs)?;
+
+        if self.config.alerting_enabled {
+            let alert_id = ANOMALY_ALERT_ID_BASE.wrapping_add(detector.anomaly_count() as u32);
+            let alert = anomaly.to_alert(alert_id);
+            log::warn!(
+                "Performance anomaly: {:?} = {:.1}, expected {:.1} ({:.0}% confidence)",
+                metric, value, anomaly.expected, anomaly.confidence * 100.0,
+            );
+            for callback in &self.alert_callbacks {
+                callback(alert.clone(), self.stats.clone());
+            }
+        }
+
+        Some(anomaly)
+    }
+
     /// Register alert callback
     pub fn register_alert_callback(&mut self, callback: AlertCallback) {
         self.alert_callbacks.push(callback);
@@ -817,11 +967,119 @@ impl PerformanceMonitor {
         Ok(())
     }
 
+    /// Enable or disable every counter bound to a CPU on hotplug
+    pub fn set_cpu_online(&mut self, cpu_id: CpuId, online: bool) {
+        for counter in self.counters.iter_mut().filter(|counter| counter.cpu_id == cpu_id) {
+            counter.enabled = online;
+        }
+    }
+
     /// Get current performance statistics
     pub fn get_current_stats(&self) -> PerformanceStats {
         self.stats.clone()
     }
 
+    /// Publish energy model estimates into the power statistics
+    pub fn update_energy_stats(&mut self, energy: &EnergyStats) {
+        let power = &mut self.stats.power_stats;
+        power.estimated_energy_joules = energy.total_energy_mj / 1000.0;
+        power.cpu_power_consumption_watts = energy.current_power_mw / 1000.0;
+        power.energy_aware_placements = energy.eas_placements;
+        power.little_core_placements = energy.little_core_placements;
+    }
+
+    /// Charge CPU time to a thread
+    pub fn account_runtime(&self, process_id: ProcessId, thread_id: ThreadId, user_ns: u64, sys_ns: u64) {
+        let mut accounting = self.task_accounting.lock();
+        let stats = accounting.entry(process_id, thread_id);
+        stats.user_time_ns += user_ns;
+        stats.sys_time_ns += sys_ns;
+    }
+
+    /// Record a context switch away from a thread
+    pub fn account_context_switch(&self, process_id: ProcessId, thread_id: ThreadId, voluntary: bool) {
+        let mut accounting = self.task_accounting.lock();
+        let stats = accounting.entry(process_id, thread_id);
+        if voluntary {
+            stats.voluntary_switches += 1;
+        } else {
+            stats.involuntary_switches += 1;
+        }
+    }
+
+    /// Record a CPU migration of a thread
+    pub fn account_migration(&self, process_id: ProcessId, thread_id: ThreadId) {
+        self.task_accounting.lock().entry(process_id, thread_id).migrations += 1;
+    }
+
+    /// Charge hardware counter deltas sampled while a thread was running.
+    ///
+    /// Ignored unless hardware counters are enabled.
+    pub fn account_counters(&self, process_id: ProcessId, thread_id: ThreadId, instructions: u64, cache_misses: u64) {
+        if !self.config.enable_hardware_counters {
+            return;
+        }
+        let mut accounting = self.task_accounting.lock();
+        let stats = accounting.entry(process_id, thread_id);
+        stats.instructions = Some(stats.instructions.unwrap_or(0) + instructions);
+        stats.cache_misses = Some(stats.cache_misses.unwrap_or(0) + cache_misses);
+    }
+
+    /// Fold an exited thread's totals into its process
+    pub fn retire_task(&self, thread_id: ThreadId) {
+        let mut accounting = self.task_accounting.lock();
+        if let Some(stats) = accounting.threads.remove(&thread_id) {
+            accounting.exited
+                .entry(stats.process_id)
+                .or_insert_with(|| TaskStats { process_id: stats.process_id, ..TaskStats::default() })
+                .merge(&stats);
+        }
+    }
+
+    /// Get accounting for a single thread
+    pub fn get_task_stats(&self, thread_id: ThreadId) -> Option<TaskStats> {
+        self.task_accounting.lock().threads.get(&thread_id).copied()
+    }
+
+    /// Get aggregated accounting for a process, including exited threads
+    pub fn get_process_task_stats(&self, process_id: ProcessId) -> Option<TaskStats> {
+        let accounting = self.task_accounting.lock();
+        let mut total = accounting.exited.get(&process_id).copied();
+
+        for stats in accounting.threads.values().filter(|s| s.process_id == process_id) {
+            total.get_or_insert_with(|| TaskStats { process_id, ..TaskStats::default() }).merge(stats);
+        }
+
+        total
+    }
+
+    /// Top-N threads ranked by `metric`
+    pub fn top_threads(&self, metric: TaskMetric, count: usize) -> Vec<TaskStats> {
+        let accounting = self.task_accounting.lock();
+        let mut threads: Vec<TaskStats> = accounting.threads.values().copied().collect();
+        threads.sort_by(|a, b| TaskAccounting::metric(b, metric).cmp(&TaskAccounting::metric(a, metric)));
+        threads.truncate(count);
+        threads
+    }
+
+    /// Top-N processes ranked by `metric`
+    pub fn top_processes(&self, metric: TaskMetric, count: usize) -> Vec<TaskStats> {
+        let accounting = self.task_accounting.lock();
+        let mut processes = accounting.exited.clone();
+
+        for stats in accounting.threads.values() {
+            processes
+                .entry(stats.process_id)
+                .or_insert_with(|| TaskStats { process_id: stats.process_id, ..TaskStats::default() })
+                .merge(stats);
+        }
+
+        let mut result: Vec<TaskStats> = processes.into_values().collect();
+        result.sort_by(|a, b| TaskAccounting::metric(b, metric).cmp(&TaskAccounting::metric(a, metric)));
+        result.truncate(count);
+        result
+    }
+
     /// Get performance history
     pub fn get_performance_history(&self, duration: Duration) -> Vec<PerformanceSample> {
         let cutoff_time = std::time::SystemTime::now()
@@ -1020,6 +1278,7 @@ impl PerformancePredictor {
             prediction_horizon: Duration::from_secs(300), // 5 minutes
             confidence_threshold: 0.8,
             accuracy_score: 0.0,
+            anomaly_detector: AnomalyDetector::new(AnomalyConfig::default()),
         }
     }
 }
@@ -1169,6 +1428,7 @@ mod tests {
             duration_seconds: 30,
             enabled: true,
             action: AlertAction::Log,
+            category: AlertCategory::Threshold,
         };
         
         assert_eq!(alert.metric_type, PerfCounterType::CpuUtilization);
@@ -1240,4 +1500,39 @@ mod tests {
         monitor.register_alert_callback(callback);
         assert_eq!(monitor.alert_callbacks.len(), 1);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_task_accounting_and_top_consumers() {
+        let monitor = PerformanceMonitor::new(PerformanceConfig::default(), 4);
+
+        monitor.account_runtime(1, 10, 5_000, 1_000);
+        monitor.account_runtime(1, 11, 2_000, 0);
+        monitor.account_runtime(2, 20, 9_000, 500);
+        monitor.account_context_switch(1, 10, true);
+        monitor.account_context_switch(1, 10, false);
+
+        let stats = monitor.get_task_stats(10).unwrap();
+        assert_eq!(stats.total_time_ns(), 6_000);
+        assert_eq!(stats.voluntary_switches, 1);
+        assert_eq!(stats.involuntary_switches, 1);
+
+        let top = monitor.top_threads(TaskMetric::CpuTime, 2);
+        assert_eq!(top.iter().map(|s| s.thread_id).collect::<Vec<_>>(), vec![20, 10]);
+
+        monitor.retire_task(11);
+        assert!(monitor.get_task_stats(11).is_none());
+        assert_eq!(monitor.get_process_task_stats(1).unwrap().total_time_ns(), 8_000);
+        assert_eq!(monitor.top_processes(TaskMetric::CpuTime, 1)[0].process_id, 2);
+    }
+
+    #[test]
+    fn test_task_counters_require_hardware_counters() {
+        let mut config = PerformanceConfig::default();
+        config.enable_hardware_counters = false;
+        let monitor = PerformanceMonitor::new(config, 1);
+
+        monitor.account_runtime(1, 1, 100, 0);
+        monitor.account_counters(1, 1, 1_000, 10);
+        assert_eq!(monitor.get_task_stats(1).unwrap().cache_mpki(), None);
+    }
+}
@@ -3,6 +3,7 @@
 //! This module provides process control blocks (PCBs), process creation,
 //! termination, and management functionality for the MultiOS kernel.
 
+use alloc::collections::BTreeMap;
 use alloc::vec::Vec;
 use spin::Mutex;
 use bitflags::bitflags;
@@ -11,10 +12,26 @@ use core::sync::atomic::{AtomicUsize, Ordering};
 
 use crate::thread::{ThreadHandle, ThreadId, ThreadState};
 use crate::scheduler_algo::SchedulerError;
+use crate::task_group::{with_task_groups, TaskGroupId, ROOT_TASK_GROUP};
+use crate::fork::{
+    AddressSpaceId, AddressSpaceOps, FileDescriptorTable, SignalDispositions,
+    SpawnAttributes, SpawnFileAction,
+};
 
 /// Process ID type
 pub type ProcessId = usize;
 
+/// Terminal device identifier
+pub type TtyId = usize;
+
+/// PID of the init process that adopts orphaned children
+pub const INIT_PROCESS_ID: ProcessId = 1;
+
+/// Hangup detected on controlling terminal or death of controlling process
+pub const SIGHUP: u32 = 1;
+/// Continue a stopped process
+pub const SIGCONT: u32 = 18;
+
 /// Process priority
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 #[repr(u8)]
@@ -79,6 +96,47 @@ pub struct ProcessControlBlock {
     pub memory_stats: ProcessMemoryStats,
     /// Exit status (for terminated processes)
     pub exit_status: Option<i32>,
+    /// CPU bandwidth task group
+    pub task_group: TaskGroupId,
+    /// Process group ID
+    pub pgid: ProcessId,
+    /// Session ID
+    pub sid: ProcessId,
+    /// Controlling terminal (shared by the whole session)
+    pub controlling_tty: Option<TtyId>,
+    /// Pending signal bitmask (bit N = signal N)
+    pub pending_signals: u64,
+    /// Memory manager address space (None for kernel-only processes)
+    pub address_space: Option<AddressSpaceId>,
+    /// Open file descriptors
+    pub fd_table: FileDescriptorTable,
+    /// Signal dispositions and blocked mask
+    pub signals: SignalDispositions,
+    /// Parent suspended by `vfork` until this process execs or exits
+    pub vfork_parent: Option<ProcessId>,
+}
+
+impl ProcessControlBlock {
+    /// Whether this process created its session
+    pub fn is_session_leader(&self) -> bool {
+        self.sid == self.process_id
+    }
+
+    /// Whether this process leads its process group
+    pub fn is_group_leader(&self) -> bool {
+        self.pgid == self.process_id
+    }
+
+    /// Mark a signal as pending
+    pub fn post_signal(&mut self, signal: u32) {
+        if signal > 0 && signal < 64 {
+            self.pending_signals |= 1 << signal;
+        }
+        if signal == SIGCONT && self.state == ProcessState::Stopped {
+            self.state = ProcessState::Running;
+            self.flags.remove(ProcessFlags::SUSPENDED);
+        }
+    }
 }
 
 /// Memory statistics for a process
@@ -126,6 +184,8 @@ pub enum ProcessError {
     AccessDenied,
     ProcessInInvalidState,
     OutOfMemory,
+    InvalidParameter,
+    GroupNotFound,
 }
 
 /// Process Manager
@@ -138,6 +198,8 @@ pub struct ProcessManager {
     processes: spin::Mutex<alloc::vec::Vec<Option<ProcessControlBlock>>>,
     /// Process by parent-child relationships
     process_tree: spin::Mutex<alloc::vec::Vec<Vec<ProcessId>>>,
+    /// Foreground process group per controlling terminal
+    foreground_groups: spin::Mutex<BTreeMap<TtyId, ProcessId>>,
 }
 
 impl ProcessManager {
@@ -147,6 +209,7 @@ impl ProcessManager {
             next_process_id: AtomicUsize::new(1),
             processes: spin::Mutex::new(alloc::vec::Vec::new()),
             process_tree: spin::Mutex::new(alloc::vec::Vec::new()),
+            foreground_groups: spin::Mutex::new(BTreeMap::new()),
         }
     }
 
@@ -183,6 +246,16 @@ impl ProcessManager {
                 stack_size: 4096, // Default stack size
             },
             exit_status: None,
+            task_group: ROOT_TASK_GROUP,
+            // A parentless process starts its own session and group
+            pgid: process_id,
+            sid: process_id,
+            controlling_tty: None,
+            pending_signals: 0,
+            address_space: None,
+            fd_table: FileDescriptorTable::new(),
+            signals: SignalDispositions::default(),
+            vfork_parent: None,
         };
 
         // Store the process
@@ -208,17 +281,56 @@ impl ProcessManager {
         }
     }
 
-    /// Terminate a process
+    /// Create a process as a child of `parent_id`, inheriting its process
+    /// group, session and controlling terminal
+    pub fn create_child_process(&self, parent_id: ProcessId, params: ProcessCreateParams) -> ProcessResult<ProcessId> {
+        let (pgid, sid, tty) = {
+            let processes = self.processes.lock();
+            match processes.get(parent_id) {
+                Some(Some(parent)) => (parent.pgid, parent.sid, parent.controlling_tty),
+                _ => return Err(ProcessError::ProcessNotFound),
+            }
+        };
+
+        let child_id = self.create_process(params)?;
+
+        let mut processes = self.processes.lock();
+        let mut process_tree = self.process_tree.lock();
+        if let Some(Some(child)) = processes.get_mut(child_id) {
+            child.parent_id = Some(parent_id);
+            child.pgid = pgid;
+            child.sid = sid;
+            child.controlling_tty = tty;
+        }
+        process_tree[parent_id].push(child_id);
+
+        Ok(child_id)
+    }
+
+    /// Terminate a process.
+    ///
+    /// A parent suspended by `vfork` is resumed. Children are reparented to init. If the process was a session leader
+    /// with a controlling terminal, the foreground process group receives
+    /// SIGHUP and SIGCONT and the terminal is released. Process groups that
+    /// become orphaned while containing stopped members receive SIGHUP then
+    /// SIGCONT, as POSIX requires.
     pub fn terminate_process(&self, process_id: ProcessId, exit_status: i32) -> ProcessResult<()> {
         let mut processes = self.processes.lock();
+        let mut process_tree = self.process_tree.lock();
         
         if process_id >= processes.len() || processes[process_id].is_none() {
             return Err(ProcessError::ProcessNotFound);
         }
 
+        // Groups whose orphan status may change: our own and our children's
+        let mut affected_groups = Vec::new();
+
+        let mut vfork_parent = None;
         if let Some(ref mut pcb) = processes[process_id] {
             pcb.state = ProcessState::Terminated;
             pcb.exit_status = Some(exit_status);
+            affected_groups.push(pcb.pgid);
+            vfork_parent = pcb.vfork_parent.take();
 
             // Terminate all threads in this process
             for thread_handle in &pcb.threads {
@@ -227,9 +339,398 @@ impl ProcessManager {
             }
         }
 
+        if let Some(parent_id) = vfork_parent {
+            Self::release_vfork_parent(&mut processes, parent_id);
+        }
+
+        // Session leader exit hangs up the controlling terminal
+        let (is_leader, sid, tty) = {
+            let pcb = processes[process_id].as_ref().unwrap();
+            (pcb.is_session_leader(), pcb.sid, pcb.controlling_tty)
+        };
+        if is_leader {
+            if let Some(tty) = tty {
+                if let Some(foreground) = self.foreground_groups.lock().remove(&tty) {
+                    Self::signal_group(&mut processes, foreground, SIGHUP);
+                    Self::signal_group(&mut processes, foreground, SIGCONT);
+                }
+                for pcb in processes.iter_mut().flatten().filter(|pcb| pcb.sid == sid) {
+                    pcb.controlling_tty = None;
+                }
+            }
+        }
+
+        // Reparent children to init
+        let children = core::mem::take(&mut process_tree[process_id]);
+        for &child_id in &children {
+            if let Some(Some(child)) = processes.get_mut(child_id) {
+                child.parent_id = Some(INIT_PROCESS_ID);
+                affected_groups.push(child.pgid);
+            }
+        }
+        if let Some(init_children) = process_tree.get_mut(INIT_PROCESS_ID) {
+            if process_id != INIT_PROCESS_ID {
+                init_children.extend(children);
+            }
+        }
+
+        affected_groups.sort_unstable();
+        affected_groups.dedup();
+        for pgid in affected_groups {
+            let has_stopped = processes.iter().flatten()
+                .any(|pcb| pcb.pgid == pgid && pcb.state == ProcessState::Stopped);
+            if has_stopped && Self::group_is_orphaned(&processes, pgid) {
+                Self::signal_group(&mut processes, pgid, SIGHUP);
+                Self::signal_group(&mut processes, pgid, SIGCONT);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Create a new session with the caller as leader (POSIX `setsid`)
+    pub fn setsid(&self, process_id: ProcessId) -> ProcessResult<ProcessId> {
+        let mut processes = self.processes.lock();
+
+        // A process group leader cannot start a new session
+        let group_in_use = processes.iter().flatten()
+            .any(|pcb| pcb.pgid == process_id && pcb.process_id != process_id);
+        let pcb = match processes.get_mut(process_id) {
+            Some(Some(pcb)) => pcb,
+            _ => return Err(ProcessError::ProcessNotFound),
+        };
+        if pcb.is_group_leader() && (pcb.parent_id.is_some() || group_in_use) {
+            return Err(ProcessError::AccessDenied);
+        }
+
+        pcb.sid = process_id;
+        pcb.pgid = process_id;
+        pcb.controlling_tty = None;
+        Ok(process_id)
+    }
+
+    /// Move a process into a process group (POSIX `setpgid`).
+    ///
+    /// A `pgid` of 0 uses the process ID. The target group must be in the
+    /// same session, and session leaders cannot change group.
+    pub fn setpgid(&self, process_id: ProcessId, pgid: ProcessId) -> ProcessResult<()> {
+        let mut processes = self.processes.lock();
+        let pgid = if pgid == 0 { process_id } else { pgid };
+
+        let sid = match processes.get(process_id) {
+            Some(Some(pcb)) if pcb.is_session_leader() => return Err(ProcessError::AccessDenied),
+            Some(Some(pcb)) => pcb.sid,
+            _ => return Err(ProcessError::ProcessNotFound),
+        };
+
+        if pgid != process_id {
+            let group_in_session = processes.iter().flatten()
+                .any(|pcb| pcb.pgid == pgid && pcb.sid == sid);
+            if !group_in_session {
+                return Err(ProcessError::AccessDenied);
+            }
+        }
+
+        if let Some(Some(pcb)) = processes.get_mut(process_id) {
+            pcb.pgid = pgid;
+        }
+        Ok(())
+    }
+
+    /// Get the process group of a process
+    pub fn getpgid(&self, process_id: ProcessId) -> ProcessResult<ProcessId> {
+        match self.processes.lock().get(process_id) {
+            Some(Some(pcb)) => Ok(pcb.pgid),
+            _ => Err(ProcessError::ProcessNotFound),
+        }
+    }
+
+    /// Get the session of a process
+    pub fn getsid(&self, process_id: ProcessId) -> ProcessResult<ProcessId> {
+        match self.processes.lock().get(process_id) {
+            Some(Some(pcb)) => Ok(pcb.sid),
+            _ => Err(ProcessError::ProcessNotFound),
+        }
+    }
+
+    /// Make `tty` the controlling terminal of a session leader's session
+    pub fn set_controlling_terminal(&self, process_id: ProcessId, tty: TtyId) -> ProcessResult<()> {
+        let mut processes = self.processes.lock();
+
+        let (sid, pgid) = match processes.get(process_id) {
+            Some(Some(pcb)) if !pcb.is_session_leader() => return Err(ProcessError::AccessDenied),
+            Some(Some(pcb)) if pcb.controlling_tty.is_some() => return Err(ProcessError::ProcessInInvalidState),
+            Some(Some(pcb)) => (pcb.sid, pcb.pgid),
+            _ => return Err(ProcessError::ProcessNotFound),
+        };
+
+        // A terminal controls at most one session
+        if processes.iter().flatten().any(|pcb| pcb.controlling_tty == Some(tty) && pcb.sid != sid) {
+            return Err(ProcessError::AccessDenied);
+        }
+
+        for pcb in processes.iter_mut().flatten().filter(|pcb| pcb.sid == sid) {
+            pcb.controlling_tty = Some(tty);
+        }
+        self.foreground_groups.lock().insert(tty, pgid);
+        Ok(())
+    }
+
+    /// Set the foreground process group of a terminal (POSIX `tcsetpgrp`)
+    pub fn set_foreground_group(&self, tty: TtyId, pgid: ProcessId) -> ProcessResult<()> {
+        let processes = self.processes.lock();
+        let in_session = processes.iter().flatten()
+            .any(|pcb| pcb.pgid == pgid && pcb.controlling_tty == Some(tty));
+        if !in_session {
+            return Err(ProcessError::AccessDenied);
+        }
+
+        self.foreground_groups.lock().insert(tty, pgid);
+        Ok(())
+    }
+
+    /// Get the foreground process group of a terminal
+    pub fn get_foreground_group(&self, tty: TtyId) -> Option<ProcessId> {
+        self.foreground_groups.lock().get(&tty).copied()
+    }
+
+    /// Check whether a process group is orphaned: no member has a live parent
+    /// in a different group of the same session
+    pub fn is_orphaned_group(&self, pgid: ProcessId) -> bool {
+        Self::group_is_orphaned(&self.processes.lock(), pgid)
+    }
+
+    /// Post a signal to every member of a process group
+    pub fn signal_process_group(&self, pgid: ProcessId, signal: u32) -> ProcessResult<usize> {
+        let mut processes = self.processes.lock();
+        match Self::signal_group(&mut processes, pgid, signal) {
+            0 => Err(ProcessError::ProcessNotFound),
+            delivered => Ok(delivered),
+        }
+    }
+
+    /// Take and clear the pending signals of a process
+    pub fn take_pending_signals(&self, process_id: ProcessId) -> ProcessResult<u64> {
+        match self.processes.lock().get_mut(process_id) {
+            Some(Some(pcb)) => Ok(core::mem::take(&mut pcb.pending_signals)),
+            _ => Err(ProcessError::ProcessNotFound),
+        }
+    }
+
+    /// Duplicate a process (POSIX `fork`).
+    ///
+    /// The child gets a copy of the parent's PCB, shares its open file
+    /// descriptions, inherits its signal dispositions and mask, and receives
+    /// a copy-on-write clone of its address space.
+    pub fn fork_process(&self, parent_id: ProcessId, mm: &mut dyn AddressSpaceOps) -> ProcessResult<ProcessId> {
+        let parent = self.snapshot(parent_id)?;
+        let space = match parent.address_space {
+            Some(space) => Some(mm.fork_space(space)?),
+            None => None,
+        };
+
+        self.duplicate(&parent, space).map_err(|error| {
+            if let Some(space) = space {
+                mm.destroy_space(space);
+            }
+            error
+        })
+    }
+
+    /// Fast-path duplicate (POSIX `vfork`).
+    ///
+    /// The child borrows the parent's address space without copying page
+    /// tables; the parent is suspended until the child calls
+    /// [`exec_process`](Self::exec_process) or terminates.
+    pub fn vfork_process(&self, parent_id: ProcessId) -> ProcessResult<ProcessId> {
+        let parent = self.snapshot(parent_id)?;
+        let child_id = self.duplicate(&parent, parent.address_space)?;
+
+        let mut processes = self.processes.lock();
+        if let Some(Some(child)) = processes.get_mut(child_id) {
+            child.vfork_parent = Some(parent_id);
+        }
+        if let Some(Some(parent)) = processes.get_mut(parent_id) {
+            parent.state = ProcessState::Waiting;
+            parent.flags.insert(ProcessFlags::SUSPENDED);
+        }
+        Ok(child_id)
+    }
+
+    /// Replace a process image (POSIX `execve`).
+    ///
+    /// A fresh address space is installed, close-on-exec descriptors are
+    /// closed and caught signals revert to their default action. A borrowed
+    /// `vfork` address space is handed back and the parent resumed; an owned
+    /// one is destroyed.
+    pub fn exec_process(&self, process_id: ProcessId, mm: &mut dyn AddressSpaceOps) -> ProcessResult<AddressSpaceId> {
+        if !matches!(self.processes.lock().get(process_id), Some(Some(_))) {
+            return Err(ProcessError::ProcessNotFound);
+        }
+        let space = mm.create_space()?;
+
+        let (old_space, vfork_parent) = {
+            let mut processes = self.processes.lock();
+            let pcb = match processes.get_mut(process_id) {
+                Some(Some(pcb)) => pcb,
+                _ => {
+                    mm.destroy_space(space);
+                    return Err(ProcessError::ProcessNotFound);
+                }
+            };
+            let old_space = pcb.address_space.replace(space);
+            let vfork_parent = pcb.vfork_parent.take();
+            pcb.fd_table.close_on_exec();
+            pcb.signals.reset_on_exec();
+
+            if let Some(parent_id) = vfork_parent {
+                Self::release_vfork_parent(&mut processes, parent_id);
+            }
+            (old_space, vfork_parent)
+        };
+
+        if let (Some(old_space), None) = (old_space, vfork_parent) {
+            mm.destroy_space(old_space);
+        }
+        Ok(space)
+    }
+
+    /// Create a child running a new image in one step (POSIX `posix_spawn`).
+    ///
+    /// Equivalent to `vfork` + file actions + attributes + `execve`, but the
+    /// parent is never suspended and no page tables are copied.
+    pub fn posix_spawn(
+        &self,
+        parent_id: ProcessId,
+        name: &[u8],
+        file_actions: &[SpawnFileAction],
+        attrs: &SpawnAttributes,
+        mm: &mut dyn AddressSpaceOps,
+    ) -> ProcessResult<ProcessId> {
+        let mut parent = self.snapshot(parent_id)?;
+        parent.name = name.to_vec();
+        let child_id = self.duplicate(&parent, None)?;
+
+        if let Err(error) = self.apply_spawn_actions(child_id, file_actions, attrs) {
+            self.discard_child(parent_id, child_id);
+            return Err(error);
+        }
+
+        match self.exec_process(child_id, mm) {
+            Ok(_) => Ok(child_id),
+            Err(error) => {
+                self.discard_child(parent_id, child_id);
+                Err(error)
+            }
+        }
+    }
+
+    fn apply_spawn_actions(&self, child_id: ProcessId, file_actions: &[SpawnFileAction], attrs: &SpawnAttributes) -> ProcessResult<()> {
+        {
+            let mut processes = self.processes.lock();
+            let child = match processes.get_mut(child_id) {
+                Some(Some(child)) => child,
+                _ => return Err(ProcessError::ProcessNotFound),
+            };
+            for action in file_actions {
+                match *action {
+                    SpawnFileAction::Close(fd) => {
+                        child.fd_table.close(fd);
+                    }
+                    SpawnFileAction::Dup2 { old_fd, new_fd } => child.fd_table.dup2(old_fd, new_fd)?,
+                }
+            }
+            child.signals.reset_to_default(attrs.sigdefault);
+            if let Some(mask) = attrs.sigmask {
+                child.signals.blocked = mask;
+            }
+        }
+
+        if attrs.setsid {
+            self.setsid(child_id)?;
+        }
+        if let Some(pgid) = attrs.pgroup {
+            self.setpgid(child_id, pgid)?;
+        }
         Ok(())
     }
 
+    /// Copy of a live process's PCB
+    fn snapshot(&self, process_id: ProcessId) -> ProcessResult<ProcessControlBlock> {
+        match self.processes.lock().get(process_id) {
+            Some(Some(pcb)) if pcb.state != ProcessState::Terminated => Ok(pcb.clone()),
+            Some(Some(_)) => Err(ProcessError::ProcessInInvalidState),
+            _ => Err(ProcessError::ProcessNotFound),
+        }
+    }
+
+    /// Create a child from a parent snapshot with the given address space
+    fn duplicate(&self, parent: &ProcessControlBlock, space: Option<AddressSpaceId>) -> ProcessResult<ProcessId> {
+        let mut flags = parent.flags;
+        flags.remove(ProcessFlags::SUSPENDED);
+        let child_id = self.create_child_process(parent.process_id, ProcessCreateParams {
+            name: parent.name.clone(),
+            priority: parent.priority,
+            flags,
+            entry_point: None,
+            thread_params: None,
+        })?;
+
+        if let Some(Some(child)) = self.processes.lock().get_mut(child_id) {
+            child.task_group = parent.task_group;
+            child.memory_stats = parent.memory_stats;
+            child.fd_table = parent.fd_table.clone();
+            child.signals = parent.signals.clone();
+            child.address_space = space;
+        }
+        Ok(child_id)
+    }
+
+    /// Remove a child that failed to start
+    fn discard_child(&self, parent_id: ProcessId, child_id: ProcessId) {
+        let mut processes = self.processes.lock();
+        let mut process_tree = self.process_tree.lock();
+        if let Some(slot) = processes.get_mut(child_id) {
+            *slot = None;
+        }
+        if let Some(children) = process_tree.get_mut(parent_id) {
+            children.retain(|&id| id != child_id);
+        }
+    }
+
+    fn release_vfork_parent(processes: &mut [Option<ProcessControlBlock>], parent_id: ProcessId) {
+        if let Some(Some(parent)) = processes.get_mut(parent_id) {
+            if parent.state == ProcessState::Waiting {
+                parent.state = ProcessState::Running;
+            }
+            parent.flags.remove(ProcessFlags::SUSPENDED);
+        }
+    }
+
+    fn group_is_orphaned(processes: &[Option<ProcessControlBlock>], pgid: ProcessId) -> bool {
+        let live = |pcb: &&ProcessControlBlock| pcb.state != ProcessState::Terminated;
+
+        !processes.iter().flatten().filter(live)
+            .filter(|pcb| pcb.pgid == pgid)
+            .any(|member| {
+                member.parent_id
+                    .and_then(|parent_id| processes.get(parent_id).and_then(|parent| parent.as_ref()))
+                    .filter(live)
+                    .map_or(false, |parent| parent.pgid != pgid && parent.sid == member.sid)
+            })
+    }
+
+    fn signal_group(processes: &mut [Option<ProcessControlBlock>], pgid: ProcessId, signal: u32) -> usize {
+        let mut delivered = 0;
+        for pcb in processes.iter_mut().flatten() {
+            if pcb.pgid == pgid && pcb.state != ProcessState::Terminated {
+                pcb.post_signal(signal);
+                delivered += 1;
+            }
+        }
+        delivered
+    }
+
     /// Get all process IDs
     pub fn get_all_processes(&self) -> Vec<ProcessId> {
         let processes = self.processes.lock();
@@ -324,6 +825,35 @@ impl ProcessManager {
         Ok(())
     }
 
+    /// Move a process and all of its threads into a CPU bandwidth task group
+    ///
+    /// Threads created later join the group when they are added to the
+    /// scheduler.
+    pub fn set_task_group(&self, process_id: ProcessId, group_id: TaskGroupId) -> ProcessResult<()> {
+        let mut processes = self.processes.lock();
+
+        let pcb = match processes.get_mut(process_id) {
+            Some(Some(pcb)) => pcb,
+            _ => return Err(ProcessError::ProcessNotFound),
+        };
+
+        let threads = crate::thread::THREAD_MANAGER.get_live_threads_by_process(process_id);
+        with_task_groups(|groups| groups.attach_threads(group_id, &threads))?;
+        pcb.task_group = group_id;
+
+        Ok(())
+    }
+
+    /// Get the task group a process belongs to
+    pub fn get_task_group(&self, process_id: ProcessId) -> ProcessResult<TaskGroupId> {
+        let processes = self.processes.lock();
+        
+        match processes.get(process_id) {
+            Some(Some(pcb)) => Ok(pcb.task_group),
+            _ => Err(ProcessError::ProcessNotFound),
+        }
+    }
+
     /// Resume a process
     pub fn resume_process(&self, process_id: ProcessId) -> ProcessResult<()> {
         let mut processes = self.processes.lock();
@@ -400,4 +930,154 @@ mod tests {
         let state = ProcessState::Running;
         assert_ne!(state, ProcessState::Terminated);
     }
-}
\ No newline at end of file
+
+    fn child_params(name: &[u8]) -> ProcessCreateParams {
+        ProcessCreateParams {
+            name: name.to_vec(),
+            priority: ProcessPriority::Normal,
+            flags: ProcessFlags::empty(),
+            entry_point: None,
+            thread_params: None,
+        }
+    }
+
+    #[test]
+    fn test_sessions_and_groups() {
+        let manager = ProcessManager::new();
+        let init = manager.create_process(child_params(b"init")).unwrap();
+        let shell = manager.create_child_process(init, child_params(b"sh")).unwrap();
+        assert_eq!(manager.getsid(shell), Ok(init));
+
+        assert_eq!(manager.setsid(shell), Ok(shell));
+        let job = manager.create_child_process(shell, child_params(b"job")).unwrap();
+        manager.setpgid(job, 0).unwrap();
+        assert_eq!(manager.getpgid(job), Ok(job));
+        assert_eq!(manager.getsid(job), Ok(shell));
+
+        // Session leaders cannot change group; groups must exist in the session
+        assert_eq!(manager.setpgid(shell, job), Err(ProcessError::AccessDenied));
+        assert_eq!(manager.setpgid(job, init), Err(ProcessError::AccessDenied));
+    }
+
+    #[test]
+    fn test_session_leader_exit_hangs_up_terminal() {
+        let manager = ProcessManager::new();
+        let init = manager.create_process(child_params(b"init")).unwrap();
+        let shell = manager.create_child_process(init, child_params(b"sh")).unwrap();
+        manager.setsid(shell).unwrap();
+        manager.set_controlling_terminal(shell, 7).unwrap();
+
+        let job = manager.create_child_process(shell, child_params(b"job")).unwrap();
+        manager.setpgid(job, 0).unwrap();
+        manager.set_foreground_group(7, job).unwrap();
+        assert!(!manager.is_orphaned_group(job));
+
+        manager.terminate_process(shell, 0).unwrap();
+        let pending = manager.take_pending_signals(job).unwrap();
+        assert_ne!(pending & (1 << SIGHUP), 0);
+        assert_ne!(pending & (1 << SIGCONT), 0);
+        assert_eq!(manager.get_foreground_group(7), None);
+        assert!(manager.is_orphaned_group(job));
+    }
+
+    #[derive(Default)]
+    struct TestSpaces {
+        next: AddressSpaceId,
+        forked: Vec<AddressSpaceId>,
+        destroyed: Vec<AddressSpaceId>,
+    }
+
+    impl AddressSpaceOps for TestSpaces {
+        fn create_space(&mut self) -> ProcessResult<AddressSpaceId> {
+            self.next += 1;
+            Ok(self.next)
+        }
+
+        fn fork_space(&mut self, parent: AddressSpaceId) -> ProcessResult<AddressSpaceId> {
+            self.forked.push(parent);
+            self.create_space()
+        }
+
+        fn destroy_space(&mut self, space: AddressSpaceId) {
+            self.destroyed.push(space);
+        }
+    }
+
+    #[test]
+    fn test_fork_copies_state_and_exec_resets_it() {
+        use crate::fork::{FileDescriptor, SignalDisposition, FD_CLOEXEC};
+
+        let manager = ProcessManager::new();
+        let mut mm = TestSpaces::default();
+        let init = manager.create_process(child_params(b"init")).unwrap();
+        let parent = manager.create_child_process(init, child_params(b"sh")).unwrap();
+        let parent_space = manager.exec_process(parent, &mut mm).unwrap();
+        {
+            let mut processes = manager.processes.lock();
+            let pcb = processes[parent].as_mut().unwrap();
+            pcb.fd_table.insert(1, FileDescriptor { handle: 7, fd_flags: 0 });
+            pcb.fd_table.insert(4, FileDescriptor { handle: 8, fd_flags: FD_CLOEXEC });
+            pcb.signals.set(2, SignalDisposition::Handler(0x1000)).unwrap();
+        }
+
+        let child = manager.fork_process(parent, &mut mm).unwrap();
+        assert_eq!(mm.forked, vec![parent_space]);
+        let pcb = manager.get_process(child).unwrap().lock().clone();
+        assert_eq!(pcb.parent_id, Some(parent));
+        assert_eq!(pcb.fd_table.fds(), vec![1, 4]);
+        assert_eq!(pcb.signals.get(2), SignalDisposition::Handler(0x1000));
+        assert_ne!(pcb.address_space, Some(parent_space));
+
+        let child_space = pcb.address_space.unwrap();
+        manager.exec_process(child, &mut mm).unwrap();
+        assert_eq!(mm.destroyed, vec![child_space]);
+        let pcb = manager.get_process(child).unwrap().lock().clone();
+        assert_eq!(pcb.fd_table.fds(), vec![1]);
+        assert_eq!(pcb.signals.get(2), SignalDisposition::Default);
+    }
+
+    #[test]
+    fn test_vfork_suspends_parent_until_exec() {
+        let manager = ProcessManager::new();
+        let mut mm = TestSpaces::default();
+        let init = manager.create_process(child_params(b"init")).unwrap();
+        let parent = manager.create_child_process(init, child_params(b"sh")).unwrap();
+        let space = manager.exec_process(parent, &mut mm).unwrap();
+
+        let child = manager.vfork_process(parent).unwrap();
+        assert_eq!(manager.get_process(child).unwrap().lock().address_space, Some(space));
+        assert_eq!(manager.get_process(parent).unwrap().lock().state, ProcessState::Waiting);
+
+        // The borrowed space belongs to the parent and must survive the exec
+        manager.exec_process(child, &mut mm).unwrap();
+        assert!(mm.destroyed.is_empty());
+        assert_eq!(manager.get_process(parent).unwrap().lock().state, ProcessState::Running);
+    }
+
+    #[test]
+    fn test_posix_spawn_applies_actions() {
+        use crate::fork::FileDescriptor;
+
+        let manager = ProcessManager::new();
+        let mut mm = TestSpaces::default();
+        let init = manager.create_process(child_params(b"init")).unwrap();
+        let shell = manager.create_child_process(init, child_params(b"sh")).unwrap();
+        manager.processes.lock()[shell].as_mut().unwrap()
+            .fd_table.insert(3, FileDescriptor { handle: 9, fd_flags: 0 });
+
+        let actions = [SpawnFileAction::Dup2 { old_fd: 3, new_fd: 0 }, SpawnFileAction::Close(3)];
+        let attrs = SpawnAttributes { pgroup: Some(0), ..Default::default() };
+        let child = manager.posix_spawn(shell, b"ls", &actions, &attrs, &mut mm).unwrap();
+
+        let pcb = manager.get_process(child).unwrap().lock().clone();
+        assert_eq!(pcb.name, b"ls".to_vec());
+        assert_eq!(pcb.fd_table.fds(), vec![0]);
+        assert_eq!(pcb.pgid, child);
+        assert!(mm.forked.is_empty());
+
+        // A failing file action leaves no child behind
+        let bad = [SpawnFileAction::Dup2 { old_fd: 42, new_fd: 1 }];
+        assert_eq!(manager.posix_spawn(shell, b"ls", &bad, &attrs, &mut mm), Err(ProcessError::InvalidParameter));
+        assert_eq!(manager.get_all_processes().len(), 3);
+    }
+}
@@ -0,0 +1,332 @@
+//! Wakeup-to-Run Latency Instrumentation for MultiOS
+//!
+//! This module measures the time between a thread becoming runnable and it
+//! being switched in:
+//! - One log2 histogram per priority class, updated lock-free
+//! - Worst-case latency and the thread that suffered it
+//! - Percentile and deadline-miss queries for tuning `rt_deadline_us`
+//! - Snapshot and reset API for measurement windows
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use spin::Mutex;
+
+use crate::Priority;
+use crate::thread::ThreadId;
+
+/// Number of histogram buckets; bucket `i > 0` holds latencies in
+/// `[2^(i-1), 2^i)` microseconds and the last bucket everything above
+pub const LATENCY_BUCKETS: usize = 24;
+
+/// Priority classes tracked, in histogram order
+pub const PRIORITY_CLASSES: [Priority; 5] = [
+    Priority::Idle,
+    Priority::Low,
+    Priority::Normal,
+    Priority::High,
+    Priority::Critical,
+];
+
+/// Nanoseconds per microsecond
+const NS_PER_US: u64 = 1_000;
+
+fn class_index(priority: Priority) -> usize {
+    match priority {
+        Priority::Idle => 0,
+        Priority::Low => 1,
+        Priority::Normal => 2,
+        Priority::High => 3,
+        Priority::Critical => 4,
+    }
+}
+
+fn bucket_index(latency_ns: u64) -> usize {
+    let latency_us = latency_ns / NS_PER_US;
+    let bucket = (u64::BITS - latency_us.leading_zeros()) as usize;
+    bucket.min(LATENCY_BUCKETS - 1)
+}
+
+/// Upper bound (exclusive, nanoseconds) of a histogram bucket
+pub fn bucket_upper_ns(bucket: usize) -> u64 {
+    if bucket >= LATENCY_BUCKETS - 1 {
+        u64::MAX
+    } else {
+        (1u64 << bucket) * NS_PER_US
+    }
+}
+
+/// Live histogram for one priority class
+#[derive(Debug)]
+struct LatencyHistogram {
+    buckets: [AtomicU64; LATENCY_BUCKETS],
+    count: AtomicU64,
+    total_ns: AtomicU64,
+    max_ns: AtomicU64,
+    max_thread: AtomicUsize,
+}
+
+impl LatencyHistogram {
+    fn new() -> Self {
+        Self {
+            buckets: core::array::from_fn(|_| AtomicU64::new(0)),
+            count: AtomicU64::new(0),
+            total_ns: AtomicU64::new(0),
+            max_ns: AtomicU64::new(0),
+            max_thread: AtomicUsize::new(0),
+        }
+    }
+
+    fn record(&self, thread_id: ThreadId, latency_ns: u64) {
+        self.buckets[bucket_index(latency_ns)].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.total_ns.fetch_add(latency_ns, Ordering::Relaxed);
+        if self.max_ns.fetch_max(latency_ns, Ordering::Relaxed) < latency_ns {
+            self.max_thread.store(thread_id, Ordering::Relaxed);
+        }
+    }
+
+    fn snapshot(&self, priority: Priority) -> LatencyHistogramSnapshot {
+        let count = self.count.load(Ordering::Relaxed);
+        LatencyHistogramSnapshot {
+            priority,
+            buckets: self.buckets.iter().map(|bucket| bucket.load(Ordering::Relaxed)).collect(),
+            count,
+            total_ns: self.total_ns.load(Ordering::Relaxed),
+            max_ns: self.max_ns.load(Ordering::Relaxed),
+            max_thread: if count == 0 { None } else { Some(self.max_thread.load(Ordering::Relaxed)) },
+        }
+    }
+
+    fn reset(&self) {
+        for bucket in &self.buckets {
+            bucket.store(0, Ordering::Relaxed);
+        }
+        self.count.store(0, Ordering::Relaxed);
+        self.total_ns.store(0, Ordering::Relaxed);
+        self.max_ns.store(0, Ordering::Relaxed);
+        self.max_thread.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Latency histogram snapshot for one priority class
+#[derive(Debug, Clone)]
+pub struct LatencyHistogramSnapshot {
+    pub priority: Priority,
+    /// Sample counts per bucket; see `bucket_upper_ns`
+    pub buckets: Vec<u64>,
+    pub count: u64,
+    pub total_ns: u64,
+    /// Worst-case latency observed
+    pub max_ns: u64,
+    /// Thread that observed the worst-case latency
+    pub max_thread: Option<ThreadId>,
+}
+
+impl LatencyHistogramSnapshot {
+    /// Mean latency in nanoseconds
+    pub fn mean_ns(&self) -> u64 {
+        if self.count == 0 { 0 } else { self.total_ns / self.count }
+    }
+
+    /// Upper bound of the bucket holding the `percentile` (0-100) sample,
+    /// capped at the observed maximum
+    pub fn percentile_ns(&self, percentile: f32) -> u64 {
+        if self.count == 0 {
+            return 0;
+        }
+        let rank = ((self.count as f32 * percentile.clamp(0.0, 100.0) / 100.0) as u64).max(1);
+        let mut seen = 0;
+        for (bucket, &samples) in self.buckets.iter().enumerate() {
+            seen += samples;
+            if seen >= rank {
+                return bucket_upper_ns(bucket).min(self.max_ns);
+            }
+        }
+        self.max_ns
+    }
+
+    /// Samples that may have exceeded `deadline_ns`, counted per bucket so
+    /// the result is an upper bound
+    pub fn deadline_misses(&self, deadline_ns: u64) -> u64 {
+        self.buckets.iter().enumerate()
+            .filter(|&(bucket, _)| bucket_upper_ns(bucket) > deadline_ns)
+            .map(|(_, &samples)| samples)
+            .sum()
+    }
+}
+
+/// Latency snapshot across all priority classes
+#[derive(Debug, Clone)]
+pub struct LatencyReport {
+    pub classes: Vec<LatencyHistogramSnapshot>,
+    /// Runs seen without a matching wakeup
+    pub unmatched_runs: u64,
+}
+
+impl LatencyReport {
+    /// Histogram for a priority class
+    pub fn class(&self, priority: Priority) -> &LatencyHistogramSnapshot {
+        &self.classes[class_index(priority)]
+    }
+
+    /// Worst-case latency across all classes
+    pub fn max_ns(&self) -> u64 {
+        self.classes.iter().map(|class| class.max_ns).max().unwrap_or(0)
+    }
+}
+
+/// Wakeup-to-run latency tracker
+pub struct LatencyTracker {
+    enabled: AtomicBool,
+    clock: fn() -> u64,
+    pending: Mutex<BTreeMap<ThreadId, (u64, Priority)>>,
+    classes: [LatencyHistogram; PRIORITY_CLASSES.len()],
+    unmatched_runs: AtomicU64,
+}
+
+impl LatencyTracker {
+    /// Create a tracker timestamping with `clock` (nanoseconds)
+    pub fn new(clock: fn() -> u64) -> Self {
+        Self {
+            enabled: AtomicBool::new(true),
+            clock,
+            pending: Mutex::new(BTreeMap::new()),
+            classes: core::array::from_fn(|_| LatencyHistogram::new()),
+            unmatched_runs: AtomicU64::new(0),
+        }
+    }
+
+    /// Replace the timestamp clock
+    pub fn set_clock(&mut self, clock: fn() -> u64) {
+        self.clock = clock;
+    }
+
+    /// Enable or disable measurement; disabling drops pending wakeups
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Release);
+        if !enabled {
+            self.pending.lock().clear();
+        }
+    }
+
+    /// Whether latencies are being measured
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Record that a thread became runnable
+    pub fn record_wakeup(&self, thread_id: ThreadId, priority: Priority) {
+        if !self.is_enabled() {
+            return;
+        }
+        let now = (self.clock)();
+        // A second wakeup before running keeps the earliest timestamp
+        self.pending.lock().entry(thread_id).or_insert((now, priority));
+    }
+
+    /// Record that a thread was switched in; returns its wakeup-to-run
+    /// latency if a wakeup was pending
+    pub fn record_run(&self, thread_id: ThreadId) -> Option<u64> {
+        if !self.is_enabled() {
+            return None;
+        }
+        let now = (self.clock)();
+        let Some((woken_at, priority)) = self.pending.lock().remove(&thread_id) else {
+            self.unmatched_runs.fetch_add(1, Ordering::Relaxed);
+            return None;
+        };
+
+        let latency_ns = now.saturating_sub(woken_at);
+        self.classes[class_index(priority)].record(thread_id, latency_ns);
+        Some(latency_ns)
+    }
+
+    /// Forget a pending wakeup, e.g. when the thread exits before running
+    pub fn cancel(&self, thread_id: ThreadId) {
+        self.pending.lock().remove(&thread_id);
+    }
+
+    /// Snapshot all priority class histograms
+    pub fn report(&self) -> LatencyReport {
+        LatencyReport {
+            classes: PRIORITY_CLASSES.iter()
+                .map(|&priority| self.classes[class_index(priority)].snapshot(priority))
+                .collect(),
+            unmatched_runs: self.unmatched_runs.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Clear histograms and worst-case tracking; pending wakeups are kept
+    pub fn reset(&self) {
+        for class in &self.classes {
+            class.reset();
+        }
+        self.unmatched_runs.store(0, Ordering::Relaxed);
+    }
+
+    /// Snapshot and then clear, starting a new measurement window
+    pub fn take_report(&self) -> LatencyReport {
+        let report = self.report();
+        self.reset();
+        report
+    }
+}
+
+impl core::fmt::Debug for LatencyTracker {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("LatencyTracker")
+            .field("enabled", &self.is_enabled())
+            .field("pending", &self.pending.lock().len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static NOW: AtomicU64 = AtomicU64::new(0);
+
+    fn test_clock() -> u64 {
+        NOW.load(Ordering::Relaxed)
+    }
+
+    #[test]
+    fn test_bucket_bounds() {
+        assert_eq!(bucket_index(500), 0);
+        assert_eq!(bucket_index(1_000), 1);
+        assert_eq!(bucket_index(3_000), 2);
+        assert_eq!(bucket_index(u64::MAX), LATENCY_BUCKETS - 1);
+        assert_eq!(bucket_upper_ns(2), 4_000);
+    }
+
+    #[test]
+    fn test_per_class_latency_and_reset() {
+        let tracker = LatencyTracker::new(test_clock);
+
+        NOW.store(0, Ordering::Relaxed);
+        tracker.record_wakeup(1, Priority::Critical);
+        tracker.record_wakeup(2, Priority::Normal);
+        NOW.store(5_000, Ordering::Relaxed);
+        assert_eq!(tracker.record_run(1), Some(5_000));
+        NOW.store(200_000, Ordering::Relaxed);
+        assert_eq!(tracker.record_run(2), Some(200_000));
+        assert_eq!(tracker.record_run(3), None);
+
+        let report = tracker.report();
+        let critical = report.class(Priority::Critical);
+        assert_eq!(critical.count, 1);
+        assert_eq!(critical.max_ns, 5_000);
+        assert_eq!(critical.max_thread, Some(1));
+        assert_eq!(critical.percentile_ns(99.0), 5_000);
+        assert_eq!(report.class(Priority::Normal).deadline_misses(100_000), 1);
+        assert_eq!(report.max_ns(), 200_000);
+        assert_eq!(report.unmatched_runs, 1);
+
+        let report = tracker.take_report();
+        assert_eq!(report.class(Priority::Normal).count, 1);
+        assert_eq!(tracker.report().class(Priority::Normal).count, 0);
+        assert_eq!(tracker.report().max_ns(), 0);
+    }
+}
@@ -0,0 +1,360 @@
+//! Scheduler Tracing for MultiOS
+//!
+//! This module records a low-overhead stream of scheduling events:
+//! - Context switches, wakeups, migrations and preemptions
+//! - Per-CPU lock-free trace buffers; events are dropped, never blocked on,
+//!   when a buffer is full
+//! - Text and Perfetto (Chrome trace-event JSON) exporters
+//! - Wakeup-to-run latency extraction for scheduling latency analysis
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use crate::lockfree::BoundedMpmcQueue;
+use crate::scheduler_algo::CpuId;
+use crate::thread::ThreadId;
+
+/// Default number of events buffered per CPU
+pub const DEFAULT_TRACE_BUFFER_EVENTS: usize = 4096;
+
+/// Scheduling event kind
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchedEventKind {
+    /// `prev` stopped running and `next` started on the CPU
+    Switch { prev: Option<ThreadId>, next: ThreadId },
+    /// Thread became runnable and was queued on the CPU
+    Wakeup { thread_id: ThreadId },
+    /// Thread moved between CPUs
+    Migrate { thread_id: ThreadId, from_cpu: CpuId, to_cpu: CpuId },
+    /// Running thread was preempted while still runnable
+    Preempt { thread_id: ThreadId },
+}
+
+/// Timestamped scheduling event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SchedEvent {
+    pub timestamp_ns: u64,
+    pub cpu_id: CpuId,
+    pub kind: SchedEventKind,
+}
+
+/// Trace export format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// One line per event, ftrace-like
+    Text,
+    /// Chrome trace-event JSON, loadable in Perfetto UI
+    Perfetto,
+}
+
+/// Tracer statistics
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SchedTraceStats {
+    pub recorded: u64,
+    pub dropped: u64,
+}
+
+/// Per-CPU scheduler event tracer
+pub struct SchedTracer {
+    enabled: AtomicBool,
+    buffers: Vec<BoundedMpmcQueue<SchedEvent>>,
+    clock: fn() -> u64,
+    sequence: AtomicU64,
+    recorded: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl SchedTracer {
+    /// Create a tracer for `cpu_count` CPUs buffering `events_per_cpu` events each
+    pub fn new(cpu_count: usize, events_per_cpu: usize) -> Self {
+        Self {
+            enabled: AtomicBool::new(true),
+            buffers: (0..cpu_count).map(|_| BoundedMpmcQueue::new(events_per_cpu)).collect(),
+            clock: || 0,
+            sequence: AtomicU64::new(0),
+            recorded: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Use a platform clock for timestamps instead of event sequence numbers
+    pub fn set_clock(&mut self, clock: fn() -> u64) {
+        self.clock = clock;
+    }
+
+    /// Enable or disable recording
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Release);
+    }
+
+    /// Whether events are being recorded
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Record an event on a CPU's trace buffer
+    pub fn record(&self, cpu_id: CpuId, kind: SchedEventKind) {
+        if !self.is_enabled() {
+            return;
+        }
+        let buffer = match self.buffers.get(cpu_id) {
+            Some(buffer) => buffer,
+            None => return,
+        };
+
+        let sequence = self.sequence.fetch_add(1, Ordering::Relaxed);
+        let timestamp_ns = match (self.clock)() {
+            0 => sequence,
+            now => now,
+        };
+
+        match buffer.push(SchedEvent { timestamp_ns, cpu_id, kind }) {
+            Ok(()) => self.recorded.fetch_add(1, Ordering::Relaxed),
+            Err(_) => self.dropped.fetch_add(1, Ordering::Relaxed),
+        };
+    }
+
+    /// Record a context switch
+    pub fn trace_switch(&self, cpu_id: CpuId, prev: Option<ThreadId>, next: ThreadId) {
+        self.record(cpu_id, SchedEventKind::Switch { prev, next });
+    }
+
+    /// Record a wakeup
+    pub fn trace_wakeup(&self, cpu_id: CpuId, thread_id: ThreadId) {
+        self.record(cpu_id, SchedEventKind::Wakeup { thread_id });
+    }
+
+    /// Record a migration (logged on the destination CPU)
+    pub fn trace_migrate(&self, thread_id: ThreadId, from_cpu: CpuId, to_cpu: CpuId) {
+        self.record(to_cpu, SchedEventKind::Migrate { thread_id, from_cpu, to_cpu });
+    }
+
+    /// Record a preemption
+    pub fn trace_preempt(&self, cpu_id: CpuId, thread_id: ThreadId) {
+        self.record(cpu_id, SchedEventKind::Preempt { thread_id });
+    }
+
+    /// Drain all per-CPU buffers into a single timestamp-ordered stream
+    pub fn drain(&self) -> Vec<SchedEvent> {
+        let mut events = Vec::new();
+        for buffer in &self.buffers {
+            while let Some(event) = buffer.pop() {
+                events.push(event);
+            }
+        }
+        events.sort_by_key(|event| event.timestamp_ns);
+        events
+    }
+
+    /// Get tracer statistics
+    pub fn get_stats(&self) -> SchedTraceStats {
+        SchedTraceStats {
+            recorded: self.recorded.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl core::fmt::Debug for SchedTracer {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("SchedTracer")
+            .field("enabled", &self.is_enabled())
+            .field("cpus", &self.buffers.len())
+            .field("stats", &self.get_stats())
+            .finish()
+    }
+}
+
+/// Export events in the requested format
+pub fn export_trace(events: &[SchedEvent], format: TraceFormat) -> String {
+    match format {
+        TraceFormat::Text => export_text(events),
+        TraceFormat::Perfetto => export_perfetto(events),
+    }
+}
+
+/// Export events as ftrace-style text lines
+pub fn export_text(events: &[SchedEvent]) -> String {
+    let mut out = String::new();
+    for event in events {
+        let _ = write!(out, "[{:03}] {:>16}: ", event.cpu_id, event.timestamp_ns);
+        let _ = match event.kind {
+            SchedEventKind::Switch { prev: Some(prev), next } => {
+                writeln!(out, "sched_switch: prev_tid={} next_tid={}", prev, next)
+            }
+            SchedEventKind::Switch { prev: None, next } => {
+                writeln!(out, "sched_switch: prev_tid=idle next_tid={}", next)
+            }
+            SchedEventKind::Wakeup { thread_id } => {
+                writeln!(out, "sched_wakeup: tid={}", thread_id)
+            }
+            SchedEventKind::Migrate { thread_id, from_cpu, to_cpu } => {
+                writeln!(out, "sched_migrate_task: tid={} orig_cpu={} dest_cpu={}", thread_id, from_cpu, to_cpu)
+            }
+            SchedEventKind::Preempt { thread_id } => {
+                writeln!(out, "sched_preempt: tid={}", thread_id)
+            }
+        };
+    }
+    out
+}
+
+/// Export events as Chrome trace-event JSON.
+///
+/// Each CPU is a track; a thread's time on a CPU becomes a complete (`X`)
+/// slice and wakeups, migrations and preemptions become instant (`i`) events.
+pub fn export_perfetto(events: &[SchedEvent]) -> String {
+    let mut out = String::from("{\"traceEvents\":[");
+    let mut running: BTreeMap<CpuId, (ThreadId, u64)> = BTreeMap::new();
+
+    for event in events {
+        // Chrome trace timestamps are in microseconds
+        let ts = event.timestamp_ns as f64 / 1000.0;
+        match event.kind {
+            SchedEventKind::Switch { next, .. } => {
+                if let Some((thread_id, start)) = running.insert(event.cpu_id, (next, event.timestamp_ns)) {
+                    emit_slice(&mut out, event.cpu_id, thread_id, start, event.timestamp_ns);
+                }
+            }
+            SchedEventKind::Wakeup { thread_id } => emit(&mut out, format_args!(
+                "{{\"name\":\"wakeup\",\"ph\":\"i\",\"s\":\"t\",\"pid\":0,\"tid\":{},\"ts\":{:.3},\"args\":{{\"tid\":{}}}}}",
+                event.cpu_id, ts, thread_id,
+            )),
+            SchedEventKind::Migrate { thread_id, from_cpu, to_cpu } => emit(&mut out, format_args!(
+                "{{\"name\":\"migrate\",\"ph\":\"i\",\"s\":\"t\",\"pid\":0,\"tid\":{},\"ts\":{:.3},\"args\":{{\"tid\":{},\"from\":{},\"to\":{}}}}}",
+                event.cpu_id, ts, thread_id, from_cpu, to_cpu,
+            )),
+            SchedEventKind::Preempt { thread_id } => emit(&mut out, format_args!(
+                "{{\"name\":\"preempt\",\"ph\":\"i\",\"s\":\"t\",\"pid\":0,\"tid\":{},\"ts\":{:.3},\"args\":{{\"tid\":{}}}}}",
+                event.cpu_id, ts, thread_id,
+            )),
+        }
+    }
+
+    // Close slices still running when the trace ends
+    let end_ns = events.iter().map(|event| event.timestamp_ns).max().unwrap_or(0);
+    for (cpu_id, (thread_id, start)) in running {
+        emit_slice(&mut out, cpu_id, thread_id, start, end_ns);
+    }
+
+    // Name each CPU track
+    for cpu_id in events.iter().map(|event| event.cpu_id).collect::<alloc::collections::BTreeSet<_>>() {
+        emit(&mut out, format_args!(
+            "{{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":0,\"tid\":{},\"args\":{{\"name\":\"CPU {}\"}}}}",
+            cpu_id, cpu_id,
+        ));
+    }
+
+    out.push_str("]}");
+    out
+}
+
+/// Append one trace event, separating it from the previous one
+fn emit(out: &mut String, entry: core::fmt::Arguments) {
+    if !out.ends_with('[') {
+        out.push(',');
+    }
+    let _ = out.write_fmt(entry);
+}
+
+/// Append a complete slice for a thread's time on a CPU
+fn emit_slice(out: &mut String, cpu_id: CpuId, thread_id: ThreadId, start_ns: u64, end_ns: u64) {
+    let dur = end_ns.saturating_sub(start_ns) as f64 / 1000.0;
+    emit(out, format_args!(
+        "{{\"name\":\"tid {}\",\"ph\":\"X\",\"pid\":0,\"tid\":{},\"ts\":{:.3},\"dur\":{:.3}}}",
+        thread_id, cpu_id, start_ns as f64 / 1000.0, dur,
+    ));
+}
+
+/// Wakeup-to-run latencies `(thread, latency_ns)` found in an event stream
+pub fn wakeup_latencies(events: &[SchedEvent]) -> Vec<(ThreadId, u64)> {
+    let mut pending: BTreeMap<ThreadId, u64> = BTreeMap::new();
+    let mut latencies = Vec::new();
+
+    for event in events {
+        match event.kind {
+            SchedEventKind::Wakeup { thread_id } => {
+                pending.insert(thread_id, event.timestamp_ns);
+            }
+            SchedEventKind::Switch { next, .. } => {
+                if let Some(woken_at) = pending.remove(&next) {
+                    latencies.push((next, event.timestamp_ns.saturating_sub(woken_at)));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    latencies
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trace_and_latency() {
+        let tracer = SchedTracer::new(2, 16);
+        tracer.trace_wakeup(0, 7);
+        tracer.trace_switch(0, None, 7);
+        tracer.trace_preempt(0, 7);
+        tracer.trace_migrate(7, 0, 1);
+
+        let events = tracer.drain();
+        assert_eq!(events.len(), 4);
+        assert_eq!(wakeup_latencies(&events), vec![(7, 1)]);
+
+        let text = export_text(&events);
+        assert!(text.contains("sched_switch: prev_tid=idle next_tid=7"));
+        assert!(text.contains("sched_migrate_task: tid=7 orig_cpu=0 dest_cpu=1"));
+    }
+
+    #[test]
+    fn test_full_buffer_drops_and_disable() {
+        let tracer = SchedTracer::new(1, 2);
+        for thread_id in 0..3 {
+            tracer.trace_wakeup(0, thread_id);
+        }
+        assert_eq!(tracer.get_stats().dropped, 1);
+
+        tracer.set_enabled(false);
+        tracer.trace_wakeup(0, 9);
+        assert_eq!(tracer.get_stats().recorded, 2);
+    }
+
+    #[test]
+    fn test_perfetto_export() {
+        let tracer = SchedTracer::new(1, 8);
+        tracer.trace_switch(0, None, 1);
+        tracer.trace_switch(0, Some(1), 2);
+
+        let json = export_perfetto(&tracer.drain());
+        assert!(json.starts_with("{\"traceEvents\":["));
+        assert!(json.contains("\"ph\":\"X\""));
+        assert!(json.contains("\"name\":\"CPU 0\""));
+        assert!(json.ends_with("]}"));
+    }
+
+    #[test]
+    fn test_perfetto_closes_open_slices() {
+        let switch = |timestamp_ns, prev, next| SchedEvent {
+            timestamp_ns,
+            cpu_id: 0,
+            kind: SchedEventKind::Switch { prev, next },
+        };
+        let events = vec![
+            switch(1_000, None, 1),
+            switch(3_000, Some(1), 2),
+            SchedEvent { timestamp_ns: 6_000, cpu_id: 1, kind: SchedEventKind::Wakeup { thread_id: 3 } },
+        ];
+
+        let json = export_perfetto(&events);
+        assert!(json.contains("\"name\":\"tid 1\",\"ph\":\"X\",\"pid\":0,\"tid\":0,\"ts\":1.000,\"dur\":2.000"));
+        // Thread 2 is still running on CPU 0 when the trace ends
+        assert!(json.contains("\"name\":\"tid 2\",\"ph\":\"X\",\"pid\":0,\"tid\":0,\"ts\":3.000,\"dur\":3.000"));
+        assert!(!json.contains(",,"));
+    }
+}
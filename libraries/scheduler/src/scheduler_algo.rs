@@ -11,6 +11,9 @@ use core::sync::atomic::{AtomicUsize, AtomicU32, AtomicU64, Ordering};
 use crate::{Priority, ThreadState, SchedulerError};
 use crate::thread::{ThreadHandle, ThreadId, ThreadManager, ThreadControlBlock};
 use crate::process::{ProcessManager, ProcessId};
+use crate::sched_trace::{SchedTracer, SchedEvent, DEFAULT_TRACE_BUFFER_EVENTS};
+use crate::sched_latency::{LatencyTracker, LatencyReport};
+use crate::task_group::{with_task_groups, ROOT_TASK_GROUP};
 
 /// CPU ID type
 pub type CpuId = usize;
@@ -192,6 +195,10 @@ pub struct SchedulerConfig {
     pub enable_cpu_affinity: bool,
     /// Enable automatic load balancing
     pub enable_load_balancing: bool,
+    /// Record sched_switch/wakeup/migrate/preempt trace events
+    pub enable_tracing: bool,
+    /// Measure wakeup-to-run latency per priority class; see `Scheduler::set_latency_clock`
+    pub enable_latency_tracking: bool,
 }
 
 impl Default for SchedulerConfig {
@@ -203,6 +210,8 @@ impl Default for SchedulerConfig {
             load_balance_interval: 100,
             enable_cpu_affinity: true,
             enable_load_balancing: true,
+            enable_tracing: false,
+            enable_latency_tracking: false,
         }
     }
 }
@@ -221,6 +230,10 @@ pub struct Scheduler {
     global_ready_queue: Mutex<ReadyQueue>,
     /// Scheduler statistics
     stats: SchedulerStats,
+    /// Scheduling event tracer
+    tracer: Option<SchedTracer>,
+    /// Wakeup-to-run latency histograms
+    latency: Option<LatencyTracker>,
 }
 
 /// Scheduler statistics
@@ -266,29 +279,80 @@ impl Scheduler {
                 load_balance_interval: 100,
                 enable_cpu_affinity: true,
                 enable_load_balancing: true,
+                enable_tracing: false,
+                enable_latency_tracking: false,
             },
             thread_manager,
             process_manager,
             cpu_schedulers,
             global_ready_queue: Mutex::new(ReadyQueue::new()),
             stats: SchedulerStats::default(),
+            tracer: None,
+            latency: None,
         }
     }
 
     /// Initialize scheduler with configuration
     pub fn with_config(config: SchedulerConfig) -> Self {
         let mut scheduler = Self::new();
+        if config.enable_tracing {
+            scheduler.tracer = Some(SchedTracer::new(scheduler.cpu_schedulers.len(), DEFAULT_TRACE_BUFFER_EVENTS));
+        }
+        if config.enable_latency_tracking {
+            scheduler.latency = Some(LatencyTracker::new(|| 0));
+        }
         scheduler.config = config;
         scheduler
     }
 
+    /// Get the scheduling event tracer, if tracing is enabled
+    pub fn tracer(&self) -> Option<&SchedTracer> {
+        self.tracer.as_ref()
+    }
+
+    /// Drain recorded scheduling events
+    pub fn drain_trace(&self) -> Vec<SchedEvent> {
+        self.tracer.as_ref().map_or_else(Vec::new, |tracer| tracer.drain())
+    }
+
+    /// Install the platform clock used to timestamp wakeups and switches
+    pub fn set_latency_clock(&mut self, clock: fn() -> u64) {
+        if let Some(latency) = &mut self.latency {
+            latency.set_clock(clock);
+        }
+    }
+
+    /// Get wakeup-to-run latency histograms, if latency tracking is enabled
+    pub fn latency_report(&self) -> Option<LatencyReport> {
+        self.latency.as_ref().map(|latency| latency.report())
+    }
+
+    /// Clear latency histograms and worst-case tracking
+    pub fn reset_latency_stats(&self) {
+        if let Some(latency) = &self.latency {
+            latency.reset();
+        }
+    }
+
     /// Add a thread to the scheduler
     pub fn add_thread(&self, thread_handle: ThreadHandle) -> Result<(), SchedulerError> {
         let tcb = thread_handle.lock();
         let thread_id = tcb.thread_id;
         let priority = tcb.priority;
+        let process_id = tcb.process_id;
         drop(tcb);
 
+        // Threads created after their process moved to a task group join it here
+        if let Ok(group_id) = self.process_manager.get_task_group(process_id) {
+            if group_id != ROOT_TASK_GROUP {
+                with_task_groups(|groups| {
+                    if groups.group_of(thread_id) != group_id {
+                        let _ = groups.attach_thread(group_id, thread_id);
+                    }
+                });
+            }
+        }
+
         // Determine which CPU to add this thread to
         let cpu_id = self.select_cpu_for_thread(thread_id, priority);
         
@@ -299,6 +363,13 @@ impl Scheduler {
             cpu_scheduler.load += 1;
         }
 
+        if let Some(tracer) = &self.tracer {
+            tracer.trace_wakeup(cpu_id, thread_id);
+        }
+        if let Some(latency) = &self.latency {
+            latency.record_wakeup(thread_id, priority);
+        }
+
         self.stats.threads_scheduled.fetch_add(1, Ordering::SeqCst);
         Ok(())
     }
@@ -324,6 +395,10 @@ impl Scheduler {
             }
         }
 
+        if let Some(latency) = &self.latency {
+            latency.cancel(thread_id);
+        }
+
         Ok(())
     }
 
@@ -331,8 +406,13 @@ impl Scheduler {
     pub fn schedule_next(&self, cpu_id: CpuId) -> Result<ThreadHandle, SchedulerError> {
         let mut cpu_scheduler = self.cpu_schedulers[cpu_id].lock();
         
+        let prev_thread = cpu_scheduler.current_thread;
+
         // If there's already a current thread, put it back in the ready queue
         if let Some(current_thread_id) = cpu_scheduler.current_thread {
+            if let Some(tracer) = &self.tracer {
+                tracer.trace_preempt(cpu_id, current_thread_id);
+            }
             if let Ok(thread_handle) = self.thread_manager.get_thread(current_thread_id) {
                 let mut tcb = thread_handle.lock();
                 tcb.state = ThreadState::Ready;
@@ -343,8 +423,23 @@ impl Scheduler {
             }
         }
 
-        // Get next thread from ready queue
-        let next_thread_id = if let Some(thread_id) = cpu_scheduler.ready_queue.get_next_thread(self.config.algorithm) {
+        // Get next thread from ready queue, passing over threads whose task
+        // group has used up its bandwidth for this period
+        let mut throttled = Vec::new();
+        let next = with_task_groups(|groups| loop {
+            match cpu_scheduler.ready_queue.get_next_thread(self.config.algorithm) {
+                Some(thread_id) if !groups.can_run(thread_id) => throttled.push(thread_id),
+                next => break next,
+            }
+        });
+        for thread_id in throttled {
+            if let Ok(thread_handle) = self.thread_manager.get_thread(thread_id) {
+                let priority = thread_handle.lock().priority;
+                cpu_scheduler.ready_queue.add_thread(thread_id, priority, self.config.algorithm);
+            }
+        }
+
+        let next_thread_id = if let Some(thread_id) = next {
             thread_id
         } else {
             // No ready threads, return idle thread
@@ -362,11 +457,35 @@ impl Scheduler {
             tcb.last_scheduled = cpu_scheduler.last_scheduled;
         }
 
+        if let Some(tracer) = &self.tracer {
+            tracer.trace_switch(cpu_id, prev_thread, next_thread_id);
+        }
+        if let Some(latency) = &self.latency {
+            latency.record_run(next_thread_id);
+        }
+
         self.stats.context_switches.fetch_add(1, Ordering::SeqCst);
         self.thread_manager.get_thread(next_thread_id)
             .map_err(|_| SchedulerError::NoRunnableThreads)
     }
 
+    /// Charge `runtime_ns` of CPU time to the thread running on `cpu_id` and
+    /// its task groups, replenishing quotas whose period has elapsed.
+    ///
+    /// Called from the timer tick. Returns `true` if the thread's group is
+    /// throttled and the CPU should reschedule.
+    pub fn charge_runtime(&self, cpu_id: CpuId, runtime_ns: u64, now_ns: u64) -> bool {
+        let current = match self.cpu_schedulers.get(cpu_id) {
+            Some(cpu_scheduler) => cpu_scheduler.lock().current_thread,
+            None => return false,
+        };
+
+        with_task_groups(|groups| {
+            groups.refresh_periods(now_ns);
+            current.map_or(false, |thread_id| groups.charge(thread_id, runtime_ns, now_ns))
+        })
+    }
+
     /// Get the current thread running on a CPU
     pub fn get_current_thread(&self, cpu_id: CpuId) -> Option<ThreadId> {
         let cpu_scheduler = self.cpu_schedulers[cpu_id].lock();
@@ -421,6 +540,10 @@ impl Scheduler {
                         underloaded.load += 1;
                     }
 
+                    if let Some(tracer) = &self.tracer {
+                        tracer.trace_migrate(thread_id, overloaded_cpu, *underloaded_cpu);
+                    }
+
                     // Update thread's CPU affinity if needed
                     // thread_manager.update_thread_cpu_affinity(thread_id, *underloaded_cpu)?;
                     break;
@@ -687,6 +810,45 @@ mod tests {
         assert_eq!(scheduler.get_cpu_count(), 4);
     }
 
+    #[test]
+    fn test_throttled_group_is_skipped() {
+        use crate::task_group::CpuBandwidth;
+        use crate::thread::{ThreadParams, THREAD_MANAGER};
+
+        let scheduler = Scheduler::new();
+        for cpu_id in 1..scheduler.get_cpu_count() {
+            scheduler.take_cpu_offline(cpu_id).unwrap();
+        }
+        let params = ThreadParams { stack_size: 4096, priority: Priority::Normal, detached: false, inherit_priority: false };
+        let limited = THREAD_MANAGER.create_thread(1, b"limited".to_vec(), None, params.clone()).unwrap();
+        let free = THREAD_MANAGER.create_thread(1, b"free".to_vec(), None, params).unwrap();
+        let limited_id = limited.lock().thread_id;
+        let free_id = free.lock().thread_id;
+
+        with_task_groups(|groups| {
+            let group = groups.create_group(ROOT_TASK_GROUP, "throttle-test").unwrap();
+            groups.set_bandwidth(group, Some(CpuBandwidth { quota_us: 1_000, period_us: 10_000 })).unwrap();
+            groups.attach_thread(group, limited_id).unwrap();
+        });
+
+        scheduler.add_thread(limited).unwrap();
+        assert_eq!(scheduler.schedule_next(0).unwrap().lock().thread_id, limited_id);
+        assert!(scheduler.charge_runtime(0, 1_000_000, 1_000_000));
+
+        // The throttled thread stays queued but never runs this period
+        scheduler.add_thread(free).unwrap();
+        for _ in 0..3 {
+            assert_eq!(scheduler.schedule_next(0).unwrap().lock().thread_id, free_id);
+        }
+
+        // A new period lets it run again
+        assert!(!scheduler.charge_runtime(0, 0, 10_000_000));
+        let picked: Vec<ThreadId> = (0..4)
+            .map(|_| scheduler.schedule_next(0).unwrap().lock().thread_id)
+            .collect();
+        assert!(picked.contains(&limited_id));
+    }
+
     #[test]
     fn test_time_quantum_calculation() {
         assert_eq!(SchedulerHelpers::calculate_time_quantum(Priority::Idle, SchedulingAlgorithm::RoundRobin), 5);
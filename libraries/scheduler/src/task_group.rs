@@ -0,0 +1,433 @@
+//! Hierarchical Task Groups and CPU Bandwidth Control for MultiOS
+//!
+//! This module provides cgroup-style CPU control for the scheduler:
+//! - Hierarchical task groups rooted at a single root group
+//! - Proportional CPU shares between sibling groups
+//! - Quota/period bandwidth limits (cfs_quota-like) enforced up the hierarchy
+//! - Throttling and per-period replenishment with statistics
+//!
+//! The process manager and the hypervisor resource controls attach processes
+//! or VCPU threads to groups to enforce per-tenant CPU limits.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use crate::thread::ThreadId;
+use crate::process::{ProcessError, ProcessResult};
+
+/// Task group identifier
+pub type TaskGroupId = usize;
+
+/// Identifier of the root task group
+pub const ROOT_TASK_GROUP: TaskGroupId = 0;
+
+/// Default CPU shares for a group (matches Linux cpu.shares)
+pub const DEFAULT_CPU_SHARES: u32 = 1024;
+
+/// Minimum and maximum CPU shares
+const MIN_CPU_SHARES: u32 = 2;
+const MAX_CPU_SHARES: u32 = 262_144;
+
+/// Minimum bandwidth period (microseconds)
+const MIN_PERIOD_US: u64 = 1_000;
+
+/// Quota/period bandwidth limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuBandwidth {
+    /// CPU time allowed per period (microseconds)
+    pub quota_us: u64,
+    /// Enforcement period (microseconds)
+    pub period_us: u64,
+}
+
+impl CpuBandwidth {
+    /// Fraction of a single CPU this limit allows
+    pub fn cpu_fraction(&self) -> f32 {
+        self.quota_us as f32 / self.period_us as f32
+    }
+}
+
+/// Task group bandwidth statistics
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TaskGroupStats {
+    /// Total runtime charged to the group (nanoseconds)
+    pub total_runtime_ns: u64,
+    /// Number of periods elapsed
+    pub nr_periods: u64,
+    /// Number of periods in which the group was throttled
+    pub nr_throttled: u64,
+    /// Total time spent throttled (nanoseconds)
+    pub throttled_time_ns: u64,
+}
+
+/// Hierarchical task group
+#[derive(Debug, Clone)]
+pub struct TaskGroup {
+    pub group_id: TaskGroupId,
+    pub name: String,
+    pub parent: Option<TaskGroupId>,
+    pub children: Vec<TaskGroupId>,
+    /// Relative CPU weight among siblings
+    pub shares: u32,
+    /// Optional hard bandwidth limit
+    pub bandwidth: Option<CpuBandwidth>,
+    /// Runtime left in the current period (nanoseconds)
+    pub runtime_remaining_ns: u64,
+    /// Whether the group is currently throttled
+    pub throttled: bool,
+    /// Time the group was throttled
+    pub throttled_at_ns: u64,
+    /// Start of the group's current enforcement period (nanoseconds)
+    pub period_start_ns: u64,
+    /// Threads directly attached to this group
+    pub threads: Vec<ThreadId>,
+    pub stats: TaskGroupStats,
+}
+
+/// Task group manager
+#[derive(Debug)]
+pub struct TaskGroupManager {
+    groups: Vec<Option<TaskGroup>>,
+}
+
+impl TaskGroupManager {
+    /// Create a manager containing only the root group
+    pub fn new() -> Self {
+        let root = TaskGroup {
+            group_id: ROOT_TASK_GROUP,
+            name: String::from("root"),
+            parent: None,
+            children: Vec::new(),
+            shares: DEFAULT_CPU_SHARES,
+            bandwidth: None,
+            runtime_remaining_ns: 0,
+            throttled: false,
+            throttled_at_ns: 0,
+            period_start_ns: 0,
+            threads: Vec::new(),
+            stats: TaskGroupStats::default(),
+        };
+
+        Self {
+            groups: vec![Some(root)],
+        }
+    }
+
+    /// Create a child group under `parent`
+    pub fn create_group(&mut self, parent: TaskGroupId, name: &str) -> ProcessResult<TaskGroupId> {
+        self.group(parent)?;
+
+        let group_id = self.groups.len();
+        self.groups.push(Some(TaskGroup {
+            group_id,
+            name: String::from(name),
+            parent: Some(parent),
+            children: Vec::new(),
+            shares: DEFAULT_CPU_SHARES,
+            bandwidth: None,
+            runtime_remaining_ns: 0,
+            throttled: false,
+            throttled_at_ns: 0,
+            period_start_ns: 0,
+            threads: Vec::new(),
+            stats: TaskGroupStats::default(),
+        }));
+        self.group_mut(parent)?.children.push(group_id);
+
+        Ok(group_id)
+    }
+
+    /// Destroy an empty group; its threads must have been moved first
+    pub fn destroy_group(&mut self, group_id: TaskGroupId) -> ProcessResult<()> {
+        if group_id == ROOT_TASK_GROUP {
+            return Err(ProcessError::AccessDenied);
+        }
+
+        let group = self.group(group_id)?;
+        if !group.children.is_empty() || !group.threads.is_empty() {
+            return Err(ProcessError::ProcessInInvalidState);
+        }
+
+        if let Some(parent) = group.parent {
+            self.group_mut(parent)?.children.retain(|&child| child != group_id);
+        }
+        self.groups[group_id] = None;
+        Ok(())
+    }
+
+    /// Set relative CPU shares for a group
+    pub fn set_shares(&mut self, group_id: TaskGroupId, shares: u32) -> ProcessResult<()> {
+        self.group_mut(group_id)?.shares = shares.clamp(MIN_CPU_SHARES, MAX_CPU_SHARES);
+        Ok(())
+    }
+
+    /// Set or clear the bandwidth limit for a group
+    pub fn set_bandwidth(&mut self, group_id: TaskGroupId, bandwidth: Option<CpuBandwidth>) -> ProcessResult<()> {
+        if let Some(bw) = bandwidth {
+            if bw.period_us < MIN_PERIOD_US || bw.quota_us == 0 {
+                return Err(ProcessError::InvalidParameter);
+            }
+        }
+
+        let group = self.group_mut(group_id)?;
+        group.bandwidth = bandwidth;
+        group.runtime_remaining_ns = bandwidth.map_or(0, |bw| bw.quota_us * 1_000);
+        group.throttled = false;
+        Ok(())
+    }
+
+    /// Attach a thread to a group, detaching it from any previous group
+    pub fn attach_thread(&mut self, group_id: TaskGroupId, thread_id: ThreadId) -> ProcessResult<()> {
+        self.group(group_id)?;
+        for group in self.groups.iter_mut().flatten() {
+            group.threads.retain(|&tid| tid != thread_id);
+        }
+        self.group_mut(group_id)?.threads.push(thread_id);
+        Ok(())
+    }
+
+    /// Attach all threads of a process to a group
+    pub fn attach_threads(&mut self, group_id: TaskGroupId, threads: &[ThreadId]) -> ProcessResult<()> {
+        self.group(group_id)?;
+        for &thread_id in threads {
+            self.attach_thread(group_id, thread_id)?;
+        }
+        Ok(())
+    }
+
+    /// Find the group a thread belongs to (root if unattached)
+    pub fn group_of(&self, thread_id: ThreadId) -> TaskGroupId {
+        self.groups
+            .iter()
+            .flatten()
+            .find(|group| group.threads.contains(&thread_id))
+            .map_or(ROOT_TASK_GROUP, |group| group.group_id)
+    }
+
+    /// Charge runtime to a thread's group and all of its ancestors.
+    ///
+    /// Returns `true` if any group in the hierarchy became throttled.
+    pub fn charge(&mut self, thread_id: ThreadId, runtime_ns: u64, now_ns: u64) -> bool {
+        let mut throttled = false;
+        let mut current = Some(self.group_of(thread_id));
+
+        while let Some(group_id) = current {
+            let group = match self.groups.get_mut(group_id).and_then(|g| g.as_mut()) {
+                Some(group) => group,
+                None => break,
+            };
+
+            group.stats.total_runtime_ns += runtime_ns;
+            if group.bandwidth.is_some() {
+                group.runtime_remaining_ns = group.runtime_remaining_ns.saturating_sub(runtime_ns);
+                if group.runtime_remaining_ns == 0 && !group.throttled {
+                    group.throttled = true;
+                    group.throttled_at_ns = now_ns;
+                    group.stats.nr_throttled += 1;
+                }
+            }
+            throttled |= group.throttled;
+            current = group.parent;
+        }
+
+        throttled
+    }
+
+    /// Check whether a thread may run (no ancestor is throttled)
+    pub fn can_run(&self, thread_id: ThreadId) -> bool {
+        let mut current = Some(self.group_of(thread_id));
+        while let Some(group_id) = current {
+            match self.groups.get(group_id).and_then(|g| g.as_ref()) {
+                Some(group) if group.throttled => return false,
+                Some(group) => current = group.parent,
+                None => break,
+            }
+        }
+        true
+    }
+
+    /// Replenish quotas whose period has elapsed and unthrottle groups.
+    ///
+    /// Every group runs its own period, starting from its last refresh.
+    /// Returns the number of groups unthrottled.
+    pub fn refresh_periods(&mut self, now_ns: u64) -> usize {
+        let mut unthrottled = 0;
+
+        for group in self.groups.iter_mut().flatten() {
+            let bw = match group.bandwidth {
+                Some(bw) => bw,
+                None => continue,
+            };
+            let period_ns = bw.period_us * 1_000;
+            let elapsed_ns = now_ns.saturating_sub(group.period_start_ns);
+            if elapsed_ns < period_ns {
+                continue;
+            }
+
+            let periods = elapsed_ns / period_ns;
+            group.stats.nr_periods += periods;
+            group.period_start_ns += periods * period_ns;
+            group.runtime_remaining_ns = bw.quota_us * 1_000;
+            if group.throttled {
+                group.stats.throttled_time_ns += now_ns.saturating_sub(group.throttled_at_ns);
+                group.throttled = false;
+                unthrottled += 1;
+            }
+        }
+
+        unthrottled
+    }
+
+    /// Effective share of total CPU for a group, combining shares at every level
+    pub fn effective_weight(&self, group_id: TaskGroupId) -> f32 {
+        let mut weight = 1.0;
+        let mut current = group_id;
+
+        while let Ok(group) = self.group(current) {
+            let parent = match group.parent {
+                Some(parent) => parent,
+                None => break,
+            };
+            let sibling_total: u32 = self.group(parent)
+                .map(|p| p.children.iter()
+                    .filter_map(|&c| self.group(c).ok())
+                    .map(|c| c.shares)
+                    .sum())
+                .unwrap_or(group.shares);
+            weight *= group.shares as f32 / sibling_total.max(1) as f32;
+            current = parent;
+        }
+
+        weight
+    }
+
+    /// Get a group by ID
+    pub fn get_group(&self, group_id: TaskGroupId) -> Option<&TaskGroup> {
+        self.groups.get(group_id).and_then(|g| g.as_ref())
+    }
+
+    /// Get bandwidth statistics for a group
+    pub fn get_stats(&self, group_id: TaskGroupId) -> ProcessResult<TaskGroupStats> {
+        Ok(self.group(group_id)?.stats)
+    }
+
+    fn group(&self, group_id: TaskGroupId) -> ProcessResult<&TaskGroup> {
+        self.groups.get(group_id).and_then(|g| g.as_ref()).ok_or(ProcessError::GroupNotFound)
+    }
+
+    fn group_mut(&mut self, group_id: TaskGroupId) -> ProcessResult<&mut TaskGroup> {
+        self.groups.get_mut(group_id).and_then(|g| g.as_mut()).ok_or(ProcessError::GroupNotFound)
+    }
+}
+
+impl Default for TaskGroupManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// System-wide task groups, created on first use
+static TASK_GROUPS: Mutex<Option<TaskGroupManager>> = Mutex::new(None);
+
+/// Run `f` on the system-wide task group manager shared by the process
+/// manager and the scheduler
+pub fn with_task_groups<R>(f: impl FnOnce(&mut TaskGroupManager) -> R) -> R {
+    let mut groups = TASK_GROUPS.lock();
+    f(groups.get_or_insert_with(TaskGroupManager::new))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quota_throttles_and_replenishes() {
+        let mut manager = TaskGroupManager::new();
+        let tenant = manager.create_group(ROOT_TASK_GROUP, "tenant-a").unwrap();
+        manager.set_bandwidth(tenant, Some(CpuBandwidth { quota_us: 50_000, period_us: 100_000 })).unwrap();
+        manager.attach_thread(tenant, 7).unwrap();
+
+        assert!(!manager.charge(7, 30_000_000, 30_000_000));
+        assert!(manager.charge(7, 20_000_000, 50_000_000));
+        assert!(!manager.can_run(7));
+
+        assert_eq!(manager.refresh_periods(100_000_000), 1);
+        assert!(manager.can_run(7));
+        assert_eq!(manager.get_stats(tenant).unwrap().nr_throttled, 1);
+    }
+
+    #[test]
+    fn test_groups_refresh_on_their_own_periods() {
+        let mut manager = TaskGroupManager::new();
+        let fast = manager.create_group(ROOT_TASK_GROUP, "fast").unwrap();
+        let slow = manager.create_group(ROOT_TASK_GROUP, "slow").unwrap();
+        manager.set_bandwidth(fast, Some(CpuBandwidth { quota_us: 5_000, period_us: 10_000 })).unwrap();
+        manager.set_bandwidth(slow, Some(CpuBandwidth { quota_us: 50_000, period_us: 100_000 })).unwrap();
+        manager.attach_thread(slow, 9).unwrap();
+
+        // The fast group's refreshes must not restart the slow group's period
+        assert!(manager.charge(9, 50_000_000, 50_000_000));
+        for now_ms in (10..100).step_by(10) {
+            manager.refresh_periods(now_ms * 1_000_000);
+            assert!(!manager.can_run(9));
+        }
+        assert_eq!(manager.refresh_periods(100_000_000), 1);
+        assert!(manager.can_run(9));
+        assert_eq!(manager.get_stats(fast).unwrap().nr_periods, 10);
+        assert_eq!(manager.get_stats(slow).unwrap().nr_periods, 1);
+    }
+
+    #[test]
+    fn test_set_bandwidth_errors() {
+        let mut manager = TaskGroupManager::new();
+        let group = manager.create_group(ROOT_TASK_GROUP, "tenant-b").unwrap();
+
+        let zero_quota = Some(CpuBandwidth { quota_us: 0, period_us: 100_000 });
+        assert_eq!(manager.set_bandwidth(group, zero_quota), Err(ProcessError::InvalidParameter));
+        let short_period = Some(CpuBandwidth { quota_us: 500, period_us: MIN_PERIOD_US - 1 });
+        assert_eq!(manager.set_bandwidth(group, short_period), Err(ProcessError::InvalidParameter));
+
+        let valid = Some(CpuBandwidth { quota_us: 50_000, period_us: 100_000 });
+        assert_eq!(manager.set_bandwidth(group + 1, valid), Err(ProcessError::GroupNotFound));
+        manager.destroy_group(group).unwrap();
+        assert_eq!(manager.set_bandwidth(group, valid), Err(ProcessError::GroupNotFound));
+    }
+
+    #[test]
+    fn test_parent_limit_applies_to_children() {
+        let mut manager = TaskGroupManager::new();
+        let parent = manager.create_group(ROOT_TASK_GROUP, "lab").unwrap();
+        let child = manager.create_group(parent, "student").unwrap();
+        manager.set_bandwidth(parent, Some(CpuBandwidth { quota_us: 10_000, period_us: 100_000 })).unwrap();
+        manager.attach_thread(child, 3).unwrap();
+
+        manager.charge(3, 10_000_000, 10_000_000);
+        assert!(!manager.can_run(3));
+    }
+
+    #[test]
+    fn test_hierarchical_shares() {
+        let mut manager = TaskGroupManager::new();
+        let a = manager.create_group(ROOT_TASK_GROUP, "a").unwrap();
+        let b = manager.create_group(ROOT_TASK_GROUP, "b").unwrap();
+        manager.set_shares(a, 3072).unwrap();
+        let a1 = manager.create_group(a, "a1").unwrap();
+        let _a2 = manager.create_group(a, "a2").unwrap();
+
+        assert!((manager.effective_weight(b) - 0.25).abs() < 1e-6);
+        assert!((manager.effective_weight(a1) - 0.375).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_destroy_requires_empty_group() {
+        let mut manager = TaskGroupManager::new();
+        let group = manager.create_group(ROOT_TASK_GROUP, "tmp").unwrap();
+        manager.attach_thread(group, 1).unwrap();
+        assert_eq!(manager.destroy_group(group), Err(ProcessError::ProcessInInvalidState));
+
+        manager.attach_thread(ROOT_TASK_GROUP, 1).unwrap();
+        assert!(manager.destroy_group(group).is_ok());
+        assert_eq!(manager.destroy_group(ROOT_TASK_GROUP), Err(ProcessError::AccessDenied));
+    }
+}
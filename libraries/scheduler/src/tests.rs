@@ -46,6 +46,8 @@ mod integration_tests {
             load_balance_interval: 75,
             enable_cpu_affinity: true,
             enable_load_balancing: false,
+            enable_tracing: false,
+            enable_latency_tracking: false,
         };
 
         let result = init_with_config(config);
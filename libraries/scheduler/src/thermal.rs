@@ -0,0 +1,359 @@
+//! Thermal Governor for MultiOS
+//!
+//! This module closes the loop between temperature sensors and the scheduler:
+//! - Reads real or simulated per-CPU temperature sensors
+//! - Applies `ThermalAction`s: frequency capping, load migration, idle injection
+//! - Exposes a per-CPU thermal pressure the load balancer uses to steer
+//!   runnable threads away from hot cores
+//! - Relaxes throttling with hysteresis once a core cools down
+
+use alloc::vec::Vec;
+
+use crate::multicore::ThermalAction;
+use crate::scheduler_algo::CpuId;
+
+/// Source of per-CPU temperature readings
+pub trait TemperatureSensor {
+    /// Read the current temperature of a CPU in degrees Celsius
+    fn read_celsius(&mut self, cpu_id: CpuId) -> Option<u8>;
+}
+
+/// Thermal governor configuration
+#[derive(Debug, Clone)]
+pub struct ThermalGovernorConfig {
+    /// Temperature at which passive cooling (frequency capping) starts
+    pub passive_temp: u8,
+    /// Temperature at which the CPU is throttled and load migrated away
+    pub throttle_temp: u8,
+    /// Temperature at which the CPU must be shut down
+    pub critical_temp: u8,
+    /// Degrees below a trip point before its action is released
+    pub hysteresis: u8,
+    /// Frequency cap reduction per step (percent of max frequency)
+    pub freq_step_percent: u32,
+    /// Lowest allowed frequency cap (percent of max frequency)
+    pub min_freq_percent: u32,
+    /// Idle injection increase per step (percent of time)
+    pub idle_inject_step_percent: u32,
+    /// Maximum idle injection (percent of time)
+    pub max_idle_inject_percent: u32,
+}
+
+impl Default for ThermalGovernorConfig {
+    fn default() -> Self {
+        Self {
+            passive_temp: 75,
+            throttle_temp: 85,
+            critical_temp: 100,
+            hysteresis: 5,
+            freq_step_percent: 10,
+            min_freq_percent: 30,
+            idle_inject_step_percent: 10,
+            max_idle_inject_percent: 50,
+        }
+    }
+}
+
+impl ThermalGovernorConfig {
+    /// Configuration derived from a single throttle temperature
+    pub fn with_throttle_temp(throttle_temp: u8) -> Self {
+        Self {
+            passive_temp: throttle_temp.saturating_sub(10),
+            throttle_temp,
+            critical_temp: throttle_temp.saturating_add(15),
+            ..Default::default()
+        }
+    }
+}
+
+/// Per-CPU thermal state
+#[derive(Debug, Clone)]
+pub struct CpuThermalState {
+    pub cpu_id: CpuId,
+    /// Last sensor reading (Celsius)
+    pub temperature: u8,
+    /// Action currently in effect
+    pub action: ThermalAction,
+    /// Frequency cap (percent of max frequency)
+    pub freq_cap_percent: u32,
+    /// Forced idle time (percent)
+    pub idle_inject_percent: u32,
+}
+
+impl CpuThermalState {
+    /// Thermal pressure in `[0, 1]`: how much of the CPU's capacity is lost to throttling
+    pub fn pressure(&self) -> f32 {
+        let freq_loss = (100 - self.freq_cap_percent.min(100)) as f32 / 100.0;
+        let idle_loss = self.idle_inject_percent.min(100) as f32 / 100.0;
+        (freq_loss + idle_loss).min(1.0)
+    }
+}
+
+/// Decision produced by a governor update for one CPU
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ThermalDecision {
+    pub cpu_id: CpuId,
+    pub action: ThermalAction,
+    /// Whether runnable load should be migrated off this CPU
+    pub migrate_load: bool,
+}
+
+/// Thermal governor statistics
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ThermalGovernorStats {
+    pub updates: u64,
+    pub throttle_events: u64,
+    pub passive_events: u64,
+    pub critical_events: u64,
+    pub migrations_requested: u64,
+    pub max_temperature: u8,
+}
+
+/// Thermal governor
+#[derive(Debug)]
+pub struct ThermalGovernor {
+    config: ThermalGovernorConfig,
+    cpus: Vec<CpuThermalState>,
+    stats: ThermalGovernorStats,
+}
+
+impl ThermalGovernor {
+    /// Create a governor for `cpu_count` CPUs
+    pub fn new(config: ThermalGovernorConfig, cpu_count: usize) -> Self {
+        let cpus = (0..cpu_count).map(|cpu_id| CpuThermalState {
+            cpu_id,
+            temperature: 0,
+            action: ThermalAction::None,
+            freq_cap_percent: 100,
+            idle_inject_percent: 0,
+        }).collect();
+
+        Self {
+            config,
+            cpus,
+            stats: ThermalGovernorStats::default(),
+        }
+    }
+
+    /// Get the governor configuration
+    pub fn config(&self) -> &ThermalGovernorConfig {
+        &self.config
+    }
+
+    /// Sample all sensors and apply thermal actions.
+    ///
+    /// Returns the CPUs whose action changed or that need load migrated away.
+    pub fn update(&mut self, sensor: &mut dyn TemperatureSensor) -> Vec<ThermalDecision> {
+        let mut decisions = Vec::new();
+        self.stats.updates += 1;
+
+        for index in 0..self.cpus.len() {
+            let cpu_id = self.cpus[index].cpu_id;
+            let temperature = match sensor.read_celsius(cpu_id) {
+                Some(temperature) => temperature,
+                None => continue,
+            };
+
+            let previous = self.cpus[index].action;
+            let action = self.classify(temperature, previous);
+            self.apply(index, temperature, action);
+
+            let migrate_load = matches!(action, ThermalAction::ThrottleCPU | ThermalAction::Shutdown);
+            if migrate_load {
+                self.stats.migrations_requested += 1;
+            }
+            if action != previous || migrate_load {
+                decisions.push(ThermalDecision { cpu_id, action, migrate_load });
+            }
+        }
+
+        decisions
+    }
+
+    /// Pick the action for a temperature, releasing trip points with hysteresis
+    fn classify(&self, temperature: u8, previous: ThermalAction) -> ThermalAction {
+        let config = &self.config;
+        let release = |trip: u8| temperature.saturating_add(config.hysteresis) > trip;
+
+        if temperature >= config.critical_temp {
+            ThermalAction::Shutdown
+        } else if temperature >= config.throttle_temp
+            || (previous == ThermalAction::ThrottleCPU && release(config.throttle_temp))
+        {
+            ThermalAction::ThrottleCPU
+        } else if temperature >= config.passive_temp
+            || (previous != ThermalAction::None && release(config.passive_temp))
+        {
+            ThermalAction::PassiveCooling
+        } else {
+            ThermalAction::None
+        }
+    }
+
+    /// Adjust frequency cap and idle injection for an action
+    fn apply(&mut self, index: usize, temperature: u8, action: ThermalAction) {
+        let config = self.config.clone();
+        let cpu = &mut self.cpus[index];
+        cpu.temperature = temperature;
+        cpu.action = action;
+        self.stats.max_temperature = self.stats.max_temperature.max(temperature);
+
+        match action {
+            ThermalAction::Shutdown => {
+                cpu.freq_cap_percent = config.min_freq_percent;
+                cpu.idle_inject_percent = config.max_idle_inject_percent;
+                self.stats.critical_events += 1;
+            }
+            ThermalAction::ThrottleCPU => {
+                cpu.freq_cap_percent = cpu.freq_cap_percent
+                    .saturating_sub(config.freq_step_percent)
+                    .max(config.min_freq_percent);
+                cpu.idle_inject_percent = (cpu.idle_inject_percent + config.idle_inject_step_percent)
+                    .min(config.max_idle_inject_percent);
+                self.stats.throttle_events += 1;
+            }
+            ThermalAction::PassiveCooling | ThermalAction::ActiveCooling => {
+                cpu.freq_cap_percent = cpu.freq_cap_percent
+                    .saturating_sub(config.freq_step_percent)
+                    .max(config.min_freq_percent);
+                cpu.idle_inject_percent = cpu.idle_inject_percent
+                    .saturating_sub(config.idle_inject_step_percent);
+                self.stats.passive_events += 1;
+            }
+            ThermalAction::None => {
+                // Cooled down: step back towards full performance
+                cpu.freq_cap_percent = (cpu.freq_cap_percent + config.freq_step_percent).min(100);
+                cpu.idle_inject_percent = cpu.idle_inject_percent
+                    .saturating_sub(config.idle_inject_step_percent);
+            }
+        }
+    }
+
+    /// Thermal pressure of a CPU for the load balancer
+    pub fn pressure(&self, cpu_id: CpuId) -> f32 {
+        self.cpus.get(cpu_id).map_or(0.0, |cpu| cpu.pressure())
+    }
+
+    /// Frequency cap for a CPU given its maximum frequency
+    pub fn capped_frequency(&self, cpu_id: CpuId, max_freq_mhz: u32) -> u32 {
+        let cap = self.cpus.get(cpu_id).map_or(100, |cpu| cpu.freq_cap_percent);
+        max_freq_mhz * cap / 100
+    }
+
+    /// Get per-CPU thermal state
+    pub fn get_cpu_state(&self, cpu_id: CpuId) -> Option<&CpuThermalState> {
+        self.cpus.get(cpu_id)
+    }
+
+    /// Get governor statistics
+    pub fn get_stats(&self) -> ThermalGovernorStats {
+        self.stats
+    }
+}
+
+/// Simulated sensor driven by CPU utilization, for systems without thermal hardware
+#[derive(Debug, Clone)]
+pub struct SimulatedSensor {
+    /// Ambient temperature (Celsius)
+    pub ambient: u8,
+    /// Temperature rise at 100% utilization (Celsius)
+    pub max_rise: u8,
+    /// Current utilization per CPU (percent)
+    pub utilization: Vec<u32>,
+    temperatures: Vec<u8>,
+}
+
+impl SimulatedSensor {
+    /// Create a simulated sensor for `cpu_count` CPUs
+    pub fn new(cpu_count: usize, ambient: u8, max_rise: u8) -> Self {
+        Self {
+            ambient,
+            max_rise,
+            utilization: vec![0; cpu_count],
+            temperatures: vec![ambient; cpu_count],
+        }
+    }
+}
+
+impl TemperatureSensor for SimulatedSensor {
+    fn read_celsius(&mut self, cpu_id: CpuId) -> Option<u8> {
+        let util = (*self.utilization.get(cpu_id)?).min(100);
+        let target = self.ambient as u32 + self.max_rise as u32 * util / 100;
+        let current = self.temperatures[cpu_id] as u32;
+        // First-order lag towards the steady-state temperature
+        let next = (current * 3 + target) / 4;
+        self.temperatures[cpu_id] = next.min(u8::MAX as u32) as u8;
+        Some(self.temperatures[cpu_id])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct FixedSensor(Vec<u8>);
+
+    impl TemperatureSensor for FixedSensor {
+        fn read_celsius(&mut self, cpu_id: CpuId) -> Option<u8> {
+            self.0.get(cpu_id).copied()
+        }
+    }
+
+    #[test]
+    fn test_hot_core_is_throttled_and_migrated() {
+        let mut governor = ThermalGovernor::new(ThermalGovernorConfig::default(), 2);
+        let mut sensor = FixedSensor(vec![90, 40]);
+
+        let decisions = governor.update(&mut sensor);
+        assert_eq!(decisions, vec![ThermalDecision { cpu_id: 0, action: ThermalAction::ThrottleCPU, migrate_load: true }]);
+        assert_eq!(governor.get_cpu_state(0).unwrap().freq_cap_percent, 90);
+        assert!(governor.pressure(0) > governor.pressure(1));
+        assert_eq!(governor.capped_frequency(0, 3000), 2700);
+    }
+
+    #[test]
+    fn test_hysteresis_release() {
+        let mut governor = ThermalGovernor::new(ThermalGovernorConfig::default(), 1);
+
+        governor.update(&mut FixedSensor(vec![86]));
+        // Within hysteresis of the throttle trip point: stays throttled
+        governor.update(&mut FixedSensor(vec![82]));
+        assert_eq!(governor.get_cpu_state(0).unwrap().action, ThermalAction::ThrottleCPU);
+
+        governor.update(&mut FixedSensor(vec![78]));
+        assert_eq!(governor.get_cpu_state(0).unwrap().action, ThermalAction::PassiveCooling);
+
+        governor.update(&mut FixedSensor(vec![60]));
+        assert_eq!(governor.get_cpu_state(0).unwrap().action, ThermalAction::None);
+    }
+
+    #[test]
+    fn test_hysteresis_near_u8_max() {
+        let config = ThermalGovernorConfig {
+            passive_temp: 240,
+            throttle_temp: 250,
+            critical_temp: 255,
+            hysteresis: 10,
+            ..ThermalGovernorConfig::default()
+        };
+        let mut governor = ThermalGovernor::new(config, 1);
+
+        governor.update(&mut FixedSensor(vec![252]));
+        assert_eq!(governor.get_cpu_state(0).unwrap().action, ThermalAction::ThrottleCPU);
+        // 249 + hysteresis would overflow a u8
+        governor.update(&mut FixedSensor(vec![249]));
+        assert_eq!(governor.get_cpu_state(0).unwrap().action, ThermalAction::ThrottleCPU);
+
+        governor.update(&mut FixedSensor(vec![255]));
+        assert_eq!(governor.get_cpu_state(0).unwrap().action, ThermalAction::Shutdown);
+    }
+
+    #[test]
+    fn test_simulated_sensor_heats_with_load() {
+        let mut sensor = SimulatedSensor::new(1, 40, 60);
+        sensor.utilization[0] = 100;
+        let first = sensor.read_celsius(0).unwrap();
+        let second = sensor.read_celsius(0).unwrap();
+        assert!(second > first && first > 40);
+    }
+}
@@ -296,6 +296,16 @@ impl ThreadManager {
         result
     }
 
+    /// Get every thread of a process that has not terminated
+    pub fn get_live_threads_by_process(&self, process_id: usize) -> Vec<ThreadId> {
+        let threads = self.threads.lock();
+        threads.iter()
+            .flatten()
+            .filter(|tcb| tcb.process_id == process_id && !matches!(tcb.state, ThreadState::Terminated))
+            .map(|tcb| tcb.thread_id)
+            .collect()
+    }
+
     /// Get threads by priority
     pub fn get_threads_by_priority(&self, priority: Priority) -> Vec<ThreadId> {
         let threads = self.threads.lock();
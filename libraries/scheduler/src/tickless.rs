@@ -0,0 +1,396 @@
+//! Tickless Idle and Adaptive Timer Tick for MultiOS
+//!
+//! This module provides dynamic tick support for the multi-core scheduler:
+//! - Periodic ticks are suppressed while a CPU is idle
+//! - A one-shot timer is programmed for the next pending deadline instead
+//! - Busy CPUs running a single thread can stretch their tick (adaptive mode)
+//! - Isolated (nohz_full) CPUs stop the tick while running a single thread
+//! - Statistics on how many ticks were avoided per CPU
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::{SchedulerError, SchedulerResult};
+use crate::scheduler_algo::CpuId;
+
+/// Nanoseconds per millisecond
+const NS_PER_MS: u64 = 1_000_000;
+
+/// Tick operating mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TickMode {
+    /// Classic fixed-period tick on every CPU
+    Periodic,
+    /// Stop the tick only while a CPU is idle
+    IdleDynamic,
+    /// Stop the tick when idle and stretch it when a single thread is runnable
+    Adaptive,
+}
+
+/// Tickless configuration
+#[derive(Debug, Clone)]
+pub struct TicklessConfig {
+    /// Tick operating mode
+    pub mode: TickMode,
+    /// Base tick period (nanoseconds)
+    pub tick_period_ns: u64,
+    /// Minimum sleep worth stopping the tick for (nanoseconds)
+    pub min_idle_ns: u64,
+    /// Upper bound on a single one-shot sleep (nanoseconds)
+    pub max_idle_ns: u64,
+    /// Maximum stretch factor applied in adaptive mode
+    pub max_stretch: u32,
+}
+
+impl Default for TicklessConfig {
+    fn default() -> Self {
+        Self {
+            mode: TickMode::IdleDynamic,
+            tick_period_ns: 4 * NS_PER_MS, // 250 Hz
+            min_idle_ns: 2 * NS_PER_MS,
+            max_idle_ns: 1_000 * NS_PER_MS,
+            max_stretch: 8,
+        }
+    }
+}
+
+/// One-shot timer programming request handed to the clock event device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OneShotTimer {
+    pub cpu_id: CpuId,
+    /// Absolute expiry time (nanoseconds)
+    pub expires_ns: u64,
+}
+
+/// Per-CPU tick state
+#[derive(Debug, Clone)]
+pub struct CpuTickState {
+    pub cpu_id: CpuId,
+    /// Whether the periodic tick is currently stopped
+    pub tick_stopped: bool,
+    /// Time the tick was stopped (nanoseconds)
+    pub idle_entry_ns: u64,
+    /// Time of the last tick delivered
+    pub last_tick_ns: u64,
+    /// Next pending deadline on this CPU
+    pub next_deadline_ns: Option<u64>,
+    /// Current effective tick period
+    pub current_period_ns: u64,
+    /// Isolated CPU: the tick is stopped while a single thread runs
+    pub nohz_full: bool,
+}
+
+/// Tick reduction statistics
+#[derive(Debug, Default)]
+pub struct TickStats {
+    /// Ticks actually delivered
+    pub ticks_delivered: AtomicU64,
+    /// Ticks that would have fired but were skipped
+    pub ticks_skipped: AtomicU64,
+    /// One-shot timers programmed on idle entry
+    pub oneshots_programmed: AtomicU64,
+    /// Idle entries that kept the tick running (sleep too short)
+    pub short_idle_entries: AtomicU64,
+    /// Total time spent with the tick stopped (nanoseconds)
+    pub stopped_time_ns: AtomicU64,
+}
+
+/// Tick statistics snapshot
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TickStatsSnapshot {
+    pub ticks_delivered: u64,
+    pub ticks_skipped: u64,
+    pub oneshots_programmed: u64,
+    pub short_idle_entries: u64,
+    pub stopped_time_ns: u64,
+    /// Percentage of periodic ticks avoided
+    pub reduction_percent: f32,
+}
+
+/// Dynamic tick controller
+#[derive(Debug)]
+pub struct TickController {
+    config: TicklessConfig,
+    cpus: Vec<CpuTickState>,
+    stats: TickStats,
+}
+
+impl TickController {
+    /// Create a new tick controller for `cpu_count` CPUs
+    pub fn new(config: TicklessConfig, cpu_count: usize) -> Self {
+        let cpus = (0..cpu_count).map(|cpu_id| CpuTickState {
+            cpu_id,
+            tick_stopped: false,
+            idle_entry_ns: 0,
+            last_tick_ns: 0,
+            next_deadline_ns: None,
+            current_period_ns: config.tick_period_ns,
+            nohz_full: false,
+        }).collect();
+
+        Self {
+            config,
+            cpus,
+            stats: TickStats::default(),
+        }
+    }
+
+    /// Get the configured tick mode
+    pub fn mode(&self) -> TickMode {
+        self.config.mode
+    }
+
+    /// Mark a CPU as nohz_full so it runs tickless while busy with one thread
+    pub fn set_nohz_full(&mut self, cpu_id: CpuId, enabled: bool) -> SchedulerResult<()> {
+        let cpu = self.cpus.get_mut(cpu_id).ok_or(SchedulerError::InvalidThreadId)?;
+        cpu.nohz_full = enabled;
+        Ok(())
+    }
+
+    /// Check whether a CPU is nohz_full
+    pub fn is_nohz_full(&self, cpu_id: CpuId) -> bool {
+        self.cpus.get(cpu_id).map_or(false, |cpu| cpu.nohz_full)
+    }
+
+    /// Record a pending deadline (timer, RT period) for a CPU
+    pub fn set_next_deadline(&mut self, cpu_id: CpuId, deadline_ns: Option<u64>) -> SchedulerResult<()> {
+        let cpu = self.cpus.get_mut(cpu_id).ok_or(SchedulerError::InvalidThreadId)?;
+        cpu.next_deadline_ns = match (cpu.next_deadline_ns, deadline_ns) {
+            (Some(current), Some(new)) => Some(current.min(new)),
+            (_, new) => new,
+        };
+        Ok(())
+    }
+
+    /// Called when a CPU is about to enter idle.
+    ///
+    /// Returns the one-shot timer to program if the tick was stopped, or
+    /// `None` if the periodic tick should keep running.
+    pub fn enter_idle(&mut self, cpu_id: CpuId, now_ns: u64) -> SchedulerResult<Option<OneShotTimer>> {
+        if self.config.mode == TickMode::Periodic {
+            return Ok(None);
+        }
+
+        let min_idle = self.config.min_idle_ns;
+        let max_idle = self.config.max_idle_ns;
+        let cpu = self.cpus.get_mut(cpu_id).ok_or(SchedulerError::InvalidThreadId)?;
+
+        let expires_ns = match cpu.next_deadline_ns {
+            Some(deadline) => deadline.min(now_ns.saturating_add(max_idle)),
+            None => now_ns.saturating_add(max_idle),
+        };
+
+        if expires_ns.saturating_sub(now_ns) < min_idle {
+            self.stats.short_idle_entries.fetch_add(1, Ordering::Relaxed);
+            return Ok(None);
+        }
+
+        cpu.tick_stopped = true;
+        cpu.idle_entry_ns = now_ns;
+        self.stats.oneshots_programmed.fetch_add(1, Ordering::Relaxed);
+
+        Ok(Some(OneShotTimer { cpu_id, expires_ns }))
+    }
+
+    /// Called when a CPU leaves idle (interrupt, wakeup or one-shot expiry).
+    ///
+    /// Accounts the ticks that were skipped and restarts the periodic tick.
+    pub fn exit_idle(&mut self, cpu_id: CpuId, now_ns: u64) -> SchedulerResult<u64> {
+        let period = self.config.tick_period_ns.max(1);
+        let cpu = self.cpus.get_mut(cpu_id).ok_or(SchedulerError::InvalidThreadId)?;
+
+        if !cpu.tick_stopped {
+            return Ok(0);
+        }
+
+        let stopped_for = now_ns.saturating_sub(cpu.idle_entry_ns);
+        let skipped = stopped_for / period;
+
+        cpu.tick_stopped = false;
+        cpu.last_tick_ns = now_ns;
+        cpu.current_period_ns = period;
+        if cpu.next_deadline_ns.map_or(false, |deadline| deadline <= now_ns) {
+            cpu.next_deadline_ns = None;
+        }
+
+        self.stats.ticks_skipped.fetch_add(skipped, Ordering::Relaxed);
+        self.stats.stopped_time_ns.fetch_add(stopped_for, Ordering::Relaxed);
+
+        Ok(skipped)
+    }
+
+    /// Record a delivered tick.
+    ///
+    /// Periods a stretched tick covered since the previous one count as
+    /// skipped; this is the only place busy-CPU skips are accounted.
+    pub fn on_tick(&mut self, cpu_id: CpuId, now_ns: u64) -> SchedulerResult<()> {
+        let period = self.config.tick_period_ns.max(1);
+        let cpu = self.cpus.get_mut(cpu_id).ok_or(SchedulerError::InvalidThreadId)?;
+
+        // The first tick has no previous one to measure from
+        if cpu.last_tick_ns != 0 {
+            let elapsed = now_ns.saturating_sub(cpu.last_tick_ns);
+            let skipped = (elapsed / period).saturating_sub(1);
+            self.stats.ticks_skipped.fetch_add(skipped, Ordering::Relaxed);
+        }
+        cpu.last_tick_ns = now_ns;
+        if cpu.next_deadline_ns.map_or(false, |deadline| deadline <= now_ns) {
+            cpu.next_deadline_ns = None;
+        }
+
+        self.stats.ticks_delivered.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Compute the tick period for a busy CPU.
+    ///
+    /// In adaptive mode a CPU with a single runnable thread stretches its tick
+    /// up to `max_stretch` periods or the next deadline, whichever is sooner.
+    /// A nohz_full CPU with a single runnable thread goes up to `max_idle_ns`
+    /// in any mode but periodic. Skipped ticks are counted by `on_tick()`
+    /// once the stretched tick actually fires.
+    pub fn adapt_tick(&mut self, cpu_id: CpuId, runnable: usize, now_ns: u64) -> SchedulerResult<u64> {
+        let base = self.config.tick_period_ns;
+        let adaptive = self.config.mode == TickMode::Adaptive;
+        let periodic = self.config.mode == TickMode::Periodic;
+        let max_stretch = self.config.max_stretch.max(1) as u64;
+        let max_idle = self.config.max_idle_ns;
+        let cpu = self.cpus.get_mut(cpu_id).ok_or(SchedulerError::InvalidThreadId)?;
+
+        let mut period = base;
+        if runnable <= 1 && (adaptive || (cpu.nohz_full && !periodic)) {
+            period = if cpu.nohz_full { max_idle.max(base) } else { base * max_stretch };
+            if let Some(deadline) = cpu.next_deadline_ns {
+                period = period.min(deadline.saturating_sub(now_ns).max(base));
+            }
+        }
+
+        cpu.current_period_ns = period;
+        Ok(period)
+    }
+
+    /// Check whether a CPU currently has its tick stopped
+    pub fn is_tick_stopped(&self, cpu_id: CpuId) -> bool {
+        self.cpus.get(cpu_id).map_or(false, |cpu| cpu.tick_stopped)
+    }
+
+    /// Get per-CPU tick state
+    pub fn get_cpu_state(&self, cpu_id: CpuId) -> Option<&CpuTickState> {
+        self.cpus.get(cpu_id)
+    }
+
+    /// Get tick reduction statistics
+    pub fn get_stats(&self) -> TickStatsSnapshot {
+        let delivered = self.stats.ticks_delivered.load(Ordering::Relaxed);
+        let skipped = self.stats.ticks_skipped.load(Ordering::Relaxed);
+        let total = delivered + skipped;
+
+        TickStatsSnapshot {
+            ticks_delivered: delivered,
+            ticks_skipped: skipped,
+            oneshots_programmed: self.stats.oneshots_programmed.load(Ordering::Relaxed),
+            short_idle_entries: self.stats.short_idle_entries.load(Ordering::Relaxed),
+            stopped_time_ns: self.stats.stopped_time_ns.load(Ordering::Relaxed),
+            reduction_percent: if total == 0 {
+                0.0
+            } else {
+                skipped as f32 * 100.0 / total as f32
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_idle_stops_tick_until_deadline() {
+        let mut controller = TickController::new(TicklessConfig::default(), 2);
+        controller.set_next_deadline(0, Some(100 * NS_PER_MS)).unwrap();
+
+        let timer = controller.enter_idle(0, 0).unwrap();
+        assert_eq!(timer, Some(OneShotTimer { cpu_id: 0, expires_ns: 100 * NS_PER_MS }));
+        assert!(controller.is_tick_stopped(0));
+
+        let skipped = controller.exit_idle(0, 100 * NS_PER_MS).unwrap();
+        assert_eq!(skipped, 25);
+        assert!(!controller.is_tick_stopped(0));
+        assert!(controller.get_stats().reduction_percent > 99.0);
+    }
+
+    #[test]
+    fn test_short_idle_keeps_tick() {
+        let mut controller = TickController::new(TicklessConfig::default(), 1);
+        controller.set_next_deadline(0, Some(NS_PER_MS)).unwrap();
+
+        assert_eq!(controller.enter_idle(0, 0).unwrap(), None);
+        assert_eq!(controller.get_stats().short_idle_entries, 1);
+    }
+
+    #[test]
+    fn test_periodic_mode_never_stops() {
+        let config = TicklessConfig { mode: TickMode::Periodic, ..Default::default() };
+        let mut controller = TickController::new(config, 1);
+        assert_eq!(controller.enter_idle(0, 0).unwrap(), None);
+    }
+
+    #[test]
+    fn test_adaptive_tick_stretch() {
+        let config = TicklessConfig { mode: TickMode::Adaptive, ..Default::default() };
+        let base = config.tick_period_ns;
+        let mut controller = TickController::new(config, 1);
+
+        assert_eq!(controller.adapt_tick(0, 1, 0).unwrap(), base * 8);
+        assert_eq!(controller.adapt_tick(0, 3, 0).unwrap(), base);
+    }
+
+    #[test]
+    fn test_nohz_full_stops_busy_tick() {
+        let config = TicklessConfig::default();
+        let base = config.tick_period_ns;
+        let max_idle = config.max_idle_ns;
+        let mut controller = TickController::new(config, 2);
+        controller.set_nohz_full(1, true).unwrap();
+
+        assert_eq!(controller.adapt_tick(0, 1, 0).unwrap(), base);
+        assert_eq!(controller.adapt_tick(1, 1, 0).unwrap(), max_idle);
+        assert_eq!(controller.adapt_tick(1, 2, 0).unwrap(), base);
+
+        controller.set_next_deadline(1, Some(50 * NS_PER_MS)).unwrap();
+        assert_eq!(controller.adapt_tick(1, 1, 0).unwrap(), 50 * NS_PER_MS);
+    }
+
+    #[test]
+    fn test_stretched_tick_skips_are_counted_once() {
+        let config = TicklessConfig { mode: TickMode::Adaptive, ..Default::default() };
+        let base = config.tick_period_ns;
+        let mut controller = TickController::new(config, 1);
+
+        controller.on_tick(0, base).unwrap();
+        // Recomputing the period before the tick fires adds nothing
+        assert_eq!(controller.adapt_tick(0, 1, base).unwrap(), base * 8);
+        assert_eq!(controller.adapt_tick(0, 1, base).unwrap(), base * 8);
+        assert_eq!(controller.get_stats().ticks_skipped, 0);
+
+        controller.on_tick(0, base * 9).unwrap();
+        let stats = controller.get_stats();
+        assert_eq!(stats.ticks_delivered, 2);
+        assert_eq!(stats.ticks_skipped, 7);
+
+        // A wakeup that cuts the stretch short only counts what elapsed
+        controller.on_tick(0, base * 12).unwrap();
+        assert_eq!(controller.get_stats().ticks_skipped, 9);
+    }
+
+    #[test]
+    fn test_tick_clears_passed_deadline() {
+        let mut controller = TickController::new(TicklessConfig::default(), 1);
+        controller.set_next_deadline(0, Some(10 * NS_PER_MS)).unwrap();
+
+        controller.on_tick(0, 8 * NS_PER_MS).unwrap();
+        assert_eq!(controller.get_cpu_state(0).unwrap().next_deadline_ns, Some(10 * NS_PER_MS));
+        controller.on_tick(0, 12 * NS_PER_MS).unwrap();
+        assert_eq!(controller.get_cpu_state(0).unwrap().next_deadline_ns, None);
+    }
+}
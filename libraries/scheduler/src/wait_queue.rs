@@ -0,0 +1,566 @@
+//! Wait Queues and Priority-Inheritance Mutexes for MultiOS
+//!
+//! Kernel-level blocking primitives:
+//! - Priority-ordered wait queues (FIFO among equal priorities)
+//! - Priority-inheritance mutexes: a thread blocking on a lock lends its
+//!   priority to the holder, transitively along the chain of owners that
+//!   are themselves blocked on other PI mutexes
+//! - Deadlock detection on lock chains and statistics on inversion events
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+
+use crate::Priority;
+use crate::thread::{ThreadId, THREAD_MANAGER};
+
+/// Longest owner chain walked when propagating a boost
+pub const MAX_PI_CHAIN_DEPTH: usize = 32;
+
+/// PI mutex identifier
+pub type PiMutexId = usize;
+
+/// PI mutex result type
+pub type PiMutexResult<T> = Result<T, PiMutexError>;
+
+/// PI mutex errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PiMutexError {
+    /// No such mutex
+    InvalidMutex,
+    /// Caller does not own the mutex
+    NotOwner,
+    /// Blocking would close a cycle of owners (or the caller owns it already)
+    Deadlock,
+    /// Mutex is held or has waiters
+    Busy,
+    /// Owner chain is longer than `MAX_PI_CHAIN_DEPTH`
+    ChainTooDeep,
+}
+
+/// A thread blocked on a wait queue
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WaitQueueEntry {
+    pub thread_id: ThreadId,
+    pub priority: Priority,
+}
+
+/// Priority-ordered wait queue
+///
+/// The highest priority waiter is woken first; waiters of equal priority are
+/// woken in arrival order. The queue does no locking of its own, callers
+/// keep it under the lock of the object being waited on.
+#[derive(Debug, Clone, Default)]
+pub struct WaitQueue {
+    waiters: VecDeque<WaitQueueEntry>,
+}
+
+impl WaitQueue {
+    /// Create an empty wait queue
+    pub const fn new() -> Self {
+        Self { waiters: VecDeque::new() }
+    }
+
+    /// Add a waiter behind all waiters of the same or higher priority
+    pub fn enqueue(&mut self, thread_id: ThreadId, priority: Priority) {
+        let pos = self.waiters.iter().position(|w| w.priority < priority).unwrap_or(self.waiters.len());
+        self.waiters.insert(pos, WaitQueueEntry { thread_id, priority });
+    }
+
+    /// Remove and return the highest-priority waiter
+    pub fn wake_one(&mut self) -> Option<ThreadId> {
+        self.waiters.pop_front().map(|w| w.thread_id)
+    }
+
+    /// Remove and return all waiters, highest priority first
+    pub fn wake_all(&mut self) -> Vec<ThreadId> {
+        self.waiters.drain(..).map(|w| w.thread_id).collect()
+    }
+
+    /// Remove a waiter (timeout or signal)
+    pub fn remove(&mut self, thread_id: ThreadId) -> bool {
+        let before = self.waiters.len();
+        self.waiters.retain(|w| w.thread_id != thread_id);
+        self.waiters.len() != before
+    }
+
+    /// Re-sort a waiter whose priority changed
+    pub fn reprioritize(&mut self, thread_id: ThreadId, priority: Priority) -> bool {
+        if !self.remove(thread_id) {
+            return false;
+        }
+        self.enqueue(thread_id, priority);
+        true
+    }
+
+    /// Highest-priority waiter without removing it
+    pub fn peek(&self) -> Option<&WaitQueueEntry> {
+        self.waiters.front()
+    }
+
+    pub fn contains(&self, thread_id: ThreadId) -> bool {
+        self.waiters.iter().any(|w| w.thread_id == thread_id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.waiters.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.waiters.is_empty()
+    }
+}
+
+/// Change of a thread's effective priority caused by priority inheritance
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PriorityChange {
+    pub thread_id: ThreadId,
+    pub from: Priority,
+    pub to: Priority,
+}
+
+/// Result of a PI mutex lock attempt
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PiLockOutcome {
+    /// The lock was free and is now held by the caller
+    Acquired,
+    /// The caller was queued; owners along the chain may have been boosted
+    Blocked {
+        owner: ThreadId,
+        changes: Vec<PriorityChange>,
+    },
+}
+
+/// Result of a PI mutex unlock
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PiUnlockOutcome {
+    /// Waiter the lock was handed to
+    pub next_owner: Option<ThreadId>,
+    /// Deboost of the old owner and boost of the new one
+    pub changes: Vec<PriorityChange>,
+}
+
+/// PI mutex statistics
+#[derive(Debug, Default)]
+pub struct PiMutexStats {
+    pub acquisitions: AtomicU64,
+    pub contended: AtomicU64,
+    /// A waiter blocked behind a lower-priority owner
+    pub inversions: AtomicU64,
+    pub boosts: AtomicU64,
+    pub deboosts: AtomicU64,
+    pub deadlocks: AtomicU64,
+    pub max_chain_length: AtomicU64,
+}
+
+/// PI mutex statistics snapshot
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PiMutexStatsSnapshot {
+    pub acquisitions: u64,
+    pub contended: u64,
+    pub inversions: u64,
+    pub boosts: u64,
+    pub deboosts: u64,
+    pub deadlocks: u64,
+    pub max_chain_length: u64,
+}
+
+impl PiMutexStats {
+    const fn new() -> Self {
+        Self {
+            acquisitions: AtomicU64::new(0),
+            contended: AtomicU64::new(0),
+            inversions: AtomicU64::new(0),
+            boosts: AtomicU64::new(0),
+            deboosts: AtomicU64::new(0),
+            deadlocks: AtomicU64::new(0),
+            max_chain_length: AtomicU64::new(0),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct PiMutexState {
+    owner: Option<ThreadId>,
+    waiters: WaitQueue,
+}
+
+/// PI bookkeeping of a thread that holds or waits on PI mutexes
+#[derive(Debug, Clone)]
+struct PiThread {
+    base: Priority,
+    effective: Priority,
+    blocked_on: Option<PiMutexId>,
+    held: Vec<PiMutexId>,
+}
+
+#[derive(Debug)]
+struct PiState {
+    mutexes: BTreeMap<PiMutexId, PiMutexState>,
+    threads: BTreeMap<ThreadId, PiThread>,
+    next_id: PiMutexId,
+}
+
+/// Priority-inheritance mutex manager
+///
+/// Owns every PI mutex so boosts can follow owner chains across locks.
+/// Priority changes are returned to the caller; `apply_priority_changes`
+/// pushes them to the thread manager.
+#[derive(Debug)]
+pub struct PiMutexManager {
+    state: Mutex<PiState>,
+    stats: PiMutexStats,
+}
+
+impl PiMutexManager {
+    /// Create an empty manager
+    pub const fn new() -> Self {
+        Self {
+            state: Mutex::new(PiState {
+                mutexes: BTreeMap::new(),
+                threads: BTreeMap::new(),
+                next_id: 1,
+            }),
+            stats: PiMutexStats::new(),
+        }
+    }
+
+    /// Create an unlocked PI mutex
+    pub fn create(&self) -> PiMutexId {
+        let mut state = self.state.lock();
+        let id = state.next_id;
+        state.next_id += 1;
+        state.mutexes.insert(id, PiMutexState::default());
+        id
+    }
+
+    /// Destroy an unlocked PI mutex
+    pub fn destroy(&self, id: PiMutexId) -> PiMutexResult<()> {
+        let mut state = self.state.lock();
+        let mutex = state.mutexes.get(&id).ok_or(PiMutexError::InvalidMutex)?;
+        if mutex.owner.is_some() || !mutex.waiters.is_empty() {
+            return Err(PiMutexError::Busy);
+        }
+        state.mutexes.remove(&id);
+        Ok(())
+    }
+
+    /// Take the mutex if it is free
+    pub fn try_lock(&self, id: PiMutexId, thread_id: ThreadId, priority: Priority) -> PiMutexResult<bool> {
+        let mut state = self.state.lock();
+        self.try_acquire(&mut state, id, thread_id, priority)
+    }
+
+    /// Acquire the mutex or queue the caller on it.
+    ///
+    /// When queued, the owner and every owner further down the chain inherit
+    /// the caller's priority if it is higher than theirs.
+    pub fn lock(&self, id: PiMutexId, thread_id: ThreadId, priority: Priority) -> PiMutexResult<PiLockOutcome> {
+        let mut state = self.state.lock();
+        if self.try_acquire(&mut state, id, thread_id, priority)? {
+            return Ok(PiLockOutcome::Acquired);
+        }
+        let owner = state.mutexes.get(&id).and_then(|m| m.owner).ok_or(PiMutexError::InvalidMutex)?;
+
+        // Blocking must not close a cycle back to the caller
+        let chain = Self::owner_chain(&state, id)?;
+        if chain.contains(&thread_id) {
+            self.stats.deadlocks.fetch_add(1, Ordering::Relaxed);
+            return Err(PiMutexError::Deadlock);
+        }
+
+        let effective = Self::thread_entry(&mut state, thread_id, priority).effective;
+        Self::thread_entry(&mut state, thread_id, priority).blocked_on = Some(id);
+        if let Some(mutex) = state.mutexes.get_mut(&id) {
+            mutex.waiters.enqueue(thread_id, effective);
+        }
+        self.stats.contended.fetch_add(1, Ordering::Relaxed);
+        if state.threads.get(&owner).map_or(false, |t| t.effective < effective) {
+            self.stats.inversions.fetch_add(1, Ordering::Relaxed);
+        }
+        self.stats.max_chain_length.fetch_max(chain.len() as u64, Ordering::Relaxed);
+
+        let changes = self.propagate(&mut state, owner);
+        Ok(PiLockOutcome::Blocked { owner, changes })
+    }
+
+    /// Release the mutex and hand it to the highest-priority waiter
+    pub fn unlock(&self, id: PiMutexId, thread_id: ThreadId) -> PiMutexResult<PiUnlockOutcome> {
+        let mut state = self.state.lock();
+        let mutex = state.mutexes.get_mut(&id).ok_or(PiMutexError::InvalidMutex)?;
+        if mutex.owner != Some(thread_id) {
+            return Err(PiMutexError::NotOwner);
+        }
+
+        let next_owner = mutex.waiters.wake_one();
+        mutex.owner = next_owner;
+
+        if let Some(thread) = state.threads.get_mut(&thread_id) {
+            thread.held.retain(|&held| held != id);
+        }
+        if let Some(next) = next_owner {
+            if let Some(thread) = state.threads.get_mut(&next) {
+                thread.blocked_on = None;
+                thread.held.push(id);
+            }
+            self.stats.acquisitions.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let mut outcome = PiUnlockOutcome { next_owner, changes: Vec::new() };
+        outcome.changes.extend(self.propagate(&mut state, thread_id));
+        if let Some(next) = next_owner {
+            outcome.changes.extend(self.propagate(&mut state, next));
+        }
+        Self::forget_idle(&mut state, thread_id);
+        Ok(outcome)
+    }
+
+    /// Stop waiting on a mutex (timeout or signal), undoing the boosts the
+    /// waiter caused
+    pub fn cancel_wait(&self, id: PiMutexId, thread_id: ThreadId) -> PiMutexResult<Vec<PriorityChange>> {
+        let mut state = self.state.lock();
+        let mutex = state.mutexes.get_mut(&id).ok_or(PiMutexError::InvalidMutex)?;
+        if !mutex.waiters.remove(thread_id) {
+            return Ok(Vec::new());
+        }
+        let owner = mutex.owner;
+
+        if let Some(thread) = state.threads.get_mut(&thread_id) {
+            thread.blocked_on = None;
+        }
+        Self::forget_idle(&mut state, thread_id);
+
+        Ok(match owner {
+            Some(owner) => self.propagate(&mut state, owner),
+            None => Vec::new(),
+        })
+    }
+
+    /// Current owner of a mutex
+    pub fn owner(&self, id: PiMutexId) -> Option<ThreadId> {
+        self.state.lock().mutexes.get(&id).and_then(|m| m.owner)
+    }
+
+    /// Number of threads waiting on a mutex
+    pub fn waiter_count(&self, id: PiMutexId) -> usize {
+        self.state.lock().mutexes.get(&id).map_or(0, |m| m.waiters.len())
+    }
+
+    /// Effective priority of a thread holding or waiting on PI mutexes
+    pub fn effective_priority(&self, thread_id: ThreadId) -> Option<Priority> {
+        self.state.lock().threads.get(&thread_id).map(|t| t.effective)
+    }
+
+    /// Owners a blocked thread is transitively waiting on, nearest first
+    pub fn pi_chain(&self, thread_id: ThreadId) -> Vec<ThreadId> {
+        let state = self.state.lock();
+        match state.threads.get(&thread_id).and_then(|t| t.blocked_on) {
+            Some(id) => Self::owner_chain(&state, id).unwrap_or_default(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Get PI mutex statistics
+    pub fn get_stats(&self) -> PiMutexStatsSnapshot {
+        PiMutexStatsSnapshot {
+            acquisitions: self.stats.acquisitions.load(Ordering::Relaxed),
+            contended: self.stats.contended.load(Ordering::Relaxed),
+            inversions: self.stats.inversions.load(Ordering::Relaxed),
+            boosts: self.stats.boosts.load(Ordering::Relaxed),
+            deboosts: self.stats.deboosts.load(Ordering::Relaxed),
+            deadlocks: self.stats.deadlocks.load(Ordering::Relaxed),
+            max_chain_length: self.stats.max_chain_length.load(Ordering::Relaxed),
+        }
+    }
+
+    fn try_acquire(&self, state: &mut PiState, id: PiMutexId, thread_id: ThreadId, priority: Priority) -> PiMutexResult<bool> {
+        let mutex = state.mutexes.get_mut(&id).ok_or(PiMutexError::InvalidMutex)?;
+        match mutex.owner {
+            None => {
+                mutex.owner = Some(thread_id);
+                Self::thread_entry(state, thread_id, priority).held.push(id);
+                self.stats.acquisitions.fetch_add(1, Ordering::Relaxed);
+                Ok(true)
+            }
+            Some(owner) if owner == thread_id => Err(PiMutexError::Deadlock),
+            Some(_) => Ok(false),
+        }
+    }
+
+    fn thread_entry(state: &mut PiState, thread_id: ThreadId, priority: Priority) -> &mut PiThread {
+        state.threads.entry(thread_id).or_insert_with(|| PiThread {
+            base: priority,
+            effective: priority,
+            blocked_on: None,
+            held: Vec::new(),
+        })
+    }
+
+    /// Owners starting at the owner of `id`, following what each is blocked on
+    fn owner_chain(state: &PiState, id: PiMutexId) -> PiMutexResult<Vec<ThreadId>> {
+        let mut chain = Vec::new();
+        let mut next = state.mutexes.get(&id).and_then(|m| m.owner);
+        while let Some(owner) = next {
+            if chain.contains(&owner) {
+                break;
+            }
+            if chain.len() == MAX_PI_CHAIN_DEPTH {
+                return Err(PiMutexError::ChainTooDeep);
+            }
+            chain.push(owner);
+            next = state
+                .threads
+                .get(&owner)
+                .and_then(|t| t.blocked_on)
+                .and_then(|blocked| state.mutexes.get(&blocked))
+                .and_then(|m| m.owner);
+        }
+        Ok(chain)
+    }
+
+    /// Recompute effective priorities from `start` down its owner chain
+    fn propagate(&self, state: &mut PiState, start: ThreadId) -> Vec<PriorityChange> {
+        let mut changes = Vec::new();
+        let mut current = Some(start);
+
+        for _ in 0..MAX_PI_CHAIN_DEPTH {
+            let thread_id = match current {
+                Some(thread_id) => thread_id,
+                None => break,
+            };
+            let thread = match state.threads.get(&thread_id) {
+                Some(thread) => thread.clone(),
+                None => break,
+            };
+
+            // Base priority raised by the top waiter of every held mutex
+            let mut effective = thread.base;
+            for held in &thread.held {
+                if let Some(top) = state.mutexes.get(held).and_then(|m| m.waiters.peek()) {
+                    if top.priority > effective {
+                        effective = top.priority;
+                    }
+                }
+            }
+            if effective == thread.effective {
+                break;
+            }
+
+            if effective > thread.effective {
+                self.stats.boosts.fetch_add(1, Ordering::Relaxed);
+            } else {
+                self.stats.deboosts.fetch_add(1, Ordering::Relaxed);
+            }
+            changes.push(PriorityChange { thread_id, from: thread.effective, to: effective });
+            if let Some(entry) = state.threads.get_mut(&thread_id) {
+                entry.effective = effective;
+            }
+
+            // A blocked owner moves within its queue and passes the change on
+            current = None;
+            if let Some(blocked) = thread.blocked_on {
+                if let Some(mutex) = state.mutexes.get_mut(&blocked) {
+                    mutex.waiters.reprioritize(thread_id, effective);
+                    current = mutex.owner;
+                }
+            }
+        }
+
+        changes
+    }
+
+    /// Drop bookkeeping of a thread that neither holds nor waits on a PI mutex
+    fn forget_idle(state: &mut PiState, thread_id: ThreadId) {
+        if state.threads.get(&thread_id).map_or(false, |t| t.held.is_empty() && t.blocked_on.is_none()) {
+            state.threads.remove(&thread_id);
+        }
+    }
+}
+
+/// Global PI mutex manager instance
+pub static PI_MUTEX_MANAGER: PiMutexManager = PiMutexManager::new();
+
+/// Push priority-inheritance changes to the thread manager
+pub fn apply_priority_changes(changes: &[PriorityChange]) {
+    for change in changes {
+        let _ = THREAD_MANAGER.set_thread_priority(change.thread_id, change.to);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wait_queue_priority_order() {
+        let mut queue = WaitQueue::new();
+        queue.enqueue(1, Priority::Low);
+        queue.enqueue(2, Priority::High);
+        queue.enqueue(3, Priority::High);
+        queue.enqueue(4, Priority::Normal);
+
+        assert_eq!(queue.wake_one(), Some(2));
+        assert!(queue.reprioritize(1, Priority::Critical));
+        assert_eq!(queue.wake_all(), vec![1, 3, 4]);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn test_pi_mutex_boosts_and_deboosts_owner() {
+        let manager = PiMutexManager::new();
+        let mutex = manager.create();
+
+        assert_eq!(manager.lock(mutex, 1, Priority::Low), Ok(PiLockOutcome::Acquired));
+        assert_eq!(manager.lock(mutex, 1, Priority::Low), Err(PiMutexError::Deadlock));
+
+        let outcome = manager.lock(mutex, 2, Priority::Critical).unwrap();
+        assert_eq!(outcome, PiLockOutcome::Blocked {
+            owner: 1,
+            changes: vec![PriorityChange { thread_id: 1, from: Priority::Low, to: Priority::Critical }],
+        });
+        assert_eq!(manager.effective_priority(1), Some(Priority::Critical));
+        assert_eq!(manager.get_stats().inversions, 1);
+
+        assert_eq!(manager.unlock(mutex, 2), Err(PiMutexError::NotOwner));
+        let unlocked = manager.unlock(mutex, 1).unwrap();
+        assert_eq!(unlocked.next_owner, Some(2));
+        assert_eq!(manager.owner(mutex), Some(2));
+        assert_eq!(manager.effective_priority(1), None);
+        assert_eq!(manager.get_stats().deboosts, 1);
+    }
+
+    #[test]
+    fn test_pi_chain_propagates_transitively() {
+        let manager = PiMutexManager::new();
+        let first = manager.create();
+        let second = manager.create();
+
+        manager.lock(first, 1, Priority::Low).unwrap();
+        manager.lock(second, 2, Priority::Normal).unwrap();
+        manager.lock(first, 2, Priority::Normal).unwrap();
+        manager.lock(second, 3, Priority::Critical).unwrap();
+
+        assert_eq!(manager.pi_chain(3), vec![2, 1]);
+        assert_eq!(manager.effective_priority(2), Some(Priority::Critical));
+        assert_eq!(manager.effective_priority(1), Some(Priority::Critical));
+
+        // A timed-out top waiter takes its boost back down the whole chain
+        assert_eq!(manager.cancel_wait(second, 3).unwrap().len(), 2);
+        assert_eq!(manager.effective_priority(1), Some(Priority::Normal));
+    }
+
+    #[test]
+    fn test_pi_deadlock_detected() {
+        let manager = PiMutexManager::new();
+        let a = manager.create();
+        let b = manager.create();
+
+        manager.lock(a, 1, Priority::Normal).unwrap();
+        manager.lock(b, 2, Priority::Normal).unwrap();
+        manager.lock(b, 1, Priority::Normal).unwrap();
+        assert_eq!(manager.lock(a, 2, Priority::Normal), Err(PiMutexError::Deadlock));
+        assert_eq!(manager.get_stats().deadlocks, 1);
+        assert_eq!(manager.destroy(a), Err(PiMutexError::Busy));
+    }
+}
@@ -155,6 +155,17 @@ pub mod syscall {
     pub const SYSCALL_GETPID: u64 = 8;
     pub const SYSCALL_FORK: u64 = 9;
     pub const SYSCALL_EXEC: u64 = 10;
+    pub const SYSCALL_CLONE: u64 = 11;
+    
+    /// Flags accepted by SYSCALL_CLONE
+    pub const CLONE_VM: u64 = 0x0000_0100;
+    pub const CLONE_FS: u64 = 0x0000_0200;
+    pub const CLONE_FILES: u64 = 0x0000_0400;
+    pub const CLONE_SIGHAND: u64 = 0x0000_0800;
+    pub const CLONE_THREAD: u64 = 0x0001_0000;
+    pub const CLONE_SYSVSEM: u64 = 0x0004_0000;
+    pub const CLONE_PARENT_SETTID: u64 = 0x0010_0000;
+    pub const CLONE_CHILD_CLEARTID: u64 = 0x0020_0000;
     
     /// Maximum number of system call parameters
     pub const MAX_SYSCALL_PARAMS: usize = 6;
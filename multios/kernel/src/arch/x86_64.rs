@@ -412,6 +412,18 @@ fn handle_syscall(mut context: syscall::SyscallContext) -> Result<i64, i32> {
             )
         }
         
+        syscall::SYSCALL_CLONE => {
+            debug!("SYSCALL_CLONE: create thread");
+            handle_syscall_clone(
+                context.parameters[0],
+                context.parameters[1] as usize,
+                context.parameters[2] as *mut u32,
+                context.parameters[3] as *mut u32,
+                context.parameters[4] as usize,
+                context.parameters[5] as usize,
+            )
+        }
+        
         0 => {
             // Test system call
             debug!("Test system call received");
@@ -525,6 +537,32 @@ fn handle_syscall_exec(pathname: *const u8, argv: *const *const u8) -> Result<i6
     Ok(0)
 }
 
+fn handle_syscall_clone(
+    flags: u64,
+    stack: usize,
+    parent_tid: *mut u32,
+    child_tid: *mut u32,
+    entry: usize,
+    arg: usize,
+) -> Result<i64, i32> {
+    if stack == 0 || entry == 0 {
+        return Err(libc::EFAULT);
+    }
+    if syscall::validate_clone_flags(flags).is_err() {
+        return Err(libc::EINVAL);
+    }
+    if flags & super::syscall::CLONE_PARENT_SETTID != 0 && parent_tid.is_null() {
+        return Err(libc::EFAULT);
+    }
+    
+    let clear_tid = if flags & super::syscall::CLONE_CHILD_CLEARTID != 0 { child_tid as usize } else { 0 };
+    let tid = crate::scheduler::create_thread(entry, arg, stack, clear_tid).map_err(|_| libc::EAGAIN)?;
+    if flags & super::syscall::CLONE_PARENT_SETTID != 0 {
+        unsafe { parent_tid.write_volatile(tid) };
+    }
+    Ok(tid as i64)
+}
+
 // Helper Functions
 
 fn get_cr2() -> u64 {
@@ -4,11 +4,15 @@
 
 use crate::KernelResult;
 use log::debug;
+use core::sync::atomic::{AtomicU32, Ordering};
 
 /// Process/Thread ID type
 pub type ProcessId = u32;
 pub type ThreadId = u32;
 
+/// Next thread ID to hand out; thread 1 is the initial thread
+static NEXT_THREAD_ID: AtomicU32 = AtomicU32::new(2);
+
 /// Process state
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ProcessState {
@@ -75,6 +79,22 @@ pub fn create_process(_executable: &[u8]) -> KernelResult<ProcessId> {
     Ok(1)
 }
 
+/// Create a thread in the current process
+///
+/// The thread starts at `entry(arg)` on `stack`. If `_clear_tid` is non-zero,
+/// the word at that address is zeroed and futex-woken when the thread exits.
+pub fn create_thread(entry: usize, arg: usize, stack: usize, _clear_tid: usize) -> KernelResult<ThreadId> {
+    debug!("Creating thread at {:#x}({:#x}) on stack {:#x}...", entry, arg, stack);
+    
+    // TODO: Implement thread creation
+    // - Allocate thread control block in the current process
+    // - Set up initial registers for entry(arg) on stack
+    // - Record clear_tid for the exit path
+    // - Add to run queue
+    
+    Ok(NEXT_THREAD_ID.fetch_add(1, Ordering::Relaxed))
+}
+
 /// Terminate process
 pub fn terminate_process(_pid: ProcessId) -> KernelResult<()> {
     debug!("Terminating process {}...", _pid);
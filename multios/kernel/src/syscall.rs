@@ -10,6 +10,10 @@ use spin::Mutex;
 use log::{debug, warn, error};
 use crate::KernelResult;
 use crate::arch::syscall::{SyscallContext, SyscallParam, SyscallResult, MAX_SYSCALL_PARAMS};
+use crate::arch::syscall::{
+    SYSCALL_CLONE, CLONE_VM, CLONE_FS, CLONE_FILES, CLONE_SIGHAND, CLONE_THREAD, CLONE_SYSVSEM,
+    CLONE_PARENT_SETTID, CLONE_CHILD_CLEARTID,
+};
 
 /// System call error types
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -58,6 +62,7 @@ const SYSCALL_NAMES: &[&str] = &[
     "getpid",         // 8
     "fork",           // 9
     "exec",           // 10
+    "clone",          // 11
 ];
 
 /// Initialize system call interface
@@ -208,6 +213,26 @@ fn validate_parameters(context: &SyscallContext) -> KernelResult<()> {
             }
         }
         
+        SYSCALL_CLONE => {
+            // Validate the new thread's stack, entry point and flags
+            let flags = context.parameters[0];
+            let stack = context.parameters[1];
+            let parent_tid = context.parameters[2];
+            let child_tid = context.parameters[3];
+            let entry = context.parameters[4];
+            
+            if stack == 0 || entry == 0 {
+                return Err(SyscallError::InvalidAddress.into());
+            }
+            if flags & CLONE_PARENT_SETTID != 0 && parent_tid == 0 {
+                return Err(SyscallError::InvalidAddress.into());
+            }
+            if flags & CLONE_CHILD_CLEARTID != 0 && child_tid == 0 {
+                return Err(SyscallError::InvalidAddress.into());
+            }
+            validate_clone_flags(flags)?;
+        }
+        
         // Add validation for other system calls as needed
         _ => {
             // For now, other system calls don't have additional validation
@@ -291,6 +316,17 @@ fn dispatch_syscall(context: SyscallContext) -> KernelResult<SyscallResult> {
             )
         }
         
+        SYSCALL_CLONE => {
+            handle_syscall_clone(
+                context.parameters[0],
+                context.parameters[1] as usize,
+                context.parameters[2] as *mut u32,
+                context.parameters[3] as *mut u32,
+                context.parameters[4] as usize,
+                context.parameters[5] as usize,
+            )
+        }
+        
         _ => {
             warn!("Unknown system call: {}", syscall_num);
             Err(SyscallError::NotImplemented.into())
@@ -438,8 +474,53 @@ fn handle_syscall_exec(pathname: *const u8, argv: *const *const u8) -> KernelRes
     Ok(0)
 }
 
+fn handle_syscall_clone(
+    flags: u64,
+    stack: usize,
+    parent_tid: *mut u32,
+    child_tid: *mut u32,
+    entry: usize,
+    arg: usize,
+) -> KernelResult<SyscallResult> {
+    debug!("Clone syscall: flags={:#x}, stack={:#x}, entry={:#x}, arg={:#x}",
+           flags, stack, entry, arg);
+    
+    validate_clone_flags(flags)?;
+    
+    // The exit path zeroes and futex-wakes the child's TID word
+    let clear_tid = if flags & CLONE_CHILD_CLEARTID != 0 { child_tid as usize } else { 0 };
+    let tid = crate::scheduler::create_thread(entry, arg, stack, clear_tid)?;
+    
+    if flags & CLONE_PARENT_SETTID != 0 {
+        if parent_tid.is_null() {
+            return Err(SyscallError::InvalidAddress.into());
+        }
+        unsafe { parent_tid.write_volatile(tid) };
+    }
+    
+    Ok(tid as i64)
+}
+
 // Helper Functions
 
+/// Check that clone flags describe a thread this kernel can create
+///
+/// Only threads sharing the creator's address space, files and signal
+/// handlers are supported, so CLONE_THREAD and its prerequisites are required.
+pub(crate) fn validate_clone_flags(flags: u64) -> Result<(), SyscallError> {
+    const SUPPORTED: u64 = CLONE_VM | CLONE_FS | CLONE_FILES | CLONE_SIGHAND | CLONE_THREAD
+        | CLONE_SYSVSEM | CLONE_PARENT_SETTID | CLONE_CHILD_CLEARTID;
+    const REQUIRED: u64 = CLONE_VM | CLONE_SIGHAND | CLONE_THREAD;
+    
+    if flags & !SUPPORTED != 0 {
+        return Err(SyscallError::NotImplemented);
+    }
+    if flags & REQUIRED != REQUIRED {
+        return Err(SyscallError::InvalidArgument);
+    }
+    Ok(())
+}
+
 fn get_syscall_name(syscall_num: usize) -> &'static str {
     if syscall_num < SYSCALL_NAMES.len() {
         SYSCALL_NAMES[syscall_num]
@@ -483,3 +564,48 @@ pub fn print_syscall_stats() {
     }
     debug!("  Average Execution Time: {} ns", stats.average_execution_time_ns);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const THREAD_FLAGS: u64 = CLONE_VM | CLONE_FS | CLONE_FILES | CLONE_SIGHAND | CLONE_THREAD
+        | CLONE_SYSVSEM | CLONE_PARENT_SETTID | CLONE_CHILD_CLEARTID;
+
+    #[test]
+    fn test_clone_accepts_pthread_flags() {
+        assert_eq!(validate_clone_flags(THREAD_FLAGS), Ok(()));
+        assert_eq!(validate_clone_flags(CLONE_VM | CLONE_SIGHAND | CLONE_THREAD), Ok(()));
+    }
+
+    #[test]
+    fn test_clone_rejects_threads_without_shared_state() {
+        assert_eq!(validate_clone_flags(THREAD_FLAGS & !CLONE_VM), Err(SyscallError::InvalidArgument));
+        assert_eq!(validate_clone_flags(THREAD_FLAGS & !CLONE_SIGHAND), Err(SyscallError::InvalidArgument));
+        // A new process is created with fork, not clone
+        assert_eq!(validate_clone_flags(0), Err(SyscallError::InvalidArgument));
+    }
+
+    #[test]
+    fn test_clone_rejects_unsupported_flags() {
+        const CLONE_SETTLS: u64 = 0x0008_0000;
+        assert_eq!(validate_clone_flags(THREAD_FLAGS | CLONE_SETTLS), Err(SyscallError::NotImplemented));
+    }
+
+    #[test]
+    fn test_clone_requires_stack_entry_and_tid_words() {
+        let mut context = SyscallContext {
+            syscall_number: SYSCALL_CLONE,
+            parameters: [THREAD_FLAGS, 0x7000_0000, 0x1000, 0x1000, 0x40_0000, 0],
+            return_value: 0,
+        };
+        assert!(validate_parameters(&context).is_ok());
+
+        context.parameters[1] = 0;
+        assert!(validate_parameters(&context).is_err());
+        context.parameters[1] = 0x7000_0000;
+        context.parameters[3] = 0;
+        assert!(validate_parameters(&context).is_err());
+    }
+}
+
@@ -0,0 +1,713 @@
+//! Descriptors Served by the POSIX Layer
+//!
+//! The kernel has no protocol stack, pipes, epoll or timerfds, so these are
+//! run in-process: AF_INET and AF_INET6 sockets by a process-wide
+//! `NetStack`, epoll instances by `EventQueue`s and timerfds by a
+//! `TimerTable`. Their descriptors are numbered from `LOCAL_FD_BASE`, a
+//! range the kernel never hands out, and the wrappers resolve them here
+//! before making any system call.
+//!
+//! Every change in readiness (socket wakeups drained from the stack, pipe
+//! reads and writes, closes) is reported to the event queues watching the
+//! descriptor. Operations that would block sleep until a descriptor is
+//! notified or their timeout expires, then retry, advancing the stack's and
+//! timers' clocks on every pass.
+
+use core::ops::ControlFlow;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Condvar, PoisonError};
+use std::time::Duration;
+
+use spin::Mutex;
+
+use crate::errors::*;
+use crate::internal::{itimerspec, pollfd};
+use crate::netstack::{NetStack, SocketId};
+use crate::poll::{epoll_event, poll_scan, timeout_deadline, EventQueue, EventSourceKind, EventWait};
+use crate::poll::{EPOLLERR, EPOLLHUP, EPOLLIN, EPOLLOUT, EPOLL_CTL_DEL, POLLIN};
+use crate::socket::MSG_DONTWAIT;
+use crate::sys_types::sa_family_t;
+use crate::timer::{TimerEvent, TimerId, TimerTable, TFD_NONBLOCK};
+use crate::types::{clockid_t, fd_t, pid_t};
+
+/// First descriptor number used for objects served by this layer
+pub const LOCAL_FD_BASE: fd_t = 0x4000_0000;
+
+/// Bytes a pipe buffers before writers block
+pub const PIPE_CAPACITY: usize = 64 * 1024;
+
+/// Longest a blocked caller sleeps between passes. Nothing but the callers
+/// advances the stack's and timers' clocks, and kernel descriptors mixed
+/// into a poll() set are only checked on a pass.
+const WAIT_SLICE_NS: u64 = 10_000_000;
+
+/// Direction of a blocking socket operation, selecting its timeout
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Receive,
+    Send,
+}
+
+type PipeId = u64;
+
+/// Objects behind local descriptors
+#[derive(Debug)]
+enum LocalFile {
+    Socket(SocketId),
+    PipeRead { pipe: PipeId, nonblocking: bool },
+    PipeWrite { pipe: PipeId, nonblocking: bool },
+    Epoll(EventQueue),
+    Timer { timer: TimerId, nonblocking: bool },
+}
+
+#[derive(Debug)]
+struct Pipe {
+    data: VecDeque<u8>,
+    /// Open ends; `None` once closed
+    read_fd: Option<fd_t>,
+    write_fd: Option<fd_t>,
+}
+
+#[derive(Debug)]
+struct Descriptors {
+    stack: NetStack,
+    timers: TimerTable,
+    files: BTreeMap<fd_t, LocalFile>,
+    pipes: BTreeMap<PipeId, Pipe>,
+    next_pipe: PipeId,
+    /// Threads sleeping in `block_on`; every notification wakes them
+    sleepers: usize,
+}
+
+#[cfg(not(test))]
+mod os {
+    use super::*;
+    use crate::internal::CLOCK_MONOTONIC;
+    use crate::timer::{clock_gettime, ns_to_timespec, timespec_to_ns};
+
+    pub fn monotonic_ns() -> u64 {
+        let mut now = ns_to_timespec(0);
+        match clock_gettime(CLOCK_MONOTONIC, &mut now) {
+            Ok(()) => timespec_to_ns(&now).unwrap_or(0),
+            Err(_) => 0,
+        }
+    }
+
+    pub fn getpid() -> pid_t {
+        crate::unistd::getpid()
+    }
+
+    pub fn raise_sigpipe() {
+        let _ = crate::unistd::kill(getpid(), crate::signal::SIGPIPE);
+    }
+
+    /// POLL* readiness of a kernel descriptor, without blocking
+    pub fn kernel_readiness(fd: fd_t) -> Option<i16> {
+        use crate::poll::{POLLNVAL, POLLOUT, POLLPRI};
+
+        let mut entry = pollfd { fd, events: POLLIN | POLLOUT | POLLPRI, revents: 0 };
+        crate::syscall::poll(&mut entry, 1, 0).ok()?;
+        if entry.revents & POLLNVAL != 0 {
+            None
+        } else {
+            Some(entry.revents)
+        }
+    }
+}
+
+#[cfg(test)]
+mod os {
+    use super::*;
+    use std::sync::OnceLock;
+    use std::time::Instant;
+
+    static EPOCH: OnceLock<Instant> = OnceLock::new();
+
+    pub fn monotonic_ns() -> u64 {
+        EPOCH.get_or_init(Instant::now).elapsed().as_nanos() as u64
+    }
+
+    pub fn getpid() -> pid_t {
+        1
+    }
+
+    pub fn raise_sigpipe() {}
+
+    pub fn kernel_readiness(_fd: fd_t) -> Option<i16> {
+        None
+    }
+}
+
+/// Wakes threads blocked on local descriptors
+struct Wakeup {
+    /// Bumped on every wakeup, so a sleeper can tell it missed none
+    generation: std::sync::Mutex<u64>,
+    changed: Condvar,
+}
+
+static WAKEUP: Wakeup = Wakeup { generation: std::sync::Mutex::new(0), changed: Condvar::new() };
+
+impl Wakeup {
+    fn generation(&self) -> u64 {
+        *self.generation.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn signal(&self) {
+        *self.generation.lock().unwrap_or_else(PoisonError::into_inner) += 1;
+        self.changed.notify_all();
+    }
+
+    /// Sleep until the generation moves past `seen`, `wake_ns` passes or one
+    /// slice has elapsed
+    fn sleep(&self, seen: u64, wake_ns: Option<u64>) {
+        let now_ns = os::monotonic_ns();
+        let timeout_ns = wake_ns.map_or(WAIT_SLICE_NS, |wake_ns| wake_ns.saturating_sub(now_ns).min(WAIT_SLICE_NS));
+        let generation = self.generation.lock().unwrap_or_else(PoisonError::into_inner);
+        let _ = self.changed
+            .wait_timeout_while(generation, Duration::from_nanos(timeout_ns), |generation| *generation == seen)
+            .unwrap_or_else(PoisonError::into_inner);
+    }
+}
+
+impl Descriptors {
+    fn new() -> Self {
+        Self {
+            stack: NetStack::new(),
+            timers: TimerTable::new(),
+            files: BTreeMap::new(),
+            pipes: BTreeMap::new(),
+            next_pipe: 1,
+            sleepers: 0,
+        }
+    }
+
+    fn install(&mut self, file: LocalFile) -> PosixResult<fd_t> {
+        // Reuse the lowest free number, as the kernel does for its own range
+        let mut fd = LOCAL_FD_BASE;
+        while self.files.contains_key(&fd) {
+            fd = fd.checked_add(1).ok_or(Errno::Emfile)?;
+        }
+        self.files.insert(fd, file);
+        Ok(fd)
+    }
+
+    /// Install a socket, closing it if no descriptor is left
+    fn install_socket(&mut self, id: SocketId) -> PosixResult<fd_t> {
+        let result = self.install(LocalFile::Socket(id));
+        if result.is_err() {
+            let _ = self.stack.close(id);
+        }
+        result
+    }
+
+    fn socket_id(&self, fd: fd_t) -> PosixResult<SocketId> {
+        match self.files.get(&fd) {
+            Some(LocalFile::Socket(id)) => Ok(*id),
+            _ => Err(Errno::Ebadf),
+        }
+    }
+
+    fn timer_id(&self, fd: fd_t) -> PosixResult<TimerId> {
+        match self.files.get(&fd) {
+            Some(LocalFile::Timer { timer, .. }) => Ok(*timer),
+            Some(_) => Err(Errno::Einval),
+            None => Err(Errno::Ebadf),
+        }
+    }
+
+    fn queue_mut(&mut self, epfd: fd_t) -> PosixResult<&mut EventQueue> {
+        match self.files.get_mut(&epfd) {
+            Some(LocalFile::Epoll(queue)) => Ok(queue),
+            Some(_) => Err(Errno::Einval),
+            None => Err(Errno::Ebadf),
+        }
+    }
+
+    fn queues(&mut self) -> impl Iterator<Item = &mut EventQueue> {
+        self.files.values_mut().filter_map(|file| match file {
+            LocalFile::Epoll(queue) => Some(queue),
+            _ => None,
+        })
+    }
+
+    /// Current EPOLL* readiness of `fd`; `None` for epoll instances
+    fn readiness(&self, fd: fd_t) -> Option<u32> {
+        match self.files.get(&fd)? {
+            LocalFile::Socket(id) => self.stack.readiness(*id).ok(),
+            LocalFile::PipeRead { pipe, .. } => {
+                let pipe = self.pipes.get(pipe)?;
+                let mut readiness = 0;
+                if !pipe.data.is_empty() {
+                    readiness |= EPOLLIN;
+                }
+                if pipe.write_fd.is_none() {
+                    readiness |= EPOLLHUP;
+                }
+                Some(readiness)
+            }
+            LocalFile::PipeWrite { pipe, .. } => {
+                let pipe = self.pipes.get(pipe)?;
+                Some(if pipe.read_fd.is_none() {
+                    EPOLLERR
+                } else if pipe.data.len() < PIPE_CAPACITY {
+                    EPOLLOUT
+                } else {
+                    0
+                })
+            }
+            LocalFile::Timer { timer, .. } => self.timers.readiness(*timer).ok(),
+            LocalFile::Epoll(_) => None,
+        }
+    }
+
+    /// POLL* readiness of any descriptor for poll(); `None` if it is not open
+    fn poll_readiness(&self, fd: fd_t) -> Option<i16> {
+        if !is_local(fd) {
+            return os::kernel_readiness(fd);
+        }
+        match self.files.get(&fd)? {
+            // An epoll instance polls readable while it has events to report
+            LocalFile::Epoll(queue) => Some(if queue.is_ready() { POLLIN } else { 0 }),
+            // EPOLL* and POLL* share their bit values
+            _ => self.readiness(fd).map(|readiness| readiness as i16),
+        }
+    }
+
+    fn source_kind(&self, fd: fd_t) -> PosixResult<EventSourceKind> {
+        match self.files.get(&fd) {
+            Some(LocalFile::Socket(_)) => Ok(EventSourceKind::Socket),
+            Some(LocalFile::PipeRead { .. } | LocalFile::PipeWrite { .. }) => Ok(EventSourceKind::Pipe),
+            Some(LocalFile::Timer { .. }) => Ok(EventSourceKind::Timer),
+            // Nested epoll instances are not supported
+            Some(LocalFile::Epoll(_)) => Err(Errno::Einval),
+            None => Err(Errno::Ebadf),
+        }
+    }
+
+    /// Report the readiness of `fd` to every event queue watching it and
+    /// wake the threads that may be waiting for it
+    fn notify(&mut self, fd: fd_t) {
+        if let Some(readiness) = self.readiness(fd) {
+            let mut wake = self.sleepers > 0;
+            for queue in self.queues() {
+                wake |= queue.notify(fd, readiness);
+            }
+            if wake {
+                WAKEUP.signal();
+            }
+        }
+    }
+
+    /// Mirror the expiry of the timerfd `fd` onto the queues watching it
+    fn arm_queues(&mut self, fd: fd_t, timer: TimerId) {
+        let (expires_ns, interval_ns) = match self.timers.expiry(timer) {
+            Ok(expiry) => expiry,
+            Err(_) => return,
+        };
+        for queue in self.queues() {
+            // Queues not watching the timer refuse with Enoent
+            let _ = queue.arm_timer(fd, expires_ns.unwrap_or(0), interval_ns);
+        }
+    }
+
+    /// Report the sockets whose readiness changed inside the stack
+    fn publish_network(&mut self) {
+        for id in self.stack.drain_wakeups() {
+            let fd = self.files.iter()
+                .find(|(_, file)| matches!(file, LocalFile::Socket(socket) if *socket == id))
+                .map(|(&fd, _)| fd);
+            if let Some(fd) = fd {
+                self.notify(fd);
+            }
+        }
+    }
+
+    /// Advance the stack and timers to the current time
+    fn advance(&mut self) {
+        let now_ns = os::monotonic_ns();
+        self.stack.tick(now_ns / 1_000_000);
+        let queues = self.files.values_mut().filter_map(|file| match file {
+            LocalFile::Epoll(queue) => Some(queue),
+            _ => None,
+        });
+        for event in self.timers.tick_queues(now_ns, queues) {
+            if let TimerEvent::Readable(timer) = event {
+                let fd = self.files.iter()
+                    .find(|(_, file)| matches!(file, LocalFile::Timer { timer: id, .. } if *id == timer))
+                    .map(|(&fd, _)| fd);
+                if let Some(fd) = fd {
+                    self.notify(fd);
+                }
+            }
+        }
+        self.publish_network();
+    }
+
+    /// One attempt at reading a pipe or timerfd; `None` if it would block
+    fn read_file(&mut self, fd: fd_t, buf: &mut [u8]) -> PosixResult<Option<usize>> {
+        match self.files.get(&fd) {
+            Some(&LocalFile::PipeRead { pipe, nonblocking }) => {
+                let pipe = self.pipes.get_mut(&pipe).ok_or(Errno::Ebadf)?;
+                if pipe.data.is_empty() {
+                    return match (pipe.write_fd, nonblocking) {
+                        (None, _) => Ok(Some(0)),
+                        (Some(_), true) => Err(Errno::Eagain),
+                        (Some(_), false) => Ok(None),
+                    };
+                }
+                let count = buf.len().min(pipe.data.len());
+                for (byte, value) in buf.iter_mut().zip(pipe.data.drain(..count)) {
+                    *byte = value;
+                }
+                let writer = pipe.write_fd;
+                self.notify(fd);
+                if let Some(writer) = writer {
+                    self.notify(writer);
+                }
+                Ok(Some(count))
+            }
+            Some(&LocalFile::Timer { timer, nonblocking }) => {
+                if buf.len() < 8 {
+                    return Err(Errno::Einval);
+                }
+                let expirations = match self.timers.read_timerfd(timer) {
+                    Ok(expirations) => expirations,
+                    Err(Errno::Eagain) if !nonblocking => return Ok(None),
+                    Err(err) => return Err(err),
+                };
+                for queue in self.queues() {
+                    let _ = queue.read_timer(fd);
+                }
+                self.notify(fd);
+                buf[..8].copy_from_slice(&expirations.to_ne_bytes());
+                Ok(Some(8))
+            }
+            Some(LocalFile::Epoll(_)) => Err(Errno::Einval),
+            _ => Err(Errno::Ebadf),
+        }
+    }
+
+    /// One attempt at writing a pipe; `None` if it would block
+    fn write_file(&mut self, fd: fd_t, buf: &[u8]) -> PosixResult<Option<usize>> {
+        let (pipe, nonblocking) = match self.files.get(&fd) {
+            Some(&LocalFile::PipeWrite { pipe, nonblocking }) => (pipe, nonblocking),
+            Some(LocalFile::Epoll(_) | LocalFile::Timer { .. }) => return Err(Errno::Einval),
+            _ => return Err(Errno::Ebadf),
+        };
+        let pipe = self.pipes.get_mut(&pipe).ok_or(Errno::Ebadf)?;
+        let reader = pipe.read_fd.ok_or(Errno::Epipe)?;
+        let space = PIPE_CAPACITY - pipe.data.len();
+        if space == 0 {
+            return if nonblocking { Err(Errno::Eagain) } else { Ok(None) };
+        }
+        let count = buf.len().min(space);
+        pipe.data.extend(&buf[..count]);
+        self.notify(reader);
+        self.notify(fd);
+        Ok(Some(count))
+    }
+}
+
+static DESCRIPTORS: Mutex<Option<Descriptors>> = Mutex::new(None);
+
+fn with_descriptors<R>(f: impl FnOnce(&mut Descriptors) -> R) -> R {
+    let mut descriptors = DESCRIPTORS.lock();
+    f(descriptors.get_or_insert_with(Descriptors::new))
+}
+
+/// Run `attempt` until it returns a value, sleeping until the next
+/// notification in between; `None` once `deadline_ns` has passed
+fn block_on<R>(
+    deadline_ns: Option<u64>,
+    mut attempt: impl FnMut(&mut Descriptors) -> PosixResult<Option<R>>,
+) -> PosixResult<Option<R>> {
+    let mut sleeping = false;
+    loop {
+        let step = with_descriptors(|descriptors| {
+            if sleeping {
+                descriptors.sleepers -= 1;
+            }
+            descriptors.advance();
+            let result = attempt(descriptors);
+            descriptors.publish_network();
+            match result? {
+                Some(value) => Ok(ControlFlow::Break(Some(value))),
+                None if deadline_ns.is_some_and(|deadline| os::monotonic_ns() >= deadline) => Ok(ControlFlow::Break(None)),
+                None => {
+                    // Read under the lock every notifier takes, so no wakeup is lost
+                    descriptors.sleepers += 1;
+                    Ok(ControlFlow::Continue(WAKEUP.generation()))
+                }
+            }
+        })?;
+        match step {
+            ControlFlow::Break(value) => return Ok(value),
+            ControlFlow::Continue(seen) => {
+                sleeping = true;
+                WAKEUP.sleep(seen, deadline_ns);
+            }
+        }
+    }
+}
+
+/// Run `attempt` until it returns a value, giving up with `Eagain` once
+/// `timeout_us` (0 = never) has passed
+fn retry<R>(timeout_us: u64, attempt: impl FnMut(&mut Descriptors) -> PosixResult<Option<R>>) -> PosixResult<R> {
+    let deadline_ns = (timeout_us != 0).then(|| os::monotonic_ns() + timeout_us * 1_000);
+    block_on(deadline_ns, attempt)?.ok_or(Errno::Eagain)
+}
+
+/// Whether `fd` belongs to this layer rather than the kernel
+pub fn is_local(fd: fd_t) -> bool {
+    fd >= LOCAL_FD_BASE
+}
+
+/// Whether `fd` is a local socket, as opposed to a pipe, epoll or timerfd
+pub fn is_socket(fd: fd_t) -> bool {
+    with_descriptors(|descriptors| matches!(descriptors.files.get(&fd), Some(LocalFile::Socket(_))))
+}
+
+/// Create an AF_INET or AF_INET6 socket and return its descriptor
+pub fn open_socket(domain: sa_family_t, ty: i32, protocol: i32) -> PosixResult<fd_t> {
+    with_descriptors(|descriptors| {
+        let id = descriptors.stack.socket(domain, ty, protocol)?;
+        descriptors.install_socket(id)
+    })
+}
+
+/// Give a socket created inside the stack (an accepted connection) a descriptor
+pub fn install_socket(id: SocketId) -> PosixResult<fd_t> {
+    with_descriptors(|descriptors| descriptors.install_socket(id))
+}
+
+/// Run `op` once on the socket behind `fd`
+pub fn socket_op<R>(fd: fd_t, op: impl FnOnce(&mut NetStack, SocketId) -> PosixResult<R>) -> PosixResult<R> {
+    with_descriptors(|descriptors| {
+        let id = descriptors.socket_id(fd)?;
+        let result = op(&mut descriptors.stack, id);
+        descriptors.publish_network();
+        descriptors.notify(fd);
+        result
+    })
+}
+
+/// Run `op` on the socket behind `fd`, retrying while it returns `Eagain`
+///
+/// Non-blocking sockets and MSG_DONTWAIT in `flags` return `Eagain` at once;
+/// otherwise the call gives up with `Eagain` when the socket's timeout for
+/// `direction` passes.
+pub fn socket_wait<R>(
+    fd: fd_t,
+    direction: Direction,
+    flags: i32,
+    mut op: impl FnMut(&mut NetStack, SocketId) -> PosixResult<R>,
+) -> PosixResult<R> {
+    let timeout_us = with_descriptors(|descriptors| {
+        let options = descriptors.stack.options(descriptors.socket_id(fd)?)?;
+        Ok(match direction {
+            Direction::Receive => options.rcvtimeo_us,
+            Direction::Send => options.sndtimeo_us,
+        })
+    })?;
+    retry(timeout_us, |descriptors| {
+        let id = descriptors.socket_id(fd)?;
+        let result = op(&mut descriptors.stack, id);
+        descriptors.notify(fd);
+        match result {
+            Err(Errno::Eagain) if flags & MSG_DONTWAIT == 0 && !descriptors.stack.is_nonblocking(id)? => Ok(None),
+            result => result.map(Some),
+        }
+    })
+}
+
+/// Create a pipe; returns its read and write descriptors
+pub fn open_pipe(nonblocking: bool) -> PosixResult<(fd_t, fd_t)> {
+    with_descriptors(|descriptors| {
+        let pipe = descriptors.next_pipe;
+        let read_fd = descriptors.install(LocalFile::PipeRead { pipe, nonblocking })?;
+        let write_fd = match descriptors.install(LocalFile::PipeWrite { pipe, nonblocking }) {
+            Ok(fd) => fd,
+            Err(err) => {
+                descriptors.files.remove(&read_fd);
+                return Err(err);
+            }
+        };
+        descriptors.next_pipe += 1;
+        descriptors.pipes.insert(pipe, Pipe {
+            data: VecDeque::new(),
+            read_fd: Some(read_fd),
+            write_fd: Some(write_fd),
+        });
+        Ok((read_fd, write_fd))
+    })
+}
+
+/// Create a timerfd on `clock`
+pub fn open_timerfd(clock: clockid_t, flags: i32) -> PosixResult<fd_t> {
+    with_descriptors(|descriptors| {
+        let timer = descriptors.timers.create_timerfd(os::getpid(), clock)?;
+        let nonblocking = flags & TFD_NONBLOCK != 0;
+        descriptors.install(LocalFile::Timer { timer, nonblocking }).map_err(|err| {
+            let _ = descriptors.timers.delete(timer);
+            err
+        })
+    })
+}
+
+/// Arm or disarm the timerfd `fd`; returns the previous setting
+pub fn timerfd_settime(fd: fd_t, flags: i32, value: &itimerspec) -> PosixResult<itimerspec> {
+    with_descriptors(|descriptors| {
+        let timer = descriptors.timer_id(fd)?;
+        let old = descriptors.timers.settime(timer, flags, value, os::monotonic_ns())?;
+        descriptors.arm_queues(fd, timer);
+        descriptors.notify(fd);
+        Ok(old)
+    })
+}
+
+/// Time until the next expiry of the timerfd `fd`, and its interval
+pub fn timerfd_gettime(fd: fd_t) -> PosixResult<itimerspec> {
+    with_descriptors(|descriptors| {
+        let timer = descriptors.timer_id(fd)?;
+        descriptors.timers.gettime(timer, os::monotonic_ns())
+    })
+}
+
+/// Create an epoll instance
+pub fn open_epoll() -> PosixResult<fd_t> {
+    with_descriptors(|descriptors| descriptors.install(LocalFile::Epoll(EventQueue::new())))
+}
+
+/// Add, modify or remove interest of the epoll instance `epfd` in `fd`
+pub fn epoll_ctl(epfd: fd_t, op: i32, fd: fd_t, event: Option<epoll_event>) -> PosixResult<()> {
+    with_descriptors(|descriptors| {
+        if !descriptors.files.contains_key(&fd) {
+            // Kernel descriptors cannot be watched by a local queue
+            return Err(if is_local(fd) { Errno::Ebadf } else { Errno::Eperm });
+        }
+        let kind = descriptors.source_kind(fd)?;
+        let readiness = descriptors.readiness(fd);
+        let expiry = match descriptors.files.get(&fd) {
+            Some(LocalFile::Timer { timer, .. }) => descriptors.timers.expiry(*timer).ok(),
+            _ => None,
+        };
+
+        let queue = descriptors.queue_mut(epfd)?;
+        queue.ctl(op, fd, event, kind)?;
+        if op == EPOLL_CTL_DEL {
+            return Ok(());
+        }
+        if let Some((expires_ns, interval_ns)) = expiry {
+            queue.arm_timer(fd, expires_ns.unwrap_or(0), interval_ns)?;
+        }
+        if let Some(readiness) = readiness {
+            queue.notify(fd, readiness);
+        }
+        Ok(())
+    })
+}
+
+/// Wait for events on the epoll instance `epfd`
+pub fn epoll_wait(epfd: fd_t, events: &mut [epoll_event], timeout_ms: i32) -> PosixResult<usize> {
+    let deadline_ns = timeout_deadline(timeout_ms, os::monotonic_ns());
+    let mut waiting = false;
+    loop {
+        let step = with_descriptors(|descriptors| {
+            descriptors.advance();
+            let queue = descriptors.queue_mut(epfd)?;
+            if waiting {
+                queue.remove_waiter();
+            }
+            match queue.wait(events.len(), deadline_ns, os::monotonic_ns())? {
+                EventWait::Ready(ready) => {
+                    events[..ready.len()].copy_from_slice(&ready);
+                    Ok(ControlFlow::Break(ready.len()))
+                }
+                EventWait::TimedOut => Ok(ControlFlow::Break(0)),
+                EventWait::WouldBlock { deadline_ns } => {
+                    // Notifications wake the queue's waiters; its timers do not
+                    // fire on their own, so sleep no later than the first one
+                    queue.add_waiter();
+                    let wake_ns = deadline_ns.into_iter().chain(queue.next_timer_deadline()).min();
+                    Ok(ControlFlow::Continue((WAKEUP.generation(), wake_ns)))
+                }
+            }
+        })?;
+        match step {
+            ControlFlow::Break(count) => return Ok(count),
+            ControlFlow::Continue((seen, wake_ns)) => {
+                waiting = true;
+                WAKEUP.sleep(seen, wake_ns);
+            }
+        }
+    }
+}
+
+/// poll() over a set naming local descriptors. Kernel descriptors in the
+/// set are checked without blocking on every pass.
+pub fn poll(fds: &mut [pollfd], timeout_ms: i32) -> PosixResult<usize> {
+    let deadline_ns = timeout_deadline(timeout_ms, os::monotonic_ns());
+    let ready = block_on(deadline_ns, |descriptors| {
+        let ready = poll_scan(fds, &|fd| descriptors.poll_readiness(fd));
+        Ok((ready > 0).then_some(ready))
+    })?;
+    Ok(ready.unwrap_or(0))
+}
+
+/// Read from a local pipe or timerfd, blocking unless it is non-blocking
+pub fn read(fd: fd_t, buf: &mut [u8]) -> PosixResult<usize> {
+    retry(0, |descriptors| descriptors.read_file(fd, buf))
+}
+
+/// Write to a local pipe, blocking unless it is non-blocking
+///
+/// Writing with no reader left raises SIGPIPE and fails with `Epipe`.
+pub fn write(fd: fd_t, buf: &[u8]) -> PosixResult<usize> {
+    let result = retry(0, |descriptors| descriptors.write_file(fd, buf));
+    if result == Err(Errno::Epipe) {
+        os::raise_sigpipe();
+    }
+    result
+}
+
+/// Close a local descriptor
+pub fn close(fd: fd_t) -> PosixResult<()> {
+    with_descriptors(|descriptors| {
+        let file = descriptors.files.remove(&fd).ok_or(Errno::Ebadf)?;
+        for queue in descriptors.queues() {
+            let _ = queue.ctl(EPOLL_CTL_DEL, fd, None, EventSourceKind::Other);
+        }
+
+        match file {
+            LocalFile::Socket(id) => {
+                let result = descriptors.stack.close(id);
+                descriptors.publish_network();
+                result
+            }
+            LocalFile::PipeRead { pipe, .. } | LocalFile::PipeWrite { pipe, .. } => {
+                let peer = match descriptors.pipes.get_mut(&pipe) {
+                    Some(state) => {
+                        if state.read_fd == Some(fd) {
+                            state.read_fd = None;
+                            state.write_fd
+                        } else {
+                            state.write_fd = None;
+                            state.read_fd
+                        }
+                    }
+                    None => None,
+                };
+                match peer {
+                    // The other end sees EPOLLHUP or EPOLLERR
+                    Some(peer) => descriptors.notify(peer),
+                    None => {
+                        descriptors.pipes.remove(&pipe);
+                    }
+                }
+                Ok(())
+            }
+            LocalFile::Timer { timer, .. } => descriptors.timers.delete(timer),
+            LocalFile::Epoll(_) => Ok(()),
+        }
+    })
+}
@@ -0,0 +1,313 @@
+//! POSIX dirent.h Compatibility
+//!
+//! This module provides directory iteration for MultiOS on top of the
+//! GETDENTS64 system call, along with the `*at()` family of directory
+//! manipulation calls:
+//! - `Dir` streams with a safe iterator over `DirEntry` values
+//! - opendir()/readdir()/rewinddir()/closedir() equivalents
+//! - mkdirat()/unlinkat()/renameat() safe wrappers
+
+use crate::errors::*;
+use crate::stdio::PATH_MAX;
+use crate::syscall;
+use crate::types::*;
+
+/// Use the current working directory for relative `*at()` paths
+pub const AT_FDCWD: fd_t = -100;
+
+/// unlinkat() flag: remove a directory instead of a file
+pub const AT_REMOVEDIR: i32 = 0x200;
+
+/// Directory entry types (d_type)
+pub const DT_UNKNOWN: u8 = 0;
+pub const DT_FIFO: u8 = 1;
+pub const DT_CHR: u8 = 2;
+pub const DT_DIR: u8 = 4;
+pub const DT_BLK: u8 = 6;
+pub const DT_REG: u8 = 8;
+pub const DT_LNK: u8 = 10;
+pub const DT_SOCK: u8 = 12;
+
+/// Size of the buffer handed to GETDENTS64
+const DIR_BUFFER_SIZE: usize = 4096;
+
+/// Offset of the fields inside a `linux_dirent64` record
+const DIRENT_INO: usize = 0;
+const DIRENT_OFF: usize = 8;
+const DIRENT_RECLEN: usize = 16;
+const DIRENT_TYPE: usize = 18;
+const DIRENT_NAME: usize = 19;
+
+/// Directory entry returned by `Dir`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub ino: u64,          // Inode number
+    pub offset: i64,       // Position of the next entry
+    pub d_type: u8,        // Entry type (DT_*)
+    name: Vec<u8>,         // Entry name, without terminator
+}
+
+impl DirEntry {
+    /// Entry name as raw bytes
+    pub fn name_bytes(&self) -> &[u8] {
+        &self.name
+    }
+
+    /// Entry name, if it is valid UTF-8
+    pub fn name(&self) -> Option<&str> {
+        core::str::from_utf8(&self.name).ok()
+    }
+
+    /// Whether this is the `.` or `..` entry
+    pub fn is_dot(&self) -> bool {
+        self.name == b"." || self.name == b".."
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.d_type == DT_DIR
+    }
+}
+
+/// Open directory stream (DIR)
+///
+/// Entries are fetched from the kernel a buffer at a time. The descriptor
+/// is closed when the stream is dropped.
+#[derive(Debug)]
+pub struct Dir {
+    fd: fd_t,
+    buffer: Vec<u8>,
+    pos: usize,
+    len: usize,
+    finished: bool,
+}
+
+impl Dir {
+    /// Open the directory at `path`
+    pub fn open(path: &str) -> PosixResult<Self> {
+        let fd = with_c_path(path, |path| {
+            syscall::open(path, OpenFlags::READ | OpenFlags::DIRECTORY, 0)
+        })?;
+        Ok(Self::from_fd(fd))
+    }
+
+    /// Wrap an already open directory descriptor
+    pub fn from_fd(fd: fd_t) -> Self {
+        Self {
+            fd,
+            buffer: vec![0; DIR_BUFFER_SIZE],
+            pos: 0,
+            len: 0,
+            finished: false,
+        }
+    }
+
+    /// Descriptor backing the stream
+    pub fn fd(&self) -> fd_t {
+        self.fd
+    }
+
+    /// Read the next entry; `None` once the directory is exhausted
+    pub fn read(&mut self) -> PosixResult<Option<DirEntry>> {
+        if self.pos >= self.len {
+            if self.finished {
+                return Ok(None);
+            }
+            self.len = syscall::getdents64(self.fd, self.buffer.as_mut_ptr(), self.buffer.len())?;
+            self.pos = 0;
+            if self.len == 0 {
+                self.finished = true;
+                return Ok(None);
+            }
+        }
+
+        let record = &self.buffer[self.pos..self.len];
+        let entry = parse_record(record).ok_or(Errno::Eio)?;
+        self.pos += entry.1;
+        Ok(Some(entry.0))
+    }
+
+    /// Restart iteration from the first entry
+    pub fn rewind(&mut self) -> PosixResult<()> {
+        syscall::lseek(self.fd, 0, SeekMode::Set)?;
+        self.pos = 0;
+        self.len = 0;
+        self.finished = false;
+        Ok(())
+    }
+}
+
+impl Iterator for Dir {
+    type Item = PosixResult<DirEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.read() {
+            Ok(Some(entry)) => Some(Ok(entry)),
+            Ok(None) => None,
+            Err(err) => {
+                // Stop after reporting an error instead of retrying forever
+                self.finished = true;
+                self.pos = self.len;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+impl Drop for Dir {
+    fn drop(&mut self) {
+        let _ = syscall::close(self.fd);
+    }
+}
+
+/// Decode one `linux_dirent64` record, returning the entry and its length
+fn parse_record(record: &[u8]) -> Option<(DirEntry, usize)> {
+    if record.len() < DIRENT_NAME {
+        return None;
+    }
+    let field = |offset: usize, len: usize| &record[offset..offset + len];
+    let reclen = u16::from_ne_bytes(field(DIRENT_RECLEN, 2).try_into().ok()?) as usize;
+    if reclen < DIRENT_NAME || reclen > record.len() {
+        return None;
+    }
+
+    let name = &record[DIRENT_NAME..reclen];
+    let name_len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+    let entry = DirEntry {
+        ino: u64::from_ne_bytes(field(DIRENT_INO, 8).try_into().ok()?),
+        offset: i64::from_ne_bytes(field(DIRENT_OFF, 8).try_into().ok()?),
+        d_type: record[DIRENT_TYPE],
+        name: name[..name_len].to_vec(),
+    };
+    Some((entry, reclen))
+}
+
+/// Run `f` with `path` as a NUL-terminated string
+fn with_c_path<T>(path: &str, f: impl FnOnce(*const u8) -> PosixResult<T>) -> PosixResult<T> {
+    let bytes = path.as_bytes();
+    if bytes.len() > PATH_MAX {
+        return Err(Errno::Enametoolong);
+    }
+    if bytes.contains(&0) {
+        return Err(Errno::Einval);
+    }
+    let mut buf = [0u8; PATH_MAX + 1];
+    buf[..bytes.len()].copy_from_slice(bytes);
+    f(buf.as_ptr())
+}
+
+/// Open a directory stream
+///
+/// This function provides compatibility with the POSIX opendir() function.
+///
+/// # Arguments
+/// * `name` - Directory path
+///
+/// # Returns
+/// * `PosixResult<Dir>` - Directory stream, error on failure
+pub fn opendir(name: &str) -> PosixResult<Dir> {
+    Dir::open(name)
+}
+
+/// Open a directory stream on a descriptor
+///
+/// This function provides compatibility with the POSIX fdopendir() function.
+/// The stream takes ownership of the descriptor.
+pub fn fdopendir(fd: fd_t) -> PosixResult<Dir> {
+    if fd < 0 {
+        return Err(Errno::Ebadf);
+    }
+    Ok(Dir::from_fd(fd))
+}
+
+/// Read a directory entry
+///
+/// This function provides compatibility with the POSIX readdir() function.
+///
+/// # Arguments
+/// * `dirp` - Directory stream
+///
+/// # Returns
+/// * `PosixResult<Option<DirEntry>>` - Next entry, `None` at the end of the directory
+pub fn readdir(dirp: &mut Dir) -> PosixResult<Option<DirEntry>> {
+    dirp.read()
+}
+
+/// Reset a directory stream to the first entry
+///
+/// This function provides compatibility with the POSIX rewinddir() function.
+pub fn rewinddir(dirp: &mut Dir) -> PosixResult<()> {
+    dirp.rewind()
+}
+
+/// Descriptor of a directory stream
+///
+/// This function provides compatibility with the POSIX dirfd() function.
+pub fn dirfd(dirp: &Dir) -> fd_t {
+    dirp.fd()
+}
+
+/// Close a directory stream
+///
+/// This function provides compatibility with the POSIX closedir() function.
+///
+/// # Arguments
+/// * `dirp` - Directory stream
+///
+/// # Returns
+/// * `PosixResult<()>` - Success on closedir, error on failure
+pub fn closedir(dirp: Dir) -> PosixResult<()> {
+    let fd = dirp.fd;
+    core::mem::forget(dirp);
+    syscall::close(fd)
+}
+
+/// Create a directory relative to a directory descriptor
+///
+/// This function provides compatibility with the POSIX mkdirat() function.
+///
+/// # Arguments
+/// * `dirfd` - Directory that relative paths start from (or `AT_FDCWD`)
+/// * `pathname` - Directory to create
+/// * `mode` - Permissions for the new directory
+///
+/// # Returns
+/// * `PosixResult<()>` - Success on creation, error on failure
+pub fn mkdirat(dirfd: fd_t, pathname: &str, mode: mode_t) -> PosixResult<()> {
+    with_c_path(pathname, |path| syscall::mkdirat(dirfd, path, mode))
+}
+
+/// Remove a directory entry relative to a directory descriptor
+///
+/// This function provides compatibility with the POSIX unlinkat() function.
+///
+/// # Arguments
+/// * `dirfd` - Directory that relative paths start from (or `AT_FDCWD`)
+/// * `pathname` - Entry to remove
+/// * `flags` - 0, or `AT_REMOVEDIR` to remove an empty directory
+///
+/// # Returns
+/// * `PosixResult<()>` - Success on removal, error on failure
+pub fn unlinkat(dirfd: fd_t, pathname: &str, flags: i32) -> PosixResult<()> {
+    if flags & !AT_REMOVEDIR != 0 {
+        return Err(Errno::Einval);
+    }
+    with_c_path(pathname, |path| syscall::unlinkat(dirfd, path, flags))
+}
+
+/// Rename an entry relative to directory descriptors
+///
+/// This function provides compatibility with the POSIX renameat() function.
+///
+/// # Arguments
+/// * `olddirfd` - Directory that `oldpath` is relative to (or `AT_FDCWD`)
+/// * `oldpath` - Entry to rename
+/// * `newdirfd` - Directory that `newpath` is relative to (or `AT_FDCWD`)
+/// * `newpath` - New name
+///
+/// # Returns
+/// * `PosixResult<()>` - Success on rename, error on failure
+pub fn renameat(olddirfd: fd_t, oldpath: &str, newdirfd: fd_t, newpath: &str) -> PosixResult<()> {
+    with_c_path(oldpath, |old| {
+        with_c_path(newpath, |new| syscall::renameat(olddirfd, old, newdirfd, new))
+    })
+}
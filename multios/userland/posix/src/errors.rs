@@ -96,6 +96,7 @@ pub enum Errno {
     Enetunreach = 91,     // Network is unreachable
     Enetreset = 92,       // Network dropped connection because of reset
     Econnaborted = 93,    // Software caused connection abort
+    Eafnosupport = 97,    // Address family not supported by protocol
     Econnreset = 104,     // Connection reset by peer
     Enobufs = 105,        // No buffer space available
     Eacces = 106,         // Operation already in progress
@@ -231,6 +232,7 @@ impl Errno {
             91 => Errno::Enetunreach,
             92 => Errno::Enetreset,
             93 => Errno::Econnaborted,
+            97 => Errno::Eafnosupport,
             104 => Errno::Econnreset,
             105 => Errno::Enobufs,
             106 => Errno::Eacces,
@@ -363,6 +365,7 @@ impl Errno {
             Errno::Enetunreach => "ENETUNREACH",
             Errno::Enetreset => "ENETRESET",
             Errno::Econnaborted => "ECONNABORTED",
+            Errno::Eafnosupport => "EAFNOSUPPORT",
             Errno::Econnreset => "ECONNRESET",
             Errno::Enobufs => "ENOBUFS",
             Errno::Eacces => "EALREADY",
@@ -491,6 +494,7 @@ impl Errno {
             Errno::Enetunreach => "Network is unreachable",
             Errno::Enetreset => "Network dropped connection because of reset",
             Errno::Econnaborted => "Software caused connection abort",
+            Errno::Eafnosupport => "Address family not supported by protocol",
             Errno::Econnreset => "Connection reset by peer",
             Errno::Enobufs => "No buffer space available",
             Errno::Eacces => "Operation already in progress",
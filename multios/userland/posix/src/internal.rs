@@ -459,6 +459,27 @@ pub const CLONE_IO: i64 = 0x80000000;       // Clone with new I/O context
 /// Clone protection flags
 pub const CLONE_ONE_SHOT: i64 = 0x00000000; // One-shot thread (unused)
 
+/// Clock identifiers
+pub const CLOCK_REALTIME: clockid_t = 0;   // Wall-clock time
+pub const CLOCK_MONOTONIC: clockid_t = 1;  // Monotonic time since boot
+
+/// Memory protection flags
+pub const PROT_NONE: i32 = 0x0;            // Page cannot be accessed
+pub const PROT_READ: i32 = 0x1;            // Page can be read
+pub const PROT_WRITE: i32 = 0x2;           // Page can be written
+pub const PROT_EXEC: i32 = 0x4;            // Page can be executed
+
+/// Memory mapping flags
+pub const MAP_SHARED: i32 = 0x01;          // Share changes
+pub const MAP_PRIVATE: i32 = 0x02;         // Changes are private
+pub const MAP_FIXED: i32 = 0x10;           // Interpret addr exactly
+pub const MAP_ANONYMOUS: i32 = 0x20;       // Not backed by a file
+
+/// Futex operations
+pub const FUTEX_WAIT: i32 = 0;             // Sleep while *uaddr == val
+pub const FUTEX_WAKE: i32 = 1;             // Wake up to val waiters
+pub const FUTEX_PRIVATE_FLAG: i32 = 128;   // Futex is not shared between processes
+
 /// Utility functions for parameter validation and conversion
 pub mod utils {
     use super::*;
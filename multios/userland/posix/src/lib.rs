@@ -9,15 +9,36 @@
 //! - unistd.h: Process management, system operations
 //! - sys/types.h: Basic system type definitions  
 //! - signal.h: Signal handling and management
-//! - socket.h: Network socket operations
+//! - socket.h: Network socket operations, backed by the native TCP/UDP stack
+//! - sys/un.h: UNIX domain sockets and descriptor passing
 //! - pthread.h: Threading and synchronization primitives
+//! - stdlib.h: Dynamic memory allocation (malloc/free)
+//! - dirent.h: Directory iteration
+//! - termios.h, pty: Terminal control and pseudo-terminals
+//! - poll.h, sys/select.h, sys/epoll.h: Event polling
+//! - time.h, sys/timerfd.h: Clocks, POSIX timers and timer descriptors
+//! - sys/mman.h (shm_open), semaphore.h: Shared memory and semaphores
+//! - vDSO: clock_gettime(), gettimeofday() and getpid() without a system call
 
 pub mod stdio;
 pub mod unistd;
 pub mod sys_types;
 pub mod signal;
+pub mod signal_delivery;
 pub mod socket;
+pub mod netstack;
+pub mod descriptors;
+pub mod unix_socket;
 pub mod pthread;
+pub mod malloc;
+pub mod dirent;
+pub mod termios;
+pub mod pty;
+pub mod poll;
+pub mod timer;
+pub mod shm;
+pub mod semaphore;
+pub mod vdso;
 pub mod internal;
 pub mod errors;
 
@@ -26,10 +47,27 @@ pub use stdio::*;
 pub use unistd::*;
 pub use sys_types::*;
 pub use signal::*;
+pub use signal_delivery::*;
 pub use socket::*;
+pub use netstack::*;
+pub use unix_socket::*;
 pub use pthread::*;
+pub use malloc::*;
+pub use dirent::*;
+pub use termios::*;
+pub use pty::*;
+pub use poll::*;
+pub use timer::*;
+pub use shm::*;
+pub use semaphore::*;
+pub use vdso::*;
 pub use errors::*;
 
+/// Route Rust allocations through malloc() when requested
+#[cfg(feature = "malloc")]
+#[global_allocator]
+static GLOBAL_ALLOCATOR: malloc::PosixAllocator = malloc::PosixAllocator;
+
 /// Core POSIX types that are used across multiple modules
 /// 
 /// These types provide compatibility with POSIX standard type definitions
@@ -449,7 +487,8 @@ pub mod syscall {
         pub const DUP2: usize = 1021;
         pub const DUP3: usize = 1022;
         pub const FCNTL: usize = 1023;
-        pub const SYSCALLS_END: usize = 1024;
+        pub const FTRUNCATE: usize = 1024;
+        pub const SYSCALLS_END: usize = 1025;
 
         // Process management
         pub const FORK: usize = 2000;
@@ -465,6 +504,7 @@ pub mod syscall {
         pub const GETSID: usize = 2010;
         pub const GETPGID: usize = 2011;
         pub const SETPGID: usize = 2012;
+        pub const VFORK: usize = 2013;
 
         // Memory management
         pub const BRK: usize = 3000;
@@ -497,6 +537,15 @@ pub mod syscall {
         pub const CLOCK_SETTIME: usize = 5004;
         pub const CLOCK_GETRES: usize = 5005;
         pub const CLOCK_NANOSLEEP: usize = 5006;
+        pub const TIMER_CREATE: usize = 5007;
+        pub const TIMER_SETTIME: usize = 5008;
+        pub const TIMER_GETTIME: usize = 5009;
+        pub const TIMER_GETOVERRUN: usize = 5010;
+        pub const TIMER_DELETE: usize = 5011;
+        pub const TIMERFD_CREATE: usize = 5012;
+        pub const TIMERFD_SETTIME: usize = 5013;
+        pub const TIMERFD_GETTIME: usize = 5014;
+        pub const VDSO_ADDRESS: usize = 5015;
 
         // Signal operations
         pub const RT_SIGACTION: usize = 6000;
@@ -524,9 +573,11 @@ pub mod syscall {
         pub const SETSOCKOPT: usize = 7013;
         pub const GETSOCKOPT: usize = 7014;
         pub const SOCKETPAIR: usize = 7015;
+        pub const SENDMSG: usize = 7016;
+        pub const RECVMSG: usize = 7017;
 
         // Thread operations
-        pub const CLONE: usize = 8000;
+        pub const CLONE: usize = 11;           // SYSCALL_CLONE in the kernel
         pub const SET_TID_ADDRESS: usize = 8001;
         pub const SET_ROBUST_LIST: usize = 8002;
         pub const GET_ROBUST_LIST: usize = 8003;
@@ -535,6 +586,7 @@ pub mod syscall {
         pub const RT_SIGQUEUEINFO: usize = 8006;
         pub const RT_SIGTIMEDWAIT: usize = 8007;
         pub const RT_SIGSUSPEND: usize = 8008;
+        pub const GETTID: usize = 8009;
 
         // File descriptor operations
         pub const SELECT: usize = 9000;
@@ -543,6 +595,7 @@ pub mod syscall {
         pub const EPOLL_CTL: usize = 9003;
         pub const EPOLL_WAIT: usize = 9004;
         pub const EPOLL_PWAIT: usize = 9005;
+        pub const IOCTL: usize = 9006;
     }
 
     /// Perform a system call with parameter validation and error handling
@@ -636,6 +689,15 @@ pub mod syscall {
         }
     }
 
+    pub fn ftruncate(fd: fd_t, length: off_t) -> Result<(), Errno> {
+        let result = syscall!(numbers::FTRUNCATE, fd as usize, length as usize);
+        if result < 0 {
+            Err(Errno::from_raw(-(result as i32)))
+        } else {
+            Ok(())
+        }
+    }
+
     pub fn dup(oldfd: fd_t) -> Result<fd_t, Errno> {
         let result = syscall!(numbers::DUP, oldfd as usize);
         if result < 0 {
@@ -664,6 +726,15 @@ pub mod syscall {
         }
     }
 
+    pub fn vfork() -> Result<pid_t, Errno> {
+        let result = syscall!(numbers::VFORK);
+        if result < 0 {
+            Err(Errno::from_raw(-(result as i32)))
+        } else {
+            Ok(result as pid_t)
+        }
+    }
+
     pub fn execve(path: *const u8, argv: *const *const u8, envp: *const *const u8) -> Result<!, Errno> {
         let result = syscall!(numbers::EXECVE, path as usize, argv as usize, envp as usize);
         if result < 0 {
@@ -696,6 +767,42 @@ pub mod syscall {
         }
     }
 
+    pub fn setsid() -> Result<pid_t, Errno> {
+        let result = syscall!(numbers::SETSID);
+        if result < 0 {
+            Err(Errno::from_raw(-(result as i32)))
+        } else {
+            Ok(result as pid_t)
+        }
+    }
+
+    pub fn getsid(pid: pid_t) -> Result<pid_t, Errno> {
+        let result = syscall!(numbers::GETSID, pid as usize);
+        if result < 0 {
+            Err(Errno::from_raw(-(result as i32)))
+        } else {
+            Ok(result as pid_t)
+        }
+    }
+
+    pub fn getpgid(pid: pid_t) -> Result<pid_t, Errno> {
+        let result = syscall!(numbers::GETPGID, pid as usize);
+        if result < 0 {
+            Err(Errno::from_raw(-(result as i32)))
+        } else {
+            Ok(result as pid_t)
+        }
+    }
+
+    pub fn setpgid(pid: pid_t, pgid: pid_t) -> Result<(), Errno> {
+        let result = syscall!(numbers::SETPGID, pid as usize, pgid as usize);
+        if result < 0 {
+            Err(Errno::from_raw(-(result as i32)))
+        } else {
+            Ok(())
+        }
+    }
+
     // Memory management
     pub fn brk(addr: usize) -> Result<usize, Errno> {
         let result = syscall!(numbers::BRK, addr);
@@ -797,6 +904,96 @@ pub mod syscall {
         }
     }
 
+    pub fn accept4(sockfd: fd_t, addr: *mut sockaddr, addrlen: *mut socklen_t, flags: i32) -> Result<fd_t, Errno> {
+        let result = syscall!(numbers::ACCEPT4, sockfd as usize, addr as usize, addrlen as usize, flags as usize);
+        if result < 0 {
+            Err(Errno::from_raw(-(result as i32)))
+        } else {
+            Ok(result as fd_t)
+        }
+    }
+
+    pub fn sendto(sockfd: fd_t, buf: *const u8, len: size_t, flags: i32, dest_addr: *const sockaddr, addrlen: socklen_t) -> Result<ssize_t, Errno> {
+        let result = syscall!(numbers::SENDTO, sockfd as usize, buf as usize, len, flags as usize, dest_addr as usize, addrlen as usize);
+        if result < 0 {
+            Err(Errno::from_raw(-(result as i32)))
+        } else {
+            Ok(result as ssize_t)
+        }
+    }
+
+    pub fn recvfrom(sockfd: fd_t, buf: *mut u8, len: size_t, flags: i32, src_addr: *mut sockaddr, addrlen: *mut socklen_t) -> Result<ssize_t, Errno> {
+        let result = syscall!(numbers::RECVFROM, sockfd as usize, buf as usize, len, flags as usize, src_addr as usize, addrlen as usize);
+        if result < 0 {
+            Err(Errno::from_raw(-(result as i32)))
+        } else {
+            Ok(result as ssize_t)
+        }
+    }
+
+    pub fn getsockname(sockfd: fd_t, addr: *mut sockaddr, addrlen: *mut socklen_t) -> Result<(), Errno> {
+        let result = syscall!(numbers::GETSOCKNAME, sockfd as usize, addr as usize, addrlen as usize);
+        if result < 0 {
+            Err(Errno::from_raw(-(result as i32)))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn getpeername(sockfd: fd_t, addr: *mut sockaddr, addrlen: *mut socklen_t) -> Result<(), Errno> {
+        let result = syscall!(numbers::GETPEERNAME, sockfd as usize, addr as usize, addrlen as usize);
+        if result < 0 {
+            Err(Errno::from_raw(-(result as i32)))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn setsockopt(sockfd: fd_t, level: i32, optname: i32, optval: *const u8, optlen: socklen_t) -> Result<(), Errno> {
+        let result = syscall!(numbers::SETSOCKOPT, sockfd as usize, level as usize, optname as usize, optval as usize, optlen as usize);
+        if result < 0 {
+            Err(Errno::from_raw(-(result as i32)))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn getsockopt(sockfd: fd_t, level: i32, optname: i32, optval: *mut u8, optlen: *mut socklen_t) -> Result<(), Errno> {
+        let result = syscall!(numbers::GETSOCKOPT, sockfd as usize, level as usize, optname as usize, optval as usize, optlen as usize);
+        if result < 0 {
+            Err(Errno::from_raw(-(result as i32)))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn sendmsg(sockfd: fd_t, msg: *const msghdr, flags: i32) -> Result<ssize_t, Errno> {
+        let result = syscall!(numbers::SENDMSG, sockfd as usize, msg as usize, flags as usize);
+        if result < 0 {
+            Err(Errno::from_raw(-(result as i32)))
+        } else {
+            Ok(result as ssize_t)
+        }
+    }
+
+    pub fn recvmsg(sockfd: fd_t, msg: *mut msghdr, flags: i32) -> Result<ssize_t, Errno> {
+        let result = syscall!(numbers::RECVMSG, sockfd as usize, msg as usize, flags as usize);
+        if result < 0 {
+            Err(Errno::from_raw(-(result as i32)))
+        } else {
+            Ok(result as ssize_t)
+        }
+    }
+
+    pub fn socketpair(domain: i32, ty: i32, protocol: i32, sv: *mut fd_t) -> Result<(), Errno> {
+        let result = syscall!(numbers::SOCKETPAIR, domain as usize, ty as usize, protocol as usize, sv as usize);
+        if result < 0 {
+            Err(Errno::from_raw(-(result as i32)))
+        } else {
+            Ok(())
+        }
+    }
+
     // Time operations
     pub fn time(tloc: *mut time_t) -> Result<time_t, Errno> {
         let result = syscall!(numbers::TIME, tloc as usize);
@@ -815,4 +1012,344 @@ pub mod syscall {
             Ok(())
         }
     }
+
+    // Signals
+    pub fn rt_sigaction(
+        signo: i32,
+        act: *const crate::signal::sigaction,
+        oldact: *mut crate::signal::sigaction,
+    ) -> Result<(), Errno> {
+        let result = syscall!(numbers::RT_SIGACTION, signo as usize, act as usize, oldact as usize,
+                              core::mem::size_of::<crate::signal::sigset_t>());
+        if result < 0 {
+            Err(Errno::from_raw(-(result as i32)))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn rt_sigprocmask(
+        how: i32,
+        set: *const crate::signal::sigset_t,
+        oldset: *mut crate::signal::sigset_t,
+    ) -> Result<(), Errno> {
+        let result = syscall!(numbers::RT_SIGPROCMASK, how as usize, set as usize, oldset as usize,
+                              core::mem::size_of::<crate::signal::sigset_t>());
+        if result < 0 {
+            Err(Errno::from_raw(-(result as i32)))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn rt_sigpending(set: *mut crate::signal::sigset_t) -> Result<(), Errno> {
+        let result = syscall!(numbers::RT_SIGPENDING, set as usize,
+                              core::mem::size_of::<crate::signal::sigset_t>());
+        if result < 0 {
+            Err(Errno::from_raw(-(result as i32)))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn rt_sigsuspend(mask: *const crate::signal::sigset_t) -> Result<(), Errno> {
+        let result = syscall!(numbers::RT_SIGSUSPEND, mask as usize,
+                              core::mem::size_of::<crate::signal::sigset_t>());
+        if result < 0 {
+            Err(Errno::from_raw(-(result as i32)))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn rt_sigtimedwait(
+        set: *const crate::signal::sigset_t,
+        info: *mut crate::signal::siginfo,
+        timeout: *const crate::internal::timespec,
+    ) -> Result<i32, Errno> {
+        let result = syscall!(numbers::RT_SIGTIMEDWAIT, set as usize, info as usize, timeout as usize,
+                              core::mem::size_of::<crate::signal::sigset_t>());
+        if result < 0 {
+            Err(Errno::from_raw(-(result as i32)))
+        } else {
+            Ok(result as i32)
+        }
+    }
+
+    pub fn rt_sigqueueinfo(pid: pid_t, tid: pid_t, signo: i32, info: *const crate::signal::siginfo) -> Result<(), Errno> {
+        let result = syscall!(numbers::RT_SIGQUEUEINFO, pid as usize, tid as usize, signo as usize, info as usize);
+        if result < 0 {
+            Err(Errno::from_raw(-(result as i32)))
+        } else {
+            Ok(())
+        }
+    }
+
+    // Device control
+    pub fn ioctl(fd: fd_t, request: u32, arg: usize) -> Result<usize, Errno> {
+        let result = syscall!(numbers::IOCTL, fd as usize, request as usize, arg);
+        if result < 0 {
+            Err(Errno::from_raw(-(result as i32)))
+        } else {
+            Ok(result as usize)
+        }
+    }
+
+    // Directory operations
+    pub fn getdents64(fd: fd_t, dirp: *mut u8, count: usize) -> Result<usize, Errno> {
+        let result = syscall!(numbers::GETDENTS64, fd as usize, dirp as usize, count);
+        if result < 0 {
+            Err(Errno::from_raw(-(result as i32)))
+        } else {
+            Ok(result as usize)
+        }
+    }
+
+    pub fn mkdirat(dirfd: fd_t, path: *const u8, mode: mode_t) -> Result<(), Errno> {
+        let result = syscall!(numbers::MKDIRAT, dirfd as usize, path as usize, mode as usize);
+        if result < 0 {
+            Err(Errno::from_raw(-(result as i32)))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn unlinkat(dirfd: fd_t, path: *const u8, flags: i32) -> Result<(), Errno> {
+        let result = syscall!(numbers::UNLINKAT, dirfd as usize, path as usize, flags as usize);
+        if result < 0 {
+            Err(Errno::from_raw(-(result as i32)))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn renameat(olddirfd: fd_t, oldpath: *const u8, newdirfd: fd_t, newpath: *const u8) -> Result<(), Errno> {
+        let result = syscall!(numbers::RENAMEAT, olddirfd as usize, oldpath as usize,
+                              newdirfd as usize, newpath as usize);
+        if result < 0 {
+            Err(Errno::from_raw(-(result as i32)))
+        } else {
+            Ok(())
+        }
+    }
+
+    // Thread operations
+
+    /// Create a thread of execution
+    ///
+    /// The child starts running `entry(arg)` on `stack`; `parent_tid` receives
+    /// its TID (`CLONE_PARENT_SETTID`) and `child_tid` is zeroed and futex-woken
+    /// when it exits (`CLONE_CHILD_CLEARTID`).
+    pub fn clone(
+        flags: i64,
+        stack: usize,
+        parent_tid: *mut u32,
+        child_tid: *mut u32,
+        entry: extern "C" fn(usize) -> !,
+        arg: usize,
+    ) -> Result<pid_t, Errno> {
+        let result = syscall!(numbers::CLONE, flags as usize, stack, parent_tid as usize,
+                              child_tid as usize, entry as usize, arg);
+        if result < 0 {
+            Err(Errno::from_raw(-(result as i32)))
+        } else {
+            Ok(result as pid_t)
+        }
+    }
+
+    pub fn gettid() -> pid_t {
+        syscall!(numbers::GETTID) as pid_t
+    }
+
+    pub fn futex(
+        uaddr: *const u32,
+        op: i32,
+        val: u32,
+        timeout: *const crate::internal::timespec,
+        uaddr2: *const u32,
+        val3: u32,
+    ) -> Result<usize, Errno> {
+        let result = syscall!(numbers::FUTEX, uaddr as usize, op as usize, val as usize,
+                              timeout as usize, uaddr2 as usize, val3 as usize);
+        if result < 0 {
+            Err(Errno::from_raw(-(result as i32)))
+        } else {
+            Ok(result as usize)
+        }
+    }
+
+    pub fn clock_gettime(clock_id: clockid_t, tp: *mut crate::internal::timespec) -> Result<(), Errno> {
+        let result = syscall!(numbers::CLOCK_GETTIME, clock_id as usize, tp as usize);
+        if result < 0 {
+            Err(Errno::from_raw(-(result as i32)))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn clock_settime(clock_id: clockid_t, tp: *const crate::internal::timespec) -> Result<(), Errno> {
+        let result = syscall!(numbers::CLOCK_SETTIME, clock_id as usize, tp as usize);
+        if result < 0 {
+            Err(Errno::from_raw(-(result as i32)))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn clock_getres(clock_id: clockid_t, res: *mut crate::internal::timespec) -> Result<(), Errno> {
+        let result = syscall!(numbers::CLOCK_GETRES, clock_id as usize, res as usize);
+        if result < 0 {
+            Err(Errno::from_raw(-(result as i32)))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn clock_nanosleep(clock_id: clockid_t, flags: i32, request: *const crate::internal::timespec, remain: *mut crate::internal::timespec) -> Result<(), Errno> {
+        let result = syscall!(numbers::CLOCK_NANOSLEEP, clock_id as usize, flags as usize, request as usize, remain as usize);
+        if result < 0 {
+            Err(Errno::from_raw(-(result as i32)))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn timer_create(clock_id: clockid_t, sevp: *const crate::signal::sigevent, timerid: *mut crate::internal::timer_t) -> Result<(), Errno> {
+        let result = syscall!(numbers::TIMER_CREATE, clock_id as usize, sevp as usize, timerid as usize);
+        if result < 0 {
+            Err(Errno::from_raw(-(result as i32)))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn timer_settime(timerid: crate::internal::timer_t, flags: i32, new_value: *const crate::internal::itimerspec, old_value: *mut crate::internal::itimerspec) -> Result<(), Errno> {
+        let result = syscall!(numbers::TIMER_SETTIME, timerid as usize, flags as usize, new_value as usize, old_value as usize);
+        if result < 0 {
+            Err(Errno::from_raw(-(result as i32)))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn timer_gettime(timerid: crate::internal::timer_t, curr_value: *mut crate::internal::itimerspec) -> Result<(), Errno> {
+        let result = syscall!(numbers::TIMER_GETTIME, timerid as usize, curr_value as usize);
+        if result < 0 {
+            Err(Errno::from_raw(-(result as i32)))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn timer_getoverrun(timerid: crate::internal::timer_t) -> Result<i32, Errno> {
+        let result = syscall!(numbers::TIMER_GETOVERRUN, timerid as usize);
+        if result < 0 {
+            Err(Errno::from_raw(-(result as i32)))
+        } else {
+            Ok(result as i32)
+        }
+    }
+
+    pub fn timer_delete(timerid: crate::internal::timer_t) -> Result<(), Errno> {
+        let result = syscall!(numbers::TIMER_DELETE, timerid as usize);
+        if result < 0 {
+            Err(Errno::from_raw(-(result as i32)))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn timerfd_create(clock_id: clockid_t, flags: i32) -> Result<fd_t, Errno> {
+        let result = syscall!(numbers::TIMERFD_CREATE, clock_id as usize, flags as usize);
+        if result < 0 {
+            Err(Errno::from_raw(-(result as i32)))
+        } else {
+            Ok(result as fd_t)
+        }
+    }
+
+    pub fn timerfd_settime(fd: fd_t, flags: i32, new_value: *const crate::internal::itimerspec, old_value: *mut crate::internal::itimerspec) -> Result<(), Errno> {
+        let result = syscall!(numbers::TIMERFD_SETTIME, fd as usize, flags as usize, new_value as usize, old_value as usize);
+        if result < 0 {
+            Err(Errno::from_raw(-(result as i32)))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn timerfd_gettime(fd: fd_t, curr_value: *mut crate::internal::itimerspec) -> Result<(), Errno> {
+        let result = syscall!(numbers::TIMERFD_GETTIME, fd as usize, curr_value as usize);
+        if result < 0 {
+            Err(Errno::from_raw(-(result as i32)))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn vdso_address() -> Result<usize, Errno> {
+        let result = syscall!(numbers::VDSO_ADDRESS);
+        if result < 0 {
+            Err(Errno::from_raw(-(result as i32)))
+        } else {
+            Ok(result as usize)
+        }
+    }
+
+    // Event polling
+    pub fn select(
+        nfds: i32,
+        readfds: *mut crate::poll::fd_set,
+        writefds: *mut crate::poll::fd_set,
+        exceptfds: *mut crate::poll::fd_set,
+        timeout: *mut timeval,
+    ) -> Result<usize, Errno> {
+        let result = syscall!(numbers::SELECT, nfds as usize, readfds as usize, writefds as usize,
+                              exceptfds as usize, timeout as usize);
+        if result < 0 {
+            Err(Errno::from_raw(-(result as i32)))
+        } else {
+            Ok(result as usize)
+        }
+    }
+
+    pub fn poll(fds: *mut crate::internal::pollfd, nfds: usize, timeout_ms: i32) -> Result<usize, Errno> {
+        let result = syscall!(numbers::POLL, fds as usize, nfds, timeout_ms as usize);
+        if result < 0 {
+            Err(Errno::from_raw(-(result as i32)))
+        } else {
+            Ok(result as usize)
+        }
+    }
+
+    pub fn epoll_create(flags: i32) -> Result<fd_t, Errno> {
+        let result = syscall!(numbers::EPOLL_CREATE, flags as usize);
+        if result < 0 {
+            Err(Errno::from_raw(-(result as i32)))
+        } else {
+            Ok(result as fd_t)
+        }
+    }
+
+    pub fn epoll_ctl(epfd: fd_t, op: i32, fd: fd_t, event: *const crate::poll::epoll_event) -> Result<(), Errno> {
+        let result = syscall!(numbers::EPOLL_CTL, epfd as usize, op as usize, fd as usize, event as usize);
+        if result < 0 {
+            Err(Errno::from_raw(-(result as i32)))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn epoll_wait(
+        epfd: fd_t,
+        events: *mut crate::poll::epoll_event,
+        max_events: usize,
+        timeout_ms: i32,
+    ) -> Result<usize, Errno> {
+        let result = syscall!(numbers::EPOLL_WAIT, epfd as usize, events as usize, max_events, timeout_ms as usize);
+        if result < 0 {
+            Err(Errno::from_raw(-(result as i32)))
+        } else {
+            Ok(result as usize)
+        }
+    }
 }
@@ -0,0 +1,644 @@
+//! POSIX stdlib.h Memory Allocation
+//!
+//! This module provides malloc()/free() compatibility for MultiOS on top of
+//! the BRK and MMAP system calls:
+//! - Small requests are served from size-class bins carved out of heap
+//!   chunks obtained with BRK (falling back to MMAP when the break cannot grow)
+//! - Each thread keeps a cache of free blocks per size class, so most
+//!   allocations and frees never take the shared lock. Caches are claimed
+//!   by thread ID rather than kept in TLS, since threads created by
+//!   `pthread_create()` share their creator's TLS block
+//! - Large requests get a dedicated anonymous mapping returned on free
+
+use crate::errors::*;
+use crate::internal::*;
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr;
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use spin::Mutex;
+
+/// Alignment guaranteed for every allocation
+pub const MALLOC_ALIGN: usize = 16;
+
+/// Size classes served from bins; larger requests are mapped directly
+const SIZE_CLASSES: [usize; 40] = [
+    16, 32, 48, 64, 80, 96, 112, 128,
+    160, 192, 224, 256, 320, 384, 448, 512,
+    640, 768, 896, 1024, 1280, 1536, 1792, 2048,
+    2560, 3072, 3584, 4096, 5120, 6144, 7168, 8192,
+    10240, 12288, 14336, 16384, 20480, 24576, 28672, 32768,
+];
+
+const NUM_CLASSES: usize = SIZE_CLASSES.len();
+
+/// Largest request served from a bin
+pub const MAX_SMALL_SIZE: usize = SIZE_CLASSES[NUM_CLASSES - 1];
+
+/// Heap growth granularity for bin chunks
+const CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Page size used to round large mappings
+const PAGE_SIZE: usize = 4096;
+
+/// Blocks a thread cache holds per class before returning half to the bins
+const CACHE_LIMIT: usize = 64;
+
+/// Thread caches available; threads beyond this use the bins directly
+const CACHE_SLOTS: usize = 64;
+
+/// Bytes moved between a thread cache and the bins in one refill
+const REFILL_BYTES: usize = 64 * 1024;
+
+/// Header magic; the low bits carry the block kind
+const HEADER_MAGIC: usize = 0x4d41_4c4c_4f43_0000;
+const KIND_SMALL: usize = 1;
+const KIND_LARGE: usize = 2;
+const KIND_ALIGNED: usize = 3;
+
+/// Header stored immediately before every pointer handed out
+///
+/// `value` is the size class index (small), the mapping length (large), or
+/// the distance back to the underlying allocation (aligned).
+#[repr(C, align(16))]
+struct BlockHeader {
+    tag: usize,
+    value: usize,
+}
+
+const HEADER_SIZE: usize = core::mem::size_of::<BlockHeader>();
+
+/// Allocator statistics
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MallocStats {
+    pub heap_bytes: usize,          // Bytes obtained through BRK
+    pub mapped_bytes: usize,        // Bytes currently obtained through MMAP
+    pub small_in_use: usize,        // Usable bytes of live bin allocations
+    pub large_in_use: usize,        // Usable bytes of live mapped allocations
+    pub small_allocations: usize,   // Bin allocations made
+    pub large_allocations: usize,   // Mapped allocations made
+    pub cache_refills: usize,       // Thread cache refills from the bins
+}
+
+struct AtomicStats {
+    heap_bytes: AtomicUsize,
+    mapped_bytes: AtomicUsize,
+    small_in_use: AtomicUsize,
+    large_in_use: AtomicUsize,
+    small_allocations: AtomicUsize,
+    large_allocations: AtomicUsize,
+    cache_refills: AtomicUsize,
+}
+
+static STATS: AtomicStats = AtomicStats {
+    heap_bytes: AtomicUsize::new(0),
+    mapped_bytes: AtomicUsize::new(0),
+    small_in_use: AtomicUsize::new(0),
+    large_in_use: AtomicUsize::new(0),
+    small_allocations: AtomicUsize::new(0),
+    large_allocations: AtomicUsize::new(0),
+    cache_refills: AtomicUsize::new(0),
+};
+
+/// Shared bins: free lists per class plus the chunk being carved
+struct Bins {
+    free: [usize; NUM_CLASSES],
+    carve_next: usize,
+    carve_end: usize,
+    brk_end: usize,
+}
+
+static BINS: Mutex<Bins> = Mutex::new(Bins {
+    free: [0; NUM_CLASSES],
+    carve_next: 0,
+    carve_end: 0,
+    brk_end: 0,
+});
+
+/// Per-thread free lists
+struct ThreadCache {
+    heads: [usize; NUM_CLASSES],
+    counts: [usize; NUM_CLASSES],
+}
+
+impl ThreadCache {
+    const fn new() -> Self {
+        Self {
+            heads: [0; NUM_CLASSES],
+            counts: [0; NUM_CLASSES],
+        }
+    }
+}
+
+/// A thread cache and the thread that owns it
+struct CacheSlot {
+    /// Thread ID of the owner, 0 while free
+    owner: AtomicU32,
+    cache: Mutex<ThreadCache>,
+}
+
+impl CacheSlot {
+    const fn new() -> Self {
+        Self {
+            owner: AtomicU32::new(0),
+            cache: Mutex::new(ThreadCache::new()),
+        }
+    }
+}
+
+const FREE_SLOT: CacheSlot = CacheSlot::new();
+
+static CACHES: [CacheSlot; CACHE_SLOTS] = [FREE_SLOT; CACHE_SLOTS];
+
+/// Memory and thread identity from the kernel
+#[cfg(not(test))]
+mod os {
+    use super::*;
+    use crate::syscall;
+
+    pub fn thread_id() -> u32 {
+        syscall::gettid() as u32
+    }
+
+    pub fn brk(addr: usize) -> PosixResult<usize> {
+        syscall::brk(addr)
+    }
+
+    pub fn map_anonymous(len: usize) -> Option<usize> {
+        syscall::mmap(0, len, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, -1, 0).ok()
+    }
+
+    pub fn unmap(addr: usize, len: usize) -> PosixResult<()> {
+        syscall::munmap(addr, len)
+    }
+}
+
+/// Host stand-ins so the allocator logic can be tested off-target
+#[cfg(test)]
+mod os {
+    use super::*;
+    use std::alloc::{alloc_zeroed, dealloc};
+
+    static NEXT_TID: AtomicU32 = AtomicU32::new(1);
+
+    std::thread_local! {
+        static TID: u32 = NEXT_TID.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn thread_id() -> u32 {
+        TID.with(|tid| *tid)
+    }
+
+    pub fn brk(_addr: usize) -> PosixResult<usize> {
+        Err(Errno::Enomem)
+    }
+
+    pub fn map_anonymous(len: usize) -> Option<usize> {
+        let block = unsafe { alloc_zeroed(Layout::from_size_align(len, PAGE_SIZE).ok()?) };
+        (!block.is_null()).then_some(block as usize)
+    }
+
+    pub fn unmap(addr: usize, len: usize) -> PosixResult<()> {
+        unsafe { dealloc(addr as *mut u8, Layout::from_size_align_unchecked(len, PAGE_SIZE)) };
+        Ok(())
+    }
+}
+
+/// Slot owned by `tid`, claiming a free one on first use
+fn cache_slot(tid: u32) -> Option<&'static CacheSlot> {
+    let start = tid as usize % CACHE_SLOTS;
+    let probe = || (0..CACHE_SLOTS).map(move |i| &CACHES[(start + i) % CACHE_SLOTS]);
+    if let Some(slot) = probe().find(|slot| slot.owner.load(Ordering::Acquire) == tid) {
+        return Some(slot);
+    }
+    probe().find(|slot| slot.owner.compare_exchange(0, tid, Ordering::AcqRel, Ordering::Relaxed).is_ok())
+}
+
+/// Run `f` on the calling thread's cache
+///
+/// Returns `None` when no slot is free, or when the cache is already in
+/// use by this thread (a signal handler interrupting the allocator).
+fn with_cache<R>(f: impl FnOnce(&mut ThreadCache) -> R) -> Option<R> {
+    let slot = cache_slot(os::thread_id())?;
+    let mut cache = slot.cache.try_lock()?;
+    Some(f(&mut cache))
+}
+
+/// Free blocks are chained through their first word
+unsafe fn next_of(block: usize) -> usize {
+    *(block as *const usize)
+}
+
+unsafe fn set_next(block: usize, next: usize) {
+    *(block as *mut usize) = next;
+}
+
+unsafe fn header_of(ptr: *mut u8) -> *mut BlockHeader {
+    ptr.sub(HEADER_SIZE) as *mut BlockHeader
+}
+
+unsafe fn write_header(ptr: usize, kind: usize, value: usize) {
+    *header_of(ptr as *mut u8) = BlockHeader { tag: HEADER_MAGIC | kind, value };
+}
+
+fn class_of(size: usize) -> Option<usize> {
+    let class = SIZE_CLASSES.partition_point(|&class_size| class_size < size);
+    (class < NUM_CLASSES).then_some(class)
+}
+
+fn round_up(value: usize, align: usize) -> usize {
+    (value + align - 1) & !(align - 1)
+}
+
+fn map_anonymous(len: usize) -> Option<usize> {
+    let addr = os::map_anonymous(len)?;
+    STATS.mapped_bytes.fetch_add(len, Ordering::Relaxed);
+    Some(addr)
+}
+
+impl Bins {
+    /// Obtain a fresh chunk, extending the break when possible
+    fn grow(&mut self) -> Option<()> {
+        if self.brk_end == 0 {
+            self.brk_end = os::brk(0).unwrap_or(0);
+        }
+        let start = round_up(self.brk_end, MALLOC_ALIGN);
+        let end = start + CHUNK_SIZE;
+        let chunk = match os::brk(end) {
+            Ok(new_end) if self.brk_end != 0 && new_end >= end => {
+                STATS.heap_bytes.fetch_add(new_end - self.brk_end, Ordering::Relaxed);
+                self.brk_end = new_end;
+                start
+            }
+            _ => map_anonymous(CHUNK_SIZE)?,
+        };
+        self.carve_next = chunk;
+        self.carve_end = chunk + CHUNK_SIZE;
+        Some(())
+    }
+
+    /// Take a block of `class` from the free list or the current chunk
+    fn take(&mut self, class: usize) -> Option<usize> {
+        let head = self.free[class];
+        if head != 0 {
+            self.free[class] = unsafe { next_of(head) };
+            return Some(head);
+        }
+
+        let stride = HEADER_SIZE + SIZE_CLASSES[class];
+        if self.carve_end - self.carve_next < stride {
+            self.grow()?;
+        }
+        let block = self.carve_next + HEADER_SIZE;
+        self.carve_next += stride;
+        unsafe { write_header(block, KIND_SMALL, class) };
+        Some(block)
+    }
+}
+
+/// Move a batch of blocks from the bins into the calling thread's cache
+fn refill(cache: &mut ThreadCache, class: usize) -> Option<usize> {
+    let batch = (REFILL_BYTES / SIZE_CLASSES[class]).clamp(1, CACHE_LIMIT / 2);
+    let mut bins = BINS.lock();
+    let first = bins.take(class)?;
+    for _ in 1..batch {
+        match bins.take(class) {
+            Some(block) => {
+                unsafe { set_next(block, cache.heads[class]) };
+                cache.heads[class] = block;
+                cache.counts[class] += 1;
+            }
+            None => break,
+        }
+    }
+    STATS.cache_refills.fetch_add(1, Ordering::Relaxed);
+    Some(first)
+}
+
+/// Return the older half of an overfull cache list to the bins
+fn drain(cache: &mut ThreadCache, class: usize, keep: usize) {
+    let mut bins = BINS.lock();
+    while cache.counts[class] > keep {
+        let block = cache.heads[class];
+        cache.heads[class] = unsafe { next_of(block) };
+        cache.counts[class] -= 1;
+        unsafe { set_next(block, bins.free[class]) };
+        bins.free[class] = block;
+    }
+}
+
+fn alloc_small(class: usize) -> *mut u8 {
+    let cached = with_cache(|cache| {
+        let head = cache.heads[class];
+        if head != 0 {
+            cache.heads[class] = unsafe { next_of(head) };
+            cache.counts[class] -= 1;
+            Some(head)
+        } else {
+            refill(cache, class)
+        }
+    });
+    // Without a cache go straight to the bins
+    let block = match cached {
+        Some(block) => block,
+        None => BINS.lock().take(class),
+    };
+    match block {
+        Some(block) => {
+            STATS.small_allocations.fetch_add(1, Ordering::Relaxed);
+            STATS.small_in_use.fetch_add(SIZE_CLASSES[class], Ordering::Relaxed);
+            block as *mut u8
+        }
+        None => ptr::null_mut(),
+    }
+}
+
+fn free_small(block: usize, class: usize) {
+    STATS.small_in_use.fetch_sub(SIZE_CLASSES[class], Ordering::Relaxed);
+    let cached = with_cache(|cache| {
+        unsafe { set_next(block, cache.heads[class]) };
+        cache.heads[class] = block;
+        cache.counts[class] += 1;
+        if cache.counts[class] > CACHE_LIMIT {
+            drain(cache, class, CACHE_LIMIT / 2);
+        }
+    });
+    if cached.is_none() {
+        let mut bins = BINS.lock();
+        unsafe { set_next(block, bins.free[class]) };
+        bins.free[class] = block;
+    }
+}
+
+fn alloc_large(size: usize) -> *mut u8 {
+    let len = match size.checked_add(HEADER_SIZE + PAGE_SIZE - 1) {
+        Some(padded) => padded & !(PAGE_SIZE - 1),
+        None => return ptr::null_mut(),
+    };
+    match map_anonymous(len) {
+        Some(base) => {
+            let block = base + HEADER_SIZE;
+            unsafe { write_header(block, KIND_LARGE, len) };
+            STATS.large_allocations.fetch_add(1, Ordering::Relaxed);
+            STATS.large_in_use.fetch_add(len - HEADER_SIZE, Ordering::Relaxed);
+            block as *mut u8
+        }
+        None => ptr::null_mut(),
+    }
+}
+
+/// Allocate memory
+///
+/// This function provides compatibility with the POSIX malloc() function.
+///
+/// # Arguments
+/// * `size` - Number of bytes to allocate
+///
+/// # Returns
+/// * `*mut u8` - 16-byte aligned block, or null when memory is exhausted
+pub fn malloc(size: usize) -> *mut u8 {
+    match class_of(size.max(1)) {
+        Some(class) => alloc_small(class),
+        None => alloc_large(size),
+    }
+}
+
+/// Allocate zeroed memory for an array
+///
+/// This function provides compatibility with the POSIX calloc() function.
+///
+/// # Arguments
+/// * `nmemb` - Number of elements
+/// * `size` - Size of each element
+///
+/// # Returns
+/// * `*mut u8` - Zeroed block, or null on overflow or exhaustion
+pub fn calloc(nmemb: usize, size: usize) -> *mut u8 {
+    let total = match nmemb.checked_mul(size) {
+        Some(total) => total,
+        None => return ptr::null_mut(),
+    };
+    let block = malloc(total);
+    if !block.is_null() {
+        unsafe { ptr::write_bytes(block, 0, total) };
+    }
+    block
+}
+
+/// Release memory
+///
+/// This function provides compatibility with the POSIX free() function.
+///
+/// # Safety
+/// `ptr` must be null or a live pointer returned by this allocator.
+pub unsafe fn free(ptr: *mut u8) {
+    if ptr.is_null() {
+        return;
+    }
+    let header = &*header_of(ptr);
+    debug_assert_eq!(header.tag & !0xf, HEADER_MAGIC, "free() of a pointer not from malloc()");
+    match header.tag & 0xf {
+        KIND_SMALL => free_small(ptr as usize, header.value),
+        KIND_LARGE => {
+            let len = header.value;
+            STATS.large_in_use.fetch_sub(len - HEADER_SIZE, Ordering::Relaxed);
+            if os::unmap(ptr as usize - HEADER_SIZE, len).is_ok() {
+                STATS.mapped_bytes.fetch_sub(len, Ordering::Relaxed);
+            }
+        }
+        KIND_ALIGNED => free(ptr.sub(header.value)),
+        _ => {}
+    }
+}
+
+/// Resize an allocation
+///
+/// This function provides compatibility with the POSIX realloc() function.
+/// The block is kept in place when it is already large enough.
+///
+/// # Safety
+/// `ptr` must be null or a live pointer returned by this allocator.
+///
+/// # Returns
+/// * `*mut u8` - Resized block, or null on failure (the old block stays valid)
+pub unsafe fn realloc(ptr: *mut u8, size: usize) -> *mut u8 {
+    if ptr.is_null() {
+        return malloc(size);
+    }
+    if size == 0 {
+        free(ptr);
+        return ptr::null_mut();
+    }
+
+    let usable = malloc_usable_size(ptr);
+    // Keep the block unless it would waste more than half of it
+    if size <= usable && (usable <= MAX_SMALL_SIZE || size > usable / 2) {
+        return ptr;
+    }
+    let resized = malloc(size);
+    if !resized.is_null() {
+        ptr::copy_nonoverlapping(ptr, resized, usable.min(size));
+        free(ptr);
+    }
+    resized
+}
+
+/// Allocate aligned memory
+///
+/// This function provides compatibility with the POSIX posix_memalign() function.
+///
+/// # Arguments
+/// * `memptr` - Receives the allocated block
+/// * `alignment` - Power of two multiple of `size_of::<usize>()`
+/// * `size` - Number of bytes to allocate
+///
+/// # Returns
+/// * `PosixResult<()>` - Success on allocation, `Einval` or `Enomem` on failure
+pub fn posix_memalign(memptr: &mut *mut u8, alignment: usize, size: usize) -> PosixResult<()> {
+    if !alignment.is_power_of_two() || alignment % core::mem::size_of::<usize>() != 0 {
+        return Err(Errno::Einval);
+    }
+    if alignment <= MALLOC_ALIGN {
+        *memptr = malloc(size);
+        return if memptr.is_null() { Err(Errno::Enomem) } else { Ok(()) };
+    }
+
+    // Over-allocate, then place an aligned header pointing back at the block
+    let padded = size.checked_add(alignment + HEADER_SIZE).ok_or(Errno::Enomem)?;
+    let base = malloc(padded);
+    if base.is_null() {
+        return Err(Errno::Enomem);
+    }
+    let aligned = round_up(base as usize + HEADER_SIZE, alignment);
+    unsafe { write_header(aligned, KIND_ALIGNED, aligned - base as usize) };
+    *memptr = aligned as *mut u8;
+    Ok(())
+}
+
+/// Usable size of an allocation
+///
+/// This function provides compatibility with the GNU malloc_usable_size() function.
+///
+/// # Safety
+/// `ptr` must be null or a live pointer returned by this allocator.
+///
+/// # Returns
+/// * `usize` - Bytes that may be used through `ptr` (at least the requested size)
+pub unsafe fn malloc_usable_size(ptr: *mut u8) -> usize {
+    if ptr.is_null() {
+        return 0;
+    }
+    let header = &*header_of(ptr);
+    match header.tag & 0xf {
+        KIND_SMALL => SIZE_CLASSES[header.value],
+        KIND_LARGE => header.value - HEADER_SIZE,
+        KIND_ALIGNED => malloc_usable_size(ptr.sub(header.value)) - header.value,
+        _ => 0,
+    }
+}
+
+/// Return the calling thread's cached blocks to the shared bins
+///
+/// Called when a thread exits so its cache is not leaked and its slot can
+/// be claimed by another thread.
+pub fn release_thread_cache() {
+    let tid = os::thread_id();
+    if let Some(slot) = CACHES.iter().find(|slot| slot.owner.load(Ordering::Acquire) == tid) {
+        let mut cache = slot.cache.lock();
+        for class in 0..NUM_CLASSES {
+            drain(&mut cache, class, 0);
+        }
+        slot.owner.store(0, Ordering::Release);
+    }
+}
+
+/// Snapshot of the allocator statistics
+pub fn malloc_stats() -> MallocStats {
+    MallocStats {
+        heap_bytes: STATS.heap_bytes.load(Ordering::Relaxed),
+        mapped_bytes: STATS.mapped_bytes.load(Ordering::Relaxed),
+        small_in_use: STATS.small_in_use.load(Ordering::Relaxed),
+        large_in_use: STATS.large_in_use.load(Ordering::Relaxed),
+        small_allocations: STATS.small_allocations.load(Ordering::Relaxed),
+        large_allocations: STATS.large_allocations.load(Ordering::Relaxed),
+        cache_refills: STATS.cache_refills.load(Ordering::Relaxed),
+    }
+}
+
+/// Rust global allocator backed by malloc()
+///
+/// Installed as the `#[global_allocator]` when the `malloc` feature is enabled.
+pub struct PosixAllocator;
+
+unsafe impl GlobalAlloc for PosixAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if layout.align() <= MALLOC_ALIGN {
+            return malloc(layout.size());
+        }
+        let mut block = ptr::null_mut();
+        match posix_memalign(&mut block, layout.align(), layout.size()) {
+            Ok(()) => block,
+            Err(_) => ptr::null_mut(),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, _layout: Layout) {
+        free(ptr)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if layout.align() <= MALLOC_ALIGN {
+            return realloc(ptr, new_size);
+        }
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        let resized = self.alloc(new_layout);
+        if !resized.is_null() {
+            ptr::copy_nonoverlapping(ptr, resized, layout.size().min(new_size));
+            free(ptr);
+        }
+        resized
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::vec::Vec;
+
+    #[test]
+    fn test_threads_allocate_and_free_concurrently() {
+        let workers: Vec<_> = (0..8usize)
+            .map(|seed| {
+                thread::spawn(move || {
+                    let mut live: Vec<(usize, usize)> = Vec::new();
+                    for round in 0..4000usize {
+                        let size = 1 + (round * 37 + seed * 101) % 3000;
+                        let block = malloc(size);
+                        assert!(!block.is_null());
+                        assert_eq!(block as usize % MALLOC_ALIGN, 0);
+                        unsafe { ptr::write_bytes(block, seed as u8, size) };
+                        live.push((block as usize, size));
+
+                        // Free out of order so lists cross the cache limit both ways
+                        if live.len() > 48 {
+                            let (block, size) = live.swap_remove(round % live.len());
+                            let bytes = unsafe { core::slice::from_raw_parts(block as *const u8, size) };
+                            assert!(bytes.iter().all(|&byte| byte == seed as u8), "block shared between threads");
+                            unsafe { free(block as *mut u8) };
+                        }
+                    }
+                    for (block, _) in live {
+                        unsafe { free(block as *mut u8) };
+                    }
+                    release_thread_cache();
+                })
+            })
+            .collect();
+
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        assert_eq!(malloc_stats().small_in_use, 0);
+        assert!(CACHES.iter().all(|slot| slot.owner.load(Ordering::Relaxed) == 0));
+    }
+}
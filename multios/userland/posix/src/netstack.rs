@@ -0,0 +1,2229 @@
+//! Native TCP/UDP Network Stack for MultiOS
+//!
+//! This module provides the protocol side of the socket system calls:
+//! - `NetStack`, which owns every AF_INET and AF_INET6 socket: port
+//!   binding, the TCP connection state machine, socket buffers, listen
+//!   backlogs and accept queues, UDP datagram queues and SO_*/TCP_* options
+//! - IPv4/IPv6 encoding and decoding of TCP and UDP packets, the boundary
+//!   with network interface drivers
+//!
+//! Traffic between local addresses is looped back inside the stack; other
+//! packets are queued for the interface driver (`NetStack::take_transmit`),
+//! which hands received packets back through `NetStack::receive`. Timers
+//! (retransmission, keepalive, TIME-WAIT) run from `NetStack::tick`.
+//!
+//! Operations that cannot complete immediately return `Eagain`, and
+//! connect() returns `Einprogress`; the descriptor layer (`descriptors`)
+//! retries blocking callers until the socket is ready.
+
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+use crate::errors::*;
+use crate::poll::{EPOLLERR, EPOLLHUP, EPOLLIN, EPOLLOUT, EPOLLRDHUP};
+use crate::socket::{
+    in6_addr, in_addr, linger, sockaddr, sockaddr_in, sockaddr_in6, timeval,
+    AF_INET, AF_INET6, IPPROTO_TCP, IPPROTO_UDP, MSG_PEEK, MSG_TRUNC, SHUT_RD, SHUT_RDWR,
+    SHUT_WR, SOCK_CLOEXEC, SOCK_DGRAM, SOCK_NONBLOCK, SOCK_STREAM, SOL_SOCKET,
+    SO_ACCEPTCONN, SO_BROADCAST, SO_DOMAIN, SO_ERROR, SO_KEEPALIVE, SO_LINGER, SO_PROTOCOL,
+    SO_RCVBUF, SO_RCVLOWAT, SO_RCVTIMEO, SO_REUSEADDR, SO_REUSEPORT, SO_SNDBUF, SO_SNDTIMEO,
+    SO_TYPE, TCP_KEEPCNT, TCP_KEEPIDLE, TCP_KEEPINTVL, TCP_MAXSEG, TCP_NODELAY,
+};
+use crate::sys_types::{sa_family_t, socklen_t};
+
+/// Identifier of a socket inside the stack
+pub type SocketId = u32;
+
+/// Default size of the send and receive buffers
+pub const DEFAULT_BUFFER_SIZE: usize = 64 * 1024;
+/// Smallest buffer size accepted by SO_SNDBUF/SO_RCVBUF
+pub const MIN_BUFFER_SIZE: usize = 2048;
+/// Largest buffer size accepted by SO_SNDBUF/SO_RCVBUF
+pub const MAX_BUFFER_SIZE: usize = 4 * 1024 * 1024;
+/// Default maximum segment size (1500-byte MTU minus IPv4 and TCP headers)
+pub const DEFAULT_MSS: u16 = 1460;
+/// Upper bound for listen() backlogs
+pub const SOMAXCONN: i32 = 4096;
+/// Ports handed out for implicit binds
+pub const EPHEMERAL_PORT_FIRST: u16 = 49152;
+pub const EPHEMERAL_PORT_LAST: u16 = 65535;
+
+/// TCP header flags
+pub const TCP_FIN: u8 = 0x01;
+pub const TCP_SYN: u8 = 0x02;
+pub const TCP_RST: u8 = 0x04;
+pub const TCP_PSH: u8 = 0x08;
+pub const TCP_ACK: u8 = 0x10;
+
+const IPV4_HEADER_LEN: usize = 20;
+const IPV6_HEADER_LEN: usize = 40;
+const TCP_HEADER_LEN: usize = 20;
+const UDP_HEADER_LEN: usize = 8;
+const DEFAULT_TTL: u8 = 64;
+/// Largest UDP payload that fits an unfragmented-size IPv4 datagram
+const MAX_UDP_PAYLOAD: usize = 65507;
+
+/// Retransmission timeout bounds (RFC 6298)
+const INITIAL_RTO_MS: u64 = 1000;
+const MAX_RTO_MS: u64 = 60_000;
+/// Retransmissions before a connection is given up
+const MAX_RETRIES: u32 = 8;
+/// Maximum segment lifetime; TIME-WAIT lasts twice this
+const MSL_MS: u64 = 30_000;
+
+/// Keepalive defaults, in seconds and probes
+const DEFAULT_KEEPIDLE: u32 = 7200;
+const DEFAULT_KEEPINTVL: u32 = 75;
+const DEFAULT_KEEPCNT: u32 = 9;
+
+/// IP address of either family
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum IpAddr {
+    V4([u8; 4]),
+    V6([u8; 16]),
+}
+
+impl IpAddr {
+    pub const V4_ANY: IpAddr = IpAddr::V4([0; 4]);
+    pub const V4_LOOPBACK: IpAddr = IpAddr::V4([127, 0, 0, 1]);
+    pub const V4_BROADCAST: IpAddr = IpAddr::V4([255; 4]);
+    pub const V6_ANY: IpAddr = IpAddr::V6([0; 16]);
+    pub const V6_LOOPBACK: IpAddr = IpAddr::V6([0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+
+    pub fn family(&self) -> sa_family_t {
+        match self {
+            IpAddr::V4(_) => AF_INET,
+            IpAddr::V6(_) => AF_INET6,
+        }
+    }
+
+    pub fn is_unspecified(&self) -> bool {
+        match self {
+            IpAddr::V4(addr) => *addr == [0; 4],
+            IpAddr::V6(addr) => *addr == [0; 16],
+        }
+    }
+
+    pub fn is_loopback(&self) -> bool {
+        match self {
+            IpAddr::V4(addr) => addr[0] == 127,
+            IpAddr::V6(_) => *self == IpAddr::V6_LOOPBACK,
+        }
+    }
+
+    pub fn is_broadcast(&self) -> bool {
+        *self == IpAddr::V4_BROADCAST
+    }
+
+    /// Loopback address of the same family
+    pub fn loopback_of(&self) -> IpAddr {
+        match self {
+            IpAddr::V4(_) => IpAddr::V4_LOOPBACK,
+            IpAddr::V6(_) => IpAddr::V6_LOOPBACK,
+        }
+    }
+
+    /// IPv6 form, mapping IPv4 addresses to ::ffff:a.b.c.d
+    pub fn to_v6(&self) -> [u8; 16] {
+        match self {
+            IpAddr::V4(addr) => {
+                let mut mapped = [0u8; 16];
+                mapped[10] = 0xff;
+                mapped[11] = 0xff;
+                mapped[12..].copy_from_slice(addr);
+                mapped
+            }
+            IpAddr::V6(addr) => *addr,
+        }
+    }
+
+    /// Turn IPv4-mapped IPv6 addresses back into IPv4 addresses
+    pub fn canonical(&self) -> IpAddr {
+        match self {
+            IpAddr::V6(addr) if addr[..10] == [0; 10] && addr[10] == 0xff && addr[11] == 0xff => {
+                IpAddr::V4([addr[12], addr[13], addr[14], addr[15]])
+            }
+            other => *other,
+        }
+    }
+}
+
+/// Transport endpoint: an address and a port
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Endpoint {
+    pub addr: IpAddr,
+    pub port: u16,
+}
+
+impl Endpoint {
+    pub fn new(addr: IpAddr, port: u16) -> Self {
+        Self { addr, port }
+    }
+
+    pub fn from_sockaddr_in(sin: &sockaddr_in) -> Self {
+        // s_addr is kept in network byte order, so its memory layout is the address
+        Self::new(IpAddr::V4(sin.sin_addr.s_addr.to_ne_bytes()), u16::from_be(sin.sin_port))
+    }
+
+    pub fn from_sockaddr_in6(sin6: &sockaddr_in6) -> Self {
+        Self::new(IpAddr::V6(sin6.sin6_addr.s6_addr).canonical(), u16::from_be(sin6.sin6_port))
+    }
+
+    /// IPv4 socket address; `None` for IPv6 endpoints
+    pub fn to_sockaddr_in(&self) -> Option<sockaddr_in> {
+        match self.addr {
+            IpAddr::V4(addr) => Some(sockaddr_in {
+                sin_family: AF_INET,
+                sin_port: self.port.to_be(),
+                sin_addr: in_addr { s_addr: u32::from_ne_bytes(addr) },
+                sin_zero: [0; 8],
+            }),
+            IpAddr::V6(_) => None,
+        }
+    }
+
+    /// IPv6 socket address; IPv4 endpoints are IPv4-mapped
+    pub fn to_sockaddr_in6(&self) -> sockaddr_in6 {
+        sockaddr_in6 {
+            sin6_family: AF_INET6,
+            sin6_port: self.port.to_be(),
+            sin6_flowinfo: 0,
+            sin6_addr: in6_addr { s6_addr: self.addr.to_v6() },
+            sin6_scope_id: 0,
+        }
+    }
+
+    /// Decode a socket address passed to a system call
+    ///
+    /// # Safety
+    /// `addr` must point to at least `len` readable bytes.
+    pub unsafe fn read_raw(addr: *const sockaddr, len: socklen_t) -> PosixResult<Self> {
+        if addr.is_null() || (len as usize) < core::mem::size_of::<sa_family_t>() {
+            return Err(Errno::Einval);
+        }
+        match (*addr).sa_family {
+            AF_INET if len as usize >= core::mem::size_of::<sockaddr_in>() => {
+                Ok(Self::from_sockaddr_in(&*(addr as *const sockaddr_in)))
+            }
+            AF_INET6 if len as usize >= core::mem::size_of::<sockaddr_in6>() => {
+                Ok(Self::from_sockaddr_in6(&*(addr as *const sockaddr_in6)))
+            }
+            AF_INET | AF_INET6 => Err(Errno::Einval),
+            _ => Err(Errno::Eafnosupport),
+        }
+    }
+
+    /// Store the address for a socket of `family` into a caller buffer
+    ///
+    /// The copy is truncated to `*len` bytes and `*len` is set to the full
+    /// size of the address, as getsockname() and accept() require.
+    ///
+    /// # Safety
+    /// `addr` must point to at least `*len` writable bytes.
+    pub unsafe fn write_raw(&self, family: sa_family_t, addr: *mut sockaddr, len: &mut socklen_t) {
+        let v4;
+        let v6;
+        let bytes: &[u8] = match (family, self.to_sockaddr_in()) {
+            (AF_INET, Some(sin)) => {
+                v4 = sin;
+                core::slice::from_raw_parts(&v4 as *const sockaddr_in as *const u8, core::mem::size_of::<sockaddr_in>())
+            }
+            _ => {
+                v6 = self.to_sockaddr_in6();
+                core::slice::from_raw_parts(&v6 as *const sockaddr_in6 as *const u8, core::mem::size_of::<sockaddr_in6>())
+            }
+        };
+        if !addr.is_null() {
+            let count = bytes.len().min(*len as usize);
+            core::ptr::copy_nonoverlapping(bytes.as_ptr(), addr as *mut u8, count);
+        }
+        *len = bytes.len() as socklen_t;
+    }
+}
+
+/// TCP segment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TcpSegment {
+    pub seq: u32,
+    pub ack: u32,
+    pub flags: u8,
+    pub window: u16,
+    /// Maximum segment size option, carried on SYNs
+    pub mss: Option<u16>,
+    pub payload: Vec<u8>,
+}
+
+impl TcpSegment {
+    /// Sequence space consumed by the segment
+    pub fn seq_len(&self) -> u32 {
+        self.payload.len() as u32
+            + (self.flags & TCP_SYN != 0) as u32
+            + (self.flags & TCP_FIN != 0) as u32
+    }
+
+    fn encode(&self, src: &Endpoint, dst: &Endpoint) -> Vec<u8> {
+        let header_len = if self.mss.is_some() { TCP_HEADER_LEN + 4 } else { TCP_HEADER_LEN };
+        let mut bytes = vec![0u8; header_len];
+        bytes[0..2].copy_from_slice(&src.port.to_be_bytes());
+        bytes[2..4].copy_from_slice(&dst.port.to_be_bytes());
+        bytes[4..8].copy_from_slice(&self.seq.to_be_bytes());
+        bytes[8..12].copy_from_slice(&self.ack.to_be_bytes());
+        bytes[12] = ((header_len / 4) as u8) << 4;
+        bytes[13] = self.flags;
+        bytes[14..16].copy_from_slice(&self.window.to_be_bytes());
+        if let Some(mss) = self.mss {
+            bytes[20] = 2; // Maximum segment size option
+            bytes[21] = 4;
+            bytes[22..24].copy_from_slice(&mss.to_be_bytes());
+        }
+        bytes.extend_from_slice(&self.payload);
+        let pseudo = pseudo_header(&src.addr, &dst.addr, IPPROTO_TCP as u8, bytes.len());
+        let sum = checksum(&[&pseudo[..], &bytes[..]]);
+        bytes[16..18].copy_from_slice(&sum.to_be_bytes());
+        bytes
+    }
+
+    fn decode(src: &IpAddr, dst: &IpAddr, bytes: &[u8]) -> PosixResult<(u16, u16, Self)> {
+        if bytes.len() < TCP_HEADER_LEN {
+            return Err(Errno::Ebadmsg);
+        }
+        let header_len = ((bytes[12] >> 4) as usize) * 4;
+        if header_len < TCP_HEADER_LEN || header_len > bytes.len() {
+            return Err(Errno::Ebadmsg);
+        }
+        let pseudo = pseudo_header(src, dst, IPPROTO_TCP as u8, bytes.len());
+        if checksum(&[&pseudo[..], bytes]) != 0 {
+            return Err(Errno::Ebadmsg);
+        }
+
+        let mut mss = None;
+        let mut options = &bytes[TCP_HEADER_LEN..header_len];
+        while let Some(&kind) = options.first() {
+            match kind {
+                0 => break,
+                1 => options = &options[1..],
+                _ => {
+                    let len = *options.get(1).ok_or(Errno::Ebadmsg)? as usize;
+                    if len < 2 || len > options.len() {
+                        return Err(Errno::Ebadmsg);
+                    }
+                    if kind == 2 && len == 4 {
+                        mss = Some(u16::from_be_bytes([options[2], options[3]]));
+                    }
+                    options = &options[len..];
+                }
+            }
+        }
+
+        let segment = TcpSegment {
+            seq: be32(&bytes[4..8]),
+            ack: be32(&bytes[8..12]),
+            flags: bytes[13],
+            window: be16(&bytes[14..16]),
+            mss,
+            payload: bytes[header_len..].to_vec(),
+        };
+        Ok((be16(&bytes[0..2]), be16(&bytes[2..4]), segment))
+    }
+}
+
+/// Transport-layer contents of a packet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Transport {
+    Tcp(TcpSegment),
+    Udp(Vec<u8>),
+}
+
+/// IP packet carrying TCP or UDP
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Packet {
+    pub src: Endpoint,
+    pub dst: Endpoint,
+    pub transport: Transport,
+}
+
+impl Packet {
+    /// Serialize to an IPv4 or IPv6 packet
+    pub fn encode(&self) -> Vec<u8> {
+        let (protocol, transport) = match &self.transport {
+            Transport::Tcp(segment) => (IPPROTO_TCP as u8, segment.encode(&self.src, &self.dst)),
+            Transport::Udp(payload) => (IPPROTO_UDP as u8, encode_udp(&self.src, &self.dst, payload)),
+        };
+
+        let mut packet = match (self.src.addr, self.dst.addr) {
+            (IpAddr::V4(src), IpAddr::V4(dst)) => {
+                let mut header = vec![0u8; IPV4_HEADER_LEN];
+                header[0] = 0x45;
+                header[2..4].copy_from_slice(&((IPV4_HEADER_LEN + transport.len()) as u16).to_be_bytes());
+                header[6] = 0x40; // Don't fragment
+                header[8] = DEFAULT_TTL;
+                header[9] = protocol;
+                header[12..16].copy_from_slice(&src);
+                header[16..20].copy_from_slice(&dst);
+                let sum = checksum(&[&header[..]]);
+                header[10..12].copy_from_slice(&sum.to_be_bytes());
+                header
+            }
+            (src, dst) => {
+                let mut header = vec![0u8; IPV6_HEADER_LEN];
+                header[0] = 0x60;
+                header[4..6].copy_from_slice(&(transport.len() as u16).to_be_bytes());
+                header[6] = protocol;
+                header[7] = DEFAULT_TTL;
+                header[8..24].copy_from_slice(&src.to_v6());
+                header[24..40].copy_from_slice(&dst.to_v6());
+                header
+            }
+        };
+        packet.extend_from_slice(&transport);
+        packet
+    }
+
+    /// Parse an IPv4 or IPv6 packet, verifying lengths and checksums
+    ///
+    /// Fragments and IPv6 extension headers are not supported.
+    pub fn decode(bytes: &[u8]) -> PosixResult<Self> {
+        let version = bytes.first().ok_or(Errno::Ebadmsg)? >> 4;
+        let (src, dst, protocol, payload) = match version {
+            4 => {
+                if bytes.len() < IPV4_HEADER_LEN {
+                    return Err(Errno::Ebadmsg);
+                }
+                let header_len = ((bytes[0] & 0x0f) as usize) * 4;
+                let total_len = be16(&bytes[2..4]) as usize;
+                if header_len < IPV4_HEADER_LEN || total_len < header_len || total_len > bytes.len() {
+                    return Err(Errno::Ebadmsg);
+                }
+                if checksum(&[&bytes[..header_len]]) != 0 {
+                    return Err(Errno::Ebadmsg);
+                }
+                if be16(&bytes[6..8]) & 0x3fff != 0 {
+                    return Err(Errno::Eopnotsupp);
+                }
+                let src = IpAddr::V4([bytes[12], bytes[13], bytes[14], bytes[15]]);
+                let dst = IpAddr::V4([bytes[16], bytes[17], bytes[18], bytes[19]]);
+                (src, dst, bytes[9], &bytes[header_len..total_len])
+            }
+            6 => {
+                if bytes.len() < IPV6_HEADER_LEN {
+                    return Err(Errno::Ebadmsg);
+                }
+                let payload_len = be16(&bytes[4..6]) as usize;
+                if IPV6_HEADER_LEN + payload_len > bytes.len() {
+                    return Err(Errno::Ebadmsg);
+                }
+                let mut src = [0u8; 16];
+                let mut dst = [0u8; 16];
+                src.copy_from_slice(&bytes[8..24]);
+                dst.copy_from_slice(&bytes[24..40]);
+                (IpAddr::V6(src), IpAddr::V6(dst), bytes[6], &bytes[IPV6_HEADER_LEN..IPV6_HEADER_LEN + payload_len])
+            }
+            _ => return Err(Errno::Ebadmsg),
+        };
+
+        match protocol as i32 {
+            IPPROTO_TCP => {
+                let (src_port, dst_port, segment) = TcpSegment::decode(&src, &dst, payload)?;
+                Ok(Packet {
+                    src: Endpoint::new(src.canonical(), src_port),
+                    dst: Endpoint::new(dst.canonical(), dst_port),
+                    transport: Transport::Tcp(segment),
+                })
+            }
+            IPPROTO_UDP => {
+                if payload.len() < UDP_HEADER_LEN {
+                    return Err(Errno::Ebadmsg);
+                }
+                let udp_len = be16(&payload[4..6]) as usize;
+                if udp_len < UDP_HEADER_LEN || udp_len > payload.len() {
+                    return Err(Errno::Ebadmsg);
+                }
+                let datagram = &payload[..udp_len];
+                // A zero checksum means "not computed", which only IPv4 allows
+                let sum_present = be16(&datagram[6..8]) != 0;
+                if sum_present || version == 6 {
+                    let pseudo = pseudo_header(&src, &dst, IPPROTO_UDP as u8, udp_len);
+                    if checksum(&[&pseudo[..], datagram]) != 0 {
+                        return Err(Errno::Ebadmsg);
+                    }
+                }
+                Ok(Packet {
+                    src: Endpoint::new(src.canonical(), be16(&datagram[0..2])),
+                    dst: Endpoint::new(dst.canonical(), be16(&datagram[2..4])),
+                    transport: Transport::Udp(datagram[UDP_HEADER_LEN..].to_vec()),
+                })
+            }
+            _ => Err(Errno::Eprotonosupp),
+        }
+    }
+}
+
+fn encode_udp(src: &Endpoint, dst: &Endpoint, payload: &[u8]) -> Vec<u8> {
+    let len = UDP_HEADER_LEN + payload.len();
+    let mut bytes = vec![0u8; UDP_HEADER_LEN];
+    bytes[0..2].copy_from_slice(&src.port.to_be_bytes());
+    bytes[2..4].copy_from_slice(&dst.port.to_be_bytes());
+    bytes[4..6].copy_from_slice(&(len as u16).to_be_bytes());
+    bytes.extend_from_slice(payload);
+    let pseudo = pseudo_header(&src.addr, &dst.addr, IPPROTO_UDP as u8, len);
+    let sum = match checksum(&[&pseudo[..], &bytes[..]]) {
+        0 => 0xffff,
+        sum => sum,
+    };
+    bytes[6..8].copy_from_slice(&sum.to_be_bytes());
+    bytes
+}
+
+/// Pseudo-header covered by TCP and UDP checksums
+fn pseudo_header(src: &IpAddr, dst: &IpAddr, protocol: u8, len: usize) -> Vec<u8> {
+    let mut header = Vec::with_capacity(40);
+    match (src, dst) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            header.extend_from_slice(src);
+            header.extend_from_slice(dst);
+            header.extend_from_slice(&[0, protocol]);
+            header.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        _ => {
+            header.extend_from_slice(&src.to_v6());
+            header.extend_from_slice(&dst.to_v6());
+            header.extend_from_slice(&(len as u32).to_be_bytes());
+            header.extend_from_slice(&[0, 0, 0, protocol]);
+        }
+    }
+    header
+}
+
+/// Internet checksum (RFC 1071) over the concatenation of `parts`
+fn checksum(parts: &[&[u8]]) -> u16 {
+    let mut sum: u32 = 0;
+    let mut high: Option<u8> = None;
+    for part in parts {
+        for &byte in part.iter() {
+            match high.take() {
+                Some(first) => sum += u16::from_be_bytes([first, byte]) as u32,
+                None => high = Some(byte),
+            }
+        }
+    }
+    if let Some(first) = high {
+        sum += (first as u32) << 8;
+    }
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+fn be16(bytes: &[u8]) -> u16 {
+    u16::from_be_bytes([bytes[0], bytes[1]])
+}
+
+fn be32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+/// Sequence number comparisons modulo 2^32
+fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+fn seq_le(a: u32, b: u32) -> bool {
+    a == b || seq_lt(a, b)
+}
+
+/// TCP connection states (RFC 793)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TcpState {
+    Closed,
+    Listen,
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+    TimeWait,
+}
+
+impl TcpState {
+    /// States in which the handshake has completed
+    fn is_synchronized(&self) -> bool {
+        !matches!(self, TcpState::Closed | TcpState::Listen | TcpState::SynSent | TcpState::SynReceived)
+    }
+}
+
+/// Socket options
+#[derive(Debug, Clone, Copy)]
+pub struct SocketOptions {
+    pub reuse_addr: bool,
+    pub reuse_port: bool,
+    pub keepalive: bool,
+    pub broadcast: bool,
+    pub nodelay: bool,
+    pub sndbuf: usize,
+    pub rcvbuf: usize,
+    pub rcvlowat: usize,
+    /// Seconds to linger on close; `Some(0)` resets the connection
+    pub linger: Option<u32>,
+    /// Blocking timeouts in microseconds (0 waits forever)
+    pub rcvtimeo_us: u64,
+    pub sndtimeo_us: u64,
+    pub maxseg: u16,
+    pub keepidle: u32,
+    pub keepintvl: u32,
+    pub keepcnt: u32,
+}
+
+impl Default for SocketOptions {
+    fn default() -> Self {
+        Self {
+            reuse_addr: false,
+            reuse_port: false,
+            keepalive: false,
+            broadcast: false,
+            nodelay: false,
+            sndbuf: DEFAULT_BUFFER_SIZE,
+            rcvbuf: DEFAULT_BUFFER_SIZE,
+            rcvlowat: 1,
+            linger: None,
+            rcvtimeo_us: 0,
+            sndtimeo_us: 0,
+            maxseg: DEFAULT_MSS,
+            keepidle: DEFAULT_KEEPIDLE,
+            keepintvl: DEFAULT_KEEPINTVL,
+            keepcnt: DEFAULT_KEEPCNT,
+        }
+    }
+}
+
+/// TCP transmission control block
+#[derive(Debug, Clone, Default)]
+struct Tcb {
+    iss: u32,
+    snd_una: u32,
+    snd_nxt: u32,
+    snd_wnd: u32,
+    rcv_nxt: u32,
+    /// Effective segment size, the minimum of both sides' MSS
+    mss: u16,
+    /// Bytes at the front of the send buffer that have been transmitted
+    in_flight: usize,
+    syn_acked: bool,
+    fin_queued: bool,
+    fin_sent: bool,
+    fin_acked: bool,
+    ack_pending: bool,
+    rto_ms: u64,
+    retransmit_at: Option<u64>,
+    retries: u32,
+    timewait_until: Option<u64>,
+    last_activity: u64,
+    keepalive_probes: u32,
+}
+
+/// One AF_INET or AF_INET6 socket
+#[derive(Debug, Clone)]
+struct Socket {
+    family: sa_family_t,
+    kind: i32,
+    protocol: i32,
+    local: Option<Endpoint>,
+    remote: Option<Endpoint>,
+    nonblocking: bool,
+    options: SocketOptions,
+    state: TcpState,
+    tcb: Tcb,
+    send_buf: VecDeque<u8>,
+    recv_buf: VecDeque<u8>,
+    datagrams: VecDeque<(Endpoint, Vec<u8>)>,
+    datagram_bytes: usize,
+    backlog: usize,
+    syn_queue: Vec<SocketId>,
+    accept_queue: VecDeque<SocketId>,
+    /// Listener of a connection that has not been accepted yet
+    parent: Option<SocketId>,
+    /// Handshake still in progress on behalf of a listener
+    embryonic: bool,
+    /// Closed by its owner; released once the connection is gone
+    orphaned: bool,
+    error: Option<Errno>,
+    read_shutdown: bool,
+    write_shutdown: bool,
+    peer_closed: bool,
+    was_connected: bool,
+}
+
+impl Socket {
+    fn new(family: sa_family_t, kind: i32, protocol: i32) -> Self {
+        Self {
+            family,
+            kind,
+            protocol,
+            local: None,
+            remote: None,
+            nonblocking: false,
+            options: SocketOptions::default(),
+            state: TcpState::Closed,
+            tcb: Tcb::default(),
+            send_buf: VecDeque::new(),
+            recv_buf: VecDeque::new(),
+            datagrams: VecDeque::new(),
+            datagram_bytes: 0,
+            backlog: 0,
+            syn_queue: Vec::new(),
+            accept_queue: VecDeque::new(),
+            parent: None,
+            embryonic: false,
+            orphaned: false,
+            error: None,
+            read_shutdown: false,
+            write_shutdown: false,
+            peer_closed: false,
+            was_connected: false,
+        }
+    }
+
+    fn is_stream(&self) -> bool {
+        self.kind == SOCK_STREAM
+    }
+
+    /// Window advertised to the peer
+    fn recv_window(&self) -> u16 {
+        self.options.rcvbuf.saturating_sub(self.recv_buf.len()).min(u16::MAX as usize) as u16
+    }
+
+    fn segment(&self, flags: u8, seq: u32, payload: Vec<u8>) -> Packet {
+        let syn = flags & TCP_SYN != 0;
+        Packet {
+            src: self.local.expect("connected socket has a local endpoint"),
+            dst: self.remote.expect("connected socket has a remote endpoint"),
+            transport: Transport::Tcp(TcpSegment {
+                seq,
+                ack: if flags & TCP_ACK != 0 { self.tcb.rcv_nxt } else { 0 },
+                flags,
+                window: self.recv_window(),
+                mss: syn.then_some(self.options.maxseg),
+                payload,
+            }),
+        }
+    }
+
+    fn arm_retransmit(&mut self, now: u64) {
+        if self.tcb.retransmit_at.is_none() {
+            self.tcb.retransmit_at = Some(now + self.tcb.rto_ms);
+        }
+    }
+
+    /// Start a handshake with a fresh initial sequence number
+    fn init_tcb(&mut self, iss: u32, now: u64) {
+        self.tcb = Tcb {
+            iss,
+            snd_una: iss,
+            snd_nxt: iss.wrapping_add(1),
+            mss: self.options.maxseg,
+            rto_ms: INITIAL_RTO_MS,
+            last_activity: now,
+            ..Tcb::default()
+        };
+    }
+
+    /// Send whatever data, FIN and ACK the connection allows
+    fn tcp_output(&mut self, now: u64, out: &mut Vec<Packet>) {
+        match self.state {
+            TcpState::Established | TcpState::CloseWait | TcpState::FinWait1
+            | TcpState::Closing | TcpState::LastAck => {}
+            TcpState::FinWait2 | TcpState::TimeWait => {
+                if self.tcb.ack_pending {
+                    out.push(self.segment(TCP_ACK, self.tcb.snd_nxt, Vec::new()));
+                    self.tcb.ack_pending = false;
+                }
+                return;
+            }
+            _ => return,
+        }
+
+        let mss = self.tcb.mss.max(1) as usize;
+        let mut sent = false;
+        loop {
+            let pending = self.send_buf.len() - self.tcb.in_flight;
+            if pending == 0 {
+                break;
+            }
+            let usable = (self.tcb.snd_wnd as usize).saturating_sub(self.tcb.in_flight);
+            let len = pending.min(mss).min(usable);
+            if len == 0 {
+                break;
+            }
+            // Nagle: hold back small segments while data is unacknowledged
+            if !self.options.nodelay && len < mss && self.tcb.in_flight > 0 {
+                break;
+            }
+            let payload: Vec<u8> = self.send_buf.iter().skip(self.tcb.in_flight).take(len).copied().collect();
+            out.push(self.segment(TCP_ACK | TCP_PSH, self.tcb.snd_nxt, payload));
+            self.tcb.snd_nxt = self.tcb.snd_nxt.wrapping_add(len as u32);
+            self.tcb.in_flight += len;
+            sent = true;
+        }
+
+        if self.tcb.fin_queued && !self.tcb.fin_sent && self.tcb.in_flight == self.send_buf.len() {
+            out.push(self.segment(TCP_FIN | TCP_ACK, self.tcb.snd_nxt, Vec::new()));
+            self.tcb.snd_nxt = self.tcb.snd_nxt.wrapping_add(1);
+            self.tcb.fin_sent = true;
+            sent = true;
+        }
+
+        if sent {
+            self.tcb.ack_pending = false;
+            self.arm_retransmit(now);
+        } else if self.tcb.ack_pending {
+            out.push(self.segment(TCP_ACK, self.tcb.snd_nxt, Vec::new()));
+            self.tcb.ack_pending = false;
+        }
+        // A closed window is probed when the (persist) timer fires
+        if self.tcb.snd_wnd == 0 && self.tcb.in_flight < self.send_buf.len() {
+            self.arm_retransmit(now);
+        }
+    }
+
+    /// Retransmission timeout: resend from the oldest unacknowledged byte.
+    ///
+    /// Returns false once the connection should be given up.
+    fn retransmit(&mut self, now: u64, out: &mut Vec<Packet>) -> bool {
+        // Zero-window probes persist; they do not count towards giving up
+        let probing = self.state.is_synchronized() && self.tcb.snd_wnd == 0;
+        if !probing {
+            self.tcb.retries += 1;
+            if self.tcb.retries > MAX_RETRIES {
+                return false;
+            }
+        }
+        self.tcb.rto_ms = (self.tcb.rto_ms * 2).min(MAX_RTO_MS);
+        self.tcb.retransmit_at = None;
+
+        match self.state {
+            TcpState::SynSent => out.push(self.segment(TCP_SYN, self.tcb.iss, Vec::new())),
+            TcpState::SynReceived => out.push(self.segment(TCP_SYN | TCP_ACK, self.tcb.iss, Vec::new())),
+            _ => {
+                // Go back to the first unacknowledged byte
+                self.tcb.snd_nxt = self.tcb.snd_una;
+                self.tcb.in_flight = 0;
+                self.tcb.fin_sent = false;
+                if probing && !self.send_buf.is_empty() {
+                    // Zero-window probe: one byte the peer must answer
+                    let probe = vec![self.send_buf[0]];
+                    out.push(self.segment(TCP_ACK, self.tcb.snd_nxt, probe));
+                    self.tcb.snd_nxt = self.tcb.snd_nxt.wrapping_add(1);
+                    self.tcb.in_flight = 1;
+                } else {
+                    self.tcp_output(now, out);
+                }
+            }
+        }
+        self.arm_retransmit(now);
+        true
+    }
+
+    /// Process an acknowledgment; returns false if it acknowledges unsent data
+    fn process_ack(&mut self, ack: u32, window: u16, now: u64) -> bool {
+        if seq_lt(self.tcb.snd_nxt, ack) {
+            return false;
+        }
+        if seq_le(ack, self.tcb.snd_una) {
+            // Duplicate: only the window may have changed
+            if ack == self.tcb.snd_una {
+                let reopened = self.tcb.snd_wnd == 0 && window > 0;
+                self.tcb.snd_wnd = window as u32;
+                if reopened && self.tcb.in_flight > 0 {
+                    // The probe was not taken; resend from the window edge
+                    self.tcb.snd_nxt = self.tcb.snd_una;
+                    self.tcb.in_flight = 0;
+                    self.tcb.fin_sent = false;
+                }
+            }
+            return true;
+        }
+
+        let mut acked = ack.wrapping_sub(self.tcb.snd_una) as usize;
+        if !self.tcb.syn_acked {
+            self.tcb.syn_acked = true;
+            acked -= 1;
+        }
+        let data = acked.min(self.tcb.in_flight);
+        self.send_buf.drain(..data);
+        self.tcb.in_flight -= data;
+        if acked > data && self.tcb.fin_sent {
+            self.tcb.fin_acked = true;
+        }
+
+        self.tcb.snd_una = ack;
+        self.tcb.snd_wnd = window as u32;
+        self.tcb.retries = 0;
+        self.tcb.rto_ms = INITIAL_RTO_MS;
+        self.tcb.retransmit_at = (self.tcb.snd_una != self.tcb.snd_nxt).then_some(now + self.tcb.rto_ms);
+        true
+    }
+
+    /// Tear the connection down, reporting `err` to the owner
+    fn abort(&mut self, err: Option<Errno>) {
+        if err.is_some() {
+            self.error = err;
+        }
+        self.state = TcpState::Closed;
+        self.send_buf.clear();
+        self.tcb.in_flight = 0;
+        self.tcb.retransmit_at = None;
+        self.peer_closed = true;
+    }
+
+    fn enter_time_wait(&mut self, now: u64) {
+        self.state = TcpState::TimeWait;
+        self.tcb.retransmit_at = None;
+        self.tcb.timewait_until = Some(now + 2 * MSL_MS);
+    }
+
+    /// Current poll events of the socket
+    fn readiness(&self) -> u32 {
+        let mut events = 0;
+        if self.error.is_some() {
+            events |= EPOLLERR;
+        }
+        if !self.is_stream() {
+            if !self.datagrams.is_empty() {
+                events |= EPOLLIN;
+            }
+            if !self.write_shutdown {
+                events |= EPOLLOUT;
+            }
+            return events;
+        }
+
+        match self.state {
+            TcpState::Listen => {
+                if !self.accept_queue.is_empty() {
+                    events |= EPOLLIN;
+                }
+            }
+            TcpState::SynSent | TcpState::SynReceived => {}
+            _ => {
+                if self.recv_buf.len() >= self.options.rcvlowat.max(1) || self.peer_closed || self.read_shutdown {
+                    events |= EPOLLIN;
+                }
+                let writable = matches!(self.state, TcpState::Established | TcpState::CloseWait);
+                if writable && !self.write_shutdown && self.send_buf.len() < self.options.sndbuf {
+                    events |= EPOLLOUT;
+                }
+                if self.peer_closed {
+                    events |= EPOLLRDHUP;
+                }
+                if (self.peer_closed && self.write_shutdown) || (self.state == TcpState::Closed && self.was_connected) {
+                    events |= EPOLLHUP;
+                }
+            }
+        }
+        events
+    }
+}
+
+/// Network stack statistics
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NetStackStats {
+    pub sockets_created: u64,
+    pub sockets_released: u64,
+    pub tcp_segments_in: u64,
+    pub tcp_segments_out: u64,
+    pub tcp_retransmits: u64,
+    pub tcp_resets_sent: u64,
+    pub connections_established: u64,
+    pub connections_reset: u64,
+    pub syns_dropped: u64,
+    pub udp_datagrams_in: u64,
+    pub udp_datagrams_out: u64,
+    pub udp_dropped: u64,
+    pub packets_looped_back: u64,
+    pub packets_transmitted: u64,
+    pub packets_malformed: u64,
+}
+
+/// The TCP/UDP stack behind AF_INET and AF_INET6 sockets
+#[derive(Debug, Default)]
+pub struct NetStack {
+    sockets: BTreeMap<SocketId, Socket>,
+    next_id: SocketId,
+    /// Addresses configured on network interfaces (loopback is implicit)
+    addresses: Vec<IpAddr>,
+    loopback: VecDeque<Packet>,
+    transmit: VecDeque<Vec<u8>>,
+    /// Sockets whose readiness may have changed since the last drain
+    dirty: BTreeSet<SocketId>,
+    next_ephemeral: u16,
+    connections: u32,
+    now_ms: u64,
+    stats: NetStackStats,
+}
+
+impl NetStack {
+    pub fn new() -> Self {
+        Self {
+            next_id: 1,
+            next_ephemeral: EPHEMERAL_PORT_FIRST,
+            ..Self::default()
+        }
+    }
+
+    /// Assign an address to the host's interfaces
+    pub fn add_address(&mut self, addr: IpAddr) {
+        let addr = addr.canonical();
+        if !addr.is_unspecified() && !self.addresses.contains(&addr) {
+            self.addresses.push(addr);
+        }
+    }
+
+    pub fn remove_address(&mut self, addr: IpAddr) {
+        let addr = addr.canonical();
+        self.addresses.retain(|configured| *configured != addr);
+    }
+
+    /// Whether packets to `addr` are delivered to this host
+    pub fn is_local(&self, addr: &IpAddr) -> bool {
+        addr.is_loopback() || self.addresses.contains(addr)
+    }
+
+    fn sock(&self, id: SocketId) -> PosixResult<&Socket> {
+        self.sockets.get(&id).ok_or(Errno::Ebadf)
+    }
+
+    fn sock_mut(&mut self, id: SocketId) -> PosixResult<&mut Socket> {
+        self.sockets.get_mut(&id).ok_or(Errno::Ebadf)
+    }
+
+    /// Create a socket (socket())
+    ///
+    /// `ty` may include SOCK_NONBLOCK and SOCK_CLOEXEC; the latter is a
+    /// descriptor flag and is ignored here.
+    pub fn socket(&mut self, domain: sa_family_t, ty: i32, protocol: i32) -> PosixResult<SocketId> {
+        if domain != AF_INET && domain != AF_INET6 {
+            return Err(Errno::Eafnosupport);
+        }
+        let kind = ty & !(SOCK_NONBLOCK | SOCK_CLOEXEC);
+        let protocol = match (kind, protocol) {
+            (SOCK_STREAM, 0) | (SOCK_STREAM, IPPROTO_TCP) => IPPROTO_TCP,
+            (SOCK_DGRAM, 0) | (SOCK_DGRAM, IPPROTO_UDP) => IPPROTO_UDP,
+            _ => return Err(Errno::Eprotonosupp),
+        };
+
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1).max(1);
+        let mut sock = Socket::new(domain, kind, protocol);
+        sock.nonblocking = ty & SOCK_NONBLOCK != 0;
+        self.sockets.insert(id, sock);
+        self.stats.sockets_created += 1;
+        Ok(id)
+    }
+
+    /// Check the family of an endpoint against the socket
+    fn check_family(sock: &Socket, endpoint: &Endpoint) -> PosixResult<()> {
+        match (sock.family, endpoint.addr) {
+            (AF_INET, IpAddr::V6(_)) => Err(Errno::Eafnosupport),
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether binding `local` would clash with another socket
+    fn port_in_use(&self, id: SocketId, local: &Endpoint) -> bool {
+        let sock = match self.sockets.get(&id) {
+            Some(sock) => sock,
+            None => return false,
+        };
+        self.sockets.iter().any(|(&other_id, other)| {
+            let bound = match other.local {
+                Some(bound) => bound,
+                None => return false,
+            };
+            if other_id == id || other.kind != sock.kind || other.parent.is_some() || bound.port != local.port {
+                return false;
+            }
+            let overlap = bound.addr == local.addr || bound.addr.is_unspecified() || local.addr.is_unspecified();
+            if !overlap {
+                return false;
+            }
+            if sock.options.reuse_port && other.options.reuse_port {
+                return false;
+            }
+            // SO_REUSEADDR lets a socket bind next to connections that are not listening
+            let shareable = sock.options.reuse_addr && other.options.reuse_addr && other.state != TcpState::Listen;
+            !(shareable || (sock.options.reuse_addr && other.state == TcpState::TimeWait))
+        })
+    }
+
+    fn ephemeral_port(&mut self, id: SocketId, addr: IpAddr) -> PosixResult<u16> {
+        let range = (EPHEMERAL_PORT_LAST - EPHEMERAL_PORT_FIRST) as u32 + 1;
+        for _ in 0..range {
+            let port = self.next_ephemeral;
+            self.next_ephemeral = if port == EPHEMERAL_PORT_LAST { EPHEMERAL_PORT_FIRST } else { port + 1 };
+            if !self.port_in_use(id, &Endpoint::new(addr, port)) {
+                return Ok(port);
+            }
+        }
+        Err(Errno::Eaddrinuse)
+    }
+
+    /// Bind a socket to a local endpoint (bind()); port 0 picks an ephemeral port
+    pub fn bind(&mut self, id: SocketId, local: Endpoint) -> PosixResult<()> {
+        let sock = self.sock(id)?;
+        Self::check_family(sock, &local)?;
+        if sock.local.is_some() {
+            return Err(Errno::Einval);
+        }
+        let local = Endpoint::new(local.addr.canonical(), local.port);
+        if !local.addr.is_unspecified() && !self.is_local(&local.addr) {
+            return Err(Errno::Eaddrnotavail);
+        }
+        let port = match local.port {
+            0 => self.ephemeral_port(id, local.addr)?,
+            _ if self.port_in_use(id, &local) => return Err(Errno::Eaddrinuse),
+            port => port,
+        };
+        self.sock_mut(id)?.local = Some(Endpoint::new(local.addr, port));
+        Ok(())
+    }
+
+    /// Bind to a wildcard ephemeral port if the socket is not bound yet
+    fn autobind(&mut self, id: SocketId) -> PosixResult<()> {
+        let sock = self.sock(id)?;
+        if sock.local.is_some() {
+            return Ok(());
+        }
+        let any = if sock.family == AF_INET { IpAddr::V4_ANY } else { IpAddr::V6_ANY };
+        self.bind(id, Endpoint::new(any, 0))
+    }
+
+    /// Pick the source address for reaching `dst`
+    fn source_for(&self, dst: &IpAddr) -> PosixResult<IpAddr> {
+        if self.is_local(dst) {
+            return Ok(*dst);
+        }
+        let same_family = |addr: &&IpAddr| addr.family() == dst.family();
+        self.addresses.iter().find(same_family).copied().ok_or(Errno::Enetunreach)
+    }
+
+    /// Resolve the wildcard parts of a destination and the matching source
+    fn route(&self, sock: &Socket, dst: Endpoint) -> PosixResult<(Endpoint, Endpoint)> {
+        let mut dst = Endpoint::new(dst.addr.canonical(), dst.port);
+        if dst.addr.is_unspecified() {
+            dst.addr = dst.addr.loopback_of();
+        }
+        let bound = sock.local.ok_or(Errno::Einval)?;
+        let src_addr = if bound.addr.is_unspecified() { self.source_for(&dst.addr)? } else { bound.addr };
+        if src_addr.family() != dst.addr.family() {
+            return Err(Errno::Enetunreach);
+        }
+        Ok((Endpoint::new(src_addr, bound.port), dst))
+    }
+
+    /// Start accepting connections (listen())
+    pub fn listen(&mut self, id: SocketId, backlog: i32) -> PosixResult<()> {
+        let sock = self.sock(id)?;
+        if !sock.is_stream() {
+            return Err(Errno::Eopnotsupp);
+        }
+        if !matches!(sock.state, TcpState::Closed | TcpState::Listen) || sock.was_connected {
+            return Err(Errno::Einval);
+        }
+        self.autobind(id)?;
+        let sock = self.sock_mut(id)?;
+        sock.backlog = backlog.clamp(1, SOMAXCONN) as usize;
+        sock.state = TcpState::Listen;
+        Ok(())
+    }
+
+    fn next_iss(&mut self) -> u32 {
+        // Clock-driven ISN with a per-connection offset (RFC 793 section 3.3)
+        self.connections = self.connections.wrapping_add(1);
+        ((self.now_ms.wrapping_mul(250)) as u32).wrapping_add(self.connections.wrapping_mul(64_000))
+    }
+
+    /// Connect to a remote endpoint (connect())
+    ///
+    /// For TCP this sends the SYN and returns `Einprogress`; completion is
+    /// reported by EPOLLOUT (or EPOLLERR) and collected with
+    /// `finish_connect`. UDP sockets just record the default destination.
+    pub fn connect(&mut self, id: SocketId, remote: Endpoint) -> PosixResult<()> {
+        let sock = self.sock(id)?;
+        Self::check_family(sock, &remote)?;
+        if remote.port == 0 {
+            return Err(Errno::Econnrefused);
+        }
+        if sock.is_stream() {
+            match sock.state {
+                TcpState::Closed if !sock.was_connected => {}
+                TcpState::SynSent | TcpState::SynReceived => return Err(Errno::Ealread),
+                _ => return Err(Errno::Eisconn),
+            }
+        }
+
+        self.autobind(id)?;
+        let (local, remote) = self.route(self.sock(id)?, remote)?;
+        let iss = self.next_iss();
+        let now = self.now_ms;
+        let sock = self.sock_mut(id)?;
+        sock.remote = Some(remote);
+        if !sock.is_stream() {
+            return Ok(());
+        }
+
+        sock.local = Some(local);
+        sock.init_tcb(iss, now);
+        sock.state = TcpState::SynSent;
+        sock.error = None;
+        let syn = sock.segment(TCP_SYN, iss, Vec::new());
+        sock.arm_retransmit(now);
+        self.flush(vec![syn]);
+        // Loopback handshakes complete (or are refused) immediately
+        let sock = self.sock_mut(id)?;
+        match sock.state {
+            TcpState::Established => Ok(()),
+            TcpState::Closed => Err(sock.error.take().unwrap_or(Errno::Econnrefused)),
+            _ => Err(Errno::Einprogress),
+        }
+    }
+
+    /// Result of an in-progress connect(): `Eagain` while still connecting
+    pub fn finish_connect(&mut self, id: SocketId) -> PosixResult<()> {
+        let sock = self.sock_mut(id)?;
+        if let Some(err) = sock.error.take() {
+            return Err(err);
+        }
+        match sock.state {
+            TcpState::SynSent | TcpState::SynReceived => Err(Errno::Eagain),
+            TcpState::Closed | TcpState::Listen => Err(Errno::Enotconn),
+            _ => Ok(()),
+        }
+    }
+
+    /// Take a completed connection off a listener (accept())
+    ///
+    /// The new socket does not inherit the listener's non-blocking mode.
+    pub fn accept(&mut self, id: SocketId) -> PosixResult<(SocketId, Endpoint)> {
+        let sock = self.sock_mut(id)?;
+        if sock.state != TcpState::Listen {
+            return Err(Errno::Einval);
+        }
+        let child = sock.accept_queue.pop_front().ok_or(Errno::Eagain)?;
+        self.dirty.insert(id);
+        let child = self.sock_mut(child).map(|sock| {
+            sock.parent = None;
+            (child, sock.remote.expect("accepted connection has a peer"))
+        })?;
+        Ok(child)
+    }
+
+    /// Queue data on a connected socket (send())
+    ///
+    /// Returns the number of bytes accepted; `Epipe` after shutdown, in which
+    /// case the caller raises SIGPIPE unless MSG_NOSIGNAL was given.
+    pub fn send(&mut self, id: SocketId, data: &[u8], flags: i32) -> PosixResult<usize> {
+        if !self.sock(id)?.is_stream() {
+            return self.sendto(id, data, flags, None);
+        }
+        let now = self.now_ms;
+        let sock = self.sock_mut(id)?;
+        if let Some(err) = sock.error.take() {
+            return Err(err);
+        }
+        if sock.write_shutdown {
+            return Err(Errno::Epipe);
+        }
+        match sock.state {
+            TcpState::Established | TcpState::CloseWait => {}
+            TcpState::SynSent | TcpState::SynReceived => return Err(Errno::Eagain),
+            TcpState::Closed if sock.was_connected => return Err(Errno::Epipe),
+            _ => return Err(Errno::Enotconn),
+        }
+
+        let space = sock.options.sndbuf.saturating_sub(sock.send_buf.len());
+        if space == 0 {
+            return Err(Errno::Eagain);
+        }
+        let count = data.len().min(space);
+        sock.send_buf.extend(&data[..count]);
+        let mut out = Vec::new();
+        sock.tcp_output(now, &mut out);
+        self.flush(out);
+        Ok(count)
+    }
+
+    /// Send a datagram, or stream data if `dest` is `None` (sendto())
+    pub fn sendto(&mut self, id: SocketId, data: &[u8], flags: i32, dest: Option<Endpoint>) -> PosixResult<usize> {
+        let sock = self.sock(id)?;
+        if sock.is_stream() {
+            // The destination is ignored on connected streams
+            return self.send(id, data, flags);
+        }
+        if sock.write_shutdown {
+            return Err(Errno::Epipe);
+        }
+        let dest = match dest.or(sock.remote) {
+            Some(dest) => dest,
+            None => return Err(Errno::Enotconn),
+        };
+        Self::check_family(sock, &dest)?;
+        if data.len() > MAX_UDP_PAYLOAD {
+            return Err(Errno::Elength);
+        }
+        if dest.addr.canonical().is_broadcast() && !sock.options.broadcast {
+            return Err(Errno::Eaccess);
+        }
+
+        self.autobind(id)?;
+        let sock = self.sock(id)?;
+        let (src, dst) = self.route(sock, dest)?;
+        let packet = Packet { src, dst, transport: Transport::Udp(data.to_vec()) };
+        self.flush(vec![packet]);
+        Ok(data.len())
+    }
+
+    /// Receive stream data (recv()); `Ok(0)` at end of stream
+    pub fn recv(&mut self, id: SocketId, buf: &mut [u8], flags: i32) -> PosixResult<usize> {
+        if !self.sock(id)?.is_stream() {
+            return self.recvfrom(id, buf, flags).map(|(len, _)| len);
+        }
+        let now = self.now_ms;
+        let sock = self.sock_mut(id)?;
+        if sock.recv_buf.is_empty() {
+            if let Some(err) = sock.error.take() {
+                return Err(err);
+            }
+            if sock.peer_closed || sock.read_shutdown {
+                return Ok(0);
+            }
+            return match sock.state {
+                TcpState::Closed | TcpState::Listen => Err(Errno::Enotconn),
+                _ => Err(Errno::Eagain),
+            };
+        }
+
+        let count = buf.len().min(sock.recv_buf.len());
+        for (dst, src) in buf.iter_mut().zip(sock.recv_buf.iter()) {
+            *dst = *src;
+        }
+        if flags & MSG_PEEK == 0 {
+            let window_before = sock.recv_window() as usize;
+            sock.recv_buf.drain(..count);
+            // Tell the peer about a window that reopened from below one segment
+            if window_before < sock.tcb.mss as usize && sock.state.is_synchronized() {
+                sock.tcb.ack_pending = true;
+                let mut out = Vec::new();
+                sock.tcp_output(now, &mut out);
+                self.flush(out);
+            }
+        }
+        Ok(count)
+    }
+
+    /// Receive a datagram and its sender (recvfrom())
+    ///
+    /// Excess bytes of a datagram are discarded; with MSG_TRUNC the full
+    /// datagram length is returned. Stream sockets report their peer.
+    pub fn recvfrom(&mut self, id: SocketId, buf: &mut [u8], flags: i32) -> PosixResult<(usize, Endpoint)> {
+        let sock = self.sock(id)?;
+        if sock.is_stream() {
+            let peer = sock.remote.ok_or(Errno::Enotconn)?;
+            return self.recv(id, buf, flags).map(|len| (len, peer));
+        }
+
+        let sock = self.sock_mut(id)?;
+        if let Some(err) = sock.error.take() {
+            return Err(err);
+        }
+        let (from, len, count) = match sock.datagrams.front() {
+            Some((from, payload)) => {
+                let count = buf.len().min(payload.len());
+                buf[..count].copy_from_slice(&payload[..count]);
+                (*from, payload.len(), count)
+            }
+            None if sock.read_shutdown => return Ok((0, Endpoint::new(IpAddr::V4_ANY, 0))),
+            None => return Err(Errno::Eagain),
+        };
+        if flags & MSG_PEEK == 0 {
+            sock.datagrams.pop_front();
+            sock.datagram_bytes -= len;
+        }
+        Ok((if flags & MSG_TRUNC != 0 { len } else { count }, from))
+    }
+
+    /// Shut down one or both directions (shutdown())
+    pub fn shutdown(&mut self, id: SocketId, how: i32) -> PosixResult<()> {
+        if how != SHUT_RD && how != SHUT_WR && how != SHUT_RDWR {
+            return Err(Errno::Einval);
+        }
+        let now = self.now_ms;
+        let sock = self.sock_mut(id)?;
+        let connected = if sock.is_stream() { sock.state.is_synchronized() } else { sock.remote.is_some() };
+        if !connected {
+            return Err(Errno::Enotconn);
+        }
+        self.dirty.insert(id);
+
+        let sock = self.sock_mut(id)?;
+        if how != SHUT_WR {
+            sock.read_shutdown = true;
+            sock.recv_buf.clear();
+            sock.datagrams.clear();
+            sock.datagram_bytes = 0;
+        }
+        if how != SHUT_RD && !sock.write_shutdown {
+            sock.write_shutdown = true;
+            if sock.is_stream() {
+                sock.tcb.fin_queued = true;
+                sock.state = match sock.state {
+                    TcpState::Established => TcpState::FinWait1,
+                    TcpState::CloseWait => TcpState::LastAck,
+                    other => other,
+                };
+                let mut out = Vec::new();
+                sock.tcp_output(now, &mut out);
+                self.flush(out);
+            }
+        }
+        Ok(())
+    }
+
+    /// Send a reset for an established connection
+    fn reset_packet(sock: &Socket) -> Option<Packet> {
+        match (sock.local, sock.remote) {
+            (Some(_), Some(_)) => Some(sock.segment(TCP_RST | TCP_ACK, sock.tcb.snd_nxt, Vec::new())),
+            _ => None,
+        }
+    }
+
+    /// Close a socket
+    ///
+    /// Connections are shut down gracefully and released once the FIN
+    /// exchange finishes; unread data or a zero linger time resets them.
+    pub fn close(&mut self, id: SocketId) -> PosixResult<()> {
+        let sock = self.sock(id)?;
+        let mut out = Vec::new();
+
+        if !sock.is_stream() || matches!(sock.state, TcpState::Closed | TcpState::SynSent | TcpState::TimeWait) {
+            self.release(id);
+            return Ok(());
+        }
+
+        if sock.state == TcpState::Listen {
+            let children: Vec<SocketId> = sock.syn_queue.iter().chain(sock.accept_queue.iter()).copied().collect();
+            for child in children {
+                if let Some(packet) = self.sockets.get(&child).and_then(Self::reset_packet) {
+                    out.push(packet);
+                    self.stats.tcp_resets_sent += 1;
+                }
+                self.release(child);
+            }
+            self.release(id);
+            self.flush(out);
+            return Ok(());
+        }
+
+        let abortive = !sock.recv_buf.is_empty() || sock.options.linger == Some(0)
+            || sock.state == TcpState::SynReceived;
+        if abortive {
+            if let Some(packet) = Self::reset_packet(sock) {
+                out.push(packet);
+                self.stats.tcp_resets_sent += 1;
+            }
+            self.release(id);
+            self.flush(out);
+            return Ok(());
+        }
+
+        if !self.sock(id)?.write_shutdown {
+            self.shutdown(id, SHUT_WR)?;
+        }
+        self.sock_mut(id)?.orphaned = true;
+        self.reap(id);
+        Ok(())
+    }
+
+    fn release(&mut self, id: SocketId) {
+        if let Some(sock) = self.sockets.remove(&id) {
+            if let Some(parent) = sock.parent.and_then(|parent| self.sockets.get_mut(&parent)) {
+                parent.syn_queue.retain(|&child| child != id);
+                parent.accept_queue.retain(|&child| child != id);
+            }
+            self.dirty.remove(&id);
+            self.stats.sockets_released += 1;
+        }
+    }
+
+    /// Release a socket that nobody can reach any more
+    fn reap(&mut self, id: SocketId) {
+        let dead = self.sockets.get(&id)
+            .map_or(false, |sock| sock.state == TcpState::Closed && (sock.orphaned || sock.embryonic));
+        if dead {
+            self.release(id);
+        }
+    }
+
+    /// Set or clear non-blocking mode (O_NONBLOCK)
+    pub fn set_nonblocking(&mut self, id: SocketId, nonblocking: bool) -> PosixResult<()> {
+        self.sock_mut(id)?.nonblocking = nonblocking;
+        Ok(())
+    }
+
+    pub fn is_nonblocking(&self, id: SocketId) -> PosixResult<bool> {
+        self.sock(id).map(|sock| sock.nonblocking)
+    }
+
+    /// Address family of the socket, for formatting addresses
+    pub fn family(&self, id: SocketId) -> PosixResult<sa_family_t> {
+        self.sock(id).map(|sock| sock.family)
+    }
+
+    /// Local endpoint (getsockname()); unbound sockets report the wildcard
+    pub fn local_endpoint(&self, id: SocketId) -> PosixResult<Endpoint> {
+        let sock = self.sock(id)?;
+        let any = if sock.family == AF_INET { IpAddr::V4_ANY } else { IpAddr::V6_ANY };
+        Ok(sock.local.unwrap_or(Endpoint::new(any, 0)))
+    }
+
+    /// Remote endpoint (getpeername())
+    pub fn peer_endpoint(&self, id: SocketId) -> PosixResult<Endpoint> {
+        let sock = self.sock(id)?;
+        let connected = if sock.is_stream() { sock.state.is_synchronized() } else { true };
+        sock.remote.filter(|_| connected).ok_or(Errno::Enotconn)
+    }
+
+    pub fn tcp_state(&self, id: SocketId) -> PosixResult<TcpState> {
+        self.sock(id).map(|sock| sock.state)
+    }
+
+    pub fn options(&self, id: SocketId) -> PosixResult<SocketOptions> {
+        self.sock(id).map(|sock| sock.options)
+    }
+
+    /// Current poll events (EPOLLIN, EPOLLOUT, ...) of a socket
+    pub fn readiness(&self, id: SocketId) -> PosixResult<u32> {
+        self.sock(id).map(Socket::readiness)
+    }
+
+    /// Sockets whose readiness changed through network activity or timers
+    ///
+    /// The descriptor layer forwards their readiness to `EventQueue::notify`.
+    pub fn drain_wakeups(&mut self) -> Vec<SocketId> {
+        core::mem::take(&mut self.dirty).into_iter().collect()
+    }
+
+    /// Set a socket option (setsockopt())
+    pub fn setsockopt(&mut self, id: SocketId, level: i32, name: i32, value: &[u8]) -> PosixResult<()> {
+        let sock = self.sock_mut(id)?;
+        let int = || -> PosixResult<i32> {
+            value.get(..4).map(|bytes| i32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])).ok_or(Errno::Einval)
+        };
+        let buffer_size = |size: i32| (size.max(0) as usize).clamp(MIN_BUFFER_SIZE, MAX_BUFFER_SIZE);
+        let options = &mut sock.options;
+
+        match (level, name) {
+            (SOL_SOCKET, SO_REUSEADDR) => options.reuse_addr = int()? != 0,
+            (SOL_SOCKET, SO_REUSEPORT) => options.reuse_port = int()? != 0,
+            (SOL_SOCKET, SO_KEEPALIVE) => options.keepalive = int()? != 0,
+            (SOL_SOCKET, SO_BROADCAST) => options.broadcast = int()? != 0,
+            (SOL_SOCKET, SO_SNDBUF) => options.sndbuf = buffer_size(int()?),
+            (SOL_SOCKET, SO_RCVBUF) => options.rcvbuf = buffer_size(int()?),
+            (SOL_SOCKET, SO_RCVLOWAT) => options.rcvlowat = int()?.max(1) as usize,
+            (SOL_SOCKET, SO_LINGER) => {
+                if value.len() < core::mem::size_of::<linger>() {
+                    return Err(Errno::Einval);
+                }
+                let onoff = i32::from_ne_bytes([value[0], value[1], value[2], value[3]]);
+                let seconds = i32::from_ne_bytes([value[4], value[5], value[6], value[7]]);
+                options.linger = (onoff != 0).then_some(seconds.max(0) as u32);
+            }
+            (SOL_SOCKET, SO_RCVTIMEO) | (SOL_SOCKET, SO_SNDTIMEO) => {
+                if value.len() < core::mem::size_of::<timeval>() {
+                    return Err(Errno::Einval);
+                }
+                let tv = unsafe { core::ptr::read_unaligned(value.as_ptr() as *const timeval) };
+                if tv.tv_sec < 0 || tv.tv_usec < 0 || tv.tv_usec >= 1_000_000 {
+                    return Err(Errno::Edom);
+                }
+                let timeout = tv.tv_sec as u64 * 1_000_000 + tv.tv_usec as u64;
+                if name == SO_RCVTIMEO {
+                    options.rcvtimeo_us = timeout;
+                } else {
+                    options.sndtimeo_us = timeout;
+                }
+            }
+            (SOL_SOCKET, SO_TYPE) | (SOL_SOCKET, SO_ERROR) | (SOL_SOCKET, SO_ACCEPTCONN)
+            | (SOL_SOCKET, SO_PROTOCOL) | (SOL_SOCKET, SO_DOMAIN) => return Err(Errno::Eprotoall),
+            (IPPROTO_TCP, _) if sock.kind != SOCK_STREAM => return Err(Errno::Eopnotsupp),
+            (IPPROTO_TCP, TCP_NODELAY) => options.nodelay = int()? != 0,
+            (IPPROTO_TCP, TCP_MAXSEG) => {
+                let mss = int()?;
+                if !(88..=u16::MAX as i32).contains(&mss) {
+                    return Err(Errno::Einval);
+                }
+                options.maxseg = mss as u16;
+            }
+            (IPPROTO_TCP, TCP_KEEPIDLE) | (IPPROTO_TCP, TCP_KEEPINTVL) | (IPPROTO_TCP, TCP_KEEPCNT) => {
+                let value = int()?;
+                if value < 1 {
+                    return Err(Errno::Einval);
+                }
+                match name {
+                    TCP_KEEPIDLE => options.keepidle = value as u32,
+                    TCP_KEEPINTVL => options.keepintvl = value as u32,
+                    _ => options.keepcnt = value as u32,
+                }
+            }
+            _ => return Err(Errno::Eprotoall),
+        }
+
+        // A larger receive buffer may reopen a closed window
+        if level == SOL_SOCKET && name == SO_RCVBUF && sock.state.is_synchronized() {
+            sock.tcb.ack_pending = true;
+        }
+        // Disabling Nagle flushes held-back data
+        let now = self.now_ms;
+        let mut out = Vec::new();
+        self.sock_mut(id)?.tcp_output(now, &mut out);
+        self.flush(out);
+        Ok(())
+    }
+
+    /// Read a socket option (getsockopt()); SO_ERROR clears the pending error
+    pub fn getsockopt(&mut self, id: SocketId, level: i32, name: i32) -> PosixResult<Vec<u8>> {
+        let sock = self.sock_mut(id)?;
+        let options = sock.options;
+        let int = |value: i32| Ok(value.to_ne_bytes().to_vec());
+
+        match (level, name) {
+            (SOL_SOCKET, SO_REUSEADDR) => int(options.reuse_addr as i32),
+            (SOL_SOCKET, SO_REUSEPORT) => int(options.reuse_port as i32),
+            (SOL_SOCKET, SO_KEEPALIVE) => int(options.keepalive as i32),
+            (SOL_SOCKET, SO_BROADCAST) => int(options.broadcast as i32),
+            (SOL_SOCKET, SO_SNDBUF) => int(options.sndbuf as i32),
+            (SOL_SOCKET, SO_RCVBUF) => int(options.rcvbuf as i32),
+            (SOL_SOCKET, SO_RCVLOWAT) => int(options.rcvlowat as i32),
+            (SOL_SOCKET, SO_TYPE) => int(sock.kind),
+            (SOL_SOCKET, SO_PROTOCOL) => int(sock.protocol),
+            (SOL_SOCKET, SO_DOMAIN) => int(sock.family as i32),
+            (SOL_SOCKET, SO_ACCEPTCONN) => int((sock.state == TcpState::Listen) as i32),
+            (SOL_SOCKET, SO_ERROR) => int(sock.error.take().map_or(0, |err| err as i32)),
+            (SOL_SOCKET, SO_LINGER) => {
+                let mut bytes = (options.linger.is_some() as i32).to_ne_bytes().to_vec();
+                bytes.extend_from_slice(&(options.linger.unwrap_or(0) as i32).to_ne_bytes());
+                Ok(bytes)
+            }
+            (SOL_SOCKET, SO_RCVTIMEO) | (SOL_SOCKET, SO_SNDTIMEO) => {
+                let timeout = if name == SO_RCVTIMEO { options.rcvtimeo_us } else { options.sndtimeo_us };
+                let tv = timeval {
+                    tv_sec: (timeout / 1_000_000) as _,
+                    tv_usec: (timeout % 1_000_000) as _,
+                };
+                let bytes = unsafe {
+                    core::slice::from_raw_parts(&tv as *const timeval as *const u8, core::mem::size_of::<timeval>())
+                };
+                Ok(bytes.to_vec())
+            }
+            (IPPROTO_TCP, _) if sock.kind != SOCK_STREAM => Err(Errno::Eopnotsupp),
+            (IPPROTO_TCP, TCP_NODELAY) => int(options.nodelay as i32),
+            (IPPROTO_TCP, TCP_MAXSEG) => {
+                let mss = if sock.state.is_synchronized() { sock.tcb.mss } else { options.maxseg };
+                int(mss as i32)
+            }
+            (IPPROTO_TCP, TCP_KEEPIDLE) => int(options.keepidle as i32),
+            (IPPROTO_TCP, TCP_KEEPINTVL) => int(options.keepintvl as i32),
+            (IPPROTO_TCP, TCP_KEEPCNT) => int(options.keepcnt as i32),
+            _ => Err(Errno::Eprotoall),
+        }
+    }
+
+    /// Hand a packet received by an interface driver to the stack
+    pub fn receive(&mut self, bytes: &[u8]) -> PosixResult<()> {
+        let packet = match Packet::decode(bytes) {
+            Ok(packet) => packet,
+            Err(err) => {
+                self.stats.packets_malformed += 1;
+                return Err(err);
+            }
+        };
+        if !self.is_local(&packet.dst.addr) && !packet.dst.addr.is_broadcast() {
+            return Err(Errno::Eaddrnotavail);
+        }
+        self.deliver(packet);
+        self.run_loopback();
+        Ok(())
+    }
+
+    /// Next packet for the interface driver to put on the wire
+    pub fn take_transmit(&mut self) -> Option<Vec<u8>> {
+        self.transmit.pop_front()
+    }
+
+    /// Advance the stack's clock, running retransmission, keepalive and
+    /// TIME-WAIT timers
+    pub fn tick(&mut self, now_ms: u64) {
+        self.now_ms = now_ms;
+        let ids: Vec<SocketId> = self.sockets.keys().copied().collect();
+        let mut out = Vec::new();
+
+        for id in ids {
+            let sock = match self.sockets.get_mut(&id) {
+                Some(sock) => sock,
+                None => continue,
+            };
+            let mut touched = false;
+
+            if sock.tcb.retransmit_at.map_or(false, |at| now_ms >= at) {
+                self.stats.tcp_retransmits += 1;
+                if !sock.retransmit(now_ms, &mut out) {
+                    sock.abort(Some(Errno::Etimedout));
+                }
+                touched = true;
+            }
+
+            let idle_line = sock.tcb.snd_una == sock.tcb.snd_nxt;
+            if sock.options.keepalive && sock.state == TcpState::Established && idle_line {
+                let idle = now_ms.saturating_sub(sock.tcb.last_activity);
+                let due = (sock.options.keepidle as u64 + sock.tcb.keepalive_probes as u64 * sock.options.keepintvl as u64) * 1000;
+                if idle >= due {
+                    if sock.tcb.keepalive_probes >= sock.options.keepcnt {
+                        sock.abort(Some(Errno::Etimedout));
+                        touched = true;
+                    } else {
+                        // A segment just left of the window forces the peer to ACK
+                        out.push(sock.segment(TCP_ACK, sock.tcb.snd_nxt.wrapping_sub(1), Vec::new()));
+                        sock.tcb.keepalive_probes += 1;
+                    }
+                }
+            }
+
+            // Orphaned connections wait in FIN-WAIT-2 no longer than in TIME-WAIT
+            if sock.state == TcpState::FinWait2 && sock.orphaned && sock.tcb.timewait_until.is_none() {
+                sock.tcb.timewait_until = Some(now_ms + 2 * MSL_MS);
+            }
+            let waiting = matches!(sock.state, TcpState::TimeWait | TcpState::FinWait2);
+            if waiting && sock.tcb.timewait_until.map_or(false, |until| now_ms >= until) {
+                sock.state = TcpState::Closed;
+                touched = true;
+            }
+
+            if touched {
+                self.dirty.insert(id);
+                self.reap(id);
+            }
+        }
+        self.flush(out);
+    }
+
+    /// Route outgoing packets and process everything looped back
+    fn flush(&mut self, packets: Vec<Packet>) {
+        for packet in packets {
+            self.queue_packet(packet);
+        }
+        self.run_loopback();
+    }
+
+    /// Queue a packet on the loopback or the interface, without processing
+    /// the loopback queue yet
+    fn queue_packet(&mut self, packet: Packet) {
+        match packet.transport {
+            Transport::Tcp(_) => self.stats.tcp_segments_out += 1,
+            Transport::Udp(_) => self.stats.udp_datagrams_out += 1,
+        }
+        if self.is_local(&packet.dst.addr) {
+            self.loopback.push_back(packet);
+        } else {
+            self.stats.packets_transmitted += 1;
+            self.transmit.push_back(packet.encode());
+        }
+    }
+
+    fn run_loopback(&mut self) {
+        while let Some(packet) = self.loopback.pop_front() {
+            self.stats.packets_looped_back += 1;
+            self.deliver(packet);
+        }
+    }
+
+    fn deliver(&mut self, packet: Packet) {
+        let Packet { src, dst, transport } = packet;
+        match transport {
+            Transport::Tcp(segment) => {
+                self.stats.tcp_segments_in += 1;
+                self.tcp_input(src, dst, segment);
+            }
+            Transport::Udp(payload) => {
+                self.stats.udp_datagrams_in += 1;
+                self.udp_input(src, dst, payload);
+            }
+        }
+    }
+
+    fn udp_input(&mut self, src: Endpoint, dst: Endpoint, payload: Vec<u8>) {
+        // Prefer a connected socket, then a specific bind, then the wildcard
+        let target = self.sockets.iter()
+            .filter(|(_, sock)| !sock.is_stream() && !sock.read_shutdown)
+            .filter_map(|(&id, sock)| {
+                let local = sock.local?;
+                if local.port != dst.port || (sock.family == AF_INET && matches!(src.addr, IpAddr::V6(_))) {
+                    return None;
+                }
+                let addr_match = local.addr == dst.addr || local.addr.is_unspecified() || dst.addr.is_broadcast();
+                let score = match sock.remote {
+                    Some(remote) if remote == src => 2,
+                    Some(_) => return None,
+                    None if local.addr.is_unspecified() => 0,
+                    None => 1,
+                };
+                addr_match.then_some((score, id))
+            })
+            .max_by_key(|&(score, id)| (score, core::cmp::Reverse(id)))
+            .map(|(_, id)| id);
+
+        let (id, sock) = match target.and_then(|id| self.sockets.get_mut(&id).map(|sock| (id, sock))) {
+            Some(found) => found,
+            None => {
+                self.stats.udp_dropped += 1;
+                return;
+            }
+        };
+        if sock.datagram_bytes + payload.len() > sock.options.rcvbuf {
+            self.stats.udp_dropped += 1;
+            return;
+        }
+        sock.datagram_bytes += payload.len();
+        sock.datagrams.push_back((src, payload));
+        self.dirty.insert(id);
+    }
+
+    /// Find the socket a segment belongs to: the exact connection first,
+    /// then a listener on the destination
+    fn tcp_lookup(&self, src: &Endpoint, dst: &Endpoint) -> Option<SocketId> {
+        let streams = || self.sockets.iter().filter(|(_, sock)| sock.is_stream());
+        streams()
+            .find(|(_, sock)| sock.state != TcpState::Listen && sock.remote == Some(*src) && sock.local == Some(*dst))
+            .or_else(|| {
+                streams().filter(|(_, sock)| sock.state == TcpState::Listen)
+                    .filter(|(_, sock)| {
+                        let local = sock.local.expect("listener is bound");
+                        local.port == dst.port
+                            && (local.addr == dst.addr || (local.addr.is_unspecified()
+                                && !(sock.family == AF_INET && matches!(dst.addr, IpAddr::V6(_)))))
+                    })
+                    .max_by_key(|(_, sock)| !sock.local.map_or(true, |local| local.addr.is_unspecified()))
+            })
+            .map(|(&id, _)| id)
+    }
+
+    /// Reset answering a segment that matches no connection
+    fn reset_reply(src: Endpoint, dst: Endpoint, segment: &TcpSegment) -> Option<Packet> {
+        if segment.flags & TCP_RST != 0 {
+            return None;
+        }
+        let (seq, ack, flags) = if segment.flags & TCP_ACK != 0 {
+            (segment.ack, 0, TCP_RST)
+        } else {
+            (0, segment.seq.wrapping_add(segment.seq_len()), TCP_RST | TCP_ACK)
+        };
+        Some(Packet {
+            src: dst,
+            dst: src,
+            transport: Transport::Tcp(TcpSegment { seq, ack, flags, window: 0, mss: None, payload: Vec::new() }),
+        })
+    }
+
+    fn send_reset(&mut self, src: Endpoint, dst: Endpoint, segment: &TcpSegment) {
+        if let Some(reset) = Self::reset_reply(src, dst, segment) {
+            self.stats.tcp_resets_sent += 1;
+            self.queue_packet(reset);
+        }
+    }
+
+    fn tcp_input(&mut self, src: Endpoint, dst: Endpoint, segment: TcpSegment) {
+        let id = match self.tcp_lookup(&src, &dst) {
+            Some(id) => id,
+            None => return self.send_reset(src, dst, &segment),
+        };
+        let state = self.sockets[&id].state;
+        match state {
+            TcpState::Listen => self.tcp_listen_input(id, src, dst, segment),
+            TcpState::SynSent => self.tcp_syn_sent_input(id, src, dst, segment),
+            TcpState::Closed => self.send_reset(src, dst, &segment),
+            _ => self.tcp_synchronized_input(id, src, dst, segment),
+        }
+        self.reap(id);
+    }
+
+    fn tcp_listen_input(&mut self, id: SocketId, src: Endpoint, dst: Endpoint, segment: TcpSegment) {
+        if segment.flags & TCP_RST != 0 {
+            return;
+        }
+        if segment.flags & TCP_ACK != 0 || segment.flags & TCP_SYN == 0 {
+            return self.send_reset(src, dst, &segment);
+        }
+
+        let listener = &self.sockets[&id];
+        if listener.syn_queue.len() + listener.accept_queue.len() >= listener.backlog {
+            // Dropped SYNs are retransmitted by the client
+            self.stats.syns_dropped += 1;
+            return;
+        }
+
+        let mut child = Socket::new(listener.family, listener.kind, listener.protocol);
+        child.options = listener.options;
+        child.local = Some(dst);
+        child.remote = Some(src);
+        child.parent = Some(id);
+        child.embryonic = true;
+        child.state = TcpState::SynReceived;
+        let iss = self.next_iss();
+        child.init_tcb(iss, self.now_ms);
+        child.tcb.rcv_nxt = segment.seq.wrapping_add(1);
+        child.tcb.snd_wnd = segment.window as u32;
+        child.tcb.mss = segment.mss.unwrap_or(536).min(child.options.maxseg);
+        let syn_ack = child.segment(TCP_SYN | TCP_ACK, iss, Vec::new());
+        child.arm_retransmit(self.now_ms);
+
+        let child_id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1).max(1);
+        self.sockets.insert(child_id, child);
+        self.stats.sockets_created += 1;
+        if let Some(listener) = self.sockets.get_mut(&id) {
+            listener.syn_queue.push(child_id);
+        }
+        self.queue_packet(syn_ack);
+    }
+
+    fn tcp_syn_sent_input(&mut self, id: SocketId, src: Endpoint, dst: Endpoint, segment: TcpSegment) {
+        let now = self.now_ms;
+        let sock = self.sockets.get_mut(&id).expect("socket exists");
+        let ack_ok = segment.ack == sock.tcb.snd_nxt;
+
+        if segment.flags & TCP_ACK != 0 && !ack_ok {
+            return self.send_reset(src, dst, &segment);
+        }
+        if segment.flags & TCP_RST != 0 {
+            if segment.flags & TCP_ACK != 0 {
+                sock.abort(Some(Errno::Econnrefused));
+                self.stats.connections_reset += 1;
+                self.dirty.insert(id);
+            }
+            return;
+        }
+        if segment.flags & TCP_SYN == 0 {
+            return;
+        }
+
+        sock.tcb.rcv_nxt = segment.seq.wrapping_add(1);
+        sock.tcb.mss = segment.mss.unwrap_or(536).min(sock.options.maxseg);
+        sock.tcb.last_activity = now;
+        if segment.flags & TCP_ACK != 0 {
+            sock.process_ack(segment.ack, segment.window, now);
+            sock.state = TcpState::Established;
+            sock.was_connected = true;
+            sock.tcb.ack_pending = true;
+            self.stats.connections_established += 1;
+            self.dirty.insert(id);
+            let mut out = Vec::new();
+            sock.tcp_output(now, &mut out);
+            out.into_iter().for_each(|packet| self.queue_packet(packet));
+        } else {
+            // Simultaneous open
+            sock.state = TcpState::SynReceived;
+            let syn_ack = sock.segment(TCP_SYN | TCP_ACK, sock.tcb.iss, Vec::new());
+            self.queue_packet(syn_ack);
+        }
+    }
+
+    fn tcp_synchronized_input(&mut self, id: SocketId, src: Endpoint, dst: Endpoint, mut segment: TcpSegment) {
+        let now = self.now_ms;
+        let sock = self.sockets.get_mut(&id).expect("socket exists");
+        let mut out = Vec::new();
+        self.dirty.insert(id);
+
+        // Trim bytes that were already received
+        let rcv_nxt = sock.tcb.rcv_nxt;
+        if seq_lt(segment.seq, rcv_nxt) && segment.flags & TCP_RST == 0 {
+            let behind = rcv_nxt.wrapping_sub(segment.seq) as usize;
+            if behind >= segment.seq_len() as usize {
+                // Duplicate or keepalive probe: answer with an ACK
+                sock.tcb.ack_pending = true;
+                sock.tcp_output(now, &mut out);
+                out.into_iter().for_each(|packet| self.queue_packet(packet));
+                return;
+            }
+            segment.payload.drain(..behind.min(segment.payload.len()));
+            segment.seq = rcv_nxt;
+        }
+        if segment.seq != rcv_nxt {
+            // Out of order segments are dropped; ask for what is missing
+            if segment.flags & TCP_RST == 0 {
+                sock.tcb.ack_pending = true;
+                sock.tcp_output(now, &mut out);
+                out.into_iter().for_each(|packet| self.queue_packet(packet));
+            }
+            return;
+        }
+
+        if segment.flags & TCP_RST != 0 {
+            if sock.embryonic {
+                sock.abort(None);
+            } else {
+                let err = if sock.state == TcpState::SynReceived { Errno::Econnrefused } else { Errno::Econnreset };
+                sock.abort(Some(err));
+                self.stats.connections_reset += 1;
+            }
+            return;
+        }
+        if segment.flags & TCP_SYN != 0 {
+            // SYN inside an established connection: reset it
+            if let Some(reset) = Self::reset_packet(sock) {
+                out.push(reset);
+            }
+            sock.abort(Some(Errno::Econnreset));
+            self.stats.tcp_resets_sent += 1;
+            self.stats.connections_reset += 1;
+            out.into_iter().for_each(|packet| self.queue_packet(packet));
+            return;
+        }
+        if segment.flags & TCP_ACK == 0 {
+            return;
+        }
+        sock.tcb.last_activity = now;
+        sock.tcb.keepalive_probes = 0;
+
+        if sock.state == TcpState::SynReceived {
+            if !seq_lt(sock.tcb.snd_una, segment.ack) || seq_lt(sock.tcb.snd_nxt, segment.ack) {
+                return self.send_reset(src, dst, &segment);
+            }
+            sock.state = TcpState::Established;
+            sock.was_connected = true;
+            self.stats.connections_established += 1;
+            if sock.embryonic {
+                sock.embryonic = false;
+                let parent = sock.parent;
+                if let Some(listener) = parent.and_then(|parent| self.sockets.get_mut(&parent)) {
+                    listener.syn_queue.retain(|&child| child != id);
+                    listener.accept_queue.push_back(id);
+                    self.dirty.insert(parent.unwrap_or_default());
+                }
+            }
+        }
+
+        let sock = self.sockets.get_mut(&id).expect("socket exists");
+        if !sock.process_ack(segment.ack, segment.window, now) {
+            sock.tcb.ack_pending = true;
+            sock.tcp_output(now, &mut out);
+            out.into_iter().for_each(|packet| self.queue_packet(packet));
+            return;
+        }
+        if sock.tcb.fin_acked {
+            match sock.state {
+                TcpState::FinWait1 => sock.state = TcpState::FinWait2,
+                TcpState::Closing => sock.enter_time_wait(now),
+                TcpState::LastAck => {
+                    sock.state = TcpState::Closed;
+                    return;
+                }
+                _ => {}
+            }
+        }
+
+        // In-order data
+        let receiving = matches!(sock.state, TcpState::Established | TcpState::FinWait1 | TcpState::FinWait2);
+        let mut accepted_all = true;
+        if !segment.payload.is_empty() && receiving {
+            let space = if sock.read_shutdown {
+                segment.payload.len()
+            } else {
+                sock.options.rcvbuf.saturating_sub(sock.recv_buf.len())
+            };
+            let count = segment.payload.len().min(space);
+            if !sock.read_shutdown {
+                sock.recv_buf.extend(&segment.payload[..count]);
+            }
+            sock.tcb.rcv_nxt = sock.tcb.rcv_nxt.wrapping_add(count as u32);
+            sock.tcb.ack_pending = true;
+            accepted_all = count == segment.payload.len();
+        }
+
+        if segment.flags & TCP_FIN != 0 && accepted_all && !sock.peer_closed {
+            sock.tcb.rcv_nxt = sock.tcb.rcv_nxt.wrapping_add(1);
+            sock.tcb.ack_pending = true;
+            sock.peer_closed = true;
+            match sock.state {
+                TcpState::Established => sock.state = TcpState::CloseWait,
+                TcpState::FinWait1 if sock.tcb.fin_acked => sock.enter_time_wait(now),
+                TcpState::FinWait1 => sock.state = TcpState::Closing,
+                TcpState::FinWait2 => sock.enter_time_wait(now),
+                _ => {}
+            }
+        }
+
+        sock.tcp_output(now, &mut out);
+        out.into_iter().for_each(|packet| self.queue_packet(packet));
+    }
+
+    pub fn len(&self) -> usize {
+        self.sockets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sockets.is_empty()
+    }
+
+    pub fn get_stats(&self) -> NetStackStats {
+        self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn loopback(port: u16) -> Endpoint {
+        Endpoint::new(IpAddr::V4_LOOPBACK, port)
+    }
+
+    /// Listener on `port` and a client connected to it over loopback
+    fn connected_pair(stack: &mut NetStack, port: u16) -> (SocketId, SocketId) {
+        let listener = stack.socket(AF_INET, SOCK_STREAM, 0).unwrap();
+        stack.bind(listener, loopback(port)).unwrap();
+        stack.listen(listener, 4).unwrap();
+        let client = stack.socket(AF_INET, SOCK_STREAM, 0).unwrap();
+        assert_eq!(stack.connect(client, loopback(port)), Ok(()));
+        let (server, _) = stack.accept(listener).unwrap();
+        (client, server)
+    }
+
+    #[test]
+    fn test_checksum() {
+        // Example from RFC 1071 section 3
+        let data = [0x00, 0x01, 0xf2, 0x03, 0xf4, 0xf5, 0xf6, 0xf7];
+        assert_eq!(checksum(&[&data]), 0x220d);
+        // Words may straddle the parts
+        assert_eq!(checksum(&[&data[..3], &data[3..]]), 0x220d);
+        // An odd trailing byte is padded with zero
+        assert_eq!(checksum(&[&[0xab]]), 0x54ff);
+
+        let mut with_sum = data.to_vec();
+        with_sum.extend_from_slice(&0x220du16.to_be_bytes());
+        assert_eq!(checksum(&[&with_sum]), 0);
+    }
+
+    #[test]
+    fn test_segment_round_trip() {
+        let segment = TcpSegment {
+            seq: 0xfffffff0,
+            ack: 42,
+            flags: TCP_SYN | TCP_ACK,
+            window: 8192,
+            mss: Some(1400),
+            payload: b"hello".to_vec(),
+        };
+        let v6 = |port| Endpoint::new(IpAddr::V6_LOOPBACK, port);
+        for (src, dst) in [(loopback(1234), loopback(80)), (v6(1234), v6(80))] {
+            let packet = Packet { src, dst, transport: Transport::Tcp(segment.clone()) };
+            assert_eq!(Packet::decode(&packet.encode()), Ok(packet));
+        }
+
+        let datagram = Packet { src: loopback(53), dst: loopback(5353), transport: Transport::Udp(b"query".to_vec()) };
+        assert_eq!(Packet::decode(&datagram.encode()), Ok(datagram));
+        assert_eq!(segment.seq_len(), 7);
+    }
+
+    #[test]
+    fn test_malformed_segments_are_rejected() {
+        let (src, dst) = (loopback(1234), loopback(80));
+        let segment = TcpSegment { seq: 1, ack: 0, flags: TCP_SYN, window: 1024, mss: Some(536), payload: Vec::new() };
+        let bytes = segment.encode(&src, &dst);
+        assert_eq!(TcpSegment::decode(&src.addr, &dst.addr, &bytes).map(|(_, _, s)| s), Ok(segment));
+
+        // Truncated header
+        assert_eq!(TcpSegment::decode(&src.addr, &dst.addr, &bytes[..TCP_HEADER_LEN - 1]), Err(Errno::Ebadmsg));
+        // Bad checksum
+        let mut corrupt = bytes.clone();
+        corrupt[4] ^= 0x80;
+        assert_eq!(TcpSegment::decode(&src.addr, &dst.addr, &corrupt), Err(Errno::Ebadmsg));
+        // Data offset shorter than the fixed header
+        let mut short = bytes.clone();
+        short[12] = 4 << 4;
+        assert_eq!(TcpSegment::decode(&src.addr, &dst.addr, &short), Err(Errno::Ebadmsg));
+
+        // Option length running past the header, with a valid checksum
+        let mut overrun = bytes.clone();
+        overrun[21] = 8;
+        overrun[16..18].copy_from_slice(&[0, 0]);
+        let pseudo = pseudo_header(&src.addr, &dst.addr, IPPROTO_TCP as u8, overrun.len());
+        let sum = checksum(&[&pseudo[..], &overrun[..]]);
+        overrun[16..18].copy_from_slice(&sum.to_be_bytes());
+        assert_eq!(TcpSegment::decode(&src.addr, &dst.addr, &overrun), Err(Errno::Ebadmsg));
+
+        // IP total length beyond the buffer, and fragments
+        let (_, _, decoded) = TcpSegment::decode(&src.addr, &dst.addr, &bytes).unwrap();
+        let packet = Packet { src, dst, transport: Transport::Tcp(decoded) }.encode();
+        assert_eq!(Packet::decode(&packet[..packet.len() - 1]), Err(Errno::Ebadmsg));
+        let mut fragment = packet.clone();
+        fragment[6] = 0x20; // More fragments
+        fragment[10..12].copy_from_slice(&[0, 0]);
+        let sum = checksum(&[&fragment[..IPV4_HEADER_LEN]]);
+        fragment[10..12].copy_from_slice(&sum.to_be_bytes());
+        assert_eq!(Packet::decode(&fragment), Err(Errno::Eopnotsupp));
+        assert_eq!(Packet::decode(&[]), Err(Errno::Ebadmsg));
+    }
+
+    #[test]
+    fn test_tcp_handshake() {
+        let mut stack = NetStack::new();
+        let (client, server) = connected_pair(&mut stack, 8080);
+
+        assert_eq!(stack.tcp_state(client), Ok(TcpState::Established));
+        assert_eq!(stack.tcp_state(server), Ok(TcpState::Established));
+        assert_eq!(stack.peer_endpoint(server), stack.local_endpoint(client));
+        assert_eq!(stack.peer_endpoint(client), Ok(loopback(8080)));
+        assert_eq!(stack.get_stats().connections_established, 2);
+
+        assert_eq!(stack.send(client, b"ping", 0), Ok(4));
+        let mut buf = [0u8; 8];
+        assert_eq!(stack.recv(server, &mut buf, 0), Ok(4));
+        assert_eq!(&buf[..4], b"ping");
+    }
+
+    #[test]
+    fn test_tcp_connect_without_listener_is_refused() {
+        let mut stack = NetStack::new();
+        let client = stack.socket(AF_INET, SOCK_STREAM, 0).unwrap();
+
+        assert_eq!(stack.connect(client, loopback(9)), Err(Errno::Econnrefused));
+        assert_eq!(stack.tcp_state(client), Ok(TcpState::Closed));
+        assert_eq!(stack.get_stats().tcp_resets_sent, 1);
+    }
+
+    #[test]
+    fn test_tcp_teardown() {
+        let mut stack = NetStack::new();
+        let (client, server) = connected_pair(&mut stack, 8081);
+
+        // Active close: the peer's ACK moves the client on to FIN-WAIT-2
+        stack.shutdown(client, SHUT_WR).unwrap();
+        assert_eq!(stack.tcp_state(client), Ok(TcpState::FinWait2));
+        assert_eq!(stack.tcp_state(server), Ok(TcpState::CloseWait));
+        let mut buf = [0u8; 8];
+        assert_eq!(stack.recv(server, &mut buf, 0), Ok(0));
+
+        // Passive close: LAST-ACK ends once the client acknowledges the FIN
+        stack.close(server).unwrap();
+        assert_eq!(stack.tcp_state(server), Err(Errno::Ebadf));
+        assert_eq!(stack.tcp_state(client), Ok(TcpState::TimeWait));
+
+        stack.tick(2 * MSL_MS - 1);
+        assert_eq!(stack.tcp_state(client), Ok(TcpState::TimeWait));
+        stack.tick(2 * MSL_MS);
+        assert_eq!(stack.tcp_state(client), Ok(TcpState::Closed));
+
+        stack.close(client).unwrap();
+        assert_eq!(stack.len(), 1);
+    }
+}
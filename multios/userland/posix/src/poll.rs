@@ -0,0 +1,835 @@
+//! POSIX poll.h, sys/select.h and sys/epoll.h Compatibility
+//!
+//! This module provides the event-polling subsystem for MultiOS: the kernel
+//! `EventQueue` object behind epoll, the readiness scan shared by poll() and
+//! select(), and the user-space wrappers for all three interfaces.
+//!
+//! Sockets, pipes and timers report readiness changes to the queues watching
+//! them through `EventQueue::notify`; timers armed on a queue fire from
+//! `EventQueue::expire_timers`. Waiters block until a queue has ready events
+//! or their deadline passes.
+
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::errors::*;
+use crate::internal::*;
+use crate::syscall;
+use crate::types::*;
+
+/// Data available to read
+pub const POLLIN: i16 = 0x001;
+/// Urgent data available
+pub const POLLPRI: i16 = 0x002;
+/// Writing will not block
+pub const POLLOUT: i16 = 0x004;
+/// Error condition (output only)
+pub const POLLERR: i16 = 0x008;
+/// Hang up (output only)
+pub const POLLHUP: i16 = 0x010;
+/// Invalid file descriptor (output only)
+pub const POLLNVAL: i16 = 0x020;
+
+pub const EPOLLIN: u32 = 0x001;
+pub const EPOLLPRI: u32 = 0x002;
+pub const EPOLLOUT: u32 = 0x004;
+pub const EPOLLERR: u32 = 0x008;
+pub const EPOLLHUP: u32 = 0x010;
+/// Peer closed its writing half
+pub const EPOLLRDHUP: u32 = 0x2000;
+/// Disable the interest after one event until re-armed with EPOLL_CTL_MOD
+pub const EPOLLONESHOT: u32 = 1 << 30;
+/// Edge-triggered notification
+pub const EPOLLET: u32 = 1 << 31;
+
+/// Register a file descriptor
+pub const EPOLL_CTL_ADD: i32 = 1;
+/// Remove a file descriptor
+pub const EPOLL_CTL_DEL: i32 = 2;
+/// Change the events of a registered file descriptor
+pub const EPOLL_CTL_MOD: i32 = 3;
+
+/// Close the epoll descriptor on exec
+pub const EPOLL_CLOEXEC: i32 = 0o2000000;
+
+/// Maximum number of descriptors in an `fd_set`
+pub const FD_SETSIZE: usize = 1024;
+
+/// Events that are always reported, whether requested or not
+const ALWAYS_REPORTED: u32 = EPOLLERR | EPOLLHUP;
+/// Flag bits of `epoll_event.events` that are not readiness bits
+const EPOLL_FLAGS: u32 = EPOLLONESHOT | EPOLLET;
+
+/// epoll event (matches the packed x86_64 kernel layout)
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct epoll_event {
+    pub events: u32,             // Requested / returned events
+    pub data: u64,               // User data
+}
+
+/// Descriptor set for select()
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct fd_set {
+    pub fds_bits: [u64; FD_SETSIZE / 64],
+}
+
+impl Default for fd_set {
+    fn default() -> Self {
+        Self { fds_bits: [0; FD_SETSIZE / 64] }
+    }
+}
+
+impl fd_set {
+    /// Remove all descriptors (FD_ZERO)
+    pub fn zero(&mut self) {
+        self.fds_bits = [0; FD_SETSIZE / 64];
+    }
+
+    /// Add a descriptor (FD_SET)
+    pub fn set(&mut self, fd: fd_t) {
+        if let Some((word, bit)) = Self::slot(fd) {
+            self.fds_bits[word] |= bit;
+        }
+    }
+
+    /// Remove a descriptor (FD_CLR)
+    pub fn clear(&mut self, fd: fd_t) {
+        if let Some((word, bit)) = Self::slot(fd) {
+            self.fds_bits[word] &= !bit;
+        }
+    }
+
+    /// Test for a descriptor (FD_ISSET)
+    pub fn is_set(&self, fd: fd_t) -> bool {
+        Self::slot(fd).map_or(false, |(word, bit)| self.fds_bits[word] & bit != 0)
+    }
+
+    fn slot(fd: fd_t) -> Option<(usize, u64)> {
+        if fd < 0 || fd as usize >= FD_SETSIZE {
+            None
+        } else {
+            Some((fd as usize / 64, 1 << (fd as usize % 64)))
+        }
+    }
+}
+
+/// Kind of object a watched descriptor refers to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventSourceKind {
+    Socket,
+    Pipe,
+    Timer,
+    Other,
+}
+
+/// Registered interest in one descriptor
+#[derive(Debug, Clone)]
+struct Interest {
+    kind: EventSourceKind,
+    /// Requested events including EPOLLET/EPOLLONESHOT
+    events: u32,
+    data: u64,
+    /// Current readiness reported by the source
+    readiness: u32,
+    /// Readiness rose since the last edge-triggered report
+    edge_pending: bool,
+    /// One-shot interest already fired
+    disarmed: bool,
+    queued: bool,
+}
+
+impl Interest {
+    fn active(&self) -> u32 {
+        if self.disarmed {
+            0
+        } else {
+            self.readiness & ((self.events & !EPOLL_FLAGS) | ALWAYS_REPORTED)
+        }
+    }
+}
+
+/// Timer armed on an event queue
+#[derive(Debug, Clone, Copy)]
+struct Timer {
+    deadline_ns: u64,
+    interval_ns: u64,
+    /// Expirations not yet read
+    expirations: u64,
+}
+
+/// Outcome of an `EventQueue::wait` call
+#[derive(Debug, Clone)]
+pub enum EventWait {
+    /// Events are ready
+    Ready(Vec<epoll_event>),
+    /// Nothing ready yet; block until notified or the deadline passes
+    WouldBlock { deadline_ns: Option<u64> },
+    /// The timeout expired with nothing ready
+    TimedOut,
+}
+
+/// Event queue statistics
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EventQueueStats {
+    pub notifications: u64,
+    pub wakeups: u64,
+    pub events_delivered: u64,
+    pub timer_expirations: u64,
+    pub timeouts: u64,
+}
+
+/// Kernel event queue backing an epoll instance
+#[derive(Debug, Default)]
+pub struct EventQueue {
+    interests: BTreeMap<fd_t, Interest>,
+    ready: VecDeque<fd_t>,
+    timers: BTreeMap<fd_t, Timer>,
+    /// Threads blocked in `wait`
+    waiters: usize,
+    stats: EventQueueStats,
+}
+
+impl EventQueue {
+    /// Create an empty event queue
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add, modify or remove interest in a descriptor (epoll_ctl)
+    pub fn ctl(&mut self, op: i32, fd: fd_t, event: Option<epoll_event>, kind: EventSourceKind) -> PosixResult<()> {
+        if fd < 0 {
+            return Err(Errno::Ebadf);
+        }
+
+        match op {
+            EPOLL_CTL_ADD => {
+                let event = event.ok_or(Errno::Einval)?;
+                if self.interests.contains_key(&fd) {
+                    return Err(Errno::Eexist);
+                }
+                self.interests.insert(fd, Interest {
+                    kind,
+                    events: event.events,
+                    data: event.data,
+                    readiness: 0,
+                    edge_pending: false,
+                    disarmed: false,
+                    queued: false,
+                });
+                Ok(())
+            }
+            EPOLL_CTL_MOD => {
+                let event = event.ok_or(Errno::Einval)?;
+                let interest = self.interests.get_mut(&fd).ok_or(Errno::Enoent)?;
+                interest.events = event.events;
+                interest.data = event.data;
+                interest.disarmed = false;
+                // Re-arming reports current readiness again, as Linux does
+                interest.edge_pending = interest.readiness != 0;
+                self.enqueue(fd);
+                Ok(())
+            }
+            EPOLL_CTL_DEL => {
+                self.interests.remove(&fd).ok_or(Errno::Enoent)?;
+                self.ready.retain(|&ready| ready != fd);
+                self.timers.remove(&fd);
+                Ok(())
+            }
+            _ => Err(Errno::Einval),
+        }
+    }
+
+    /// Report the current readiness of a socket, pipe or timer.
+    ///
+    /// Returns whether a blocked waiter should be woken.
+    pub fn notify(&mut self, fd: fd_t, readiness: u32) -> bool {
+        let interest = match self.interests.get_mut(&fd) {
+            Some(interest) => interest,
+            None => return false,
+        };
+        self.stats.notifications += 1;
+
+        let rising = readiness & !interest.readiness;
+        interest.readiness = readiness;
+        if rising != 0 {
+            interest.edge_pending = true;
+        }
+        if interest.active() == 0 {
+            return false;
+        }
+
+        self.enqueue(fd);
+        if self.waiters > 0 {
+            self.stats.wakeups += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Arm a timer descriptor to become readable at `deadline_ns`,
+    /// repeating every `interval_ns` if non-zero
+    pub fn arm_timer(&mut self, fd: fd_t, deadline_ns: u64, interval_ns: u64) -> PosixResult<()> {
+        match self.interests.get(&fd) {
+            Some(interest) if interest.kind == EventSourceKind::Timer => {}
+            Some(_) => return Err(Errno::Einval),
+            None => return Err(Errno::Enoent),
+        }
+        self.timers.insert(fd, Timer { deadline_ns, interval_ns, expirations: 0 });
+        Ok(())
+    }
+
+    /// Fire expired timers; returns whether a blocked waiter should be woken
+    pub fn expire_timers(&mut self, now_ns: u64) -> bool {
+        let mut fired = Vec::new();
+        for (&fd, timer) in self.timers.iter_mut() {
+            if timer.deadline_ns == 0 || now_ns < timer.deadline_ns {
+                continue;
+            }
+            let periods = if timer.interval_ns > 0 {
+                (now_ns - timer.deadline_ns) / timer.interval_ns + 1
+            } else {
+                1
+            };
+            timer.expirations += periods;
+            timer.deadline_ns = if timer.interval_ns > 0 {
+                timer.deadline_ns + periods * timer.interval_ns
+            } else {
+                0
+            };
+            self.stats.timer_expirations += periods;
+            fired.push(fd);
+        }
+
+        let mut wake = false;
+        for fd in fired {
+            wake |= self.notify(fd, EPOLLIN);
+        }
+        wake
+    }
+
+    /// Read and reset a timer's expiration count, clearing its readiness
+    pub fn read_timer(&mut self, fd: fd_t) -> PosixResult<u64> {
+        let timer = self.timers.get_mut(&fd).ok_or(Errno::Einval)?;
+        match core::mem::take(&mut timer.expirations) {
+            0 => Err(Errno::Eagain),
+            expirations => {
+                self.notify(fd, 0);
+                Ok(expirations)
+            }
+        }
+    }
+
+    /// Earliest armed timer deadline, for bounding a blocking wait
+    pub fn next_timer_deadline(&self) -> Option<u64> {
+        self.timers.values()
+            .filter(|timer| timer.deadline_ns != 0)
+            .map(|timer| timer.deadline_ns)
+            .min()
+    }
+
+    /// Collect up to `max_events` ready events (epoll_wait).
+    ///
+    /// Level-triggered descriptors stay queued while ready; edge-triggered
+    /// ones are reported once per readiness rise; one-shot interests are
+    /// disarmed after reporting.
+    pub fn wait(&mut self, max_events: usize, deadline_ns: Option<u64>, now_ns: u64) -> PosixResult<EventWait> {
+        if max_events == 0 {
+            return Err(Errno::Einval);
+        }
+
+        let events = self.harvest(max_events);
+        if !events.is_empty() {
+            self.stats.events_delivered += events.len() as u64;
+            return Ok(EventWait::Ready(events));
+        }
+
+        match deadline_ns {
+            Some(deadline) if now_ns >= deadline => {
+                self.stats.timeouts += 1;
+                Ok(EventWait::TimedOut)
+            }
+            _ => Ok(EventWait::WouldBlock { deadline_ns }),
+        }
+    }
+
+    /// Record a thread blocking in `wait`
+    pub fn add_waiter(&mut self) {
+        self.waiters += 1;
+    }
+
+    /// Record a thread leaving `wait`
+    pub fn remove_waiter(&mut self) {
+        self.waiters = self.waiters.saturating_sub(1);
+    }
+
+    /// Whether `wait` would report an event now
+    pub fn is_ready(&self) -> bool {
+        self.ready.iter().any(|fd| {
+            self.interests.get(fd).map_or(false, |interest| {
+                interest.active() != 0 && (interest.events & EPOLLET == 0 || interest.edge_pending)
+            })
+        })
+    }
+
+    /// Number of registered descriptors
+    pub fn len(&self) -> usize {
+        self.interests.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.interests.is_empty()
+    }
+
+    /// Get event queue statistics
+    pub fn get_stats(&self) -> EventQueueStats {
+        self.stats
+    }
+
+    fn enqueue(&mut self, fd: fd_t) {
+        if let Some(interest) = self.interests.get_mut(&fd) {
+            if !interest.queued {
+                interest.queued = true;
+                self.ready.push_back(fd);
+            }
+        }
+    }
+
+    fn harvest(&mut self, max_events: usize) -> Vec<epoll_event> {
+        let mut events = Vec::new();
+        let mut requeue = Vec::new();
+
+        while events.len() < max_events {
+            let fd = match self.ready.pop_front() {
+                Some(fd) => fd,
+                None => break,
+            };
+            let interest = match self.interests.get_mut(&fd) {
+                Some(interest) => interest,
+                None => continue,
+            };
+            interest.queued = false;
+
+            let active = interest.active();
+            let edge = interest.events & EPOLLET != 0;
+            if active == 0 || (edge && !interest.edge_pending) {
+                continue;
+            }
+
+            events.push(epoll_event { events: active, data: interest.data });
+            interest.edge_pending = false;
+            if interest.events & EPOLLONESHOT != 0 {
+                interest.disarmed = true;
+            } else if !edge {
+                requeue.push(fd);
+            }
+        }
+
+        for fd in requeue {
+            self.enqueue(fd);
+        }
+        events
+    }
+}
+
+/// Convert a millisecond timeout (-1 = infinite) to an absolute deadline
+pub fn timeout_deadline(timeout_ms: i32, now_ns: u64) -> Option<u64> {
+    if timeout_ms < 0 {
+        None
+    } else {
+        Some(now_ns + timeout_ms as u64 * 1_000_000)
+    }
+}
+
+/// Kernel side of poll(): fill `revents` from current readiness.
+///
+/// `readiness` returns the POLL* bits of an open descriptor, or `None` if
+/// the descriptor is not open. Returns the number of descriptors with
+/// non-zero `revents`.
+pub fn poll_scan(fds: &mut [pollfd], readiness: &dyn Fn(fd_t) -> Option<i16>) -> usize {
+    let mut ready = 0;
+    for entry in fds.iter_mut() {
+        entry.revents = if entry.fd < 0 {
+            0
+        } else {
+            match readiness(entry.fd) {
+                Some(bits) => bits & (entry.events | POLLERR | POLLHUP),
+                None => POLLNVAL,
+            }
+        };
+        if entry.revents != 0 {
+            ready += 1;
+        }
+    }
+    ready
+}
+
+/// Kernel side of select(): keep only ready descriptors in each set.
+///
+/// Returns the total number of bits left set, or `Ebadf` if a requested
+/// descriptor is not open.
+pub fn select_scan(
+    nfds: usize,
+    mut readfds: Option<&mut fd_set>,
+    mut writefds: Option<&mut fd_set>,
+    mut exceptfds: Option<&mut fd_set>,
+    readiness: &dyn Fn(fd_t) -> Option<i16>,
+) -> PosixResult<usize> {
+    let mut ready = 0;
+    for fd in 0..nfds.min(FD_SETSIZE) as fd_t {
+        let sets: [(&mut Option<&mut fd_set>, i16); 3] = [
+            (&mut readfds, POLLIN | POLLHUP | POLLERR),
+            (&mut writefds, POLLOUT | POLLERR),
+            (&mut exceptfds, POLLPRI),
+        ];
+        let requested = sets.iter().any(|(set, _)| set.as_ref().map_or(false, |set| set.is_set(fd)));
+        if !requested {
+            continue;
+        }
+
+        let bits = readiness(fd).ok_or(Errno::Ebadf)?;
+        for (set, mask) in sets {
+            if let Some(set) = set.as_mut() {
+                if set.is_set(fd) {
+                    if bits & mask != 0 {
+                        ready += 1;
+                    } else {
+                        set.clear(fd);
+                    }
+                }
+            }
+        }
+    }
+    Ok(ready)
+}
+
+/// Create an epoll instance
+///
+/// This function provides compatibility with the Linux epoll_create1() function.
+///
+/// # Arguments
+/// * `flags` - 0 or `EPOLL_CLOEXEC`
+///
+/// # Returns
+/// * `PosixResult<fd_t>` - epoll file descriptor, error on failure
+pub fn epoll_create1(flags: i32) -> PosixResult<fd_t> {
+    if flags & !EPOLL_CLOEXEC != 0 {
+        return Err(Errno::Einval);
+    }
+    // The kernel has no epoll; the descriptor layer runs the event queue
+    crate::descriptors::open_epoll()
+}
+
+/// Create an epoll instance (legacy interface)
+///
+/// # Arguments
+/// * `size` - Ignored hint, must be positive
+pub fn epoll_create(size: i32) -> PosixResult<fd_t> {
+    if size <= 0 {
+        return Err(Errno::Einval);
+    }
+    epoll_create1(0)
+}
+
+/// Control an epoll instance
+///
+/// This function provides compatibility with the Linux epoll_ctl() function.
+///
+/// # Arguments
+/// * `epfd` - epoll file descriptor
+/// * `op` - `EPOLL_CTL_ADD`, `EPOLL_CTL_MOD` or `EPOLL_CTL_DEL`
+/// * `fd` - Target file descriptor
+/// * `event` - Events and user data (ignored for `EPOLL_CTL_DEL`)
+pub fn epoll_ctl(epfd: fd_t, op: i32, fd: fd_t, event: Option<&epoll_event>) -> PosixResult<()> {
+    if epfd == fd {
+        return Err(Errno::Einval);
+    }
+    if crate::descriptors::is_local(epfd) {
+        return crate::descriptors::epoll_ctl(epfd, op, fd, event.copied());
+    }
+    let event_ptr = event.map_or(core::ptr::null(), |event| event as *const epoll_event);
+    syscall::epoll_ctl(epfd, op, fd, event_ptr)
+}
+
+/// Wait for events on an epoll instance
+///
+/// This function provides compatibility with the Linux epoll_wait() function.
+///
+/// # Arguments
+/// * `epfd` - epoll file descriptor
+/// * `events` - Buffer receiving ready events
+/// * `timeout_ms` - Timeout in milliseconds (-1 blocks indefinitely, 0 polls)
+///
+/// # Returns
+/// * `PosixResult<usize>` - Number of events written to `events`
+pub fn epoll_wait(epfd: fd_t, events: &mut [epoll_event], timeout_ms: i32) -> PosixResult<usize> {
+    if events.is_empty() {
+        return Err(Errno::Einval);
+    }
+    if crate::descriptors::is_local(epfd) {
+        return crate::descriptors::epoll_wait(epfd, events, timeout_ms);
+    }
+    syscall::epoll_wait(epfd, events.as_mut_ptr(), events.len(), timeout_ms)
+}
+
+/// Wait for events on file descriptors
+///
+/// This function provides compatibility with the POSIX poll() function.
+///
+/// # Arguments
+/// * `fds` - Descriptors and requested events; `revents` is filled in
+/// * `timeout_ms` - Timeout in milliseconds (-1 blocks indefinitely, 0 polls)
+///
+/// # Returns
+/// * `PosixResult<usize>` - Number of descriptors with events
+pub fn poll(fds: &mut [pollfd], timeout_ms: i32) -> PosixResult<usize> {
+    if fds.iter().any(|entry| crate::descriptors::is_local(entry.fd)) {
+        return crate::descriptors::poll(fds, timeout_ms);
+    }
+    syscall::poll(fds.as_mut_ptr(), fds.len(), timeout_ms)
+}
+
+/// Synchronous I/O multiplexing
+///
+/// This function provides compatibility with the POSIX select() function.
+/// It is served by poll(), so the sets may name any descriptor poll() can
+/// watch; `timeout` is not updated.
+///
+/// # Arguments
+/// * `nfds` - Highest-numbered descriptor in any set, plus one
+/// * `readfds` / `writefds` / `exceptfds` - Sets to watch; updated in place
+/// * `timeout` - Maximum wait (None blocks indefinitely)
+///
+/// # Returns
+/// * `PosixResult<usize>` - Number of ready descriptors
+pub fn select(
+    nfds: i32,
+    readfds: Option<&mut fd_set>,
+    writefds: Option<&mut fd_set>,
+    exceptfds: Option<&mut fd_set>,
+    timeout: Option<&mut timeval>,
+) -> PosixResult<usize> {
+    if nfds < 0 || nfds as usize > FD_SETSIZE {
+        return Err(Errno::Einval);
+    }
+    let timeout_ms = match timeout {
+        Some(timeout) if timeout.tv_sec < 0 || timeout.tv_usec < 0 => return Err(Errno::Einval),
+        Some(timeout) => (timeout.tv_sec as i64)
+            .saturating_mul(1_000)
+            .saturating_add((timeout.tv_usec as i64 + 999) / 1_000)
+            .min(i32::MAX as i64) as i32,
+        None => -1,
+    };
+
+    let watched = |set: &Option<&mut fd_set>, fd: fd_t| set.as_ref().map_or(false, |set| set.is_set(fd));
+    let mut fds: Vec<pollfd> = (0..nfds)
+        .filter_map(|fd| {
+            let mut events = 0;
+            if watched(&readfds, fd) {
+                events |= POLLIN;
+            }
+            if watched(&writefds, fd) {
+                events |= POLLOUT;
+            }
+            if watched(&exceptfds, fd) {
+                events |= POLLPRI;
+            }
+            (events != 0).then_some(pollfd { fd, events, revents: 0 })
+        })
+        .collect();
+    poll(&mut fds, timeout_ms)?;
+
+    let revents = |fd: fd_t| {
+        let entry = fds.iter().find(|entry| entry.fd == fd)?;
+        (entry.revents & POLLNVAL == 0).then_some(entry.revents)
+    };
+    select_scan(nfds as usize, readfds, writefds, exceptfds, &revents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::itimerspec;
+    use crate::timer::{ns_to_timespec, timerfd_create, timerfd_read, timerfd_settime};
+    use crate::{stdio, unistd};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_pipe_write_wakes_epoll_wait() {
+        let mut fds = [0; 2];
+        unistd::pipe(&mut fds).unwrap();
+        let epfd = epoll_create1(0).unwrap();
+        epoll_ctl(epfd, EPOLL_CTL_ADD, fds[0], Some(&epoll_event { events: EPOLLIN, data: 7 })).unwrap();
+
+        let mut events = [epoll_event::default(); 4];
+        assert_eq!(epoll_wait(epfd, &mut events, 0), Ok(0));
+
+        let writer = fds[1];
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            stdio::write(writer, b"wake")
+        });
+        assert_eq!(epoll_wait(epfd, &mut events, 5_000), Ok(1));
+        assert_eq!({ events[0].events }, EPOLLIN);
+        assert_eq!({ events[0].data }, 7);
+        assert_eq!(handle.join().unwrap(), Ok(4));
+
+        // Level-triggered: reported until drained
+        assert_eq!(epoll_wait(epfd, &mut events, 0), Ok(1));
+        let mut buf = [0u8; 8];
+        assert_eq!(stdio::read(fds[0], &mut buf), Ok(4));
+        assert_eq!(&buf[..4], b"wake");
+        assert_eq!(epoll_wait(epfd, &mut events, 0), Ok(0));
+
+        // Closing the write end hangs up the read end
+        stdio::close(fds[1]).unwrap();
+        assert_eq!(epoll_wait(epfd, &mut events, 0), Ok(1));
+        assert_eq!({ events[0].events }, EPOLLHUP);
+        assert_eq!(stdio::read(fds[0], &mut buf), Ok(0));
+
+        stdio::close(fds[0]).unwrap();
+        stdio::close(epfd).unwrap();
+    }
+
+    #[test]
+    fn test_timerfd_expiry_wakes_epoll_wait() {
+        let timer = timerfd_create(crate::internal::CLOCK_MONOTONIC, 0).unwrap();
+        let epfd = epoll_create1(0).unwrap();
+        epoll_ctl(epfd, EPOLL_CTL_ADD, timer, Some(&epoll_event { events: EPOLLIN, data: 1 })).unwrap();
+
+        let value = itimerspec { it_interval: ns_to_timespec(0), it_value: ns_to_timespec(10_000_000) };
+        timerfd_settime(timer, 0, &value, None).unwrap();
+
+        let mut events = [epoll_event::default(); 4];
+        assert_eq!(epoll_wait(epfd, &mut events, 5_000), Ok(1));
+        assert_eq!({ events[0].data }, 1);
+        assert_eq!(timerfd_read(timer), Ok(1));
+        assert_eq!(epoll_wait(epfd, &mut events, 0), Ok(0));
+
+        stdio::close(timer).unwrap();
+        stdio::close(epfd).unwrap();
+    }
+
+    #[test]
+    fn test_poll_scan_masks_readiness() {
+        let readiness = |fd: fd_t| match fd {
+            3 => Some(POLLIN | POLLOUT),
+            4 => Some(POLLHUP),
+            5 => Some(0),
+            _ => None,
+        };
+        let mut fds = [
+            pollfd { fd: 3, events: POLLIN, revents: 0 },
+            pollfd { fd: 4, events: POLLOUT, revents: 0 },
+            pollfd { fd: 5, events: POLLIN, revents: POLLIN },
+            pollfd { fd: 9, events: POLLIN, revents: 0 },
+            pollfd { fd: -1, events: POLLIN, revents: POLLIN },
+        ];
+        assert_eq!(poll_scan(&mut fds, &readiness), 3);
+        let revents: Vec<i16> = fds.iter().map(|entry| entry.revents).collect();
+        // Hang-ups are reported unrequested; negative descriptors are skipped
+        assert_eq!(revents, [POLLIN, POLLHUP, 0, POLLNVAL, 0]);
+    }
+
+    #[test]
+    fn test_select_scan_keeps_only_ready_descriptors() {
+        let readiness = |fd: fd_t| match fd {
+            3 => Some(POLLIN),
+            4 => Some(POLLOUT),
+            5 => Some(POLLHUP),
+            6 => Some(0),
+            _ => None,
+        };
+        let mut read = fd_set::default();
+        let mut write = fd_set::default();
+        for fd in [3, 4, 5, 6] {
+            read.set(fd);
+        }
+        write.set(4);
+        write.set(6);
+
+        assert_eq!(select_scan(7, Some(&mut read), Some(&mut write), None, &readiness), Ok(3));
+        assert!(read.is_set(3) && read.is_set(5));
+        assert!(!read.is_set(4) && !read.is_set(6));
+        assert!(write.is_set(4) && !write.is_set(6));
+
+        // Descriptors at or past nfds are not examined
+        let mut read = fd_set::default();
+        read.set(9);
+        assert_eq!(select_scan(9, Some(&mut read), None, None, &readiness), Ok(0));
+        assert_eq!(select_scan(10, Some(&mut read), None, None, &readiness), Err(Errno::Ebadf));
+    }
+
+    #[test]
+    fn test_poll_serves_local_pipes() {
+        let mut fds = [0; 2];
+        unistd::pipe(&mut fds).unwrap();
+        let mut entries = [
+            pollfd { fd: fds[0], events: POLLIN, revents: 0 },
+            pollfd { fd: fds[1], events: POLLOUT, revents: 0 },
+        ];
+        assert_eq!(poll(&mut entries, 0), Ok(1));
+        assert_eq!((entries[0].revents, entries[1].revents), (0, POLLOUT));
+
+        let mut read_end = [pollfd { fd: fds[0], events: POLLIN, revents: 0 }];
+        let writer = fds[1];
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            stdio::write(writer, b"x")
+        });
+        assert_eq!(poll(&mut read_end, 5_000), Ok(1));
+        assert_eq!(read_end[0].revents, POLLIN);
+        assert_eq!(handle.join().unwrap(), Ok(1));
+
+        stdio::close(fds[1]).unwrap();
+        assert_eq!(poll(&mut read_end, 0), Ok(1));
+        assert_eq!(read_end[0].revents, POLLIN | POLLHUP);
+
+        stdio::close(fds[0]).unwrap();
+        assert_eq!(poll(&mut read_end, 0), Ok(1));
+        assert_eq!(read_end[0].revents, POLLNVAL);
+    }
+
+    #[test]
+    fn test_poll_serves_timerfd_and_epoll() {
+        let timer = timerfd_create(crate::internal::CLOCK_MONOTONIC, 0).unwrap();
+        let epfd = epoll_create1(0).unwrap();
+        epoll_ctl(epfd, EPOLL_CTL_ADD, timer, Some(&epoll_event { events: EPOLLIN, data: 1 })).unwrap();
+
+        let mut entries = [
+            pollfd { fd: timer, events: POLLIN, revents: 0 },
+            pollfd { fd: epfd, events: POLLIN, revents: 0 },
+        ];
+        assert_eq!(poll(&mut entries, 0), Ok(0));
+
+        let value = itimerspec { it_interval: ns_to_timespec(0), it_value: ns_to_timespec(10_000_000) };
+        timerfd_settime(timer, 0, &value, None).unwrap();
+        assert_eq!(poll(&mut entries, 5_000), Ok(2));
+        assert_eq!((entries[0].revents, entries[1].revents), (POLLIN, POLLIN));
+
+        assert_eq!(timerfd_read(timer), Ok(1));
+        assert_eq!(poll(&mut entries, 0), Ok(0));
+
+        stdio::close(timer).unwrap();
+        stdio::close(epfd).unwrap();
+    }
+
+    #[test]
+    fn test_epoll_wait_sleeps_until_timeout() {
+        let mut fds = [0; 2];
+        unistd::pipe(&mut fds).unwrap();
+        let epfd = epoll_create1(0).unwrap();
+        epoll_ctl(epfd, EPOLL_CTL_ADD, fds[0], Some(&epoll_event { events: EPOLLIN, data: 0 })).unwrap();
+
+        let mut events = [epoll_event::default(); 1];
+        let start = std::time::Instant::now();
+        assert_eq!(epoll_wait(epfd, &mut events, 30), Ok(0));
+        assert!(start.elapsed() >= Duration::from_millis(30));
+
+        stdio::close(fds[0]).unwrap();
+        stdio::close(fds[1]).unwrap();
+        stdio::close(epfd).unwrap();
+    }
+}
@@ -3,6 +3,10 @@
 //! This module provides comprehensive pthread.h compatibility for MultiOS,
 //! including thread creation, synchronization primitives, and thread-specific
 //! data while maintaining Rust safety guarantees.
+//!
+//! Threads are created with the CLONE system call and all blocking
+//! primitives park on futex words, so uncontended operations never enter
+//! the kernel.
 
 use crate::errors::*;
 use crate::internal::*;
@@ -10,6 +14,9 @@ use crate::types::*;
 use crate::syscall;
 use core::ffi;
 use core::ptr;
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use spin::Mutex;
+use std::collections::BTreeMap;
 
 /// Thread identifier type
 pub type pthread_t = usize;
@@ -17,26 +24,63 @@ pub type pthread_t = usize;
 /// Thread attribute type
 pub type pthread_attr_t = usize;
 
-/// Mutex type
-pub type pthread_mutex_t = usize;
+/// Mutex object
+///
+/// `state` is the futex word: 0 = unlocked, 1 = locked, 2 = locked with
+/// waiters. The owner and recursion depth back the error-checking and
+/// recursive mutex types.
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct pthread_mutex_t {
+    state: AtomicU32,
+    owner: AtomicU32,
+    count: AtomicU32,
+    kind: AtomicU32,
+}
 
 /// Mutex attribute type
 pub type pthread_mutexattr_t = usize;
 
-/// Condition variable type
-pub type pthread_cond_t = usize;
+/// Condition variable object
+///
+/// Waiters sleep on the `seq` futex word; every signal or broadcast bumps it.
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct pthread_cond_t {
+    seq: AtomicU32,
+    clock: AtomicU32,
+}
 
 /// Condition variable attribute type
 pub type pthread_condattr_t = usize;
 
-/// Read-write lock type
-pub type pthread_rwlock_t = usize;
+/// Read-write lock object
+///
+/// `state` holds the reader count, or `RWLOCK_WRITER` while write-locked.
+/// Blocked threads sleep on the `seq` futex word, which every unlock bumps.
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct pthread_rwlock_t {
+    state: AtomicU32,
+    writer: AtomicU32,
+    writers_waiting: AtomicU32,
+    seq: AtomicU32,
+}
 
 /// Read-write lock attribute type
 pub type pthread_rwlockattr_t = usize;
 
-/// Barrier type
-pub type pthread_barrier_t = usize;
+/// Barrier object
+///
+/// Threads sleep on the `generation` futex word until the last arrival
+/// advances it.
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct pthread_barrier_t {
+    count: AtomicU32,
+    arrived: AtomicU32,
+    generation: AtomicU32,
+}
 
 /// Barrier attribute type
 pub type pthread_barrierattr_t = usize;
@@ -53,6 +97,174 @@ pub type pthread_key_t = usize;
 /// One-time initialization type
 pub type pthread_once_t = usize;
 
+impl pthread_mutex_t {
+    /// Statically initialized mutex (PTHREAD_MUTEX_INITIALIZER)
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicU32::new(0),
+            owner: AtomicU32::new(0),
+            count: AtomicU32::new(0),
+            kind: AtomicU32::new(MUTEX_KIND_DEFAULT),
+        }
+    }
+
+    fn kind(&self) -> MutexType {
+        match self.kind.load(Ordering::Relaxed) {
+            MUTEX_KIND_NORMAL => MutexType::Normal,
+            MUTEX_KIND_ERRORCHECK => MutexType::ErrorCheck,
+            MUTEX_KIND_RECURSIVE => MutexType::Recursive,
+            _ => MutexType::Default,
+        }
+    }
+}
+
+impl pthread_cond_t {
+    /// Statically initialized condition variable (PTHREAD_COND_INITIALIZER)
+    pub const fn new() -> Self {
+        Self {
+            seq: AtomicU32::new(0),
+            clock: AtomicU32::new(CLOCK_REALTIME as u32),
+        }
+    }
+}
+
+impl pthread_rwlock_t {
+    /// Statically initialized read-write lock (PTHREAD_RWLOCK_INITIALIZER)
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicU32::new(0),
+            writer: AtomicU32::new(0),
+            writers_waiting: AtomicU32::new(0),
+            seq: AtomicU32::new(0),
+        }
+    }
+}
+
+impl pthread_barrier_t {
+    pub const fn new() -> Self {
+        Self {
+            count: AtomicU32::new(0),
+            arrived: AtomicU32::new(0),
+            generation: AtomicU32::new(0),
+        }
+    }
+}
+
+const MUTEX_KIND_NORMAL: u32 = 0;
+const MUTEX_KIND_ERRORCHECK: u32 = 1;
+const MUTEX_KIND_RECURSIVE: u32 = 2;
+const MUTEX_KIND_DEFAULT: u32 = 3;
+
+const RWLOCK_WRITER: u32 = u32::MAX;
+
+/// Thread lifecycle states kept in `ThreadControl::state`
+const THREAD_JOINABLE: u32 = 0;
+const THREAD_DETACHED: u32 = 1;
+const THREAD_EXITED: u32 = 2;
+
+/// Flags used to create a thread sharing everything with its creator
+///
+/// There is no `CLONE_SETTLS`: threads share the creator's TLS block, so
+/// per-thread state (such as the malloc caches) is keyed by TID instead.
+const THREAD_CLONE_FLAGS: i64 = CLONE_VM | CLONE_FS | CLONE_FILES | CLONE_SIGHAND | CLONE_THREAD
+    | CLONE_SYSVSEM | CLONE_PARENT_SETTID | CLONE_CHILD_CLEARTID;
+
+/// Memory protection and mapping flags for thread stacks
+const STACK_PROT: i32 = PROT_READ | PROT_WRITE;
+const STACK_MAP: i32 = MAP_PRIVATE | MAP_ANONYMOUS;
+
+/// Per-thread control block
+///
+/// `tid` is set by the kernel when the thread is created and cleared (with a
+/// futex wake) when it exits, which is what `join()` waits on.
+struct ThreadControl {
+    tid: AtomicU32,
+    state: AtomicU32,
+    retval: AtomicUsize,
+    invoke: fn(usize, usize) -> usize,
+    start_routine: usize,
+    arg: usize,
+    stack_base: usize,
+    stack_size: usize,
+    owns_stack: bool,
+}
+
+/// Control blocks of live and unjoined threads, keyed by thread ID
+static THREADS: Mutex<BTreeMap<pthread_t, usize>> = Mutex::new(BTreeMap::new());
+
+/// Control blocks of exited detached threads awaiting stack reclamation
+static ZOMBIES: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+
+/// Block while `*word == expected`
+///
+/// Returns `Etimedout` when `timeout` (relative) elapses, and `Eagain` or
+/// `Eintr` on a value mismatch or signal; callers treat both as spurious
+/// wakeups.
+pub(crate) fn futex_wait(word: &AtomicU32, expected: u32, timeout: Option<&timespec>) -> PosixResult<()> {
+    let timeout = timeout.map_or(ptr::null(), |t| t as *const timespec);
+    syscall::futex(word.as_ptr(), FUTEX_WAIT | FUTEX_PRIVATE_FLAG, expected, timeout, ptr::null(), 0)?;
+    Ok(())
+}
+
+/// Wake up to `count` threads blocked on `word`
+pub(crate) fn futex_wake(word: &AtomicU32, count: u32) -> usize {
+    syscall::futex(word.as_ptr(), FUTEX_WAKE | FUTEX_PRIVATE_FLAG, count, ptr::null(), ptr::null(), 0)
+        .unwrap_or(0)
+}
+
+/// Current time on `clock`
+fn clock_now(clock: clockid_t) -> PosixResult<timespec> {
+    let mut now = timespec { tv_sec: 0, tv_nsec: 0 };
+    crate::timer::clock_gettime(clock, &mut now)?;
+    Ok(now)
+}
+
+/// Relative time left until the absolute `deadline` on `clock`
+///
+/// Returns `Etimedout` once the deadline has passed.
+pub(crate) fn time_until(clock: clockid_t, deadline: &timespec) -> PosixResult<timespec> {
+    if deadline.tv_nsec < 0 || deadline.tv_nsec >= 1_000_000_000 {
+        return Err(Errno::Einval);
+    }
+    let now = clock_now(clock)?;
+    let mut sec = deadline.tv_sec - now.tv_sec;
+    let mut nsec = deadline.tv_nsec - now.tv_nsec;
+    if nsec < 0 {
+        sec -= 1;
+        nsec += 1_000_000_000;
+    }
+    if sec < 0 || (sec == 0 && nsec == 0) {
+        return Err(Errno::Etimedout);
+    }
+    Ok(timespec { tv_sec: sec, tv_nsec: nsec })
+}
+
+fn current_tid() -> u32 {
+    syscall::gettid() as u32
+}
+
+/// Take a futex lock word, parking while it is held
+fn lock_word(word: &AtomicU32) {
+    if word.compare_exchange(0, 1, Ordering::Acquire, Ordering::Relaxed).is_ok() {
+        return;
+    }
+    lock_word_contended(word);
+}
+
+/// Take a futex lock word assuming other threads may be parked on it
+fn lock_word_contended(word: &AtomicU32) {
+    while word.swap(2, Ordering::Acquire) != 0 {
+        let _ = futex_wait(word, 2, None);
+    }
+}
+
+/// Release a futex lock word, waking one waiter if any are parked
+fn unlock_word(word: &AtomicU32) {
+    if word.swap(0, Ordering::Release) == 2 {
+        futex_wake(word, 1);
+    }
+}
+
 /// Thread creation attributes
 /// 
 /// This structure contains attributes for thread creation, providing
@@ -358,13 +570,152 @@ pub fn create<T>(
 where
     T: Send + Sync,
 {
-    if thread.is_null() {
-        return Err(Errno::Ebadf);
+    reap_zombies();
+
+    let request = clone_request(attr)?;
+    let (stack_base, owns_stack) = match request.stackaddr {
+        Some(addr) => (addr, false),
+        None => {
+            let mapped = syscall::mmap(0, request.stacksize, STACK_PROT, STACK_MAP, -1, 0).map_err(|_| Errno::Eagain)?;
+            (mapped, true)
+        }
+    };
+
+    let control = Box::into_raw(Box::new(ThreadControl {
+        tid: AtomicU32::new(0),
+        state: AtomicU32::new(if request.detached { THREAD_DETACHED } else { THREAD_JOINABLE }),
+        retval: AtomicUsize::new(0),
+        invoke: invoke_start_routine::<T>,
+        start_routine: start_routine as usize,
+        arg: arg as usize,
+        stack_base,
+        stack_size: request.stacksize,
+        owns_stack,
+    }));
+
+    let stack_top = stack_top(stack_base, request.stacksize);
+    let tid_word = unsafe { (*control).tid.as_ptr() };
+
+    // Hold the registry across clone so the child cannot exit unregistered
+    let mut threads = THREADS.lock();
+    let tid = match syscall::clone(request.flags, stack_top, tid_word, tid_word, thread_entry, control as usize) {
+        Ok(tid) => tid as pthread_t,
+        Err(err) => {
+            drop(threads);
+            unsafe { free_control(control) };
+            return Err(err);
+        }
+    };
+    threads.insert(tid, control as usize);
+    *thread = tid;
+    Ok(())
+}
+
+/// How a set of thread attributes is turned into a clone call
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct CloneRequest {
+    flags: i64,
+    detached: bool,
+    /// Caller-supplied stack; None maps a new one
+    stackaddr: Option<usize>,
+    stacksize: usize,
+}
+
+fn clone_request(attr: Option<&ThreadAttributes>) -> PosixResult<CloneRequest> {
+    let request = match attr {
+        Some(attr) => CloneRequest {
+            flags: THREAD_CLONE_FLAGS,
+            detached: attr.detach_state == DetachState::Detached,
+            stackaddr: attr.stackaddr.map(|addr| addr as usize),
+            stacksize: attr.stacksize,
+        },
+        None => CloneRequest { flags: THREAD_CLONE_FLAGS, detached: false, stackaddr: None, stacksize: 2 * 1024 * 1024 },
+    };
+    if request.stacksize < PTHREAD_STACK_MIN {
+        return Err(Errno::Einval);
+    }
+    Ok(request)
+}
+
+/// Stacks grow down; start 16-byte aligned at the top of the stack
+fn stack_top(stack_base: usize, stack_size: usize) -> usize {
+    (stack_base + stack_size) & !0xf
+}
+
+/// Call a type-erased start routine with its argument
+fn invoke_start_routine<T>(start_routine: usize, arg: usize) -> usize {
+    let start_routine: fn(*mut T) -> *mut u8 = unsafe { core::mem::transmute(start_routine) };
+    start_routine(arg as *mut T) as usize
+}
+
+/// First code run by a new thread, on its own stack
+extern "C" fn thread_entry(control: usize) -> ! {
+    let control = unsafe { &*(control as *const ThreadControl) };
+    let retval = (control.invoke)(control.start_routine, control.arg);
+    finish_thread(control, retval)
+}
+
+/// Record the exit value and leave; the kernel clears `tid` on the way out
+fn finish_thread(control: &ThreadControl, retval: usize) -> ! {
+    control.retval.store(retval, Ordering::Release);
+    let mut threads = THREADS.lock();
+    if control.state.swap(THREAD_EXITED, Ordering::AcqRel) == THREAD_DETACHED {
+        // Nobody will join; the stack is reclaimed once the kernel releases it
+        threads.remove(&(control.tid.load(Ordering::Relaxed) as pthread_t));
+        ZOMBIES.lock().push(control as *const ThreadControl as usize);
+    }
+    drop(threads);
+    crate::malloc::release_thread_cache();
+    syscall::exit(0)
+}
+
+/// Wait for the kernel to release a thread (its TID word reads zero)
+fn wait_for_exit(control: &ThreadControl) {
+    loop {
+        let tid = control.tid.load(Ordering::Acquire);
+        if tid == 0 {
+            return;
+        }
+        let _ = futex_wait(&control.tid, tid, None);
+    }
+}
+
+/// Free a control block and the stack it owns
+///
+/// # Safety
+/// The thread must have been released by the kernel, or never started.
+unsafe fn free_control(control: *mut ThreadControl) {
+    let control = Box::from_raw(control);
+    if control.owns_stack {
+        let _ = syscall::munmap(control.stack_base, control.stack_size);
+    }
+}
+
+/// Reclaim detached threads that have finished running
+fn reap_zombies() {
+    let mut zombies = ZOMBIES.lock();
+    zombies.retain(|&control| {
+        let released = unsafe { (*(control as *const ThreadControl)).tid.load(Ordering::Acquire) == 0 };
+        if released {
+            unsafe { free_control(control as *mut ThreadControl) };
+        }
+        !released
+    });
+}
+
+/// Terminate the calling thread
+/// 
+/// This function provides compatibility with pthread_exit().
+/// 
+/// # Arguments
+/// * `retval` - Value made available to a thread joining this one
+pub fn exit(retval: *mut u8) -> ! {
+    let tid = current_tid() as pthread_t;
+    let control = THREADS.lock().get(&tid).copied();
+    match control {
+        Some(control) => finish_thread(unsafe { &*(control as *const ThreadControl) }, retval as usize),
+        None => syscall::exit(0),
     }
-    
-    // In a real implementation, this would call syscall::clone
-    // For now, return not implemented
-    Err(Errno::Enosys)
 }
 
 /// Join with a terminated thread
@@ -381,10 +732,27 @@ pub fn join(thread: pthread_t, retval: Option<&mut *mut u8>) -> PosixResult<()>
     if thread == 0 {
         return Err(Errno::Einval);
     }
-    
-    // In a real implementation, this would wait for the thread to terminate
-    // For now, return not implemented
-    Err(Errno::Enosys)
+    if thread == self_() {
+        return Err(Errno::Edeadlk);
+    }
+
+    // Removing the entry claims the join; a second joiner gets Esrch
+    let control = {
+        let mut threads = THREADS.lock();
+        let control = *threads.get(&thread).ok_or(Errno::Esrch)? as *mut ThreadControl;
+        if unsafe { (*control).state.load(Ordering::Acquire) } == THREAD_DETACHED {
+            return Err(Errno::Einval);
+        }
+        threads.remove(&thread);
+        control
+    };
+    wait_for_exit(unsafe { &*control });
+
+    if let Some(retval) = retval {
+        *retval = unsafe { (*control).retval.load(Ordering::Acquire) } as *mut u8;
+    }
+    unsafe { free_control(control) };
+    Ok(())
 }
 
 /// Detach a thread
@@ -400,10 +768,22 @@ pub fn detach(thread: pthread_t) -> PosixResult<()> {
     if thread == 0 {
         return Err(Errno::Einval);
     }
-    
-    // In a real implementation, this would detach the thread
-    // For now, return not implemented
-    Err(Errno::Enosys)
+
+    let mut threads = THREADS.lock();
+    let control = *threads.get(&thread).ok_or(Errno::Esrch)? as *mut ThreadControl;
+    let state = unsafe { &(*control).state };
+    match state.compare_exchange(THREAD_JOINABLE, THREAD_DETACHED, Ordering::AcqRel, Ordering::Acquire) {
+        Ok(_) => Ok(()),
+        Err(THREAD_DETACHED) => Err(Errno::Einval),
+        Err(_) => {
+            // Already exited: reclaim it now, as the thread could not queue itself
+            threads.remove(&thread);
+            drop(threads);
+            wait_for_exit(unsafe { &*control });
+            unsafe { free_control(control) };
+            Ok(())
+        }
+    }
 }
 
 /// Get the current thread ID
@@ -413,9 +793,7 @@ pub fn detach(thread: pthread_t) -> PosixResult<()> {
 /// # Returns
 /// * `pthread_t` - ID of the current thread
 pub fn self_() -> pthread_t {
-    // In a real implementation, this would get the current thread ID
-    // For now, return a placeholder
-    1
+    current_tid() as pthread_t
 }
 
 /// Equal thread IDs
@@ -529,13 +907,14 @@ pub fn mutexattr_destroy(attr: &mut MutexAttributes) -> PosixResult<()> {
 /// # Returns
 /// * `PosixResult<()>` - Success on initialization, error on failure
 pub fn mutex_init(mutex: &mut pthread_mutex_t, attr: Option<&MutexAttributes>) -> PosixResult<()> {
-    if mutex.is_null() {
-        return Err(Errno::Ebadf);
-    }
-    
-    // In a real implementation, this would initialize the mutex
-    // For now, set a placeholder value
-    *mutex = 1;
+    let kind = match attr.map(|attr| attr.type_) {
+        Some(MutexType::Normal) => MUTEX_KIND_NORMAL,
+        Some(MutexType::ErrorCheck) => MUTEX_KIND_ERRORCHECK,
+        Some(MutexType::Recursive) => MUTEX_KIND_RECURSIVE,
+        Some(MutexType::Default) | None => MUTEX_KIND_DEFAULT,
+    };
+    *mutex = pthread_mutex_t::new();
+    mutex.kind.store(kind, Ordering::Relaxed);
     Ok(())
 }
 
@@ -549,12 +928,9 @@ pub fn mutex_init(mutex: &mut pthread_mutex_t, attr: Option<&MutexAttributes>) -
 /// # Returns
 /// * `PosixResult<()>` - Success on destruction, error on failure
 pub fn mutex_destroy(mutex: &pthread_mutex_t) -> PosixResult<()> {
-    if mutex.is_null() {
-        return Err(Errno::Ebadf);
+    if mutex.state.load(Ordering::Acquire) != 0 {
+        return Err(Errno::Ebusy);
     }
-    
-    // In a real implementation, this would destroy the mutex
-    // For now, just check if it's valid
     Ok(())
 }
 
@@ -568,12 +944,29 @@ pub fn mutex_destroy(mutex: &pthread_mutex_t) -> PosixResult<()> {
 /// # Returns
 /// * `PosixResult<()>` - Success on lock, error on failure
 pub fn mutex_lock(mutex: &pthread_mutex_t) -> PosixResult<()> {
-    if mutex.is_null() {
-        return Err(Errno::Ebadf);
+    let tid = current_tid();
+    if mutex.owner.load(Ordering::Relaxed) == tid {
+        match mutex.kind() {
+            MutexType::Recursive => return mutex_relock(mutex),
+            MutexType::ErrorCheck => return Err(Errno::Edeadlk),
+            // Normal and default mutexes deadlock, as POSIX specifies
+            MutexType::Normal | MutexType::Default => {}
+        }
     }
-    
-    // In a real implementation, this would lock the mutex
-    // For now, just check if it's valid
+
+    lock_word(&mutex.state);
+    mutex.owner.store(tid, Ordering::Relaxed);
+    mutex.count.store(1, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Take another level of a recursive mutex already held by the caller
+fn mutex_relock(mutex: &pthread_mutex_t) -> PosixResult<()> {
+    let count = mutex.count.load(Ordering::Relaxed);
+    if count == u32::MAX {
+        return Err(Errno::Eagain);
+    }
+    mutex.count.store(count + 1, Ordering::Relaxed);
     Ok(())
 }
 
@@ -587,12 +980,14 @@ pub fn mutex_lock(mutex: &pthread_mutex_t) -> PosixResult<()> {
 /// # Returns
 /// * `PosixResult<()>` - Success on lock, error on failure
 pub fn mutex_trylock(mutex: &pthread_mutex_t) -> PosixResult<()> {
-    if mutex.is_null() {
-        return Err(Errno::Ebadf);
+    let tid = current_tid();
+    if mutex.kind() == MutexType::Recursive && mutex.owner.load(Ordering::Relaxed) == tid {
+        return mutex_relock(mutex);
     }
-    
-    // In a real implementation, this would try to lock the mutex
-    // For now, just check if it's valid
+
+    mutex.state.compare_exchange(0, 1, Ordering::Acquire, Ordering::Relaxed).map_err(|_| Errno::Ebusy)?;
+    mutex.owner.store(tid, Ordering::Relaxed);
+    mutex.count.store(1, Ordering::Relaxed);
     Ok(())
 }
 
@@ -606,12 +1001,20 @@ pub fn mutex_trylock(mutex: &pthread_mutex_t) -> PosixResult<()> {
 /// # Returns
 /// * `PosixResult<()>` - Success on unlock, error on failure
 pub fn mutex_unlock(mutex: &pthread_mutex_t) -> PosixResult<()> {
-    if mutex.is_null() {
-        return Err(Errno::Ebadf);
+    let checked = matches!(mutex.kind(), MutexType::ErrorCheck | MutexType::Recursive);
+    if checked && mutex.owner.load(Ordering::Relaxed) != current_tid() {
+        return Err(Errno::Epperm);
     }
-    
-    // In a real implementation, this would unlock the mutex
-    // For now, just check if it's valid
+
+    let count = mutex.count.load(Ordering::Relaxed);
+    if count > 1 {
+        mutex.count.store(count - 1, Ordering::Relaxed);
+        return Ok(());
+    }
+
+    mutex.owner.store(0, Ordering::Relaxed);
+    mutex.count.store(0, Ordering::Relaxed);
+    unlock_word(&mutex.state);
     Ok(())
 }
 
@@ -649,6 +1052,34 @@ pub fn condattr_destroy(attr: &mut CondAttributes) -> PosixResult<()> {
     Ok(())
 }
 
+/// Set the clock used for timed waits
+/// 
+/// This function provides compatibility with pthread_condattr_setclock().
+/// 
+/// # Arguments
+/// * `attr` - Condition variable attributes
+/// * `clock` - Clock measuring `cond_timedwait()` deadlines
+/// 
+/// # Returns
+/// * `PosixResult<()>` - Success on set, error on failure
+pub fn condattr_setclock(attr: &mut CondAttributes, clock: ClockId) -> PosixResult<()> {
+    attr.clock = clock;
+    Ok(())
+}
+
+/// Get the clock used for timed waits
+/// 
+/// This function provides compatibility with pthread_condattr_getclock().
+/// 
+/// # Arguments
+/// * `attr` - Condition variable attributes
+/// 
+/// # Returns
+/// * `PosixResult<ClockId>` - Clock measuring `cond_timedwait()` deadlines
+pub fn condattr_getclock(attr: &CondAttributes) -> PosixResult<ClockId> {
+    Ok(attr.clock)
+}
+
 /// Initialize a condition variable
 /// 
 /// This function provides compatibility with pthread_cond_init().
@@ -660,13 +1091,12 @@ pub fn condattr_destroy(attr: &mut CondAttributes) -> PosixResult<()> {
 /// # Returns
 /// * `PosixResult<()>` - Success on initialization, error on failure
 pub fn cond_init(cond: &mut pthread_cond_t, attr: Option<&CondAttributes>) -> PosixResult<()> {
-    if cond.is_null() {
-        return Err(Errno::Ebadf);
-    }
-    
-    // In a real implementation, this would initialize the condition variable
-    // For now, set a placeholder value
-    *cond = 1;
+    let clock = match attr.map(|attr| attr.clock) {
+        Some(ClockId::Monotonic) => CLOCK_MONOTONIC,
+        Some(ClockId::Realtime) | None => CLOCK_REALTIME,
+    };
+    *cond = pthread_cond_t::new();
+    cond.clock.store(clock as u32, Ordering::Relaxed);
     Ok(())
 }
 
@@ -680,11 +1110,8 @@ pub fn cond_init(cond: &mut pthread_cond_t, attr: Option<&CondAttributes>) -> Po
 /// # Returns
 /// * `PosixResult<()>` - Success on destruction, error on failure
 pub fn cond_destroy(cond: &pthread_cond_t) -> PosixResult<()> {
-    if cond.is_null() {
-        return Err(Errno::Ebadf);
-    }
-    
-    // In a real implementation, this would destroy the condition variable
+    // Waiters hold no state in the condition variable itself
+    let _ = cond;
     Ok(())
 }
 
@@ -699,13 +1126,58 @@ pub fn cond_destroy(cond: &pthread_cond_t) -> PosixResult<()> {
 /// # Returns
 /// * `PosixResult<()>` - Success on wait, error on failure
 pub fn cond_wait(cond: &pthread_cond_t, mutex: &pthread_mutex_t) -> PosixResult<()> {
-    if cond.is_null() || mutex.is_null() {
-        return Err(Errno::Ebadf);
+    cond_wait_until(cond, mutex, None)
+}
+
+/// Wait on a condition variable with a deadline
+/// 
+/// This function provides compatibility with pthread_cond_timedwait().
+/// The deadline is measured against the clock chosen at initialization
+/// (CLOCK_REALTIME unless `CondAttributes::clock` selected CLOCK_MONOTONIC).
+/// 
+/// # Arguments
+/// * `cond` - Condition variable to wait on
+/// * `mutex` - Mutex held by the caller, released while waiting
+/// * `abstime` - Absolute deadline
+/// 
+/// # Returns
+/// * `PosixResult<()>` - Success when woken, `Etimedout` once the deadline passes
+pub fn cond_timedwait(cond: &pthread_cond_t, mutex: &pthread_mutex_t, abstime: &timespec) -> PosixResult<()> {
+    cond_wait_until(cond, mutex, Some(abstime))
+}
+
+fn cond_wait_until(cond: &pthread_cond_t, mutex: &pthread_mutex_t, abstime: Option<&timespec>) -> PosixResult<()> {
+    let tid = current_tid();
+    let checked = matches!(mutex.kind(), MutexType::ErrorCheck | MutexType::Recursive);
+    if checked && mutex.owner.load(Ordering::Relaxed) != tid {
+        return Err(Errno::Epperm);
+    }
+
+    // Sample the sequence before releasing the mutex so no signal is lost
+    let seq = cond.seq.load(Ordering::Acquire);
+    let count = mutex.count.load(Ordering::Relaxed);
+    mutex.owner.store(0, Ordering::Relaxed);
+    mutex.count.store(0, Ordering::Relaxed);
+    unlock_word(&mutex.state);
+
+    let clock = cond.clock.load(Ordering::Relaxed) as clockid_t;
+    let result = match abstime.map(|deadline| time_until(clock, deadline)) {
+        Some(Err(err)) => Err(err),
+        Some(Ok(timeout)) => futex_wait(&cond.seq, seq, Some(&timeout)),
+        None => futex_wait(&cond.seq, seq, None),
+    };
+
+    // Other waiters may be parked on the mutex once we were woken
+    lock_word_contended(&mutex.state);
+    mutex.owner.store(tid, Ordering::Relaxed);
+    mutex.count.store(count, Ordering::Relaxed);
+
+    match result {
+        Err(Errno::Etimedout) => Err(Errno::Etimedout),
+        Err(Errno::Einval) => Err(Errno::Einval),
+        // Value changes and signals are spurious wakeups
+        _ => Ok(()),
     }
-    
-    // In a real implementation, this would wait on the condition variable
-    // For now, return not implemented
-    Err(Errno::Enosys)
 }
 
 /// Signal a condition variable
@@ -718,11 +1190,8 @@ pub fn cond_wait(cond: &pthread_cond_t, mutex: &pthread_mutex_t) -> PosixResult<
 /// # Returns
 /// * `PosixResult<()>` - Success on signal, error on failure
 pub fn cond_signal(cond: &pthread_cond_t) -> PosixResult<()> {
-    if cond.is_null() {
-        return Err(Errno::Ebadf);
-    }
-    
-    // In a real implementation, this would signal the condition variable
+    cond.seq.fetch_add(1, Ordering::Release);
+    futex_wake(&cond.seq, 1);
     Ok(())
 }
 
@@ -736,11 +1205,8 @@ pub fn cond_signal(cond: &pthread_cond_t) -> PosixResult<()> {
 /// # Returns
 /// * `PosixResult<()>` - Success on broadcast, error on failure
 pub fn cond_broadcast(cond: &pthread_cond_t) -> PosixResult<()> {
-    if cond.is_null() {
-        return Err(Errno::Ebadf);
-    }
-    
-    // In a real implementation, this would broadcast the condition variable
+    cond.seq.fetch_add(1, Ordering::Release);
+    futex_wake(&cond.seq, i32::MAX as u32);
     Ok(())
 }
 
@@ -787,12 +1253,8 @@ pub fn rwlockattr_destroy(attr: &mut RWLockAttributes) -> PosixResult<()> {
 /// # Returns
 /// * `PosixResult<()>` - Success on initialization, error on failure
 pub fn rwlock_init(rwlock: &mut pthread_rwlock_t, attr: Option<&RWLockAttributes>) -> PosixResult<()> {
-    if rwlock.is_null() {
-        return Err(Errno::Ebadf);
-    }
-    
-    // In a real implementation, this would initialize the read-write lock
-    *rwlock = 1;
+    let _ = attr;
+    *rwlock = pthread_rwlock_t::new();
     Ok(())
 }
 
@@ -806,11 +1268,9 @@ pub fn rwlock_init(rwlock: &mut pthread_rwlock_t, attr: Option<&RWLockAttributes
 /// # Returns
 /// * `PosixResult<()>` - Success on destruction, error on failure
 pub fn rwlock_destroy(rwlock: &pthread_rwlock_t) -> PosixResult<()> {
-    if rwlock.is_null() {
-        return Err(Errno::Ebadf);
+    if rwlock.state.load(Ordering::Acquire) != 0 {
+        return Err(Errno::Ebusy);
     }
-    
-    // In a real implementation, this would destroy the read-write lock
     Ok(())
 }
 
@@ -824,13 +1284,25 @@ pub fn rwlock_destroy(rwlock: &pthread_rwlock_t) -> PosixResult<()> {
 /// # Returns
 /// * `PosixResult<()>` - Success on acquire, error on failure
 pub fn rwlock_rdlock(rwlock: &pthread_rwlock_t) -> PosixResult<()> {
-    if rwlock.is_null() {
-        return Err(Errno::Ebadf);
+    if rwlock.writer.load(Ordering::Relaxed) == current_tid() {
+        return Err(Errno::Edeadlk);
+    }
+
+    loop {
+        let seq = rwlock.seq.load(Ordering::Acquire);
+        let state = rwlock.state.load(Ordering::Acquire);
+        // Queued writers take precedence over new readers
+        if state != RWLOCK_WRITER && rwlock.writers_waiting.load(Ordering::Acquire) == 0 {
+            if state == RWLOCK_WRITER - 1 {
+                return Err(Errno::Eagain);
+            }
+            if rwlock.state.compare_exchange(state, state + 1, Ordering::Acquire, Ordering::Relaxed).is_ok() {
+                return Ok(());
+            }
+            continue;
+        }
+        let _ = futex_wait(&rwlock.seq, seq, None);
     }
-    
-    // In a real implementation, this would acquire the read lock
-    // For now, return not implemented
-    Err(Errno::Enosys)
 }
 
 /// Acquire write lock on read-write lock
@@ -843,13 +1315,21 @@ pub fn rwlock_rdlock(rwlock: &pthread_rwlock_t) -> PosixResult<()> {
 /// # Returns
 /// * `PosixResult<()>` - Success on acquire, error on failure
 pub fn rwlock_wrlock(rwlock: &pthread_rwlock_t) -> PosixResult<()> {
-    if rwlock.is_null() {
-        return Err(Errno::Ebadf);
+    let tid = current_tid();
+    if rwlock.writer.load(Ordering::Relaxed) == tid {
+        return Err(Errno::Edeadlk);
+    }
+
+    rwlock.writers_waiting.fetch_add(1, Ordering::AcqRel);
+    loop {
+        let seq = rwlock.seq.load(Ordering::Acquire);
+        if rwlock.state.compare_exchange(0, RWLOCK_WRITER, Ordering::Acquire, Ordering::Relaxed).is_ok() {
+            rwlock.writers_waiting.fetch_sub(1, Ordering::AcqRel);
+            rwlock.writer.store(tid, Ordering::Relaxed);
+            return Ok(());
+        }
+        let _ = futex_wait(&rwlock.seq, seq, None);
     }
-    
-    // In a real implementation, this would acquire the write lock
-    // For now, return not implemented
-    Err(Errno::Enosys)
 }
 
 /// Unlock read-write lock
@@ -862,13 +1342,26 @@ pub fn rwlock_wrlock(rwlock: &pthread_rwlock_t) -> PosixResult<()> {
 /// # Returns
 /// * `PosixResult<()>` - Success on unlock, error on failure
 pub fn rwlock_unlock(rwlock: &pthread_rwlock_t) -> PosixResult<()> {
-    if rwlock.is_null() {
-        return Err(Errno::Ebadf);
+    let state = rwlock.state.load(Ordering::Acquire);
+    let released = match state {
+        0 => return Err(Errno::Epperm),
+        RWLOCK_WRITER => {
+            if rwlock.writer.load(Ordering::Relaxed) != current_tid() {
+                return Err(Errno::Epperm);
+            }
+            rwlock.writer.store(0, Ordering::Relaxed);
+            rwlock.state.store(0, Ordering::Release);
+            true
+        }
+        _ => rwlock.state.fetch_sub(1, Ordering::Release) == 1,
+    };
+
+    // Only the last reader or a writer can unblock anyone
+    if released {
+        rwlock.seq.fetch_add(1, Ordering::Release);
+        futex_wake(&rwlock.seq, i32::MAX as u32);
     }
-    
-    // In a real implementation, this would unlock the read-write lock
-    // For now, return not implemented
-    Err(Errno::Enosys)
+    Ok(())
 }
 
 /// Initialize barrier attributes
@@ -915,12 +1408,12 @@ pub fn barrierattr_destroy(attr: &mut BarrierAttributes) -> PosixResult<()> {
 /// # Returns
 /// * `PosixResult<()>` - Success on initialization, error on failure
 pub fn barrier_init(barrier: &mut pthread_barrier_t, attr: Option<&BarrierAttributes>, count: i32) -> PosixResult<()> {
-    if barrier.is_null() || count <= 0 {
+    let _ = attr;
+    if count <= 0 {
         return Err(Errno::Einval);
     }
-    
-    // In a real implementation, this would initialize the barrier
-    *barrier = 1;
+    *barrier = pthread_barrier_t::new();
+    barrier.count.store(count as u32, Ordering::Relaxed);
     Ok(())
 }
 
@@ -934,11 +1427,9 @@ pub fn barrier_init(barrier: &mut pthread_barrier_t, attr: Option<&BarrierAttrib
 /// # Returns
 /// * `PosixResult<()>` - Success on destruction, error on failure
 pub fn barrier_destroy(barrier: &pthread_barrier_t) -> PosixResult<()> {
-    if barrier.is_null() {
-        return Err(Errno::Ebadf);
+    if barrier.arrived.load(Ordering::Acquire) != 0 {
+        return Err(Errno::Ebusy);
     }
-    
-    // In a real implementation, this would destroy the barrier
     Ok(())
 }
 
@@ -952,19 +1443,30 @@ pub fn barrier_destroy(barrier: &pthread_barrier_t) -> PosixResult<()> {
 /// # Returns
 /// * `PosixResult<BarrierWaitResult>` - Barrier wait result, error on failure
 pub fn barrier_wait(barrier: &pthread_barrier_t) -> PosixResult<BarrierWaitResult> {
-    if barrier.is_null() {
-        return Err(Errno::Ebadf);
+    let count = barrier.count.load(Ordering::Relaxed);
+    if count == 0 {
+        return Err(Errno::Einval);
     }
-    
-    // In a real implementation, this would wait at the barrier
-    // For now, return not implemented
-    Err(Errno::Enosys)
+
+    let generation = barrier.generation.load(Ordering::Acquire);
+    if barrier.arrived.fetch_add(1, Ordering::AcqRel) + 1 == count {
+        // Last arrival: reset for the next round and release everyone
+        barrier.arrived.store(0, Ordering::Relaxed);
+        barrier.generation.fetch_add(1, Ordering::Release);
+        futex_wake(&barrier.generation, i32::MAX as u32);
+        return Ok(BarrierWaitResult::Success);
+    }
+
+    while barrier.generation.load(Ordering::Acquire) == generation {
+        let _ = futex_wait(&barrier.generation, generation, None);
+    }
+    Ok(BarrierWaitResult::NotLast)
 }
 
 /// Barrier wait result
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum BarrierWaitResult {
-    Success,             // Successful wait
+    Success,             // Last thread to arrive (PTHREAD_BARRIER_SERIAL_THREAD)
     NotLast,             // Not the last thread to reach barrier
 }
 
@@ -1415,3 +1917,73 @@ pub const PTHREAD_STACK_MIN: usize = 16384;
 
 /// Threads maximum
 pub const PTHREAD_THREADS_MAX: i32 = -1; // No limit
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attributes() -> ThreadAttributes {
+        ThreadAttributes {
+            detach_state: DetachState::Joinable,
+            scope: ThreadScope::System,
+            inheritsched: InheritSched::Inherit,
+            schedpolicy: SchedPolicy::Other,
+            schedparam: SchedParam { priority: 0 },
+            guardsize: 4096,
+            stacksize: 2 * 1024 * 1024,
+            stackaddr: None,
+            stack: None,
+        }
+    }
+
+    #[test]
+    fn test_thread_clone_flags_share_everything() {
+        let shared = CLONE_VM | CLONE_FS | CLONE_FILES | CLONE_SIGHAND | CLONE_THREAD | CLONE_SYSVSEM;
+        assert_eq!(THREAD_CLONE_FLAGS & shared, shared);
+        // join() waits on the TID word the kernel sets and clears
+        assert_ne!(THREAD_CLONE_FLAGS & CLONE_PARENT_SETTID, 0);
+        assert_ne!(THREAD_CLONE_FLAGS & CLONE_CHILD_CLEARTID, 0);
+        assert_eq!(THREAD_CLONE_FLAGS & (CLONE_SETTLS | CLONE_VFORK | CLONE_CHILD_SETTID), 0);
+    }
+
+    #[test]
+    fn test_default_attributes_make_a_joinable_thread() {
+        let request = clone_request(None).unwrap();
+        assert_eq!(request, CloneRequest {
+            flags: THREAD_CLONE_FLAGS,
+            detached: false,
+            stackaddr: None,
+            stacksize: 2 * 1024 * 1024,
+        });
+        assert_eq!(clone_request(Some(&attributes())), Ok(request));
+    }
+
+    #[test]
+    fn test_attributes_translate_into_the_clone_request() {
+        let mut attr = attributes();
+        attr_setdetachstate(&mut attr, DetachState::Detached).unwrap();
+        attr_setstacksize(&mut attr, 64 * 1024).unwrap();
+        attr.stackaddr = Some(0x7000_0000 as *mut u8);
+
+        let request = clone_request(Some(&attr)).unwrap();
+        assert_eq!(request.flags, THREAD_CLONE_FLAGS);
+        assert!(request.detached);
+        assert_eq!(request.stackaddr, Some(0x7000_0000));
+        assert_eq!(request.stacksize, 64 * 1024);
+    }
+
+    #[test]
+    fn test_undersized_stack_is_rejected() {
+        let mut attr = attributes();
+        attr.stacksize = PTHREAD_STACK_MIN - 1;
+        assert_eq!(clone_request(Some(&attr)), Err(Errno::Einval));
+    }
+
+    #[test]
+    fn test_stack_top_is_aligned_within_the_stack() {
+        assert_eq!(stack_top(0x1000, 0x4000), 0x5000);
+        assert_eq!(stack_top(0x1003, 0x4000), 0x5000);
+        assert_eq!(stack_top(0x1000, 0x4008) % 16, 0);
+    }
+}
+
@@ -0,0 +1,341 @@
+//! Pseudo-Terminal Support for MultiOS
+//!
+//! This module provides pseudo-terminals (ptys):
+//! - `PtyTable`, the device state behind /dev/ptmx and /dev/pts/N: each pty
+//!   pairs a master side (a terminal emulator, remote shell or the
+//!   hypervisor console) with a slave terminal that programs read and write
+//!   through a `LineDiscipline`
+//! - posix_openpt()/grantpt()/unlockpt()/ptsname() for user programs
+
+use crate::errors::*;
+use crate::internal::termios;
+use crate::signal::{SIGHUP, SIGWINCH};
+use crate::stdio::PATH_MAX;
+use crate::syscall;
+use crate::termios::*;
+use crate::types::*;
+use std::collections::BTreeMap;
+
+/// Pseudo-terminal multiplexer device
+pub const PTMX_PATH: &str = "/dev/ptmx";
+
+/// Directory holding the slave devices
+pub const PTS_DIR: &str = "/dev/pts";
+
+/// Maximum number of ptys
+pub const PTY_MAX: u32 = 256;
+
+/// One pseudo-terminal pair
+#[derive(Debug, Clone)]
+pub struct Pty {
+    pub index: u32,
+    /// Slave opens are refused until unlockpt()
+    pub locked: bool,
+    /// Owner set by grantpt()
+    pub owner: Option<uid_t>,
+    /// Foreground process group of the slave terminal
+    pub foreground: pid_t,
+    /// Session that has this pty as controlling terminal
+    pub session: Option<pid_t>,
+    pub master_open: bool,
+    pub slave_opens: u32,
+    ldisc: LineDiscipline,
+}
+
+/// Signal to deliver to a process group as a side effect of a pty operation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PtySignal {
+    pub pgrp: pid_t,
+    pub signal: i32,
+}
+
+/// Pty statistics
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PtyStats {
+    pub allocated: u64,
+    pub released: u64,
+    pub bytes_to_slave: u64,
+    pub bytes_to_master: u64,
+    pub signals_generated: u64,
+}
+
+/// All pseudo-terminals of the system
+#[derive(Debug, Default)]
+pub struct PtyTable {
+    ptys: BTreeMap<u32, Pty>,
+    stats: PtyStats,
+}
+
+impl PtyTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allocate a pty (open of /dev/ptmx); the slave starts locked
+    pub fn open_master(&mut self) -> PosixResult<u32> {
+        let index = (0..PTY_MAX).find(|index| !self.ptys.contains_key(index)).ok_or(Errno::Eagain)?;
+        self.ptys.insert(index, Pty {
+            index,
+            locked: true,
+            owner: None,
+            foreground: 0,
+            session: None,
+            master_open: true,
+            slave_opens: 0,
+            ldisc: LineDiscipline::new(),
+        });
+        self.stats.allocated += 1;
+        Ok(index)
+    }
+
+    pub fn get(&self, index: u32) -> Option<&Pty> {
+        self.ptys.get(&index)
+    }
+
+    fn pty_mut(&mut self, index: u32) -> PosixResult<&mut Pty> {
+        self.ptys.get_mut(&index).ok_or(Errno::Enoent)
+    }
+
+    /// Give the slave to `uid` (grantpt())
+    pub fn grant(&mut self, index: u32, uid: uid_t) -> PosixResult<()> {
+        self.pty_mut(index)?.owner = Some(uid);
+        Ok(())
+    }
+
+    /// Lock or unlock the slave (TIOCSPTLCK)
+    pub fn set_locked(&mut self, index: u32, locked: bool) -> PosixResult<()> {
+        self.pty_mut(index)?.locked = locked;
+        Ok(())
+    }
+
+    /// Open the slave (/dev/pts/N)
+    ///
+    /// A session leader without a controlling terminal acquires this one.
+    pub fn open_slave(&mut self, index: u32, session: Option<pid_t>, pgrp: pid_t) -> PosixResult<()> {
+        let pty = self.pty_mut(index)?;
+        if pty.locked || !pty.master_open {
+            return Err(Errno::Eio);
+        }
+        pty.slave_opens += 1;
+        if pty.session.is_none() {
+            if let Some(session) = session {
+                pty.session = Some(session);
+                pty.foreground = pgrp;
+            }
+        }
+        Ok(())
+    }
+
+    /// Close one slave descriptor
+    pub fn close_slave(&mut self, index: u32) -> PosixResult<()> {
+        let pty = self.pty_mut(index)?;
+        pty.slave_opens = pty.slave_opens.saturating_sub(1);
+        if pty.slave_opens == 0 {
+            pty.session = None;
+        }
+        self.release_if_unused(index);
+        Ok(())
+    }
+
+    /// Close the master; the foreground group of the slave is hung up
+    pub fn close_master(&mut self, index: u32) -> PosixResult<Option<PtySignal>> {
+        let pty = self.pty_mut(index)?;
+        pty.master_open = false;
+        let hangup = (pty.slave_opens > 0 && pty.foreground > 0)
+            .then_some(PtySignal { pgrp: pty.foreground, signal: SIGHUP });
+        if hangup.is_some() {
+            self.stats.signals_generated += 1;
+        }
+        self.release_if_unused(index);
+        Ok(hangup)
+    }
+
+    fn release_if_unused(&mut self, index: u32) {
+        let unused = self.ptys.get(&index).map_or(false, |pty| !pty.master_open && pty.slave_opens == 0);
+        if unused {
+            self.ptys.remove(&index);
+            self.stats.released += 1;
+        }
+    }
+
+    /// Master writes: the bytes are typed into the slave terminal
+    pub fn master_write(&mut self, index: u32, bytes: &[u8]) -> PosixResult<Vec<PtySignal>> {
+        let pty = self.pty_mut(index)?;
+        let pgrp = pty.foreground;
+        let signals: Vec<PtySignal> = pty.ldisc.receive(bytes).into_iter()
+            .filter(|_| pgrp > 0)
+            .map(|signal| PtySignal { pgrp, signal })
+            .collect();
+        self.stats.bytes_to_slave += bytes.len() as u64;
+        self.stats.signals_generated += signals.len() as u64;
+        Ok(signals)
+    }
+
+    /// Master reads: collect what the slave side displayed
+    pub fn master_read(&mut self, index: u32, buf: &mut [u8]) -> PosixResult<usize> {
+        let pty = self.pty_mut(index)?;
+        let count = pty.ldisc.take_output(buf);
+        if count == 0 && pty.slave_opens == 0 && !pty.locked {
+            // Every slave descriptor is closed: report hangup
+            return Err(Errno::Eio);
+        }
+        self.stats.bytes_to_master += count as u64;
+        Ok(count)
+    }
+
+    /// Slave reads: processed input; `Eagain` when a reader must block
+    pub fn slave_read(&mut self, index: u32, buf: &mut [u8]) -> PosixResult<usize> {
+        let pty = self.pty_mut(index)?;
+        if !pty.ldisc.readable() {
+            return if pty.master_open { Err(Errno::Eagain) } else { Ok(0) };
+        }
+        Ok(pty.ldisc.read(buf))
+    }
+
+    /// Slave writes: program output, post-processed for the master
+    pub fn slave_write(&mut self, index: u32, bytes: &[u8]) -> PosixResult<usize> {
+        let pty = self.pty_mut(index)?;
+        if !pty.master_open {
+            return Err(Errno::Eio);
+        }
+        Ok(pty.ldisc.write(bytes))
+    }
+
+    /// Whether the master has output to collect
+    pub fn master_readable(&self, index: u32) -> bool {
+        self.ptys.get(&index).map_or(false, |pty| pty.ldisc.has_output())
+    }
+
+    /// Whether a slave read would not block
+    pub fn slave_readable(&self, index: u32) -> bool {
+        self.ptys.get(&index).map_or(false, |pty| pty.ldisc.readable() || !pty.master_open)
+    }
+
+    pub fn tcgetattr(&self, index: u32) -> PosixResult<termios> {
+        self.ptys.get(&index).map(|pty| pty.ldisc.termios()).ok_or(Errno::Enoent)
+    }
+
+    pub fn tcsetattr(&mut self, index: u32, action: i32, settings: &termios) -> PosixResult<()> {
+        self.pty_mut(index)?.ldisc.set_termios(action, settings)
+    }
+
+    pub fn tcflush(&mut self, index: u32, queue_selector: i32) -> PosixResult<()> {
+        self.pty_mut(index)?.ldisc.flush(queue_selector)
+    }
+
+    pub fn winsize(&self, index: u32) -> PosixResult<winsize> {
+        self.ptys.get(&index).map(|pty| pty.ldisc.winsize()).ok_or(Errno::Enoent)
+    }
+
+    /// Resize the terminal; the foreground group gets SIGWINCH on change
+    pub fn set_winsize(&mut self, index: u32, ws: winsize) -> PosixResult<Option<PtySignal>> {
+        let pty = self.pty_mut(index)?;
+        let signal = (pty.ldisc.set_winsize(ws) && pty.foreground > 0)
+            .then_some(PtySignal { pgrp: pty.foreground, signal: SIGWINCH });
+        if signal.is_some() {
+            self.stats.signals_generated += 1;
+        }
+        Ok(signal)
+    }
+
+    /// Change the foreground process group (tcsetpgrp())
+    pub fn set_foreground(&mut self, index: u32, session: pid_t, pgrp: pid_t) -> PosixResult<()> {
+        let pty = self.pty_mut(index)?;
+        if pty.session != Some(session) {
+            return Err(Errno::Enotty);
+        }
+        pty.foreground = pgrp;
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.ptys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ptys.is_empty()
+    }
+
+    pub fn get_stats(&self) -> PtyStats {
+        self.stats
+    }
+}
+
+/// Path of the slave device for pty `index`
+pub fn pts_path(index: u32) -> String {
+    format!("{}/{}", PTS_DIR, index)
+}
+
+/// Open a pseudo-terminal master
+///
+/// This function provides compatibility with the POSIX posix_openpt() function.
+///
+/// # Arguments
+/// * `flags` - Open flags (READ | WRITE, optionally NOCTTY)
+///
+/// # Returns
+/// * `PosixResult<fd_t>` - Master descriptor, error on failure
+pub fn posix_openpt(flags: OpenFlags) -> PosixResult<fd_t> {
+    let mut path = [0u8; 16];
+    path[..PTMX_PATH.len()].copy_from_slice(PTMX_PATH.as_bytes());
+    syscall::open(path.as_ptr(), flags, 0)
+}
+
+/// Index of the pty behind a master descriptor
+fn pty_index(fd: fd_t) -> PosixResult<u32> {
+    let mut index: u32 = 0;
+    syscall::ioctl(fd, TIOCGPTN, &mut index as *mut u32 as usize)?;
+    Ok(index)
+}
+
+/// Grant access to the slave pseudo-terminal
+///
+/// This function provides compatibility with the POSIX grantpt() function.
+/// The devpts driver assigns ownership when the master is opened, so this
+/// only validates that `fd` is a master.
+pub fn grantpt(fd: fd_t) -> PosixResult<()> {
+    pty_index(fd).map(|_| ())
+}
+
+/// Unlock the slave pseudo-terminal
+///
+/// This function provides compatibility with the POSIX unlockpt() function.
+pub fn unlockpt(fd: fd_t) -> PosixResult<()> {
+    let unlock: i32 = 0;
+    syscall::ioctl(fd, TIOCSPTLCK, &unlock as *const i32 as usize).map(|_| ())
+}
+
+/// Name of the slave pseudo-terminal
+///
+/// This function provides compatibility with the POSIX ptsname() function.
+///
+/// # Returns
+/// * `PosixResult<String>` - Path of the slave device, error on failure
+pub fn ptsname(fd: fd_t) -> PosixResult<String> {
+    pty_index(fd).map(pts_path)
+}
+
+/// Open a master and its unlocked slave
+///
+/// Convenience for shells and console bridges.
+///
+/// # Returns
+/// * `PosixResult<(fd_t, fd_t)>` - Master and slave descriptors, error on failure
+pub fn openpty() -> PosixResult<(fd_t, fd_t)> {
+    let master = posix_openpt(OpenFlags::READ | OpenFlags::WRITE | OpenFlags::NOCTTY)?;
+    let slave = grantpt(master)
+        .and_then(|_| unlockpt(master))
+        .and_then(|_| ptsname(master))
+        .and_then(|path| {
+            let mut buf = [0u8; PATH_MAX + 1];
+            buf[..path.len()].copy_from_slice(path.as_bytes());
+            syscall::open(buf.as_ptr(), OpenFlags::READ | OpenFlags::WRITE, 0)
+        });
+    match slave {
+        Ok(slave) => Ok((master, slave)),
+        Err(err) => {
+            let _ = syscall::close(master);
+            Err(err)
+        }
+    }
+}
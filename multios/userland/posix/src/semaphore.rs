@@ -0,0 +1,333 @@
+//! POSIX semaphore.h Compatibility
+//!
+//! This module provides counting semaphores for MultiOS:
+//! - unnamed semaphores (sem_init()) living in ordinary or shared memory
+//! - named semaphores (sem_open()) stored as small objects in /dev/shm and
+//!   mapped into every process that opens them
+//!
+//! Waiters block on the count word with a futex; the futex is private unless
+//! the semaphore is shared between processes. An all-zero `sem_t` is a valid
+//! process-shared semaphore with a count of zero, which lets sem_open()
+//! publish a new semaphore without a window where it is half initialized.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+use core::ptr;
+use spin::Mutex;
+use std::collections::BTreeMap;
+
+use crate::errors::*;
+use crate::internal::*;
+use crate::pthread::time_until;
+use crate::shm::shm_path;
+use crate::syscall;
+use crate::types::*;
+
+/// Largest value a semaphore can hold
+pub const SEM_VALUE_MAX: u32 = i32::MAX as u32;
+
+/// Semaphore
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct sem_t {
+    /// Current count
+    value: AtomicU32,
+    /// Threads blocked in sem_wait()
+    waiters: AtomicU32,
+    /// Non-zero when only threads of one process use the semaphore
+    private: u32,
+    _reserved: u32,
+}
+
+/// Named semaphores mapped by this process, by path, with their open count
+static NAMED: Mutex<BTreeMap<String, (usize, u32)>> = Mutex::new(BTreeMap::new());
+
+/// Attempts to see a freshly created named semaphore reach its full size
+const SEM_OPEN_RETRIES: u32 = 100;
+
+impl sem_t {
+    fn futex_flags(&self) -> i32 {
+        if self.private != 0 { FUTEX_PRIVATE_FLAG } else { 0 }
+    }
+
+    /// Take one unit if the count is positive
+    fn try_acquire(&self) -> bool {
+        let mut value = self.value.load(Ordering::Relaxed);
+        while value > 0 {
+            match self.value.compare_exchange_weak(value, value - 1, Ordering::Acquire, Ordering::Relaxed) {
+                Ok(_) => return true,
+                Err(current) => value = current,
+            }
+        }
+        false
+    }
+
+    /// Add `count` units and wake as many waiters
+    fn release(&self, count: u32) -> PosixResult<()> {
+        let mut value = self.value.load(Ordering::Relaxed);
+        loop {
+            if value > SEM_VALUE_MAX - count {
+                return Err(Errno::Eoverflow);
+            }
+            match self.value.compare_exchange_weak(value, value + count, Ordering::Release, Ordering::Relaxed) {
+                Ok(_) => break,
+                Err(current) => value = current,
+            }
+        }
+        if self.waiters.load(Ordering::SeqCst) > 0 {
+            syscall::futex(self.value.as_ptr(), FUTEX_WAKE | self.futex_flags(), count, ptr::null(), ptr::null(), 0)?;
+        }
+        Ok(())
+    }
+
+    /// Block until a unit is available or the absolute `deadline` on `clock`
+    /// passes
+    fn acquire(&self, deadline: Option<(clockid_t, &timespec)>) -> PosixResult<()> {
+        loop {
+            if self.try_acquire() {
+                return Ok(());
+            }
+            let timeout = match deadline {
+                Some((clock, abstime)) => Some(time_until(clock, abstime)?),
+                None => None,
+            };
+            let timeout_ptr = timeout.as_ref().map_or(ptr::null(), |timeout| timeout as *const timespec);
+
+            self.waiters.fetch_add(1, Ordering::SeqCst);
+            let result = syscall::futex(self.value.as_ptr(), FUTEX_WAIT | self.futex_flags(), 0, timeout_ptr, ptr::null(), 0);
+            self.waiters.fetch_sub(1, Ordering::SeqCst);
+            match result {
+                // A post raced with us going to sleep; retry
+                Ok(_) | Err(Errno::Eagain) => {}
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+/// Initialize an unnamed semaphore
+///
+/// This function provides compatibility with the POSIX sem_init() function.
+///
+/// # Arguments
+/// * `sem` - Semaphore to initialize
+/// * `pshared` - Non-zero if the semaphore lives in memory shared between processes
+/// * `value` - Initial count
+///
+/// # Returns
+/// * `PosixResult<()>` - Success, `Einval` if `value` exceeds SEM_VALUE_MAX
+pub fn sem_init(sem: &mut sem_t, pshared: i32, value: u32) -> PosixResult<()> {
+    if value > SEM_VALUE_MAX {
+        return Err(Errno::Einval);
+    }
+    *sem = sem_t {
+        value: AtomicU32::new(value),
+        waiters: AtomicU32::new(0),
+        private: (pshared == 0) as u32,
+        _reserved: 0,
+    };
+    Ok(())
+}
+
+/// Destroy an unnamed semaphore
+///
+/// This function provides compatibility with the POSIX sem_destroy() function.
+///
+/// # Returns
+/// * `PosixResult<()>` - Success, `Ebusy` if threads are blocked on it
+pub fn sem_destroy(sem: &mut sem_t) -> PosixResult<()> {
+    if sem.waiters.load(Ordering::SeqCst) > 0 {
+        return Err(Errno::Ebusy);
+    }
+    Ok(())
+}
+
+/// Increment a semaphore, waking one waiter
+///
+/// This function provides compatibility with the POSIX sem_post() function.
+/// It is async-signal-safe.
+///
+/// # Returns
+/// * `PosixResult<()>` - Success, `Eoverflow` at SEM_VALUE_MAX
+pub fn sem_post(sem: &sem_t) -> PosixResult<()> {
+    sem.release(1)
+}
+
+/// Decrement a semaphore, blocking while it is zero
+///
+/// This function provides compatibility with the POSIX sem_wait() function.
+///
+/// # Returns
+/// * `PosixResult<()>` - Success, `Eintr` if a signal interrupted the wait
+pub fn sem_wait(sem: &sem_t) -> PosixResult<()> {
+    sem.acquire(None)
+}
+
+/// Decrement a semaphore without blocking
+///
+/// This function provides compatibility with the POSIX sem_trywait() function.
+///
+/// # Returns
+/// * `PosixResult<()>` - Success, `Eagain` if the count is zero
+pub fn sem_trywait(sem: &sem_t) -> PosixResult<()> {
+    if sem.try_acquire() { Ok(()) } else { Err(Errno::Eagain) }
+}
+
+/// Decrement a semaphore, giving up at an absolute CLOCK_REALTIME time
+///
+/// This function provides compatibility with the POSIX sem_timedwait() function.
+///
+/// # Returns
+/// * `PosixResult<()>` - Success, `Etimedout` once `abstime` passes
+pub fn sem_timedwait(sem: &sem_t, abstime: &timespec) -> PosixResult<()> {
+    sem_clockwait(sem, CLOCK_REALTIME, abstime)
+}
+
+/// Decrement a semaphore, giving up at an absolute time on `clock`
+///
+/// This function provides compatibility with the POSIX sem_clockwait() function.
+///
+/// # Returns
+/// * `PosixResult<()>` - Success, `Etimedout` once `abstime` passes
+pub fn sem_clockwait(sem: &sem_t, clock: clockid_t, abstime: &timespec) -> PosixResult<()> {
+    if clock != CLOCK_REALTIME && clock != CLOCK_MONOTONIC {
+        return Err(Errno::Einval);
+    }
+    sem.acquire(Some((clock, abstime)))
+}
+
+/// Read the current count of a semaphore
+///
+/// This function provides compatibility with the POSIX sem_getvalue() function.
+pub fn sem_getvalue(sem: &sem_t) -> i32 {
+    sem.value.load(Ordering::Relaxed) as i32
+}
+
+/// Open or create a named semaphore
+///
+/// This function provides compatibility with the POSIX sem_open() function.
+/// Opening the same name twice in a process returns the same semaphore.
+///
+/// # Arguments
+/// * `name` - Semaphore name of the form "/name"
+/// * `oflag` - Empty, CREAT, or CREAT | EXCL
+/// * `mode` - Permissions of a newly created semaphore
+/// * `value` - Initial count of a newly created semaphore
+///
+/// # Returns
+/// * `PosixResult<&'static sem_t>` - The semaphore, valid until sem_close()
+pub fn sem_open(name: &str, oflag: OpenFlags, mode: mode_t, value: u32) -> PosixResult<&'static sem_t> {
+    if value > SEM_VALUE_MAX || !(OpenFlags::CREAT | OpenFlags::EXCL).contains(oflag) {
+        return Err(Errno::Einval);
+    }
+    let path = shm_path(&sem_shm_name(name)?)?;
+
+    let mut named = NAMED.lock();
+    if let Some((addr, opens)) = named.get_mut(&path) {
+        if oflag.contains(OpenFlags::CREAT | OpenFlags::EXCL) {
+            return Err(Errno::Eexist);
+        }
+        *opens += 1;
+        return Ok(unsafe { &*(*addr as *const sem_t) });
+    }
+
+    let mut cpath = vec![0u8; path.len() + 1];
+    cpath[..path.len()].copy_from_slice(path.as_bytes());
+    let access = OpenFlags::READ | OpenFlags::WRITE | OpenFlags::NOFOLLOW;
+
+    // Try to create the semaphore first so exactly one opener initializes it
+    let (fd, created) = if oflag.contains(OpenFlags::CREAT) {
+        match syscall::open(cpath.as_ptr(), access | OpenFlags::CREAT | OpenFlags::EXCL, mode) {
+            Ok(fd) => (fd, true),
+            Err(Errno::Eexist) if !oflag.contains(OpenFlags::EXCL) => (syscall::open(cpath.as_ptr(), access, 0)?, false),
+            Err(err) => return Err(err),
+        }
+    } else {
+        (syscall::open(cpath.as_ptr(), access, 0)?, false)
+    };
+
+    let mapped = map_semaphore(fd, created);
+    let _ = syscall::close(fd);
+    let addr = match mapped {
+        Ok(addr) => addr,
+        Err(err) => {
+            if created {
+                let _ = crate::dirent::unlinkat(crate::dirent::AT_FDCWD, &path, 0);
+            }
+            return Err(err);
+        }
+    };
+
+    let sem = unsafe { &*(addr as *const sem_t) };
+    if created && value > 0 {
+        // Openers may already be using the zero-filled semaphore, so the
+        // initial count is added rather than stored
+        sem.release(value)?;
+    }
+    named.insert(path, (addr, 1));
+    Ok(sem)
+}
+
+/// Name of the /dev/shm object behind semaphore `name`
+fn sem_shm_name(name: &str) -> PosixResult<String> {
+    let component = name.strip_prefix('/').ok_or(Errno::Einval)?;
+    Ok(format!("/sem.{}", component))
+}
+
+/// Size and map the object behind a named semaphore
+fn map_semaphore(fd: fd_t, created: bool) -> PosixResult<usize> {
+    let size = core::mem::size_of::<sem_t>();
+    if created {
+        syscall::ftruncate(fd, size as off_t)?;
+    } else {
+        // The creator may not have sized the object yet
+        let mut attempts = 0;
+        loop {
+            let mut st = Stat::default();
+            syscall::fstat(fd, &mut st)?;
+            if st.st_size as usize >= size {
+                break;
+            }
+            attempts += 1;
+            if attempts == SEM_OPEN_RETRIES {
+                return Err(Errno::Eagain);
+            }
+            let pause = timespec { tv_sec: 0, tv_nsec: 10_000 };
+            crate::timer::clock_nanosleep(CLOCK_MONOTONIC, 0, &pause, None)?;
+        }
+    }
+    syscall::mmap(0, size, PROT_READ | PROT_WRITE, MAP_SHARED, fd, 0)
+}
+
+/// Close a named semaphore
+///
+/// This function provides compatibility with the POSIX sem_close() function.
+/// The mapping is removed once every sem_open() of it has been closed.
+///
+/// # Returns
+/// * `PosixResult<()>` - Success, `Einval` if `sem` is not a named semaphore
+pub fn sem_close(sem: &'static sem_t) -> PosixResult<()> {
+    let addr = sem as *const sem_t as usize;
+    let mut named = NAMED.lock();
+    let path = named.iter()
+        .find(|(_, entry)| entry.0 == addr)
+        .map(|(path, _)| path.clone())
+        .ok_or(Errno::Einval)?;
+    let (_, opens) = named.get_mut(&path).expect("entry exists");
+    *opens -= 1;
+    if *opens == 0 {
+        named.remove(&path);
+        syscall::munmap(addr, core::mem::size_of::<sem_t>())?;
+    }
+    Ok(())
+}
+
+/// Remove a named semaphore
+///
+/// This function provides compatibility with the POSIX sem_unlink() function.
+/// Processes that have it open keep using it until they close it.
+///
+/// # Returns
+/// * `PosixResult<()>` - Success, error on failure
+pub fn sem_unlink(name: &str) -> PosixResult<()> {
+    crate::shm::shm_unlink(&sem_shm_name(name)?)
+}
@@ -0,0 +1,347 @@
+//! POSIX Shared Memory for MultiOS
+//!
+//! This module provides POSIX shared memory objects:
+//! - `ShmTable`, the memory-backed filesystem mounted on /dev/shm: named
+//!   objects whose pages are allocated on first touch, stay resident while
+//!   the object is linked, open or mapped, and are shared by every mapping
+//! - shm_open()/shm_unlink() for user programs
+//!
+//! Named semaphores (semaphore.rs) are small objects in the same namespace.
+
+use std::collections::BTreeMap;
+
+use crate::errors::*;
+use crate::internal::{Stat, S_IFREG};
+use crate::stdio::NAME_MAX;
+use crate::syscall;
+use crate::types::*;
+
+/// Mount point of the shared memory filesystem
+pub const SHM_DIR: &str = "/dev/shm";
+
+/// Page size of shared memory objects
+pub const SHM_PAGE_SIZE: usize = 4096;
+
+/// Largest size an object can be truncated to
+pub const SHM_MAX_SIZE: u64 = 1 << 40;
+
+/// Identifier of an object inside the table
+pub type ShmId = u32;
+
+/// One shared memory object
+#[derive(Debug, Clone)]
+pub struct ShmObject {
+    pub name: Vec<u8>,
+    pub size: u64,
+    pub mode: mode_t,
+    pub uid: uid_t,
+    pub gid: gid_t,
+    /// Still reachable by name (not yet shm_unlink()ed)
+    pub linked: bool,
+    pub opens: u32,
+    pub mappings: u32,
+    /// Resident pages by page index; missing pages read as zeros
+    pages: BTreeMap<u64, Box<[u8]>>,
+}
+
+impl ShmObject {
+    fn in_use(&self) -> bool {
+        self.linked || self.opens > 0 || self.mappings > 0
+    }
+
+    /// Whether `uid`/`gid` may open the object with the requested access
+    fn permits(&self, uid: uid_t, gid: gid_t, read: bool, write: bool) -> bool {
+        if uid == 0 {
+            return true;
+        }
+        let shift = if uid == self.uid { 6 } else if gid == self.gid { 3 } else { 0 };
+        let granted = (self.mode >> shift) & 0o7;
+        (!read || granted & 0o4 != 0) && (!write || granted & 0o2 != 0)
+    }
+}
+
+/// Shared memory statistics
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ShmStats {
+    pub objects_created: u64,
+    pub objects_freed: u64,
+    pub pages_allocated: u64,
+    pub pages_freed: u64,
+}
+
+/// The shared memory filesystem
+#[derive(Debug, Default)]
+pub struct ShmTable {
+    objects: BTreeMap<ShmId, ShmObject>,
+    names: BTreeMap<Vec<u8>, ShmId>,
+    next_id: ShmId,
+    stats: ShmStats,
+}
+
+impl ShmTable {
+    pub fn new() -> Self {
+        Self { next_id: 1, ..Self::default() }
+    }
+
+    pub fn get(&self, id: ShmId) -> Option<&ShmObject> {
+        self.objects.get(&id)
+    }
+
+    fn object_mut(&mut self, id: ShmId) -> PosixResult<&mut ShmObject> {
+        self.objects.get_mut(&id).ok_or(Errno::Ebadf)
+    }
+
+    fn check_name(name: &[u8]) -> PosixResult<()> {
+        if name.is_empty() || name == b"." || name == b".." || name.contains(&b'/') || name.contains(&0) {
+            return Err(Errno::Einval);
+        }
+        if name.len() > NAME_MAX {
+            return Err(Errno::Enametoolong);
+        }
+        Ok(())
+    }
+
+    /// Open or create `name` (open under /dev/shm)
+    ///
+    /// `mode` is the permission mode after the caller's umask.
+    pub fn open(&mut self, name: &[u8], flags: OpenFlags, mode: mode_t, uid: uid_t, gid: gid_t) -> PosixResult<ShmId> {
+        Self::check_name(name)?;
+        let write = flags.contains(OpenFlags::WRITE);
+        let id = match self.names.get(name) {
+            Some(_) if flags.contains(OpenFlags::CREAT | OpenFlags::EXCL) => return Err(Errno::Eexist),
+            Some(&id) => {
+                let object = self.object_mut(id)?;
+                if !object.permits(uid, gid, true, write) {
+                    return Err(Errno::Eaccess);
+                }
+                id
+            }
+            None if flags.contains(OpenFlags::CREAT) => {
+                let id = self.next_id;
+                self.next_id = self.next_id.wrapping_add(1).max(1);
+                self.objects.insert(id, ShmObject {
+                    name: name.to_vec(),
+                    size: 0,
+                    mode: mode & 0o7777,
+                    uid,
+                    gid,
+                    linked: true,
+                    opens: 0,
+                    mappings: 0,
+                    pages: BTreeMap::new(),
+                });
+                self.names.insert(name.to_vec(), id);
+                self.stats.objects_created += 1;
+                id
+            }
+            None => return Err(Errno::Enoent),
+        };
+
+        if flags.contains(OpenFlags::TRUNC) {
+            if !write {
+                return Err(Errno::Eaccess);
+            }
+            self.truncate(id, 0)?;
+        }
+        self.object_mut(id)?.opens += 1;
+        Ok(id)
+    }
+
+    /// Set the size of an object (ftruncate())
+    ///
+    /// Pages past the new end are freed and the tail of the last page is
+    /// cleared, so growing the object again exposes zeros.
+    pub fn truncate(&mut self, id: ShmId, size: u64) -> PosixResult<()> {
+        if size > SHM_MAX_SIZE {
+            return Err(Errno::Efbig);
+        }
+        let object = self.object_mut(id)?;
+        let page_size = SHM_PAGE_SIZE as u64;
+        let kept = (size + page_size - 1) / page_size;
+        let freed = object.pages.split_off(&kept).len() as u64;
+        if size % page_size != 0 {
+            if let Some(page) = object.pages.get_mut(&(size / page_size)) {
+                page[(size % page_size) as usize..].fill(0);
+            }
+        }
+        object.size = size;
+        self.stats.pages_freed += freed;
+        Ok(())
+    }
+
+    /// Resolve a page fault on a mapping of `id`
+    ///
+    /// Returns the page holding `offset`, allocating it on first touch.
+    /// Offsets past the end of the object fail with `Ebadaddr`; the caller
+    /// raises SIGBUS.
+    pub fn fault(&mut self, id: ShmId, offset: u64) -> PosixResult<&mut [u8]> {
+        let object = self.objects.get_mut(&id).ok_or(Errno::Ebadf)?;
+        if offset >= object.size {
+            return Err(Errno::Ebadaddr);
+        }
+        let index = offset / SHM_PAGE_SIZE as u64;
+        if !object.pages.contains_key(&index) {
+            object.pages.insert(index, vec![0u8; SHM_PAGE_SIZE].into_boxed_slice());
+            self.stats.pages_allocated += 1;
+        }
+        Ok(&mut object.pages.get_mut(&index).expect("page was just inserted")[..])
+    }
+
+    /// Read from an object (read()/pread() on its descriptor)
+    pub fn read_at(&self, id: ShmId, offset: u64, buf: &mut [u8]) -> PosixResult<usize> {
+        let object = self.objects.get(&id).ok_or(Errno::Ebadf)?;
+        let count = (object.size.saturating_sub(offset)).min(buf.len() as u64) as usize;
+        let mut done = 0;
+        while done < count {
+            let position = offset + done as u64;
+            let index = position / SHM_PAGE_SIZE as u64;
+            let within = (position % SHM_PAGE_SIZE as u64) as usize;
+            let length = (SHM_PAGE_SIZE - within).min(count - done);
+            match object.pages.get(&index) {
+                Some(page) => buf[done..done + length].copy_from_slice(&page[within..within + length]),
+                None => buf[done..done + length].fill(0),
+            }
+            done += length;
+        }
+        Ok(count)
+    }
+
+    /// Write to an object (write()/pwrite()), growing it as needed
+    pub fn write_at(&mut self, id: ShmId, offset: u64, data: &[u8]) -> PosixResult<usize> {
+        let end = offset.checked_add(data.len() as u64).ok_or(Errno::Efbig)?;
+        if end > SHM_MAX_SIZE {
+            return Err(Errno::Efbig);
+        }
+        let object = self.object_mut(id)?;
+        object.size = object.size.max(end);
+        let mut done = 0;
+        while done < data.len() {
+            let position = offset + done as u64;
+            let within = (position % SHM_PAGE_SIZE as u64) as usize;
+            let length = (SHM_PAGE_SIZE - within).min(data.len() - done);
+            let page = self.fault(id, position)?;
+            page[within..within + length].copy_from_slice(&data[done..done + length]);
+            done += length;
+        }
+        Ok(data.len())
+    }
+
+    /// Account for a new mapping of the object (mmap())
+    pub fn map(&mut self, id: ShmId) -> PosixResult<()> {
+        self.object_mut(id)?.mappings += 1;
+        Ok(())
+    }
+
+    /// A mapping of the object went away (munmap(), exit)
+    pub fn unmap(&mut self, id: ShmId) -> PosixResult<()> {
+        let object = self.object_mut(id)?;
+        object.mappings = object.mappings.saturating_sub(1);
+        self.release_if_unused(id);
+        Ok(())
+    }
+
+    /// Close one descriptor of the object
+    pub fn close(&mut self, id: ShmId) -> PosixResult<()> {
+        let object = self.object_mut(id)?;
+        object.opens = object.opens.saturating_sub(1);
+        self.release_if_unused(id);
+        Ok(())
+    }
+
+    /// Remove a name (shm_unlink()); the memory lives on while it is open
+    /// or mapped
+    ///
+    /// /dev/shm is sticky: only the owner or root may remove an object.
+    pub fn unlink(&mut self, name: &[u8], uid: uid_t) -> PosixResult<()> {
+        Self::check_name(name)?;
+        let id = *self.names.get(name).ok_or(Errno::Enoent)?;
+        let object = self.object_mut(id)?;
+        if uid != 0 && uid != object.uid {
+            return Err(Errno::Epperm);
+        }
+        object.linked = false;
+        self.names.remove(name);
+        self.release_if_unused(id);
+        Ok(())
+    }
+
+    fn release_if_unused(&mut self, id: ShmId) {
+        if self.objects.get(&id).map_or(false, |object| !object.in_use()) {
+            let object = self.objects.remove(&id).expect("object exists");
+            self.stats.pages_freed += object.pages.len() as u64;
+            self.stats.objects_freed += 1;
+        }
+    }
+
+    /// Status of an object (fstat())
+    pub fn stat(&self, id: ShmId) -> PosixResult<Stat> {
+        let object = self.objects.get(&id).ok_or(Errno::Ebadf)?;
+        Ok(Stat {
+            st_ino: id as _,
+            st_mode: S_IFREG | object.mode,
+            st_nlink: object.linked as _,
+            st_uid: object.uid,
+            st_gid: object.gid,
+            st_size: object.size as _,
+            st_blksize: SHM_PAGE_SIZE as _,
+            st_blocks: (object.pages.len() * SHM_PAGE_SIZE / 512) as _,
+            ..Stat::default()
+        })
+    }
+
+    /// Pages currently allocated across all objects
+    pub fn resident_pages(&self) -> usize {
+        self.objects.values().map(|object| object.pages.len()).sum()
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    pub fn get_stats(&self) -> ShmStats {
+        self.stats
+    }
+}
+
+/// Path under /dev/shm for a POSIX IPC name ("/name")
+pub(crate) fn shm_path(name: &str) -> PosixResult<String> {
+    let component = name.strip_prefix('/').ok_or(Errno::Einval)?;
+    ShmTable::check_name(component.as_bytes())?;
+    Ok(format!("{}/{}", SHM_DIR, component))
+}
+
+/// Open a shared memory object
+///
+/// This function provides compatibility with the POSIX shm_open() function.
+/// The descriptor is sized with ftruncate() and mapped with mmap(MAP_SHARED).
+///
+/// # Arguments
+/// * `name` - Object name of the form "/name"
+/// * `oflag` - READ or READ | WRITE, optionally CREAT, EXCL, TRUNC
+/// * `mode` - Permissions of a newly created object
+///
+/// # Returns
+/// * `PosixResult<fd_t>` - Descriptor of the object, error on failure
+pub fn shm_open(name: &str, oflag: OpenFlags, mode: mode_t) -> PosixResult<fd_t> {
+    let path = shm_path(name)?;
+    let mut buf = vec![0u8; path.len() + 1];
+    buf[..path.len()].copy_from_slice(path.as_bytes());
+    syscall::open(buf.as_ptr(), oflag | OpenFlags::NOFOLLOW, mode)
+}
+
+/// Remove a shared memory object name
+///
+/// This function provides compatibility with the POSIX shm_unlink() function.
+///
+/// # Arguments
+/// * `name` - Object name of the form "/name"
+///
+/// # Returns
+/// * `PosixResult<()>` - Success, error on failure
+pub fn shm_unlink(name: &str) -> PosixResult<()> {
+    crate::dirent::unlinkat(crate::dirent::AT_FDCWD, &shm_path(name)?, 0)
+}
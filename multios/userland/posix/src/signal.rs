@@ -46,6 +46,7 @@ pub struct siginfo {
     pub si_timer2: i32,              // Timer ID (for real-time signals)
     pub si_uid: uid_t,               // Real user ID
     pub si_pid: pid_t,               // Real process ID
+    pub si_value: u64,               // Signal value (for sigqueue)
 }
 
 /// Signal stack structure
@@ -210,9 +211,9 @@ pub fn sigismember(set: &sigset_t, signo: i32) -> PosixResult<bool> {
 /// # Returns
 /// * `PosixResult<()>` - Success on sigaction, error on failure
 pub fn sigaction(signo: i32, act: Option<&sigaction>, oldact: Option<&mut sigaction>) -> PosixResult<()> {
-    // In a real implementation, this would call syscall::rt_sigaction
-    // For now, return not implemented
-    Err(Errno::Enosys)
+    let act_ptr = act.map_or(core::ptr::null(), |act| act as *const sigaction);
+    let oldact_ptr = oldact.map_or(core::ptr::null_mut(), |oldact| oldact as *mut sigaction);
+    syscall::rt_sigaction(signo, act_ptr, oldact_ptr)
 }
 
 /// Get or change signal mask
@@ -227,9 +228,9 @@ pub fn sigaction(signo: i32, act: Option<&sigaction>, oldact: Option<&mut sigact
 /// # Returns
 /// * `PosixResult<()>` - Success on sigprocmask, error on failure
 pub fn sigprocmask(how: i32, set: Option<&sigset_t>, oldset: Option<&mut sigset_t>) -> PosixResult<()> {
-    // In a real implementation, this would call syscall::rt_sigprocmask
-    // For now, return not implemented
-    Err(Errno::Enosys)
+    let set_ptr = set.map_or(core::ptr::null(), |set| set as *const sigset_t);
+    let oldset_ptr = oldset.map_or(core::ptr::null_mut(), |oldset| oldset as *mut sigset_t);
+    syscall::rt_sigprocmask(how, set_ptr, oldset_ptr)
 }
 
 /// Examine pending signals
@@ -242,9 +243,7 @@ pub fn sigprocmask(how: i32, set: Option<&sigset_t>, oldset: Option<&mut sigset_
 /// # Returns
 /// * `PosixResult<()>` - Success on sigpending, error on failure
 pub fn sigpending(set: &mut sigset_t) -> PosixResult<()> {
-    // In a real implementation, this would call syscall::rt_sigpending
-    // For now, return not implemented
-    Err(Errno::Enosys)
+    syscall::rt_sigpending(set as *mut sigset_t)
 }
 
 /// Wait for a signal
@@ -257,9 +256,12 @@ pub fn sigpending(set: &mut sigset_t) -> PosixResult<()> {
 /// # Returns
 /// * `PosixResult<()>` - Success on sigsuspend, error on failure
 pub fn sigsuspend(sigmask: Option<&sigset_t>) -> PosixResult<()> {
-    // In a real implementation, this would call syscall::rt_sigsuspend
-    // For now, return not implemented
-    Err(Errno::Enosys)
+    let mask = sigmask.copied().unwrap_or(0);
+    // Only returns once a handler has run, always with EINTR
+    match syscall::rt_sigsuspend(&mask as *const sigset_t) {
+        Err(Errno::Eintr) => Ok(()),
+        other => other,
+    }
 }
 
 /// Wait for a signal with timeout
@@ -274,9 +276,9 @@ pub fn sigsuspend(sigmask: Option<&sigset_t>) -> PosixResult<()> {
 /// # Returns
 /// * `PosixResult<i32>` - Signal number received, error on failure
 pub fn sigtimedwait(set: &sigset_t, info: Option<&mut siginfo>, timeout: Option<&timespec>) -> PosixResult<i32> {
-    // In a real implementation, this would call syscall::rt_sigtimedwait
-    // For now, return not implemented
-    Err(Errno::Enosys)
+    let info_ptr = info.map_or(core::ptr::null_mut(), |info| info as *mut siginfo);
+    let timeout_ptr = timeout.map_or(core::ptr::null(), |timeout| timeout as *const timespec);
+    syscall::rt_sigtimedwait(set as *const sigset_t, info_ptr, timeout_ptr)
 }
 
 /// Send a signal to a thread
@@ -292,9 +294,15 @@ pub fn sigtimedwait(set: &sigset_t, info: Option<&mut siginfo>, timeout: Option<
 /// # Returns
 /// * `PosixResult<()>` - Success on sigqueue, error on failure
 pub fn sigqueue(pid: pid_t, tid: pid_t, signo: i32, value: u64) -> PosixResult<()> {
-    // In a real implementation, this would call syscall::rt_sigqueueinfo
-    // For now, return not implemented
-    Err(Errno::Enosys)
+    if !utils::is_valid_signal(signo) {
+        return Err(Errno::Einval);
+    }
+    let mut info: siginfo = unsafe { core::mem::zeroed() };
+    info.si_signo = signo;
+    info.si_code = SI_QUEUE;
+    info.si_pid = crate::unistd::getpid();
+    info.si_value = value;
+    syscall::rt_sigqueueinfo(pid, tid, signo, &info as *const siginfo)
 }
 
 /// Set up alternative signal stack
@@ -342,21 +350,29 @@ pub fn signal(signo: i32, handler: sighandler_t) -> PosixResult<sighandler_t> {
     Ok(oldact.sa_handler)
 }
 
-/// Set up real-time signal handling
+/// Create a per-process interval timer
 /// 
-/// This function provides compatibility with the POSIX sigevent() function.
+/// This function provides compatibility with the POSIX timer_create() function.
+/// Expirations are reported with SIGEV_SIGNAL, SIGEV_THREAD_ID or SIGEV_NONE;
+/// SIGEV_THREAD is not supported.
 /// 
 /// # Arguments
-/// * `ev` - Pointer to sigevent structure
-/// * `timerid` - Pointer to timer ID
-/// * `osev` - Pointer to old sigevent structure (NULL to ignore)
+/// * `clockid` - Clock to measure against (CLOCK_REALTIME, CLOCK_MONOTONIC)
+/// * `ev` - How to notify the process on expiry
+/// * `timerid` - Pointer to store the new timer ID
 /// 
 /// # Returns
-/// * `PosixResult<()>` - Success on sigevent, error on failure
+/// * `PosixResult<()>` - Success on timer create, error on failure
 pub fn timer_create(clockid: clockid_t, ev: &sigevent, timerid: *mut timer_t) -> PosixResult<()> {
-    // In a real implementation, this would call syscall::timer_create
-    // For now, return not implemented
-    Err(Errno::Enosys)
+    if timerid.is_null() {
+        return Err(Errno::Einval);
+    }
+    
+    match ev.sigev_notify {
+        SIGEV_SIGNAL | SIGEV_NONE | SIGEV_THREAD_ID => syscall::timer_create(clockid, ev, timerid),
+        SIGEV_THREAD => Err(Errno::Eopnotsupp),
+        _ => Err(Errno::Einval),
+    }
 }
 
 /// Delete a timer
@@ -369,9 +385,7 @@ pub fn timer_create(clockid: clockid_t, ev: &sigevent, timerid: *mut timer_t) ->
 /// # Returns
 /// * `PosixResult<()>` - Success on timer delete, error on failure
 pub fn timer_delete(timerid: timer_t) -> PosixResult<()> {
-    // In a real implementation, this would call syscall::timer_delete
-    // For now, return not implemented
-    Err(Errno::Enosys)
+    syscall::timer_delete(timerid)
 }
 
 /// Get timer overruns
@@ -382,11 +396,9 @@ pub fn timer_delete(timerid: timer_t) -> PosixResult<()> {
 /// * `timerid` - Timer ID
 /// 
 /// # Returns
-/// * `PosixResult<i32>` - Number of overruns, error on failure
+/// * `PosixResult<i32>` - Expirations missed while the last signal was pending
 pub fn timer_getoverrun(timerid: timer_t) -> PosixResult<i32> {
-    // In a real implementation, this would call syscall::timer_getoverrun
-    // For now, return not implemented
-    Err(Errno::Enosys)
+    syscall::timer_getoverrun(timerid)
 }
 
 /// Get timer expiration
@@ -400,9 +412,7 @@ pub fn timer_getoverrun(timerid: timer_t) -> PosixResult<i32> {
 /// # Returns
 /// * `PosixResult<()>` - Success on timer gettime, error on failure
 pub fn timer_gettime(timerid: timer_t, value: &mut itimerspec) -> PosixResult<()> {
-    // In a real implementation, this would call syscall::timer_gettime
-    // For now, return not implemented
-    Err(Errno::Enosys)
+    syscall::timer_gettime(timerid, value)
 }
 
 /// Set timer expiration
@@ -411,16 +421,19 @@ pub fn timer_gettime(timerid: timer_t, value: &mut itimerspec) -> PosixResult<()
 /// 
 /// # Arguments
 /// * `timerid` - Timer ID
-/// * `flags` - Timer flags
+/// * `flags` - Timer flags (TIMER_ABSTIME)
 /// * `value` - Pointer to itimerspec structure
 /// * `ovalue` - Pointer to old itimerspec structure (NULL to ignore)
 /// 
 /// # Returns
 /// * `PosixResult<()>` - Success on timer settime, error on failure
 pub fn timer_settime(timerid: timer_t, flags: i32, value: &itimerspec, ovalue: Option<&mut itimerspec>) -> PosixResult<()> {
-    // In a real implementation, this would call syscall::timer_settime
-    // For now, return not implemented
-    Err(Errno::Enosys)
+    if flags & !crate::timer::TIMER_ABSTIME != 0 {
+        return Err(Errno::Einval);
+    }
+    
+    let ovalue = ovalue.map_or(core::ptr::null_mut(), |old| old as *mut itimerspec);
+    syscall::timer_settime(timerid, flags, value, ovalue)
 }
 
 /// Advanced signal handling utilities
@@ -559,6 +572,43 @@ pub const SIG_IGN: sighandler_t = 1;
 /// Error indicator for signal handler setup
 pub const SIG_ERR: sighandler_t = !0;
 
+/// Standard signal numbers
+pub const SIGHUP: i32 = 1;
+pub const SIGINT: i32 = 2;
+pub const SIGQUIT: i32 = 3;
+pub const SIGILL: i32 = 4;
+pub const SIGTRAP: i32 = 5;
+pub const SIGABRT: i32 = 6;
+pub const SIGBUS: i32 = 7;
+pub const SIGFPE: i32 = 8;
+pub const SIGKILL: i32 = 9;
+pub const SIGUSR1: i32 = 10;
+pub const SIGSEGV: i32 = 11;
+pub const SIGUSR2: i32 = 12;
+pub const SIGPIPE: i32 = 13;
+pub const SIGALRM: i32 = 14;
+pub const SIGTERM: i32 = 15;
+pub const SIGSTKFLT: i32 = 16;
+pub const SIGCHLD: i32 = 17;
+pub const SIGCONT: i32 = 18;
+pub const SIGSTOP: i32 = 19;
+pub const SIGTSTP: i32 = 20;
+pub const SIGTTIN: i32 = 21;
+pub const SIGTTOU: i32 = 22;
+pub const SIGURG: i32 = 23;
+pub const SIGXCPU: i32 = 24;
+pub const SIGXFSZ: i32 = 25;
+pub const SIGVTALRM: i32 = 26;
+pub const SIGPROF: i32 = 27;
+pub const SIGWINCH: i32 = 28;
+pub const SIGIO: i32 = 29;
+pub const SIGPWR: i32 = 30;
+pub const SIGSYS: i32 = 31;
+
+/// Real-time signal range (queued, delivered lowest-numbered first)
+pub const SIGRTMIN: i32 = 32;
+pub const SIGRTMAX: i32 = 64;
+
 /// Signal mask operations
 pub const SIG_BLOCK: i32 = 0;      // Add signals to current mask
 pub const SIG_UNBLOCK: i32 = 1;    // Remove signals from current mask
@@ -571,11 +621,13 @@ pub const SA_SIGINFO: i32 = 0x00000004;      // Signal handler takes 3 arguments
 pub const SA_ONSTACK: i32 = 0x08000000;      // Use alternate signal stack
 pub const SA_RESTART: i32 = 0x10000000;      // Restart interrupted system calls
 pub const SA_NODEFER: i32 = 0x40000000;      // Don't mask signal during handler
+pub const SA_RESETHAND: i32 = 0x80000000u32 as i32; // Reset to SIG_DFL on delivery
 
 /// Signal delivery methods for timer_create
 pub const SIGEV_SIGNAL: i32 = 0;             // Notify via signal
 pub const SIGEV_NONE: i32 = 1;               // No notification
 pub const SIGEV_THREAD: i32 = 2;             // Notify via thread
+pub const SIGEV_THREAD_ID: i32 = 4;          // Notify a specific thread
 
 /// Signal code values for different types of signals
 pub const SI_USER: i32 = 0;                  // Signal from user process
@@ -583,6 +635,7 @@ pub const SI_KERNEL: i32 = 0x80;             // Signal from kernel
 pub const SI_QUEUE: i32 = -1;                // Signal from sigqueue
 pub const SI_TIMER: i32 = -2;                // Signal from timer
 pub const SI_MESGQ: i32 = -3;                // Signal from message queue
+pub const SI_TKILL: i32 = -6;                // Signal from tkill/pthread_kill
 
 /// Platform-specific signal stack flags
 pub const SS_ONSTACK: i32 = 1;               // Process is on signal stack
@@ -0,0 +1,650 @@
+//! Signal Delivery
+//!
+//! This module implements the kernel side of signal.h for MultiOS:
+//! - Per-process dispositions set through RT_SIGACTION, with SA_SIGINFO,
+//!   SA_NODEFER, SA_RESETHAND and SA_ONSTACK
+//! - Per-thread signal masks and thread-directed pending signals, plus a
+//!   process-wide pending set
+//! - Coalescing standard signals and queued real-time signals
+//!   (SIGRTMIN..=SIGRTMAX), delivered lowest-numbered first
+//! - Default dispositions (terminate, core dump, ignore, stop, continue)
+//! - Signal frame setup on handler entry and teardown on RT_SIGRETURN
+
+use std::collections::BTreeMap;
+
+use crate::errors::*;
+use crate::signal::*;
+use crate::types::*;
+
+/// Maximum queued real-time signals per process (RLIMIT_SIGPENDING)
+pub const RT_QUEUE_LIMIT: usize = 128;
+
+/// Magic value marking a frame built by `setup_frame`
+pub const SIGNAL_FRAME_MAGIC: u32 = 0x5349_4746;
+
+/// Number of supported signals
+const NSIG: usize = SIGRTMAX as usize;
+
+/// Signals that can never be blocked, caught or ignored
+const UNBLOCKABLE: sigset_t = sig_bit(SIGKILL) | sig_bit(SIGSTOP);
+
+/// Stop signals discarded when SIGCONT is generated
+const STOP_SIGNALS: sigset_t = sig_bit(SIGSTOP) | sig_bit(SIGTSTP) | sig_bit(SIGTTIN) | sig_bit(SIGTTOU);
+
+/// Bit of `signo` in a `sigset_t`
+const fn sig_bit(signo: i32) -> sigset_t {
+    1 << (signo - 1)
+}
+
+/// Action taken for a signal left at `SIG_DFL`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DefaultAction {
+    Terminate,
+    CoreDump,
+    Ignore,
+    Stop,
+    Continue,
+}
+
+/// Default action of a signal
+pub fn default_action(signo: i32) -> DefaultAction {
+    match signo {
+        SIGQUIT | SIGILL | SIGTRAP | SIGABRT | SIGBUS | SIGFPE | SIGSEGV
+        | SIGXCPU | SIGXFSZ | SIGSYS => DefaultAction::CoreDump,
+        SIGCHLD | SIGURG | SIGWINCH => DefaultAction::Ignore,
+        SIGSTOP | SIGTSTP | SIGTTIN | SIGTTOU => DefaultAction::Stop,
+        SIGCONT => DefaultAction::Continue,
+        _ => DefaultAction::Terminate,
+    }
+}
+
+/// Whether a signal is a queued real-time signal
+pub fn is_realtime(signo: i32) -> bool {
+    (SIGRTMIN..=SIGRTMAX).contains(&signo)
+}
+
+fn empty_siginfo(signo: i32, code: i32) -> siginfo {
+    // siginfo is plain integers; all-zero is a valid value
+    let mut info: siginfo = unsafe { core::mem::zeroed() };
+    info.si_signo = signo;
+    info.si_code = code;
+    info
+}
+
+fn default_sigaction() -> sigaction {
+    sigaction {
+        sa_handler: SIG_DFL,
+        sa_mask: 0,
+        sa_flags: 0,
+        sa_restorer: 0,
+    }
+}
+
+/// Pending signals of a thread or process.
+///
+/// Standard signals coalesce (one instance, first siginfo wins); real-time
+/// signals queue every instance in order.
+#[derive(Debug, Clone, Default)]
+struct PendingSignals {
+    queue: Vec<siginfo>,
+}
+
+impl PendingSignals {
+    fn set(&self) -> sigset_t {
+        self.queue.iter().fold(0, |set, info| set | sig_bit(info.si_signo))
+    }
+
+    fn push(&mut self, info: siginfo) -> bool {
+        if !is_realtime(info.si_signo) && self.set() & sig_bit(info.si_signo) != 0 {
+            return false;
+        }
+        self.queue.push(info);
+        true
+    }
+
+    fn realtime_count(&self) -> usize {
+        self.queue.iter().filter(|info| is_realtime(info.si_signo)).count()
+    }
+
+    /// Remove the lowest-numbered pending signal in `allowed`
+    fn take(&mut self, allowed: sigset_t) -> Option<siginfo> {
+        let index = self.queue.iter().enumerate()
+            .filter(|(_, info)| allowed & sig_bit(info.si_signo) != 0)
+            .min_by_key(|(index, info)| (info.si_signo, *index))
+            .map(|(index, _)| index)?;
+        Some(self.queue.remove(index))
+    }
+
+    fn discard(&mut self, set: sigset_t) {
+        self.queue.retain(|info| set & sig_bit(info.si_signo) == 0);
+    }
+}
+
+/// Per-thread signal state
+#[derive(Debug, Clone, Default)]
+struct ThreadSignals {
+    mask: sigset_t,
+    pending: PendingSignals,
+    /// Alternate signal stack (base, size)
+    altstack: Option<(usize, usize)>,
+    on_altstack: bool,
+}
+
+/// Frame pushed on the user stack before running a handler.
+///
+/// The handler returns into `restorer`, which issues RT_SIGRETURN with the
+/// frame still on the stack.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct SignalFrame {
+    /// Return address for the handler (`sa_restorer`)
+    pub restorer: usize,
+    pub magic: u32,
+    pub signo: i32,
+    pub info: siginfo,
+    /// Interrupted user context
+    pub context: sigcontext,
+    /// Mask to restore on return
+    pub saved_mask: sigset_t,
+    /// Whether the interrupted code was already on the alternate stack
+    pub was_on_altstack: bool,
+}
+
+/// What the kernel must do for the next deliverable signal
+#[derive(Debug, Clone, Copy)]
+pub enum SignalDelivery {
+    /// Write `frame` at `frame_addr`, then enter `handler` with `args`
+    /// and the stack pointer set to `frame_addr`
+    Handler {
+        frame: SignalFrame,
+        frame_addr: usize,
+        handler: usize,
+        args: [usize; 3],
+    },
+    /// Carry out the signal's default action
+    Default { info: siginfo, action: DefaultAction },
+}
+
+/// Signal delivery statistics
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SignalStats {
+    pub generated: u64,
+    pub ignored: u64,
+    pub coalesced: u64,
+    pub queue_overflows: u64,
+    pub handlers_run: u64,
+    pub default_actions: u64,
+}
+
+/// Signal state of one process and its threads
+#[derive(Debug, Clone)]
+pub struct ProcessSignals {
+    actions: [sigaction; NSIG],
+    shared: PendingSignals,
+    threads: BTreeMap<pid_t, ThreadSignals>,
+    stopped: bool,
+    stats: SignalStats,
+}
+
+impl ProcessSignals {
+    /// Create signal state for a process whose first thread is `tid`
+    pub fn new(tid: pid_t) -> Self {
+        let mut threads = BTreeMap::new();
+        threads.insert(tid, ThreadSignals::default());
+        Self {
+            actions: [default_sigaction(); NSIG],
+            shared: PendingSignals::default(),
+            threads,
+            stopped: false,
+            stats: SignalStats::default(),
+        }
+    }
+
+    /// Register a new thread, inheriting `creator`'s mask
+    pub fn add_thread(&mut self, tid: pid_t, creator: pid_t) {
+        let mask = self.threads.get(&creator).map_or(0, |thread| thread.mask);
+        self.threads.insert(tid, ThreadSignals { mask, ..Default::default() });
+    }
+
+    /// Drop a thread; its directed pending signals are lost
+    pub fn remove_thread(&mut self, tid: pid_t) {
+        self.threads.remove(&tid);
+    }
+
+    /// Whether a stop signal's default action left the process stopped
+    pub fn is_stopped(&self) -> bool {
+        self.stopped
+    }
+
+    /// RT_SIGACTION: examine and change a disposition, returning the old one
+    pub fn rt_sigaction(&mut self, signo: i32, act: Option<&sigaction>) -> PosixResult<sigaction> {
+        if !utils::is_valid_signal(signo) {
+            return Err(Errno::Einval);
+        }
+        let old = self.actions[signo as usize - 1];
+
+        if let Some(act) = act {
+            if UNBLOCKABLE & sig_bit(signo) != 0 {
+                return Err(Errno::Einval);
+            }
+            let mut act = *act;
+            act.sa_mask &= !UNBLOCKABLE;
+            self.actions[signo as usize - 1] = act;
+
+            // Setting a pending signal to be ignored discards it
+            if self.is_ignored(signo) {
+                self.discard_everywhere(sig_bit(signo));
+            }
+        }
+        Ok(old)
+    }
+
+    /// RT_SIGPROCMASK: change a thread's mask, returning the old one
+    pub fn rt_sigprocmask(&mut self, tid: pid_t, how: i32, set: Option<sigset_t>) -> PosixResult<sigset_t> {
+        let thread = self.threads.get_mut(&tid).ok_or(Errno::Esrch)?;
+        let old = thread.mask;
+
+        if let Some(set) = set {
+            let mask = match how {
+                SIG_BLOCK => old | set,
+                SIG_UNBLOCK => old & !set,
+                SIG_SETMASK => set,
+                _ => return Err(Errno::Einval),
+            };
+            thread.mask = mask & !UNBLOCKABLE;
+        }
+        Ok(old)
+    }
+
+    /// RT_SIGPENDING: signals pending for a thread but blocked by its mask
+    pub fn rt_sigpending(&self, tid: pid_t) -> PosixResult<sigset_t> {
+        let thread = self.threads.get(&tid).ok_or(Errno::Esrch)?;
+        Ok((thread.pending.set() | self.shared.set()) & thread.mask)
+    }
+
+    /// Configure a thread's alternate signal stack
+    pub fn sigaltstack(&mut self, tid: pid_t, stack: Option<(usize, usize)>) -> PosixResult<()> {
+        let thread = self.threads.get_mut(&tid).ok_or(Errno::Esrch)?;
+        if thread.on_altstack {
+            return Err(Errno::Epperm);
+        }
+        thread.altstack = stack;
+        Ok(())
+    }
+
+    /// Generate a process-directed signal (kill, sigqueue).
+    ///
+    /// Returns the thread that should be woken to take it, if any.
+    pub fn send(&mut self, info: siginfo) -> PosixResult<Option<pid_t>> {
+        if !self.generate(&info)? {
+            return Ok(None);
+        }
+        if !self.shared.push(info) {
+            self.stats.coalesced += 1;
+            return Ok(None);
+        }
+
+        let bit = sig_bit(info.si_signo);
+        Ok(self.threads.iter()
+            .find(|(_, thread)| thread.mask & bit == 0)
+            .map(|(&tid, _)| tid))
+    }
+
+    /// Generate a thread-directed signal (tgkill, pthread_kill, faults)
+    pub fn send_to_thread(&mut self, tid: pid_t, info: siginfo) -> PosixResult<bool> {
+        if !self.threads.contains_key(&tid) {
+            return Err(Errno::Esrch);
+        }
+        if !self.generate(&info)? {
+            return Ok(false);
+        }
+
+        let thread = self.threads.get_mut(&tid).ok_or(Errno::Esrch)?;
+        if !thread.pending.push(info) {
+            self.stats.coalesced += 1;
+            return Ok(false);
+        }
+        Ok(thread.mask & sig_bit(info.si_signo) == 0)
+    }
+
+    /// Whether a thread has an unblocked pending signal
+    pub fn has_deliverable(&self, tid: pid_t) -> bool {
+        self.threads.get(&tid).map_or(false, |thread| {
+            (thread.pending.set() | self.shared.set()) & !thread.mask != 0
+        })
+    }
+
+    /// Take a pending signal from `set` regardless of the mask (sigtimedwait, sigwaitinfo)
+    pub fn take_waited(&mut self, tid: pid_t, set: sigset_t) -> Option<siginfo> {
+        let thread = self.threads.get_mut(&tid)?;
+        thread.pending.take(set).or_else(|| self.shared.take(set))
+    }
+
+    /// Dequeue the next deliverable signal for a thread and prepare its delivery.
+    ///
+    /// Ignored signals are skipped. Handlers get a `SignalFrame` below
+    /// `user_sp` (or on the alternate stack for SA_ONSTACK) and run with
+    /// their `sa_mask` plus the signal itself blocked. If the frame does not
+    /// fit on that stack the signal is lost and the thread gets a SIGSEGV
+    /// it cannot catch instead. Fails with `Esrch` if `tid` has exited.
+    pub fn deliver(&mut self, tid: pid_t, context: &sigcontext, user_sp: usize) -> PosixResult<Option<SignalDelivery>> {
+        loop {
+            let info = {
+                let thread = self.threads.get_mut(&tid).ok_or(Errno::Esrch)?;
+                let allowed = !thread.mask;
+                match thread.pending.take(allowed).or_else(|| self.shared.take(allowed)) {
+                    Some(info) => info,
+                    None => return Ok(None),
+                }
+            };
+            let signo = info.si_signo;
+            let action = self.actions[signo as usize - 1];
+
+            match action.sa_handler {
+                SIG_IGN => {
+                    self.stats.ignored += 1;
+                    continue;
+                }
+                SIG_DFL => {
+                    let default = default_action(signo);
+                    match default {
+                        DefaultAction::Ignore => {
+                            self.stats.ignored += 1;
+                            continue;
+                        }
+                        DefaultAction::Stop => self.stopped = true,
+                        DefaultAction::Continue => self.stopped = false,
+                        _ => {}
+                    }
+                    self.stats.default_actions += 1;
+                    return Ok(Some(SignalDelivery::Default { info, action: default }));
+                }
+                handler => return self.setup_frame(tid, info, &action, handler, context, user_sp).map(Some),
+            }
+        }
+    }
+
+    /// RT_SIGRETURN: tear down a frame and return the context to resume
+    pub fn sigreturn(&mut self, tid: pid_t, frame: &SignalFrame) -> PosixResult<sigcontext> {
+        if frame.magic != SIGNAL_FRAME_MAGIC {
+            return Err(Errno::Ebadaddr);
+        }
+        let thread = self.threads.get_mut(&tid).ok_or(Errno::Esrch)?;
+        thread.mask = frame.saved_mask & !UNBLOCKABLE;
+        thread.on_altstack = frame.was_on_altstack;
+        Ok(frame.context)
+    }
+
+    /// Get signal statistics
+    pub fn get_stats(&self) -> SignalStats {
+        self.stats
+    }
+
+    fn setup_frame(
+        &mut self,
+        tid: pid_t,
+        info: siginfo,
+        action: &sigaction,
+        handler: usize,
+        context: &sigcontext,
+        user_sp: usize,
+    ) -> PosixResult<SignalDelivery> {
+        let signo = info.si_signo;
+        let thread = self.threads.get_mut(&tid).ok_or(Errno::Esrch)?;
+
+        let was_on_altstack = thread.on_altstack;
+        let (stack_base, stack_top, switch_stack) = match thread.altstack {
+            Some((base, size)) if action.sa_flags & SA_ONSTACK != 0 && !was_on_altstack => {
+                (base, base.checked_add(size), true)
+            }
+            _ => (0, Some(user_sp), false),
+        };
+        // 16-byte aligned, minus the return address slot, as at a call site
+        let frame_addr = stack_top
+            .and_then(|top| top.checked_sub(core::mem::size_of::<SignalFrame>()))
+            .and_then(|frame| (frame & !0xf).checked_sub(8))
+            .filter(|&frame_addr| frame_addr >= stack_base);
+        let Some(frame_addr) = frame_addr else {
+            return Ok(self.force_sigsegv(tid, stack_top.unwrap_or(usize::MAX)));
+        };
+        thread.on_altstack |= switch_stack;
+
+        let frame = SignalFrame {
+            restorer: action.sa_restorer,
+            magic: SIGNAL_FRAME_MAGIC,
+            signo,
+            info,
+            context: *context,
+            saved_mask: thread.mask,
+            was_on_altstack,
+        };
+
+        let mut blocked = action.sa_mask;
+        if action.sa_flags & SA_NODEFER == 0 {
+            blocked |= sig_bit(signo);
+        }
+        thread.mask = (thread.mask | blocked) & !UNBLOCKABLE;
+
+        if action.sa_flags & SA_RESETHAND != 0 {
+            self.actions[signo as usize - 1] = default_sigaction();
+        }
+
+        let args = if action.sa_flags & SA_SIGINFO != 0 {
+            [
+                signo as usize,
+                frame_addr + core::mem::offset_of!(SignalFrame, info),
+                frame_addr + core::mem::offset_of!(SignalFrame, context),
+            ]
+        } else {
+            [signo as usize, 0, 0]
+        };
+
+        self.stats.handlers_run += 1;
+        Ok(SignalDelivery::Handler { frame, frame_addr, handler, args })
+    }
+
+    /// Kill a thread whose handler frame did not fit with a SIGSEGV that
+    /// cannot be blocked or caught
+    fn force_sigsegv(&mut self, tid: pid_t, stack_top: usize) -> SignalDelivery {
+        self.actions[SIGSEGV as usize - 1] = default_sigaction();
+        if let Some(thread) = self.threads.get_mut(&tid) {
+            thread.mask &= !sig_bit(SIGSEGV);
+        }
+        self.stats.default_actions += 1;
+        SignalDelivery::Default {
+            info: kernel_siginfo(SIGSEGV, SI_KERNEL, stack_top),
+            action: default_action(SIGSEGV),
+        }
+    }
+
+    /// Generation-time checks; returns false if the signal is discarded
+    fn generate(&mut self, info: &siginfo) -> PosixResult<bool> {
+        let signo = info.si_signo;
+        if !utils::is_valid_signal(signo) {
+            return Err(Errno::Einval);
+        }
+        self.stats.generated += 1;
+
+        // SIGCONT and stop signals cancel each other
+        if signo == SIGCONT {
+            self.discard_everywhere(STOP_SIGNALS);
+            self.stopped = false;
+        } else if STOP_SIGNALS & sig_bit(signo) != 0 {
+            self.discard_everywhere(sig_bit(SIGCONT));
+        } else if signo == SIGKILL {
+            self.stopped = false;
+        }
+
+        if self.is_ignored(signo) {
+            self.stats.ignored += 1;
+            return Ok(false);
+        }
+
+        if is_realtime(signo) {
+            let queued = self.shared.realtime_count()
+                + self.threads.values().map(|thread| thread.pending.realtime_count()).sum::<usize>();
+            if queued >= RT_QUEUE_LIMIT {
+                self.stats.queue_overflows += 1;
+                return Err(Errno::Eagain);
+            }
+        }
+        Ok(true)
+    }
+
+    fn is_ignored(&self, signo: i32) -> bool {
+        match self.actions[signo as usize - 1].sa_handler {
+            SIG_IGN => true,
+            SIG_DFL => default_action(signo) == DefaultAction::Ignore,
+            _ => false,
+        }
+    }
+
+    fn discard_everywhere(&mut self, set: sigset_t) {
+        self.shared.discard(set);
+        for thread in self.threads.values_mut() {
+            thread.pending.discard(set);
+        }
+    }
+}
+
+/// Build the siginfo for a signal sent by `kill` from `sender`
+pub fn user_siginfo(signo: i32, sender: pid_t, uid: uid_t) -> siginfo {
+    let mut info = empty_siginfo(signo, SI_USER);
+    info.si_pid = sender;
+    info.si_uid = uid;
+    info
+}
+
+/// Build the siginfo for a signal raised by the kernel (faults, timers)
+pub fn kernel_siginfo(signo: i32, code: i32, addr: usize) -> siginfo {
+    let mut info = empty_siginfo(signo, code);
+    info.si_addr = addr;
+    info
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TID: pid_t = 100;
+    const HANDLER: usize = 0x40_1000;
+    const RESTORER: usize = 0x40_2000;
+
+    fn context() -> sigcontext {
+        // sigcontext is plain integers; all-zero is a valid value
+        let mut context: sigcontext = unsafe { core::mem::zeroed() };
+        context.sc_pc = 0x40_0000;
+        context
+    }
+
+    fn catching(flags: i32) -> ProcessSignals {
+        let mut signals = ProcessSignals::new(TID);
+        let act = sigaction { sa_handler: HANDLER, sa_mask: sig_bit(SIGUSR2), sa_flags: flags, sa_restorer: RESTORER };
+        signals.rt_sigaction(SIGUSR1, Some(&act)).unwrap();
+        signals.send_to_thread(TID, user_siginfo(SIGUSR1, 1, 0)).unwrap();
+        signals
+    }
+
+    fn handler_frame(delivery: Option<SignalDelivery>) -> (SignalFrame, usize, [usize; 3]) {
+        match delivery {
+            Some(SignalDelivery::Handler { frame, frame_addr, handler, args }) => {
+                assert_eq!(handler, HANDLER);
+                (frame, frame_addr, args)
+            }
+            other => panic!("expected a handler frame, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_frame_layout_and_alignment() {
+        let user_sp = 0x7fff_f123;
+        let mut signals = catching(SA_SIGINFO);
+        let (frame, frame_addr, args) = handler_frame(signals.deliver(TID, &context(), user_sp).unwrap());
+
+        // Entered as if called: sp + 8 is 16-byte aligned
+        assert_eq!((frame_addr + 8) % 16, 0);
+        assert!(frame_addr + core::mem::size_of::<SignalFrame>() <= user_sp);
+        assert!(user_sp - frame_addr < core::mem::size_of::<SignalFrame>() + 32);
+
+        assert_eq!(frame.magic, SIGNAL_FRAME_MAGIC);
+        assert_eq!(frame.restorer, RESTORER);
+        assert_eq!(frame.signo, SIGUSR1);
+        assert_eq!(frame.context.sc_pc, 0x40_0000);
+        assert_eq!(frame.saved_mask, 0);
+        assert!(!frame.was_on_altstack);
+        assert_eq!(args, [
+            SIGUSR1 as usize,
+            frame_addr + core::mem::offset_of!(SignalFrame, info),
+            frame_addr + core::mem::offset_of!(SignalFrame, context),
+        ]);
+
+        // The handler runs with sa_mask and the signal blocked until sigreturn
+        assert_eq!(signals.rt_sigprocmask(TID, SIG_BLOCK, None), Ok(sig_bit(SIGUSR1) | sig_bit(SIGUSR2)));
+        signals.sigreturn(TID, &frame).unwrap();
+        assert_eq!(signals.rt_sigprocmask(TID, SIG_BLOCK, None), Ok(0));
+    }
+
+    #[test]
+    fn test_handler_without_siginfo_gets_only_the_signal_number() {
+        let mut signals = catching(0);
+        let (_, _, args) = handler_frame(signals.deliver(TID, &context(), 0x8000_0000).unwrap());
+        assert_eq!(args, [SIGUSR1 as usize, 0, 0]);
+    }
+
+    #[test]
+    fn test_onstack_handler_runs_on_the_altstack() {
+        let (base, size) = (0x10_0000, 0x4000);
+        let mut signals = catching(SA_ONSTACK);
+        signals.sigaltstack(TID, Some((base, size))).unwrap();
+
+        let (frame, frame_addr, _) = handler_frame(signals.deliver(TID, &context(), 0x8000_0000).unwrap());
+        assert!(frame_addr >= base && frame_addr + core::mem::size_of::<SignalFrame>() <= base + size);
+        assert_eq!((frame_addr + 8) % 16, 0);
+        assert!(!frame.was_on_altstack);
+        // The stack cannot be changed while a handler runs on it
+        assert_eq!(signals.sigaltstack(TID, None), Err(Errno::Epperm));
+
+        // A nested signal stays on the altstack, below the interrupted handler
+        signals.rt_sigprocmask(TID, SIG_SETMASK, Some(0)).unwrap();
+        signals.send_to_thread(TID, user_siginfo(SIGUSR1, 1, 0)).unwrap();
+        let nested_sp = frame_addr - 64;
+        let (nested, nested_addr, _) = handler_frame(signals.deliver(TID, &context(), nested_sp).unwrap());
+        assert!(nested.was_on_altstack);
+        assert!(nested_addr < nested_sp);
+
+        signals.sigreturn(TID, &nested).unwrap();
+        signals.sigreturn(TID, &frame).unwrap();
+        assert_eq!(signals.sigaltstack(TID, None), Ok(()));
+    }
+
+    #[test]
+    fn test_frame_that_does_not_fit_raises_sigsegv() {
+        // An altstack smaller than a frame
+        let mut signals = catching(SA_ONSTACK);
+        signals.sigaltstack(TID, Some((0x10_0000, 64))).unwrap();
+        match signals.deliver(TID, &context(), 0x8000_0000).unwrap() {
+            Some(SignalDelivery::Default { info, action }) => {
+                assert_eq!(info.si_signo, SIGSEGV);
+                assert_eq!(info.si_code, SI_KERNEL);
+                assert_eq!(action, DefaultAction::CoreDump);
+            }
+            other => panic!("expected SIGSEGV, got {:?}", other),
+        }
+        assert_eq!(signals.rt_sigaction(SIGSEGV, None).unwrap().sa_handler, SIG_DFL);
+        assert_eq!(signals.sigaltstack(TID, None), Ok(()));
+
+        // A user stack pointer too close to zero
+        let mut signals = catching(0);
+        let delivery = signals.deliver(TID, &context(), 16).unwrap();
+        assert!(matches!(delivery, Some(SignalDelivery::Default { action: DefaultAction::CoreDump, .. })));
+        assert_eq!(signals.get_stats().handlers_run, 0);
+    }
+
+    #[test]
+    fn test_deliver_to_exited_thread_fails() {
+        let mut signals = catching(0);
+        signals.remove_thread(TID);
+        assert!(matches!(signals.deliver(TID, &context(), 0x8000_0000), Err(Errno::Esrch)));
+    }
+}
+
@@ -4,8 +4,10 @@
 //! including network socket operations, address resolution, and socket options
 //! while maintaining Rust safety guarantees.
 
+use crate::descriptors::{self, Direction};
 use crate::errors::*;
 use crate::internal::*;
+use crate::netstack::Endpoint;
 use crate::syscall;
 use crate::types::*;
 use crate::sys_types::*;
@@ -124,6 +126,15 @@ pub struct cmsghdr {
     pub cmsg_data: [u8; 0],        // Data (variable length)
 }
 
+/// Peer credentials (SO_PEERCRED, SCM_CREDENTIALS)
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ucred {
+    pub pid: crate::types::pid_t,  // Process ID of the sender
+    pub uid: crate::types::uid_t,  // User ID of the sender
+    pub gid: crate::types::gid_t,  // Group ID of the sender
+}
+
 /// Protocol-independent socket creation
 /// 
 /// This function provides compatibility with the POSIX socket() function.
@@ -136,13 +147,12 @@ pub struct cmsghdr {
 /// # Returns
 /// * `PosixResult<fd_t>` - Socket file descriptor on success, error on failure
 pub fn socket(domain: SocketDomain, ty: SocketType, protocol: SocketProtocol) -> PosixResult<fd_t> {
-    unsafe {
-        let result = syscall::socket(domain, ty, protocol);
-        if result < 0 {
-            Err(Errno::from_raw(-result))
-        } else {
-            Ok(result as fd_t)
+    match domain {
+        // Internet sockets are served by the native stack in this process
+        SocketDomain::Inet | SocketDomain::Inet6 => {
+            descriptors::open_socket(domain as sa_family_t, ty as i32, protocol as i32)
         }
+        _ => syscall::socket(domain, ty, protocol),
     }
 }
 
@@ -162,14 +172,12 @@ pub fn bind(sockfd: fd_t, addr: &sockaddr, addrlen: socklen_t) -> PosixResult<()
         return Err(Errno::Ebadf);
     }
     
-    unsafe {
-        let result = syscall::bind(sockfd, addr as *const sockaddr, addrlen);
-        if result < 0 {
-            Err(Errno::from_raw(-result))
-        } else {
-            Ok(())
-        }
+    if descriptors::is_local(sockfd) {
+        let local = unsafe { Endpoint::read_raw(addr, addrlen)? };
+        return descriptors::socket_op(sockfd, |stack, id| stack.bind(id, local));
     }
+    
+    syscall::bind(sockfd, addr as *const sockaddr, addrlen)
 }
 
 /// Connect a socket to an address
@@ -188,14 +196,18 @@ pub fn connect(sockfd: fd_t, addr: &sockaddr, addrlen: socklen_t) -> PosixResult
         return Err(Errno::Ebadf);
     }
     
-    unsafe {
-        let result = syscall::connect(sockfd, addr as *const sockaddr, addrlen);
-        if result < 0 {
-            Err(Errno::from_raw(-result))
-        } else {
-            Ok(())
-        }
+    if descriptors::is_local(sockfd) {
+        let remote = unsafe { Endpoint::read_raw(addr, addrlen)? };
+        return match descriptors::socket_op(sockfd, |stack, id| stack.connect(id, remote)) {
+            // Blocking sockets wait for the handshake to finish
+            Err(Errno::Einprogress) if !descriptors::socket_op(sockfd, |stack, id| stack.is_nonblocking(id))? => {
+                descriptors::socket_wait(sockfd, Direction::Send, 0, |stack, id| stack.finish_connect(id))
+            }
+            result => result,
+        };
     }
+    
+    syscall::connect(sockfd, addr as *const sockaddr, addrlen)
 }
 
 /// Listen for incoming connections
@@ -217,14 +229,11 @@ pub fn listen(sockfd: fd_t, backlog: i32) -> PosixResult<()> {
         return Err(Errno::Einval);
     }
     
-    unsafe {
-        let result = syscall::listen(sockfd, backlog);
-        if result < 0 {
-            Err(Errno::from_raw(-result))
-        } else {
-            Ok(())
-        }
+    if descriptors::is_local(sockfd) {
+        return descriptors::socket_op(sockfd, |stack, id| stack.listen(id, backlog));
     }
+    
+    syscall::listen(sockfd, backlog)
 }
 
 /// Accept a new connection
@@ -239,21 +248,7 @@ pub fn listen(sockfd: fd_t, backlog: i32) -> PosixResult<()> {
 /// # Returns
 /// * `PosixResult<fd_t>` - Connected socket file descriptor, error on failure
 pub fn accept(sockfd: fd_t, addr: Option<&mut sockaddr>, addrlen: Option<&mut socklen_t>) -> PosixResult<fd_t> {
-    if sockfd < 0 {
-        return Err(Errno::Ebadf);
-    }
-    
-    unsafe {
-        let addr_ptr = addr.map_or(core::ptr::null_mut(), |a| a as *mut sockaddr);
-        let len_ptr = addrlen.map_or(core::ptr::null_mut(), |l| l as *mut socklen_t);
-        
-        let result = syscall::accept(sockfd, addr_ptr, len_ptr);
-        if result < 0 {
-            Err(Errno::from_raw(-result))
-        } else {
-            Ok(result as fd_t)
-        }
-    }
+    accept4(sockfd, addr, addrlen, 0)
 }
 
 /// Accept a new connection with flags
@@ -269,9 +264,33 @@ pub fn accept(sockfd: fd_t, addr: Option<&mut sockaddr>, addrlen: Option<&mut so
 /// # Returns
 /// * `PosixResult<fd_t>` - Connected socket file descriptor, error on failure
 pub fn accept4(sockfd: fd_t, addr: Option<&mut sockaddr>, addrlen: Option<&mut socklen_t>, flags: i32) -> PosixResult<fd_t> {
-    // In a real implementation, this would call syscall::accept4
-    // For now, return not implemented
-    Err(Errno::Enosys)
+    if sockfd < 0 {
+        return Err(Errno::Ebadf);
+    }
+    
+    if flags & !(SOCK_NONBLOCK | SOCK_CLOEXEC) != 0 {
+        return Err(Errno::Einval);
+    }
+    
+    if descriptors::is_local(sockfd) {
+        let (child, peer, family) = descriptors::socket_wait(sockfd, Direction::Receive, 0, |stack, id| {
+            let (child, peer) = stack.accept(id)?;
+            stack.set_nonblocking(child, flags & SOCK_NONBLOCK != 0)?;
+            Ok((child, peer, stack.family(id)?))
+        })?;
+        if let (Some(addr), Some(addrlen)) = (addr, addrlen) {
+            unsafe { peer.write_raw(family, addr, addrlen) };
+        }
+        return descriptors::install_socket(child);
+    }
+    
+    let addr_ptr = addr.map_or(core::ptr::null_mut(), |a| a as *mut sockaddr);
+    let len_ptr = addrlen.map_or(core::ptr::null_mut(), |l| l as *mut socklen_t);
+    if flags == 0 {
+        syscall::accept(sockfd, addr_ptr, len_ptr)
+    } else {
+        syscall::accept4(sockfd, addr_ptr, len_ptr, flags)
+    }
 }
 
 /// Send data on a socket
@@ -294,14 +313,12 @@ pub fn send(sockfd: fd_t, buf: &[u8], flags: i32) -> PosixResult<usize> {
         return Ok(0);
     }
     
-    unsafe {
-        let result = syscall::send(sockfd, buf.as_ptr(), buf.len(), flags);
-        if result < 0 {
-            Err(Errno::from_raw(-result))
-        } else {
-            Ok(result as usize)
-        }
+    if descriptors::is_local(sockfd) {
+        let result = descriptors::socket_wait(sockfd, Direction::Send, flags, |stack, id| stack.send(id, buf, flags));
+        return local_send_result(result, flags);
     }
+    
+    syscall::send(sockfd, buf.as_ptr(), buf.len(), flags).map(|sent| sent as usize)
 }
 
 /// Send data to a specific address
@@ -322,13 +339,21 @@ pub fn sendto(sockfd: fd_t, buf: &[u8], flags: i32, dest_addr: Option<&sockaddr>
         return Err(Errno::Ebadf);
     }
     
-    if buf.is_empty() {
-        return Ok(0);
+    if descriptors::is_local(sockfd) {
+        let dest = match dest_addr {
+            Some(addr) => Some(unsafe { Endpoint::read_raw(addr, addrlen)? }),
+            None => None,
+        };
+        let result = descriptors::socket_wait(sockfd, Direction::Send, flags, |stack, id| stack.sendto(id, buf, flags, dest));
+        return local_send_result(result, flags);
     }
     
-    // In a real implementation, this would call syscall::sendto
-    // For now, return not implemented
-    Err(Errno::Enosys)
+    // Empty datagrams are valid, so zero-length sends still reach the stack
+    let (addr_ptr, addrlen) = match dest_addr {
+        Some(addr) => (addr as *const sockaddr, addrlen),
+        None => (core::ptr::null(), 0),
+    };
+    syscall::sendto(sockfd, buf.as_ptr(), buf.len(), flags, addr_ptr, addrlen).map(|sent| sent as usize)
 }
 
 /// Send data using message structure
@@ -347,9 +372,33 @@ pub fn sendmsg(sockfd: fd_t, msg: &msghdr, flags: i32) -> PosixResult<usize> {
         return Err(Errno::Ebadf);
     }
     
-    // In a real implementation, this would call syscall::sendmsg
-    // For now, return not implemented
-    Err(Errno::Enosys)
+    if msg.msg_iovlen < 0 || (msg.msg_iovlen > 0 && msg.msg_iov.is_null()) {
+        return Err(Errno::Einval);
+    }
+    
+    if msg.msg_controllen > 0 && msg.msg_control.is_null() {
+        return Err(Errno::Einval);
+    }
+    
+    if descriptors::is_local(sockfd) {
+        // Internet sockets carry no ancillary data
+        if msg.msg_controllen > 0 {
+            return Err(Errno::Eopnotsupp);
+        }
+        let data: Vec<u8> = unsafe { iovecs(msg) }.iter()
+            .flat_map(|iov| unsafe { core::slice::from_raw_parts(iov.iov_base as *const u8, iov.iov_len) })
+            .copied()
+            .collect();
+        let dest = match msg.msg_name.is_null() {
+            true => None,
+            false => Some(unsafe { Endpoint::read_raw(msg.msg_name, msg.msg_namelen)? }),
+        };
+        let result = descriptors::socket_wait(sockfd, Direction::Send, flags, |stack, id| stack.sendto(id, &data, flags, dest));
+        return local_send_result(result, flags);
+    }
+    
+    // The kernel gathers the iovecs and consumes the ancillary data
+    syscall::sendmsg(sockfd, msg as *const msghdr, flags).map(|sent| sent as usize)
 }
 
 /// Receive data from a socket
@@ -372,14 +421,11 @@ pub fn recv(sockfd: fd_t, buf: &mut [u8], flags: i32) -> PosixResult<usize> {
         return Ok(0);
     }
     
-    unsafe {
-        let result = syscall::recv(sockfd, buf.as_mut_ptr(), buf.len(), flags);
-        if result < 0 {
-            Err(Errno::from_raw(-result))
-        } else {
-            Ok(result as usize)
-        }
+    if descriptors::is_local(sockfd) {
+        return descriptors::socket_wait(sockfd, Direction::Receive, flags, |stack, id| stack.recv(id, buf, flags));
     }
+    
+    syscall::recv(sockfd, buf.as_mut_ptr(), buf.len(), flags).map(|received| received as usize)
 }
 
 /// Receive data from a specific address
@@ -400,13 +446,21 @@ pub fn recvfrom(sockfd: fd_t, buf: &mut [u8], flags: i32, from_addr: Option<&mut
         return Err(Errno::Ebadf);
     }
     
-    if buf.is_empty() {
-        return Ok(0);
+    if descriptors::is_local(sockfd) {
+        let (received, from, family) = descriptors::socket_wait(sockfd, Direction::Receive, flags, |stack, id| {
+            let (received, from) = stack.recvfrom(id, buf, flags)?;
+            Ok((received, from, stack.family(id)?))
+        })?;
+        if let (Some(addr), Some(addrlen)) = (from_addr, addrlen) {
+            unsafe { from.write_raw(family, addr, addrlen) };
+        }
+        return Ok(received);
     }
     
-    // In a real implementation, this would call syscall::recvfrom
-    // For now, return not implemented
-    Err(Errno::Enosys)
+    let addr_ptr = from_addr.map_or(core::ptr::null_mut(), |a| a as *mut sockaddr);
+    let len_ptr = addrlen.map_or(core::ptr::null_mut(), |l| l as *mut socklen_t);
+    syscall::recvfrom(sockfd, buf.as_mut_ptr(), buf.len(), flags, addr_ptr, len_ptr)
+        .map(|received| received as usize)
 }
 
 /// Receive data using message structure
@@ -425,9 +479,159 @@ pub fn recvmsg(sockfd: fd_t, msg: &mut msghdr, flags: i32) -> PosixResult<usize>
         return Err(Errno::Ebadf);
     }
     
-    // In a real implementation, this would call syscall::recvmsg
-    // For now, return not implemented
-    Err(Errno::Enosys)
+    if msg.msg_iovlen < 0 || (msg.msg_iovlen > 0 && msg.msg_iov.is_null()) {
+        return Err(Errno::Einval);
+    }
+    
+    if descriptors::is_local(sockfd) {
+        let iovs = unsafe { iovecs(msg) };
+        let mut data = vec![0u8; iovs.iter().map(|iov| iov.iov_len).sum()];
+        // Ask for the full datagram length so truncation can be reported
+        let (length, from, family) = descriptors::socket_wait(sockfd, Direction::Receive, flags, |stack, id| {
+            let (length, from) = stack.recvfrom(id, &mut data, flags | MSG_TRUNC)?;
+            Ok((length, from, stack.family(id)?))
+        })?;
+        let received = length.min(data.len());
+        let mut rest = &data[..received];
+        for iov in iovs {
+            let count = rest.len().min(iov.iov_len);
+            unsafe { core::ptr::copy_nonoverlapping(rest.as_ptr(), iov.iov_base, count) };
+            rest = &rest[count..];
+        }
+        if !msg.msg_name.is_null() {
+            unsafe { from.write_raw(family, msg.msg_name as *mut sockaddr, &mut msg.msg_namelen) };
+        }
+        msg.msg_controllen = 0;
+        msg.msg_flags = if length > received { MSG_TRUNC } else { 0 };
+        return Ok(if flags & MSG_TRUNC != 0 { length } else { received });
+    }
+    
+    // The kernel scatters the data, installs passed descriptors, fills
+    // msg_control and sets msg_namelen, msg_controllen and msg_flags
+    syscall::recvmsg(sockfd, msg as *mut msghdr, flags).map(|received| received as usize)
+}
+
+/// Send data together with open file descriptors (SCM_RIGHTS)
+/// 
+/// The receiver gets new descriptors referring to the same open files.
+/// 
+/// # Arguments
+/// * `sockfd` - UNIX domain socket file descriptor
+/// * `data` - Data to send; must not be empty for stream sockets
+/// * `fds` - Descriptors to pass (at most SCM_MAX_FD)
+/// 
+/// # Returns
+/// * `PosixResult<usize>` - Number of bytes sent, error on failure
+pub fn send_fds(sockfd: fd_t, data: &[u8], fds: &[fd_t]) -> PosixResult<usize> {
+    if fds.len() > SCM_MAX_FD {
+        return Err(Errno::Einval);
+    }
+    
+    let payload = fds.len() * core::mem::size_of::<fd_t>();
+    let mut control = vec![0u8; if fds.is_empty() { 0 } else { cmsg_space(payload) }];
+    if !fds.is_empty() {
+        let header = cmsghdr {
+            cmsg_len: cmsg_len(payload) as socklen_t,
+            cmsg_level: SOL_SOCKET,
+            cmsg_type: SCM_RIGHTS,
+            cmsg_data: [],
+        };
+        unsafe {
+            core::ptr::write_unaligned(control.as_mut_ptr() as *mut cmsghdr, header);
+            core::ptr::copy_nonoverlapping(fds.as_ptr() as *const u8, control.as_mut_ptr().add(cmsg_len(0)), payload);
+        }
+    }
+    
+    let iov = iovec { iov_base: data.as_ptr() as *mut u8, iov_len: data.len() };
+    let msg = msghdr {
+        msg_name: core::ptr::null(),
+        msg_namelen: 0,
+        msg_iov: &iov,
+        msg_iovlen: 1,
+        msg_control: if control.is_empty() { core::ptr::null_mut() } else { control.as_mut_ptr() },
+        msg_controllen: control.len() as socklen_t,
+        msg_flags: 0,
+    };
+    sendmsg(sockfd, &msg, MSG_NOSIGNAL)
+}
+
+/// Receive data and any file descriptors passed with it (SCM_RIGHTS)
+/// 
+/// # Arguments
+/// * `sockfd` - UNIX domain socket file descriptor
+/// * `buf` - Buffer to receive data into
+/// * `max_fds` - Maximum number of descriptors to accept
+/// 
+/// # Returns
+/// * `PosixResult<(usize, Vec<fd_t>)>` - Bytes received and the new descriptors
+pub fn recv_fds(sockfd: fd_t, buf: &mut [u8], max_fds: usize) -> PosixResult<(usize, Vec<fd_t>)> {
+    let max_fds = max_fds.min(SCM_MAX_FD);
+    let mut control = vec![0u8; cmsg_space(max_fds * core::mem::size_of::<fd_t>())];
+    let iov = iovec { iov_base: buf.as_mut_ptr(), iov_len: buf.len() };
+    let mut msg = msghdr {
+        msg_name: core::ptr::null(),
+        msg_namelen: 0,
+        msg_iov: &iov,
+        msg_iovlen: 1,
+        msg_control: control.as_mut_ptr(),
+        msg_controllen: control.len() as socklen_t,
+        msg_flags: 0,
+    };
+    let received = recvmsg(sockfd, &mut msg, MSG_CMSG_CLOEXEC)?;
+    
+    // Walk the control messages the kernel filled in
+    let mut fds = Vec::new();
+    let mut offset = 0;
+    let filled = (msg.msg_controllen as usize).min(control.len());
+    while offset + cmsg_len(0) <= filled {
+        let header = unsafe { core::ptr::read_unaligned(control.as_ptr().add(offset) as *const cmsghdr) };
+        let len = header.cmsg_len as usize;
+        if len < cmsg_len(0) || offset + len > filled {
+            break;
+        }
+        if header.cmsg_level == SOL_SOCKET && header.cmsg_type == SCM_RIGHTS {
+            let data = &control[offset + cmsg_len(0)..offset + len];
+            fds.extend(data.chunks_exact(core::mem::size_of::<fd_t>())
+                .map(|chunk| fd_t::from_ne_bytes(chunk.try_into().expect("chunk has fd size"))));
+        }
+        offset += cmsg_align(len);
+    }
+    Ok((received, fds))
+}
+
+/// The iovec array of a message
+///
+/// # Safety
+/// `msg_iov` must point to `msg_iovlen` valid iovecs when `msg_iovlen > 0`.
+unsafe fn iovecs(msg: &msghdr) -> &[iovec] {
+    if msg.msg_iovlen <= 0 {
+        return &[];
+    }
+    core::slice::from_raw_parts(msg.msg_iov, msg.msg_iovlen as usize)
+}
+
+/// Raise SIGPIPE for a send on a closed connection unless MSG_NOSIGNAL was given
+fn local_send_result(result: PosixResult<usize>, flags: i32) -> PosixResult<usize> {
+    if matches!(result, Err(Errno::Epipe)) && flags & MSG_NOSIGNAL == 0 {
+        let _ = crate::unistd::kill(crate::unistd::getpid(), crate::signal::SIGPIPE);
+    }
+    result
+}
+
+/// Round a control message length up to the cmsghdr alignment (CMSG_ALIGN)
+pub const fn cmsg_align(len: usize) -> usize {
+    let align = core::mem::size_of::<usize>();
+    (len + align - 1) & !(align - 1)
+}
+
+/// Length of a control message carrying `len` bytes of data (CMSG_LEN)
+pub const fn cmsg_len(len: usize) -> usize {
+    cmsg_align(core::mem::size_of::<cmsghdr>()) + len
+}
+
+/// Buffer space needed for a control message with `len` bytes (CMSG_SPACE)
+pub const fn cmsg_space(len: usize) -> usize {
+    cmsg_align(core::mem::size_of::<cmsghdr>()) + cmsg_align(len)
 }
 
 /// Shut down part of a socket connection
@@ -449,14 +653,11 @@ pub fn shutdown(sockfd: fd_t, how: i32) -> PosixResult<()> {
         return Err(Errno::Einval);
     }
     
-    unsafe {
-        let result = syscall::shutdown(sockfd, how);
-        if result < 0 {
-            Err(Errno::from_raw(-result))
-        } else {
-            Ok(())
-        }
+    if descriptors::is_local(sockfd) {
+        return descriptors::socket_op(sockfd, |stack, id| stack.shutdown(id, how));
     }
+    
+    syscall::shutdown(sockfd, how)
 }
 
 /// Get socket name (local address)
@@ -475,9 +676,13 @@ pub fn getsockname(sockfd: fd_t, addr: &mut sockaddr, addrlen: &mut socklen_t) -
         return Err(Errno::Ebadf);
     }
     
-    // In a real implementation, this would call syscall::getsockname
-    // For now, return not implemented
-    Err(Errno::Enosys)
+    if descriptors::is_local(sockfd) {
+        let (local, family) = descriptors::socket_op(sockfd, |stack, id| Ok((stack.local_endpoint(id)?, stack.family(id)?)))?;
+        unsafe { local.write_raw(family, addr, addrlen) };
+        return Ok(());
+    }
+    
+    syscall::getsockname(sockfd, addr as *mut sockaddr, addrlen as *mut socklen_t)
 }
 
 /// Get peer name (remote address)
@@ -496,9 +701,13 @@ pub fn getpeername(sockfd: fd_t, addr: &mut sockaddr, addrlen: &mut socklen_t) -
         return Err(Errno::Ebadf);
     }
     
-    // In a real implementation, this would call syscall::getpeername
-    // For now, return not implemented
-    Err(Errno::Enosys)
+    if descriptors::is_local(sockfd) {
+        let (peer, family) = descriptors::socket_op(sockfd, |stack, id| Ok((stack.peer_endpoint(id)?, stack.family(id)?)))?;
+        unsafe { peer.write_raw(family, addr, addrlen) };
+        return Ok(());
+    }
+    
+    syscall::getpeername(sockfd, addr as *mut sockaddr, addrlen as *mut socklen_t)
 }
 
 /// Get socket option
@@ -519,9 +728,15 @@ pub fn getsockopt(sockfd: fd_t, level: i32, optname: i32, optval: &mut [u8], opt
         return Err(Errno::Ebadf);
     }
     
-    // In a real implementation, this would call syscall::getsockopt
-    // For now, return not implemented
-    Err(Errno::Enosys)
+    *optlen = (*optlen).min(optval.len() as socklen_t);
+    if descriptors::is_local(sockfd) {
+        let value = descriptors::socket_op(sockfd, |stack, id| stack.getsockopt(id, level, optname))?;
+        let count = value.len().min(*optlen as usize);
+        optval[..count].copy_from_slice(&value[..count]);
+        *optlen = count as socklen_t;
+        return Ok(());
+    }
+    syscall::getsockopt(sockfd, level, optname, optval.as_mut_ptr(), optlen as *mut socklen_t)
 }
 
 /// Set socket option
@@ -542,9 +757,11 @@ pub fn setsockopt(sockfd: fd_t, level: i32, optname: i32, optval: &[u8]) -> Posi
         return Err(Errno::Ebadf);
     }
     
-    // In a real implementation, this would call syscall::setsockopt
-    // For now, return not implemented
-    Err(Errno::Enosys)
+    if descriptors::is_local(sockfd) {
+        return descriptors::socket_op(sockfd, |stack, id| stack.setsockopt(id, level, optname, optval));
+    }
+    
+    syscall::setsockopt(sockfd, level, optname, optval.as_ptr(), optval.len() as socklen_t)
 }
 
 /// Create a pair of connected sockets
@@ -560,13 +777,11 @@ pub fn setsockopt(sockfd: fd_t, level: i32, optname: i32, optval: &[u8]) -> Posi
 /// # Returns
 /// * `PosixResult<()>` - Success on socketpair, error on failure
 pub fn socketpair(domain: i32, ty: i32, protocol: i32, sv: &mut [fd_t; 2]) -> PosixResult<()> {
-    if domain != AF_UNIX {
+    if domain != AF_UNIX as i32 {
         return Err(Errno::Eafnosupport);
     }
     
-    // In a real implementation, this would call syscall::socketpair
-    // For now, return not implemented
-    Err(Errno::Enosys)
+    syscall::socketpair(domain, ty, protocol, sv.as_mut_ptr())
 }
 
 /// IP address conversion functions
@@ -787,11 +1002,8 @@ pub const SHUT_RD: i32 = 0;                 // Further receptions disallowed
 pub const SHUT_WR: i32 = 1;                 // Further transmissions disallowed  
 pub const SHUT_RDWR: i32 = 2;               // Further receptions and transmissions disallowed
 
-/// Socket option levels
+/// Socket option levels (IPPROTO_TCP, IPPROTO_IP and IPPROTO_IPV6 double as levels)
 pub const SOL_SOCKET: i32 = 1;              // Socket-level options
-pub const IPPROTO_TCP: i32 = 6;             // TCP protocol options
-pub const IPPROTO_IP: i32 = 0;              // IP protocol options
-pub const IPPROTO_IPV6: i32 = 41;           // IPv6 protocol options
 
 /// Socket options (for SOL_SOCKET level)
 pub const SO_REUSEADDR: i32 = 2;            // Allow reuse of local addresses
@@ -835,6 +1047,13 @@ pub const TCP_WINDOW_CLAMP: i32 = 10;       // Bound advertised window
 pub const TCP_INFO: i32 = 11;               // Information about this socket
 pub const TCP_QUICKACK: i32 = 12;           // Enable quickack mode
 
+/// Ancillary message types (SOL_SOCKET level)
+pub const SCM_RIGHTS: i32 = 1;              // Pass file descriptors
+pub const SCM_CREDENTIALS: i32 = 2;         // Pass credentials
+
+/// Maximum number of descriptors in one SCM_RIGHTS message
+pub const SCM_MAX_FD: usize = 253;
+
 /// MSG flags for sendmsg/recvmsg
 pub const MSG_OOB: i32 = 0x01;              // Process out-of-band data
 pub const MSG_PEEK: i32 = 0x02;             // Peek at incoming data
@@ -846,6 +1065,7 @@ pub const MSG_DONTWAIT: i32 = 0x40;         // Non-blocking operation
 pub const MSG_EOF: i32 = 0x100;             // Data completes transaction
 pub const MSG_NOSIGNAL: i32 = 0x4000;       // Do not generate SIGPIPE
 pub const MSG_MORE: i32 = 0x8000;           // More data coming
+pub const MSG_CMSG_CLOEXEC: i32 = 0x40000000; // Close-on-exec for SCM_RIGHTS descriptors
 
 /// Address utility functions
 pub mod addr {
@@ -877,6 +1097,25 @@ pub mod addr {
         Ok(addr)
     }
     
+    /// Create a Unix domain socket address in the abstract namespace
+    /// 
+    /// Returns the address and the length to pass to bind() or connect(),
+    /// since abstract names are not NUL terminated.
+    pub fn unix_abstract(name: &[u8]) -> PosixResult<(sockaddr_un, socklen_t)> {
+        if name.len() > 107 {
+            return Err(Errno::Enametoolong);
+        }
+        
+        let mut addr = sockaddr_un {
+            sun_family: AF_UNIX as sa_family_t,
+            sun_path: [0; 108],
+        };
+        
+        addr.sun_path[1..=name.len()].copy_from_slice(name);
+        
+        Ok((addr, (core::mem::size_of::<sa_family_t>() + 1 + name.len()) as socklen_t))
+    }
+    
     /// Create an IPv4 socket address
     pub fn ipv4(port: u16, addr: in_addr_t) -> sockaddr_in {
         sockaddr_in {
@@ -942,3 +1181,117 @@ pub mod addr {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stdio;
+
+    const SOCKADDR_IN_LEN: socklen_t = core::mem::size_of::<sockaddr_in>() as socklen_t;
+
+    fn loopback(port: u16) -> sockaddr {
+        let sin = addr::ipv4(port, u32::from_ne_bytes([127, 0, 0, 1]));
+        unsafe { core::mem::transmute::<sockaddr_in, sockaddr>(sin) }
+    }
+
+    fn port_of(name: sockaddr) -> u16 {
+        let sin = unsafe { core::mem::transmute::<sockaddr, sockaddr_in>(name) };
+        u16::from_be(sin.sin_port)
+    }
+
+    fn local_port(fd: fd_t) -> u16 {
+        let mut name = loopback(0);
+        let mut len = SOCKADDR_IN_LEN;
+        getsockname(fd, &mut name, &mut len).unwrap();
+        port_of(name)
+    }
+
+    #[test]
+    fn test_tcp_loopback_round_trip() {
+        let listener = socket(SocketDomain::Inet, SocketType::Stream, SocketProtocol::Any).unwrap();
+        assert!(descriptors::is_local(listener));
+        bind(listener, &loopback(0), SOCKADDR_IN_LEN).unwrap();
+        listen(listener, 4).unwrap();
+
+        let client = socket(SocketDomain::Inet, SocketType::Stream, SocketProtocol::Tcp).unwrap();
+        connect(client, &loopback(local_port(listener)), SOCKADDR_IN_LEN).unwrap();
+
+        let mut peer = loopback(0);
+        let mut peer_len = SOCKADDR_IN_LEN;
+        let server = accept(listener, Some(&mut peer), Some(&mut peer_len)).unwrap();
+        assert_eq!(peer_len, SOCKADDR_IN_LEN);
+        assert_eq!(port_of(peer), local_port(client));
+
+        let mut buf = [0u8; 16];
+        assert_eq!(send(client, b"ping", 0), Ok(4));
+        assert_eq!(recv(server, &mut buf, 0), Ok(4));
+        assert_eq!(&buf[..4], b"ping");
+
+        // read() and write() on the descriptors reach the stack too
+        assert_eq!(stdio::write(server, b"pong"), Ok(4));
+        assert_eq!(stdio::read(client, &mut buf), Ok(4));
+        assert_eq!(&buf[..4], b"pong");
+        assert_eq!(recv(server, &mut buf, MSG_DONTWAIT), Err(Errno::Eagain));
+
+        // The peer sees end of stream once the client closes
+        stdio::close(client).unwrap();
+        assert_eq!(recv(server, &mut buf, 0), Ok(0));
+        stdio::close(server).unwrap();
+        stdio::close(listener).unwrap();
+        assert_eq!(stdio::close(listener), Err(Errno::Ebadf));
+    }
+
+    #[test]
+    fn test_incoming_data_wakes_epoll_wait() {
+        use crate::poll::{epoll_create1, epoll_ctl, epoll_event, epoll_wait, EPOLLIN, EPOLL_CTL_ADD};
+
+        let listener = socket(SocketDomain::Inet, SocketType::Stream, SocketProtocol::Any).unwrap();
+        bind(listener, &loopback(0), SOCKADDR_IN_LEN).unwrap();
+        listen(listener, 4).unwrap();
+        let client = socket(SocketDomain::Inet, SocketType::Stream, SocketProtocol::Tcp).unwrap();
+        connect(client, &loopback(local_port(listener)), SOCKADDR_IN_LEN).unwrap();
+        let server = accept(listener, None, None).unwrap();
+
+        let epfd = epoll_create1(0).unwrap();
+        epoll_ctl(epfd, EPOLL_CTL_ADD, server, Some(&epoll_event { events: EPOLLIN, data: 3 })).unwrap();
+        let mut events = [epoll_event::default(); 4];
+        assert_eq!(epoll_wait(epfd, &mut events, 0), Ok(0));
+
+        // The stack's wakeup for the server reaches the queue
+        let handle = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            send(client, b"data", 0)
+        });
+        assert_eq!(epoll_wait(epfd, &mut events, 5_000), Ok(1));
+        assert_eq!({ events[0].data }, 3);
+        assert_eq!(handle.join().unwrap(), Ok(4));
+
+        let mut buf = [0u8; 8];
+        assert_eq!(recv(server, &mut buf, 0), Ok(4));
+        assert_eq!(epoll_wait(epfd, &mut events, 0), Ok(0));
+
+        for fd in [epfd, server, client, listener] {
+            stdio::close(fd).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_udp_loopback_datagrams() {
+        let receiver = socket(SocketDomain::Inet, SocketType::Datagram, SocketProtocol::Any).unwrap();
+        let sender = socket(SocketDomain::Inet, SocketType::Datagram, SocketProtocol::Udp).unwrap();
+        bind(receiver, &loopback(0), SOCKADDR_IN_LEN).unwrap();
+
+        let dest = loopback(local_port(receiver));
+        assert_eq!(sendto(sender, b"hello", 0, Some(&dest), SOCKADDR_IN_LEN), Ok(5));
+
+        let mut buf = [0u8; 16];
+        let mut from = loopback(0);
+        let mut from_len = SOCKADDR_IN_LEN;
+        assert_eq!(recvfrom(receiver, &mut buf, 0, Some(&mut from), Some(&mut from_len)), Ok(5));
+        assert_eq!(&buf[..5], b"hello");
+        assert_eq!(port_of(from), local_port(sender));
+
+        stdio::close(sender).unwrap();
+        stdio::close(receiver).unwrap();
+    }
+}
@@ -3,6 +3,10 @@
 //! This module provides comprehensive stdio.h compatibility for MultiOS,
 //! including file operations, streams, and formatting functions while
 //! maintaining Rust safety guarantees.
+//!
+//! `File` streams buffer I/O in user space according to their buffering
+//! mode, and the printf family is implemented by a formatter that writes
+//! through a byte sink without allocating.
 
 use crate::errors::*;
 use crate::internal::*;
@@ -10,6 +14,7 @@ use crate::syscall;
 use crate::types::*;
 use core::fmt;
 use core::ptr;
+use spin::Mutex;
 
 /// Maximum filename length
 pub const NAME_MAX: usize = 255;
@@ -17,25 +22,32 @@ pub const NAME_MAX: usize = 255;
 /// Maximum path length
 pub const PATH_MAX: usize = 4096;
 
-/// Standard streams
-pub const stdin: *mut File = 0 as *mut File;
-pub const stdout: *mut File = 1 as *mut File;
-pub const stderr: *mut File = 2 as *mut File;
+/// Default stream buffer size
+pub const BUFSIZ: usize = 8192;
 
-/// File structure
-#[repr(C)]
-#[derive(Debug, Clone, Copy)]
+/// Standard streams, bound to the standard descriptors
+pub static stdin: Mutex<File> = Mutex::new(File::with_mode(STDIN_FILENO, FileFlags::READ.bits(), _IOLBF));
+pub static stdout: Mutex<File> = Mutex::new(File::with_mode(STDOUT_FILENO, FileFlags::WRITE.bits(), _IOLBF));
+pub static stderr: Mutex<File> = Mutex::new(File::with_mode(STDERR_FILENO, FileFlags::WRITE.bits(), _IONBF));
+
+/// Buffered file stream (FILE)
+///
+/// The buffer holds either unread input (`buf_pos..buf_count`) or pending
+/// output (`..buf_count`), never both; `writing` says which.
+#[derive(Debug)]
 pub struct File {
     pub fd: fd_t,                // File descriptor
     pub flags: u32,              // File status flags
     pub mode: mode_t,            // File mode
-    pub offset: off_t,           // Current file offset
+    pub offset: off_t,           // Current stream position
     pub eof: bool,               // End of file flag
     pub error: i32,              // Error code
-    pub buffer: *mut u8,         // I/O buffer
+    pub buffer: Vec<u8>,         // I/O buffer, allocated on first use
     pub buf_size: usize,         // Buffer size
     pub buf_pos: usize,          // Buffer position
     pub buf_count: usize,        // Buffer count
+    pub buf_mode: i32,           // Buffering mode (_IOFBF, _IOLBF, _IONBF)
+    writing: bool,               // Buffer holds pending output
 }
 
 /// File status flags
@@ -124,6 +136,10 @@ pub fn open(pathname: &str, flags: FileFlags, mode: mode_t) -> PosixResult<fd_t>
 pub fn close(fd: fd_t) -> PosixResult<()> {
     check_fd!(fd)?;
     
+    if crate::descriptors::is_local(fd) {
+        return crate::descriptors::close(fd);
+    }
+    
     unsafe {
         let result = syscall::close(fd);
         if result < 0 {
@@ -152,6 +168,13 @@ pub fn read(fd: fd_t, buf: &mut [u8]) -> PosixResult<usize> {
         return Ok(0);
     }
     
+    if crate::descriptors::is_local(fd) {
+        if crate::descriptors::is_socket(fd) {
+            return crate::socket::recv(fd, buf, 0);
+        }
+        return crate::descriptors::read(fd, buf);
+    }
+    
     unsafe {
         let result = syscall::read(fd, buf.as_mut_ptr(), buf.len());
         if result < 0 {
@@ -180,6 +203,13 @@ pub fn write(fd: fd_t, buf: &[u8]) -> PosixResult<usize> {
         return Ok(0);
     }
     
+    if crate::descriptors::is_local(fd) {
+        if crate::descriptors::is_socket(fd) {
+            return crate::socket::send(fd, buf, 0);
+        }
+        return crate::descriptors::write(fd, buf);
+    }
+    
     unsafe {
         let result = syscall::write(fd, buf.as_ptr(), buf.len());
         if result < 0 {
@@ -295,9 +325,11 @@ pub fn dup2(oldfd: fd_t, newfd: fd_t) -> PosixResult<fd_t> {
 pub fn ftruncate(fd: fd_t, length: off_t) -> PosixResult<()> {
     check_fd!(fd)?;
     
-    // In a real implementation, this would call syscall::ftruncate
-    // For now, return not implemented
-    Err(Errno::Enosys)
+    if length < 0 {
+        return Err(Errno::Einval);
+    }
+    
+    syscall::ftruncate(fd, length)
 }
 
 /// Get file status by path
@@ -352,14 +384,7 @@ pub fn access(pathname: &str, mode: mode_t) -> PosixResult<bool> {
 /// # Returns
 /// * `PosixResult<()>` - Success on unlink, error on failure
 pub fn unlink(pathname: &str) -> PosixResult<()> {
-    let path_bytes = pathname.as_bytes();
-    if path_bytes.len() > PATH_MAX {
-        return Err(Errno::Enametoolong);
-    }
-    
-    // In a real implementation, this would call syscall::unlink
-    // For now, return not implemented
-    Err(Errno::Enosys)
+    crate::dirent::unlinkat(crate::dirent::AT_FDCWD, pathname, 0)
 }
 
 /// Create a hard link
@@ -445,16 +470,7 @@ pub fn readlink(linkpath: &str, buf: &mut [u8]) -> PosixResult<usize> {
 /// # Returns
 /// * `PosixResult<()>` - Success on rename, error on failure
 pub fn rename(oldpath: &str, newpath: &str) -> PosixResult<()> {
-    let old_path_bytes = oldpath.as_bytes();
-    let new_path_bytes = newpath.as_bytes();
-    
-    if old_path_bytes.len() > PATH_MAX || new_path_bytes.len() > PATH_MAX {
-        return Err(Errno::Enametoolong);
-    }
-    
-    // In a real implementation, this would call syscall::rename
-    // For now, return not implemented
-    Err(Errno::Enosys)
+    crate::dirent::renameat(crate::dirent::AT_FDCWD, oldpath, crate::dirent::AT_FDCWD, newpath)
 }
 
 /// Change file permissions
@@ -551,14 +567,7 @@ pub fn getcwd(buf: &mut [u8]) -> PosixResult<&str> {
 /// # Returns
 /// * `PosixResult<()>` - Success on mkdir, error on failure
 pub fn mkdir(pathname: &str, mode: mode_t) -> PosixResult<()> {
-    let path_bytes = pathname.as_bytes();
-    if path_bytes.len() > PATH_MAX {
-        return Err(Errno::Enametoolong);
-    }
-    
-    // In a real implementation, this would call syscall::mkdir
-    // For now, return not implemented
-    Err(Errno::Enosys)
+    crate::dirent::mkdirat(crate::dirent::AT_FDCWD, pathname, mode)
 }
 
 /// Remove a directory
@@ -571,117 +580,972 @@ pub fn mkdir(pathname: &str, mode: mode_t) -> PosixResult<()> {
 /// # Returns
 /// * `PosixResult<()>` - Success on rmdir, error on failure
 pub fn rmdir(pathname: &str) -> PosixResult<()> {
-    let path_bytes = pathname.as_bytes();
-    if path_bytes.len() > PATH_MAX {
-        return Err(Errno::Enametoolong);
+    crate::dirent::unlinkat(crate::dirent::AT_FDCWD, pathname, crate::dirent::AT_REMOVEDIR)
+}
+
+impl File {
+    /// Create a fully buffered stream over a descriptor
+    pub const fn new(fd: fd_t) -> Self {
+        Self::with_mode(fd, FileFlags::READ.bits() | FileFlags::WRITE.bits(), _IOFBF)
+    }
+
+    /// Create a stream with the given access flags and buffering mode
+    pub const fn with_mode(fd: fd_t, flags: u32, buf_mode: i32) -> Self {
+        Self {
+            fd,
+            flags,
+            mode: 0,
+            offset: 0,
+            eof: false,
+            error: 0,
+            buffer: Vec::new(),
+            buf_size: BUFSIZ,
+            buf_pos: 0,
+            buf_count: 0,
+            buf_mode,
+            writing: false,
+        }
     }
     
-    // In a real implementation, this would call syscall::rmdir
-    // For now, return not implemented
-    Err(Errno::Enosys)
+    /// Check if the file is at end of file
+    pub fn eof(&self) -> bool {
+        self.eof
+    }
+    
+    /// Check if an error occurred
+    pub fn error(&self) -> i32 {
+        self.error
+    }
+    
+    /// Get the current file descriptor
+    pub fn fd(&self) -> fd_t {
+        self.fd
+    }
+    
+    /// Get the current file offset
+    pub fn offset(&self) -> off_t {
+        self.offset
+    }
+
+    fn readable(&self) -> bool {
+        self.flags & FileFlags::READ.bits() != 0
+    }
+
+    fn writable(&self) -> bool {
+        self.flags & FileFlags::WRITE.bits() != 0
+    }
+
+    /// Record an error on the stream and pass it on
+    fn fail(&mut self, err: Errno) -> Errno {
+        self.error = err as i32;
+        err
+    }
+
+    /// Write out pending output
+    fn flush_output(&mut self) -> PosixResult<()> {
+        let mut done = 0;
+        while done < self.buf_count {
+            let pending = &self.buffer[done..self.buf_count];
+            match syscall::write(self.fd, pending.as_ptr(), pending.len()) {
+                Ok(0) => return Err(self.fail(Errno::Eio)),
+                Ok(n) => done += n as usize,
+                Err(Errno::Eintr) => continue,
+                Err(err) => {
+                    // Keep what was not written so a later flush can retry
+                    self.buffer.copy_within(done..self.buf_count, 0);
+                    self.buf_count -= done;
+                    return Err(self.fail(err));
+                }
+            }
+        }
+        self.buf_count = 0;
+        self.writing = false;
+        Ok(())
+    }
+
+    /// Drop unread input, moving the descriptor back to the stream position
+    fn discard_input(&mut self) -> PosixResult<()> {
+        let unread = (self.buf_count - self.buf_pos) as off_t;
+        if unread > 0 {
+            syscall::lseek(self.fd, -unread, SeekMode::Current).map_err(|err| self.fail(err))?;
+        }
+        self.buf_pos = 0;
+        self.buf_count = 0;
+        Ok(())
+    }
+
+    /// Switch the buffer to output
+    fn start_writing(&mut self) -> PosixResult<()> {
+        if !self.writable() {
+            return Err(self.fail(Errno::Ebadf));
+        }
+        if !self.writing {
+            self.discard_input()?;
+            self.writing = true;
+        }
+        Ok(())
+    }
+
+    /// Switch the buffer to input
+    fn start_reading(&mut self) -> PosixResult<()> {
+        if !self.readable() {
+            return Err(self.fail(Errno::Ebadf));
+        }
+        if self.writing {
+            self.flush_output()?;
+        }
+        Ok(())
+    }
+
+    /// Read from the descriptor, retrying interrupted calls
+    fn read_fd(&mut self, buf: &mut [u8]) -> PosixResult<usize> {
+        loop {
+            match syscall::read(self.fd, buf.as_mut_ptr(), buf.len()) {
+                Ok(0) => {
+                    self.eof = true;
+                    return Ok(0);
+                }
+                Ok(n) => return Ok(n as usize),
+                Err(Errno::Eintr) => continue,
+                Err(err) => return Err(self.fail(err)),
+            }
+        }
+    }
+
+    /// Refill an empty input buffer; returns the number of bytes now buffered
+    fn fill_buffer(&mut self) -> PosixResult<usize> {
+        if self.buf_pos < self.buf_count {
+            return Ok(self.buf_count - self.buf_pos);
+        }
+        let size = self.buf_size.max(1);
+        if self.buffer.len() < size {
+            self.buffer.resize(size, 0);
+        }
+        let mut buffer = core::mem::take(&mut self.buffer);
+        let result = self.read_fd(&mut buffer[..size]);
+        self.buffer = buffer;
+        self.buf_pos = 0;
+        self.buf_count = result?;
+        Ok(self.buf_count)
+    }
+}
+
+impl fmt::Write for File {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        fwrite(s.as_bytes(), self).map(|_| ()).map_err(|_| fmt::Error)
+    }
 }
 
-/// Open a directory stream
+/// Parse an fopen() mode string into access flags and open flags
+fn parse_fopen_mode(mode: &str) -> PosixResult<(u32, OpenFlags)> {
+    let bytes = mode.as_bytes();
+    let plus = bytes[1..].contains(&b'+');
+    let (access, open) = match bytes.first() {
+        Some(b'r') => (FileFlags::READ, OpenFlags::empty()),
+        Some(b'w') => (FileFlags::WRITE, OpenFlags::CREAT | OpenFlags::TRUNC),
+        Some(b'a') => (FileFlags::WRITE | FileFlags::APPEND, OpenFlags::CREAT | OpenFlags::APPEND),
+        _ => return Err(Errno::Einval),
+    };
+    let access = if plus { access | FileFlags::READ | FileFlags::WRITE } else { access };
+    let mut open = open;
+    if access.contains(FileFlags::READ) {
+        open |= OpenFlags::READ;
+    }
+    if access.contains(FileFlags::WRITE) {
+        open |= OpenFlags::WRITE;
+    }
+    if bytes[1..].contains(&b'x') {
+        open |= OpenFlags::EXCL;
+    }
+    Ok((access.bits(), open))
+}
+
+/// Open a stream
 /// 
-/// This function provides compatibility with the POSIX opendir() function.
+/// This function provides compatibility with the POSIX fopen() function.
 /// 
 /// # Arguments
-/// * `name` - Directory path
+/// * `pathname` - Path to the file to open
+/// * `mode` - Access mode ("r", "w", "a", optionally with "+", "b" or "x")
 /// 
 /// # Returns
-/// * `PosixResult<*mut Dir>` - Directory stream pointer, error on failure
-pub fn opendir(name: &str) -> PosixResult<*mut Dir> {
-    let path_bytes = name.as_bytes();
+/// * `PosixResult<File>` - Fully buffered stream on success, error on failure
+pub fn fopen(pathname: &str, mode: &str) -> PosixResult<File> {
+    let (access, open_flags) = parse_fopen_mode(mode)?;
+    let path_bytes = pathname.as_bytes();
     if path_bytes.len() > PATH_MAX {
         return Err(Errno::Enametoolong);
     }
-    
-    // In a real implementation, this would create a directory stream
-    // For now, return not implemented
-    Err(Errno::Enosys)
+
+    let mut path_buf = [0u8; PATH_MAX + 1];
+    path_buf[..path_bytes.len()].copy_from_slice(path_bytes);
+    let fd = syscall::open(path_buf.as_ptr(), open_flags, 0o666)?;
+    fdopen(fd, access)
 }
 
-/// Close a directory stream
+/// Associate a stream with an open descriptor
 /// 
-/// This function provides compatibility with the POSIX closedir() function.
+/// This function provides compatibility with the POSIX fdopen() function.
+/// Terminals are line buffered; everything else is fully buffered.
 /// 
 /// # Arguments
-/// * `dirp` - Directory stream pointer
+/// * `fd` - Open file descriptor
+/// * `flags` - Stream access flags (`FileFlags` bits)
 /// 
 /// # Returns
-/// * `PosixResult<()>` - Success on closedir, error on failure
-pub fn closedir(dirp: *mut Dir) -> PosixResult<()> {
-    if dirp.is_null() {
-        return Err(Errno::Ebadf);
+/// * `PosixResult<File>` - Stream on success, error on failure
+pub fn fdopen(fd: fd_t, flags: u32) -> PosixResult<File> {
+    check_fd!(fd)?;
+    let buf_mode = if fd <= STDERR_FILENO { _IOLBF } else { _IOFBF };
+    Ok(File::with_mode(fd, flags, buf_mode))
+}
+
+/// Flush and close a stream
+/// 
+/// This function provides compatibility with the POSIX fclose() function.
+/// The descriptor is closed even when flushing fails.
+/// 
+/// # Arguments
+/// * `file` - Stream to close
+/// 
+/// # Returns
+/// * `PosixResult<()>` - Success on close, error on failure
+pub fn fclose(mut file: File) -> PosixResult<()> {
+    let flushed = fflush(&mut file);
+    syscall::close(file.fd)?;
+    flushed
+}
+
+/// Flush a stream
+/// 
+/// This function provides compatibility with the POSIX fflush() function.
+/// 
+/// # Arguments
+/// * `file` - Stream to flush
+/// 
+/// # Returns
+/// * `PosixResult<()>` - Success on flush, error on failure
+pub fn fflush(file: &mut File) -> PosixResult<()> {
+    if file.writing {
+        file.flush_output()
+    } else {
+        Ok(())
     }
-    
-    // In a real implementation, this would close the directory stream
-    // For now, return not implemented
-    Err(Errno::Enosys)
 }
 
-/// Read a directory entry
+/// Set the buffering mode of a stream
 /// 
-/// This function provides compatibility with the POSIX readdir() function.
+/// This function provides compatibility with the POSIX setvbuf() function.
+/// It must be called before any other operation on the stream.
 /// 
 /// # Arguments
-/// * `dirp` - Directory stream pointer
+/// * `file` - Stream to configure
+/// * `mode` - Buffering mode (_IOFBF, _IOLBF or _IONBF)
+/// * `size` - Buffer size (0 for the default)
 /// 
 /// # Returns
-/// * `PosixResult<*mut dirent>` - Directory entry pointer, error on failure
-pub fn readdir(dirp: *mut Dir) -> PosixResult<*mut dirent> {
-    if dirp.is_null() {
-        return Err(Errno::Ebadf);
+/// * `PosixResult<()>` - Success on set, error on failure
+pub fn setvbuf(file: &mut File, mode: i32, size: usize) -> PosixResult<()> {
+    if !matches!(mode, _IOFBF | _IOLBF | _IONBF) {
+        return Err(Errno::Einval);
     }
-    
-    // In a real implementation, this would read the next directory entry
-    // For now, return not implemented
-    Err(Errno::Enosys)
+    if file.buf_count != 0 {
+        return Err(Errno::Ebusy);
+    }
+    file.buf_mode = mode;
+    file.buf_size = if size == 0 { BUFSIZ } else { size };
+    file.buffer = Vec::new();
+    Ok(())
 }
 
-/// Directory stream structure
-pub type Dir = core::ffi::c_void;
+/// Read items from a stream
+/// 
+/// This function provides compatibility with the POSIX fread() function,
+/// counting bytes rather than items.
+/// 
+/// # Arguments
+/// * `buf` - Buffer to read into
+/// * `file` - Stream to read from
+/// 
+/// # Returns
+/// * `PosixResult<usize>` - Number of bytes read (short at end of file), error on failure
+pub fn fread(buf: &mut [u8], file: &mut File) -> PosixResult<usize> {
+    file.start_reading()?;
+    let mut done = 0;
+    while done < buf.len() {
+        let buffered = file.buf_count - file.buf_pos;
+        if buffered > 0 {
+            let n = buffered.min(buf.len() - done);
+            buf[done..done + n].copy_from_slice(&file.buffer[file.buf_pos..file.buf_pos + n]);
+            file.buf_pos += n;
+            done += n;
+            continue;
+        }
 
-/// Directory entry structure
-pub type dirent = crate::internal::dirent;
+        // Large or unbuffered reads bypass the buffer
+        if file.buf_mode == _IONBF || buf.len() - done >= file.buf_size {
+            let n = file.read_fd(&mut buf[done..])?;
+            if n == 0 {
+                break;
+            }
+            done += n;
+        } else if file.fill_buffer()? == 0 {
+            break;
+        }
+    }
+    file.offset += done as off_t;
+    Ok(done)
+}
 
-/// Common file operations using File structure
-impl File {
-    /// Create a new File structure
-    pub fn new(fd: fd_t) -> Self {
-        Self {
-            fd,
-            flags: 0,
-            mode: 0,
-            offset: 0,
-            eof: false,
-            error: 0,
-            buffer: ptr::null_mut(),
-            buf_size: 0,
-            buf_pos: 0,
-            buf_count: 0,
+/// Write items to a stream
+/// 
+/// This function provides compatibility with the POSIX fwrite() function,
+/// counting bytes rather than items.
+/// 
+/// # Arguments
+/// * `buf` - Data to write
+/// * `file` - Stream to write to
+/// 
+/// # Returns
+/// * `PosixResult<usize>` - Number of bytes accepted, error on failure
+pub fn fwrite(buf: &[u8], file: &mut File) -> PosixResult<usize> {
+    file.start_writing()?;
+    if file.buf_mode == _IONBF {
+        file.flush_output()?;
+        let mut done = 0;
+        while done < buf.len() {
+            match syscall::write(file.fd, buf[done..].as_ptr(), buf.len() - done) {
+                Ok(n) => done += n as usize,
+                Err(Errno::Eintr) => continue,
+                Err(err) => return Err(file.fail(err)),
+            }
         }
+        file.offset += done as off_t;
+        return Ok(done);
     }
-    
-    /// Check if the file is at end of file
-    pub fn eof(&self) -> bool {
-        self.eof
+
+    if file.buffer.len() < file.buf_size {
+        file.buffer.resize(file.buf_size, 0);
     }
-    
-    /// Check if an error occurred
-    pub fn error(&self) -> i32 {
-        self.error
+    let mut done = 0;
+    while done < buf.len() {
+        if file.buf_count == file.buf_size {
+            file.flush_output()?;
+            file.writing = true;
+        }
+        let n = (file.buf_size - file.buf_count).min(buf.len() - done);
+        file.buffer[file.buf_count..file.buf_count + n].copy_from_slice(&buf[done..done + n]);
+        file.buf_count += n;
+        done += n;
     }
-    
-    /// Get the current file descriptor
-    pub fn fd(&self) -> fd_t {
-        self.fd
+    file.offset += done as off_t;
+
+    if file.buf_mode == _IOLBF && buf.contains(&b'\n') {
+        file.flush_output()?;
     }
-    
-    /// Get the current file offset
-    pub fn offset(&self) -> off_t {
-        self.offset
+    Ok(done)
+}
+
+/// Reposition a stream
+/// 
+/// This function provides compatibility with the POSIX fseek() function.
+/// 
+/// # Arguments
+/// * `file` - Stream to reposition
+/// * `offset` - Offset relative to `whence`
+/// * `whence` - SEEK_SET, SEEK_CUR or SEEK_END
+/// 
+/// # Returns
+/// * `PosixResult<()>` - Success on seek, error on failure
+pub fn fseek(file: &mut File, offset: off_t, whence: i32) -> PosixResult<()> {
+    let whence = match whence {
+        SEEK_SET => SeekMode::Set,
+        SEEK_CUR => SeekMode::Current,
+        SEEK_END => SeekMode::End,
+        _ => return Err(Errno::Einval),
+    };
+    fflush(file)?;
+    file.discard_input()?;
+    file.offset = syscall::lseek(file.fd, offset, whence).map_err(|err| file.fail(err))?;
+    file.eof = false;
+    Ok(())
+}
+
+/// Report the position of a stream
+/// 
+/// This function provides compatibility with the POSIX ftell() function.
+/// 
+/// # Arguments
+/// * `file` - Stream to query
+/// 
+/// # Returns
+/// * `PosixResult<off_t>` - Current position, accounting for buffered data
+pub fn ftell(file: &File) -> PosixResult<off_t> {
+    Ok(file.offset)
+}
+
+/// Rewind a stream to its start and clear its error indicators
+/// 
+/// This function provides compatibility with the POSIX rewind() function.
+pub fn rewind(file: &mut File) {
+    if fseek(file, 0, SEEK_SET).is_ok() {
+        clearerr(file);
+    }
+}
+
+/// Clear the end-of-file and error indicators
+/// 
+/// This function provides compatibility with the POSIX clearerr() function.
+pub fn clearerr(file: &mut File) {
+    file.eof = false;
+    file.error = 0;
+}
+
+/// Test the end-of-file indicator
+/// 
+/// This function provides compatibility with the POSIX feof() function.
+pub fn feof(file: &File) -> bool {
+    file.eof
+}
+
+/// Test the error indicator
+/// 
+/// This function provides compatibility with the POSIX ferror() function.
+pub fn ferror(file: &File) -> bool {
+    file.error != 0
+}
+
+/// Read a byte from a stream
+/// 
+/// This function provides compatibility with the POSIX fgetc() function.
+/// 
+/// # Returns
+/// * `PosixResult<Option<u8>>` - Next byte, `None` at end of file
+pub fn fgetc(file: &mut File) -> PosixResult<Option<u8>> {
+    let mut byte = [0u8];
+    Ok(if fread(&mut byte, file)? == 1 { Some(byte[0]) } else { None })
+}
+
+/// Write a byte to a stream
+/// 
+/// This function provides compatibility with the POSIX fputc() function.
+pub fn fputc(byte: u8, file: &mut File) -> PosixResult<()> {
+    fwrite(&[byte], file).map(|_| ())
+}
+
+/// Write a string to a stream, without a trailing newline
+/// 
+/// This function provides compatibility with the POSIX fputs() function.
+/// 
+/// # Returns
+/// * `PosixResult<usize>` - Number of bytes written, error on failure
+pub fn fputs(s: &str, file: &mut File) -> PosixResult<usize> {
+    fwrite(s.as_bytes(), file)
+}
+
+/// Read a line from a stream
+/// 
+/// This function provides compatibility with the POSIX fgets() function.
+/// Reading stops after a newline (which is kept) or when `buf` is full;
+/// the result is NUL terminated.
+/// 
+/// # Returns
+/// * `PosixResult<usize>` - Number of bytes read, excluding the terminator
+pub fn fgets(buf: &mut [u8], file: &mut File) -> PosixResult<usize> {
+    if buf.is_empty() {
+        return Ok(0);
+    }
+    let mut count = 0;
+    while count < buf.len() - 1 {
+        match fgetc(file)? {
+            Some(byte) => {
+                buf[count] = byte;
+                count += 1;
+                if byte == b'\n' {
+                    break;
+                }
+            }
+            None => break,
+        }
+    }
+    buf[count] = 0;
+    Ok(count)
+}
+
+/// Argument to a printf-family function
+#[derive(Debug, Clone, Copy)]
+pub enum PrintfArg<'a> {
+    Int(i64),
+    Uint(u64),
+    Float(f64),
+    Char(u8),
+    Str(&'a str),
+    Ptr(usize),
+}
+
+/// Destination for formatted output
+pub trait PrintfSink {
+    fn put(&mut self, bytes: &[u8]) -> PosixResult<()>;
+}
+
+impl PrintfSink for File {
+    fn put(&mut self, bytes: &[u8]) -> PosixResult<()> {
+        fwrite(bytes, self).map(|_| ())
+    }
+}
+
+/// Sink writing into a fixed buffer, keeping room for the NUL terminator
+struct SliceSink<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl PrintfSink for SliceSink<'_> {
+    fn put(&mut self, bytes: &[u8]) -> PosixResult<()> {
+        let room = self.buf.len().saturating_sub(1).saturating_sub(self.len);
+        let n = room.min(bytes.len());
+        self.buf[self.len..self.len + n].copy_from_slice(&bytes[..n]);
+        self.len += n;
+        Ok(())
+    }
+}
+
+/// Parsed conversion specification
+#[derive(Debug, Clone, Copy, Default)]
+struct Spec {
+    left: bool,
+    plus: bool,
+    space: bool,
+    alt: bool,
+    zero: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+/// Counts bytes on their way to the real sink
+struct Counter<'a> {
+    sink: &'a mut dyn PrintfSink,
+    count: usize,
+}
+
+impl Counter<'_> {
+    fn put(&mut self, bytes: &[u8]) -> PosixResult<()> {
+        self.count += bytes.len();
+        self.sink.put(bytes)
+    }
+
+    fn pad(&mut self, byte: u8, n: usize) -> PosixResult<()> {
+        let chunk = [byte; 16];
+        let mut left = n;
+        while left > 0 {
+            let step = left.min(chunk.len());
+            self.put(&chunk[..step])?;
+            left -= step;
+        }
+        Ok(())
+    }
+
+    /// Emit `sign`, `prefix` and `digits` padded to the spec's width
+    fn field(&mut self, spec: &Spec, sign: &[u8], prefix: &[u8], digits: &[u8], zeros: usize) -> PosixResult<()> {
+        let len = sign.len() + prefix.len() + zeros + digits.len();
+        let fill = spec.width.saturating_sub(len);
+        if !spec.left && !spec.zero {
+            self.pad(b' ', fill)?;
+        }
+        self.put(sign)?;
+        self.put(prefix)?;
+        if !spec.left && spec.zero {
+            self.pad(b'0', fill)?;
+        }
+        self.pad(b'0', zeros)?;
+        self.put(digits)?;
+        if spec.left {
+            self.pad(b' ', fill)?;
+        }
+        Ok(())
+    }
+}
+
+/// Render `value` in `base` into the tail of `buf`, returning the digits
+fn render_digits(buf: &mut [u8; 64], mut value: u64, base: u64, upper: bool) -> &[u8] {
+    let table: &[u8; 16] = if upper { b"0123456789ABCDEF" } else { b"0123456789abcdef" };
+    let mut pos = buf.len();
+    loop {
+        pos -= 1;
+        buf[pos] = table[(value % base) as usize];
+        value /= base;
+        if value == 0 {
+            break;
+        }
+    }
+    &buf[pos..]
+}
+
+fn sign_of<'a>(negative: bool, spec: &Spec) -> &'a [u8] {
+    if negative {
+        b"-"
+    } else if spec.plus {
+        b"+"
+    } else if spec.space {
+        b" "
+    } else {
+        b""
+    }
+}
+
+/// Format `fmt` with C printf semantics into `sink`
+///
+/// Supports the `-+ #0` flags, `*` width and precision, the `hh h l ll z j t L`
+/// length modifiers (arguments already carry their width), and the
+/// `d i u o x X c s p f F e E g G %` conversions.
+///
+/// # Returns
+/// * `PosixResult<usize>` - Number of bytes produced
+pub fn printf_format(sink: &mut dyn PrintfSink, fmt: &str, args: &[PrintfArg]) -> PosixResult<usize> {
+    let fmt = fmt.as_bytes();
+    let mut out = Counter { sink, count: 0 };
+    let mut args = args.iter();
+    let mut i = 0;
+
+    while i < fmt.len() {
+        let start = i;
+        while i < fmt.len() && fmt[i] != b'%' {
+            i += 1;
+        }
+        out.put(&fmt[start..i])?;
+        if i >= fmt.len() {
+            break;
+        }
+        i += 1;
+
+        let mut spec = Spec::default();
+        while i < fmt.len() {
+            match fmt[i] {
+                b'-' => spec.left = true,
+                b'+' => spec.plus = true,
+                b' ' => spec.space = true,
+                b'#' => spec.alt = true,
+                b'0' => spec.zero = true,
+                _ => break,
+            }
+            i += 1;
+        }
+        if fmt.get(i) == Some(&b'*') {
+            let width = next_int(&mut args)?;
+            spec.left |= width < 0;
+            spec.width = width.unsigned_abs() as usize;
+            i += 1;
+        } else {
+            spec.width = parse_number(fmt, &mut i);
+        }
+        if fmt.get(i) == Some(&b'.') {
+            i += 1;
+            if fmt.get(i) == Some(&b'*') {
+                let precision = next_int(&mut args)?;
+                spec.precision = if precision < 0 { None } else { Some(precision as usize) };
+                i += 1;
+            } else {
+                spec.precision = Some(parse_number(fmt, &mut i));
+            }
+        }
+        while i < fmt.len() && matches!(fmt[i], b'h' | b'l' | b'z' | b'j' | b't' | b'L' | b'q') {
+            i += 1;
+        }
+
+        let conv = *fmt.get(i).ok_or(Errno::Einval)?;
+        i += 1;
+        if conv == b'%' {
+            out.put(b"%")?;
+            continue;
+        }
+        let arg = *args.next().ok_or(Errno::Einval)?;
+        format_one(&mut out, conv, spec, arg)?;
+    }
+    Ok(out.count)
+}
+
+fn parse_number(fmt: &[u8], i: &mut usize) -> usize {
+    let mut value = 0usize;
+    while let Some(digit) = fmt.get(*i).filter(|b| b.is_ascii_digit()) {
+        value = value.saturating_mul(10).saturating_add((digit - b'0') as usize);
+        *i += 1;
+    }
+    value
+}
+
+fn next_int<'a>(args: &mut impl Iterator<Item = &'a PrintfArg<'a>>) -> PosixResult<i64> {
+    match args.next() {
+        Some(PrintfArg::Int(value)) => Ok(*value),
+        Some(PrintfArg::Uint(value)) => Ok(*value as i64),
+        _ => Err(Errno::Einval),
+    }
+}
+
+fn format_one(out: &mut Counter, conv: u8, mut spec: Spec, arg: PrintfArg) -> PosixResult<()> {
+    let mut digits = [0u8; 64];
+    match conv {
+        b'd' | b'i' | b'u' | b'o' | b'x' | b'X' => {
+            let (negative, magnitude) = match (conv, arg) {
+                (b'd' | b'i', PrintfArg::Int(value)) => (value < 0, value.unsigned_abs()),
+                (_, PrintfArg::Int(value)) => (false, value as u64),
+                (_, PrintfArg::Uint(value)) => (false, value),
+                (_, PrintfArg::Char(value)) => (false, value as u64),
+                (_, PrintfArg::Ptr(value)) => (false, value as u64),
+                _ => return Err(Errno::Einval),
+            };
+            let base = match conv {
+                b'o' => 8,
+                b'x' | b'X' => 16,
+                _ => 10,
+            };
+            let rendered = render_digits(&mut digits, magnitude, base, conv == b'X');
+            // An explicit precision of zero prints nothing for zero
+            let rendered = if magnitude == 0 && spec.precision == Some(0) { &[][..] } else { rendered };
+            let mut zeros = spec.precision.map_or(0, |p| p.saturating_sub(rendered.len()));
+            if spec.precision.is_some() {
+                spec.zero = false;
+            }
+            let prefix: &[u8] = match conv {
+                b'x' if spec.alt && magnitude != 0 => b"0x",
+                b'X' if spec.alt && magnitude != 0 => b"0X",
+                b'o' if spec.alt && zeros == 0 && rendered.first() != Some(&b'0') => {
+                    zeros = 1;
+                    b""
+                }
+                _ => b"",
+            };
+            let sign = if matches!(conv, b'd' | b'i') { sign_of(negative, &spec) } else { b"" };
+            out.field(&spec, sign, prefix, rendered, zeros)
+        }
+        b'c' => {
+            let byte = match arg {
+                PrintfArg::Char(value) => value,
+                PrintfArg::Int(value) => value as u8,
+                PrintfArg::Uint(value) => value as u8,
+                _ => return Err(Errno::Einval),
+            };
+            spec.zero = false;
+            out.field(&spec, b"", b"", &[byte], 0)
+        }
+        b's' => {
+            let s = match arg {
+                PrintfArg::Str(value) => value.as_bytes(),
+                _ => return Err(Errno::Einval),
+            };
+            let s = &s[..spec.precision.map_or(s.len(), |p| p.min(s.len()))];
+            spec.zero = false;
+            out.field(&spec, b"", b"", s, 0)
+        }
+        b'p' => {
+            let value = match arg {
+                PrintfArg::Ptr(value) => value as u64,
+                PrintfArg::Uint(value) => value,
+                _ => return Err(Errno::Einval),
+            };
+            let rendered = render_digits(&mut digits, value, 16, false);
+            out.field(&spec, b"", b"0x", rendered, 0)
+        }
+        b'f' | b'F' | b'e' | b'E' | b'g' | b'G' => {
+            let value = match arg {
+                PrintfArg::Float(value) => value,
+                PrintfArg::Int(value) => value as f64,
+                PrintfArg::Uint(value) => value as f64,
+                _ => return Err(Errno::Einval),
+            };
+            format_float(out, conv, spec, value)
+        }
+        _ => Err(Errno::Einval),
+    }
+}
+
+/// Floating point conversions, computed with integer arithmetic only
+fn format_float(out: &mut Counter, conv: u8, mut spec: Spec, value: f64) -> PosixResult<()> {
+    let upper = conv.is_ascii_uppercase();
+    let negative = value.is_sign_negative();
+    let sign = sign_of(negative, &spec);
+    if !value.is_finite() {
+        spec.zero = false;
+        let text: &[u8] = match (value.is_nan(), upper) {
+            (true, false) => b"nan",
+            (true, true) => b"NAN",
+            (false, false) => b"inf",
+            (false, true) => b"INF",
+        };
+        return out.field(&spec, sign, b"", text, 0);
+    }
+
+    let magnitude = if negative { -value } else { value };
+    let mut precision = spec.precision.unwrap_or(6);
+    let mut exponential = matches!(conv, b'e' | b'E');
+    let mut exponent = decimal_exponent(magnitude);
+    let mut trim = false;
+    if matches!(conv, b'g' | b'G') {
+        // %g picks %e or %f by exponent and drops trailing zeros
+        let significant = precision.max(1);
+        exponential = exponent < -4 || exponent >= significant as i32;
+        precision = if exponential { significant - 1 } else { (significant as i32 - 1 - exponent).max(0) as usize };
+        trim = !spec.alt;
+    }
+
+    let scaled = if exponential { magnitude / pow10(exponent) } else { magnitude };
+    let (mut text, mut frac_digits) = split_fixed(scaled, precision);
+    if exponential && text.len() > 1 {
+        // Rounding carried into another digit
+        exponent += 1;
+        (text, frac_digits) = split_fixed(magnitude / pow10(exponent), precision);
+    }
+
+    let frac_len = if trim {
+        frac_digits.iter().rposition(|&d| d != b'0').map_or(0, |p| p + 1)
+    } else {
+        frac_digits.len()
+    };
+    if frac_len > 0 || spec.alt {
+        text.push(b'.');
+    }
+    text.extend_from_slice(&frac_digits[..frac_len]);
+
+    if exponential {
+        text.push(if upper { b'E' } else { b'e' });
+        text.push(if exponent < 0 { b'-' } else { b'+' });
+        let mut exp_digits = [0u8; 64];
+        let rendered = render_digits(&mut exp_digits, exponent.unsigned_abs() as u64, 10, false);
+        if rendered.len() < 2 {
+            text.push(b'0');
+        }
+        text.extend_from_slice(rendered);
+    }
+    out.field(&spec, sign, b"", &text, 0)
+}
+
+fn pow10(exponent: i32) -> f64 {
+    let mut result = 1.0;
+    let base = if exponent < 0 { 0.1 } else { 10.0 };
+    for _ in 0..exponent.unsigned_abs() {
+        result *= base;
+    }
+    result
+}
+
+/// Exponent of the leading decimal digit of a non-negative finite value
+fn decimal_exponent(value: f64) -> i32 {
+    if value == 0.0 {
+        return 0;
+    }
+    let mut exponent = 0;
+    let mut scaled = value;
+    while scaled >= 10.0 {
+        scaled /= 10.0;
+        exponent += 1;
+    }
+    while scaled < 1.0 {
+        scaled *= 10.0;
+        exponent -= 1;
+    }
+    exponent
+}
+
+/// Split into integer digits and `precision` rounded fraction digits
+fn split_fixed(value: f64, precision: usize) -> (Vec<u8>, Vec<u8>) {
+    let (mut int_digits, frac) = if value < TWO_POW_64 {
+        let int_part = value as u64;
+        let mut buf = [0u8; 64];
+        (render_digits(&mut buf, int_part, 10, false).to_vec(), value - int_part as f64)
+    } else {
+        (large_integer_digits(value), 0.0)
+    };
+
+    // A double carries at most 17 significant digits; the rest print as zeros
+    let exact = precision.min(17);
+    let scale = 10u64.pow(exact as u32);
+    let mut frac = (frac * scale as f64 + 0.5) as u64;
+    if frac >= scale {
+        frac -= scale;
+        increment_digits(&mut int_digits);
+    }
+    let mut frac_digits = vec![b'0'; precision];
+    for slot in frac_digits[..exact].iter_mut().rev() {
+        *slot = b'0' + (frac % 10) as u8;
+        frac /= 10;
+    }
+    (int_digits, frac_digits)
+}
+
+const TWO_POW_64: f64 = 18_446_744_073_709_551_616.0;
+
+/// Exact decimal digits of an integral value of at least 2^64
+fn large_integer_digits(value: f64) -> Vec<u8> {
+    // value is mantissa * 2^shift; double the mantissa's digits shift times
+    let bits = value.to_bits();
+    let mantissa = (bits & ((1 << 52) - 1)) | (1 << 52);
+    let shift = ((bits >> 52) & 0x7ff) as usize - 1075;
+
+    let mut buf = [0u8; 64];
+    let mut digits: Vec<u8> = render_digits(&mut buf, mantissa, 10, false).iter().rev().map(|d| d - b'0').collect();
+    for _ in 0..shift {
+        let mut carry = 0;
+        for digit in digits.iter_mut() {
+            let doubled = *digit * 2 + carry;
+            *digit = doubled % 10;
+            carry = doubled / 10;
+        }
+        if carry != 0 {
+            digits.push(carry);
+        }
     }
+    digits.iter().rev().map(|d| d + b'0').collect()
+}
+
+/// Add one to a string of decimal digits
+fn increment_digits(digits: &mut Vec<u8>) {
+    for digit in digits.iter_mut().rev() {
+        if *digit == b'9' {
+            *digit = b'0';
+        } else {
+            *digit += 1;
+            return;
+        }
+    }
+    digits.insert(0, b'1');
+}
+
+/// Formatted output to a stream
+/// 
+/// This function provides compatibility with the POSIX fprintf() function.
+/// 
+/// # Arguments
+/// * `file` - Stream to write to
+/// * `fmt` - printf format string
+/// * `args` - Arguments consumed by the conversions
+/// 
+/// # Returns
+/// * `PosixResult<usize>` - Number of bytes written, error on failure
+pub fn fprintf(file: &mut File, fmt: &str, args: &[PrintfArg]) -> PosixResult<usize> {
+    printf_format(file, fmt, args)
+}
+
+/// Formatted output to standard output
+/// 
+/// This function provides compatibility with the POSIX printf() function.
+pub fn printf(fmt: &str, args: &[PrintfArg]) -> PosixResult<usize> {
+    printf_format(&mut *stdout.lock(), fmt, args)
+}
+
+/// Formatted output to a buffer
+/// 
+/// This function provides compatibility with the POSIX snprintf() function.
+/// Output is truncated to fit `buf` and always NUL terminated when `buf` is
+/// not empty.
+/// 
+/// # Returns
+/// * `PosixResult<usize>` - Length the full output would have had
+pub fn snprintf(buf: &mut [u8], fmt: &str, args: &[PrintfArg]) -> PosixResult<usize> {
+    let mut sink = SliceSink { buf, len: 0 };
+    let total = printf_format(&mut sink, fmt, args)?;
+    if let Some(terminator) = sink.buf.get_mut(sink.len) {
+        *terminator = 0;
+    }
+    Ok(total)
 }
 
 /// Helper functions for common file operations
@@ -689,50 +1553,20 @@ pub mod helpers {
     use super::*;
     
     /// Read a line from a file
-    pub fn fgets(buf: &mut [u8], file: &File) -> PosixResult<usize> {
-        if buf.is_empty() {
-            return Ok(0);
-        }
-        
-        let mut count = 0;
-        let mut ch;
-        
-        while count < buf.len() - 1 {
-            match read(file.fd(), &mut [0]) {
-                Ok(n) => {
-                    if n == 0 {
-                        break;
-                    }
-                    ch = unsafe { core::ptr::read_volatile(0 as *const u8) };
-                    buf[count] = ch;
-                    count += 1;
-                    
-                    if ch == b'\n' {
-                        break;
-                    }
-                }
-                Err(err) => {
-                    return Err(err);
-                }
-            }
-        }
-        
-        buf[count] = 0; // Null terminate
-        Ok(count)
+    pub fn fgets(buf: &mut [u8], file: &mut File) -> PosixResult<usize> {
+        super::fgets(buf, file)
     }
     
     /// Write a line to a file
-    pub fn fputs(line: &str, file: &File) -> PosixResult<usize> {
-        let mut buf = Vec::from(line);
-        buf.push(b'\n');
-        
-        write(file.fd(), &buf)
+    pub fn fputs(line: &str, file: &mut File) -> PosixResult<usize> {
+        let written = super::fputs(line, file)?;
+        super::fputc(b'\n', file)?;
+        Ok(written + 1)
     }
     
     /// Format and write to a file
-    pub fn fprintf(file: &File, fmt: &str, args: fmt::Arguments) -> PosixResult<usize> {
-        let formatted = fmt::format(fmt);
-        write(file.fd(), formatted.as_bytes())
+    pub fn fprintf(file: &mut File, fmt: &str, args: &[PrintfArg]) -> PosixResult<usize> {
+        super::fprintf(file, fmt, args)
     }
     
     /// Format and read from a file
@@ -815,3 +1649,61 @@ pub mod utils {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(fmt: &str, args: &[PrintfArg]) -> String {
+        let mut buf = [0u8; 512];
+        let len = snprintf(&mut buf, fmt, args).unwrap();
+        assert!(len < buf.len());
+        String::from_utf8(buf[..len].to_vec()).unwrap()
+    }
+
+    #[test]
+    fn test_printf_integers_and_strings() {
+        assert_eq!(format("%5d|%-5d|%05d", &[PrintfArg::Int(42), PrintfArg::Int(-7), PrintfArg::Int(-7)]), "   42|-7   |-0007");
+        assert_eq!(format("%#x %#o %X", &[PrintfArg::Uint(255), PrintfArg::Uint(8), PrintfArg::Uint(0xbeef)]), "0xff 010 BEEF");
+        assert_eq!(format("%.3s|%c|%%", &[PrintfArg::Str("abcdef"), PrintfArg::Char(b'z')]), "abc|z|%");
+    }
+
+    #[test]
+    fn test_printf_floats() {
+        assert_eq!(format("%f", &[PrintfArg::Float(3.14159)]), "3.141590");
+        assert_eq!(format("%.2f", &[PrintfArg::Float(-0.126)]), "-0.13");
+        assert_eq!(format("%e", &[PrintfArg::Float(12345.678)]), "1.234568e+04");
+        assert_eq!(format("%g %g", &[PrintfArg::Float(0.0001), PrintfArg::Float(100000.0)]), "0.0001 100000");
+        assert_eq!(format("%8.3f|%-8.1f|", &[PrintfArg::Float(2.5), PrintfArg::Float(2.3)]), "   2.500|2.3     |");
+        assert_eq!(format("%f %F", &[PrintfArg::Float(f64::INFINITY), PrintfArg::Float(f64::NAN)]), "inf NAN");
+    }
+
+    #[test]
+    fn test_printf_large_floats() {
+        assert_eq!(format("%f", &[PrintfArg::Float(1e30)]), "1000000000000000019884624838656.000000");
+        assert_eq!(format("%.0f", &[PrintfArg::Float(18446744073709551616.0)]), "18446744073709551616");
+        assert_eq!(format("%.1f", &[PrintfArg::Float(-18446744073709559808.0)]), "-18446744073709559808.0");
+        assert_eq!(format("%e %g", &[PrintfArg::Float(1e30), PrintfArg::Float(1e30)]), "1.000000e+30 1e+30");
+        let huge = format("%.0f", &[PrintfArg::Float(1e300)]);
+        assert_eq!(huge.len(), 301);
+        assert!(huge.starts_with("1000000000000000052504760255204420248704468581108159154915854115511802457988908195786371375080447864"));
+    }
+
+    #[test]
+    fn test_printf_rounding_carry() {
+        // 9.9995 is stored just below the halfway point
+        assert_eq!(format("%.3f", &[PrintfArg::Float(9.9995)]), "9.999");
+        assert_eq!(format("%.3f", &[PrintfArg::Float(9.99951)]), "10.000");
+        assert_eq!(format("%.2f", &[PrintfArg::Float(99.999)]), "100.00");
+        assert_eq!(format("%.0f", &[PrintfArg::Float(0.9)]), "1");
+        assert_eq!(format("%.3e", &[PrintfArg::Float(9.9996)]), "1.000e+01");
+    }
+
+    #[test]
+    fn test_printf_precision_above_17() {
+        assert_eq!(format("%.20f", &[PrintfArg::Float(1.25)]), "1.25000000000000000000");
+        assert_eq!(format("%.20e", &[PrintfArg::Float(1.5)]), "1.50000000000000000000e+00");
+        assert_eq!(format("%.25f", &[PrintfArg::Float(0.5)]).len(), 27);
+    }
+}
+
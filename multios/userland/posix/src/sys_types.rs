@@ -169,25 +169,25 @@ pub type pthread_t = usize;
 pub type pthread_attr_t = usize;
 
 /// Mutex type
-pub type pthread_mutex_t = usize;
+pub use crate::pthread::pthread_mutex_t;
 
 /// Mutex attribute type
 pub type pthread_mutexattr_t = usize;
 
 /// Condition variable type
-pub type pthread_cond_t = usize;
+pub use crate::pthread::pthread_cond_t;
 
 /// Condition variable attribute type
 pub type pthread_condattr_t = usize;
 
 /// Read-write lock type
-pub type pthread_rwlock_t = usize;
+pub use crate::pthread::pthread_rwlock_t;
 
 /// Read-write lock attribute type
 pub type pthread_rwlockattr_t = usize;
 
 /// Barrier type
-pub type pthread_barrier_t = usize;
+pub use crate::pthread::pthread_barrier_t;
 
 /// Barrier attribute type
 pub type pthread_barrierattr_t = usize;
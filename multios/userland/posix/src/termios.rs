@@ -0,0 +1,550 @@
+//! POSIX termios.h Compatibility
+//!
+//! This module provides terminal control for MultiOS:
+//! - termios flags, special characters and `tcgetattr()/tcsetattr()`
+//! - Baud rate and window size helpers
+//! - The line discipline that implements canonical editing, echo, signal
+//!   characters and output post-processing for terminal devices such as
+//!   pseudo-terminals
+
+use crate::errors::*;
+use crate::internal::termios;
+use crate::signal::{SIGINT, SIGQUIT, SIGTSTP};
+use crate::syscall;
+use crate::types::*;
+use std::collections::VecDeque;
+
+/// Number of control characters
+pub const NCCS: usize = 32;
+
+/// Control character indices (c_cc)
+pub const VINTR: usize = 0;
+pub const VQUIT: usize = 1;
+pub const VERASE: usize = 2;
+pub const VKILL: usize = 3;
+pub const VEOF: usize = 4;
+pub const VTIME: usize = 5;
+pub const VMIN: usize = 6;
+pub const VSTART: usize = 8;
+pub const VSTOP: usize = 9;
+pub const VSUSP: usize = 10;
+pub const VEOL: usize = 11;
+pub const VWERASE: usize = 14;
+
+/// Input modes (c_iflag)
+pub const IGNBRK: u32 = 0o000001;
+pub const BRKINT: u32 = 0o000002;
+pub const IGNPAR: u32 = 0o000004;
+pub const ISTRIP: u32 = 0o000040;
+pub const INLCR: u32 = 0o000100;
+pub const IGNCR: u32 = 0o000200;
+pub const ICRNL: u32 = 0o000400;
+pub const IXON: u32 = 0o002000;
+pub const IXANY: u32 = 0o004000;
+pub const IXOFF: u32 = 0o010000;
+
+/// Output modes (c_oflag)
+pub const OPOST: u32 = 0o000001;
+pub const ONLCR: u32 = 0o000004;
+pub const OCRNL: u32 = 0o000010;
+
+/// Control modes (c_cflag)
+pub const CSIZE: u32 = 0o000060;
+pub const CS8: u32 = 0o000060;
+pub const CSTOPB: u32 = 0o000100;
+pub const CREAD: u32 = 0o000200;
+pub const PARENB: u32 = 0o000400;
+pub const HUPCL: u32 = 0o002000;
+pub const CLOCAL: u32 = 0o004000;
+
+/// Local modes (c_lflag)
+pub const ISIG: u32 = 0o000001;
+pub const ICANON: u32 = 0o000002;
+pub const ECHO: u32 = 0o000010;
+pub const ECHOE: u32 = 0o000020;
+pub const ECHOK: u32 = 0o000040;
+pub const ECHONL: u32 = 0o000100;
+pub const NOFLSH: u32 = 0o000200;
+pub const TOSTOP: u32 = 0o000400;
+pub const ECHOCTL: u32 = 0o001000;
+pub const IEXTEN: u32 = 0o100000;
+
+/// tcsetattr() actions
+pub const TCSANOW: i32 = 0;
+pub const TCSADRAIN: i32 = 1;
+pub const TCSAFLUSH: i32 = 2;
+
+/// tcflush() queue selectors
+pub const TCIFLUSH: i32 = 0;
+pub const TCOFLUSH: i32 = 1;
+pub const TCIOFLUSH: i32 = 2;
+
+/// Baud rates
+pub const B0: u32 = 0;
+pub const B9600: u32 = 9600;
+pub const B19200: u32 = 19200;
+pub const B38400: u32 = 38400;
+pub const B57600: u32 = 57600;
+pub const B115200: u32 = 115200;
+
+/// Terminal ioctl requests
+pub const TCGETS: u32 = 0x5401;
+pub const TCSETS: u32 = 0x5402;
+pub const TCSETSW: u32 = 0x5403;
+pub const TCSETSF: u32 = 0x5404;
+pub const TCFLSH: u32 = 0x540b;
+pub const TIOCSCTTY: u32 = 0x540e;
+pub const TIOCGPGRP: u32 = 0x540f;
+pub const TIOCSPGRP: u32 = 0x5410;
+pub const TIOCGWINSZ: u32 = 0x5413;
+pub const TIOCSWINSZ: u32 = 0x5414;
+pub const TIOCGPTN: u32 = 0x8004_5430;
+pub const TIOCSPTLCK: u32 = 0x4004_5431;
+
+/// Terminal window size
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct winsize {
+    pub ws_row: u16,             // Rows, in characters
+    pub ws_col: u16,             // Columns, in characters
+    pub ws_xpixel: u16,          // Horizontal size, in pixels
+    pub ws_ypixel: u16,          // Vertical size, in pixels
+}
+
+/// Settings of a freshly opened terminal: cooked mode with echo
+pub fn default_termios() -> termios {
+    let mut c_cc = [0u8; NCCS];
+    c_cc[VINTR] = 0x03;    // ^C
+    c_cc[VQUIT] = 0x1c;    // ^\
+    c_cc[VERASE] = 0x7f;   // DEL
+    c_cc[VKILL] = 0x15;    // ^U
+    c_cc[VEOF] = 0x04;     // ^D
+    c_cc[VMIN] = 1;
+    c_cc[VSTART] = 0x11;   // ^Q
+    c_cc[VSTOP] = 0x13;    // ^S
+    c_cc[VSUSP] = 0x1a;    // ^Z
+    c_cc[VWERASE] = 0x17;  // ^W
+    termios {
+        c_iflag: ICRNL | IXON,
+        c_oflag: OPOST | ONLCR,
+        c_cflag: CS8 | CREAD | HUPCL,
+        c_lflag: ISIG | ICANON | ECHO | ECHOE | ECHOK | ECHOCTL | IEXTEN,
+        c_line: 0,
+        c_cc,
+        c_ispeed: B38400,
+        c_ospeed: B38400,
+    }
+}
+
+/// Issue a terminal ioctl
+fn ioctl(fd: fd_t, request: u32, arg: usize) -> PosixResult<usize> {
+    if fd < 0 {
+        return Err(Errno::Ebadf);
+    }
+    syscall::ioctl(fd, request, arg)
+}
+
+/// Get terminal attributes
+///
+/// This function provides compatibility with the POSIX tcgetattr() function.
+///
+/// # Arguments
+/// * `fd` - Terminal file descriptor
+/// * `termios_p` - Receives the current settings
+///
+/// # Returns
+/// * `PosixResult<()>` - Success, or `Enotty` if `fd` is not a terminal
+pub fn tcgetattr(fd: fd_t, termios_p: &mut termios) -> PosixResult<()> {
+    ioctl(fd, TCGETS, termios_p as *mut termios as usize).map(|_| ())
+}
+
+/// Set terminal attributes
+///
+/// This function provides compatibility with the POSIX tcsetattr() function.
+///
+/// # Arguments
+/// * `fd` - Terminal file descriptor
+/// * `optional_actions` - TCSANOW, TCSADRAIN (after output drains) or
+///   TCSAFLUSH (after output drains, discarding pending input)
+/// * `termios_p` - New settings
+///
+/// # Returns
+/// * `PosixResult<()>` - Success on set, error on failure
+pub fn tcsetattr(fd: fd_t, optional_actions: i32, termios_p: &termios) -> PosixResult<()> {
+    let request = match optional_actions {
+        TCSANOW => TCSETS,
+        TCSADRAIN => TCSETSW,
+        TCSAFLUSH => TCSETSF,
+        _ => return Err(Errno::Einval),
+    };
+    ioctl(fd, request, termios_p as *const termios as usize).map(|_| ())
+}
+
+/// Discard queued terminal data
+///
+/// This function provides compatibility with the POSIX tcflush() function.
+pub fn tcflush(fd: fd_t, queue_selector: i32) -> PosixResult<()> {
+    if !matches!(queue_selector, TCIFLUSH | TCOFLUSH | TCIOFLUSH) {
+        return Err(Errno::Einval);
+    }
+    ioctl(fd, TCFLSH, queue_selector as usize).map(|_| ())
+}
+
+/// Get the foreground process group of a terminal
+///
+/// This function provides compatibility with the POSIX tcgetpgrp() function.
+pub fn tcgetpgrp(fd: fd_t) -> PosixResult<pid_t> {
+    let mut pgrp: pid_t = 0;
+    ioctl(fd, TIOCGPGRP, &mut pgrp as *mut pid_t as usize)?;
+    Ok(pgrp)
+}
+
+/// Set the foreground process group of a terminal
+///
+/// This function provides compatibility with the POSIX tcsetpgrp() function.
+pub fn tcsetpgrp(fd: fd_t, pgrp: pid_t) -> PosixResult<()> {
+    ioctl(fd, TIOCSPGRP, &pgrp as *const pid_t as usize).map(|_| ())
+}
+
+/// Get the window size of a terminal
+pub fn tcgetwinsize(fd: fd_t) -> PosixResult<winsize> {
+    let mut ws = winsize::default();
+    ioctl(fd, TIOCGWINSZ, &mut ws as *mut winsize as usize)?;
+    Ok(ws)
+}
+
+/// Set the window size of a terminal; the foreground group receives SIGWINCH
+pub fn tcsetwinsize(fd: fd_t, ws: &winsize) -> PosixResult<()> {
+    ioctl(fd, TIOCSWINSZ, ws as *const winsize as usize).map(|_| ())
+}
+
+/// Check whether a descriptor refers to a terminal
+pub fn is_terminal(fd: fd_t) -> bool {
+    let mut settings = default_termios();
+    tcgetattr(fd, &mut settings).is_ok()
+}
+
+/// Put settings into raw mode
+///
+/// This function provides compatibility with the BSD cfmakeraw() function:
+/// no input or output processing, no echo, no signal characters, and
+/// byte-at-a-time reads.
+pub fn cfmakeraw(termios_p: &mut termios) {
+    termios_p.c_iflag &= !(IGNBRK | BRKINT | ISTRIP | INLCR | IGNCR | ICRNL | IXON);
+    termios_p.c_oflag &= !OPOST;
+    termios_p.c_lflag &= !(ECHO | ECHONL | ICANON | ISIG | IEXTEN);
+    termios_p.c_cflag &= !(CSIZE | PARENB);
+    termios_p.c_cflag |= CS8;
+    termios_p.c_cc[VMIN] = 1;
+    termios_p.c_cc[VTIME] = 0;
+}
+
+pub fn cfgetispeed(termios_p: &termios) -> u32 {
+    termios_p.c_ispeed
+}
+
+pub fn cfgetospeed(termios_p: &termios) -> u32 {
+    termios_p.c_ospeed
+}
+
+pub fn cfsetispeed(termios_p: &mut termios, speed: u32) -> PosixResult<()> {
+    termios_p.c_ispeed = speed;
+    Ok(())
+}
+
+pub fn cfsetospeed(termios_p: &mut termios, speed: u32) -> PosixResult<()> {
+    termios_p.c_ospeed = speed;
+    Ok(())
+}
+
+/// Terminal line discipline
+///
+/// Sits between a terminal's device side (the pty master, a serial port or
+/// a console) and its readers. Bytes typed on the device side go through
+/// `receive()`; bytes written by programs go through `write()`.
+#[derive(Debug, Clone)]
+pub struct LineDiscipline {
+    settings: termios,
+    winsize: winsize,
+    /// Canonical line being edited
+    line: Vec<u8>,
+    /// Bytes ready for readers
+    input: VecDeque<u8>,
+    /// Bytes ready for the device side
+    output: VecDeque<u8>,
+    /// A VEOF was typed on an empty line: the next read returns 0
+    eof_pending: bool,
+    /// Output suspended by VSTOP
+    stopped: bool,
+}
+
+impl Default for LineDiscipline {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LineDiscipline {
+    pub fn new() -> Self {
+        Self {
+            settings: default_termios(),
+            winsize: winsize { ws_row: 24, ws_col: 80, ws_xpixel: 0, ws_ypixel: 0 },
+            line: Vec::new(),
+            input: VecDeque::new(),
+            output: VecDeque::new(),
+            eof_pending: false,
+            stopped: false,
+        }
+    }
+
+    pub fn termios(&self) -> termios {
+        self.settings
+    }
+
+    /// Apply new settings with tcsetattr() semantics
+    pub fn set_termios(&mut self, action: i32, settings: &termios) -> PosixResult<()> {
+        match action {
+            TCSANOW | TCSADRAIN => {}
+            TCSAFLUSH => self.flush(TCIFLUSH)?,
+            _ => return Err(Errno::Einval),
+        }
+        // Leaving canonical mode hands the partial line to readers
+        if self.canonical() && settings.c_lflag & ICANON == 0 {
+            self.input.extend(self.line.drain(..));
+        }
+        self.settings = *settings;
+        if self.settings.c_iflag & IXON == 0 {
+            self.stopped = false;
+        }
+        Ok(())
+    }
+
+    pub fn winsize(&self) -> winsize {
+        self.winsize
+    }
+
+    /// Update the window size; returns true when it changed (raise SIGWINCH)
+    pub fn set_winsize(&mut self, ws: winsize) -> bool {
+        let changed = self.winsize != ws;
+        self.winsize = ws;
+        changed
+    }
+
+    /// Discard queued data (tcflush())
+    pub fn flush(&mut self, queue_selector: i32) -> PosixResult<()> {
+        match queue_selector {
+            TCIFLUSH | TCIOFLUSH => {
+                self.line.clear();
+                self.input.clear();
+                self.eof_pending = false;
+            }
+            TCOFLUSH => {}
+            _ => return Err(Errno::Einval),
+        }
+        if queue_selector != TCIFLUSH {
+            self.output.clear();
+        }
+        Ok(())
+    }
+
+    fn canonical(&self) -> bool {
+        self.settings.c_lflag & ICANON != 0
+    }
+
+    fn lflag(&self, flag: u32) -> bool {
+        self.settings.c_lflag & flag != 0
+    }
+
+    fn iflag(&self, flag: u32) -> bool {
+        self.settings.c_iflag & flag != 0
+    }
+
+    /// Control character `index`, if enabled (0 disables it)
+    fn is_cc(&self, byte: u8, index: usize) -> bool {
+        let cc = self.settings.c_cc[index];
+        cc != 0 && byte == cc
+    }
+
+    /// Echo a byte, showing control characters as ^X when ECHOCTL is set
+    fn echo(&mut self, byte: u8) {
+        if !self.lflag(ECHO) {
+            return;
+        }
+        if self.lflag(ECHOCTL) && byte < 0x20 && byte != b'\n' && byte != b'\t' {
+            self.output.extend([b'^', byte + 0x40]);
+        } else {
+            self.post_process(byte);
+        }
+    }
+
+    /// Erase the last character of the line being edited
+    fn erase_char(&mut self) -> bool {
+        match self.line.pop() {
+            Some(_) => {
+                if self.lflag(ECHO) && self.lflag(ECHOE) {
+                    self.output.extend(*b"\x08 \x08");
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Process bytes arriving from the device side (keyboard input)
+    ///
+    /// # Returns
+    /// * `Vec<i32>` - Signals to send to the terminal's foreground process group
+    pub fn receive(&mut self, bytes: &[u8]) -> Vec<i32> {
+        let mut signals = Vec::new();
+        for &raw in bytes {
+            let mut byte = if self.iflag(ISTRIP) { raw & 0x7f } else { raw };
+
+            if self.iflag(IXON) {
+                if self.is_cc(byte, VSTOP) {
+                    self.stopped = true;
+                    continue;
+                }
+                if self.is_cc(byte, VSTART) || (self.stopped && self.iflag(IXANY)) {
+                    self.stopped = false;
+                    if self.is_cc(byte, VSTART) {
+                        continue;
+                    }
+                }
+            }
+
+            if self.lflag(ISIG) {
+                let signal = if self.is_cc(byte, VINTR) {
+                    Some(SIGINT)
+                } else if self.is_cc(byte, VQUIT) {
+                    Some(SIGQUIT)
+                } else if self.is_cc(byte, VSUSP) {
+                    Some(SIGTSTP)
+                } else {
+                    None
+                };
+                if let Some(signal) = signal {
+                    if !self.lflag(NOFLSH) {
+                        self.line.clear();
+                        self.input.clear();
+                    }
+                    self.echo(byte);
+                    signals.push(signal);
+                    continue;
+                }
+            }
+
+            match byte {
+                b'\r' if self.iflag(IGNCR) => continue,
+                b'\r' if self.iflag(ICRNL) => byte = b'\n',
+                b'\n' if self.iflag(INLCR) => byte = b'\r',
+                _ => {}
+            }
+
+            if !self.canonical() {
+                self.input.push_back(byte);
+                self.echo(byte);
+                continue;
+            }
+
+            if self.is_cc(byte, VERASE) {
+                self.erase_char();
+            } else if self.is_cc(byte, VWERASE) && self.lflag(IEXTEN) {
+                while self.line.last() == Some(&b' ') && self.erase_char() {}
+                while self.line.last().map_or(false, |&b| b != b' ') && self.erase_char() {}
+            } else if self.is_cc(byte, VKILL) {
+                while self.erase_char() {}
+                if self.lflag(ECHOK) && !self.lflag(ECHOE) {
+                    self.echo(b'\n');
+                }
+            } else if self.is_cc(byte, VEOF) {
+                // End of file: deliver the partial line without a terminator
+                if self.line.is_empty() {
+                    self.eof_pending = true;
+                }
+                self.input.extend(self.line.drain(..));
+            } else if byte == b'\n' || self.is_cc(byte, VEOL) {
+                self.line.push(byte);
+                self.input.extend(self.line.drain(..));
+                if self.lflag(ECHO) || (byte == b'\n' && self.lflag(ECHONL)) {
+                    self.post_process(byte);
+                }
+            } else {
+                self.line.push(byte);
+                self.echo(byte);
+            }
+        }
+        signals
+    }
+
+    /// Whether a reader would get data (or end of file) right now
+    pub fn readable(&self) -> bool {
+        if !self.input.is_empty() || self.eof_pending {
+            return true;
+        }
+        // Non-canonical reads with VMIN == 0 never block
+        !self.canonical() && self.settings.c_cc[VMIN] == 0
+    }
+
+    /// Read processed input
+    ///
+    /// Canonical mode returns at most one line. Returns 0 at end of file, or
+    /// when nothing is available (check `readable()` before blocking).
+    pub fn read(&mut self, buf: &mut [u8]) -> usize {
+        let mut count = 0;
+        while count < buf.len() {
+            let byte = match self.input.pop_front() {
+                Some(byte) => byte,
+                None => break,
+            };
+            buf[count] = byte;
+            count += 1;
+            if self.canonical() && (byte == b'\n' || self.is_cc(byte, VEOL)) {
+                break;
+            }
+        }
+        if count == 0 && self.eof_pending {
+            self.eof_pending = false;
+        }
+        count
+    }
+
+    /// Apply output processing to a byte headed for the device side
+    fn post_process(&mut self, byte: u8) {
+        if self.settings.c_oflag & OPOST != 0 {
+            if byte == b'\n' && self.settings.c_oflag & ONLCR != 0 {
+                self.output.extend(*b"\r\n");
+                return;
+            }
+            if byte == b'\r' && self.settings.c_oflag & OCRNL != 0 {
+                self.output.push_back(b'\n');
+                return;
+            }
+        }
+        self.output.push_back(byte);
+    }
+
+    /// Write program output towards the device side
+    pub fn write(&mut self, bytes: &[u8]) -> usize {
+        for &byte in bytes {
+            self.post_process(byte);
+        }
+        bytes.len()
+    }
+
+    /// Whether the device side has output to collect
+    pub fn has_output(&self) -> bool {
+        !self.stopped && !self.output.is_empty()
+    }
+
+    /// Collect output for the device side; nothing while stopped by VSTOP
+    pub fn take_output(&mut self, buf: &mut [u8]) -> usize {
+        if self.stopped {
+            return 0;
+        }
+        let count = buf.len().min(self.output.len());
+        for (slot, byte) in buf.iter_mut().zip(self.output.drain(..count)) {
+            *slot = byte;
+        }
+        count
+    }
+}
@@ -0,0 +1,747 @@
+//! POSIX Timers for MultiOS
+//!
+//! This module provides the timer subsystem behind time.h and sys/timerfd.h:
+//! - `TimerWheel`, the hierarchical timing wheel advanced by the kernel's
+//!   high-resolution timer interrupt
+//! - `TimerTable`, which keeps POSIX interval timers (timer_create()),
+//!   timerfd descriptors and clock_nanosleep() sleepers on the wheel
+//! - clock_gettime()/clock_nanosleep() and the timerfd calls for user programs
+//!
+//! Deadlines are kept on the monotonic clock. CLOCK_REALTIME is the monotonic
+//! clock plus an offset, so absolute REALTIME timers are re-queued whenever
+//! the wall clock is set.
+
+use std::collections::BTreeMap;
+
+use crate::errors::*;
+use crate::internal::{itimerspec, timespec, CLOCK_MONOTONIC, CLOCK_REALTIME};
+use crate::poll::{EventQueue, EPOLLIN};
+use crate::syscall;
+use crate::types::*;
+
+/// Monotonic clock that also counts time spent suspended
+pub const CLOCK_BOOTTIME: clockid_t = 7;
+
+/// CLOCK_REALTIME as of the last timer tick, cheap but coarse
+pub const CLOCK_REALTIME_COARSE: clockid_t = 5;
+/// CLOCK_MONOTONIC as of the last timer tick, cheap but coarse
+pub const CLOCK_MONOTONIC_COARSE: clockid_t = 6;
+
+/// clock_nanosleep()/timer_settime() deadline is absolute
+pub const TIMER_ABSTIME: i32 = 1;
+
+/// timerfd_settime() deadline is absolute
+pub const TFD_TIMER_ABSTIME: i32 = 1;
+/// Fail reads with ECANCELED when CLOCK_REALTIME is set
+pub const TFD_TIMER_CANCEL_ON_SET: i32 = 2;
+/// timerfd_create() flags
+pub const TFD_NONBLOCK: i32 = 0x800;
+pub const TFD_CLOEXEC: i32 = 0x80000;
+
+/// Resolution of the kernel timer wheel, reported by clock_getres()
+pub const HRTIMER_RESOLUTION_NS: u64 = 1;
+
+const NSEC_PER_SEC: u64 = 1_000_000_000;
+
+/// Convert a timespec to nanoseconds, rejecting malformed values
+pub fn timespec_to_ns(ts: &timespec) -> PosixResult<u64> {
+    if ts.tv_sec < 0 || ts.tv_nsec < 0 || ts.tv_nsec >= NSEC_PER_SEC as i64 {
+        return Err(Errno::Einval);
+    }
+    Ok((ts.tv_sec as u64).saturating_mul(NSEC_PER_SEC).saturating_add(ts.tv_nsec as u64))
+}
+
+/// Convert nanoseconds to a timespec
+pub fn ns_to_timespec(ns: u64) -> timespec {
+    timespec {
+        tv_sec: (ns / NSEC_PER_SEC) as time_t,
+        tv_nsec: (ns % NSEC_PER_SEC) as i64,
+    }
+}
+
+const WHEEL_BITS: u32 = 6;
+const WHEEL_SLOTS: u64 = 1 << WHEEL_BITS;
+/// Enough levels to cover every 64-bit tick value
+const WHEEL_LEVELS: usize = 11;
+
+/// Hierarchical timing wheel
+///
+/// Level `n` has 64 slots of 64^n ticks each. A deadline is queued on the
+/// lowest level whose slot is less than one revolution away, and moves down
+/// a level each time the wheel reaches the start of its slot. Advancing the
+/// wheel jumps straight to the next occupied slot, so long idle periods cost
+/// nothing and each timer is touched at most once per level.
+#[derive(Debug, Clone)]
+pub struct TimerWheel<K> {
+    resolution_ns: u64,
+    /// Current time, in ticks
+    now: u64,
+    levels: Vec<Vec<Vec<(K, u64)>>>,
+    /// Level and slot of every queued key
+    index: BTreeMap<K, (usize, usize)>,
+}
+
+impl<K: Ord + Copy> TimerWheel<K> {
+    /// Create an empty wheel ticking every `resolution_ns` nanoseconds
+    pub fn new(resolution_ns: u64) -> Self {
+        Self {
+            resolution_ns: resolution_ns.max(1),
+            now: 0,
+            levels: (0..WHEEL_LEVELS)
+                .map(|_| (0..WHEEL_SLOTS).map(|_| Vec::new()).collect())
+                .collect(),
+            index: BTreeMap::new(),
+        }
+    }
+
+    /// Queue `key` to expire at `deadline_ns`, replacing any earlier entry
+    ///
+    /// Deadlines are rounded up to the next tick, so a timer never fires
+    /// early. Past deadlines expire on the next `advance`.
+    pub fn insert(&mut self, key: K, deadline_ns: u64) {
+        self.cancel(key);
+        let tick = deadline_ns / self.resolution_ns + (deadline_ns % self.resolution_ns != 0) as u64;
+        self.place(key, tick);
+    }
+
+    fn place(&mut self, key: K, tick: u64) {
+        let tick = tick.max(self.now);
+        let level = (0..WHEEL_LEVELS)
+            .find(|&level| {
+                let shift = level as u32 * WHEEL_BITS;
+                (tick >> shift) - (self.now >> shift) < WHEEL_SLOTS
+            })
+            .unwrap_or(WHEEL_LEVELS - 1);
+        let slot = ((tick >> (level as u32 * WHEEL_BITS)) % WHEEL_SLOTS) as usize;
+        self.levels[level][slot].push((key, tick));
+        self.index.insert(key, (level, slot));
+    }
+
+    /// Remove a queued key; returns whether it was queued
+    pub fn cancel(&mut self, key: K) -> bool {
+        match self.index.remove(&key) {
+            Some((level, slot)) => {
+                self.levels[level][slot].retain(|&(queued, _)| queued != key);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn contains(&self, key: K) -> bool {
+        self.index.contains_key(&key)
+    }
+
+    /// Tick at which the wheel next has work: an expiry on level 0 or a
+    /// slot to cascade on a higher level
+    fn next_event(&self) -> Option<u64> {
+        let mut next: Option<u64> = None;
+        for (level, slots) in self.levels.iter().enumerate() {
+            let shift = level as u32 * WHEEL_BITS;
+            let base = self.now >> shift;
+            // Higher levels never hold entries for their current slot
+            let first = if level == 0 { 0 } else { 1 };
+            let occupied = (first..WHEEL_SLOTS)
+                .map(|offset| base + offset)
+                .find(|index| !slots[(index % WHEEL_SLOTS) as usize].is_empty());
+            if let Some(index) = occupied {
+                let tick = if index > u64::MAX >> shift { u64::MAX } else { index << shift };
+                next = Some(next.map_or(tick, |next| next.min(tick)));
+            }
+        }
+        next
+    }
+
+    /// Earliest time the wheel must be advanced again, for programming the
+    /// one-shot hardware timer
+    pub fn next_deadline_ns(&self) -> Option<u64> {
+        self.next_event().map(|tick| tick.saturating_mul(self.resolution_ns))
+    }
+
+    /// Advance to `now_ns` and return the keys that expired, earliest first
+    pub fn advance(&mut self, now_ns: u64) -> Vec<K> {
+        let target = now_ns / self.resolution_ns;
+        let mut expired = Vec::new();
+        while let Some(tick) = self.next_event().filter(|&tick| tick <= target) {
+            self.now = tick;
+            // Cascade from the top so entries land in lower slots before
+            // those are processed
+            for level in (1..WHEEL_LEVELS).rev() {
+                let shift = level as u32 * WHEEL_BITS;
+                if self.now & ((1u64 << shift) - 1) != 0 {
+                    continue;
+                }
+                let slot = ((self.now >> shift) % WHEEL_SLOTS) as usize;
+                for (key, tick) in core::mem::take(&mut self.levels[level][slot]) {
+                    self.place(key, tick);
+                }
+            }
+            let slot = (self.now % WHEEL_SLOTS) as usize;
+            for (key, _) in core::mem::take(&mut self.levels[0][slot]) {
+                self.index.remove(&key);
+                expired.push(key);
+            }
+        }
+        self.now = self.now.max(target);
+        expired
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+}
+
+/// Identifier of a timer inside the table
+pub type TimerId = u32;
+
+/// What a timer does when it expires
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimerNotify {
+    /// Queue a signal to the process, or to one thread (SIGEV_THREAD_ID)
+    Signal { tid: Option<pid_t>, signo: i32, value: u64 },
+    /// Only count expirations (SIGEV_NONE)
+    Silent,
+    /// Make a timerfd readable
+    Descriptor,
+    /// Wake a thread blocked in clock_nanosleep()
+    Sleeper { tid: pid_t },
+}
+
+/// Side effect of a timer expiry or clock change, for the caller to carry out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimerEvent {
+    /// Queue `signo` (si_code SI_TIMER) to `pid`, or to thread `tid`
+    Signal { timer: TimerId, pid: pid_t, tid: Option<pid_t>, signo: i32, value: u64 },
+    /// A timerfd became readable; notify the event queues watching it
+    Readable(TimerId),
+    /// A sleeper's deadline passed; the timer has been released
+    Wake { timer: TimerId, tid: pid_t },
+}
+
+#[derive(Debug, Clone)]
+struct Timer {
+    owner: pid_t,
+    clock: clockid_t,
+    notify: TimerNotify,
+    /// Monotonic expiry time while armed
+    expires_ns: Option<u64>,
+    interval_ns: u64,
+    /// Wall clock deadline of an absolute CLOCK_REALTIME timer
+    realtime_deadline: Option<u64>,
+    cancel_on_set: bool,
+    /// CLOCK_REALTIME was set under a TFD_TIMER_CANCEL_ON_SET timerfd
+    canceled: bool,
+    /// Expirations not yet read from a timerfd
+    expirations: u64,
+    /// A signal for this timer is queued but not yet delivered
+    signal_pending: bool,
+    /// Expirations while the signal was pending
+    overrun: u64,
+    /// Overrun count of the last delivered signal (timer_getoverrun())
+    last_overrun: u64,
+}
+
+/// Timer statistics
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TimerStats {
+    pub timers_created: u64,
+    pub timers_deleted: u64,
+    pub expirations: u64,
+    pub signals_queued: u64,
+    pub overruns: u64,
+    pub sleepers_woken: u64,
+    pub clock_sets: u64,
+}
+
+/// POSIX timers, timerfds and sleepers of the system
+#[derive(Debug)]
+pub struct TimerTable {
+    timers: BTreeMap<TimerId, Timer>,
+    next_id: TimerId,
+    wheel: TimerWheel<TimerId>,
+    /// CLOCK_REALTIME minus CLOCK_MONOTONIC, in nanoseconds
+    realtime_offset_ns: i64,
+    stats: TimerStats,
+}
+
+impl Default for TimerTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TimerTable {
+    pub fn new() -> Self {
+        Self {
+            timers: BTreeMap::new(),
+            next_id: 1,
+            wheel: TimerWheel::new(HRTIMER_RESOLUTION_NS),
+            realtime_offset_ns: 0,
+            stats: TimerStats::default(),
+        }
+    }
+
+    fn timer(&self, id: TimerId) -> PosixResult<&Timer> {
+        self.timers.get(&id).ok_or(Errno::Einval)
+    }
+
+    fn timer_mut(&mut self, id: TimerId) -> PosixResult<&mut Timer> {
+        self.timers.get_mut(&id).ok_or(Errno::Einval)
+    }
+
+    /// Current time of `clock`, given the monotonic time
+    pub fn clock_now(&self, clock: clockid_t, mono_ns: u64) -> PosixResult<u64> {
+        match clock {
+            CLOCK_REALTIME => Ok((mono_ns as i64).saturating_add(self.realtime_offset_ns).max(0) as u64),
+            CLOCK_MONOTONIC | CLOCK_BOOTTIME => Ok(mono_ns),
+            _ => Err(Errno::Einval),
+        }
+    }
+
+    /// Set CLOCK_REALTIME (clock_settime(), settimeofday())
+    ///
+    /// Absolute REALTIME timers move with the wall clock and timerfds armed
+    /// with TFD_TIMER_CANCEL_ON_SET are cancelled.
+    pub fn set_realtime(&mut self, realtime_ns: u64, mono_ns: u64) -> Vec<TimerEvent> {
+        self.realtime_offset_ns = realtime_ns as i64 - mono_ns as i64;
+        self.stats.clock_sets += 1;
+
+        let mut events = Vec::new();
+        let ids: Vec<TimerId> = self.timers.iter()
+            .filter(|(_, timer)| timer.realtime_deadline.is_some() && timer.expires_ns.is_some())
+            .map(|(&id, _)| id)
+            .collect();
+        for id in ids {
+            let timer = self.timers.get_mut(&id).expect("timer exists");
+            if timer.cancel_on_set {
+                timer.canceled = true;
+                timer.expires_ns = None;
+                timer.realtime_deadline = None;
+                self.wheel.cancel(id);
+                events.push(TimerEvent::Readable(id));
+                continue;
+            }
+            let deadline = timer.realtime_deadline.expect("absolute realtime timer");
+            let expires = self.realtime_to_mono(deadline).max(mono_ns);
+            self.timers.get_mut(&id).expect("timer exists").expires_ns = Some(expires);
+            self.wheel.insert(id, expires);
+        }
+        events
+    }
+
+    fn realtime_to_mono(&self, realtime_ns: u64) -> u64 {
+        (realtime_ns as i64).saturating_sub(self.realtime_offset_ns).max(0) as u64
+    }
+
+    fn insert(&mut self, owner: pid_t, clock: clockid_t, notify: TimerNotify) -> PosixResult<TimerId> {
+        if !matches!(clock, CLOCK_REALTIME | CLOCK_MONOTONIC | CLOCK_BOOTTIME) {
+            return Err(Errno::Einval);
+        }
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1).max(1);
+        self.timers.insert(id, Timer {
+            owner,
+            clock,
+            notify,
+            expires_ns: None,
+            interval_ns: 0,
+            realtime_deadline: None,
+            cancel_on_set: false,
+            canceled: false,
+            expirations: 0,
+            signal_pending: false,
+            overrun: 0,
+            last_overrun: 0,
+        });
+        self.stats.timers_created += 1;
+        Ok(id)
+    }
+
+    /// Create a POSIX interval timer for process `owner` (timer_create())
+    pub fn create(&mut self, owner: pid_t, clock: clockid_t, notify: TimerNotify) -> PosixResult<TimerId> {
+        match notify {
+            TimerNotify::Signal { signo, .. } if !(1..=64).contains(&signo) => Err(Errno::Einval),
+            TimerNotify::Signal { .. } | TimerNotify::Silent => self.insert(owner, clock, notify),
+            _ => Err(Errno::Einval),
+        }
+    }
+
+    /// Create the timer behind a timerfd (timerfd_create())
+    pub fn create_timerfd(&mut self, owner: pid_t, clock: clockid_t) -> PosixResult<TimerId> {
+        self.insert(owner, clock, TimerNotify::Descriptor)
+    }
+
+    /// Arm or disarm a timer (timer_settime(), timerfd_settime())
+    ///
+    /// Returns the previous setting. Arming resets unread timerfd
+    /// expirations and pending overruns.
+    pub fn settime(&mut self, id: TimerId, flags: i32, value: &itimerspec, mono_ns: u64) -> PosixResult<itimerspec> {
+        let initial = timespec_to_ns(&value.it_value)?;
+        let interval = timespec_to_ns(&value.it_interval)?;
+        let absolute = flags & TIMER_ABSTIME != 0;
+        let cancel_on_set = flags & TFD_TIMER_CANCEL_ON_SET != 0;
+        if flags & !(TIMER_ABSTIME | TFD_TIMER_CANCEL_ON_SET) != 0 {
+            return Err(Errno::Einval);
+        }
+
+        let old = self.gettime(id, mono_ns)?;
+        let offset = self.realtime_offset_ns;
+        let timer = self.timer_mut(id)?;
+        if cancel_on_set && (timer.notify != TimerNotify::Descriptor || timer.clock != CLOCK_REALTIME || !absolute) {
+            return Err(Errno::Einval);
+        }
+        timer.interval_ns = interval;
+        timer.expirations = 0;
+        timer.overrun = 0;
+        timer.canceled = false;
+        timer.cancel_on_set = cancel_on_set;
+        timer.realtime_deadline = None;
+        if initial == 0 {
+            timer.expires_ns = None;
+            self.wheel.cancel(id);
+            return Ok(old);
+        }
+
+        let expires = if !absolute {
+            mono_ns.saturating_add(initial)
+        } else if timer.clock == CLOCK_REALTIME {
+            timer.realtime_deadline = Some(initial);
+            (initial as i64).saturating_sub(offset).max(0) as u64
+        } else {
+            initial
+        };
+        timer.expires_ns = Some(expires);
+        self.wheel.insert(id, expires);
+        Ok(old)
+    }
+
+    /// Time until the next expiry and the interval (timer_gettime())
+    pub fn gettime(&self, id: TimerId, mono_ns: u64) -> PosixResult<itimerspec> {
+        let timer = self.timer(id)?;
+        // An overdue timer still reports a non-zero value while armed
+        let remaining = timer.expires_ns.map_or(0, |expires| expires.saturating_sub(mono_ns).max(1));
+        Ok(itimerspec {
+            it_interval: ns_to_timespec(timer.interval_ns),
+            it_value: ns_to_timespec(remaining),
+        })
+    }
+
+    /// Overrun count of the last delivered signal (timer_getoverrun())
+    pub fn getoverrun(&self, id: TimerId) -> PosixResult<i32> {
+        self.timer(id).map(|timer| timer.last_overrun.min(i32::MAX as u64) as i32)
+    }
+
+    /// The signal queued for `id` was delivered; returns its si_overrun
+    pub fn signal_delivered(&mut self, id: TimerId) -> PosixResult<i32> {
+        let timer = self.timer_mut(id)?;
+        timer.signal_pending = false;
+        timer.last_overrun = core::mem::take(&mut timer.overrun);
+        Ok(timer.last_overrun.min(i32::MAX as u64) as i32)
+    }
+
+    /// Read and reset a timerfd's expiration count
+    ///
+    /// Returns `Eagain` when nothing expired yet and `Ecanceled` once after
+    /// a cancelling clock change.
+    pub fn read_timerfd(&mut self, id: TimerId) -> PosixResult<u64> {
+        let timer = self.timer_mut(id)?;
+        if core::mem::take(&mut timer.canceled) {
+            return Err(Errno::Ecanceled);
+        }
+        match core::mem::take(&mut timer.expirations) {
+            0 => Err(Errno::Eagain),
+            count => Ok(count),
+        }
+    }
+
+    /// Monotonic expiry (`None` while disarmed) and interval of a timer
+    pub fn expiry(&self, id: TimerId) -> PosixResult<(Option<u64>, u64)> {
+        self.timer(id).map(|timer| (timer.expires_ns, timer.interval_ns))
+    }
+
+    /// Poll events of a timerfd
+    pub fn readiness(&self, id: TimerId) -> PosixResult<u32> {
+        let timer = self.timer(id)?;
+        Ok(if timer.expirations > 0 || timer.canceled { EPOLLIN } else { 0 })
+    }
+
+    /// Start a clock_nanosleep() for thread `tid`
+    ///
+    /// Returns `None` when the deadline has already passed; otherwise the
+    /// thread blocks until a `TimerEvent::Wake` for the returned timer.
+    pub fn sleep(&mut self, owner: pid_t, tid: pid_t, clock: clockid_t, flags: i32, request: &timespec, mono_ns: u64) -> PosixResult<Option<TimerId>> {
+        let id = self.insert(owner, clock, TimerNotify::Sleeper { tid })?;
+        let value = itimerspec { it_interval: ns_to_timespec(0), it_value: *request };
+        let armed = self.settime(id, flags & TIMER_ABSTIME, &value, mono_ns)
+            .map(|_| self.timers[&id].expires_ns.map_or(false, |expires| expires > mono_ns));
+        match armed {
+            Ok(true) => Ok(Some(id)),
+            Ok(false) => {
+                self.delete(id)?;
+                Ok(None)
+            }
+            Err(err) => {
+                self.delete(id)?;
+                Err(err)
+            }
+        }
+    }
+
+    /// End a sleep early (a signal interrupted it); returns the time left
+    pub fn cancel_sleep(&mut self, id: TimerId, mono_ns: u64) -> PosixResult<timespec> {
+        let remaining = self.gettime(id, mono_ns)?.it_value;
+        self.delete(id)?;
+        Ok(remaining)
+    }
+
+    /// Delete a timer (timer_delete(), close of a timerfd)
+    pub fn delete(&mut self, id: TimerId) -> PosixResult<()> {
+        self.timers.remove(&id).ok_or(Errno::Einval)?;
+        self.wheel.cancel(id);
+        self.stats.timers_deleted += 1;
+        Ok(())
+    }
+
+    /// Delete every timer of a process that exited or called exec
+    pub fn release_process(&mut self, pid: pid_t) {
+        let ids: Vec<TimerId> = self.timers.iter()
+            .filter(|(_, timer)| timer.owner == pid)
+            .map(|(&id, _)| id)
+            .collect();
+        for id in ids {
+            let _ = self.delete(id);
+        }
+    }
+
+    /// Advance to `mono_ns` like `tick`, also firing the timerfd deadlines
+    /// armed on the event queues in `queues`
+    pub fn tick_queues<'a>(&mut self, mono_ns: u64, queues: impl IntoIterator<Item = &'a mut EventQueue>) -> Vec<TimerEvent> {
+        let events = self.tick(mono_ns);
+        for queue in queues {
+            queue.expire_timers(mono_ns);
+        }
+        events
+    }
+
+    /// Advance to monotonic time `mono_ns` and fire every expired timer
+    pub fn tick(&mut self, mono_ns: u64) -> Vec<TimerEvent> {
+        let mut events = Vec::new();
+        for id in self.wheel.advance(mono_ns) {
+            let timer = match self.timers.get_mut(&id) {
+                Some(timer) => timer,
+                None => continue,
+            };
+            let expires = match timer.expires_ns {
+                Some(expires) => expires,
+                None => continue,
+            };
+
+            // Count every period that elapsed, then requeue periodic timers
+            let periods = if timer.interval_ns > 0 {
+                let periods = (mono_ns.saturating_sub(expires)) / timer.interval_ns + 1;
+                let next = expires.saturating_add(periods.saturating_mul(timer.interval_ns));
+                timer.expires_ns = Some(next);
+                if let Some(deadline) = timer.realtime_deadline.as_mut() {
+                    *deadline = deadline.saturating_add(periods.saturating_mul(timer.interval_ns));
+                }
+                self.wheel.insert(id, next);
+                periods
+            } else {
+                timer.expires_ns = None;
+                timer.realtime_deadline = None;
+                1
+            };
+            self.stats.expirations += periods;
+
+            match timer.notify {
+                TimerNotify::Signal { tid, signo, value } => {
+                    if timer.signal_pending {
+                        timer.overrun += periods;
+                        self.stats.overruns += periods;
+                    } else {
+                        timer.signal_pending = true;
+                        timer.overrun += periods - 1;
+                        self.stats.overruns += periods - 1;
+                        self.stats.signals_queued += 1;
+                        events.push(TimerEvent::Signal { timer: id, pid: timer.owner, tid, signo, value });
+                    }
+                }
+                TimerNotify::Silent => {}
+                TimerNotify::Descriptor => {
+                    timer.expirations += periods;
+                    events.push(TimerEvent::Readable(id));
+                }
+                TimerNotify::Sleeper { tid } => {
+                    self.timers.remove(&id);
+                    self.stats.sleepers_woken += 1;
+                    events.push(TimerEvent::Wake { timer: id, tid });
+                }
+            }
+        }
+        events
+    }
+
+    /// Next monotonic time `tick` has work to do
+    pub fn next_deadline_ns(&self) -> Option<u64> {
+        self.wheel.next_deadline_ns()
+    }
+
+    pub fn len(&self) -> usize {
+        self.timers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.timers.is_empty()
+    }
+
+    pub fn get_stats(&self) -> TimerStats {
+        self.stats
+    }
+}
+
+/// Get the time of a clock
+///
+/// This function provides compatibility with the POSIX clock_gettime() function.
+///
+/// # Arguments
+/// * `clock` - Clock identifier (CLOCK_REALTIME, CLOCK_MONOTONIC, ...)
+/// * `tp` - Receives the current time
+///
+/// # Returns
+/// * `PosixResult<()>` - Success, error on an unknown clock
+pub fn clock_gettime(clock: clockid_t, tp: &mut timespec) -> PosixResult<()> {
+    if let Some(now) = crate::vdso::vdso_clock_gettime(clock) {
+        *tp = now;
+        return Ok(());
+    }
+    syscall::clock_gettime(clock, tp)
+}
+
+/// Get the resolution of a clock
+///
+/// This function provides compatibility with the POSIX clock_getres() function.
+///
+/// # Arguments
+/// * `clock` - Clock identifier
+/// * `res` - Receives the resolution (None to only validate `clock`)
+///
+/// # Returns
+/// * `PosixResult<()>` - Success, error on an unknown clock
+pub fn clock_getres(clock: clockid_t, res: Option<&mut timespec>) -> PosixResult<()> {
+    let res = res.map_or(core::ptr::null_mut(), |res| res as *mut timespec);
+    syscall::clock_getres(clock, res)
+}
+
+/// Set the time of a clock
+///
+/// This function provides compatibility with the POSIX clock_settime() function.
+/// Only CLOCK_REALTIME can be set.
+///
+/// # Arguments
+/// * `clock` - Clock identifier
+/// * `tp` - New time
+///
+/// # Returns
+/// * `PosixResult<()>` - Success, error on failure
+pub fn clock_settime(clock: clockid_t, tp: &timespec) -> PosixResult<()> {
+    timespec_to_ns(tp)?;
+    syscall::clock_settime(clock, tp)
+}
+
+/// High-resolution sleep against a specific clock
+///
+/// This function provides compatibility with the POSIX clock_nanosleep() function.
+/// With TIMER_ABSTIME the sleep ends when `clock` reaches `request`, which
+/// avoids the drift of repeated relative sleeps.
+///
+/// # Arguments
+/// * `clock` - CLOCK_REALTIME, CLOCK_MONOTONIC or CLOCK_BOOTTIME
+/// * `flags` - 0 or TIMER_ABSTIME
+/// * `request` - Interval or absolute deadline
+/// * `remaining` - Receives the time left of an interrupted relative sleep
+///
+/// # Returns
+/// * `PosixResult<()>` - Success, `Eintr` when interrupted by a signal
+pub fn clock_nanosleep(clock: clockid_t, flags: i32, request: &timespec, remaining: Option<&mut timespec>) -> PosixResult<()> {
+    timespec_to_ns(request)?;
+    let remaining = remaining.map_or(core::ptr::null_mut(), |remaining| remaining as *mut timespec);
+    syscall::clock_nanosleep(clock, flags, request, remaining)
+}
+
+/// Create a timer that delivers expirations through a descriptor
+///
+/// This function provides compatibility with the Linux timerfd_create() function.
+/// The descriptor becomes readable (EPOLLIN) when the timer expires.
+///
+/// # Arguments
+/// * `clock` - CLOCK_REALTIME, CLOCK_MONOTONIC or CLOCK_BOOTTIME
+/// * `flags` - TFD_NONBLOCK, TFD_CLOEXEC
+///
+/// # Returns
+/// * `PosixResult<fd_t>` - Timer descriptor, error on failure
+pub fn timerfd_create(clock: clockid_t, flags: i32) -> PosixResult<fd_t> {
+    if flags & !(TFD_NONBLOCK | TFD_CLOEXEC) != 0 {
+        return Err(Errno::Einval);
+    }
+    // The kernel has no timerfds; the descriptor layer runs them
+    crate::descriptors::open_timerfd(clock, flags)
+}
+
+/// Arm or disarm a timer descriptor
+///
+/// This function provides compatibility with the Linux timerfd_settime() function.
+///
+/// # Arguments
+/// * `fd` - Timer descriptor
+/// * `flags` - TFD_TIMER_ABSTIME, TFD_TIMER_CANCEL_ON_SET
+/// * `new_value` - Initial expiry (zero disarms) and interval
+/// * `old_value` - Receives the previous setting (None to ignore)
+///
+/// # Returns
+/// * `PosixResult<()>` - Success, error on failure
+pub fn timerfd_settime(fd: fd_t, flags: i32, new_value: &itimerspec, old_value: Option<&mut itimerspec>) -> PosixResult<()> {
+    if crate::descriptors::is_local(fd) {
+        let old = crate::descriptors::timerfd_settime(fd, flags, new_value)?;
+        if let Some(old_value) = old_value {
+            *old_value = old;
+        }
+        return Ok(());
+    }
+    let old_value = old_value.map_or(core::ptr::null_mut(), |old| old as *mut itimerspec);
+    syscall::timerfd_settime(fd, flags, new_value, old_value)
+}
+
+/// Get the setting of a timer descriptor
+///
+/// This function provides compatibility with the Linux timerfd_gettime() function.
+///
+/// # Arguments
+/// * `fd` - Timer descriptor
+/// * `curr_value` - Receives the time to the next expiry and the interval
+///
+/// # Returns
+/// * `PosixResult<()>` - Success, error on failure
+pub fn timerfd_gettime(fd: fd_t, curr_value: &mut itimerspec) -> PosixResult<()> {
+    if crate::descriptors::is_local(fd) {
+        *curr_value = crate::descriptors::timerfd_gettime(fd)?;
+        return Ok(());
+    }
+    syscall::timerfd_gettime(fd, curr_value)
+}
+
+/// Read the number of expirations since the last read of a timer descriptor
+///
+/// Blocks until the timer expires unless the descriptor is non-blocking.
+pub fn timerfd_read(fd: fd_t) -> PosixResult<u64> {
+    let mut expirations = [0u8; 8];
+    if crate::descriptors::is_local(fd) {
+        crate::descriptors::read(fd, &mut expirations)?;
+        return Ok(u64::from_ne_bytes(expirations));
+    }
+    syscall::read(fd, expirations.as_mut_ptr(), expirations.len())?;
+    Ok(u64::from_ne_bytes(expirations))
+}
@@ -17,6 +17,9 @@ use core::ffi;
 /// # Returns
 /// * `pid_t` - Process ID of the current process
 pub fn getpid() -> pid_t {
+    if let Some(pid) = crate::vdso::vdso_getpid() {
+        return pid;
+    }
     unsafe { syscall::getpid() }
 }
 
@@ -155,6 +158,17 @@ pub fn fork() -> PosixResult<pid_t> {
     }
 }
 
+/// Fork a process sharing the parent's address space
+/// 
+/// This function provides compatibility with the POSIX vfork() function.
+/// The parent is suspended until the child calls execve() or _exit().
+/// 
+/// # Returns
+/// * `PosixResult<pid_t>` - PID of child process (0 in child), error on failure
+pub fn vfork() -> PosixResult<pid_t> {
+    syscall::vfork()
+}
+
 /// Execute a program
 /// 
 /// This function provides compatibility with the POSIX execve() function.
@@ -300,9 +314,7 @@ pub fn setpgrp(pgid: pid_t) -> PosixResult<()> {
 /// # Returns
 /// * `PosixResult<pid_t>` - Session ID, error on failure
 pub fn setsid() -> PosixResult<pid_t> {
-    // In a real implementation, this would call syscall::setsid
-    // For now, return not implemented
-    Err(Errno::Enosys)
+    syscall::setsid()
 }
 
 /// Get session ID
@@ -315,9 +327,7 @@ pub fn setsid() -> PosixResult<pid_t> {
 /// # Returns
 /// * `PosixResult<pid_t>` - Session ID, error on failure
 pub fn getsid(pid: pid_t) -> PosixResult<pid_t> {
-    // In a real implementation, this would call syscall::getsid
-    // For now, return not implemented
-    Err(Errno::Enosys)
+    syscall::getsid(pid)
 }
 
 /// Get process group ID for a specific process
@@ -330,9 +340,7 @@ pub fn getsid(pid: pid_t) -> PosixResult<pid_t> {
 /// # Returns
 /// * `PosixResult<pid_t>` - Process group ID, error on failure
 pub fn getpgid(pid: pid_t) -> PosixResult<pid_t> {
-    // In a real implementation, this would call syscall::getpgid
-    // For now, return not implemented
-    Err(Errno::Enosys)
+    syscall::getpgid(pid)
 }
 
 /// Set process group ID for a specific process
@@ -346,9 +354,7 @@ pub fn getpgid(pid: pid_t) -> PosixResult<pid_t> {
 /// # Returns
 /// * `PosixResult<()>` - Success on setpgid, error on failure
 pub fn setpgid(pid: pid_t, pgid: pid_t) -> PosixResult<()> {
-    // In a real implementation, this would call syscall::setpgid
-    // For now, return not implemented
-    Err(Errno::Enosys)
+    syscall::setpgid(pid, pgid)
 }
 
 /// Change file access and modification times
@@ -497,10 +503,15 @@ pub fn killpg(pgid: pid_t, sig: i32) -> PosixResult<()> {
 /// # Returns
 /// * `u32` - Number of seconds actually slept (0 on success)
 pub fn sleep(seconds: u32) -> u32 {
-    nanosleep(&timespec {
+    let mut remaining = timespec { tv_sec: 0, tv_nsec: 0 };
+    match nanosleep(&timespec {
         tv_sec: seconds as time_t,
         tv_nsec: 0,
-    }, None).unwrap_or_else(|_| seconds)
+    }, Some(&mut remaining)) {
+        Ok(()) => 0,
+        Err(Errno::Eintr) => remaining.tv_sec as u32 + (remaining.tv_nsec > 0) as u32,
+        Err(_) => seconds,
+    }
 }
 
 /// Suspend execution for a specified time
@@ -514,9 +525,7 @@ pub fn sleep(seconds: u32) -> u32 {
 /// # Returns
 /// * `PosixResult<()>` - Success on completion, error on interruption/failure
 pub fn nanosleep(requested_time: &timespec, remaining: Option<&mut timespec>) -> PosixResult<()> {
-    // In a real implementation, this would call syscall::nanosleep
-    // For now, return not implemented
-    Err(Errno::Enosys)
+    crate::timer::clock_nanosleep(CLOCK_MONOTONIC, 0, requested_time, remaining)
 }
 
 /// Get system time
@@ -529,6 +538,12 @@ pub fn nanosleep(requested_time: &timespec, remaining: Option<&mut timespec>) ->
 /// # Returns
 /// * `PosixResult<time_t>` - Time in seconds since Unix epoch
 pub fn time(tloc: *mut time_t) -> PosixResult<time_t> {
+    if let Some(now) = crate::vdso::vdso_clock_gettime(crate::timer::CLOCK_REALTIME_COARSE) {
+        if !tloc.is_null() {
+            unsafe { *tloc = now.tv_sec };
+        }
+        return Ok(now.tv_sec);
+    }
     unsafe {
         let result = syscall::time(tloc);
         if result < 0 {
@@ -549,7 +564,10 @@ pub fn time(tloc: *mut time_t) -> PosixResult<time_t> {
 /// 
 /// # Returns
 /// * `PosixResult<()>` - Success on gettimeofday, error on failure
-pub fn gettimeofday(tv: &mut timeval, tz: Option<&mut timezone>) -> PosixResult<()> {
+pub fn gettimeofday(tv: &mut timeval, mut tz: Option<&mut timezone>) -> PosixResult<()> {
+    if crate::vdso::vdso_gettimeofday(tv, tz.as_deref_mut()).is_some() {
+        return Ok(());
+    }
     unsafe {
         let result = syscall::gettimeofday(tv as *mut timeval, 
                                          tz.map_or(core::ptr::null_mut(), |tz| tz as *mut timezone));
@@ -684,9 +702,10 @@ pub fn umask(mask: mode_t) -> mode_t {
 /// # Returns
 /// * `PosixResult<()>` - Success on pipe creation, error on failure
 pub fn pipe(pipefd: &mut [fd_t; 2]) -> PosixResult<()> {
-    // In a real implementation, this would call syscall::pipe
-    // For now, return not implemented
-    Err(Errno::Enosys)
+    // The kernel has no pipes; the descriptor layer buffers them in-process
+    let (read_fd, write_fd) = crate::descriptors::open_pipe(false)?;
+    *pipefd = [read_fd, write_fd];
+    Ok(())
 }
 
 /// Truncate a file
@@ -720,9 +739,7 @@ pub fn truncate(path: &str, length: off_t) -> PosixResult<()> {
 /// # Returns
 /// * `bool` - True if file descriptor is a terminal, false otherwise
 pub fn isatty(fd: fd_t) -> bool {
-    // In a real implementation, this would call syscall::isatty
-    // For now, return false (not a terminal)
-    false
+    crate::termios::is_terminal(fd)
 }
 
 /// Get terminal name
@@ -0,0 +1,776 @@
+//! UNIX Domain Sockets for MultiOS
+//!
+//! This module provides the kernel object behind AF_UNIX sockets:
+//! - `UnixSocketTable`, which owns every local socket: stream, datagram and
+//!   seqpacket sockets, the filesystem and abstract namespaces, listen
+//!   backlogs, socketpair() and per-socket message queues
+//! - SCM_RIGHTS passing of open files and SCM_CREDENTIALS/SO_PEERCRED
+//!
+//! Files travel as `FileHandle`s (open file descriptions): the descriptor
+//! layer turns the sender's descriptors into handles and installs new
+//! descriptors for the handles a receiver gets back. Handles still queued
+//! when a socket is closed are returned so their references can be dropped.
+
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+use crate::errors::*;
+use crate::netstack::SOMAXCONN;
+use crate::poll::{EPOLLERR, EPOLLHUP, EPOLLIN, EPOLLOUT, EPOLLRDHUP};
+use crate::socket::{
+    sockaddr, sockaddr_un, ucred, AF_UNIX, MSG_PEEK, MSG_TRUNC, SCM_MAX_FD, SHUT_RD, SHUT_RDWR,
+    SHUT_WR, SOCK_CLOEXEC, SOCK_DGRAM, SOCK_NONBLOCK, SOCK_SEQPACKET, SOCK_STREAM, SOL_SOCKET,
+    SO_ACCEPTCONN, SO_DOMAIN, SO_ERROR, SO_PASSCRED, SO_PEERCRED, SO_PROTOCOL, SO_RCVBUF,
+    SO_SNDBUF, SO_TYPE,
+};
+use crate::sys_types::socklen_t;
+
+/// Identifier of a socket inside the table
+pub type UnixSocketId = u32;
+
+/// Kernel handle of an open file description carried by SCM_RIGHTS
+pub type FileHandle = u64;
+
+/// Size of `sockaddr_un.sun_path`
+pub const UNIX_PATH_MAX: usize = 108;
+
+/// Default receive queue limit, in bytes
+pub const UNIX_DEFAULT_BUFFER: usize = 208 * 1024;
+
+const UNIX_MIN_BUFFER: usize = 2048;
+const UNIX_MAX_BUFFER: usize = 4 * 1024 * 1024;
+/// Offset of `sun_path` inside `sockaddr_un`
+const SUN_PATH_OFFSET: usize = core::mem::size_of::<u16>();
+
+/// Address of a UNIX domain socket
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum UnixAddr {
+    /// Not bound to a name
+    #[default]
+    Unnamed,
+    /// Filesystem pathname
+    Path(Vec<u8>),
+    /// Linux abstract namespace name (leading NUL byte in `sun_path`)
+    Abstract(Vec<u8>),
+}
+
+impl UnixAddr {
+    /// Decode an address passed to a system call
+    ///
+    /// # Safety
+    /// `addr` must point to at least `len` readable bytes.
+    pub unsafe fn read_raw(addr: *const sockaddr, len: socklen_t) -> PosixResult<Self> {
+        let len = len as usize;
+        if addr.is_null() || len < SUN_PATH_OFFSET || len > core::mem::size_of::<sockaddr_un>() {
+            return Err(Errno::Einval);
+        }
+        if (*addr).sa_family != AF_UNIX {
+            return Err(Errno::Eafnosupport);
+        }
+        let bytes = core::slice::from_raw_parts(addr as *const u8, len);
+        let path = &bytes[SUN_PATH_OFFSET..];
+        Ok(match path.first() {
+            None => UnixAddr::Unnamed,
+            Some(0) => UnixAddr::Abstract(path[1..].to_vec()),
+            Some(_) => {
+                let end = path.iter().position(|&b| b == 0).unwrap_or(path.len());
+                UnixAddr::Path(path[..end].to_vec())
+            }
+        })
+    }
+
+    /// Store the address into a caller buffer
+    ///
+    /// The copy is truncated to `*len` bytes and `*len` is set to the full
+    /// length of the address.
+    ///
+    /// # Safety
+    /// `addr` must point to at least `*len` writable bytes.
+    pub unsafe fn write_raw(&self, addr: *mut sockaddr, len: &mut socklen_t) {
+        let mut bytes = AF_UNIX.to_ne_bytes().to_vec();
+        match self {
+            UnixAddr::Unnamed => {}
+            UnixAddr::Path(path) => {
+                bytes.extend_from_slice(path);
+                bytes.push(0);
+            }
+            UnixAddr::Abstract(name) => {
+                bytes.push(0);
+                bytes.extend_from_slice(name);
+            }
+        }
+        if !addr.is_null() {
+            let count = bytes.len().min(*len as usize);
+            core::ptr::copy_nonoverlapping(bytes.as_ptr(), addr as *mut u8, count);
+        }
+        *len = bytes.len() as socklen_t;
+    }
+}
+
+/// Connection state of a socket
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnixState {
+    Unconnected,
+    Listening,
+    Connected,
+    /// The peer of a connection-oriented socket has gone away
+    Disconnected,
+}
+
+/// One queued message; stream sockets read across message boundaries
+#[derive(Debug, Clone)]
+struct UnixMessage {
+    data: Vec<u8>,
+    /// Bytes already consumed by stream reads
+    consumed: usize,
+    from: UnixAddr,
+    rights: Vec<FileHandle>,
+    creds: ucred,
+}
+
+/// Result of `UnixSocketTable::recvmsg`
+#[derive(Debug, Clone, Default)]
+pub struct UnixReceived {
+    /// Bytes copied, or the full message length with MSG_TRUNC
+    pub len: usize,
+    pub from: UnixAddr,
+    /// Files passed with SCM_RIGHTS, to be installed as new descriptors
+    pub rights: Vec<FileHandle>,
+    /// Sender credentials, when SO_PASSCRED is enabled
+    pub creds: Option<ucred>,
+    /// The datagram did not fit the buffer (MSG_TRUNC in msg_flags)
+    pub truncated: bool,
+}
+
+#[derive(Debug, Clone)]
+struct UnixSocket {
+    kind: i32,
+    state: UnixState,
+    local: UnixAddr,
+    peer: Option<UnixSocketId>,
+    backlog: usize,
+    accept_queue: VecDeque<UnixSocketId>,
+    queue: VecDeque<UnixMessage>,
+    queued_bytes: usize,
+    rcvbuf: usize,
+    sndbuf: usize,
+    nonblocking: bool,
+    passcred: bool,
+    /// Credentials of the creating process
+    creds: ucred,
+    /// Credentials of the peer at connect time (SO_PEERCRED)
+    peer_creds: Option<ucred>,
+    read_shutdown: bool,
+    write_shutdown: bool,
+    /// The peer will send no more data
+    peer_closed: bool,
+    error: Option<Errno>,
+}
+
+impl UnixSocket {
+    fn new(kind: i32, creds: ucred) -> Self {
+        Self {
+            kind,
+            state: UnixState::Unconnected,
+            local: UnixAddr::Unnamed,
+            peer: None,
+            backlog: 0,
+            accept_queue: VecDeque::new(),
+            queue: VecDeque::new(),
+            queued_bytes: 0,
+            rcvbuf: UNIX_DEFAULT_BUFFER,
+            sndbuf: UNIX_DEFAULT_BUFFER,
+            nonblocking: false,
+            passcred: false,
+            creds,
+            peer_creds: None,
+            read_shutdown: false,
+            write_shutdown: false,
+            peer_closed: false,
+            error: None,
+        }
+    }
+
+    fn is_connection_oriented(&self) -> bool {
+        self.kind != SOCK_DGRAM
+    }
+
+    /// Free space in the receive queue
+    fn space(&self) -> usize {
+        self.rcvbuf.saturating_sub(self.queued_bytes)
+    }
+
+    fn take_rights(&mut self) -> Vec<FileHandle> {
+        self.queue.iter_mut().flat_map(|message| core::mem::take(&mut message.rights)).collect()
+    }
+}
+
+/// UNIX domain socket statistics
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UnixSocketStats {
+    pub sockets_created: u64,
+    pub sockets_released: u64,
+    pub connections: u64,
+    pub messages_sent: u64,
+    pub bytes_sent: u64,
+    pub rights_passed: u64,
+    /// Handles dropped because their socket was closed before delivery
+    pub rights_discarded: u64,
+}
+
+/// All AF_UNIX sockets of the system
+#[derive(Debug, Default)]
+pub struct UnixSocketTable {
+    sockets: BTreeMap<UnixSocketId, UnixSocket>,
+    next_id: UnixSocketId,
+    /// Bound names in the filesystem and abstract namespaces
+    names: BTreeMap<UnixAddr, UnixSocketId>,
+    next_autobind: u32,
+    /// Sockets whose readiness may have changed since the last drain
+    dirty: BTreeSet<UnixSocketId>,
+    stats: UnixSocketStats,
+}
+
+impl UnixSocketTable {
+    pub fn new() -> Self {
+        Self { next_id: 1, ..Self::default() }
+    }
+
+    fn sock(&self, id: UnixSocketId) -> PosixResult<&UnixSocket> {
+        self.sockets.get(&id).ok_or(Errno::Ebadf)
+    }
+
+    fn sock_mut(&mut self, id: UnixSocketId) -> PosixResult<&mut UnixSocket> {
+        self.sockets.get_mut(&id).ok_or(Errno::Ebadf)
+    }
+
+    fn insert(&mut self, sock: UnixSocket) -> UnixSocketId {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1).max(1);
+        self.sockets.insert(id, sock);
+        self.stats.sockets_created += 1;
+        id
+    }
+
+    /// Create a socket (socket(AF_UNIX, ...)) owned by a process with `creds`
+    pub fn socket(&mut self, ty: i32, protocol: i32, creds: ucred) -> PosixResult<UnixSocketId> {
+        let kind = ty & !(SOCK_NONBLOCK | SOCK_CLOEXEC);
+        if !matches!(kind, SOCK_STREAM | SOCK_DGRAM | SOCK_SEQPACKET) || protocol != 0 {
+            return Err(Errno::Eprotonosupp);
+        }
+        let mut sock = UnixSocket::new(kind, creds);
+        sock.nonblocking = ty & SOCK_NONBLOCK != 0;
+        Ok(self.insert(sock))
+    }
+
+    /// Create a connected pair (socketpair())
+    pub fn socketpair(&mut self, ty: i32, protocol: i32, creds: ucred) -> PosixResult<(UnixSocketId, UnixSocketId)> {
+        let first = self.socket(ty, protocol, creds)?;
+        let second = self.socket(ty, protocol, creds)?;
+        for (id, peer) in [(first, second), (second, first)] {
+            let sock = self.sock_mut(id)?;
+            sock.peer = Some(peer);
+            sock.peer_creds = Some(creds);
+            sock.state = UnixState::Connected;
+        }
+        self.stats.connections += 1;
+        Ok((first, second))
+    }
+
+    /// Bind a socket to a name (bind()); `Unnamed` picks an abstract name
+    ///
+    /// The filesystem node of a path binding is created by the VFS; it calls
+    /// `unlink_path` when the node is removed.
+    pub fn bind(&mut self, id: UnixSocketId, addr: UnixAddr) -> PosixResult<()> {
+        if self.sock(id)?.local != UnixAddr::Unnamed {
+            return Err(Errno::Einval);
+        }
+        let addr = match addr {
+            UnixAddr::Unnamed => return self.autobind(id),
+            UnixAddr::Path(path) if path.is_empty() => return Err(Errno::Enoent),
+            UnixAddr::Path(path) if path.len() >= UNIX_PATH_MAX => return Err(Errno::Enametoolong),
+            UnixAddr::Abstract(name) if name.len() >= UNIX_PATH_MAX => return Err(Errno::Enametoolong),
+            addr => addr,
+        };
+        if self.names.contains_key(&addr) {
+            return Err(Errno::Eaddrinuse);
+        }
+        self.names.insert(addr.clone(), id);
+        self.sock_mut(id)?.local = addr;
+        Ok(())
+    }
+
+    /// Bind to a fresh five hex digit abstract name, as Linux does
+    fn autobind(&mut self, id: UnixSocketId) -> PosixResult<()> {
+        for _ in 0..0x100000 {
+            let name = format!("{:05x}", self.next_autobind).into_bytes();
+            self.next_autobind = (self.next_autobind + 1) & 0xfffff;
+            let addr = UnixAddr::Abstract(name);
+            if !self.names.contains_key(&addr) {
+                self.names.insert(addr.clone(), id);
+                self.sock_mut(id)?.local = addr;
+                return Ok(());
+            }
+        }
+        Err(Errno::Eaddrinuse)
+    }
+
+    /// Forget a filesystem binding whose socket file was unlinked
+    pub fn unlink_path(&mut self, path: &[u8]) {
+        self.names.remove(&UnixAddr::Path(path.to_vec()));
+    }
+
+    /// Socket bound to `addr`; missing paths report `Enoent`
+    fn lookup(&self, addr: &UnixAddr) -> PosixResult<UnixSocketId> {
+        match self.names.get(addr) {
+            Some(&id) => Ok(id),
+            None if matches!(addr, UnixAddr::Path(_)) => Err(Errno::Enoent),
+            None => Err(Errno::Econnrefused),
+        }
+    }
+
+    /// Start accepting connections (listen())
+    pub fn listen(&mut self, id: UnixSocketId, backlog: i32) -> PosixResult<()> {
+        let sock = self.sock_mut(id)?;
+        if !sock.is_connection_oriented() {
+            return Err(Errno::Eopnotsupp);
+        }
+        if sock.local == UnixAddr::Unnamed || !matches!(sock.state, UnixState::Unconnected | UnixState::Listening) {
+            return Err(Errno::Einval);
+        }
+        sock.backlog = backlog.clamp(1, SOMAXCONN) as usize;
+        sock.state = UnixState::Listening;
+        Ok(())
+    }
+
+    /// Connect to a bound socket (connect())
+    ///
+    /// Stream and seqpacket connections complete immediately: the server
+    /// side is created and queued on the listener. `Eagain` means the
+    /// backlog is full. Datagram sockets just record the default peer.
+    pub fn connect(&mut self, id: UnixSocketId, addr: &UnixAddr) -> PosixResult<()> {
+        let target = self.lookup(addr)?;
+        let sock = self.sock(id)?;
+        let listener = self.sock(target)?;
+        if listener.kind != sock.kind {
+            return Err(Errno::Econnrefused);
+        }
+
+        if !sock.is_connection_oriented() {
+            self.sock_mut(id)?.peer = Some(target);
+            return Ok(());
+        }
+        match sock.state {
+            UnixState::Unconnected => {}
+            UnixState::Listening => return Err(Errno::Einval),
+            _ => return Err(Errno::Eisconn),
+        }
+        if listener.state != UnixState::Listening {
+            return Err(Errno::Econnrefused);
+        }
+        if listener.accept_queue.len() >= listener.backlog {
+            return Err(Errno::Eagain);
+        }
+
+        let mut server = UnixSocket::new(sock.kind, listener.creds);
+        server.local = listener.local.clone();
+        server.peer_creds = Some(sock.creds);
+        server.passcred = listener.passcred;
+        server.rcvbuf = listener.rcvbuf;
+        server.sndbuf = listener.sndbuf;
+        server.state = UnixState::Connected;
+        server.peer = Some(id);
+        let listener_creds = listener.creds;
+        let server = self.insert(server);
+
+        let sock = self.sock_mut(id)?;
+        sock.peer = Some(server);
+        sock.peer_creds = Some(listener_creds);
+        sock.state = UnixState::Connected;
+        self.sock_mut(target)?.accept_queue.push_back(server);
+        self.dirty.insert(target);
+        self.stats.connections += 1;
+        Ok(())
+    }
+
+    /// Take a connection off a listener (accept()), with the peer's address
+    pub fn accept(&mut self, id: UnixSocketId) -> PosixResult<(UnixSocketId, UnixAddr)> {
+        let sock = self.sock_mut(id)?;
+        if sock.state != UnixState::Listening {
+            return Err(Errno::Einval);
+        }
+        let server = sock.accept_queue.pop_front().ok_or(Errno::Eagain)?;
+        let peer_addr = self.sock(server)?.peer
+            .and_then(|peer| self.sockets.get(&peer))
+            .map_or(UnixAddr::Unnamed, |peer| peer.local.clone());
+        Ok((server, peer_addr))
+    }
+
+    /// Send data, optionally with files and to an explicit address (sendmsg())
+    ///
+    /// `creds` are the sender's credentials. Stream sockets accept as much as
+    /// fits in the peer's queue; datagrams and seqpackets are all or nothing.
+    pub fn sendmsg(
+        &mut self,
+        id: UnixSocketId,
+        data: &[u8],
+        rights: &[FileHandle],
+        dest: Option<&UnixAddr>,
+        creds: ucred,
+    ) -> PosixResult<usize> {
+        if rights.len() > SCM_MAX_FD {
+            return Err(Errno::Einval);
+        }
+        let sock = self.sock(id)?;
+        if sock.write_shutdown {
+            return Err(Errno::Epipe);
+        }
+
+        let target = if sock.is_connection_oriented() {
+            if dest.is_some() && sock.state == UnixState::Connected {
+                return Err(Errno::Eisconn);
+            }
+            match (sock.state, sock.peer) {
+                (UnixState::Connected, Some(peer)) => peer,
+                (UnixState::Disconnected, _) => return Err(Errno::Epipe),
+                _ => return Err(Errno::Enotconn),
+            }
+        } else {
+            match dest {
+                Some(addr) => self.lookup(addr)?,
+                None => sock.peer.ok_or(Errno::Enotconn)?,
+            }
+        };
+        let from = sock.local.clone();
+        let kind = sock.kind;
+        let stream = kind == SOCK_STREAM;
+        let sndbuf = sock.sndbuf;
+
+        let receiver = match self.sockets.get_mut(&target) {
+            Some(receiver) => receiver,
+            // The default peer of a datagram socket has been closed
+            None => return Err(Errno::Econnrefused),
+        };
+        if receiver.kind != kind {
+            return Err(Errno::Econnrefused);
+        }
+        if receiver.read_shutdown {
+            return Err(Errno::Epipe);
+        }
+        if !receiver.is_connection_oriented() && receiver.peer.map_or(false, |peer| peer != id) {
+            // Connected datagram sockets only hear from their peer
+            return Err(Errno::Epperm);
+        }
+
+        let count = if stream {
+            let count = data.len().min(receiver.space());
+            if count == 0 && !data.is_empty() {
+                return Err(Errno::Eagain);
+            }
+            count
+        } else {
+            if data.len() > sndbuf.max(receiver.rcvbuf) {
+                return Err(Errno::Elength);
+            }
+            if data.len() > receiver.space() {
+                return Err(Errno::Eagain);
+            }
+            data.len()
+        };
+
+        receiver.queue.push_back(UnixMessage {
+            data: data[..count].to_vec(),
+            consumed: 0,
+            from,
+            rights: rights.to_vec(),
+            creds,
+        });
+        receiver.queued_bytes += count;
+        self.dirty.insert(target);
+        self.stats.messages_sent += 1;
+        self.stats.bytes_sent += count as u64;
+        self.stats.rights_passed += rights.len() as u64;
+        Ok(count)
+    }
+
+    /// Receive data and any passed files (recvmsg())
+    ///
+    /// Stream reads stop before a message that carries files, so each batch
+    /// of files arrives with the first byte it was sent with. MSG_PEEK leaves
+    /// files queued and does not report them.
+    pub fn recvmsg(&mut self, id: UnixSocketId, buf: &mut [u8], flags: i32) -> PosixResult<UnixReceived> {
+        let peek = flags & MSG_PEEK != 0;
+        let sock = self.sock_mut(id)?;
+        if sock.queue.is_empty() {
+            if let Some(err) = sock.error.take() {
+                return Err(err);
+            }
+            if sock.read_shutdown || (sock.is_connection_oriented() && sock.peer_closed) {
+                return Ok(UnixReceived::default());
+            }
+            return match sock.state {
+                UnixState::Listening => Err(Errno::Einval),
+                UnixState::Unconnected if sock.is_connection_oriented() => Err(Errno::Enotconn),
+                _ => Err(Errno::Eagain),
+            };
+        }
+
+        let passcred = sock.passcred;
+        let mut received = UnixReceived::default();
+        if sock.kind == SOCK_STREAM {
+            let mut copied = 0;
+            let mut index = 0;
+            while copied < buf.len() {
+                let message = match sock.queue.get_mut(index) {
+                    Some(message) => message,
+                    None => break,
+                };
+                if copied > 0 && !message.rights.is_empty() {
+                    break;
+                }
+                if copied == 0 {
+                    received.from = message.from.clone();
+                    received.creds = passcred.then_some(message.creds);
+                    if !peek {
+                        received.rights = core::mem::take(&mut message.rights);
+                    }
+                }
+                let available = &message.data[message.consumed..];
+                let count = available.len().min(buf.len() - copied);
+                buf[copied..copied + count].copy_from_slice(&available[..count]);
+                copied += count;
+                if peek {
+                    index += 1;
+                } else {
+                    message.consumed += count;
+                    if message.consumed == message.data.len() {
+                        sock.queue.pop_front();
+                    }
+                }
+            }
+            if !peek {
+                sock.queued_bytes -= copied;
+            }
+            received.len = copied;
+        } else {
+            let message = if peek {
+                sock.queue.front().cloned().map(|mut message| {
+                    message.rights.clear();
+                    message
+                })
+            } else {
+                sock.queue.pop_front()
+            };
+            let message = message.expect("queue is not empty");
+            if !peek {
+                sock.queued_bytes -= message.data.len();
+            }
+            let count = buf.len().min(message.data.len());
+            buf[..count].copy_from_slice(&message.data[..count]);
+            received.truncated = count < message.data.len();
+            received.len = if flags & MSG_TRUNC != 0 { message.data.len() } else { count };
+            received.from = message.from;
+            received.rights = message.rights;
+            received.creds = passcred.then_some(message.creds);
+        }
+
+        // Space freed in our queue may unblock the writer
+        if !peek {
+            if let Some(peer) = sock.peer {
+                self.dirty.insert(peer);
+            }
+        }
+        Ok(received)
+    }
+
+    /// Shut down one or both directions (shutdown())
+    pub fn shutdown(&mut self, id: UnixSocketId, how: i32) -> PosixResult<()> {
+        if how != SHUT_RD && how != SHUT_WR && how != SHUT_RDWR {
+            return Err(Errno::Einval);
+        }
+        let sock = self.sock_mut(id)?;
+        if sock.is_connection_oriented() && sock.state != UnixState::Connected {
+            return Err(Errno::Enotconn);
+        }
+        if how != SHUT_WR {
+            sock.read_shutdown = true;
+        }
+        if how != SHUT_RD {
+            sock.write_shutdown = true;
+        }
+        let peer_id = sock.peer.filter(|_| sock.is_connection_oriented());
+        self.dirty.insert(id);
+        if let Some(peer_id) = peer_id {
+            if let Some(peer) = self.sockets.get_mut(&peer_id) {
+                if how != SHUT_RD {
+                    peer.peer_closed = true;
+                }
+                if how != SHUT_WR {
+                    peer.write_shutdown = true;
+                }
+                self.dirty.insert(peer_id);
+            }
+        }
+        Ok(())
+    }
+
+    /// Close a socket, returning the file handles still queued on it (and on
+    /// unaccepted connections) so the caller can drop their references
+    pub fn close(&mut self, id: UnixSocketId) -> PosixResult<Vec<FileHandle>> {
+        let mut sock = self.sockets.remove(&id).ok_or(Errno::Ebadf)?;
+        self.stats.sockets_released += 1;
+        self.dirty.remove(&id);
+        if sock.local != UnixAddr::Unnamed && self.names.get(&sock.local) == Some(&id) {
+            self.names.remove(&sock.local);
+        }
+
+        let mut rights = sock.take_rights();
+        let unread = !sock.queue.is_empty();
+        for pending in core::mem::take(&mut sock.accept_queue) {
+            rights.extend(self.close(pending)?);
+        }
+
+        // Connection-oriented peers see end of file, or a reset if data we
+        // never read was thrown away
+        let peers: Vec<UnixSocketId> = self.sockets.iter()
+            .filter(|(_, other)| other.peer == Some(id))
+            .map(|(&other_id, _)| other_id)
+            .collect();
+        for peer_id in peers {
+            let peer = self.sockets.get_mut(&peer_id).expect("peer exists");
+            if peer.is_connection_oriented() {
+                peer.state = UnixState::Disconnected;
+                peer.peer_closed = true;
+                if unread {
+                    peer.error = Some(Errno::Econnreset);
+                }
+            }
+            self.dirty.insert(peer_id);
+        }
+
+        self.stats.rights_discarded += rights.len() as u64;
+        Ok(rights)
+    }
+
+    /// Set or clear non-blocking mode (O_NONBLOCK)
+    pub fn set_nonblocking(&mut self, id: UnixSocketId, nonblocking: bool) -> PosixResult<()> {
+        self.sock_mut(id)?.nonblocking = nonblocking;
+        Ok(())
+    }
+
+    pub fn is_nonblocking(&self, id: UnixSocketId) -> PosixResult<bool> {
+        self.sock(id).map(|sock| sock.nonblocking)
+    }
+
+    pub fn state(&self, id: UnixSocketId) -> PosixResult<UnixState> {
+        self.sock(id).map(|sock| sock.state)
+    }
+
+    /// Bound name (getsockname())
+    pub fn local_addr(&self, id: UnixSocketId) -> PosixResult<UnixAddr> {
+        self.sock(id).map(|sock| sock.local.clone())
+    }
+
+    /// Name of the peer (getpeername())
+    pub fn peer_addr(&self, id: UnixSocketId) -> PosixResult<UnixAddr> {
+        let sock = self.sock(id)?;
+        let peer = sock.peer.filter(|_| sock.state != UnixState::Disconnected).ok_or(Errno::Enotconn)?;
+        Ok(self.sockets.get(&peer).map_or(UnixAddr::Unnamed, |peer| peer.local.clone()))
+    }
+
+    /// Current poll events (EPOLLIN, EPOLLOUT, ...) of a socket
+    pub fn readiness(&self, id: UnixSocketId) -> PosixResult<u32> {
+        let sock = self.sock(id)?;
+        let mut events = 0;
+        if sock.error.is_some() {
+            events |= EPOLLERR;
+        }
+        if sock.state == UnixState::Listening {
+            if !sock.accept_queue.is_empty() {
+                events |= EPOLLIN;
+            }
+            return Ok(events);
+        }
+
+        if !sock.queue.is_empty() || sock.read_shutdown || sock.peer_closed {
+            events |= EPOLLIN;
+        }
+        if sock.peer_closed {
+            events |= EPOLLRDHUP;
+        }
+        if sock.state == UnixState::Disconnected || (sock.peer_closed && sock.write_shutdown) {
+            events |= EPOLLHUP;
+        }
+        let writable = match sock.peer.and_then(|peer| self.sockets.get(&peer)) {
+            Some(peer) => peer.space() > 0,
+            // Unconnected datagram sockets can always try a sendto()
+            None => !sock.is_connection_oriented(),
+        };
+        if writable && !sock.write_shutdown && sock.state != UnixState::Disconnected {
+            events |= EPOLLOUT;
+        }
+        Ok(events)
+    }
+
+    /// Sockets whose readiness changed through their peers' activity
+    pub fn drain_wakeups(&mut self) -> Vec<UnixSocketId> {
+        core::mem::take(&mut self.dirty).into_iter().collect()
+    }
+
+    /// Set a socket option (setsockopt())
+    pub fn setsockopt(&mut self, id: UnixSocketId, level: i32, name: i32, value: &[u8]) -> PosixResult<()> {
+        let sock = self.sock_mut(id)?;
+        if level != SOL_SOCKET {
+            return Err(Errno::Eopnotsupp);
+        }
+        let int = value.get(..4)
+            .map(|bytes| i32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            .ok_or(Errno::Einval)?;
+        let buffer_size = (int.max(0) as usize).clamp(UNIX_MIN_BUFFER, UNIX_MAX_BUFFER);
+        match name {
+            SO_PASSCRED => sock.passcred = int != 0,
+            SO_SNDBUF => sock.sndbuf = buffer_size,
+            SO_RCVBUF => sock.rcvbuf = buffer_size,
+            _ => return Err(Errno::Eprotoall),
+        }
+        Ok(())
+    }
+
+    /// Read a socket option (getsockopt()); SO_ERROR clears the pending error
+    pub fn getsockopt(&mut self, id: UnixSocketId, level: i32, name: i32) -> PosixResult<Vec<u8>> {
+        let sock = self.sock_mut(id)?;
+        if level != SOL_SOCKET {
+            return Err(Errno::Eopnotsupp);
+        }
+        let int = |value: i32| Ok(value.to_ne_bytes().to_vec());
+        match name {
+            SO_TYPE => int(sock.kind),
+            SO_DOMAIN => int(AF_UNIX as i32),
+            SO_PROTOCOL => int(0),
+            SO_ACCEPTCONN => int((sock.state == UnixState::Listening) as i32),
+            SO_PASSCRED => int(sock.passcred as i32),
+            SO_SNDBUF => int(sock.sndbuf as i32),
+            SO_RCVBUF => int(sock.rcvbuf as i32),
+            SO_ERROR => int(sock.error.take().map_or(0, |err| err as i32)),
+            SO_PEERCRED => {
+                let creds = sock.peer_creds.ok_or(Errno::Enotconn)?;
+                let bytes = unsafe {
+                    core::slice::from_raw_parts(&creds as *const ucred as *const u8, core::mem::size_of::<ucred>())
+                };
+                Ok(bytes.to_vec())
+            }
+            _ => Err(Errno::Eprotoall),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.sockets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sockets.is_empty()
+    }
+
+    pub fn get_stats(&self) -> UnixSocketStats {
+        self.stats
+    }
+}
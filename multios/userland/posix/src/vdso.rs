@@ -0,0 +1,292 @@
+//! vDSO Data Pages for MultiOS
+//!
+//! The kernel maps two read-only pages into every process:
+//! - `VdsoData`, shared by all processes, where the timekeeping code
+//!   publishes the clock bases and the hardware counter scaling
+//! - `VdsoProcess`, right after it, holding per-process values such as the
+//!   process ID
+//!
+//! clock_gettime(), gettimeofday(), time() and getpid() answer from these
+//! pages without entering the kernel, and fall back to the system call when
+//! the pages are missing or the clock cannot be read from user mode.
+//!
+//! The clock fields are protected by a sequence counter: the kernel makes
+//! it odd while updating and readers retry when it was odd or changed.
+
+use core::sync::atomic::{fence, AtomicI32, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+
+use crate::internal::{timespec, timeval, timezone, CLOCK_MONOTONIC, CLOCK_REALTIME};
+use crate::syscall;
+use crate::timer::{CLOCK_BOOTTIME, CLOCK_MONOTONIC_COARSE, CLOCK_REALTIME_COARSE};
+use crate::types::*;
+
+/// "MVDS", identifies a valid data page
+pub const VDSO_MAGIC: u32 = 0x4d56_4453;
+
+/// Layout version of `VdsoData` and `VdsoProcess`
+pub const VDSO_VERSION: u32 = 1;
+
+/// Offset of the `VdsoProcess` page from the `VdsoData` page
+pub const VDSO_PROCESS_OFFSET: usize = 4096;
+
+/// Clocks must be read with the system call
+pub const VDSO_CLOCKMODE_NONE: u32 = 0;
+/// The architecture counter (TSC, CNTVCT_EL0, time CSR) is readable from user mode
+pub const VDSO_CLOCKMODE_COUNTER: u32 = 1;
+
+/// Clock bases published in the data page
+const VDSO_BASE_REALTIME: usize = 0;
+const VDSO_BASE_MONOTONIC: usize = 1;
+const VDSO_BASE_BOOTTIME: usize = 2;
+const VDSO_BASES: usize = 3;
+
+const NSEC_PER_SEC: u64 = 1_000_000_000;
+
+/// Clock state captured by the timekeeping code at one counter value
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VdsoClockUpdate {
+    pub clock_mode: u32,
+    /// Counter value the bases were sampled at
+    pub cycle_last: u64,
+    /// Valid bits of the counter
+    pub mask: u64,
+    /// Counter cycles to nanoseconds: `(cycles * mult) >> shift`
+    pub mult: u32,
+    pub shift: u32,
+    /// Seconds and shifted nanoseconds (`nsec << shift`) of CLOCK_REALTIME,
+    /// CLOCK_MONOTONIC and CLOCK_BOOTTIME at `cycle_last`
+    pub base: [(u64, u64); VDSO_BASES],
+}
+
+/// Shared clock data page
+#[repr(C)]
+#[derive(Debug)]
+pub struct VdsoData {
+    magic: u32,
+    version: u32,
+    seq: AtomicU32,
+    clock_mode: AtomicU32,
+    cycle_last: AtomicU64,
+    mask: AtomicU64,
+    mult: AtomicU32,
+    shift: AtomicU32,
+    base_sec: [AtomicU64; VDSO_BASES],
+    base_snsec: [AtomicU64; VDSO_BASES],
+    tz_minuteswest: AtomicI32,
+    tz_dsttime: AtomicI32,
+}
+
+/// Per-process data page
+#[repr(C)]
+#[derive(Debug)]
+pub struct VdsoProcess {
+    magic: u32,
+    pid: AtomicI32,
+}
+
+impl Default for VdsoData {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VdsoData {
+    /// An initialized page whose clocks still fall back to the system call
+    pub const fn new() -> Self {
+        Self {
+            magic: VDSO_MAGIC,
+            version: VDSO_VERSION,
+            seq: AtomicU32::new(0),
+            clock_mode: AtomicU32::new(VDSO_CLOCKMODE_NONE),
+            cycle_last: AtomicU64::new(0),
+            mask: AtomicU64::new(u64::MAX),
+            mult: AtomicU32::new(0),
+            shift: AtomicU32::new(0),
+            base_sec: [AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0)],
+            base_snsec: [AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0)],
+            tz_minuteswest: AtomicI32::new(0),
+            tz_dsttime: AtomicI32::new(0),
+        }
+    }
+
+    /// Publish new clock state (kernel timekeeping tick, clock_settime())
+    pub fn update(&self, update: &VdsoClockUpdate) {
+        let seq = self.seq.load(Ordering::Relaxed);
+        self.seq.store(seq.wrapping_add(1), Ordering::Relaxed);
+        fence(Ordering::Release);
+
+        self.clock_mode.store(update.clock_mode, Ordering::Relaxed);
+        self.cycle_last.store(update.cycle_last, Ordering::Relaxed);
+        self.mask.store(update.mask, Ordering::Relaxed);
+        self.mult.store(update.mult, Ordering::Relaxed);
+        self.shift.store(update.shift, Ordering::Relaxed);
+        for (index, &(sec, snsec)) in update.base.iter().enumerate() {
+            self.base_sec[index].store(sec, Ordering::Relaxed);
+            self.base_snsec[index].store(snsec, Ordering::Relaxed);
+        }
+
+        self.seq.store(seq.wrapping_add(2), Ordering::Release);
+    }
+
+    /// Publish the system time zone (settimeofday())
+    pub fn set_timezone(&self, tz: &timezone) {
+        self.tz_minuteswest.store(tz.tz_minuteswest, Ordering::Relaxed);
+        self.tz_dsttime.store(tz.tz_dsttime, Ordering::Relaxed);
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.magic == VDSO_MAGIC && self.version == VDSO_VERSION
+    }
+
+    /// Read a clock from the page; `None` means use the system call
+    ///
+    /// `counter` reads the hardware counter and returns `None` when it is
+    /// not accessible.
+    pub fn clock_gettime_with(&self, clock: clockid_t, counter: impl Fn() -> Option<u64>) -> Option<timespec> {
+        let (index, coarse) = match clock {
+            CLOCK_REALTIME => (VDSO_BASE_REALTIME, false),
+            CLOCK_MONOTONIC => (VDSO_BASE_MONOTONIC, false),
+            CLOCK_BOOTTIME => (VDSO_BASE_BOOTTIME, false),
+            CLOCK_REALTIME_COARSE => (VDSO_BASE_REALTIME, true),
+            CLOCK_MONOTONIC_COARSE => (VDSO_BASE_MONOTONIC, true),
+            _ => return None,
+        };
+
+        loop {
+            let seq = self.seq.load(Ordering::Acquire);
+            if seq & 1 != 0 {
+                core::hint::spin_loop();
+                continue;
+            }
+            if self.clock_mode.load(Ordering::Relaxed) == VDSO_CLOCKMODE_NONE {
+                return None;
+            }
+            let shift = self.shift.load(Ordering::Relaxed);
+            let mut sec = self.base_sec[index].load(Ordering::Relaxed);
+            let mut snsec = self.base_snsec[index].load(Ordering::Relaxed);
+            if !coarse {
+                let cycles = counter()?;
+                let delta = cycles.wrapping_sub(self.cycle_last.load(Ordering::Relaxed)) & self.mask.load(Ordering::Relaxed);
+                snsec = snsec.wrapping_add(delta.wrapping_mul(self.mult.load(Ordering::Relaxed) as u64));
+            }
+            fence(Ordering::Acquire);
+            if self.seq.load(Ordering::Relaxed) != seq {
+                continue;
+            }
+
+            let nsec = snsec >> shift;
+            sec += nsec / NSEC_PER_SEC;
+            return Some(timespec {
+                tv_sec: sec as time_t,
+                tv_nsec: (nsec % NSEC_PER_SEC) as i64,
+            });
+        }
+    }
+
+    /// Read a clock using this CPU's counter
+    pub fn clock_gettime(&self, clock: clockid_t) -> Option<timespec> {
+        self.clock_gettime_with(clock, read_counter)
+    }
+
+    pub fn timezone(&self) -> timezone {
+        timezone {
+            tz_minuteswest: self.tz_minuteswest.load(Ordering::Relaxed),
+            tz_dsttime: self.tz_dsttime.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl VdsoProcess {
+    pub const fn new(pid: pid_t) -> Self {
+        Self { magic: VDSO_MAGIC, pid: AtomicI32::new(pid) }
+    }
+
+    /// Update the process ID (the child's page after fork())
+    pub fn set_pid(&self, pid: pid_t) {
+        self.pid.store(pid, Ordering::Relaxed);
+    }
+
+    pub fn pid(&self) -> Option<pid_t> {
+        match self.pid.load(Ordering::Relaxed) {
+            pid if pid > 0 && self.magic == VDSO_MAGIC => Some(pid),
+            _ => None,
+        }
+    }
+}
+
+/// Read the architecture counter published as the vDSO clock source
+#[inline]
+pub fn read_counter() -> Option<u64> {
+    #[cfg(target_arch = "x86_64")]
+    {
+        let (high, low): (u32, u32);
+        unsafe { core::arch::asm!("rdtsc", out("edx") high, out("eax") low, options(nomem, nostack)) };
+        Some(((high as u64) << 32) | low as u64)
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        let value: u64;
+        unsafe { core::arch::asm!("isb", "mrs {}, cntvct_el0", out(reg) value, options(nomem, nostack)) };
+        Some(value)
+    }
+    #[cfg(target_arch = "riscv64")]
+    {
+        let value: u64;
+        unsafe { core::arch::asm!("rdtime {}", out(reg) value, options(nomem, nostack)) };
+        Some(value)
+    }
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64", target_arch = "riscv64")))]
+    {
+        None
+    }
+}
+
+/// Address of the data page: not looked up yet, or not mapped
+const VDSO_UNKNOWN: usize = 0;
+const VDSO_MISSING: usize = 1;
+
+static VDSO_ADDRESS: AtomicUsize = AtomicUsize::new(VDSO_UNKNOWN);
+
+/// The data page of this process, looked up once
+fn vdso_data() -> Option<&'static VdsoData> {
+    let mut addr = VDSO_ADDRESS.load(Ordering::Acquire);
+    if addr == VDSO_UNKNOWN {
+        addr = match syscall::vdso_address() {
+            Ok(found) if found > VDSO_MISSING => found,
+            _ => VDSO_MISSING,
+        };
+        VDSO_ADDRESS.store(addr, Ordering::Release);
+    }
+    if addr == VDSO_MISSING {
+        return None;
+    }
+    let data = unsafe { &*(addr as *const VdsoData) };
+    data.is_valid().then_some(data)
+}
+
+/// The per-process page of this process
+fn vdso_process() -> Option<&'static VdsoProcess> {
+    vdso_data().map(|data| unsafe { &*((data as *const VdsoData as usize + VDSO_PROCESS_OFFSET) as *const VdsoProcess) })
+}
+
+/// clock_gettime() without a system call, if the clock allows it
+pub(crate) fn vdso_clock_gettime(clock: clockid_t) -> Option<timespec> {
+    vdso_data()?.clock_gettime(clock)
+}
+
+/// gettimeofday() without a system call, if the clock allows it
+pub(crate) fn vdso_gettimeofday(tv: &mut timeval, tz: Option<&mut timezone>) -> Option<()> {
+    let data = vdso_data()?;
+    let now = data.clock_gettime(CLOCK_REALTIME)?;
+    tv.tv_sec = now.tv_sec;
+    tv.tv_usec = (now.tv_nsec / 1000) as _;
+    if let Some(tz) = tz {
+        *tz = data.timezone();
+    }
+    Some(())
+}
+
+/// getpid() without a system call
+pub(crate) fn vdso_getpid() -> Option<pid_t> {
+    vdso_process()?.pid()
+}
@@ -20,6 +20,9 @@ use heapless::{String, Vec, FnvIndexMap};
 use riscv_hal::*;
 use iot_communication::*;
 
+/// Board description the firmware is built for
+const BOARD_MODEL: &str = "multios,riscv-iot";
+
 // Sensor readings
 #[derive(Clone, Copy, Debug)]
 struct SensorReading {
@@ -199,23 +202,12 @@ impl SensorNetworkApp {
     }
 
     fn init_communication(&mut self) -> Result<(), SensorNetworkError> {
-        // Initialize WiFi for MQTT communication
-        #[cfg(feature = "wifi")]
-        {
-            let uart = UART_DRIVER.borrow();
-            let static_uart = unsafe {
-                // SAFETY: This is safe as UART_DRIVER is static and lives for program lifetime
-                &*(uart as *const Uart)
-            };
-            
-            self.comm_manager.init_wifi(static_uart, "MyWiFi", "password123")?;
-        }
-        
-        // Initialize LoRaWAN for long-range communication
-        #[cfg(feature = "lora")]
-        {
-            self.comm_manager.init_lora(SPI_DRIVER)?;
-        }
+        // WiFi for MQTT and LoRa for long range, on whichever buses the
+        // board wires the radios to
+        let board = BoardConfig::builtin(BOARD_MODEL).ok_or(SensorNetworkError::HardwareInitFailed)?;
+        self.comm_manager
+            .init_from_board(&board, Some(("MyWiFi", "password123")))
+            .map_err(|_| SensorNetworkError::CommunicationError)?;
         
         println!("✅ Communication initialized");
         Ok(())
@@ -0,0 +1,516 @@
+//! Minimal CBOR (RFC 8949) encoder and decoder
+//!
+//! Both work on caller-provided byte slices without allocation. The
+//! encoder always emits the shortest head for integers and lengths, and
+//! floats as single precision when that is lossless, which keeps payloads
+//! small enough for LoRa frames. The decoder is a pull parser over
+//! definite-length items; indefinite-length strings and containers are
+//! rejected.
+
+/// Major types
+const MAJOR_UNSIGNED: u8 = 0;
+const MAJOR_NEGATIVE: u8 = 1;
+const MAJOR_BYTES: u8 = 2;
+const MAJOR_TEXT: u8 = 3;
+const MAJOR_ARRAY: u8 = 4;
+const MAJOR_MAP: u8 = 5;
+const MAJOR_TAG: u8 = 6;
+const MAJOR_SIMPLE: u8 = 7;
+
+const SIMPLE_FALSE: u8 = 20;
+const SIMPLE_TRUE: u8 = 21;
+const SIMPLE_NULL: u8 = 22;
+const SIMPLE_UNDEFINED: u8 = 23;
+const INFO_FLOAT16: u8 = 25;
+const INFO_FLOAT32: u8 = 26;
+const INFO_FLOAT64: u8 = 27;
+const INFO_INDEFINITE: u8 = 31;
+
+/// Nesting the decoder follows when skipping an item
+const MAX_SKIP_DEPTH: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CborError {
+    /// Output buffer has no room for the item
+    BufferFull,
+    /// Input ended inside an item
+    UnexpectedEnd,
+    /// Item has a different type than requested
+    UnexpectedType,
+    /// Valid CBOR this implementation does not handle, such as
+    /// indefinite lengths or integers outside i64
+    Unsupported,
+    InvalidUtf8,
+}
+
+/// Writes CBOR items into a byte slice
+pub struct CborEncoder<'b> {
+    buf: &'b mut [u8],
+    len: usize,
+}
+
+impl<'b> CborEncoder<'b> {
+    pub fn new(buf: &'b mut [u8]) -> Self {
+        Self { buf, len: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The encoded bytes so far
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    pub fn into_bytes(self) -> &'b [u8] {
+        &self.buf[..self.len]
+    }
+
+    pub(crate) fn into_bytes_mut(self) -> &'b mut [u8] {
+        &mut self.buf[..self.len]
+    }
+
+    /// Drop everything after `len`, undoing a partly written item
+    pub(crate) fn truncate(&mut self, len: usize) {
+        self.len = self.len.min(len);
+    }
+
+    fn put(&mut self, data: &[u8]) -> Result<(), CborError> {
+        let end = self.len + data.len();
+        self.buf.get_mut(self.len..end).ok_or(CborError::BufferFull)?.copy_from_slice(data);
+        self.len = end;
+        Ok(())
+    }
+
+    fn head(&mut self, major: u8, value: u64) -> Result<(), CborError> {
+        let mut out = [0u8; 9];
+        let len = encode_head(major, value, &mut out);
+        self.put(&out[..len])
+    }
+
+    pub fn uint(&mut self, value: u64) -> Result<&mut Self, CborError> {
+        self.head(MAJOR_UNSIGNED, value)?;
+        Ok(self)
+    }
+
+    pub fn int(&mut self, value: i64) -> Result<&mut Self, CborError> {
+        if value >= 0 {
+            self.head(MAJOR_UNSIGNED, value as u64)?;
+        } else {
+            // -1 - n, computed without overflowing on i64::MIN
+            self.head(MAJOR_NEGATIVE, !(value as u64))?;
+        }
+        Ok(self)
+    }
+
+    pub fn bytes(&mut self, data: &[u8]) -> Result<&mut Self, CborError> {
+        self.head(MAJOR_BYTES, data.len() as u64)?;
+        self.put(data)?;
+        Ok(self)
+    }
+
+    pub fn text(&mut self, text: &str) -> Result<&mut Self, CborError> {
+        self.head(MAJOR_TEXT, text.len() as u64)?;
+        self.put(text.as_bytes())?;
+        Ok(self)
+    }
+
+    /// Start an array of `len` items; the items follow
+    pub fn array(&mut self, len: usize) -> Result<&mut Self, CborError> {
+        self.head(MAJOR_ARRAY, len as u64)?;
+        Ok(self)
+    }
+
+    /// Start a map of `len` key/value pairs; the pairs follow
+    pub fn map(&mut self, len: usize) -> Result<&mut Self, CborError> {
+        self.head(MAJOR_MAP, len as u64)?;
+        Ok(self)
+    }
+
+    pub fn tag(&mut self, tag: u64) -> Result<&mut Self, CborError> {
+        self.head(MAJOR_TAG, tag)?;
+        Ok(self)
+    }
+
+    pub fn bool(&mut self, value: bool) -> Result<&mut Self, CborError> {
+        self.put(&[MAJOR_SIMPLE << 5 | if value { SIMPLE_TRUE } else { SIMPLE_FALSE }])?;
+        Ok(self)
+    }
+
+    pub fn null(&mut self) -> Result<&mut Self, CborError> {
+        self.put(&[MAJOR_SIMPLE << 5 | SIMPLE_NULL])?;
+        Ok(self)
+    }
+
+    /// Encode a float as single precision when that loses nothing
+    pub fn float(&mut self, value: f64) -> Result<&mut Self, CborError> {
+        let single = value as f32;
+        if single as f64 == value || value.is_nan() {
+            self.put(&[MAJOR_SIMPLE << 5 | INFO_FLOAT32])?;
+            self.put(&single.to_bits().to_be_bytes())?;
+        } else {
+            self.put(&[MAJOR_SIMPLE << 5 | INFO_FLOAT64])?;
+            self.put(&value.to_bits().to_be_bytes())?;
+        }
+        Ok(self)
+    }
+
+    /// Encode a number as an integer when it has no fractional part,
+    /// otherwise as a float
+    pub fn number(&mut self, value: f64) -> Result<&mut Self, CborError> {
+        const EXACT: f64 = (1u64 << 53) as f64;
+        if (-EXACT..=EXACT).contains(&value) && value as i64 as f64 == value {
+            self.int(value as i64)
+        } else {
+            self.float(value)
+        }
+    }
+}
+
+/// Write the initial byte and argument of an item, returning its length
+fn encode_head(major: u8, value: u64, out: &mut [u8; 9]) -> usize {
+    let major = major << 5;
+    match value {
+        0..=23 => {
+            out[0] = major | value as u8;
+            1
+        }
+        24..=0xFF => {
+            out[0] = major | 24;
+            out[1] = value as u8;
+            2
+        }
+        0x100..=0xFFFF => {
+            out[0] = major | 25;
+            out[1..3].copy_from_slice(&(value as u16).to_be_bytes());
+            3
+        }
+        0x1_0000..=0xFFFF_FFFF => {
+            out[0] = major | 26;
+            out[1..5].copy_from_slice(&(value as u32).to_be_bytes());
+            5
+        }
+        _ => {
+            out[0] = major | 27;
+            out[1..9].copy_from_slice(&value.to_be_bytes());
+            9
+        }
+    }
+}
+
+/// Write an array head right-aligned into `out[..reserved]`, returning
+/// where it starts; used to prefix a count known only after encoding
+pub(crate) fn patch_array_head(out: &mut [u8], reserved: usize, len: usize) -> Result<usize, CborError> {
+    let mut head = [0u8; 9];
+    let head_len = encode_head(MAJOR_ARRAY, len as u64, &mut head);
+    let start = reserved.checked_sub(head_len).ok_or(CborError::BufferFull)?;
+    out[start..reserved].copy_from_slice(&head[..head_len]);
+    Ok(start)
+}
+
+/// One decoded data item; containers report their length and their
+/// contents follow as further items
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CborItem<'b> {
+    Unsigned(u64),
+    /// Negative integer, already converted from CBOR's -1 - n form
+    Negative(i64),
+    Bytes(&'b [u8]),
+    Text(&'b str),
+    Array(usize),
+    Map(usize),
+    Tag(u64),
+    Bool(bool),
+    Null,
+    Undefined,
+    Float(f64),
+}
+
+/// Pull parser over an encoded buffer
+pub struct CborDecoder<'b> {
+    data: &'b [u8],
+    pos: usize,
+}
+
+impl<'b> CborDecoder<'b> {
+    pub fn new(data: &'b [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'b [u8], CborError> {
+        let end = self.pos.checked_add(len).ok_or(CborError::UnexpectedEnd)?;
+        let bytes = self.data.get(self.pos..end).ok_or(CborError::UnexpectedEnd)?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn argument(&mut self, info: u8) -> Result<u64, CborError> {
+        Ok(match info {
+            0..=23 => info as u64,
+            24 => self.take(1)?[0] as u64,
+            25 => u16::from_be_bytes(self.take(2)?.try_into().map_err(|_| CborError::UnexpectedEnd)?) as u64,
+            26 => u32::from_be_bytes(self.take(4)?.try_into().map_err(|_| CborError::UnexpectedEnd)?) as u64,
+            27 => u64::from_be_bytes(self.take(8)?.try_into().map_err(|_| CborError::UnexpectedEnd)?),
+            _ => return Err(CborError::Unsupported),
+        })
+    }
+
+    /// Decode the next item
+    pub fn next(&mut self) -> Result<CborItem<'b>, CborError> {
+        let initial = self.take(1)?[0];
+        let major = initial >> 5;
+        let info = initial & 0x1F;
+        if info == INFO_INDEFINITE {
+            return Err(CborError::Unsupported);
+        }
+        if major == MAJOR_SIMPLE {
+            return self.simple(info);
+        }
+
+        let argument = self.argument(info)?;
+        Ok(match major {
+            MAJOR_UNSIGNED => CborItem::Unsigned(argument),
+            MAJOR_NEGATIVE => {
+                let value = i64::try_from(argument).map_err(|_| CborError::Unsupported)?;
+                CborItem::Negative(-1 - value)
+            }
+            MAJOR_BYTES => CborItem::Bytes(self.take(to_len(argument)?)?),
+            MAJOR_TEXT => {
+                let bytes = self.take(to_len(argument)?)?;
+                CborItem::Text(core::str::from_utf8(bytes).map_err(|_| CborError::InvalidUtf8)?)
+            }
+            MAJOR_ARRAY => CborItem::Array(to_len(argument)?),
+            MAJOR_MAP => CborItem::Map(to_len(argument)?),
+            _ => CborItem::Tag(argument),
+        })
+    }
+
+    fn simple(&mut self, info: u8) -> Result<CborItem<'b>, CborError> {
+        Ok(match info {
+            SIMPLE_FALSE => CborItem::Bool(false),
+            SIMPLE_TRUE => CborItem::Bool(true),
+            SIMPLE_NULL => CborItem::Null,
+            SIMPLE_UNDEFINED => CborItem::Undefined,
+            INFO_FLOAT16 => {
+                let bits = u16::from_be_bytes(self.take(2)?.try_into().map_err(|_| CborError::UnexpectedEnd)?);
+                CborItem::Float(half_to_f64(bits))
+            }
+            INFO_FLOAT32 => {
+                let bits = u32::from_be_bytes(self.take(4)?.try_into().map_err(|_| CborError::UnexpectedEnd)?);
+                CborItem::Float(f32::from_bits(bits) as f64)
+            }
+            INFO_FLOAT64 => {
+                let bits = u64::from_be_bytes(self.take(8)?.try_into().map_err(|_| CborError::UnexpectedEnd)?);
+                CborItem::Float(f64::from_bits(bits))
+            }
+            _ => return Err(CborError::Unsupported),
+        })
+    }
+
+    /// Skip the next item including everything nested in it
+    pub fn skip(&mut self) -> Result<(), CborError> {
+        let mut pending = [0usize; MAX_SKIP_DEPTH];
+        let mut depth = 0;
+        loop {
+            let children = match self.next()? {
+                CborItem::Array(len) => len,
+                CborItem::Map(len) => len.checked_mul(2).ok_or(CborError::Unsupported)?,
+                CborItem::Tag(_) => 1,
+                _ => 0,
+            };
+            if children > 0 {
+                if depth == MAX_SKIP_DEPTH {
+                    return Err(CborError::Unsupported);
+                }
+                pending[depth] = children;
+                depth += 1;
+                continue;
+            }
+            // A finished item completes one child of each enclosing level
+            // whose last child it was
+            loop {
+                if depth == 0 {
+                    return Ok(());
+                }
+                pending[depth - 1] -= 1;
+                if pending[depth - 1] > 0 {
+                    break;
+                }
+                depth -= 1;
+            }
+        }
+    }
+
+    pub fn read_uint(&mut self) -> Result<u64, CborError> {
+        match self.next()? {
+            CborItem::Unsigned(value) => Ok(value),
+            _ => Err(CborError::UnexpectedType),
+        }
+    }
+
+    pub fn read_int(&mut self) -> Result<i64, CborError> {
+        match self.next()? {
+            CborItem::Unsigned(value) => i64::try_from(value).map_err(|_| CborError::Unsupported),
+            CborItem::Negative(value) => Ok(value),
+            _ => Err(CborError::UnexpectedType),
+        }
+    }
+
+    /// Read a float, accepting integers as well
+    pub fn read_number(&mut self) -> Result<f64, CborError> {
+        match self.next()? {
+            CborItem::Unsigned(value) => Ok(value as f64),
+            CborItem::Negative(value) => Ok(value as f64),
+            CborItem::Float(value) => Ok(value),
+            _ => Err(CborError::UnexpectedType),
+        }
+    }
+
+    pub fn read_text(&mut self) -> Result<&'b str, CborError> {
+        match self.next()? {
+            CborItem::Text(text) => Ok(text),
+            _ => Err(CborError::UnexpectedType),
+        }
+    }
+
+    pub fn read_bytes(&mut self) -> Result<&'b [u8], CborError> {
+        match self.next()? {
+            CborItem::Bytes(bytes) => Ok(bytes),
+            _ => Err(CborError::UnexpectedType),
+        }
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, CborError> {
+        match self.next()? {
+            CborItem::Bool(value) => Ok(value),
+            _ => Err(CborError::UnexpectedType),
+        }
+    }
+
+    pub fn read_array(&mut self) -> Result<usize, CborError> {
+        match self.next()? {
+            CborItem::Array(len) => Ok(len),
+            _ => Err(CborError::UnexpectedType),
+        }
+    }
+
+    pub fn read_map(&mut self) -> Result<usize, CborError> {
+        match self.next()? {
+            CborItem::Map(len) => Ok(len),
+            _ => Err(CborError::UnexpectedType),
+        }
+    }
+}
+
+fn to_len(argument: u64) -> Result<usize, CborError> {
+    usize::try_from(argument).map_err(|_| CborError::UnexpectedEnd)
+}
+
+/// Widen an IEEE 754 half-precision value
+fn half_to_f64(bits: u16) -> f64 {
+    let exponent = (bits >> 10) & 0x1F;
+    let mantissa = (bits & 0x3FF) as f64;
+    let magnitude = match exponent {
+        0 => mantissa * f64::from_bits(0x3E70_0000_0000_0000), // 2^-24
+        31 if mantissa == 0.0 => f64::INFINITY,
+        31 => f64::NAN,
+        _ => (1.0 + mantissa / 1024.0) * f64::from_bits((exponent as u64 + 1008) << 52),
+    };
+    if bits & 0x8000 != 0 { -magnitude } else { magnitude }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn first_item(data: &[u8]) -> Result<CborItem<'_>, CborError> {
+        CborDecoder::new(data).next()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut buf = [0u8; 64];
+        let mut encoder = CborEncoder::new(&mut buf);
+        encoder.map(2).unwrap().text("t").unwrap().int(-500).unwrap();
+        encoder.text("v").unwrap().array(2).unwrap().number(21.5).unwrap().bytes(&[1, 2]).unwrap();
+
+        let mut decoder = CborDecoder::new(encoder.as_bytes());
+        assert_eq!(decoder.read_map(), Ok(2));
+        assert_eq!(decoder.read_text(), Ok("t"));
+        assert_eq!(decoder.read_int(), Ok(-500));
+        assert_eq!(decoder.read_text(), Ok("v"));
+        assert_eq!(decoder.read_array(), Ok(2));
+        assert_eq!(decoder.read_number(), Ok(21.5));
+        assert_eq!(decoder.read_bytes(), Ok(&[1u8, 2][..]));
+        assert!(decoder.is_empty());
+    }
+
+    #[test]
+    fn test_overlong_lengths() {
+        // Byte and text strings longer than the input
+        assert_eq!(first_item(&[0x5A, 0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x00]), Err(CborError::UnexpectedEnd));
+        assert_eq!(first_item(&[0x5B, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]), Err(CborError::UnexpectedEnd));
+        assert_eq!(first_item(&[0x78, 0x10, b'a']), Err(CborError::UnexpectedEnd));
+        // Heads whose argument bytes are missing
+        assert_eq!(first_item(&[0x19, 0x01]), Err(CborError::UnexpectedEnd));
+        assert_eq!(first_item(&[0x1B, 0x00, 0x00, 0x00]), Err(CborError::UnexpectedEnd));
+        assert_eq!(first_item(&[0xFA, 0x3F]), Err(CborError::UnexpectedEnd));
+        assert_eq!(first_item(&[]), Err(CborError::UnexpectedEnd));
+
+        // Containers announcing more items than follow
+        assert_eq!(CborDecoder::new(&[0x83, 0x01]).skip(), Err(CborError::UnexpectedEnd));
+        assert_eq!(CborDecoder::new(&[0xA2, 0x01, 0x02]).skip(), Err(CborError::UnexpectedEnd));
+        let huge_map = [0xBB, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+        assert!(CborDecoder::new(&huge_map).skip().is_err());
+    }
+
+    #[test]
+    fn test_indefinite_lengths_are_rejected() {
+        let indefinite: [&[u8]; 5] = [
+            &[0x5F, 0x41, b'a', 0xFF],
+            &[0x7F, 0x61, b'a', 0xFF],
+            &[0x9F, 0x01, 0xFF],
+            &[0xBF, 0xFF],
+            // Lone break code
+            &[0xFF],
+        ];
+        for data in indefinite {
+            assert_eq!(first_item(data), Err(CborError::Unsupported));
+            assert_eq!(CborDecoder::new(data).skip(), Err(CborError::Unsupported));
+        }
+        // Indefinite item nested in a definite array
+        assert_eq!(CborDecoder::new(&[0x82, 0x01, 0x9F, 0xFF]).skip(), Err(CborError::Unsupported));
+    }
+
+    #[test]
+    fn test_malformed_items() {
+        // Reserved additional information values
+        assert_eq!(first_item(&[0x1C]), Err(CborError::Unsupported));
+        assert_eq!(first_item(&[0xF8, 0x20]), Err(CborError::Unsupported));
+        // Integers outside i64
+        assert_eq!(first_item(&[0x3B, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]), Err(CborError::Unsupported));
+        let max_uint = [0x1B, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+        assert_eq!(CborDecoder::new(&max_uint).read_int(), Err(CborError::Unsupported));
+        assert_eq!(first_item(&[0x62, 0xC3, 0x28]), Err(CborError::InvalidUtf8));
+        assert_eq!(CborDecoder::new(&[0x01]).read_text(), Err(CborError::UnexpectedType));
+
+        // Nesting deeper than the skip limit
+        let mut nested = [0x81u8; MAX_SKIP_DEPTH + 2];
+        nested[MAX_SKIP_DEPTH + 1] = 0x00;
+        assert_eq!(CborDecoder::new(&nested).skip(), Err(CborError::Unsupported));
+        assert_eq!(CborDecoder::new(&nested[1..]).skip(), Ok(()));
+    }
+}
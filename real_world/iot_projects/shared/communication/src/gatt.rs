@@ -0,0 +1,960 @@
+//! BLE GATT server
+//!
+//! `GattServer` keeps an attribute table of services and characteristics
+//! and answers ATT requests from connected centrals. Applications register
+//! characteristics with an optional `GattCharacteristicHandler` for read,
+//! write and subscription callbacks, and push updates with `notify`.
+//!
+//! The link layer and security manager live in the controller behind
+//! `BleController`; the server reacts to its events to negotiate
+//! connection parameters, hand out long-term keys for bonded peers and
+//! persist bonds (including subscriptions) through a `BleBondStore`.
+
+use heapless::Vec;
+use crate::BleError;
+
+/// Largest ATT MTU negotiated by the server
+pub const ATT_MAX_MTU: usize = 185;
+pub const ATT_DEFAULT_MTU: usize = 23;
+pub const GATT_MAX_ATTRIBUTES: usize = 48;
+pub const GATT_MAX_CHARACTERISTICS: usize = 16;
+/// Largest stored characteristic value
+pub const GATT_MAX_VALUE: usize = 64;
+pub const GATT_MAX_CONNECTIONS: usize = 2;
+
+/// Characteristic properties
+pub const GATT_PROP_READ: u8 = 0x02;
+pub const GATT_PROP_WRITE_WITHOUT_RESPONSE: u8 = 0x04;
+pub const GATT_PROP_WRITE: u8 = 0x08;
+pub const GATT_PROP_NOTIFY: u8 = 0x10;
+pub const GATT_PROP_INDICATE: u8 = 0x20;
+
+/// Assigned numbers for the standard services used by sensor projects
+pub const GATT_UUID_GAP_SERVICE: u16 = 0x1800;
+pub const GATT_UUID_DEVICE_INFORMATION: u16 = 0x180A;
+pub const GATT_UUID_BATTERY_SERVICE: u16 = 0x180F;
+pub const GATT_UUID_ENVIRONMENTAL_SENSING: u16 = 0x181A;
+pub const GATT_UUID_DEVICE_NAME: u16 = 0x2A00;
+pub const GATT_UUID_APPEARANCE: u16 = 0x2A01;
+pub const GATT_UUID_BATTERY_LEVEL: u16 = 0x2A19;
+pub const GATT_UUID_MANUFACTURER_NAME: u16 = 0x2A29;
+pub const GATT_UUID_FIRMWARE_REVISION: u16 = 0x2A26;
+pub const GATT_UUID_TEMPERATURE: u16 = 0x2A6E;
+pub const GATT_UUID_HUMIDITY: u16 = 0x2A6F;
+
+const GATT_UUID_PRIMARY_SERVICE: u16 = 0x2800;
+const GATT_UUID_CHARACTERISTIC: u16 = 0x2803;
+const GATT_UUID_CCCD: u16 = 0x2902;
+
+const CCCD_NOTIFY: u16 = 0x0001;
+const CCCD_INDICATE: u16 = 0x0002;
+
+const ATT_ERROR_RSP: u8 = 0x01;
+const ATT_EXCHANGE_MTU_REQ: u8 = 0x02;
+const ATT_EXCHANGE_MTU_RSP: u8 = 0x03;
+const ATT_FIND_INFORMATION_REQ: u8 = 0x04;
+const ATT_FIND_INFORMATION_RSP: u8 = 0x05;
+const ATT_FIND_BY_TYPE_VALUE_REQ: u8 = 0x06;
+const ATT_FIND_BY_TYPE_VALUE_RSP: u8 = 0x07;
+const ATT_READ_BY_TYPE_REQ: u8 = 0x08;
+const ATT_READ_BY_TYPE_RSP: u8 = 0x09;
+const ATT_READ_REQ: u8 = 0x0A;
+const ATT_READ_RSP: u8 = 0x0B;
+const ATT_READ_BLOB_REQ: u8 = 0x0C;
+const ATT_READ_BLOB_RSP: u8 = 0x0D;
+const ATT_READ_BY_GROUP_TYPE_REQ: u8 = 0x10;
+const ATT_READ_BY_GROUP_TYPE_RSP: u8 = 0x11;
+const ATT_WRITE_REQ: u8 = 0x12;
+const ATT_WRITE_RSP: u8 = 0x13;
+const ATT_HANDLE_VALUE_NTF: u8 = 0x1B;
+const ATT_HANDLE_VALUE_IND: u8 = 0x1D;
+const ATT_HANDLE_VALUE_CFM: u8 = 0x1E;
+const ATT_WRITE_CMD: u8 = 0x52;
+/// Opcodes with this bit set never get a response
+const ATT_COMMAND_FLAG: u8 = 0x40;
+
+/// Bluetooth base UUID in little-endian wire order, 16-bit value at bytes 12-13
+const BLE_BASE_UUID: [u8; 16] = [
+    0xFB, 0x34, 0x9B, 0x5F, 0x80, 0x00, 0x00, 0x80, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+/// Attribute type or characteristic UUID
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BleUuid {
+    Uuid16(u16),
+    /// 128-bit UUID in little-endian wire order
+    Uuid128([u8; 16]),
+}
+
+impl BleUuid {
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        match bytes.len() {
+            2 => Some(BleUuid::Uuid16(u16::from_le_bytes([bytes[0], bytes[1]]))),
+            16 => {
+                let mut uuid = [0u8; 16];
+                uuid.copy_from_slice(bytes);
+                Some(BleUuid::Uuid128(uuid))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn encoded_len(&self) -> usize {
+        match self {
+            BleUuid::Uuid16(_) => 2,
+            BleUuid::Uuid128(_) => 16,
+        }
+    }
+
+    fn write_to<const N: usize>(&self, out: &mut Vec<u8, N>) -> bool {
+        match self {
+            BleUuid::Uuid16(uuid) => out.extend_from_slice(&uuid.to_le_bytes()).is_ok(),
+            BleUuid::Uuid128(uuid) => out.extend_from_slice(uuid).is_ok(),
+        }
+    }
+
+    fn to_uuid128(self) -> [u8; 16] {
+        match self {
+            BleUuid::Uuid16(uuid) => {
+                let mut full = BLE_BASE_UUID;
+                full[12..14].copy_from_slice(&uuid.to_le_bytes());
+                full
+            }
+            BleUuid::Uuid128(uuid) => uuid,
+        }
+    }
+
+    /// Compare across 16-bit and 128-bit forms
+    pub fn matches(&self, other: &BleUuid) -> bool {
+        self.to_uuid128() == other.to_uuid128()
+    }
+}
+
+/// Security required to access a characteristic
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum GattSecurity {
+    Open,
+    /// Link must be encrypted
+    Encrypted,
+    /// Link must be encrypted with keys from authenticated (MITM) pairing
+    Authenticated,
+}
+
+/// ATT error codes returned to the client
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AttError {
+    InvalidHandle,
+    ReadNotPermitted,
+    WriteNotPermitted,
+    InvalidPdu,
+    InsufficientAuthentication,
+    RequestNotSupported,
+    InvalidOffset,
+    AttributeNotFound,
+    InvalidAttributeValueLength,
+    UnlikelyError,
+    InsufficientEncryption,
+    UnsupportedGroupType,
+    /// Application-defined error, 0x80-0x9F
+    Application(u8),
+}
+
+impl AttError {
+    pub fn code(&self) -> u8 {
+        match self {
+            AttError::InvalidHandle => 0x01,
+            AttError::ReadNotPermitted => 0x02,
+            AttError::WriteNotPermitted => 0x03,
+            AttError::InvalidPdu => 0x04,
+            AttError::InsufficientAuthentication => 0x05,
+            AttError::RequestNotSupported => 0x06,
+            AttError::InvalidOffset => 0x07,
+            AttError::AttributeNotFound => 0x0A,
+            AttError::InvalidAttributeValueLength => 0x0D,
+            AttError::UnlikelyError => 0x0E,
+            AttError::InsufficientEncryption => 0x0F,
+            AttError::UnsupportedGroupType => 0x10,
+            AttError::Application(code) => 0x80 | (code & 0x1F),
+        }
+    }
+}
+
+/// Application callbacks for one characteristic
+///
+/// Handlers take `&self` so one sensor object can serve several
+/// characteristics; keep mutable state in cells or atomics.
+pub trait GattCharacteristicHandler {
+    /// Refresh `value` before it is returned to a client
+    fn on_read(&self, _conn: u16, _value: &mut Vec<u8, GATT_MAX_VALUE>) -> Result<(), AttError> {
+        Ok(())
+    }
+
+    /// Validate and apply a client write; the stored value is only
+    /// replaced when this returns `Ok`
+    fn on_write(&self, _conn: u16, _data: &[u8]) -> Result<(), AttError> {
+        Ok(())
+    }
+
+    fn on_subscribe(&self, _conn: u16, _notify: bool, _indicate: bool) {}
+}
+
+/// Identifies a registered characteristic
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GattCharacteristic(usize);
+
+/// Identifies a registered service
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GattService(u16);
+
+/// Bluetooth device address
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BleAddress {
+    pub bytes: [u8; 6],
+    pub random: bool,
+}
+
+/// LE connection parameters in controller units
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BleConnectionParams {
+    /// Connection interval bounds in 1.25 ms units
+    pub interval_min: u16,
+    pub interval_max: u16,
+    pub latency: u16,
+    /// Supervision timeout in 10 ms units
+    pub supervision_timeout: u16,
+}
+
+impl BleConnectionParams {
+    /// Check the ranges and the timeout constraint from the Core spec
+    pub fn is_valid(&self) -> bool {
+        let interval_ok = (6..=3200).contains(&self.interval_min)
+            && (self.interval_min..=3200).contains(&self.interval_max);
+        let timeout_ok = (10..=3200).contains(&self.supervision_timeout);
+        // Timeout must exceed (1 + latency) * interval_max * 2
+        let min_timeout_ms = (1 + self.latency as u32) * self.interval_max as u32 * 125 * 2 / 100;
+        interval_ok && timeout_ok && self.latency <= 499 && self.supervision_timeout as u32 * 10 > min_timeout_ms
+    }
+
+    /// Whether parameters in effect on a link satisfy this preference
+    pub fn accepts(&self, current: &BleConnectionParams) -> bool {
+        (self.interval_min..=self.interval_max).contains(&current.interval_min)
+            && current.latency <= self.latency
+            && current.supervision_timeout <= self.supervision_timeout
+    }
+}
+
+/// Keys distributed by the security manager during bonding
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BleBondKeys {
+    pub ltk: [u8; 16],
+    pub ediv: u16,
+    pub rand: u64,
+    /// Peer identity resolving key, if distributed
+    pub irk: Option<[u8; 16]>,
+    pub authenticated: bool,
+}
+
+/// Persisted bond with a peer
+#[derive(Debug, Clone)]
+pub struct BleBond {
+    pub peer: BleAddress,
+    pub keys: BleBondKeys,
+    /// Client configuration of each CCCD handle, restored on reconnection
+    pub cccd: Vec<(u16, u16), GATT_MAX_CHARACTERISTICS>,
+}
+
+/// Bond persistence, typically backed by flash
+pub trait BleBondStore {
+    fn load(&self, peer: &BleAddress) -> Option<BleBond>;
+    fn store(&mut self, bond: &BleBond) -> Result<(), BleError>;
+    fn remove(&mut self, peer: &BleAddress);
+}
+
+/// RAM bond table that evicts the oldest bond when full
+pub struct BleBondTable<const N: usize> {
+    bonds: Vec<BleBond, N>,
+}
+
+impl<const N: usize> BleBondTable<N> {
+    pub const fn new() -> Self {
+        Self { bonds: Vec::new() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.bonds.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bonds.is_empty()
+    }
+}
+
+impl<const N: usize> BleBondStore for BleBondTable<N> {
+    fn load(&self, peer: &BleAddress) -> Option<BleBond> {
+        self.bonds.iter().find(|b| b.peer == *peer).cloned()
+    }
+
+    fn store(&mut self, bond: &BleBond) -> Result<(), BleError> {
+        self.remove(&bond.peer);
+        if self.bonds.is_full() {
+            if N == 0 {
+                return Err(BleError::TableFull);
+            }
+            self.bonds.remove(0);
+        }
+        self.bonds.push(bond.clone()).map_err(|_| BleError::TableFull)
+    }
+
+    fn remove(&mut self, peer: &BleAddress) {
+        if let Some(pos) = self.bonds.iter().position(|b| b.peer == *peer) {
+            self.bonds.remove(pos);
+        }
+    }
+}
+
+/// Events reported by the BLE controller
+#[derive(Debug, Clone)]
+pub enum BleEvent {
+    Connected { conn: u16, peer: BleAddress, params: BleConnectionParams },
+    Disconnected { conn: u16, reason: u8 },
+    ConnectionParamsUpdated { conn: u16, params: BleConnectionParams },
+    /// Central started encryption and needs the LTK for `ediv`/`rand`
+    LtkRequest { conn: u16, ediv: u16, rand: u64 },
+    EncryptionChanged { conn: u16, encrypted: bool, authenticated: bool },
+    /// Pairing finished; `keys` is set when the peer asked to bond
+    PairingComplete { conn: u16, keys: Option<BleBondKeys> },
+    AttReceived { conn: u16, pdu: Vec<u8, ATT_MAX_MTU> },
+}
+
+/// Link layer and security manager operations used by the GATT server
+pub trait BleController {
+    fn poll_event(&mut self) -> Result<Option<BleEvent>, BleError>;
+    fn send_att(&mut self, conn: u16, pdu: &[u8]) -> Result<(), BleError>;
+    fn update_connection_params(&mut self, conn: u16, params: &BleConnectionParams) -> Result<(), BleError>;
+    /// Answer an LTK request; `None` rejects it and the central re-pairs
+    fn reply_ltk(&mut self, conn: u16, ltk: Option<&[u8; 16]>) -> Result<(), BleError>;
+    fn disconnect(&mut self, conn: u16, reason: u8) -> Result<(), BleError>;
+}
+
+/// One attribute of the table; its handle is its index plus one
+#[derive(Debug, Clone, Copy)]
+enum GattAttribute {
+    PrimaryService { uuid: BleUuid, end_handle: u16 },
+    Declaration { characteristic: usize },
+    Value { characteristic: usize },
+    Cccd { characteristic: usize },
+}
+
+struct CharacteristicEntry<'a> {
+    uuid: BleUuid,
+    properties: u8,
+    security: GattSecurity,
+    value_handle: u16,
+    cccd_handle: Option<u16>,
+    value: Vec<u8, GATT_MAX_VALUE>,
+    handler: Option<&'a dyn GattCharacteristicHandler>,
+}
+
+/// Per-connection server state
+#[derive(Debug, Clone)]
+pub struct BleConnection {
+    pub handle: u16,
+    pub peer: BleAddress,
+    pub params: BleConnectionParams,
+    pub mtu: usize,
+    pub encrypted: bool,
+    pub authenticated: bool,
+    pub bonded: bool,
+    /// CCCD value of each characteristic, indexed like the characteristic table
+    cccd: [u16; GATT_MAX_CHARACTERISTICS],
+    indication_pending: bool,
+}
+
+impl BleConnection {
+    fn satisfies(&self, security: GattSecurity) -> Result<(), AttError> {
+        match security {
+            GattSecurity::Open => Ok(()),
+            GattSecurity::Encrypted if self.encrypted => Ok(()),
+            GattSecurity::Authenticated if self.encrypted && self.authenticated => Ok(()),
+            GattSecurity::Authenticated if self.encrypted => Err(AttError::InsufficientAuthentication),
+            // A bonded peer only needs to re-encrypt; others must pair first
+            _ if self.bonded => Err(AttError::InsufficientEncryption),
+            _ => Err(AttError::InsufficientAuthentication),
+        }
+    }
+}
+
+type AttPdu = Vec<u8, ATT_MAX_MTU>;
+
+/// GATT server over a BLE controller
+pub struct GattServer<'a, C: BleController, B: BleBondStore> {
+    controller: C,
+    bonds: B,
+    attributes: Vec<GattAttribute, GATT_MAX_ATTRIBUTES>,
+    characteristics: Vec<CharacteristicEntry<'a>, GATT_MAX_CHARACTERISTICS>,
+    connections: Vec<BleConnection, GATT_MAX_CONNECTIONS>,
+    preferred_params: Option<BleConnectionParams>,
+}
+
+impl<'a, C: BleController, B: BleBondStore> GattServer<'a, C, B> {
+    /// Create a server exposing the mandatory GAP service
+    pub fn new(controller: C, bonds: B, device_name: &str, appearance: u16) -> Self {
+        let mut server = Self {
+            controller,
+            bonds,
+            attributes: Vec::new(),
+            characteristics: Vec::new(),
+            connections: Vec::new(),
+            preferred_params: None,
+        };
+        let _ = server.add_service(BleUuid::Uuid16(GATT_UUID_GAP_SERVICE));
+        if let Ok(ch) = server.add_characteristic(BleUuid::Uuid16(GATT_UUID_DEVICE_NAME), GATT_PROP_READ, GattSecurity::Open, None) {
+            let name = device_name.as_bytes();
+            let _ = server.set_value(ch, &name[..name.len().min(GATT_MAX_VALUE)]);
+        }
+        if let Ok(ch) = server.add_characteristic(BleUuid::Uuid16(GATT_UUID_APPEARANCE), GATT_PROP_READ, GattSecurity::Open, None) {
+            let _ = server.set_value(ch, &appearance.to_le_bytes());
+        }
+        server
+    }
+
+    pub fn controller(&mut self) -> &mut C {
+        &mut self.controller
+    }
+
+    pub fn bonds(&mut self) -> &mut B {
+        &mut self.bonds
+    }
+
+    pub fn connections(&self) -> &[BleConnection] {
+        &self.connections
+    }
+
+    /// Start a primary service; characteristics added next belong to it
+    pub fn add_service(&mut self, uuid: BleUuid) -> Result<GattService, BleError> {
+        let handle = self.next_handle();
+        self.attributes
+            .push(GattAttribute::PrimaryService { uuid, end_handle: handle })
+            .map_err(|_| BleError::TableFull)?;
+        Ok(GattService(handle))
+    }
+
+    /// Add a characteristic to the most recently added service
+    ///
+    /// A Client Characteristic Configuration descriptor is created for
+    /// characteristics that notify or indicate.
+    pub fn add_characteristic(
+        &mut self,
+        uuid: BleUuid,
+        properties: u8,
+        security: GattSecurity,
+        handler: Option<&'a dyn GattCharacteristicHandler>,
+    ) -> Result<GattCharacteristic, BleError> {
+        let service = self
+            .attributes
+            .iter()
+            .rposition(|a| matches!(a, GattAttribute::PrimaryService { .. }))
+            .ok_or(BleError::GATTError)?;
+        let subscribable = properties & (GATT_PROP_NOTIFY | GATT_PROP_INDICATE) != 0;
+        let needed = if subscribable { 3 } else { 2 };
+        if self.characteristics.is_full() || self.attributes.len() + needed > GATT_MAX_ATTRIBUTES {
+            return Err(BleError::TableFull);
+        }
+
+        let index = self.characteristics.len();
+        let value_handle = self.next_handle() + 1;
+        let cccd_handle = subscribable.then_some(value_handle + 1);
+        let _ = self.characteristics.push(CharacteristicEntry {
+            uuid,
+            properties,
+            security,
+            value_handle,
+            cccd_handle,
+            value: Vec::new(),
+            handler,
+        });
+        let _ = self.attributes.push(GattAttribute::Declaration { characteristic: index });
+        let _ = self.attributes.push(GattAttribute::Value { characteristic: index });
+        if subscribable {
+            let _ = self.attributes.push(GattAttribute::Cccd { characteristic: index });
+        }
+        let end = self.attributes.len() as u16;
+        if let GattAttribute::PrimaryService { end_handle, .. } = &mut self.attributes[service] {
+            *end_handle = end;
+        }
+        Ok(GattCharacteristic(index))
+    }
+
+    pub fn value_handle(&self, characteristic: GattCharacteristic) -> Option<u16> {
+        self.characteristics.get(characteristic.0).map(|c| c.value_handle)
+    }
+
+    pub fn value(&self, characteristic: GattCharacteristic) -> Option<&[u8]> {
+        self.characteristics.get(characteristic.0).map(|c| &c.value[..])
+    }
+
+    /// Replace the stored value without notifying subscribers
+    pub fn set_value(&mut self, characteristic: GattCharacteristic, data: &[u8]) -> Result<(), BleError> {
+        let entry = self.characteristics.get_mut(characteristic.0).ok_or(BleError::GATTError)?;
+        entry.value = Vec::from_slice(data).map_err(|_| BleError::InvalidData)?;
+        Ok(())
+    }
+
+    /// Update a value and push it to every subscribed connection
+    ///
+    /// Returns how many connections were sent the update. Connections with
+    /// an unconfirmed indication are skipped for indications.
+    pub fn notify(&mut self, characteristic: GattCharacteristic, data: &[u8]) -> Result<usize, BleError> {
+        self.set_value(characteristic, data)?;
+        let index = characteristic.0;
+        let value_handle = self.characteristics[index].value_handle;
+        let mut sent = 0;
+        for i in 0..self.connections.len() {
+            let conn = &self.connections[i];
+            let cccd = conn.cccd[index];
+            let opcode = if cccd & CCCD_INDICATE != 0 && !conn.indication_pending {
+                ATT_HANDLE_VALUE_IND
+            } else if cccd & CCCD_NOTIFY != 0 {
+                ATT_HANDLE_VALUE_NTF
+            } else {
+                continue;
+            };
+            let mut pdu: AttPdu = Vec::new();
+            let _ = pdu.push(opcode);
+            let _ = pdu.extend_from_slice(&value_handle.to_le_bytes());
+            let _ = pdu.extend_from_slice(&data[..data.len().min(conn.mtu - 3)]);
+            let handle = conn.handle;
+            self.controller.send_att(handle, &pdu)?;
+            if opcode == ATT_HANDLE_VALUE_IND {
+                self.connections[i].indication_pending = true;
+            }
+            sent += 1;
+        }
+        Ok(sent)
+    }
+
+    /// Parameters the server asks centrals to use
+    pub fn set_preferred_connection_params(&mut self, params: BleConnectionParams) -> Result<(), BleError> {
+        if !params.is_valid() {
+            return Err(BleError::InvalidData);
+        }
+        self.preferred_params = Some(params);
+        for i in 0..self.connections.len() {
+            if !params.accepts(&self.connections[i].params) {
+                let handle = self.connections[i].handle;
+                self.controller.update_connection_params(handle, &params)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Forget a bond and drop the link if the peer is connected
+    pub fn remove_bond(&mut self, peer: &BleAddress) -> Result<(), BleError> {
+        self.bonds.remove(peer);
+        if let Some(conn) = self.connections.iter().find(|c| c.peer == *peer) {
+            // Remote User Terminated Connection
+            self.controller.disconnect(conn.handle, 0x13)?;
+        }
+        Ok(())
+    }
+
+    /// Handle every pending controller event
+    pub fn process(&mut self) -> Result<(), BleError> {
+        while let Some(event) = self.controller.poll_event()? {
+            self.handle_event(event)?;
+        }
+        Ok(())
+    }
+
+    fn handle_event(&mut self, event: BleEvent) -> Result<(), BleError> {
+        match event {
+            BleEvent::Connected { conn, peer, params } => {
+                let bonded = self.bonds.load(&peer).is_some();
+                let connection = BleConnection {
+                    handle: conn,
+                    peer,
+                    params,
+                    mtu: ATT_DEFAULT_MTU,
+                    encrypted: false,
+                    authenticated: false,
+                    bonded,
+                    cccd: [0; GATT_MAX_CHARACTERISTICS],
+                    indication_pending: false,
+                };
+                if self.connections.push(connection).is_err() {
+                    // Connection Rejected due to Limited Resources
+                    return self.controller.disconnect(conn, 0x0D);
+                }
+                if let Some(preferred) = self.preferred_params {
+                    if !preferred.accepts(&params) {
+                        self.controller.update_connection_params(conn, &preferred)?;
+                    }
+                }
+            }
+            BleEvent::Disconnected { conn, .. } => {
+                if let Some(pos) = self.connections.iter().position(|c| c.handle == conn) {
+                    let connection = self.connections.remove(pos);
+                    self.save_subscriptions(&connection)?;
+                }
+            }
+            BleEvent::ConnectionParamsUpdated { conn, params } => {
+                if let Some(c) = self.connection_mut(conn) {
+                    c.params = params;
+                }
+            }
+            BleEvent::LtkRequest { conn, ediv, rand } => {
+                let bond = self
+                    .connection_mut(conn)
+                    .map(|c| c.peer)
+                    .and_then(|peer| self.bonds.load(&peer))
+                    .filter(|b| b.keys.ediv == ediv && b.keys.rand == rand);
+                self.controller.reply_ltk(conn, bond.as_ref().map(|b| &b.keys.ltk))?;
+            }
+            BleEvent::EncryptionChanged { conn, encrypted, authenticated } => {
+                let Some(index) = self.connections.iter().position(|c| c.handle == conn) else { return Ok(()) };
+                let c = &mut self.connections[index];
+                c.encrypted = encrypted;
+                c.authenticated = encrypted && authenticated;
+                if encrypted {
+                    let peer = c.peer;
+                    if let Some(bond) = self.bonds.load(&peer) {
+                        self.restore_subscriptions(index, &bond);
+                    }
+                }
+            }
+            BleEvent::PairingComplete { conn, keys } => {
+                let Some(c) = self.connection_mut(conn) else { return Ok(()) };
+                if let Some(keys) = keys {
+                    c.bonded = true;
+                    let bond = BleBond { peer: c.peer, keys, cccd: Vec::new() };
+                    self.bonds.store(&bond)?;
+                }
+            }
+            BleEvent::AttReceived { conn, pdu } => {
+                let Some(index) = self.connections.iter().position(|c| c.handle == conn) else { return Ok(()) };
+                if let Some(response) = self.handle_att(index, &pdu) {
+                    self.controller.send_att(conn, &response)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn connection_mut(&mut self, conn: u16) -> Option<&mut BleConnection> {
+        self.connections.iter_mut().find(|c| c.handle == conn)
+    }
+
+    /// Persist the CCCDs of a bonded peer so notifications resume after reconnecting
+    fn save_subscriptions(&mut self, connection: &BleConnection) -> Result<(), BleError> {
+        if !connection.bonded {
+            return Ok(());
+        }
+        let Some(mut bond) = self.bonds.load(&connection.peer) else { return Ok(()) };
+        bond.cccd.clear();
+        for (entry, &value) in self.characteristics.iter().zip(&connection.cccd) {
+            if let (Some(handle), true) = (entry.cccd_handle, value != 0) {
+                let _ = bond.cccd.push((handle, value));
+            }
+        }
+        self.bonds.store(&bond)
+    }
+
+    fn restore_subscriptions(&mut self, index: usize, bond: &BleBond) {
+        for &(handle, value) in &bond.cccd {
+            if let Some(ch) = self.characteristics.iter().position(|c| c.cccd_handle == Some(handle)) {
+                self.connections[index].cccd[ch] = value;
+            }
+        }
+    }
+
+    fn next_handle(&self) -> u16 {
+        self.attributes.len() as u16 + 1
+    }
+
+    fn attribute(&self, handle: u16) -> Option<GattAttribute> {
+        (handle as usize).checked_sub(1).and_then(|i| self.attributes.get(i)).copied()
+    }
+
+    fn attribute_type(&self, attribute: &GattAttribute) -> BleUuid {
+        match attribute {
+            GattAttribute::PrimaryService { .. } => BleUuid::Uuid16(GATT_UUID_PRIMARY_SERVICE),
+            GattAttribute::Declaration { .. } => BleUuid::Uuid16(GATT_UUID_CHARACTERISTIC),
+            GattAttribute::Value { characteristic } => self.characteristics[*characteristic].uuid,
+            GattAttribute::Cccd { .. } => BleUuid::Uuid16(GATT_UUID_CCCD),
+        }
+    }
+
+    /// Read an attribute value, checking permissions
+    fn read_attribute(&self, conn: usize, handle: u16) -> Result<Vec<u8, GATT_MAX_VALUE>, AttError> {
+        let mut out = Vec::new();
+        match self.attribute(handle).ok_or(AttError::InvalidHandle)? {
+            GattAttribute::PrimaryService { uuid, .. } => {
+                uuid.write_to(&mut out);
+            }
+            GattAttribute::Declaration { characteristic } => {
+                let entry = &self.characteristics[characteristic];
+                let _ = out.push(entry.properties);
+                let _ = out.extend_from_slice(&entry.value_handle.to_le_bytes());
+                entry.uuid.write_to(&mut out);
+            }
+            GattAttribute::Value { characteristic } => {
+                let entry = &self.characteristics[characteristic];
+                if entry.properties & GATT_PROP_READ == 0 {
+                    return Err(AttError::ReadNotPermitted);
+                }
+                let connection = &self.connections[conn];
+                connection.satisfies(entry.security)?;
+                out = entry.value.clone();
+                if let Some(handler) = entry.handler {
+                    handler.on_read(connection.handle, &mut out)?;
+                }
+            }
+            GattAttribute::Cccd { characteristic } => {
+                let _ = out.extend_from_slice(&self.connections[conn].cccd[characteristic].to_le_bytes());
+            }
+        }
+        Ok(out)
+    }
+
+    fn write_attribute(&mut self, conn: usize, handle: u16, data: &[u8], command: bool) -> Result<(), AttError> {
+        match self.attribute(handle).ok_or(AttError::InvalidHandle)? {
+            GattAttribute::Value { characteristic } => {
+                let entry = &self.characteristics[characteristic];
+                let allowed = if command { GATT_PROP_WRITE_WITHOUT_RESPONSE } else { GATT_PROP_WRITE };
+                if entry.properties & allowed == 0 {
+                    return Err(AttError::WriteNotPermitted);
+                }
+                let connection = &self.connections[conn];
+                connection.satisfies(entry.security)?;
+                if data.len() > GATT_MAX_VALUE {
+                    return Err(AttError::InvalidAttributeValueLength);
+                }
+                if let Some(handler) = entry.handler {
+                    handler.on_write(connection.handle, data)?;
+                }
+                self.characteristics[characteristic].value = Vec::from_slice(data).map_err(|_| AttError::UnlikelyError)?;
+                Ok(())
+            }
+            GattAttribute::Cccd { characteristic } => {
+                let entry = &self.characteristics[characteristic];
+                self.connections[conn].satisfies(entry.security)?;
+                if data.len() != 2 {
+                    return Err(AttError::InvalidAttributeValueLength);
+                }
+                let mut value = u16::from_le_bytes([data[0], data[1]]);
+                if entry.properties & GATT_PROP_NOTIFY == 0 {
+                    value &= !CCCD_NOTIFY;
+                }
+                if entry.properties & GATT_PROP_INDICATE == 0 {
+                    value &= !CCCD_INDICATE;
+                }
+                let connection = &mut self.connections[conn];
+                connection.cccd[characteristic] = value;
+                if let Some(handler) = entry.handler {
+                    handler.on_subscribe(connection.handle, value & CCCD_NOTIFY != 0, value & CCCD_INDICATE != 0);
+                }
+                Ok(())
+            }
+            _ => Err(AttError::WriteNotPermitted),
+        }
+    }
+
+    /// Process one ATT PDU, returning the response to send
+    fn handle_att(&mut self, conn: usize, pdu: &[u8]) -> Option<AttPdu> {
+        let (&opcode, params) = pdu.split_first()?;
+        let result = match opcode {
+            ATT_EXCHANGE_MTU_REQ if params.len() == 2 => {
+                let client_mtu = u16::from_le_bytes([params[0], params[1]]) as usize;
+                self.connections[conn].mtu = client_mtu.clamp(ATT_DEFAULT_MTU, ATT_MAX_MTU);
+                let mut rsp: AttPdu = Vec::new();
+                let _ = rsp.push(ATT_EXCHANGE_MTU_RSP);
+                let _ = rsp.extend_from_slice(&(ATT_MAX_MTU as u16).to_le_bytes());
+                Ok(rsp)
+            }
+            ATT_FIND_INFORMATION_REQ if params.len() == 4 => self.find_information(conn, params),
+            ATT_FIND_BY_TYPE_VALUE_REQ if params.len() >= 6 => self.find_by_type_value(conn, params),
+            ATT_READ_BY_TYPE_REQ if params.len() == 6 || params.len() == 20 => self.read_by_type(conn, params),
+            ATT_READ_BY_GROUP_TYPE_REQ if params.len() == 6 || params.len() == 20 => self.read_by_group_type(conn, params),
+            ATT_READ_REQ if params.len() == 2 => {
+                let handle = u16::from_le_bytes([params[0], params[1]]);
+                self.read_response(conn, ATT_READ_RSP, handle, 0)
+            }
+            ATT_READ_BLOB_REQ if params.len() == 4 => {
+                let handle = u16::from_le_bytes([params[0], params[1]]);
+                let offset = u16::from_le_bytes([params[2], params[3]]) as usize;
+                self.read_response(conn, ATT_READ_BLOB_RSP, handle, offset)
+            }
+            ATT_WRITE_REQ | ATT_WRITE_CMD if params.len() >= 2 => {
+                let handle = u16::from_le_bytes([params[0], params[1]]);
+                let result = self.write_attribute(conn, handle, &params[2..], opcode == ATT_WRITE_CMD);
+                if opcode == ATT_WRITE_CMD {
+                    return None;
+                }
+                result.map_err(|e| (handle, e)).map(|_| {
+                    let mut rsp: AttPdu = Vec::new();
+                    let _ = rsp.push(ATT_WRITE_RSP);
+                    rsp
+                })
+            }
+            ATT_HANDLE_VALUE_CFM => {
+                self.connections[conn].indication_pending = false;
+                return None;
+            }
+            _ if opcode & ATT_COMMAND_FLAG != 0 => return None,
+            ATT_EXCHANGE_MTU_REQ | ATT_FIND_INFORMATION_REQ | ATT_FIND_BY_TYPE_VALUE_REQ | ATT_READ_BY_TYPE_REQ
+            | ATT_READ_BY_GROUP_TYPE_REQ | ATT_READ_REQ | ATT_READ_BLOB_REQ | ATT_WRITE_REQ => Err((0, AttError::InvalidPdu)),
+            _ => Err((0, AttError::RequestNotSupported)),
+        };
+        Some(match result {
+            Ok(rsp) => rsp,
+            Err((handle, error)) => {
+                let mut rsp: AttPdu = Vec::new();
+                let _ = rsp.extend_from_slice(&[ATT_ERROR_RSP, opcode]);
+                let _ = rsp.extend_from_slice(&handle.to_le_bytes());
+                let _ = rsp.push(error.code());
+                rsp
+            }
+        })
+    }
+
+    /// Parse a handle range, rejecting invalid ones per the ATT rules
+    fn handle_range(params: &[u8]) -> Result<(u16, u16), (u16, AttError)> {
+        let start = u16::from_le_bytes([params[0], params[1]]);
+        let end = u16::from_le_bytes([params[2], params[3]]);
+        if start == 0 || start > end {
+            return Err((start, AttError::InvalidHandle));
+        }
+        Ok((start, end))
+    }
+
+    fn handles_in(&self, start: u16, end: u16) -> impl Iterator<Item = u16> {
+        start..=end.min(self.attributes.len() as u16)
+    }
+
+    fn find_information(&self, conn: usize, params: &[u8]) -> Result<AttPdu, (u16, AttError)> {
+        let (start, end) = Self::handle_range(params)?;
+        let mtu = self.connections[conn].mtu;
+        let mut rsp: AttPdu = Vec::new();
+        let _ = rsp.extend_from_slice(&[ATT_FIND_INFORMATION_RSP, 0]);
+        let mut uuid_len = 0;
+        for handle in self.handles_in(start, end) {
+            let uuid = self.attribute_type(&self.attributes[handle as usize - 1]);
+            if uuid_len == 0 {
+                uuid_len = uuid.encoded_len();
+                rsp[1] = if uuid_len == 2 { 1 } else { 2 };
+            }
+            if uuid.encoded_len() != uuid_len || rsp.len() + 2 + uuid_len > mtu {
+                break;
+            }
+            let _ = rsp.extend_from_slice(&handle.to_le_bytes());
+            uuid.write_to(&mut rsp);
+        }
+        if uuid_len == 0 {
+            return Err((start, AttError::AttributeNotFound));
+        }
+        Ok(rsp)
+    }
+
+    fn find_by_type_value(&self, conn: usize, params: &[u8]) -> Result<AttPdu, (u16, AttError)> {
+        let (start, end) = Self::handle_range(params)?;
+        let kind = u16::from_le_bytes([params[4], params[5]]);
+        let value = &params[6..];
+        let mtu = self.connections[conn].mtu;
+        let mut rsp: AttPdu = Vec::new();
+        let _ = rsp.push(ATT_FIND_BY_TYPE_VALUE_RSP);
+        for handle in self.handles_in(start, end) {
+            // Only primary service discovery by UUID is meaningful here
+            if let GattAttribute::PrimaryService { uuid, end_handle } = self.attributes[handle as usize - 1] {
+                let matches = kind == GATT_UUID_PRIMARY_SERVICE
+                    && BleUuid::from_bytes(value).is_some_and(|wanted| wanted.matches(&uuid));
+                if matches {
+                    if rsp.len() + 4 > mtu {
+                        break;
+                    }
+                    let _ = rsp.extend_from_slice(&handle.to_le_bytes());
+                    let _ = rsp.extend_from_slice(&end_handle.to_le_bytes());
+                }
+            }
+        }
+        if rsp.len() == 1 {
+            return Err((start, AttError::AttributeNotFound));
+        }
+        Ok(rsp)
+    }
+
+    fn read_by_type(&self, conn: usize, params: &[u8]) -> Result<AttPdu, (u16, AttError)> {
+        let (start, end) = Self::handle_range(params)?;
+        let wanted = BleUuid::from_bytes(&params[4..]).ok_or((start, AttError::InvalidPdu))?;
+        let mtu = self.connections[conn].mtu;
+        let mut rsp: AttPdu = Vec::new();
+        let _ = rsp.extend_from_slice(&[ATT_READ_BY_TYPE_RSP, 0]);
+        let mut entry_len = 0;
+        for handle in self.handles_in(start, end) {
+            let attribute = self.attributes[handle as usize - 1];
+            if !self.attribute_type(&attribute).matches(&wanted) {
+                continue;
+            }
+            let value = match self.read_attribute(conn, handle) {
+                Ok(value) => value,
+                // An error on the first match is reported; later ones end the list
+                Err(error) if entry_len == 0 => return Err((handle, error)),
+                Err(_) => break,
+            };
+            let len = (2 + value.len()).min(mtu - 2).min(255);
+            if entry_len == 0 {
+                entry_len = len;
+                rsp[1] = len as u8;
+            } else if len != entry_len || rsp.len() + len > mtu {
+                break;
+            }
+            let _ = rsp.extend_from_slice(&handle.to_le_bytes());
+            let _ = rsp.extend_from_slice(&value[..len - 2]);
+        }
+        if entry_len == 0 {
+            return Err((start, AttError::AttributeNotFound));
+        }
+        Ok(rsp)
+    }
+
+    fn read_by_group_type(&self, conn: usize, params: &[u8]) -> Result<AttPdu, (u16, AttError)> {
+        let (start, end) = Self::handle_range(params)?;
+        let wanted = BleUuid::from_bytes(&params[4..]).ok_or((start, AttError::InvalidPdu))?;
+        if !wanted.matches(&BleUuid::Uuid16(GATT_UUID_PRIMARY_SERVICE)) {
+            return Err((start, AttError::UnsupportedGroupType));
+        }
+        let mtu = self.connections[conn].mtu;
+        let mut rsp: AttPdu = Vec::new();
+        let _ = rsp.extend_from_slice(&[ATT_READ_BY_GROUP_TYPE_RSP, 0]);
+        let mut entry_len = 0;
+        for handle in self.handles_in(start, end) {
+            let GattAttribute::PrimaryService { uuid, end_handle } = self.attributes[handle as usize - 1] else { continue };
+            let len = 4 + uuid.encoded_len();
+            if entry_len == 0 {
+                entry_len = len;
+                rsp[1] = len as u8;
+            } else if len != entry_len || rsp.len() + len > mtu {
+                break;
+            }
+            let _ = rsp.extend_from_slice(&handle.to_le_bytes());
+            let _ = rsp.extend_from_slice(&end_handle.to_le_bytes());
+            uuid.write_to(&mut rsp);
+        }
+        if entry_len == 0 {
+            return Err((start, AttError::AttributeNotFound));
+        }
+        Ok(rsp)
+    }
+
+    fn read_response(&self, conn: usize, opcode: u8, handle: u16, offset: usize) -> Result<AttPdu, (u16, AttError)> {
+        let value = self.read_attribute(conn, handle).map_err(|e| (handle, e))?;
+        if offset > value.len() {
+            return Err((handle, AttError::InvalidOffset));
+        }
+        let mtu = self.connections[conn].mtu;
+        let mut rsp: AttPdu = Vec::new();
+        let _ = rsp.push(opcode);
+        let end = value.len().min(offset + mtu - 1);
+        let _ = rsp.extend_from_slice(&value[offset..end]);
+        Ok(rsp)
+    }
+}
@@ -2,13 +2,55 @@
 //! Provides implementations for various IoT communication protocols
 //! optimized for RISC-V architectures
 
-use crate::riscv_hal::{Uart, I2CBus};
+use crate::riscv_hal::{BoardConfig, Uart, I2CBus};
 use heapless::{String, Vec};
 use core::fmt::Write;
 use core::sync::atomic::{AtomicU8, AtomicU32, Ordering};
 
+pub mod mqtt5;
+pub mod mqtt_session;
+pub mod tls;
+pub mod lorawan;
+pub mod gatt;
+pub mod outbox;
+pub mod ota;
+pub mod timesync;
+pub mod cbor;
+pub mod senml;
+
+pub use mqtt5::{
+    MqttProtocolVersion, MqttReasonCode, MqttProperty, MqttProperties, MqttServerLimits, MqttTopicAliases,
+    MQTT_DEFAULT_RECEIVE_MAXIMUM, MQTT_MAX_TOPIC_ALIASES,
+};
+use mqtt5::{encode_properties, encode_variable_int, put_binary, put_bytes, read_str, split_packet};
+pub use mqtt_session::{
+    MqttSessionStore, MqttMemoryStore, MqttSessionState, MqttInflightMessage, MqttInflightState, MqttClock,
+    MqttRetryPolicy, MQTT_MAX_INFLIGHT,
+};
+use mqtt_session::{ack_packet_id, encode_pubcomp, encode_puback, encode_pubrec, encode_pubrel};
+pub use tls::{
+    TlsTransport, TlsEngine, TlsConfig, TlsCredentials, TlsVersion, TlsSession, TlsSessionCache, TlsError,
+};
+pub use lorawan::{
+    LoRaWanDevice, LoRaWanSession, LoRaWanKeys, LoRaWanClass, LoRaWanDownlink, LoRaWanUplinkResult, LoRaWanError,
+    LoRaRadio, LoRaRegion, LoRaChannelPlan, LoRaAes, SoftwareAes, LoRaWanDeviceTime,
+};
+pub use cbor::{CborEncoder, CborDecoder, CborItem, CborError};
+pub use senml::{SenmlPack, SenmlReader, SenmlRecord, SenmlEntry, SenmlValue, SenmlUnit};
+pub use timesync::{TimeSync, TimeSample, TimeSyncError, WallClock, SyncedClock, SntpClient};
+pub use ota::{
+    OtaUpdater, OtaFlash, OtaVerifier, OtaSource, OtaManifest, OtaBootState, OtaSlot, OtaSlotState,
+    OtaSlotInfo, OtaDownload, OtaStatus, OtaBootOutcome, OtaError, MqttOtaSource, CoapOtaSource, Sha256,
+    OTA_CHUNK_SIZE, OTA_TRIAL_BOOTS,
+};
+pub use outbox::{TelemetryOutbox, OutboxMessage, OutboxStore, OUTBOX_CAPACITY, OUTBOX_MAX_MESSAGE};
+pub use gatt::{
+    GattServer, GattCharacteristic, GattCharacteristicHandler, GattSecurity, BleController, BleEvent, BleUuid,
+    BleAddress, BleConnectionParams, BleBond, BleBondStore, BleBondTable, AttError,
+};
+
 // MQTT Protocol Types
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum MqttMessageType {
     CONNECT = 1,
     CONNACK = 2,
@@ -67,10 +109,36 @@ impl MqttMessage {
 }
 
 /// MQTT Client for RISC-V
+///
+/// Speaks MQTT 3.1.1 unless built `with_protocol_version(V5)`, which adds
+/// properties, reason codes, topic aliases, session expiry and the
+/// server's receive maximum as a send quota for QoS 1 and 2 publishes.
+///
+/// QoS 1 and 2 publishes stay in an in-flight window until their
+/// acknowledgement flow completes; with a session store attached they
+/// survive reboots and are replayed when the session is resumed.
 pub struct MqttClient<'a> {
     transport: &'a dyn MqttTransport,
     client_id: String<32>,
     keep_alive: u16,
+    protocol_version: MqttProtocolVersion,
+    session_expiry_interval: u32,
+    receive_maximum: u16,
+    topic_alias_maximum: u16,
+    server_limits: MqttServerLimits,
+    /// Broker may hold a session for us from an earlier connection
+    session_held: bool,
+    session_present: bool,
+    outbound_aliases: MqttTopicAliases,
+    inbound_aliases: MqttTopicAliases,
+    /// QoS 1 and 2 publishes awaiting acknowledgement, inbound QoS 2 awaiting PUBREL
+    session: MqttSessionState,
+    store: Option<&'a mut dyn MqttSessionStore>,
+    clock: Option<&'a dyn MqttClock>,
+    retry: MqttRetryPolicy,
+    next_packet_id: u16,
+    received: Option<MqttMessage>,
+    disconnect_reason: Option<MqttReasonCode>,
 }
 
 impl<'a> MqttClient<'a> {
@@ -79,10 +147,109 @@ impl<'a> MqttClient<'a> {
             transport,
             client_id,
             keep_alive: 60,
+            protocol_version: MqttProtocolVersion::V311,
+            session_expiry_interval: 0,
+            receive_maximum: MQTT_DEFAULT_RECEIVE_MAXIMUM,
+            topic_alias_maximum: MQTT_MAX_TOPIC_ALIASES as u16,
+            server_limits: MqttServerLimits::default(),
+            session_held: false,
+            session_present: false,
+            outbound_aliases: MqttTopicAliases::new(),
+            inbound_aliases: MqttTopicAliases::new(),
+            session: MqttSessionState::new(),
+            store: None,
+            clock: None,
+            retry: MqttRetryPolicy::default(),
+            next_packet_id: 1,
+            received: None,
+            disconnect_reason: None,
         }
     }
 
+    /// Select the protocol level used by `connect()`
+    pub fn with_protocol_version(mut self, version: MqttProtocolVersion) -> Self {
+        self.protocol_version = version;
+        self
+    }
+
+    /// Ask the broker to keep the session for `seconds` after the
+    /// connection closes, so a later `connect()` resumes it (MQTT 5.0)
+    pub fn with_session_expiry(mut self, seconds: u32) -> Self {
+        self.session_expiry_interval = seconds;
+        self
+    }
+
+    /// Limit the QoS 1 and 2 publishes the broker sends before we
+    /// acknowledge them (MQTT 5.0)
+    pub fn with_receive_maximum(mut self, maximum: u16) -> Self {
+        self.receive_maximum = maximum.max(1);
+        self
+    }
+
+    /// Persist session state in `store` and restore what it holds
+    ///
+    /// The client then asks the broker to keep the session (Clean Session
+    /// zero on MQTT 3.1.1; MQTT 5.0 also needs `with_session_expiry`).
+    pub fn with_session_store(mut self, store: &'a mut dyn MqttSessionStore) -> Self {
+        store.load(&mut self.session);
+        if let Some(last) = self.session.outbound.iter().map(|m| m.packet_id).max() {
+            self.next_packet_id = last.checked_add(1).unwrap_or(1);
+        }
+        self.session_held = true;
+        self.store = Some(store);
+        self
+    }
+
+    /// Enable retransmission timers driven by `clock`
+    pub fn with_retry_policy(mut self, policy: MqttRetryPolicy, clock: &'a dyn MqttClock) -> Self {
+        self.retry = MqttRetryPolicy { max_attempts: policy.max_attempts.max(1), ..policy };
+        self.clock = Some(clock);
+        self
+    }
+
+    pub fn protocol_version(&self) -> MqttProtocolVersion {
+        self.protocol_version
+    }
+
+    /// Whether the last CONNACK resumed a stored session
+    pub fn session_present(&self) -> bool {
+        self.session_present
+    }
+
+    pub fn server_limits(&self) -> &MqttServerLimits {
+        &self.server_limits
+    }
+
+    /// QoS 1 and 2 publishes that may be sent before one is acknowledged
+    pub fn send_quota(&self) -> u16 {
+        let window = self.server_limits.receive_maximum.min(MQTT_MAX_INFLIGHT as u16);
+        window.saturating_sub(self.session.inflight() as u16)
+    }
+
+    /// Outbound publishes whose QoS flow has not completed
+    pub fn inflight(&self) -> &[MqttInflightMessage] {
+        &self.session.outbound
+    }
+
+    /// Reason code of a DISCONNECT sent by the broker
+    pub fn disconnect_reason(&self) -> Option<MqttReasonCode> {
+        self.disconnect_reason
+    }
+
+    /// Take the last PUBLISH received by `process_messages()`
+    pub fn take_message(&mut self) -> Option<MqttMessage> {
+        self.received.take()
+    }
+
+    fn is_v5(&self) -> bool {
+        self.protocol_version == MqttProtocolVersion::V5
+    }
+
     /// Connect to MQTT broker
+    ///
+    /// With MQTT 5.0 and a session expiry, reconnecting sets Clean Start
+    /// to zero so the broker resumes the session; `session_present()`
+    /// reports whether it did.
     pub fn connect(&mut self, broker_host: &str, username: Option<&str>, password: Option<&str>) -> Result<(), MqttError> {
         // Build CONNECT message
         let mut connect_message = MqttMessage::new();
@@ -90,9 +257,8 @@ impl<'a> MqttClient<'a> {
         
         // Variable header
         let protocol_name = "MQTT";
-        connect_message.payload.extend_from_slice(&[0x00, 0x04]); // Protocol name length
-        connect_message.payload.extend_from_slice(protocol_name.as_bytes());
-        connect_message.payload.extend_from_slice(&[0x04]); // Protocol level (3.1.1)
+        put_binary(&mut connect_message.payload, protocol_name.as_bytes())?;
+        put_bytes(&mut connect_message.payload, &[self.protocol_version as u8])?;
         
         // Connect flags
         let mut connect_flags = 0u8;
@@ -102,23 +268,47 @@ impl<'a> MqttClient<'a> {
         if password.is_some() {
             connect_flags |= 0x40;
         }
-        connect_flags |= 0x02; // Clean session
-        connect_message.payload.push(connect_flags);
+        let persistent = if self.is_v5() { self.session_expiry_interval > 0 } else { self.store.is_some() };
+        let resume = persistent && self.session_held;
+        if !resume {
+            connect_flags |= 0x02; // Clean session / clean start
+        }
+        put_bytes(&mut connect_message.payload, &[connect_flags])?;
         
         // Keep alive
-        connect_message.payload.extend_from_slice(&self.keep_alive.to_be_bytes());
+        put_bytes(&mut connect_message.payload, &self.keep_alive.to_be_bytes())?;
+
+        if self.is_v5() {
+            let mut properties: Vec<MqttProperty, 3> = Vec::new();
+            if self.session_expiry_interval > 0 {
+                properties.push(MqttProperty::SessionExpiryInterval(self.session_expiry_interval)).ok();
+            }
+            if self.receive_maximum != MQTT_DEFAULT_RECEIVE_MAXIMUM {
+                properties.push(MqttProperty::ReceiveMaximum(self.receive_maximum)).ok();
+            }
+            if self.topic_alias_maximum > 0 {
+                properties.push(MqttProperty::TopicAliasMaximum(self.topic_alias_maximum)).ok();
+            }
+            encode_properties(&properties, &mut connect_message.payload)?;
+        }
         
-        // Client ID
-        self.add_string_to_payload(&mut connect_message.payload, &self.client_id);
+        // Client ID, user name and password
+        put_binary(&mut connect_message.payload, self.client_id.as_bytes())?;
+        if let Some(username) = username {
+            put_binary(&mut connect_message.payload, username.as_bytes())?;
+        }
+        if let Some(password) = password {
+            put_binary(&mut connect_message.payload, password.as_bytes())?;
+        }
         
         // Send message
-        self.transport.send(&connect_message.as_bytes())?;
+        self.transport.send(&connect_message.encode()?)?;
         
         // Wait for CONNACK
         let response = self.transport.receive(1000)?; // 1 second timeout
         if let Some(resp) = response {
             if self.parse_response(&resp)? == MqttMessageType::CONNACK {
-                Ok(())
+                self.handle_connack(&resp)
             } else {
                 Err(MqttError::ProtocolError)
             }
@@ -127,27 +317,161 @@ impl<'a> MqttClient<'a> {
         }
     }
 
+    fn handle_connack(&mut self, data: &[u8]) -> Result<(), MqttError> {
+        let (_, body) = split_packet(data)?;
+        if body.len() < 2 {
+            return Err(MqttError::InvalidMessage);
+        }
+        let session_present = body[0] & 0x01 != 0;
+
+        self.server_limits = MqttServerLimits::default();
+        if self.is_v5() {
+            let reason = MqttReasonCode::from_u8(body[1]);
+            if reason.is_error() {
+                return Err(MqttError::Refused(reason));
+            }
+            let (properties, _) = if body.len() > 2 {
+                MqttProperties::read(&body[2..])?
+            } else {
+                (MqttProperties::empty(), &body[2..])
+            };
+            for property in properties {
+                let property = property?;
+                if let MqttProperty::AssignedClientIdentifier(id) = property {
+                    // Reuse the broker's identifier so the session can be resumed
+                    let mut assigned = String::new();
+                    if assigned.push_str(id).is_ok() {
+                        self.client_id = assigned;
+                    }
+                }
+                self.server_limits.apply(&property);
+            }
+        } else if body[1] != 0 {
+            return Err(MqttError::Refused(MqttReasonCode::from_v311_connack(body[1])));
+        }
+
+        self.session_present = session_present;
+        let expiry = self.server_limits.session_expiry_interval.unwrap_or(self.session_expiry_interval);
+        self.session_held = if self.is_v5() { expiry > 0 } else { self.store.is_some() };
+        self.outbound_aliases.reset(self.server_limits.topic_alias_maximum);
+        self.inbound_aliases.reset(if self.is_v5() { self.topic_alias_maximum } else { 0 });
+        self.disconnect_reason = None;
+        self.replay_session(session_present)
+    }
+
+    /// Resend the in-flight window after (re)connecting
+    ///
+    /// A resumed session continues every flow where it stopped. A fresh
+    /// session republishes messages the broker never acknowledged and drops
+    /// QoS 2 flows past PUBREC, since the broker already has those.
+    fn replay_session(&mut self, session_present: bool) -> Result<(), MqttError> {
+        if !session_present {
+            while let Some(id) = self.session.incoming.pop() {
+                self.persist_incoming(id, false)?;
+            }
+            while let Some(pos) = self.session.outbound.iter()
+                .position(|m| m.state == MqttInflightState::AwaitingPubcomp)
+            {
+                let message = self.session.outbound.remove(pos);
+                self.persist_outbound_removal(message.packet_id);
+            }
+        }
+
+        let now = self.now_ms();
+        for i in 0..self.session.outbound.len() {
+            let message = &mut self.session.outbound[i];
+            let packet = if session_present { message.retransmission() } else { message.packet.clone() };
+            message.sent_at_ms = now;
+            message.attempts = message.attempts.saturating_add(1);
+            let message = message.clone();
+            self.persist_outbound(&message)?;
+            self.transport.send(&packet)?;
+        }
+        Ok(())
+    }
+
     /// Publish a message
+    ///
+    /// QoS 1 and 2 publishes enter the in-flight window and fail with
+    /// `QuotaExceeded` once it, or the broker's receive maximum, is full.
+    /// They are stored before they are sent, so a transport error leaves
+    /// them queued for replay on the next `connect()`.
+    ///
+    /// With MQTT 5.0, repeated QoS 0 topics travel as topic aliases while
+    /// the broker grants them; QoS 1 and 2 publishes keep the full topic
+    /// because aliases do not survive into a resumed connection.
     pub fn publish(&mut self, topic: &str, payload: &[u8], qos: MqttQos) -> Result<(), MqttError> {
+        if qos != MqttQos::AtMostOnce && self.send_quota() == 0 {
+            return Err(MqttError::QuotaExceeded);
+        }
+        if qos as u8 > self.server_limits.maximum_qos {
+            return Err(MqttError::Refused(MqttReasonCode::QosNotSupported));
+        }
+
         let mut publish_message = MqttMessage::new();
         publish_message.message_type = MqttMessageType::PUBLISH;
         
         // QoS flags
         publish_message.flags |= (qos as u8) << 1;
+
+        // Topic, or an empty topic name in place of a known alias
+        let alias = if self.is_v5() && qos == MqttQos::AtMostOnce {
+            match self.outbound_aliases.lookup(topic) {
+                Some(alias) => {
+                    put_binary(&mut publish_message.payload, &[])?;
+                    Some(alias)
+                }
+                None => {
+                    put_binary(&mut publish_message.payload, topic.as_bytes())?;
+                    self.outbound_aliases.assign(topic)
+                }
+            }
+        } else {
+            put_binary(&mut publish_message.payload, topic.as_bytes())?;
+            None
+        };
         
-        // Topic
-        self.add_string_to_payload(&mut publish_message.payload, topic);
-        
-        // Message ID (for QoS 1 and 2)
-        let msg_id = self.get_next_message_id();
+        // Message ID (for QoS 1 and 2), skipping identifiers still in flight
+        let mut packet_id = 0;
         if qos != MqttQos::AtMostOnce {
-            publish_message.payload.extend_from_slice(&msg_id.to_be_bytes());
+            packet_id = self.get_next_message_id();
+            while self.session.is_packet_id_used(packet_id) {
+                packet_id = self.get_next_message_id();
+            }
+            put_bytes(&mut publish_message.payload, &packet_id.to_be_bytes())?;
+        }
+
+        if self.is_v5() {
+            let properties = alias.map(MqttProperty::TopicAlias);
+            encode_properties(properties.as_slice(), &mut publish_message.payload)?;
         }
         
         // Payload
-        publish_message.payload.extend_from_slice(payload);
-        
-        self.transport.send(&publish_message.as_bytes())
+        put_bytes(&mut publish_message.payload, payload)?;
+
+        let packet = publish_message.encode()?;
+        if let Some(maximum) = self.server_limits.maximum_packet_size {
+            if packet.len() as u32 > maximum {
+                return Err(MqttError::PacketTooLarge);
+            }
+        }
+        if qos != MqttQos::AtMostOnce {
+            let message = MqttInflightMessage {
+                packet_id,
+                qos,
+                state: if qos == MqttQos::AtLeastOnce {
+                    MqttInflightState::AwaitingPuback
+                } else {
+                    MqttInflightState::AwaitingPubrec
+                },
+                packet: Vec::from_slice(&packet).map_err(|_| MqttError::PacketTooLarge)?,
+                sent_at_ms: self.now_ms(),
+                attempts: 1,
+            };
+            self.persist_outbound(&message)?;
+            self.session.outbound.push(message).map_err(|_| MqttError::QuotaExceeded)?;
+        }
+        self.transport.send(&packet)
     }
 
     /// Subscribe to a topic
@@ -157,13 +481,16 @@ impl<'a> MqttClient<'a> {
         subscribe_message.flags |= 0x02; // QoS flags
         
         let msg_id = self.get_next_message_id();
-        subscribe_message.payload.extend_from_slice(&msg_id.to_be_bytes());
+        put_bytes(&mut subscribe_message.payload, &msg_id.to_be_bytes())?;
+        if self.is_v5() {
+            encode_properties(&[], &mut subscribe_message.payload)?;
+        }
         
-        // Topic and QoS
-        self.add_string_to_payload(&mut subscribe_message.payload, topic);
-        subscribe_message.payload.push(qos as u8);
+        // Topic and QoS (the 5.0 subscription options keep other bits clear)
+        put_binary(&mut subscribe_message.payload, topic.as_bytes())?;
+        put_bytes(&mut subscribe_message.payload, &[qos as u8])?;
         
-        self.transport.send(&subscribe_message.as_bytes())
+        self.transport.send(&subscribe_message.encode()?)
     }
 
     /// Send PINGREQ
@@ -175,24 +502,165 @@ impl<'a> MqttClient<'a> {
             topic: None,
         };
         
-        self.transport.send(&ping_message.as_bytes())
+        self.transport.send(&ping_message.encode()?)
+    }
+
+    /// Send DISCONNECT with reason code Success
+    pub fn disconnect(&mut self) -> Result<(), MqttError> {
+        let mut disconnect_message = MqttMessage::new();
+        disconnect_message.message_type = MqttMessageType::DISCONNECT;
+        if self.is_v5() {
+            put_bytes(&mut disconnect_message.payload, &[MqttReasonCode::Success as u8])?;
+            encode_properties(&[], &mut disconnect_message.payload)?;
+        }
+
+        self.transport.send(&disconnect_message.encode()?)
     }
 
     /// Process incoming messages
+    ///
+    /// Also runs the retransmission timers, returning `Timeout` when an
+    /// in-flight message has used up its attempts.
     pub fn process_messages(&mut self) -> Result<(), MqttError> {
         if let Some(message) = self.transport.receive(0)? {
             let msg_type = self.parse_response(&message)?;
             
             match msg_type {
-                MqttMessageType::PUBLISH => self.handle_publish(&message),
-                MqttMessageType::PINGREQ => self.send_ping_response(),
-                _ => Ok(()),
+                MqttMessageType::PUBLISH => self.handle_publish(&message)?,
+                MqttMessageType::PUBACK => self.handle_puback(&message)?,
+                MqttMessageType::PUBREC => self.handle_pubrec(&message)?,
+                MqttMessageType::PUBREL => self.handle_pubrel(&message)?,
+                MqttMessageType::PUBCOMP => self.handle_pubcomp(&message)?,
+                MqttMessageType::PINGREQ => self.send_ping_response()?,
+                MqttMessageType::DISCONNECT => return self.handle_disconnect(&message),
+                _ => {}
+            }
+        }
+        self.retransmit_expired()
+    }
+
+    fn handle_puback(&mut self, data: &[u8]) -> Result<(), MqttError> {
+        let (_, body) = split_packet(data)?;
+        let packet_id = ack_packet_id(body)?;
+        self.complete_outbound(packet_id, MqttInflightState::AwaitingPuback);
+        Ok(())
+    }
+
+    fn handle_pubrec(&mut self, data: &[u8]) -> Result<(), MqttError> {
+        let (_, body) = split_packet(data)?;
+        let packet_id = ack_packet_id(body)?;
+        let reason = body.get(2).copied().unwrap_or(0);
+        let state = self.session.outbound.iter().find(|m| m.packet_id == packet_id).map(|m| m.state);
+        match state {
+            None => {
+                let not_found = self.is_v5().then_some(MqttReasonCode::PacketIdentifierNotFound as u8);
+                self.transport.send(&encode_pubrel(packet_id, not_found))
+            }
+            Some(MqttInflightState::AwaitingPubrec) if reason >= 0x80 => {
+                // The broker refused the message, which ends the flow
+                self.complete_outbound(packet_id, MqttInflightState::AwaitingPubrec);
+                Ok(())
+            }
+            Some(MqttInflightState::AwaitingPubrec | MqttInflightState::AwaitingPubcomp) => {
+                let pubrel = encode_pubrel(packet_id, None);
+                let now = self.now_ms();
+                let Some(message) = self.session.outbound_mut(packet_id) else { return Ok(()) };
+                message.state = MqttInflightState::AwaitingPubcomp;
+                message.packet = Vec::from_slice(&pubrel).map_err(|_| MqttError::PacketTooLarge)?;
+                message.sent_at_ms = now;
+                message.attempts = 1;
+                let message = message.clone();
+                self.persist_outbound(&message)?;
+                self.transport.send(&pubrel)
+            }
+            Some(MqttInflightState::AwaitingPuback) => Err(MqttError::ProtocolError),
+        }
+    }
+
+    fn handle_pubrel(&mut self, data: &[u8]) -> Result<(), MqttError> {
+        let (_, body) = split_packet(data)?;
+        let packet_id = ack_packet_id(body)?;
+        let known = self.session.incoming.contains(&packet_id);
+        if known {
+            self.session.incoming.retain(|id| *id != packet_id);
+            self.persist_incoming(packet_id, false)?;
+        }
+        let reason = (!known && self.is_v5()).then_some(MqttReasonCode::PacketIdentifierNotFound as u8);
+        self.transport.send(&encode_pubcomp(packet_id, reason))
+    }
+
+    fn handle_pubcomp(&mut self, data: &[u8]) -> Result<(), MqttError> {
+        let (_, body) = split_packet(data)?;
+        let packet_id = ack_packet_id(body)?;
+        self.complete_outbound(packet_id, MqttInflightState::AwaitingPubcomp);
+        Ok(())
+    }
+
+    /// Finish an outbound flow if it is in the state the acknowledgement expects
+    fn complete_outbound(&mut self, packet_id: u16, expected: MqttInflightState) {
+        let matches = self.session.outbound.iter().any(|m| m.packet_id == packet_id && m.state == expected);
+        if matches {
+            self.session.remove_outbound(packet_id);
+            self.persist_outbound_removal(packet_id);
+        }
+    }
+
+    /// Resend (MQTT 3.1.1) or report (MQTT 5.0) messages past their retry interval
+    fn retransmit_expired(&mut self) -> Result<(), MqttError> {
+        let Some(clock) = self.clock else { return Ok(()) };
+        let now = clock.now_ms();
+        let mut stalled = false;
+        for i in 0..self.session.outbound.len() {
+            let message = &mut self.session.outbound[i];
+            if now.saturating_sub(message.sent_at_ms) < self.retry.interval_ms as u64 {
+                continue;
+            }
+            message.sent_at_ms = now;
+            if self.protocol_version == MqttProtocolVersion::V5 || message.attempts >= self.retry.max_attempts {
+                stalled = true;
+                continue;
             }
+            message.attempts += 1;
+            let packet = message.retransmission();
+            let message = message.clone();
+            self.persist_outbound(&message)?;
+            self.transport.send(&packet)?;
+        }
+        if stalled {
+            Err(MqttError::Timeout)
         } else {
             Ok(())
         }
     }
 
+    fn now_ms(&self) -> u64 {
+        self.clock.map_or(0, |clock| clock.now_ms())
+    }
+
+    fn persist_outbound(&mut self, message: &MqttInflightMessage) -> Result<(), MqttError> {
+        match self.store.as_mut() {
+            Some(store) => store.store_outbound(message),
+            None => Ok(()),
+        }
+    }
+
+    fn persist_outbound_removal(&mut self, packet_id: u16) {
+        if let Some(store) = self.store.as_mut() {
+            store.remove_outbound(packet_id);
+        }
+    }
+
+    fn persist_incoming(&mut self, packet_id: u16, present: bool) -> Result<(), MqttError> {
+        match self.store.as_mut() {
+            Some(store) if present => store.store_incoming(packet_id),
+            Some(store) => {
+                store.remove_incoming(packet_id);
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
     fn add_string_to_payload(&self, payload: &mut Vec<u8, 256>, string: &str) {
         let len = string.len() as u16;
         payload.extend_from_slice(&len.to_be_bytes());
@@ -210,19 +678,86 @@ impl<'a> MqttClient<'a> {
             2 => Ok(MqttMessageType::CONNACK),
             3 => Ok(MqttMessageType::PUBLISH),
             4 => Ok(MqttMessageType::PUBACK),
+            5 => Ok(MqttMessageType::PUBREC),
+            6 => Ok(MqttMessageType::PUBREL),
+            7 => Ok(MqttMessageType::PUBCOMP),
             8 => Ok(MqttMessageType::SUBSCRIBE),
+            9 => Ok(MqttMessageType::SUBACK),
+            11 => Ok(MqttMessageType::UNSUBACK),
+            12 => Ok(MqttMessageType::PINGREQ),
             13 => Ok(MqttMessageType::PINGRESP),
+            14 => Ok(MqttMessageType::DISCONNECT),
             _ => Err(MqttError::UnsupportedMessage),
         }
     }
 
-    fn handle_publish(&self, data: &[u8]) -> Result<(), MqttError> {
-        // Parse PUBLISH message
-        // This would parse topic, QoS, and payload
-        // For brevity, just acknowledge
+    /// Deliver an inbound PUBLISH and acknowledge it for its QoS
+    ///
+    /// QoS 2 messages are delivered on receipt; their identifiers are kept
+    /// until PUBREL so a redelivered duplicate is acknowledged but not
+    /// handed to the application again.
+    fn handle_publish(&mut self, data: &[u8]) -> Result<(), MqttError> {
+        let (header, body) = split_packet(data)?;
+        let (topic, mut rest) = read_str(body)?;
+        let qos = (header >> 1) & 0x03;
+        let mut packet_id = 0;
+        if qos != 0 {
+            packet_id = ack_packet_id(rest)?;
+            rest = &rest[2..];
+        }
+        if qos == 2 && self.session.incoming.contains(&packet_id) {
+            return self.transport.send(&encode_pubrec(packet_id, None));
+        }
+
+        let mut alias = None;
+        if self.is_v5() {
+            let (properties, payload) = MqttProperties::read(rest)?;
+            for property in properties {
+                if let MqttProperty::TopicAlias(value) = property? {
+                    alias = Some(value);
+                }
+            }
+            rest = payload;
+        }
+
+        // A topic with an alias (re)binds it; an empty topic uses the binding
+        let topic = match (topic.is_empty(), alias) {
+            (false, Some(alias)) => {
+                self.inbound_aliases.insert(alias, topic)?;
+                topic
+            }
+            (true, Some(alias)) => self.inbound_aliases.resolve(alias)
+                .ok_or(MqttError::Refused(MqttReasonCode::TopicAliasInvalid))?,
+            (true, None) => return Err(MqttError::Refused(MqttReasonCode::ProtocolError)),
+            (false, None) => topic,
+        };
+
+        let mut name = String::new();
+        name.push_str(topic).map_err(|_| MqttError::PacketTooLarge)?;
+        let mut message = MqttMessage::from_payload(&[]);
+        message.flags = header & 0x0F;
+        put_bytes(&mut message.payload, rest)?;
+        message.topic = Some(name);
+        match qos {
+            1 => self.transport.send(&encode_puback(packet_id, None))?,
+            2 => {
+                self.session.incoming.push(packet_id).map_err(|_| MqttError::QuotaExceeded)?;
+                self.persist_incoming(packet_id, true)?;
+                self.transport.send(&encode_pubrec(packet_id, None))?;
+            }
+            _ => {}
+        }
+        self.received = Some(message);
         Ok(())
     }
 
+    fn handle_disconnect(&mut self, data: &[u8]) -> Result<(), MqttError> {
+        let (_, body) = split_packet(data)?;
+        let reason = body.first().map_or(MqttReasonCode::Success, |&code| MqttReasonCode::from_u8(code));
+        self.disconnect_reason = Some(reason);
+        Err(MqttError::Disconnected(reason))
+    }
+
     fn send_ping_response(&mut self) -> Result<(), MqttError> {
         let ping_response = MqttMessage {
             message_type: MqttMessageType::PINGRESP,
@@ -231,12 +766,14 @@ impl<'a> MqttClient<'a> {
             topic: None,
         };
         
-        self.transport.send(&ping_response.as_bytes())
+        self.transport.send(&ping_response.encode()?)
     }
 
     fn get_next_message_id(&mut self) -> u16 {
-        static MESSAGE_ID: AtomicU16 = AtomicU16::new(1);
-        MESSAGE_ID.fetch_add(1, Ordering::Relaxed)
+        let id = self.next_packet_id;
+        // Packet identifiers are non-zero
+        self.next_packet_id = self.next_packet_id.checked_add(1).unwrap_or(1);
+        id
     }
 }
 
@@ -251,6 +788,15 @@ impl MqttMessage {
         // Convert message to bytes
         &self.payload
     }
+
+    /// Complete packet: fixed header, remaining length and body
+    pub fn encode(&self) -> Result<Vec<u8, 264>, MqttError> {
+        let mut packet = Vec::new();
+        put_bytes(&mut packet, &[(self.message_type as u8) << 4 | (self.flags & 0x0F)])?;
+        encode_variable_int(self.payload.len() as u32, &mut packet)?;
+        put_bytes(&mut packet, &self.payload)?;
+        Ok(packet)
+    }
 }
 
 /// MQTT Error types
@@ -262,16 +808,23 @@ pub enum MqttError {
     UnsupportedMessage,
     Timeout,
     TransportError,
+    /// Broker rejected a request with this reason code
+    Refused(MqttReasonCode),
+    /// Broker closed the connection with this reason code
+    Disconnected(MqttReasonCode),
+    /// Broker's receive maximum of unacknowledged publishes reached
+    QuotaExceeded,
+    PacketTooLarge,
 }
 
 /// WiFi Transport implementation using UART
 pub struct WifiTransport {
-    uart: &'static Uart,
+    uart: Uart,
     buffer: Vec<u8, 1024>,
 }
 
 impl WifiTransport {
-    pub fn new(uart: &'static Uart) -> Self {
+    pub fn new(uart: Uart) -> Self {
         Self {
             uart,
             buffer: Vec::new(),
@@ -439,6 +992,12 @@ impl LoRaTransport {
         }
     }
 
+    /// Check the radio answers on SPI by reading its version register
+    pub fn is_responding(&self) -> Result<bool, LoRaError> {
+        let version = self.read_register(0x42)?;
+        Ok(version != 0x00 && version != 0xFF)
+    }
+
     fn reset_module(&self) -> Result<(), LoRaError> {
         // Send reset sequence via SPI
         let reset_cmd = [0x42, 0x6D, 0x01];
@@ -608,10 +1167,21 @@ impl BluetoothLETransport {
         Ok(())
     }
 
+    /// Check the module acknowledges its I2C address
+    pub fn is_responding(&self) -> bool {
+        self.i2c_bus.start();
+        let ack = self.i2c_bus.write_byte(self.device_address << 1);
+        self.i2c_bus.stop();
+        ack
+    }
+
     fn write_register(&self, addr: u8, value: u8) -> Result<(), BleError> {
-        self.i2c_bus.write_byte(self.device_address << 1 | 0x01)?;
-        self.i2c_bus.write_byte(addr)?;
-        self.i2c_bus.write_byte(value)?;
+        // write_byte reports whether the device acknowledged
+        for byte in [self.device_address << 1 | 0x01, addr, value] {
+            if !self.i2c_bus.write_byte(byte) {
+                return Err(BleError::ConnectionFailed);
+            }
+        }
         Ok(())
     }
 }
@@ -622,14 +1192,76 @@ pub enum BleError {
     GATTError,
     Timeout,
     InvalidData,
+    /// Attribute, connection or bond table has no free entry
+    TableFull,
+}
+
+/// Consecutive failed sends or probes before a link is marked down
+const LINK_FAILURE_THRESHOLD: u8 = 3;
+/// Default interval between health probes of each link
+const DEFAULT_PROBE_INTERVAL_MS: u32 = 30_000;
+
+/// Health of one transport as seen by the manager
+#[derive(Debug, Clone, Copy)]
+pub struct LinkHealth {
+    pub up: bool,
+    pub consecutive_failures: u8,
+    pub last_probe_ms: u64,
+    pub last_success_ms: u64,
+}
+
+impl LinkHealth {
+    const fn down() -> Self {
+        Self {
+            up: false,
+            consecutive_failures: 0,
+            last_probe_ms: 0,
+            last_success_ms: 0,
+        }
+    }
+
+    fn record(&mut self, success: bool, now_ms: u64) {
+        if success {
+            self.up = true;
+            self.consecutive_failures = 0;
+            self.last_success_ms = now_ms;
+        } else {
+            self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+            if self.consecutive_failures >= LINK_FAILURE_THRESHOLD {
+                self.up = false;
+            }
+        }
+    }
+}
+
+/// Monotonic clock backed by the HAL timer
+pub struct HalClock;
+
+impl MqttClock for HalClock {
+    fn now_ms(&self) -> u64 {
+        let (seconds, nanoseconds) = crate::riscv_hal::get_time();
+        seconds as u64 * 1000 + nanoseconds as u64 / 1_000_000
+    }
 }
 
 /// Communication Manager - coordinates multiple transport protocols
+///
+/// Telemetry goes out over the first healthy transport in priority order
+/// (WiFi/MQTT, then LoRa, then BLE by default). Links are marked down after
+/// repeated failures and probed periodically from `service()`; while none
+/// is up, telemetry waits in a bounded outbox that drains on reconnect.
 pub struct CommunicationManager {
     mqtt_client: Option<MqttClient<'static>>,
     wifi_transport: Option<WifiTransport>,
     lora_transport: Option<LoRaTransport>,
     ble_transport: Option<BluetoothLETransport>,
+    priority: [CommunicationProtocol; 3],
+    health: [LinkHealth; 3],
+    probe_interval_ms: u32,
+    telemetry_topic: String<64>,
+    outbox: TelemetryOutbox,
+    outbox_store: Option<&'static mut dyn OutboxStore>,
+    time_sync: TimeSync,
 }
 
 impl CommunicationManager {
@@ -639,33 +1271,114 @@ impl CommunicationManager {
             wifi_transport: None,
             lora_transport: None,
             ble_transport: None,
+            priority: [CommunicationProtocol::MQTT, CommunicationProtocol::LoRa, CommunicationProtocol::BLE],
+            health: [LinkHealth::down(); 3],
+            probe_interval_ms: DEFAULT_PROBE_INTERVAL_MS,
+            telemetry_topic: String::new(),
+            outbox: TelemetryOutbox::new(),
+            outbox_store: None,
+            time_sync: TimeSync::new(),
         }
     }
 
     /// Initialize WiFi transport
     pub fn init_wifi(&mut self, uart: &'static Uart, ssid: &str, password: &str) -> Result<(), CommunicationError> {
-        let mut wifi = WifiTransport::new(uart);
+        let mut wifi = WifiTransport::new(*uart);
         wifi.init(ssid, password)?;
         self.wifi_transport = Some(wifi);
         Ok(())
     }
 
+    /// Initialize the transports a board wires up
+    ///
+    /// Each radio is brought up on the bus the board's bindings name;
+    /// unbound transports are left alone, and WiFi is skipped without
+    /// credentials.
+    pub fn init_from_board(&mut self, board: &BoardConfig, wifi_credentials: Option<(&str, &str)>) -> Result<(), CommunicationError> {
+        if let (Some(mut uart), Some((ssid, password))) = (board.wifi_uart(), wifi_credentials) {
+            uart.init(board.system);
+            let mut wifi = WifiTransport::new(uart);
+            wifi.init(ssid, password)?;
+            self.wifi_transport = Some(wifi);
+        }
+        if let Some(spi_bus) = board.lora_spi() {
+            self.init_lora(spi_bus)?;
+        }
+        if let Some((i2c_bus, address)) = board.ble_i2c() {
+            self.init_ble(i2c_bus, address)?;
+        }
+        Ok(())
+    }
+
     /// Initialize LoRa transport
     pub fn init_lora(&mut self, spi_bus: crate::riscv_hal::SpiBus) -> Result<(), CommunicationError> {
         let mut lora = LoRaTransport::new(spi_bus);
         lora.init()?;
         self.lora_transport = Some(lora);
+        self.mark_up(CommunicationProtocol::LoRa);
+        Ok(())
+    }
+
+    /// Initialize BLE transport
+    pub fn init_ble(&mut self, i2c_bus: crate::riscv_hal::I2CBus, address: u8) -> Result<(), CommunicationError> {
+        let mut ble = BluetoothLETransport::new(i2c_bus, address);
+        ble.init()?;
+        self.ble_transport = Some(ble);
+        self.mark_up(CommunicationProtocol::BLE);
         Ok(())
     }
 
     /// Initialize MQTT client
-    pub fn init_mqtt(&mut self, transport: Box<dyn MqttTransport>, client_id: String<32>) -> Result<(), CommunicationError> {
-        // Create static reference for MQTT client
-        let mqtt_client = MqttClient::new(transport.as_ref(), client_id);
+    pub fn init_mqtt<T: MqttTransport>(&mut self, transport: &'static T, client_id: String<32>) -> Result<(), CommunicationError> {
+        let mqtt_client = MqttClient::new(transport, client_id);
         self.mqtt_client = Some(mqtt_client);
+        self.mark_up(CommunicationProtocol::MQTT);
         Ok(())
     }
 
+    /// Order in which transports are tried for telemetry
+    pub fn set_priority(&mut self, priority: [CommunicationProtocol; 3]) {
+        self.priority = priority;
+    }
+
+    pub fn set_probe_interval(&mut self, interval_ms: u32) {
+        self.probe_interval_ms = interval_ms;
+    }
+
+    /// MQTT topic used for telemetry sent over the WiFi route
+    pub fn set_telemetry_topic(&mut self, topic: &str) -> Result<(), CommunicationError> {
+        let mut name = String::new();
+        name.push_str(topic).map_err(|_| CommunicationError::InvalidData)?;
+        self.telemetry_topic = name;
+        Ok(())
+    }
+
+    /// Persist the outbox in `store`, restoring what it already holds
+    pub fn set_outbox_store(&mut self, store: &'static mut dyn OutboxStore) {
+        store.load(&mut self.outbox);
+        self.outbox_store = Some(store);
+    }
+
+    pub fn link_health(&self, protocol: CommunicationProtocol) -> &LinkHealth {
+        &self.health[protocol.index()]
+    }
+
+    /// Highest-priority transport currently considered up
+    pub fn active_route(&self) -> Option<CommunicationProtocol> {
+        self.priority.iter().copied().find(|p| self.is_available(*p))
+    }
+
+    /// Messages waiting for a link
+    pub fn outbox(&self) -> &TelemetryOutbox {
+        &self.outbox
+    }
+
+    /// Wall-clock mapping for telemetry timestamps; samples must be taken
+    /// against `HalClock`
+    pub fn time_sync(&mut self) -> &mut TimeSync {
+        &mut self.time_sync
+    }
+
     /// Send message via available transport
     pub fn send_message(&self, data: &[u8], protocol: CommunicationProtocol) -> Result<(), CommunicationError> {
         match protocol {
@@ -690,6 +1403,131 @@ impl CommunicationManager {
         Ok(())
     }
 
+    /// Send telemetry over the best available transport
+    ///
+    /// Returns the transport that carried the message, or `None` when no
+    /// link is up and it was queued in the outbox. Messages queue behind
+    /// older ones so delivery order is preserved.
+    pub fn send_telemetry(&mut self, data: &[u8]) -> Result<Option<CommunicationProtocol>, CommunicationError> {
+        if self.outbox.is_empty() {
+            if let Some(protocol) = self.send_with_failover(data) {
+                return Ok(Some(protocol));
+            }
+        }
+        self.enqueue(data)?;
+        self.drain_outbox()?;
+        Ok(if self.outbox.is_empty() { self.active_route() } else { None })
+    }
+
+    /// Probe links that are due and drain the outbox if one is up
+    pub fn service(&mut self) -> Result<(), CommunicationError> {
+        let now = Self::now_ms();
+        for protocol in self.priority {
+            if !self.is_configured(protocol) {
+                continue;
+            }
+            let health = &self.health[protocol.index()];
+            if now.saturating_sub(health.last_probe_ms) < self.probe_interval_ms as u64 {
+                continue;
+            }
+            let alive = self.probe(protocol);
+            let health = &mut self.health[protocol.index()];
+            health.last_probe_ms = now;
+            health.record(alive, now);
+        }
+        self.drain_outbox()
+    }
+
+    fn is_configured(&self, protocol: CommunicationProtocol) -> bool {
+        match protocol {
+            CommunicationProtocol::MQTT => self.mqtt_client.is_some(),
+            CommunicationProtocol::LoRa => self.lora_transport.is_some(),
+            CommunicationProtocol::BLE => self.ble_transport.is_some(),
+        }
+    }
+
+    fn is_available(&self, protocol: CommunicationProtocol) -> bool {
+        self.is_configured(protocol) && self.health[protocol.index()].up
+    }
+
+    fn mark_up(&mut self, protocol: CommunicationProtocol) {
+        self.health[protocol.index()].record(true, Self::now_ms());
+    }
+
+    fn now_ms() -> u64 {
+        HalClock.now_ms()
+    }
+
+    /// Check a link is alive without sending telemetry
+    fn probe(&mut self, protocol: CommunicationProtocol) -> bool {
+        match protocol {
+            CommunicationProtocol::MQTT => self.mqtt_client.as_mut().is_some_and(|client| client.ping().is_ok()),
+            CommunicationProtocol::LoRa => self.lora_transport.as_ref().is_some_and(|lora| lora.is_responding().unwrap_or(false)),
+            CommunicationProtocol::BLE => self.ble_transport.as_ref().is_some_and(|ble| ble.is_responding()),
+        }
+    }
+
+    fn transmit(&mut self, protocol: CommunicationProtocol, data: &[u8]) -> Result<(), CommunicationError> {
+        match protocol {
+            CommunicationProtocol::MQTT => {
+                let client = self.mqtt_client.as_mut().ok_or(CommunicationError::TransportNotInitialized)?;
+                client.publish(&self.telemetry_topic, data, MqttQos::AtLeastOnce)?;
+            }
+            CommunicationProtocol::LoRa => {
+                let lora = self.lora_transport.as_ref().ok_or(CommunicationError::TransportNotInitialized)?;
+                lora.send_data(data, 0xFF_FF_FF_FF)?; // Broadcast
+            }
+            CommunicationProtocol::BLE => {
+                let ble = self.ble_transport.as_ref().ok_or(CommunicationError::TransportNotInitialized)?;
+                ble.send_data(data, 0x0001)?; // Default connection handle
+            }
+        }
+        Ok(())
+    }
+
+    /// Try each available transport in priority order
+    fn send_with_failover(&mut self, data: &[u8]) -> Option<CommunicationProtocol> {
+        let now = Self::now_ms();
+        for protocol in self.priority {
+            if !self.is_available(protocol) {
+                continue;
+            }
+            let sent = self.transmit(protocol, data).is_ok();
+            self.health[protocol.index()].record(sent, now);
+            if sent {
+                return Some(protocol);
+            }
+        }
+        None
+    }
+
+    fn enqueue(&mut self, data: &[u8]) -> Result<(), CommunicationError> {
+        let (message, evicted) = self.outbox.push(data)?;
+        if let Some(store) = self.outbox_store.as_mut() {
+            if let Some(sequence) = evicted {
+                store.acknowledge(sequence);
+            }
+            store.append(message)?;
+        }
+        Ok(())
+    }
+
+    /// Send queued messages oldest first until the outbox is empty or no link works
+    fn drain_outbox(&mut self) -> Result<(), CommunicationError> {
+        while let Some(message) = self.outbox.front() {
+            let payload = message.payload.clone();
+            let sequence = message.sequence;
+            if self.send_with_failover(&payload).is_none() {
+                break;
+            }
+            self.outbox.pop_front();
+            if let Some(store) = self.outbox_store.as_mut() {
+                store.acknowledge(sequence);
+            }
+        }
+        Ok(())
+    }
+
     /// Process incoming messages
     pub fn process_messages(&mut self) -> Result<(), CommunicationError> {
         // Process MQTT messages
@@ -708,17 +1546,77 @@ impl CommunicationManager {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+impl WallClock for CommunicationManager {
+    fn unix_time_ms(&self) -> Option<u64> {
+        self.time_sync.unix_ms(Self::now_ms())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum CommunicationProtocol {
     MQTT,
     LoRa,
     BLE,
 }
 
+impl CommunicationProtocol {
+    const fn index(self) -> usize {
+        match self {
+            CommunicationProtocol::MQTT => 0,
+            CommunicationProtocol::LoRa => 1,
+            CommunicationProtocol::BLE => 2,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum CommunicationError {
     TransportNotInitialized,
     ProtocolError,
     Timeout,
     InvalidData,
+    /// Transport reported a send or link failure
+    TransportFailed,
+}
+
+impl From<MqttError> for CommunicationError {
+    fn from(err: MqttError) -> Self {
+        match err {
+            MqttError::Timeout => CommunicationError::Timeout,
+            MqttError::InvalidMessage | MqttError::PacketTooLarge => CommunicationError::InvalidData,
+            MqttError::TransportError | MqttError::Disconnected(_) => CommunicationError::TransportFailed,
+            _ => CommunicationError::ProtocolError,
+        }
+    }
+}
+
+impl From<WifiError> for CommunicationError {
+    fn from(err: WifiError) -> Self {
+        match err {
+            WifiError::Timeout => CommunicationError::Timeout,
+            WifiError::InvalidResponse => CommunicationError::ProtocolError,
+            WifiError::ConnectionFailed | WifiError::AuthenticationFailed => CommunicationError::TransportFailed,
+        }
+    }
+}
+
+impl From<LoRaError> for CommunicationError {
+    fn from(err: LoRaError) -> Self {
+        match err {
+            LoRaError::Timeout => CommunicationError::Timeout,
+            LoRaError::InvalidPacket | LoRaError::CrcError => CommunicationError::InvalidData,
+            LoRaError::TransmitFailed => CommunicationError::TransportFailed,
+        }
+    }
+}
+
+impl From<BleError> for CommunicationError {
+    fn from(err: BleError) -> Self {
+        match err {
+            BleError::Timeout => CommunicationError::Timeout,
+            BleError::InvalidData => CommunicationError::InvalidData,
+            BleError::GATTError => CommunicationError::ProtocolError,
+            BleError::ConnectionFailed | BleError::TableFull => CommunicationError::TransportFailed,
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,630 @@
+//! MQTT 5.0 protocol elements
+//!
+//! Properties, reason codes and topic alias tables used by `MqttClient`
+//! when it speaks `MqttProtocolVersion::V5`. Decoded properties borrow
+//! from the packet they came from, so nothing is copied until the client
+//! decides to keep a value.
+
+use heapless::{String, Vec};
+use crate::MqttError;
+
+/// Topic aliases tracked in each direction
+pub const MQTT_MAX_TOPIC_ALIASES: usize = 16;
+/// Longest topic name kept in an alias table
+pub const MQTT_MAX_TOPIC_LENGTH: usize = 128;
+/// Receive Maximum when the peer does not send one
+pub const MQTT_DEFAULT_RECEIVE_MAXIMUM: u16 = 65535;
+
+/// Protocol level sent in CONNECT
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MqttProtocolVersion {
+    V311 = 4,
+    V5 = 5,
+}
+
+/// MQTT 5.0 reason codes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MqttReasonCode {
+    Success = 0x00,
+    GrantedQos1 = 0x01,
+    GrantedQos2 = 0x02,
+    DisconnectWithWill = 0x04,
+    NoMatchingSubscribers = 0x10,
+    NoSubscriptionExisted = 0x11,
+    ContinueAuthentication = 0x18,
+    ReAuthenticate = 0x19,
+    UnspecifiedError = 0x80,
+    MalformedPacket = 0x81,
+    ProtocolError = 0x82,
+    ImplementationSpecificError = 0x83,
+    UnsupportedProtocolVersion = 0x84,
+    ClientIdentifierNotValid = 0x85,
+    BadUserNameOrPassword = 0x86,
+    NotAuthorized = 0x87,
+    ServerUnavailable = 0x88,
+    ServerBusy = 0x89,
+    Banned = 0x8A,
+    ServerShuttingDown = 0x8B,
+    BadAuthenticationMethod = 0x8C,
+    KeepAliveTimeout = 0x8D,
+    SessionTakenOver = 0x8E,
+    TopicFilterInvalid = 0x8F,
+    TopicNameInvalid = 0x90,
+    PacketIdentifierInUse = 0x91,
+    PacketIdentifierNotFound = 0x92,
+    ReceiveMaximumExceeded = 0x93,
+    TopicAliasInvalid = 0x94,
+    PacketTooLarge = 0x95,
+    MessageRateTooHigh = 0x96,
+    QuotaExceeded = 0x97,
+    AdministrativeAction = 0x98,
+    PayloadFormatInvalid = 0x99,
+    RetainNotSupported = 0x9A,
+    QosNotSupported = 0x9B,
+    UseAnotherServer = 0x9C,
+    ServerMoved = 0x9D,
+    SharedSubscriptionsNotSupported = 0x9E,
+    ConnectionRateExceeded = 0x9F,
+    MaximumConnectTime = 0xA0,
+    SubscriptionIdentifiersNotSupported = 0xA1,
+    WildcardSubscriptionsNotSupported = 0xA2,
+}
+
+impl MqttReasonCode {
+    pub fn from_u8(value: u8) -> Self {
+        match value {
+            0x00 => MqttReasonCode::Success,
+            0x01 => MqttReasonCode::GrantedQos1,
+            0x02 => MqttReasonCode::GrantedQos2,
+            0x04 => MqttReasonCode::DisconnectWithWill,
+            0x10 => MqttReasonCode::NoMatchingSubscribers,
+            0x11 => MqttReasonCode::NoSubscriptionExisted,
+            0x18 => MqttReasonCode::ContinueAuthentication,
+            0x19 => MqttReasonCode::ReAuthenticate,
+            0x81 => MqttReasonCode::MalformedPacket,
+            0x82 => MqttReasonCode::ProtocolError,
+            0x83 => MqttReasonCode::ImplementationSpecificError,
+            0x84 => MqttReasonCode::UnsupportedProtocolVersion,
+            0x85 => MqttReasonCode::ClientIdentifierNotValid,
+            0x86 => MqttReasonCode::BadUserNameOrPassword,
+            0x87 => MqttReasonCode::NotAuthorized,
+            0x88 => MqttReasonCode::ServerUnavailable,
+            0x89 => MqttReasonCode::ServerBusy,
+            0x8A => MqttReasonCode::Banned,
+            0x8B => MqttReasonCode::ServerShuttingDown,
+            0x8C => MqttReasonCode::BadAuthenticationMethod,
+            0x8D => MqttReasonCode::KeepAliveTimeout,
+            0x8E => MqttReasonCode::SessionTakenOver,
+            0x8F => MqttReasonCode::TopicFilterInvalid,
+            0x90 => MqttReasonCode::TopicNameInvalid,
+            0x91 => MqttReasonCode::PacketIdentifierInUse,
+            0x92 => MqttReasonCode::PacketIdentifierNotFound,
+            0x93 => MqttReasonCode::ReceiveMaximumExceeded,
+            0x94 => MqttReasonCode::TopicAliasInvalid,
+            0x95 => MqttReasonCode::PacketTooLarge,
+            0x96 => MqttReasonCode::MessageRateTooHigh,
+            0x97 => MqttReasonCode::QuotaExceeded,
+            0x98 => MqttReasonCode::AdministrativeAction,
+            0x99 => MqttReasonCode::PayloadFormatInvalid,
+            0x9A => MqttReasonCode::RetainNotSupported,
+            0x9B => MqttReasonCode::QosNotSupported,
+            0x9C => MqttReasonCode::UseAnotherServer,
+            0x9D => MqttReasonCode::ServerMoved,
+            0x9E => MqttReasonCode::SharedSubscriptionsNotSupported,
+            0x9F => MqttReasonCode::ConnectionRateExceeded,
+            0xA0 => MqttReasonCode::MaximumConnectTime,
+            0xA1 => MqttReasonCode::SubscriptionIdentifiersNotSupported,
+            0xA2 => MqttReasonCode::WildcardSubscriptionsNotSupported,
+            _ => MqttReasonCode::UnspecifiedError,
+        }
+    }
+
+    /// Map a 3.1.1 CONNACK return code onto its 5.0 equivalent
+    pub fn from_v311_connack(value: u8) -> Self {
+        match value {
+            0x00 => MqttReasonCode::Success,
+            0x01 => MqttReasonCode::UnsupportedProtocolVersion,
+            0x02 => MqttReasonCode::ClientIdentifierNotValid,
+            0x03 => MqttReasonCode::ServerUnavailable,
+            0x04 => MqttReasonCode::BadUserNameOrPassword,
+            0x05 => MqttReasonCode::NotAuthorized,
+            _ => MqttReasonCode::UnspecifiedError,
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        *self as u8 >= 0x80
+    }
+}
+
+/// MQTT 5.0 property, borrowing string and binary data from its packet
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MqttProperty<'a> {
+    PayloadFormatIndicator(u8),
+    MessageExpiryInterval(u32),
+    ContentType(&'a str),
+    ResponseTopic(&'a str),
+    CorrelationData(&'a [u8]),
+    SubscriptionIdentifier(u32),
+    SessionExpiryInterval(u32),
+    AssignedClientIdentifier(&'a str),
+    ServerKeepAlive(u16),
+    AuthenticationMethod(&'a str),
+    AuthenticationData(&'a [u8]),
+    RequestProblemInformation(u8),
+    WillDelayInterval(u32),
+    RequestResponseInformation(u8),
+    ResponseInformation(&'a str),
+    ServerReference(&'a str),
+    ReasonString(&'a str),
+    ReceiveMaximum(u16),
+    TopicAliasMaximum(u16),
+    TopicAlias(u16),
+    MaximumQos(u8),
+    RetainAvailable(u8),
+    UserProperty(&'a str, &'a str),
+    MaximumPacketSize(u32),
+    WildcardSubscriptionAvailable(u8),
+    SubscriptionIdentifierAvailable(u8),
+    SharedSubscriptionAvailable(u8),
+}
+
+impl<'a> MqttProperty<'a> {
+    pub fn identifier(&self) -> u8 {
+        match self {
+            MqttProperty::PayloadFormatIndicator(_) => 0x01,
+            MqttProperty::MessageExpiryInterval(_) => 0x02,
+            MqttProperty::ContentType(_) => 0x03,
+            MqttProperty::ResponseTopic(_) => 0x08,
+            MqttProperty::CorrelationData(_) => 0x09,
+            MqttProperty::SubscriptionIdentifier(_) => 0x0B,
+            MqttProperty::SessionExpiryInterval(_) => 0x11,
+            MqttProperty::AssignedClientIdentifier(_) => 0x12,
+            MqttProperty::ServerKeepAlive(_) => 0x13,
+            MqttProperty::AuthenticationMethod(_) => 0x15,
+            MqttProperty::AuthenticationData(_) => 0x16,
+            MqttProperty::RequestProblemInformation(_) => 0x17,
+            MqttProperty::WillDelayInterval(_) => 0x18,
+            MqttProperty::RequestResponseInformation(_) => 0x19,
+            MqttProperty::ResponseInformation(_) => 0x1A,
+            MqttProperty::ServerReference(_) => 0x1C,
+            MqttProperty::ReasonString(_) => 0x1F,
+            MqttProperty::ReceiveMaximum(_) => 0x21,
+            MqttProperty::TopicAliasMaximum(_) => 0x22,
+            MqttProperty::TopicAlias(_) => 0x23,
+            MqttProperty::MaximumQos(_) => 0x24,
+            MqttProperty::RetainAvailable(_) => 0x25,
+            MqttProperty::UserProperty(_, _) => 0x26,
+            MqttProperty::MaximumPacketSize(_) => 0x27,
+            MqttProperty::WildcardSubscriptionAvailable(_) => 0x28,
+            MqttProperty::SubscriptionIdentifierAvailable(_) => 0x29,
+            MqttProperty::SharedSubscriptionAvailable(_) => 0x2A,
+        }
+    }
+
+    /// Append identifier and value
+    pub fn encode<const N: usize>(&self, out: &mut Vec<u8, N>) -> Result<(), MqttError> {
+        put_bytes(out, &[self.identifier()])?;
+        match *self {
+            MqttProperty::PayloadFormatIndicator(value)
+            | MqttProperty::RequestProblemInformation(value)
+            | MqttProperty::RequestResponseInformation(value)
+            | MqttProperty::MaximumQos(value)
+            | MqttProperty::RetainAvailable(value)
+            | MqttProperty::WildcardSubscriptionAvailable(value)
+            | MqttProperty::SubscriptionIdentifierAvailable(value)
+            | MqttProperty::SharedSubscriptionAvailable(value) => put_bytes(out, &[value]),
+            MqttProperty::ServerKeepAlive(value)
+            | MqttProperty::ReceiveMaximum(value)
+            | MqttProperty::TopicAliasMaximum(value)
+            | MqttProperty::TopicAlias(value) => put_bytes(out, &value.to_be_bytes()),
+            MqttProperty::MessageExpiryInterval(value)
+            | MqttProperty::SessionExpiryInterval(value)
+            | MqttProperty::WillDelayInterval(value)
+            | MqttProperty::MaximumPacketSize(value) => put_bytes(out, &value.to_be_bytes()),
+            MqttProperty::SubscriptionIdentifier(value) => encode_variable_int(value, out),
+            MqttProperty::ContentType(value)
+            | MqttProperty::ResponseTopic(value)
+            | MqttProperty::AssignedClientIdentifier(value)
+            | MqttProperty::AuthenticationMethod(value)
+            | MqttProperty::ResponseInformation(value)
+            | MqttProperty::ServerReference(value)
+            | MqttProperty::ReasonString(value) => put_binary(out, value.as_bytes()),
+            MqttProperty::CorrelationData(value)
+            | MqttProperty::AuthenticationData(value) => put_binary(out, value),
+            MqttProperty::UserProperty(key, value) => {
+                put_binary(out, key.as_bytes())?;
+                put_binary(out, value.as_bytes())
+            }
+        }
+    }
+
+    fn decode(data: &'a [u8]) -> Result<(Self, usize), MqttError> {
+        let identifier = *data.first().ok_or(MqttError::InvalidMessage)?;
+        let mut reader = Reader { data, offset: 1 };
+        let property = match identifier {
+            0x01 => MqttProperty::PayloadFormatIndicator(reader.u8()?),
+            0x02 => MqttProperty::MessageExpiryInterval(reader.u32()?),
+            0x03 => MqttProperty::ContentType(reader.str()?),
+            0x08 => MqttProperty::ResponseTopic(reader.str()?),
+            0x09 => MqttProperty::CorrelationData(reader.binary()?),
+            0x0B => MqttProperty::SubscriptionIdentifier(reader.variable_int()?),
+            0x11 => MqttProperty::SessionExpiryInterval(reader.u32()?),
+            0x12 => MqttProperty::AssignedClientIdentifier(reader.str()?),
+            0x13 => MqttProperty::ServerKeepAlive(reader.u16()?),
+            0x15 => MqttProperty::AuthenticationMethod(reader.str()?),
+            0x16 => MqttProperty::AuthenticationData(reader.binary()?),
+            0x17 => MqttProperty::RequestProblemInformation(reader.u8()?),
+            0x18 => MqttProperty::WillDelayInterval(reader.u32()?),
+            0x19 => MqttProperty::RequestResponseInformation(reader.u8()?),
+            0x1A => MqttProperty::ResponseInformation(reader.str()?),
+            0x1C => MqttProperty::ServerReference(reader.str()?),
+            0x1F => MqttProperty::ReasonString(reader.str()?),
+            0x21 => MqttProperty::ReceiveMaximum(reader.u16()?),
+            0x22 => MqttProperty::TopicAliasMaximum(reader.u16()?),
+            0x23 => MqttProperty::TopicAlias(reader.u16()?),
+            0x24 => MqttProperty::MaximumQos(reader.u8()?),
+            0x25 => MqttProperty::RetainAvailable(reader.u8()?),
+            0x26 => MqttProperty::UserProperty(reader.str()?, reader.str()?),
+            0x27 => MqttProperty::MaximumPacketSize(reader.u32()?),
+            0x28 => MqttProperty::WildcardSubscriptionAvailable(reader.u8()?),
+            0x29 => MqttProperty::SubscriptionIdentifierAvailable(reader.u8()?),
+            0x2A => MqttProperty::SharedSubscriptionAvailable(reader.u8()?),
+            _ => return Err(MqttError::InvalidMessage),
+        };
+        Ok((property, reader.offset))
+    }
+}
+
+/// Iterator over an encoded property list
+pub struct MqttProperties<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> MqttProperties<'a> {
+    /// Split a length-prefixed property list off the front of `data`,
+    /// returning the properties and the bytes after them
+    pub fn read(data: &'a [u8]) -> Result<(Self, &'a [u8]), MqttError> {
+        let (length, used) = decode_variable_int(data)?;
+        let end = used + length as usize;
+        if end > data.len() {
+            return Err(MqttError::InvalidMessage);
+        }
+        Ok((Self { data: &data[used..end] }, &data[end..]))
+    }
+
+    pub fn empty() -> Self {
+        Self { data: &[] }
+    }
+}
+
+impl<'a> Iterator for MqttProperties<'a> {
+    type Item = Result<MqttProperty<'a>, MqttError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.is_empty() {
+            return None;
+        }
+        match MqttProperty::decode(self.data) {
+            Ok((property, used)) => {
+                self.data = &self.data[used..];
+                Some(Ok(property))
+            }
+            Err(error) => {
+                self.data = &[];
+                Some(Err(error))
+            }
+        }
+    }
+}
+
+/// Append a length-prefixed property list
+pub fn encode_properties<const N: usize>(properties: &[MqttProperty], out: &mut Vec<u8, N>) -> Result<(), MqttError> {
+    let mut encoded: Vec<u8, N> = Vec::new();
+    for property in properties {
+        property.encode(&mut encoded)?;
+    }
+    encode_variable_int(encoded.len() as u32, out)?;
+    put_bytes(out, &encoded)
+}
+
+/// Append a variable byte integer (at most 268,435,455)
+pub fn encode_variable_int<const N: usize>(mut value: u32, out: &mut Vec<u8, N>) -> Result<(), MqttError> {
+    if value > 0x0FFF_FFFF {
+        return Err(MqttError::PacketTooLarge);
+    }
+    loop {
+        let mut byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value > 0 {
+            byte |= 0x80;
+        }
+        put_bytes(out, &[byte])?;
+        if value == 0 {
+            return Ok(());
+        }
+    }
+}
+
+/// Decode a variable byte integer; returns the value and bytes used
+pub fn decode_variable_int(data: &[u8]) -> Result<(u32, usize), MqttError> {
+    let mut value = 0u32;
+    for (index, &byte) in data.iter().take(4).enumerate() {
+        value |= ((byte & 0x7F) as u32) << (7 * index);
+        if byte & 0x80 == 0 {
+            return Ok((value, index + 1));
+        }
+    }
+    Err(MqttError::InvalidMessage)
+}
+
+/// Split a packet into its fixed header byte and body
+pub fn split_packet(data: &[u8]) -> Result<(u8, &[u8]), MqttError> {
+    let header = *data.first().ok_or(MqttError::InvalidMessage)?;
+    let (length, used) = decode_variable_int(&data[1..])?;
+    let start = 1 + used;
+    let end = start + length as usize;
+    if end > data.len() {
+        return Err(MqttError::InvalidMessage);
+    }
+    Ok((header, &data[start..end]))
+}
+
+pub(crate) fn put_bytes<const N: usize>(out: &mut Vec<u8, N>, bytes: &[u8]) -> Result<(), MqttError> {
+    out.extend_from_slice(bytes).map_err(|_| MqttError::PacketTooLarge)
+}
+
+/// Append two-byte length and data, the encoding of strings and binary data
+pub(crate) fn put_binary<const N: usize>(out: &mut Vec<u8, N>, bytes: &[u8]) -> Result<(), MqttError> {
+    if bytes.len() > u16::MAX as usize {
+        return Err(MqttError::PacketTooLarge);
+    }
+    put_bytes(out, &(bytes.len() as u16).to_be_bytes())?;
+    put_bytes(out, bytes)
+}
+
+/// Read a two-byte length-prefixed string; returns it and the bytes after it
+pub(crate) fn read_str(data: &[u8]) -> Result<(&str, &[u8]), MqttError> {
+    let mut reader = Reader { data, offset: 0 };
+    let value = reader.str()?;
+    Ok((value, &data[reader.offset..]))
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], MqttError> {
+        let bytes = self.data.get(self.offset..self.offset + count).ok_or(MqttError::InvalidMessage)?;
+        self.offset += count;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, MqttError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, MqttError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, MqttError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn variable_int(&mut self) -> Result<u32, MqttError> {
+        let (value, used) = decode_variable_int(&self.data[self.offset..])?;
+        self.offset += used;
+        Ok(value)
+    }
+
+    fn binary(&mut self) -> Result<&'a [u8], MqttError> {
+        let length = self.u16()? as usize;
+        self.take(length)
+    }
+
+    fn str(&mut self) -> Result<&'a str, MqttError> {
+        core::str::from_utf8(self.binary()?).map_err(|_| MqttError::InvalidMessage)
+    }
+}
+
+/// Limits the server announced in CONNACK
+#[derive(Debug, Clone)]
+pub struct MqttServerLimits {
+    /// QoS 1 and 2 publishes the server accepts unacknowledged
+    pub receive_maximum: u16,
+    /// Topic aliases the client may assign
+    pub topic_alias_maximum: u16,
+    pub maximum_qos: u8,
+    pub retain_available: bool,
+    pub maximum_packet_size: Option<u32>,
+    pub server_keep_alive: Option<u16>,
+    /// Session expiry the server chose in place of the requested one
+    pub session_expiry_interval: Option<u32>,
+}
+
+impl Default for MqttServerLimits {
+    fn default() -> Self {
+        Self {
+            receive_maximum: MQTT_DEFAULT_RECEIVE_MAXIMUM,
+            topic_alias_maximum: 0,
+            maximum_qos: 2,
+            retain_available: true,
+            maximum_packet_size: None,
+            server_keep_alive: None,
+            session_expiry_interval: None,
+        }
+    }
+}
+
+impl MqttServerLimits {
+    /// Record a CONNACK property; others are ignored
+    pub fn apply(&mut self, property: &MqttProperty) {
+        match *property {
+            MqttProperty::ReceiveMaximum(value) => self.receive_maximum = value.max(1),
+            MqttProperty::TopicAliasMaximum(value) => self.topic_alias_maximum = value,
+            MqttProperty::MaximumQos(value) => self.maximum_qos = value,
+            MqttProperty::RetainAvailable(value) => self.retain_available = value != 0,
+            MqttProperty::MaximumPacketSize(value) => self.maximum_packet_size = Some(value),
+            MqttProperty::ServerKeepAlive(value) => self.server_keep_alive = Some(value),
+            MqttProperty::SessionExpiryInterval(value) => self.session_expiry_interval = Some(value),
+            _ => {}
+        }
+    }
+}
+
+/// Topic alias table of one direction of a network connection
+///
+/// Aliases never outlive the connection, so the table is cleared on every
+/// CONNACK.
+#[derive(Debug)]
+pub struct MqttTopicAliases {
+    entries: Vec<(u16, String<MQTT_MAX_TOPIC_LENGTH>), MQTT_MAX_TOPIC_ALIASES>,
+    maximum: u16,
+}
+
+impl MqttTopicAliases {
+    pub const fn new() -> Self {
+        Self { entries: Vec::new(), maximum: 0 }
+    }
+
+    /// Drop all aliases and allow up to `maximum` new ones
+    pub fn reset(&mut self, maximum: u16) {
+        self.entries.clear();
+        self.maximum = maximum.min(MQTT_MAX_TOPIC_ALIASES as u16);
+    }
+
+    pub fn maximum(&self) -> u16 {
+        self.maximum
+    }
+
+    pub fn lookup(&self, topic: &str) -> Option<u16> {
+        self.entries.iter().find(|(_, known)| known.as_str() == topic).map(|(alias, _)| *alias)
+    }
+
+    pub fn resolve(&self, alias: u16) -> Option<&str> {
+        self.entries.iter().find(|(known, _)| *known == alias).map(|(_, topic)| topic.as_str())
+    }
+
+    /// Give `topic` the next free alias, if any remain
+    pub fn assign(&mut self, topic: &str) -> Option<u16> {
+        let alias = self.entries.len() as u16 + 1;
+        if alias > self.maximum {
+            return None;
+        }
+        self.insert(alias, topic).ok()?;
+        Some(alias)
+    }
+
+    /// Bind `alias` to `topic`, replacing an earlier binding
+    pub fn insert(&mut self, alias: u16, topic: &str) -> Result<(), MqttError> {
+        if alias == 0 || alias > self.maximum {
+            return Err(MqttError::Refused(MqttReasonCode::TopicAliasInvalid));
+        }
+        let mut name = String::new();
+        name.push_str(topic).map_err(|_| MqttError::PacketTooLarge)?;
+        match self.entries.iter_mut().find(|(known, _)| *known == alias) {
+            Some(entry) => entry.1 = name,
+            None => self.entries.push((alias, name)).map_err(|_| MqttError::PacketTooLarge)?,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn properties(data: &[u8]) -> Result<MqttProperties<'_>, MqttError> {
+        MqttProperties::read(data).map(|(properties, _)| properties)
+    }
+
+    #[test]
+    fn test_variable_int_round_trip() {
+        let cases: [(u32, &[u8]); 6] = [
+            (0, &[0x00]),
+            (127, &[0x7F]),
+            (128, &[0x80, 0x01]),
+            (16_383, &[0xFF, 0x7F]),
+            (16_384, &[0x80, 0x80, 0x01]),
+            (0x0FFF_FFFF, &[0xFF, 0xFF, 0xFF, 0x7F]),
+        ];
+        for (value, bytes) in cases {
+            let mut out: Vec<u8, 4> = Vec::new();
+            encode_variable_int(value, &mut out).unwrap();
+            assert_eq!(&out[..], bytes);
+            assert_eq!(decode_variable_int(bytes).unwrap(), (value, bytes.len()));
+        }
+
+        let mut out: Vec<u8, 8> = Vec::new();
+        assert!(matches!(encode_variable_int(0x1000_0000, &mut out), Err(MqttError::PacketTooLarge)));
+    }
+
+    #[test]
+    fn test_malformed_variable_int() {
+        // A fifth continuation byte is not allowed
+        assert!(matches!(decode_variable_int(&[0xFF, 0xFF, 0xFF, 0xFF, 0x01]), Err(MqttError::InvalidMessage)));
+        assert!(matches!(decode_variable_int(&[0x80, 0x80, 0x80, 0x80]), Err(MqttError::InvalidMessage)));
+        // Truncated
+        assert!(matches!(decode_variable_int(&[0x80]), Err(MqttError::InvalidMessage)));
+        assert!(matches!(decode_variable_int(&[]), Err(MqttError::InvalidMessage)));
+
+        assert!(matches!(split_packet(&[0x30, 0xFF, 0xFF, 0xFF, 0xFF, 0x01]), Err(MqttError::InvalidMessage)));
+        assert!(matches!(split_packet(&[0x30]), Err(MqttError::InvalidMessage)));
+        assert!(matches!(split_packet(&[]), Err(MqttError::InvalidMessage)));
+        // Remaining length past the end of the packet
+        assert!(matches!(split_packet(&[0x30, 0x03, 0x00, 0x01]), Err(MqttError::InvalidMessage)));
+        assert_eq!(split_packet(&[0x30, 0x02, 0x00, 0x01, 0xAA]).unwrap(), (0x30, &[0x00, 0x01][..]));
+    }
+
+    #[test]
+    fn test_property_round_trip() {
+        let sent = [
+            MqttProperty::SessionExpiryInterval(3600),
+            MqttProperty::ReceiveMaximum(20),
+            MqttProperty::SubscriptionIdentifier(300),
+            MqttProperty::UserProperty("site", "lab-2"),
+            MqttProperty::CorrelationData(&[1, 2, 3]),
+        ];
+        let mut out: Vec<u8, 64> = Vec::new();
+        encode_properties(&sent, &mut out).unwrap();
+        put_bytes(&mut out, b"rest").unwrap();
+
+        let (received, rest) = MqttProperties::read(&out).unwrap();
+        let received: Vec<MqttProperty, 8> = received.map(Result::unwrap).collect();
+        assert_eq!(&received[..], &sent[..]);
+        assert_eq!(rest, b"rest");
+    }
+
+    #[test]
+    fn test_malformed_properties() {
+        // Property length longer than the packet
+        assert!(matches!(MqttProperties::read(&[0x05, 0x21, 0x00]), Err(MqttError::InvalidMessage)));
+        // Property length that is itself a malformed variable integer
+        assert!(matches!(MqttProperties::read(&[0xFF, 0xFF, 0xFF, 0xFF, 0x01]), Err(MqttError::InvalidMessage)));
+
+        let malformed: [&[u8]; 6] = [
+            // Unknown identifier
+            &[0x02, 0x7F, 0x00],
+            // Four-byte integer cut short
+            &[0x03, 0x11, 0x00, 0x00],
+            // String length past the end
+            &[0x04, 0x03, 0x00, 0x05, b'a'],
+            // Invalid UTF-8
+            &[0x04, 0x1F, 0x00, 0x01, 0xFF],
+            // Subscription identifier longer than four bytes
+            &[0x06, 0x0B, 0x80, 0x80, 0x80, 0x80, 0x01],
+            // User property missing its value
+            &[0x05, 0x26, 0x00, 0x01, b'k', 0x00],
+        ];
+        for data in malformed {
+            let mut iter = properties(data).unwrap();
+            assert!(matches!(iter.next(), Some(Err(MqttError::InvalidMessage))));
+            assert!(iter.next().is_none());
+        }
+    }
+}
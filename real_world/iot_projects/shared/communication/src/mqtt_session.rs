@@ -0,0 +1,220 @@
+//! MQTT QoS 1 and 2 session state
+//!
+//! `MqttSessionState` tracks the in-flight window of outbound publishes
+//! and the inbound QoS 2 packet identifiers awaiting PUBREL. Every change
+//! is mirrored to an optional `MqttSessionStore` so unacknowledged
+//! messages survive a reboot and are replayed when the session resumes.
+
+use heapless::Vec;
+use crate::{MqttError, MqttQos};
+
+/// Outbound QoS 1 and 2 publishes tracked at once
+pub const MQTT_MAX_INFLIGHT: usize = 8;
+/// Largest encoded packet kept for retransmission
+pub const MQTT_MAX_STORED_PACKET: usize = 264;
+
+/// Fixed header bits of the packets the state machine sends
+const PUBLISH_DUP_FLAG: u8 = 0x08;
+const PUBACK_HEADER: u8 = 0x40;
+const PUBREC_HEADER: u8 = 0x50;
+const PUBREL_HEADER: u8 = 0x62;
+const PUBCOMP_HEADER: u8 = 0x70;
+
+/// Millisecond clock used for retransmission timers
+pub trait MqttClock {
+    fn now_ms(&self) -> u64;
+}
+
+/// When unacknowledged packets are sent again
+///
+/// MQTT 3.1.1 connections resend after `interval_ms`; MQTT 5.0 forbids
+/// resending on a live connection, so there the timer only reports the
+/// stall and packets are replayed when the session resumes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MqttRetryPolicy {
+    pub interval_ms: u32,
+    /// Sends, including the first, before `Timeout` is reported
+    pub max_attempts: u8,
+}
+
+impl Default for MqttRetryPolicy {
+    fn default() -> Self {
+        Self { interval_ms: 10_000, max_attempts: 4 }
+    }
+}
+
+/// Acknowledgement an outbound message is waiting for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MqttInflightState {
+    /// QoS 1 PUBLISH sent
+    AwaitingPuback,
+    /// QoS 2 PUBLISH sent
+    AwaitingPubrec,
+    /// QoS 2 PUBREL sent
+    AwaitingPubcomp,
+}
+
+/// An outbound publish that has not completed its QoS flow
+#[derive(Debug, Clone)]
+pub struct MqttInflightMessage {
+    pub packet_id: u16,
+    pub qos: MqttQos,
+    pub state: MqttInflightState,
+    /// Packet to retransmit: the PUBLISH, or the PUBREL once PUBREC arrived
+    pub packet: Vec<u8, MQTT_MAX_STORED_PACKET>,
+    /// Clock time of the last send; not meaningful after a reboot
+    pub sent_at_ms: u64,
+    pub attempts: u8,
+}
+
+impl MqttInflightMessage {
+    /// Packet to send again, with DUP set on a repeated PUBLISH
+    pub fn retransmission(&self) -> Vec<u8, MQTT_MAX_STORED_PACKET> {
+        let mut packet = self.packet.clone();
+        if self.state != MqttInflightState::AwaitingPubcomp {
+            if let Some(header) = packet.first_mut() {
+                *header |= PUBLISH_DUP_FLAG;
+            }
+        }
+        packet
+    }
+}
+
+/// Persistence for session state
+///
+/// Implementations typically write records to flash. `load` is called
+/// once when the store is attached to a client.
+pub trait MqttSessionStore {
+    /// Insert or replace the record for `message.packet_id`
+    fn store_outbound(&mut self, message: &MqttInflightMessage) -> Result<(), MqttError>;
+    fn remove_outbound(&mut self, packet_id: u16);
+    /// Remember an inbound QoS 2 publish delivered but not yet released
+    fn store_incoming(&mut self, packet_id: u16) -> Result<(), MqttError>;
+    fn remove_incoming(&mut self, packet_id: u16);
+    /// Restore all records into `session`, oldest outbound first
+    fn load(&self, session: &mut MqttSessionState);
+    fn clear(&mut self);
+}
+
+/// RAM-backed store, for tests and targets that only need to survive reconnects
+pub struct MqttMemoryStore {
+    outbound: Vec<MqttInflightMessage, MQTT_MAX_INFLIGHT>,
+    incoming: Vec<u16, MQTT_MAX_INFLIGHT>,
+}
+
+impl MqttMemoryStore {
+    pub const fn new() -> Self {
+        Self { outbound: Vec::new(), incoming: Vec::new() }
+    }
+}
+
+impl MqttSessionStore for MqttMemoryStore {
+    fn store_outbound(&mut self, message: &MqttInflightMessage) -> Result<(), MqttError> {
+        match self.outbound.iter_mut().find(|m| m.packet_id == message.packet_id) {
+            Some(existing) => *existing = message.clone(),
+            None => self.outbound.push(message.clone()).map_err(|_| MqttError::QuotaExceeded)?,
+        }
+        Ok(())
+    }
+
+    fn remove_outbound(&mut self, packet_id: u16) {
+        if let Some(pos) = self.outbound.iter().position(|m| m.packet_id == packet_id) {
+            self.outbound.remove(pos);
+        }
+    }
+
+    fn store_incoming(&mut self, packet_id: u16) -> Result<(), MqttError> {
+        if !self.incoming.contains(&packet_id) {
+            self.incoming.push(packet_id).map_err(|_| MqttError::QuotaExceeded)?;
+        }
+        Ok(())
+    }
+
+    fn remove_incoming(&mut self, packet_id: u16) {
+        if let Some(pos) = self.incoming.iter().position(|id| *id == packet_id) {
+            self.incoming.remove(pos);
+        }
+    }
+
+    fn load(&self, session: &mut MqttSessionState) {
+        session.outbound = self.outbound.clone();
+        session.incoming = self.incoming.clone();
+    }
+
+    fn clear(&mut self) {
+        self.outbound.clear();
+        self.incoming.clear();
+    }
+}
+
+/// In-flight window of one client session
+#[derive(Debug, Clone)]
+pub struct MqttSessionState {
+    pub outbound: Vec<MqttInflightMessage, MQTT_MAX_INFLIGHT>,
+    pub incoming: Vec<u16, MQTT_MAX_INFLIGHT>,
+}
+
+impl MqttSessionState {
+    pub const fn new() -> Self {
+        Self { outbound: Vec::new(), incoming: Vec::new() }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.outbound.is_empty() && self.incoming.is_empty()
+    }
+
+    pub fn inflight(&self) -> usize {
+        self.outbound.len()
+    }
+
+    pub fn outbound_mut(&mut self, packet_id: u16) -> Option<&mut MqttInflightMessage> {
+        self.outbound.iter_mut().find(|m| m.packet_id == packet_id)
+    }
+
+    pub fn remove_outbound(&mut self, packet_id: u16) -> Option<MqttInflightMessage> {
+        let pos = self.outbound.iter().position(|m| m.packet_id == packet_id)?;
+        Some(self.outbound.remove(pos))
+    }
+
+    pub fn is_packet_id_used(&self, packet_id: u16) -> bool {
+        self.outbound.iter().any(|m| m.packet_id == packet_id)
+    }
+}
+
+/// Two-byte packet identifier of an acknowledgement packet body
+pub(crate) fn ack_packet_id(body: &[u8]) -> Result<u16, MqttError> {
+    match body {
+        [high, low, ..] => Ok(u16::from_be_bytes([*high, *low])),
+        _ => Err(MqttError::InvalidMessage),
+    }
+}
+
+/// Encode PUBACK, PUBREC, PUBREL or PUBCOMP
+///
+/// A reason code is only written when it is not Success, which is the
+/// short form both protocol versions accept.
+pub(crate) fn encode_ack(header: u8, packet_id: u16, reason: Option<u8>) -> Vec<u8, 5> {
+    let mut packet = Vec::new();
+    let id = packet_id.to_be_bytes();
+    let _ = match reason {
+        Some(code) if code != 0 => packet.extend_from_slice(&[header, 3, id[0], id[1], code]),
+        _ => packet.extend_from_slice(&[header, 2, id[0], id[1]]),
+    };
+    packet
+}
+
+pub(crate) fn encode_puback(packet_id: u16, reason: Option<u8>) -> Vec<u8, 5> {
+    encode_ack(PUBACK_HEADER, packet_id, reason)
+}
+
+pub(crate) fn encode_pubrec(packet_id: u16, reason: Option<u8>) -> Vec<u8, 5> {
+    encode_ack(PUBREC_HEADER, packet_id, reason)
+}
+
+pub(crate) fn encode_pubrel(packet_id: u16, reason: Option<u8>) -> Vec<u8, 5> {
+    encode_ack(PUBREL_HEADER, packet_id, reason)
+}
+
+pub(crate) fn encode_pubcomp(packet_id: u16, reason: Option<u8>) -> Vec<u8, 5> {
+    encode_ack(PUBCOMP_HEADER, packet_id, reason)
+}
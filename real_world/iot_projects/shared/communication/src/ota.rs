@@ -0,0 +1,787 @@
+//! Over-the-air firmware updates
+//!
+//! Images are downloaded in chunks into the inactive slot of an A/B flash
+//! layout, over MQTT request/response topics or CoAP block-wise transfer.
+//! Progress is persisted after every chunk so an interrupted download
+//! resumes where it stopped. A finished image is accepted only when its
+//! SHA-256 matches the manifest and the manifest signature verifies; it is
+//! then booted on trial and rolled back unless the application confirms it
+//! after a successful health check.
+
+use heapless::Vec;
+use crate::{MqttClient, MqttClock, MqttError, MqttQos, MqttTransport};
+
+/// Bytes requested per chunk; fits one transport frame with its headers
+pub const OTA_CHUNK_SIZE: usize = 128;
+/// Largest manifest signature (DER-encoded ECDSA P-256)
+pub const OTA_MAX_SIGNATURE: usize = 72;
+/// Boots a new image gets before it is rolled back unconfirmed
+pub const OTA_TRIAL_BOOTS: u8 = 3;
+
+/// One of the two firmware slots
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OtaSlot {
+    A,
+    B,
+}
+
+impl OtaSlot {
+    pub fn other(self) -> Self {
+        match self {
+            OtaSlot::A => OtaSlot::B,
+            OtaSlot::B => OtaSlot::A,
+        }
+    }
+
+    fn index(self) -> usize {
+        match self {
+            OtaSlot::A => 0,
+            OtaSlot::B => 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OtaSlotState {
+    Empty,
+    /// Partially written image
+    Downloading,
+    /// Verified image waiting to be booted on trial
+    Pending,
+    /// Image that passed its health check
+    Confirmed,
+    /// Image that failed verification or its health check
+    Invalid,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OtaSlotInfo {
+    pub state: OtaSlotState,
+    pub version: u32,
+    pub size: u32,
+}
+
+/// Signed description of a firmware image
+#[derive(Debug, Clone, PartialEq)]
+pub struct OtaManifest {
+    pub version: u32,
+    pub size: u32,
+    /// SHA-256 of the image
+    pub digest: [u8; 32],
+    /// Signature over `signed_digest()`
+    pub signature: Vec<u8, OTA_MAX_SIGNATURE>,
+}
+
+impl OtaManifest {
+    /// Parse the wire form: version and size as big-endian u32, the image
+    /// digest, a signature length byte and the signature
+    pub fn parse(data: &[u8]) -> Result<Self, OtaError> {
+        if data.len() < 41 {
+            return Err(OtaError::InvalidManifest);
+        }
+        let version = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+        let size = u32::from_be_bytes([data[4], data[5], data[6], data[7]]);
+        let mut digest = [0u8; 32];
+        digest.copy_from_slice(&data[8..40]);
+        let signature_len = data[40] as usize;
+        let signature = data.get(41..41 + signature_len).ok_or(OtaError::InvalidManifest)?;
+        Ok(Self {
+            version,
+            size,
+            digest,
+            signature: Vec::from_slice(signature).map_err(|_| OtaError::InvalidManifest)?,
+        })
+    }
+
+    /// Digest the signature covers; binding version and size stops an old
+    /// signed image being replayed as a newer one
+    pub fn signed_digest(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(&self.version.to_be_bytes());
+        hasher.update(&self.size.to_be_bytes());
+        hasher.update(&self.digest);
+        hasher.finalize()
+    }
+}
+
+/// A download in progress, persisted so it can resume after a reset
+#[derive(Debug, Clone, PartialEq)]
+pub struct OtaDownload {
+    pub manifest: OtaManifest,
+    /// Bytes already programmed into the inactive slot
+    pub written: u32,
+}
+
+/// Boot control record shared with the bootloader
+#[derive(Debug, Clone, PartialEq)]
+pub struct OtaBootState {
+    /// Slot holding the last confirmed image
+    pub active: OtaSlot,
+    /// Slot the bootloader started this time
+    pub booted: OtaSlot,
+    pub slots: [OtaSlotInfo; 2],
+    /// Trial boots left for a pending image
+    pub trial_boots_left: u8,
+    pub download: Option<OtaDownload>,
+}
+
+impl OtaBootState {
+    /// Factory state: confirmed image in slot A, slot B empty
+    pub fn factory(version: u32) -> Self {
+        Self {
+            active: OtaSlot::A,
+            booted: OtaSlot::A,
+            slots: [
+                OtaSlotInfo { state: OtaSlotState::Confirmed, version, size: 0 },
+                OtaSlotInfo { state: OtaSlotState::Empty, version: 0, size: 0 },
+            ],
+            trial_boots_left: 0,
+            download: None,
+        }
+    }
+
+    pub fn slot(&self, slot: OtaSlot) -> &OtaSlotInfo {
+        &self.slots[slot.index()]
+    }
+
+    fn slot_mut(&mut self, slot: OtaSlot) -> &mut OtaSlotInfo {
+        &mut self.slots[slot.index()]
+    }
+
+    /// Pick the slot to boot, for use by the bootloader
+    ///
+    /// A pending image is booted while it has trial boots left; once they
+    /// run out it is marked invalid and the confirmed image boots again.
+    /// The caller persists the state before jumping to the image.
+    pub fn select_boot_slot(&mut self) -> OtaSlot {
+        let candidate = self.active.other();
+        self.booted = self.active;
+        if self.slot(candidate).state == OtaSlotState::Pending {
+            if self.trial_boots_left > 0 {
+                self.trial_boots_left -= 1;
+                self.booted = candidate;
+            } else {
+                self.slot_mut(candidate).state = OtaSlotState::Invalid;
+            }
+        }
+        self.booted
+    }
+}
+
+/// Dual-bank flash holding the firmware slots and the boot state
+pub trait OtaFlash {
+    /// Bytes available to an image in each slot
+    fn slot_size(&self) -> u32;
+    fn erase(&mut self, slot: OtaSlot) -> Result<(), OtaError>;
+    /// Program `data` at `offset`, which is always a multiple of `OTA_CHUNK_SIZE`
+    fn write(&mut self, slot: OtaSlot, offset: u32, data: &[u8]) -> Result<(), OtaError>;
+    fn read(&self, slot: OtaSlot, offset: u32, buf: &mut [u8]) -> Result<(), OtaError>;
+    fn load_state(&self) -> Option<OtaBootState>;
+    fn store_state(&mut self, state: &OtaBootState) -> Result<(), OtaError>;
+}
+
+/// Manifest signature check, usually backed by a crypto accelerator
+pub trait OtaVerifier {
+    fn verify(&self, digest: &[u8; 32], signature: &[u8]) -> bool;
+}
+
+/// Where image chunks come from
+pub trait OtaSource {
+    /// Fill `buf` with image bytes starting at `offset`, returning how many
+    /// were read
+    fn fetch(&mut self, offset: u32, buf: &mut [u8]) -> Result<usize, OtaError>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OtaStatus {
+    InProgress { written: u32, size: u32 },
+    /// Image verified; reset to boot it on trial
+    Ready,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OtaBootOutcome {
+    /// Running the confirmed image, nothing to do
+    Running,
+    /// The trial image passed and is now the active one
+    Confirmed,
+    /// The trial image failed; reset to return to the previous image
+    RollbackRequired,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OtaError {
+    Flash,
+    Transport,
+    Timeout,
+    InvalidManifest,
+    /// Image is not newer than the running firmware
+    VersionRejected,
+    ImageTooLarge,
+    DigestMismatch,
+    SignatureInvalid,
+    /// No download in progress
+    NotStarted,
+    /// A trial image has not been confirmed yet
+    UpdatePending,
+    /// Source returned a chunk for another offset or an error status
+    UnexpectedResponse,
+}
+
+impl From<MqttError> for OtaError {
+    fn from(err: MqttError) -> Self {
+        match err {
+            MqttError::Timeout => OtaError::Timeout,
+            _ => OtaError::Transport,
+        }
+    }
+}
+
+/// Downloads, verifies and activates firmware images
+pub struct OtaUpdater<F: OtaFlash, V: OtaVerifier> {
+    flash: F,
+    verifier: V,
+    state: OtaBootState,
+}
+
+impl<F: OtaFlash, V: OtaVerifier> OtaUpdater<F, V> {
+    /// Load the boot state, or start from the factory image `version`
+    pub fn new(flash: F, verifier: V, version: u32) -> Self {
+        let state = flash.load_state().unwrap_or_else(|| OtaBootState::factory(version));
+        Self { flash, verifier, state }
+    }
+
+    pub fn state(&self) -> &OtaBootState {
+        &self.state
+    }
+
+    pub fn flash(&mut self) -> &mut F {
+        &mut self.flash
+    }
+
+    /// Version of the image currently executing
+    pub fn running_version(&self) -> u32 {
+        self.state.slot(self.state.booted).version
+    }
+
+    /// Bytes written and total size of the current download
+    pub fn progress(&self) -> Option<(u32, u32)> {
+        self.state.download.as_ref().map(|d| (d.written, d.manifest.size))
+    }
+
+    /// Start downloading `manifest`'s image, or resume it if the same
+    /// manifest was interrupted
+    pub fn begin(&mut self, manifest: OtaManifest) -> Result<(), OtaError> {
+        if self.state.booted != self.state.active {
+            return Err(OtaError::UpdatePending);
+        }
+        if self.state.download.as_ref().is_some_and(|d| d.manifest == manifest) {
+            return Ok(());
+        }
+        if manifest.version <= self.running_version() {
+            return Err(OtaError::VersionRejected);
+        }
+        if manifest.size == 0 || manifest.size > self.flash.slot_size() {
+            return Err(OtaError::ImageTooLarge);
+        }
+
+        let target = self.state.active.other();
+        self.flash.erase(target)?;
+        *self.state.slot_mut(target) = OtaSlotInfo {
+            state: OtaSlotState::Downloading,
+            version: manifest.version,
+            size: manifest.size,
+        };
+        self.state.download = Some(OtaDownload { manifest, written: 0 });
+        self.flash.store_state(&self.state)
+    }
+
+    /// Drop the current download
+    pub fn abort(&mut self) -> Result<(), OtaError> {
+        if self.state.download.take().is_some() {
+            self.state.slot_mut(self.state.active.other()).state = OtaSlotState::Invalid;
+            self.flash.store_state(&self.state)?;
+        }
+        Ok(())
+    }
+
+    /// Fetch and program the next chunk, verifying once the image is complete
+    pub fn step(&mut self, source: &mut dyn OtaSource) -> Result<OtaStatus, OtaError> {
+        let download = self.state.download.as_ref().ok_or(OtaError::NotStarted)?;
+        let (written, size) = (download.written, download.manifest.size);
+        let target = self.state.active.other();
+
+        if written < size {
+            let mut chunk = [0u8; OTA_CHUNK_SIZE];
+            let wanted = ((size - written) as usize).min(OTA_CHUNK_SIZE);
+            let len = source.fetch(written, &mut chunk[..wanted])?;
+            if len == 0 || len > wanted {
+                return Err(OtaError::UnexpectedResponse);
+            }
+            self.flash.write(target, written, &chunk[..len])?;
+            if let Some(download) = self.state.download.as_mut() {
+                download.written += len as u32;
+            }
+            self.flash.store_state(&self.state)?;
+        }
+
+        match self.progress() {
+            Some((written, size)) if written < size => Ok(OtaStatus::InProgress { written, size }),
+            _ => self.finish(),
+        }
+    }
+
+    /// Run `step()` until the image is ready
+    pub fn download(&mut self, source: &mut dyn OtaSource) -> Result<(), OtaError> {
+        while let OtaStatus::InProgress { .. } = self.step(source)? {}
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<OtaStatus, OtaError> {
+        let download = self.state.download.take().ok_or(OtaError::NotStarted)?;
+        let target = self.state.active.other();
+        let result = self.verify_image(target, &download.manifest);
+        if result.is_ok() {
+            self.state.slot_mut(target).state = OtaSlotState::Pending;
+            self.state.trial_boots_left = OTA_TRIAL_BOOTS;
+        } else {
+            self.state.slot_mut(target).state = OtaSlotState::Invalid;
+        }
+        self.flash.store_state(&self.state)?;
+        result.map(|_| OtaStatus::Ready)
+    }
+
+    /// Hash the image as programmed, so flash write errors are caught too
+    fn verify_image(&self, slot: OtaSlot, manifest: &OtaManifest) -> Result<(), OtaError> {
+        let mut hasher = Sha256::new();
+        let mut buf = [0u8; OTA_CHUNK_SIZE];
+        let mut offset = 0;
+        while offset < manifest.size {
+            let len = ((manifest.size - offset) as usize).min(OTA_CHUNK_SIZE);
+            self.flash.read(slot, offset, &mut buf[..len])?;
+            hasher.update(&buf[..len]);
+            offset += len as u32;
+        }
+        if hasher.finalize() != manifest.digest {
+            return Err(OtaError::DigestMismatch);
+        }
+        if !self.verifier.verify(&manifest.signed_digest(), &manifest.signature) {
+            return Err(OtaError::SignatureInvalid);
+        }
+        Ok(())
+    }
+
+    /// Report the result of the application's health check after boot
+    ///
+    /// A healthy trial image becomes the active one. An unhealthy one is
+    /// marked invalid so the bootloader returns to the previous image on
+    /// the next reset.
+    pub fn confirm_boot(&mut self, healthy: bool) -> Result<OtaBootOutcome, OtaError> {
+        let booted = self.state.booted;
+        if booted == self.state.active {
+            return Ok(OtaBootOutcome::Running);
+        }
+        let outcome = if healthy {
+            self.state.slot_mut(booted).state = OtaSlotState::Confirmed;
+            self.state.active = booted;
+            OtaBootOutcome::Confirmed
+        } else {
+            self.state.slot_mut(booted).state = OtaSlotState::Invalid;
+            self.state.booted = self.state.active;
+            OtaBootOutcome::RollbackRequired
+        };
+        self.state.trial_boots_left = 0;
+        self.flash.store_state(&self.state)?;
+        Ok(outcome)
+    }
+}
+
+/// Chunks requested over MQTT
+///
+/// Each request publishes the offset (big-endian u32) and length
+/// (big-endian u16) to `request_topic`; the server answers on
+/// `chunk_topic` with the offset followed by the data. The caller
+/// subscribes to `chunk_topic` beforehand.
+pub struct MqttOtaSource<'c, 'a> {
+    client: &'c mut MqttClient<'a>,
+    clock: &'c dyn MqttClock,
+    request_topic: &'c str,
+    chunk_topic: &'c str,
+    timeout_ms: u32,
+}
+
+impl<'c, 'a> MqttOtaSource<'c, 'a> {
+    pub fn new(client: &'c mut MqttClient<'a>, clock: &'c dyn MqttClock, request_topic: &'c str, chunk_topic: &'c str) -> Self {
+        Self { client, clock, request_topic, chunk_topic, timeout_ms: 5000 }
+    }
+
+    pub fn with_timeout(mut self, timeout_ms: u32) -> Self {
+        self.timeout_ms = timeout_ms;
+        self
+    }
+}
+
+impl OtaSource for MqttOtaSource<'_, '_> {
+    fn fetch(&mut self, offset: u32, buf: &mut [u8]) -> Result<usize, OtaError> {
+        let mut request = [0u8; 6];
+        request[..4].copy_from_slice(&offset.to_be_bytes());
+        request[4..].copy_from_slice(&(buf.len() as u16).to_be_bytes());
+        self.client.publish(self.request_topic, &request, MqttQos::AtLeastOnce)?;
+
+        let started = self.clock.now_ms();
+        loop {
+            self.client.process_messages()?;
+            if let Some(message) = self.client.take_message() {
+                let on_topic = message.topic.as_ref().is_some_and(|t| t.as_str() == self.chunk_topic);
+                if let (true, [a, b, c, d, data @ ..]) = (on_topic, &message.payload[..]) {
+                    if u32::from_be_bytes([*a, *b, *c, *d]) == offset {
+                        let len = data.len().min(buf.len());
+                        buf[..len].copy_from_slice(&data[..len]);
+                        return Ok(len);
+                    }
+                }
+            }
+            if self.clock.now_ms().saturating_sub(started) >= self.timeout_ms as u64 {
+                return Err(OtaError::Timeout);
+            }
+        }
+    }
+}
+
+/// CoAP option numbers and codes used by the block-wise client
+const COAP_OPTION_URI_PATH: u16 = 11;
+const COAP_OPTION_BLOCK2: u16 = 23;
+const COAP_CODE_GET: u8 = 0x01;
+const COAP_CODE_CONTENT: u8 = 0x45;
+const COAP_TYPE_CON: u8 = 0;
+const COAP_TYPE_ACK: u8 = 2;
+/// Block size exponent for `OTA_CHUNK_SIZE` (2^(4+3) bytes)
+const COAP_BLOCK_SZX: u32 = 3;
+const COAP_MAX_RETRANSMIT: u8 = 4;
+
+/// Chunks fetched with CoAP block-wise GET (RFC 7959 Block2)
+///
+/// Runs over any datagram transport, typically a UDP socket on the WiFi
+/// module. Confirmable requests are retransmitted with exponential
+/// backoff; both piggybacked and separate responses are accepted.
+pub struct CoapOtaSource<'a> {
+    transport: &'a dyn MqttTransport,
+    /// Resource path, e.g. "fw/image"
+    path: &'a str,
+    message_id: u16,
+    token: u16,
+    ack_timeout_ms: u32,
+}
+
+impl<'a> CoapOtaSource<'a> {
+    pub fn new(transport: &'a dyn MqttTransport, path: &'a str) -> Self {
+        Self { transport, path, message_id: 1, token: 1, ack_timeout_ms: 2000 }
+    }
+
+    fn encode_request(&self, block: u32) -> Result<Vec<u8, 96>, OtaError> {
+        let mut packet: Vec<u8, 96> = Vec::new();
+        let id = self.message_id.to_be_bytes();
+        let token = self.token.to_be_bytes();
+        packet
+            .extend_from_slice(&[0x40 | (COAP_TYPE_CON << 4) | 2, COAP_CODE_GET, id[0], id[1], token[0], token[1]])
+            .map_err(|_| OtaError::Transport)?;
+
+        let mut last = 0;
+        for segment in self.path.split('/').filter(|s| !s.is_empty()) {
+            coap_option(&mut packet, COAP_OPTION_URI_PATH - last, segment.as_bytes())?;
+            last = COAP_OPTION_URI_PATH;
+        }
+        let value = block << 4 | COAP_BLOCK_SZX;
+        let bytes = value.to_be_bytes();
+        let skip = bytes.iter().take(3).take_while(|b| **b == 0).count();
+        coap_option(&mut packet, COAP_OPTION_BLOCK2 - last, &bytes[skip..])?;
+        Ok(packet)
+    }
+
+    /// Wait for the response to the outstanding request
+    fn await_response(&self, block: u32, timeout_ms: u32, buf: &mut [u8]) -> Result<Option<usize>, OtaError> {
+        while let Some(datagram) = self.transport.receive(timeout_ms)? {
+            let message = match CoapMessage::parse(&datagram) {
+                Some(message) => message,
+                None => continue,
+            };
+            if message.kind == COAP_TYPE_ACK && message.code == 0 && message.message_id == self.message_id {
+                // Empty ACK: the response follows separately
+                continue;
+            }
+            if message.token != self.token.to_be_bytes() {
+                continue;
+            }
+            if message.kind == COAP_TYPE_CON {
+                let id = message.message_id.to_be_bytes();
+                self.transport.send(&[0x40 | (COAP_TYPE_ACK << 4), 0, id[0], id[1]])?;
+            }
+            if message.code != COAP_CODE_CONTENT || message.block2.map(|b| b >> 4) != Some(block) {
+                return Err(OtaError::UnexpectedResponse);
+            }
+            let len = message.payload.len().min(buf.len());
+            buf[..len].copy_from_slice(&message.payload[..len]);
+            return Ok(Some(len));
+        }
+        Ok(None)
+    }
+}
+
+impl OtaSource for CoapOtaSource<'_> {
+    fn fetch(&mut self, offset: u32, buf: &mut [u8]) -> Result<usize, OtaError> {
+        if offset % OTA_CHUNK_SIZE as u32 != 0 {
+            return Err(OtaError::UnexpectedResponse);
+        }
+        let block = offset / OTA_CHUNK_SIZE as u32;
+        self.message_id = self.message_id.wrapping_add(1);
+        self.token = self.token.wrapping_add(1);
+        let request = self.encode_request(block)?;
+
+        let mut timeout = self.ack_timeout_ms;
+        for _ in 0..=COAP_MAX_RETRANSMIT {
+            self.transport.send(&request)?;
+            if let Some(len) = self.await_response(block, timeout, buf)? {
+                return Ok(len);
+            }
+            timeout = timeout.saturating_mul(2);
+        }
+        Err(OtaError::Timeout)
+    }
+}
+
+/// Append one option with delta/length nibbles and their extensions
+fn coap_option<const N: usize>(packet: &mut Vec<u8, N>, delta: u16, value: &[u8]) -> Result<(), OtaError> {
+    fn nibble(n: usize) -> (u8, Vec<u8, 2>) {
+        let mut ext = Vec::new();
+        let code = match n {
+            0..=12 => n as u8,
+            13..=268 => {
+                let _ = ext.push((n - 13) as u8);
+                13
+            }
+            _ => {
+                let _ = ext.extend_from_slice(&((n - 269) as u16).to_be_bytes());
+                14
+            }
+        };
+        (code, ext)
+    }
+    let (delta_code, delta_ext) = nibble(delta as usize);
+    let (len_code, len_ext) = nibble(value.len());
+    packet.push(delta_code << 4 | len_code).map_err(|_| OtaError::Transport)?;
+    packet.extend_from_slice(&delta_ext).map_err(|_| OtaError::Transport)?;
+    packet.extend_from_slice(&len_ext).map_err(|_| OtaError::Transport)?;
+    packet.extend_from_slice(value).map_err(|_| OtaError::Transport)
+}
+
+/// The parts of a CoAP response the block-wise client needs
+struct CoapMessage<'m> {
+    kind: u8,
+    code: u8,
+    message_id: u16,
+    token: &'m [u8],
+    block2: Option<u32>,
+    payload: &'m [u8],
+}
+
+impl<'m> CoapMessage<'m> {
+    fn parse(data: &'m [u8]) -> Option<Self> {
+        let (&[first, code, id_high, id_low], rest) = (data.get(..4)?, &data[4..]) else {
+            return None;
+        };
+        if first >> 6 != 1 {
+            return None;
+        }
+        let token_len = (first & 0x0F) as usize;
+        let token = rest.get(..token_len)?;
+        let mut rest = &rest[token_len..];
+
+        let mut number = 0u16;
+        let mut block2 = None;
+        let mut payload: &[u8] = &[];
+        while let Some((&byte, tail)) = rest.split_first() {
+            if byte == 0xFF {
+                payload = tail;
+                break;
+            }
+            rest = tail;
+            let delta = coap_extended(byte >> 4, &mut rest)?;
+            let len = coap_extended(byte & 0x0F, &mut rest)? as usize;
+            let value = rest.get(..len)?;
+            rest = &rest[len..];
+            number = number.checked_add(delta)?;
+            if number == COAP_OPTION_BLOCK2 && len <= 3 {
+                block2 = Some(value.iter().fold(0u32, |acc, b| acc << 8 | *b as u32));
+            }
+        }
+
+        Some(Self {
+            kind: (first >> 4) & 0x03,
+            code,
+            message_id: u16::from_be_bytes([id_high, id_low]),
+            token,
+            block2,
+            payload,
+        })
+    }
+}
+
+/// Decode an option delta or length nibble, consuming its extension bytes
+fn coap_extended(nibble: u8, rest: &mut &[u8]) -> Option<u16> {
+    match nibble {
+        0..=12 => Some(nibble as u16),
+        13 => {
+            let (&b, tail) = rest.split_first()?;
+            *rest = tail;
+            Some(b as u16 + 13)
+        }
+        14 => {
+            let bytes = rest.get(..2)?;
+            let value = u16::from_be_bytes([bytes[0], bytes[1]]).checked_add(269)?;
+            *rest = &rest[2..];
+            Some(value)
+        }
+        _ => None,
+    }
+}
+
+/// Software SHA-256 for image and manifest digests
+pub struct Sha256 {
+    state: [u32; 8],
+    block: [u8; 64],
+    block_len: usize,
+    total_len: u64,
+}
+
+const SHA256_K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+impl Sha256 {
+    pub fn new() -> Self {
+        Self {
+            state: [
+                0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+            ],
+            block: [0; 64],
+            block_len: 0,
+            total_len: 0,
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.total_len += data.len() as u64;
+        while !data.is_empty() {
+            let take = (64 - self.block_len).min(data.len());
+            self.block[self.block_len..self.block_len + take].copy_from_slice(&data[..take]);
+            self.block_len += take;
+            data = &data[take..];
+            if self.block_len == 64 {
+                self.compress();
+                self.block_len = 0;
+            }
+        }
+    }
+
+    pub fn finalize(mut self) -> [u8; 32] {
+        let bit_len = self.total_len * 8;
+        self.block[self.block_len] = 0x80;
+        self.block[self.block_len + 1..].fill(0);
+        if self.block_len >= 56 {
+            self.compress();
+            self.block.fill(0);
+        }
+        self.block[56..].copy_from_slice(&bit_len.to_be_bytes());
+        self.compress();
+
+        let mut digest = [0u8; 32];
+        for (out, word) in digest.chunks_exact_mut(4).zip(self.state.iter()) {
+            out.copy_from_slice(&word.to_be_bytes());
+        }
+        digest
+    }
+
+    fn compress(&mut self) {
+        let mut w = [0u32; 64];
+        for (i, word) in self.block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h.wrapping_add(s1).wrapping_add(ch).wrapping_add(SHA256_K[i]).wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (state, value) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sha256(data: &[u8]) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(data);
+        hasher.finalize()
+    }
+
+    #[test]
+    fn test_sha256_empty_message() {
+        assert_eq!(sha256(b""), [
+            0xe3, 0xb0, 0xc4, 0x42, 0x98, 0xfc, 0x1c, 0x14, 0x9a, 0xfb, 0xf4, 0xc8, 0x99, 0x6f, 0xb9, 0x24,
+            0x27, 0xae, 0x41, 0xe4, 0x64, 0x9b, 0x93, 0x4c, 0xa4, 0x95, 0x99, 0x1b, 0x78, 0x52, 0xb8, 0x55,
+        ]);
+    }
+
+    #[test]
+    fn test_sha256_one_block_message() {
+        assert_eq!(sha256(b"abc"), [
+            0xba, 0x78, 0x16, 0xbf, 0x8f, 0x01, 0xcf, 0xea, 0x41, 0x41, 0x40, 0xde, 0x5d, 0xae, 0x22, 0x23,
+            0xb0, 0x03, 0x61, 0xa3, 0x96, 0x17, 0x7a, 0x9c, 0xb4, 0x10, 0xff, 0x61, 0xf2, 0x00, 0x15, 0xad,
+        ]);
+    }
+
+    #[test]
+    fn test_sha256_two_block_message() {
+        // 448 bits leaves no room for the length, so padding spills into a second block
+        let message = b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq";
+        let expected = [
+            0x24, 0x8d, 0x6a, 0x61, 0xd2, 0x06, 0x38, 0xb8, 0xe5, 0xc0, 0x26, 0x93, 0x0c, 0x3e, 0x60, 0x39,
+            0xa3, 0x3c, 0xe4, 0x59, 0x64, 0xff, 0x21, 0x67, 0xf6, 0xec, 0xed, 0xd4, 0x19, 0xdb, 0x06, 0xc1,
+        ];
+        assert_eq!(sha256(message), expected);
+
+        let mut hasher = Sha256::new();
+        for chunk in message.chunks(7) {
+            hasher.update(chunk);
+        }
+        assert_eq!(hasher.finalize(), expected);
+    }
+}
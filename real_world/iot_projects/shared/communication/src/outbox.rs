@@ -0,0 +1,102 @@
+//! Store-and-forward telemetry outbox
+//!
+//! `TelemetryOutbox` queues messages while every transport is down and
+//! hands them back oldest first once a link returns. The queue is bounded:
+//! when it is full the oldest message is dropped, since fresh telemetry is
+//! worth more than stale. An `OutboxStore` mirrors the queue to flash so
+//! queued messages survive a reboot.
+
+use heapless::Vec;
+use crate::CommunicationError;
+
+/// Messages held while offline
+pub const OUTBOX_CAPACITY: usize = 16;
+/// Largest queued payload
+pub const OUTBOX_MAX_MESSAGE: usize = 200;
+
+/// One queued message
+#[derive(Debug, Clone)]
+pub struct OutboxMessage {
+    /// Monotonic sequence number, used by stores to acknowledge records
+    pub sequence: u32,
+    pub payload: Vec<u8, OUTBOX_MAX_MESSAGE>,
+}
+
+/// Persistence for the outbox
+pub trait OutboxStore {
+    fn append(&mut self, message: &OutboxMessage) -> Result<(), CommunicationError>;
+    /// Forget every message up to and including `sequence`
+    fn acknowledge(&mut self, sequence: u32);
+    /// Restore persisted messages into `outbox`, oldest first
+    fn load(&self, outbox: &mut TelemetryOutbox);
+}
+
+/// Bounded FIFO of telemetry awaiting a link
+pub struct TelemetryOutbox {
+    messages: Vec<OutboxMessage, OUTBOX_CAPACITY>,
+    next_sequence: u32,
+    dropped: u32,
+}
+
+impl TelemetryOutbox {
+    pub const fn new() -> Self {
+        Self {
+            messages: Vec::new(),
+            next_sequence: 0,
+            dropped: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    /// Messages discarded because the outbox was full
+    pub fn dropped(&self) -> u32 {
+        self.dropped
+    }
+
+    /// Queue `payload`, evicting the oldest message when full
+    ///
+    /// Returns the queued message and the sequence number of the evicted
+    /// one, if any.
+    pub fn push(&mut self, payload: &[u8]) -> Result<(&OutboxMessage, Option<u32>), CommunicationError> {
+        let payload = Vec::from_slice(payload).map_err(|_| CommunicationError::InvalidData)?;
+        let evicted = if self.messages.is_full() {
+            self.dropped = self.dropped.wrapping_add(1);
+            Some(self.messages.remove(0).sequence)
+        } else {
+            None
+        };
+        let sequence = self.next_sequence;
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+        let _ = self.messages.push(OutboxMessage { sequence, payload });
+        let queued = self.messages.last().ok_or(CommunicationError::InvalidData)?;
+        Ok((queued, evicted))
+    }
+
+    /// Re-insert a restored message, keeping sequence numbers increasing
+    pub fn restore(&mut self, message: OutboxMessage) {
+        if self.messages.is_full() {
+            self.messages.remove(0);
+        }
+        self.next_sequence = self.next_sequence.max(message.sequence.wrapping_add(1));
+        let _ = self.messages.push(message);
+    }
+
+    pub fn front(&self) -> Option<&OutboxMessage> {
+        self.messages.first()
+    }
+
+    pub fn pop_front(&mut self) -> Option<OutboxMessage> {
+        if self.messages.is_empty() {
+            None
+        } else {
+            Some(self.messages.remove(0))
+        }
+    }
+}
@@ -0,0 +1,411 @@
+//! SenML sensor records in CBOR (RFC 8428)
+//!
+//! `SenmlPack` packs readings into the CBOR representation, which uses
+//! small integer labels instead of field names. Base fields set on the
+//! first record (device name prefix, base time, default unit) are
+//! inherited by the following ones, so a pack of several readings from
+//! one device costs a few bytes per reading. `SenmlReader` walks a pack
+//! and resolves base fields back into every record.
+
+use crate::cbor::{patch_array_head, CborDecoder, CborEncoder, CborError, CborItem};
+
+/// CBOR labels from RFC 8428 section 6
+const LABEL_BASE_SUM: i64 = -6;
+const LABEL_BASE_VALUE: i64 = -5;
+const LABEL_BASE_UNIT: i64 = -4;
+const LABEL_BASE_TIME: i64 = -3;
+const LABEL_BASE_NAME: i64 = -2;
+const LABEL_BASE_VERSION: i64 = -1;
+const LABEL_NAME: i64 = 0;
+const LABEL_UNIT: i64 = 1;
+const LABEL_VALUE: i64 = 2;
+const LABEL_STRING_VALUE: i64 = 3;
+const LABEL_BOOL_VALUE: i64 = 4;
+const LABEL_SUM: i64 = 5;
+const LABEL_TIME: i64 = 6;
+const LABEL_UPDATE_TIME: i64 = 7;
+const LABEL_DATA_VALUE: i64 = 8;
+
+/// Highest SenML version this reader understands
+const SENML_VERSION: u64 = 10;
+/// Room kept in front of the records for the array head
+const PACK_HEAD_RESERVE: usize = 3;
+
+/// Units from the SenML units registry
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SenmlUnit<'a> {
+    Meter,
+    Kilogram,
+    Second,
+    Ampere,
+    Kelvin,
+    Celsius,
+    Volt,
+    Watt,
+    Joule,
+    Hertz,
+    Pascal,
+    Lux,
+    /// Relative humidity
+    RelativeHumidity,
+    /// Remaining battery level
+    BatteryPercent,
+    Percent,
+    DecibelMilliwatt,
+    /// Parts per million, for gas concentrations
+    Ppm,
+    MetersPerSecond,
+    /// Count per second, e.g. pulses or events
+    PerSecond,
+    /// Unit outside the list above
+    Other(&'a str),
+}
+
+impl<'a> SenmlUnit<'a> {
+    pub fn as_str(&self) -> &'a str {
+        match self {
+            SenmlUnit::Meter => "m",
+            SenmlUnit::Kilogram => "kg",
+            SenmlUnit::Second => "s",
+            SenmlUnit::Ampere => "A",
+            SenmlUnit::Kelvin => "K",
+            SenmlUnit::Celsius => "Cel",
+            SenmlUnit::Volt => "V",
+            SenmlUnit::Watt => "W",
+            SenmlUnit::Joule => "J",
+            SenmlUnit::Hertz => "Hz",
+            SenmlUnit::Pascal => "Pa",
+            SenmlUnit::Lux => "lx",
+            SenmlUnit::RelativeHumidity => "%RH",
+            SenmlUnit::BatteryPercent => "%EL",
+            SenmlUnit::Percent => "%",
+            SenmlUnit::DecibelMilliwatt => "dBm",
+            SenmlUnit::Ppm => "ppm",
+            SenmlUnit::MetersPerSecond => "m/s",
+            SenmlUnit::PerSecond => "1/s",
+            SenmlUnit::Other(unit) => unit,
+        }
+    }
+
+    pub fn parse(unit: &'a str) -> Self {
+        match unit {
+            "m" => SenmlUnit::Meter,
+            "kg" => SenmlUnit::Kilogram,
+            "s" => SenmlUnit::Second,
+            "A" => SenmlUnit::Ampere,
+            "K" => SenmlUnit::Kelvin,
+            "Cel" => SenmlUnit::Celsius,
+            "V" => SenmlUnit::Volt,
+            "W" => SenmlUnit::Watt,
+            "J" => SenmlUnit::Joule,
+            "Hz" => SenmlUnit::Hertz,
+            "Pa" => SenmlUnit::Pascal,
+            "lx" => SenmlUnit::Lux,
+            "%RH" => SenmlUnit::RelativeHumidity,
+            "%EL" => SenmlUnit::BatteryPercent,
+            "%" => SenmlUnit::Percent,
+            "dBm" => SenmlUnit::DecibelMilliwatt,
+            "ppm" => SenmlUnit::Ppm,
+            "m/s" => SenmlUnit::MetersPerSecond,
+            "1/s" => SenmlUnit::PerSecond,
+            other => SenmlUnit::Other(other),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SenmlValue<'a> {
+    Number(f64),
+    Bool(bool),
+    Text(&'a str),
+    Data(&'a [u8]),
+}
+
+/// One SenML record; base fields apply to this and later records
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct SenmlRecord<'a> {
+    pub base_name: Option<&'a str>,
+    pub base_time: Option<f64>,
+    pub base_unit: Option<SenmlUnit<'a>>,
+    pub base_value: Option<f64>,
+    pub base_sum: Option<f64>,
+    pub name: Option<&'a str>,
+    pub unit: Option<SenmlUnit<'a>>,
+    pub value: Option<SenmlValue<'a>>,
+    pub sum: Option<f64>,
+    /// Seconds relative to the base time, or absolute Unix seconds when
+    /// there is no base time
+    pub time: Option<f64>,
+    /// Seconds until the sensor reports a new value
+    pub update_time: Option<f64>,
+}
+
+impl<'a> SenmlRecord<'a> {
+    /// Numeric reading named `name`
+    pub fn number(name: &'a str, value: f64) -> Self {
+        Self { name: Some(name), value: Some(SenmlValue::Number(value)), ..Self::default() }
+    }
+
+    pub fn boolean(name: &'a str, value: bool) -> Self {
+        Self { name: Some(name), value: Some(SenmlValue::Bool(value)), ..Self::default() }
+    }
+
+    pub fn text(name: &'a str, value: &'a str) -> Self {
+        Self { name: Some(name), value: Some(SenmlValue::Text(value)), ..Self::default() }
+    }
+
+    pub fn with_unit(mut self, unit: SenmlUnit<'a>) -> Self {
+        self.unit = Some(unit);
+        self
+    }
+
+    pub fn with_time(mut self, time: f64) -> Self {
+        self.time = Some(time);
+        self
+    }
+
+    pub fn with_sum(mut self, sum: f64) -> Self {
+        self.sum = Some(sum);
+        self
+    }
+
+    /// Prefix prepended to this and later record names, e.g. a device URN
+    pub fn with_base_name(mut self, base_name: &'a str) -> Self {
+        self.base_name = Some(base_name);
+        self
+    }
+
+    pub fn with_base_time(mut self, base_time: f64) -> Self {
+        self.base_time = Some(base_time);
+        self
+    }
+
+    pub fn with_base_unit(mut self, base_unit: SenmlUnit<'a>) -> Self {
+        self.base_unit = Some(base_unit);
+        self
+    }
+
+    fn encode(&self, encoder: &mut CborEncoder) -> Result<(), CborError> {
+        let fields = [
+            self.base_name.is_some(),
+            self.base_time.is_some(),
+            self.base_unit.is_some(),
+            self.base_value.is_some(),
+            self.base_sum.is_some(),
+            self.name.is_some(),
+            self.unit.is_some(),
+            self.value.is_some(),
+            self.sum.is_some(),
+            self.time.is_some(),
+            self.update_time.is_some(),
+        ];
+        encoder.map(fields.iter().filter(|present| **present).count())?;
+
+        if let Some(base_name) = self.base_name {
+            encoder.int(LABEL_BASE_NAME)?.text(base_name)?;
+        }
+        if let Some(base_time) = self.base_time {
+            encoder.int(LABEL_BASE_TIME)?.number(base_time)?;
+        }
+        if let Some(base_unit) = self.base_unit {
+            encoder.int(LABEL_BASE_UNIT)?.text(base_unit.as_str())?;
+        }
+        if let Some(base_value) = self.base_value {
+            encoder.int(LABEL_BASE_VALUE)?.number(base_value)?;
+        }
+        if let Some(base_sum) = self.base_sum {
+            encoder.int(LABEL_BASE_SUM)?.number(base_sum)?;
+        }
+        if let Some(name) = self.name {
+            encoder.int(LABEL_NAME)?.text(name)?;
+        }
+        if let Some(unit) = self.unit {
+            encoder.int(LABEL_UNIT)?.text(unit.as_str())?;
+        }
+        match self.value {
+            Some(SenmlValue::Number(value)) => encoder.int(LABEL_VALUE)?.number(value)?,
+            Some(SenmlValue::Text(value)) => encoder.int(LABEL_STRING_VALUE)?.text(value)?,
+            Some(SenmlValue::Bool(value)) => encoder.int(LABEL_BOOL_VALUE)?.bool(value)?,
+            Some(SenmlValue::Data(value)) => encoder.int(LABEL_DATA_VALUE)?.bytes(value)?,
+            None => encoder,
+        };
+        if let Some(sum) = self.sum {
+            encoder.int(LABEL_SUM)?.number(sum)?;
+        }
+        if let Some(time) = self.time {
+            encoder.int(LABEL_TIME)?.number(time)?;
+        }
+        if let Some(update_time) = self.update_time {
+            encoder.int(LABEL_UPDATE_TIME)?.number(update_time)?;
+        }
+        Ok(())
+    }
+}
+
+/// Builds a SenML pack into a caller-provided buffer
+pub struct SenmlPack<'b> {
+    encoder: CborEncoder<'b>,
+    records: usize,
+}
+
+impl<'b> SenmlPack<'b> {
+    pub fn new(buf: &'b mut [u8]) -> Result<Self, CborError> {
+        if buf.len() < PACK_HEAD_RESERVE {
+            return Err(CborError::BufferFull);
+        }
+        let mut encoder = CborEncoder::new(buf);
+        // Space for the array head, written once the record count is known
+        for _ in 0..PACK_HEAD_RESERVE {
+            encoder.null()?;
+        }
+        Ok(Self { encoder, records: 0 })
+    }
+
+    pub fn len(&self) -> usize {
+        self.records
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records == 0
+    }
+
+    /// Append a record; on `BufferFull` the pack is unchanged and can
+    /// still be finished
+    pub fn push(&mut self, record: &SenmlRecord) -> Result<(), CborError> {
+        let mark = self.encoder.len();
+        if let Err(err) = record.encode(&mut self.encoder) {
+            self.encoder.truncate(mark);
+            return Err(err);
+        }
+        self.records += 1;
+        Ok(())
+    }
+
+    /// Complete the pack, returning the encoded bytes
+    pub fn finish(self) -> Result<&'b [u8], CborError> {
+        let records = self.records;
+        let bytes = self.encoder.into_bytes_mut();
+        let start = patch_array_head(bytes, PACK_HEAD_RESERVE, records)?;
+        Ok(&bytes[start..])
+    }
+}
+
+/// A record with base fields resolved
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SenmlEntry<'a> {
+    /// Base name in effect; the full name is `base_name` followed by `name`
+    pub base_name: &'a str,
+    pub name: &'a str,
+    pub unit: Option<SenmlUnit<'a>>,
+    /// Value with any base value added
+    pub value: Option<SenmlValue<'a>>,
+    pub sum: Option<f64>,
+    /// Base time plus record time
+    pub time: f64,
+    pub update_time: Option<f64>,
+}
+
+/// Iterates the records of a SenML pack
+pub struct SenmlReader<'a> {
+    decoder: CborDecoder<'a>,
+    remaining: usize,
+    base_name: &'a str,
+    base_time: f64,
+    base_unit: Option<SenmlUnit<'a>>,
+    base_value: f64,
+    base_sum: f64,
+}
+
+impl<'a> SenmlReader<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Self, CborError> {
+        let mut decoder = CborDecoder::new(data);
+        let remaining = decoder.read_array()?;
+        Ok(Self {
+            decoder,
+            remaining,
+            base_name: "",
+            base_time: 0.0,
+            base_unit: None,
+            base_value: 0.0,
+            base_sum: 0.0,
+        })
+    }
+
+    /// Decode the next record, or `None` after the last one
+    pub fn next_record(&mut self) -> Result<Option<SenmlEntry<'a>>, CborError> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        self.remaining -= 1;
+
+        let mut record = SenmlRecord::default();
+        for _ in 0..self.decoder.read_map()? {
+            let label = match self.decoder.next()? {
+                CborItem::Unsigned(label) => label as i64,
+                CborItem::Negative(label) => label,
+                CborItem::Text(label) if label.ends_with('_') => {
+                    // Must-understand extension field
+                    return Err(CborError::Unsupported);
+                }
+                _ => {
+                    self.decoder.skip()?;
+                    continue;
+                }
+            };
+            let d = &mut self.decoder;
+            match label {
+                LABEL_BASE_NAME => record.base_name = Some(d.read_text()?),
+                LABEL_BASE_TIME => record.base_time = Some(d.read_number()?),
+                LABEL_BASE_UNIT => record.base_unit = Some(SenmlUnit::parse(d.read_text()?)),
+                LABEL_BASE_VALUE => record.base_value = Some(d.read_number()?),
+                LABEL_BASE_SUM => record.base_sum = Some(d.read_number()?),
+                LABEL_NAME => record.name = Some(d.read_text()?),
+                LABEL_UNIT => record.unit = Some(SenmlUnit::parse(d.read_text()?)),
+                LABEL_VALUE => record.value = Some(SenmlValue::Number(d.read_number()?)),
+                LABEL_STRING_VALUE => record.value = Some(SenmlValue::Text(d.read_text()?)),
+                LABEL_BOOL_VALUE => record.value = Some(SenmlValue::Bool(d.read_bool()?)),
+                LABEL_DATA_VALUE => record.value = Some(SenmlValue::Data(d.read_bytes()?)),
+                LABEL_SUM => record.sum = Some(d.read_number()?),
+                LABEL_TIME => record.time = Some(d.read_number()?),
+                LABEL_UPDATE_TIME => record.update_time = Some(d.read_number()?),
+                LABEL_BASE_VERSION => {
+                    if d.read_uint()? > SENML_VERSION {
+                        return Err(CborError::Unsupported);
+                    }
+                }
+                // Unregistered labels carry nothing we use
+                _ => d.skip()?,
+            }
+        }
+
+        if let Some(base_name) = record.base_name {
+            self.base_name = base_name;
+        }
+        if let Some(base_time) = record.base_time {
+            self.base_time = base_time;
+        }
+        if record.base_unit.is_some() {
+            self.base_unit = record.base_unit;
+        }
+        if let Some(base_value) = record.base_value {
+            self.base_value = base_value;
+        }
+        if let Some(base_sum) = record.base_sum {
+            self.base_sum = base_sum;
+        }
+
+        let value = match record.value {
+            Some(SenmlValue::Number(value)) => Some(SenmlValue::Number(self.base_value + value)),
+            other => other,
+        };
+        Ok(Some(SenmlEntry {
+            base_name: self.base_name,
+            name: record.name.unwrap_or(""),
+            unit: record.unit.or(self.base_unit),
+            value,
+            sum: record.sum.map(|sum| self.base_sum + sum),
+            time: self.base_time + record.time.unwrap_or(0.0),
+            update_time: record.update_time,
+        }))
+    }
+}
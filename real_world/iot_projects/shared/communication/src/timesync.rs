@@ -0,0 +1,278 @@
+//! Wall-clock time for nodes without a synchronized RTC
+//!
+//! `TimeSync` maps the monotonic millisecond clock onto Unix time from
+//! samples taken by `SntpClient` or a LoRaWAN DeviceTimeAns. Between
+//! samples it corrects for the estimated drift of the local oscillator,
+//! so timestamps stay usable when syncs are hours apart.
+
+use crate::{LoRaWanDeviceTime, MqttClock, MqttError, MqttTransport};
+
+/// Seconds between the NTP epoch (1900) and the Unix epoch
+pub const NTP_UNIX_OFFSET_S: u64 = 2_208_988_800;
+/// Seconds between the Unix epoch and the GPS epoch (1980-01-06)
+pub const GPS_UNIX_OFFSET_S: u64 = 315_964_800;
+/// GPS time runs ahead of UTC by the leap seconds inserted since 1980
+pub const GPS_LEAP_SECONDS: u64 = 18;
+
+/// Samples closer together than this say little about drift
+const MIN_DRIFT_INTERVAL_MS: u64 = 60_000;
+/// Corrections larger than this are clock steps, not drift
+const MAX_SLEW_MS: i64 = 10_000;
+/// Crystal tolerance; larger estimates are clamped
+const MAX_DRIFT_PPM: i64 = 500;
+/// Accuracy of DeviceTimeAns required by LoRaWAN
+const LORAWAN_TIME_UNCERTAINTY_MS: u32 = 100;
+
+const SNTP_PACKET_LEN: usize = 48;
+const SNTP_MODE_CLIENT: u8 = 3;
+const SNTP_MODE_SERVER: u8 = 4;
+const SNTP_VERSION: u8 = 4;
+const SNTP_LEAP_UNSYNCHRONIZED: u8 = 3;
+
+/// One observation of wall-clock time against the monotonic clock
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimeSample {
+    pub monotonic_ms: u64,
+    pub unix_ms: u64,
+    /// Half the round trip, or the protocol's stated accuracy
+    pub uncertainty_ms: u32,
+}
+
+impl From<LoRaWanDeviceTime> for TimeSample {
+    fn from(time: LoRaWanDeviceTime) -> Self {
+        let seconds = time.gps_seconds as u64 + GPS_UNIX_OFFSET_S - GPS_LEAP_SECONDS;
+        Self {
+            monotonic_ms: time.tx_end_ms,
+            unix_ms: seconds * 1000 + time.fraction as u64 * 1000 / 256,
+            uncertainty_ms: LORAWAN_TIME_UNCERTAINTY_MS,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimeSyncError {
+    Transport,
+    Timeout,
+    InvalidResponse,
+    /// Server sent a kiss-o'-death packet asking the client to back off
+    KissOfDeath,
+    /// Server has no time source itself
+    Unsynchronized,
+    /// Sample is less accurate than the configured limit or goes back in
+    /// monotonic time
+    SampleRejected,
+}
+
+impl From<MqttError> for TimeSyncError {
+    fn from(err: MqttError) -> Self {
+        match err {
+            MqttError::Timeout => TimeSyncError::Timeout,
+            _ => TimeSyncError::Transport,
+        }
+    }
+}
+
+/// Source of Unix timestamps for telemetry and MQTT payloads
+pub trait WallClock {
+    /// Current Unix time in milliseconds, once synchronized
+    fn unix_time_ms(&self) -> Option<u64>;
+}
+
+/// Monotonic-to-wallclock mapping with drift estimation
+#[derive(Debug, Clone)]
+pub struct TimeSync {
+    reference: Option<TimeSample>,
+    /// Wall-clock ppm gained per monotonic ms; positive when the local clock runs slow
+    drift_ppm: i64,
+    drift_known: bool,
+    max_uncertainty_ms: u32,
+}
+
+impl TimeSync {
+    pub const fn new() -> Self {
+        Self {
+            reference: None,
+            drift_ppm: 0,
+            drift_known: false,
+            max_uncertainty_ms: 1000,
+        }
+    }
+
+    /// Reject samples whose uncertainty exceeds `uncertainty_ms`
+    pub fn with_max_uncertainty(mut self, uncertainty_ms: u32) -> Self {
+        self.max_uncertainty_ms = uncertainty_ms;
+        self
+    }
+
+    pub fn is_synchronized(&self) -> bool {
+        self.reference.is_some()
+    }
+
+    pub fn last_sync(&self) -> Option<&TimeSample> {
+        self.reference.as_ref()
+    }
+
+    /// Estimated oscillator drift, once two samples far enough apart arrived
+    pub fn drift_ppm(&self) -> Option<i32> {
+        self.drift_known.then_some(self.drift_ppm as i32)
+    }
+
+    /// Take a new sample as the reference, refining the drift estimate
+    pub fn apply(&mut self, sample: TimeSample) -> Result<(), TimeSyncError> {
+        if sample.uncertainty_ms > self.max_uncertainty_ms {
+            return Err(TimeSyncError::SampleRejected);
+        }
+        if let Some(reference) = self.reference {
+            if sample.monotonic_ms < reference.monotonic_ms {
+                return Err(TimeSyncError::SampleRejected);
+            }
+            let elapsed = (sample.monotonic_ms - reference.monotonic_ms) as i64;
+            let predicted = self.unix_ms(sample.monotonic_ms).unwrap_or(sample.unix_ms);
+            let error = sample.unix_ms as i64 - predicted as i64;
+            if elapsed as u64 >= MIN_DRIFT_INTERVAL_MS && error.abs() <= MAX_SLEW_MS {
+                let measured = self.drift_ppm + error * 1_000_000 / elapsed;
+                // Average with the previous estimate to smooth out sample jitter
+                let estimate = if self.drift_known { (self.drift_ppm * 3 + measured) / 4 } else { measured };
+                self.drift_ppm = estimate.clamp(-MAX_DRIFT_PPM, MAX_DRIFT_PPM);
+                self.drift_known = true;
+            }
+        }
+        self.reference = Some(sample);
+        Ok(())
+    }
+
+    /// Unix time in milliseconds at `monotonic_ms`
+    pub fn unix_ms(&self, monotonic_ms: u64) -> Option<u64> {
+        let reference = self.reference?;
+        let elapsed = monotonic_ms as i64 - reference.monotonic_ms as i64;
+        let correction = elapsed * self.drift_ppm / 1_000_000;
+        u64::try_from(reference.unix_ms as i64 + elapsed + correction).ok()
+    }
+
+    /// Unix time in whole seconds at `monotonic_ms`
+    pub fn unix_seconds(&self, monotonic_ms: u64) -> Option<u32> {
+        self.unix_ms(monotonic_ms).and_then(|ms| u32::try_from(ms / 1000).ok())
+    }
+
+    /// Wall clock reading `clock`, the monotonic source the samples use
+    pub fn clock<'a>(&'a self, clock: &'a dyn MqttClock) -> SyncedClock<'a> {
+        SyncedClock { sync: self, clock }
+    }
+}
+
+/// `TimeSync` paired with the monotonic clock it maps
+pub struct SyncedClock<'a> {
+    sync: &'a TimeSync,
+    clock: &'a dyn MqttClock,
+}
+
+impl WallClock for SyncedClock<'_> {
+    fn unix_time_ms(&self) -> Option<u64> {
+        self.sync.unix_ms(self.clock.now_ms())
+    }
+}
+
+/// SNTPv4 client (RFC 4330) over a datagram transport
+///
+/// The transport is typically a UDP socket to port 123 on the WiFi
+/// module. The request's transmit timestamp carries the local monotonic
+/// time, which the server echoes back so stale or spoofed replies can be
+/// told apart from the answer to this query.
+pub struct SntpClient<'a> {
+    transport: &'a dyn MqttTransport,
+    clock: &'a dyn MqttClock,
+    timeout_ms: u32,
+}
+
+impl<'a> SntpClient<'a> {
+    pub fn new(transport: &'a dyn MqttTransport, clock: &'a dyn MqttClock) -> Self {
+        Self { transport, clock, timeout_ms: 3000 }
+    }
+
+    pub fn with_timeout(mut self, timeout_ms: u32) -> Self {
+        self.timeout_ms = timeout_ms;
+        self
+    }
+
+    /// Query the server and return the resulting sample
+    pub fn query(&self) -> Result<TimeSample, TimeSyncError> {
+        let sent_ms = self.clock.now_ms();
+        let cookie = ntp_timestamp(sent_ms);
+        let mut request = [0u8; SNTP_PACKET_LEN];
+        request[0] = SNTP_VERSION << 3 | SNTP_MODE_CLIENT;
+        request[40..48].copy_from_slice(&cookie);
+        self.transport.send(&request)?;
+
+        loop {
+            let elapsed = self.clock.now_ms().saturating_sub(sent_ms);
+            let remaining = (self.timeout_ms as u64).saturating_sub(elapsed) as u32;
+            if remaining == 0 {
+                return Err(TimeSyncError::Timeout);
+            }
+            let Some(response) = self.transport.receive(remaining)? else {
+                return Err(TimeSyncError::Timeout);
+            };
+            if response.len() < SNTP_PACKET_LEN || response[24..32] != cookie {
+                continue;
+            }
+            let received_ms = self.clock.now_ms();
+            return Self::parse_response(&response, sent_ms, received_ms);
+        }
+    }
+
+    /// Query and feed the result into `sync`
+    pub fn synchronize(&self, sync: &mut TimeSync) -> Result<TimeSample, TimeSyncError> {
+        let sample = self.query()?;
+        sync.apply(sample)?;
+        Ok(sample)
+    }
+
+    fn parse_response(packet: &[u8], sent_ms: u64, received_ms: u64) -> Result<TimeSample, TimeSyncError> {
+        let leap = packet[0] >> 6;
+        let mode = packet[0] & 0x07;
+        let stratum = packet[1];
+        if mode != SNTP_MODE_SERVER {
+            return Err(TimeSyncError::InvalidResponse);
+        }
+        if stratum == 0 {
+            return Err(TimeSyncError::KissOfDeath);
+        }
+        if leap == SNTP_LEAP_UNSYNCHRONIZED || stratum > 15 {
+            return Err(TimeSyncError::Unsynchronized);
+        }
+
+        let server_received = ntp_to_unix_ms(&packet[32..40]).ok_or(TimeSyncError::InvalidResponse)?;
+        let server_sent = ntp_to_unix_ms(&packet[40..48]).ok_or(TimeSyncError::InvalidResponse)?;
+        let processing = server_sent.saturating_sub(server_received);
+        let delay = (received_ms - sent_ms).saturating_sub(processing);
+        Ok(TimeSample {
+            monotonic_ms: received_ms,
+            unix_ms: server_sent + delay / 2,
+            uncertainty_ms: (delay / 2).min(u32::MAX as u64) as u32 + 1,
+        })
+    }
+}
+
+/// Encode a millisecond count as an NTP timestamp
+fn ntp_timestamp(ms: u64) -> [u8; 8] {
+    let seconds = (ms / 1000) as u32;
+    let fraction = (((ms % 1000) << 32) / 1000) as u32;
+    let mut out = [0u8; 8];
+    out[..4].copy_from_slice(&seconds.to_be_bytes());
+    out[4..].copy_from_slice(&fraction.to_be_bytes());
+    out
+}
+
+/// Convert an NTP timestamp to Unix milliseconds
+///
+/// Seconds with the top bit clear are taken to be in NTP era 1, which
+/// starts in 2036.
+fn ntp_to_unix_ms(timestamp: &[u8]) -> Option<u64> {
+    let seconds = u32::from_be_bytes(timestamp.get(..4)?.try_into().ok()?) as u64;
+    let fraction = u32::from_be_bytes(timestamp.get(4..8)?.try_into().ok()?) as u64;
+    if seconds == 0 && fraction == 0 {
+        return None;
+    }
+    let seconds = if seconds & 0x8000_0000 == 0 { seconds + (1 << 32) } else { seconds };
+    Some((seconds - NTP_UNIX_OFFSET_S) * 1000 + ((fraction * 1000 + (1 << 31)) >> 32))
+}
@@ -0,0 +1,775 @@
+//! TLS and DTLS session layer
+//!
+//! `TlsTransport` wraps any `MqttTransport` with a TLS 1.2/1.3 or DTLS 1.2
+//! session. This module owns record framing, stream reassembly, DTLS replay
+//! protection, certificate pinning and session resumption; handshake
+//! messages and record protection come from a `TlsEngine`, so targets can
+//! plug in a software stack or a crypto accelerator.
+//!
+//! Buffers are sized for a 512-byte maximum fragment, which engines are
+//! expected to negotiate with the Maximum Fragment Length or Record Size
+//! Limit extensions.
+
+use core::cell::RefCell;
+use heapless::{String, Vec};
+use crate::{MqttError, MqttTransport};
+
+/// Largest plaintext fragment carried by one record
+pub const TLS_MAX_FRAGMENT: usize = 512;
+/// Largest protected record payload: fragment plus AEAD expansion
+pub const TLS_MAX_RECORD_PAYLOAD: usize = TLS_MAX_FRAGMENT + 64;
+pub const TLS_RECORD_HEADER_LEN: usize = 5;
+pub const DTLS_RECORD_HEADER_LEN: usize = 13;
+/// Received bytes buffered while a record is incomplete
+const TLS_RECEIVE_BUFFER: usize = 2 * (TLS_MAX_RECORD_PAYLOAD + DTLS_RECORD_HEADER_LEN);
+/// Receive timeout of each handshake poll
+const TLS_POLL_INTERVAL_MS: u32 = 100;
+/// DTLS flight retransmission interval
+const DTLS_RETRANSMIT_MS: u32 = 1000;
+/// Sessions remembered for resumption
+pub const TLS_SESSION_CACHE_SIZE: usize = 2;
+
+/// Alert descriptions sent by the session layer
+const TLS_ALERT_CLOSE_NOTIFY: u8 = 0;
+const TLS_ALERT_BAD_CERTIFICATE: u8 = 42;
+const TLS_ALERT_LEVEL_WARNING: u8 = 1;
+const TLS_ALERT_LEVEL_FATAL: u8 = 2;
+
+/// Protocol version of a session
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TlsVersion {
+    Tls12,
+    Tls13,
+    Dtls12,
+}
+
+impl TlsVersion {
+    pub fn is_datagram(&self) -> bool {
+        *self == TlsVersion::Dtls12
+    }
+}
+
+/// Record content types
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TlsContentType {
+    ChangeCipherSpec = 20,
+    Alert = 21,
+    Handshake = 22,
+    ApplicationData = 23,
+}
+
+impl TlsContentType {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            20 => Some(TlsContentType::ChangeCipherSpec),
+            21 => Some(TlsContentType::Alert),
+            22 => Some(TlsContentType::Handshake),
+            23 => Some(TlsContentType::ApplicationData),
+            _ => None,
+        }
+    }
+}
+
+/// How the peers authenticate
+#[derive(Debug, Clone, Copy)]
+pub enum TlsCredentials<'a> {
+    /// Pre-shared key, the usual choice for constrained nodes
+    Psk { identity: &'a [u8], key: &'a [u8] },
+    /// Server certificate chain checked against DER trust anchors, with
+    /// an optional client certificate and key
+    X509 {
+        trust_anchors: &'a [&'a [u8]],
+        client_certificate: Option<&'a [u8]>,
+        client_key: Option<&'a [u8]>,
+    },
+}
+
+/// Session parameters
+#[derive(Debug, Clone, Copy)]
+pub struct TlsConfig<'a> {
+    pub version: TlsVersion,
+    pub credentials: TlsCredentials<'a>,
+    /// Server name for SNI, certificate checks and the session cache
+    pub server_name: &'a str,
+    /// SHA-256 hashes of acceptable server SubjectPublicKeyInfo; when not
+    /// empty, X.509 sessions with any other server key are refused
+    pub pinned_keys: &'a [[u8; 32]],
+    pub handshake_timeout_ms: u32,
+}
+
+impl<'a> TlsConfig<'a> {
+    pub fn psk(version: TlsVersion, server_name: &'a str, identity: &'a [u8], key: &'a [u8]) -> Self {
+        Self {
+            version,
+            credentials: TlsCredentials::Psk { identity, key },
+            server_name,
+            pinned_keys: &[],
+            handshake_timeout_ms: 10_000,
+        }
+    }
+
+    pub fn x509(version: TlsVersion, server_name: &'a str, trust_anchors: &'a [&'a [u8]]) -> Self {
+        Self {
+            version,
+            credentials: TlsCredentials::X509 { trust_anchors, client_certificate: None, client_key: None },
+            server_name,
+            pinned_keys: &[],
+            handshake_timeout_ms: 10_000,
+        }
+    }
+
+    pub fn with_pinned_keys(mut self, pinned_keys: &'a [[u8; 32]]) -> Self {
+        self.pinned_keys = pinned_keys;
+        self
+    }
+}
+
+/// Resumption state exported by an engine after a full handshake: a TLS
+/// 1.2 session ID or ticket, or a TLS 1.3 resumption PSK
+#[derive(Debug, Clone)]
+pub struct TlsSession {
+    pub server_name: String<64>,
+    pub version: TlsVersion,
+    pub session_id: Vec<u8, 32>,
+    pub ticket: Vec<u8, 256>,
+    pub secret: Vec<u8, 48>,
+    pub lifetime_s: u32,
+    /// Pinned key the session was established with, since resumed
+    /// handshakes do not resend the certificate
+    pub peer_key_hash: Option<[u8; 32]>,
+}
+
+/// Small cache of resumable sessions, one per server name
+#[derive(Debug)]
+pub struct TlsSessionCache {
+    sessions: Vec<TlsSession, TLS_SESSION_CACHE_SIZE>,
+}
+
+impl TlsSessionCache {
+    pub const fn new() -> Self {
+        Self { sessions: Vec::new() }
+    }
+
+    pub fn get(&self, server_name: &str) -> Option<&TlsSession> {
+        self.sessions.iter().find(|session| session.server_name.as_str() == server_name)
+    }
+
+    /// Remember a session, replacing the oldest when full
+    pub fn store(&mut self, session: TlsSession) {
+        self.remove(&session.server_name.clone());
+        if self.sessions.is_full() {
+            self.sessions.remove(0);
+        }
+        self.sessions.push(session).ok();
+    }
+
+    pub fn remove(&mut self, server_name: &str) {
+        self.sessions.retain(|session| session.server_name.as_str() != server_name);
+    }
+}
+
+/// Handshake progress reported by an engine
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TlsHandshakeStatus {
+    InProgress,
+    Complete,
+}
+
+/// Handshake and record protection backend
+///
+/// Engines exchange record payloads; the session layer adds and strips
+/// record headers.
+pub trait TlsEngine {
+    /// Start a handshake, offering `resume` when the cache holds a session
+    fn start(&mut self, config: &TlsConfig, resume: Option<&TlsSession>) -> Result<(), TlsError>;
+
+    /// Next record payload to send; returns its content type and length
+    fn poll_output(&mut self, out: &mut [u8]) -> Option<(TlsContentType, usize)>;
+
+    /// Consume a received handshake, change cipher spec or post-handshake
+    /// record
+    fn handle_record(&mut self, content_type: TlsContentType, epoch: u16, sequence: u64, payload: &[u8]) -> Result<TlsHandshakeStatus, TlsError>;
+
+    /// Queue the last flight again after a DTLS timeout
+    fn retransmit(&mut self) -> Result<(), TlsError> {
+        Ok(())
+    }
+
+    /// Protect application data into a record payload
+    fn seal(&mut self, plaintext: &[u8], out: &mut [u8]) -> Result<usize, TlsError>;
+
+    /// Remove protection from an application data record payload
+    fn open(&mut self, epoch: u16, sequence: u64, payload: &[u8], out: &mut [u8]) -> Result<usize, TlsError>;
+
+    /// Epoch of records sealed now (DTLS)
+    fn write_epoch(&self) -> u16 {
+        0
+    }
+
+    /// SHA-256 of the server's SubjectPublicKeyInfo after a full X.509
+    /// handshake
+    fn peer_key_hash(&self) -> Option<[u8; 32]>;
+
+    /// Resumption state of the established session
+    fn session(&self) -> Option<TlsSession>;
+
+    /// Whether the handshake resumed an offered session
+    fn resumed(&self) -> bool;
+}
+
+/// TLS error types
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TlsError {
+    HandshakeFailed,
+    /// Peer sent a fatal alert with this description
+    Alert(u8),
+    /// Server key matched none of the pinned keys
+    PinMismatch,
+    BadRecord,
+    RecordOverflow,
+    DecryptFailed,
+    NotConnected,
+    Closed,
+    Timeout,
+    Transport,
+}
+
+impl From<MqttError> for TlsError {
+    fn from(_: MqttError) -> Self {
+        TlsError::Transport
+    }
+}
+
+/// Header fields of a received record
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TlsRecordHeader {
+    pub content_type: TlsContentType,
+    pub epoch: u16,
+    pub sequence: u64,
+}
+
+/// Sliding window of the last 64 DTLS sequence numbers of one epoch
+#[derive(Debug, Default)]
+pub struct DtlsReplayWindow {
+    epoch: u16,
+    highest: u64,
+    bitmap: u64,
+    seen_any: bool,
+}
+
+impl DtlsReplayWindow {
+    /// Accept `sequence` once; replays and records older than the window
+    /// are refused
+    pub fn accept(&mut self, epoch: u16, sequence: u64) -> bool {
+        if epoch != self.epoch {
+            if epoch < self.epoch {
+                return false;
+            }
+            *self = Self { epoch, ..Self::default() };
+        }
+        if !self.seen_any || sequence > self.highest {
+            let shift = if self.seen_any { sequence - self.highest } else { 64 };
+            self.bitmap = if shift >= 64 { 0 } else { self.bitmap << shift };
+            self.bitmap |= 1;
+            self.highest = sequence;
+            self.seen_any = true;
+            return true;
+        }
+        let offset = self.highest - sequence;
+        if offset >= 64 || self.bitmap & (1 << offset) != 0 {
+            return false;
+        }
+        self.bitmap |= 1 << offset;
+        true
+    }
+}
+
+/// Record framing for a stream (TLS) or datagram (DTLS) transport
+#[derive(Debug)]
+pub struct TlsRecordLayer {
+    datagram: bool,
+    pending: Vec<u8, TLS_RECEIVE_BUFFER>,
+    write_epoch: u16,
+    write_sequence: u64,
+    replay: DtlsReplayWindow,
+}
+
+impl TlsRecordLayer {
+    pub fn new(datagram: bool) -> Self {
+        Self {
+            datagram,
+            pending: Vec::new(),
+            write_epoch: 0,
+            write_sequence: 0,
+            replay: DtlsReplayWindow::default(),
+        }
+    }
+
+    fn header_len(&self) -> usize {
+        if self.datagram { DTLS_RECORD_HEADER_LEN } else { TLS_RECORD_HEADER_LEN }
+    }
+
+    /// Frame a record payload; DTLS sequence numbers restart with each epoch
+    pub fn encode<const N: usize>(&mut self, content_type: TlsContentType, epoch: u16, payload: &[u8], out: &mut Vec<u8, N>) -> Result<(), TlsError> {
+        if payload.len() > TLS_MAX_RECORD_PAYLOAD {
+            return Err(TlsError::RecordOverflow);
+        }
+        out.clear();
+        let length = (payload.len() as u16).to_be_bytes();
+        let result = if self.datagram {
+            if epoch != self.write_epoch {
+                self.write_epoch = epoch;
+                self.write_sequence = 0;
+            }
+            let sequence = self.write_sequence.to_be_bytes();
+            self.write_sequence += 1;
+            out.extend_from_slice(&[content_type as u8, 0xFE, 0xFD])
+                .and_then(|_| out.extend_from_slice(&epoch.to_be_bytes()))
+                .and_then(|_| out.extend_from_slice(&sequence[2..]))
+        } else {
+            out.extend_from_slice(&[content_type as u8, 0x03, 0x03])
+        };
+        result.and_then(|_| out.extend_from_slice(&length))
+            .and_then(|_| out.extend_from_slice(payload))
+            .map_err(|_| TlsError::RecordOverflow)
+    }
+
+    /// Buffer bytes from the transport; a datagram must hold whole records
+    pub fn push_received(&mut self, data: &[u8]) -> Result<(), TlsError> {
+        if self.datagram {
+            self.pending.clear();
+        }
+        self.pending.extend_from_slice(data).map_err(|_| TlsError::RecordOverflow)
+    }
+
+    /// Take the next complete record into `payload`
+    ///
+    /// DTLS silently drops malformed, replayed and unknown records as the
+    /// protocol requires; TLS treats them as fatal.
+    pub fn next_record(&mut self, payload: &mut Vec<u8, TLS_MAX_RECORD_PAYLOAD>) -> Result<Option<TlsRecordHeader>, TlsError> {
+        loop {
+            let header_len = self.header_len();
+            if self.pending.len() < header_len {
+                if self.datagram {
+                    self.pending.clear();
+                }
+                return Ok(None);
+            }
+            let length = u16::from_be_bytes([self.pending[header_len - 2], self.pending[header_len - 1]]) as usize;
+            if length > TLS_MAX_RECORD_PAYLOAD {
+                if self.datagram {
+                    self.pending.clear();
+                    return Ok(None);
+                }
+                return Err(TlsError::RecordOverflow);
+            }
+            if self.pending.len() < header_len + length {
+                if self.datagram {
+                    self.pending.clear();
+                }
+                return Ok(None);
+            }
+
+            let content_type = TlsContentType::from_u8(self.pending[0]);
+            let (epoch, sequence) = if self.datagram {
+                let mut sequence = [0u8; 8];
+                sequence[2..].copy_from_slice(&self.pending[5..11]);
+                (u16::from_be_bytes([self.pending[3], self.pending[4]]), u64::from_be_bytes(sequence))
+            } else {
+                (0, 0)
+            };
+            payload.clear();
+            payload.extend_from_slice(&self.pending[header_len..header_len + length]).map_err(|_| TlsError::RecordOverflow)?;
+            self.consume(header_len + length);
+
+            match content_type {
+                Some(content_type) if !self.datagram || self.replay.accept(epoch, sequence) => {
+                    return Ok(Some(TlsRecordHeader { content_type, epoch, sequence }));
+                }
+                Some(_) => continue,
+                None if self.datagram => continue,
+                None => return Err(TlsError::BadRecord),
+            }
+        }
+    }
+
+    fn consume(&mut self, count: usize) {
+        let remaining = self.pending.len() - count;
+        self.pending.copy_within(count.., 0);
+        self.pending.truncate(remaining);
+    }
+}
+
+struct TlsState<E: TlsEngine> {
+    engine: E,
+    records: TlsRecordLayer,
+    /// Decrypted application data not yet returned by `receive()`
+    plaintext: Vec<u8, TLS_MAX_FRAGMENT>,
+    established: bool,
+    closed: bool,
+    resumed: bool,
+    session: Option<TlsSession>,
+}
+
+/// TLS or DTLS session over another transport
+///
+/// `connect()` runs the handshake; afterwards the session is itself an
+/// `MqttTransport`, so `MqttClient` runs over it unchanged. A CoAP client
+/// uses it the same way with `TlsVersion::Dtls12` over a UDP transport.
+pub struct TlsTransport<'a, E: TlsEngine> {
+    inner: &'a dyn MqttTransport,
+    state: RefCell<TlsState<E>>,
+}
+
+impl<'a, E: TlsEngine> TlsTransport<'a, E> {
+    pub fn new(inner: &'a dyn MqttTransport, engine: E, version: TlsVersion) -> Self {
+        Self {
+            inner,
+            state: RefCell::new(TlsState {
+                engine,
+                records: TlsRecordLayer::new(version.is_datagram()),
+                plaintext: Vec::new(),
+                established: false,
+                closed: false,
+                resumed: false,
+                session: None,
+            }),
+        }
+    }
+
+    /// Run the handshake, resuming a cached session for the server when
+    /// there is one and caching the new session on success
+    pub fn connect(&self, config: &TlsConfig, cache: &mut TlsSessionCache) -> Result<(), TlsError> {
+        let resume = cache.get(config.server_name).cloned();
+        let result = self.handshake(config, resume.as_ref());
+        let mut state = self.state.borrow_mut();
+        match result {
+            Ok(()) => {
+                if let Some(mut session) = state.engine.session() {
+                    if session.peer_key_hash.is_none() {
+                        session.peer_key_hash = state.engine.peer_key_hash()
+                            .or_else(|| resume.as_ref().and_then(|previous| previous.peer_key_hash));
+                    }
+                    state.session = Some(session.clone());
+                    cache.store(session);
+                }
+                Ok(())
+            }
+            Err(error) => {
+                // A refused resumption must not be offered again
+                if resume.is_some() {
+                    cache.remove(config.server_name);
+                }
+                Err(error)
+            }
+        }
+    }
+
+    fn handshake(&self, config: &TlsConfig, resume: Option<&TlsSession>) -> Result<(), TlsError> {
+        let mut state = self.state.borrow_mut();
+        state.established = false;
+        state.closed = false;
+        state.plaintext.clear();
+        state.records = TlsRecordLayer::new(config.version.is_datagram());
+        state.engine.start(config, resume)?;
+        self.flush(&mut state)?;
+
+        let mut payload = Vec::new();
+        let mut waited = 0u32;
+        let mut since_retransmit = 0u32;
+        loop {
+            match self.inner.receive(TLS_POLL_INTERVAL_MS)? {
+                Some(data) => {
+                    state.records.push_received(&data)?;
+                    since_retransmit = 0;
+                }
+                None => {
+                    waited += TLS_POLL_INTERVAL_MS;
+                    since_retransmit += TLS_POLL_INTERVAL_MS;
+                    if waited >= config.handshake_timeout_ms {
+                        return Err(TlsError::Timeout);
+                    }
+                    if config.version.is_datagram() && since_retransmit >= DTLS_RETRANSMIT_MS {
+                        since_retransmit = 0;
+                        state.engine.retransmit()?;
+                        self.flush(&mut state)?;
+                    }
+                    continue;
+                }
+            }
+
+            while let Some(header) = state.records.next_record(&mut payload)? {
+                if header.content_type == TlsContentType::Alert {
+                    return Err(Self::alert_error(&payload));
+                }
+                let status = state.engine.handle_record(header.content_type, header.epoch, header.sequence, &payload)?;
+                self.flush(&mut state)?;
+                if status == TlsHandshakeStatus::Complete {
+                    return self.finish_handshake(&mut state, config, resume);
+                }
+            }
+        }
+    }
+
+    /// Check the server key against the pins, taking it from the resumed
+    /// session when the server did not resend its certificate
+    fn finish_handshake(&self, state: &mut TlsState<E>, config: &TlsConfig, resume: Option<&TlsSession>) -> Result<(), TlsError> {
+        let resumed = state.engine.resumed();
+        if matches!(config.credentials, TlsCredentials::X509 { .. }) && !config.pinned_keys.is_empty() {
+            let key = state.engine.peer_key_hash()
+                .or_else(|| if resumed { resume.and_then(|session| session.peer_key_hash) } else { None });
+            if !key.map_or(false, |key| config.pinned_keys.contains(&key)) {
+                self.send_alert(state, TLS_ALERT_LEVEL_FATAL, TLS_ALERT_BAD_CERTIFICATE).ok();
+                return Err(TlsError::PinMismatch);
+            }
+        }
+        state.resumed = resumed;
+        state.established = true;
+        Ok(())
+    }
+
+    /// Send close_notify; the session cannot carry data afterwards
+    pub fn close(&self) -> Result<(), TlsError> {
+        let mut state = self.state.borrow_mut();
+        if !state.established || state.closed {
+            return Ok(());
+        }
+        state.closed = true;
+        self.send_alert(&mut state, TLS_ALERT_LEVEL_WARNING, TLS_ALERT_CLOSE_NOTIFY)
+    }
+
+    pub fn is_established(&self) -> bool {
+        let state = self.state.borrow();
+        state.established && !state.closed
+    }
+
+    /// Whether the last handshake resumed a cached session
+    pub fn is_resumed(&self) -> bool {
+        self.state.borrow().resumed
+    }
+
+    /// Resumption state of the current session, for keeping across reboots
+    pub fn session(&self) -> Option<TlsSession> {
+        self.state.borrow().session.clone()
+    }
+
+    fn flush(&self, state: &mut TlsState<E>) -> Result<(), TlsError> {
+        let mut payload = [0u8; TLS_MAX_RECORD_PAYLOAD];
+        let mut record: Vec<u8, { TLS_MAX_RECORD_PAYLOAD + DTLS_RECORD_HEADER_LEN }> = Vec::new();
+        while let Some((content_type, length)) = state.engine.poll_output(&mut payload) {
+            let epoch = state.engine.write_epoch();
+            state.records.encode(content_type, epoch, &payload[..length], &mut record)?;
+            self.inner.send(&record)?;
+        }
+        Ok(())
+    }
+
+    fn send_alert(&self, state: &mut TlsState<E>, level: u8, description: u8) -> Result<(), TlsError> {
+        let mut payload = [0u8; TLS_MAX_RECORD_PAYLOAD];
+        let mut record: Vec<u8, { TLS_MAX_RECORD_PAYLOAD + DTLS_RECORD_HEADER_LEN }> = Vec::new();
+        let epoch = state.engine.write_epoch();
+        // Alerts are protected like application data once keys are in use
+        let length = if state.established {
+            state.engine.seal(&[level, description], &mut payload)?
+        } else {
+            payload[..2].copy_from_slice(&[level, description]);
+            2
+        };
+        state.records.encode(TlsContentType::Alert, epoch, &payload[..length], &mut record)?;
+        self.inner.send(&record)?;
+        Ok(())
+    }
+
+    fn alert_error(payload: &[u8]) -> TlsError {
+        match payload {
+            [_, TLS_ALERT_CLOSE_NOTIFY] => TlsError::Closed,
+            [_, description] => TlsError::Alert(*description),
+            _ => TlsError::BadRecord,
+        }
+    }
+
+    fn read_records(&self, state: &mut TlsState<E>) -> Result<(), TlsError> {
+        let mut payload = Vec::new();
+        let mut plaintext = [0u8; TLS_MAX_RECORD_PAYLOAD];
+        while let Some(header) = state.records.next_record(&mut payload)? {
+            match header.content_type {
+                TlsContentType::ApplicationData => {
+                    let length = match state.engine.open(header.epoch, header.sequence, &payload, &mut plaintext) {
+                        Ok(length) => length,
+                        // Forged or damaged datagrams are dropped
+                        Err(TlsError::DecryptFailed) if state.records.datagram => continue,
+                        Err(error) => return Err(error),
+                    };
+                    state.plaintext.extend_from_slice(&plaintext[..length]).map_err(|_| TlsError::RecordOverflow)?;
+                }
+                TlsContentType::Alert => {
+                    let length = state.engine.open(header.epoch, header.sequence, &payload, &mut plaintext)?;
+                    state.closed = true;
+                    return Err(Self::alert_error(&plaintext[..length]));
+                }
+                content_type => {
+                    // Post-handshake messages: tickets, key updates, DTLS
+                    // retransmissions of the server's last flight
+                    state.engine.handle_record(content_type, header.epoch, header.sequence, &payload)?;
+                    self.flush(state)?;
+                    if let Some(session) = state.engine.session() {
+                        state.session = Some(session);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a, E: TlsEngine> MqttTransport for TlsTransport<'a, E> {
+    fn send(&self, data: &[u8]) -> Result<(), MqttError> {
+        let mut state = self.state.borrow_mut();
+        if !state.established || state.closed {
+            return Err(MqttError::TransportError);
+        }
+        let mut payload = [0u8; TLS_MAX_RECORD_PAYLOAD];
+        let mut record: Vec<u8, { TLS_MAX_RECORD_PAYLOAD + DTLS_RECORD_HEADER_LEN }> = Vec::new();
+        for fragment in data.chunks(TLS_MAX_FRAGMENT) {
+            let length = state.engine.seal(fragment, &mut payload).map_err(|_| MqttError::TransportError)?;
+            let epoch = state.engine.write_epoch();
+            state.records.encode(TlsContentType::ApplicationData, epoch, &payload[..length], &mut record)
+                .map_err(|_| MqttError::TransportError)?;
+            self.inner.send(&record)?;
+        }
+        Ok(())
+    }
+
+    fn receive(&self, timeout_ms: u32) -> Result<Option<Vec<u8, 256>>, MqttError> {
+        let mut state = self.state.borrow_mut();
+        if !state.established {
+            return Err(MqttError::TransportError);
+        }
+        if state.plaintext.is_empty() && !state.closed {
+            // Records may already be buffered behind the last handshake record
+            self.read_records(&mut state).map_err(|_| MqttError::TransportError)?;
+        }
+        if state.plaintext.is_empty() && !state.closed {
+            if let Some(data) = self.inner.receive(timeout_ms)? {
+                state.records.push_received(&data).map_err(|_| MqttError::TransportError)?;
+                self.read_records(&mut state).map_err(|_| MqttError::TransportError)?;
+            }
+        }
+        if state.plaintext.is_empty() {
+            return Ok(None);
+        }
+
+        let mut out = Vec::new();
+        let count = state.plaintext.len().min(out.capacity());
+        out.extend_from_slice(&state.plaintext[..count]).ok();
+        let remaining = state.plaintext.len() - count;
+        state.plaintext.copy_within(count.., 0);
+        state.plaintext.truncate(remaining);
+        Ok(Some(out))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tls_header(content_type: u8, length: usize) -> [u8; TLS_RECORD_HEADER_LEN] {
+        let length = (length as u16).to_be_bytes();
+        [content_type, 0x03, 0x03, length[0], length[1]]
+    }
+
+    #[test]
+    fn test_record_round_trip() {
+        let mut sender = TlsRecordLayer::new(false);
+        let mut receiver = TlsRecordLayer::new(false);
+        let mut wire: Vec<u8, 64> = Vec::new();
+        sender.encode(TlsContentType::Handshake, 0, b"client hello", &mut wire).unwrap();
+
+        let mut payload = Vec::new();
+        receiver.push_received(&wire).unwrap();
+        let header = receiver.next_record(&mut payload).unwrap().unwrap();
+        assert_eq!(header.content_type, TlsContentType::Handshake);
+        assert_eq!(&payload[..], b"client hello");
+        assert_eq!(receiver.next_record(&mut payload), Ok(None));
+    }
+
+    #[test]
+    fn test_truncated_tls_record_waits_for_more_data() {
+        let mut records = TlsRecordLayer::new(false);
+        let mut payload = Vec::new();
+
+        // Partial header
+        records.push_received(&tls_header(23, 8)[..3]).unwrap();
+        assert_eq!(records.next_record(&mut payload), Ok(None));
+
+        // Full header, partial body
+        records.push_received(&tls_header(23, 8)[3..]).unwrap();
+        records.push_received(b"appl").unwrap();
+        assert_eq!(records.next_record(&mut payload), Ok(None));
+
+        records.push_received(b"data").unwrap();
+        let header = records.next_record(&mut payload).unwrap().unwrap();
+        assert_eq!(header.content_type, TlsContentType::ApplicationData);
+        assert_eq!(&payload[..], b"appldata");
+    }
+
+    #[test]
+    fn test_truncated_dtls_record_is_dropped() {
+        let mut sender = TlsRecordLayer::new(true);
+        let mut records = TlsRecordLayer::new(true);
+        let mut payload = Vec::new();
+        let mut wire: Vec<u8, 64> = Vec::new();
+
+        sender.encode(TlsContentType::Handshake, 0, b"hello verify", &mut wire).unwrap();
+        records.push_received(&wire[..wire.len() - 1]).unwrap();
+        assert_eq!(records.next_record(&mut payload), Ok(None));
+        records.push_received(&wire[..DTLS_RECORD_HEADER_LEN - 1]).unwrap();
+        assert_eq!(records.next_record(&mut payload), Ok(None));
+
+        // The next whole datagram is not mixed with the dropped bytes
+        sender.encode(TlsContentType::Handshake, 0, b"server hello", &mut wire).unwrap();
+        records.push_received(&wire).unwrap();
+        let header = records.next_record(&mut payload).unwrap().unwrap();
+        assert_eq!(header.sequence, 1);
+        assert_eq!(&payload[..], b"server hello");
+    }
+
+    #[test]
+    fn test_oversized_records_are_rejected() {
+        let mut payload = Vec::new();
+
+        let mut records = TlsRecordLayer::new(false);
+        records.push_received(&tls_header(23, TLS_MAX_RECORD_PAYLOAD + 1)).unwrap();
+        assert_eq!(records.next_record(&mut payload), Err(TlsError::RecordOverflow));
+
+        // DTLS drops the datagram instead of failing the session
+        let mut records = TlsRecordLayer::new(true);
+        let length = ((TLS_MAX_RECORD_PAYLOAD + 1) as u16).to_be_bytes();
+        records.push_received(&[23, 0xFE, 0xFD, 0, 1, 0, 0, 0, 0, 0, 0, length[0], length[1]]).unwrap();
+        assert_eq!(records.next_record(&mut payload), Ok(None));
+
+        let mut sender = TlsRecordLayer::new(false);
+        let mut wire: Vec<u8, 1024> = Vec::new();
+        let oversized = [0u8; TLS_MAX_RECORD_PAYLOAD + 1];
+        assert_eq!(sender.encode(TlsContentType::ApplicationData, 0, &oversized, &mut wire), Err(TlsError::RecordOverflow));
+        // Output buffer too small for the record
+        let mut small: Vec<u8, 8> = Vec::new();
+        assert_eq!(sender.encode(TlsContentType::ApplicationData, 0, b"too long", &mut small), Err(TlsError::RecordOverflow));
+
+        let mut records = TlsRecordLayer::new(false);
+        assert_eq!(records.push_received(&[0u8; TLS_RECEIVE_BUFFER + 1]), Err(TlsError::RecordOverflow));
+    }
+
+    #[test]
+    fn test_unknown_content_type() {
+        let mut payload = Vec::new();
+
+        let mut records = TlsRecordLayer::new(false);
+        records.push_received(&tls_header(99, 0)).unwrap();
+        assert_eq!(records.next_record(&mut payload), Err(TlsError::BadRecord));
+
+        let mut records = TlsRecordLayer::new(true);
+        records.push_received(&[99, 0xFE, 0xFD, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]).unwrap();
+        assert_eq!(records.next_record(&mut payload), Ok(None));
+    }
+}
@@ -0,0 +1,542 @@
+//! Board Descriptions
+//!
+//! Peripheral instances of a board and the buses its radios hang off,
+//! either from a built-in table or parsed from a small devicetree-style
+//! source, so the same firmware runs on several RISC-V boards.
+//!
+//! The source format is the subset of DTS a Zephyr board file uses for
+//! these peripherals:
+//!
+//! ```text
+//! /dts-v1/;
+//! / {
+//!     model = "sifive,hifive1-revb";
+//!     clock-frequency = <16000000>;
+//!     memory-size = <0x4000>;
+//!     soc {
+//!         uart1: serial@10023000 { current-speed = <115200>; interrupts = <4>; };
+//!         spi1: spi@10024000 { interrupts = <6>; status = "disabled"; };
+//!     };
+//!     chosen { wifi-uart = &uart1; };
+//! };
+//! ```
+//!
+//! Devices are recognised by node name (`serial`/`uart`, `spi`, `i2c`,
+//! `gpio`) at any depth; other nodes and properties are ignored.
+
+use heapless::Vec;
+use crate::{I2CBus, InterruptType, PowerMode, SpiBus, SystemConfig, Uart};
+
+/// Peripherals a board description can hold
+pub const MAX_BOARD_DEVICES: usize = 16;
+
+/// Baud rate of a UART without `current-speed`
+pub const DEFAULT_BAUD_RATE: u32 = 115_200;
+
+/// Nodes nested deeper than this are rejected
+const MAX_DEPTH: u32 = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeviceKind {
+    Uart,
+    Spi,
+    I2c,
+    Gpio,
+}
+
+/// One peripheral instance
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BoardDevice<'a> {
+    /// Label other nodes refer to it by, or the node name
+    pub label: &'a str,
+    pub kind: DeviceKind,
+    pub base_address: usize,
+    pub interrupt: Option<u32>,
+    /// Baud rate of a UART, bus clock of SPI/I2C
+    pub frequency_hz: Option<u32>,
+    pub enabled: bool,
+}
+
+impl<'a> BoardDevice<'a> {
+    pub const fn new(label: &'a str, kind: DeviceKind, base_address: usize, interrupt: u32) -> Self {
+        Self { label, kind, base_address, interrupt: Some(interrupt), frequency_hz: None, enabled: true }
+    }
+}
+
+/// Which bus each transport's radio is wired to (the `chosen` node)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TransportBindings<'a> {
+    /// UART of the AT-command WiFi module
+    pub wifi_uart: Option<&'a str>,
+    /// SPI bus of the LoRa radio
+    pub lora_spi: Option<&'a str>,
+    /// I2C bus of the BLE module
+    pub ble_i2c: Option<&'a str>,
+    /// 7-bit I2C address of the BLE module
+    pub ble_address: Option<u8>,
+}
+
+/// Compiled-in board description
+pub struct BoardTable {
+    pub model: &'static str,
+    pub system: SystemConfig,
+    pub devices: &'static [BoardDevice<'static>],
+    pub bindings: TransportBindings<'static>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BoardError {
+    /// Malformed source at the given line
+    Syntax { line: u32 },
+    /// Property value of the wrong type or out of range
+    InvalidValue { line: u32 },
+    TooManyDevices,
+    DuplicateLabel,
+    /// A device node without a unit address or `reg`
+    MissingAddress,
+    /// A binding names no enabled device of the right kind
+    UnresolvedReference,
+}
+
+/// Layout the MultiOS HAL has always assumed
+pub static MULTIOS_RISCV_IOT: BoardTable = BoardTable {
+    model: "multios,riscv-iot",
+    system: SystemConfig {
+        core_frequency_hz: 50_000_000,
+        memory_size: 512 * 1024,
+        interrupt_controller: InterruptType::PLIC,
+        power_management: PowerMode::Normal,
+    },
+    devices: &[
+        BoardDevice::new("uart0", DeviceKind::Uart, super::UART0_BASE, super::UART_INTERRUPT),
+        BoardDevice::new("gpio0", DeviceKind::Gpio, super::GPIO_BASE, super::GPIO_INTERRUPT),
+        BoardDevice::new("i2c0", DeviceKind::I2c, super::I2C0_BASE, super::I2C_INTERRUPT),
+        BoardDevice::new("spi0", DeviceKind::Spi, super::SPI0_BASE, super::SPI_INTERRUPT),
+    ],
+    bindings: TransportBindings { wifi_uart: Some("uart0"), lora_spi: Some("spi0"), ble_i2c: None, ble_address: None },
+};
+
+/// SiFive HiFive1 Rev B (FE310-G002); the ESP32 WiFi module sits on UART1
+pub static HIFIVE1_REVB: BoardTable = BoardTable {
+    model: "sifive,hifive1-revb",
+    system: SystemConfig {
+        core_frequency_hz: 16_000_000,
+        memory_size: 16 * 1024,
+        interrupt_controller: InterruptType::PLIC,
+        power_management: PowerMode::Normal,
+    },
+    devices: &[
+        BoardDevice::new("gpio0", DeviceKind::Gpio, 0x1001_2000, 8),
+        BoardDevice::new("uart0", DeviceKind::Uart, 0x1001_3000, 3),
+        BoardDevice::new("i2c0", DeviceKind::I2c, 0x1001_6000, 52),
+        BoardDevice::new("uart1", DeviceKind::Uart, 0x1002_3000, 4),
+        BoardDevice::new("spi1", DeviceKind::Spi, 0x1002_4000, 6),
+        BoardDevice::new("spi2", DeviceKind::Spi, 0x1003_4000, 7),
+    ],
+    bindings: TransportBindings { wifi_uart: Some("uart1"), lora_spi: None, ble_i2c: None, ble_address: None },
+};
+
+/// Boards `BoardConfig::builtin()` knows by model
+pub static BUILTIN_BOARDS: &[&BoardTable] = &[&MULTIOS_RISCV_IOT, &HIFIVE1_REVB];
+
+/// Peripherals and transport bindings of the board firmware runs on
+#[derive(Clone, Debug)]
+pub struct BoardConfig<'a> {
+    pub model: &'a str,
+    pub system: SystemConfig,
+    pub devices: Vec<BoardDevice<'a>, MAX_BOARD_DEVICES>,
+    pub bindings: TransportBindings<'a>,
+}
+
+impl BoardConfig<'static> {
+    pub fn from_table(table: &'static BoardTable) -> Result<Self, BoardError> {
+        let mut devices = Vec::new();
+        for device in table.devices {
+            devices.push(*device).map_err(|_| BoardError::TooManyDevices)?;
+        }
+        let board = Self { model: table.model, system: table.system, devices, bindings: table.bindings };
+        board.validate()?;
+        Ok(board)
+    }
+
+    /// Built-in board with the given model
+    pub fn builtin(model: &str) -> Option<Self> {
+        let table = BUILTIN_BOARDS.iter().find(|table| table.model == model)?;
+        Self::from_table(table).ok()
+    }
+}
+
+impl<'a> BoardConfig<'a> {
+    /// Parse a devicetree-style board source
+    pub fn parse(source: &'a str) -> Result<Self, BoardError> {
+        let mut board = Self {
+            model: "",
+            system: SystemConfig::default(),
+            devices: Vec::new(),
+            bindings: TransportBindings::default(),
+        };
+        let mut parser = Parser::new(source);
+
+        parser.expect(Token::Punct(b'/'))?;
+        if parser.peek()? == Some(Token::Word("dts-v1")) {
+            parser.next()?;
+            parser.expect(Token::Punct(b'/'))?;
+            parser.expect(Token::Punct(b';'))?;
+            parser.expect(Token::Punct(b'/'))?;
+        }
+        parser.expect(Token::Punct(b'{'))?;
+        parser.node_body(&mut board, Node::Root, 0)?;
+        parser.expect(Token::Punct(b';'))?;
+        if parser.next()?.is_some() {
+            return Err(parser.syntax_error());
+        }
+
+        board.validate()?;
+        Ok(board)
+    }
+
+    fn validate(&self) -> Result<(), BoardError> {
+        for (index, device) in self.devices.iter().enumerate() {
+            if device.base_address == 0 {
+                return Err(BoardError::MissingAddress);
+            }
+            if self.devices[..index].iter().any(|other| other.label == device.label) {
+                return Err(BoardError::DuplicateLabel);
+            }
+        }
+
+        let bindings = [
+            (self.bindings.wifi_uart, DeviceKind::Uart),
+            (self.bindings.lora_spi, DeviceKind::Spi),
+            (self.bindings.ble_i2c, DeviceKind::I2c),
+        ];
+        for (label, kind) in bindings {
+            if let Some(label) = label {
+                if self.device(label).map_or(true, |device| device.kind != kind) {
+                    return Err(BoardError::UnresolvedReference);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Enabled device with the given label
+    pub fn device(&self, label: &str) -> Option<&BoardDevice<'a>> {
+        self.devices.iter().find(|device| device.enabled && device.label == label)
+    }
+
+    /// Enabled devices of one kind
+    pub fn devices_of(&self, kind: DeviceKind) -> impl Iterator<Item = &BoardDevice<'a>> {
+        self.devices.iter().filter(move |device| device.enabled && device.kind == kind)
+    }
+
+    fn device_of(&self, label: &str, kind: DeviceKind) -> Option<&BoardDevice<'a>> {
+        self.device(label).filter(|device| device.kind == kind)
+    }
+
+    /// Driver for a UART at its configured baud rate; `init()` it with
+    /// `self.system` before use
+    pub fn uart(&self, label: &str) -> Option<Uart> {
+        let device = self.device_of(label, DeviceKind::Uart)?;
+        Some(Uart::new(device.base_address, device.frequency_hz.unwrap_or(DEFAULT_BAUD_RATE)))
+    }
+
+    pub fn spi(&self, label: &str) -> Option<SpiBus> {
+        self.device_of(label, DeviceKind::Spi).map(|device| SpiBus::new(device.base_address))
+    }
+
+    pub fn i2c(&self, label: &str) -> Option<I2CBus> {
+        self.device_of(label, DeviceKind::I2c).map(|device| I2CBus::new(device.base_address))
+    }
+
+    pub fn wifi_uart(&self) -> Option<Uart> {
+        self.uart(self.bindings.wifi_uart?)
+    }
+
+    pub fn lora_spi(&self) -> Option<SpiBus> {
+        self.spi(self.bindings.lora_spi?)
+    }
+
+    /// BLE module's bus and address, if both are given
+    pub fn ble_i2c(&self) -> Option<(I2CBus, u8)> {
+        let address = self.bindings.ble_address?;
+        Some((self.i2c(self.bindings.ble_i2c?)?, address))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Token<'a> {
+    Word(&'a str),
+    Str(&'a str),
+    /// Contents of `< ... >`
+    Cells(&'a str),
+    Ref(&'a str),
+    Punct(u8),
+}
+
+#[derive(Clone, Copy)]
+enum Node {
+    Root,
+    Chosen,
+    Device(usize),
+    Other,
+}
+
+fn is_word_byte(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || matches!(byte, b',' | b'.' | b'_' | b'+' | b'-' | b'@' | b'#')
+}
+
+fn parse_number(text: &str) -> Option<u32> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    pos: usize,
+    line: u32,
+    peeked: Option<Token<'a>>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(source: &'a str) -> Self {
+        Self { source, pos: 0, line: 1, peeked: None }
+    }
+
+    fn syntax_error(&self) -> BoardError {
+        BoardError::Syntax { line: self.line }
+    }
+
+    fn value_error(&self) -> BoardError {
+        BoardError::InvalidValue { line: self.line }
+    }
+
+    fn skip_space(&mut self) {
+        let bytes = self.source.as_bytes();
+        while let Some(&byte) = bytes.get(self.pos) {
+            let rest = &self.source[self.pos..];
+            if byte.is_ascii_whitespace() {
+                self.line += (byte == b'\n') as u32;
+                self.pos += 1;
+            } else if rest.starts_with("//") {
+                self.pos += rest.find('\n').unwrap_or(rest.len());
+            } else if rest.starts_with("/*") {
+                let end = rest.find("*/").map_or(rest.len(), |end| end + 2);
+                self.line += rest[..end].matches('\n').count() as u32;
+                self.pos += end;
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Text up to `close`, consuming the delimiter
+    fn delimited(&mut self, close: char) -> Result<&'a str, BoardError> {
+        let rest = &self.source[self.pos..];
+        let end = rest.find(close).ok_or(self.syntax_error())?;
+        self.line += rest[..end].matches('\n').count() as u32;
+        self.pos += end + 1;
+        Ok(&rest[..end])
+    }
+
+    fn word(&mut self) -> &'a str {
+        let start = self.pos;
+        while self.source.as_bytes().get(self.pos).map_or(false, |&byte| is_word_byte(byte)) {
+            self.pos += 1;
+        }
+        &self.source[start..self.pos]
+    }
+
+    fn lex(&mut self) -> Result<Option<Token<'a>>, BoardError> {
+        self.skip_space();
+        let Some(&byte) = self.source.as_bytes().get(self.pos) else {
+            return Ok(None);
+        };
+        let token = match byte {
+            b'"' => {
+                self.pos += 1;
+                Token::Str(self.delimited('"')?)
+            }
+            b'<' => {
+                self.pos += 1;
+                Token::Cells(self.delimited('>')?)
+            }
+            b'&' => {
+                self.pos += 1;
+                let label = self.word();
+                if label.is_empty() {
+                    return Err(self.syntax_error());
+                }
+                Token::Ref(label)
+            }
+            b'{' | b'}' | b';' | b'=' | b':' | b'/' => {
+                self.pos += 1;
+                Token::Punct(byte)
+            }
+            _ if is_word_byte(byte) => Token::Word(self.word()),
+            _ => return Err(self.syntax_error()),
+        };
+        Ok(Some(token))
+    }
+
+    fn peek(&mut self) -> Result<Option<Token<'a>>, BoardError> {
+        if self.peeked.is_none() {
+            self.peeked = self.lex()?;
+        }
+        Ok(self.peeked)
+    }
+
+    fn next(&mut self) -> Result<Option<Token<'a>>, BoardError> {
+        match self.peeked.take() {
+            Some(token) => Ok(Some(token)),
+            None => self.lex(),
+        }
+    }
+
+    fn next_required(&mut self) -> Result<Token<'a>, BoardError> {
+        self.next()?.ok_or(self.syntax_error())
+    }
+
+    fn expect(&mut self, expected: Token<'a>) -> Result<(), BoardError> {
+        if self.next_required()? != expected {
+            return Err(self.syntax_error());
+        }
+        Ok(())
+    }
+
+    /// Properties and children of a node, through its closing brace
+    fn node_body(&mut self, board: &mut BoardConfig<'a>, node: Node, depth: u32) -> Result<(), BoardError> {
+        if depth > MAX_DEPTH {
+            return Err(self.syntax_error());
+        }
+        loop {
+            let first = match self.next_required()? {
+                Token::Punct(b'}') => return Ok(()),
+                Token::Word(word) => word,
+                _ => return Err(self.syntax_error()),
+            };
+            let (label, name) = if self.peek()? == Some(Token::Punct(b':')) {
+                self.next()?;
+                match self.next_required()? {
+                    Token::Word(name) => (Some(first), name),
+                    _ => return Err(self.syntax_error()),
+                }
+            } else {
+                (None, first)
+            };
+
+            match self.next_required()? {
+                Token::Punct(b'=') if label.is_none() => {
+                    let value = self.next_required()?;
+                    self.expect(Token::Punct(b';'))?;
+                    self.property(board, node, name, value)?;
+                }
+                // Boolean property
+                Token::Punct(b';') if label.is_none() => {}
+                Token::Punct(b'{') => {
+                    let child = self.open_node(board, node, label, name)?;
+                    self.node_body(board, child, depth + 1)?;
+                    self.expect(Token::Punct(b';'))?;
+                }
+                _ => return Err(self.syntax_error()),
+            }
+        }
+    }
+
+    fn open_node(&self, board: &mut BoardConfig<'a>, parent: Node, label: Option<&'a str>, name: &'a str) -> Result<Node, BoardError> {
+        let (base, unit_address) = match name.split_once('@') {
+            Some((base, unit_address)) => (base, Some(unit_address)),
+            None => (name, None),
+        };
+        let kind = match (parent, base) {
+            (Node::Root, "chosen") => return Ok(Node::Chosen),
+            (Node::Device(_), _) | (Node::Chosen, _) => return Ok(Node::Other),
+            (_, "serial") | (_, "uart") => DeviceKind::Uart,
+            (_, "spi") => DeviceKind::Spi,
+            (_, "i2c") => DeviceKind::I2c,
+            (_, "gpio") => DeviceKind::Gpio,
+            _ => return Ok(Node::Other),
+        };
+
+        let base_address = match unit_address {
+            Some(unit_address) => usize::from_str_radix(unit_address, 16).map_err(|_| self.syntax_error())?,
+            None => 0,
+        };
+        let device = BoardDevice {
+            label: label.unwrap_or(name),
+            kind,
+            base_address,
+            interrupt: None,
+            frequency_hz: None,
+            enabled: true,
+        };
+        board.devices.push(device).map_err(|_| BoardError::TooManyDevices)?;
+        Ok(Node::Device(board.devices.len() - 1))
+    }
+
+    fn cell(&self, value: Token<'a>) -> Result<u32, BoardError> {
+        match value {
+            Token::Cells(cells) => cells.split_whitespace().next().and_then(parse_number).ok_or(self.value_error()),
+            _ => Err(self.value_error()),
+        }
+    }
+
+    fn string(&self, value: Token<'a>) -> Result<&'a str, BoardError> {
+        match value {
+            Token::Str(text) => Ok(text),
+            _ => Err(self.value_error()),
+        }
+    }
+
+    fn reference(&self, value: Token<'a>) -> Result<&'a str, BoardError> {
+        match value {
+            Token::Ref(label) => Ok(label),
+            _ => Err(self.value_error()),
+        }
+    }
+
+    fn property(&self, board: &mut BoardConfig<'a>, node: Node, name: &str, value: Token<'a>) -> Result<(), BoardError> {
+        match node {
+            Node::Root => match name {
+                "model" => board.model = self.string(value)?,
+                "clock-frequency" => board.system.core_frequency_hz = self.cell(value)?,
+                "memory-size" => board.system.memory_size = self.cell(value)?,
+                "interrupt-controller" => {
+                    board.system.interrupt_controller = match self.string(value)? {
+                        "clint" => InterruptType::CLINT,
+                        "plic" => InterruptType::PLIC,
+                        _ => return Err(self.value_error()),
+                    }
+                }
+                _ => {}
+            },
+            Node::Chosen => match name {
+                "wifi-uart" => board.bindings.wifi_uart = Some(self.reference(value)?),
+                "lora-spi" => board.bindings.lora_spi = Some(self.reference(value)?),
+                "ble-i2c" => board.bindings.ble_i2c = Some(self.reference(value)?),
+                "ble-address" => {
+                    let address = self.cell(value)?;
+                    if address > 0x7F {
+                        return Err(self.value_error());
+                    }
+                    board.bindings.ble_address = Some(address as u8);
+                }
+                _ => {}
+            },
+            Node::Device(index) => {
+                let device = &mut board.devices[index];
+                match name {
+                    "reg" => device.base_address = self.cell(value)? as usize,
+                    "interrupts" => device.interrupt = Some(self.cell(value)?),
+                    "current-speed" | "clock-frequency" => device.frequency_hz = Some(self.cell(value)?),
+                    "status" => device.enabled = matches!(self.string(value)?, "okay" | "ok"),
+                    _ => {}
+                }
+            }
+            Node::Other => {}
+        }
+        Ok(())
+    }
+}
@@ -9,6 +9,10 @@ use core::arch::asm;
 use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
 use core::cell::RefCell;
 
+pub mod board;
+
+pub use board::{BoardConfig, BoardDevice, BoardError, BoardTable, DeviceKind, TransportBindings};
+
 // Re-export commonly used types
 pub use embedded_hal::digital::{OutputPin, InputPin, StatefulOutputPin};
 pub use embedded_hal::spi::{SpiBus, SpiBusWrite, SpiBusRead};
@@ -36,7 +40,7 @@ const ADC_INTERRUPT: u32 = 0x06;
 const PWM_INTERRUPT: u32 = 0x07;
 
 /// System configuration for RISC-V
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug)]
 pub struct SystemConfig {
     pub core_frequency_hz: u32,
     pub memory_size: u32,
@@ -232,6 +236,7 @@ impl GpioDriver {
 }
 
 /// UART driver for serial communication
+#[derive(Clone, Copy, Debug)]
 pub struct Uart {
     base_address: usize,
     baud_rate: u32,
@@ -310,6 +315,7 @@ impl Uart {
 }
 
 /// I2C bus driver
+#[derive(Clone, Copy, Debug)]
 pub struct I2CBus {
     base_address: usize,
 }
@@ -381,6 +387,7 @@ impl I2CBus {
 }
 
 /// SPI bus driver
+#[derive(Clone, Copy, Debug)]
 pub struct SpiBus {
     base_address: usize,
 }
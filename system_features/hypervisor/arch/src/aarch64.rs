@@ -0,0 +1,614 @@
+//! AArch64 Guest Backend
+//!
+//! Runs AArch64 guests at EL1 under an EL2 hypervisor. Guest memory is
+//! translated by stage-2 tables, interrupts go through a virtual GICv3, the
+//! EL1 virtual timer is offset per VM and the physical timer is emulated,
+//! and secondary vCPUs are powered on and off by the guest through PSCI
+//! calls over HVC or SMC. The memory map follows the common `virt` machine
+//! layout so unmodified kernels and device trees boot.
+
+use crate::{VmId, VmConfig, HypervisorError};
+use super::stage2::{Stage2Tables, Stage2Attributes};
+use super::vgic::{VirtualGic, GICD_SIZE, GICR_STRIDE, GIC_VTIMER_PPI, GIC_PTIMER_PPI};
+
+use alloc::vec::Vec;
+
+/// Distributor base address in the guest memory map
+pub const AARCH64_GICD_BASE: u64 = 0x0800_0000;
+/// First redistributor base address in the guest memory map
+pub const AARCH64_GICR_BASE: u64 = 0x080A_0000;
+/// Guest RAM base address
+pub const AARCH64_RAM_BASE: u64 = 0x4000_0000;
+/// SPIs provided by the virtual GIC
+pub const AARCH64_SPI_COUNT: u32 = 96;
+
+/// HCR_EL2 for guests: stage-2 enabled, IRQ/FIQ/SError routed to EL2,
+/// WFI and SMC trapped, EL1 is AArch64
+pub const AARCH64_HCR_EL2: u64 = (1 << 0) | (1 << 1) | (1 << 3) | (1 << 4) | (1 << 5) | (1 << 13) | (1 << 19) | (1 << 31);
+
+/// PSTATE of a vCPU entering the guest: EL1h with D, A, I and F masked
+const PSTATE_EL1H_MASKED: u64 = 0x3C5;
+
+// Exception classes of ESR_EL2
+const EC_WFX: u32 = 0x01;
+const EC_HVC64: u32 = 0x16;
+const EC_SMC64: u32 = 0x17;
+const EC_SYSREG: u32 = 0x18;
+const EC_IABT_LOWER: u32 = 0x20;
+const EC_DABT_LOWER: u32 = 0x24;
+
+/// PSCI function IDs (SMC32 numbering; the SMC64 variants set bit 30)
+pub mod psci {
+    pub const PSCI_VERSION: u32 = 0x8400_0000;
+    pub const CPU_SUSPEND: u32 = 0x8400_0001;
+    pub const CPU_OFF: u32 = 0x8400_0002;
+    pub const CPU_ON: u32 = 0x8400_0003;
+    pub const AFFINITY_INFO: u32 = 0x8400_0004;
+    pub const MIGRATE_INFO_TYPE: u32 = 0x8400_0006;
+    pub const SYSTEM_OFF: u32 = 0x8400_0008;
+    pub const SYSTEM_RESET: u32 = 0x8400_0009;
+    pub const PSCI_FEATURES: u32 = 0x8400_000A;
+
+    pub const SUCCESS: i64 = 0;
+    pub const NOT_SUPPORTED: i64 = -1;
+    pub const INVALID_PARAMETERS: i64 = -2;
+    pub const ALREADY_ON: i64 = -4;
+    pub const ON_PENDING: i64 = -5;
+    pub const INVALID_ADDRESS: i64 = -9;
+
+    /// PSCI 1.0
+    pub const VERSION_1_0: i64 = 0x0001_0000;
+    /// MIGRATE_INFO_TYPE: no trusted OS needs migrating
+    pub const TOS_NOT_PRESENT: i64 = 2;
+}
+
+/// Register state of an AArch64 vCPU
+#[derive(Debug, Clone, Copy)]
+pub struct Aarch64Regs {
+    /// X0-X30
+    pub x: [u64; 31],
+    pub sp_el0: u64,
+    pub sp_el1: u64,
+    pub pc: u64,
+    pub pstate: u64,
+    pub elr_el1: u64,
+    pub spsr_el1: u64,
+    pub sctlr_el1: u64,
+    pub ttbr0_el1: u64,
+    pub ttbr1_el1: u64,
+    pub tcr_el1: u64,
+    pub vbar_el1: u64,
+    /// Virtual MPIDR presented through VMPIDR_EL2
+    pub mpidr: u64,
+}
+
+impl Aarch64Regs {
+    fn reset(mpidr: u64) -> Self {
+        Aarch64Regs {
+            x: [0; 31],
+            sp_el0: 0,
+            sp_el1: 0,
+            pc: 0,
+            pstate: PSTATE_EL1H_MASKED,
+            elr_el1: 0,
+            spsr_el1: 0,
+            // RES1 bits, MMU and caches off
+            sctlr_el1: 0x30C5_0830,
+            ttbr0_el1: 0,
+            ttbr1_el1: 0,
+            tcr_el1: 0,
+            vbar_el1: 0,
+            mpidr,
+        }
+    }
+
+    /// Read a general purpose register; register 31 is XZR
+    pub fn read_gpr(&self, index: u8) -> u64 {
+        self.x.get(index as usize).copied().unwrap_or(0)
+    }
+
+    /// Write a general purpose register; writes to XZR are discarded
+    pub fn write_gpr(&mut self, index: u8, value: u64) {
+        if let Some(register) = self.x.get_mut(index as usize) {
+            *register = value;
+        }
+    }
+}
+
+/// Generic timer of a vCPU (CNTV_* or an emulated CNTP_*)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GenericTimer {
+    /// Subtracted from the host counter (CNTVOFF_EL2)
+    pub offset: u64,
+    pub ctl: u64,
+    pub cval: u64,
+}
+
+const TIMER_CTL_ENABLE: u64 = 1 << 0;
+const TIMER_CTL_IMASK: u64 = 1 << 1;
+const TIMER_CTL_ISTATUS: u64 = 1 << 2;
+
+impl GenericTimer {
+    /// Counter value the guest sees
+    pub fn guest_counter(&self, host_counter: u64) -> u64 {
+        host_counter.wrapping_sub(self.offset)
+    }
+
+    /// Whether the timer condition is met and the interrupt is unmasked
+    pub fn is_firing(&self, host_counter: u64) -> bool {
+        self.ctl & TIMER_CTL_ENABLE != 0
+            && self.ctl & TIMER_CTL_IMASK == 0
+            && self.guest_counter(host_counter) >= self.cval
+    }
+
+    /// CTL value with ISTATUS reflecting the timer condition
+    pub fn read_ctl(&self, host_counter: u64) -> u64 {
+        let met = self.ctl & TIMER_CTL_ENABLE != 0 && self.guest_counter(host_counter) >= self.cval;
+        (self.ctl & !TIMER_CTL_ISTATUS) | if met { TIMER_CTL_ISTATUS } else { 0 }
+    }
+
+    /// Host counter value at which the timer fires, if armed
+    pub fn deadline(&self) -> Option<u64> {
+        if self.ctl & TIMER_CTL_ENABLE != 0 && self.ctl & TIMER_CTL_IMASK == 0 {
+            Some(self.cval.wrapping_add(self.offset))
+        } else {
+            None
+        }
+    }
+}
+
+/// PSCI power state of a vCPU
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PsciPowerState {
+    On,
+    Off,
+}
+
+/// One AArch64 vCPU
+#[derive(Debug, Clone)]
+pub struct Aarch64Vcpu {
+    pub regs: Aarch64Regs,
+    pub power: PsciPowerState,
+    /// EL1 virtual timer, saved from hardware on exit
+    pub vtimer: GenericTimer,
+    /// EL1 physical timer, emulated by trapping CNTP_* accesses
+    pub ptimer: GenericTimer,
+}
+
+/// A decoded AArch64 trap to EL2
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Aarch64Exit {
+    /// WFI or WFE
+    Wfx { wfe: bool },
+    Hvc { imm: u16 },
+    Smc { imm: u16 },
+    /// Trapped MSR/MRS
+    SysReg { encoding: SysRegEncoding, rt: u8, read: bool },
+    /// Stage-2 fault on an instruction fetch
+    InstructionAbort { ipa: u64 },
+    /// Stage-2 fault on a data access; `valid` is false when the syndrome
+    /// does not describe the access (ISV clear)
+    DataAbort { ipa: u64, write: bool, size: u8, rt: u8, valid: bool },
+    /// Physical interrupt while the guest ran (taken through the IRQ
+    /// vector, not decoded from ESR_EL2)
+    Irq,
+    Unknown { ec: u32 },
+}
+
+/// op0, op1, CRn, CRm, op2 of a system register
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SysRegEncoding {
+    pub op0: u8,
+    pub op1: u8,
+    pub crn: u8,
+    pub crm: u8,
+    pub op2: u8,
+}
+
+impl SysRegEncoding {
+    pub const fn new(op0: u8, op1: u8, crn: u8, crm: u8, op2: u8) -> Self {
+        SysRegEncoding { op0, op1, crn, crm, op2 }
+    }
+}
+
+const ICC_SGI1R_EL1: SysRegEncoding = SysRegEncoding::new(3, 0, 12, 11, 5);
+const CNTP_CTL_EL0: SysRegEncoding = SysRegEncoding::new(3, 3, 14, 2, 1);
+const CNTP_CVAL_EL0: SysRegEncoding = SysRegEncoding::new(3, 3, 14, 2, 2);
+const CNTP_TVAL_EL0: SysRegEncoding = SysRegEncoding::new(3, 3, 14, 2, 0);
+
+impl Aarch64Exit {
+    /// Decode ESR_EL2, FAR_EL2 and HPFAR_EL2 after a trap from the guest
+    pub fn decode(esr: u64, far: u64, hpfar: u64) -> Self {
+        let ec = ((esr >> 26) & 0x3F) as u32;
+        let iss = esr & 0x1FF_FFFF;
+        // HPFAR holds IPA[47:12] in bits [43:4]; the page offset comes from FAR
+        let ipa = ((hpfar >> 4) << 12) | (far & 0xFFF);
+
+        match ec {
+            EC_WFX => Aarch64Exit::Wfx { wfe: iss & 1 != 0 },
+            EC_HVC64 => Aarch64Exit::Hvc { imm: iss as u16 },
+            EC_SMC64 => Aarch64Exit::Smc { imm: iss as u16 },
+            EC_SYSREG => Aarch64Exit::SysReg {
+                encoding: SysRegEncoding {
+                    op0: ((iss >> 20) & 0x3) as u8,
+                    op2: ((iss >> 17) & 0x7) as u8,
+                    op1: ((iss >> 14) & 0x7) as u8,
+                    crn: ((iss >> 10) & 0xF) as u8,
+                    crm: ((iss >> 1) & 0xF) as u8,
+                },
+                rt: ((iss >> 5) & 0x1F) as u8,
+                read: iss & 1 != 0,
+            },
+            EC_IABT_LOWER => Aarch64Exit::InstructionAbort { ipa },
+            EC_DABT_LOWER => Aarch64Exit::DataAbort {
+                ipa,
+                write: iss & (1 << 6) != 0,
+                size: 1 << ((iss >> 22) & 0x3),
+                rt: ((iss >> 16) & 0x1F) as u8,
+                valid: iss & (1 << 24) != 0,
+            },
+            _ => Aarch64Exit::Unknown { ec },
+        }
+    }
+}
+
+/// What the run loop does after an exit was handled
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Aarch64ExitAction {
+    /// Re-enter the guest
+    Resume,
+    /// WFI with nothing pending: block the vCPU until an interrupt arrives
+    Halt,
+    /// Access to an IPA with no stage-2 mapping and no emulated device;
+    /// the device model completes it and writes reads back to `rt`
+    Mmio { ipa: u64, write: bool, size: u8, rt: u8 },
+    /// Instruction fetch or unemulatable access to unmapped memory
+    Stage2Fault { ipa: u64 },
+    /// A PSCI CPU_ON powered on another vCPU, which must start running
+    StartVcpu { vcpu: usize },
+    /// The vCPU called PSCI CPU_OFF
+    VcpuOff,
+    SystemOff,
+    SystemReset,
+}
+
+/// AArch64 exit statistics
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Aarch64ExitStats {
+    pub wfx: u64,
+    pub hvc: u64,
+    pub smc: u64,
+    pub psci_calls: u64,
+    pub sysreg_traps: u64,
+    pub gic_mmio: u64,
+    pub device_mmio: u64,
+    pub stage2_faults: u64,
+    pub timer_interrupts: u64,
+}
+
+/// AArch64 VM state owned by the architecture backend
+#[derive(Debug)]
+pub struct Aarch64Vm {
+    vmid: u8,
+    ram_size: u64,
+    vcpus: Vec<Aarch64Vcpu>,
+    stage2: Stage2Tables,
+    gic: VirtualGic,
+    stats: Aarch64ExitStats,
+}
+
+impl Aarch64Vm {
+    /// Create the VM: stage-2 tables, a vGIC, and vCPU 0 powered on with
+    /// the secondaries off until the guest starts them through PSCI
+    pub fn new(vm_id: VmId, config: &VmConfig) -> Result<Self, HypervisorError> {
+        if config.vcpu_count == 0 || config.vcpu_count > 256 {
+            return Err(HypervisorError::TooManyVcpus);
+        }
+
+        let mpidrs: Vec<u64> = (0..config.vcpu_count).map(Self::mpidr_for).collect();
+        let vcpus = mpidrs
+            .iter()
+            .enumerate()
+            .map(|(index, &mpidr)| Aarch64Vcpu {
+                regs: Aarch64Regs::reset(mpidr),
+                power: if index == 0 { PsciPowerState::On } else { PsciPowerState::Off },
+                vtimer: GenericTimer::default(),
+                ptimer: GenericTimer::default(),
+            })
+            .collect();
+
+        info!("AArch64 backend for VM {}: {} vCPUs, GICv3", vm_id.0, config.vcpu_count);
+        Ok(Aarch64Vm {
+            vmid: vm_id.0 as u8,
+            ram_size: config.memory_mb * 1024 * 1024,
+            vcpus,
+            stage2: Stage2Tables::new(),
+            gic: VirtualGic::new(&mpidrs, AARCH64_SPI_COUNT),
+            stats: Aarch64ExitStats::default(),
+        })
+    }
+
+    /// MPIDR of a vCPU: 16 CPUs per cluster in Aff0, cluster in Aff1
+    pub fn mpidr_for(vcpu: usize) -> u64 {
+        (1 << 31) | (((vcpu / 16) as u64) << 8) | (vcpu % 16) as u64
+    }
+
+    /// Map guest RAM at `AARCH64_RAM_BASE` to host memory at `host_address`
+    pub fn map_ram(&mut self, host_address: u64) -> Result<(), HypervisorError> {
+        self.stage2.map(AARCH64_RAM_BASE, host_address, self.ram_size, Stage2Attributes::ram())
+    }
+
+    /// Set the entry point and boot arguments of vCPU 0 (e.g. X0 = DTB address)
+    pub fn set_boot_entry(&mut self, entry: u64, x0: u64) -> Result<(), HypervisorError> {
+        let vcpu = self.vcpus.get_mut(0).ok_or(HypervisorError::VcpuNotFound)?;
+        vcpu.regs.pc = entry;
+        vcpu.regs.x[0] = x0;
+        Ok(())
+    }
+
+    /// VTTBR_EL2 to load before entering this VM
+    pub fn vttbr(&self) -> u64 {
+        self.stage2.vttbr(self.vmid)
+    }
+
+    pub fn stage2(&self) -> &Stage2Tables {
+        &self.stage2
+    }
+
+    pub fn stage2_mut(&mut self) -> &mut Stage2Tables {
+        &mut self.stage2
+    }
+
+    pub fn gic(&self) -> &VirtualGic {
+        &self.gic
+    }
+
+    pub fn gic_mut(&mut self) -> &mut VirtualGic {
+        &mut self.gic
+    }
+
+    pub fn vcpu(&self, vcpu: usize) -> Option<&Aarch64Vcpu> {
+        self.vcpus.get(vcpu)
+    }
+
+    pub fn vcpu_mut(&mut self, vcpu: usize) -> Option<&mut Aarch64Vcpu> {
+        self.vcpus.get_mut(vcpu)
+    }
+
+    /// Give every vCPU's virtual timer the same offset so the guest counter
+    /// starts at zero when the VM boots
+    pub fn reset_virtual_counter(&mut self, host_counter: u64) {
+        for vcpu in &mut self.vcpus {
+            vcpu.vtimer.offset = host_counter;
+        }
+    }
+
+    /// Raise timer PPIs whose condition is met; call on every exit and when
+    /// a timer deadline expires
+    pub fn update_timers(&mut self, host_counter: u64) {
+        for index in 0..self.vcpus.len() {
+            let (vtimer, ptimer) = (self.vcpus[index].vtimer, self.vcpus[index].ptimer);
+            for (timer, ppi) in [(vtimer, GIC_VTIMER_PPI), (ptimer, GIC_PTIMER_PPI)] {
+                if timer.is_firing(host_counter) {
+                    self.gic.set_pending(ppi, index);
+                    self.stats.timer_interrupts += 1;
+                } else {
+                    self.gic.clear_pending(ppi, index);
+                }
+            }
+        }
+    }
+
+    /// Earliest host counter value at which a timer of `vcpu` fires
+    pub fn next_timer_deadline(&self, vcpu: usize) -> Option<u64> {
+        let vcpu = self.vcpus.get(vcpu)?;
+        match (vcpu.vtimer.deadline(), vcpu.ptimer.deadline()) {
+            (Some(v), Some(p)) => Some(v.min(p)),
+            (deadline, None) | (None, deadline) => deadline,
+        }
+    }
+
+    /// Handle a trap of `vcpu` to EL2
+    pub fn handle_exit(&mut self, vcpu: usize, exit: Aarch64Exit, host_counter: u64) -> Result<Aarch64ExitAction, HypervisorError> {
+        if vcpu >= self.vcpus.len() {
+            return Err(HypervisorError::VcpuNotFound);
+        }
+        self.update_timers(host_counter);
+
+        match exit {
+            Aarch64Exit::Wfx { wfe } => {
+                self.stats.wfx += 1;
+                self.vcpus[vcpu].regs.pc += 4;
+                if wfe || self.gic.highest_pending(vcpu).is_some() {
+                    Ok(Aarch64ExitAction::Resume)
+                } else {
+                    Ok(Aarch64ExitAction::Halt)
+                }
+            }
+            Aarch64Exit::Hvc { .. } => {
+                // ELR_EL2 already points past the HVC
+                self.stats.hvc += 1;
+                Ok(self.handle_psci(vcpu))
+            }
+            Aarch64Exit::Smc { .. } => {
+                // A trapped SMC returns to the SMC itself
+                self.stats.smc += 1;
+                self.vcpus[vcpu].regs.pc += 4;
+                Ok(self.handle_psci(vcpu))
+            }
+            Aarch64Exit::SysReg { encoding, rt, read } => {
+                self.stats.sysreg_traps += 1;
+                self.handle_sysreg(vcpu, encoding, rt, read, host_counter);
+                self.vcpus[vcpu].regs.pc += 4;
+                Ok(Aarch64ExitAction::Resume)
+            }
+            Aarch64Exit::DataAbort { ipa, write, size, rt, valid } => {
+                if !valid {
+                    self.stats.stage2_faults += 1;
+                    return Ok(Aarch64ExitAction::Stage2Fault { ipa });
+                }
+                if self.emulate_gic_access(vcpu, ipa, write, size, rt) {
+                    self.stats.gic_mmio += 1;
+                    self.vcpus[vcpu].regs.pc += 4;
+                    return Ok(Aarch64ExitAction::Resume);
+                }
+                if self.stage2.translate(ipa).is_some() {
+                    // Permission fault on mapped memory
+                    self.stats.stage2_faults += 1;
+                    return Ok(Aarch64ExitAction::Stage2Fault { ipa });
+                }
+                self.stats.device_mmio += 1;
+                self.vcpus[vcpu].regs.pc += 4;
+                Ok(Aarch64ExitAction::Mmio { ipa, write, size, rt })
+            }
+            Aarch64Exit::InstructionAbort { ipa } => {
+                self.stats.stage2_faults += 1;
+                Ok(Aarch64ExitAction::Stage2Fault { ipa })
+            }
+            Aarch64Exit::Irq => Ok(Aarch64ExitAction::Resume),
+            Aarch64Exit::Unknown { ec } => {
+                warn!("VM {} vCPU {}: unhandled exception class 0x{:02x}", self.vmid, vcpu, ec);
+                Err(HypervisorError::FeatureNotSupported)
+            }
+        }
+    }
+
+    pub fn stats(&self) -> Aarch64ExitStats {
+        self.stats
+    }
+
+    /// Handle a PSCI call in X0-X3, returning the result in X0
+    fn handle_psci(&mut self, vcpu: usize) -> Aarch64ExitAction {
+        self.stats.psci_calls += 1;
+        let regs = self.vcpus[vcpu].regs;
+        // SMC64 function IDs differ from SMC32 only in bit 30
+        let function = regs.x[0] as u32 & !(1 << 30);
+        let (target, entry, context) = (regs.x[1], regs.x[2], regs.x[3]);
+
+        let (result, action) = match function {
+            psci::PSCI_VERSION => (psci::VERSION_1_0, Aarch64ExitAction::Resume),
+            psci::CPU_SUSPEND => (psci::SUCCESS, Aarch64ExitAction::Halt),
+            psci::CPU_OFF => {
+                self.vcpus[vcpu].power = PsciPowerState::Off;
+                (psci::SUCCESS, Aarch64ExitAction::VcpuOff)
+            }
+            psci::CPU_ON => self.psci_cpu_on(target, entry, context),
+            psci::AFFINITY_INFO => match self.vcpu_by_mpidr(target) {
+                Some(index) if self.vcpus[index].power == PsciPowerState::On => (0, Aarch64ExitAction::Resume),
+                Some(_) => (1, Aarch64ExitAction::Resume),
+                None => (psci::INVALID_PARAMETERS, Aarch64ExitAction::Resume),
+            },
+            psci::MIGRATE_INFO_TYPE => (psci::TOS_NOT_PRESENT, Aarch64ExitAction::Resume),
+            psci::SYSTEM_OFF => (psci::SUCCESS, Aarch64ExitAction::SystemOff),
+            psci::SYSTEM_RESET => (psci::SUCCESS, Aarch64ExitAction::SystemReset),
+            psci::PSCI_FEATURES => {
+                let queried = target as u32 & !(1 << 30);
+                let supported = matches!(
+                    queried,
+                    psci::PSCI_VERSION | psci::CPU_SUSPEND | psci::CPU_OFF | psci::CPU_ON | psci::AFFINITY_INFO
+                        | psci::MIGRATE_INFO_TYPE | psci::SYSTEM_OFF | psci::SYSTEM_RESET | psci::PSCI_FEATURES
+                );
+                (if supported { psci::SUCCESS } else { psci::NOT_SUPPORTED }, Aarch64ExitAction::Resume)
+            }
+            _ => (psci::NOT_SUPPORTED, Aarch64ExitAction::Resume),
+        };
+
+        self.vcpus[vcpu].regs.x[0] = result as u64;
+        action
+    }
+
+    fn psci_cpu_on(&mut self, target: u64, entry: u64, context: u64) -> (i64, Aarch64ExitAction) {
+        let index = match self.vcpu_by_mpidr(target) {
+            Some(index) => index,
+            None => return (psci::INVALID_PARAMETERS, Aarch64ExitAction::Resume),
+        };
+        if self.vcpus[index].power == PsciPowerState::On {
+            return (psci::ALREADY_ON, Aarch64ExitAction::Resume);
+        }
+        if entry < AARCH64_RAM_BASE || entry >= AARCH64_RAM_BASE + self.ram_size {
+            return (psci::INVALID_ADDRESS, Aarch64ExitAction::Resume);
+        }
+
+        let target_vcpu = &mut self.vcpus[index];
+        target_vcpu.regs = Aarch64Regs::reset(target_vcpu.regs.mpidr);
+        target_vcpu.regs.pc = entry;
+        target_vcpu.regs.x[0] = context;
+        target_vcpu.power = PsciPowerState::On;
+        info!("VM {}: PSCI CPU_ON vCPU {} at 0x{:x}", self.vmid, index, entry);
+        (psci::SUCCESS, Aarch64ExitAction::StartVcpu { vcpu: index })
+    }
+
+    fn vcpu_by_mpidr(&self, mpidr: u64) -> Option<usize> {
+        let affinity = mpidr & 0xFF_00FF_FFFF;
+        self.vcpus.iter().position(|vcpu| vcpu.regs.mpidr & 0xFF_00FF_FFFF == affinity)
+    }
+
+    fn handle_sysreg(&mut self, vcpu: usize, encoding: SysRegEncoding, rt: u8, read: bool, host_counter: u64) {
+        let value = self.vcpus[vcpu].regs.read_gpr(rt);
+        let ptimer = &mut self.vcpus[vcpu].ptimer;
+
+        let result = match (encoding, read) {
+            (ICC_SGI1R_EL1, false) => {
+                self.gic.write_sgi1r(vcpu, value);
+                None
+            }
+            (CNTP_CTL_EL0, true) => Some(ptimer.read_ctl(host_counter)),
+            (CNTP_CTL_EL0, false) => {
+                ptimer.ctl = value & (TIMER_CTL_ENABLE | TIMER_CTL_IMASK);
+                None
+            }
+            (CNTP_CVAL_EL0, true) => Some(ptimer.cval),
+            (CNTP_CVAL_EL0, false) => {
+                ptimer.cval = value;
+                None
+            }
+            // TVAL is a signed 32-bit view of CVAL relative to the counter
+            (CNTP_TVAL_EL0, true) => Some(ptimer.cval.wrapping_sub(ptimer.guest_counter(host_counter)) & 0xFFFF_FFFF),
+            (CNTP_TVAL_EL0, false) => {
+                ptimer.cval = ptimer.guest_counter(host_counter).wrapping_add(value as i32 as i64 as u64);
+                None
+            }
+            // Other trapped registers read as zero and ignore writes
+            (_, true) => Some(0),
+            (_, false) => None,
+        };
+
+        if let Some(result) = result {
+            self.vcpus[vcpu].regs.write_gpr(rt, result);
+        }
+        self.update_timers(host_counter);
+    }
+
+    /// Emulate an access to the GIC distributor or a redistributor;
+    /// returns false if `ipa` is outside the GIC frames
+    fn emulate_gic_access(&mut self, vcpu: usize, ipa: u64, write: bool, size: u8, rt: u8) -> bool {
+        let gicr_end = AARCH64_GICR_BASE + GICR_STRIDE * self.vcpus.len() as u64;
+        let value = self.vcpus[vcpu].regs.read_gpr(rt);
+
+        let result = if (AARCH64_GICD_BASE..AARCH64_GICD_BASE + GICD_SIZE).contains(&ipa) {
+            let offset = ipa - AARCH64_GICD_BASE;
+            if write {
+                self.gic.distributor_write(vcpu, offset, size, value);
+                None
+            } else {
+                Some(self.gic.distributor_read(vcpu, offset, size))
+            }
+        } else if (AARCH64_GICR_BASE..gicr_end).contains(&ipa) {
+            let target = ((ipa - AARCH64_GICR_BASE) / GICR_STRIDE) as usize;
+            let offset = (ipa - AARCH64_GICR_BASE) % GICR_STRIDE;
+            if write {
+                self.gic.redistributor_write(target, offset, size, value);
+                None
+            } else {
+                Some(self.gic.redistributor_read(target, offset, size))
+            }
+        } else {
+            return false;
+        };
+
+        if let Some(result) = result {
+            self.vcpus[vcpu].regs.write_gpr(rt, result);
+        }
+        true
+    }
+}
@@ -0,0 +1,383 @@
+//! RISC-V Two-Stage Address Translation
+//!
+//! With the H extension a guest virtual address goes through two stages:
+//! the guest's own VS-stage tables (`vsatp`) produce a guest physical
+//! address, and the hypervisor's G-stage tables (`hgatp`) translate that to
+//! a host physical address. The G-stage uses Sv39x4: a 41-bit guest
+//! physical space whose 16KB root table has 2048 entries, with 1GB and 2MB
+//! superpages and 4K leaf pages below it.
+
+use crate::HypervisorError;
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+/// Size of a G-stage page
+pub const GSTAGE_PAGE_SIZE: u64 = 0x1000;
+/// Size of a level 1 megapage
+pub const GSTAGE_MEGAPAGE_SIZE: u64 = 0x20_0000;
+/// Bits of guest physical address space covered by Sv39x4
+pub const GSTAGE_GPA_BITS: u32 = 41;
+
+/// HGATP.MODE for Sv39x4
+const HGATP_MODE_SV39X4: u64 = 8;
+/// SATP.MODE for Sv39
+const SATP_MODE_SV39: u64 = 8;
+
+const ROOT_ENTRIES: usize = 2048;
+const ENTRIES_PER_TABLE: usize = 512;
+
+// PTE fields
+const PTE_V: u64 = 1 << 0;
+const PTE_R: u64 = 1 << 1;
+const PTE_W: u64 = 1 << 2;
+const PTE_X: u64 = 1 << 3;
+const PTE_U: u64 = 1 << 4;
+const PTE_A: u64 = 1 << 6;
+const PTE_D: u64 = 1 << 7;
+const PTE_PPN_SHIFT: u64 = 10;
+const PTE_PPN_MASK: u64 = 0x003F_FFFF_FFFF_FC00;
+
+/// Sv39x4 root table; hgatp requires 16KB alignment
+#[derive(Debug)]
+#[repr(C, align(16384))]
+struct RootTable([u64; ROOT_ENTRIES]);
+
+/// Level 1 or level 0 table
+#[derive(Debug)]
+#[repr(C, align(4096))]
+struct PageTable([u64; ENTRIES_PER_TABLE]);
+
+/// Access permissions of a G-stage mapping
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GStagePermissions {
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+}
+
+impl GStagePermissions {
+    /// Guest RAM
+    pub fn ram() -> Self {
+        GStagePermissions { read: true, write: true, execute: true }
+    }
+
+    /// Guest ROM or firmware
+    pub fn rom() -> Self {
+        GStagePermissions { read: true, write: false, execute: true }
+    }
+
+    /// Passed-through device registers
+    pub fn device() -> Self {
+        GStagePermissions { read: true, write: true, execute: false }
+    }
+
+    fn pte_bits(&self) -> u64 {
+        // G-stage accesses are checked as U-mode, so every leaf sets U;
+        // A and D are preset so hardware never has to update them
+        let mut bits = PTE_V | PTE_U | PTE_A | PTE_D;
+        if self.read {
+            bits |= PTE_R;
+        }
+        if self.write {
+            bits |= PTE_W;
+        }
+        if self.execute {
+            bits |= PTE_X;
+        }
+        bits
+    }
+
+    fn from_pte(pte: u64) -> Self {
+        GStagePermissions {
+            read: pte & PTE_R != 0,
+            write: pte & PTE_W != 0,
+            execute: pte & PTE_X != 0,
+        }
+    }
+}
+
+/// Result of a G-stage walk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GStageTranslation {
+    pub host_address: u64,
+    pub permissions: GStagePermissions,
+    /// Level of the leaf entry (2 = gigapage, 1 = megapage, 0 = page)
+    pub level: u8,
+}
+
+/// Result of a full VS-stage plus G-stage walk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TwoStageTranslation {
+    pub guest_physical: u64,
+    pub host_address: u64,
+}
+
+/// G-stage table statistics
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GStageStats {
+    pub tables: usize,
+    pub megapage_mappings: u64,
+    pub page_mappings: u64,
+    pub megapage_splits: u64,
+}
+
+/// Where a table lives: the root or an index into the level 1/0 list
+#[derive(Debug, Clone, Copy)]
+enum TableRef {
+    Root,
+    Table(usize),
+}
+
+/// G-stage translation tables of one VM
+#[derive(Debug)]
+pub struct GStageTables {
+    root: Box<RootTable>,
+    tables: Vec<Box<PageTable>>,
+    /// Table address to index in `tables`
+    table_index: BTreeMap<u64, usize>,
+    stats: GStageStats,
+}
+
+impl GStageTables {
+    /// Create empty tables
+    pub fn new() -> Self {
+        GStageTables {
+            root: Box::new(RootTable([0; ROOT_ENTRIES])),
+            tables: Vec::new(),
+            table_index: BTreeMap::new(),
+            stats: GStageStats::default(),
+        }
+    }
+
+    /// HGATP value: Sv39x4 mode, VMID and root table PPN
+    pub fn hgatp(&self, vmid: u16) -> u64 {
+        let root = &*self.root as *const _ as u64;
+        (HGATP_MODE_SV39X4 << 60) | (((vmid & 0x3FFF) as u64) << 44) | (root >> 12)
+    }
+
+    /// Map `size` bytes of guest physical space to host memory, using 2MB
+    /// megapages where alignment allows
+    pub fn map(&mut self, gpa: u64, host_address: u64, size: u64, permissions: GStagePermissions) -> Result<(), HypervisorError> {
+        if (gpa | host_address | size) & (GSTAGE_PAGE_SIZE - 1) != 0 || size == 0 {
+            return Err(HypervisorError::InvalidParameter);
+        }
+        if gpa + size > 1 << GSTAGE_GPA_BITS {
+            return Err(HypervisorError::InvalidParameter);
+        }
+
+        let bits = permissions.pte_bits();
+        let mut offset = 0;
+        while offset < size {
+            let (guest, host) = (gpa + offset, host_address + offset);
+            let l1 = self.next_table(TableRef::Root, Self::index(guest, 2))?;
+
+            let megapage_free = self.tables[l1].0[Self::index(guest, 1)] & PTE_V == 0;
+            if megapage_free && (guest | host) & (GSTAGE_MEGAPAGE_SIZE - 1) == 0 && size - offset >= GSTAGE_MEGAPAGE_SIZE {
+                self.tables[l1].0[Self::index(guest, 1)] = Self::pte(host, bits);
+                self.stats.megapage_mappings += 1;
+                offset += GSTAGE_MEGAPAGE_SIZE;
+            } else {
+                let l0 = self.next_table(TableRef::Table(l1), Self::index(guest, 1))?;
+                self.tables[l0].0[Self::index(guest, 0)] = Self::pte(host, bits);
+                self.stats.page_mappings += 1;
+                offset += GSTAGE_PAGE_SIZE;
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove the mappings of `size` bytes of guest physical space,
+    /// splitting megapages that are only partly unmapped. The caller must
+    /// issue HFENCE.GVMA for the VMID afterwards.
+    pub fn unmap(&mut self, gpa: u64, size: u64) -> Result<(), HypervisorError> {
+        if (gpa | size) & (GSTAGE_PAGE_SIZE - 1) != 0 {
+            return Err(HypervisorError::InvalidParameter);
+        }
+
+        let mut offset = 0;
+        while offset < size {
+            let guest = gpa + offset;
+            let l1 = match self.existing_table(TableRef::Root, Self::index(guest, 2)) {
+                Some(l1) => l1,
+                None => {
+                    offset += GSTAGE_PAGE_SIZE;
+                    continue;
+                }
+            };
+
+            let l1_index = Self::index(guest, 1);
+            let entry = self.tables[l1].0[l1_index];
+            if Self::is_leaf(entry) {
+                if guest & (GSTAGE_MEGAPAGE_SIZE - 1) == 0 && size - offset >= GSTAGE_MEGAPAGE_SIZE {
+                    self.tables[l1].0[l1_index] = 0;
+                    offset += GSTAGE_MEGAPAGE_SIZE;
+                    continue;
+                }
+                self.split_megapage(l1, l1_index);
+            }
+
+            if let Some(l0) = self.existing_table(TableRef::Table(l1), l1_index) {
+                self.tables[l0].0[Self::index(guest, 0)] = 0;
+            }
+            offset += GSTAGE_PAGE_SIZE;
+        }
+        Ok(())
+    }
+
+    /// Walk the G-stage tables for a guest physical address
+    pub fn translate(&self, gpa: u64) -> Option<GStageTranslation> {
+        if gpa >= 1 << GSTAGE_GPA_BITS {
+            return None;
+        }
+
+        let mut table = TableRef::Root;
+        for level in (0..=2u8).rev() {
+            let entry = self.entries(table)[Self::index(gpa, level)];
+            if entry & PTE_V == 0 {
+                return None;
+            }
+            if Self::is_leaf(entry) {
+                let page_mask = Self::level_size(level) - 1;
+                return Some(GStageTranslation {
+                    host_address: (Self::pte_address(entry) & !page_mask) | (gpa & page_mask),
+                    permissions: GStagePermissions::from_pte(entry),
+                    level,
+                });
+            }
+            table = TableRef::Table(*self.table_index.get(&Self::pte_address(entry))?);
+        }
+        None
+    }
+
+    /// Translate a guest virtual address through the guest's Sv39 tables
+    /// and then the G-stage, as hardware does for a VS-mode access. Used to
+    /// fetch trapped instructions when `htinst` gives no transformed
+    /// instruction. `read_host` reads a 64-bit PTE at a host address.
+    pub fn translate_guest_virtual<F>(&self, vsatp: u64, gva: u64, read_host: F) -> Option<TwoStageTranslation>
+    where
+        F: Fn(u64) -> Option<u64>,
+    {
+        let mode = vsatp >> 60;
+        if mode == 0 {
+            // Bare: guest virtual equals guest physical
+            let host = self.translate(gva)?;
+            return Some(TwoStageTranslation { guest_physical: gva, host_address: host.host_address });
+        }
+        if mode != SATP_MODE_SV39 {
+            return None;
+        }
+
+        let mut table_gpa = (vsatp & 0xFFF_FFFF_FFFF) << 12;
+        for level in (0..=2u8).rev() {
+            let pte_gpa = table_gpa + ((gva >> (12 + 9 * level as u64)) & 0x1FF) * 8;
+            // Every VS-stage PTE fetch is itself translated by the G-stage
+            let pte = read_host(self.translate(pte_gpa)?.host_address)?;
+            if pte & PTE_V == 0 {
+                return None;
+            }
+            if Self::is_leaf(pte) {
+                let page_mask = Self::level_size(level) - 1;
+                let guest_physical = (Self::pte_address(pte) & !page_mask) | (gva & page_mask);
+                let host = self.translate(guest_physical)?;
+                return Some(TwoStageTranslation { guest_physical, host_address: host.host_address });
+            }
+            table_gpa = Self::pte_address(pte);
+        }
+        None
+    }
+
+    pub fn stats(&self) -> GStageStats {
+        GStageStats {
+            tables: self.tables.len() + 1,
+            ..self.stats
+        }
+    }
+
+    fn entries(&self, table: TableRef) -> &[u64] {
+        match table {
+            TableRef::Root => &self.root.0,
+            TableRef::Table(index) => &self.tables[index].0,
+        }
+    }
+
+    fn entries_mut(&mut self, table: TableRef) -> &mut [u64] {
+        match table {
+            TableRef::Root => &mut self.root.0,
+            TableRef::Table(index) => &mut self.tables[index].0,
+        }
+    }
+
+    fn allocate_table(&mut self) -> usize {
+        let table = Box::new(PageTable([0; ENTRIES_PER_TABLE]));
+        let address = &*table as *const _ as u64;
+        self.tables.push(table);
+        self.table_index.insert(address, self.tables.len() - 1);
+        self.tables.len() - 1
+    }
+
+    fn table_address(&self, index: usize) -> u64 {
+        &*self.tables[index] as *const _ as u64
+    }
+
+    fn existing_table(&self, table: TableRef, index: usize) -> Option<usize> {
+        let entry = self.entries(table)[index];
+        if entry & PTE_V == 0 || Self::is_leaf(entry) {
+            return None;
+        }
+        self.table_index.get(&Self::pte_address(entry)).copied()
+    }
+
+    /// Next-level table behind an entry, creating it if the entry is invalid
+    fn next_table(&mut self, table: TableRef, index: usize) -> Result<usize, HypervisorError> {
+        if let Some(next) = self.existing_table(table, index) {
+            return Ok(next);
+        }
+        if self.entries(table)[index] & PTE_V != 0 {
+            // A superpage already maps this range
+            return Err(HypervisorError::InvalidParameter);
+        }
+        let next = self.allocate_table();
+        let address = self.table_address(next);
+        self.entries_mut(table)[index] = Self::pte(address, PTE_V);
+        Ok(next)
+    }
+
+    /// Replace a 2MB megapage with a level 0 table of equivalent pages
+    fn split_megapage(&mut self, l1: usize, index: usize) {
+        let megapage = self.tables[l1].0[index];
+        let base = Self::pte_address(megapage);
+        let bits = megapage & !PTE_PPN_MASK;
+
+        let l0 = self.allocate_table();
+        for page in 0..ENTRIES_PER_TABLE {
+            self.tables[l0].0[page] = Self::pte(base + page as u64 * GSTAGE_PAGE_SIZE, bits);
+        }
+        self.tables[l1].0[index] = Self::pte(self.table_address(l0), PTE_V);
+        self.stats.megapage_splits += 1;
+    }
+
+    fn pte(address: u64, bits: u64) -> u64 {
+        ((address >> 12) << PTE_PPN_SHIFT) | bits
+    }
+
+    fn pte_address(pte: u64) -> u64 {
+        ((pte & PTE_PPN_MASK) >> PTE_PPN_SHIFT) << 12
+    }
+
+    fn is_leaf(pte: u64) -> bool {
+        pte & (PTE_R | PTE_W | PTE_X) != 0
+    }
+
+    /// Table index of an address at a level; the Sv39x4 root uses 11 bits
+    fn index(address: u64, level: u8) -> usize {
+        let shift = 12 + 9 * level as u64;
+        let mask = if level == 2 { 0x7FF } else { 0x1FF };
+        ((address >> shift) & mask) as usize
+    }
+
+    fn level_size(level: u8) -> u64 {
+        GSTAGE_PAGE_SIZE << (9 * level as u64)
+    }
+}
@@ -0,0 +1,84 @@
+//! Guest Architecture Backends
+//!
+//! Selects the architecture-specific half of a VM from `VmConfig.arch`.
+//! x86 guests run on the VT-x/AMD-V paths in the CPU and memory modules;
+//! AArch64 guests get EL2 stage-2 translation, a virtual GICv3, generic
+//! timer virtualization and PSCI power control; RISC-V guests run in VS-mode
+//! under the H extension with G-stage translation, a virtual PLIC and SBI.
+
+use crate::{VmId, VmConfig, HypervisorError};
+use crate::core::VmArchitecture;
+
+mod aarch64;
+mod stage2;
+mod vgic;
+mod riscv;
+mod gstage;
+mod plic;
+
+pub use aarch64::*;
+pub use stage2::*;
+pub use vgic::*;
+pub use riscv::*;
+pub use gstage::*;
+pub use plic::*;
+
+/// Architecture-specific state of a VM
+#[derive(Debug)]
+pub enum ArchBackend {
+    /// VT-x / AMD-V, handled by the CPU and memory modules
+    X86,
+    /// EL2 backend for AArch64 guests
+    AArch64(Aarch64Vm),
+    /// HS-mode backend for RISC-V guests
+    RiscV(RiscvVm),
+}
+
+impl ArchBackend {
+    /// Create the backend for the architecture in the VM configuration
+    pub fn for_config(vm_id: VmId, config: &VmConfig) -> Result<Self, HypervisorError> {
+        match config.arch {
+            VmArchitecture::X86_64 | VmArchitecture::AMD64 => Ok(ArchBackend::X86),
+            VmArchitecture::AArch64 => Ok(ArchBackend::AArch64(Aarch64Vm::new(vm_id, config)?)),
+            VmArchitecture::RiscV64 => Ok(ArchBackend::RiscV(RiscvVm::new(vm_id, config)?)),
+            VmArchitecture::ARMv7 => Err(HypervisorError::FeatureNotSupported),
+        }
+    }
+
+    /// Guest architecture this backend runs
+    pub fn architecture(&self) -> VmArchitecture {
+        match self {
+            ArchBackend::X86 => VmArchitecture::X86_64,
+            ArchBackend::AArch64(_) => VmArchitecture::AArch64,
+            ArchBackend::RiscV(_) => VmArchitecture::RiscV64,
+        }
+    }
+
+    pub fn as_aarch64(&self) -> Option<&Aarch64Vm> {
+        match self {
+            ArchBackend::AArch64(vm) => Some(vm),
+            _ => None,
+        }
+    }
+
+    pub fn as_aarch64_mut(&mut self) -> Option<&mut Aarch64Vm> {
+        match self {
+            ArchBackend::AArch64(vm) => Some(vm),
+            _ => None,
+        }
+    }
+
+    pub fn as_riscv(&self) -> Option<&RiscvVm> {
+        match self {
+            ArchBackend::RiscV(vm) => Some(vm),
+            _ => None,
+        }
+    }
+
+    pub fn as_riscv_mut(&mut self) -> Option<&mut RiscvVm> {
+        match self {
+            ArchBackend::RiscV(vm) => Some(vm),
+            _ => None,
+        }
+    }
+}
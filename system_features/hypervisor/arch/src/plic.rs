@@ -0,0 +1,251 @@
+//! Virtual PLIC
+//!
+//! Emulates the RISC-V platform-level interrupt controller behind G-stage
+//! MMIO traps. The guest sees one S-mode context per hart; a context with
+//! a claimable interrupt raises the VS external interrupt (hvip.VSEIP) of
+//! its vCPU. The register layout matches the SiFive PLIC used by the `virt`
+//! machine, so guests that would otherwise probe an APLIC use the same
+//! device tree node.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Size of the PLIC register frame
+pub const PLIC_SIZE: u64 = 0x60_0000;
+/// Highest priority a source can be given
+pub const PLIC_MAX_PRIORITY: u32 = 7;
+/// Largest number of interrupt sources (source 0 is reserved)
+pub const PLIC_MAX_SOURCES: u32 = 1023;
+
+// Register offsets
+const PLIC_PRIORITY: u64 = 0x00_0000;
+const PLIC_PENDING: u64 = 0x00_1000;
+const PLIC_ENABLE: u64 = 0x00_2000;
+const PLIC_ENABLE_STRIDE: u64 = 0x80;
+const PLIC_CONTEXT: u64 = 0x20_0000;
+const PLIC_CONTEXT_STRIDE: u64 = 0x1000;
+const PLIC_CONTEXT_CLAIM: u64 = 0x4;
+
+/// Per-hart S-mode context
+#[derive(Debug, Clone)]
+struct PlicContext {
+    enabled: Vec<u32>,
+    threshold: u32,
+}
+
+/// Virtual PLIC statistics
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PlicStats {
+    pub raised: u64,
+    pub claims: u64,
+    pub completions: u64,
+    /// Claims that found nothing to deliver
+    pub spurious_claims: u64,
+}
+
+/// Virtual PLIC of one VM
+#[derive(Debug, Clone)]
+pub struct VirtualPlic {
+    sources: u32,
+    priority: Vec<u32>,
+    pending: Vec<u32>,
+    /// Claimed and not yet completed; the gateway holds new requests back
+    in_service: Vec<u32>,
+    /// Requests that arrived while the source was in service
+    deferred: Vec<u32>,
+    contexts: Vec<PlicContext>,
+    stats: PlicStats,
+}
+
+impl VirtualPlic {
+    /// Create a PLIC with `sources` interrupt sources and one context per hart
+    pub fn new(harts: usize, sources: u32) -> Self {
+        let sources = sources.min(PLIC_MAX_SOURCES);
+        let words = (sources as usize + 1 + 31) / 32;
+        VirtualPlic {
+            sources,
+            priority: vec![0; sources as usize + 1],
+            pending: vec![0; words],
+            in_service: vec![0; words],
+            deferred: vec![0; words],
+            contexts: vec![PlicContext { enabled: vec![0; words], threshold: 0 }; harts],
+            stats: PlicStats::default(),
+        }
+    }
+
+    /// Signal an interrupt request from a device
+    pub fn raise(&mut self, source: u32) {
+        if source == 0 || source > self.sources {
+            return;
+        }
+        self.stats.raised += 1;
+        if Self::test(&self.in_service, source) {
+            Self::set(&mut self.deferred, source, true);
+        } else {
+            Self::set(&mut self.pending, source, true);
+        }
+    }
+
+    /// Withdraw a request that has not been claimed yet
+    pub fn lower(&mut self, source: u32) {
+        if source == 0 || source > self.sources {
+            return;
+        }
+        Self::set(&mut self.pending, source, false);
+        Self::set(&mut self.deferred, source, false);
+    }
+
+    /// Highest-priority interrupt `hart` could claim; ties go to the lowest
+    /// source ID
+    pub fn highest_pending(&self, hart: usize) -> Option<u32> {
+        let context = self.contexts.get(hart)?;
+        let mut best: Option<(u32, u32)> = None;
+        for source in 1..=self.sources {
+            if !Self::test(&self.pending, source) || !Self::test(&context.enabled, source) {
+                continue;
+            }
+            let priority = self.priority[source as usize];
+            if priority <= context.threshold {
+                continue;
+            }
+            if best.map_or(true, |(_, best_priority)| priority > best_priority) {
+                best = Some((source, priority));
+            }
+        }
+        best.map(|(source, _)| source)
+    }
+
+    /// Whether the VS external interrupt of `hart` should be asserted
+    pub fn external_interrupt_pending(&self, hart: usize) -> bool {
+        self.highest_pending(hart).is_some()
+    }
+
+    /// Claim the highest-priority interrupt for `hart`; 0 if none
+    pub fn claim(&mut self, hart: usize) -> u32 {
+        match self.highest_pending(hart) {
+            Some(source) => {
+                Self::set(&mut self.pending, source, false);
+                Self::set(&mut self.in_service, source, true);
+                self.stats.claims += 1;
+                source
+            }
+            None => {
+                self.stats.spurious_claims += 1;
+                0
+            }
+        }
+    }
+
+    /// Complete a claimed interrupt, letting the gateway forward the next
+    /// request from the source
+    pub fn complete(&mut self, hart: usize, source: u32) {
+        if source == 0 || source > self.sources || !Self::test(&self.in_service, source) {
+            return;
+        }
+        // Completions for sources the context has disabled are ignored
+        let enabled = self.contexts.get(hart).map_or(false, |context| Self::test(&context.enabled, source));
+        if !enabled {
+            return;
+        }
+        Self::set(&mut self.in_service, source, false);
+        if Self::test(&self.deferred, source) {
+            Self::set(&mut self.deferred, source, false);
+            Self::set(&mut self.pending, source, true);
+        }
+        self.stats.completions += 1;
+    }
+
+    /// Guest read of a 32-bit PLIC register
+    pub fn read(&mut self, offset: u64) -> u32 {
+        match offset {
+            o if o < PLIC_PENDING => {
+                let source = (o / 4) as usize;
+                self.priority.get(source).copied().unwrap_or(0)
+            }
+            o if o < PLIC_ENABLE => {
+                let word = ((o - PLIC_PENDING) / 4) as usize;
+                self.pending.get(word).copied().unwrap_or(0)
+            }
+            o if o < PLIC_CONTEXT => {
+                let hart = ((o - PLIC_ENABLE) / PLIC_ENABLE_STRIDE) as usize;
+                let word = (((o - PLIC_ENABLE) % PLIC_ENABLE_STRIDE) / 4) as usize;
+                self.contexts.get(hart).and_then(|context| context.enabled.get(word).copied()).unwrap_or(0)
+            }
+            o => {
+                let hart = ((o - PLIC_CONTEXT) / PLIC_CONTEXT_STRIDE) as usize;
+                match (o - PLIC_CONTEXT) % PLIC_CONTEXT_STRIDE {
+                    0 => self.contexts.get(hart).map_or(0, |context| context.threshold),
+                    PLIC_CONTEXT_CLAIM if hart < self.contexts.len() => self.claim(hart),
+                    _ => 0,
+                }
+            }
+        }
+    }
+
+    /// Guest write of a 32-bit PLIC register
+    pub fn write(&mut self, offset: u64, value: u32) {
+        match offset {
+            o if o < PLIC_PENDING => {
+                let source = (o / 4) as usize;
+                // Source 0 does not exist and its priority is hardwired to 0
+                if source != 0 {
+                    if let Some(priority) = self.priority.get_mut(source) {
+                        *priority = value.min(PLIC_MAX_PRIORITY);
+                    }
+                }
+            }
+            // Pending bits are read-only
+            o if o < PLIC_ENABLE => {}
+            o if o < PLIC_CONTEXT => {
+                let hart = ((o - PLIC_ENABLE) / PLIC_ENABLE_STRIDE) as usize;
+                let word = (((o - PLIC_ENABLE) % PLIC_ENABLE_STRIDE) / 4) as usize;
+                let valid = self.valid_mask(word);
+                if let Some(enabled) = self.contexts.get_mut(hart).and_then(|context| context.enabled.get_mut(word)) {
+                    *enabled = value & valid;
+                }
+            }
+            o => {
+                let hart = ((o - PLIC_CONTEXT) / PLIC_CONTEXT_STRIDE) as usize;
+                match (o - PLIC_CONTEXT) % PLIC_CONTEXT_STRIDE {
+                    0 => {
+                        if let Some(context) = self.contexts.get_mut(hart) {
+                            context.threshold = value.min(PLIC_MAX_PRIORITY);
+                        }
+                    }
+                    PLIC_CONTEXT_CLAIM => self.complete(hart, value),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    pub fn stats(&self) -> PlicStats {
+        self.stats
+    }
+
+    /// Bits of an enable word that name existing sources
+    fn valid_mask(&self, word: usize) -> u32 {
+        let mut mask = 0;
+        for bit in 0..32 {
+            let source = word as u32 * 32 + bit;
+            if source != 0 && source <= self.sources {
+                mask |= 1 << bit;
+            }
+        }
+        mask
+    }
+
+    fn test(bits: &[u32], source: u32) -> bool {
+        bits.get(source as usize / 32).map_or(false, |word| word & (1 << (source % 32)) != 0)
+    }
+
+    fn set(bits: &mut [u32], source: u32, value: bool) {
+        if let Some(word) = bits.get_mut(source as usize / 32) {
+            if value {
+                *word |= 1 << (source % 32);
+            } else {
+                *word &= !(1 << (source % 32));
+            }
+        }
+    }
+}
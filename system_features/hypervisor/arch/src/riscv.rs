@@ -0,0 +1,736 @@
+//! RISC-V Guest Backend
+//!
+//! Runs RISC-V guests in VS-mode under an HS-mode hypervisor using the H
+//! extension. Entering the guest sets hstatus.SPV so `sret` switches to
+//! VS-mode; every trap returns to HS-mode, where this module decodes it.
+//! Guest memory is translated by the G-stage tables, external interrupts
+//! come from a virtual PLIC and are injected through hvip, and the guest's
+//! SBI calls are emulated here or forwarded to the host SBI implementation.
+//! The memory map follows the `virt` machine so unmodified kernels boot.
+
+use crate::{VmId, VmConfig, HypervisorError};
+use super::gstage::{GStageTables, GStagePermissions, GSTAGE_PAGE_SIZE};
+use super::plic::{VirtualPlic, PLIC_SIZE};
+
+use alloc::vec::Vec;
+
+/// PLIC base address in the guest memory map
+pub const RISCV_PLIC_BASE: u64 = 0x0C00_0000;
+/// Guest RAM base address
+pub const RISCV_RAM_BASE: u64 = 0x8000_0000;
+/// Interrupt sources provided by the virtual PLIC
+pub const RISCV_PLIC_SOURCES: u32 = 96;
+/// Harts a VM can have; SBI hart masks are handled as one 64-bit word
+pub const RISCV_MAX_HARTS: usize = 64;
+
+// hstatus fields
+const HSTATUS_SPV: u64 = 1 << 7;
+const HSTATUS_SPVP: u64 = 1 << 8;
+const HSTATUS_VTW: u64 = 1 << 21;
+const HSTATUS_VSXL_64: u64 = 2 << 32;
+// sstatus fields
+const SSTATUS_SPP: u64 = 1 << 8;
+const SSTATUS_SPIE: u64 = 1 << 5;
+
+// VS-level interrupt bits of hideleg/hvip
+const IRQ_VS_SOFT: u64 = 1 << 2;
+const IRQ_VS_TIMER: u64 = 1 << 6;
+const IRQ_VS_EXT: u64 = 1 << 10;
+
+/// Exceptions the guest handles itself: misaligned fetch, breakpoint,
+/// U-mode ecall and the VS-stage page faults
+pub const RISCV_HEDELEG: u64 = (1 << 0) | (1 << 3) | (1 << 8) | (1 << 12) | (1 << 13) | (1 << 15);
+/// Interrupts delivered straight to VS-mode
+pub const RISCV_HIDELEG: u64 = IRQ_VS_SOFT | IRQ_VS_TIMER | IRQ_VS_EXT;
+
+// scause values of traps taken to HS-mode
+const CAUSE_INTERRUPT: u64 = 1 << 63;
+const CAUSE_ECALL_VS: u64 = 10;
+const CAUSE_FETCH_GUEST_PAGE_FAULT: u64 = 20;
+const CAUSE_LOAD_GUEST_PAGE_FAULT: u64 = 21;
+const CAUSE_VIRTUAL_INSTRUCTION: u64 = 22;
+const CAUSE_STORE_GUEST_PAGE_FAULT: u64 = 23;
+
+const INSN_WFI: u64 = 0x1050_0073;
+const OPCODE_LOAD: u32 = 0x03;
+const OPCODE_STORE: u32 = 0x23;
+
+// Argument and return registers of an SBI call
+const REG_A0: usize = 10;
+const REG_A1: usize = 11;
+const REG_A6: usize = 16;
+const REG_A7: usize = 17;
+
+/// SBI extension IDs, function IDs and error codes
+pub mod sbi {
+    pub const EXT_LEGACY_PUTCHAR: u64 = 0x01;
+    pub const EXT_LEGACY_GETCHAR: u64 = 0x02;
+    pub const EXT_BASE: u64 = 0x10;
+    pub const EXT_TIME: u64 = 0x5449_4D45;
+    pub const EXT_IPI: u64 = 0x0073_5049;
+    pub const EXT_RFENCE: u64 = 0x5246_4E43;
+    pub const EXT_HSM: u64 = 0x0048_534D;
+    pub const EXT_SRST: u64 = 0x5352_5354;
+    pub const EXT_DBCN: u64 = 0x4442_434E;
+
+    pub const BASE_GET_SPEC_VERSION: u64 = 0;
+    pub const BASE_GET_IMPL_ID: u64 = 1;
+    pub const BASE_GET_IMPL_VERSION: u64 = 2;
+    pub const BASE_PROBE_EXTENSION: u64 = 3;
+
+    pub const HSM_HART_START: u64 = 0;
+    pub const HSM_HART_STOP: u64 = 1;
+    pub const HSM_HART_GET_STATUS: u64 = 2;
+    pub const HSM_HART_SUSPEND: u64 = 3;
+
+    pub const DBCN_CONSOLE_WRITE: u64 = 0;
+    pub const DBCN_CONSOLE_READ: u64 = 1;
+    pub const DBCN_CONSOLE_WRITE_BYTE: u64 = 2;
+
+    pub const SRST_SHUTDOWN: u64 = 0;
+
+    pub const SUCCESS: i64 = 0;
+    pub const ERR_FAILED: i64 = -1;
+    pub const ERR_NOT_SUPPORTED: i64 = -2;
+    pub const ERR_INVALID_PARAM: i64 = -3;
+    pub const ERR_INVALID_ADDRESS: i64 = -5;
+    pub const ERR_ALREADY_AVAILABLE: i64 = -6;
+
+    /// SBI 2.0
+    pub const SPEC_VERSION_2_0: u64 = 2 << 24;
+    /// Implementation ID reported to guests
+    pub const IMPL_ID_MULTIOS: u64 = 0x4D4F;
+}
+
+/// Register state of a RISC-V vCPU
+#[derive(Debug, Clone, Copy)]
+pub struct RiscvRegs {
+    /// x0-x31; x0 is kept at zero
+    pub x: [u64; 32],
+    /// Guest PC, saved from sepc on exit
+    pub pc: u64,
+    pub vsstatus: u64,
+    pub vsie: u64,
+    pub vstvec: u64,
+    pub vsscratch: u64,
+    pub vsepc: u64,
+    pub vscause: u64,
+    pub vstval: u64,
+    pub vsatp: u64,
+    /// Pending VS interrupts injected by the hypervisor
+    pub hvip: u64,
+}
+
+impl RiscvRegs {
+    fn reset() -> Self {
+        RiscvRegs {
+            x: [0; 32],
+            pc: 0,
+            vsstatus: 0,
+            vsie: 0,
+            vstvec: 0,
+            vsscratch: 0,
+            vsepc: 0,
+            vscause: 0,
+            vstval: 0,
+            vsatp: 0,
+            hvip: 0,
+        }
+    }
+
+    /// Write a general purpose register; writes to x0 are discarded
+    pub fn write_gpr(&mut self, index: u8, value: u64) {
+        if index != 0 {
+            if let Some(register) = self.x.get_mut(index as usize) {
+                *register = value;
+            }
+        }
+    }
+
+    fn set_sbi_result(&mut self, error: i64, value: u64) {
+        self.x[REG_A0] = error as u64;
+        self.x[REG_A1] = value;
+    }
+}
+
+/// SBI HSM state of a hart
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SbiHartState {
+    Started,
+    Stopped,
+}
+
+impl SbiHartState {
+    fn status_code(&self) -> u64 {
+        match self {
+            SbiHartState::Started => 0,
+            SbiHartState::Stopped => 1,
+        }
+    }
+}
+
+/// One RISC-V vCPU
+#[derive(Debug, Clone)]
+pub struct RiscvVcpu {
+    pub regs: RiscvRegs,
+    pub state: SbiHartState,
+    /// Guest time at which the SBI timer fires
+    pub timer_deadline: Option<u64>,
+}
+
+/// CSR values the run loop loads before `sret` into VS-mode
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RiscvEntryState {
+    pub hstatus: u64,
+    pub sstatus: u64,
+    pub sepc: u64,
+    pub hgatp: u64,
+    pub hedeleg: u64,
+    pub hideleg: u64,
+    pub hvip: u64,
+    pub htimedelta: u64,
+}
+
+/// Decoded load or store that faulted on unmapped guest memory
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MmioAccess {
+    pub write: bool,
+    /// Access size in bytes
+    pub size: u8,
+    /// Destination register of a load, source register of a store
+    pub reg: u8,
+    pub sign_extend: bool,
+    /// Length of the trapping instruction (2 if compressed)
+    pub length: u8,
+}
+
+impl MmioAccess {
+    /// Decode a load or store from `htinst`. A transformed instruction with
+    /// bit 1 clear stands for a compressed original.
+    pub fn decode(insn: u64) -> Option<Self> {
+        if insn & 1 == 0 {
+            return None;
+        }
+        let length = if insn & 0b10 == 0 { 2 } else { 4 };
+        let insn = (insn as u32) | 0b11;
+        let funct3 = (insn >> 12) & 0x7;
+
+        match insn & 0x7F {
+            OPCODE_LOAD if funct3 != 7 => Some(MmioAccess {
+                write: false,
+                size: 1 << (funct3 & 0x3),
+                reg: ((insn >> 7) & 0x1F) as u8,
+                sign_extend: funct3 < 4 && funct3 != 3,
+                length,
+            }),
+            OPCODE_STORE if funct3 < 4 => Some(MmioAccess {
+                write: true,
+                size: 1 << funct3,
+                reg: ((insn >> 20) & 0x1F) as u8,
+                sign_extend: false,
+                length,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Decode an instruction fetched from guest memory; compressed loads and
+    /// stores are not decoded
+    pub fn decode_fetched(insn: u32) -> Option<Self> {
+        if insn & 0b11 != 0b11 {
+            return None;
+        }
+        Self::decode(insn as u64)
+    }
+}
+
+/// A decoded trap from VS-mode to HS-mode
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RiscvExit {
+    /// Host interrupt while the guest ran
+    Interrupt { cause: u64 },
+    /// ECALL from VS-mode: an SBI call
+    SbiCall,
+    /// G-stage fault on an instruction fetch
+    FetchGuestPageFault { gpa: u64 },
+    /// G-stage fault on a load or store; `access` is None when `htinst`
+    /// gave no transformed instruction
+    GuestPageFault { gpa: u64, write: bool, access: Option<MmioAccess> },
+    /// Privileged instruction trapped by hstatus (e.g. WFI with VTW set)
+    VirtualInstruction { insn: u64 },
+    Unknown { cause: u64 },
+}
+
+impl RiscvExit {
+    /// Decode scause, stval, htval and htinst after a trap from the guest
+    pub fn decode(scause: u64, stval: u64, htval: u64, htinst: u64) -> Self {
+        if scause & CAUSE_INTERRUPT != 0 {
+            return RiscvExit::Interrupt { cause: scause & !CAUSE_INTERRUPT };
+        }
+        // htval holds GPA >> 2; the low bits come from stval
+        let gpa = (htval << 2) | (stval & 0x3);
+
+        match scause {
+            CAUSE_ECALL_VS => RiscvExit::SbiCall,
+            CAUSE_FETCH_GUEST_PAGE_FAULT => RiscvExit::FetchGuestPageFault { gpa },
+            CAUSE_LOAD_GUEST_PAGE_FAULT | CAUSE_STORE_GUEST_PAGE_FAULT => RiscvExit::GuestPageFault {
+                gpa,
+                write: scause == CAUSE_STORE_GUEST_PAGE_FAULT,
+                access: MmioAccess::decode(htinst),
+            },
+            CAUSE_VIRTUAL_INSTRUCTION => RiscvExit::VirtualInstruction { insn: stval },
+            cause => RiscvExit::Unknown { cause },
+        }
+    }
+}
+
+/// What the run loop does after an exit was handled
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RiscvExitAction {
+    /// Re-enter the guest
+    Resume,
+    /// WFI with nothing pending: block the vCPU until an interrupt arrives
+    Halt,
+    /// Access to a GPA with no G-stage mapping and no emulated device; the
+    /// device model completes it and writes reads back to `reg`
+    Mmio { gpa: u64, write: bool, size: u8, reg: u8, sign_extend: bool },
+    /// Fetch or undecodable access to unmapped memory
+    GuestPageFault { gpa: u64 },
+    /// Issue this SBI call to the host and return its result to the guest
+    /// with `complete_sbi_forward`
+    SbiForward { extension: u64, function: u64, args: [u64; 3] },
+    /// Remote harts in the mask must execute HFENCE.VVMA (or FENCE.I)
+    RemoteFence { hart_mask: u64 },
+    /// An SBI HART_START started another vCPU, which must start running
+    StartVcpu { vcpu: usize },
+    /// The vCPU called SBI HART_STOP
+    VcpuOff,
+    SystemOff,
+    SystemReset,
+}
+
+/// RISC-V exit statistics
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RiscvExitStats {
+    pub sbi_calls: u64,
+    pub sbi_forwarded: u64,
+    pub ipis: u64,
+    pub wfi: u64,
+    pub plic_mmio: u64,
+    pub device_mmio: u64,
+    pub guest_page_faults: u64,
+    pub timer_interrupts: u64,
+}
+
+/// RISC-V VM state owned by the architecture backend
+#[derive(Debug)]
+pub struct RiscvVm {
+    vmid: u16,
+    ram_size: u64,
+    /// Added to the host time to give the guest time
+    htimedelta: u64,
+    vcpus: Vec<RiscvVcpu>,
+    gstage: GStageTables,
+    plic: VirtualPlic,
+    stats: RiscvExitStats,
+}
+
+impl RiscvVm {
+    /// Create the VM: G-stage tables, a PLIC, and hart 0 started with the
+    /// secondaries stopped until the guest starts them through SBI HSM
+    pub fn new(vm_id: VmId, config: &VmConfig) -> Result<Self, HypervisorError> {
+        if config.vcpu_count == 0 || config.vcpu_count > RISCV_MAX_HARTS {
+            return Err(HypervisorError::TooManyVcpus);
+        }
+
+        let vcpus = (0..config.vcpu_count)
+            .map(|hart| RiscvVcpu {
+                regs: RiscvRegs::reset(),
+                state: if hart == 0 { SbiHartState::Started } else { SbiHartState::Stopped },
+                timer_deadline: None,
+            })
+            .collect();
+
+        info!("RISC-V backend for VM {}: {} harts, PLIC", vm_id.0, config.vcpu_count);
+        Ok(RiscvVm {
+            vmid: vm_id.0 as u16,
+            ram_size: config.memory_mb * 1024 * 1024,
+            htimedelta: 0,
+            vcpus,
+            gstage: GStageTables::new(),
+            plic: VirtualPlic::new(config.vcpu_count, RISCV_PLIC_SOURCES),
+            stats: RiscvExitStats::default(),
+        })
+    }
+
+    /// Map guest RAM at `RISCV_RAM_BASE` to host memory at `host_address`
+    pub fn map_ram(&mut self, host_address: u64) -> Result<(), HypervisorError> {
+        self.gstage.map(RISCV_RAM_BASE, host_address, self.ram_size, GStagePermissions::ram())
+    }
+
+    /// Set the entry point of hart 0; the kernel gets its hart ID in a0 and
+    /// the device tree address in a1
+    pub fn set_boot_entry(&mut self, entry: u64, dtb: u64) -> Result<(), HypervisorError> {
+        let vcpu = self.vcpus.get_mut(0).ok_or(HypervisorError::VcpuNotFound)?;
+        vcpu.regs.pc = entry;
+        vcpu.regs.x[REG_A0] = 0;
+        vcpu.regs.x[REG_A1] = dtb;
+        Ok(())
+    }
+
+    /// HGATP to load before entering this VM
+    pub fn hgatp(&self) -> u64 {
+        self.gstage.hgatp(self.vmid)
+    }
+
+    /// CSR state for switching `vcpu` from HS-mode into VS-mode. SPV and
+    /// SPP make `sret` land in VS-mode; VTW traps WFI so idle harts can be
+    /// descheduled.
+    pub fn entry_state(&self, vcpu: usize) -> Option<RiscvEntryState> {
+        let vcpu = self.vcpus.get(vcpu)?;
+        Some(RiscvEntryState {
+            hstatus: HSTATUS_VSXL_64 | HSTATUS_VTW | HSTATUS_SPVP | HSTATUS_SPV,
+            sstatus: SSTATUS_SPP | SSTATUS_SPIE,
+            sepc: vcpu.regs.pc,
+            hgatp: self.hgatp(),
+            hedeleg: RISCV_HEDELEG,
+            hideleg: RISCV_HIDELEG,
+            hvip: vcpu.regs.hvip,
+            htimedelta: self.htimedelta,
+        })
+    }
+
+    pub fn gstage(&self) -> &GStageTables {
+        &self.gstage
+    }
+
+    pub fn gstage_mut(&mut self) -> &mut GStageTables {
+        &mut self.gstage
+    }
+
+    pub fn plic(&self) -> &VirtualPlic {
+        &self.plic
+    }
+
+    pub fn vcpu(&self, vcpu: usize) -> Option<&RiscvVcpu> {
+        self.vcpus.get(vcpu)
+    }
+
+    pub fn vcpu_mut(&mut self, vcpu: usize) -> Option<&mut RiscvVcpu> {
+        self.vcpus.get_mut(vcpu)
+    }
+
+    /// Raise a device interrupt on the PLIC
+    pub fn raise_irq(&mut self, source: u32) {
+        self.plic.raise(source);
+        self.update_external_interrupts();
+    }
+
+    /// Lower a device interrupt that has not been claimed
+    pub fn lower_irq(&mut self, source: u32) {
+        self.plic.lower(source);
+        self.update_external_interrupts();
+    }
+
+    /// Start the guest time base at zero
+    pub fn reset_guest_time(&mut self, host_time: u64) {
+        self.htimedelta = host_time.wrapping_neg();
+    }
+
+    /// Assert or clear VSTIP for every hart against its SBI timer
+    pub fn update_timers(&mut self, host_time: u64) {
+        let guest_time = host_time.wrapping_add(self.htimedelta);
+        for vcpu in &mut self.vcpus {
+            match vcpu.timer_deadline {
+                Some(deadline) if guest_time >= deadline => {
+                    if vcpu.regs.hvip & IRQ_VS_TIMER == 0 {
+                        self.stats.timer_interrupts += 1;
+                    }
+                    vcpu.regs.hvip |= IRQ_VS_TIMER;
+                }
+                _ => vcpu.regs.hvip &= !IRQ_VS_TIMER,
+            }
+        }
+    }
+
+    /// Host time at which the SBI timer of `vcpu` fires
+    pub fn next_timer_deadline(&self, vcpu: usize) -> Option<u64> {
+        let deadline = self.vcpus.get(vcpu)?.timer_deadline?;
+        Some(deadline.wrapping_sub(self.htimedelta))
+    }
+
+    /// Fetch the instruction at the guest PC of `vcpu` through both
+    /// translation stages, for guest page faults without `htinst`; decode
+    /// it with `MmioAccess::decode_fetched`
+    pub fn fetch_trapped_instruction<F>(&self, vcpu: usize, read_host: F) -> Option<u32>
+    where
+        F: Fn(u64) -> Option<u64>,
+    {
+        let regs = &self.vcpus.get(vcpu)?.regs;
+        let translation = self.gstage.translate_guest_virtual(regs.vsatp, regs.pc & !0x7, &read_host)?;
+        let word = read_host(translation.host_address)?;
+        let insn = (word >> ((regs.pc & 0x7) * 8)) as u32;
+        if regs.pc & 0x7 == 6 && insn & 0b11 == 0b11 {
+            // A full-size instruction straddling the 8-byte word
+            let next = self.gstage.translate_guest_virtual(regs.vsatp, regs.pc + 2, &read_host)?;
+            let high = read_host(next.host_address)? as u32;
+            return Some((insn & 0xFFFF) | (high << 16));
+        }
+        Some(insn)
+    }
+
+    /// Handle a trap of `vcpu` to HS-mode
+    pub fn handle_exit(&mut self, vcpu: usize, exit: RiscvExit, host_time: u64) -> Result<RiscvExitAction, HypervisorError> {
+        if vcpu >= self.vcpus.len() {
+            return Err(HypervisorError::VcpuNotFound);
+        }
+        self.update_timers(host_time);
+        self.update_external_interrupts();
+
+        match exit {
+            RiscvExit::Interrupt { .. } => Ok(RiscvExitAction::Resume),
+            RiscvExit::SbiCall => {
+                self.stats.sbi_calls += 1;
+                // sepc points at the ECALL itself
+                self.vcpus[vcpu].regs.pc += 4;
+                Ok(self.handle_sbi(vcpu, host_time))
+            }
+            RiscvExit::VirtualInstruction { insn } if insn == INSN_WFI => {
+                self.stats.wfi += 1;
+                let regs = &mut self.vcpus[vcpu].regs;
+                regs.pc += 4;
+                // vsie enables VS interrupts at the bit positions of sie
+                if regs.hvip & (regs.vsie << 1) != 0 {
+                    Ok(RiscvExitAction::Resume)
+                } else {
+                    Ok(RiscvExitAction::Halt)
+                }
+            }
+            RiscvExit::GuestPageFault { gpa, write, access } => {
+                let access = match access {
+                    Some(access) if access.write == write => access,
+                    _ => {
+                        self.stats.guest_page_faults += 1;
+                        return Ok(RiscvExitAction::GuestPageFault { gpa });
+                    }
+                };
+                if self.emulate_plic_access(vcpu, gpa, access) {
+                    self.stats.plic_mmio += 1;
+                    self.vcpus[vcpu].regs.pc += access.length as u64;
+                    return Ok(RiscvExitAction::Resume);
+                }
+                if self.gstage.translate(gpa).is_some() {
+                    // Permission fault on mapped memory
+                    self.stats.guest_page_faults += 1;
+                    return Ok(RiscvExitAction::GuestPageFault { gpa });
+                }
+                self.stats.device_mmio += 1;
+                self.vcpus[vcpu].regs.pc += access.length as u64;
+                Ok(RiscvExitAction::Mmio {
+                    gpa,
+                    write,
+                    size: access.size,
+                    reg: access.reg,
+                    sign_extend: access.sign_extend,
+                })
+            }
+            RiscvExit::FetchGuestPageFault { gpa } => {
+                self.stats.guest_page_faults += 1;
+                Ok(RiscvExitAction::GuestPageFault { gpa })
+            }
+            RiscvExit::VirtualInstruction { insn } => {
+                warn!("VM {} hart {}: unhandled virtual instruction 0x{:08x}", self.vmid, vcpu, insn);
+                Err(HypervisorError::FeatureNotSupported)
+            }
+            RiscvExit::Unknown { cause } => {
+                warn!("VM {} hart {}: unhandled trap cause {}", self.vmid, vcpu, cause);
+                Err(HypervisorError::FeatureNotSupported)
+            }
+        }
+    }
+
+    /// Return the host's answer to a forwarded SBI call to the guest
+    pub fn complete_sbi_forward(&mut self, vcpu: usize, error: i64, value: u64) -> Result<(), HypervisorError> {
+        let vcpu = self.vcpus.get_mut(vcpu).ok_or(HypervisorError::VcpuNotFound)?;
+        vcpu.regs.set_sbi_result(error, value);
+        Ok(())
+    }
+
+    pub fn stats(&self) -> RiscvExitStats {
+        self.stats
+    }
+
+    /// Handle the SBI call in a0-a7, leaving the result in a0/a1
+    fn handle_sbi(&mut self, vcpu: usize, host_time: u64) -> RiscvExitAction {
+        let regs = self.vcpus[vcpu].regs;
+        let (extension, function) = (regs.x[REG_A7], regs.x[REG_A6]);
+        let args = [regs.x[REG_A0], regs.x[REG_A1], regs.x[REG_A0 + 2]];
+
+        let (error, value, action) = match extension {
+            sbi::EXT_BASE => {
+                let value = match function {
+                    sbi::BASE_GET_SPEC_VERSION => Some(sbi::SPEC_VERSION_2_0),
+                    sbi::BASE_GET_IMPL_ID => Some(sbi::IMPL_ID_MULTIOS),
+                    sbi::BASE_GET_IMPL_VERSION => Some(1),
+                    sbi::BASE_PROBE_EXTENSION => Some(Self::extension_supported(args[0]) as u64),
+                    // mvendorid, marchid and mimpid read as zero
+                    4..=6 => Some(0),
+                    _ => None,
+                };
+                match value {
+                    Some(value) => (sbi::SUCCESS, value, RiscvExitAction::Resume),
+                    None => (sbi::ERR_NOT_SUPPORTED, 0, RiscvExitAction::Resume),
+                }
+            }
+            sbi::EXT_TIME if function == 0 => {
+                let timer = &mut self.vcpus[vcpu];
+                timer.timer_deadline = Some(args[0]);
+                timer.regs.hvip &= !IRQ_VS_TIMER;
+                self.update_timers(host_time);
+                (sbi::SUCCESS, 0, RiscvExitAction::Resume)
+            }
+            sbi::EXT_IPI if function == 0 => match self.hart_mask(args[0], args[1]) {
+                Some(mask) => {
+                    for hart in (0..self.vcpus.len()).filter(|hart| mask & (1 << hart) != 0) {
+                        self.vcpus[hart].regs.hvip |= IRQ_VS_SOFT;
+                        self.stats.ipis += 1;
+                    }
+                    (sbi::SUCCESS, 0, RiscvExitAction::Resume)
+                }
+                None => (sbi::ERR_INVALID_PARAM, 0, RiscvExitAction::Resume),
+            },
+            sbi::EXT_RFENCE => match self.hart_mask(args[0], args[1]) {
+                Some(hart_mask) => (sbi::SUCCESS, 0, RiscvExitAction::RemoteFence { hart_mask }),
+                None => (sbi::ERR_INVALID_PARAM, 0, RiscvExitAction::Resume),
+            },
+            sbi::EXT_HSM => self.handle_hsm(vcpu, function, args),
+            sbi::EXT_SRST if function == 0 => match args[0] {
+                sbi::SRST_SHUTDOWN => (sbi::SUCCESS, 0, RiscvExitAction::SystemOff),
+                1 | 2 => (sbi::SUCCESS, 0, RiscvExitAction::SystemReset),
+                _ => (sbi::ERR_INVALID_PARAM, 0, RiscvExitAction::Resume),
+            },
+            sbi::EXT_DBCN | sbi::EXT_LEGACY_PUTCHAR | sbi::EXT_LEGACY_GETCHAR => {
+                return self.forward_console(vcpu, extension, function, args);
+            }
+            _ => (sbi::ERR_NOT_SUPPORTED, 0, RiscvExitAction::Resume),
+        };
+
+        self.vcpus[vcpu].regs.set_sbi_result(error, value);
+        action
+    }
+
+    fn handle_hsm(&mut self, vcpu: usize, function: u64, args: [u64; 3]) -> (i64, u64, RiscvExitAction) {
+        match function {
+            sbi::HSM_HART_START => {
+                let (hart, start, opaque) = (args[0] as usize, args[1], args[2]);
+                if hart >= self.vcpus.len() {
+                    return (sbi::ERR_INVALID_PARAM, 0, RiscvExitAction::Resume);
+                }
+                if self.vcpus[hart].state != SbiHartState::Stopped {
+                    return (sbi::ERR_ALREADY_AVAILABLE, 0, RiscvExitAction::Resume);
+                }
+                if start < RISCV_RAM_BASE || start >= RISCV_RAM_BASE + self.ram_size {
+                    return (sbi::ERR_INVALID_ADDRESS, 0, RiscvExitAction::Resume);
+                }
+
+                let target = &mut self.vcpus[hart];
+                target.regs = RiscvRegs::reset();
+                target.regs.pc = start;
+                target.regs.x[REG_A0] = hart as u64;
+                target.regs.x[REG_A1] = opaque;
+                target.timer_deadline = None;
+                target.state = SbiHartState::Started;
+                info!("VM {}: SBI HART_START hart {} at 0x{:x}", self.vmid, hart, start);
+                (sbi::SUCCESS, 0, RiscvExitAction::StartVcpu { vcpu: hart })
+            }
+            sbi::HSM_HART_STOP => {
+                self.vcpus[vcpu].state = SbiHartState::Stopped;
+                (sbi::SUCCESS, 0, RiscvExitAction::VcpuOff)
+            }
+            sbi::HSM_HART_GET_STATUS => match self.vcpus.get(args[0] as usize) {
+                Some(target) => (sbi::SUCCESS, target.state.status_code(), RiscvExitAction::Resume),
+                None => (sbi::ERR_INVALID_PARAM, 0, RiscvExitAction::Resume),
+            },
+            // Only retentive suspend is offered; it resumes like WFI
+            sbi::HSM_HART_SUSPEND if args[0] == 0 => (sbi::SUCCESS, 0, RiscvExitAction::Halt),
+            sbi::HSM_HART_SUSPEND => (sbi::ERR_NOT_SUPPORTED, 0, RiscvExitAction::Resume),
+            _ => (sbi::ERR_NOT_SUPPORTED, 0, RiscvExitAction::Resume),
+        }
+    }
+
+    /// Forward a console call to the host SBI. Buffer addresses are guest
+    /// physical, so they are translated and clamped to one page; SBI lets
+    /// the console write fewer bytes than asked.
+    fn forward_console(&mut self, vcpu: usize, extension: u64, function: u64, mut args: [u64; 3]) -> RiscvExitAction {
+        if extension == sbi::EXT_DBCN && matches!(function, sbi::DBCN_CONSOLE_WRITE | sbi::DBCN_CONSOLE_READ) {
+            let (length, gpa) = (args[0], args[1] | (args[2] << 32));
+            match self.gstage.translate(gpa) {
+                Some(translation) if translation.permissions.read => {
+                    let in_page = GSTAGE_PAGE_SIZE - (gpa & (GSTAGE_PAGE_SIZE - 1));
+                    args = [length.min(in_page), translation.host_address & 0xFFFF_FFFF, translation.host_address >> 32];
+                }
+                _ => {
+                    self.vcpus[vcpu].regs.set_sbi_result(sbi::ERR_INVALID_PARAM, 0);
+                    return RiscvExitAction::Resume;
+                }
+            }
+        }
+        self.stats.sbi_forwarded += 1;
+        RiscvExitAction::SbiForward { extension, function, args }
+    }
+
+    /// Resolve an SBI hart mask and base to a mask of existing harts
+    fn hart_mask(&self, mask: u64, base: u64) -> Option<u64> {
+        let all = if self.vcpus.len() == RISCV_MAX_HARTS { u64::MAX } else { (1 << self.vcpus.len()) - 1 };
+        if base == u64::MAX {
+            return Some(all);
+        }
+        if base >= self.vcpus.len() as u64 {
+            return None;
+        }
+        let mask = mask << base;
+        if mask & !all != 0 {
+            return None;
+        }
+        Some(mask)
+    }
+
+    fn extension_supported(extension: u64) -> bool {
+        matches!(
+            extension,
+            sbi::EXT_BASE | sbi::EXT_TIME | sbi::EXT_IPI | sbi::EXT_RFENCE | sbi::EXT_HSM | sbi::EXT_SRST
+                | sbi::EXT_DBCN | sbi::EXT_LEGACY_PUTCHAR | sbi::EXT_LEGACY_GETCHAR
+        )
+    }
+
+    /// Mirror each context's claimable interrupt into hvip.VSEIP
+    fn update_external_interrupts(&mut self) {
+        for hart in 0..self.vcpus.len() {
+            if self.plic.external_interrupt_pending(hart) {
+                self.vcpus[hart].regs.hvip |= IRQ_VS_EXT;
+            } else {
+                self.vcpus[hart].regs.hvip &= !IRQ_VS_EXT;
+            }
+        }
+    }
+
+    /// Emulate a 32-bit access to the PLIC; returns false if `gpa` is
+    /// outside the PLIC frame
+    fn emulate_plic_access(&mut self, vcpu: usize, gpa: u64, access: MmioAccess) -> bool {
+        if !(RISCV_PLIC_BASE..RISCV_PLIC_BASE + PLIC_SIZE).contains(&gpa) {
+            return false;
+        }
+        let offset = gpa - RISCV_PLIC_BASE;
+
+        if access.write {
+            let value = self.vcpus[vcpu].regs.x[access.reg as usize];
+            self.plic.write(offset, value as u32);
+        } else {
+            let value = self.plic.read(offset);
+            let value = if access.sign_extend { value as i32 as i64 as u64 } else { value as u64 };
+            self.vcpus[vcpu].regs.write_gpr(access.reg, value);
+        }
+        self.update_external_interrupts();
+        true
+    }
+}
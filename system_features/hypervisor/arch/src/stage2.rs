@@ -0,0 +1,306 @@
+//! AArch64 Stage-2 Translation
+//!
+//! EL2 stage-2 tables translate guest intermediate physical addresses (IPA)
+//! to host physical addresses. The tables use the 4K granule with a 39-bit
+//! IPA space, so the walk starts at level 1: level 1 and level 2 entries may
+//! be 1GB/2MB blocks, level 3 entries are 4K pages.
+
+use crate::HypervisorError;
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+/// Size of a stage-2 page
+pub const STAGE2_PAGE_SIZE: u64 = 0x1000;
+/// Size of a level 2 block
+pub const STAGE2_BLOCK_SIZE: u64 = 0x20_0000;
+/// Bits of IPA space covered by the tables
+pub const STAGE2_IPA_BITS: u32 = 39;
+
+const ENTRIES_PER_TABLE: usize = 512;
+
+/// One translation table; descriptors hold 4K-aligned table addresses
+#[derive(Debug)]
+#[repr(C, align(4096))]
+struct TranslationTable([u64; ENTRIES_PER_TABLE]);
+
+// Descriptor fields
+const DESC_VALID: u64 = 1 << 0;
+const DESC_TABLE: u64 = 1 << 1;
+const DESC_AF: u64 = 1 << 10;
+const DESC_SH_INNER: u64 = 0b11 << 8;
+const DESC_S2AP_READ: u64 = 1 << 6;
+const DESC_S2AP_WRITE: u64 = 1 << 7;
+const DESC_MEMATTR_SHIFT: u64 = 2;
+const DESC_MEMATTR_MASK: u64 = 0b1111 << DESC_MEMATTR_SHIFT;
+const DESC_XN: u64 = 1 << 54;
+const DESC_ADDRESS_MASK: u64 = 0x0000_FFFF_FFFF_F000;
+
+/// Stage-2 MemAttr for normal write-back memory
+const MEMATTR_NORMAL_WB: u64 = 0b1111;
+/// Stage-2 MemAttr for Device-nGnRE memory
+const MEMATTR_DEVICE: u64 = 0b0001;
+
+/// Access permissions and memory type of a stage-2 mapping
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stage2Attributes {
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+    /// Device memory instead of normal cacheable memory
+    pub device: bool,
+}
+
+impl Stage2Attributes {
+    /// Guest RAM
+    pub fn ram() -> Self {
+        Stage2Attributes { read: true, write: true, execute: true, device: false }
+    }
+
+    /// Guest ROM or firmware
+    pub fn rom() -> Self {
+        Stage2Attributes { read: true, write: false, execute: true, device: false }
+    }
+
+    /// Passed-through device registers
+    pub fn device() -> Self {
+        Stage2Attributes { read: true, write: true, execute: false, device: true }
+    }
+
+    fn descriptor_bits(&self) -> u64 {
+        let mut bits = DESC_AF | DESC_SH_INNER;
+        if self.read {
+            bits |= DESC_S2AP_READ;
+        }
+        if self.write {
+            bits |= DESC_S2AP_WRITE;
+        }
+        if !self.execute {
+            bits |= DESC_XN;
+        }
+        let memattr = if self.device { MEMATTR_DEVICE } else { MEMATTR_NORMAL_WB };
+        bits | (memattr << DESC_MEMATTR_SHIFT)
+    }
+
+    fn from_descriptor(descriptor: u64) -> Self {
+        Stage2Attributes {
+            read: descriptor & DESC_S2AP_READ != 0,
+            write: descriptor & DESC_S2AP_WRITE != 0,
+            execute: descriptor & DESC_XN == 0,
+            device: (descriptor & DESC_MEMATTR_MASK) >> DESC_MEMATTR_SHIFT == MEMATTR_DEVICE,
+        }
+    }
+}
+
+/// Result of a stage-2 walk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stage2Translation {
+    pub host_address: u64,
+    pub attributes: Stage2Attributes,
+    /// Level of the leaf entry (1, 2 or 3)
+    pub level: u8,
+}
+
+/// Stage-2 table statistics
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Stage2Stats {
+    pub tables: usize,
+    pub block_mappings: u64,
+    pub page_mappings: u64,
+    pub block_splits: u64,
+}
+
+/// Stage-2 translation tables of one VM
+#[derive(Debug)]
+pub struct Stage2Tables {
+    /// Tables are boxed so their addresses stay stable as the list grows
+    tables: Vec<Box<TranslationTable>>,
+    /// Table address to index in `tables`
+    table_index: BTreeMap<u64, usize>,
+    stats: Stage2Stats,
+}
+
+impl Stage2Tables {
+    /// Create empty tables with a level 1 root
+    pub fn new() -> Self {
+        let mut tables = Stage2Tables {
+            tables: Vec::new(),
+            table_index: BTreeMap::new(),
+            stats: Stage2Stats::default(),
+        };
+        tables.allocate_table();
+        tables
+    }
+
+    /// VTTBR_EL2 value: VMID and root table address
+    pub fn vttbr(&self, vmid: u8) -> u64 {
+        ((vmid as u64) << 48) | self.table_address(0)
+    }
+
+    /// VTCR_EL2 value: 4K granule, 39-bit IPA starting at level 1, 40-bit PA,
+    /// inner-shareable write-back table walks
+    pub fn vtcr() -> u64 {
+        let t0sz = (64 - STAGE2_IPA_BITS) as u64;
+        let sl0 = 0b01 << 6;
+        let irgn0 = 0b01 << 8;
+        let orgn0 = 0b01 << 10;
+        let sh0 = 0b11 << 12;
+        let ps = 0b010 << 16;
+        (1 << 31) | ps | sh0 | orgn0 | irgn0 | sl0 | t0sz
+    }
+
+    /// Map `size` bytes of IPA space to host memory, using 2MB blocks where
+    /// alignment allows
+    pub fn map(&mut self, ipa: u64, host_address: u64, size: u64, attributes: Stage2Attributes) -> Result<(), HypervisorError> {
+        if (ipa | host_address | size) & (STAGE2_PAGE_SIZE - 1) != 0 || size == 0 {
+            return Err(HypervisorError::InvalidParameter);
+        }
+        if ipa + size > 1 << STAGE2_IPA_BITS {
+            return Err(HypervisorError::InvalidParameter);
+        }
+
+        let bits = attributes.descriptor_bits();
+        let mut offset = 0;
+        while offset < size {
+            let (guest, host) = (ipa + offset, host_address + offset);
+            let l2 = self.next_table(0, Self::index(guest, 1))?;
+
+            let block_free = self.tables[l2].0[Self::index(guest, 2)] & DESC_VALID == 0;
+            if block_free && (guest | host) & (STAGE2_BLOCK_SIZE - 1) == 0 && size - offset >= STAGE2_BLOCK_SIZE {
+                self.tables[l2].0[Self::index(guest, 2)] = host | bits | DESC_VALID;
+                self.stats.block_mappings += 1;
+                offset += STAGE2_BLOCK_SIZE;
+            } else {
+                let l3 = self.next_table(l2, Self::index(guest, 2))?;
+                self.tables[l3].0[Self::index(guest, 3)] = host | bits | DESC_TABLE | DESC_VALID;
+                self.stats.page_mappings += 1;
+                offset += STAGE2_PAGE_SIZE;
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove the mappings of `size` bytes of IPA space, splitting blocks
+    /// that are only partly unmapped
+    pub fn unmap(&mut self, ipa: u64, size: u64) -> Result<(), HypervisorError> {
+        if (ipa | size) & (STAGE2_PAGE_SIZE - 1) != 0 {
+            return Err(HypervisorError::InvalidParameter);
+        }
+
+        let mut offset = 0;
+        while offset < size {
+            let guest = ipa + offset;
+            let l2 = match self.existing_table(0, Self::index(guest, 1)) {
+                Some(l2) => l2,
+                None => {
+                    offset += STAGE2_PAGE_SIZE;
+                    continue;
+                }
+            };
+
+            let l2_index = Self::index(guest, 2);
+            let entry = self.tables[l2].0[l2_index];
+            if entry & DESC_VALID != 0 && entry & DESC_TABLE == 0 {
+                if guest & (STAGE2_BLOCK_SIZE - 1) == 0 && size - offset >= STAGE2_BLOCK_SIZE {
+                    self.tables[l2].0[l2_index] = 0;
+                    offset += STAGE2_BLOCK_SIZE;
+                    continue;
+                }
+                self.split_block(l2, l2_index);
+            }
+
+            if let Some(l3) = self.existing_table(l2, l2_index) {
+                self.tables[l3].0[Self::index(guest, 3)] = 0;
+            }
+            offset += STAGE2_PAGE_SIZE;
+        }
+        Ok(())
+    }
+
+    /// Walk the tables for an IPA
+    pub fn translate(&self, ipa: u64) -> Option<Stage2Translation> {
+        let mut table = 0;
+        for level in 1..=3u8 {
+            let entry = self.tables[table].0[Self::index(ipa, level)];
+            if entry & DESC_VALID == 0 {
+                return None;
+            }
+            let is_leaf = level == 3 || entry & DESC_TABLE == 0;
+            if is_leaf {
+                let block_mask = Self::level_size(level) - 1;
+                return Some(Stage2Translation {
+                    host_address: (entry & DESC_ADDRESS_MASK & !block_mask) | (ipa & block_mask),
+                    attributes: Stage2Attributes::from_descriptor(entry),
+                    level,
+                });
+            }
+            table = *self.table_index.get(&(entry & DESC_ADDRESS_MASK))?;
+        }
+        None
+    }
+
+    pub fn stats(&self) -> Stage2Stats {
+        Stage2Stats {
+            tables: self.tables.len(),
+            ..self.stats
+        }
+    }
+
+    fn allocate_table(&mut self) -> usize {
+        let table = Box::new(TranslationTable([0; ENTRIES_PER_TABLE]));
+        let address = &*table as *const _ as u64;
+        self.tables.push(table);
+        self.table_index.insert(address, self.tables.len() - 1);
+        self.tables.len() - 1
+    }
+
+    fn table_address(&self, index: usize) -> u64 {
+        &*self.tables[index] as *const _ as u64
+    }
+
+    fn existing_table(&self, table: usize, index: usize) -> Option<usize> {
+        let entry = self.tables[table].0[index];
+        if entry & (DESC_VALID | DESC_TABLE) != DESC_VALID | DESC_TABLE {
+            return None;
+        }
+        self.table_index.get(&(entry & DESC_ADDRESS_MASK)).copied()
+    }
+
+    /// Next-level table behind an entry, creating it if the entry is invalid
+    fn next_table(&mut self, table: usize, index: usize) -> Result<usize, HypervisorError> {
+        if let Some(next) = self.existing_table(table, index) {
+            return Ok(next);
+        }
+        if self.tables[table].0[index] & DESC_VALID != 0 {
+            // A block already maps this range
+            return Err(HypervisorError::InvalidParameter);
+        }
+        let next = self.allocate_table();
+        self.tables[table].0[index] = self.table_address(next) | DESC_TABLE | DESC_VALID;
+        Ok(next)
+    }
+
+    /// Replace a 2MB block with a level 3 table of equivalent pages
+    fn split_block(&mut self, l2: usize, index: usize) {
+        let block = self.tables[l2].0[index];
+        let base = block & DESC_ADDRESS_MASK & !(STAGE2_BLOCK_SIZE - 1);
+        let bits = block & !DESC_ADDRESS_MASK & !(DESC_VALID | DESC_TABLE);
+
+        let l3 = self.allocate_table();
+        for page in 0..ENTRIES_PER_TABLE {
+            self.tables[l3].0[page] = (base + page as u64 * STAGE2_PAGE_SIZE) | bits | DESC_TABLE | DESC_VALID;
+        }
+        self.tables[l2].0[index] = self.table_address(l3) | DESC_TABLE | DESC_VALID;
+        self.stats.block_splits += 1;
+    }
+
+    fn index(ipa: u64, level: u8) -> usize {
+        let shift = 12 + 9 * (3 - level as u64);
+        ((ipa >> shift) & 0x1FF) as usize
+    }
+
+    fn level_size(level: u8) -> u64 {
+        STAGE2_PAGE_SIZE << (9 * (3 - level as u64))
+    }
+}
@@ -0,0 +1,435 @@
+//! Virtual GICv3
+//!
+//! Emulates the GICv3 distributor (shared peripheral interrupts) and one
+//! redistributor per vCPU (SGIs and PPIs) behind stage-2 MMIO traps. Pending
+//! interrupts are delivered to the guest through the ICH list registers;
+//! the guest acknowledges and completes them on its virtual CPU interface.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// First private peripheral interrupt
+pub const GIC_PPI_BASE: u32 = 16;
+/// First shared peripheral interrupt
+pub const GIC_SPI_BASE: u32 = 32;
+/// Maintenance interrupt PPI
+pub const GIC_MAINTENANCE_PPI: u32 = 25;
+/// EL1 virtual timer PPI
+pub const GIC_VTIMER_PPI: u32 = 27;
+/// EL1 physical timer PPI
+pub const GIC_PTIMER_PPI: u32 = 30;
+/// List registers implemented by the virtual CPU interface
+pub const GIC_LIST_REGISTERS: usize = 4;
+
+/// Size of the distributor register frame
+pub const GICD_SIZE: u64 = 0x1_0000;
+/// Size of one redistributor (RD_base and SGI_base frames)
+pub const GICR_STRIDE: u64 = 0x2_0000;
+
+// Distributor registers
+const GICD_CTLR: u64 = 0x0000;
+const GICD_TYPER: u64 = 0x0004;
+const GICD_IIDR: u64 = 0x0008;
+const GICD_IGROUPR: u64 = 0x0080;
+const GICD_ISENABLER: u64 = 0x0100;
+const GICD_ICENABLER: u64 = 0x0180;
+const GICD_ISPENDR: u64 = 0x0200;
+const GICD_ICPENDR: u64 = 0x0280;
+const GICD_ISACTIVER: u64 = 0x0300;
+const GICD_ICACTIVER: u64 = 0x0380;
+const GICD_IPRIORITYR: u64 = 0x0400;
+const GICD_ICFGR: u64 = 0x0C00;
+const GICD_IROUTER: u64 = 0x6000;
+const GICD_PIDR2: u64 = 0xFFE8;
+
+// Redistributor RD_base registers
+const GICR_CTLR: u64 = 0x0000;
+const GICR_IIDR: u64 = 0x0004;
+const GICR_TYPER: u64 = 0x0008;
+const GICR_WAKER: u64 = 0x0014;
+const GICR_PIDR2: u64 = 0xFFE8;
+/// Offset of the SGI_base frame
+const GICR_SGI_BASE: u64 = 0x1_0000;
+
+const GICD_CTLR_ENABLE_GRP1: u32 = 1 << 1;
+const GICD_CTLR_ARE: u32 = 1 << 4;
+const GICR_WAKER_PROCESSOR_SLEEP: u32 = 1 << 1;
+const GICR_WAKER_CHILDREN_ASLEEP: u32 = 1 << 2;
+/// PIDR2 architecture revision field for GICv3
+const PIDR2_ARCH_GICV3: u32 = 0x3 << 4;
+/// Implementer JEP106 code reported in IIDR (ARM)
+const GIC_IIDR_ARM: u32 = 0x43B;
+
+/// State of one interrupt
+#[derive(Debug, Clone, Copy, Default)]
+struct IrqState {
+    enabled: bool,
+    pending: bool,
+    active: bool,
+    group1: bool,
+    /// Edge-triggered (ICFGR) instead of level-sensitive
+    edge: bool,
+    priority: u8,
+    /// Target affinity for SPIs (IROUTER)
+    route: u64,
+}
+
+/// Per-vCPU redistributor
+#[derive(Debug, Clone)]
+struct Redistributor {
+    mpidr: u64,
+    asleep: bool,
+    private: [IrqState; 32],
+}
+
+/// vGIC statistics
+#[derive(Debug, Clone, Copy, Default)]
+pub struct VgicStats {
+    pub mmio_reads: u64,
+    pub mmio_writes: u64,
+    pub injected: u64,
+    pub sgis: u64,
+    pub acknowledged: u64,
+}
+
+/// Virtual GICv3 of one VM
+#[derive(Debug, Clone)]
+pub struct VirtualGic {
+    ctlr: u32,
+    spis: Vec<IrqState>,
+    redistributors: Vec<Redistributor>,
+    stats: VgicStats,
+}
+
+impl VirtualGic {
+    /// Create a GIC with `spi_count` SPIs (rounded up to a multiple of 32)
+    /// and a redistributor per vCPU affinity
+    pub fn new(vcpu_mpidrs: &[u64], spi_count: u32) -> Self {
+        let spi_count = ((spi_count + 31) / 32 * 32).min(988);
+        let private = [IrqState::default(); 32];
+        let mut redistributors: Vec<Redistributor> = vcpu_mpidrs
+            .iter()
+            .map(|&mpidr| Redistributor { mpidr, asleep: true, private })
+            .collect();
+        // SGIs are always edge-triggered
+        for redistributor in &mut redistributors {
+            for sgi in redistributor.private.iter_mut().take(GIC_PPI_BASE as usize) {
+                sgi.edge = true;
+            }
+        }
+
+        VirtualGic {
+            ctlr: GICD_CTLR_ARE,
+            spis: vec![IrqState::default(); spi_count as usize],
+            redistributors,
+            stats: VgicStats::default(),
+        }
+    }
+
+    /// Number of interrupt IDs, including SGIs and PPIs
+    pub fn irq_count(&self) -> u32 {
+        GIC_SPI_BASE + self.spis.len() as u32
+    }
+
+    /// Raise an interrupt: an SPI, or a PPI/SGI of `vcpu`
+    pub fn set_pending(&mut self, intid: u32, vcpu: usize) {
+        if let Some(irq) = self.irq_mut(vcpu, intid) {
+            irq.pending = true;
+            self.stats.injected += 1;
+        }
+    }
+
+    /// Lower a level-sensitive interrupt
+    pub fn clear_pending(&mut self, intid: u32, vcpu: usize) {
+        if let Some(irq) = self.irq_mut(vcpu, intid) {
+            if !irq.edge {
+                irq.pending = false;
+            }
+        }
+    }
+
+    /// Guest write to ICC_SGI1R_EL1: raise an SGI on the listed vCPUs
+    pub fn write_sgi1r(&mut self, source: usize, value: u64) {
+        let intid = ((value >> 24) & 0xF) as u32;
+        let target_list = value & 0xFFFF;
+        let aff1 = (value >> 16) & 0xFF;
+        let broadcast = value & (1 << 40) != 0;
+
+        for vcpu in 0..self.redistributors.len() {
+            let mpidr = self.redistributors[vcpu].mpidr;
+            let targeted = if broadcast {
+                vcpu != source
+            } else {
+                (mpidr >> 8) & 0xFF == aff1 && (mpidr & 0xFF) < 16 && target_list & (1 << (mpidr & 0xFF)) != 0
+            };
+            if targeted {
+                self.redistributors[vcpu].private[intid as usize].pending = true;
+                self.stats.sgis += 1;
+            }
+        }
+    }
+
+    /// Highest-priority interrupt that can be delivered to `vcpu`
+    pub fn highest_pending(&self, vcpu: usize) -> Option<u32> {
+        self.deliverable(vcpu).min_by_key(|&(_, priority)| priority).map(|(intid, _)| intid)
+    }
+
+    /// ICH_LR<n>_EL2 values for the pending interrupts of `vcpu`, highest
+    /// priority first, up to the number of list registers
+    pub fn list_registers(&self, vcpu: usize) -> Vec<u64> {
+        let mut pending: Vec<(u32, u8)> = self.deliverable(vcpu).collect();
+        pending.sort_by_key(|&(intid, priority)| (priority, intid));
+        pending
+            .into_iter()
+            .take(GIC_LIST_REGISTERS)
+            .map(|(intid, priority)| {
+                // State = pending, Group 1, priority and virtual INTID
+                (0b01 << 62) | (1 << 60) | ((priority as u64) << 48) | intid as u64
+            })
+            .collect()
+    }
+
+    /// Guest read of ICC_IAR1_EL1: take the highest-priority pending interrupt
+    pub fn acknowledge(&mut self, vcpu: usize) -> Option<u32> {
+        let intid = self.highest_pending(vcpu)?;
+        if let Some(irq) = self.irq_mut(vcpu, intid) {
+            irq.active = true;
+            if irq.edge {
+                irq.pending = false;
+            }
+        }
+        self.stats.acknowledged += 1;
+        Some(intid)
+    }
+
+    /// Guest write of ICC_EOIR1_EL1
+    pub fn end_of_interrupt(&mut self, vcpu: usize, intid: u32) {
+        if let Some(irq) = self.irq_mut(vcpu, intid) {
+            irq.active = false;
+        }
+    }
+
+    /// Emulate a distributor register read
+    pub fn distributor_read(&mut self, vcpu: usize, offset: u64, size: u8) -> u64 {
+        self.stats.mmio_reads += 1;
+        match offset {
+            GICD_CTLR => self.ctlr as u64,
+            GICD_TYPER => {
+                // ITLinesNumber, IDbits = 10 bits
+                let it_lines = (self.irq_count() / 32 - 1) as u64;
+                (9 << 19) | it_lines
+            }
+            GICD_IIDR => GIC_IIDR_ARM as u64,
+            GICD_PIDR2 => PIDR2_ARCH_GICV3 as u64,
+            GICD_IROUTER..=0x7FD8 => {
+                let intid = ((offset - GICD_IROUTER) / 8) as u32;
+                self.spi(intid).map_or(0, |irq| irq.route)
+            }
+            GICD_IPRIORITYR..=0x07F8 => self.read_priorities(vcpu, false, offset - GICD_IPRIORITYR, size),
+            _ => self.read_bank(vcpu, false, offset),
+        }
+    }
+
+    /// Emulate a distributor register write
+    pub fn distributor_write(&mut self, vcpu: usize, offset: u64, size: u8, value: u64) {
+        self.stats.mmio_writes += 1;
+        match offset {
+            // Affinity routing stays enabled
+            GICD_CTLR => self.ctlr = (value as u32 & GICD_CTLR_ENABLE_GRP1) | GICD_CTLR_ARE,
+            GICD_IROUTER..=0x7FD8 => {
+                let intid = ((offset - GICD_IROUTER) / 8) as u32;
+                if let Some(irq) = self.spi_mut(intid) {
+                    irq.route = value & 0xFF_00FF_FFFF;
+                }
+            }
+            GICD_IPRIORITYR..=0x07F8 => self.write_priorities(vcpu, false, offset - GICD_IPRIORITYR, size, value),
+            _ => self.write_bank(vcpu, false, offset, value as u32),
+        }
+    }
+
+    /// Emulate a read from the redistributor of `vcpu`
+    pub fn redistributor_read(&mut self, vcpu: usize, offset: u64, size: u8) -> u64 {
+        self.stats.mmio_reads += 1;
+        let last = vcpu + 1 == self.redistributors.len();
+        let redistributor = match self.redistributors.get(vcpu) {
+            Some(redistributor) => redistributor,
+            None => return 0,
+        };
+
+        match offset {
+            GICR_CTLR => 0,
+            GICR_IIDR => GIC_IIDR_ARM as u64,
+            GICR_TYPER => {
+                // Affinity value, processor number and the Last bit
+                let mpidr = redistributor.mpidr;
+                let affinity = (mpidr & 0xFF_FFFF) | ((mpidr >> 8) & 0xFF00_0000);
+                (affinity << 32) | ((vcpu as u64) << 8) | if last { 1 << 4 } else { 0 }
+            }
+            GICR_WAKER => {
+                if redistributor.asleep {
+                    (GICR_WAKER_PROCESSOR_SLEEP | GICR_WAKER_CHILDREN_ASLEEP) as u64
+                } else {
+                    0
+                }
+            }
+            GICR_PIDR2 => PIDR2_ARCH_GICV3 as u64,
+            _ if offset >= GICR_SGI_BASE => {
+                let offset = offset - GICR_SGI_BASE;
+                match offset {
+                    GICD_IPRIORITYR..=0x041C => self.read_priorities(vcpu, true, offset - GICD_IPRIORITYR, size),
+                    _ => self.read_bank(vcpu, true, offset),
+                }
+            }
+            _ => 0,
+        }
+    }
+
+    /// Emulate a write to the redistributor of `vcpu`
+    pub fn redistributor_write(&mut self, vcpu: usize, offset: u64, size: u8, value: u64) {
+        self.stats.mmio_writes += 1;
+        if vcpu >= self.redistributors.len() {
+            return;
+        }
+
+        match offset {
+            GICR_WAKER => self.redistributors[vcpu].asleep = value as u32 & GICR_WAKER_PROCESSOR_SLEEP != 0,
+            _ if offset >= GICR_SGI_BASE => {
+                let offset = offset - GICR_SGI_BASE;
+                match offset {
+                    GICD_IPRIORITYR..=0x041C => self.write_priorities(vcpu, true, offset - GICD_IPRIORITYR, size, value),
+                    _ => self.write_bank(vcpu, true, offset, value as u32),
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn stats(&self) -> VgicStats {
+        self.stats
+    }
+
+    /// Enabled, pending, inactive interrupts routed to `vcpu`
+    fn deliverable(&self, vcpu: usize) -> impl Iterator<Item = (u32, u8)> + '_ {
+        let ready = |irq: &IrqState| irq.enabled && irq.pending && !irq.active;
+        let private = self
+            .redistributors
+            .get(vcpu)
+            .into_iter()
+            .flat_map(|redistributor| redistributor.private.iter().enumerate())
+            .filter(move |(_, irq)| ready(irq))
+            .map(|(intid, irq)| (intid as u32, irq.priority));
+
+        let mpidr = self.redistributors.get(vcpu).map_or(u64::MAX, |redistributor| redistributor.mpidr & 0xFF_00FF_FFFF);
+        let grp1 = self.ctlr & GICD_CTLR_ENABLE_GRP1 != 0;
+        let shared = self
+            .spis
+            .iter()
+            .enumerate()
+            .filter(move |(_, irq)| grp1 && ready(irq) && irq.route == mpidr)
+            .map(|(index, irq)| (GIC_SPI_BASE + index as u32, irq.priority));
+
+        private.chain(shared)
+    }
+
+    fn spi(&self, intid: u32) -> Option<&IrqState> {
+        intid.checked_sub(GIC_SPI_BASE).and_then(|index| self.spis.get(index as usize))
+    }
+
+    fn spi_mut(&mut self, intid: u32) -> Option<&mut IrqState> {
+        intid.checked_sub(GIC_SPI_BASE).and_then(move |index| self.spis.get_mut(index as usize))
+    }
+
+    fn irq_mut(&mut self, vcpu: usize, intid: u32) -> Option<&mut IrqState> {
+        if intid < GIC_SPI_BASE {
+            self.redistributors.get_mut(vcpu).map(|redistributor| &mut redistributor.private[intid as usize])
+        } else {
+            self.spi_mut(intid)
+        }
+    }
+
+    /// Interrupt `intid` as seen through the distributor (`private` false,
+    /// SPIs only) or a redistributor (`private` true, SGIs and PPIs only)
+    fn banked_irq(&mut self, vcpu: usize, private: bool, intid: u32) -> Option<&mut IrqState> {
+        match (private, intid < GIC_SPI_BASE) {
+            (true, true) => self.irq_mut(vcpu, intid),
+            (false, false) => self.spi_mut(intid),
+            _ => None,
+        }
+    }
+
+    /// Read a one-bit-per-interrupt or two-bits-per-interrupt register
+    fn read_bank(&mut self, vcpu: usize, private: bool, offset: u64) -> u64 {
+        let (field, first, bits): (fn(&IrqState) -> bool, u32, u32) = match offset {
+            GICD_IGROUPR..=0x00FC => (|irq| irq.group1, ((offset - GICD_IGROUPR) / 4 * 32) as u32, 1),
+            GICD_ISENABLER..=0x01FC => (|irq| irq.enabled, (((offset - GICD_ISENABLER) % 0x80) / 4 * 32) as u32, 1),
+            GICD_ISPENDR..=0x02FC => (|irq| irq.pending, (((offset - GICD_ISPENDR) % 0x80) / 4 * 32) as u32, 1),
+            GICD_ISACTIVER..=0x03FC => (|irq| irq.active, (((offset - GICD_ISACTIVER) % 0x80) / 4 * 32) as u32, 1),
+            GICD_ICFGR..=0x0CFC => (|irq| irq.edge, ((offset - GICD_ICFGR) / 4 * 16) as u32, 2),
+            _ => return 0,
+        };
+
+        let mut value = 0u64;
+        for bit in 0..32 / bits {
+            if let Some(irq) = self.banked_irq(vcpu, private, first + bit) {
+                if field(irq) {
+                    // ICFGR reports edge triggering in the upper bit of each field
+                    value |= 1 << (bit * bits + bits - 1);
+                }
+            }
+        }
+        value
+    }
+
+    fn write_bank(&mut self, vcpu: usize, private: bool, offset: u64, value: u32) {
+        let (first, bits) = match offset {
+            GICD_IGROUPR..=0x03FC => ((((offset - GICD_IGROUPR) % 0x80) / 4 * 32) as u32, 1),
+            GICD_ICFGR..=0x0CFC => (((offset - GICD_ICFGR) / 4 * 16) as u32, 2),
+            _ => return,
+        };
+
+        for bit in 0..32 / bits {
+            let set = value & (1 << (bit * bits + bits - 1)) != 0;
+            let intid = first + bit;
+            let irq = match self.banked_irq(vcpu, private, intid) {
+                Some(irq) => irq,
+                None => continue,
+            };
+            match offset {
+                GICD_IGROUPR..=0x00FC => irq.group1 = set,
+                // SGIs stay edge-triggered
+                GICD_ICFGR..=0x0CFC => {
+                    if intid >= GIC_PPI_BASE {
+                        irq.edge = set;
+                    }
+                }
+                // Set/clear registers ignore zero bits
+                _ if !set => {}
+                GICD_ISENABLER..=0x017C => irq.enabled = true,
+                GICD_ICENABLER..=0x01FC => irq.enabled = false,
+                GICD_ISPENDR..=0x027C => irq.pending = true,
+                GICD_ICPENDR..=0x02FC => irq.pending = false,
+                GICD_ISACTIVER..=0x037C => irq.active = true,
+                GICD_ICACTIVER..=0x03FC => irq.active = false,
+                _ => {}
+            }
+        }
+    }
+
+    fn read_priorities(&mut self, vcpu: usize, private: bool, offset: u64, size: u8) -> u64 {
+        let mut value = 0u64;
+        for byte in 0..size.min(8) as u32 {
+            if let Some(irq) = self.banked_irq(vcpu, private, offset as u32 + byte) {
+                value |= (irq.priority as u64) << (byte * 8);
+            }
+        }
+        value
+    }
+
+    fn write_priorities(&mut self, vcpu: usize, private: bool, offset: u64, size: u8, value: u64) {
+        for byte in 0..size.min(8) as u32 {
+            if let Some(irq) = self.banked_irq(vcpu, private, offset as u32 + byte) {
+                irq.priority = (value >> (byte * 8)) as u8;
+            }
+        }
+    }
+}
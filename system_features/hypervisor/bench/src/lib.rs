@@ -0,0 +1,242 @@
+//! VM Exit Latency Micro-Benchmarks
+//!
+//! Self-contained benchmarks for the hypervisor's hottest exit paths, run
+//! against a minimal guest stub instead of a booted VM. Results are printed
+//! as JSON lines in the format consumed by the regression testing crate.
+
+use crate::{VmId, HypervisorCapabilities, HypervisorError};
+use crate::core::{Vcpu, VmExitReason};
+use crate::cpu::{ApicAccelerator, CpuModel};
+use crate::memory::{MemoryManager, VirtualizationType};
+
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+/// VM ID used by the guest stub
+const BENCH_VM_ID: VmId = VmId(u32::MAX);
+/// Guest memory given to the stub; the minimum the memory manager accepts
+const STUB_MEMORY_MB: u64 = 16;
+/// Guest page size used to spread EPT faults
+const PAGE_SIZE: u64 = 4096;
+/// Vector injected by the interrupt benchmark
+const BENCH_VECTOR: u8 = 0x30;
+/// Hypercall number placed in RAX by the hypercall benchmark
+const BENCH_HYPERCALL: u64 = 0;
+
+/// A micro-benchmark
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BenchKind {
+    /// CPUID exit, filtered through the CPU model, and re-entry
+    CpuidExit,
+    /// EPT violation handled by the memory manager
+    EptFault,
+    /// Interrupt delivery until the vector is injected on VM entry
+    InterruptInjection,
+    /// VMCALL dispatched to the hypercall handler
+    Hypercall,
+}
+
+impl BenchKind {
+    pub const ALL: [BenchKind; 4] = [
+        BenchKind::CpuidExit,
+        BenchKind::EptFault,
+        BenchKind::InterruptInjection,
+        BenchKind::Hypercall,
+    ];
+
+    /// Name used on the command line and in the JSON output
+    pub fn name(&self) -> &'static str {
+        match self {
+            BenchKind::CpuidExit => "cpuid_exit_latency",
+            BenchKind::EptFault => "ept_fault_latency",
+            BenchKind::InterruptInjection => "interrupt_injection_latency",
+            BenchKind::Hypercall => "hypercall_latency",
+        }
+    }
+
+    /// Look a benchmark up by its command-line name
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.name() == name)
+    }
+}
+
+/// Benchmark parameters
+#[derive(Debug, Clone, Copy)]
+pub struct BenchConfig {
+    /// Timed iterations per benchmark
+    pub iterations: usize,
+    /// Untimed iterations run first to warm caches and branch predictors
+    pub warmup_iterations: usize,
+    /// Timestamp source
+    pub clock: fn() -> u64,
+    /// Unit of `clock`, reported with every result
+    pub unit: &'static str,
+    /// Host capabilities given to the interrupt controller; empty selects
+    /// software injection
+    pub capabilities: HypervisorCapabilities,
+}
+
+impl Default for BenchConfig {
+    fn default() -> Self {
+        BenchConfig {
+            iterations: 10_000,
+            warmup_iterations: 100,
+            clock: host_tsc,
+            unit: "cycles",
+            capabilities: HypervisorCapabilities::empty(),
+        }
+    }
+}
+
+/// Minimal guest a benchmark runs against: one VCPU, the smallest memory
+/// manager and an interrupt controller, with no devices and no boot code.
+/// The guest is never entered; each iteration replays the exit its code
+/// would cause.
+pub struct GuestStub {
+    pub vcpu: Vcpu,
+    pub memory: MemoryManager,
+    pub interrupts: ApicAccelerator,
+    next_fault_page: u64,
+}
+
+impl GuestStub {
+    /// Guest code: `cpuid; jmp $-2`
+    pub const CPUID_LOOP: [u8; 4] = [0x0F, 0xA2, 0xEB, 0xFC];
+    /// Guest code: `vmcall; jmp $-3`
+    pub const VMCALL_LOOP: [u8; 5] = [0x0F, 0x01, 0xC1, 0xEB, 0xFB];
+
+    pub fn new(capabilities: HypervisorCapabilities) -> Result<Self, HypervisorError> {
+        let mut vcpu = Vcpu::new(BENCH_VM_ID, 0, Arc::new(CpuModel::host()))?;
+        vcpu.initialize()?;
+
+        let mut memory = MemoryManager::new(STUB_MEMORY_MB)?;
+        memory.initialize(BENCH_VM_ID, VirtualizationType::IntelVTx)?;
+
+        Ok(GuestStub {
+            vcpu,
+            memory,
+            interrupts: ApicAccelerator::new(capabilities, 1),
+            next_fault_page: 0,
+        })
+    }
+
+    /// Run one iteration of a benchmark
+    fn iterate(&mut self, kind: BenchKind) -> Result<(), HypervisorError> {
+        match kind {
+            BenchKind::CpuidExit => {
+                // Leaf 1 goes through the model's feature filtering
+                self.vcpu.vcpu_state.regs.rax = 1;
+                self.vcpu.vcpu_state.regs.rcx = 0;
+                self.vcpu.handle_exit(VmExitReason::CpuidInstruction)
+            }
+            BenchKind::EptFault => {
+                // Touch a new page every time, wrapping within guest memory
+                let pages = STUB_MEMORY_MB * 1024 * 1024 / PAGE_SIZE;
+                let address = (self.next_fault_page % pages) * PAGE_SIZE;
+                self.next_fault_page += 1;
+                self.memory.handle_ept_violation(address).map(|_| ())
+            }
+            BenchKind::InterruptInjection => {
+                self.interrupts.deliver(0, BENCH_VECTOR);
+                // Posted vectors come back on entry; software mode injects
+                if self.interrupts.vcpu_entering(0, 0).is_none() {
+                    if let Some(injection) = self.interrupts.next_injection(0) {
+                        self.interrupts.injected(0, injection);
+                    }
+                }
+                self.interrupts.vcpu_exited(0);
+                Ok(())
+            }
+            BenchKind::Hypercall => {
+                self.vcpu.vcpu_state.regs.rax = BENCH_HYPERCALL;
+                self.vcpu.handle_exit(VmExitReason::SoftwareInterrupt)
+            }
+        }
+    }
+}
+
+/// Latency distribution of one benchmark
+#[derive(Debug, Clone)]
+pub struct BenchResult {
+    pub kind: BenchKind,
+    pub iterations: usize,
+    pub unit: &'static str,
+    pub min: u64,
+    pub max: u64,
+    pub mean: f64,
+    pub p50: u64,
+    pub p99: u64,
+}
+
+impl BenchResult {
+    fn from_samples(kind: BenchKind, unit: &'static str, mut samples: Vec<u64>) -> Self {
+        samples.sort_unstable();
+        let percentile = |p: usize| samples.get((samples.len() * p / 100).min(samples.len().saturating_sub(1))).copied().unwrap_or(0);
+        let total: u64 = samples.iter().sum();
+
+        BenchResult {
+            kind,
+            iterations: samples.len(),
+            unit,
+            min: samples.first().copied().unwrap_or(0),
+            max: samples.last().copied().unwrap_or(0),
+            mean: if samples.is_empty() { 0.0 } else { total as f64 / samples.len() as f64 },
+            p50: percentile(50),
+            p99: percentile(99),
+        }
+    }
+
+    /// One JSON line; `value` is the median, the other fields are extra
+    /// detail that consumers may ignore
+    pub fn to_json_line(&self) -> String {
+        format!(
+            "{{\"benchmark\":\"{}\",\"value\":{},\"unit\":\"{}\",\"iterations\":{},\"min\":{},\"max\":{},\"mean\":{:.1},\"p99\":{}}}",
+            self.kind.name(), self.p50, self.unit, self.iterations, self.min, self.max, self.mean, self.p99,
+        )
+    }
+}
+
+/// Run one benchmark against a fresh guest stub
+pub fn run(kind: BenchKind, config: &BenchConfig) -> Result<BenchResult, HypervisorError> {
+    if config.iterations == 0 {
+        return Err(HypervisorError::InvalidParameter);
+    }
+    let mut stub = GuestStub::new(config.capabilities)?;
+
+    for _ in 0..config.warmup_iterations {
+        stub.iterate(kind)?;
+    }
+
+    let mut samples = Vec::with_capacity(config.iterations);
+    for _ in 0..config.iterations {
+        let start = (config.clock)();
+        stub.iterate(kind)?;
+        samples.push((config.clock)().saturating_sub(start));
+    }
+
+    Ok(BenchResult::from_samples(kind, config.unit, samples))
+}
+
+/// Run every benchmark
+pub fn run_all(config: &BenchConfig) -> Result<Vec<BenchResult>, HypervisorError> {
+    BenchKind::ALL.iter().map(|&kind| run(kind, config)).collect()
+}
+
+/// Render results as JSON lines for the regression harness
+pub fn to_json_lines(results: &[BenchResult]) -> String {
+    results.iter().map(|result| result.to_json_line() + "\n").collect()
+}
+
+/// Host time stamp counter
+fn host_tsc() -> u64 {
+    #[cfg(target_arch = "x86_64")]
+    unsafe {
+        core::arch::x86_64::_rdtsc()
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    {
+        0
+    }
+}
@@ -0,0 +1,84 @@
+//! ACPI Tables
+//!
+//! Helpers shared by the firmware tables the hypervisor builds for its
+//! guests, and the MADT that lists the guest's local APICs and I/O APIC.
+
+use alloc::vec::Vec;
+
+/// ACPI system description table header size
+pub const ACPI_HEADER_SIZE: usize = 36;
+const ACPI_OEM_ID: &[u8; 6] = b"MULTIO";
+const ACPI_OEM_TABLE_ID: &[u8; 8] = b"MOSHYPER";
+const ACPI_CREATOR_ID: &[u8; 4] = b"MOSH";
+
+const MADT_REVISION: u8 = 3;
+/// MADT flags: the guest also has dual 8259 PICs
+const MADT_PCAT_COMPAT: u32 = 1;
+/// MADT structure types
+const MADT_LOCAL_APIC: u8 = 0;
+const MADT_IO_APIC: u8 = 1;
+const MADT_INTERRUPT_OVERRIDE: u8 = 2;
+const MADT_LOCAL_APIC_NMI: u8 = 4;
+const MADT_LOCAL_X2APIC: u8 = 9;
+const MADT_LAPIC_ENABLED: u32 = 1;
+
+/// Table header with the length and checksum left for `finish_acpi_table`
+pub fn acpi_header(signature: &[u8; 4], revision: u8) -> Vec<u8> {
+    let mut header = Vec::with_capacity(ACPI_HEADER_SIZE);
+    header.extend_from_slice(signature);
+    header.extend_from_slice(&[0; 4]);
+    header.push(revision);
+    header.push(0);
+    header.extend_from_slice(ACPI_OEM_ID);
+    header.extend_from_slice(ACPI_OEM_TABLE_ID);
+    header.extend_from_slice(&1u32.to_le_bytes());
+    header.extend_from_slice(ACPI_CREATOR_ID);
+    header.extend_from_slice(&1u32.to_le_bytes());
+    header
+}
+
+/// Fill in the length and make the bytes of the table sum to zero
+pub fn finish_acpi_table(mut table: Vec<u8>) -> Vec<u8> {
+    let length = table.len() as u32;
+    table[4..8].copy_from_slice(&length.to_le_bytes());
+    let sum = table.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+    table[9] = 0u8.wrapping_sub(sum);
+    table
+}
+
+/// Build the MADT for `vcpu_count` VCPUs; VCPU N has APIC ID N and the
+/// I/O APIC takes the next ID
+pub fn build_madt(vcpu_count: usize, lapic_base: u64, ioapic_base: u64) -> Vec<u8> {
+    let mut table = acpi_header(b"APIC", MADT_REVISION);
+    table.extend_from_slice(&(lapic_base as u32).to_le_bytes());
+    table.extend_from_slice(&MADT_PCAT_COMPAT.to_le_bytes());
+
+    for vcpu in 0..vcpu_count {
+        let apic_id = vcpu as u32;
+        if apic_id < 0xFF {
+            table.extend_from_slice(&[MADT_LOCAL_APIC, 8, vcpu as u8, apic_id as u8]);
+            table.extend_from_slice(&MADT_LAPIC_ENABLED.to_le_bytes());
+        } else {
+            table.extend_from_slice(&[MADT_LOCAL_X2APIC, 16, 0, 0]);
+            table.extend_from_slice(&apic_id.to_le_bytes());
+            table.extend_from_slice(&MADT_LAPIC_ENABLED.to_le_bytes());
+            table.extend_from_slice(&(vcpu as u32).to_le_bytes());
+        }
+    }
+
+    table.extend_from_slice(&[MADT_IO_APIC, 12, vcpu_count.min(0xFE) as u8, 0]);
+    table.extend_from_slice(&(ioapic_base as u32).to_le_bytes());
+    table.extend_from_slice(&0u32.to_le_bytes());
+
+    // The PIT is wired to I/O APIC pin 2, not pin 0
+    table.extend_from_slice(&[MADT_INTERRUPT_OVERRIDE, 10, 0, 0]);
+    table.extend_from_slice(&2u32.to_le_bytes());
+    table.extend_from_slice(&0u16.to_le_bytes());
+
+    // NMI on LINT1 of every processor, active high and edge triggered
+    table.extend_from_slice(&[MADT_LOCAL_APIC_NMI, 6, 0xFF]);
+    table.extend_from_slice(&0x5u16.to_le_bytes());
+    table.push(1);
+
+    finish_acpi_table(table)
+}
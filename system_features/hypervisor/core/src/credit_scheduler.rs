@@ -0,0 +1,555 @@
+//! Credit-Based VCPU Scheduler
+//!
+//! Shares the host CPUs between VCPUs in proportion to their VM's weight,
+//! in the style of the Xen credit scheduler. Every accounting period each
+//! active VM is handed credits according to its weight, limited by its cap
+//! if it has one; running VCPUs are debited for the time they run. VCPUs
+//! with credit left (UNDER) run before those that overspent (OVER), and a
+//! VCPU woken from I/O wait with credit left is BOOSTed ahead of both so
+//! latency-sensitive guests respond quickly. OVER VCPUs of uncapped VMs
+//! still soak up otherwise idle CPUs; capped VMs are parked instead.
+//!
+//! Time a VCPU spends runnable but not running is its steal time, reported
+//! to the guest through the paravirtual clock's steal time record.
+
+use crate::{VmId, HypervisorError, ConfigError, ConfigErrorReason, MAX_VCPUS_PER_VM};
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::vec::Vec;
+
+/// Default VM weight
+pub const DEFAULT_SCHED_WEIGHT: u32 = 256;
+/// Largest VM weight
+pub const MAX_SCHED_WEIGHT: u32 = 65535;
+/// Scheduler tick
+pub const SCHED_TICK_NS: u64 = 10_000_000;
+/// Credits are handed out every accounting period
+pub const SCHED_ACCOUNTING_PERIOD_NS: u64 = 30_000_000;
+/// Credits debited for one tick of running
+const CREDITS_PER_TICK: i64 = 100;
+/// Credits one host CPU provides per accounting period
+const CREDITS_PER_PERIOD: i64 = CREDITS_PER_TICK * (SCHED_ACCOUNTING_PERIOD_NS / SCHED_TICK_NS) as i64;
+/// VCPUs cannot bank more than one period of credit
+const CREDIT_MAX: i64 = CREDITS_PER_PERIOD;
+
+/// Paravirtual steal time MSR (KVM layout); bit 0 enables the record
+pub const MSR_KVM_STEAL_TIME: u32 = 0x4b56_4d03;
+
+/// Scheduling parameters of a VM
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SchedParams {
+    /// Relative share of CPU time
+    pub weight: u32,
+    /// Hard limit in percent of one host CPU, 0 for none
+    pub cap_percent: u32,
+}
+
+impl Default for SchedParams {
+    fn default() -> Self {
+        SchedParams { weight: DEFAULT_SCHED_WEIGHT, cap_percent: 0 }
+    }
+}
+
+/// Scheduling priority of a VCPU
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CreditPriority {
+    /// Woken from I/O wait with credit left
+    Boost,
+    /// Credit left
+    Under,
+    /// Overspent
+    Over,
+}
+
+/// Scheduling state of a VCPU
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchedState {
+    Running { pcpu: usize },
+    Runnable,
+    /// Halted or waiting for I/O
+    Blocked,
+    /// Capped VM out of credit
+    Parked,
+}
+
+/// A VCPU as seen by the scheduler
+pub type SchedVcpu = (VmId, usize);
+
+/// Steal time record shared with the guest, in the layout of the KVM
+/// paravirtual clock. `version` is odd while the record is being updated.
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+pub struct PvStealTime {
+    pub steal: u64,
+    pub version: u32,
+    pub flags: u32,
+    pub preempted: u8,
+    pub pad: [u8; 3],
+    pub reserved: [u32; 11],
+}
+
+#[derive(Debug, Clone)]
+struct VcpuSched {
+    credit: i64,
+    priority: CreditPriority,
+    state: SchedState,
+    /// Host CPU whose run queue the VCPU is on, or last ran on
+    pcpu: usize,
+    /// When it last started running or became runnable
+    since_ns: u64,
+    run_ns: u64,
+    steal_ns: u64,
+    /// Whether it was preempted rather than blocked when it last stopped
+    preempted: bool,
+    steal_version: u32,
+}
+
+#[derive(Debug, Clone)]
+struct VmSched {
+    params: SchedParams,
+    vcpus: Vec<VcpuSched>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct PcpuSched {
+    runq: VecDeque<SchedVcpu>,
+    current: Option<SchedVcpu>,
+    idle_ns: u64,
+    idle_since_ns: Option<u64>,
+}
+
+/// Scheduler statistics
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CreditSchedStats {
+    pub schedules: u64,
+    pub accounting_periods: u64,
+    pub boosts: u64,
+    pub migrations: u64,
+    pub parks: u64,
+    pub preemptions: u64,
+}
+
+/// Per-VCPU scheduling information
+#[derive(Debug, Clone, Copy)]
+pub struct VcpuSchedInfo {
+    pub credit: i64,
+    pub priority: CreditPriority,
+    pub state: SchedState,
+    pub run_ns: u64,
+    pub steal_ns: u64,
+}
+
+/// Credit scheduler for the VCPUs of all VMs
+#[derive(Debug, Clone)]
+pub struct CreditScheduler {
+    pcpus: Vec<PcpuSched>,
+    vms: BTreeMap<VmId, VmSched>,
+    last_accounting_ns: u64,
+    stats: CreditSchedStats,
+}
+
+impl CreditScheduler {
+    /// Create a scheduler for `pcpus` host CPUs
+    pub fn new(pcpus: usize) -> Self {
+        CreditScheduler {
+            pcpus: (0..pcpus.max(1)).map(|_| PcpuSched::default()).collect(),
+            vms: BTreeMap::new(),
+            last_accounting_ns: 0,
+            stats: CreditSchedStats::default(),
+        }
+    }
+
+    pub fn pcpu_count(&self) -> usize {
+        self.pcpus.len()
+    }
+
+    /// Add the VCPUs of a VM; they start blocked until woken
+    pub fn add_vm(&mut self, vm_id: VmId, vcpu_count: usize, params: SchedParams, now_ns: u64) -> Result<(), HypervisorError> {
+        Self::validate(&params)?;
+        if vcpu_count == 0 || vcpu_count > MAX_VCPUS_PER_VM {
+            return Err(HypervisorError::InvalidParameter);
+        }
+        if self.vms.contains_key(&vm_id) {
+            return Err(HypervisorError::InvalidVmState);
+        }
+
+        let pcpus = self.pcpus.len();
+        let vcpus = (0..vcpu_count)
+            .map(|vcpu| VcpuSched {
+                credit: 0,
+                priority: CreditPriority::Under,
+                state: SchedState::Blocked,
+                // Spread the VCPUs of a VM over the host CPUs
+                pcpu: (vm_id.0 as usize + vcpu) % pcpus,
+                since_ns: now_ns,
+                run_ns: 0,
+                steal_ns: 0,
+                preempted: false,
+                steal_version: 0,
+            })
+            .collect();
+        self.vms.insert(vm_id, VmSched { params, vcpus });
+        info!("Scheduler: VM {} added with weight {} cap {}%", vm_id.0, params.weight, params.cap_percent);
+        Ok(())
+    }
+
+    /// Remove the VCPUs of a VM
+    pub fn remove_vm(&mut self, vm_id: VmId) -> Result<(), HypervisorError> {
+        self.vms.remove(&vm_id).ok_or(HypervisorError::VmNotFound(vm_id))?;
+        for pcpu in &mut self.pcpus {
+            pcpu.runq.retain(|&(vm, _)| vm != vm_id);
+            if matches!(pcpu.current, Some((vm, _)) if vm == vm_id) {
+                pcpu.current = None;
+            }
+        }
+        Ok(())
+    }
+
+    /// Change the weight and cap of a VM
+    pub fn set_params(&mut self, vm_id: VmId, params: SchedParams) -> Result<(), HypervisorError> {
+        Self::validate(&params)?;
+        let vm = self.vms.get_mut(&vm_id).ok_or(HypervisorError::VmNotFound(vm_id))?;
+        vm.params = params;
+        Ok(())
+    }
+
+    pub fn params(&self, vm_id: VmId) -> Result<SchedParams, HypervisorError> {
+        self.vms.get(&vm_id).map(|vm| vm.params).ok_or(HypervisorError::VmNotFound(vm_id))
+    }
+
+    /// Make a blocked VCPU runnable; `io` marks a wakeup for completed I/O
+    /// or an interrupt, which boosts a VCPU that has credit left
+    pub fn wake(&mut self, vm_id: VmId, vcpu: usize, io: bool, now_ns: u64) -> Result<(), HypervisorError> {
+        let sched = self.vcpu_mut(vm_id, vcpu)?;
+        if sched.state != SchedState::Blocked {
+            return Ok(());
+        }
+        sched.state = SchedState::Runnable;
+        sched.since_ns = now_ns;
+        sched.preempted = false;
+        let boosted = io && sched.credit >= 0;
+        if boosted {
+            sched.priority = CreditPriority::Boost;
+        }
+        let pcpu = sched.pcpu;
+
+        if boosted {
+            self.stats.boosts += 1;
+            // Boosted VCPUs go ahead of everything else on their CPU
+            self.pcpus[pcpu].runq.push_front((vm_id, vcpu));
+        } else {
+            self.pcpus[pcpu].runq.push_back((vm_id, vcpu));
+        }
+        Ok(())
+    }
+
+    /// The running or runnable VCPU halted or started waiting for I/O
+    pub fn block(&mut self, vm_id: VmId, vcpu: usize, now_ns: u64) -> Result<(), HypervisorError> {
+        let state = self.vcpu_mut(vm_id, vcpu)?.state;
+        match state {
+            SchedState::Running { pcpu } => {
+                self.charge(pcpu, now_ns);
+                self.pcpus[pcpu].current = None;
+                self.pcpus[pcpu].idle_since_ns = Some(now_ns);
+            }
+            SchedState::Runnable => {
+                self.account_wait(vm_id, vcpu, now_ns);
+                for pcpu in &mut self.pcpus {
+                    pcpu.runq.retain(|&entry| entry != (vm_id, vcpu));
+                }
+            }
+            SchedState::Blocked | SchedState::Parked => {}
+        }
+        let sched = self.vcpu_mut(vm_id, vcpu)?;
+        sched.state = SchedState::Blocked;
+        sched.preempted = false;
+        sched.since_ns = now_ns;
+        if sched.priority == CreditPriority::Boost {
+            sched.priority = CreditPriority::Under;
+        }
+        Ok(())
+    }
+
+    /// Scheduler tick on a host CPU: charge the running VCPU and hand out
+    /// credits when a period ended. Returns whether `schedule` should run
+    /// because a higher-priority VCPU is waiting.
+    pub fn tick(&mut self, pcpu: usize, now_ns: u64) -> bool {
+        if pcpu >= self.pcpus.len() {
+            return false;
+        }
+        if now_ns.saturating_sub(self.last_accounting_ns) >= SCHED_ACCOUNTING_PERIOD_NS {
+            self.accounting(now_ns);
+        }
+
+        let current = match self.pcpus[pcpu].current {
+            Some(current) => current,
+            None => return !self.pcpus[pcpu].runq.is_empty(),
+        };
+        self.charge(pcpu, now_ns);
+
+        let priority = match self.vcpu(current.0, current.1) {
+            Some(sched) => sched.priority,
+            None => return true,
+        };
+        // A boost lasts for one tick
+        let priority = if priority == CreditPriority::Boost {
+            if let Ok(sched) = self.vcpu_mut(current.0, current.1) {
+                sched.priority = if sched.credit >= 0 { CreditPriority::Under } else { CreditPriority::Over };
+                sched.priority
+            } else {
+                priority
+            }
+        } else {
+            priority
+        };
+        let parked = matches!(self.vcpu(current.0, current.1), Some(sched) if sched.state == SchedState::Parked);
+
+        parked || self.best_waiting(pcpu).map_or(false, |waiting| waiting < priority)
+    }
+
+    /// Pick the next VCPU for a host CPU. The running VCPU goes back to the
+    /// run queue; an idle or overspent CPU steals UNDER work from the others.
+    pub fn schedule(&mut self, pcpu: usize, now_ns: u64) -> Option<SchedVcpu> {
+        if pcpu >= self.pcpus.len() {
+            return None;
+        }
+        self.stats.schedules += 1;
+
+        self.charge(pcpu, now_ns);
+        if let Some((vm_id, vcpu)) = self.pcpus[pcpu].current.take() {
+            if let Ok(sched) = self.vcpu_mut(vm_id, vcpu) {
+                if matches!(sched.state, SchedState::Running { .. }) {
+                    sched.state = SchedState::Runnable;
+                    sched.since_ns = now_ns;
+                    sched.preempted = true;
+                    self.pcpus[pcpu].runq.push_back((vm_id, vcpu));
+                    self.stats.preemptions += 1;
+                }
+            }
+        }
+
+        let local = self.pop_best(pcpu, None);
+        let next = match local {
+            Some((entry, CreditPriority::Boost)) | Some((entry, CreditPriority::Under)) => Some(entry),
+            other => {
+                // Prefer UNDER work from another CPU over local OVER work
+                match self.steal_work(pcpu) {
+                    Some(stolen) => {
+                        if let Some((entry, _)) = other {
+                            self.pcpus[pcpu].runq.push_front(entry);
+                        }
+                        Some(stolen)
+                    }
+                    None => other.map(|(entry, _)| entry),
+                }
+            }
+        };
+
+        let state = &mut self.pcpus[pcpu];
+        match next {
+            Some((vm_id, vcpu)) => {
+                if let Some(idle_since) = state.idle_since_ns.take() {
+                    state.idle_ns += now_ns.saturating_sub(idle_since);
+                }
+                state.current = Some((vm_id, vcpu));
+                self.account_wait(vm_id, vcpu, now_ns);
+                if let Ok(sched) = self.vcpu_mut(vm_id, vcpu) {
+                    sched.state = SchedState::Running { pcpu };
+                    sched.pcpu = pcpu;
+                    sched.since_ns = now_ns;
+                    sched.preempted = false;
+                }
+            }
+            None => {
+                if state.idle_since_ns.is_none() {
+                    state.idle_since_ns = Some(now_ns);
+                }
+            }
+        }
+        next
+    }
+
+    /// VCPU running on a host CPU
+    pub fn current(&self, pcpu: usize) -> Option<SchedVcpu> {
+        self.pcpus.get(pcpu).and_then(|state| state.current)
+    }
+
+    /// Steal time record to write to the guest for a VCPU
+    pub fn steal_time(&mut self, vm_id: VmId, vcpu: usize, now_ns: u64) -> Result<PvStealTime, HypervisorError> {
+        self.account_wait(vm_id, vcpu, now_ns);
+        let sched = self.vcpu_mut(vm_id, vcpu)?;
+        sched.steal_version = sched.steal_version.wrapping_add(2);
+        Ok(PvStealTime {
+            steal: sched.steal_ns,
+            version: sched.steal_version,
+            preempted: sched.preempted as u8,
+            ..PvStealTime::default()
+        })
+    }
+
+    pub fn vcpu_info(&self, vm_id: VmId, vcpu: usize) -> Result<VcpuSchedInfo, HypervisorError> {
+        let sched = self.vcpu(vm_id, vcpu).ok_or(HypervisorError::VcpuNotFound)?;
+        Ok(VcpuSchedInfo {
+            credit: sched.credit,
+            priority: sched.priority,
+            state: sched.state,
+            run_ns: sched.run_ns,
+            steal_ns: sched.steal_ns,
+        })
+    }
+
+    /// Idle time of a host CPU
+    pub fn idle_ns(&self, pcpu: usize) -> u64 {
+        self.pcpus.get(pcpu).map_or(0, |state| state.idle_ns)
+    }
+
+    pub fn stats(&self) -> CreditSchedStats {
+        self.stats
+    }
+
+    /// Hand out one period of credits to the VMs with runnable VCPUs
+    fn accounting(&mut self, now_ns: u64) {
+        self.last_accounting_ns = now_ns;
+        self.stats.accounting_periods += 1;
+
+        let active = |vcpu: &VcpuSched| !matches!(vcpu.state, SchedState::Blocked);
+        let total_weight: u64 = self.vms.values()
+            .filter(|vm| vm.vcpus.iter().any(active))
+            .map(|vm| vm.params.weight as u64)
+            .sum();
+        if total_weight == 0 {
+            return;
+        }
+        let total_credit = CREDITS_PER_PERIOD * self.pcpus.len() as i64;
+
+        let mut unparked = Vec::new();
+        for (&vm_id, vm) in self.vms.iter_mut() {
+            let active_vcpus = vm.vcpus.iter().filter(|vcpu| active(vcpu)).count() as i64;
+            if active_vcpus == 0 {
+                continue;
+            }
+            let mut share = total_credit * vm.params.weight as i64 / total_weight as i64;
+            if vm.params.cap_percent > 0 {
+                share = share.min(CREDITS_PER_PERIOD * vm.params.cap_percent as i64 / 100);
+            }
+            let per_vcpu = share / active_vcpus;
+
+            for (index, vcpu) in vm.vcpus.iter_mut().enumerate() {
+                if !active(vcpu) {
+                    continue;
+                }
+                vcpu.credit = (vcpu.credit + per_vcpu).min(CREDIT_MAX);
+                if vcpu.priority != CreditPriority::Boost {
+                    vcpu.priority = if vcpu.credit >= 0 { CreditPriority::Under } else { CreditPriority::Over };
+                }
+                if vcpu.state == SchedState::Parked && vcpu.credit >= 0 {
+                    vcpu.state = SchedState::Runnable;
+                    vcpu.since_ns = now_ns;
+                    unparked.push((vcpu.pcpu, (vm_id, index)));
+                }
+            }
+        }
+        for (pcpu, entry) in unparked {
+            self.pcpus[pcpu].runq.push_back(entry);
+        }
+    }
+
+    /// Debit the running VCPU of a host CPU for the time since it started
+    fn charge(&mut self, pcpu: usize, now_ns: u64) {
+        let (vm_id, vcpu) = match self.pcpus[pcpu].current {
+            Some(current) => current,
+            None => return,
+        };
+        let capped = self.vms.get(&vm_id).map_or(false, |vm| vm.params.cap_percent > 0);
+        let sched = match self.vcpu_mut(vm_id, vcpu) {
+            Ok(sched) => sched,
+            Err(_) => return,
+        };
+        let ran_ns = now_ns.saturating_sub(sched.since_ns);
+        sched.since_ns = now_ns;
+        sched.run_ns += ran_ns;
+        sched.credit -= (ran_ns as i64) * CREDITS_PER_TICK / SCHED_TICK_NS as i64;
+        if sched.credit < 0 && sched.priority != CreditPriority::Boost {
+            sched.priority = CreditPriority::Over;
+        }
+
+        // Capped VMs stop when their credit runs out
+        if capped && sched.credit < 0 {
+            sched.state = SchedState::Parked;
+            self.pcpus[pcpu].current = None;
+            self.pcpus[pcpu].idle_since_ns = Some(now_ns);
+            self.stats.parks += 1;
+        }
+    }
+
+    /// Add the time a runnable VCPU has been waiting to its steal time
+    fn account_wait(&mut self, vm_id: VmId, vcpu: usize, now_ns: u64) {
+        if let Ok(sched) = self.vcpu_mut(vm_id, vcpu) {
+            if sched.state == SchedState::Runnable {
+                sched.steal_ns += now_ns.saturating_sub(sched.since_ns);
+                sched.since_ns = now_ns;
+            }
+        }
+    }
+
+    /// Best priority waiting on a host CPU
+    fn best_waiting(&self, pcpu: usize) -> Option<CreditPriority> {
+        self.pcpus[pcpu].runq.iter()
+            .filter_map(|&(vm_id, vcpu)| self.vcpu(vm_id, vcpu))
+            .filter(|sched| sched.state == SchedState::Runnable)
+            .map(|sched| sched.priority)
+            .min()
+    }
+
+    /// Take the highest-priority runnable VCPU off a run queue, first come
+    /// first served within a priority; `below` limits it to better priorities
+    fn pop_best(&mut self, pcpu: usize, below: Option<CreditPriority>) -> Option<(SchedVcpu, CreditPriority)> {
+        let mut best: Option<(usize, CreditPriority)> = None;
+        for (position, &(vm_id, vcpu)) in self.pcpus[pcpu].runq.iter().enumerate() {
+            let sched = match self.vcpu(vm_id, vcpu) {
+                Some(sched) if sched.state == SchedState::Runnable => sched,
+                _ => continue,
+            };
+            if below.map_or(false, |limit| sched.priority >= limit) {
+                continue;
+            }
+            if best.map_or(true, |(_, priority)| sched.priority < priority) {
+                best = Some((position, sched.priority));
+            }
+        }
+        let (position, priority) = best?;
+        self.pcpus[pcpu].runq.remove(position).map(|entry| (entry, priority))
+    }
+
+    /// Take UNDER or BOOST work queued on another host CPU
+    fn steal_work(&mut self, pcpu: usize) -> Option<SchedVcpu> {
+        for other in (1..self.pcpus.len()).map(|offset| (pcpu + offset) % self.pcpus.len()) {
+            if let Some((entry, _)) = self.pop_best(other, Some(CreditPriority::Over)) {
+                self.stats.migrations += 1;
+                return Some(entry);
+            }
+        }
+        None
+    }
+
+    fn vcpu(&self, vm_id: VmId, vcpu: usize) -> Option<&VcpuSched> {
+        self.vms.get(&vm_id).and_then(|vm| vm.vcpus.get(vcpu))
+    }
+
+    fn vcpu_mut(&mut self, vm_id: VmId, vcpu: usize) -> Result<&mut VcpuSched, HypervisorError> {
+        self.vms.get_mut(&vm_id)
+            .ok_or(HypervisorError::VmNotFound(vm_id))?
+            .vcpus.get_mut(vcpu)
+            .ok_or(HypervisorError::VcpuNotFound)
+    }
+
+    fn validate(params: &SchedParams) -> Result<(), HypervisorError> {
+        if params.weight == 0 || params.weight > MAX_SCHED_WEIGHT {
+            return Err(ConfigError::new("sched.weight", ConfigErrorReason::OutOfRange).value(params.weight as u64).into());
+        }
+        if params.cap_percent > 100 * MAX_VCPUS_PER_VM as u32 {
+            return Err(ConfigError::new("sched.cap_percent", ConfigErrorReason::OutOfRange).value(params.cap_percent as u64).into());
+        }
+        Ok(())
+    }
+}
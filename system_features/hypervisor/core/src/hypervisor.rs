@@ -4,8 +4,10 @@
 //! virtualization services for the MultiOS system.
 
 use crate::{HypervisorCapabilities, ArchType, MAX_VMS};
-use crate::vm_manager::VmManager;
+use crate::vm_manager::{VmManager, VmStats};
 use crate::vcpu::VcpuManager;
+use crate::credit_scheduler::{CreditScheduler, SchedParams, PvStealTime};
+use crate::network::{VirtualSwitch, FirewallProfile, FirewallRule, FirewallStats, RuleCounters, LinkConditions, PortId};
 use crate::HypervisorError;
 
 use alloc::vec::Vec;
@@ -23,6 +25,10 @@ pub struct Hypervisor {
     vm_manager: Arc<RwLock<VmManager>>,
     /// VCPU Manager
     vcpu_manager: Arc<RwLock<VcpuManager>>,
+    /// Credit scheduler sharing the host CPUs between VCPUs
+    scheduler: Arc<RwLock<CreditScheduler>>,
+    /// Virtual switch connecting the VMs' NICs
+    network: Arc<RwLock<VirtualSwitch>>,
     /// Number of active VMs
     active_vm_count: usize,
     /// Hypervisor uptime in milliseconds
@@ -43,17 +49,25 @@ impl Hypervisor {
         }
         
         // Initialize VM manager
-        let vm_manager = Arc::new(RwLock::new(VmManager::new()?));
+        let vm_manager = Arc::new(RwLock::new(VmManager::new(capabilities)?));
         
         // Initialize VCPU manager  
         let vcpu_manager = Arc::new(RwLock::new(VcpuManager::new()?));
         
+        // Initialize VCPU scheduler
+        let scheduler = Arc::new(RwLock::new(CreditScheduler::new(detect_host_cpus())));
+        
+        // Initialize virtual switch
+        let network = Arc::new(RwLock::new(VirtualSwitch::new(FirewallProfile::Open)));
+        
         // Create hypervisor instance
         let hypervisor = Hypervisor {
             capabilities,
             arch,
             vm_manager,
             vcpu_manager,
+            scheduler,
+            network,
             active_vm_count: 0,
             uptime_ms: 0,
             stats: HypervisorStats::default(),
@@ -79,7 +93,12 @@ impl Hypervisor {
             return Err(HypervisorError::TooManyVms);
         }
         
+        let vcpu_count = config.vcpu_count;
         let vm_id = self.vm_manager.write().create_vm(config)?;
+        if let Err(e) = self.scheduler.write().add_vm(vm_id, vcpu_count, SchedParams::default(), self.now_ns()) {
+            self.vm_manager.write().delete_vm(vm_id)?;
+            return Err(e);
+        }
         self.active_vm_count += 1;
         
         info!("Created VM with ID: {:?}", vm_id);
@@ -120,6 +139,8 @@ impl Hypervisor {
     /// Delete a virtual machine
     pub fn delete_vm(&mut self, vm_id: VmId) -> Result<(), HypervisorError> {
         self.vm_manager.write().delete_vm(vm_id)?;
+        self.scheduler.write().remove_vm(vm_id)?;
+        self.network.write().remove_vm(vm_id);
         self.active_vm_count = self.active_vm_count.saturating_sub(1);
         
         info!("Deleted VM: {:?}", vm_id);
@@ -136,6 +157,112 @@ impl Hypervisor {
         self.vm_manager.read().list_vms()
     }
     
+    /// Get VM information
+    pub fn get_vm_info(&self, vm_id: VmId) -> Result<VmInfo, HypervisorError> {
+        self.vm_manager.read().get_vm_info(vm_id)
+    }
+    
+    /// Get VM statistics
+    pub fn get_vm_stats(&self, vm_id: VmId) -> Result<VmStats, HypervisorError> {
+        self.vm_manager.read().get_vm_stats(vm_id)
+    }
+    
+    /// Set the scheduling weight and cap of a VM
+    pub fn set_vm_sched_params(&mut self, vm_id: VmId, params: SchedParams) -> Result<(), HypervisorError> {
+        self.scheduler.write().set_params(vm_id, params)?;
+        info!("VM {:?}: weight {} cap {}%", vm_id, params.weight, params.cap_percent);
+        Ok(())
+    }
+    
+    /// Get the scheduling weight and cap of a VM
+    pub fn get_vm_sched_params(&self, vm_id: VmId) -> Result<SchedParams, HypervisorError> {
+        self.scheduler.read().params(vm_id)
+    }
+    
+    /// Get the VCPU scheduler
+    pub fn scheduler(&self) -> Arc<RwLock<CreditScheduler>> {
+        Arc::clone(&self.scheduler)
+    }
+    
+    /// Get the virtual switch
+    pub fn network(&self) -> Arc<RwLock<VirtualSwitch>> {
+        Arc::clone(&self.network)
+    }
+    
+    /// Set the firewall profile of a VM, replacing the profile's built-in
+    /// rules while keeping rules added by the administrator
+    pub fn set_vm_firewall_profile(&mut self, vm_id: VmId, profile: FirewallProfile) -> Result<(), HypervisorError> {
+        self.get_vm_info(vm_id)?;
+        self.network.write().firewall_mut(vm_id).ok_or(HypervisorError::InvalidParameter)?.set_profile(profile);
+        info!("VM {:?}: firewall profile {:?}", vm_id, profile);
+        Ok(())
+    }
+    
+    /// Add a firewall rule to a VM; returns the rule ID
+    pub fn add_vm_firewall_rule(&mut self, vm_id: VmId, rule: FirewallRule) -> Result<u32, HypervisorError> {
+        self.get_vm_info(vm_id)?;
+        let id = self.network.write().firewall_mut(vm_id).ok_or(HypervisorError::InvalidParameter)?.add_rule(rule);
+        info!("VM {:?}: added firewall rule {}", vm_id, id);
+        Ok(id)
+    }
+    
+    /// Remove a firewall rule from a VM
+    pub fn remove_vm_firewall_rule(&mut self, vm_id: VmId, rule_id: u32) -> Result<FirewallRule, HypervisorError> {
+        self.get_vm_info(vm_id)?;
+        let rule = self.network.write().firewall_mut(vm_id).ok_or(HypervisorError::InvalidParameter)?.remove_rule(rule_id)?;
+        info!("VM {:?}: removed firewall rule {}", vm_id, rule_id);
+        Ok(rule)
+    }
+    
+    /// Get the firewall rules of a VM with their hit counters
+    pub fn get_vm_firewall_rules(&self, vm_id: VmId) -> Result<Vec<(u32, FirewallRule, RuleCounters)>, HypervisorError> {
+        self.get_vm_info(vm_id)?;
+        let network = self.network.read();
+        let firewall = network.firewall(vm_id).ok_or(HypervisorError::InvalidParameter)?;
+        Ok(firewall.rules().to_vec())
+    }
+    
+    /// Get the firewall statistics of a VM
+    pub fn get_vm_firewall_stats(&self, vm_id: VmId) -> Result<FirewallStats, HypervisorError> {
+        self.get_vm_info(vm_id)?;
+        let network = self.network.read();
+        Ok(network.firewall(vm_id).ok_or(HypervisorError::InvalidParameter)?.stats())
+    }
+    
+    /// Set the emulated link of one of a VM's NICs; takes effect for the
+    /// next frame sent
+    pub fn set_vm_link_conditions(&mut self, vm_id: VmId, port: PortId, conditions: LinkConditions) -> Result<(), HypervisorError> {
+        self.get_vm_info(vm_id)?;
+        let mut network = self.network.write();
+        if network.port(port).and_then(|nic| nic.vm_id) != Some(vm_id) {
+            return Err(HypervisorError::InvalidParameter);
+        }
+        network.set_link_conditions(port, conditions)?;
+        info!("VM {:?} port {}: link conditions {:?}", vm_id, port, conditions);
+        Ok(())
+    }
+    
+    /// Get the emulated link of one of a VM's NICs
+    pub fn get_vm_link_conditions(&self, vm_id: VmId, port: PortId) -> Result<LinkConditions, HypervisorError> {
+        self.get_vm_info(vm_id)?;
+        let network = self.network.read();
+        if network.port(port).and_then(|nic| nic.vm_id) != Some(vm_id) {
+            return Err(HypervisorError::InvalidParameter);
+        }
+        network.link_conditions(port).ok_or(HypervisorError::InvalidParameter)
+    }
+    
+    /// Steal time record for a VCPU and the guest address to write it to,
+    /// if the guest enabled steal time reporting
+    pub fn steal_time_update(&self, vm_id: VmId, vcpu: usize) -> Result<Option<(u64, PvStealTime)>, HypervisorError> {
+        let gpa = match self.vm_manager.read().steal_time_gpa(vm_id, vcpu)? {
+            Some(gpa) => gpa,
+            None => return Ok(None),
+        };
+        let record = self.scheduler.write().steal_time(vm_id, vcpu, self.now_ns())?;
+        Ok(Some((gpa, record)))
+    }
+    
     /// Get hypervisor statistics
     pub fn get_stats(&self) -> &HypervisorStats {
         &self.stats
@@ -179,6 +306,8 @@ impl Clone for Hypervisor {
             arch: self.arch,
             vm_manager: Arc::clone(&self.vm_manager),
             vcpu_manager: Arc::clone(&self.vcpu_manager),
+            scheduler: Arc::clone(&self.scheduler),
+            network: Arc::clone(&self.network),
             active_vm_count: self.active_vm_count,
             uptime_ms: self.uptime_ms,
             stats: self.stats,
@@ -186,6 +315,27 @@ impl Clone for Hypervisor {
     }
 }
 
+impl Hypervisor {
+    fn now_ns(&self) -> u64 {
+        self.uptime_ms * 1_000_000
+    }
+}
+
+/// Number of host CPUs to schedule VCPUs on
+fn detect_host_cpus() -> usize {
+    #[cfg(target_arch = "x86_64")]
+    {
+        // Logical processors per package, CPUID.1:EBX[23:16]
+        let result = crate::cpu::host_cpuid(1, 0);
+        (((result.ebx >> 16) & 0xff) as usize).max(1)
+    }
+    
+    #[cfg(not(target_arch = "x86_64"))]
+    {
+        1
+    }
+}
+
 /// Detect CPU architecture
 fn detect_architecture() -> ArchType {
     #[cfg(target_arch = "x86_64")]
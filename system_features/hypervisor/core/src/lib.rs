@@ -7,6 +7,8 @@
 #![feature(asm)]
 
 extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
 extern crate spin;
 extern crate bitflags;
 extern crate log;
@@ -21,11 +23,19 @@ mod vm_manager;
 mod vcpu;
 mod hypervisor;
 mod vm_config;
+mod smp;
+mod acpi;
+mod credit_scheduler;
+mod record_replay;
 
 pub use vm_manager::*;
 pub use vcpu::*;
 pub use hypervisor::*;
 pub use vm_config::*;
+pub use smp::*;
+pub use acpi::*;
+pub use credit_scheduler::*;
+pub use record_replay::*;
 
 /// Hypervisor version information
 pub const HYPERVISOR_VERSION: &str = "1.0.0";
@@ -52,6 +62,7 @@ bitflags! {
         const SINGLE_STEP = 1 << 8;
         const DEBUG_ASSIST = 1 << 9;
         const NESTED_VIRT = 1 << 10;
+        const POSTED_INTERRUPTS = 1 << 11;
     }
 }
 
@@ -113,6 +124,10 @@ fn detect_cpu_capabilities() -> HypervisorCapabilities {
         if has_nested_paging() {
             caps |= HypervisorCapabilities::NESTED_PAGING;
         }
+        
+        if has_apic_virtualization() {
+            caps |= HypervisorCapabilities::APIC_VIRTUALIZATION | HypervisorCapabilities::POSTED_INTERRUPTS;
+        }
     }
     
     #[cfg(target_arch = "aarch64")]
@@ -159,6 +174,32 @@ fn has_nested_paging() -> bool {
     true // Assume supported for now
 }
 
+#[cfg(target_arch = "x86_64")]
+fn has_apic_virtualization() -> bool {
+    if is_amd_v_supported() {
+        // Check CPUID.8000_000A:EDX.AVIC[13] = 1
+        let edx: u32;
+        unsafe {
+            core::arch::asm!(
+                "push rbx",
+                "cpuid",
+                "pop rbx",
+                in("eax") 0x8000000Au32,
+                in("ecx") 0,
+                out("edx") edx,
+                lateout("eax") _,
+                lateout("ecx") _,
+            );
+        }
+        return (edx & (1 << 13)) != 0;
+    }
+    
+    // Intel needs IA32_VMX_PINBASED_CTLS bit 39 (posted interrupts) and
+    // IA32_VMX_PROCBASED_CTLS2 bit 41 (virtual-interrupt delivery); without
+    // MSR access assume they are absent so delivery stays in software
+    false
+}
+
 #[cfg(target_arch = "aarch64")]
 fn is_intel_vtx_supported() -> bool { false }
 
@@ -0,0 +1,396 @@
+//! Deterministic Record and Replay
+//!
+//! While recording, every input a VCPU sees that does not follow from its
+//! own state is logged against the point in its instruction stream where
+//! it arrived: injected interrupts and NMIs, port and MMIO read results,
+//! TSC and other host-dependent MSR reads, and hardware random numbers.
+//! Replaying feeds the same inputs back at the same points, so a guest
+//! started from the same state executes the same instructions again.
+//!
+//! Each VCPU has its own stream of inputs. Runs reproduce exactly for a
+//! single VCPU, or for several that communicate only through interrupts;
+//! races between VCPUs on shared memory are not recorded. Posted
+//! interrupts bypass the injection point, so APIC acceleration is turned
+//! off for the VMs involved.
+//!
+//! When the guest asks for an input that differs from the log, replay
+//! stops with the divergence kept for inspection.
+
+use crate::{VmId, HypervisorError, ConfigError, ConfigErrorReason};
+use crate::cpu::PendingInjection;
+
+use alloc::vec::Vec;
+
+/// Log header magic and format version
+const REPLAY_LOG_MAGIC: &[u8; 4] = b"MRPL";
+const REPLAY_LOG_VERSION: u16 = 1;
+
+// Entry tags in the encoded log
+const TAG_INTERRUPT: u8 = 1;
+const TAG_NMI: u8 = 2;
+const TAG_PORT_READ: u8 = 3;
+const TAG_MMIO_READ: u8 = 4;
+const TAG_TSC: u8 = 5;
+const TAG_MSR_READ: u8 = 6;
+const TAG_RANDOM: u8 = 7;
+
+/// Where in a VCPU's execution an input arrived
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ExecutionPoint {
+    /// Instructions retired since the VCPU was created
+    pub instructions: u64,
+    pub rip: u64,
+}
+
+/// A non-deterministic input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayInput {
+    Interrupt { vector: u8 },
+    Nmi,
+    PortRead { port: u16, size: u8, value: u32 },
+    MmioRead { address: u64, size: u8, value: u64 },
+    Tsc { value: u64 },
+    MsrRead { index: u32, value: u64 },
+    Random { value: u64 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReplayEntry {
+    pub vcpu: u16,
+    pub point: ExecutionPoint,
+    pub input: ReplayInput,
+}
+
+/// Inputs of one run, in the order they arrived
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplayLog {
+    pub vm_id: VmId,
+    pub vcpu_count: u16,
+    pub entries: Vec<ReplayEntry>,
+}
+
+impl ReplayLog {
+    pub fn new(vm_id: VmId, vcpu_count: u16) -> Self {
+        ReplayLog { vm_id, vcpu_count, entries: Vec::new() }
+    }
+
+    /// Serialize for storing next to the VM's disk images
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(20 + self.entries.len() * 32);
+        out.extend_from_slice(REPLAY_LOG_MAGIC);
+        out.extend_from_slice(&REPLAY_LOG_VERSION.to_le_bytes());
+        out.extend_from_slice(&self.vm_id.0.to_le_bytes());
+        out.extend_from_slice(&self.vcpu_count.to_le_bytes());
+        out.extend_from_slice(&(self.entries.len() as u64).to_le_bytes());
+        for entry in &self.entries {
+            let (tag, a, b) = match entry.input {
+                ReplayInput::Interrupt { vector } => (TAG_INTERRUPT, vector as u64, 0),
+                ReplayInput::Nmi => (TAG_NMI, 0, 0),
+                ReplayInput::PortRead { port, size, value } => (TAG_PORT_READ, (port as u64) << 8 | size as u64, value as u64),
+                ReplayInput::MmioRead { address, size, value } => (TAG_MMIO_READ, address << 8 | size as u64, value),
+                ReplayInput::Tsc { value } => (TAG_TSC, 0, value),
+                ReplayInput::MsrRead { index, value } => (TAG_MSR_READ, index as u64, value),
+                ReplayInput::Random { value } => (TAG_RANDOM, 0, value),
+            };
+            out.push(tag);
+            out.extend_from_slice(&entry.vcpu.to_le_bytes());
+            out.extend_from_slice(&entry.point.instructions.to_le_bytes());
+            out.extend_from_slice(&entry.point.rip.to_le_bytes());
+            out.extend_from_slice(&a.to_le_bytes());
+            out.extend_from_slice(&b.to_le_bytes());
+        }
+        out
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, HypervisorError> {
+        let invalid = |field| HypervisorError::from(ConfigError::new(field, ConfigErrorReason::Malformed));
+        if bytes.len() < 20 || &bytes[..4] != REPLAY_LOG_MAGIC {
+            return Err(invalid("replay_log.header"));
+        }
+        let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+        if version != REPLAY_LOG_VERSION {
+            return Err(ConfigError::new("replay_log.version", ConfigErrorReason::Mismatch).value(version as u64).into());
+        }
+        let vm_id = VmId(u32::from_le_bytes([bytes[6], bytes[7], bytes[8], bytes[9]]));
+        let vcpu_count = u16::from_le_bytes([bytes[10], bytes[11]]);
+        let count = read_u64(bytes, 12) as usize;
+
+        const ENTRY_SIZE: usize = 1 + 2 + 8 * 4;
+        let body = &bytes[20..];
+        if count.checked_mul(ENTRY_SIZE) != Some(body.len()) {
+            return Err(invalid("replay_log.entries"));
+        }
+        let mut entries = Vec::with_capacity(count);
+        for raw in body.chunks_exact(ENTRY_SIZE) {
+            let vcpu = u16::from_le_bytes([raw[1], raw[2]]);
+            if vcpu >= vcpu_count {
+                return Err(ConfigError::new("replay_log.vcpu", ConfigErrorReason::OutOfRange).value(vcpu as u64).into());
+            }
+            let point = ExecutionPoint { instructions: read_u64(raw, 3), rip: read_u64(raw, 11) };
+            let (a, b) = (read_u64(raw, 19), read_u64(raw, 27));
+            let input = match raw[0] {
+                TAG_INTERRUPT => ReplayInput::Interrupt { vector: a as u8 },
+                TAG_NMI => ReplayInput::Nmi,
+                TAG_PORT_READ => ReplayInput::PortRead { port: (a >> 8) as u16, size: a as u8, value: b as u32 },
+                TAG_MMIO_READ => ReplayInput::MmioRead { address: a >> 8, size: a as u8, value: b },
+                TAG_TSC => ReplayInput::Tsc { value: b },
+                TAG_MSR_READ => ReplayInput::MsrRead { index: a as u32, value: b },
+                TAG_RANDOM => ReplayInput::Random { value: b },
+                tag => return Err(ConfigError::new("replay_log.tag", ConfigErrorReason::Malformed).value(tag as u64).into()),
+            };
+            entries.push(ReplayEntry { vcpu, point, input });
+        }
+        Ok(ReplayLog { vm_id, vcpu_count, entries })
+    }
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    let mut raw = [0u8; 8];
+    raw.copy_from_slice(&bytes[offset..offset + 8]);
+    u64::from_le_bytes(raw)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayMode {
+    Recording,
+    Replaying,
+    /// Replay consumed the whole log
+    Finished,
+    /// The guest asked for something the log does not have
+    Diverged,
+}
+
+/// Where and how replay left the recorded execution
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Divergence {
+    pub vcpu: u16,
+    pub point: ExecutionPoint,
+    /// What the log had next for the VCPU, if anything
+    pub expected: Option<ReplayEntry>,
+    /// What the guest did instead
+    pub actual: ReplayInput,
+}
+
+/// Records or replays the inputs of one VM
+#[derive(Debug)]
+pub struct RecordReplay {
+    mode: ReplayMode,
+    log: ReplayLog,
+    /// Per VCPU, indices of its entries in the log
+    streams: Vec<Vec<usize>>,
+    /// Per VCPU, how many of its entries were replayed
+    cursors: Vec<usize>,
+    divergence: Option<Divergence>,
+}
+
+impl RecordReplay {
+    pub fn record(vm_id: VmId, vcpu_count: u16) -> Self {
+        RecordReplay {
+            mode: ReplayMode::Recording,
+            log: ReplayLog::new(vm_id, vcpu_count),
+            streams: Vec::new(),
+            cursors: Vec::new(),
+            divergence: None,
+        }
+    }
+
+    pub fn replay(log: ReplayLog) -> Self {
+        let mut streams = alloc::vec![Vec::new(); log.vcpu_count as usize];
+        for (index, entry) in log.entries.iter().enumerate() {
+            streams[entry.vcpu as usize].push(index);
+        }
+        let cursors = alloc::vec![0; streams.len()];
+        let mut replay = RecordReplay { mode: ReplayMode::Replaying, log, streams, cursors, divergence: None };
+        replay.check_finished();
+        replay
+    }
+
+    pub fn mode(&self) -> ReplayMode {
+        self.mode
+    }
+
+    pub fn divergence(&self) -> Option<Divergence> {
+        self.divergence
+    }
+
+    /// Entries recorded, or replayed so far
+    pub fn position(&self) -> usize {
+        match self.mode {
+            ReplayMode::Recording => self.log.entries.len(),
+            _ => self.cursors.iter().sum(),
+        }
+    }
+
+    /// End recording and hand out the log
+    pub fn finish(self) -> ReplayLog {
+        self.log
+    }
+
+    /// Result of an I/O port read; `device` performs the live read
+    pub fn port_read(&mut self, vcpu: u16, point: ExecutionPoint, port: u16, size: u8,
+                     device: impl FnOnce() -> u32) -> Result<u32, HypervisorError> {
+        if self.mode == ReplayMode::Recording {
+            let value = device();
+            self.push(vcpu, point, ReplayInput::PortRead { port, size, value });
+            return Ok(value);
+        }
+        let actual = ReplayInput::PortRead { port, size, value: 0 };
+        self.next_input(vcpu, point, actual, |input| match input {
+            ReplayInput::PortRead { port: p, size: s, value } if p == port && s == size => Some(value as u64),
+            _ => None,
+        }).map(|value| value as u32)
+    }
+
+    /// Result of an emulated MMIO read
+    pub fn mmio_read(&mut self, vcpu: u16, point: ExecutionPoint, address: u64, size: u8,
+                     device: impl FnOnce() -> u64) -> Result<u64, HypervisorError> {
+        if self.mode == ReplayMode::Recording {
+            let value = device();
+            self.push(vcpu, point, ReplayInput::MmioRead { address, size, value });
+            return Ok(value);
+        }
+        let actual = ReplayInput::MmioRead { address, size, value: 0 };
+        self.next_input(vcpu, point, actual, |input| match input {
+            ReplayInput::MmioRead { address: a, size: s, value } if a == address && s == size => Some(value),
+            _ => None,
+        })
+    }
+
+    /// Guest TSC value for RDTSC, RDTSCP and IA32_TSC reads
+    pub fn tsc(&mut self, vcpu: u16, point: ExecutionPoint, host: impl FnOnce() -> u64) -> Result<u64, HypervisorError> {
+        if self.mode == ReplayMode::Recording {
+            let value = host();
+            self.push(vcpu, point, ReplayInput::Tsc { value });
+            return Ok(value);
+        }
+        self.next_input(vcpu, point, ReplayInput::Tsc { value: 0 }, |input| match input {
+            ReplayInput::Tsc { value } => Some(value),
+            _ => None,
+        })
+    }
+
+    /// An MSR whose value depends on the host, such as a performance counter
+    pub fn msr_read(&mut self, vcpu: u16, point: ExecutionPoint, index: u32,
+                    host: impl FnOnce() -> u64) -> Result<u64, HypervisorError> {
+        if self.mode == ReplayMode::Recording {
+            let value = host();
+            self.push(vcpu, point, ReplayInput::MsrRead { index, value });
+            return Ok(value);
+        }
+        self.next_input(vcpu, point, ReplayInput::MsrRead { index, value: 0 }, |input| match input {
+            ReplayInput::MsrRead { index: i, value } if i == index => Some(value),
+            _ => None,
+        })
+    }
+
+    /// RDRAND/RDSEED result
+    pub fn random(&mut self, vcpu: u16, point: ExecutionPoint, host: impl FnOnce() -> u64) -> Result<u64, HypervisorError> {
+        if self.mode == ReplayMode::Recording {
+            let value = host();
+            self.push(vcpu, point, ReplayInput::Random { value });
+            return Ok(value);
+        }
+        self.next_input(vcpu, point, ReplayInput::Random { value: 0 }, |input| match input {
+            ReplayInput::Random { value } => Some(value),
+            _ => None,
+        })
+    }
+
+    /// Log an event injected at VM entry
+    pub fn record_injection(&mut self, vcpu: u16, point: ExecutionPoint, injection: PendingInjection) {
+        if self.mode == ReplayMode::Recording {
+            self.push(vcpu, point, Self::injection_input(injection));
+        }
+    }
+
+    /// Event to inject on this VM entry. Fails if the VCPU ran past the
+    /// point where the log injected one.
+    pub fn replay_injection(&mut self, vcpu: u16, point: ExecutionPoint) -> Result<Option<PendingInjection>, HypervisorError> {
+        if self.mode != ReplayMode::Replaying {
+            return self.replay_state();
+        }
+        let Some(entry) = self.peek(vcpu) else {
+            return Ok(None);
+        };
+        let injection = match entry.input {
+            ReplayInput::Interrupt { vector } => PendingInjection::Vector(vector),
+            ReplayInput::Nmi => PendingInjection::Nmi,
+            _ => return Ok(None),
+        };
+        if entry.point.instructions > point.instructions {
+            return Ok(None);
+        }
+        if entry.point != point {
+            let actual = Self::injection_input(injection);
+            return Err(self.diverge(vcpu, point, Some(entry), actual));
+        }
+        self.advance(vcpu);
+        Ok(Some(injection))
+    }
+
+    fn injection_input(injection: PendingInjection) -> ReplayInput {
+        match injection {
+            PendingInjection::Vector(vector) => ReplayInput::Interrupt { vector },
+            PendingInjection::Nmi => ReplayInput::Nmi,
+        }
+    }
+
+    fn push(&mut self, vcpu: u16, point: ExecutionPoint, input: ReplayInput) {
+        self.log.entries.push(ReplayEntry { vcpu, point, input });
+    }
+
+    fn peek(&self, vcpu: u16) -> Option<ReplayEntry> {
+        let stream = self.streams.get(vcpu as usize)?;
+        let index = *stream.get(self.cursors[vcpu as usize])?;
+        Some(self.log.entries[index])
+    }
+
+    fn advance(&mut self, vcpu: u16) {
+        self.cursors[vcpu as usize] += 1;
+        self.check_finished();
+    }
+
+    fn check_finished(&mut self) {
+        let done = self.streams.iter().zip(&self.cursors).all(|(stream, cursor)| *cursor >= stream.len());
+        if done && self.mode == ReplayMode::Replaying {
+            info!("Replay of VM {} finished after {} inputs", self.log.vm_id.0, self.log.entries.len());
+            self.mode = ReplayMode::Finished;
+        }
+    }
+
+    /// Take the VCPU's next logged input, which has to be of the kind
+    /// `value_of` accepts and arrive at the same point
+    fn next_input(&mut self, vcpu: u16, point: ExecutionPoint, actual: ReplayInput,
+                  value_of: impl Fn(ReplayInput) -> Option<u64>) -> Result<u64, HypervisorError> {
+        if self.mode != ReplayMode::Replaying {
+            self.replay_state::<()>()?;
+            return Err(self.diverge(vcpu, point, None, actual));
+        }
+        let expected = self.peek(vcpu);
+        match expected {
+            Some(entry) if entry.point == point => match value_of(entry.input) {
+                Some(value) => {
+                    self.advance(vcpu);
+                    Ok(value)
+                }
+                None => Err(self.diverge(vcpu, point, expected, actual)),
+            },
+            _ => Err(self.diverge(vcpu, point, expected, actual)),
+        }
+    }
+
+    fn replay_state<T: Default>(&self) -> Result<T, HypervisorError> {
+        match self.mode {
+            ReplayMode::Diverged => Err(HypervisorError::InvalidVmState),
+            _ => Ok(T::default()),
+        }
+    }
+
+    fn diverge(&mut self, vcpu: u16, point: ExecutionPoint, expected: Option<ReplayEntry>, actual: ReplayInput) -> HypervisorError {
+        warn!("Replay of VM {} diverged on VCPU {} at instruction {} (RIP {:#x}): expected {:?}, got {:?}",
+              self.log.vm_id.0, vcpu, point.instructions, point.rip, expected.map(|entry| entry.input), actual);
+        self.mode = ReplayMode::Diverged;
+        self.divergence = Some(Divergence { vcpu, point, expected, actual });
+        ConfigError::new("replay", ConfigErrorReason::Mismatch).vm(self.log.vm_id).value(point.instructions).into()
+    }
+}
@@ -0,0 +1,480 @@
+//! SMP Guest Bringup
+//!
+//! An x86 guest starts with only its bootstrap processor (VCPU 0) running.
+//! The application processors wait until the guest sends them the
+//! INIT-SIPI-SIPI sequence through the local APIC interrupt command register
+//! (ICR). Each VCPU runs on its own scheduler thread from a `VcpuThreadPool`;
+//! a thread stays parked while its VCPU waits for a startup IPI or sits in
+//! HLT, and is woken when an IPI arrives for it.
+
+use crate::{VmId, HypervisorError, ConfigError, ConfigErrorReason};
+use crate::vcpu::{Vcpu, VcpuStateType};
+use crate::cpu::{ApicAccelerator, DeliveryAction};
+use multios_scheduler::{Priority, ThreadId, ThreadParams, THREAD_MANAGER};
+
+use alloc::collections::VecDeque;
+use alloc::format;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::{Mutex, RwLock};
+
+/// Guest physical address of the local APIC registers
+pub const LAPIC_BASE: u64 = 0xFEE0_0000;
+/// Guest physical address of the I/O APIC registers
+pub const IOAPIC_BASE: u64 = 0xFEC0_0000;
+/// Guest physical address of the MP floating pointer, in the BIOS area
+/// where legacy guests search for it
+pub const MP_TABLE_BASE: u64 = 0x000F_0000;
+/// x2APIC interrupt command register MSR
+pub const MSR_X2APIC_ICR: u32 = 0x830;
+/// xAPIC interrupt command register, offsets from `LAPIC_BASE`
+pub const XAPIC_ICR_LOW: u64 = 0x300;
+pub const XAPIC_ICR_HIGH: u64 = 0x310;
+
+/// Stack size of a VCPU thread
+const VCPU_THREAD_STACK_SIZE: usize = 64 * 1024;
+
+/// Multiprocessor state of a VCPU
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MpState {
+    /// Executing, or halted until its next interrupt
+    Runnable,
+    /// Reset by INIT; waits for a startup IPI
+    WaitForSipi,
+}
+
+/// ICR delivery mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IcrDeliveryMode {
+    Fixed,
+    LowestPriority,
+    Smi,
+    Nmi,
+    Init,
+    StartUp,
+    ExtInt,
+}
+
+/// ICR destination shorthand
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IcrShorthand {
+    None,
+    SelfOnly,
+    AllIncludingSelf,
+    AllExcludingSelf,
+}
+
+/// Decoded interrupt command register write
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IcrCommand {
+    pub vector: u8,
+    pub mode: IcrDeliveryMode,
+    /// Logical rather than physical destination
+    pub logical: bool,
+    /// Level assert; only an INIT de-assert clears it
+    pub assert: bool,
+    pub level_triggered: bool,
+    pub shorthand: IcrShorthand,
+    pub destination: u32,
+    /// x2APIC format: 32-bit destinations and cluster logical mode
+    pub x2apic: bool,
+}
+
+impl IcrCommand {
+    /// Decode a write of the x2APIC ICR MSR
+    pub fn from_x2apic(icr: u64) -> Self {
+        Self::decode(icr as u32, (icr >> 32) as u32, true)
+    }
+
+    /// Decode the xAPIC ICR; the write of the low half sends the IPI
+    pub fn from_xapic(low: u32, high: u32) -> Self {
+        Self::decode(low, high >> 24, false)
+    }
+
+    fn decode(low: u32, destination: u32, x2apic: bool) -> Self {
+        let mode = match (low >> 8) & 0x7 {
+            0 => IcrDeliveryMode::Fixed,
+            1 => IcrDeliveryMode::LowestPriority,
+            2 => IcrDeliveryMode::Smi,
+            4 => IcrDeliveryMode::Nmi,
+            5 => IcrDeliveryMode::Init,
+            6 => IcrDeliveryMode::StartUp,
+            _ => IcrDeliveryMode::ExtInt,
+        };
+        let shorthand = match (low >> 18) & 0x3 {
+            0 => IcrShorthand::None,
+            1 => IcrShorthand::SelfOnly,
+            2 => IcrShorthand::AllIncludingSelf,
+            _ => IcrShorthand::AllExcludingSelf,
+        };
+        IcrCommand {
+            vector: low as u8,
+            mode,
+            logical: low & (1 << 11) != 0,
+            assert: low & (1 << 14) != 0,
+            level_triggered: low & (1 << 15) != 0,
+            shorthand,
+            destination,
+            x2apic,
+        }
+    }
+
+    /// VCPUs addressed by an IPI from `source`; VCPU N has APIC ID N. In
+    /// xAPIC logical mode the flat model is assumed, with logical ID 1 << N.
+    pub fn targets(&self, source: usize, vcpu_count: usize) -> Vec<usize> {
+        let broadcast = if self.x2apic { u32::MAX } else { 0xFF };
+        match self.shorthand {
+            IcrShorthand::SelfOnly => alloc::vec![source],
+            IcrShorthand::AllIncludingSelf => (0..vcpu_count).collect(),
+            IcrShorthand::AllExcludingSelf => (0..vcpu_count).filter(|&vcpu| vcpu != source).collect(),
+            IcrShorthand::None if self.destination == broadcast => (0..vcpu_count).collect(),
+            IcrShorthand::None if self.logical && self.x2apic => {
+                let cluster = (self.destination >> 16) as usize;
+                (0..16)
+                    .filter(|bit| self.destination & (1 << bit) != 0)
+                    .map(|bit| cluster * 16 + bit)
+                    .filter(|&vcpu| vcpu < vcpu_count)
+                    .collect()
+            }
+            IcrShorthand::None if self.logical => (0..vcpu_count.min(8))
+                .filter(|&vcpu| self.destination & (1 << vcpu) != 0)
+                .collect(),
+            IcrShorthand::None => {
+                let vcpu = self.destination as usize;
+                if vcpu < vcpu_count { alloc::vec![vcpu] } else { Vec::new() }
+            }
+        }
+    }
+}
+
+/// State of a VCPU thread
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VcpuThreadState {
+    /// Waiting for a startup IPI or an interrupt
+    Parked,
+    Running,
+    /// Terminated when the VM stopped
+    Exited,
+}
+
+/// Scheduler thread running one VCPU
+#[derive(Debug, Clone, Copy)]
+pub struct VcpuThread {
+    pub thread_id: ThreadId,
+    pub state: VcpuThreadState,
+    pub wakeups: u64,
+}
+
+/// SMP bringup statistics of a VM
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SmpStats {
+    pub inits: u64,
+    pub sipis: u64,
+    /// Startup IPIs to VCPUs that were not waiting for one
+    pub ignored_sipis: u64,
+    pub fixed_ipis: u64,
+    pub nmis: u64,
+}
+
+/// VCPUs whose threads have been created but not yet picked up their VCPU
+static PENDING_STARTS: Mutex<VecDeque<(Arc<VcpuThreadPool>, usize)>> = Mutex::new(VecDeque::new());
+
+/// The scheduler threads running the VCPUs of one VM
+#[derive(Debug)]
+pub struct VcpuThreadPool {
+    vm_id: VmId,
+    vcpus: Vec<Arc<RwLock<Vcpu>>>,
+    interrupts: Arc<Mutex<ApicAccelerator>>,
+    threads: Mutex<Vec<VcpuThread>>,
+    stats: Mutex<SmpStats>,
+}
+
+impl VcpuThreadPool {
+    pub fn new(vm_id: VmId, vcpus: Vec<Arc<RwLock<Vcpu>>>, interrupts: Arc<Mutex<ApicAccelerator>>) -> Arc<Self> {
+        Arc::new(VcpuThreadPool {
+            vm_id,
+            vcpus,
+            interrupts,
+            threads: Mutex::new(Vec::new()),
+            stats: Mutex::new(SmpStats::default()),
+        })
+    }
+
+    /// Create one thread per VCPU. Only VCPUs that are runnable (the
+    /// bootstrap processor) start executing; the rest stay parked until
+    /// their startup IPI.
+    pub fn spawn(self: &Arc<Self>) -> Result<(), HypervisorError> {
+        let mut threads = self.threads.lock();
+        if !threads.is_empty() {
+            return Err(HypervisorError::InvalidVmState);
+        }
+
+        for (index, vcpu) in self.vcpus.iter().enumerate() {
+            let params = ThreadParams {
+                stack_size: VCPU_THREAD_STACK_SIZE,
+                priority: Priority::High,
+                detached: true,
+                inherit_priority: false,
+            };
+            let name = format!("vm{}-vcpu{}", self.vm_id.0, index).into_bytes();
+            let handle = THREAD_MANAGER.create_thread(0, name, Some(vcpu_thread_main), params)
+                .map_err(|err| {
+                    warn!("Cannot create thread for VM {} VCPU {}: {:?}", self.vm_id.0, index, err);
+                    HypervisorError::from(ConfigError::new("vcpu_thread", ConfigErrorReason::Exhausted).vm(self.vm_id).value(index as u64))
+                })?;
+            let thread_id = handle.lock().thread_id;
+
+            let state = if vcpu.read().mp_state == MpState::Runnable {
+                VcpuThreadState::Running
+            } else {
+                VcpuThreadState::Parked
+            };
+            threads.push(VcpuThread { thread_id, state, wakeups: 0 });
+            PENDING_STARTS.lock().push_back((self.clone(), index));
+        }
+
+        info!("VM {}: {} VCPU threads started", self.vm_id.0, threads.len());
+        Ok(())
+    }
+
+    /// Terminate the VCPU threads
+    pub fn stop(&self) {
+        let mut threads = self.threads.lock();
+        for thread in threads.iter_mut() {
+            thread.state = VcpuThreadState::Exited;
+            let _ = THREAD_MANAGER.terminate_thread(thread.thread_id);
+        }
+        threads.clear();
+    }
+
+    /// Send an IPI from VCPU `source`. Returns the interrupt deliveries the
+    /// caller still has to carry out (notification IPIs, doorbells, kicks).
+    pub fn send_ipi(&self, source: usize, icr: IcrCommand) -> Vec<(usize, DeliveryAction)> {
+        let mut targets = icr.targets(source, self.vcpus.len());
+        let mut actions = Vec::new();
+
+        match icr.mode {
+            // The level de-assert INIT of the legacy sequence does nothing
+            IcrDeliveryMode::Init if !icr.assert && icr.level_triggered => {}
+            IcrDeliveryMode::Init => {
+                for target in targets {
+                    self.vcpus[target].write().receive_init();
+                    self.set_thread_state(target, VcpuThreadState::Parked);
+                    self.stats.lock().inits += 1;
+                }
+            }
+            IcrDeliveryMode::StartUp => {
+                for target in targets {
+                    if self.vcpus[target].write().receive_sipi(icr.vector) {
+                        info!("VM {}: VCPU {} started at 0x{:x}", self.vm_id.0, target, (icr.vector as u64) << 12);
+                        self.wake(target);
+                        self.stats.lock().sipis += 1;
+                    } else {
+                        self.stats.lock().ignored_sipis += 1;
+                    }
+                }
+            }
+            IcrDeliveryMode::Fixed | IcrDeliveryMode::LowestPriority => {
+                if icr.mode == IcrDeliveryMode::LowestPriority {
+                    targets.truncate(1);
+                }
+                for target in targets {
+                    let action = self.interrupts.lock().deliver(target, icr.vector);
+                    self.complete_delivery(target, action, &mut actions);
+                    self.stats.lock().fixed_ipis += 1;
+                }
+            }
+            IcrDeliveryMode::Nmi => {
+                for target in targets {
+                    let action = self.interrupts.lock().deliver_nmi(target);
+                    self.complete_delivery(target, action, &mut actions);
+                    self.stats.lock().nmis += 1;
+                }
+            }
+            IcrDeliveryMode::Smi | IcrDeliveryMode::ExtInt => {
+                warn!("VM {}: VCPU {} sent unsupported {:?} IPI", self.vm_id.0, source, icr.mode);
+            }
+        }
+
+        actions
+    }
+
+    /// Wake the thread of a VCPU, e.g. for an interrupt arriving in HLT
+    pub fn wake(&self, vcpu: usize) {
+        let mut threads = self.threads.lock();
+        if let Some(thread) = threads.get_mut(vcpu) {
+            if thread.state == VcpuThreadState::Parked {
+                thread.state = VcpuThreadState::Running;
+                thread.wakeups += 1;
+                let _ = THREAD_MANAGER.wake_thread(thread.thread_id);
+            }
+        }
+    }
+
+    /// VCPUs whose threads are executing
+    pub fn running_vcpus(&self) -> usize {
+        self.threads.lock().iter().filter(|thread| thread.state == VcpuThreadState::Running).count()
+    }
+
+    pub fn threads(&self) -> Vec<VcpuThread> {
+        self.threads.lock().clone()
+    }
+
+    pub fn stats(&self) -> SmpStats {
+        *self.stats.lock()
+    }
+
+    /// Wake halted targets ourselves and hand the rest to the caller
+    fn complete_delivery(&self, target: usize, action: DeliveryAction, actions: &mut Vec<(usize, DeliveryAction)>) {
+        match action {
+            DeliveryAction::WakeVcpu => self.wake(target),
+            DeliveryAction::None => {}
+            action => actions.push((target, action)),
+        }
+    }
+
+    fn thread_state(&self, vcpu: usize) -> VcpuThreadState {
+        self.threads.lock().get(vcpu).map_or(VcpuThreadState::Exited, |thread| thread.state)
+    }
+
+    fn set_thread_state(&self, vcpu: usize, state: VcpuThreadState) {
+        if let Some(thread) = self.threads.lock().get_mut(vcpu) {
+            if thread.state != VcpuThreadState::Exited {
+                thread.state = state;
+            }
+        }
+    }
+
+    /// Body of a VCPU thread
+    fn run_vcpu(&self, index: usize) -> ! {
+        loop {
+            match self.thread_state(index) {
+                VcpuThreadState::Running => {}
+                VcpuThreadState::Parked | VcpuThreadState::Exited => {
+                    core::hint::spin_loop();
+                    continue;
+                }
+            }
+
+            let (result, icr, halted) = {
+                let mut vcpu = self.vcpus[index].write();
+                if vcpu.mp_state != MpState::Runnable || vcpu.state == VcpuStateType::Paused {
+                    drop(vcpu);
+                    core::hint::spin_loop();
+                    continue;
+                }
+                let result = vcpu.run();
+                (result, vcpu.take_pending_icr(), vcpu.state == VcpuStateType::Halted)
+            };
+
+            if let Err(err) = result {
+                warn!("VM {}: VCPU {} stopped: {:?}", self.vm_id.0, index, err);
+                self.set_thread_state(index, VcpuThreadState::Exited);
+                continue;
+            }
+            if let Some(icr) = icr {
+                // Kicks and doorbells for running targets are sent by the
+                // interrupt path of the host; nothing more to do here
+                let _ = self.send_ipi(index, IcrCommand::from_x2apic(icr));
+            }
+            if halted {
+                self.set_thread_state(index, VcpuThreadState::Parked);
+            }
+        }
+    }
+}
+
+/// Entry point of VCPU threads; each picks up the next queued VCPU
+fn vcpu_thread_main() -> ! {
+    let (pool, index) = loop {
+        if let Some(start) = PENDING_STARTS.lock().pop_front() {
+            break start;
+        }
+        core::hint::spin_loop();
+    };
+    pool.run_vcpu(index)
+}
+
+/// Build the Intel MP specification tables for `vcpu_count` VCPUs: the
+/// floating pointer at `base` followed by the configuration table. Only
+/// APIC IDs below 255 can be described; larger guests rely on the MADT.
+pub fn build_mp_table(vcpu_count: usize, base: u64) -> Vec<u8> {
+    const MP_SPEC_REVISION: u8 = 4;
+    const PROCESSOR_ENTRY: u8 = 0;
+    const BUS_ENTRY: u8 = 1;
+    const IOAPIC_ENTRY: u8 = 2;
+    const IO_INTERRUPT_ENTRY: u8 = 3;
+    const LOCAL_INTERRUPT_ENTRY: u8 = 4;
+    const CPU_ENABLED: u8 = 1 << 0;
+    const CPU_BOOTSTRAP: u8 = 1 << 1;
+    const LAPIC_VERSION: u8 = 0x14;
+    const IOAPIC_VERSION: u8 = 0x11;
+
+    let cpus = vcpu_count.min(0xFE);
+    let ioapic_id = cpus as u8;
+    let mut entries = Vec::new();
+    let mut entry_count = 0u16;
+
+    for cpu in 0..cpus {
+        let flags = if cpu == 0 { CPU_ENABLED | CPU_BOOTSTRAP } else { CPU_ENABLED };
+        entries.extend_from_slice(&[PROCESSOR_ENTRY, cpu as u8, LAPIC_VERSION, flags]);
+        // CPU signature, feature flags and reserved; guests read CPUID
+        entries.extend_from_slice(&[0; 16]);
+        entry_count += 1;
+    }
+
+    entries.extend_from_slice(&[BUS_ENTRY, 0]);
+    entries.extend_from_slice(b"ISA   ");
+    entry_count += 1;
+
+    entries.extend_from_slice(&[IOAPIC_ENTRY, ioapic_id, IOAPIC_VERSION, 1]);
+    entries.extend_from_slice(&(IOAPIC_BASE as u32).to_le_bytes());
+    entry_count += 1;
+
+    // ISA IRQs map one to one, except the PIT on pin 2
+    for irq in 0..16u8 {
+        let pin = if irq == 0 { 2 } else { irq };
+        if irq == 2 {
+            continue;
+        }
+        entries.extend_from_slice(&[IO_INTERRUPT_ENTRY, 0, 0, 0, 0, irq, ioapic_id, pin]);
+        entry_count += 1;
+    }
+
+    // ExtINT on LINT0 and NMI on LINT1 of every local APIC
+    entries.extend_from_slice(&[LOCAL_INTERRUPT_ENTRY, 3, 0, 0, 0, 0, 0xFF, 0]);
+    entries.extend_from_slice(&[LOCAL_INTERRUPT_ENTRY, 1, 0, 0, 0, 0, 0xFF, 1]);
+    entry_count += 2;
+
+    let config_base = base + 16;
+    let mut table = Vec::with_capacity(16 + 44 + entries.len());
+
+    // Floating pointer structure
+    table.extend_from_slice(b"_MP_");
+    table.extend_from_slice(&(config_base as u32).to_le_bytes());
+    table.extend_from_slice(&[1, MP_SPEC_REVISION, 0]);
+    table.extend_from_slice(&[0; 5]);
+    table[10] = checksum(&table[0..16]);
+
+    // Configuration table header
+    let config_start = table.len();
+    table.extend_from_slice(b"PCMP");
+    table.extend_from_slice(&((44 + entries.len()) as u16).to_le_bytes());
+    table.extend_from_slice(&[MP_SPEC_REVISION, 0]);
+    table.extend_from_slice(b"MULTIOS ");
+    table.extend_from_slice(b"HYPERVISOR  ");
+    table.extend_from_slice(&0u32.to_le_bytes());
+    table.extend_from_slice(&0u16.to_le_bytes());
+    table.extend_from_slice(&entry_count.to_le_bytes());
+    table.extend_from_slice(&(LAPIC_BASE as u32).to_le_bytes());
+    table.extend_from_slice(&[0; 4]);
+    table.extend_from_slice(&entries);
+    table[config_start + 7] = checksum(&table[config_start..]);
+
+    table
+}
+
+/// Byte that makes `bytes` sum to zero
+fn checksum(bytes: &[u8]) -> u8 {
+    0u8.wrapping_sub(bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)))
+}
@@ -5,9 +5,13 @@
 
 use crate::{VmId, HypervisorError, MAX_VCPUS_PER_VM};
 use crate::hypervisor::HypervisorCapabilities;
+use crate::cpu::{CpuModel, MsrDisposition, host_cpuid};
+use crate::smp::{MpState, MSR_X2APIC_ICR};
+use crate::credit_scheduler::MSR_KVM_STEAL_TIME;
+use crate::record_replay::{ExecutionPoint, RecordReplay};
 
 use alloc::sync::Arc;
-use spin::RwLock;
+use spin::{Mutex, RwLock};
 use bitflags::bitflags;
 
 /// Virtual CPU ID
@@ -92,7 +96,7 @@ bitflags! {
 }
 
 /// VM Exit reason enumeration
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum VmExitReason {
     Exception,
     Interrupt,
@@ -126,6 +130,15 @@ pub enum VmExitReason {
     Unknown,
 }
 
+/// Architectural state of a VCPU at a checkpoint
+#[derive(Debug, Clone, Copy)]
+pub struct VcpuCheckpoint {
+    pub vcpu_state: VcpuState,
+    pub instruction_count: u64,
+    pub mp_state: MpState,
+    pub pending_exception: Option<u8>,
+}
+
 /// Virtual CPU structure
 #[derive(Debug)]
 pub struct Vcpu {
@@ -139,11 +152,28 @@ pub struct Vcpu {
     pub vm_exit_count: u64,
     pub instruction_count: u64,
     pub last_exit_time: u64,
+    /// CPUID/MSR view shared by all VCPUs of the VM
+    pub cpu_model: Arc<CpuModel>,
+    /// Exception vector to inject on the next VM entry
+    pub pending_exception: Option<u8>,
+    /// Whether the VCPU executes or waits for a startup IPI
+    pub mp_state: MpState,
+    /// ICR write waiting to be routed to the other VCPUs
+    pending_icr: Option<u64>,
+    /// Guest address of the steal time record, once the guest enabled it
+    pub steal_time_gpa: Option<u64>,
+    /// Recorder or replayer of the VM's non-deterministic inputs
+    pub record_replay: Option<Arc<Mutex<RecordReplay>>>,
 }
 
+/// #GP exception vector
+const GP_VECTOR: u8 = 13;
+/// Time stamp counter MSR
+const MSR_IA32_TSC: u32 = 0x10;
+
 impl Vcpu {
     /// Create a new VCPU
-    pub fn new(vm_id: VmId, vcpu_id: usize) -> Result<Self, HypervisorError> {
+    pub fn new(vm_id: VmId, vcpu_id: usize, cpu_model: Arc<CpuModel>) -> Result<Self, HypervisorError> {
         if vcpu_id >= MAX_VCPUS_PER_VM {
             return Err(HypervisorError::TooManyVcpus);
         }
@@ -189,9 +219,150 @@ impl Vcpu {
             vm_exit_count: 0,
             instruction_count: 0,
             last_exit_time: 0,
+            cpu_model,
+            pending_exception: None,
+            mp_state: MpState::Runnable,
+            pending_icr: None,
+            steal_time_gpa: None,
+            record_replay: None,
         })
     }
     
+    /// Whether this is the bootstrap processor
+    pub fn is_bsp(&self) -> bool {
+        self.vcpu_id == 0
+    }
+    
+    /// Run until the VCPU halts or sends an IPI
+    pub fn run(&mut self) -> Result<(), HypervisorError> {
+        if self.mp_state != MpState::Runnable {
+            return Err(HypervisorError::InvalidVcpuState);
+        }
+        match self.state {
+            VcpuStateType::Paused | VcpuStateType::Error => Err(HypervisorError::InvalidVcpuState),
+            _ => {
+                self.state = VcpuStateType::Running;
+                self.execute_instruction_loop()
+            }
+        }
+    }
+    
+    /// INIT IPI: reset to the power-on state. Application processors then
+    /// wait for a startup IPI; the bootstrap processor restarts at the
+    /// reset vector.
+    pub fn receive_init(&mut self) {
+        let regs = &mut self.vcpu_state.regs;
+        *regs = VcpuRegs {
+            rax: 0, rbx: 0, rcx: 0, rdx: 0,
+            rsi: 0, rdi: 0, rbp: 0, rsp: 0,
+            r8: 0, r9: 0, r10: 0, r11: 0,
+            r12: 0, r13: 0, r14: 0, r15: 0,
+            rip: 0, rflags: 2,
+        };
+        let ctrl = &mut self.vcpu_state.ctrl_regs;
+        ctrl.cr0 = 0x6000_0010; // CD, NW, ET
+        ctrl.cr2 = 0;
+        ctrl.cr3 = 0;
+        ctrl.cr4 = 0;
+        ctrl.gdt_base = 0;
+        ctrl.gdt_limit = 0xffff;
+        ctrl.idt_base = 0;
+        ctrl.idt_limit = 0xffff;
+        self.vcpu_state.ds_selector = 0;
+        self.vcpu_state.es_selector = 0;
+        self.vcpu_state.fs_selector = 0;
+        self.vcpu_state.gs_selector = 0;
+        self.vcpu_state.ss_selector = 0;
+        self.pending_exception = None;
+        self.pending_icr = None;
+        
+        if self.is_bsp() {
+            // F000:FFF0 with the CS base at 0xFFFF0000 is applied on VM entry
+            self.vcpu_state.cs_selector = 0xf000;
+            self.vcpu_state.regs.rip = 0xfff0;
+            self.mp_state = MpState::Runnable;
+        } else {
+            self.vcpu_state.cs_selector = 0;
+            self.mp_state = MpState::WaitForSipi;
+        }
+        self.state = VcpuStateType::Halted;
+    }
+    
+    /// Startup IPI: begin executing in real mode at `vector` * 4 KiB.
+    /// Ignored unless the VCPU is waiting for one, so the second SIPI of
+    /// the INIT-SIPI-SIPI sequence is harmless.
+    pub fn receive_sipi(&mut self, vector: u8) -> bool {
+        if self.mp_state != MpState::WaitForSipi {
+            return false;
+        }
+        // Real mode: CS base is the selector shifted left by 4
+        self.vcpu_state.cs_selector = (vector as u16) << 8;
+        self.vcpu_state.regs.rip = 0;
+        self.mp_state = MpState::Runnable;
+        true
+    }
+    
+    /// Take the ICR write the guest made since the last call
+    pub fn take_pending_icr(&mut self) -> Option<u64> {
+        self.pending_icr.take()
+    }
+    
+    /// Where the VCPU is in its instruction stream
+    pub fn execution_point(&self) -> ExecutionPoint {
+        ExecutionPoint {
+            instructions: self.instruction_count,
+            rip: self.vcpu_state.regs.rip,
+        }
+    }
+    
+    pub fn checkpoint(&self) -> VcpuCheckpoint {
+        VcpuCheckpoint {
+            vcpu_state: self.vcpu_state,
+            instruction_count: self.instruction_count,
+            mp_state: self.mp_state,
+            pending_exception: self.pending_exception,
+        }
+    }
+    
+    /// Go back to a checkpoint; the instruction count goes back with it so
+    /// replayed inputs line up
+    pub fn restore_checkpoint(&mut self, checkpoint: &VcpuCheckpoint) {
+        self.vcpu_state = checkpoint.vcpu_state;
+        self.instruction_count = checkpoint.instruction_count;
+        self.mp_state = checkpoint.mp_state;
+        self.pending_exception = checkpoint.pending_exception;
+        self.pending_icr = None;
+    }
+    
+    /// Complete a port read the guest made; `device` performs it, unless a
+    /// replay supplies the recorded result
+    pub fn port_read(&mut self, port: u16, size: u8, device: impl FnOnce() -> u32) -> Result<u32, HypervisorError> {
+        let point = self.execution_point();
+        match &self.record_replay {
+            Some(record_replay) => record_replay.lock().port_read(self.vcpu_id as u16, point, port, size, device),
+            None => Ok(device()),
+        }
+    }
+    
+    /// Complete an emulated MMIO read the guest made
+    pub fn mmio_read(&mut self, address: u64, size: u8, device: impl FnOnce() -> u64) -> Result<u64, HypervisorError> {
+        let point = self.execution_point();
+        match &self.record_replay {
+            Some(record_replay) => record_replay.lock().mmio_read(self.vcpu_id as u16, point, address, size, device),
+            None => Ok(device()),
+        }
+    }
+    
+    /// Guest TSC for an intercepted RDTSC, RDTSCP or IA32_TSC read
+    pub fn read_tsc(&mut self, tsc_offset: u64) -> Result<u64, HypervisorError> {
+        let point = self.execution_point();
+        let host = || host_tsc().wrapping_add(tsc_offset);
+        match &self.record_replay {
+            Some(record_replay) => record_replay.lock().tsc(self.vcpu_id as u16, point, host),
+            None => Ok(host()),
+        }
+    }
+    
     /// Initialize the VCPU
     pub fn initialize(&mut self) -> Result<(), HypervisorError> {
         // Configure VMCS/VMCB based on hardware capabilities
@@ -277,6 +448,10 @@ impl Vcpu {
                 _ => {
                     // Handle other VM exits
                     self.handle_vm_exit(exit_reason)?;
+                    // IPIs are routed by the VM, outside of this VCPU
+                    if self.pending_icr.is_some() {
+                        break;
+                    }
                 }
             }
         }
@@ -306,6 +481,19 @@ impl Vcpu {
         Ok(exit_reason)
     }
     
+    /// Dispatch one VM exit as the execution loop would, without running
+    /// the guest; used by the exit micro-benchmarks
+    pub fn handle_exit(&mut self, reason: VmExitReason) -> Result<(), HypervisorError> {
+        self.exit_reason = Some(reason);
+        self.vm_exit_count += 1;
+
+        match reason {
+            VmExitReason::SoftwareInterrupt => self.handle_system_call(),
+            VmExitReason::Exception => self.handle_exception(),
+            _ => self.handle_vm_exit(reason),
+        }
+    }
+
     /// Setup VMCS/VMCB structure
     fn setup_vmcs_structure(&self) -> Result<(), HypervisorError> {
         // Configure VMCS for Intel VT-x or VMCB for AMD-V
@@ -360,19 +548,62 @@ impl Vcpu {
     
     /// Handle MSR read
     fn handle_msr_read(&mut self) -> Result<(), HypervisorError> {
-        // Simulate MSR read operation
+        let index = self.vcpu_state.regs.rcx as u32;
+        let value = match self.cpu_model.filter_msr(index, false) {
+            // The TSC is intercepted only while recording or replaying
+            MsrDisposition::Allow if index == MSR_IA32_TSC && self.record_replay.is_some() => self.read_tsc(0)?,
+            MsrDisposition::Allow => self.vcpu_state.msrs.iter()
+                .find(|entry| entry.index == index)
+                .map_or(0, |entry| entry.value),
+            MsrDisposition::ReadOnly(value) => value,
+            MsrDisposition::Fault => {
+                self.pending_exception = Some(GP_VECTOR);
+                return Ok(());
+            }
+        };
+        
+        self.vcpu_state.regs.rax = value & 0xffff_ffff;
+        self.vcpu_state.regs.rdx = value >> 32;
         Ok(())
     }
     
     /// Handle MSR write
     fn handle_msr_write(&mut self) -> Result<(), HypervisorError> {
-        // Simulate MSR write operation
+        let index = self.vcpu_state.regs.rcx as u32;
+        if self.cpu_model.filter_msr(index, true) != MsrDisposition::Allow {
+            self.pending_exception = Some(GP_VECTOR);
+            return Ok(());
+        }
+        
+        let value = ((self.vcpu_state.regs.rdx & 0xffff_ffff) << 32) | (self.vcpu_state.regs.rax & 0xffff_ffff);
+        if index == MSR_X2APIC_ICR {
+            self.pending_icr = Some(value);
+            return Ok(());
+        }
+        if index == MSR_KVM_STEAL_TIME {
+            // The record is 64-byte aligned; bit 0 enables it
+            self.steal_time_gpa = if value & 1 != 0 { Some(value & !0x3f) } else { None };
+        }
+        // Update the saved MSR, or take a free slot (index 0 is unused)
+        let slot = self.vcpu_state.msrs.iter().position(|entry| entry.index == index)
+            .or_else(|| self.vcpu_state.msrs.iter().position(|entry| entry.index == 0));
+        match slot {
+            Some(slot) => self.vcpu_state.msrs[slot] = MsrEntry { index, value },
+            None => warn!("VCPU {} of VM {}: no MSR slot for 0x{:x}", self.vcpu_id, self.vm_id.0, index),
+        }
         Ok(())
     }
     
     /// Handle CPUID instruction
     fn handle_cpuid(&mut self) -> Result<(), HypervisorError> {
-        // Simulate CPUID instruction
+        let leaf = self.vcpu_state.regs.rax as u32;
+        let subleaf = self.vcpu_state.regs.rcx as u32;
+        let result = self.cpu_model.filter_cpuid(leaf, subleaf, self.vcpu_id as u32, host_cpuid(leaf, subleaf));
+        
+        self.vcpu_state.regs.rax = result.eax as u64;
+        self.vcpu_state.regs.rbx = result.ebx as u64;
+        self.vcpu_state.regs.rcx = result.ecx as u64;
+        self.vcpu_state.regs.rdx = result.edx as u64;
         Ok(())
     }
     
@@ -396,6 +627,18 @@ pub struct CpuStats {
     pub instruction_count: u64,
 }
 
+/// Host time stamp counter
+fn host_tsc() -> u64 {
+    #[cfg(target_arch = "x86_64")]
+    unsafe {
+        core::arch::x86_64::_rdtsc()
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    {
+        0
+    }
+}
+
 /// VCPU Manager
 pub struct VcpuManager {
     total_vcpus: usize,
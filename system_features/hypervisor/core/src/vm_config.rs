@@ -3,7 +3,10 @@
 //! Defines the configuration structure for virtual machines and error types
 //! used throughout the hypervisor system.
 
+use crate::cpu::CpuModel;
+
 use alloc::string::String;
+use alloc::vec::Vec;
 use bitflags::bitflags;
 
 /// Virtual Machine ID
@@ -33,6 +36,11 @@ pub struct VmConfig {
     pub memory_mb: u64,
     /// CPU architecture type
     pub arch: VmArchitecture,
+    /// CPUID and MSR view of x86 guests; pick a fixed model for VMs that
+    /// are snapshotted or migrated between hosts
+    pub cpu_model: CpuModel,
+    /// Virtual NUMA topology; None presents a single node
+    pub numa: Option<VnumaConfig>,
     /// Boot configuration
     pub boot: BootConfig,
     /// Device configuration
@@ -55,6 +63,8 @@ impl VmConfig {
             vcpu_count,
             memory_mb,
             arch: VmArchitecture::X86_64,
+            cpu_model: CpuModel::host(),
+            numa: None,
             boot: BootConfig::default(),
             devices: DeviceConfig::default(),
             features: VmFeatures::empty(),
@@ -71,6 +81,8 @@ impl VmConfig {
             vcpu_count: 1,
             memory_mb: 512,
             arch: VmArchitecture::X86_64,
+            cpu_model: CpuModel::edu_baseline(),
+            numa: None,
             boot: BootConfig::default(),
             devices: DeviceConfig::educational(),
             features: VmFeatures::EDUCATIONAL | VmFeatures::DEBUG,
@@ -87,6 +99,8 @@ impl VmConfig {
             vcpu_count: host_vcpu_count,
             memory_mb: 4096,
             arch: VmArchitecture::X86_64,
+            cpu_model: CpuModel::host().with_nested(),
+            numa: None,
             boot: BootConfig::default(),
             devices: DeviceConfig::nested(),
             features: VmFeatures::NESTED | VmFeatures::RESOURCE_MONITORING,
@@ -97,6 +111,93 @@ impl VmConfig {
     }
 }
 
+/// One node of a virtual NUMA topology
+#[derive(Debug, Clone, PartialEq)]
+pub struct VnumaNode {
+    /// Guest memory on this node in MB
+    pub memory_mb: u64,
+    /// VCPUs (by index) that belong to this node
+    pub vcpus: Vec<usize>,
+    /// Host NUMA node backing this node's memory
+    pub host_node: usize,
+    /// SLIT distances from this node to every node, itself included
+    pub distances: Vec<u8>,
+}
+
+/// Virtual NUMA topology presented to the guest through SRAT and SLIT
+#[derive(Debug, Clone, PartialEq)]
+pub struct VnumaConfig {
+    pub nodes: Vec<VnumaNode>,
+}
+
+/// SLIT distance of a node to itself
+pub const NUMA_LOCAL_DISTANCE: u8 = 10;
+/// SLIT distance between nodes when none is given
+pub const NUMA_REMOTE_DISTANCE: u8 = 20;
+
+impl VnumaConfig {
+    /// Split memory and VCPUs evenly over `node_count` nodes, backing
+    /// virtual node N with host node N
+    pub fn even(node_count: usize, vcpu_count: usize, memory_mb: u64) -> Self {
+        let node_count = node_count.max(1);
+        let nodes = (0..node_count)
+            .map(|node| {
+                let mut node_memory = memory_mb / node_count as u64;
+                if node == node_count - 1 {
+                    node_memory += memory_mb % node_count as u64;
+                }
+                VnumaNode {
+                    memory_mb: node_memory,
+                    vcpus: (0..vcpu_count).filter(|vcpu| vcpu * node_count / vcpu_count.max(1) == node).collect(),
+                    host_node: node,
+                    distances: (0..node_count)
+                        .map(|other| if other == node { NUMA_LOCAL_DISTANCE } else { NUMA_REMOTE_DISTANCE })
+                        .collect(),
+                }
+            })
+            .collect();
+        VnumaConfig { nodes }
+    }
+
+    /// Node a VCPU belongs to
+    pub fn node_of_vcpu(&self, vcpu: usize) -> Option<usize> {
+        self.nodes.iter().position(|node| node.vcpus.contains(&vcpu))
+    }
+
+    /// Check the topology against the VM's memory size and VCPU count
+    pub fn validate(&self, vcpu_count: usize, memory_mb: u64) -> Result<(), HypervisorError> {
+        if self.nodes.is_empty() {
+            return Err(ConfigError::new("vnuma.nodes", ConfigErrorReason::NotFound).into());
+        }
+        let total: u64 = self.nodes.iter().map(|node| node.memory_mb).sum();
+        if total != memory_mb {
+            return Err(ConfigError::new("vnuma.memory_mb", ConfigErrorReason::Mismatch).value(total).into());
+        }
+        for vcpu in 0..vcpu_count {
+            let owners = self.nodes.iter().filter(|node| node.vcpus.contains(&vcpu)).count();
+            if owners != 1 {
+                return Err(ConfigError::new("vnuma.vcpus", ConfigErrorReason::Mismatch).value(vcpu as u64).into());
+            }
+        }
+        for (index, node) in self.nodes.iter().enumerate() {
+            if node.vcpus.iter().any(|&vcpu| vcpu >= vcpu_count) {
+                return Err(ConfigError::new("vnuma.vcpus", ConfigErrorReason::OutOfRange).value(index as u64).into());
+            }
+            if node.distances.len() != self.nodes.len() {
+                return Err(ConfigError::new("vnuma.distances", ConfigErrorReason::Mismatch).value(index as u64).into());
+            }
+            // ACPI requires 10 for the local distance and more for remote nodes
+            for (other, &distance) in node.distances.iter().enumerate() {
+                let valid = if other == index { distance == NUMA_LOCAL_DISTANCE } else { distance > NUMA_LOCAL_DISTANCE && distance != 0xFF };
+                if !valid {
+                    return Err(ConfigError::new("vnuma.distances", ConfigErrorReason::OutOfRange).value(distance as u64).into());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
 /// CPU Architecture for VMs
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum VmArchitecture {
@@ -108,6 +209,8 @@ pub enum VmArchitecture {
     AArch64,
     /// ARM 32-bit architecture
     ARMv7,
+    /// RISC-V 64-bit architecture (RV64GC with the H extension on the host)
+    RiscV64,
 }
 
 /// Boot Configuration
@@ -180,6 +283,8 @@ pub struct DeviceConfig {
     pub audio: AudioConfig,
     /// USB controller configuration
     pub usb: UsbConfig,
+    /// RTC and HPET configuration
+    pub clock: ClockConfig,
 }
 
 impl Default for DeviceConfig {
@@ -191,6 +296,7 @@ impl Default for DeviceConfig {
             serial_console: SerialConfig::default(),
             audio: AudioConfig::disabled(),
             usb: UsbConfig::disabled(),
+            clock: ClockConfig::default(),
         }
     }
 }
@@ -205,6 +311,7 @@ impl DeviceConfig {
             serial_console: SerialConfig::enabled(),
             audio: AudioConfig::disabled(),
             usb: UsbConfig::disabled(),
+            clock: ClockConfig::default(),
         }
     }
     
@@ -217,6 +324,7 @@ impl DeviceConfig {
             serial_console: SerialConfig::default(),
             audio: AudioConfig::disabled(),
             usb: UsbConfig::default(),
+            clock: ClockConfig::default(),
         }
     }
 }
@@ -364,6 +472,36 @@ pub enum CacheMode {
     WriteBack,
 }
 
+/// What the guest's RTC counts
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RtcBase {
+    /// UTC, as Linux and most Unix guests expect
+    Utc,
+    /// Local time at an offset from UTC, as Windows guests expect
+    LocalTime { offset_minutes: i32 },
+}
+
+/// Clock device configuration
+#[derive(Debug, Clone, Copy)]
+pub struct ClockConfig {
+    pub rtc_base: RtcBase,
+    /// Fixed RTC start time in seconds since the Unix epoch, for
+    /// reproducible runs; None starts at the host's time
+    pub start_time: Option<u64>,
+    /// Provide an HPET besides the RTC
+    pub hpet: bool,
+}
+
+impl Default for ClockConfig {
+    fn default() -> Self {
+        ClockConfig {
+            rtc_base: RtcBase::Utc,
+            start_time: None,
+            hpet: true,
+        }
+    }
+}
+
 /// Serial Console Configuration
 #[derive(Debug, Clone)]
 pub struct SerialConfig {
@@ -610,7 +748,7 @@ pub enum HypervisorError {
     /// Too many VCPUs requested
     TooManyVcpus,
     /// VM not found
-    VmNotFound,
+    VmNotFound(VmId),
     /// VCPU not found
     VcpuNotFound,
     /// Invalid VM state
@@ -622,17 +760,229 @@ pub enum HypervisorError {
     /// Feature not supported
     FeatureNotSupported,
     /// Configuration error
-    ConfigurationError(String),
+    Config(ConfigError),
     /// Hardware virtualization not available
     HardwareVirtNotAvailable,
     /// Memory allocation failed
     MemoryAllocationFailed,
     /// I/O error
-    IoError(String),
+    Io(IoError),
     /// Invalid parameter
     InvalidParameter,
 }
 
+/// Why a setting was rejected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigErrorReason {
+    /// Value outside the allowed range
+    OutOfRange,
+    /// Address or size not suitably aligned
+    Misaligned,
+    /// Object exists already
+    AlreadyExists,
+    /// Referenced object does not exist
+    NotFound,
+    /// Feature or mode is enabled already
+    AlreadyEnabled,
+    /// Feature or mode is not enabled
+    NotEnabled,
+    /// No free slot, address or space is left, or a limit was reached
+    Exhausted,
+    /// Saved state does not fit the object it is restored into
+    Mismatch,
+    /// Stored data cannot be parsed
+    Malformed,
+    /// Forbidden by the security policy
+    Denied,
+}
+
+impl ConfigErrorReason {
+    fn description(&self) -> &'static str {
+        match self {
+            ConfigErrorReason::OutOfRange => "out of range",
+            ConfigErrorReason::Misaligned => "misaligned",
+            ConfigErrorReason::AlreadyExists => "already exists",
+            ConfigErrorReason::NotFound => "not found",
+            ConfigErrorReason::AlreadyEnabled => "already enabled",
+            ConfigErrorReason::NotEnabled => "not enabled",
+            ConfigErrorReason::Exhausted => "exhausted",
+            ConfigErrorReason::Mismatch => "does not match",
+            ConfigErrorReason::Malformed => "malformed",
+            ConfigErrorReason::Denied => "denied",
+        }
+    }
+}
+
+/// A rejected setting
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigError {
+    /// Offending setting or object, e.g. `"vnuma.distances"` or `"pci.bar"`
+    pub field: &'static str,
+    pub reason: ConfigErrorReason,
+    /// VM the setting belongs to
+    pub vm_id: Option<VmId>,
+    /// Offending value, e.g. an index, address or size
+    pub value: Option<u64>,
+}
+
+impl ConfigError {
+    pub fn new(field: &'static str, reason: ConfigErrorReason) -> Self {
+        ConfigError { field, reason, vm_id: None, value: None }
+    }
+
+    pub fn vm(mut self, vm_id: VmId) -> Self {
+        self.vm_id = Some(vm_id);
+        self
+    }
+
+    pub fn value(mut self, value: u64) -> Self {
+        self.value = Some(value);
+        self
+    }
+}
+
+impl From<ConfigError> for HypervisorError {
+    fn from(error: ConfigError) -> Self {
+        HypervisorError::Config(error)
+    }
+}
+
+/// Why a device access or transfer failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoErrorKind {
+    /// Nothing decodes the port, address or device ID
+    NoDevice,
+    /// Access of an unsupported width, offset or direction
+    InvalidAccess,
+    /// The guest or a peer broke the protocol of a ring or stream
+    Protocol,
+    /// The connection or backing file is gone
+    Closed,
+    /// Host storage failed or returned short data
+    Backend,
+    /// Stored data is malformed or fails its checksum
+    Corrupt,
+}
+
+impl IoErrorKind {
+    fn description(&self) -> &'static str {
+        match self {
+            IoErrorKind::NoDevice => "no device",
+            IoErrorKind::InvalidAccess => "invalid access",
+            IoErrorKind::Protocol => "protocol violation",
+            IoErrorKind::Closed => "closed",
+            IoErrorKind::Backend => "backend failure",
+            IoErrorKind::Corrupt => "corrupt data",
+        }
+    }
+}
+
+/// A failed device access or transfer
+#[derive(Debug, Clone, PartialEq)]
+pub struct IoError {
+    pub kind: IoErrorKind,
+    /// Device or stream involved, e.g. `"pci"` or `"virtqueue"`
+    pub source: &'static str,
+    /// Port, guest address, device ID or block accessed
+    pub address: Option<u64>,
+}
+
+impl IoError {
+    pub fn new(source: &'static str, kind: IoErrorKind) -> Self {
+        IoError { kind, source, address: None }
+    }
+
+    pub fn at(mut self, address: u64) -> Self {
+        self.address = Some(address);
+        self
+    }
+}
+
+impl From<IoError> for HypervisorError {
+    fn from(error: IoError) -> Self {
+        HypervisorError::Io(error)
+    }
+}
+
+impl HypervisorError {
+    /// Stable numeric code for the management API. The hundreds digit
+    /// is the category: 1 hardware, 2 resources, 3 lookup, 4 state,
+    /// 5 configuration and 6 I/O.
+    pub fn to_code(&self) -> u16 {
+        match self {
+            HypervisorError::InsufficientHardwareSupport => 100,
+            HypervisorError::HardwareVirtNotAvailable => 101,
+            HypervisorError::FeatureNotSupported => 102,
+            HypervisorError::TooManyVms => 200,
+            HypervisorError::TooManyVcpus => 201,
+            HypervisorError::MemoryAllocationFailed => 202,
+            HypervisorError::VmNotFound(_) => 300,
+            HypervisorError::VcpuNotFound => 301,
+            HypervisorError::InvalidVmState => 400,
+            HypervisorError::InvalidVcpuState => 401,
+            HypervisorError::CannotDeleteRunningVm => 402,
+            HypervisorError::InvalidParameter => 500,
+            HypervisorError::Config(error) => 501 + error.reason as u16,
+            HypervisorError::Io(error) => 600 + error.kind as u16,
+        }
+    }
+
+    /// Process exit status for command line tools, following sysexits.h
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            HypervisorError::InsufficientHardwareSupport
+            | HypervisorError::HardwareVirtNotAvailable
+            | HypervisorError::FeatureNotSupported => 69, // EX_UNAVAILABLE
+            HypervisorError::TooManyVms
+            | HypervisorError::TooManyVcpus
+            | HypervisorError::MemoryAllocationFailed
+            | HypervisorError::InvalidVmState
+            | HypervisorError::InvalidVcpuState
+            | HypervisorError::CannotDeleteRunningVm => 75, // EX_TEMPFAIL
+            HypervisorError::VmNotFound(_) | HypervisorError::VcpuNotFound => 66, // EX_NOINPUT
+            HypervisorError::InvalidParameter => 64, // EX_USAGE
+            HypervisorError::Config(error) if error.reason == ConfigErrorReason::Malformed => 65, // EX_DATAERR
+            HypervisorError::Config(_) => 78, // EX_CONFIG
+            HypervisorError::Io(error) if error.kind == IoErrorKind::Protocol => 76, // EX_PROTOCOL
+            HypervisorError::Io(_) => 74, // EX_IOERR
+        }
+    }
+}
+
+/// Error body returned by the management API
+#[derive(Debug, Clone, PartialEq)]
+pub struct ErrorResponse {
+    /// Stable code from [`HypervisorError::to_code`]
+    pub code: u16,
+    pub message: String,
+    /// Offending setting of a configuration error
+    pub field: Option<&'static str>,
+    /// VM the failed request addressed
+    pub vm_id: Option<VmId>,
+}
+
+impl From<&HypervisorError> for ErrorResponse {
+    fn from(error: &HypervisorError) -> Self {
+        let (field, vm_id) = match error {
+            HypervisorError::Config(config) => (Some(config.field), config.vm_id),
+            HypervisorError::VmNotFound(vm_id) => (None, Some(*vm_id)),
+            _ => (None, None),
+        };
+        ErrorResponse {
+            code: error.to_code(),
+            message: alloc::format!("{}", error),
+            field,
+            vm_id,
+        }
+    }
+}
+
+impl core::fmt::Display for ErrorResponse {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "error {}: {}", self.code, self.message)
+    }
+}
+
 /// Convert errors to debug strings
 impl core::fmt::Display for HypervisorError {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
@@ -642,17 +992,132 @@ impl core::fmt::Display for HypervisorError {
             },
             HypervisorError::TooManyVms => write!(f, "Too many virtual machines"),
             HypervisorError::TooManyVcpus => write!(f, "Too many VCPUs requested"),
-            HypervisorError::VmNotFound => write!(f, "Virtual machine not found"),
+            HypervisorError::VmNotFound(vm_id) => write!(f, "Virtual machine {} not found", vm_id.0),
             HypervisorError::VcpuNotFound => write!(f, "VCPU not found"),
             HypervisorError::InvalidVmState => write!(f, "Invalid virtual machine state"),
             HypervisorError::InvalidVcpuState => write!(f, "Invalid VCPU state"),
             HypervisorError::CannotDeleteRunningVm => write!(f, "Cannot delete running virtual machine"),
             HypervisorError::FeatureNotSupported => write!(f, "Feature not supported"),
-            HypervisorError::ConfigurationError(msg) => write!(f, "Configuration error: {}", msg),
+            HypervisorError::Config(error) => {
+                write!(f, "Configuration error: {} {}", error.field, error.reason.description())?;
+                if let Some(value) = error.value {
+                    write!(f, " ({})", value)?;
+                }
+                if let Some(vm_id) = error.vm_id {
+                    write!(f, " for VM {}", vm_id.0)?;
+                }
+                Ok(())
+            },
             HypervisorError::HardwareVirtNotAvailable => write!(f, "Hardware virtualization not available"),
             HypervisorError::MemoryAllocationFailed => write!(f, "Memory allocation failed"),
-            HypervisorError::IoError(msg) => write!(f, "I/O error: {}", msg),
+            HypervisorError::Io(error) => {
+                write!(f, "I/O error: {}: {}", error.source, error.kind.description())?;
+                if let Some(address) = error.address {
+                    write!(f, " at 0x{:x}", address)?;
+                }
+                Ok(())
+            },
             HypervisorError::InvalidParameter => write!(f, "Invalid parameter"),
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG_REASONS: [ConfigErrorReason; 10] = [
+        ConfigErrorReason::OutOfRange,
+        ConfigErrorReason::Misaligned,
+        ConfigErrorReason::AlreadyExists,
+        ConfigErrorReason::NotFound,
+        ConfigErrorReason::AlreadyEnabled,
+        ConfigErrorReason::NotEnabled,
+        ConfigErrorReason::Exhausted,
+        ConfigErrorReason::Mismatch,
+        ConfigErrorReason::Malformed,
+        ConfigErrorReason::Denied,
+    ];
+
+    const IO_KINDS: [IoErrorKind; 6] = [
+        IoErrorKind::NoDevice,
+        IoErrorKind::InvalidAccess,
+        IoErrorKind::Protocol,
+        IoErrorKind::Closed,
+        IoErrorKind::Backend,
+        IoErrorKind::Corrupt,
+    ];
+
+    #[test]
+    fn test_error_codes_are_stable() {
+        let cases = [
+            (HypervisorError::InsufficientHardwareSupport, 100),
+            (HypervisorError::HardwareVirtNotAvailable, 101),
+            (HypervisorError::FeatureNotSupported, 102),
+            (HypervisorError::TooManyVms, 200),
+            (HypervisorError::TooManyVcpus, 201),
+            (HypervisorError::MemoryAllocationFailed, 202),
+            (HypervisorError::VmNotFound(VmId(7)), 300),
+            (HypervisorError::VcpuNotFound, 301),
+            (HypervisorError::InvalidVmState, 400),
+            (HypervisorError::InvalidVcpuState, 401),
+            (HypervisorError::CannotDeleteRunningVm, 402),
+            (HypervisorError::InvalidParameter, 500),
+        ];
+        for (error, code) in cases {
+            assert_eq!(error.to_code(), code, "{:?}", error);
+        }
+
+        let config_codes: Vec<u16> = CONFIG_REASONS
+            .iter()
+            .map(|&reason| HypervisorError::from(ConfigError::new("vcpus", reason)).to_code())
+            .collect();
+        assert_eq!(config_codes, [501, 502, 503, 504, 505, 506, 507, 508, 509, 510]);
+
+        let io_codes: Vec<u16> = IO_KINDS
+            .iter()
+            .map(|&kind| HypervisorError::from(IoError::new("pci", kind)).to_code())
+            .collect();
+        assert_eq!(io_codes, [600, 601, 602, 603, 604, 605]);
+    }
+
+    #[test]
+    fn test_exit_codes_follow_sysexits() {
+        let cases = [
+            (HypervisorError::InsufficientHardwareSupport, 69),
+            (HypervisorError::HardwareVirtNotAvailable, 69),
+            (HypervisorError::FeatureNotSupported, 69),
+            (HypervisorError::TooManyVms, 75),
+            (HypervisorError::TooManyVcpus, 75),
+            (HypervisorError::MemoryAllocationFailed, 75),
+            (HypervisorError::InvalidVmState, 75),
+            (HypervisorError::InvalidVcpuState, 75),
+            (HypervisorError::CannotDeleteRunningVm, 75),
+            (HypervisorError::VmNotFound(VmId(7)), 66),
+            (HypervisorError::VcpuNotFound, 66),
+            (HypervisorError::InvalidParameter, 64),
+        ];
+        for (error, code) in cases {
+            assert_eq!(error.exit_code(), code, "{:?}", error);
+        }
+
+        for reason in CONFIG_REASONS {
+            let expected = if reason == ConfigErrorReason::Malformed { 65 } else { 78 };
+            assert_eq!(HypervisorError::from(ConfigError::new("vcpus", reason)).exit_code(), expected);
+        }
+        for kind in IO_KINDS {
+            let expected = if kind == IoErrorKind::Protocol { 76 } else { 74 };
+            assert_eq!(HypervisorError::from(IoError::new("pci", kind)).exit_code(), expected);
+        }
+    }
+
+    #[test]
+    fn test_error_response_carries_code_and_context() {
+        let error = HypervisorError::from(ConfigError::new("memory.size", ConfigErrorReason::Misaligned).vm(VmId(3)));
+        let response = ErrorResponse::from(&error);
+        assert_eq!(response.code, 502);
+        assert_eq!(response.field, Some("memory.size"));
+        assert_eq!(response.vm_id, Some(VmId(3)));
+        assert_eq!(alloc::format!("{}", response), "error 502: Configuration error: memory.size misaligned for VM 3");
+    }
+}
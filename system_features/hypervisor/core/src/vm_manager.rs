@@ -3,14 +3,20 @@
 //! Manages the lifecycle of virtual machines, including creation, configuration,
 //! startup, shutdown, and resource allocation.
 
-use crate::{VmConfig, VmInfo, VmId, HypervisorError, MAX_VCPUS_PER_VM};
-use crate::vcpu::Vcpu;
-use crate::memory::MemoryManager;
+use crate::{VmConfig, VmInfo, VmId, HypervisorCapabilities, HypervisorError, ConfigError, ConfigErrorReason, MAX_VCPUS_PER_VM};
+use crate::vcpu::{Vcpu, VcpuCheckpoint};
+use crate::smp::{MpState, IcrCommand, SmpStats, VcpuThreadPool, VcpuThread, build_mp_table, LAPIC_BASE, IOAPIC_BASE, MP_TABLE_BASE};
+use crate::acpi::build_madt;
+use crate::memory::{MemoryManager, SwapStats, VnumaLayout, build_srat, build_slit};
+use multios_memory_manager::NumaManager;
+use crate::arch::ArchBackend;
+use crate::cpu::{ApicAccelerator, DeliveryAction, InterruptDeliveryStats, PendingInjection};
+use crate::record_replay::{Divergence, RecordReplay, ReplayLog, ReplayMode};
 
 use alloc::vec::Vec;
 use alloc::collections::BTreeMap;
 use alloc::sync::Arc;
-use spin::RwLock;
+use spin::{Mutex, RwLock};
 use bitflags::bitflags;
 
 /// VM state enumeration
@@ -63,6 +69,14 @@ struct VirtualMachine {
     state: VmState,
     vcpus: Vec<Arc<RwLock<Vcpu>>>,
     memory_manager: Arc<RwLock<MemoryManager>>,
+    /// Architecture-specific state selected by `config.arch`
+    arch: ArchBackend,
+    /// Interrupt delivery, accelerated by APICv/AVIC when available
+    interrupts: Arc<Mutex<ApicAccelerator>>,
+    /// Scheduler threads running the VCPUs
+    threads: Arc<VcpuThreadPool>,
+    /// Recorder or replayer shared with the VCPUs, while one is active
+    record_replay: Option<Arc<Mutex<RecordReplay>>>,
     flags: VmFlags,
     creation_time_ms: u64,
     uptime_ms: u64,
@@ -70,19 +84,35 @@ struct VirtualMachine {
 
 impl VirtualMachine {
     /// Create a new virtual machine
-    fn new(id: VmId, config: VmConfig) -> Result<Self, HypervisorError> {
+    fn new(id: VmId, config: VmConfig, capabilities: HypervisorCapabilities) -> Result<Self, HypervisorError> {
         let vcpu_count = config.vcpu_count.min(MAX_VCPUS_PER_VM);
         
-        // Create VCPUs
+        if let Some(numa) = &config.numa {
+            numa.validate(vcpu_count, config.memory_mb)?;
+        }
+        
+        // Create VCPUs sharing the VM's CPU model
+        let cpu_model = Arc::new(config.cpu_model.clone());
         let mut vcpus = Vec::with_capacity(vcpu_count);
         for i in 0..vcpu_count {
-            let vcpu = Arc::new(RwLock::new(Vcpu::new(id, i)?));
+            let vcpu = Arc::new(RwLock::new(Vcpu::new(id, i, cpu_model.clone())?));
             vcpus.push(vcpu);
         }
         
         // Create memory manager
         let memory_manager = Arc::new(RwLock::new(MemoryManager::new(config.memory_mb)?));
         
+        let arch = ArchBackend::for_config(id, &config)?;
+        
+        // APICv/AVIC only apply to x86 guests; the other backends have
+        // their own interrupt controllers
+        let capabilities = match arch {
+            ArchBackend::X86 => capabilities,
+            _ => HypervisorCapabilities::empty(),
+        };
+        let interrupts = Arc::new(Mutex::new(ApicAccelerator::new(capabilities, vcpu_count)));
+        let threads = VcpuThreadPool::new(id, vcpus.clone(), interrupts.clone());
+        
         // Calculate creation time (simplified)
         let creation_time_ms = 0; // Would use actual timestamp
         
@@ -92,25 +122,60 @@ impl VirtualMachine {
             state: VmState::Created,
             vcpus,
             memory_manager,
+            arch,
+            interrupts,
+            threads,
+            record_replay: None,
             flags: VmFlags::empty(),
             creation_time_ms,
             uptime_ms: 0,
         })
     }
     
+    fn replaying(&self) -> bool {
+        self.record_replay.as_ref()
+            .map_or(false, |record_replay| record_replay.lock().mode() != ReplayMode::Recording)
+    }
+    
+    /// Share a recorder or replayer with the VCPUs. Both have to see the
+    /// run from a known state, reset or a restored checkpoint, and every
+    /// injection has to go through VM entry.
+    fn attach_record_replay(&mut self, record_replay: RecordReplay) -> Result<(), HypervisorError> {
+        if self.state == VmState::Running || self.record_replay.is_some() {
+            return Err(HypervisorError::InvalidVmState);
+        }
+        self.interrupts.lock().disable_acceleration();
+        let record_replay = Arc::new(Mutex::new(record_replay));
+        for vcpu in &self.vcpus {
+            vcpu.write().record_replay = Some(record_replay.clone());
+        }
+        self.record_replay = Some(record_replay);
+        Ok(())
+    }
+    
+    fn detach_record_replay(&mut self) -> Option<Arc<Mutex<RecordReplay>>> {
+        for vcpu in &self.vcpus {
+            vcpu.write().record_replay = None;
+        }
+        self.record_replay.take()
+    }
+    
     /// Start the VM
     fn start(&mut self) -> Result<(), HypervisorError> {
         match self.state {
             VmState::Created | VmState::Stopped => {
-                // Initialize VCPUs
+                // Initialize VCPUs. On x86 only the bootstrap processor
+                // runs and the others wait for INIT-SIPI-SIPI; the other
+                // backends park secondary VCPUs until PSCI CPU_ON or SBI HSM
+                let x86 = matches!(self.arch, ArchBackend::X86);
                 for vcpu in &self.vcpus {
-                    vcpu.write().initialize()?;
+                    let mut vcpu = vcpu.write();
+                    vcpu.initialize()?;
+                    vcpu.mp_state = if vcpu.is_bsp() || !x86 { MpState::Runnable } else { MpState::WaitForSipi };
                 }
                 
-                // Start VCPUs
-                for vcpu in &self.vcpus {
-                    vcpu.write().start()?;
-                }
+                // Start a thread per VCPU
+                self.threads.spawn()?;
                 
                 self.state = VmState::Running;
                 Ok(())
@@ -145,10 +210,12 @@ impl VirtualMachine {
                     }
                     
                     // Wait for graceful shutdown (simplified)
+                    self.threads.stop();
                     self.state = VmState::Stopped;
                     return Ok(());
                 }
                 
+                self.threads.stop();
                 self.state = VmState::Stopped;
                 Ok(())
             },
@@ -209,6 +276,8 @@ impl VirtualMachine {
             vcpu_stats: self.vcpus.iter().map(|v| v.read().get_stats()).collect(),
             memory_stats: self.memory_manager.read().get_stats(),
             total_uptime_ms: self.uptime_ms,
+            interrupts: self.interrupts.lock().stats(),
+            smp: self.threads.stats(),
         }
     }
 }
@@ -219,6 +288,10 @@ pub struct VmStats {
     pub vcpu_stats: Vec<CpuStats>,
     pub memory_stats: MemoryStats,
     pub total_uptime_ms: u64,
+    /// Accelerated vs. emulated interrupt deliveries
+    pub interrupts: InterruptDeliveryStats,
+    /// INIT, startup and other IPIs between VCPUs
+    pub smp: SmpStats,
 }
 
 /// CPU Statistics
@@ -236,20 +309,24 @@ pub struct MemoryStats {
     pub allocated_mb: u64,
     pub used_mb: u64,
     pub page_faults: u64,
+    /// Swap activity; all zero unless the VM is overcommitted
+    pub swap: SwapStats,
 }
 
 /// Virtual Machine Manager
 pub struct VmManager {
     vms: BTreeMap<VmId, VirtualMachine>,
     next_vm_id: VmId,
+    capabilities: HypervisorCapabilities,
 }
 
 impl VmManager {
     /// Create a new VM manager
-    pub fn new() -> Result<Self, HypervisorError> {
+    pub fn new(capabilities: HypervisorCapabilities) -> Result<Self, HypervisorError> {
         Ok(VmManager {
             vms: BTreeMap::new(),
             next_vm_id: VmId::new(1),
+            capabilities,
         })
     }
     
@@ -259,7 +336,7 @@ impl VmManager {
         self.next_vm_id = VmId::new(vm_id.0 + 1);
         
         // Create the VM
-        let vm = VirtualMachine::new(vm_id, config)?;
+        let vm = VirtualMachine::new(vm_id, config, self.capabilities)?;
         self.vms.insert(vm_id, vm);
         
         Ok(vm_id)
@@ -268,7 +345,7 @@ impl VmManager {
     /// Start a virtual machine
     pub fn start_vm(&mut self, vm_id: VmId) -> Result<(), HypervisorError> {
         let vm = self.vms.get_mut(&vm_id)
-            .ok_or(HypervisorError::VmNotFound)?;
+            .ok_or(HypervisorError::VmNotFound(vm_id))?;
         
         vm.start()
     }
@@ -276,7 +353,7 @@ impl VmManager {
     /// Stop a virtual machine
     pub fn stop_vm(&mut self, vm_id: VmId, force: bool) -> Result<(), HypervisorError> {
         let vm = self.vms.get_mut(&vm_id)
-            .ok_or(HypervisorError::VmNotFound)?;
+            .ok_or(HypervisorError::VmNotFound(vm_id))?;
         
         vm.stop(force)
     }
@@ -284,7 +361,7 @@ impl VmManager {
     /// Pause a virtual machine
     pub fn pause_vm(&mut self, vm_id: VmId) -> Result<(), HypervisorError> {
         let vm = self.vms.get_mut(&vm_id)
-            .ok_or(HypervisorError::VmNotFound)?;
+            .ok_or(HypervisorError::VmNotFound(vm_id))?;
         
         vm.pause()
     }
@@ -292,7 +369,7 @@ impl VmManager {
     /// Resume a virtual machine
     pub fn resume_vm(&mut self, vm_id: VmId) -> Result<(), HypervisorError> {
         let vm = self.vms.get_mut(&vm_id)
-            .ok_or(HypervisorError::VmNotFound)?;
+            .ok_or(HypervisorError::VmNotFound(vm_id))?;
         
         vm.resume()
     }
@@ -307,7 +384,7 @@ impl VmManager {
         }
         
         self.vms.remove(&vm_id)
-            .ok_or(HypervisorError::VmNotFound)?;
+            .ok_or(HypervisorError::VmNotFound(vm_id))?;
         
         Ok(())
     }
@@ -315,15 +392,287 @@ impl VmManager {
     /// Get VM configuration
     pub fn get_vm_config(&self, vm_id: VmId) -> Result<VmConfig, HypervisorError> {
         let vm = self.vms.get(&vm_id)
-            .ok_or(HypervisorError::VmNotFound)?;
+            .ok_or(HypervisorError::VmNotFound(vm_id))?;
         
         Ok(vm.config.clone())
     }
     
+    /// Get the architecture backend of a VM
+    pub fn arch_backend(&self, vm_id: VmId) -> Result<&ArchBackend, HypervisorError> {
+        let vm = self.vms.get(&vm_id)
+            .ok_or(HypervisorError::VmNotFound(vm_id))?;
+        
+        Ok(&vm.arch)
+    }
+    
+    /// Get the architecture backend of a VM for exit handling
+    pub fn arch_backend_mut(&mut self, vm_id: VmId) -> Result<&mut ArchBackend, HypervisorError> {
+        let vm = self.vms.get_mut(&vm_id)
+            .ok_or(HypervisorError::VmNotFound(vm_id))?;
+        
+        Ok(&mut vm.arch)
+    }
+    
+    /// Back the VM's virtual NUMA nodes with memory from their host nodes
+    pub fn back_vnuma_memory(&mut self, vm_id: VmId, numa: &mut NumaManager) -> Result<(), HypervisorError> {
+        let vm = self.vms.get(&vm_id)
+            .ok_or(HypervisorError::VmNotFound(vm_id))?;
+        let config = vm.config.numa.as_ref()
+            .ok_or(HypervisorError::FeatureNotSupported)?;
+        
+        let layout = VnumaLayout::new(config, 0);
+        vm.memory_manager.write().back_numa_nodes(config, &layout, numa)
+    }
+    
+    /// SRAT and SLIT describing the VM's virtual NUMA topology, for the
+    /// firmware tables; None if the VM has a single node
+    pub fn vnuma_acpi_tables(&self, vm_id: VmId) -> Result<Option<(Vec<u8>, Vec<u8>)>, HypervisorError> {
+        let vm = self.vms.get(&vm_id)
+            .ok_or(HypervisorError::VmNotFound(vm_id))?;
+        
+        Ok(vm.config.numa.as_ref().map(|config| {
+            let layout = VnumaLayout::new(config, 0);
+            (build_srat(config, &layout), build_slit(config))
+        }))
+    }
+    
+    /// Deliver a fixed interrupt to a VCPU; the caller carries out the
+    /// returned action (notification IPI, doorbell, wakeup or kick)
+    pub fn deliver_interrupt(&mut self, vm_id: VmId, vcpu: usize, vector: u8) -> Result<DeliveryAction, HypervisorError> {
+        let vm = self.vms.get_mut(&vm_id)
+            .ok_or(HypervisorError::VmNotFound(vm_id))?;
+        if vcpu >= vm.vcpus.len() {
+            return Err(HypervisorError::VcpuNotFound);
+        }
+        
+        // Replay injects the recorded interrupts instead
+        if vm.replaying() {
+            return Ok(DeliveryAction::None);
+        }
+        let action = vm.interrupts.lock().deliver(vcpu, vector);
+        if action == DeliveryAction::WakeVcpu {
+            vm.threads.wake(vcpu);
+        }
+        Ok(action)
+    }
+    
+    /// Deliver a fixed-mode, physically addressed MSI a device model wrote;
+    /// VCPU N has APIC ID N
+    pub fn deliver_msi(&mut self, vm_id: VmId, address: u64, data: u32) -> Result<DeliveryAction, HypervisorError> {
+        if address & 0xFFF0_0000 != 0xFEE0_0000 {
+            return Err(HypervisorError::InvalidParameter);
+        }
+        let destination = ((address >> 12) & 0xFF) as usize;
+        self.deliver_interrupt(vm_id, destination, data as u8)
+    }
+    
+    /// Deliver an NMI to a VCPU
+    pub fn deliver_nmi(&mut self, vm_id: VmId, vcpu: usize) -> Result<DeliveryAction, HypervisorError> {
+        let vm = self.vms.get_mut(&vm_id)
+            .ok_or(HypervisorError::VmNotFound(vm_id))?;
+        if vcpu >= vm.vcpus.len() {
+            return Err(HypervisorError::VcpuNotFound);
+        }
+        
+        if vm.replaying() {
+            return Ok(DeliveryAction::None);
+        }
+        let action = vm.interrupts.lock().deliver_nmi(vcpu);
+        if action == DeliveryAction::WakeVcpu {
+            vm.threads.wake(vcpu);
+        }
+        Ok(action)
+    }
+    
+    /// Prepare a VCPU's interrupts for VM entry on `host_apic_id`; returns
+    /// vectors posted while it was out of the guest and the event to inject
+    /// through the VM-entry interruption field, if any
+    pub fn prepare_interrupt_entry(&mut self, vm_id: VmId, vcpu: usize, host_apic_id: u32)
+        -> Result<(Option<[u64; 4]>, Option<PendingInjection>), HypervisorError> {
+        let vm = self.vms.get_mut(&vm_id)
+            .ok_or(HypervisorError::VmNotFound(vm_id))?;
+        
+        let mut interrupts = vm.interrupts.lock();
+        let posted = interrupts.vcpu_entering(vcpu, host_apic_id);
+        let Some(record_replay) = &vm.record_replay else {
+            let injection = interrupts.next_injection(vcpu);
+            if let Some(injection) = injection {
+                interrupts.injected(vcpu, injection);
+            }
+            return Ok((posted, injection));
+        };
+        
+        let point = vm.vcpus.get(vcpu).ok_or(HypervisorError::VcpuNotFound)?.read().execution_point();
+        let mut record_replay = record_replay.lock();
+        if record_replay.mode() == ReplayMode::Recording {
+            let injection = interrupts.next_injection(vcpu);
+            if let Some(injection) = injection {
+                interrupts.injected(vcpu, injection);
+                record_replay.record_injection(vcpu as u16, point, injection);
+            }
+            return Ok((posted, injection));
+        }
+        Ok((posted, record_replay.replay_injection(vcpu as u16, point)?))
+    }
+    
+    /// Start logging the VM's non-deterministic inputs; the VM has to be
+    /// stopped or paused, so the log starts from a known state
+    pub fn start_recording(&mut self, vm_id: VmId) -> Result<(), HypervisorError> {
+        let vm = self.vms.get_mut(&vm_id)
+            .ok_or(HypervisorError::VmNotFound(vm_id))?;
+        
+        let record_replay = RecordReplay::record(vm_id, vm.vcpus.len() as u16);
+        vm.attach_record_replay(record_replay)?;
+        info!("Recording VM {}", vm_id.0);
+        Ok(())
+    }
+    
+    /// Stop recording and return the log
+    pub fn stop_recording(&mut self, vm_id: VmId) -> Result<ReplayLog, HypervisorError> {
+        let vm = self.vms.get_mut(&vm_id)
+            .ok_or(HypervisorError::VmNotFound(vm_id))?;
+        
+        match vm.record_replay.as_ref().map(|record_replay| record_replay.lock().mode()) {
+            Some(ReplayMode::Recording) => {}
+            _ => return Err(HypervisorError::InvalidVmState),
+        }
+        let record_replay = vm.detach_record_replay().ok_or(HypervisorError::InvalidVmState)?;
+        let log = Arc::try_unwrap(record_replay)
+            .map_err(|_| HypervisorError::InvalidVmState)?
+            .into_inner()
+            .finish();
+        info!("Recorded {} inputs of VM {}", log.entries.len(), vm_id.0);
+        Ok(log)
+    }
+    
+    /// Replay a log recorded from the same VM configuration, from the state
+    /// the recording started in; the VM is started or resumed afterwards
+    pub fn start_replay(&mut self, vm_id: VmId, log: ReplayLog) -> Result<(), HypervisorError> {
+        let vm = self.vms.get_mut(&vm_id)
+            .ok_or(HypervisorError::VmNotFound(vm_id))?;
+        
+        if log.vcpu_count as usize != vm.vcpus.len() {
+            return Err(ConfigError::new("replay_log.vcpu_count", ConfigErrorReason::Mismatch).vm(vm_id).value(log.vcpu_count as u64).into());
+        }
+        vm.attach_record_replay(RecordReplay::replay(log))?;
+        info!("Replaying VM {}", vm_id.0);
+        Ok(())
+    }
+    
+    /// Stop replaying, e.g. after a divergence was inspected
+    pub fn stop_replay(&mut self, vm_id: VmId) -> Result<(), HypervisorError> {
+        let vm = self.vms.get_mut(&vm_id)
+            .ok_or(HypervisorError::VmNotFound(vm_id))?;
+        
+        vm.detach_record_replay().map(|_| ()).ok_or(HypervisorError::InvalidVmState)
+    }
+    
+    /// Recording or replay state, and the inputs handled so far
+    pub fn record_replay_status(&self, vm_id: VmId) -> Result<Option<(ReplayMode, usize)>, HypervisorError> {
+        let vm = self.vms.get(&vm_id)
+            .ok_or(HypervisorError::VmNotFound(vm_id))?;
+        
+        Ok(vm.record_replay.as_ref().map(|record_replay| {
+            let record_replay = record_replay.lock();
+            (record_replay.mode(), record_replay.position())
+        }))
+    }
+    
+    /// Where a replay left the recorded execution, if it did
+    pub fn replay_divergence(&self, vm_id: VmId) -> Result<Option<Divergence>, HypervisorError> {
+        let vm = self.vms.get(&vm_id)
+            .ok_or(HypervisorError::VmNotFound(vm_id))?;
+        
+        Ok(vm.record_replay.as_ref().and_then(|record_replay| record_replay.lock().divergence()))
+    }
+    
+    /// Architectural state of every VCPU; the VM should be paused
+    pub fn vcpu_checkpoints(&self, vm_id: VmId) -> Result<Vec<VcpuCheckpoint>, HypervisorError> {
+        let vm = self.vms.get(&vm_id)
+            .ok_or(HypervisorError::VmNotFound(vm_id))?;
+        
+        Ok(vm.vcpus.iter().map(|vcpu| vcpu.read().checkpoint()).collect())
+    }
+    
+    /// Put every VCPU back to a checkpoint taken with `vcpu_checkpoints`
+    pub fn restore_vcpu_checkpoints(&mut self, vm_id: VmId, checkpoints: &[VcpuCheckpoint]) -> Result<(), HypervisorError> {
+        let vm = self.vms.get_mut(&vm_id)
+            .ok_or(HypervisorError::VmNotFound(vm_id))?;
+        if vm.state == VmState::Running {
+            return Err(HypervisorError::InvalidVmState);
+        }
+        if checkpoints.len() != vm.vcpus.len() {
+            return Err(HypervisorError::InvalidParameter);
+        }
+        
+        for (vcpu, checkpoint) in vm.vcpus.iter().zip(checkpoints) {
+            vcpu.write().restore_checkpoint(checkpoint);
+        }
+        Ok(())
+    }
+    
+    /// Record that a VCPU left the guest
+    pub fn interrupt_exit(&mut self, vm_id: VmId, vcpu: usize) -> Result<(), HypervisorError> {
+        let vm = self.vms.get_mut(&vm_id)
+            .ok_or(HypervisorError::VmNotFound(vm_id))?;
+        
+        vm.interrupts.lock().vcpu_exited(vcpu);
+        Ok(())
+    }
+    
+    /// Fall back to software interrupt injection for a VM
+    pub fn disable_apic_acceleration(&mut self, vm_id: VmId) -> Result<(), HypervisorError> {
+        let vm = self.vms.get_mut(&vm_id)
+            .ok_or(HypervisorError::VmNotFound(vm_id))?;
+        
+        vm.interrupts.lock().disable_acceleration();
+        Ok(())
+    }
+    
+    /// Route an IPI written to the local APIC ICR of `source`, e.g. from
+    /// xAPIC MMIO emulation; x2APIC ICR writes are routed by the VCPU
+    /// threads. Returns the deliveries the caller has to carry out.
+    pub fn send_ipi(&mut self, vm_id: VmId, source: usize, icr: IcrCommand) -> Result<Vec<(usize, DeliveryAction)>, HypervisorError> {
+        let vm = self.vms.get(&vm_id)
+            .ok_or(HypervisorError::VmNotFound(vm_id))?;
+        if source >= vm.vcpus.len() {
+            return Err(HypervisorError::VcpuNotFound);
+        }
+        
+        Ok(vm.threads.send_ipi(source, icr))
+    }
+    
+    /// Threads running the VCPUs of a VM
+    pub fn vcpu_threads(&self, vm_id: VmId) -> Result<Vec<VcpuThread>, HypervisorError> {
+        let vm = self.vms.get(&vm_id)
+            .ok_or(HypervisorError::VmNotFound(vm_id))?;
+        
+        Ok(vm.threads.threads())
+    }
+    
+    /// MADT and MP table (for `MP_TABLE_BASE`) describing the VM's
+    /// processors, for the firmware tables
+    pub fn smp_tables(&self, vm_id: VmId) -> Result<(Vec<u8>, Vec<u8>), HypervisorError> {
+        let vm = self.vms.get(&vm_id)
+            .ok_or(HypervisorError::VmNotFound(vm_id))?;
+        
+        let vcpu_count = vm.vcpus.len();
+        Ok((build_madt(vcpu_count, LAPIC_BASE, IOAPIC_BASE), build_mp_table(vcpu_count, MP_TABLE_BASE)))
+    }
+    
+    /// Guest address of a VCPU's steal time record, if the guest enabled it
+    pub fn steal_time_gpa(&self, vm_id: VmId, vcpu: usize) -> Result<Option<u64>, HypervisorError> {
+        let vm = self.vms.get(&vm_id)
+            .ok_or(HypervisorError::VmNotFound(vm_id))?;
+        
+        let vcpu = vm.vcpus.get(vcpu).ok_or(HypervisorError::VcpuNotFound)?;
+        Ok(vcpu.read().steal_time_gpa)
+    }
+    
     /// Get VM information
     pub fn get_vm_info(&self, vm_id: VmId) -> Result<VmInfo, HypervisorError> {
         let vm = self.vms.get(&vm_id)
-            .ok_or(HypervisorError::VmNotFound)?;
+            .ok_or(HypervisorError::VmNotFound(vm_id))?;
         
         Ok(vm.get_info())
     }
@@ -331,7 +680,7 @@ impl VmManager {
     /// Get VM statistics
     pub fn get_vm_stats(&self, vm_id: VmId) -> Result<VmStats, HypervisorError> {
         let vm = self.vms.get(&vm_id)
-            .ok_or(HypervisorError::VmNotFound)?;
+            .ok_or(HypervisorError::VmNotFound(vm_id))?;
         
         Ok(vm.get_stats())
     }
@@ -0,0 +1,359 @@
+//! APIC Virtualization and Posted Interrupts
+//!
+//! Without acceleration every interrupt for a guest costs at least one VM
+//! exit: the target vCPU is kicked out of the guest, the vector is injected
+//! through the VM-entry interruption field, and the guest's EOI traps again.
+//! With Intel APICv the vector is posted into the vCPU's posted-interrupt
+//! descriptor and a notification IPI lets the CPU deliver it without an
+//! exit; AMD AVIC does the same through the vAPIC backing page and a
+//! doorbell write. Hosts without either fall back to software injection.
+
+use crate::HypervisorCapabilities;
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// Host vector used for posted-interrupt notifications
+pub const POSTED_INTERRUPT_VECTOR: u8 = 0xF2;
+/// Vectors below this are exceptions and cannot be delivered as fixed interrupts
+const FIRST_INTERRUPT_VECTOR: u8 = 16;
+
+// Control word of the posted-interrupt descriptor
+const PID_ON: u64 = 1 << 0;
+const PID_SN: u64 = 1 << 1;
+const PID_NV_SHIFT: u64 = 16;
+const PID_NDST_SHIFT: u64 = 32;
+
+/// How interrupts reach a VM's vCPUs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApicAcceleration {
+    /// Intel APIC virtualization with posted interrupts
+    Apicv,
+    /// AMD Advanced Virtual Interrupt Controller
+    Avic,
+    /// Exit-based injection
+    Software,
+}
+
+impl ApicAcceleration {
+    /// Best mode the host capabilities allow
+    pub fn detect(capabilities: HypervisorCapabilities) -> Self {
+        let accelerated = capabilities.contains(HypervisorCapabilities::APIC_VIRTUALIZATION | HypervisorCapabilities::POSTED_INTERRUPTS);
+        if accelerated && capabilities.contains(HypervisorCapabilities::INTEL_VT_X) {
+            ApicAcceleration::Apicv
+        } else if accelerated && capabilities.contains(HypervisorCapabilities::AMD_V) {
+            ApicAcceleration::Avic
+        } else {
+            ApicAcceleration::Software
+        }
+    }
+}
+
+/// Intel posted-interrupt descriptor; the CPU reads it while the guest runs
+#[derive(Debug)]
+#[repr(C, align(64))]
+pub struct PostedInterruptDescriptor {
+    /// Posted-interrupt requests, one bit per vector
+    pir: [AtomicU64; 4],
+    /// ON, SN, notification vector and notification destination
+    control: AtomicU64,
+    reserved: [u64; 3],
+}
+
+impl PostedInterruptDescriptor {
+    pub fn new() -> Self {
+        PostedInterruptDescriptor {
+            pir: [AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0)],
+            control: AtomicU64::new((POSTED_INTERRUPT_VECTOR as u64) << PID_NV_SHIFT),
+            reserved: [0; 3],
+        }
+    }
+
+    /// Post a vector; returns true if the caller must send the notification
+    /// IPI (ON was clear and notifications are not suppressed)
+    pub fn post(&self, vector: u8) -> bool {
+        self.pir[vector as usize / 64].fetch_or(1 << (vector % 64), Ordering::SeqCst);
+        let previous = self.control.fetch_or(PID_ON, Ordering::SeqCst);
+        previous & (PID_ON | PID_SN) == 0
+    }
+
+    /// Take the posted vectors and clear ON, for merging into the virtual
+    /// IRR when the vCPU was not in the guest to receive the notification
+    pub fn take_pending(&self) -> [u64; 4] {
+        self.control.fetch_and(!PID_ON, Ordering::SeqCst);
+        [
+            self.pir[0].swap(0, Ordering::SeqCst),
+            self.pir[1].swap(0, Ordering::SeqCst),
+            self.pir[2].swap(0, Ordering::SeqCst),
+            self.pir[3].swap(0, Ordering::SeqCst),
+        ]
+    }
+
+    /// Whether posted vectors are waiting
+    pub fn outstanding(&self) -> bool {
+        self.control.load(Ordering::SeqCst) & PID_ON != 0
+    }
+
+    /// Point notifications at the physical CPU the vCPU runs on and stop
+    /// suppressing them
+    pub fn set_running(&self, host_apic_id: u32) {
+        let control = self.control.load(Ordering::SeqCst);
+        let control = (control & 0x0000_0000_FFFF_FFFF & !PID_SN) | ((host_apic_id as u64) << PID_NDST_SHIFT);
+        self.control.store(control, Ordering::SeqCst);
+    }
+
+    /// Suppress notifications while the vCPU is out of the guest
+    pub fn set_blocked(&self) {
+        self.control.fetch_or(PID_SN, Ordering::SeqCst);
+    }
+
+    /// Physical address to program into the VMCS
+    pub fn address(&self) -> u64 {
+        self as *const _ as u64
+    }
+}
+
+/// What the caller does to complete a delivery
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryAction {
+    /// Send the posted-interrupt notification vector to this host CPU
+    NotifyHost { host_apic_id: u32 },
+    /// Write the AVIC doorbell MSR for this host CPU
+    RingDoorbell { host_apic_id: u32 },
+    /// The vCPU is not in the guest: wake it if it is halted; the vector is
+    /// picked up on the next VM entry
+    WakeVcpu,
+    /// Force the running vCPU out of the guest so the vector can be injected
+    KickVcpu { host_apic_id: u32 },
+    /// Already pending or nothing to do
+    None,
+}
+
+/// Interrupt delivery counters of one VM
+#[derive(Debug, Clone, Copy, Default)]
+pub struct InterruptDeliveryStats {
+    /// Deliveries completed by APICv or AVIC without a VM exit
+    pub accelerated: u64,
+    /// Deliveries that needed a VM exit and software injection
+    pub emulated: u64,
+    pub notifications: u64,
+    pub doorbells: u64,
+    pub wakeups: u64,
+    /// Posted vectors merged into the virtual IRR at VM entry
+    pub pir_syncs: u64,
+}
+
+/// Per-vCPU delivery state
+#[derive(Debug)]
+struct VcpuInterrupts {
+    /// Host APIC ID while in the guest
+    running_on: Option<u32>,
+    descriptor: PostedInterruptDescriptor,
+    /// AVIC backing page IRR, or pending vectors for software injection
+    irr: [u64; 4],
+    nmi_pending: bool,
+}
+
+impl VcpuInterrupts {
+    fn new() -> Self {
+        VcpuInterrupts {
+            running_on: None,
+            descriptor: PostedInterruptDescriptor::new(),
+            irr: [0; 4],
+            nmi_pending: false,
+        }
+    }
+
+    fn set_irr(&mut self, vector: u8) -> bool {
+        let (word, bit) = (vector as usize / 64, 1u64 << (vector % 64));
+        let newly = self.irr[word] & bit == 0;
+        self.irr[word] |= bit;
+        newly
+    }
+}
+
+/// Interrupt delivery for the vCPUs of one VM
+#[derive(Debug)]
+pub struct ApicAccelerator {
+    mode: ApicAcceleration,
+    vcpus: Vec<VcpuInterrupts>,
+    stats: InterruptDeliveryStats,
+}
+
+impl ApicAccelerator {
+    /// Create delivery state for `vcpu_count` vCPUs in the best mode the
+    /// host supports
+    pub fn new(capabilities: HypervisorCapabilities, vcpu_count: usize) -> Self {
+        let mode = ApicAcceleration::detect(capabilities);
+        info!("Interrupt delivery for {} vCPUs: {:?}", vcpu_count, mode);
+        ApicAccelerator {
+            mode,
+            vcpus: (0..vcpu_count).map(|_| VcpuInterrupts::new()).collect(),
+            stats: InterruptDeliveryStats::default(),
+        }
+    }
+
+    pub fn mode(&self) -> ApicAcceleration {
+        self.mode
+    }
+
+    /// Fall back to software injection, e.g. for a VM that runs its own
+    /// hypervisor; vectors already posted are kept pending
+    pub fn disable_acceleration(&mut self) {
+        if self.mode == ApicAcceleration::Software {
+            return;
+        }
+        for vcpu in &mut self.vcpus {
+            let posted = vcpu.descriptor.take_pending();
+            for (word, bits) in posted.iter().enumerate() {
+                vcpu.irr[word] |= bits;
+            }
+        }
+        info!("APIC acceleration disabled, using software injection");
+        self.mode = ApicAcceleration::Software;
+    }
+
+    /// Deliver a fixed interrupt to a vCPU
+    pub fn deliver(&mut self, vcpu: usize, vector: u8) -> DeliveryAction {
+        if vector < FIRST_INTERRUPT_VECTOR {
+            return DeliveryAction::None;
+        }
+        let state = match self.vcpus.get_mut(vcpu) {
+            Some(state) => state,
+            None => return DeliveryAction::None,
+        };
+
+        match (self.mode, state.running_on) {
+            (ApicAcceleration::Apicv, running_on) => {
+                let notify = state.descriptor.post(vector);
+                self.stats.accelerated += 1;
+                match running_on {
+                    Some(host_apic_id) if notify => {
+                        self.stats.notifications += 1;
+                        DeliveryAction::NotifyHost { host_apic_id }
+                    }
+                    Some(_) => DeliveryAction::None,
+                    None => {
+                        self.stats.wakeups += 1;
+                        DeliveryAction::WakeVcpu
+                    }
+                }
+            }
+            (ApicAcceleration::Avic, running_on) => {
+                state.set_irr(vector);
+                self.stats.accelerated += 1;
+                match running_on {
+                    Some(host_apic_id) => {
+                        self.stats.doorbells += 1;
+                        DeliveryAction::RingDoorbell { host_apic_id }
+                    }
+                    None => {
+                        self.stats.wakeups += 1;
+                        DeliveryAction::WakeVcpu
+                    }
+                }
+            }
+            (ApicAcceleration::Software, running_on) => {
+                if !state.set_irr(vector) {
+                    return DeliveryAction::None;
+                }
+                self.stats.emulated += 1;
+                match running_on {
+                    Some(host_apic_id) => DeliveryAction::KickVcpu { host_apic_id },
+                    None => {
+                        self.stats.wakeups += 1;
+                        DeliveryAction::WakeVcpu
+                    }
+                }
+            }
+        }
+    }
+
+    /// Deliver an NMI; NMIs are always injected in software
+    pub fn deliver_nmi(&mut self, vcpu: usize) -> DeliveryAction {
+        let state = match self.vcpus.get_mut(vcpu) {
+            Some(state) => state,
+            None => return DeliveryAction::None,
+        };
+        state.nmi_pending = true;
+        self.stats.emulated += 1;
+        match state.running_on {
+            Some(host_apic_id) => DeliveryAction::KickVcpu { host_apic_id },
+            None => DeliveryAction::WakeVcpu,
+        }
+    }
+
+    /// Called before VM entry on `host_apic_id`. With APICv, vectors posted
+    /// while the vCPU was out are returned for merging into the virtual IRR.
+    pub fn vcpu_entering(&mut self, vcpu: usize, host_apic_id: u32) -> Option<[u64; 4]> {
+        let state = self.vcpus.get_mut(vcpu)?;
+        state.running_on = Some(host_apic_id);
+        if self.mode != ApicAcceleration::Apicv {
+            return None;
+        }
+        state.descriptor.set_running(host_apic_id);
+        if !state.descriptor.outstanding() {
+            return None;
+        }
+        self.stats.pir_syncs += 1;
+        Some(state.descriptor.take_pending())
+    }
+
+    /// Called after VM exit
+    pub fn vcpu_exited(&mut self, vcpu: usize) {
+        if let Some(state) = self.vcpus.get_mut(vcpu) {
+            state.running_on = None;
+            if self.mode == ApicAcceleration::Apicv {
+                state.descriptor.set_blocked();
+            }
+        }
+    }
+
+    /// Next event to inject through the VM-entry interruption field in
+    /// software mode: an NMI first, then the highest pending vector
+    pub fn next_injection(&self, vcpu: usize) -> Option<PendingInjection> {
+        let state = self.vcpus.get(vcpu)?;
+        if state.nmi_pending {
+            return Some(PendingInjection::Nmi);
+        }
+        if self.mode != ApicAcceleration::Software {
+            return None;
+        }
+        (0..4).rev().find_map(|word| {
+            let bits = state.irr[word];
+            (bits != 0).then(|| PendingInjection::Vector((word * 64 + 63 - bits.leading_zeros() as usize) as u8))
+        })
+    }
+
+    /// Record that an injection was delivered on VM entry
+    pub fn injected(&mut self, vcpu: usize, injection: PendingInjection) {
+        if let Some(state) = self.vcpus.get_mut(vcpu) {
+            match injection {
+                PendingInjection::Nmi => state.nmi_pending = false,
+                PendingInjection::Vector(vector) => state.irr[vector as usize / 64] &= !(1 << (vector % 64)),
+            }
+        }
+    }
+
+    /// Posted-interrupt descriptor of a vCPU, for the VMCS
+    pub fn descriptor(&self, vcpu: usize) -> Option<&PostedInterruptDescriptor> {
+        self.vcpus.get(vcpu).map(|state| &state.descriptor)
+    }
+
+    pub fn stats(&self) -> InterruptDeliveryStats {
+        self.stats
+    }
+}
+
+/// Event waiting for software injection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PendingInjection {
+    Nmi,
+    Vector(u8),
+}
+
+impl Default for PostedInterruptDescriptor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,573 @@
+//! Guest CPU Models
+//!
+//! A `CpuModel` decides which CPUID leaves and MSRs a guest sees. The
+//! `host` model passes the host CPU through with selected features hidden;
+//! named models such as `edu-baseline` expose a fixed feature set and a
+//! fixed signature, so a snapshot taken on one host restores on any host
+//! that has at least those features. Nested virtualization (VMX/SVM) is
+//! hidden unless the model asks for it.
+
+use alloc::collections::BTreeSet;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Highest basic leaf any model reports
+const MAX_BASIC_LEAF: u32 = 0x1F;
+/// Highest extended leaf any model reports
+const MAX_EXTENDED_LEAF: u32 = 0x8000_0008;
+
+// MSRs affected by feature masking
+const MSR_IA32_FEATURE_CONTROL: u32 = 0x3A;
+const MSR_IA32_SPEC_CTRL: u32 = 0x48;
+const MSR_IA32_PRED_CMD: u32 = 0x49;
+const MSR_IA32_PMC_FIRST: u32 = 0xC1;
+const MSR_IA32_PMC_LAST: u32 = 0xC8;
+const MSR_IA32_ARCH_CAPABILITIES: u32 = 0x10A;
+const MSR_IA32_PERFEVTSEL_FIRST: u32 = 0x186;
+const MSR_IA32_PERFEVTSEL_LAST: u32 = 0x18D;
+const MSR_IA32_PERF_FIXED_FIRST: u32 = 0x309;
+const MSR_IA32_PERF_GLOBAL_CTRL_LAST: u32 = 0x390;
+const MSR_IA32_VMX_FIRST: u32 = 0x480;
+const MSR_IA32_VMX_LAST: u32 = 0x491;
+const MSR_IA32_TSC_DEADLINE: u32 = 0x6E0;
+const MSR_X2APIC_FIRST: u32 = 0x800;
+const MSR_X2APIC_LAST: u32 = 0x8FF;
+const MSR_AMD_VM_CR: u32 = 0xC001_0114;
+const MSR_AMD_VM_HSAVE_PA: u32 = 0xC001_0117;
+
+/// IA32_FEATURE_CONTROL with the lock bit set and VMX disabled
+const FEATURE_CONTROL_LOCKED: u64 = 1;
+
+/// Output registers of one CPUID leaf
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CpuidResult {
+    pub eax: u32,
+    pub ebx: u32,
+    pub ecx: u32,
+    pub edx: u32,
+}
+
+/// CPUID output register
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CpuidRegister {
+    Eax,
+    Ebx,
+    Ecx,
+    Edx,
+}
+
+/// CPU features a model can expose or hide
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CpuFeature {
+    // Leaf 1 EDX
+    Fpu,
+    Tsc,
+    Msr,
+    Pae,
+    Cx8,
+    Apic,
+    Sep,
+    Mtrr,
+    Pge,
+    Cmov,
+    Pat,
+    Clflush,
+    Mmx,
+    Fxsr,
+    Sse,
+    Sse2,
+    // Leaf 1 ECX
+    Sse3,
+    Pclmulqdq,
+    Vmx,
+    Ssse3,
+    Fma,
+    Cx16,
+    Pcid,
+    Sse4_1,
+    Sse4_2,
+    X2Apic,
+    Movbe,
+    Popcnt,
+    TscDeadline,
+    Aes,
+    Xsave,
+    Osxsave,
+    Avx,
+    F16c,
+    Rdrand,
+    // Leaf 7 EBX
+    Fsgsbase,
+    Bmi1,
+    Hle,
+    Avx2,
+    Smep,
+    Bmi2,
+    Erms,
+    Invpcid,
+    Rtm,
+    Avx512F,
+    Avx512Dq,
+    Rdseed,
+    Adx,
+    Smap,
+    Clflushopt,
+    Avx512Cd,
+    Sha,
+    Avx512Bw,
+    Avx512Vl,
+    // Leaf 7 ECX
+    Avx512Vbmi,
+    Umip,
+    Pku,
+    Avx512Vnni,
+    Rdpid,
+    // Leaf 7 EDX
+    MdClear,
+    SpecCtrl,
+    ArchCapabilities,
+    // Leaf 0x8000_0001 ECX
+    LahfLm,
+    Svm,
+    Lzcnt,
+    Prefetchw,
+    // Leaf 0x8000_0001 EDX
+    Syscall,
+    Nx,
+    Page1Gb,
+    Rdtscp,
+    LongMode,
+}
+
+impl CpuFeature {
+    /// Every feature, in declaration order
+    pub const ALL: &'static [CpuFeature] = &[
+        CpuFeature::Fpu, CpuFeature::Tsc, CpuFeature::Msr, CpuFeature::Pae, CpuFeature::Cx8,
+        CpuFeature::Apic, CpuFeature::Sep, CpuFeature::Mtrr, CpuFeature::Pge, CpuFeature::Cmov,
+        CpuFeature::Pat, CpuFeature::Clflush, CpuFeature::Mmx, CpuFeature::Fxsr, CpuFeature::Sse,
+        CpuFeature::Sse2, CpuFeature::Sse3, CpuFeature::Pclmulqdq, CpuFeature::Vmx, CpuFeature::Ssse3,
+        CpuFeature::Fma, CpuFeature::Cx16, CpuFeature::Pcid, CpuFeature::Sse4_1, CpuFeature::Sse4_2,
+        CpuFeature::X2Apic, CpuFeature::Movbe, CpuFeature::Popcnt, CpuFeature::TscDeadline, CpuFeature::Aes,
+        CpuFeature::Xsave, CpuFeature::Osxsave, CpuFeature::Avx, CpuFeature::F16c, CpuFeature::Rdrand,
+        CpuFeature::Fsgsbase, CpuFeature::Bmi1, CpuFeature::Hle, CpuFeature::Avx2, CpuFeature::Smep,
+        CpuFeature::Bmi2, CpuFeature::Erms, CpuFeature::Invpcid, CpuFeature::Rtm, CpuFeature::Avx512F,
+        CpuFeature::Avx512Dq, CpuFeature::Rdseed, CpuFeature::Adx, CpuFeature::Smap, CpuFeature::Clflushopt,
+        CpuFeature::Avx512Cd, CpuFeature::Sha, CpuFeature::Avx512Bw, CpuFeature::Avx512Vl, CpuFeature::Avx512Vbmi,
+        CpuFeature::Umip, CpuFeature::Pku, CpuFeature::Avx512Vnni, CpuFeature::Rdpid, CpuFeature::MdClear,
+        CpuFeature::SpecCtrl, CpuFeature::ArchCapabilities, CpuFeature::LahfLm, CpuFeature::Svm, CpuFeature::Lzcnt,
+        CpuFeature::Prefetchw, CpuFeature::Syscall, CpuFeature::Nx, CpuFeature::Page1Gb, CpuFeature::Rdtscp,
+        CpuFeature::LongMode,
+    ];
+
+    /// AVX-512 subsets, hidden together by `CpuModel::without_avx512`
+    pub const AVX512: &'static [CpuFeature] = &[
+        CpuFeature::Avx512F, CpuFeature::Avx512Dq, CpuFeature::Avx512Cd, CpuFeature::Avx512Bw,
+        CpuFeature::Avx512Vl, CpuFeature::Avx512Vbmi, CpuFeature::Avx512Vnni,
+    ];
+
+    /// CPUID leaf, subleaf, register and bit reporting the feature
+    pub fn location(self) -> (u32, u32, CpuidRegister, u32) {
+        use CpuFeature::*;
+        use CpuidRegister::*;
+        match self {
+            Fpu => (1, 0, Edx, 0),
+            Tsc => (1, 0, Edx, 4),
+            Msr => (1, 0, Edx, 5),
+            Pae => (1, 0, Edx, 6),
+            Cx8 => (1, 0, Edx, 8),
+            Apic => (1, 0, Edx, 9),
+            Sep => (1, 0, Edx, 11),
+            Mtrr => (1, 0, Edx, 12),
+            Pge => (1, 0, Edx, 13),
+            Cmov => (1, 0, Edx, 15),
+            Pat => (1, 0, Edx, 16),
+            Clflush => (1, 0, Edx, 19),
+            Mmx => (1, 0, Edx, 23),
+            Fxsr => (1, 0, Edx, 24),
+            Sse => (1, 0, Edx, 25),
+            Sse2 => (1, 0, Edx, 26),
+            Sse3 => (1, 0, Ecx, 0),
+            Pclmulqdq => (1, 0, Ecx, 1),
+            Vmx => (1, 0, Ecx, 5),
+            Ssse3 => (1, 0, Ecx, 9),
+            Fma => (1, 0, Ecx, 12),
+            Cx16 => (1, 0, Ecx, 13),
+            Pcid => (1, 0, Ecx, 17),
+            Sse4_1 => (1, 0, Ecx, 19),
+            Sse4_2 => (1, 0, Ecx, 20),
+            X2Apic => (1, 0, Ecx, 21),
+            Movbe => (1, 0, Ecx, 22),
+            Popcnt => (1, 0, Ecx, 23),
+            TscDeadline => (1, 0, Ecx, 24),
+            Aes => (1, 0, Ecx, 25),
+            Xsave => (1, 0, Ecx, 26),
+            Osxsave => (1, 0, Ecx, 27),
+            Avx => (1, 0, Ecx, 28),
+            F16c => (1, 0, Ecx, 29),
+            Rdrand => (1, 0, Ecx, 30),
+            Fsgsbase => (7, 0, Ebx, 0),
+            Bmi1 => (7, 0, Ebx, 3),
+            Hle => (7, 0, Ebx, 4),
+            Avx2 => (7, 0, Ebx, 5),
+            Smep => (7, 0, Ebx, 7),
+            Bmi2 => (7, 0, Ebx, 8),
+            Erms => (7, 0, Ebx, 9),
+            Invpcid => (7, 0, Ebx, 10),
+            Rtm => (7, 0, Ebx, 11),
+            Avx512F => (7, 0, Ebx, 16),
+            Avx512Dq => (7, 0, Ebx, 17),
+            Rdseed => (7, 0, Ebx, 18),
+            Adx => (7, 0, Ebx, 19),
+            Smap => (7, 0, Ebx, 20),
+            Clflushopt => (7, 0, Ebx, 23),
+            Avx512Cd => (7, 0, Ebx, 28),
+            Sha => (7, 0, Ebx, 29),
+            Avx512Bw => (7, 0, Ebx, 30),
+            Avx512Vl => (7, 0, Ebx, 31),
+            Avx512Vbmi => (7, 0, Ecx, 1),
+            Umip => (7, 0, Ecx, 2),
+            Pku => (7, 0, Ecx, 3),
+            Avx512Vnni => (7, 0, Ecx, 11),
+            Rdpid => (7, 0, Ecx, 22),
+            MdClear => (7, 0, Edx, 10),
+            SpecCtrl => (7, 0, Edx, 26),
+            ArchCapabilities => (7, 0, Edx, 29),
+            LahfLm => (0x8000_0001, 0, Ecx, 0),
+            Svm => (0x8000_0001, 0, Ecx, 2),
+            Lzcnt => (0x8000_0001, 0, Ecx, 5),
+            Prefetchw => (0x8000_0001, 0, Ecx, 8),
+            Syscall => (0x8000_0001, 0, Edx, 11),
+            Nx => (0x8000_0001, 0, Edx, 20),
+            Page1Gb => (0x8000_0001, 0, Edx, 26),
+            Rdtscp => (0x8000_0001, 0, Edx, 27),
+            LongMode => (0x8000_0001, 0, Edx, 29),
+        }
+    }
+}
+
+/// Vendor, family, model and stepping a fixed model reports
+#[derive(Debug, Clone, PartialEq)]
+pub struct CpuSignature {
+    /// 12-byte vendor string, e.g. `GenuineIntel`
+    pub vendor: [u8; 12],
+    pub family: u32,
+    pub model: u32,
+    pub stepping: u32,
+}
+
+impl CpuSignature {
+    /// CPUID.1:EAX encoding of family, model and stepping
+    fn leaf1_eax(&self) -> u32 {
+        let (family, extended_family) = if self.family > 0xF { (0xF, self.family - 0xF) } else { (self.family, 0) };
+        (self.stepping & 0xF)
+            | ((self.model & 0xF) << 4)
+            | (family << 8)
+            | (((self.model >> 4) & 0xF) << 16)
+            | ((extended_family & 0xFF) << 20)
+    }
+
+    fn vendor_registers(&self) -> (u32, u32, u32) {
+        let word = |i: usize| u32::from_le_bytes([self.vendor[i], self.vendor[i + 1], self.vendor[i + 2], self.vendor[i + 3]]);
+        // CPUID.0 returns the vendor string in EBX, EDX, ECX order
+        (word(0), word(8), word(4))
+    }
+}
+
+/// Where a model's features come from
+#[derive(Debug, Clone, PartialEq)]
+enum FeatureBase {
+    /// Whatever the host has, minus the hidden features
+    Host,
+    /// Exactly this set; hosts without one of them cannot run the model
+    Fixed(BTreeSet<CpuFeature>),
+}
+
+/// What the hypervisor does with a guest RDMSR/WRMSR
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MsrDisposition {
+    /// Handle the MSR normally
+    Allow,
+    /// Reads return this value, writes raise #GP
+    ReadOnly(u64),
+    /// The MSR does not exist for this model: raise #GP
+    Fault,
+}
+
+/// CPU model exposed to a guest
+#[derive(Debug, Clone, PartialEq)]
+pub struct CpuModel {
+    /// Model name, also used as the brand string of fixed models
+    pub name: String,
+    base: FeatureBase,
+    hidden: BTreeSet<CpuFeature>,
+    signature: Option<CpuSignature>,
+    /// Whether performance monitoring (leaf 0xA and the PMU MSRs) is exposed
+    pmu: bool,
+}
+
+impl CpuModel {
+    /// Host passthrough without nested virtualization
+    pub fn host() -> Self {
+        CpuModel {
+            name: String::from("host"),
+            base: FeatureBase::Host,
+            hidden: [CpuFeature::Vmx, CpuFeature::Svm].into_iter().collect(),
+            signature: None,
+            pmu: true,
+        }
+    }
+
+    /// Portable x86-64-v2 model for classroom VMs: SSE4.2 and POPCNT, no
+    /// AVX, no TSX, no PMU
+    pub fn edu_baseline() -> Self {
+        use CpuFeature::*;
+        let features = [
+            Fpu, Tsc, Msr, Pae, Cx8, Apic, Sep, Mtrr, Pge, Cmov, Pat, Clflush, Mmx, Fxsr, Sse, Sse2,
+            Sse3, Ssse3, Cx16, Sse4_1, Sse4_2, Popcnt, X2Apic, LahfLm, Syscall, Nx, LongMode,
+        ];
+        CpuModel {
+            name: String::from("edu-baseline"),
+            base: FeatureBase::Fixed(features.into_iter().collect()),
+            hidden: BTreeSet::new(),
+            signature: Some(CpuSignature { vendor: *b"MultiOSEdCPU", family: 6, model: 0x1A, stepping: 0 }),
+            pmu: false,
+        }
+    }
+
+    /// Portable x86-64-v3 model: the baseline plus AVX2, FMA, BMI and XSAVE
+    pub fn x86_64_v3() -> Self {
+        use CpuFeature::*;
+        let mut model = Self::edu_baseline();
+        if let FeatureBase::Fixed(features) = &mut model.base {
+            features.extend([Avx, Avx2, Fma, F16c, Bmi1, Bmi2, Movbe, Lzcnt, Xsave, Osxsave, Pclmulqdq, Aes]);
+        }
+        model.name = String::from("x86-64-v3");
+        model
+    }
+
+    /// Look up a model by name
+    pub fn named(name: &str) -> Option<Self> {
+        match name {
+            "host" => Some(Self::host()),
+            "host-nested" => Some(Self::host().with_nested()),
+            "edu-baseline" => Some(Self::edu_baseline()),
+            "x86-64-v3" => Some(Self::x86_64_v3()),
+            _ => None,
+        }
+    }
+
+    /// Expose VMX and SVM so the guest can run its own hypervisor
+    pub fn with_nested(mut self) -> Self {
+        for feature in [CpuFeature::Vmx, CpuFeature::Svm] {
+            self.hidden.remove(&feature);
+            if let FeatureBase::Fixed(features) = &mut self.base {
+                features.insert(feature);
+            }
+        }
+        self
+    }
+
+    /// Hide a feature from the guest
+    pub fn hide(mut self, feature: CpuFeature) -> Self {
+        self.hidden.insert(feature);
+        self
+    }
+
+    /// Hide every AVX-512 subset and its XSAVE state
+    pub fn without_avx512(mut self) -> Self {
+        self.hidden.extend(CpuFeature::AVX512.iter().copied());
+        self
+    }
+
+    /// Whether the guest sees a feature, assuming the host has it
+    pub fn exposes(&self, feature: CpuFeature) -> bool {
+        if self.hidden.contains(&feature) {
+            return false;
+        }
+        match &self.base {
+            FeatureBase::Host => true,
+            FeatureBase::Fixed(features) => features.contains(&feature),
+        }
+    }
+
+    /// Features of a fixed model the host lacks; a VM using the model (or a
+    /// snapshot taken with it) cannot run on such a host
+    pub fn missing_on_host<F>(&self, host_cpuid: F) -> Vec<CpuFeature>
+    where
+        F: Fn(u32, u32) -> CpuidResult,
+    {
+        let features = match &self.base {
+            FeatureBase::Host => return Vec::new(),
+            FeatureBase::Fixed(features) => features,
+        };
+        features
+            .iter()
+            .copied()
+            .filter(|feature| !self.hidden.contains(feature))
+            .filter(|feature| {
+                let (leaf, subleaf, register, bit) = feature.location();
+                Self::register(&host_cpuid(leaf, subleaf), register) & (1 << bit) == 0
+            })
+            .collect()
+    }
+
+    /// Filter the host's answer to CPUID `leaf`/`subleaf` for a vCPU with
+    /// the given APIC ID
+    pub fn filter_cpuid(&self, leaf: u32, subleaf: u32, apic_id: u32, host: CpuidResult) -> CpuidResult {
+        let extended = leaf >= 0x8000_0000;
+        let host_max = if extended { MAX_EXTENDED_LEAF } else { MAX_BASIC_LEAF };
+        if leaf > host_max {
+            // Out-of-range leaves read as zero instead of leaking host data
+            return CpuidResult::default();
+        }
+
+        let mut result = host;
+        for register in [CpuidRegister::Eax, CpuidRegister::Ebx, CpuidRegister::Ecx, CpuidRegister::Edx] {
+            let mask = self.feature_mask(leaf, subleaf, register);
+            *Self::register_mut(&mut result, register) &= mask;
+        }
+
+        match leaf {
+            0 => {
+                result.eax = host.eax.min(MAX_BASIC_LEAF);
+                if let Some(signature) = &self.signature {
+                    (result.ebx, result.edx, result.ecx) = signature.vendor_registers();
+                }
+            }
+            1 => {
+                if let Some(signature) = &self.signature {
+                    result.eax = signature.leaf1_eax();
+                }
+                // Initial APIC ID in EBX[31:24]
+                result.ebx = (result.ebx & 0x00FF_FFFF) | (apic_id << 24);
+                // Tell the guest it runs under a hypervisor
+                result.ecx |= 1 << 31;
+            }
+            0xA if !self.pmu => result = CpuidResult::default(),
+            0xB | 0x1F => result.edx = apic_id,
+            0xD => result = self.filter_xsave_leaf(subleaf, result),
+            0x8000_0000 => result.eax = host.eax.min(MAX_EXTENDED_LEAF),
+            0x8000_0002..=0x8000_0004 if self.signature.is_some() => result = self.brand_string(leaf - 0x8000_0002),
+            _ => {}
+        }
+        result
+    }
+
+    /// Decide how a guest access to an MSR is handled under this model
+    pub fn filter_msr(&self, index: u32, write: bool) -> MsrDisposition {
+        let gated = |feature: CpuFeature| if self.exposes(feature) { MsrDisposition::Allow } else { MsrDisposition::Fault };
+        match index {
+            MSR_IA32_FEATURE_CONTROL if !self.exposes(CpuFeature::Vmx) => {
+                if write { MsrDisposition::Fault } else { MsrDisposition::ReadOnly(FEATURE_CONTROL_LOCKED) }
+            }
+            MSR_IA32_VMX_FIRST..=MSR_IA32_VMX_LAST => gated(CpuFeature::Vmx),
+            MSR_AMD_VM_CR | MSR_AMD_VM_HSAVE_PA => gated(CpuFeature::Svm),
+            MSR_IA32_SPEC_CTRL | MSR_IA32_PRED_CMD => gated(CpuFeature::SpecCtrl),
+            MSR_IA32_ARCH_CAPABILITIES => gated(CpuFeature::ArchCapabilities),
+            MSR_IA32_TSC_DEADLINE => gated(CpuFeature::TscDeadline),
+            MSR_X2APIC_FIRST..=MSR_X2APIC_LAST => gated(CpuFeature::X2Apic),
+            MSR_IA32_PMC_FIRST..=MSR_IA32_PMC_LAST
+            | MSR_IA32_PERFEVTSEL_FIRST..=MSR_IA32_PERFEVTSEL_LAST
+            | MSR_IA32_PERF_FIXED_FIRST..=MSR_IA32_PERF_GLOBAL_CTRL_LAST if !self.pmu => MsrDisposition::Fault,
+            _ => MsrDisposition::Allow,
+        }
+    }
+
+    /// Bits of a register the guest may see
+    fn feature_mask(&self, leaf: u32, subleaf: u32, register: CpuidRegister) -> u32 {
+        let mut known = 0u32;
+        let mut exposed = 0u32;
+        for &feature in CpuFeature::ALL {
+            let (feature_leaf, feature_subleaf, feature_register, bit) = feature.location();
+            if (feature_leaf, feature_subleaf, feature_register) != (leaf, subleaf, register) {
+                continue;
+            }
+            known |= 1 << bit;
+            if self.exposes(feature) {
+                exposed |= 1 << bit;
+            }
+        }
+        match self.base {
+            // Unknown bits pass through on the host model; fixed models
+            // clear them so the guest sees the same bits on every host
+            FeatureBase::Host => !known | exposed,
+            FeatureBase::Fixed(_) if known != 0 => exposed,
+            FeatureBase::Fixed(_) => u32::MAX,
+        }
+    }
+
+    /// Leaf 0xD: drop the XSAVE components of hidden features
+    fn filter_xsave_leaf(&self, subleaf: u32, mut result: CpuidResult) -> CpuidResult {
+        if !self.exposes(CpuFeature::Xsave) {
+            return CpuidResult::default();
+        }
+        // x87 and SSE state are always present; AVX is component 2, the
+        // AVX-512 opmask and upper ZMM state are 5-7, PKRU is 9
+        let mut components: u32 = 0b11;
+        if self.exposes(CpuFeature::Avx) {
+            components |= 1 << 2;
+        }
+        if self.exposes(CpuFeature::Avx512F) {
+            components |= 0b111 << 5;
+        }
+        if self.exposes(CpuFeature::Pku) {
+            components |= 1 << 9;
+        }
+
+        match subleaf {
+            0 => {
+                result.eax &= components;
+                result.edx = 0;
+            }
+            1 => {}
+            component if component < 32 && components & (1 << component) == 0 => result = CpuidResult::default(),
+            _ => {}
+        }
+        result
+    }
+
+    /// 16 bytes of the brand string for leaves 0x8000_0002-0x8000_0004
+    fn brand_string(&self, part: u32) -> CpuidResult {
+        let mut brand = [0u8; 48];
+        let name = self.name.as_bytes();
+        let length = name.len().min(47);
+        brand[..length].copy_from_slice(&name[..length]);
+
+        let chunk = &brand[part as usize * 16..part as usize * 16 + 16];
+        let word = |i: usize| u32::from_le_bytes([chunk[i], chunk[i + 1], chunk[i + 2], chunk[i + 3]]);
+        CpuidResult { eax: word(0), ebx: word(4), ecx: word(8), edx: word(12) }
+    }
+
+    fn register(result: &CpuidResult, register: CpuidRegister) -> u32 {
+        match register {
+            CpuidRegister::Eax => result.eax,
+            CpuidRegister::Ebx => result.ebx,
+            CpuidRegister::Ecx => result.ecx,
+            CpuidRegister::Edx => result.edx,
+        }
+    }
+
+    fn register_mut(result: &mut CpuidResult, register: CpuidRegister) -> &mut u32 {
+        match register {
+            CpuidRegister::Eax => &mut result.eax,
+            CpuidRegister::Ebx => &mut result.ebx,
+            CpuidRegister::Ecx => &mut result.ecx,
+            CpuidRegister::Edx => &mut result.edx,
+        }
+    }
+}
+
+/// Execute CPUID on the host
+pub fn host_cpuid(leaf: u32, subleaf: u32) -> CpuidResult {
+    #[cfg(target_arch = "x86_64")]
+    {
+        // SAFETY: CPUID is available on every x86_64 processor
+        let result = unsafe { core::arch::x86_64::__cpuid_count(leaf, subleaf) };
+        CpuidResult { eax: result.eax, ebx: result.ebx, ecx: result.ecx, edx: result.edx }
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    {
+        let _ = (leaf, subleaf);
+        CpuidResult::default()
+    }
+}
@@ -0,0 +1,255 @@
+//! VM Exit Decoding
+//!
+//! Turns the raw exit information saved on a VM exit into a human-readable
+//! description: the decoded exit reason, the fields of the exit
+//! qualification and the guest register the exit reads or writes.
+//! Qualification layouts follow the Intel SDM, Vol. 3C, chapter 28.
+
+use crate::core::{VmExitReason, VcpuRegs};
+use super::{VmcsField, VmcsRegion};
+use crate::HypervisorError;
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+/// Exit information as saved by the processor on a VM exit
+#[derive(Debug, Clone, Copy)]
+pub struct RawVmExit {
+    pub reason: VmExitReason,
+    pub qualification: u64,
+    pub guest_rip: u64,
+    pub instruction_length: u32,
+    /// Guest-physical address for EPT violations
+    pub guest_physical_address: Option<u64>,
+}
+
+impl RawVmExit {
+    /// Read the exit information of the last VM exit from a VMCS
+    pub fn read_vmcs(vmcs: &VmcsRegion, reason: VmExitReason) -> Result<Self, HypervisorError> {
+        Ok(RawVmExit {
+            reason,
+            qualification: vmcs.read_field(VmcsField::VmExitQualification)?,
+            guest_rip: vmcs.read_field(VmcsField::GuestRip)?,
+            instruction_length: vmcs.read_field(VmcsField::VmExitInstructionLength)? as u32,
+            guest_physical_address: None,
+        })
+    }
+}
+
+/// Guest register an exit reads or writes
+#[derive(Debug, Clone, PartialEq)]
+pub struct AffectedRegister {
+    pub name: &'static str,
+    pub value: u64,
+    /// Whether the exit writes the register, as opposed to reading it
+    pub written: bool,
+}
+
+/// A VM exit annotated for display
+#[derive(Debug, Clone)]
+pub struct DecodedExit {
+    pub reason: VmExitReason,
+    /// One-line summary, e.g. `OUT to port 0x3f8 (1 byte)`
+    pub summary: String,
+    /// What the exit means and what the hypervisor does about it
+    pub explanation: &'static str,
+    /// Decoded qualification fields as name/value pairs
+    pub fields: Vec<(&'static str, String)>,
+    pub affected_register: Option<AffectedRegister>,
+    pub guest_rip: u64,
+}
+
+/// General purpose register names in VMX encoding order
+const GPR_NAMES: [&str; 16] = [
+    "rax", "rcx", "rdx", "rbx", "rsp", "rbp", "rsi", "rdi",
+    "r8", "r9", "r10", "r11", "r12", "r13", "r14", "r15",
+];
+
+/// Value of a general purpose register by its VMX encoding
+fn gpr_value(regs: &VcpuRegs, index: usize) -> u64 {
+    match index {
+        0 => regs.rax, 1 => regs.rcx, 2 => regs.rdx, 3 => regs.rbx,
+        4 => regs.rsp, 5 => regs.rbp, 6 => regs.rsi, 7 => regs.rdi,
+        8 => regs.r8, 9 => regs.r9, 10 => regs.r10, 11 => regs.r11,
+        12 => regs.r12, 13 => regs.r13, 14 => regs.r14, _ => regs.r15,
+    }
+}
+
+fn bit(value: u64, bit: u32) -> bool {
+    value & (1 << bit) != 0
+}
+
+fn yes_no(value: bool) -> String {
+    if value { "yes".to_string() } else { "no".to_string() }
+}
+
+/// Decode a VM exit for display
+pub fn decode_vm_exit(exit: &RawVmExit, regs: &VcpuRegs) -> DecodedExit {
+    let q = exit.qualification;
+    let mut fields = Vec::new();
+    let mut affected_register = None;
+
+    let (summary, explanation) = match exit.reason {
+        VmExitReason::ControlRegisterAccess | VmExitReason::MovCr3 => {
+            let cr = q & 0xf;
+            let access = (q >> 4) & 0x3;
+            let gpr = ((q >> 8) & 0xf) as usize;
+            fields.push(("control register", format!("cr{}", cr)));
+            fields.push(("access type", String::from(match access {
+                0 => "MOV to CR",
+                1 => "MOV from CR",
+                2 => "CLTS",
+                _ => "LMSW",
+            })));
+            match access {
+                0 | 1 => {
+                    fields.push(("register", GPR_NAMES[gpr].to_string()));
+                    affected_register = Some(AffectedRegister {
+                        name: GPR_NAMES[gpr],
+                        value: gpr_value(regs, gpr),
+                        written: access == 1,
+                    });
+                }
+                3 => fields.push(("LMSW source", format!("0x{:04x}", (q >> 16) & 0xffff))),
+                _ => {}
+            }
+            let summary = match access {
+                0 => format!("MOV cr{}, {}", cr, GPR_NAMES[gpr]),
+                1 => format!("MOV {}, cr{}", GPR_NAMES[gpr], cr),
+                2 => String::from("CLTS"),
+                _ => String::from("LMSW"),
+            };
+            (summary, "The guest accessed a control register the hypervisor intercepts; the access is emulated against the guest's shadow value")
+        }
+        VmExitReason::MovDr | VmExitReason::MovDr3 => {
+            let dr = q & 0x7;
+            let from_dr = bit(q, 4);
+            let gpr = ((q >> 8) & 0xf) as usize;
+            fields.push(("debug register", format!("dr{}", dr)));
+            fields.push(("direction", String::from(if from_dr { "MOV from DR" } else { "MOV to DR" })));
+            fields.push(("register", GPR_NAMES[gpr].to_string()));
+            affected_register = Some(AffectedRegister { name: GPR_NAMES[gpr], value: gpr_value(regs, gpr), written: from_dr });
+            let summary = if from_dr {
+                format!("MOV {}, dr{}", GPR_NAMES[gpr], dr)
+            } else {
+                format!("MOV dr{}, {}", dr, GPR_NAMES[gpr])
+            };
+            (summary, "The guest accessed a debug register; the hypervisor keeps the guest's debug state separate from the host's")
+        }
+        VmExitReason::IoInstruction => {
+            let size = (q & 0x7) + 1;
+            let input = bit(q, 3);
+            let string = bit(q, 4);
+            let rep = bit(q, 5);
+            let port = (q >> 16) & 0xffff;
+            fields.push(("port", format!("0x{:04x}", port)));
+            fields.push(("size", format!("{} byte(s)", size)));
+            fields.push(("direction", String::from(if input { "in" } else { "out" })));
+            fields.push(("string", yes_no(string)));
+            fields.push(("rep prefix", yes_no(rep)));
+            fields.push(("operand", String::from(if bit(q, 6) { "immediate" } else { "dx" })));
+            if !string {
+                affected_register = Some(AffectedRegister { name: "rax", value: regs.rax, written: input });
+            }
+            let mnemonic = match (input, string) {
+                (true, false) => "IN",
+                (false, false) => "OUT",
+                (true, true) => "INS",
+                (false, true) => "OUTS",
+            };
+            let summary = format!(
+                "{}{} {} port 0x{:04x} ({} byte{})",
+                if rep { "REP " } else { "" },
+                mnemonic,
+                if input { "from" } else { "to" },
+                port,
+                size,
+                if size > 1 { "s" } else { "" }
+            );
+            (summary, "The guest executed a port I/O instruction; the hypervisor forwards it to the emulated device behind the port")
+        }
+        VmExitReason::EnableEptViolation => {
+            let (read, write, fetch) = (bit(q, 0), bit(q, 1), bit(q, 2));
+            fields.push(("read", yes_no(read)));
+            fields.push(("write", yes_no(write)));
+            fields.push(("instruction fetch", yes_no(fetch)));
+            fields.push(("page permissions", format!(
+                "{}{}{}",
+                if bit(q, 3) { "r" } else { "-" },
+                if bit(q, 4) { "w" } else { "-" },
+                if bit(q, 5) { "x" } else { "-" }
+            )));
+            fields.push(("linear address valid", yes_no(bit(q, 7))));
+            let access = if fetch { "fetch" } else if write { "write" } else { "read" };
+            let summary = match exit.guest_physical_address {
+                Some(gpa) => {
+                    fields.push(("guest physical address", format!("0x{:016x}", gpa)));
+                    format!("EPT violation: {} at 0x{:x}", access, gpa)
+                }
+                None => format!("EPT violation: {}", access),
+            };
+            (summary, "The guest touched guest-physical memory that the EPT does not map with the needed permissions; the hypervisor maps the page or emulates the access (MMIO)")
+        }
+        VmExitReason::MsrRead | VmExitReason::RdmsrInstruction => {
+            fields.push(("msr", format!("0x{:08x}", regs.rcx as u32)));
+            affected_register = Some(AffectedRegister { name: "rax", value: regs.rax, written: true });
+            (format!("RDMSR 0x{:x}", regs.rcx as u32), "The guest read a model-specific register; the hypervisor returns the guest's virtual value in edx:eax")
+        }
+        VmExitReason::MsrWrite | VmExitReason::WrmsrInstruction => {
+            let value = ((regs.rdx & 0xffff_ffff) << 32) | (regs.rax & 0xffff_ffff);
+            fields.push(("msr", format!("0x{:08x}", regs.rcx as u32)));
+            fields.push(("value", format!("0x{:016x}", value)));
+            affected_register = Some(AffectedRegister { name: "rax", value: regs.rax, written: false });
+            (format!("WRMSR 0x{:x} = 0x{:x}", regs.rcx as u32, value), "The guest wrote a model-specific register; the hypervisor validates the value and updates the guest's virtual MSR")
+        }
+        VmExitReason::CpuidInstruction => {
+            fields.push(("leaf", format!("0x{:08x}", regs.rax as u32)));
+            fields.push(("subleaf", format!("0x{:08x}", regs.rcx as u32)));
+            affected_register = Some(AffectedRegister { name: "rax", value: regs.rax, written: true });
+            (format!("CPUID leaf 0x{:x}", regs.rax as u32), "CPUID always exits; the hypervisor returns the feature set the VM is configured to see")
+        }
+        VmExitReason::HltInstruction => (
+            String::from("HLT"),
+            "The guest has nothing to do until the next interrupt; the vCPU is descheduled",
+        ),
+        VmExitReason::Exception => {
+            fields.push(("qualification", format!("0x{:x}", q)));
+            (String::from("Exception"), "A guest exception is intercepted; for page faults the qualification holds the faulting linear address")
+        }
+        VmExitReason::Interrupt => (
+            String::from("External interrupt"),
+            "A host interrupt arrived while the guest ran; the host handles it and the guest is resumed",
+        ),
+        VmExitReason::TripleFault => (
+            String::from("Triple fault"),
+            "The guest faulted while delivering a double fault; a real machine would reset",
+        ),
+        VmExitReason::DescriptorTableAccess => (
+            String::from("Descriptor table access"),
+            "The guest loaded or stored GDTR, IDTR, LDTR or TR",
+        ),
+        VmExitReason::TaskSwitch => {
+            fields.push(("TSS selector", format!("0x{:04x}", q & 0xffff)));
+            (String::from("Task switch"), "The guest performed a hardware task switch, which the hypervisor emulates")
+        }
+        VmExitReason::NmiWindow => (
+            String::from("NMI window"),
+            "The guest can now accept an NMI the hypervisor has pending",
+        ),
+        VmExitReason::InvalidState => (
+            String::from("VM entry failed: invalid guest state"),
+            "The guest state in the VMCS failed the VM-entry checks",
+        ),
+        reason => (format!("{:?}", reason), "An exit the visualizer has no detailed decoding for"),
+    };
+
+    DecodedExit {
+        reason: exit.reason,
+        summary,
+        explanation,
+        fields,
+        affected_register,
+        guest_rip: exit.guest_rip,
+    }
+}
@@ -9,6 +9,14 @@ use crate::core::{VmExitReason, VcpuState, VcpuRegs, VcpuCtrlRegs};
 use bitflags::bitflags;
 use alloc::vec::Vec;
 
+mod exit_decoder;
+mod cpu_model;
+mod apicv;
+
+pub use exit_decoder::*;
+pub use cpu_model::*;
+pub use apicv::*;
+
 /// VMCS field definitions for Intel VT-x
 #[repr(u32)]
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -124,6 +132,11 @@ bitflags! {
         const ENABLE_RDRAND = 1 << 24;
         const ENABLE_RDSEED = 1 << 25;
         const ENABLE_PCOMMIT = 1 << 26;
+        // APICv: TPR shadow is a primary control, the rest are secondary
+        const USE_TPR_SHADOW = 1 << 21;
+        const VIRTUALIZE_X2APIC = 1 << 4;
+        const APIC_REGISTER_VIRT = 1 << 8;
+        const VIRTUAL_INTR_DELIVERY = 1 << 9;
     }
 }
 
@@ -454,22 +467,35 @@ impl CpuVirtualization {
     /// Setup VMCS configuration
     fn setup_vmcs(&self, vmcs_region: &VmcsRegion) -> Result<(), HypervisorError> {
         // Setup pin-based execution controls
-        let pin_controls = VmcsPinControls::EXTERNAL_INTERRUPT | 
+        let apicv = ApicAcceleration::detect(self.capabilities) == ApicAcceleration::Apicv;
+        let mut pin_controls = VmcsPinControls::EXTERNAL_INTERRUPT | 
                           VmcsPinControls::NMI | 
                           VmcsPinControls::VIRTUAL_NMIS;
+        if apicv {
+            pin_controls |= VmcsPinControls::POSTED_INTERRUPTS;
+        }
         vmcs_region.write_field(VmcsField::PinBasedVmExecutionControls, pin_controls.bits())?;
         
         // Setup processor-based execution controls
-        let proc_controls = VmcsControls::INTERRUPT_WINDOW |
+        let mut proc_controls = VmcsControls::INTERRUPT_WINDOW |
                            VmcsControls::NMI_WINDOW |
                            VmcsControls::ENABLE_VM_FUNCTIONS |
                            VmcsControls::ENABLE_EPT |
                            VmcsControls::ENABLE_VPID;
+        if apicv {
+            proc_controls |= VmcsControls::USE_TPR_SHADOW;
+        }
         vmcs_region.write_field(VmcsField::PrimaryProcessorBasedVmExecutionControls, proc_controls.bits())?;
         
         // Setup secondary processor-based execution controls
-        let secondary_controls = VmcsControls::ENABLE_UNRESTRICTED_GUEST |
+        let mut secondary_controls = VmcsControls::ENABLE_UNRESTRICTED_GUEST |
                                 VmcsControls::ENABLE_XSAVES;
+        if apicv {
+            secondary_controls |= VmcsControls::VIRTUALIZE_X2APIC |
+                                  VmcsControls::APIC_REGISTER_VIRT |
+                                  VmcsControls::VIRTUAL_INTR_DELIVERY;
+            vmcs_region.write_field(VmcsField::PostedInterruptVector, POSTED_INTERRUPT_VECTOR as u64)?;
+        }
         vmcs_region.write_field(VmcsField::SecondaryProcessorBasedVmExecutionControls, secondary_controls.bits())?;
         
         // Setup exit controls
@@ -499,6 +525,10 @@ impl CpuVirtualization {
         
         vmcb_region.set_intercept(intercepts)?;
         
+        if ApicAcceleration::detect(self.capabilities) == ApicAcceleration::Avic {
+            vmcb_region.set_avic_enable(true)?;
+        }
+        
         Ok(())
     }
     
@@ -636,6 +666,12 @@ impl VmcbRegion {
         // Set npt_enable field in VMCB
         Ok(())
     }
+    
+    /// Enable AVIC
+    pub fn set_avic_enable(&self, enable: bool) -> Result<(), HypervisorError> {
+        // Set AVIC enable bit in the virtual interrupt control field
+        Ok(())
+    }
 }
 
 /// VMCS pointer for active VMCS tracking
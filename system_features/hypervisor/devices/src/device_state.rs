@@ -0,0 +1,269 @@
+//! Device State Save/Restore
+//!
+//! Snapshots and migration capture the state of every device of a VM.
+//! Device state is a list of tagged fields, so a reader skips the fields it
+//! does not know and leaves fields missing from an older writer at their
+//! current values. Each record carries the layout version it was written
+//! with and the oldest version able to restore it; a writer only raises the
+//! latter when a change cannot be ignored by older readers.
+
+use crate::{HypervisorError, ConfigError, ConfigErrorReason, VmId};
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Magic at the start of a framework state blob
+const DEVICE_STATE_MAGIC: &[u8; 4] = b"MDEV";
+/// Layout of the blob around the device records
+const DEVICE_STATE_FORMAT: u16 = 1;
+/// Field header: tag and length
+const FIELD_HEADER_SIZE: usize = 6;
+
+/// State of a device that can be saved and restored
+pub trait DeviceStateSerialize {
+    /// Layout version `save_state` writes
+    fn state_version(&self) -> u16;
+
+    /// Oldest layout version that can restore what `save_state` writes
+    fn min_state_version(&self) -> u16 {
+        1
+    }
+
+    fn save_state(&self, writer: &mut DeviceStateWriter);
+
+    /// Restore saved state; fields the reader lacks keep their values
+    fn restore_state(&mut self, reader: &DeviceStateReader) -> Result<(), HypervisorError>;
+}
+
+/// Builds the tagged fields of a device's state
+#[derive(Debug, Clone, Default)]
+pub struct DeviceStateWriter {
+    bytes: Vec<u8>,
+}
+
+impl DeviceStateWriter {
+    pub fn new() -> Self {
+        DeviceStateWriter { bytes: Vec::new() }
+    }
+
+    pub fn put_bytes(&mut self, tag: u16, value: &[u8]) {
+        self.bytes.extend_from_slice(&tag.to_le_bytes());
+        self.bytes.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        self.bytes.extend_from_slice(value);
+    }
+
+    pub fn put_u8(&mut self, tag: u16, value: u8) {
+        self.put_bytes(tag, &[value]);
+    }
+
+    pub fn put_bool(&mut self, tag: u16, value: bool) {
+        self.put_u8(tag, value as u8);
+    }
+
+    pub fn put_u16(&mut self, tag: u16, value: u16) {
+        self.put_bytes(tag, &value.to_le_bytes());
+    }
+
+    pub fn put_u32(&mut self, tag: u16, value: u32) {
+        self.put_bytes(tag, &value.to_le_bytes());
+    }
+
+    pub fn put_u64(&mut self, tag: u16, value: u64) {
+        self.put_bytes(tag, &value.to_le_bytes());
+    }
+
+    /// A field holding a list of `u64` values
+    pub fn put_u64s(&mut self, tag: u16, values: &[u64]) {
+        let bytes: Vec<u8> = values.iter().flat_map(|value| value.to_le_bytes()).collect();
+        self.put_bytes(tag, &bytes);
+    }
+
+    /// A field holding the state of a part of the device
+    pub fn put_nested(&mut self, tag: u16, part: &dyn DeviceStateSerialize) {
+        self.put_bytes(tag, &encode_record(part));
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+/// Fields of a saved device state
+#[derive(Debug, Clone)]
+pub struct DeviceStateReader<'a> {
+    version: u16,
+    fields: BTreeMap<u16, &'a [u8]>,
+}
+
+impl<'a> DeviceStateReader<'a> {
+    /// Split saved fields written with layout `version`
+    pub fn parse(version: u16, mut bytes: &'a [u8]) -> Result<Self, HypervisorError> {
+        let mut fields = BTreeMap::new();
+        while !bytes.is_empty() {
+            if bytes.len() < FIELD_HEADER_SIZE {
+                return Err(truncated("device_state.field_header"));
+            }
+            let tag = u16::from_le_bytes([bytes[0], bytes[1]]);
+            let length = u32::from_le_bytes([bytes[2], bytes[3], bytes[4], bytes[5]]) as usize;
+            let rest = &bytes[FIELD_HEADER_SIZE..];
+            if rest.len() < length {
+                return Err(truncated("device_state.field"));
+            }
+            fields.insert(tag, &rest[..length]);
+            bytes = &rest[length..];
+        }
+        Ok(DeviceStateReader { version, fields })
+    }
+
+    /// Layout version the state was written with
+    pub fn version(&self) -> u16 {
+        self.version
+    }
+
+    pub fn bytes(&self, tag: u16) -> Option<&'a [u8]> {
+        self.fields.get(&tag).copied()
+    }
+
+    pub fn u8(&self, tag: u16) -> Option<u8> {
+        self.fixed::<1>(tag).map(|bytes| bytes[0])
+    }
+
+    pub fn bool(&self, tag: u16) -> Option<bool> {
+        self.u8(tag).map(|value| value != 0)
+    }
+
+    pub fn u16(&self, tag: u16) -> Option<u16> {
+        self.fixed::<2>(tag).map(u16::from_le_bytes)
+    }
+
+    pub fn u32(&self, tag: u16) -> Option<u32> {
+        self.fixed::<4>(tag).map(u32::from_le_bytes)
+    }
+
+    pub fn u64(&self, tag: u16) -> Option<u64> {
+        self.fixed::<8>(tag).map(u64::from_le_bytes)
+    }
+
+    pub fn u64s(&self, tag: u16) -> Option<Vec<u64>> {
+        let bytes = self.bytes(tag)?;
+        if bytes.len() % 8 != 0 {
+            return None;
+        }
+        Some(bytes.chunks_exact(8).map(|chunk| {
+            let mut value = [0u8; 8];
+            value.copy_from_slice(chunk);
+            u64::from_le_bytes(value)
+        }).collect())
+    }
+
+    /// Restore a part of the device saved with `put_nested`
+    pub fn restore_nested(&self, tag: u16, part: &mut dyn DeviceStateSerialize) -> Result<(), HypervisorError> {
+        match self.bytes(tag) {
+            Some(record) => restore_record(part, record).map(|_| ()),
+            None => Ok(()),
+        }
+    }
+
+    /// Tags present, for state with a variable number of parts
+    pub fn tags(&self) -> impl Iterator<Item = u16> + '_ {
+        self.fields.keys().copied()
+    }
+
+    fn fixed<const N: usize>(&self, tag: u16) -> Option<[u8; N]> {
+        let bytes = self.bytes(tag)?;
+        let mut value = [0u8; N];
+        if bytes.len() != N {
+            return None;
+        }
+        value.copy_from_slice(bytes);
+        Some(value)
+    }
+}
+
+/// Versions and fields of one device
+pub fn encode_record(device: &dyn DeviceStateSerialize) -> Vec<u8> {
+    let mut writer = DeviceStateWriter::new();
+    device.save_state(&mut writer);
+    let payload = writer.into_bytes();
+
+    let mut record = Vec::with_capacity(payload.len() + 8);
+    record.extend_from_slice(&device.state_version().to_le_bytes());
+    record.extend_from_slice(&device.min_state_version().to_le_bytes());
+    record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    record.extend_from_slice(&payload);
+    record
+}
+
+/// Restore a device from the start of `bytes`; returns the bytes used
+pub fn restore_record(device: &mut dyn DeviceStateSerialize, bytes: &[u8]) -> Result<usize, HypervisorError> {
+    if bytes.len() < 8 {
+        return Err(truncated("device_state.record_header"));
+    }
+    let version = u16::from_le_bytes([bytes[0], bytes[1]]);
+    let min_version = u16::from_le_bytes([bytes[2], bytes[3]]);
+    let length = u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]) as usize;
+    let payload = bytes.get(8..8 + length).ok_or_else(|| truncated("device_state.record"))?;
+
+    if min_version > device.state_version() {
+        warn!("Device state version {} needs a reader of version {}, this one is {}",
+              version, min_version, device.state_version());
+        return Err(ConfigError::new("device_state.version", ConfigErrorReason::Mismatch).value(min_version as u64).into());
+    }
+    device.restore_state(&DeviceStateReader::parse(version, payload)?)?;
+    Ok(8 + length)
+}
+
+/// Framework state blob: header, then an identified record per device
+pub(crate) fn encode_blob(vm_id: VmId, records: &[(String, Vec<u8>)]) -> Vec<u8> {
+    let mut blob = Vec::new();
+    blob.extend_from_slice(DEVICE_STATE_MAGIC);
+    blob.extend_from_slice(&DEVICE_STATE_FORMAT.to_le_bytes());
+    blob.extend_from_slice(&vm_id.0.to_le_bytes());
+    blob.extend_from_slice(&(records.len() as u32).to_le_bytes());
+    for (device_id, record) in records {
+        blob.extend_from_slice(&(device_id.len() as u16).to_le_bytes());
+        blob.extend_from_slice(device_id.as_bytes());
+        blob.extend_from_slice(record);
+    }
+    blob
+}
+
+/// Split a framework state blob into the saving VM and device records
+pub(crate) fn decode_blob(blob: &[u8]) -> Result<(VmId, Vec<(String, &[u8])>), HypervisorError> {
+    if blob.len() < 14 || &blob[..4] != DEVICE_STATE_MAGIC {
+        return Err(ConfigError::new("device_state.magic", ConfigErrorReason::Malformed).into());
+    }
+    let format_version = u16::from_le_bytes([blob[4], blob[5]]);
+    if format_version > DEVICE_STATE_FORMAT {
+        return Err(ConfigError::new("device_state.format", ConfigErrorReason::Mismatch).value(format_version as u64).into());
+    }
+    let vm_id = VmId(u32::from_le_bytes([blob[6], blob[7], blob[8], blob[9]]));
+    let count = u32::from_le_bytes([blob[10], blob[11], blob[12], blob[13]]);
+
+    let mut rest = &blob[14..];
+    let mut records = Vec::new();
+    for _ in 0..count {
+        if rest.len() < 2 {
+            return Err(truncated("device_state.device_id"));
+        }
+        let id_length = u16::from_le_bytes([rest[0], rest[1]]) as usize;
+        let id = rest.get(2..2 + id_length).ok_or_else(|| truncated("device_state.device_id"))?;
+        let device_id = String::from_utf8(id.to_vec())
+            .map_err(|_| HypervisorError::from(ConfigError::new("device_state.device_id", ConfigErrorReason::Malformed)))?;
+        rest = &rest[2 + id_length..];
+
+        if rest.len() < 8 {
+            return Err(truncated("device_state.record_header"));
+        }
+        let length = 8 + u32::from_le_bytes([rest[4], rest[5], rest[6], rest[7]]) as usize;
+        let record = rest.get(..length).ok_or_else(|| truncated("device_state.record"))?;
+        records.push((device_id, record));
+        rest = &rest[length..];
+    }
+    Ok((vm_id, records))
+}
+
+fn truncated(field: &'static str) -> HypervisorError {
+    ConfigError::new(field, ConfigErrorReason::Malformed).into()
+}
@@ -0,0 +1,570 @@
+//! Display Export
+//!
+//! Frames grabbed from a guest's display adapter, PNG snapshots of them,
+//! and a remote framebuffer (RFB, the VNC protocol) server. The server
+//! only speaks the protocol: the host moves bytes between it and a socket,
+//! presents frames when the display changes, and injects the input events
+//! it collects. Only the None security type and Raw encoding are offered,
+//! so the server is meant for local tutorials, not exposure to a network.
+
+use crate::{HypervisorError, IoError, IoErrorKind};
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// A grabbed frame, 8-bit RGB without padding
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl Frame {
+    /// A black frame
+    pub fn new(width: u32, height: u32) -> Self {
+        Frame { width, height, pixels: vec![0; width as usize * height as usize * 3] }
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 3] {
+        let offset = (y as usize * self.width as usize + x as usize) * 3;
+        [self.pixels[offset], self.pixels[offset + 1], self.pixels[offset + 2]]
+    }
+
+    pub fn set_pixel(&mut self, x: u32, y: u32, rgb: [u8; 3]) {
+        let offset = (y as usize * self.width as usize + x as usize) * 3;
+        self.pixels[offset..offset + 3].copy_from_slice(&rgb);
+    }
+
+    /// Encode as a PNG. Image data is stored without compression, which
+    /// keeps the encoder small at the cost of file size.
+    pub fn to_png(&self) -> Vec<u8> {
+        let row_bytes = self.width as usize * 3;
+        let mut raw = Vec::with_capacity((row_bytes + 1) * self.height as usize);
+        for row in self.pixels.chunks_exact(row_bytes.max(1)).take(self.height as usize) {
+            // Filter type None
+            raw.push(0);
+            raw.extend_from_slice(row);
+        }
+
+        let mut png = Vec::with_capacity(raw.len() + raw.len() / PNG_STORED_BLOCK * 5 + 64);
+        png.extend_from_slice(PNG_SIGNATURE);
+
+        let mut header = Vec::with_capacity(13);
+        header.extend_from_slice(&self.width.to_be_bytes());
+        header.extend_from_slice(&self.height.to_be_bytes());
+        // 8 bits per channel, truecolour, deflate, adaptive filters, no interlace
+        header.extend_from_slice(&[8, 2, 0, 0, 0]);
+        png_chunk(&mut png, b"IHDR", &header);
+        png_chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+        png_chunk(&mut png, b"IEND", &[]);
+        png
+    }
+}
+
+const PNG_SIGNATURE: &[u8; 8] = b"\x89PNG\r\n\x1a\n";
+/// Largest stored deflate block
+const PNG_STORED_BLOCK: usize = 0xFFFF;
+
+fn png_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// A zlib stream of stored deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut stream = Vec::with_capacity(data.len() + data.len() / PNG_STORED_BLOCK * 5 + 11);
+    // Deflate with a 32K window, no preset dictionary
+    stream.extend_from_slice(&[0x78, 0x01]);
+    let mut blocks = data.chunks(PNG_STORED_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        stream.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        stream.push(blocks.peek().is_none() as u8);
+        stream.extend_from_slice(&(block.len() as u16).to_le_bytes());
+        stream.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+        stream.extend_from_slice(block);
+    }
+    stream.extend_from_slice(&adler32(data).to_be_bytes());
+    stream
+}
+
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut index = 0;
+    while index < 256 {
+        let mut crc = index as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { 0xEDB8_8320 ^ (crc >> 1) } else { crc >> 1 };
+            bit += 1;
+        }
+        table[index] = crc;
+        index += 1;
+    }
+    table
+};
+
+/// CRC-32 (IEEE 802.3), as used by PNG and zlib
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, &byte| CRC32_TABLE[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8))
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    const MODULUS: u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+    // Sums stay below 2^32 for this many bytes between reductions
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= MODULUS;
+        b %= MODULUS;
+    }
+    b << 16 | a
+}
+
+/// Protocol version the server offers
+const RFB_VERSION: &[u8; 12] = b"RFB 003.008\n";
+const RFB_SECURITY_NONE: u8 = 1;
+
+// Client to server messages
+const MSG_SET_PIXEL_FORMAT: u8 = 0;
+const MSG_SET_ENCODINGS: u8 = 2;
+const MSG_UPDATE_REQUEST: u8 = 3;
+const MSG_KEY_EVENT: u8 = 4;
+const MSG_POINTER_EVENT: u8 = 5;
+const MSG_CLIENT_CUT_TEXT: u8 = 6;
+// Server to client messages
+const MSG_FRAMEBUFFER_UPDATE: u8 = 0;
+
+const ENCODING_RAW: i32 = 0;
+/// Pseudo-encoding announcing a framebuffer size change
+const ENCODING_DESKTOP_SIZE: i32 = -223;
+/// Longest cut text accepted from a client
+const MAX_CUT_TEXT: usize = 1 << 20;
+
+/// How a client wants pixels encoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RfbPixelFormat {
+    pub bits_per_pixel: u8,
+    pub depth: u8,
+    pub big_endian: bool,
+    pub true_colour: bool,
+    pub red_max: u16,
+    pub green_max: u16,
+    pub blue_max: u16,
+    pub red_shift: u8,
+    pub green_shift: u8,
+    pub blue_shift: u8,
+}
+
+impl RfbPixelFormat {
+    /// 32-bit little-endian XRGB, what the server starts with
+    pub fn xrgb8888() -> Self {
+        RfbPixelFormat {
+            bits_per_pixel: 32,
+            depth: 24,
+            big_endian: false,
+            true_colour: true,
+            red_max: 255,
+            green_max: 255,
+            blue_max: 255,
+            red_shift: 16,
+            green_shift: 8,
+            blue_shift: 0,
+        }
+    }
+
+    fn encode(&self) -> [u8; 16] {
+        let mut bytes = [0u8; 16];
+        bytes[0] = self.bits_per_pixel;
+        bytes[1] = self.depth;
+        bytes[2] = self.big_endian as u8;
+        bytes[3] = self.true_colour as u8;
+        bytes[4..6].copy_from_slice(&self.red_max.to_be_bytes());
+        bytes[6..8].copy_from_slice(&self.green_max.to_be_bytes());
+        bytes[8..10].copy_from_slice(&self.blue_max.to_be_bytes());
+        bytes[10] = self.red_shift;
+        bytes[11] = self.green_shift;
+        bytes[12] = self.blue_shift;
+        bytes
+    }
+
+    fn decode(bytes: &[u8]) -> Self {
+        RfbPixelFormat {
+            bits_per_pixel: bytes[0],
+            depth: bytes[1],
+            big_endian: bytes[2] != 0,
+            true_colour: bytes[3] != 0,
+            red_max: u16::from_be_bytes([bytes[4], bytes[5]]),
+            green_max: u16::from_be_bytes([bytes[6], bytes[7]]),
+            blue_max: u16::from_be_bytes([bytes[8], bytes[9]]),
+            red_shift: bytes[10],
+            green_shift: bytes[11],
+            blue_shift: bytes[12],
+        }
+    }
+
+    fn write_pixel(&self, out: &mut Vec<u8>, rgb: [u8; 3]) {
+        let scale = |value: u8, max: u16| (value as u32 * max as u32 + 127) / 255;
+        let value = scale(rgb[0], self.red_max) << self.red_shift
+            | scale(rgb[1], self.green_max) << self.green_shift
+            | scale(rgb[2], self.blue_max) << self.blue_shift;
+        let bytes = (self.bits_per_pixel / 8) as usize;
+        if self.big_endian {
+            out.extend_from_slice(&value.to_be_bytes()[4 - bytes..]);
+        } else {
+            out.extend_from_slice(&value.to_le_bytes()[..bytes]);
+        }
+    }
+}
+
+/// Input from a client, for the host to inject into the guest
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RfbInput {
+    /// An X11 keysym pressed or released
+    Key { keysym: u32, down: bool },
+    /// Pointer position and button mask
+    Pointer { x: u16, y: u16, buttons: u8 },
+    /// Clipboard text, in Latin-1
+    CutText(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RfbPhase {
+    Version,
+    Security,
+    ClientInit,
+    Normal,
+    Closed,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct UpdateRequest {
+    incremental: bool,
+    x: u16,
+    y: u16,
+    width: u16,
+    height: u16,
+}
+
+/// RFB server for one client connection
+#[derive(Debug, Clone)]
+pub struct RfbServer {
+    name: String,
+    phase: RfbPhase,
+    /// Minor protocol version the client chose
+    minor_version: u8,
+    input: Vec<u8>,
+    output: Vec<u8>,
+    pixel_format: RfbPixelFormat,
+    desktop_size: bool,
+    request: Option<UpdateRequest>,
+    /// Frame the client last saw all of
+    shown: Option<Frame>,
+    /// Latest frame presented
+    current: Frame,
+    events: Vec<RfbInput>,
+}
+
+impl RfbServer {
+    /// A server for a new connection; the version greeting is queued
+    pub fn new(name: &str, width: u32, height: u32) -> Self {
+        RfbServer {
+            name: String::from(name),
+            phase: RfbPhase::Version,
+            minor_version: 8,
+            input: Vec::new(),
+            output: RFB_VERSION.to_vec(),
+            pixel_format: RfbPixelFormat::xrgb8888(),
+            desktop_size: false,
+            request: None,
+            shown: None,
+            current: Frame::new(width, height),
+            events: Vec::new(),
+        }
+    }
+
+    /// Whether the connection failed or the client misbehaved
+    pub fn is_closed(&self) -> bool {
+        self.phase == RfbPhase::Closed
+    }
+
+    /// Bytes to send to the client
+    pub fn take_output(&mut self) -> Vec<u8> {
+        core::mem::take(&mut self.output)
+    }
+
+    /// Input events received since the last call
+    pub fn take_input(&mut self) -> Vec<RfbInput> {
+        core::mem::take(&mut self.events)
+    }
+
+    /// Bytes received from the client; they may split or join messages
+    pub fn receive(&mut self, bytes: &[u8]) -> Result<(), HypervisorError> {
+        if self.phase == RfbPhase::Closed {
+            return Err(IoError::new("rfb", IoErrorKind::Closed).into());
+        }
+        self.input.extend_from_slice(bytes);
+        loop {
+            let used = match self.process() {
+                Ok(Some(used)) => used,
+                Ok(None) => break,
+                Err(e) => {
+                    warn!("RFB: closing connection: {}", e);
+                    self.phase = RfbPhase::Closed;
+                    return Err(e);
+                }
+            };
+            self.input.drain(..used);
+        }
+        self.send_update();
+        Ok(())
+    }
+
+    /// The display's latest frame; sent when the client asks for it
+    pub fn present(&mut self, frame: &Frame) {
+        if self.current != *frame {
+            self.current = frame.clone();
+        }
+        self.send_update();
+    }
+
+    /// Handle the message at the start of the input; None if incomplete
+    fn process(&mut self) -> Result<Option<usize>, HypervisorError> {
+        let input = &self.input;
+        match self.phase {
+            RfbPhase::Version => {
+                if input.len() < RFB_VERSION.len() {
+                    return Ok(None);
+                }
+                self.minor_version = match &input[..12] {
+                    b"RFB 003.003\n" => 3,
+                    b"RFB 003.007\n" => 7,
+                    b"RFB 003.008\n" => 8,
+                    other if other.starts_with(b"RFB 003.") => 3,
+                    _ => return Err(protocol_error("bad version greeting")),
+                };
+                if self.minor_version == 3 {
+                    // The server picks the security type
+                    self.output.extend_from_slice(&(RFB_SECURITY_NONE as u32).to_be_bytes());
+                    self.phase = RfbPhase::ClientInit;
+                } else {
+                    self.output.extend_from_slice(&[1, RFB_SECURITY_NONE]);
+                    self.phase = RfbPhase::Security;
+                }
+                Ok(Some(12))
+            }
+            RfbPhase::Security => {
+                let choice = match input.first() {
+                    Some(&choice) => choice,
+                    None => return Ok(None),
+                };
+                if choice != RFB_SECURITY_NONE {
+                    return Err(protocol_error("unsupported security type"));
+                }
+                if self.minor_version >= 8 {
+                    self.output.extend_from_slice(&0u32.to_be_bytes());
+                }
+                self.phase = RfbPhase::ClientInit;
+                Ok(Some(1))
+            }
+            RfbPhase::ClientInit => {
+                if input.is_empty() {
+                    return Ok(None);
+                }
+                // The shared flag does not matter with one client per server
+                self.send_server_init();
+                self.phase = RfbPhase::Normal;
+                Ok(Some(1))
+            }
+            RfbPhase::Normal => self.process_message(),
+            RfbPhase::Closed => Ok(None),
+        }
+    }
+
+    fn process_message(&mut self) -> Result<Option<usize>, HypervisorError> {
+        let input = &self.input;
+        let kind = match input.first() {
+            Some(&kind) => kind,
+            None => return Ok(None),
+        };
+        let length = match kind {
+            MSG_SET_PIXEL_FORMAT => 20,
+            MSG_SET_ENCODINGS if input.len() >= 4 => 4 + 4 * u16::from_be_bytes([input[2], input[3]]) as usize,
+            MSG_UPDATE_REQUEST => 10,
+            MSG_KEY_EVENT => 8,
+            MSG_POINTER_EVENT => 6,
+            MSG_CLIENT_CUT_TEXT if input.len() >= 8 => {
+                let text = u32::from_be_bytes([input[4], input[5], input[6], input[7]]) as usize;
+                if text > MAX_CUT_TEXT {
+                    return Err(protocol_error("cut text too long"));
+                }
+                8 + text
+            }
+            MSG_SET_ENCODINGS | MSG_CLIENT_CUT_TEXT => return Ok(None),
+            other => return Err(protocol_error(&format!("unknown message type {}", other))),
+        };
+        if input.len() < length {
+            return Ok(None);
+        }
+        let message = &input[..length];
+
+        match kind {
+            MSG_SET_PIXEL_FORMAT => {
+                let format = RfbPixelFormat::decode(&message[4..20]);
+                let shifts_fit = [format.red_shift, format.green_shift, format.blue_shift]
+                    .iter()
+                    .all(|&shift| shift < format.bits_per_pixel);
+                if !format.true_colour || !matches!(format.bits_per_pixel, 8 | 16 | 32) || !shifts_fit {
+                    return Err(protocol_error("only true colour pixel formats are supported"));
+                }
+                self.pixel_format = format;
+                // Everything the client has is in the old format
+                self.shown = None;
+            }
+            MSG_SET_ENCODINGS => {
+                self.desktop_size = message[4..]
+                    .chunks_exact(4)
+                    .any(|encoding| i32::from_be_bytes([encoding[0], encoding[1], encoding[2], encoding[3]]) == ENCODING_DESKTOP_SIZE);
+            }
+            MSG_UPDATE_REQUEST => {
+                self.request = Some(UpdateRequest {
+                    incremental: message[1] != 0,
+                    x: u16::from_be_bytes([message[2], message[3]]),
+                    y: u16::from_be_bytes([message[4], message[5]]),
+                    width: u16::from_be_bytes([message[6], message[7]]),
+                    height: u16::from_be_bytes([message[8], message[9]]),
+                });
+            }
+            MSG_KEY_EVENT => self.events.push(RfbInput::Key {
+                keysym: u32::from_be_bytes([message[4], message[5], message[6], message[7]]),
+                down: message[1] != 0,
+            }),
+            MSG_POINTER_EVENT => self.events.push(RfbInput::Pointer {
+                x: u16::from_be_bytes([message[2], message[3]]),
+                y: u16::from_be_bytes([message[4], message[5]]),
+                buttons: message[1],
+            }),
+            _ => self.events.push(RfbInput::CutText(message[8..].iter().map(|&byte| byte as char).collect())),
+        }
+        Ok(Some(length))
+    }
+
+    fn send_server_init(&mut self) {
+        self.output.extend_from_slice(&(self.current.width as u16).to_be_bytes());
+        self.output.extend_from_slice(&(self.current.height as u16).to_be_bytes());
+        self.output.extend_from_slice(&self.pixel_format.encode());
+        self.output.extend_from_slice(&(self.name.len() as u32).to_be_bytes());
+        self.output.extend_from_slice(self.name.as_bytes());
+        self.shown = Some(Frame::new(self.current.width, self.current.height));
+    }
+
+    /// Answer an outstanding update request if there is something to send
+    fn send_update(&mut self) {
+        let request = match self.request {
+            Some(request) if self.phase == RfbPhase::Normal => request,
+            _ => return,
+        };
+        let (width, height) = (self.current.width, self.current.height);
+
+        let resized = self.shown.as_ref().map_or(false, |shown| shown.width != width || shown.height != height);
+        if resized && self.desktop_size {
+            self.output.extend_from_slice(&[MSG_FRAMEBUFFER_UPDATE, 0]);
+            self.output.extend_from_slice(&2u16.to_be_bytes());
+            push_rect_header(&mut self.output, 0, 0, width as u16, height as u16, ENCODING_DESKTOP_SIZE);
+            push_rect_header(&mut self.output, 0, 0, width as u16, height as u16, ENCODING_RAW);
+            self.push_pixels(0, 0, width, height);
+            self.shown = Some(self.current.clone());
+            self.request = None;
+            return;
+        }
+
+        // Clip to the request and to what the client thinks the size is
+        let (shown_width, shown_height) = self.shown.as_ref().map_or((width, height), |shown| (shown.width, shown.height));
+        let right = (request.x as u32 + request.width as u32).min(width).min(shown_width);
+        let bottom = (request.y as u32 + request.height as u32).min(height).min(shown_height);
+        let (left, top) = (request.x as u32, request.y as u32);
+        if left >= right || top >= bottom {
+            return;
+        }
+
+        let dirty = match (&self.shown, request.incremental && !resized) {
+            (Some(shown), true) => changed_area(shown, &self.current, left, top, right, bottom),
+            _ => Some((left, top, right, bottom)),
+        };
+        let (x0, y0, x1, y1) = match dirty {
+            Some(area) => area,
+            None => return,
+        };
+
+        self.output.extend_from_slice(&[MSG_FRAMEBUFFER_UPDATE, 0]);
+        self.output.extend_from_slice(&1u16.to_be_bytes());
+        push_rect_header(&mut self.output, x0 as u16, y0 as u16, (x1 - x0) as u16, (y1 - y0) as u16, ENCODING_RAW);
+        self.push_pixels(x0, y0, x1 - x0, y1 - y0);
+
+        if resized {
+            // A client without DesktopSize keeps its old size
+            self.shown = None;
+        } else if let Some(shown) = self.shown.as_mut() {
+            for y in y0..y1 {
+                let start = (y as usize * width as usize + x0 as usize) * 3;
+                let end = start + (x1 - x0) as usize * 3;
+                shown.pixels[start..end].copy_from_slice(&self.current.pixels[start..end]);
+            }
+        }
+        self.request = None;
+    }
+
+    fn push_pixels(&mut self, x: u32, y: u32, width: u32, height: u32) {
+        let bytes_per_pixel = (self.pixel_format.bits_per_pixel / 8) as usize;
+        self.output.reserve(width as usize * height as usize * bytes_per_pixel);
+        for row in y..y + height {
+            for column in x..x + width {
+                self.pixel_format.write_pixel(&mut self.output, self.current.pixel(column, row));
+            }
+        }
+    }
+}
+
+fn push_rect_header(out: &mut Vec<u8>, x: u16, y: u16, width: u16, height: u16, encoding: i32) {
+    out.extend_from_slice(&x.to_be_bytes());
+    out.extend_from_slice(&y.to_be_bytes());
+    out.extend_from_slice(&width.to_be_bytes());
+    out.extend_from_slice(&height.to_be_bytes());
+    out.extend_from_slice(&encoding.to_be_bytes());
+}
+
+/// Bounding box of the pixels that differ within an area of equally
+/// sized frames
+fn changed_area(old: &Frame, new: &Frame, left: u32, top: u32, right: u32, bottom: u32) -> Option<(u32, u32, u32, u32)> {
+    let mut area: Option<(u32, u32, u32, u32)> = None;
+    for y in top..bottom {
+        let row = (y as usize * new.width as usize) * 3;
+        let span = row + left as usize * 3..row + right as usize * 3;
+        if old.pixels[span.clone()] == new.pixels[span.clone()] {
+            continue;
+        }
+        let first = (left..right).find(|&x| old.pixel(x, y) != new.pixel(x, y)).unwrap_or(left);
+        let last = (left..right).rev().find(|&x| old.pixel(x, y) != new.pixel(x, y)).unwrap_or(right - 1);
+        area = Some(match area {
+            Some((x0, y0, x1, _)) => (x0.min(first), y0, x1.max(last + 1), y + 1),
+            None => (first, y, last + 1, y + 1),
+        });
+    }
+    area
+}
+
+fn protocol_error(what: &str) -> HypervisorError {
+    warn!("RFB protocol error: {}", what);
+    IoError::new("rfb", IoErrorKind::Protocol).into()
+}
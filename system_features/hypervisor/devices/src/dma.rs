@@ -0,0 +1,462 @@
+//! DMA Protection
+//!
+//! An IOMMU for device models. Instead of the raw `GuestMemoryAccess` of
+//! the VM, a device model gets a `DmaContext` that checks every access
+//! against the guest memory map and the VM's DMA policy before passing it
+//! on. Accesses outside guest RAM, to read-only or protected ranges, or
+//! outside the windows a policy grants a device are refused and recorded as
+//! security events.
+//!
+//! Scatter-gather lists are mapped into a contiguous bounce buffer: the
+//! guest data is copied in when the mapping is made or synced for the
+//! device, and the buffer is copied back when it is synced for the CPU or
+//! unmapped. Every copy is checked again, so a policy change also applies
+//! to mappings made before it.
+
+use crate::{HypervisorError, ConfigError, ConfigErrorReason, IoError, IoErrorKind, VmId};
+use super::GuestMemoryAccess;
+
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+/// Security events kept before the oldest are dropped
+pub const MAX_DMA_VIOLATIONS: usize = 256;
+/// Default limit of a single access or mapping
+pub const DEFAULT_MAX_DMA_TRANSFER: u64 = 16 * 1024 * 1024;
+
+/// What backs a range of guest-physical addresses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GuestRegionKind {
+    Ram,
+    /// Firmware and option ROMs
+    Rom,
+    /// Device registers; peer-to-peer DMA is not emulated
+    Mmio,
+    Reserved,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GuestRegion {
+    pub start: u64,
+    pub size: u64,
+    pub kind: GuestRegionKind,
+}
+
+impl GuestRegion {
+    fn end(&self) -> u64 {
+        self.start + self.size
+    }
+}
+
+/// Guest-physical address layout of a VM
+#[derive(Debug, Clone, Default)]
+pub struct GuestMemoryMap {
+    /// Sorted by start, not overlapping
+    regions: Vec<GuestRegion>,
+}
+
+impl GuestMemoryMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, start: u64, size: u64, kind: GuestRegionKind) -> Result<(), HypervisorError> {
+        if size == 0 || start.checked_add(size).is_none() {
+            return Err(ConfigError::new("dma.region", ConfigErrorReason::OutOfRange).value(start).into());
+        }
+        let index = self.regions.partition_point(|region| region.start < start);
+        let overlaps_previous = index > 0 && self.regions[index - 1].end() > start;
+        let overlaps_next = self.regions.get(index).is_some_and(|next| next.start < start + size);
+        if overlaps_previous || overlaps_next {
+            return Err(ConfigError::new("dma.region", ConfigErrorReason::AlreadyExists).value(start).into());
+        }
+        self.regions.insert(index, GuestRegion { start, size, kind });
+        Ok(())
+    }
+
+    /// Region containing `gpa`
+    pub fn region_at(&self, gpa: u64) -> Option<&GuestRegion> {
+        let index = self.regions.partition_point(|region| region.start <= gpa);
+        self.regions[..index].last().filter(|region| gpa < region.end())
+    }
+
+    pub fn regions(&self) -> &[GuestRegion] {
+        &self.regions
+    }
+}
+
+/// Per-VM rules on which guest memory devices may reach
+#[derive(Debug, Clone)]
+pub struct DmaPolicy {
+    /// (start, size) ranges of guest memory each device is limited to
+    pub windows: BTreeMap<String, Vec<(u64, u64)>>,
+    /// Devices without windows get no DMA access at all instead of all
+    /// guest RAM
+    pub isolate_devices: bool,
+    /// (start, size) ranges no device may touch
+    pub protected: Vec<(u64, u64)>,
+    /// Allow reads, e.g. by a boot device, from ROM
+    pub allow_rom_reads: bool,
+    pub max_transfer: u64,
+    /// Refuse violating accesses; otherwise they are only recorded
+    pub enforce: bool,
+}
+
+impl Default for DmaPolicy {
+    fn default() -> Self {
+        DmaPolicy {
+            windows: BTreeMap::new(),
+            isolate_devices: false,
+            protected: Vec::new(),
+            allow_rom_reads: true,
+            max_transfer: DEFAULT_MAX_DMA_TRANSFER,
+            enforce: true,
+        }
+    }
+}
+
+/// Why a DMA access was refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DmaViolationReason {
+    /// Not backed by anything in the memory map
+    Unmapped,
+    Mmio,
+    Reserved,
+    /// Write to ROM, or read with `allow_rom_reads` off
+    ReadOnly,
+    Protected,
+    OutsideWindow,
+    TooLarge,
+}
+
+/// A refused DMA access, as a security event
+#[derive(Debug, Clone, PartialEq)]
+pub struct DmaViolation {
+    pub timestamp_ns: u64,
+    pub vm_id: VmId,
+    pub device_id: String,
+    pub gpa: u64,
+    pub len: u64,
+    pub write: bool,
+    pub reason: DmaViolationReason,
+    /// Whether the access was refused or only recorded
+    pub blocked: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DmaStats {
+    pub reads: u64,
+    pub writes: u64,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    pub mappings: u64,
+    pub bounced_bytes: u64,
+    pub violations: u64,
+    /// Security events dropped from the full log
+    pub violations_dropped: u64,
+}
+
+/// DMA checks of one VM, shared by the contexts of its devices
+pub struct Iommu {
+    vm_id: VmId,
+    /// Monotonic nanoseconds
+    clock: fn() -> u64,
+    map: GuestMemoryMap,
+    policy: DmaPolicy,
+    violations: VecDeque<DmaViolation>,
+    stats: DmaStats,
+}
+
+impl Iommu {
+    pub fn new(vm_id: VmId, map: GuestMemoryMap, policy: DmaPolicy, clock: fn() -> u64) -> Self {
+        Iommu { vm_id, clock, map, policy, violations: VecDeque::new(), stats: DmaStats::default() }
+    }
+
+    pub fn policy(&self) -> &DmaPolicy {
+        &self.policy
+    }
+
+    pub fn set_policy(&mut self, policy: DmaPolicy) {
+        self.policy = policy;
+    }
+
+    pub fn memory_map(&self) -> &GuestMemoryMap {
+        &self.map
+    }
+
+    pub fn set_memory_map(&mut self, map: GuestMemoryMap) {
+        self.map = map;
+    }
+
+    pub fn stats(&self) -> DmaStats {
+        self.stats
+    }
+
+    /// Recorded security events, oldest first
+    pub fn violations(&self) -> impl Iterator<Item = &DmaViolation> {
+        self.violations.iter()
+    }
+
+    pub fn take_violations(&mut self) -> Vec<DmaViolation> {
+        self.violations.drain(..).collect()
+    }
+
+    /// Check an access of `len` bytes at `gpa` by a device, recording a
+    /// violation if it breaks the map or the policy
+    pub fn check(&mut self, device_id: &str, gpa: u64, len: u64, write: bool) -> Result<(), HypervisorError> {
+        let Some(reason) = self.violation(device_id, gpa, len, write) else {
+            return Ok(());
+        };
+
+        let blocked = self.policy.enforce;
+        warn!("DMA {} of {} bytes at {:#x} by {} in VM {}: {:?}{}",
+              if write { "write" } else { "read" }, len, gpa, device_id, self.vm_id.0, reason,
+              if blocked { "" } else { " (audit only)" });
+        if self.violations.len() == MAX_DMA_VIOLATIONS {
+            self.violations.pop_front();
+            self.stats.violations_dropped += 1;
+        }
+        self.violations.push_back(DmaViolation {
+            timestamp_ns: (self.clock)(),
+            vm_id: self.vm_id,
+            device_id: String::from(device_id),
+            gpa,
+            len,
+            write,
+            reason,
+            blocked,
+        });
+        self.stats.violations += 1;
+
+        if blocked {
+            Err(IoError::new("dma", IoErrorKind::InvalidAccess).at(gpa).into())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn violation(&self, device_id: &str, gpa: u64, len: u64, write: bool) -> Option<DmaViolationReason> {
+        if len > self.policy.max_transfer {
+            return Some(DmaViolationReason::TooLarge);
+        }
+        if len == 0 {
+            return None;
+        }
+        let Some(end) = gpa.checked_add(len) else {
+            return Some(DmaViolationReason::Unmapped);
+        };
+
+        // The access may span adjacent regions; every one must allow it
+        let mut cursor = gpa;
+        while cursor < end {
+            let Some(region) = self.map.region_at(cursor) else {
+                return Some(DmaViolationReason::Unmapped);
+            };
+            match region.kind {
+                GuestRegionKind::Ram => {}
+                GuestRegionKind::Rom if !write && self.policy.allow_rom_reads => {}
+                GuestRegionKind::Rom => return Some(DmaViolationReason::ReadOnly),
+                GuestRegionKind::Mmio => return Some(DmaViolationReason::Mmio),
+                GuestRegionKind::Reserved => return Some(DmaViolationReason::Reserved),
+            }
+            cursor = region.end();
+        }
+
+        let overlaps = |&(start, size): &(u64, u64)| gpa < start.saturating_add(size) && start < end;
+        if self.policy.protected.iter().any(overlaps) {
+            return Some(DmaViolationReason::Protected);
+        }
+
+        let within = |&(start, size): &(u64, u64)| start <= gpa && end <= start.saturating_add(size);
+        match self.policy.windows.get(device_id) {
+            Some(windows) if !windows.iter().any(within) => Some(DmaViolationReason::OutsideWindow),
+            None if self.policy.isolate_devices => Some(DmaViolationReason::OutsideWindow),
+            _ => None,
+        }
+    }
+
+    fn account(&mut self, len: usize, write: bool) {
+        if write {
+            self.stats.writes += 1;
+            self.stats.bytes_written += len as u64;
+        } else {
+            self.stats.reads += 1;
+            self.stats.bytes_read += len as u64;
+        }
+    }
+}
+
+/// Which way data of a mapping moves
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DmaDirection {
+    /// The device reads guest memory
+    ToDevice,
+    /// The device writes guest memory
+    FromDevice,
+    Bidirectional,
+}
+
+impl DmaDirection {
+    fn reads_guest(self) -> bool {
+        self != DmaDirection::FromDevice
+    }
+
+    fn writes_guest(self) -> bool {
+        self != DmaDirection::ToDevice
+    }
+}
+
+/// One element of a scatter-gather list
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DmaSegment {
+    pub gpa: u64,
+    pub len: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DmaHandle(pub u32);
+
+struct SgMapping {
+    segments: Vec<DmaSegment>,
+    direction: DmaDirection,
+    bounce: Vec<u8>,
+}
+
+/// Guest memory as seen by one device, through the VM's IOMMU
+pub struct DmaContext {
+    device_id: String,
+    iommu: Arc<Mutex<Iommu>>,
+    memory: Box<dyn GuestMemoryAccess + Send>,
+    mappings: BTreeMap<DmaHandle, SgMapping>,
+    next_handle: u32,
+}
+
+impl DmaContext {
+    pub fn new(device_id: &str, iommu: Arc<Mutex<Iommu>>, memory: Box<dyn GuestMemoryAccess + Send>) -> Self {
+        DmaContext {
+            device_id: String::from(device_id),
+            iommu,
+            memory,
+            mappings: BTreeMap::new(),
+            next_handle: 1,
+        }
+    }
+
+    pub fn device_id(&self) -> &str {
+        &self.device_id
+    }
+
+    /// Map a scatter-gather list into one contiguous bounce buffer
+    pub fn map_sg(&mut self, segments: &[DmaSegment], direction: DmaDirection) -> Result<DmaHandle, HypervisorError> {
+        let total = segments.iter().try_fold(0u64, |total, segment| total.checked_add(segment.len))
+            .ok_or(HypervisorError::InvalidParameter)?;
+        {
+            let mut iommu = self.iommu.lock();
+            // Refused even when only auditing, the bounce buffer would be
+            // too large
+            if total > iommu.policy.max_transfer {
+                let gpa = segments.first().map_or(0, |segment| segment.gpa);
+                iommu.check(&self.device_id, gpa, total, direction.writes_guest())?;
+                return Err(HypervisorError::InvalidParameter);
+            }
+            for segment in segments {
+                if direction.reads_guest() {
+                    iommu.check(&self.device_id, segment.gpa, segment.len, false)?;
+                }
+                if direction.writes_guest() {
+                    iommu.check(&self.device_id, segment.gpa, segment.len, true)?;
+                }
+            }
+            iommu.stats.mappings += 1;
+        }
+
+        let handle = DmaHandle(self.next_handle);
+        self.next_handle = self.next_handle.wrapping_add(1).max(1);
+        self.mappings.insert(handle, SgMapping {
+            segments: segments.to_vec(),
+            direction,
+            bounce: alloc::vec![0; total as usize],
+        });
+        if direction.reads_guest() {
+            if let Err(e) = self.sync_for_device(handle) {
+                self.mappings.remove(&handle);
+                return Err(e);
+            }
+        }
+        Ok(handle)
+    }
+
+    /// Bounce buffer of a mapping, for the device model to read or fill
+    pub fn buffer(&mut self, handle: DmaHandle) -> Option<&mut [u8]> {
+        self.mappings.get_mut(&handle).map(|mapping| mapping.bounce.as_mut_slice())
+    }
+
+    /// Copy guest memory into the bounce buffer, after the guest changed it
+    pub fn sync_for_device(&mut self, handle: DmaHandle) -> Result<(), HypervisorError> {
+        let mapping = self.mappings.get_mut(&handle).ok_or(HypervisorError::InvalidParameter)?;
+        if !mapping.direction.reads_guest() {
+            return Ok(());
+        }
+        let mut iommu = self.iommu.lock();
+        let mut offset = 0;
+        for segment in &mapping.segments {
+            let len = segment.len as usize;
+            iommu.check(&self.device_id, segment.gpa, segment.len, false)?;
+            self.memory.read_guest(segment.gpa, &mut mapping.bounce[offset..offset + len])?;
+            iommu.account(len, false);
+            iommu.stats.bounced_bytes += len as u64;
+            offset += len;
+        }
+        Ok(())
+    }
+
+    /// Copy the bounce buffer back to guest memory, for the guest to see
+    /// what the device wrote
+    pub fn sync_for_cpu(&mut self, handle: DmaHandle) -> Result<(), HypervisorError> {
+        let mapping = self.mappings.get(&handle).ok_or(HypervisorError::InvalidParameter)?;
+        if !mapping.direction.writes_guest() {
+            return Ok(());
+        }
+        let mut iommu = self.iommu.lock();
+        let mut offset = 0;
+        for segment in &mapping.segments {
+            let len = segment.len as usize;
+            iommu.check(&self.device_id, segment.gpa, segment.len, true)?;
+            self.memory.write_guest(segment.gpa, &mapping.bounce[offset..offset + len])?;
+            iommu.account(len, true);
+            iommu.stats.bounced_bytes += len as u64;
+            offset += len;
+        }
+        Ok(())
+    }
+
+    /// Sync a mapping for the CPU and release it; the mapping is released
+    /// even if the sync is refused
+    pub fn unmap(&mut self, handle: DmaHandle) -> Result<(), HypervisorError> {
+        let result = self.sync_for_cpu(handle);
+        self.mappings.remove(&handle);
+        result
+    }
+}
+
+impl GuestMemoryAccess for DmaContext {
+    fn read_guest(&self, gpa: u64, buf: &mut [u8]) -> Result<(), HypervisorError> {
+        let mut iommu = self.iommu.lock();
+        iommu.check(&self.device_id, gpa, buf.len() as u64, false)?;
+        self.memory.read_guest(gpa, buf)?;
+        iommu.account(buf.len(), false);
+        Ok(())
+    }
+
+    fn write_guest(&mut self, gpa: u64, data: &[u8]) -> Result<(), HypervisorError> {
+        let mut iommu = self.iommu.lock();
+        iommu.check(&self.device_id, gpa, data.len() as u64, true)?;
+        self.memory.write_guest(gpa, data)?;
+        iommu.account(data.len(), true);
+        Ok(())
+    }
+}
@@ -0,0 +1,421 @@
+//! High Precision Event Timer
+//!
+//! A 100 MHz main counter derived from the host clock and three
+//! comparators. Timer 0 can run periodically; every timer can interrupt
+//! through an I/O APIC pin, through IRQ 0/8 in legacy replacement mode, or
+//! with an MSI through FSB delivery. Comparators are checked when the VMM
+//! polls, so an interrupt is late by at most the polling interval; the VMM
+//! arms a host timer for `next_deadline_ns` to bound that.
+
+use crate::HypervisorError;
+use crate::core::{acpi_header, finish_acpi_table};
+use super::{DeviceInterrupt, DeviceStateReader, DeviceStateSerialize, DeviceStateWriter, MsiMessage};
+
+use alloc::vec::Vec;
+
+/// Register block
+pub const HPET_BASE: u64 = 0xFED0_0000;
+pub const HPET_SIZE: u64 = 0x400;
+pub const HPET_TIMERS: usize = 3;
+/// Counter tick in nanoseconds (100 MHz)
+pub const HPET_TICK_NS: u64 = 10;
+
+// General registers
+const REG_CAPABILITIES: u64 = 0x000;
+const REG_CONFIG: u64 = 0x010;
+const REG_INTERRUPT_STATUS: u64 = 0x020;
+const REG_COUNTER: u64 = 0x0F0;
+const REG_TIMER_BASE: u64 = 0x100;
+const TIMER_STRIDE: u64 = 0x20;
+// Timer registers, from the timer's base
+const TIMER_CONFIG: u64 = 0x00;
+const TIMER_COMPARATOR: u64 = 0x08;
+const TIMER_FSB_ROUTE: u64 = 0x10;
+
+const CAP_REVISION: u64 = 0x01;
+const CAP_COUNT_64BIT: u64 = 1 << 13;
+const CAP_LEGACY_ROUTE: u64 = 1 << 15;
+const CAP_VENDOR: u64 = 0x8086 << 16;
+const CAP_PERIOD_FS: u64 = HPET_TICK_NS * 1_000_000;
+
+const CONFIG_ENABLE: u64 = 1 << 0;
+const CONFIG_LEGACY: u64 = 1 << 1;
+
+const TIMER_LEVEL: u64 = 1 << 1;
+const TIMER_ENABLE: u64 = 1 << 2;
+const TIMER_PERIODIC: u64 = 1 << 3;
+const TIMER_PERIODIC_CAP: u64 = 1 << 4;
+const TIMER_64BIT_CAP: u64 = 1 << 5;
+const TIMER_SET_VALUE: u64 = 1 << 6;
+const TIMER_32BIT_MODE: u64 = 1 << 8;
+const TIMER_ROUTE_SHIFT: u64 = 9;
+const TIMER_ROUTE_MASK: u64 = 0x1F << TIMER_ROUTE_SHIFT;
+const TIMER_FSB_ENABLE: u64 = 1 << 14;
+const TIMER_FSB_CAP: u64 = 1 << 15;
+/// I/O APIC pins 20 to 23 may be routed to
+const TIMER_ROUTE_CAP: u64 = 0x00F0_0000 << 32;
+const TIMER_WRITABLE: u64 = TIMER_LEVEL | TIMER_ENABLE | TIMER_PERIODIC | TIMER_SET_VALUE
+    | TIMER_32BIT_MODE | TIMER_ROUTE_MASK | TIMER_FSB_ENABLE;
+
+/// Pins used in legacy replacement mode: timer 0 takes IRQ 0, which the
+/// MADT overrides to pin 2, and timer 1 takes the RTC's IRQ 8
+const LEGACY_GSI: [u32; 2] = [2, 8];
+
+const ACPI_HPET_REVISION: u8 = 1;
+/// Minimum periodic tick the guest should program, in counter ticks
+const HPET_MIN_TICK: u16 = 100;
+
+#[derive(Debug, Clone, Copy, Default)]
+struct HpetTimer {
+    config: u64,
+    comparator: u64,
+    period: u64,
+    fsb_route: u64,
+    /// Whether a one-shot comparator has yet to fire
+    armed: bool,
+    /// Whether a level-triggered pin is asserted
+    asserted: bool,
+}
+
+/// HPET with `HPET_TIMERS` comparators
+#[derive(Debug, Clone)]
+pub struct Hpet {
+    config: u64,
+    interrupt_status: u64,
+    /// Counter value when it last started or was written
+    counter_base: u64,
+    /// Clock time the counter last started
+    started_ns: u64,
+    timers: [HpetTimer; HPET_TIMERS],
+    clock: fn() -> u64,
+    pending: Vec<DeviceInterrupt>,
+}
+
+impl Hpet {
+    /// A stopped HPET; `clock` returns monotonic nanoseconds
+    pub fn new(clock: fn() -> u64) -> Self {
+        let mut timers = [HpetTimer::default(); HPET_TIMERS];
+        for (index, timer) in timers.iter_mut().enumerate() {
+            timer.config = TIMER_64BIT_CAP | TIMER_FSB_CAP | TIMER_ROUTE_CAP;
+            if index == 0 {
+                timer.config |= TIMER_PERIODIC_CAP;
+            }
+            timer.comparator = u64::MAX;
+        }
+        Hpet {
+            config: 0,
+            interrupt_status: 0,
+            counter_base: 0,
+            started_ns: 0,
+            timers,
+            clock,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Whether timers 0 and 1 replace the PIT and RTC interrupts
+    pub fn legacy_replacement(&self) -> bool {
+        self.config & CONFIG_LEGACY != 0
+    }
+
+    pub fn counter(&self) -> u64 {
+        if self.config & CONFIG_ENABLE == 0 {
+            return self.counter_base;
+        }
+        let elapsed = (self.clock)().saturating_sub(self.started_ns);
+        self.counter_base.wrapping_add(elapsed / HPET_TICK_NS)
+    }
+
+    /// Register read of 4 or 8 bytes at an offset into the block
+    pub fn mmio_read(&mut self, offset: u64, size: usize) -> u64 {
+        let value = self.read_register(offset & !0x7);
+        match size {
+            8 => value,
+            _ if offset & 0x4 != 0 => value >> 32,
+            _ => value & 0xFFFF_FFFF,
+        }
+    }
+
+    /// Register write of 4 or 8 bytes at an offset into the block
+    pub fn mmio_write(&mut self, offset: u64, value: u64, size: usize) {
+        let register = offset & !0x7;
+        let value = match size {
+            8 => value,
+            _ => {
+                let current = self.read_register(register);
+                if offset & 0x4 != 0 {
+                    (current & 0xFFFF_FFFF) | (value & 0xFFFF_FFFF) << 32
+                } else {
+                    (current & !0xFFFF_FFFF) | (value & 0xFFFF_FFFF)
+                }
+            }
+        };
+        self.write_register(register, value, size == 4 && offset & 0x4 != 0);
+    }
+
+    /// Fire comparators the counter reached
+    pub fn poll(&mut self) {
+        if self.config & CONFIG_ENABLE == 0 {
+            return;
+        }
+        let counter = self.counter();
+        for index in 0..HPET_TIMERS {
+            let timer = self.timers[index];
+            if timer.config & TIMER_ENABLE == 0 || !timer.armed || !Self::reached(&timer, counter) {
+                continue;
+            }
+            if timer.config & TIMER_PERIODIC != 0 && timer.period != 0 {
+                let missed = counter.wrapping_sub(timer.comparator) / timer.period + 1;
+                self.timers[index].comparator = timer.comparator.wrapping_add(missed * timer.period);
+            } else {
+                self.timers[index].armed = false;
+            }
+            self.fire(index);
+        }
+    }
+
+    /// When the next enabled comparator is reached
+    pub fn next_deadline_ns(&self) -> Option<u64> {
+        if self.config & CONFIG_ENABLE == 0 {
+            return None;
+        }
+        let counter = self.counter();
+        let now = (self.clock)();
+        self.timers
+            .iter()
+            .filter(|timer| timer.config & TIMER_ENABLE != 0 && timer.armed)
+            .map(|timer| {
+                let ticks = if Self::reached(timer, counter) { 0 } else { Self::ticks_until(timer, counter) };
+                now.saturating_add(ticks.saturating_mul(HPET_TICK_NS))
+            })
+            .min()
+    }
+
+    pub fn take_interrupts(&mut self) -> Vec<DeviceInterrupt> {
+        core::mem::take(&mut self.pending)
+    }
+
+    /// ACPI HPET table describing the block
+    pub fn build_acpi_table(&self) -> Vec<u8> {
+        let mut table = acpi_header(b"HPET", ACPI_HPET_REVISION);
+        let block_id = (self.read_capabilities() & 0xFFFF_FFFF) as u32;
+        table.extend_from_slice(&block_id.to_le_bytes());
+        // Generic address: system memory, 64 bits wide
+        table.extend_from_slice(&[0, 64, 0, 0]);
+        table.extend_from_slice(&HPET_BASE.to_le_bytes());
+        table.push(0);
+        table.extend_from_slice(&HPET_MIN_TICK.to_le_bytes());
+        table.push(0);
+        finish_acpi_table(table)
+    }
+
+    fn read_register(&self, register: u64) -> u64 {
+        match register {
+            REG_CAPABILITIES => self.read_capabilities(),
+            REG_CONFIG => self.config,
+            REG_INTERRUPT_STATUS => self.interrupt_status,
+            REG_COUNTER => self.counter(),
+            _ => match Self::timer_register(register) {
+                Some((index, TIMER_CONFIG)) => self.timers[index].config,
+                Some((index, TIMER_COMPARATOR)) => self.timers[index].comparator,
+                Some((index, TIMER_FSB_ROUTE)) => self.timers[index].fsb_route,
+                _ => 0,
+            },
+        }
+    }
+
+    fn write_register(&mut self, register: u64, value: u64, high_half: bool) {
+        match register {
+            REG_CONFIG => {
+                let enabling = value & CONFIG_ENABLE != 0 && self.config & CONFIG_ENABLE == 0;
+                let disabling = value & CONFIG_ENABLE == 0 && self.config & CONFIG_ENABLE != 0;
+                if disabling {
+                    self.counter_base = self.counter();
+                }
+                if enabling {
+                    self.started_ns = (self.clock)();
+                }
+                self.config = value & (CONFIG_ENABLE | CONFIG_LEGACY);
+            }
+            REG_INTERRUPT_STATUS => {
+                // Write one to clear, which releases level-triggered pins
+                for index in 0..HPET_TIMERS {
+                    if value & (1 << index) != 0 && self.interrupt_status & (1 << index) != 0 {
+                        self.interrupt_status &= !(1 << index);
+                        self.release(index);
+                    }
+                }
+            }
+            REG_COUNTER => {
+                if self.config & CONFIG_ENABLE != 0 {
+                    warn!("HPET: main counter written while running");
+                    self.started_ns = (self.clock)();
+                }
+                self.counter_base = value;
+                self.rearm_all();
+            }
+            _ => match Self::timer_register(register) {
+                Some((index, TIMER_CONFIG)) => {
+                    let timer = &mut self.timers[index];
+                    let mut writable = TIMER_WRITABLE;
+                    if timer.config & TIMER_PERIODIC_CAP == 0 {
+                        writable &= !TIMER_PERIODIC;
+                    }
+                    let was_level = timer.config & TIMER_LEVEL != 0;
+                    timer.config = (timer.config & !writable) | (value & writable);
+                    if was_level && timer.config & TIMER_LEVEL == 0 {
+                        self.release(index);
+                    }
+                }
+                Some((index, TIMER_COMPARATOR)) => {
+                    let counter = self.counter();
+                    let timer = &mut self.timers[index];
+                    let value = if timer.config & TIMER_32BIT_MODE != 0 { value & 0xFFFF_FFFF } else { value };
+                    // In periodic mode the write sets the period, and also
+                    // the comparator when SET_VALUE is set
+                    if timer.config & TIMER_PERIODIC == 0 || timer.config & TIMER_SET_VALUE != 0 {
+                        timer.comparator = value;
+                    }
+                    if timer.config & TIMER_PERIODIC != 0 && !high_half {
+                        timer.period = value;
+                    }
+                    timer.config &= !TIMER_SET_VALUE;
+                    timer.armed = !Self::reached(timer, counter) || timer.config & TIMER_PERIODIC != 0;
+                }
+                Some((index, TIMER_FSB_ROUTE)) => self.timers[index].fsb_route = value,
+                _ => {}
+            },
+        }
+    }
+
+    fn read_capabilities(&self) -> u64 {
+        CAP_PERIOD_FS << 32
+            | CAP_VENDOR
+            | CAP_LEGACY_ROUTE
+            | CAP_COUNT_64BIT
+            | ((HPET_TIMERS as u64 - 1) << 8)
+            | CAP_REVISION
+    }
+
+    /// Signal timer `index`'s interrupt by its configured route
+    fn fire(&mut self, index: usize) {
+        let timer = self.timers[index];
+        if timer.config & TIMER_FSB_ENABLE != 0 {
+            self.pending.push(DeviceInterrupt::Msi(MsiMessage {
+                address: timer.fsb_route >> 32,
+                data: timer.fsb_route as u32,
+            }));
+            return;
+        }
+
+        let gsi = self.gsi(index);
+        if timer.config & TIMER_LEVEL != 0 {
+            self.interrupt_status |= 1 << index;
+            if !timer.asserted {
+                self.timers[index].asserted = true;
+                self.pending.push(DeviceInterrupt::GsiLevel { gsi, asserted: true });
+            }
+        } else {
+            self.pending.push(DeviceInterrupt::Gsi(gsi));
+        }
+    }
+
+    /// Deassert a level-triggered pin
+    fn release(&mut self, index: usize) {
+        if self.timers[index].asserted {
+            self.timers[index].asserted = false;
+            let gsi = self.gsi(index);
+            self.pending.push(DeviceInterrupt::GsiLevel { gsi, asserted: false });
+        }
+    }
+
+    fn gsi(&self, index: usize) -> u32 {
+        if self.legacy_replacement() && index < LEGACY_GSI.len() {
+            return LEGACY_GSI[index];
+        }
+        ((self.timers[index].config & TIMER_ROUTE_MASK) >> TIMER_ROUTE_SHIFT) as u32
+    }
+
+    fn rearm_all(&mut self) {
+        let counter = self.counter();
+        for timer in self.timers.iter_mut() {
+            timer.armed = !Self::reached(timer, counter) || timer.config & TIMER_PERIODIC != 0;
+        }
+    }
+
+    /// Whether the counter has reached the comparator; in 32-bit mode only
+    /// the low halves count
+    fn reached(timer: &HpetTimer, counter: u64) -> bool {
+        Self::ticks_until(timer, counter) == 0
+    }
+
+    fn ticks_until(timer: &HpetTimer, counter: u64) -> u64 {
+        if timer.config & TIMER_32BIT_MODE != 0 {
+            let ahead = (timer.comparator as u32).wrapping_sub(counter as u32);
+            // More than half the range ahead means it has passed
+            if ahead > u32::MAX / 2 { 0 } else { ahead as u64 }
+        } else {
+            timer.comparator.saturating_sub(counter)
+        }
+    }
+
+    fn timer_register(register: u64) -> Option<(usize, u64)> {
+        let relative = register.checked_sub(REG_TIMER_BASE)?;
+        let index = (relative / TIMER_STRIDE) as usize;
+        if index >= HPET_TIMERS {
+            return None;
+        }
+        Some((index, relative % TIMER_STRIDE))
+    }
+}
+
+// Saved state fields; timer N is saved under STATE_TIMER + N
+const STATE_CONFIG: u16 = 1;
+const STATE_INTERRUPT_STATUS: u16 = 2;
+const STATE_COUNTER: u16 = 3;
+const STATE_TIMER: u16 = 0x10;
+
+/// The counter is saved as its value, so it continues from there on the
+/// restoring side's clock
+impl DeviceStateSerialize for Hpet {
+    fn state_version(&self) -> u16 {
+        1
+    }
+
+    fn save_state(&self, writer: &mut DeviceStateWriter) {
+        writer.put_u64(STATE_CONFIG, self.config);
+        writer.put_u64(STATE_INTERRUPT_STATUS, self.interrupt_status);
+        writer.put_u64(STATE_COUNTER, self.counter());
+        for (index, timer) in self.timers.iter().enumerate() {
+            writer.put_u64s(STATE_TIMER + index as u16, &[
+                timer.config, timer.comparator, timer.period, timer.fsb_route,
+                timer.armed as u64, timer.asserted as u64,
+            ]);
+        }
+    }
+
+    fn restore_state(&mut self, reader: &DeviceStateReader) -> Result<(), HypervisorError> {
+        if let Some(config) = reader.u64(STATE_CONFIG) {
+            self.config = config & (CONFIG_ENABLE | CONFIG_LEGACY);
+        }
+        if let Some(status) = reader.u64(STATE_INTERRUPT_STATUS) {
+            self.interrupt_status = status;
+        }
+        if let Some(counter) = reader.u64(STATE_COUNTER) {
+            self.counter_base = counter;
+            self.started_ns = (self.clock)();
+        }
+        for index in 0..HPET_TIMERS {
+            if let Some(values) = reader.u64s(STATE_TIMER + index as u16).filter(|values| values.len() >= 6) {
+                let timer = &mut self.timers[index];
+                timer.config = (timer.config & !TIMER_WRITABLE) | (values[0] & TIMER_WRITABLE);
+                timer.comparator = values[1];
+                timer.period = values[2];
+                timer.fsb_route = values[3];
+                timer.armed = values[4] != 0;
+                timer.asserted = values[5] != 0;
+            }
+        }
+        Ok(())
+    }
+}
@@ -3,13 +3,39 @@
 //! Provides a framework for virtualizing devices in virtual machines,
 //! including educational VMs with simplified device models.
 
-use crate::{HypervisorError, VmId};
-use crate::core::VmExitReason;
+use crate::{HypervisorError, ConfigError, ConfigErrorReason, IoError, IoErrorKind, VmId};
+use crate::core::{ClockConfig, GraphicsCardType, GraphicsConfig, VmExitReason};
 
+use alloc::boxed::Box;
 use alloc::vec::Vec;
 use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
 use bitflags::bitflags;
-use spin::RwLock;
+use spin::{Mutex, RwLock};
+
+mod usb_passthrough;
+mod pci;
+mod device_state;
+mod rtc;
+mod hpet;
+mod display;
+mod vga;
+mod virtio;
+mod virtio_input;
+mod naming;
+mod dma;
+
+pub use usb_passthrough::*;
+pub use pci::*;
+pub use device_state::*;
+pub use rtc::*;
+pub use hpet::*;
+pub use display::*;
+pub use vga::*;
+pub use virtio::*;
+pub use virtio_input::*;
+pub use naming::*;
+pub use dma::*;
 
 /// Device types enumeration
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -63,6 +89,20 @@ pub enum DeviceState {
     Error,
 }
 
+impl DeviceState {
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(DeviceState::Uninitialized),
+            1 => Some(DeviceState::Initialized),
+            2 => Some(DeviceState::Ready),
+            3 => Some(DeviceState::Running),
+            4 => Some(DeviceState::Paused),
+            5 => Some(DeviceState::Error),
+            _ => None,
+        }
+    }
+}
+
 /// Device access permissions
 bitflags! {
     #[derive(Debug, Clone, Copy)]
@@ -85,6 +125,17 @@ pub struct InterruptInfo {
     pub active: bool,
 }
 
+/// Interrupt a device model raised, for the interrupt controller
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceInterrupt {
+    /// Edge on an I/O APIC pin
+    Gsi(u32),
+    /// Level change on an I/O APIC pin
+    GsiLevel { gsi: u32, asserted: bool },
+    /// Message signalled interrupt
+    Msi(MsiMessage),
+}
+
 /// Device register information
 #[derive(Debug, Clone)]
 pub struct DeviceRegister {
@@ -130,6 +181,90 @@ pub struct VirtualDevice {
     pub stats: DeviceStats,
 }
 
+// Saved state fields of a virtual device
+const STATE_DEVICE_STATE: u16 = 1;
+const STATE_ENABLED: u16 = 2;
+const STATE_ADDRESS: u16 = 3;
+const STATE_INTERRUPT_LINE: u16 = 4;
+const STATE_INTERRUPT_ACTIVE: u16 = 5;
+const STATE_MMIO_REGIONS: u16 = 6;
+const STATE_IO_PORTS: u16 = 7;
+const STATE_STATS: u16 = 8;
+
+impl DeviceStateSerialize for VirtualDevice {
+    fn state_version(&self) -> u16 {
+        1
+    }
+
+    fn save_state(&self, writer: &mut DeviceStateWriter) {
+        writer.put_u8(STATE_DEVICE_STATE, self.state as u8);
+        writer.put_bool(STATE_ENABLED, self.config.enabled);
+        writer.put_u32(STATE_ADDRESS, self.config.address);
+        if let Some(line) = self.config.interrupt_line {
+            writer.put_u8(STATE_INTERRUPT_LINE, line);
+        }
+        if let Some(interrupt) = &self.interrupt {
+            writer.put_bool(STATE_INTERRUPT_ACTIVE, interrupt.active);
+        }
+        let regions: Vec<u64> = self.mmio_regions.iter().flat_map(|region| [region.base_address, region.size]).collect();
+        writer.put_u64s(STATE_MMIO_REGIONS, &regions);
+        let ports: Vec<u64> = self.io_ports.iter().flat_map(|range| [range.base_port as u64, range.size as u64]).collect();
+        writer.put_u64s(STATE_IO_PORTS, &ports);
+        writer.put_u64s(STATE_STATS, &[
+            self.stats.read_count,
+            self.stats.write_count,
+            self.stats.interrupt_count,
+            self.stats.error_count,
+            self.stats.last_access_time,
+        ]);
+    }
+
+    fn restore_state(&mut self, reader: &DeviceStateReader) -> Result<(), HypervisorError> {
+        if let Some(value) = reader.u8(STATE_DEVICE_STATE) {
+            self.state = DeviceState::from_u8(value)
+                .ok_or_else(|| HypervisorError::from(ConfigError::new("device.state", ConfigErrorReason::Malformed).value(value as u64)))?;
+        }
+        if let Some(enabled) = reader.bool(STATE_ENABLED) {
+            self.config.enabled = enabled;
+        }
+        if let Some(address) = reader.u32(STATE_ADDRESS) {
+            self.config.address = address;
+        }
+        if let Some(line) = reader.u8(STATE_INTERRUPT_LINE) {
+            self.config.interrupt_line = Some(line);
+            if let Some(interrupt) = self.interrupt.as_mut() {
+                interrupt.interrupt_line = line;
+            }
+        }
+        if let (Some(active), Some(interrupt)) = (reader.bool(STATE_INTERRUPT_ACTIVE), self.interrupt.as_mut()) {
+            interrupt.active = active;
+        }
+        // Regions keep the access rights of the restoring side's model
+        if let Some(regions) = reader.u64s(STATE_MMIO_REGIONS) {
+            let access = self.mmio_regions.first().map_or(DeviceAccess::READ | DeviceAccess::WRITE, |region| region.access);
+            self.mmio_regions = regions.chunks_exact(2)
+                .map(|pair| MmioRegion { base_address: pair[0], size: pair[1], access })
+                .collect();
+        }
+        if let Some(ports) = reader.u64s(STATE_IO_PORTS) {
+            let access = self.io_ports.first().map_or(DeviceAccess::READ | DeviceAccess::WRITE, |range| range.access);
+            self.io_ports = ports.chunks_exact(2)
+                .map(|pair| IoPortRange { base_port: pair[0] as u16, size: pair[1] as u16, access })
+                .collect();
+        }
+        if let Some(stats) = reader.u64s(STATE_STATS).filter(|stats| stats.len() >= 5) {
+            self.stats = DeviceStats {
+                read_count: stats[0],
+                write_count: stats[1],
+                interrupt_count: stats[2],
+                error_count: stats[3],
+                last_access_time: stats[4],
+            };
+        }
+        Ok(())
+    }
+}
+
 /// Device configuration
 #[derive(Debug, Clone)]
 pub struct DeviceConfig {
@@ -171,6 +306,14 @@ pub struct DeviceStats {
     pub last_access_time: u64,
 }
 
+/// Record IDs of framework-owned state in a save blob
+const PCI_STATE_ID: &str = "pci";
+const USB_STATE_PREFIX: &str = "usb:";
+const RTC_STATE_ID: &str = "rtc";
+const HPET_STATE_ID: &str = "hpet";
+const VGA_STATE_ID: &str = "vga";
+const VIRTIO_INPUT_STATE_PREFIX: &str = "virtio-input:";
+
 /// Device framework manager
 pub struct DeviceFramework {
     /// VM ID this framework belongs to
@@ -179,8 +322,27 @@ pub struct DeviceFramework {
     pub devices: BTreeMap<String, Arc<RwLock<VirtualDevice>>>,
     /// Device count
     pub device_count: usize,
+    /// Device IDs and aliases
+    names: DeviceNamespace,
     /// Framework initialization time
     pub init_time: u64,
+    /// Passed-through USB devices, keyed by device ID
+    pub usb_passthrough: BTreeMap<String, UsbPassthrough>,
+    /// PCI host bridge the guest enumerates devices through
+    pub pci: PciHostBridge,
+    /// CMOS real-time clock, once clock devices are attached
+    pub rtc: Option<Rtc>,
+    /// High precision event timer, if the VM's clock config enables it
+    pub hpet: Option<Hpet>,
+    /// Interrupts raised by device models, awaiting delivery
+    pending_interrupts: Vec<DeviceInterrupt>,
+    /// Display adapter and the ID of the device backing its PCI function
+    pub vga: Option<Vga>,
+    vga_device_id: Option<String>,
+    /// Virtio keyboards and tablets, keyed by device ID
+    pub virtio_input: BTreeMap<String, VirtioPci<VirtioInput>>,
+    /// DMA checks of device models, once protection is enabled
+    iommu: Option<Arc<Mutex<Iommu>>>,
 }
 
 impl DeviceFramework {
@@ -190,21 +352,140 @@ impl DeviceFramework {
             vm_id,
             devices: BTreeMap::new(),
             device_count: 0,
+            names: DeviceNamespace::new(vm_id),
             init_time: 0, // Would use actual timestamp
+            usb_passthrough: BTreeMap::new(),
+            pci: PciHostBridge::new(PCI_ECAM_BASE),
+            rtc: None,
+            hpet: None,
+            pending_interrupts: Vec::new(),
+            vga: None,
+            vga_device_id: None,
+            virtio_input: BTreeMap::new(),
+            iommu: None,
         }
     }
     
-    /// Register a virtual device
-    pub fn register_device(&mut self, device: VirtualDevice) -> Result<String, HypervisorError> {
-        let device_id = format!("dev_{}_{}", device.device_type as u32, self.device_count);
+    /// Register a virtual device under the ID in `device.device_id`, or
+    /// under a generated one if that is empty
+    pub fn register_device(&mut self, mut device: VirtualDevice) -> Result<String, HypervisorError> {
+        let requested = Some(device.device_id.as_str()).filter(|device_id| !device_id.is_empty());
+        let device_id = self.names.allocate(device.device_type, requested)?;
+        device.device_id = device_id.clone();
         
+        info!("Registered device {} of type {:?}", device_id, device.device_type);
         self.devices.insert(device_id.clone(), Arc::new(RwLock::new(device)));
-        self.device_count += 1;
-        
-        info!("Registered device {} of type {:?}", device_id, self.devices[&device_id].read().device_type);
+        self.device_count = self.devices.len();
         Ok(device_id)
     }
     
+    /// Unregister a device and take its PCI function off the bus, e.g. on
+    /// hot-unplug. Passed-through USB devices are detached with
+    /// `detach_usb_passthrough`.
+    pub fn unregister_device(&mut self, name: &str) -> Result<(), HypervisorError> {
+        let device_id = self.names.resolve(name)
+            .map(String::from)
+            .ok_or_else(|| HypervisorError::from(IoError::new("device", IoErrorKind::NoDevice)))?;
+        if self.usb_passthrough.contains_key(&device_id)
+            || self.virtio_input.contains_key(&device_id)
+            || self.vga_device_id.as_deref() == Some(device_id.as_str()) {
+            return Err(ConfigError::new("device.id", ConfigErrorReason::Denied).vm(self.vm_id).into());
+        }
+        if let Some(address) = self.pci.address_of(&device_id) {
+            self.pci.remove_function(address)?;
+        }
+        self.remove_device(&device_id);
+        info!("Unregistered device {}", device_id);
+        Ok(())
+    }
+    
+    fn remove_device(&mut self, device_id: &str) {
+        self.devices.remove(device_id);
+        self.names.release(device_id);
+        self.device_count = self.devices.len();
+    }
+    
+    /// Give a device, named by ID or alias, another name
+    pub fn set_device_alias(&mut self, name: &str, alias: &str) -> Result<(), HypervisorError> {
+        self.names.set_alias(name, alias)
+    }
+    
+    /// Remove a device alias
+    pub fn remove_device_alias(&mut self, alias: &str) -> Result<(), HypervisorError> {
+        self.names.remove_alias(alias)
+    }
+    
+    /// Get the device ID and alias namespace
+    pub fn device_names(&self) -> &DeviceNamespace {
+        &self.names
+    }
+    
+    /// Look up a device's ID
+    pub fn resolve_device(&self, selector: &DeviceSelector) -> Option<String> {
+        match selector {
+            DeviceSelector::Name(name) => self.names.resolve(name).map(String::from),
+            DeviceSelector::Type(device_type, index) => self.names.by_type(*device_type, *index).map(String::from),
+            DeviceSelector::Pci(address) => self.pci.backing_device(*address).map(String::from),
+        }
+    }
+    
+    /// Look up a device
+    pub fn find_device(&self, selector: &DeviceSelector) -> Option<Arc<RwLock<VirtualDevice>>> {
+        self.resolve_device(selector).and_then(|device_id| self.devices.get(&device_id).cloned())
+    }
+    
+    /// Check DMA of device models against `map` and `policy`; `clock`
+    /// returns monotonic nanoseconds for timestamping violations
+    pub fn enable_dma_protection(&mut self, map: GuestMemoryMap, policy: DmaPolicy, clock: fn() -> u64) -> Result<(), HypervisorError> {
+        if self.iommu.is_some() {
+            return Err(ConfigError::new("dma", ConfigErrorReason::AlreadyEnabled).vm(self.vm_id).into());
+        }
+        self.iommu = Some(Arc::new(Mutex::new(Iommu::new(self.vm_id, map, policy, clock))));
+        info!("DMA protection enabled for VM {}", self.vm_id.0);
+        Ok(())
+    }
+    
+    /// Replace the DMA policy; applies to existing mappings as well
+    pub fn set_dma_policy(&mut self, policy: DmaPolicy) -> Result<(), HypervisorError> {
+        self.iommu()?.lock().set_policy(policy);
+        Ok(())
+    }
+    
+    /// Replace the guest memory map, e.g. after memory hot-plug
+    pub fn set_guest_memory_map(&mut self, map: GuestMemoryMap) -> Result<(), HypervisorError> {
+        self.iommu()?.lock().set_memory_map(map);
+        Ok(())
+    }
+    
+    /// Guest memory access for a device model, checked by the IOMMU
+    pub fn dma_context(&self, name: &str, memory: Box<dyn GuestMemoryAccess + Send>) -> Result<DmaContext, HypervisorError> {
+        let device_id = self.names.resolve(name)
+            .ok_or_else(|| HypervisorError::from(ConfigError::new("device.id", ConfigErrorReason::NotFound).vm(self.vm_id)))?;
+        Ok(DmaContext::new(device_id, self.iommu()?, memory))
+    }
+    
+    /// `memory` wrapped in a `DmaContext` for `device_id` if DMA protection
+    /// is enabled
+    fn protect_dma(&self, device_id: &str, memory: Box<dyn GuestMemoryAccess + Send>) -> Box<dyn GuestMemoryAccess + Send> {
+        match &self.iommu {
+            Some(iommu) => Box::new(DmaContext::new(device_id, iommu.clone(), memory)),
+            None => memory,
+        }
+    }
+    
+    pub fn dma_stats(&self) -> Option<DmaStats> {
+        self.iommu.as_ref().map(|iommu| iommu.lock().stats())
+    }
+    
+    /// Take the recorded DMA security events
+    pub fn take_dma_violations(&mut self) -> Vec<DmaViolation> {
+        self.iommu.as_ref().map_or_else(Vec::new, |iommu| iommu.lock().take_violations())
+    }
+    
+    fn iommu(&self) -> Result<Arc<Mutex<Iommu>>, HypervisorError> {
+        self.iommu.clone().ok_or_else(|| ConfigError::new("dma", ConfigErrorReason::NotEnabled).vm(self.vm_id).into())
+    }
+    
     /// Create and register educational demo device
     pub fn create_educational_demo_device(&mut self) -> Result<String, HypervisorError> {
         let device = self.build_educational_demo_device()?;
@@ -448,8 +729,548 @@ impl DeviceFramework {
         })
     }
     
+    /// Attach a claimed USB device behind an emulated xHCI controller
+    pub fn attach_usb_passthrough(&mut self, passthrough: UsbPassthrough, base_address: u64, interrupt_line: u8) -> Result<String, HypervisorError> {
+        if self.usb_passthrough.values().any(|other| other.host_address() == passthrough.host_address()) {
+            return Err(ConfigError::new("usb_passthrough", ConfigErrorReason::AlreadyExists)
+                .vm(self.vm_id).value(passthrough.host_address() as u64).into());
+        }
+        
+        let device = passthrough.build_virtual_device(base_address, interrupt_line);
+        let function = passthrough.build_pci_function(base_address, interrupt_line)?;
+        let (device_id, _) = self.attach_pci_device(device, function)?;
+        self.usb_passthrough.insert(device_id.clone(), passthrough);
+        Ok(device_id)
+    }
+    
+    /// Detach a passed-through USB device and hand it back to the caller
+    pub fn detach_usb_passthrough(&mut self, device_id: &str) -> Result<UsbPassthrough, HypervisorError> {
+        let passthrough = self.usb_passthrough.remove(device_id)
+            .ok_or_else(|| HypervisorError::from(IoError::new("device", IoErrorKind::NoDevice)))?;
+        self.remove_device(device_id);
+        if let Some(address) = self.pci.address_of(device_id) {
+            self.pci.remove_function(address)?;
+        }
+        
+        let (vendor_id, product_id) = passthrough.device_id();
+        info!("Detached USB passthrough device {:04x}:{:04x}", vendor_id, product_id);
+        Ok(passthrough)
+    }
+    
+    /// Retry pending USB passthrough transfers and latch their interrupts
+    pub fn poll_usb_passthrough(&mut self) -> Result<(), HypervisorError> {
+        for (device_id, passthrough) in self.usb_passthrough.iter_mut() {
+            passthrough.poll()?;
+            Self::latch_usb_interrupt(&self.devices, device_id, passthrough);
+        }
+        Ok(())
+    }
+    
+    /// Mirror a passthrough controller's interrupt onto its virtual device
+    fn latch_usb_interrupt(devices: &BTreeMap<String, Arc<RwLock<VirtualDevice>>>, device_id: &str, passthrough: &mut UsbPassthrough) {
+        if !passthrough.take_interrupt() {
+            return;
+        }
+        if let Some(device) = devices.get(device_id) {
+            let mut device = device.write();
+            device.stats.interrupt_count += 1;
+            if let Some(interrupt) = device.interrupt.as_mut() {
+                interrupt.active = true;
+            }
+        }
+    }
+    
+    /// Register a device and put its PCI function on the bus
+    pub fn attach_pci_device(&mut self, device: VirtualDevice, function: PciFunction) -> Result<(String, PciAddress), HypervisorError> {
+        let device_id = self.register_device(device)?;
+        let address = match self.pci.add_function(function, Some(device_id.clone())) {
+            Ok(address) => address,
+            Err(e) => {
+                self.remove_device(&device_id);
+                return Err(e);
+            }
+        };
+        self.devices[&device_id].write().config.address = address.bdf() as u32;
+        Ok((device_id, address))
+    }
+    
+    /// Assign BARs left unprogrammed and enable decoding, before boot
+    pub fn assign_pci_resources(&mut self) -> Result<(), HypervisorError> {
+        self.pci.assign_resources()?;
+        for device in self.pci.enumerate() {
+            self.sync_pci_resources(device.address);
+        }
+        Ok(())
+    }
+    
+    /// Functions on the guest's PCI bus
+    pub fn enumerate_pci(&self) -> Vec<PciDeviceInfo> {
+        self.pci.enumerate()
+    }
+    
+    /// Handle a port read from CF8/CFC or a BAR in I/O space
+    pub fn handle_pci_io_read(&mut self, port: u16, size: usize) -> Result<u64, HypervisorError> {
+        if let Some(value) = self.pci.io_read(port, size) {
+            return Ok(value as u64);
+        }
+        let hit = self.pci.route(port as u64, true)
+            .ok_or_else(|| HypervisorError::from(IoError::new("pci", IoErrorKind::NoDevice).at(port as u64)))?;
+        self.handle_bar_read(hit, size)
+    }
+    
+    /// Handle a port write to CF8/CFC or a BAR in I/O space
+    pub fn handle_pci_io_write(&mut self, port: u16, value: u64, size: usize) -> Result<(), HypervisorError> {
+        if self.pci.io_write(port, size, value as u32) {
+            if let Some(address) = self.pci.selected_function() {
+                self.sync_pci_resources(address);
+            }
+            return Ok(());
+        }
+        let hit = self.pci.route(port as u64, true)
+            .ok_or_else(|| HypervisorError::from(IoError::new("pci", IoErrorKind::NoDevice).at(port as u64)))?;
+        self.handle_bar_write(hit, value, size)
+    }
+    
+    /// Handle a read from the ECAM window or a memory BAR
+    pub fn handle_pci_mmio_read(&mut self, guest_address: u64, size: usize) -> Result<u64, HypervisorError> {
+        let ecam_base = self.pci.ecam_base();
+        if (ecam_base..ecam_base + PCI_ECAM_SIZE).contains(&guest_address) {
+            return Ok(self.pci.ecam_read(guest_address - ecam_base, size) as u64);
+        }
+        let hit = self.pci.route(guest_address, false)
+            .ok_or_else(|| HypervisorError::from(IoError::new("pci", IoErrorKind::NoDevice).at(guest_address)))?;
+        self.handle_bar_read(hit, size)
+    }
+    
+    /// Handle a write to the ECAM window or a memory BAR
+    pub fn handle_pci_mmio_write(&mut self, guest_address: u64, value: u64, size: usize) -> Result<(), HypervisorError> {
+        let ecam_base = self.pci.ecam_base();
+        if (ecam_base..ecam_base + PCI_ECAM_SIZE).contains(&guest_address) {
+            let address = self.pci.ecam_write(guest_address - ecam_base, size, value as u32);
+            self.sync_pci_resources(address);
+            return Ok(());
+        }
+        let hit = self.pci.route(guest_address, false)
+            .ok_or_else(|| HypervisorError::from(IoError::new("pci", IoErrorKind::NoDevice).at(guest_address)))?;
+        self.handle_bar_write(hit, value, size)
+    }
+    
+    fn handle_bar_read(&mut self, hit: PciBarHit, size: usize) -> Result<u64, HypervisorError> {
+        if let Some(value) = self.pci.bar_read(&hit, size) {
+            return Ok(value);
+        }
+        match hit.backing_device {
+            Some(device_id) => self.handle_device_read(&device_id, hit.offset, size),
+            None => Ok(0),
+        }
+    }
+    
+    fn handle_bar_write(&mut self, hit: PciBarHit, value: u64, size: usize) -> Result<(), HypervisorError> {
+        if self.pci.bar_write(&hit, value, size) {
+            return Ok(());
+        }
+        match hit.backing_device {
+            Some(device_id) => self.handle_device_write(&device_id, hit.offset, value, size),
+            None => Ok(()),
+        }
+    }
+    
+    /// Mirror the BARs and interrupt line the guest programmed onto the
+    /// backing device
+    fn sync_pci_resources(&self, address: PciAddress) {
+        let info = match self.pci.enumerate().into_iter().find(|device| device.address == address) {
+            Some(info) => info,
+            None => return,
+        };
+        let device = match info.backing_device.as_ref().and_then(|device_id| self.devices.get(device_id)) {
+            Some(device) => device,
+            None => return,
+        };
+        let function = match self.pci.function(address) {
+            Some(function) => function,
+            None => return,
+        };
+        
+        let mut device = device.write();
+        let access = device.mmio_regions.first().map_or(DeviceAccess::READ | DeviceAccess::WRITE, |region| region.access);
+        device.mmio_regions = info.bars.iter()
+            .filter(|assignment| assignment.bar.kind != PciBarKind::Io && function.bar_decoding(assignment.index))
+            .map(|assignment| MmioRegion { base_address: assignment.address, size: assignment.bar.size, access })
+            .collect();
+        device.io_ports = info.bars.iter()
+            .filter(|assignment| assignment.bar.kind == PciBarKind::Io && function.bar_decoding(assignment.index))
+            .map(|assignment| IoPortRange {
+                base_port: assignment.address as u16,
+                size: assignment.bar.size as u16,
+                access: DeviceAccess::READ | DeviceAccess::WRITE,
+            })
+            .collect();
+        device.config.interrupt_line = Some(info.interrupt_line);
+        if let Some(interrupt) = device.interrupt.as_mut() {
+            interrupt.interrupt_line = info.interrupt_line;
+        }
+    }
+    
+    /// Attach the RTC and, if configured, the HPET. `host_time` is the
+    /// host's time in seconds since the Unix epoch, used unless the config
+    /// fixes a start time; `clock` returns monotonic nanoseconds.
+    pub fn attach_clock_devices(&mut self, config: &ClockConfig, host_time: u64, memory_bytes: u64, clock: fn() -> u64) {
+        let mut rtc = Rtc::new(config.rtc_base, config.start_time.unwrap_or(host_time), clock);
+        rtc.set_memory_size(memory_bytes);
+        self.rtc = Some(rtc);
+        self.hpet = if config.hpet { Some(Hpet::new(clock)) } else { None };
+        info!("Attached RTC ({:?}){} to VM {}", config.rtc_base,
+              if config.hpet { " and HPET" } else { "" }, self.vm_id.0);
+    }
+    
+    /// Handle a read from the RTC index or data port
+    pub fn handle_rtc_io_read(&mut self, port: u16) -> Result<u8, HypervisorError> {
+        self.rtc.as_mut()
+            .and_then(|rtc| rtc.io_read(port))
+            .ok_or_else(|| IoError::new("rtc", IoErrorKind::NoDevice).at(port as u64).into())
+    }
+    
+    /// Handle a write to the RTC index or data port
+    pub fn handle_rtc_io_write(&mut self, port: u16, value: u8) -> Result<(), HypervisorError> {
+        match self.rtc.as_mut() {
+            Some(rtc) if rtc.io_write(port, value) => {
+                self.collect_timer_interrupts();
+                Ok(())
+            }
+            _ => Err(IoError::new("rtc", IoErrorKind::NoDevice).at(port as u64).into()),
+        }
+    }
+    
+    /// Handle a read from the HPET register block
+    pub fn handle_hpet_read(&mut self, guest_address: u64, size: usize) -> Result<u64, HypervisorError> {
+        let hpet = self.hpet.as_mut().ok_or(HypervisorError::FeatureNotSupported)?;
+        if !(HPET_BASE..HPET_BASE + HPET_SIZE).contains(&guest_address) || (size != 4 && size != 8) {
+            return Err(IoError::new("hpet", IoErrorKind::InvalidAccess).at(guest_address).into());
+        }
+        Ok(hpet.mmio_read(guest_address - HPET_BASE, size))
+    }
+    
+    /// Handle a write to the HPET register block
+    pub fn handle_hpet_write(&mut self, guest_address: u64, value: u64, size: usize) -> Result<(), HypervisorError> {
+        let hpet = self.hpet.as_mut().ok_or(HypervisorError::FeatureNotSupported)?;
+        if !(HPET_BASE..HPET_BASE + HPET_SIZE).contains(&guest_address) || (size != 4 && size != 8) {
+            return Err(IoError::new("hpet", IoErrorKind::InvalidAccess).at(guest_address).into());
+        }
+        hpet.mmio_write(guest_address - HPET_BASE, value, size);
+        self.collect_timer_interrupts();
+        Ok(())
+    }
+    
+    /// Advance the clock devices; returns when they next need polling
+    pub fn poll_timers(&mut self) -> Option<u64> {
+        if let Some(rtc) = self.rtc.as_mut() {
+            rtc.poll();
+        }
+        if let Some(hpet) = self.hpet.as_mut() {
+            hpet.poll();
+        }
+        self.collect_timer_interrupts();
+        
+        let rtc_deadline = self.rtc.as_ref().and_then(|rtc| rtc.next_deadline_ns());
+        let hpet_deadline = self.hpet.as_ref().and_then(|hpet| hpet.next_deadline_ns());
+        match (rtc_deadline, hpet_deadline) {
+            (Some(rtc), Some(hpet)) => Some(rtc.min(hpet)),
+            (deadline, None) | (None, deadline) => deadline,
+        }
+    }
+    
+    /// Interrupts raised since the last call, for the interrupt controller
+    pub fn take_interrupts(&mut self) -> Vec<DeviceInterrupt> {
+        core::mem::take(&mut self.pending_interrupts)
+    }
+    
+    /// Move the clock devices' interrupts to the pending queue. In legacy
+    /// replacement mode the HPET owns IRQ 8, so the RTC's is dropped.
+    fn collect_timer_interrupts(&mut self) {
+        let legacy = self.hpet.as_ref().map_or(false, |hpet| hpet.legacy_replacement());
+        if let Some(rtc) = self.rtc.as_mut() {
+            let interrupts = rtc.take_interrupts();
+            if !legacy {
+                self.pending_interrupts.extend(interrupts);
+            }
+        }
+        if let Some(hpet) = self.hpet.as_mut() {
+            self.pending_interrupts.extend(hpet.take_interrupts());
+        }
+    }
+    
+    /// Attach the display adapter the graphics config asks for; None for
+    /// a headless VM
+    pub fn attach_display(&mut self, config: &GraphicsConfig) -> Result<Option<PciAddress>, HypervisorError> {
+        if config.card_type == GraphicsCardType::Headless {
+            return Ok(None);
+        }
+        if self.vga.is_some() {
+            return Err(ConfigError::new("display", ConfigErrorReason::AlreadyExists).vm(self.vm_id).into());
+        }
+        
+        let mut device = self.build_vga_controller()?;
+        device.config.custom_config.insert(String::from("vram_size"), format!("{}MB", VGA_VRAM_SIZE >> 20));
+        device.config.custom_config.insert(String::from("resolution"), format!("{}x{}", config.resolution.0, config.resolution.1));
+        device.io_ports = vec![
+            IoPortRange { base_port: 0x3B0, size: 0x30, access: DeviceAccess::READ | DeviceAccess::WRITE },
+            IoPortRange { base_port: VBE_INDEX_PORT, size: 2, access: DeviceAccess::READ | DeviceAccess::WRITE },
+        ];
+        let (device_id, address) = self.attach_pci_device(device, Vga::pci_function())?;
+        self.vga = Some(Vga::new(config));
+        self.vga_device_id = Some(device_id);
+        info!("Attached VGA display at {} to VM {}", address, self.vm_id.0);
+        Ok(Some(address))
+    }
+    
+    /// Handle a read from a VGA or VBE register port
+    pub fn handle_vga_io_read(&mut self, port: u16, size: usize) -> Result<u64, HypervisorError> {
+        match self.vga.as_mut() {
+            Some(vga) if Vga::handles_port(port) => Ok(vga.io_read(port, size) as u64),
+            _ => Err(IoError::new("vga", IoErrorKind::NoDevice).at(port as u64).into()),
+        }
+    }
+    
+    /// Handle a write to a VGA or VBE register port
+    pub fn handle_vga_io_write(&mut self, port: u16, value: u64, size: usize) -> Result<(), HypervisorError> {
+        match self.vga.as_mut() {
+            Some(vga) if Vga::handles_port(port) => {
+                vga.io_write(port, value as u32, size);
+                Ok(())
+            }
+            _ => Err(IoError::new("vga", IoErrorKind::NoDevice).at(port as u64).into()),
+        }
+    }
+    
+    /// Handle a read from the legacy video memory window
+    pub fn handle_vga_mmio_read(&mut self, guest_address: u64, size: usize) -> Result<u64, HypervisorError> {
+        match self.vga.as_ref() {
+            Some(vga) if (VGA_LEGACY_BASE..VGA_LEGACY_BASE + VGA_LEGACY_SIZE).contains(&guest_address) => {
+                Ok(vga.legacy_read(guest_address - VGA_LEGACY_BASE, size))
+            }
+            _ => Err(IoError::new("vga", IoErrorKind::NoDevice).at(guest_address).into()),
+        }
+    }
+    
+    /// Handle a write to the legacy video memory window
+    pub fn handle_vga_mmio_write(&mut self, guest_address: u64, value: u64, size: usize) -> Result<(), HypervisorError> {
+        match self.vga.as_mut() {
+            Some(vga) if (VGA_LEGACY_BASE..VGA_LEGACY_BASE + VGA_LEGACY_SIZE).contains(&guest_address) => {
+                vga.legacy_write(guest_address - VGA_LEGACY_BASE, value, size);
+                Ok(())
+            }
+            _ => Err(IoError::new("vga", IoErrorKind::NoDevice).at(guest_address).into()),
+        }
+    }
+    
+    /// The guest's current picture, for the host display or an RFB server
+    pub fn capture_display(&self) -> Option<Frame> {
+        self.vga.as_ref().map(|vga| vga.capture())
+    }
+    
+    /// PNG snapshot of the guest's current picture
+    pub fn capture_display_png(&self) -> Option<Vec<u8>> {
+        self.capture_display().map(|frame| frame.to_png())
+    }
+    
+    /// Attach a virtio-input keyboard or tablet whose queues live in
+    /// `memory`
+    pub fn attach_virtio_input(&mut self, kind: VirtioInputKind, memory: Box<dyn GuestMemoryAccess + Send>) -> Result<(String, PciAddress), HypervisorError> {
+        let input = VirtioInput::new(kind);
+        let mut device = input.build_virtual_device();
+        // Pick the ID up front so the device's DMA is checked under it
+        device.device_id = self.names.next_id(device.device_type)
+            .ok_or_else(|| HypervisorError::from(ConfigError::new("device.id", ConfigErrorReason::Exhausted).vm(self.vm_id)))?;
+        let memory = self.protect_dma(&device.device_id, memory);
+        let transport = VirtioPci::new(input, memory);
+        let (device_id, address) = self.attach_pci_device(device, transport.pci_function()?)?;
+        self.virtio_input.insert(device_id.clone(), transport);
+        info!("Attached virtio {:?} at {} to VM {}", kind, address, self.vm_id.0);
+        Ok((device_id, address))
+    }
+    
+    /// Press or release a key on the VM's virtio keyboard, by PC set 1
+    /// scancode; see `scancode_to_keycode`
+    pub fn inject_key(&mut self, vm_id: VmId, scancode: u16) -> Result<(), HypervisorError> {
+        if vm_id != self.vm_id {
+            return Err(HypervisorError::VmNotFound(vm_id));
+        }
+        let (keycode, pressed) = scancode_to_keycode(scancode).ok_or(HypervisorError::InvalidParameter)?;
+        let device_id = self.virtio_input_of_kind(VirtioInputKind::Keyboard)?;
+        if let Some(transport) = self.virtio_input.get_mut(&device_id) {
+            transport.device_mut().press_key(keycode, pressed)?;
+            transport.process_queue(0);
+        }
+        self.collect_virtio_interrupts(&device_id);
+        Ok(())
+    }
+    
+    /// Move the VM's virtio tablet to a display pixel and set its buttons
+    /// (`POINTER_BUTTON_*` and `POINTER_WHEEL_*`). Without a display the
+    /// coordinates are taken as already scaled to `VIRTIO_INPUT_ABS_MAX`.
+    pub fn inject_pointer(&mut self, vm_id: VmId, x: u32, y: u32, buttons: u8) -> Result<(), HypervisorError> {
+        if vm_id != self.vm_id {
+            return Err(HypervisorError::VmNotFound(vm_id));
+        }
+        let (x, y) = match self.vga.as_ref().map(|vga| vga.resolution()) {
+            Some((width, height)) => (scale_to_axis(x, width), scale_to_axis(y, height)),
+            None => (x, y),
+        };
+        let device_id = self.virtio_input_of_kind(VirtioInputKind::Tablet)?;
+        if let Some(transport) = self.virtio_input.get_mut(&device_id) {
+            transport.device_mut().move_pointer(x, y, buttons)?;
+            transport.process_queue(0);
+        }
+        self.collect_virtio_interrupts(&device_id);
+        Ok(())
+    }
+    
+    fn virtio_input_of_kind(&self, kind: VirtioInputKind) -> Result<String, HypervisorError> {
+        self.virtio_input
+            .iter()
+            .find(|(_, transport)| transport.device().kind() == kind)
+            .map(|(device_id, _)| device_id.clone())
+            .ok_or_else(|| ConfigError::new("virtio_input", ConfigErrorReason::NotFound).vm(self.vm_id).value(kind as u64).into())
+    }
+    
+    /// Turn a virtio device's signalled vectors into MSIs, or its ISR into
+    /// the level of its interrupt line
+    fn collect_virtio_interrupts(&mut self, device_id: &str) {
+        let transport = match self.virtio_input.get_mut(device_id) {
+            Some(transport) => transport,
+            None => return,
+        };
+        let vectors = transport.take_vectors();
+        let intx = transport.take_intx_change();
+        let function = match self.pci.address_of(device_id).and_then(|address| self.pci.function_mut(address)) {
+            Some(function) => function,
+            None => return,
+        };
+        
+        let mut signalled = 0;
+        for vector in vectors {
+            if vector == VIRTIO_MSI_NO_VECTOR {
+                continue;
+            }
+            if let Some(message) = function.msi_message(vector) {
+                self.pending_interrupts.push(DeviceInterrupt::Msi(message));
+                signalled += 1;
+            }
+        }
+        if let Some(asserted) = intx {
+            if !asserted || function.uses_intx() {
+                let gsi = function.interrupt_line() as u32;
+                self.pending_interrupts.push(DeviceInterrupt::GsiLevel { gsi, asserted });
+                signalled += asserted as u64;
+            }
+        }
+        if let Some(device) = self.devices.get(device_id) {
+            device.write().stats.interrupt_count += signalled;
+        }
+    }
+    
+    /// Save the state of every device, the PCI bus and passed-through
+    /// controllers, for a snapshot or migration
+    pub fn save_all(&self, vm_id: VmId) -> Result<Vec<u8>, HypervisorError> {
+        if vm_id != self.vm_id {
+            return Err(HypervisorError::VmNotFound(vm_id));
+        }
+        
+        let mut records = Vec::with_capacity(self.devices.len() + self.usb_passthrough.len() + 1);
+        records.push((String::from(PCI_STATE_ID), encode_record(&self.pci)));
+        for (device_id, device) in &self.devices {
+            records.push((device_id.clone(), encode_record(&*device.read())));
+        }
+        for (device_id, passthrough) in &self.usb_passthrough {
+            records.push((format!("{}{}", USB_STATE_PREFIX, device_id), encode_record(passthrough)));
+        }
+        if let Some(rtc) = &self.rtc {
+            records.push((String::from(RTC_STATE_ID), encode_record(rtc)));
+        }
+        if let Some(hpet) = &self.hpet {
+            records.push((String::from(HPET_STATE_ID), encode_record(hpet)));
+        }
+        if let Some(vga) = &self.vga {
+            records.push((String::from(VGA_STATE_ID), encode_record(vga)));
+        }
+        for (device_id, transport) in &self.virtio_input {
+            records.push((format!("{}{}", VIRTIO_INPUT_STATE_PREFIX, device_id), encode_record(transport)));
+        }
+        
+        info!("Saved state of {} devices of VM {}", self.devices.len(), vm_id.0);
+        Ok(encode_blob(vm_id, &records))
+    }
+    
+    /// Restore device state saved by `save_all`, possibly by another VM or
+    /// host. The same devices must already be attached; devices without
+    /// saved state keep their current state.
+    pub fn restore_all(&mut self, vm_id: VmId, blob: &[u8]) -> Result<(), HypervisorError> {
+        if vm_id != self.vm_id {
+            return Err(HypervisorError::VmNotFound(vm_id));
+        }
+        let (saved_vm, records) = decode_blob(blob)?;
+        
+        for (device_id, record) in &records {
+            let restored = if device_id == PCI_STATE_ID {
+                restore_record(&mut self.pci, record)
+            } else if device_id == RTC_STATE_ID || device_id == HPET_STATE_ID || device_id == VGA_STATE_ID {
+                let device: Option<&mut dyn DeviceStateSerialize> = match device_id.as_str() {
+                    RTC_STATE_ID => self.rtc.as_mut().map(|rtc| rtc as &mut dyn DeviceStateSerialize),
+                    HPET_STATE_ID => self.hpet.as_mut().map(|hpet| hpet as &mut dyn DeviceStateSerialize),
+                    _ => self.vga.as_mut().map(|vga| vga as &mut dyn DeviceStateSerialize),
+                };
+                match device {
+                    Some(device) => restore_record(device, record),
+                    None => Err(ConfigError::new("device_state.device_id", ConfigErrorReason::Mismatch).into()),
+                }
+            } else if let Some(input_id) = device_id.strip_prefix(VIRTIO_INPUT_STATE_PREFIX) {
+                match self.virtio_input.get_mut(input_id) {
+                    Some(transport) => restore_record(transport, record),
+                    None => Err(ConfigError::new("device_state.device_id", ConfigErrorReason::Mismatch).into()),
+                }
+            } else if let Some(usb_id) = device_id.strip_prefix(USB_STATE_PREFIX) {
+                match self.usb_passthrough.get_mut(usb_id) {
+                    Some(passthrough) => restore_record(passthrough, record),
+                    None => Err(ConfigError::new("device_state.device_id", ConfigErrorReason::Mismatch).into()),
+                }
+            } else {
+                match self.devices.get(device_id.as_str()) {
+                    Some(device) => restore_record(&mut *device.write(), record),
+                    None => Err(ConfigError::new("device_state.device_id", ConfigErrorReason::Mismatch).into()),
+                }
+            };
+            restored.map_err(|e| {
+                warn!("Restoring device {} of VM {} failed: {:?}", device_id, vm_id.0, e);
+                e
+            })?;
+        }
+        
+        for device_id in self.devices.keys().filter(|device_id| !records.iter().any(|(saved, _)| saved == *device_id)) {
+            warn!("Device {} has no saved state, keeping its current state", device_id);
+        }
+        info!("Restored {} device records of VM {} into VM {}", records.len(), saved_vm.0, vm_id.0);
+        Ok(())
+    }
+    
     /// Handle device read operation
     pub fn handle_device_read(&mut self, device_id: &str, offset: u64, size: usize) -> Result<u64, HypervisorError> {
+        if self.vga_device_id.as_deref() == Some(device_id) {
+            if let Some(vga) = self.vga.as_ref() {
+                return Ok(vga.framebuffer_read(offset, size));
+            }
+        }
+        if let Some(transport) = self.virtio_input.get_mut(device_id) {
+            let value = transport.mmio_read(offset, size);
+            self.collect_virtio_interrupts(device_id);
+            return Ok(value);
+        }
+        if let Some(passthrough) = self.usb_passthrough.get_mut(device_id) {
+            if let Some(device) = self.devices.get(device_id) {
+                device.write().stats.read_count += 1;
+            }
+            return Ok(passthrough.mmio_read(offset, size));
+        }
+        
         if let Some(device) = self.devices.get(device_id) {
             let mut device = device.write();
             device.stats.read_count += 1;
@@ -469,16 +1290,40 @@ impl DeviceFramework {
                 },
                 _ => {
                     device.stats.error_count += 1;
-                    Err(HypervisorError::IoError(String::from("Unsupported device read")))
+                    Err(IoError::new("device", IoErrorKind::InvalidAccess).at(offset).into())
                 },
             }
         } else {
-            Err(HypervisorError::IoError(format!("Device {} not found", device_id)))
+            Err(IoError::new("device", IoErrorKind::NoDevice).into())
         }
     }
     
     /// Handle device write operation
     pub fn handle_device_write(&mut self, device_id: &str, offset: u64, value: u64, size: usize) -> Result<(), HypervisorError> {
+        if self.vga_device_id.as_deref() == Some(device_id) {
+            if let Some(vga) = self.vga.as_mut() {
+                vga.framebuffer_write(offset, value, size);
+                return Ok(());
+            }
+        }
+        if let Some(transport) = self.virtio_input.get_mut(device_id) {
+            transport.mmio_write(offset, value, size);
+            self.collect_virtio_interrupts(device_id);
+            return Ok(());
+        }
+        if let Some(passthrough) = self.usb_passthrough.get_mut(device_id) {
+            let result = passthrough.mmio_write(offset, value, size);
+            if let Some(device) = self.devices.get(device_id) {
+                let mut device = device.write();
+                device.stats.write_count += 1;
+                if result.is_err() {
+                    device.stats.error_count += 1;
+                }
+            }
+            Self::latch_usb_interrupt(&self.devices, device_id, passthrough);
+            return result;
+        }
+        
         if let Some(device) = self.devices.get(device_id) {
             let mut device = device.write();
             device.stats.write_count += 1;
@@ -497,13 +1342,13 @@ impl DeviceFramework {
                 },
                 _ => {
                     device.stats.error_count += 1;
-                    return Err(HypervisorError::IoError(String::from("Unsupported device write")));
+                    return Err(IoError::new("device", IoErrorKind::InvalidAccess).at(offset).into());
                 },
             }
             
             Ok(())
         } else {
-            Err(HypervisorError::IoError(format!("Device {} not found", device_id)))
+            Err(IoError::new("device", IoErrorKind::NoDevice).into())
         }
     }
     
@@ -611,4 +1456,12 @@ impl DeviceFramework {
         }
         None
     }
-}
\ No newline at end of file
+}
+
+/// Map a display pixel coordinate onto an absolute pointer axis
+fn scale_to_axis(position: u32, extent: u32) -> u32 {
+    if extent <= 1 {
+        return 0;
+    }
+    (position.min(extent - 1) as u64 * VIRTIO_INPUT_ABS_MAX as u64 / (extent - 1) as u64) as u32
+}
@@ -0,0 +1,190 @@
+//! Device Naming
+//!
+//! Every VM has its own namespace of device IDs. A device gets the ID
+//! `<type><n>`, e.g. `serial0` or `usb1`, where `n` is the lowest number
+//! free for its type, so the same devices attached in the same order get
+//! the same IDs in every run of the VM. Snapshots, hot-plug and the
+//! management API refer to devices by these IDs or by user-assigned
+//! aliases, which share the namespace with the IDs.
+
+use crate::{HypervisorError, ConfigError, ConfigErrorReason, VmId};
+use super::{DeviceType, PciAddress, PCI_STATE_ID, RTC_STATE_ID, HPET_STATE_ID, VGA_STATE_ID};
+
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Longest device ID or alias
+pub const MAX_DEVICE_NAME_LEN: usize = 64;
+
+impl DeviceType {
+    /// Prefix of generated device IDs
+    pub fn id_prefix(self) -> &'static str {
+        match self {
+            DeviceType::PciBridge => "pcibridge",
+            DeviceType::IsaBus => "isa",
+            DeviceType::VgaController => "vga",
+            DeviceType::NetworkCard => "net",
+            DeviceType::DiskController => "disk",
+            DeviceType::SerialPort => "serial",
+            DeviceType::ParallelPort => "parallel",
+            DeviceType::UsbController => "usb",
+            DeviceType::AudioDevice => "audio",
+            DeviceType::KeyboardController => "kbd",
+            DeviceType::MouseController => "mouse",
+            DeviceType::TimerDevice => "timer",
+            DeviceType::RtcDevice => "rtc",
+            DeviceType::GpioDevice => "gpio",
+            DeviceType::EducationalDemo => "demo",
+        }
+    }
+}
+
+/// How a device is looked up
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeviceSelector {
+    /// Device ID or alias
+    Name(String),
+    /// The `n`th device of a type, counting from 0; `serial0` for a
+    /// device with a generated ID
+    Type(DeviceType, u32),
+    /// Device backing a PCI function
+    Pci(PciAddress),
+}
+
+#[derive(Debug, Clone)]
+struct DeviceEntry {
+    device_type: DeviceType,
+    index: u32,
+}
+
+/// Device IDs and aliases of one VM
+#[derive(Debug, Clone)]
+pub struct DeviceNamespace {
+    vm_id: VmId,
+    devices: BTreeMap<String, DeviceEntry>,
+    /// Alias to device ID
+    aliases: BTreeMap<String, String>,
+}
+
+impl DeviceNamespace {
+    pub fn new(vm_id: VmId) -> Self {
+        DeviceNamespace { vm_id, devices: BTreeMap::new(), aliases: BTreeMap::new() }
+    }
+
+    /// Assign an ID to a new device: `requested` if given, otherwise
+    /// `<type><n>`
+    pub fn allocate(&mut self, device_type: DeviceType, requested: Option<&str>) -> Result<String, HypervisorError> {
+        let (device_id, index) = match requested {
+            Some(device_id) => {
+                self.check_free(device_id)?;
+                (String::from(device_id), (0..).find(|index| self.free_index(device_type, *index)).unwrap_or(0))
+            }
+            None => self.generated_id(device_type)
+                .ok_or_else(|| HypervisorError::from(ConfigError::new("device.id", ConfigErrorReason::Exhausted).vm(self.vm_id)))?,
+        };
+        self.devices.insert(device_id.clone(), DeviceEntry { device_type, index });
+        Ok(device_id)
+    }
+
+    /// ID the next device of a type would get without a requested ID
+    pub fn next_id(&self, device_type: DeviceType) -> Option<String> {
+        self.generated_id(device_type).map(|(device_id, _)| device_id)
+    }
+
+    /// Free a device's ID and drop its aliases
+    pub fn release(&mut self, device_id: &str) {
+        self.devices.remove(device_id);
+        self.aliases.retain(|_, target| target != device_id);
+    }
+
+    /// Give a device (by ID or alias) an additional name
+    pub fn set_alias(&mut self, name: &str, alias: &str) -> Result<(), HypervisorError> {
+        let device_id = self.resolve(name)
+            .map(String::from)
+            .ok_or_else(|| HypervisorError::from(ConfigError::new("device.id", ConfigErrorReason::NotFound).vm(self.vm_id)))?;
+        if self.aliases.get(alias) == Some(&device_id) {
+            return Ok(());
+        }
+        self.check_free(alias)?;
+        self.aliases.insert(String::from(alias), device_id);
+        Ok(())
+    }
+
+    pub fn remove_alias(&mut self, alias: &str) -> Result<(), HypervisorError> {
+        self.aliases.remove(alias)
+            .map(|_| ())
+            .ok_or_else(|| ConfigError::new("device.alias", ConfigErrorReason::NotFound).vm(self.vm_id).into())
+    }
+
+    /// Device ID of an ID or alias
+    pub fn resolve(&self, name: &str) -> Option<&str> {
+        match self.devices.get_key_value(name) {
+            Some((device_id, _)) => Some(device_id),
+            None => self.aliases.get(name).map(|device_id| device_id.as_str()),
+        }
+    }
+
+    /// Device ID of the `index`th device of a type
+    pub fn by_type(&self, device_type: DeviceType, index: u32) -> Option<&str> {
+        self.devices
+            .iter()
+            .find(|(_, entry)| entry.device_type == device_type && entry.index == index)
+            .map(|(device_id, _)| device_id.as_str())
+    }
+
+    /// IDs of the devices of a type, by index
+    pub fn of_type(&self, device_type: DeviceType) -> Vec<&str> {
+        let mut devices: Vec<(&str, u32)> = self.devices
+            .iter()
+            .filter(|(_, entry)| entry.device_type == device_type)
+            .map(|(device_id, entry)| (device_id.as_str(), entry.index))
+            .collect();
+        devices.sort_by_key(|&(_, index)| index);
+        devices.into_iter().map(|(device_id, _)| device_id).collect()
+    }
+
+    /// Aliases of a device
+    pub fn aliases_of(&self, device_id: &str) -> Vec<&str> {
+        self.aliases
+            .iter()
+            .filter(|(_, target)| target.as_str() == device_id)
+            .map(|(alias, _)| alias.as_str())
+            .collect()
+    }
+
+    fn free_index(&self, device_type: DeviceType, index: u32) -> bool {
+        !self.devices.values().any(|entry| entry.device_type == device_type && entry.index == index)
+    }
+
+    fn generated_id(&self, device_type: DeviceType) -> Option<(String, u32)> {
+        (0..)
+            .filter(|index| self.free_index(device_type, *index))
+            .map(|index| (format!("{}{}", device_type.id_prefix(), index), index))
+            .find(|(device_id, _)| !self.is_taken(device_id))
+    }
+
+    fn is_taken(&self, name: &str) -> bool {
+        self.devices.contains_key(name)
+            || self.aliases.contains_key(name)
+            || [PCI_STATE_ID, RTC_STATE_ID, HPET_STATE_ID, VGA_STATE_ID].contains(&name)
+    }
+
+    /// A name must be unused, not a record ID of saved framework state, at
+    /// most `MAX_DEVICE_NAME_LEN` long and made of letters, digits, `-`, `_`
+    /// and `.`, which keeps it apart from the prefixed record IDs
+    fn check_free(&self, name: &str) -> Result<(), HypervisorError> {
+        let valid = !name.is_empty()
+            && name.len() <= MAX_DEVICE_NAME_LEN
+            && name.bytes().all(|byte| byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.'));
+        if !valid {
+            return Err(ConfigError::new("device.name", ConfigErrorReason::Malformed).vm(self.vm_id).into());
+        }
+        if self.is_taken(name) {
+            warn!("Device name '{}' is already used in VM {}", name, self.vm_id.0);
+            return Err(ConfigError::new("device.name", ConfigErrorReason::AlreadyExists).vm(self.vm_id).into());
+        }
+        Ok(())
+    }
+}
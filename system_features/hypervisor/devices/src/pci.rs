@@ -0,0 +1,924 @@
+//! PCI Host Bridge Emulation
+//!
+//! Puts devices on an emulated PCI bus 0 so guests find them by scanning
+//! config space, through the legacy CF8/CFC port pair or the memory-mapped
+//! ECAM window described by the MCFG table. Each function has a 4 KiB
+//! config space with a per-byte write mask, which gives BAR sizing for
+//! free: bits below a BAR's size are read-only, so writing all ones reads
+//! back the size mask. MSI and MSI-X capabilities are kept in the
+//! capability list; the MSI-X table is emulated in its BAR.
+
+use crate::{HypervisorError, ConfigError, ConfigErrorReason};
+use crate::core::{acpi_header, finish_acpi_table};
+use super::{DeviceStateReader, DeviceStateSerialize, DeviceStateWriter};
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Legacy config mechanism #1 ports
+pub const PCI_CONFIG_ADDRESS: u16 = 0xCF8;
+pub const PCI_CONFIG_DATA: u16 = 0xCFC;
+
+/// ECAM window, one bus
+pub const PCI_ECAM_BASE: u64 = 0xB000_0000;
+pub const PCI_ECAM_SIZE: u64 = 1 << 20;
+/// Windows BARs are assigned from
+pub const PCI_MMIO32_BASE: u64 = 0xC000_0000;
+pub const PCI_MMIO32_SIZE: u64 = 0x3000_0000;
+pub const PCI_MMIO64_BASE: u64 = 0x80_0000_0000;
+pub const PCI_MMIO64_SIZE: u64 = 0x10_0000_0000;
+pub const PCI_IO_BASE: u64 = 0xC000;
+pub const PCI_IO_SIZE: u64 = 0x4000;
+
+pub const PCI_SLOTS: u8 = 32;
+pub const PCI_FUNCTIONS: u8 = 8;
+pub const PCI_CONFIG_SIZE: usize = 4096;
+pub const PCI_BAR_COUNT: usize = 6;
+
+pub const PCI_VENDOR_VIRTIO: u16 = 0x1AF4;
+/// Modern virtio device IDs are 0x1040 plus the virtio device type
+pub const PCI_DEVICE_VIRTIO_BASE: u16 = 0x1040;
+
+// Generic host bridge ID QEMU uses, which guests bind without a driver
+const HOST_BRIDGE_VENDOR: u16 = 0x1B36;
+const HOST_BRIDGE_DEVICE: u16 = 0x0008;
+const CLASS_HOST_BRIDGE: u32 = 0x06_00_00;
+
+// Type 0 header
+const REG_VENDOR_ID: usize = 0x00;
+const REG_DEVICE_ID: usize = 0x02;
+const REG_COMMAND: usize = 0x04;
+const REG_STATUS: usize = 0x06;
+const REG_REVISION: usize = 0x08;
+const REG_CLASS: usize = 0x09;
+const REG_CACHE_LINE: usize = 0x0C;
+const REG_LATENCY: usize = 0x0D;
+const REG_HEADER_TYPE: usize = 0x0E;
+const REG_BAR0: usize = 0x10;
+const REG_SUBSYSTEM_VENDOR: usize = 0x2C;
+const REG_SUBSYSTEM_ID: usize = 0x2E;
+const REG_CAPABILITIES: usize = 0x34;
+const REG_INTERRUPT_LINE: usize = 0x3C;
+const REG_INTERRUPT_PIN: usize = 0x3D;
+/// Standard capabilities live between the header and 0x100
+const CAP_START: usize = 0x40;
+const CAP_END: usize = 0x100;
+
+pub const COMMAND_IO: u16 = 1 << 0;
+pub const COMMAND_MEMORY: u16 = 1 << 1;
+pub const COMMAND_BUS_MASTER: u16 = 1 << 2;
+pub const COMMAND_INTX_DISABLE: u16 = 1 << 10;
+const STATUS_CAP_LIST: u16 = 1 << 4;
+const HEADER_MULTIFUNCTION: u8 = 0x80;
+
+const BAR_IO: u32 = 1 << 0;
+const BAR_MEM64: u32 = 0b10 << 1;
+const BAR_PREFETCH: u32 = 1 << 3;
+
+pub const PCI_CAP_ID_MSI: u8 = 0x05;
+pub const PCI_CAP_ID_VENDOR: u8 = 0x09;
+pub const PCI_CAP_ID_MSIX: u8 = 0x11;
+
+const MSI_CONTROL_ENABLE: u16 = 1 << 0;
+const MSI_CONTROL_64BIT: u16 = 1 << 7;
+const MSI_CAP_SIZE: usize = 14;
+const MSIX_CONTROL_MASK_ALL: u16 = 1 << 14;
+const MSIX_CONTROL_ENABLE: u16 = 1 << 15;
+const MSIX_CAP_SIZE: usize = 12;
+const MSIX_ENTRY_SIZE: u64 = 16;
+const MSIX_ENTRY_MASKED: u32 = 1 << 0;
+pub const PCI_MSIX_MAX_VECTORS: u16 = 2048;
+
+const MCFG_REVISION: u8 = 1;
+
+/// Bus/device/function address of a PCI function
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PciAddress {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+impl PciAddress {
+    pub fn new(bus: u8, device: u8, function: u8) -> Self {
+        PciAddress { bus, device: device & 0x1F, function: function & 0x7 }
+    }
+
+    /// Packed bus:device.function, as in a requester ID
+    pub fn bdf(&self) -> u16 {
+        (self.bus as u16) << 8 | (self.device as u16) << 3 | self.function as u16
+    }
+
+    /// Address and register selected by a CONFIG_ADDRESS value, if enabled
+    fn from_config_address(value: u32) -> Option<(PciAddress, usize)> {
+        if value & (1 << 31) == 0 {
+            return None;
+        }
+        let address = PciAddress::new((value >> 16) as u8, (value >> 11) as u8, (value >> 8) as u8);
+        Some((address, (value & 0xFC) as usize))
+    }
+
+    /// Address and register of an offset into the ECAM window
+    fn from_ecam_offset(offset: u64) -> (PciAddress, usize) {
+        let address = PciAddress::new((offset >> 20) as u8, (offset >> 15) as u8, (offset >> 12) as u8);
+        (address, (offset & 0xFFF) as usize)
+    }
+}
+
+impl core::fmt::Display for PciAddress {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "{:02x}:{:02x}.{}", self.bus, self.device, self.function)
+    }
+}
+
+/// Kind of resource a BAR decodes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PciBarKind {
+    Io,
+    Memory32 { prefetchable: bool },
+    /// Occupies this BAR and the next
+    Memory64 { prefetchable: bool },
+}
+
+/// A base address register
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PciBar {
+    pub kind: PciBarKind,
+    /// Power of two
+    pub size: u64,
+}
+
+/// A BAR with the address currently programmed into it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PciBarAssignment {
+    pub index: usize,
+    pub bar: PciBar,
+    pub address: u64,
+}
+
+/// Message the function would write to signal an interrupt
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MsiMessage {
+    pub address: u64,
+    pub data: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct MsixLayout {
+    cap: usize,
+    vectors: u16,
+    table_bar: usize,
+    table_offset: u64,
+    pba_bar: usize,
+    pba_offset: u64,
+}
+
+/// One PCI function and its config space
+#[derive(Debug, Clone)]
+pub struct PciFunction {
+    config: Vec<u8>,
+    write_mask: Vec<u8>,
+    bars: [Option<PciBar>; PCI_BAR_COUNT],
+    next_cap: usize,
+    last_cap: Option<usize>,
+    msi_cap: Option<usize>,
+    msix: Option<MsixLayout>,
+    msix_table: Vec<[u32; 4]>,
+    msix_pending: Vec<u64>,
+}
+
+impl PciFunction {
+    /// A type 0 function; `class_code` is class, subclass and programming
+    /// interface in the low three bytes
+    pub fn new(vendor_id: u16, device_id: u16, class_code: u32, revision: u8) -> Self {
+        let mut function = PciFunction {
+            config: vec![0; PCI_CONFIG_SIZE],
+            write_mask: vec![0; PCI_CONFIG_SIZE],
+            bars: [None; PCI_BAR_COUNT],
+            next_cap: CAP_START,
+            last_cap: None,
+            msi_cap: None,
+            msix: None,
+            msix_table: Vec::new(),
+            msix_pending: Vec::new(),
+        };
+        function.set_u16(REG_VENDOR_ID, vendor_id);
+        function.set_u16(REG_DEVICE_ID, device_id);
+        function.config[REG_REVISION] = revision;
+        function.config[REG_CLASS..REG_CLASS + 3].copy_from_slice(&class_code.to_le_bytes()[..3]);
+        function.set_u16(REG_SUBSYSTEM_VENDOR, vendor_id);
+        function.set_u16(REG_SUBSYSTEM_ID, device_id);
+
+        function.set_mask(REG_COMMAND, 2, (COMMAND_IO | COMMAND_MEMORY | COMMAND_BUS_MASTER | COMMAND_INTX_DISABLE) as u32);
+        function.set_mask(REG_CACHE_LINE, 1, 0xFF);
+        function.set_mask(REG_LATENCY, 1, 0xFF);
+        function.set_mask(REG_INTERRUPT_LINE, 1, 0xFF);
+        function
+    }
+
+    /// A modern virtio-pci function for a virtio device type
+    pub fn virtio(virtio_type: u16, class_code: u32) -> Self {
+        PciFunction::new(PCI_VENDOR_VIRTIO, PCI_DEVICE_VIRTIO_BASE + virtio_type, class_code, 1)
+    }
+
+    pub fn vendor_id(&self) -> u16 {
+        self.get_u16(REG_VENDOR_ID)
+    }
+
+    pub fn device_id(&self) -> u16 {
+        self.get_u16(REG_DEVICE_ID)
+    }
+
+    pub fn class_code(&self) -> u32 {
+        self.get_u32(REG_REVISION) >> 8
+    }
+
+    pub fn command(&self) -> u16 {
+        self.get_u16(REG_COMMAND)
+    }
+
+    /// Legacy interrupt pin, 1 for INTA# to 4 for INTD#, 0 for none
+    pub fn set_interrupt_pin(&mut self, pin: u8) {
+        self.config[REG_INTERRUPT_PIN] = pin.min(4);
+    }
+
+    /// Legacy interrupt line as firmware routes it
+    pub fn set_interrupt_line(&mut self, line: u8) {
+        self.config[REG_INTERRUPT_LINE] = line;
+    }
+
+    pub fn interrupt_line(&self) -> u8 {
+        self.config[REG_INTERRUPT_LINE]
+    }
+
+    /// Declare a BAR; a 64-bit BAR also takes index + 1
+    pub fn add_bar(&mut self, index: usize, bar: PciBar) -> Result<(), HypervisorError> {
+        let wide = matches!(bar.kind, PciBarKind::Memory64 { .. });
+        let minimum = if bar.kind == PciBarKind::Io { 4 } else { 16 };
+        if index >= PCI_BAR_COUNT || (wide && index + 1 >= PCI_BAR_COUNT) {
+            return Err(HypervisorError::InvalidParameter);
+        }
+        if !bar.size.is_power_of_two() || bar.size < minimum || (!wide && bar.size > 1 << 31) {
+            return Err(ConfigError::new("pci.bar.size", ConfigErrorReason::OutOfRange).value(bar.size).into());
+        }
+        if self.bars[index].is_some() || (wide && self.bars[index + 1].is_some()) || self.is_upper_half(index) {
+            return Err(ConfigError::new("pci.bar", ConfigErrorReason::AlreadyExists).value(index as u64).into());
+        }
+
+        let offset = REG_BAR0 + index * 4;
+        let (flags, address_mask) = match bar.kind {
+            PciBarKind::Io => (BAR_IO, !(bar.size as u32 - 1) & !0x3),
+            PciBarKind::Memory32 { prefetchable } => {
+                (if prefetchable { BAR_PREFETCH } else { 0 }, !(bar.size as u32 - 1) & !0xF)
+            }
+            PciBarKind::Memory64 { prefetchable } => {
+                (BAR_MEM64 | if prefetchable { BAR_PREFETCH } else { 0 }, !((bar.size - 1) as u32) & !0xF)
+            }
+        };
+        self.set_u32(offset, flags);
+        self.set_mask(offset, 4, address_mask);
+        if wide {
+            self.set_u32(offset + 4, 0);
+            self.set_mask(offset + 4, 4, !((bar.size - 1) >> 32) as u32);
+        }
+        self.bars[index] = Some(bar);
+        Ok(())
+    }
+
+    /// Program a BAR as firmware would
+    pub fn set_bar_address(&mut self, index: usize, address: u64) -> Result<(), HypervisorError> {
+        let bar = self.bars.get(index).copied().flatten().ok_or(HypervisorError::InvalidParameter)?;
+        if address & (bar.size - 1) != 0 {
+            return Err(ConfigError::new("pci.bar.address", ConfigErrorReason::Misaligned).value(address).into());
+        }
+        let wide = matches!(bar.kind, PciBarKind::Memory64 { .. });
+        if !wide && address >> 32 != 0 {
+            return Err(HypervisorError::InvalidParameter);
+        }
+        let offset = REG_BAR0 + index * 4;
+        let flags = self.get_u32(offset) & if bar.kind == PciBarKind::Io { 0x3 } else { 0xF };
+        self.set_u32(offset, address as u32 | flags);
+        if wide {
+            self.set_u32(offset + 4, (address >> 32) as u32);
+        }
+        Ok(())
+    }
+
+    /// BARs with the addresses the guest or firmware programmed
+    pub fn bar_assignments(&self) -> Vec<PciBarAssignment> {
+        self.bars
+            .iter()
+            .enumerate()
+            .filter_map(|(index, bar)| bar.map(|bar| PciBarAssignment { index, bar, address: self.bar_address(index) }))
+            .collect()
+    }
+
+    /// Current address of a BAR, 0 if unassigned
+    pub fn bar_address(&self, index: usize) -> u64 {
+        let bar = match self.bars.get(index).copied().flatten() {
+            Some(bar) => bar,
+            None => return 0,
+        };
+        let offset = REG_BAR0 + index * 4;
+        let low = self.get_u32(offset);
+        match bar.kind {
+            PciBarKind::Io => (low & !0x3) as u64,
+            PciBarKind::Memory32 { .. } => (low & !0xF) as u64,
+            PciBarKind::Memory64 { .. } => (self.get_u32(offset + 4) as u64) << 32 | (low & !0xF) as u64,
+        }
+    }
+
+    /// Whether accesses to the BAR's address reach the function
+    pub fn bar_decoding(&self, index: usize) -> bool {
+        let bit = match self.bars.get(index).copied().flatten() {
+            Some(PciBar { kind: PciBarKind::Io, .. }) => COMMAND_IO,
+            Some(_) => COMMAND_MEMORY,
+            None => return false,
+        };
+        self.command() & bit != 0 && self.bar_address(index) != 0
+    }
+
+    /// Add an MSI capability with `vectors` (a power of two up to 32)
+    /// vectors and a 64-bit message address
+    pub fn add_msi(&mut self, vectors: u8) -> Result<usize, HypervisorError> {
+        if self.msi_cap.is_some() || !vectors.is_power_of_two() || vectors > 32 {
+            return Err(HypervisorError::InvalidParameter);
+        }
+        let cap = self.add_capability(PCI_CAP_ID_MSI, MSI_CAP_SIZE)?;
+        let multiple_capable = vectors.trailing_zeros() as u16;
+        self.set_u16(cap + 2, MSI_CONTROL_64BIT | multiple_capable << 1);
+        // Enable and multiple message enable
+        self.set_mask(cap + 2, 2, (MSI_CONTROL_ENABLE | 0x7 << 4) as u32);
+        self.set_mask(cap + 4, 4, !0x3);
+        self.set_mask(cap + 8, 4, 0xFFFF_FFFF);
+        self.set_mask(cap + 12, 2, 0xFFFF);
+        self.msi_cap = Some(cap);
+        Ok(cap)
+    }
+
+    /// Add an MSI-X capability whose table and pending bit array live in
+    /// memory BARs the function already declared
+    pub fn add_msix(&mut self, vectors: u16, table_bar: usize, table_offset: u64, pba_bar: usize, pba_offset: u64) -> Result<usize, HypervisorError> {
+        if self.msix.is_some() || vectors == 0 || vectors > PCI_MSIX_MAX_VECTORS {
+            return Err(HypervisorError::InvalidParameter);
+        }
+        let table_size = vectors as u64 * MSIX_ENTRY_SIZE;
+        let pba_size = ((vectors as u64 + 63) / 64) * 8;
+        for &(bar, offset, size) in &[(table_bar, table_offset, table_size), (pba_bar, pba_offset, pba_size)] {
+            match self.bars.get(bar).copied().flatten() {
+                Some(PciBar { kind, size: bar_size }) if kind != PciBarKind::Io && offset & 0x7 == 0 && offset + size <= bar_size => {}
+                _ => return Err(ConfigError::new("pci.msix.bar", ConfigErrorReason::OutOfRange).value(bar as u64).into()),
+            }
+        }
+
+        let cap = self.add_capability(PCI_CAP_ID_MSIX, MSIX_CAP_SIZE)?;
+        self.set_u16(cap + 2, vectors - 1);
+        self.set_mask(cap + 2, 2, (MSIX_CONTROL_ENABLE | MSIX_CONTROL_MASK_ALL) as u32);
+        self.set_u32(cap + 4, table_offset as u32 | table_bar as u32);
+        self.set_u32(cap + 8, pba_offset as u32 | pba_bar as u32);
+        self.msix = Some(MsixLayout { cap, vectors, table_bar, table_offset, pba_bar, pba_offset });
+        // Entries start masked
+        self.msix_table = vec![[0, 0, 0, MSIX_ENTRY_MASKED]; vectors as usize];
+        self.msix_pending = vec![0; ((vectors as usize) + 63) / 64];
+        Ok(cap)
+    }
+
+    /// Add a vendor-specific capability; `body` follows the length byte
+    pub fn add_vendor_capability(&mut self, body: &[u8]) -> Result<usize, HypervisorError> {
+        let cap = self.add_capability(PCI_CAP_ID_VENDOR, body.len() + 3)?;
+        self.config[cap + 2] = (body.len() + 3) as u8;
+        self.config[cap + 3..cap + 3 + body.len()].copy_from_slice(body);
+        Ok(cap)
+    }
+
+    /// Message to signal `vector` with: MSI-X if enabled, else MSI.
+    /// `None` means the vector is masked or the function uses INTx; a
+    /// masked MSI-X vector is left pending and delivered when unmasked.
+    pub fn msi_message(&mut self, vector: u16) -> Option<MsiMessage> {
+        if let Some(msix) = self.msix {
+            let control = self.get_u16(msix.cap + 2);
+            if control & MSIX_CONTROL_ENABLE != 0 {
+                let entry = *self.msix_table.get(vector as usize)?;
+                if control & MSIX_CONTROL_MASK_ALL != 0 || entry[3] & MSIX_ENTRY_MASKED != 0 {
+                    self.msix_pending[vector as usize / 64] |= 1 << (vector % 64);
+                    return None;
+                }
+                return Some(MsiMessage { address: (entry[1] as u64) << 32 | entry[0] as u64, data: entry[2] });
+            }
+        }
+
+        let cap = self.msi_cap?;
+        let control = self.get_u16(cap + 2);
+        if control & MSI_CONTROL_ENABLE == 0 {
+            return None;
+        }
+        let enabled_vectors = 1u16 << ((control >> 4) & 0x7);
+        if vector >= enabled_vectors {
+            return None;
+        }
+        let address = (self.get_u32(cap + 8) as u64) << 32 | self.get_u32(cap + 4) as u64;
+        // The low bits of the data select the vector
+        let data = (self.get_u16(cap + 12) as u32 & !(enabled_vectors as u32 - 1)) | vector as u32;
+        Some(MsiMessage { address, data })
+    }
+
+    /// Pending MSI-X vectors that are now unmasked, cleared as returned
+    pub fn take_unmasked_pending(&mut self) -> Vec<(u16, MsiMessage)> {
+        let mut delivered = Vec::new();
+        let vectors = self.msix.map_or(0, |msix| msix.vectors);
+        for vector in 0..vectors {
+            if self.msix_pending[vector as usize / 64] & (1 << (vector % 64)) == 0 {
+                continue;
+            }
+            self.msix_pending[vector as usize / 64] &= !(1 << (vector % 64));
+            if let Some(message) = self.msi_message(vector) {
+                delivered.push((vector, message));
+            }
+        }
+        delivered
+    }
+
+    /// Whether the interrupt is signalled with INTx rather than messages
+    pub fn uses_intx(&self) -> bool {
+        let msi = self.msi_cap.map_or(false, |cap| self.get_u16(cap + 2) & MSI_CONTROL_ENABLE != 0);
+        let msix = self.msix.map_or(false, |msix| self.get_u16(msix.cap + 2) & MSIX_CONTROL_ENABLE != 0);
+        !msi && !msix && self.command() & COMMAND_INTX_DISABLE == 0 && self.config[REG_INTERRUPT_PIN] != 0
+    }
+
+    /// Config space read of 1, 2 or 4 bytes
+    pub fn config_read(&self, offset: usize, size: usize) -> u32 {
+        if !matches!(size, 1 | 2 | 4) || offset + size > PCI_CONFIG_SIZE {
+            return 0xFFFF_FFFF;
+        }
+        let mut bytes = [0u8; 4];
+        bytes[..size].copy_from_slice(&self.config[offset..offset + size]);
+        u32::from_le_bytes(bytes)
+    }
+
+    /// Config space write of 1, 2 or 4 bytes; read-only bits are kept
+    pub fn config_write(&mut self, offset: usize, size: usize, value: u32) {
+        if !matches!(size, 1 | 2 | 4) || offset + size > PCI_CONFIG_SIZE {
+            return;
+        }
+        for (index, byte) in value.to_le_bytes()[..size].iter().enumerate() {
+            let mask = self.write_mask[offset + index];
+            let old = self.config[offset + index];
+            self.config[offset + index] = (old & !mask) | (byte & mask);
+        }
+    }
+
+    /// MSI-X table or PBA access at `offset` into BAR `bar`; `None` if the
+    /// access is for the device model
+    fn msix_read(&self, bar: usize, offset: u64, size: usize) -> Option<u64> {
+        let msix = self.msix?;
+        let table_end = msix.table_offset + msix.vectors as u64 * MSIX_ENTRY_SIZE;
+        if bar == msix.table_bar && (msix.table_offset..table_end).contains(&offset) {
+            let relative = offset - msix.table_offset;
+            let entry = &self.msix_table[(relative / MSIX_ENTRY_SIZE) as usize];
+            let word = ((relative % MSIX_ENTRY_SIZE) / 4) as usize;
+            let value = if size == 8 && word % 2 == 0 {
+                (entry[word + 1] as u64) << 32 | entry[word] as u64
+            } else {
+                entry[word] as u64
+            };
+            return Some(value);
+        }
+        let pba_end = msix.pba_offset + self.msix_pending.len() as u64 * 8;
+        if bar == msix.pba_bar && (msix.pba_offset..pba_end).contains(&offset) {
+            let relative = offset - msix.pba_offset;
+            let qword = self.msix_pending[(relative / 8) as usize];
+            return Some(if size == 8 { qword } else { qword >> ((relative % 8) * 8) & 0xFFFF_FFFF });
+        }
+        None
+    }
+
+    /// MSI-X table write; the PBA is read-only. Returns whether it was one.
+    fn msix_write(&mut self, bar: usize, offset: u64, value: u64, size: usize) -> bool {
+        let msix = match self.msix {
+            Some(msix) => msix,
+            None => return false,
+        };
+        let table_end = msix.table_offset + msix.vectors as u64 * MSIX_ENTRY_SIZE;
+        if bar == msix.table_bar && (msix.table_offset..table_end).contains(&offset) {
+            let relative = offset - msix.table_offset;
+            let entry = &mut self.msix_table[(relative / MSIX_ENTRY_SIZE) as usize];
+            let word = ((relative % MSIX_ENTRY_SIZE) / 4) as usize;
+            entry[word] = value as u32;
+            if size == 8 && word % 2 == 0 {
+                entry[word + 1] = (value >> 32) as u32;
+            }
+            return true;
+        }
+        let pba_end = msix.pba_offset + self.msix_pending.len() as u64 * 8;
+        bar == msix.pba_bar && (msix.pba_offset..pba_end).contains(&offset)
+    }
+
+    fn add_capability(&mut self, id: u8, size: usize) -> Result<usize, HypervisorError> {
+        let cap = self.next_cap;
+        if cap + size > CAP_END {
+            return Err(ConfigError::new("pci.capabilities", ConfigErrorReason::Exhausted).into());
+        }
+        self.config[cap] = id;
+        self.config[cap + 1] = 0;
+        match self.last_cap {
+            Some(last) => self.config[last + 1] = cap as u8,
+            None => self.config[REG_CAPABILITIES] = cap as u8,
+        }
+        let status = self.get_u16(REG_STATUS) | STATUS_CAP_LIST;
+        self.set_u16(REG_STATUS, status);
+        self.last_cap = Some(cap);
+        // Capabilities are dword aligned
+        self.next_cap = (cap + size + 3) & !3;
+        Ok(cap)
+    }
+
+    fn is_upper_half(&self, index: usize) -> bool {
+        index > 0 && matches!(self.bars[index - 1], Some(PciBar { kind: PciBarKind::Memory64 { .. }, .. }))
+    }
+
+    fn set_multifunction(&mut self) {
+        self.config[REG_HEADER_TYPE] |= HEADER_MULTIFUNCTION;
+    }
+
+    fn get_u16(&self, offset: usize) -> u16 {
+        u16::from_le_bytes([self.config[offset], self.config[offset + 1]])
+    }
+
+    fn get_u32(&self, offset: usize) -> u32 {
+        self.config_read(offset, 4)
+    }
+
+    fn set_u16(&mut self, offset: usize, value: u16) {
+        self.config[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn set_u32(&mut self, offset: usize, value: u32) {
+        self.config[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn set_mask(&mut self, offset: usize, size: usize, mask: u32) {
+        self.write_mask[offset..offset + size].copy_from_slice(&mask.to_le_bytes()[..size]);
+    }
+}
+
+/// A function found by enumerating the bus
+#[derive(Debug, Clone)]
+pub struct PciDeviceInfo {
+    pub address: PciAddress,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class_code: u32,
+    /// Device framework device backing the function
+    pub backing_device: Option<String>,
+    pub bars: Vec<PciBarAssignment>,
+    pub interrupt_line: u8,
+}
+
+/// A guest memory or port access that hit a BAR
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PciBarHit {
+    pub address: PciAddress,
+    pub bar: usize,
+    pub offset: u64,
+    pub backing_device: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+struct PciSlot {
+    function: PciFunction,
+    backing_device: Option<String>,
+}
+
+/// Emulated PCI host bridge with bus 0 behind it
+#[derive(Debug, Clone)]
+pub struct PciHostBridge {
+    functions: BTreeMap<PciAddress, PciSlot>,
+    /// Last value written to CONFIG_ADDRESS
+    config_address: u32,
+    ecam_base: u64,
+}
+
+impl PciHostBridge {
+    /// A bridge at 00:00.0 with its ECAM window at `ecam_base`
+    pub fn new(ecam_base: u64) -> Self {
+        let mut functions = BTreeMap::new();
+        functions.insert(PciAddress::new(0, 0, 0), PciSlot {
+            function: PciFunction::new(HOST_BRIDGE_VENDOR, HOST_BRIDGE_DEVICE, CLASS_HOST_BRIDGE, 0),
+            backing_device: None,
+        });
+        PciHostBridge { functions, config_address: 0, ecam_base }
+    }
+
+    pub fn ecam_base(&self) -> u64 {
+        self.ecam_base
+    }
+
+    /// Put a function in the first free slot
+    pub fn add_function(&mut self, function: PciFunction, backing_device: Option<String>) -> Result<PciAddress, HypervisorError> {
+        let address = (1..PCI_SLOTS)
+            .map(|device| PciAddress::new(0, device, 0))
+            .find(|address| !self.functions.contains_key(address))
+            .ok_or_else(|| HypervisorError::from(ConfigError::new("pci.slot", ConfigErrorReason::Exhausted)))?;
+        self.add_function_at(address, function, backing_device)?;
+        Ok(address)
+    }
+
+    /// Put a function at a fixed address; function 0 of a device must be
+    /// added before its other functions
+    pub fn add_function_at(&mut self, address: PciAddress, function: PciFunction, backing_device: Option<String>) -> Result<(), HypervisorError> {
+        if address.bus != 0 || self.functions.contains_key(&address) {
+            return Err(ConfigError::new("pci.address", ConfigErrorReason::AlreadyExists).value(address.bdf() as u64).into());
+        }
+        if address.function != 0 {
+            let first = PciAddress::new(0, address.device, 0);
+            let slot = self.functions.get_mut(&first)
+                .ok_or_else(|| HypervisorError::from(ConfigError::new("pci.function", ConfigErrorReason::NotFound).value(first.bdf() as u64)))?;
+            slot.function.set_multifunction();
+        }
+        info!("PCI {}: {:04x}:{:04x} class {:06x}", address, function.vendor_id(), function.device_id(), function.class_code());
+        self.functions.insert(address, PciSlot { function, backing_device });
+        Ok(())
+    }
+
+    pub fn remove_function(&mut self, address: PciAddress) -> Result<PciFunction, HypervisorError> {
+        if address == PciAddress::new(0, 0, 0) {
+            return Err(HypervisorError::InvalidParameter);
+        }
+        self.functions.remove(&address)
+            .map(|slot| slot.function)
+            .ok_or(HypervisorError::InvalidParameter)
+    }
+
+    pub fn function(&self, address: PciAddress) -> Option<&PciFunction> {
+        self.functions.get(&address).map(|slot| &slot.function)
+    }
+
+    pub fn function_mut(&mut self, address: PciAddress) -> Option<&mut PciFunction> {
+        self.functions.get_mut(&address).map(|slot| &mut slot.function)
+    }
+
+    /// Framework device backing a function
+    pub fn backing_device(&self, address: PciAddress) -> Option<&str> {
+        self.functions.get(&address).and_then(|slot| slot.backing_device.as_deref())
+    }
+
+    /// Address of the function backed by a framework device
+    pub fn address_of(&self, device_id: &str) -> Option<PciAddress> {
+        self.functions
+            .iter()
+            .find(|(_, slot)| slot.backing_device.as_deref() == Some(device_id))
+            .map(|(&address, _)| address)
+    }
+
+    /// Assign every unprogrammed BAR from the bridge windows, largest
+    /// first for natural alignment, and enable decoding, as firmware does
+    pub fn assign_resources(&mut self) -> Result<(), HypervisorError> {
+        let mut pending: Vec<(PciAddress, usize, PciBar)> = self.functions
+            .iter()
+            .flat_map(|(&address, slot)| {
+                slot.function.bar_assignments()
+                    .into_iter()
+                    .filter(|assignment| assignment.address == 0)
+                    .map(move |assignment| (address, assignment.index, assignment.bar))
+            })
+            .collect();
+        pending.sort_by(|a, b| b.2.size.cmp(&a.2.size).then(a.0.cmp(&b.0)).then(a.1.cmp(&b.1)));
+
+        let mut io_next = PCI_IO_BASE;
+        let mut mmio32_next = PCI_MMIO32_BASE;
+        let mut mmio64_next = PCI_MMIO64_BASE;
+        for (address, index, bar) in pending {
+            let (next, end) = match bar.kind {
+                PciBarKind::Io => (&mut io_next, PCI_IO_BASE + PCI_IO_SIZE),
+                PciBarKind::Memory32 { .. } => (&mut mmio32_next, PCI_MMIO32_BASE + PCI_MMIO32_SIZE),
+                PciBarKind::Memory64 { .. } => (&mut mmio64_next, PCI_MMIO64_BASE + PCI_MMIO64_SIZE),
+            };
+            let base = (*next + bar.size - 1) & !(bar.size - 1);
+            if base + bar.size > end {
+                warn!("No room for BAR{} of PCI {}", index, address);
+                return Err(ConfigError::new("pci.bar.address", ConfigErrorReason::Exhausted).value(bar.size).into());
+            }
+            *next = base + bar.size;
+            if let Some(function) = self.function_mut(address) {
+                function.set_bar_address(index, base)?;
+            }
+        }
+
+        for slot in self.functions.values_mut() {
+            let bars = slot.function.bar_assignments();
+            let mut command = slot.function.command();
+            if bars.iter().any(|assignment| assignment.bar.kind == PciBarKind::Io) {
+                command |= COMMAND_IO;
+            }
+            if bars.iter().any(|assignment| assignment.bar.kind != PciBarKind::Io) {
+                command |= COMMAND_MEMORY;
+            }
+            slot.function.config_write(REG_COMMAND, 2, (command | COMMAND_BUS_MASTER) as u32);
+        }
+        Ok(())
+    }
+
+    /// Functions on the bus, as a guest scanning config space finds them
+    pub fn enumerate(&self) -> Vec<PciDeviceInfo> {
+        self.functions
+            .iter()
+            .map(|(&address, slot)| PciDeviceInfo {
+                address,
+                vendor_id: slot.function.vendor_id(),
+                device_id: slot.function.device_id(),
+                class_code: slot.function.class_code(),
+                backing_device: slot.backing_device.clone(),
+                bars: slot.function.bar_assignments(),
+                interrupt_line: slot.function.interrupt_line(),
+            })
+            .collect()
+    }
+
+    /// Function CONFIG_ADDRESS currently selects
+    pub fn selected_function(&self) -> Option<PciAddress> {
+        PciAddress::from_config_address(self.config_address).map(|(address, _)| address)
+    }
+
+    /// Port read; the CONFIG_ADDRESS latch or config data through CFC
+    pub fn io_read(&self, port: u16, size: usize) -> Option<u32> {
+        match port {
+            PCI_CONFIG_ADDRESS if size == 4 => Some(self.config_address),
+            PCI_CONFIG_DATA..=0xCFF => {
+                let (address, register) = PciAddress::from_config_address(self.config_address)?;
+                Some(self.config_read(address, register + (port - PCI_CONFIG_DATA) as usize, size))
+            }
+            _ => None,
+        }
+    }
+
+    /// Port write; returns whether the port belongs to the bridge
+    pub fn io_write(&mut self, port: u16, size: usize, value: u32) -> bool {
+        match port {
+            PCI_CONFIG_ADDRESS if size == 4 => {
+                self.config_address = value;
+                true
+            }
+            PCI_CONFIG_DATA..=0xCFF => {
+                if let Some((address, register)) = PciAddress::from_config_address(self.config_address) {
+                    self.config_write(address, register + (port - PCI_CONFIG_DATA) as usize, size, value);
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Read at an offset into the ECAM window
+    pub fn ecam_read(&self, offset: u64, size: usize) -> u32 {
+        let (address, register) = PciAddress::from_ecam_offset(offset);
+        self.config_read(address, register, size)
+    }
+
+    /// Write at an offset into the ECAM window; returns the function
+    /// addressed
+    pub fn ecam_write(&mut self, offset: u64, size: usize, value: u32) -> PciAddress {
+        let (address, register) = PciAddress::from_ecam_offset(offset);
+        self.config_write(address, register, size, value);
+        address
+    }
+
+    /// BAR a guest physical address or port falls in, among BARs that are
+    /// decoding
+    pub fn route(&self, guest_address: u64, io: bool) -> Option<PciBarHit> {
+        self.functions.iter().find_map(|(&address, slot)| {
+            slot.function.bar_assignments().into_iter().find_map(|assignment| {
+                let in_range = (assignment.bar.kind == PciBarKind::Io) == io
+                    && slot.function.bar_decoding(assignment.index)
+                    && assignment.address.checked_add(assignment.bar.size)
+                        .is_some_and(|end| (assignment.address..end).contains(&guest_address));
+                if !in_range {
+                    return None;
+                }
+                Some(PciBarHit {
+                    address,
+                    bar: assignment.index,
+                    offset: guest_address - assignment.address,
+                    backing_device: slot.backing_device.clone(),
+                })
+            })
+        })
+    }
+
+    /// Serve a BAR read the bridge emulates itself (the MSI-X table and PBA)
+    pub fn bar_read(&self, hit: &PciBarHit, size: usize) -> Option<u64> {
+        self.function(hit.address)?.msix_read(hit.bar, hit.offset, size)
+    }
+
+    /// Serve a BAR write the bridge emulates itself; returns whether it did
+    pub fn bar_write(&mut self, hit: &PciBarHit, value: u64, size: usize) -> bool {
+        match self.function_mut(hit.address) {
+            Some(function) => function.msix_write(hit.bar, hit.offset, value, size),
+            None => false,
+        }
+    }
+
+    /// MCFG table describing the ECAM window
+    pub fn build_mcfg(&self) -> Vec<u8> {
+        let mut table = acpi_header(b"MCFG", MCFG_REVISION);
+        table.extend_from_slice(&[0; 8]);
+        table.extend_from_slice(&self.ecam_base.to_le_bytes());
+        // Segment group 0, buses 0 to 0
+        table.extend_from_slice(&0u16.to_le_bytes());
+        table.extend_from_slice(&[0, 0]);
+        table.extend_from_slice(&[0; 4]);
+        finish_acpi_table(table)
+    }
+
+    /// Absent functions read as all ones
+    fn config_read(&self, address: PciAddress, register: usize, size: usize) -> u32 {
+        match self.functions.get(&address) {
+            Some(slot) => slot.function.config_read(register, size),
+            None => match size {
+                1 => 0xFF,
+                2 => 0xFFFF,
+                _ => 0xFFFF_FFFF,
+            },
+        }
+    }
+
+    fn config_write(&mut self, address: PciAddress, register: usize, size: usize, value: u32) {
+        if let Some(slot) = self.functions.get_mut(&address) {
+            slot.function.config_write(register, size, value);
+        }
+    }
+}
+
+// Saved state fields
+const STATE_CONFIG: u16 = 1;
+const STATE_MSIX_TABLE: u16 = 2;
+const STATE_MSIX_PENDING: u16 = 3;
+const STATE_CONFIG_ADDRESS: u16 = 1;
+/// Function at bus:device.function B is saved under STATE_FUNCTION + B
+const STATE_FUNCTION: u16 = 0x1000;
+
+/// The writable parts of config space and the MSI-X table; the read-only
+/// identity comes from the restoring side's device model
+impl DeviceStateSerialize for PciFunction {
+    fn state_version(&self) -> u16 {
+        1
+    }
+
+    fn save_state(&self, writer: &mut DeviceStateWriter) {
+        writer.put_bytes(STATE_CONFIG, &self.config);
+        let table: Vec<u64> = self.msix_table
+            .iter()
+            .flat_map(|entry| [(entry[1] as u64) << 32 | entry[0] as u64, (entry[3] as u64) << 32 | entry[2] as u64])
+            .collect();
+        writer.put_u64s(STATE_MSIX_TABLE, &table);
+        writer.put_u64s(STATE_MSIX_PENDING, &self.msix_pending);
+    }
+
+    fn restore_state(&mut self, reader: &DeviceStateReader) -> Result<(), HypervisorError> {
+        if let Some(config) = reader.bytes(STATE_CONFIG) {
+            for (offset, &saved) in config.iter().enumerate().take(PCI_CONFIG_SIZE) {
+                let mask = self.write_mask[offset];
+                self.config[offset] = (self.config[offset] & !mask) | (saved & mask);
+            }
+        }
+        if let Some(table) = reader.u64s(STATE_MSIX_TABLE) {
+            for (entry, words) in self.msix_table.iter_mut().zip(table.chunks_exact(2)) {
+                *entry = [words[0] as u32, (words[0] >> 32) as u32, words[1] as u32, (words[1] >> 32) as u32];
+            }
+        }
+        if let Some(pending) = reader.u64s(STATE_MSIX_PENDING) {
+            for (word, saved) in self.msix_pending.iter_mut().zip(pending) {
+                *word = saved;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl DeviceStateSerialize for PciHostBridge {
+    fn state_version(&self) -> u16 {
+        1
+    }
+
+    fn save_state(&self, writer: &mut DeviceStateWriter) {
+        writer.put_u32(STATE_CONFIG_ADDRESS, self.config_address);
+        for (address, slot) in &self.functions {
+            writer.put_nested(STATE_FUNCTION + address.bdf(), &slot.function);
+        }
+    }
+
+    fn restore_state(&mut self, reader: &DeviceStateReader) -> Result<(), HypervisorError> {
+        if let Some(config_address) = reader.u32(STATE_CONFIG_ADDRESS) {
+            self.config_address = config_address;
+        }
+        for tag in reader.tags().filter(|&tag| tag >= STATE_FUNCTION) {
+            let bdf = tag - STATE_FUNCTION;
+            let address = PciAddress::new((bdf >> 8) as u8, (bdf >> 3) as u8, bdf as u8);
+            let slot = self.functions.get_mut(&address)
+                .ok_or_else(|| HypervisorError::from(ConfigError::new("pci.function", ConfigErrorReason::Mismatch).value(bdf as u64)))?;
+            reader.restore_nested(tag, &mut slot.function)?;
+        }
+        Ok(())
+    }
+}
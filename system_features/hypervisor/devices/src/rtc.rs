@@ -0,0 +1,473 @@
+//! MC146818 RTC and CMOS NVRAM
+//!
+//! The guest reads wall-clock time through index/data ports 0x70/0x71. The
+//! time is kept as an offset from the host clock rather than ticked, and
+//! encoded on read in the BCD or binary, 12 or 24 hour format the guest
+//! chose in register B. Setting the time while SET is held moves the
+//! offset. The periodic, alarm and update-ended interrupts latch their
+//! flags in register C and raise IRQ 8 until the guest reads it.
+
+use crate::HypervisorError;
+use crate::core::RtcBase;
+use super::{DeviceInterrupt, DeviceStateReader, DeviceStateSerialize, DeviceStateWriter, PCI_ECAM_BASE};
+
+use alloc::vec::Vec;
+
+/// Index and data ports
+pub const RTC_INDEX_PORT: u16 = 0x70;
+pub const RTC_DATA_PORT: u16 = 0x71;
+/// ISA interrupt of the RTC
+pub const RTC_IRQ: u32 = 8;
+
+pub const CMOS_SIZE: usize = 128;
+
+const NS_PER_SEC: u64 = 1_000_000_000;
+/// Update-in-progress is reported this long before each second
+const UIP_WINDOW_NS: u64 = 244_000;
+
+// Clock and control registers
+const REG_SECONDS: usize = 0x00;
+const REG_SECONDS_ALARM: usize = 0x01;
+const REG_MINUTES: usize = 0x02;
+const REG_MINUTES_ALARM: usize = 0x03;
+const REG_HOURS: usize = 0x04;
+const REG_HOURS_ALARM: usize = 0x05;
+const REG_WEEKDAY: usize = 0x06;
+const REG_DAY: usize = 0x07;
+const REG_MONTH: usize = 0x08;
+const REG_YEAR: usize = 0x09;
+const REG_A: usize = 0x0A;
+const REG_B: usize = 0x0B;
+const REG_C: usize = 0x0C;
+const REG_D: usize = 0x0D;
+const REG_CENTURY: usize = 0x32;
+
+// NVRAM memory size fields read by firmware
+const CMOS_BASE_MEMORY: usize = 0x15;
+const CMOS_EXTENDED_MEMORY: usize = 0x17;
+const CMOS_EXTENDED_MEMORY_ALT: usize = 0x30;
+const CMOS_MEMORY_ABOVE_16M: usize = 0x34;
+const CMOS_MEMORY_ABOVE_4G: usize = 0x5B;
+
+const REG_A_UIP: u8 = 1 << 7;
+/// 32.768 kHz time base, divider running
+const REG_A_DEFAULT: u8 = 0x26;
+const REG_A_RATE_MASK: u8 = 0x0F;
+const REG_B_SET: u8 = 1 << 7;
+const REG_B_PIE: u8 = 1 << 6;
+const REG_B_AIE: u8 = 1 << 5;
+const REG_B_UIE: u8 = 1 << 4;
+const REG_B_BINARY: u8 = 1 << 2;
+const REG_B_24H: u8 = 1 << 1;
+const REG_C_IRQF: u8 = 1 << 7;
+const REG_C_PF: u8 = 1 << 6;
+const REG_C_AF: u8 = 1 << 5;
+const REG_C_UF: u8 = 1 << 4;
+const REG_D_VRT: u8 = 1 << 7;
+/// Alarm bytes at or above this match any value
+const ALARM_DONT_CARE: u8 = 0xC0;
+const HOUR_PM: u8 = 1 << 7;
+
+/// Broken-down time as the RTC shows it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RtcTime {
+    pub year: i64,
+    pub month: u8,
+    pub day: u8,
+    /// 1 for Sunday to 7 for Saturday
+    pub weekday: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl RtcTime {
+    pub fn from_epoch(seconds: i64) -> Self {
+        let days = seconds.div_euclid(86400);
+        let of_day = seconds.rem_euclid(86400);
+        let (year, month, day) = civil_from_days(days);
+        RtcTime {
+            year,
+            month,
+            day,
+            // 1970-01-01 was a Thursday
+            weekday: ((days + 4).rem_euclid(7) + 1) as u8,
+            hour: (of_day / 3600) as u8,
+            minute: (of_day / 60 % 60) as u8,
+            second: (of_day % 60) as u8,
+        }
+    }
+
+    pub fn to_epoch(&self) -> i64 {
+        days_from_civil(self.year, self.month, self.day) * 86400
+            + self.hour as i64 * 3600
+            + self.minute as i64 * 60
+            + self.second as i64
+    }
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date
+fn days_from_civil(year: i64, month: u8, day: u8) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = month as i64;
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, u8, u8) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u8;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u8;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn to_bcd(value: u8) -> u8 {
+    (value / 10) << 4 | value % 10
+}
+
+fn from_bcd(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0x0F)
+}
+
+/// MC146818 real-time clock with CMOS NVRAM
+#[derive(Debug, Clone)]
+pub struct Rtc {
+    cmos: [u8; CMOS_SIZE],
+    index: u8,
+    nmi_disabled: bool,
+    /// Guest time in seconds is this plus the clock in seconds
+    offset_secs: i64,
+    clock: fn() -> u64,
+    next_periodic_ns: Option<u64>,
+    /// Guest second the update-ended and alarm checks last ran for
+    last_update_secs: i64,
+    pending: Vec<DeviceInterrupt>,
+}
+
+impl Rtc {
+    /// An RTC starting at `start_time` (seconds since the Unix epoch, UTC)
+    /// shown in `base`; `clock` returns monotonic nanoseconds
+    pub fn new(base: RtcBase, start_time: u64, clock: fn() -> u64) -> Self {
+        let local_offset = match base {
+            RtcBase::Utc => 0,
+            RtcBase::LocalTime { offset_minutes } => offset_minutes as i64 * 60,
+        };
+        let now_secs = (clock() / NS_PER_SEC) as i64;
+        let mut cmos = [0u8; CMOS_SIZE];
+        cmos[REG_A] = REG_A_DEFAULT;
+        cmos[REG_B] = REG_B_24H;
+        cmos[REG_D] = REG_D_VRT;
+
+        let mut rtc = Rtc {
+            cmos,
+            index: 0,
+            nmi_disabled: false,
+            offset_secs: start_time as i64 + local_offset - now_secs,
+            clock,
+            next_periodic_ns: None,
+            last_update_secs: 0,
+            pending: Vec::new(),
+        };
+        rtc.last_update_secs = rtc.guest_secs();
+        rtc
+    }
+
+    /// Fill the NVRAM memory size fields firmware reads
+    pub fn set_memory_size(&mut self, bytes: u64) {
+        const KB: u64 = 1024;
+        const MB: u64 = 1024 * KB;
+        let below_4g = bytes.min(PCI_ECAM_BASE);
+        let above_4g = bytes.saturating_sub(PCI_ECAM_BASE);
+
+        self.set_word(CMOS_BASE_MEMORY, 640);
+        let extended = (below_4g.saturating_sub(MB) / KB).min(0xFFFF) as u16;
+        self.set_word(CMOS_EXTENDED_MEMORY, extended);
+        self.set_word(CMOS_EXTENDED_MEMORY_ALT, extended);
+        self.set_word(CMOS_MEMORY_ABOVE_16M, (below_4g.saturating_sub(16 * MB) / (64 * KB)).min(0xFFFF) as u16);
+        let blocks = above_4g / (64 * KB);
+        self.cmos[CMOS_MEMORY_ABOVE_4G] = blocks as u8;
+        self.cmos[CMOS_MEMORY_ABOVE_4G + 1] = (blocks >> 8) as u8;
+        self.cmos[CMOS_MEMORY_ABOVE_4G + 2] = (blocks >> 16) as u8;
+    }
+
+    /// Current guest time
+    pub fn time(&self) -> RtcTime {
+        RtcTime::from_epoch(self.guest_secs())
+    }
+
+    /// Whether NMIs are masked through bit 7 of the index port
+    pub fn nmi_disabled(&self) -> bool {
+        self.nmi_disabled
+    }
+
+    pub fn io_read(&mut self, port: u16) -> Option<u8> {
+        match port {
+            RTC_INDEX_PORT => Some(self.index | if self.nmi_disabled { 0x80 } else { 0 }),
+            RTC_DATA_PORT => Some(self.read_register(self.index as usize)),
+            _ => None,
+        }
+    }
+
+    /// Returns whether the port belongs to the RTC
+    pub fn io_write(&mut self, port: u16, value: u8) -> bool {
+        match port {
+            RTC_INDEX_PORT => {
+                self.index = value & 0x7F;
+                self.nmi_disabled = value & 0x80 != 0;
+                true
+            }
+            RTC_DATA_PORT => {
+                self.write_register(self.index as usize, value);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Latch periodic, alarm and update-ended events that came due and
+    /// raise IRQ 8 if an enabled one did
+    pub fn poll(&mut self) {
+        let now = (self.clock)();
+        let mut flags = 0;
+
+        if let Some(next) = self.next_periodic_ns {
+            if now >= next {
+                flags |= REG_C_PF;
+                let period = self.periodic_ns().unwrap_or(NS_PER_SEC);
+                let missed = (now - next) / period + 1;
+                self.next_periodic_ns = Some(next + missed * period);
+            }
+        }
+
+        let secs = self.guest_secs();
+        if self.cmos[REG_B] & REG_B_SET == 0 && secs != self.last_update_secs {
+            self.last_update_secs = secs;
+            flags |= REG_C_UF;
+            self.latch_time(secs);
+            if self.alarm_matches() {
+                flags |= REG_C_AF;
+            }
+        }
+        self.latch_flags(flags);
+    }
+
+    /// When `poll` next has something to do
+    pub fn next_deadline_ns(&self) -> Option<u64> {
+        let now = (self.clock)();
+        let next_second = if self.cmos[REG_B] & (REG_B_UIE | REG_B_AIE) != 0 {
+            Some((now / NS_PER_SEC + 1) * NS_PER_SEC)
+        } else {
+            None
+        };
+        match (self.next_periodic_ns, next_second) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    pub fn take_interrupts(&mut self) -> Vec<DeviceInterrupt> {
+        core::mem::take(&mut self.pending)
+    }
+
+    fn read_register(&mut self, index: usize) -> u8 {
+        match index {
+            REG_SECONDS | REG_MINUTES | REG_HOURS | REG_WEEKDAY | REG_DAY | REG_MONTH | REG_YEAR | REG_CENTURY => {
+                // While SET is held the guest sees what it wrote
+                if self.cmos[REG_B] & REG_B_SET == 0 {
+                    self.latch_time(self.guest_secs());
+                }
+                self.cmos[index]
+            }
+            REG_A => {
+                let now = (self.clock)();
+                let uip = now % NS_PER_SEC >= NS_PER_SEC - UIP_WINDOW_NS && self.cmos[REG_B] & REG_B_SET == 0;
+                self.cmos[REG_A] | if uip { REG_A_UIP } else { 0 }
+            }
+            REG_C => {
+                // Reading C acknowledges the interrupt
+                self.poll();
+                let flags = self.cmos[REG_C];
+                self.cmos[REG_C] = 0;
+                flags
+            }
+            REG_D => REG_D_VRT,
+            _ => self.cmos.get(index).copied().unwrap_or(0xFF),
+        }
+    }
+
+    fn write_register(&mut self, index: usize, value: u8) {
+        match index {
+            REG_A => {
+                self.cmos[REG_A] = value & !REG_A_UIP;
+                self.next_periodic_ns = self.periodic_ns().map(|period| (self.clock)() + period);
+            }
+            REG_B => {
+                let was_set = self.cmos[REG_B] & REG_B_SET != 0;
+                if value & REG_B_SET != 0 && !was_set {
+                    // Freeze the registers at the current time
+                    self.latch_time(self.guest_secs());
+                }
+                // Switching format re-encodes on the next read
+                self.cmos[REG_B] = value;
+                if value & REG_B_SET == 0 && was_set {
+                    self.commit_time();
+                }
+                if value & REG_B_PIE != 0 && self.next_periodic_ns.is_none() {
+                    self.next_periodic_ns = self.periodic_ns().map(|period| (self.clock)() + period);
+                }
+                // Events already flagged interrupt once enabled
+                self.latch_flags(0);
+            }
+            REG_C | REG_D => {}
+            _ => {
+                if let Some(byte) = self.cmos.get_mut(index) {
+                    *byte = value;
+                }
+            }
+        }
+    }
+
+    /// Set the offset from the time the guest wrote while SET was held
+    fn commit_time(&mut self) {
+        let year = self.decode(self.cmos[REG_YEAR]) as i64;
+        let century = match self.decode(self.cmos[REG_CENTURY]) {
+            0 => 20,
+            century => century as i64,
+        };
+        let hours = self.cmos[REG_HOURS];
+        let hour = if self.cmos[REG_B] & REG_B_24H != 0 {
+            self.decode(hours)
+        } else {
+            let hour = self.decode(hours & !HOUR_PM) % 12;
+            if hours & HOUR_PM != 0 { hour + 12 } else { hour }
+        };
+        let time = RtcTime {
+            year: century * 100 + year,
+            month: self.decode(self.cmos[REG_MONTH]).clamp(1, 12),
+            day: self.decode(self.cmos[REG_DAY]).clamp(1, 31),
+            weekday: 0,
+            hour,
+            minute: self.decode(self.cmos[REG_MINUTES]),
+            second: self.decode(self.cmos[REG_SECONDS]),
+        };
+        let now_secs = ((self.clock)() / NS_PER_SEC) as i64;
+        self.offset_secs = time.to_epoch() - now_secs;
+        self.last_update_secs = time.to_epoch();
+        info!("RTC set to {:04}-{:02}-{:02} {:02}:{:02}:{:02}", time.year, time.month, time.day, time.hour, time.minute, time.second);
+    }
+
+    /// Encode the time at `secs` into the clock registers
+    fn latch_time(&mut self, secs: i64) {
+        let time = RtcTime::from_epoch(secs);
+        self.cmos[REG_SECONDS] = self.encode(time.second);
+        self.cmos[REG_MINUTES] = self.encode(time.minute);
+        self.cmos[REG_HOURS] = self.encode_hour(time.hour);
+        self.cmos[REG_WEEKDAY] = self.encode(time.weekday);
+        self.cmos[REG_DAY] = self.encode(time.day);
+        self.cmos[REG_MONTH] = self.encode(time.month);
+        self.cmos[REG_YEAR] = self.encode(time.year.rem_euclid(100) as u8);
+        self.cmos[REG_CENTURY] = self.encode(time.year.div_euclid(100) as u8);
+    }
+
+    fn alarm_matches(&self) -> bool {
+        [(REG_SECONDS_ALARM, REG_SECONDS), (REG_MINUTES_ALARM, REG_MINUTES), (REG_HOURS_ALARM, REG_HOURS)]
+            .iter()
+            .all(|&(alarm, current)| self.cmos[alarm] >= ALARM_DONT_CARE || self.cmos[alarm] == self.cmos[current])
+    }
+
+    /// Set event flags and raise IRQ 8 when an enabled event is newly flagged
+    fn latch_flags(&mut self, flags: u8) {
+        self.cmos[REG_C] |= flags;
+        let enabled = (self.cmos[REG_B] & (REG_B_PIE | REG_B_AIE | REG_B_UIE)) << 2;
+        if self.cmos[REG_C] & enabled != 0 && self.cmos[REG_C] & REG_C_IRQF == 0 {
+            self.cmos[REG_C] |= REG_C_IRQF;
+            self.pending.push(DeviceInterrupt::Gsi(RTC_IRQ));
+        }
+    }
+
+    /// Periodic interrupt period selected by register A
+    fn periodic_ns(&self) -> Option<u64> {
+        let rate = match self.cmos[REG_A] & REG_A_RATE_MASK {
+            0 => return None,
+            // Rates 1 and 2 repeat rates 8 and 9
+            rate @ 1..=2 => rate + 7,
+            rate => rate,
+        };
+        Some((1u64 << (rate - 1)) * NS_PER_SEC / 32768)
+    }
+
+    fn guest_secs(&self) -> i64 {
+        self.offset_secs + ((self.clock)() / NS_PER_SEC) as i64
+    }
+
+    fn encode(&self, value: u8) -> u8 {
+        if self.cmos[REG_B] & REG_B_BINARY != 0 { value } else { to_bcd(value) }
+    }
+
+    fn decode(&self, value: u8) -> u8 {
+        if self.cmos[REG_B] & REG_B_BINARY != 0 { value } else { from_bcd(value) }
+    }
+
+    fn encode_hour(&self, hour: u8) -> u8 {
+        if self.cmos[REG_B] & REG_B_24H != 0 {
+            return self.encode(hour);
+        }
+        let twelve = if hour % 12 == 0 { 12 } else { hour % 12 };
+        self.encode(twelve) | if hour >= 12 { HOUR_PM } else { 0 }
+    }
+
+    fn set_word(&mut self, index: usize, value: u16) {
+        self.cmos[index] = value as u8;
+        self.cmos[index + 1] = (value >> 8) as u8;
+    }
+}
+
+// Saved state fields
+const STATE_CMOS: u16 = 1;
+const STATE_INDEX: u16 = 2;
+const STATE_GUEST_TIME: u16 = 3;
+const STATE_PERIODIC_REMAINING: u16 = 4;
+
+/// Time is saved as guest seconds and the periodic timer as time left, so
+/// the restoring side's clock can start anywhere
+impl DeviceStateSerialize for Rtc {
+    fn state_version(&self) -> u16 {
+        1
+    }
+
+    fn save_state(&self, writer: &mut DeviceStateWriter) {
+        writer.put_bytes(STATE_CMOS, &self.cmos);
+        writer.put_u8(STATE_INDEX, self.index | if self.nmi_disabled { 0x80 } else { 0 });
+        writer.put_u64(STATE_GUEST_TIME, self.guest_secs() as u64);
+        if let Some(next) = self.next_periodic_ns {
+            writer.put_u64(STATE_PERIODIC_REMAINING, next.saturating_sub((self.clock)()));
+        }
+    }
+
+    fn restore_state(&mut self, reader: &DeviceStateReader) -> Result<(), HypervisorError> {
+        if let Some(cmos) = reader.bytes(STATE_CMOS) {
+            let length = cmos.len().min(CMOS_SIZE);
+            self.cmos[..length].copy_from_slice(&cmos[..length]);
+        }
+        if let Some(index) = reader.u8(STATE_INDEX) {
+            self.index = index & 0x7F;
+            self.nmi_disabled = index & 0x80 != 0;
+        }
+        let now = (self.clock)();
+        if let Some(guest_secs) = reader.u64(STATE_GUEST_TIME) {
+            self.offset_secs = guest_secs as i64 - (now / NS_PER_SEC) as i64;
+            self.last_update_secs = guest_secs as i64;
+        }
+        self.next_periodic_ns = reader.u64(STATE_PERIODIC_REMAINING).map(|remaining| now + remaining);
+        Ok(())
+    }
+}
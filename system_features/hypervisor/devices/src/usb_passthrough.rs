@@ -0,0 +1,1401 @@
+//! USB Device Passthrough
+//!
+//! Hands a device enumerated by the host USB framework to a guest. The
+//! guest sees a single-port xHCI controller; its command ring is answered
+//! locally while transfer TRBs are forwarded to the claimed host device.
+
+use crate::{HypervisorError, ConfigError, ConfigErrorReason, IoError, IoErrorKind};
+use super::{
+    DeviceAccess, DeviceCapability, DeviceConfig, DeviceState, DeviceStats, DeviceType,
+    DeviceStateReader, DeviceStateSerialize, DeviceStateWriter, InterruptInfo, MmioRegion,
+    PciBar, PciBarKind, PciFunction, VirtualDevice,
+};
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use multios_usb::{
+    SecurityManager, TrustState, UsbDriverError, UsbFramework, UsbResult, UsbSecurityLevel,
+    UsbSetupPacket, UsbSpeed, UsbTransferStatus, UsbTRB, XhciController,
+};
+
+/// Operation name checked against the host security policy
+pub const USB_PASSTHROUGH_OPERATION: &str = "passthrough";
+
+/// Size of the emulated controller's MMIO window
+pub const VXHCI_MMIO_SIZE: u64 = 0x4000;
+
+/// PCI identity of the emulated controller (QEMU's xHCI, which guests
+/// drive with their generic xHCI driver)
+const VXHCI_PCI_VENDOR: u16 = 0x1B36;
+const VXHCI_PCI_DEVICE: u16 = 0x000D;
+const VXHCI_PCI_CLASS: u32 = 0x0C_03_30;
+/// MSI-X table and PBA, above the doorbells in BAR0
+const VXHCI_MSIX_TABLE: u64 = 0x3000;
+const VXHCI_MSIX_PBA: u64 = 0x3800;
+
+/// Guest-visible slot and device address of the passed-through device
+const VXHCI_SLOT_ID: u8 = 1;
+/// Root port the device is attached to
+const VXHCI_PORT: u8 = 1;
+
+/// Upper bound on TRBs consumed per doorbell, so a guest cannot stall the
+/// VMM with a ring that never runs dry
+const VXHCI_MAX_TRBS_PER_DOORBELL: usize = 256;
+/// Upper bound on consecutive link TRBs followed while fetching
+const VXHCI_MAX_LINK_HOPS: usize = 8;
+/// Largest buffer a single TRB can describe
+const VXHCI_MAX_TRB_LENGTH: u32 = 0x1FFFF;
+
+// Register layout of the emulated controller
+const VXHCI_CAPLENGTH: u64 = 0x20;
+const VXHCI_PORTSC: u64 = VXHCI_CAPLENGTH + 0x400;
+const VXHCI_XECP: u64 = 0x500;
+const VXHCI_RTSOFF: u64 = 0x1000;
+const VXHCI_DBOFF: u64 = 0x2000;
+const VXHCI_INTERRUPTER: u64 = VXHCI_RTSOFF + 0x20;
+
+// Capability registers
+const CAP_CAPLENGTH: u64 = 0x00;
+const CAP_HCSPARAMS1: u64 = 0x04;
+const CAP_HCSPARAMS2: u64 = 0x08;
+const CAP_HCSPARAMS3: u64 = 0x0C;
+const CAP_HCCPARAMS1: u64 = 0x10;
+const CAP_DBOFF: u64 = 0x14;
+const CAP_RTSOFF: u64 = 0x18;
+const CAP_HCCPARAMS2: u64 = 0x1C;
+
+// Operational registers, relative to CAPLENGTH
+const OP_USBCMD: u64 = 0x00;
+const OP_USBSTS: u64 = 0x04;
+const OP_PAGESIZE: u64 = 0x08;
+const OP_DNCTRL: u64 = 0x14;
+const OP_CRCR: u64 = 0x18;
+const OP_DCBAAP: u64 = 0x30;
+const OP_CONFIG: u64 = 0x38;
+
+// Interrupter registers, relative to the interrupter base
+const IR_IMAN: u64 = 0x00;
+const IR_IMOD: u64 = 0x04;
+const IR_ERSTSZ: u64 = 0x08;
+const IR_ERSTBA: u64 = 0x10;
+const IR_ERDP: u64 = 0x18;
+
+const USBCMD_RS: u32 = 1 << 0;
+const USBCMD_HCRST: u32 = 1 << 1;
+const USBCMD_INTE: u32 = 1 << 2;
+
+const USBSTS_HCH: u32 = 1 << 0;
+const USBSTS_HSE: u32 = 1 << 2;
+const USBSTS_EINT: u32 = 1 << 3;
+const USBSTS_PCD: u32 = 1 << 4;
+const USBSTS_W1C: u32 = USBSTS_HSE | USBSTS_EINT | USBSTS_PCD;
+
+const IMAN_IP: u32 = 1 << 0;
+const IMAN_IE: u32 = 1 << 1;
+
+const ERDP_EHB: u64 = 1 << 3;
+
+const PORTSC_CCS: u32 = 1 << 0;
+const PORTSC_PED: u32 = 1 << 1;
+const PORTSC_PR: u32 = 1 << 4;
+const PORTSC_PLS_SHIFT: u32 = 5;
+const PORTSC_PP: u32 = 1 << 9;
+const PORTSC_SPEED_SHIFT: u32 = 10;
+const PORTSC_CSC: u32 = 1 << 17;
+const PORTSC_PEC: u32 = 1 << 18;
+const PORTSC_PRC: u32 = 1 << 21;
+const PORTSC_CHANGE_MASK: u32 = 0x7F << 17;
+
+const PLS_U0: u32 = 0;
+const PLS_RX_DETECT: u32 = 5;
+const PLS_POLLING: u32 = 7;
+
+const TRB_NORMAL: u32 = 1;
+const TRB_SETUP: u32 = 2;
+const TRB_DATA: u32 = 3;
+const TRB_STATUS: u32 = 4;
+const TRB_LINK: u32 = 6;
+const TRB_ENABLE_SLOT: u32 = 9;
+const TRB_DISABLE_SLOT: u32 = 10;
+const TRB_ADDRESS_DEVICE: u32 = 11;
+const TRB_CONFIGURE_ENDPOINT: u32 = 12;
+const TRB_EVALUATE_CONTEXT: u32 = 13;
+const TRB_RESET_ENDPOINT: u32 = 14;
+const TRB_STOP_ENDPOINT: u32 = 15;
+const TRB_SET_TR_DEQUEUE: u32 = 16;
+const TRB_RESET_DEVICE: u32 = 17;
+const TRB_NO_OP_COMMAND: u32 = 23;
+const TRB_TRANSFER_EVENT: u32 = 32;
+const TRB_COMMAND_COMPLETION: u32 = 33;
+const TRB_PORT_STATUS_CHANGE: u32 = 34;
+
+const TRB_CYCLE: u32 = 1 << 0;
+const TRB_TOGGLE_CYCLE: u32 = 1 << 1;
+const TRB_ISP: u32 = 1 << 2;
+const TRB_IOC: u32 = 1 << 5;
+const TRB_IDT: u32 = 1 << 6;
+const TRB_DC: u32 = 1 << 9;
+const TRB_TYPE_SHIFT: u32 = 10;
+
+const CC_SUCCESS: u32 = 1;
+const CC_DATA_BUFFER_ERROR: u32 = 2;
+const CC_BABBLE: u32 = 3;
+const CC_USB_TRANSACTION_ERROR: u32 = 4;
+const CC_TRB_ERROR: u32 = 5;
+const CC_STALL: u32 = 6;
+const CC_NO_SLOTS: u32 = 9;
+const CC_SLOT_NOT_ENABLED: u32 = 11;
+const CC_ENDPOINT_NOT_ENABLED: u32 = 12;
+const CC_SHORT_PACKET: u32 = 13;
+const CC_CONTEXT_STATE_ERROR: u32 = 19;
+
+// Endpoint context types
+const EP_TYPE_BULK_OUT: u8 = 2;
+const EP_TYPE_INTERRUPT_OUT: u8 = 3;
+const EP_TYPE_CONTROL: u8 = 4;
+const EP_TYPE_BULK_IN: u8 = 6;
+const EP_TYPE_INTERRUPT_IN: u8 = 7;
+
+// Slot and endpoint context states
+const SLOT_STATE_ADDRESSED: u32 = 2;
+const SLOT_STATE_CONFIGURED: u32 = 3;
+const EP_STATE_RUNNING: u32 = 1;
+
+/// Size of one slot or endpoint context (HCCPARAMS1.CSZ = 0)
+const CONTEXT_SIZE: u64 = 0x20;
+
+/// Access to guest-physical memory for ring and buffer DMA
+pub trait GuestMemoryAccess {
+    /// Copy `buf.len()` bytes from guest-physical address `gpa`
+    fn read_guest(&self, gpa: u64, buf: &mut [u8]) -> Result<(), HypervisorError>;
+
+    /// Copy `data` to guest-physical address `gpa`
+    fn write_guest(&mut self, gpa: u64, data: &[u8]) -> Result<(), HypervisorError>;
+}
+
+/// Host-side transfer path for a claimed device
+///
+/// `address` is the device address the host framework enumerated.
+pub trait UsbPassthroughHost {
+    /// Run a control transfer and return the bytes moved
+    fn control_transfer(&mut self, address: u8, setup: &UsbSetupPacket, data: &mut [u8]) -> UsbResult<usize>;
+
+    /// Run a bulk transfer and return the bytes moved
+    fn bulk_transfer(&mut self, address: u8, endpoint: u8, data: &mut [u8]) -> UsbResult<usize>;
+
+    /// Run an interrupt transfer and return the bytes moved
+    fn interrupt_transfer(&mut self, address: u8, endpoint: u8, data: &mut [u8]) -> UsbResult<usize>;
+}
+
+impl UsbPassthroughHost for XhciController {
+    fn control_transfer(&mut self, address: u8, setup: &UsbSetupPacket, data: &mut [u8]) -> UsbResult<usize> {
+        let slot_id = self.slot_for_address(address).ok_or(UsbDriverError::DeviceNotFound { address })?;
+        XhciController::control_transfer(self, slot_id, setup, data)
+    }
+
+    fn bulk_transfer(&mut self, address: u8, endpoint: u8, data: &mut [u8]) -> UsbResult<usize> {
+        let slot_id = self.slot_for_address(address).ok_or(UsbDriverError::DeviceNotFound { address })?;
+        XhciController::bulk_transfer(self, slot_id, endpoint, data)
+    }
+
+    fn interrupt_transfer(&mut self, address: u8, endpoint: u8, data: &mut [u8]) -> UsbResult<usize> {
+        let slot_id = self.slot_for_address(address).ok_or(UsbDriverError::DeviceNotFound { address })?;
+        XhciController::interrupt_transfer(self, slot_id, endpoint, data)
+    }
+}
+
+/// Passthrough statistics
+#[derive(Debug, Clone, Default)]
+pub struct UsbPassthroughStats {
+    pub commands: u64,
+    pub transfers: u64,
+    pub bytes_to_guest: u64,
+    pub bytes_from_guest: u64,
+    pub events: u64,
+    pub dropped_events: u64,
+    pub errors: u64,
+}
+
+/// Producer/consumer position in a guest TRB ring
+#[derive(Debug, Clone, Copy)]
+struct GuestRing {
+    dequeue: u64,
+    cycle: bool,
+}
+
+impl GuestRing {
+    fn from_pointer(pointer: u64) -> Self {
+        GuestRing { dequeue: pointer & !0xF, cycle: pointer & 1 != 0 }
+    }
+}
+
+/// Transfer ring state for one enabled endpoint
+#[derive(Debug, Clone, Copy)]
+struct PassthroughEndpoint {
+    ring: GuestRing,
+    ep_type: u8,
+    halted: bool,
+}
+
+/// Emulated xHCI register file
+#[derive(Debug, Clone, Default)]
+struct VirtualXhciRegisters {
+    usbcmd: u32,
+    usbsts: u32,
+    dnctrl: u32,
+    crcr: u64,
+    dcbaap: u64,
+    config: u32,
+    portsc: u32,
+    iman: u32,
+    imod: u32,
+    erstsz: u32,
+    erstba: u64,
+    erdp: u64,
+}
+
+/// Event ring producer state
+#[derive(Debug, Clone, Copy, Default)]
+struct EventRing {
+    segment_base: u64,
+    segment_size: u32,
+    enqueue: u32,
+    cycle: bool,
+}
+
+/// A host USB device passed through to a guest behind an emulated xHCI
+pub struct UsbPassthrough {
+    vendor_id: u16,
+    product_id: u16,
+    host_address: u8,
+    speed: UsbSpeed,
+    security_level: UsbSecurityLevel,
+    attached: bool,
+    host: Box<dyn UsbPassthroughHost + Send>,
+    memory: Box<dyn GuestMemoryAccess + Send>,
+    regs: VirtualXhciRegisters,
+    command_ring: GuestRing,
+    event_ring: EventRing,
+    slot_enabled: bool,
+    endpoints: BTreeMap<u8, PassthroughEndpoint>,
+    interrupt_pending: bool,
+    stats: UsbPassthroughStats,
+}
+
+impl UsbPassthrough {
+    /// Claim the first host device matching `vendor_id:product_id`
+    ///
+    /// The claim is refused unless the framework's security manager allows
+    /// the passthrough operation for this device and `device_security` has
+    /// trusted or verified it. A framework without a security manager, or a
+    /// device still quarantined by the BadUSB checks, is always refused.
+    pub fn claim(
+        framework: &UsbFramework,
+        device_security: &SecurityManager,
+        vendor_id: u16,
+        product_id: u16,
+        host: Box<dyn UsbPassthroughHost + Send>,
+        memory: Box<dyn GuestMemoryAccess + Send>,
+    ) -> Result<Self, HypervisorError> {
+        let device = framework.get_devices().iter()
+            .find(|device| device.vendor_id == vendor_id && device.product_id == product_id)
+            .ok_or_else(|| HypervisorError::from(IoError::new("usb", IoErrorKind::NoDevice).at(usb_device_id(vendor_id, product_id))))?;
+
+        let denied = || HypervisorError::from(
+            ConfigError::new("usb_passthrough", ConfigErrorReason::Denied).value(usb_device_id(vendor_id, product_id)));
+
+        let security_level = match &framework.security_manager {
+            Some(security) => security.authorize(vendor_id, product_id, USB_PASSTHROUGH_OPERATION)
+                .map_err(|_| denied())?,
+            None => {
+                warn!("No USB security manager, refusing passthrough of {:04x}:{:04x}", vendor_id, product_id);
+                return Err(denied());
+            }
+        };
+
+        match device_security.device_trust(vendor_id, product_id) {
+            TrustState::Trusted | TrustState::Verified => {}
+            trust_state => {
+                warn!("USB device {:04x}:{:04x} is {:?}, refusing passthrough", vendor_id, product_id, trust_state);
+                return Err(denied());
+            }
+        }
+
+        info!("Claimed USB device {:04x}:{:04x} at address {} for passthrough",
+              vendor_id, product_id, device.address);
+
+        let mut passthrough = UsbPassthrough {
+            vendor_id,
+            product_id,
+            host_address: device.address,
+            speed: device.speed,
+            security_level,
+            attached: true,
+            host,
+            memory,
+            regs: VirtualXhciRegisters::default(),
+            command_ring: GuestRing { dequeue: 0, cycle: false },
+            event_ring: EventRing::default(),
+            slot_enabled: false,
+            endpoints: BTreeMap::new(),
+            interrupt_pending: false,
+            stats: UsbPassthroughStats::default(),
+        };
+        passthrough.reset();
+        Ok(passthrough)
+    }
+
+    /// Vendor and product ID of the claimed device
+    pub fn device_id(&self) -> (u16, u16) {
+        (self.vendor_id, self.product_id)
+    }
+
+    /// Host-side address of the claimed device
+    pub fn host_address(&self) -> u8 {
+        self.host_address
+    }
+
+    /// Security level the device was granted under
+    pub fn security_level(&self) -> UsbSecurityLevel {
+        self.security_level
+    }
+
+    /// Whether the device is still present on the host
+    pub fn is_attached(&self) -> bool {
+        self.attached
+    }
+
+    /// Passthrough statistics
+    pub fn stats(&self) -> &UsbPassthroughStats {
+        &self.stats
+    }
+
+    /// Take the pending guest interrupt, if any
+    pub fn take_interrupt(&mut self) -> bool {
+        core::mem::replace(&mut self.interrupt_pending, false)
+    }
+
+    /// Build the `VirtualDevice` describing the emulated controller
+    pub fn build_virtual_device(&self, base_address: u64, interrupt_line: u8) -> VirtualDevice {
+        let mut custom_config = BTreeMap::new();
+        custom_config.insert(String::from("vendor_id"), format!("{:04x}", self.vendor_id));
+        custom_config.insert(String::from("product_id"), format!("{:04x}", self.product_id));
+        custom_config.insert(String::from("host_address"), format!("{}", self.host_address));
+
+        VirtualDevice {
+            device_type: DeviceType::UsbController,
+            device_id: String::new(),
+            name: format!("USB Passthrough {:04x}:{:04x}", self.vendor_id, self.product_id),
+            state: DeviceState::Uninitialized,
+            config: DeviceConfig {
+                enabled: true,
+                address: base_address as u32,
+                interrupt_line: Some(interrupt_line),
+                dma_channels: Vec::new(),
+                custom_config,
+            },
+            mmio_regions: vec![
+                MmioRegion {
+                    base_address,
+                    size: VXHCI_MMIO_SIZE,
+                    access: DeviceAccess::READ | DeviceAccess::WRITE | DeviceAccess::DMA,
+                }
+            ],
+            io_ports: Vec::new(),
+            interrupt: Some(InterruptInfo {
+                interrupt_line,
+                level_triggered: true,
+                edge_triggered: false,
+                active: false,
+            }),
+            registers: Vec::new(),
+            capabilities: vec![
+                DeviceCapability {
+                    name: String::from("usb_passthrough"),
+                    description: String::from("Host USB device passed through to the guest"),
+                    value: format!("{:04x}:{:04x}", self.vendor_id, self.product_id),
+                },
+            ],
+            stats: DeviceStats {
+                read_count: 0,
+                write_count: 0,
+                interrupt_count: 0,
+                error_count: 0,
+                last_access_time: 0,
+            },
+        }
+    }
+
+    /// PCI function the guest enumerates the controller through, with
+    /// BAR0 at `base_address` when it is suitably aligned
+    pub fn build_pci_function(&self, base_address: u64, interrupt_line: u8) -> Result<PciFunction, HypervisorError> {
+        let mut function = PciFunction::new(VXHCI_PCI_VENDOR, VXHCI_PCI_DEVICE, VXHCI_PCI_CLASS, 1);
+        function.add_bar(0, PciBar { kind: PciBarKind::Memory64 { prefetchable: false }, size: VXHCI_MMIO_SIZE })?;
+        function.add_msi(1)?;
+        function.add_msix(1, 0, VXHCI_MSIX_TABLE, 0, VXHCI_MSIX_PBA)?;
+        function.set_interrupt_pin(1);
+        function.set_interrupt_line(interrupt_line);
+        if base_address % VXHCI_MMIO_SIZE == 0 {
+            function.set_bar_address(0, base_address)?;
+        }
+        Ok(function)
+    }
+
+    /// Return the emulated controller to its power-on state
+    pub fn reset(&mut self) {
+        self.regs = VirtualXhciRegisters::default();
+        self.regs.usbsts = USBSTS_HCH;
+        self.command_ring = GuestRing { dequeue: 0, cycle: false };
+        self.event_ring = EventRing::default();
+        self.slot_enabled = false;
+        self.endpoints.clear();
+        self.interrupt_pending = false;
+        self.regs.portsc = PORTSC_PP;
+        if self.attached {
+            self.regs.portsc |= PORTSC_CCS | PORTSC_CSC | (PLS_POLLING << PORTSC_PLS_SHIFT)
+                | (speed_id(self.speed) << PORTSC_SPEED_SHIFT);
+        } else {
+            self.regs.portsc |= PLS_RX_DETECT << PORTSC_PLS_SHIFT;
+        }
+    }
+
+    /// Report the host device as gone and tell the guest
+    pub fn detach(&mut self) -> Result<(), HypervisorError> {
+        if !self.attached {
+            return Ok(());
+        }
+        info!("USB passthrough device {:04x}:{:04x} detached", self.vendor_id, self.product_id);
+        self.attached = false;
+        let was_enabled = self.regs.portsc & PORTSC_PED != 0;
+        self.regs.portsc = PORTSC_PP | PORTSC_CSC | (PLS_RX_DETECT << PORTSC_PLS_SHIFT);
+        if was_enabled {
+            self.regs.portsc |= PORTSC_PEC;
+        }
+        self.slot_enabled = false;
+        self.endpoints.clear();
+        self.port_status_changed()
+    }
+
+    /// Handle a guest MMIO read at `offset` within the controller window
+    pub fn mmio_read(&mut self, offset: u64, size: usize) -> u64 {
+        match size {
+            8 => self.read_dword(offset) as u64 | ((self.read_dword(offset + 4) as u64) << 32),
+            4 => self.read_dword(offset) as u64,
+            1 | 2 => {
+                let shift = (offset & 3) * 8;
+                let mask = if size == 1 { 0xFF } else { 0xFFFF };
+                ((self.read_dword(offset & !3) >> shift) & mask) as u64
+            }
+            _ => 0,
+        }
+    }
+
+    /// Handle a guest MMIO write at `offset` within the controller window
+    ///
+    /// A guest memory fault while walking rings halts the controller and
+    /// raises Host System Error, as real hardware does on a DMA failure.
+    pub fn mmio_write(&mut self, offset: u64, value: u64, size: usize) -> Result<(), HypervisorError> {
+        let result = match size {
+            8 => self.write_dword(offset, value as u32)
+                .and_then(|_| self.write_dword(offset + 4, (value >> 32) as u32)),
+            4 => self.write_dword(offset, value as u32),
+            1 | 2 => {
+                let shift = (offset & 3) * 8;
+                let mask: u32 = if size == 1 { 0xFF } else { 0xFFFF };
+                let current = self.read_dword(offset & !3) & !(mask << shift);
+                self.write_dword(offset & !3, current | ((value as u32 & mask) << shift))
+            }
+            _ => Err(HypervisorError::InvalidParameter),
+        };
+
+        if let Err(ref err) = result {
+            warn!("USB passthrough controller fault: {:?}", err);
+            self.stats.errors += 1;
+            self.regs.usbcmd &= !USBCMD_RS;
+            self.regs.usbsts |= USBSTS_HCH | USBSTS_HSE;
+        }
+        result
+    }
+
+    /// Retry transfers the device could not complete yet
+    ///
+    /// Interrupt IN endpoints time out on the host while the device has
+    /// nothing to report; their TDs stay queued and are retried here.
+    pub fn poll(&mut self) -> Result<(), HypervisorError> {
+        if !self.is_running() || !self.attached {
+            return Ok(());
+        }
+        let pending: Vec<u8> = self.endpoints.iter()
+            .filter(|(_, ep)| ep.ep_type == EP_TYPE_INTERRUPT_IN && !ep.halted)
+            .map(|(&dci, _)| dci)
+            .collect();
+        for dci in pending {
+            self.ring_endpoint(dci)?;
+        }
+        Ok(())
+    }
+
+    fn is_running(&self) -> bool {
+        self.regs.usbcmd & USBCMD_RS != 0
+    }
+
+    fn read_dword(&self, offset: u64) -> u32 {
+        match offset {
+            CAP_CAPLENGTH => VXHCI_CAPLENGTH as u32 | (0x0100 << 16),
+            // One slot, one interrupter, one port
+            CAP_HCSPARAMS1 => 1 | (1 << 8) | ((VXHCI_PORT as u32) << 24),
+            CAP_HCSPARAMS2 | CAP_HCSPARAMS3 | CAP_HCCPARAMS2 => 0,
+            // 64-bit addressing, 32-byte contexts, extended capabilities at XECP
+            CAP_HCCPARAMS1 => 1 | (((VXHCI_XECP / 4) as u32) << 16),
+            CAP_DBOFF => VXHCI_DBOFF as u32,
+            CAP_RTSOFF => VXHCI_RTSOFF as u32,
+            _ if offset >= VXHCI_CAPLENGTH && offset < VXHCI_PORTSC => {
+                self.read_operational(offset - VXHCI_CAPLENGTH)
+            }
+            VXHCI_PORTSC => self.regs.portsc,
+            _ if offset >= VXHCI_XECP && offset < VXHCI_XECP + 0x10 => {
+                self.read_protocol_capability(offset - VXHCI_XECP)
+            }
+            _ if offset >= VXHCI_INTERRUPTER && offset < VXHCI_INTERRUPTER + 0x20 => {
+                self.read_interrupter(offset - VXHCI_INTERRUPTER)
+            }
+            _ => 0,
+        }
+    }
+
+    fn read_operational(&self, offset: u64) -> u32 {
+        match offset {
+            OP_USBCMD => self.regs.usbcmd,
+            OP_USBSTS => self.regs.usbsts,
+            // 4 KiB pages only
+            OP_PAGESIZE => 1,
+            OP_DNCTRL => self.regs.dnctrl,
+            // Command ring pointer reads back as zero
+            OP_CRCR | 0x1C => 0,
+            OP_DCBAAP => self.regs.dcbaap as u32,
+            0x34 => (self.regs.dcbaap >> 32) as u32,
+            OP_CONFIG => self.regs.config,
+            _ => 0,
+        }
+    }
+
+    fn read_protocol_capability(&self, offset: u64) -> u32 {
+        let (major, minor) = match self.speed {
+            UsbSpeed::Super => (3, 0x00),
+            UsbSpeed::SuperPlus => (3, 0x10),
+            _ => (2, 0x00),
+        };
+        match offset {
+            // Supported Protocol capability, last in the list
+            0x0 => 2 | (minor << 16) | (major << 24),
+            0x4 => u32::from_le_bytes(*b"USB "),
+            0x8 => VXHCI_PORT as u32 | (1 << 8),
+            _ => 0,
+        }
+    }
+
+    fn read_interrupter(&self, offset: u64) -> u32 {
+        match offset {
+            IR_IMAN => self.regs.iman,
+            IR_IMOD => self.regs.imod,
+            IR_ERSTSZ => self.regs.erstsz,
+            IR_ERSTBA => self.regs.erstba as u32,
+            0x14 => (self.regs.erstba >> 32) as u32,
+            IR_ERDP => self.regs.erdp as u32,
+            0x1C => (self.regs.erdp >> 32) as u32,
+            _ => 0,
+        }
+    }
+
+    fn write_dword(&mut self, offset: u64, value: u32) -> Result<(), HypervisorError> {
+        if offset >= VXHCI_DBOFF && offset < VXHCI_DBOFF + 0x100 {
+            return self.ring_doorbell(((offset - VXHCI_DBOFF) / 4) as u8, value);
+        }
+        if offset >= VXHCI_CAPLENGTH && offset < VXHCI_PORTSC {
+            return self.write_operational(offset - VXHCI_CAPLENGTH, value);
+        }
+        if offset == VXHCI_PORTSC {
+            return self.write_portsc(value);
+        }
+        if offset >= VXHCI_INTERRUPTER && offset < VXHCI_INTERRUPTER + 0x20 {
+            return self.write_interrupter(offset - VXHCI_INTERRUPTER, value);
+        }
+        // Capability and runtime MFINDEX registers are read-only
+        Ok(())
+    }
+
+    fn write_operational(&mut self, offset: u64, value: u32) -> Result<(), HypervisorError> {
+        match offset {
+            OP_USBCMD => {
+                if value & USBCMD_HCRST != 0 {
+                    info!("USB passthrough controller reset by guest");
+                    self.reset();
+                    return Ok(());
+                }
+                let was_running = self.is_running();
+                self.regs.usbcmd = value;
+                if value & USBCMD_RS != 0 {
+                    self.regs.usbsts &= !USBSTS_HCH;
+                    if !was_running && self.regs.portsc & PORTSC_CHANGE_MASK != 0 {
+                        self.port_status_changed()?;
+                    }
+                } else {
+                    self.regs.usbsts |= USBSTS_HCH;
+                }
+            }
+            OP_USBSTS => self.regs.usbsts &= !(value & USBSTS_W1C),
+            OP_DNCTRL => self.regs.dnctrl = value,
+            OP_CRCR => {
+                self.regs.crcr = (self.regs.crcr & !0xFFFF_FFFF) | value as u64;
+                self.command_ring = GuestRing::from_pointer(self.regs.crcr & !0x30);
+            }
+            0x1C => {
+                self.regs.crcr = (self.regs.crcr & 0xFFFF_FFFF) | ((value as u64) << 32);
+                self.command_ring = GuestRing::from_pointer(self.regs.crcr & !0x30);
+            }
+            OP_DCBAAP => self.regs.dcbaap = (self.regs.dcbaap & !0xFFFF_FFFF) | (value & !0x3F) as u64,
+            0x34 => self.regs.dcbaap = (self.regs.dcbaap & 0xFFFF_FFFF) | ((value as u64) << 32),
+            OP_CONFIG => self.regs.config = value & 0xFF,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn write_portsc(&mut self, value: u32) -> Result<(), HypervisorError> {
+        // Change bits are write-one-to-clear
+        self.regs.portsc &= !(value & PORTSC_CHANGE_MASK);
+
+        if value & PORTSC_PED != 0 && self.regs.portsc & PORTSC_PED != 0 {
+            // Writing PED disables the port
+            self.regs.portsc &= !PORTSC_PED;
+            self.regs.portsc = (self.regs.portsc & !(0xF << PORTSC_PLS_SHIFT)) | (PLS_POLLING << PORTSC_PLS_SHIFT);
+        }
+
+        if value & PORTSC_PR != 0 && self.attached {
+            // The host already owns the device; a guest reset completes at once
+            self.regs.portsc |= PORTSC_PED | PORTSC_PRC;
+            self.regs.portsc = (self.regs.portsc & !(0xF << PORTSC_PLS_SHIFT)) | (PLS_U0 << PORTSC_PLS_SHIFT);
+            self.port_status_changed()?;
+        }
+        Ok(())
+    }
+
+    fn write_interrupter(&mut self, offset: u64, value: u32) -> Result<(), HypervisorError> {
+        match offset {
+            IR_IMAN => {
+                let pending = self.regs.iman & IMAN_IP & !value;
+                self.regs.iman = pending | (value & IMAN_IE);
+            }
+            IR_IMOD => self.regs.imod = value,
+            IR_ERSTSZ => self.regs.erstsz = value & 0xFFFF,
+            IR_ERSTBA => self.regs.erstba = (self.regs.erstba & !0xFFFF_FFFF) | (value & !0x3F) as u64,
+            0x14 => {
+                // The high half is written last; that is when the ring goes live
+                self.regs.erstba = (self.regs.erstba & 0xFFFF_FFFF) | ((value as u64) << 32);
+                self.load_event_ring()?;
+            }
+            IR_ERDP => {
+                let ehb = value as u64 & ERDP_EHB;
+                self.regs.erdp = (self.regs.erdp & !0xFFFF_FFFF) | (value as u64 & !0xF) | (self.regs.erdp & ERDP_EHB & !ehb);
+            }
+            0x1C => self.regs.erdp = (self.regs.erdp & 0xFFFF_FFFF) | ((value as u64) << 32),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn load_event_ring(&mut self) -> Result<(), HypervisorError> {
+        if self.regs.erstsz == 0 {
+            self.event_ring = EventRing::default();
+            return Ok(());
+        }
+        // Only the first segment is used
+        let mut entry = [0u8; 16];
+        self.memory.read_guest(self.regs.erstba, &mut entry)?;
+        let base = u64::from_le_bytes([entry[0], entry[1], entry[2], entry[3], entry[4], entry[5], entry[6], entry[7]]);
+        let size = u32::from_le_bytes([entry[8], entry[9], entry[10], entry[11]]) & 0xFFFF;
+        self.event_ring = EventRing {
+            segment_base: base & !0x3F,
+            segment_size: size,
+            enqueue: 0,
+            cycle: true,
+        };
+        Ok(())
+    }
+
+    fn ring_doorbell(&mut self, target: u8, value: u32) -> Result<(), HypervisorError> {
+        if !self.is_running() {
+            return Ok(());
+        }
+        match target {
+            0 => self.process_command_ring(),
+            VXHCI_SLOT_ID if self.slot_enabled && self.attached => self.ring_endpoint((value & 0xFF) as u8),
+            _ => {
+                warn!("USB passthrough doorbell for unknown slot {}", target);
+                Ok(())
+            }
+        }
+    }
+
+    fn read_trb(&self, address: u64) -> Result<UsbTRB, HypervisorError> {
+        let mut raw = [0u8; 16];
+        self.memory.read_guest(address, &mut raw)?;
+        let dword = |i: usize| u32::from_le_bytes([raw[i], raw[i + 1], raw[i + 2], raw[i + 3]]);
+        Ok(UsbTRB { ptr_low: dword(0), ptr_high: dword(4), status: dword(8), control: dword(12) })
+    }
+
+    fn write_trb(&mut self, address: u64, trb: &UsbTRB) -> Result<(), HypervisorError> {
+        let mut raw = [0u8; 16];
+        raw[0..4].copy_from_slice(&trb.ptr_low.to_le_bytes());
+        raw[4..8].copy_from_slice(&trb.ptr_high.to_le_bytes());
+        raw[8..12].copy_from_slice(&trb.status.to_le_bytes());
+        raw[12..16].copy_from_slice(&trb.control.to_le_bytes());
+        self.memory.write_guest(address, &raw)
+    }
+
+    fn read_u32(&self, address: u64) -> Result<u32, HypervisorError> {
+        let mut raw = [0u8; 4];
+        self.memory.read_guest(address, &mut raw)?;
+        Ok(u32::from_le_bytes(raw))
+    }
+
+    fn write_u32(&mut self, address: u64, value: u32) -> Result<(), HypervisorError> {
+        self.memory.write_guest(address, &value.to_le_bytes())
+    }
+
+    fn read_u64(&self, address: u64) -> Result<u64, HypervisorError> {
+        let mut raw = [0u8; 8];
+        self.memory.read_guest(address, &mut raw)?;
+        Ok(u64::from_le_bytes(raw))
+    }
+
+    /// Fetch the next TRB owned by the controller, following link TRBs
+    fn fetch_trb(&self, ring: GuestRing) -> Result<Option<(u64, UsbTRB, GuestRing)>, HypervisorError> {
+        let mut ring = ring;
+        for _ in 0..VXHCI_MAX_LINK_HOPS {
+            let trb = self.read_trb(ring.dequeue)?;
+            if (trb.control & TRB_CYCLE != 0) != ring.cycle {
+                return Ok(None);
+            }
+            if trb_type(&trb) == TRB_LINK {
+                let toggle = trb.control & TRB_TOGGLE_CYCLE != 0;
+                ring = GuestRing { dequeue: trb_pointer(&trb) & !0xF, cycle: ring.cycle ^ toggle };
+                continue;
+            }
+            let next = GuestRing { dequeue: ring.dequeue + 16, cycle: ring.cycle };
+            return Ok(Some((ring.dequeue, trb, next)));
+        }
+        Err(IoError::new("usb_passthrough.ring", IoErrorKind::Protocol).at(ring.dequeue).into())
+    }
+
+    /// Append an event to the guest's event ring and raise the interrupter
+    fn post_event(&mut self, mut event: UsbTRB) -> Result<(), HypervisorError> {
+        let ring = self.event_ring;
+        if ring.segment_size == 0 {
+            self.stats.dropped_events += 1;
+            warn!("USB passthrough event dropped, no event ring configured");
+            return Ok(());
+        }
+
+        event.control = (event.control & !TRB_CYCLE) | ring.cycle as u32;
+        self.write_trb(ring.segment_base + ring.enqueue as u64 * 16, &event)?;
+        self.stats.events += 1;
+
+        self.event_ring.enqueue += 1;
+        if self.event_ring.enqueue == ring.segment_size {
+            self.event_ring.enqueue = 0;
+            self.event_ring.cycle = !ring.cycle;
+        }
+
+        self.regs.iman |= IMAN_IP;
+        self.regs.usbsts |= USBSTS_EINT;
+        self.regs.erdp |= ERDP_EHB;
+        if self.regs.iman & IMAN_IE != 0 && self.regs.usbcmd & USBCMD_INTE != 0 {
+            self.interrupt_pending = true;
+        }
+        Ok(())
+    }
+
+    fn port_status_changed(&mut self) -> Result<(), HypervisorError> {
+        self.regs.usbsts |= USBSTS_PCD;
+        if !self.is_running() {
+            return Ok(());
+        }
+        self.post_event(UsbTRB {
+            ptr_low: (VXHCI_PORT as u32) << 24,
+            ptr_high: 0,
+            status: CC_SUCCESS << 24,
+            control: TRB_PORT_STATUS_CHANGE << TRB_TYPE_SHIFT,
+        })
+    }
+
+    fn complete_command(&mut self, address: u64, code: u32, slot_id: u8) -> Result<(), HypervisorError> {
+        self.post_event(UsbTRB {
+            ptr_low: address as u32,
+            ptr_high: (address >> 32) as u32,
+            status: code << 24,
+            control: (TRB_COMMAND_COMPLETION << TRB_TYPE_SHIFT) | ((slot_id as u32) << 24),
+        })
+    }
+
+    fn process_command_ring(&mut self) -> Result<(), HypervisorError> {
+        for _ in 0..VXHCI_MAX_TRBS_PER_DOORBELL {
+            let (address, trb, next) = match self.fetch_trb(self.command_ring)? {
+                Some(entry) => entry,
+                None => break,
+            };
+            self.command_ring = next;
+            self.stats.commands += 1;
+
+            let slot_id = (trb.control >> 24) as u8;
+            let (code, slot) = match trb_type(&trb) {
+                TRB_NO_OP_COMMAND => (CC_SUCCESS, 0),
+                TRB_ENABLE_SLOT if self.slot_enabled || !self.attached => (CC_NO_SLOTS, 0),
+                TRB_ENABLE_SLOT => {
+                    self.slot_enabled = true;
+                    (CC_SUCCESS, VXHCI_SLOT_ID)
+                }
+                _ if slot_id != VXHCI_SLOT_ID || !self.slot_enabled => (CC_SLOT_NOT_ENABLED, slot_id),
+                TRB_DISABLE_SLOT => {
+                    self.slot_enabled = false;
+                    self.endpoints.clear();
+                    (CC_SUCCESS, slot_id)
+                }
+                TRB_ADDRESS_DEVICE => (self.address_device(&trb)?, slot_id),
+                TRB_CONFIGURE_ENDPOINT => (self.configure_endpoints(&trb)?, slot_id),
+                TRB_EVALUATE_CONTEXT | TRB_STOP_ENDPOINT => (CC_SUCCESS, slot_id),
+                TRB_RESET_ENDPOINT => {
+                    let dci = ((trb.control >> 16) & 0x1F) as u8;
+                    match self.endpoints.get_mut(&dci) {
+                        Some(ep) if ep.halted => {
+                            ep.halted = false;
+                            (CC_SUCCESS, slot_id)
+                        }
+                        Some(_) => (CC_CONTEXT_STATE_ERROR, slot_id),
+                        None => (CC_ENDPOINT_NOT_ENABLED, slot_id),
+                    }
+                }
+                TRB_SET_TR_DEQUEUE => {
+                    let dci = ((trb.control >> 16) & 0x1F) as u8;
+                    match self.endpoints.get_mut(&dci) {
+                        Some(ep) => {
+                            ep.ring = GuestRing::from_pointer(trb_pointer(&trb));
+                            (CC_SUCCESS, slot_id)
+                        }
+                        None => (CC_ENDPOINT_NOT_ENABLED, slot_id),
+                    }
+                }
+                TRB_RESET_DEVICE => {
+                    self.endpoints.retain(|&dci, _| dci == 1);
+                    (CC_SUCCESS, slot_id)
+                }
+                other => {
+                    warn!("USB passthrough: unsupported command TRB type {}", other);
+                    (CC_TRB_ERROR, slot_id)
+                }
+            };
+            self.complete_command(address, code, slot)?;
+        }
+        Ok(())
+    }
+
+    /// Output device context for the passthrough slot
+    fn device_context(&self) -> Result<u64, HypervisorError> {
+        let context = self.read_u64(self.regs.dcbaap + VXHCI_SLOT_ID as u64 * 8)? & !0x3F;
+        if context == 0 {
+            return Err(IoError::new("usb_passthrough.slot", IoErrorKind::Protocol).into());
+        }
+        Ok(context)
+    }
+
+    /// Copy a 32-byte context from the input context to the output context
+    fn copy_context(&mut self, from: u64, to: u64) -> Result<[u32; 8], HypervisorError> {
+        let mut raw = [0u8; CONTEXT_SIZE as usize];
+        self.memory.read_guest(from, &mut raw)?;
+        self.memory.write_guest(to, &raw)?;
+        let mut dwords = [0u32; 8];
+        for (i, dword) in dwords.iter_mut().enumerate() {
+            *dword = u32::from_le_bytes([raw[i * 4], raw[i * 4 + 1], raw[i * 4 + 2], raw[i * 4 + 3]]);
+        }
+        Ok(dwords)
+    }
+
+    fn address_device(&mut self, trb: &UsbTRB) -> Result<u32, HypervisorError> {
+        let input = trb_pointer(trb) & !0xF;
+        let output = self.device_context()?;
+
+        // Slot context, then the default control endpoint
+        let slot = self.copy_context(input + CONTEXT_SIZE, output)?;
+        self.write_u32(output + 12, (slot[3] & 0x07FF_FF00)
+            | (SLOT_STATE_ADDRESSED << 27) | VXHCI_SLOT_ID as u32)?;
+
+        let ep0 = self.copy_context(input + 2 * CONTEXT_SIZE, output + CONTEXT_SIZE)?;
+        self.write_u32(output + CONTEXT_SIZE, (ep0[0] & !0x7) | EP_STATE_RUNNING)?;
+
+        let dequeue = ep0[2] as u64 | ((ep0[3] as u64) << 32);
+        self.endpoints.insert(1, PassthroughEndpoint {
+            ring: GuestRing::from_pointer(dequeue),
+            ep_type: EP_TYPE_CONTROL,
+            halted: false,
+        });
+        Ok(CC_SUCCESS)
+    }
+
+    fn configure_endpoints(&mut self, trb: &UsbTRB) -> Result<u32, HypervisorError> {
+        let output = self.device_context()?;
+        if trb.control & TRB_DC != 0 {
+            self.endpoints.retain(|&dci, _| dci == 1);
+            return Ok(CC_SUCCESS);
+        }
+
+        let input = trb_pointer(trb) & !0xF;
+        let drop_flags = self.read_u32(input)?;
+        let add_flags = self.read_u32(input + 4)?;
+
+        for dci in 2..32u8 {
+            if drop_flags & (1 << dci) != 0 {
+                self.endpoints.remove(&dci);
+            }
+            if add_flags & (1 << dci) == 0 {
+                continue;
+            }
+            let ctx = self.copy_context(input + (dci as u64 + 1) * CONTEXT_SIZE, output + dci as u64 * CONTEXT_SIZE)?;
+            self.write_u32(output + dci as u64 * CONTEXT_SIZE, (ctx[0] & !0x7) | EP_STATE_RUNNING)?;
+
+            let ep_type = ((ctx[1] >> 3) & 0x7) as u8;
+            let dequeue = ctx[2] as u64 | ((ctx[3] as u64) << 32);
+            self.endpoints.insert(dci, PassthroughEndpoint {
+                ring: GuestRing::from_pointer(dequeue),
+                ep_type,
+                halted: false,
+            });
+        }
+
+        let slot_state = self.read_u32(output + 12)?;
+        self.write_u32(output + 12, (slot_state & !(0x1F << 27)) | (SLOT_STATE_CONFIGURED << 27))?;
+        Ok(CC_SUCCESS)
+    }
+
+    fn ring_endpoint(&mut self, dci: u8) -> Result<(), HypervisorError> {
+        for _ in 0..VXHCI_MAX_TRBS_PER_DOORBELL {
+            let ep = match self.endpoints.get(&dci) {
+                Some(ep) if !ep.halted => *ep,
+                _ => return Ok(()),
+            };
+            let progressed = if ep.ep_type == EP_TYPE_CONTROL {
+                self.process_control_td(dci, ep)?
+            } else {
+                self.process_normal_td(dci, ep)?
+            };
+            if !progressed {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn transfer_event(&mut self, address: u64, code: u32, residual: u32, dci: u8) -> Result<(), HypervisorError> {
+        self.post_event(UsbTRB {
+            ptr_low: address as u32,
+            ptr_high: (address >> 32) as u32,
+            status: (code << 24) | (residual & 0xFF_FFFF),
+            control: (TRB_TRANSFER_EVENT << TRB_TYPE_SHIFT) | ((dci as u32) << 16) | ((VXHCI_SLOT_ID as u32) << 24),
+        })
+    }
+
+    /// Fail a TD: report the error on `address` and halt the endpoint
+    fn fail_td(&mut self, dci: u8, next: GuestRing, address: u64, code: u32, residual: u32) -> Result<bool, HypervisorError> {
+        self.stats.errors += 1;
+        if let Some(ep) = self.endpoints.get_mut(&dci) {
+            ep.ring = next;
+            ep.halted = code == CC_STALL || code == CC_USB_TRANSACTION_ERROR || code == CC_BABBLE;
+        }
+        self.transfer_event(address, code, residual, dci)?;
+        Ok(true)
+    }
+
+    /// Run one Setup/Data/Status TD; returns false if the TD is incomplete
+    fn process_control_td(&mut self, dci: u8, ep: PassthroughEndpoint) -> Result<bool, HypervisorError> {
+        let (setup_address, setup_trb, mut next) = match self.fetch_trb(ep.ring)? {
+            Some(entry) => entry,
+            None => return Ok(false),
+        };
+        if trb_type(&setup_trb) != TRB_SETUP {
+            return self.fail_td(dci, next, setup_address, CC_TRB_ERROR, 0);
+        }
+
+        let mut data = None;
+        let (status_address, status_trb) = loop {
+            let (address, trb, after) = match self.fetch_trb(next)? {
+                Some(entry) => entry,
+                // The guest has not queued the whole TD yet
+                None => return Ok(false),
+            };
+            next = after;
+            match trb_type(&trb) {
+                TRB_DATA if data.is_none() => data = Some((address, trb)),
+                TRB_STATUS => break (address, trb),
+                _ => return self.fail_td(dci, next, address, CC_TRB_ERROR, 0),
+            }
+        };
+
+        let setup = UsbSetupPacket {
+            bmRequestType: setup_trb.ptr_low as u8,
+            bRequest: (setup_trb.ptr_low >> 8) as u8,
+            wValue: (setup_trb.ptr_low >> 16) as u16,
+            wIndex: setup_trb.ptr_high as u16,
+            wLength: (setup_trb.ptr_high >> 16) as u16,
+        };
+        let direction_in = setup.bmRequestType & 0x80 != 0;
+        let data_length = data.map(|(_, trb)| (trb.status & VXHCI_MAX_TRB_LENGTH).min(setup.wLength as u32)).unwrap_or(0);
+
+        let mut buffer = vec![0u8; setup.wLength as usize];
+        if let Some((_, trb)) = data {
+            if !direction_in {
+                self.read_trb_buffer(&trb, &mut buffer[..data_length as usize])?;
+            }
+        }
+
+        self.stats.transfers += 1;
+        let result = self.host.control_transfer(self.host_address, &setup, &mut buffer);
+        let actual = match result {
+            Ok(actual) => (actual as u32).min(data_length),
+            Err(err) => {
+                let code = completion_code(&err);
+                let address = data.map(|(address, _)| address).unwrap_or(status_address);
+                return self.fail_td(dci, next, address, code, data_length);
+            }
+        };
+
+        if let Some((data_address, trb)) = data {
+            if direction_in {
+                self.memory.write_guest(trb_pointer(&trb), &buffer[..actual as usize])?;
+                self.stats.bytes_to_guest += actual as u64;
+            } else {
+                self.stats.bytes_from_guest += actual as u64;
+            }
+            let short = actual < data_length;
+            if trb.control & TRB_IOC != 0 || (short && trb.control & TRB_ISP != 0) {
+                let code = if short { CC_SHORT_PACKET } else { CC_SUCCESS };
+                self.transfer_event(data_address, code, data_length - actual, dci)?;
+            }
+        }
+
+        if let Some(ep) = self.endpoints.get_mut(&dci) {
+            ep.ring = next;
+        }
+        if status_trb.control & TRB_IOC != 0 {
+            self.transfer_event(status_address, CC_SUCCESS, 0, dci)?;
+        }
+        Ok(true)
+    }
+
+    /// Run one Normal TRB on a bulk or interrupt endpoint; returns false if
+    /// nothing was queued or the device has no data yet
+    fn process_normal_td(&mut self, dci: u8, ep: PassthroughEndpoint) -> Result<bool, HypervisorError> {
+        let (address, trb, next) = match self.fetch_trb(ep.ring)? {
+            Some(entry) => entry,
+            None => return Ok(false),
+        };
+        if trb_type(&trb) != TRB_NORMAL {
+            return self.fail_td(dci, next, address, CC_TRB_ERROR, 0);
+        }
+
+        let length = trb.status & VXHCI_MAX_TRB_LENGTH;
+        let endpoint = dci_endpoint(dci);
+        let direction_in = endpoint & 0x80 != 0;
+        let mut buffer = vec![0u8; length as usize];
+        if !direction_in {
+            self.read_trb_buffer(&trb, &mut buffer)?;
+        }
+
+        let result = match ep.ep_type {
+            EP_TYPE_BULK_OUT | EP_TYPE_BULK_IN => {
+                self.host.bulk_transfer(self.host_address, endpoint, &mut buffer)
+            }
+            EP_TYPE_INTERRUPT_OUT | EP_TYPE_INTERRUPT_IN => {
+                self.host.interrupt_transfer(self.host_address, endpoint, &mut buffer)
+            }
+            // Isochronous endpoints are not forwarded
+            _ => return self.fail_td(dci, next, address, CC_TRB_ERROR, length),
+        };
+
+        let actual = match result {
+            Ok(actual) => (actual as u32).min(length),
+            // An idle interrupt endpoint: leave the TD queued for poll()
+            Err(UsbDriverError::Timeout) if ep.ep_type == EP_TYPE_INTERRUPT_IN => return Ok(false),
+            Err(err) => return self.fail_td(dci, next, address, completion_code(&err), length),
+        };
+        self.stats.transfers += 1;
+
+        if direction_in {
+            self.memory.write_guest(trb_pointer(&trb), &buffer[..actual as usize])?;
+            self.stats.bytes_to_guest += actual as u64;
+        } else {
+            self.stats.bytes_from_guest += actual as u64;
+        }
+
+        if let Some(ep) = self.endpoints.get_mut(&dci) {
+            ep.ring = next;
+        }
+        let short = actual < length;
+        if trb.control & TRB_IOC != 0 || (short && trb.control & TRB_ISP != 0) {
+            let code = if short { CC_SHORT_PACKET } else { CC_SUCCESS };
+            self.transfer_event(address, code, length - actual, dci)?;
+        }
+        Ok(true)
+    }
+
+    /// Read an OUT TRB's payload, either immediate or from guest memory
+    fn read_trb_buffer(&self, trb: &UsbTRB, buffer: &mut [u8]) -> Result<(), HypervisorError> {
+        if trb.control & TRB_IDT != 0 {
+            let mut immediate = [0u8; 8];
+            immediate[..4].copy_from_slice(&trb.ptr_low.to_le_bytes());
+            immediate[4..].copy_from_slice(&trb.ptr_high.to_le_bytes());
+            let len = buffer.len().min(8);
+            buffer[..len].copy_from_slice(&immediate[..len]);
+            Ok(())
+        } else {
+            self.memory.read_guest(trb_pointer(trb), buffer)
+        }
+    }
+}
+
+impl core::fmt::Debug for UsbPassthrough {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("UsbPassthrough")
+            .field("vendor_id", &self.vendor_id)
+            .field("product_id", &self.product_id)
+            .field("host_address", &self.host_address)
+            .field("attached", &self.attached)
+            .field("stats", &self.stats)
+            .finish()
+    }
+}
+
+/// Vendor and product ID as one value, for errors
+fn usb_device_id(vendor_id: u16, product_id: u16) -> u64 {
+    ((vendor_id as u64) << 16) | product_id as u64
+}
+
+fn trb_type(trb: &UsbTRB) -> u32 {
+    (trb.control >> TRB_TYPE_SHIFT) & 0x3F
+}
+
+fn trb_pointer(trb: &UsbTRB) -> u64 {
+    trb.ptr_low as u64 | ((trb.ptr_high as u64) << 32)
+}
+
+/// USB endpoint address for a device context index
+fn dci_endpoint(dci: u8) -> u8 {
+    let number = dci / 2;
+    if dci & 1 != 0 { number | 0x80 } else { number }
+}
+
+/// xHCI protocol speed ID for a USB speed
+fn speed_id(speed: UsbSpeed) -> u32 {
+    match speed {
+        UsbSpeed::Full => 1,
+        UsbSpeed::Low => 2,
+        UsbSpeed::High => 3,
+        UsbSpeed::Super => 4,
+        UsbSpeed::SuperPlus => 5,
+    }
+}
+
+/// Completion code reported to the guest for a host transfer error
+fn completion_code(err: &UsbDriverError) -> u32 {
+    match err {
+        UsbDriverError::TransferFailed { status: UsbTransferStatus::Stalled } => CC_STALL,
+        UsbDriverError::TransferFailed { status: UsbTransferStatus::BabbleDetected } => CC_BABBLE,
+        UsbDriverError::TransferFailed { status: UsbTransferStatus::BufferOverrun }
+        | UsbDriverError::TransferFailed { status: UsbTransferStatus::BufferUnderrun } => CC_DATA_BUFFER_ERROR,
+        _ => CC_USB_TRANSACTION_ERROR,
+    }
+}
+
+// Saved state fields; endpoint N is saved under STATE_ENDPOINT + N
+const STATE_REGISTERS: u16 = 1;
+const STATE_COMMAND_RING: u16 = 2;
+const STATE_EVENT_RING: u16 = 3;
+const STATE_SLOT_ENABLED: u16 = 4;
+const STATE_INTERRUPT_PENDING: u16 = 5;
+const STATE_ENDPOINT: u16 = 0x100;
+
+/// The guest-visible controller state; the host device is claimed again
+/// on the restoring side rather than saved
+impl DeviceStateSerialize for UsbPassthrough {
+    fn state_version(&self) -> u16 {
+        1
+    }
+
+    fn save_state(&self, writer: &mut DeviceStateWriter) {
+        let regs = &self.regs;
+        writer.put_u64s(STATE_REGISTERS, &[
+            regs.usbcmd as u64, regs.usbsts as u64, regs.dnctrl as u64, regs.crcr, regs.dcbaap,
+            regs.config as u64, regs.portsc as u64, regs.iman as u64, regs.imod as u64,
+            regs.erstsz as u64, regs.erstba, regs.erdp,
+        ]);
+        writer.put_u64s(STATE_COMMAND_RING, &[self.command_ring.dequeue, self.command_ring.cycle as u64]);
+        writer.put_u64s(STATE_EVENT_RING, &[
+            self.event_ring.segment_base,
+            self.event_ring.segment_size as u64,
+            self.event_ring.enqueue as u64,
+            self.event_ring.cycle as u64,
+        ]);
+        writer.put_bool(STATE_SLOT_ENABLED, self.slot_enabled);
+        writer.put_bool(STATE_INTERRUPT_PENDING, self.interrupt_pending);
+        for (&endpoint, state) in &self.endpoints {
+            writer.put_u64s(STATE_ENDPOINT + endpoint as u16, &[
+                state.ring.dequeue, state.ring.cycle as u64, state.ep_type as u64, state.halted as u64,
+            ]);
+        }
+    }
+
+    fn restore_state(&mut self, reader: &DeviceStateReader) -> Result<(), HypervisorError> {
+        if let Some(values) = reader.u64s(STATE_REGISTERS).filter(|values| values.len() >= 12) {
+            self.regs = VirtualXhciRegisters {
+                usbcmd: values[0] as u32,
+                usbsts: values[1] as u32,
+                dnctrl: values[2] as u32,
+                crcr: values[3],
+                dcbaap: values[4],
+                config: values[5] as u32,
+                portsc: values[6] as u32,
+                iman: values[7] as u32,
+                imod: values[8] as u32,
+                erstsz: values[9] as u32,
+                erstba: values[10],
+                erdp: values[11],
+            };
+        }
+        if let Some(values) = reader.u64s(STATE_COMMAND_RING).filter(|values| values.len() >= 2) {
+            self.command_ring = GuestRing { dequeue: values[0], cycle: values[1] != 0 };
+        }
+        if let Some(values) = reader.u64s(STATE_EVENT_RING).filter(|values| values.len() >= 4) {
+            self.event_ring = EventRing {
+                segment_base: values[0],
+                segment_size: values[1] as u32,
+                enqueue: values[2] as u32,
+                cycle: values[3] != 0,
+            };
+        }
+        if let Some(enabled) = reader.bool(STATE_SLOT_ENABLED) {
+            self.slot_enabled = enabled;
+        }
+        if let Some(pending) = reader.bool(STATE_INTERRUPT_PENDING) {
+            self.interrupt_pending = pending;
+        }
+
+        self.endpoints.clear();
+        for tag in reader.tags().filter(|&tag| (STATE_ENDPOINT..STATE_ENDPOINT + 0x100).contains(&tag)) {
+            if let Some(values) = reader.u64s(tag).filter(|values| values.len() >= 4) {
+                self.endpoints.insert((tag - STATE_ENDPOINT) as u8, PassthroughEndpoint {
+                    ring: GuestRing { dequeue: values[0], cycle: values[1] != 0 },
+                    ep_type: values[2] as u8,
+                    halted: values[3] != 0,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use multios_usb::{DeviceFingerprint, SecurityLevel, UsbClass, UsbDevice, UsbDeviceState, UsbSecurityManager};
+
+    const VENDOR: u16 = 0x0951;
+    const PRODUCT: u16 = 0x1666;
+
+    struct NoHost;
+
+    impl UsbPassthroughHost for NoHost {
+        fn control_transfer(&mut self, _address: u8, _setup: &UsbSetupPacket, _data: &mut [u8]) -> UsbResult<usize> {
+            Err(UsbDriverError::UnsupportedFeature)
+        }
+
+        fn bulk_transfer(&mut self, _address: u8, _endpoint: u8, _data: &mut [u8]) -> UsbResult<usize> {
+            Err(UsbDriverError::UnsupportedFeature)
+        }
+
+        fn interrupt_transfer(&mut self, _address: u8, _endpoint: u8, _data: &mut [u8]) -> UsbResult<usize> {
+            Err(UsbDriverError::UnsupportedFeature)
+        }
+    }
+
+    struct NoMemory;
+
+    impl GuestMemoryAccess for NoMemory {
+        fn read_guest(&self, _gpa: u64, buf: &mut [u8]) -> Result<(), HypervisorError> {
+            buf.fill(0);
+            Ok(())
+        }
+
+        fn write_guest(&mut self, _gpa: u64, _data: &[u8]) -> Result<(), HypervisorError> {
+            Ok(())
+        }
+    }
+
+    fn framework(security: Option<UsbSecurityManager>) -> UsbFramework {
+        let mut framework = UsbFramework::new();
+        framework.devices.push(UsbDevice {
+            address: 3,
+            vendor_id: VENDOR,
+            product_id: PRODUCT,
+            speed: UsbSpeed::High,
+            state: UsbDeviceState::Configured,
+            configuration: 1,
+            interfaces: Vec::new(),
+            descriptor: None,
+        });
+        framework.security_manager = security;
+        framework
+    }
+
+    fn permissive_policy() -> UsbSecurityManager {
+        UsbSecurityManager {
+            global_security_level: UsbSecurityLevel::Basic,
+            device_policies: Vec::new(),
+            quarantine_list: Vec::new(),
+            audit_enabled: true,
+        }
+    }
+
+    fn claim(framework: &UsbFramework, device_security: &SecurityManager) -> Result<UsbPassthrough, HypervisorError> {
+        UsbPassthrough::claim(framework, device_security, VENDOR, PRODUCT, Box::new(NoHost), Box::new(NoMemory))
+    }
+
+    #[test]
+    fn test_claim_refused_without_security_manager() {
+        let mut device_security = SecurityManager::new(SecurityLevel::Medium);
+        device_security.trust_device(&DeviceFingerprint::new(VENDOR, PRODUCT, (0, 0, 0)));
+
+        assert!(claim(&framework(None), &device_security).is_err());
+        assert!(claim(&framework(Some(permissive_policy())), &device_security).is_ok());
+    }
+
+    #[test]
+    fn test_claim_refused_while_quarantined() {
+        let framework = framework(Some(permissive_policy()));
+        let mut device_security = SecurityManager::new(SecurityLevel::Medium);
+
+        // A storage device that also exposes a keyboard trips the BadUSB check
+        let mut fingerprint = DeviceFingerprint::new(VENDOR, PRODUCT, (0, 0, 0));
+        fingerprint.interfaces = vec![(UsbClass::MassStorage as u8, 0x06, 0x50), (UsbClass::HID as u8, 0x01, 0x01)];
+        assert_eq!(device_security.evaluate_enumeration(&fingerprint, 0).unwrap(), TrustState::Quarantined);
+        assert!(claim(&framework, &device_security).is_err());
+
+        let id = device_security.pending_approvals()[0].id;
+        device_security.approve_device(id, 1000).unwrap();
+        let passthrough = claim(&framework, &device_security).unwrap();
+        assert_eq!(passthrough.device_id(), (VENDOR, PRODUCT));
+        assert_eq!(passthrough.host_address(), 3);
+    }
+}
@@ -0,0 +1,712 @@
+//! VGA Display Adapter
+//!
+//! A standard VGA with the Bochs VBE extensions, like QEMU's "std" VGA.
+//! Text mode is served from the legacy window at 0xB8000; once the guest
+//! enables a VBE mode the picture comes from the linear framebuffer in
+//! BAR 0, or through 64K banks at 0xA0000. Planar 16-colour modes are not
+//! modelled. The host grabs the current picture with `capture`.
+
+use crate::{HypervisorError, ConfigError, ConfigErrorReason};
+use crate::core::GraphicsConfig;
+use super::{DeviceStateReader, DeviceStateSerialize, DeviceStateWriter, Frame, PciBar, PciBarKind, PciFunction};
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Legacy video memory window
+pub const VGA_LEGACY_BASE: u64 = 0xA0000;
+pub const VGA_LEGACY_SIZE: u64 = 0x20000;
+/// Video memory behind the linear framebuffer
+pub const VGA_VRAM_SIZE: usize = 8 << 20;
+/// PCI identity of the Bochs display adapter
+pub const VGA_PCI_VENDOR: u16 = 0x1234;
+pub const VGA_PCI_DEVICE: u16 = 0x1111;
+const VGA_PCI_CLASS: u32 = 0x03_00_00;
+/// Bochs VBE register ports
+pub const VBE_INDEX_PORT: u16 = 0x1CE;
+pub const VBE_DATA_PORT: u16 = 0x1CF;
+
+/// Text buffer, as an offset into the legacy window
+const TEXT_OFFSET: usize = 0x18000;
+const TEXT_SIZE: usize = 0x8000;
+const BANK_SIZE: usize = 0x10000;
+/// Glyph bitmaps hosts supply: 256 glyphs of 16 rows of 8 pixels
+pub const VGA_FONT_SIZE: usize = 256 * 16;
+const GLYPH_WIDTH: u32 = 8;
+const GLYPH_ROWS: usize = 16;
+
+// Register ports
+const PORT_ATTRIBUTE: u16 = 0x3C0;
+const PORT_ATTRIBUTE_READ: u16 = 0x3C1;
+const PORT_MISC_WRITE: u16 = 0x3C2;
+const PORT_SEQUENCER_INDEX: u16 = 0x3C4;
+const PORT_SEQUENCER_DATA: u16 = 0x3C5;
+const PORT_PEL_MASK: u16 = 0x3C6;
+const PORT_DAC_READ_INDEX: u16 = 0x3C7;
+const PORT_DAC_WRITE_INDEX: u16 = 0x3C8;
+const PORT_DAC_DATA: u16 = 0x3C9;
+const PORT_MISC_READ: u16 = 0x3CC;
+const PORT_GRAPHICS_INDEX: u16 = 0x3CE;
+const PORT_GRAPHICS_DATA: u16 = 0x3CF;
+const PORT_CRTC_INDEX_MONO: u16 = 0x3B4;
+const PORT_CRTC_DATA_MONO: u16 = 0x3B5;
+const PORT_STATUS_MONO: u16 = 0x3BA;
+const PORT_CRTC_INDEX: u16 = 0x3D4;
+const PORT_CRTC_DATA: u16 = 0x3D5;
+const PORT_STATUS: u16 = 0x3DA;
+
+const STATUS_DISPLAY_DISABLED: u8 = 1 << 0;
+const STATUS_VERTICAL_RETRACE: u8 = 1 << 3;
+
+// CRTC registers
+const CRTC_HORIZONTAL_END: usize = 0x01;
+const CRTC_OVERFLOW: usize = 0x07;
+const CRTC_MAX_SCAN_LINE: usize = 0x09;
+const CRTC_CURSOR_START: usize = 0x0A;
+const CRTC_CURSOR_END: usize = 0x0B;
+const CRTC_START_HIGH: usize = 0x0C;
+const CRTC_START_LOW: usize = 0x0D;
+const CRTC_CURSOR_HIGH: usize = 0x0E;
+const CRTC_CURSOR_LOW: usize = 0x0F;
+const CRTC_VERTICAL_END: usize = 0x12;
+const CURSOR_DISABLED: u8 = 1 << 5;
+
+/// Register values of BIOS mode 3, 80x25 colour text
+const MODE3_CRTC: [u8; 25] = [
+    0x5F, 0x4F, 0x50, 0x82, 0x55, 0x81, 0xBF, 0x1F, 0x00, 0x4F, 0x0D, 0x0E, 0x00,
+    0x00, 0x00, 0x00, 0x9C, 0x8E, 0x8F, 0x28, 0x1F, 0x96, 0xB9, 0xA3, 0xFF,
+];
+const MODE3_ATTRIBUTE_PALETTE: [u8; 16] = [
+    0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x14, 0x07, 0x38, 0x39, 0x3A, 0x3B, 0x3C, 0x3D, 0x3E, 0x3F,
+];
+const MODE3_MISC: u8 = 0x67;
+const ATTRIBUTE_REGISTERS: usize = 21;
+const ATTRIBUTE_INDEX_MASK: u8 = 0x1F;
+/// Palette address source: set while the display uses the palette
+const ATTRIBUTE_PAS: u8 = 1 << 5;
+
+// Bochs VBE registers
+const VBE_ID: usize = 0x0;
+const VBE_XRES: usize = 0x1;
+const VBE_YRES: usize = 0x2;
+const VBE_BPP: usize = 0x3;
+const VBE_ENABLE: usize = 0x4;
+const VBE_BANK: usize = 0x5;
+const VBE_VIRT_WIDTH: usize = 0x6;
+const VBE_VIRT_HEIGHT: usize = 0x7;
+const VBE_X_OFFSET: usize = 0x8;
+const VBE_Y_OFFSET: usize = 0x9;
+const VBE_VIDEO_MEMORY_64K: usize = 0xA;
+const VBE_REGISTERS: usize = 11;
+
+const VBE_ID_MIN: u16 = 0xB0C0;
+const VBE_ID_MAX: u16 = 0xB0C5;
+const VBE_ENABLED: u16 = 0x01;
+const VBE_GETCAPS: u16 = 0x02;
+const VBE_8BIT_DAC: u16 = 0x20;
+const VBE_LFB_ENABLED: u16 = 0x40;
+const VBE_NOCLEARMEM: u16 = 0x80;
+const VBE_MAX_BPP: u16 = 32;
+
+/// VGA adapter with Bochs VBE extensions
+#[derive(Debug, Clone)]
+pub struct Vga {
+    legacy: Vec<u8>,
+    vram: Vec<u8>,
+    misc: u8,
+    sequencer_index: u8,
+    sequencer: [u8; 5],
+    graphics_index: u8,
+    graphics: [u8; 9],
+    crtc_index: u8,
+    crtc: [u8; 25],
+    attribute_index: u8,
+    attribute: [u8; ATTRIBUTE_REGISTERS],
+    /// Whether the next write to 0x3C0 is data rather than an index
+    attribute_flip_flop: bool,
+    pel_mask: u8,
+    dac_read_index: u8,
+    dac_write_index: u8,
+    /// Component of the current DAC entry the next data access is for
+    dac_component: u8,
+    dac: [[u8; 3]; 256],
+    status_toggle: u8,
+    vbe_index: u16,
+    vbe: [u16; VBE_REGISTERS],
+    max_resolution: (u16, u16),
+    font: Option<Vec<u8>>,
+    /// Bumped by every change that may alter the picture
+    generation: u64,
+}
+
+impl Vga {
+    /// An adapter in 80x25 text mode; `config.resolution` caps VBE modes
+    pub fn new(config: &GraphicsConfig) -> Self {
+        let mut attribute = [0u8; ATTRIBUTE_REGISTERS];
+        attribute[..16].copy_from_slice(&MODE3_ATTRIBUTE_PALETTE);
+        // Mode control: text, blink; colour plane enable: all planes
+        attribute[0x10] = 0x0C;
+        attribute[0x12] = 0x0F;
+        attribute[0x13] = 0x08;
+
+        let mut vbe = [0u16; VBE_REGISTERS];
+        vbe[VBE_ID] = VBE_ID_MAX;
+        vbe[VBE_VIDEO_MEMORY_64K] = (VGA_VRAM_SIZE / BANK_SIZE) as u16;
+
+        let max_width = (config.resolution.0.clamp(320, 2560) & !7) as u16;
+        let max_height = config.resolution.1.clamp(200, 1600) as u16;
+
+        Vga {
+            legacy: vec![0; VGA_LEGACY_SIZE as usize],
+            vram: vec![0; VGA_VRAM_SIZE],
+            misc: MODE3_MISC,
+            sequencer_index: 0,
+            sequencer: [0x03, 0x00, 0x03, 0x00, 0x02],
+            graphics_index: 0,
+            graphics: [0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x0E, 0x00, 0xFF],
+            crtc_index: 0,
+            crtc: MODE3_CRTC,
+            attribute_index: ATTRIBUTE_PAS,
+            attribute,
+            attribute_flip_flop: false,
+            pel_mask: 0xFF,
+            dac_read_index: 0,
+            dac_write_index: 0,
+            dac_component: 0,
+            dac: default_dac(),
+            status_toggle: 0,
+            vbe_index: 0,
+            vbe,
+            max_resolution: (max_width, max_height),
+            font: None,
+            generation: 0,
+        }
+    }
+
+    /// The adapter's PCI function, with the framebuffer in BAR 0
+    pub fn pci_function() -> PciFunction {
+        let mut function = PciFunction::new(VGA_PCI_VENDOR, VGA_PCI_DEVICE, VGA_PCI_CLASS, 2);
+        function
+            .add_bar(0, PciBar { kind: PciBarKind::Memory32 { prefetchable: true }, size: VGA_VRAM_SIZE as u64 })
+            .expect("VGA framebuffer BAR is valid");
+        function
+    }
+
+    /// Whether a port belongs to the adapter
+    pub fn handles_port(port: u16) -> bool {
+        matches!(port,
+            VBE_INDEX_PORT | VBE_DATA_PORT
+            | PORT_CRTC_INDEX_MONO | PORT_CRTC_DATA_MONO | PORT_STATUS_MONO
+            | PORT_ATTRIBUTE..=PORT_GRAPHICS_DATA
+            | PORT_CRTC_INDEX | PORT_CRTC_DATA | PORT_STATUS)
+    }
+
+    /// Glyphs to draw text mode with, `VGA_FONT_SIZE` bytes. Guests load
+    /// fonts into plane 2, which is not modelled, so the host provides one;
+    /// without it text cells are drawn as blocks.
+    pub fn set_font(&mut self, font: &[u8]) -> Result<(), HypervisorError> {
+        if font.len() != VGA_FONT_SIZE {
+            return Err(HypervisorError::InvalidParameter);
+        }
+        self.font = Some(font.to_vec());
+        self.generation += 1;
+        Ok(())
+    }
+
+    /// Changes whenever the picture may have changed
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Whether a VBE mode is active rather than text mode
+    pub fn vbe_enabled(&self) -> bool {
+        self.vbe[VBE_ENABLE] & VBE_ENABLED != 0
+    }
+
+    pub fn io_read(&mut self, port: u16, size: usize) -> u32 {
+        match port {
+            VBE_INDEX_PORT => self.vbe_index as u32,
+            VBE_DATA_PORT => self.vbe_read() as u32,
+            _ if size == 2 => self.port_read(port) as u32 | (self.port_read(port + 1) as u32) << 8,
+            _ => self.port_read(port) as u32,
+        }
+    }
+
+    pub fn io_write(&mut self, port: u16, value: u32, size: usize) {
+        match port {
+            VBE_INDEX_PORT => self.vbe_index = value as u16,
+            VBE_DATA_PORT => self.vbe_write(value as u16),
+            // A word write sets an index register and its data port together
+            _ if size == 2 => {
+                self.port_write(port, value as u8);
+                self.port_write(port + 1, (value >> 8) as u8);
+            }
+            _ => self.port_write(port, value as u8),
+        }
+    }
+
+    /// Access at an offset into the legacy window
+    pub fn legacy_read(&self, offset: u64, size: usize) -> u64 {
+        (0..size.min(8)).fold(0, |value, byte| {
+            value | (self.legacy_byte(offset as usize + byte).map_or(0xFF, |index| self.read_byte(index)) as u64) << (byte * 8)
+        })
+    }
+
+    pub fn legacy_write(&mut self, offset: u64, value: u64, size: usize) {
+        for byte in 0..size.min(8) {
+            if let Some(index) = self.legacy_byte(offset as usize + byte) {
+                self.write_byte(index, (value >> (byte * 8)) as u8);
+            }
+        }
+        self.generation += 1;
+    }
+
+    /// Access at an offset into the linear framebuffer
+    pub fn framebuffer_read(&self, offset: u64, size: usize) -> u64 {
+        (0..size.min(8)).fold(0, |value, byte| {
+            let data = self.vram.get(offset as usize + byte).copied().unwrap_or(0xFF);
+            value | (data as u64) << (byte * 8)
+        })
+    }
+
+    pub fn framebuffer_write(&mut self, offset: u64, value: u64, size: usize) {
+        for byte in 0..size.min(8) {
+            if let Some(data) = self.vram.get_mut(offset as usize + byte) {
+                *data = (value >> (byte * 8)) as u8;
+            }
+        }
+        self.generation += 1;
+    }
+
+    /// Size of the picture `capture` returns
+    pub fn resolution(&self) -> (u32, u32) {
+        if self.vbe_enabled() {
+            return (self.vbe[VBE_XRES] as u32, self.vbe[VBE_YRES] as u32);
+        }
+        let (columns, rows, cell_height) = self.text_geometry();
+        (columns * GLYPH_WIDTH, rows * cell_height)
+    }
+
+    /// Text mode screen contents, one line per row; None in a VBE mode
+    pub fn text_contents(&self) -> Option<String> {
+        if self.vbe_enabled() {
+            return None;
+        }
+        let (columns, rows, _) = self.text_geometry();
+        let mut text = String::with_capacity((columns as usize + 1) * rows as usize);
+        for row in 0..rows {
+            let line: String = (0..columns)
+                .map(|column| match self.text_cell(row * columns + column).0 {
+                    0 => ' ',
+                    character @ 0x20..=0x7E => character as char,
+                    _ => '.',
+                })
+                .collect();
+            text.push_str(line.trim_end());
+            text.push('\n');
+        }
+        Some(text)
+    }
+
+    /// The picture as the monitor would show it
+    pub fn capture(&self) -> Frame {
+        if self.vbe_enabled() {
+            self.capture_vbe()
+        } else {
+            self.capture_text()
+        }
+    }
+
+    fn capture_text(&self) -> Frame {
+        let (columns, rows, cell_height) = self.text_geometry();
+        let mut frame = Frame::new(columns * GLYPH_WIDTH, rows * cell_height);
+        let cursor = self.cursor();
+
+        for row in 0..rows {
+            for column in 0..columns {
+                let cell = row * columns + column;
+                let (character, attribute) = self.text_cell(cell);
+                let foreground = self.palette_colour(attribute & 0x0F);
+                let background = self.palette_colour((attribute >> 4) & 0x07);
+
+                for line in 0..cell_height {
+                    let bits = match &self.font {
+                        Some(font) if (line as usize) < GLYPH_ROWS => font[character as usize * GLYPH_ROWS + line as usize],
+                        Some(_) => 0,
+                        // A block inside the cell, so neighbouring cells stay apart
+                        None if matches!(character, 0 | 0x20 | 0xFF) => 0,
+                        None if line >= 2 && line + 2 < cell_height => 0x7E,
+                        None => 0,
+                    };
+                    let bits = match cursor {
+                        Some((position, start, end)) if position == cell && (start..=end).contains(&line) => 0xFF,
+                        _ => bits,
+                    };
+                    for x in 0..GLYPH_WIDTH {
+                        let colour = if bits & (0x80 >> x) != 0 { foreground } else { background };
+                        frame.set_pixel(column * GLYPH_WIDTH + x, row * cell_height + line, colour);
+                    }
+                }
+            }
+        }
+        frame
+    }
+
+    fn capture_vbe(&self) -> Frame {
+        let width = self.vbe[VBE_XRES] as u32;
+        let height = self.vbe[VBE_YRES] as u32;
+        let bpp = self.vbe[VBE_BPP];
+        let bytes_per_pixel = ((bpp + 7) / 8) as usize;
+        let stride = self.vbe[VBE_VIRT_WIDTH] as usize * bytes_per_pixel;
+        let start = self.vbe[VBE_Y_OFFSET] as usize * stride + self.vbe[VBE_X_OFFSET] as usize * bytes_per_pixel;
+
+        let mut frame = Frame::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let offset = start + y as usize * stride + x as usize * bytes_per_pixel;
+                let pixel = match self.vram.get(offset..offset + bytes_per_pixel) {
+                    Some(pixel) => pixel,
+                    None => continue,
+                };
+                let rgb = match bpp {
+                    32 | 24 => [pixel[2], pixel[1], pixel[0]],
+                    16 => {
+                        let value = u16::from_le_bytes([pixel[0], pixel[1]]);
+                        [expand5((value >> 11) as u8), expand6((value >> 5) as u8), expand5(value as u8)]
+                    }
+                    15 => {
+                        let value = u16::from_le_bytes([pixel[0], pixel[1]]);
+                        [expand5((value >> 10) as u8), expand5((value >> 5) as u8), expand5(value as u8)]
+                    }
+                    _ => self.dac_colour(pixel[0] & self.pel_mask),
+                };
+                frame.set_pixel(x, y, rgb);
+            }
+        }
+        frame
+    }
+
+    /// Columns, rows and scan lines per row of text mode
+    fn text_geometry(&self) -> (u32, u32, u32) {
+        let columns = self.crtc[CRTC_HORIZONTAL_END] as u32 + 1;
+        let overflow = self.crtc[CRTC_OVERFLOW] as u32;
+        let lines = (self.crtc[CRTC_VERTICAL_END] as u32 | (overflow >> 1 & 1) << 8 | (overflow >> 6 & 1) << 9) + 1;
+        let cell_height = (self.crtc[CRTC_MAX_SCAN_LINE] & 0x1F) as u32 + 1;
+        (columns, (lines / cell_height).max(1), cell_height)
+    }
+
+    /// Character and attribute of a cell counted from the start address
+    fn text_cell(&self, cell: u32) -> (u8, u8) {
+        let start = (self.crtc[CRTC_START_HIGH] as usize) << 8 | self.crtc[CRTC_START_LOW] as usize;
+        let offset = ((start + cell as usize) * 2) % TEXT_SIZE;
+        (self.legacy[TEXT_OFFSET + offset], self.legacy[TEXT_OFFSET + (offset + 1) % TEXT_SIZE])
+    }
+
+    /// Cursor cell and scan lines, if shown
+    fn cursor(&self) -> Option<(u32, u32, u32)> {
+        if self.crtc[CRTC_CURSOR_START] & CURSOR_DISABLED != 0 {
+            return None;
+        }
+        let start = (self.crtc[CRTC_START_HIGH] as u32) << 8 | self.crtc[CRTC_START_LOW] as u32;
+        let location = (self.crtc[CRTC_CURSOR_HIGH] as u32) << 8 | self.crtc[CRTC_CURSOR_LOW] as u32;
+        let position = location.checked_sub(start)?;
+        Some((position, (self.crtc[CRTC_CURSOR_START] & 0x1F) as u32, (self.crtc[CRTC_CURSOR_END] & 0x1F) as u32))
+    }
+
+    /// Colour of a text attribute through the attribute palette and DAC
+    fn palette_colour(&self, index: u8) -> [u8; 3] {
+        self.dac_colour(self.attribute[index as usize] & 0x3F)
+    }
+
+    fn dac_colour(&self, index: u8) -> [u8; 3] {
+        let entry = self.dac[index as usize];
+        if self.vbe[VBE_ENABLE] & VBE_8BIT_DAC != 0 {
+            entry
+        } else {
+            [expand6(entry[0]), expand6(entry[1]), expand6(entry[2])]
+        }
+    }
+
+    /// Index into the legacy buffer or VRAM behind an offset into the
+    /// legacy window; `VGA_VRAM_SIZE` and up address the legacy buffer
+    fn legacy_byte(&self, offset: usize) -> Option<usize> {
+        if offset >= VGA_LEGACY_SIZE as usize {
+            return None;
+        }
+        if !self.vbe_enabled() {
+            return Some(VGA_VRAM_SIZE + offset);
+        }
+        if offset >= BANK_SIZE {
+            return None;
+        }
+        let index = self.vbe[VBE_BANK] as usize * BANK_SIZE + offset;
+        (index < VGA_VRAM_SIZE).then_some(index)
+    }
+
+    fn read_byte(&self, index: usize) -> u8 {
+        match index.checked_sub(VGA_VRAM_SIZE) {
+            Some(legacy) => self.legacy[legacy],
+            None => self.vram[index],
+        }
+    }
+
+    fn write_byte(&mut self, index: usize, value: u8) {
+        match index.checked_sub(VGA_VRAM_SIZE) {
+            Some(legacy) => self.legacy[legacy] = value,
+            None => self.vram[index] = value,
+        }
+    }
+
+    fn port_read(&mut self, port: u16) -> u8 {
+        match port {
+            PORT_ATTRIBUTE => self.attribute_index,
+            PORT_ATTRIBUTE_READ => self.attribute.get((self.attribute_index & ATTRIBUTE_INDEX_MASK) as usize).copied().unwrap_or(0),
+            PORT_MISC_READ => self.misc,
+            PORT_SEQUENCER_INDEX => self.sequencer_index,
+            PORT_SEQUENCER_DATA => self.sequencer.get(self.sequencer_index as usize).copied().unwrap_or(0),
+            PORT_PEL_MASK => self.pel_mask,
+            PORT_DAC_WRITE_INDEX => self.dac_write_index,
+            PORT_DAC_DATA => {
+                let value = self.dac[self.dac_read_index as usize][self.dac_component as usize];
+                self.dac_component += 1;
+                if self.dac_component == 3 {
+                    self.dac_component = 0;
+                    self.dac_read_index = self.dac_read_index.wrapping_add(1);
+                }
+                value
+            }
+            PORT_GRAPHICS_INDEX => self.graphics_index,
+            PORT_GRAPHICS_DATA => self.graphics.get(self.graphics_index as usize).copied().unwrap_or(0),
+            PORT_CRTC_INDEX | PORT_CRTC_INDEX_MONO => self.crtc_index,
+            PORT_CRTC_DATA | PORT_CRTC_DATA_MONO => self.crtc.get(self.crtc_index as usize).copied().unwrap_or(0),
+            PORT_STATUS | PORT_STATUS_MONO => {
+                // Reading status resets the attribute flip-flop; retrace
+                // toggles so guests polling for it make progress
+                self.attribute_flip_flop = false;
+                self.status_toggle ^= STATUS_VERTICAL_RETRACE | STATUS_DISPLAY_DISABLED;
+                self.status_toggle
+            }
+            _ => 0xFF,
+        }
+    }
+
+    fn port_write(&mut self, port: u16, value: u8) {
+        match port {
+            PORT_ATTRIBUTE => {
+                if self.attribute_flip_flop {
+                    let index = (self.attribute_index & ATTRIBUTE_INDEX_MASK) as usize;
+                    if let Some(register) = self.attribute.get_mut(index) {
+                        *register = value;
+                    }
+                } else {
+                    self.attribute_index = value;
+                }
+                self.attribute_flip_flop = !self.attribute_flip_flop;
+            }
+            PORT_MISC_WRITE => self.misc = value,
+            PORT_SEQUENCER_INDEX => self.sequencer_index = value,
+            PORT_SEQUENCER_DATA => {
+                if let Some(register) = self.sequencer.get_mut(self.sequencer_index as usize) {
+                    *register = value;
+                }
+            }
+            PORT_PEL_MASK => self.pel_mask = value,
+            PORT_DAC_READ_INDEX => {
+                self.dac_read_index = value;
+                self.dac_component = 0;
+            }
+            PORT_DAC_WRITE_INDEX => {
+                self.dac_write_index = value;
+                self.dac_component = 0;
+            }
+            PORT_DAC_DATA => {
+                let mask = if self.vbe[VBE_ENABLE] & VBE_8BIT_DAC != 0 { 0xFF } else { 0x3F };
+                self.dac[self.dac_write_index as usize][self.dac_component as usize] = value & mask;
+                self.dac_component += 1;
+                if self.dac_component == 3 {
+                    self.dac_component = 0;
+                    self.dac_write_index = self.dac_write_index.wrapping_add(1);
+                }
+            }
+            PORT_GRAPHICS_INDEX => self.graphics_index = value,
+            PORT_GRAPHICS_DATA => {
+                if let Some(register) = self.graphics.get_mut(self.graphics_index as usize) {
+                    *register = value;
+                }
+            }
+            PORT_CRTC_INDEX | PORT_CRTC_INDEX_MONO => self.crtc_index = value,
+            PORT_CRTC_DATA | PORT_CRTC_DATA_MONO => {
+                if let Some(register) = self.crtc.get_mut(self.crtc_index as usize) {
+                    *register = value;
+                }
+            }
+            _ => return,
+        }
+        self.generation += 1;
+    }
+
+    fn vbe_read(&self) -> u16 {
+        let index = self.vbe_index as usize;
+        if self.vbe[VBE_ENABLE] & VBE_GETCAPS != 0 {
+            match index {
+                VBE_XRES => return self.max_resolution.0,
+                VBE_YRES => return self.max_resolution.1,
+                VBE_BPP => return VBE_MAX_BPP,
+                _ => {}
+            }
+        }
+        self.vbe.get(index).copied().unwrap_or(0)
+    }
+
+    fn vbe_write(&mut self, value: u16) {
+        let index = self.vbe_index as usize;
+        let enabled = self.vbe_enabled();
+        match index {
+            VBE_ID if (VBE_ID_MIN..=VBE_ID_MAX).contains(&value) => self.vbe[VBE_ID] = value,
+            VBE_XRES if !enabled && value % 8 == 0 && value <= self.max_resolution.0 => self.vbe[VBE_XRES] = value,
+            VBE_YRES if !enabled && value <= self.max_resolution.1 => self.vbe[VBE_YRES] = value,
+            VBE_BPP if !enabled => {
+                // Bochs treats 0 as 8 bits per pixel
+                let bpp = if value == 0 { 8 } else { value };
+                if matches!(bpp, 8 | 15 | 16 | 24 | 32) {
+                    self.vbe[VBE_BPP] = bpp;
+                }
+            }
+            VBE_ENABLE => self.vbe_enable(value),
+            VBE_BANK if (value as usize) < VGA_VRAM_SIZE / BANK_SIZE => self.vbe[VBE_BANK] = value,
+            VBE_VIRT_WIDTH if enabled && value >= self.vbe[VBE_XRES] => {
+                self.vbe[VBE_VIRT_WIDTH] = value;
+                self.vbe[VBE_VIRT_HEIGHT] = self.virtual_height(value);
+            }
+            VBE_X_OFFSET | VBE_Y_OFFSET => self.vbe[index] = value,
+            _ => return,
+        }
+        self.generation += 1;
+    }
+
+    fn vbe_enable(&mut self, value: u16) {
+        let enabling = value & VBE_ENABLED != 0;
+        if enabling && !self.vbe_enabled() {
+            let (width, height, bpp) = (self.vbe[VBE_XRES], self.vbe[VBE_YRES], self.vbe[VBE_BPP]);
+            let bytes = width as usize * height as usize * ((bpp as usize + 7) / 8);
+            if width == 0 || height == 0 || bpp == 0 || bytes > VGA_VRAM_SIZE {
+                warn!("VGA: rejecting VBE mode {}x{}x{}", width, height, bpp);
+                return;
+            }
+            self.vbe[VBE_VIRT_WIDTH] = width;
+            self.vbe[VBE_VIRT_HEIGHT] = self.virtual_height(width);
+            self.vbe[VBE_X_OFFSET] = 0;
+            self.vbe[VBE_Y_OFFSET] = 0;
+            self.vbe[VBE_BANK] = 0;
+            if value & VBE_NOCLEARMEM == 0 {
+                self.vram[..bytes].fill(0);
+            }
+            info!("VGA: VBE mode {}x{}x{}", width, height, bpp);
+        }
+        self.vbe[VBE_ENABLE] = value & (VBE_ENABLED | VBE_GETCAPS | VBE_8BIT_DAC | VBE_LFB_ENABLED);
+    }
+
+    fn virtual_height(&self, virtual_width: u16) -> u16 {
+        let bytes_per_pixel = (self.vbe[VBE_BPP] as usize + 7) / 8;
+        (VGA_VRAM_SIZE / (virtual_width as usize * bytes_per_pixel).max(1)).min(u16::MAX as usize) as u16
+    }
+}
+
+/// DAC loaded with the EGA colours, as the BIOS leaves it for text mode
+fn default_dac() -> [[u8; 3]; 256] {
+    let mut dac = [[0u8; 3]; 256];
+    for (index, entry) in dac.iter_mut().enumerate().take(64) {
+        // Bits 2/5 are the primary/secondary red, 1/4 green and 0/3 blue
+        let level = |primary: usize, secondary: usize| (((index >> primary) & 1) * 2 + ((index >> secondary) & 1)) as u8 * 21;
+        *entry = [level(2, 5), level(1, 4), level(0, 3)];
+    }
+    dac
+}
+
+fn expand5(value: u8) -> u8 {
+    let value = value & 0x1F;
+    value << 3 | value >> 2
+}
+
+fn expand6(value: u8) -> u8 {
+    let value = value & 0x3F;
+    value << 2 | value >> 4
+}
+
+// Saved state fields
+const STATE_LEGACY: u16 = 1;
+const STATE_VRAM: u16 = 2;
+const STATE_REGISTERS: u16 = 3;
+const STATE_DAC: u16 = 4;
+const STATE_VBE: u16 = 5;
+const STATE_INDEXES: u16 = 6;
+
+impl DeviceStateSerialize for Vga {
+    fn state_version(&self) -> u16 {
+        1
+    }
+
+    fn save_state(&self, writer: &mut DeviceStateWriter) {
+        writer.put_bytes(STATE_LEGACY, &self.legacy);
+        writer.put_bytes(STATE_VRAM, &self.vram);
+        let mut registers = Vec::with_capacity(64);
+        registers.push(self.misc);
+        registers.push(self.pel_mask);
+        registers.extend_from_slice(&self.sequencer);
+        registers.extend_from_slice(&self.graphics);
+        registers.extend_from_slice(&self.crtc);
+        registers.extend_from_slice(&self.attribute);
+        writer.put_bytes(STATE_REGISTERS, &registers);
+        writer.put_bytes(STATE_DAC, &self.dac.concat());
+        writer.put_u64s(STATE_VBE, &self.vbe.map(|register| register as u64));
+        writer.put_bytes(STATE_INDEXES, &[
+            self.sequencer_index, self.graphics_index, self.crtc_index, self.attribute_index,
+            self.attribute_flip_flop as u8, self.dac_read_index, self.dac_write_index, self.dac_component,
+            self.vbe_index as u8, (self.vbe_index >> 8) as u8,
+        ]);
+    }
+
+    fn restore_state(&mut self, reader: &DeviceStateReader) -> Result<(), HypervisorError> {
+        if let Some(legacy) = reader.bytes(STATE_LEGACY).filter(|legacy| legacy.len() == self.legacy.len()) {
+            self.legacy.copy_from_slice(legacy);
+        }
+        if let Some(vram) = reader.bytes(STATE_VRAM) {
+            if vram.len() > self.vram.len() {
+                return Err(ConfigError::new("vga.vram", ConfigErrorReason::Mismatch).value(vram.len() as u64).into());
+            }
+            self.vram[..vram.len()].copy_from_slice(vram);
+        }
+        if let Some(registers) = reader.bytes(STATE_REGISTERS).filter(|registers| registers.len() == 2 + 5 + 9 + 25 + ATTRIBUTE_REGISTERS) {
+            self.misc = registers[0];
+            self.pel_mask = registers[1];
+            self.sequencer.copy_from_slice(&registers[2..7]);
+            self.graphics.copy_from_slice(&registers[7..16]);
+            self.crtc.copy_from_slice(&registers[16..41]);
+            self.attribute.copy_from_slice(&registers[41..]);
+        }
+        if let Some(dac) = reader.bytes(STATE_DAC).filter(|dac| dac.len() == 768) {
+            for (entry, saved) in self.dac.iter_mut().zip(dac.chunks_exact(3)) {
+                entry.copy_from_slice(saved);
+            }
+        }
+        if let Some(vbe) = reader.u64s(STATE_VBE).filter(|vbe| vbe.len() == VBE_REGISTERS) {
+            for (register, saved) in self.vbe.iter_mut().zip(vbe) {
+                *register = saved as u16;
+            }
+        }
+        if let Some(indexes) = reader.bytes(STATE_INDEXES).filter(|indexes| indexes.len() == 10) {
+            self.sequencer_index = indexes[0];
+            self.graphics_index = indexes[1];
+            self.crtc_index = indexes[2];
+            self.attribute_index = indexes[3];
+            self.attribute_flip_flop = indexes[4] != 0;
+            self.dac_read_index = indexes[5];
+            self.dac_write_index = indexes[6];
+            self.dac_component = indexes[7] % 3;
+            self.vbe_index = u16::from_le_bytes([indexes[8], indexes[9]]);
+        }
+        self.generation += 1;
+        Ok(())
+    }
+}
@@ -0,0 +1,646 @@
+//! Virtio PCI Transport
+//!
+//! The modern (virtio 1.x) PCI transport and split virtqueues, shared by
+//! the virtio device models. A model implements `VirtioDevice`; the
+//! transport owns its queues, feature negotiation and status, and turns
+//! used buffers into MSI-X vectors or the legacy interrupt line.
+//! Indirect descriptors and event index suppression are not offered.
+
+use crate::{HypervisorError, ConfigError, ConfigErrorReason, IoError, IoErrorKind};
+use super::{
+    DeviceStateReader, DeviceStateSerialize, DeviceStateWriter, GuestMemoryAccess, PciBar, PciBarKind, PciFunction,
+};
+
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Feature bit every modern device offers
+pub const VIRTIO_F_VERSION_1: u64 = 1 << 32;
+/// MSI-X vector meaning "no vector"
+pub const VIRTIO_MSI_NO_VECTOR: u16 = 0xFFFF;
+
+// Device status bits
+pub const VIRTIO_STATUS_ACKNOWLEDGE: u8 = 1;
+pub const VIRTIO_STATUS_DRIVER: u8 = 2;
+pub const VIRTIO_STATUS_DRIVER_OK: u8 = 4;
+pub const VIRTIO_STATUS_FEATURES_OK: u8 = 8;
+pub const VIRTIO_STATUS_NEEDS_RESET: u8 = 64;
+pub const VIRTIO_STATUS_FAILED: u8 = 128;
+
+// ISR status bits
+const ISR_QUEUE: u8 = 1;
+const ISR_CONFIG: u8 = 2;
+
+/// Layout of the transport BAR
+const VIRTIO_BAR: usize = 4;
+const VIRTIO_BAR_SIZE: u64 = 0x8000;
+const COMMON_OFFSET: u64 = 0x0000;
+const COMMON_SIZE: u64 = 0x38;
+const ISR_OFFSET: u64 = 0x1000;
+const DEVICE_OFFSET: u64 = 0x2000;
+const DEVICE_SIZE: u64 = 0x1000;
+const NOTIFY_OFFSET: u64 = 0x3000;
+const NOTIFY_MULTIPLIER: u32 = 4;
+const MSIX_TABLE_OFFSET: u64 = 0x4000;
+const MSIX_PBA_OFFSET: u64 = 0x5000;
+
+// Vendor capability types
+const CAP_COMMON_CFG: u8 = 1;
+const CAP_NOTIFY_CFG: u8 = 2;
+const CAP_ISR_CFG: u8 = 3;
+const CAP_DEVICE_CFG: u8 = 4;
+
+// Common configuration registers
+const COMMON_DEVICE_FEATURE_SELECT: u64 = 0x00;
+const COMMON_DEVICE_FEATURE: u64 = 0x04;
+const COMMON_DRIVER_FEATURE_SELECT: u64 = 0x08;
+const COMMON_DRIVER_FEATURE: u64 = 0x0C;
+const COMMON_MSIX_CONFIG: u64 = 0x10;
+const COMMON_NUM_QUEUES: u64 = 0x12;
+const COMMON_DEVICE_STATUS: u64 = 0x14;
+const COMMON_CONFIG_GENERATION: u64 = 0x15;
+const COMMON_QUEUE_SELECT: u64 = 0x16;
+const COMMON_QUEUE_SIZE: u64 = 0x18;
+const COMMON_QUEUE_MSIX_VECTOR: u64 = 0x1A;
+const COMMON_QUEUE_ENABLE: u64 = 0x1C;
+const COMMON_QUEUE_NOTIFY_OFF: u64 = 0x1E;
+const COMMON_QUEUE_DESC: u64 = 0x20;
+const COMMON_QUEUE_DRIVER: u64 = 0x28;
+const COMMON_QUEUE_DEVICE: u64 = 0x30;
+
+// Descriptor flags
+const VIRTQ_DESC_F_NEXT: u16 = 1;
+const VIRTQ_DESC_F_WRITE: u16 = 2;
+const VIRTQ_DESC_F_INDIRECT: u16 = 4;
+/// Driver asks not to be interrupted for used buffers
+const VIRTQ_AVAIL_F_NO_INTERRUPT: u16 = 1;
+const DESCRIPTOR_SIZE: u64 = 16;
+/// Most a device copies out of a chain in one go
+const MAX_CHAIN_READ: usize = 16 << 20;
+
+/// A virtio device model behind the PCI transport
+pub trait VirtioDevice {
+    /// Virtio device type, e.g. 18 for input
+    fn device_type(&self) -> u16;
+
+    /// PCI class code the function reports
+    fn pci_class(&self) -> u32;
+
+    /// Device-specific feature bits; the transport adds `VIRTIO_F_VERSION_1`
+    fn device_features(&self) -> u64;
+
+    /// Largest size of each queue
+    fn queue_max_sizes(&self) -> &[u16];
+
+    fn read_config(&self, offset: usize, data: &mut [u8]);
+
+    fn write_config(&mut self, offset: usize, data: &[u8]);
+
+    /// Handle buffers the driver made available; returns whether any were
+    /// used
+    fn process_queue(&mut self, index: usize, queue: &mut VirtQueue, memory: &mut dyn GuestMemoryAccess)
+        -> Result<bool, HypervisorError>;
+
+    /// Return to the state before the driver started
+    fn reset(&mut self);
+}
+
+/// A descriptor chain taken from a queue
+#[derive(Debug, Clone)]
+pub struct DescriptorChain {
+    pub head: u16,
+    /// Buffers the device reads, as address and length
+    pub readable: Vec<(u64, u32)>,
+    /// Buffers the device writes
+    pub writable: Vec<(u64, u32)>,
+}
+
+impl DescriptorChain {
+    /// Contents of the readable buffers
+    pub fn read_all(&self, memory: &dyn GuestMemoryAccess) -> Result<Vec<u8>, HypervisorError> {
+        let total: usize = self.readable.iter().map(|&(_, length)| length as usize).sum();
+        if total > MAX_CHAIN_READ {
+            return Err(queue_error("descriptor chain too large to read at once"));
+        }
+        let mut data = vec![0u8; total];
+        let mut offset = 0;
+        for &(address, length) in &self.readable {
+            memory.read_guest(address, &mut data[offset..offset + length as usize])?;
+            offset += length as usize;
+        }
+        Ok(data)
+    }
+
+    /// Fill the writable buffers in order; returns the bytes written
+    pub fn write(&self, memory: &mut dyn GuestMemoryAccess, mut data: &[u8]) -> Result<u32, HypervisorError> {
+        let mut written = 0;
+        for &(address, length) in &self.writable {
+            if data.is_empty() {
+                break;
+            }
+            let part = data.len().min(length as usize);
+            memory.write_guest(address, &data[..part])?;
+            data = &data[part..];
+            written += part as u32;
+        }
+        Ok(written)
+    }
+
+    pub fn writable_len(&self) -> u32 {
+        self.writable.iter().map(|&(_, length)| length).sum()
+    }
+}
+
+/// A split virtqueue
+#[derive(Debug, Clone, Copy, Default)]
+pub struct VirtQueue {
+    pub max_size: u16,
+    pub size: u16,
+    pub ready: bool,
+    pub msix_vector: u16,
+    pub desc_addr: u64,
+    pub driver_addr: u64,
+    pub device_addr: u64,
+    last_avail: u16,
+    used_index: u16,
+}
+
+impl VirtQueue {
+    fn new(max_size: u16) -> Self {
+        VirtQueue { max_size, size: max_size, msix_vector: VIRTIO_MSI_NO_VECTOR, ..Default::default() }
+    }
+
+    /// Take the next chain the driver made available
+    pub fn pop(&mut self, memory: &dyn GuestMemoryAccess) -> Result<Option<DescriptorChain>, HypervisorError> {
+        if !self.ready || self.size == 0 {
+            return Ok(None);
+        }
+        let avail_index = read_u16(memory, self.driver_addr + 2)?;
+        if avail_index == self.last_avail {
+            return Ok(None);
+        }
+        if avail_index.wrapping_sub(self.last_avail) > self.size {
+            return Err(queue_error("driver advanced the available ring past its size"));
+        }
+
+        let slot = (self.last_avail % self.size) as u64;
+        let head = read_u16(memory, self.driver_addr + 4 + slot * 2)?;
+        self.last_avail = self.last_avail.wrapping_add(1);
+
+        let mut chain = DescriptorChain { head, readable: Vec::new(), writable: Vec::new() };
+        let mut index = head;
+        // A chain longer than the table has a loop
+        for _ in 0..self.size {
+            if index >= self.size {
+                return Err(queue_error("descriptor index out of range"));
+            }
+            let mut descriptor = [0u8; DESCRIPTOR_SIZE as usize];
+            memory.read_guest(self.desc_addr + index as u64 * DESCRIPTOR_SIZE, &mut descriptor)?;
+            let mut address = [0u8; 8];
+            address.copy_from_slice(&descriptor[0..8]);
+            let address = u64::from_le_bytes(address);
+            let length = u32::from_le_bytes([descriptor[8], descriptor[9], descriptor[10], descriptor[11]]);
+            let flags = u16::from_le_bytes([descriptor[12], descriptor[13]]);
+            let next = u16::from_le_bytes([descriptor[14], descriptor[15]]);
+
+            if flags & VIRTQ_DESC_F_INDIRECT != 0 {
+                return Err(queue_error("indirect descriptors were not negotiated"));
+            }
+            if flags & VIRTQ_DESC_F_WRITE != 0 {
+                chain.writable.push((address, length));
+            } else if chain.writable.is_empty() {
+                chain.readable.push((address, length));
+            } else {
+                return Err(queue_error("readable descriptor after a writable one"));
+            }
+            if flags & VIRTQ_DESC_F_NEXT == 0 {
+                return Ok(Some(chain));
+            }
+            index = next;
+        }
+        Err(queue_error("descriptor chain loops"))
+    }
+
+    /// Return a chain to the driver with `written` bytes filled in
+    pub fn push_used(&mut self, memory: &mut dyn GuestMemoryAccess, head: u16, written: u32) -> Result<(), HypervisorError> {
+        let slot = (self.used_index % self.size) as u64;
+        let mut element = [0u8; 8];
+        element[..4].copy_from_slice(&(head as u32).to_le_bytes());
+        element[4..].copy_from_slice(&written.to_le_bytes());
+        memory.write_guest(self.device_addr + 4 + slot * 8, &element)?;
+        self.used_index = self.used_index.wrapping_add(1);
+        memory.write_guest(self.device_addr + 2, &self.used_index.to_le_bytes())
+    }
+
+    /// Whether the driver wants an interrupt for used buffers
+    fn wants_interrupt(&self, memory: &dyn GuestMemoryAccess) -> Result<bool, HypervisorError> {
+        Ok(read_u16(memory, self.driver_addr)? & VIRTQ_AVAIL_F_NO_INTERRUPT == 0)
+    }
+
+    fn reset(&mut self) {
+        *self = VirtQueue::new(self.max_size);
+    }
+}
+
+fn read_u16(memory: &dyn GuestMemoryAccess, address: u64) -> Result<u16, HypervisorError> {
+    let mut bytes = [0u8; 2];
+    memory.read_guest(address, &mut bytes)?;
+    Ok(u16::from_le_bytes(bytes))
+}
+
+fn queue_error(what: &str) -> HypervisorError {
+    warn!("virtqueue: {}", what);
+    IoError::new("virtqueue", IoErrorKind::Protocol).into()
+}
+
+/// A virtio device on the PCI transport
+pub struct VirtioPci<D: VirtioDevice> {
+    device: D,
+    memory: Box<dyn GuestMemoryAccess + Send>,
+    queues: Vec<VirtQueue>,
+    device_feature_select: u32,
+    driver_feature_select: u32,
+    driver_features: u64,
+    status: u8,
+    queue_select: u16,
+    msix_config: u16,
+    config_generation: u8,
+    isr: u8,
+    /// Interrupt line level last reported by `take_intx_change`
+    intx_reported: bool,
+    pending_vectors: Vec<u16>,
+}
+
+impl<D: VirtioDevice> VirtioPci<D> {
+    /// `memory` is the guest memory the device's queues live in
+    pub fn new(device: D, memory: Box<dyn GuestMemoryAccess + Send>) -> Self {
+        let queues = device.queue_max_sizes().iter().map(|&size| VirtQueue::new(size)).collect();
+        VirtioPci {
+            device,
+            memory,
+            queues,
+            device_feature_select: 0,
+            driver_feature_select: 0,
+            driver_features: 0,
+            status: 0,
+            queue_select: 0,
+            msix_config: VIRTIO_MSI_NO_VECTOR,
+            config_generation: 0,
+            isr: 0,
+            intx_reported: false,
+            pending_vectors: Vec::new(),
+        }
+    }
+
+    pub fn device(&self) -> &D {
+        &self.device
+    }
+
+    pub fn device_mut(&mut self) -> &mut D {
+        &mut self.device
+    }
+
+    /// Whether the driver finished initialisation
+    pub fn driver_ok(&self) -> bool {
+        self.status & VIRTIO_STATUS_DRIVER_OK != 0
+    }
+
+    pub fn negotiated_features(&self) -> u64 {
+        self.driver_features
+    }
+
+    /// The PCI function: transport structures and MSI-X table in BAR 4,
+    /// one vector for configuration changes plus one per queue
+    pub fn pci_function(&self) -> Result<PciFunction, HypervisorError> {
+        let mut function = PciFunction::virtio(self.device.device_type(), self.device.pci_class());
+        function.add_bar(VIRTIO_BAR, PciBar { kind: PciBarKind::Memory64 { prefetchable: false }, size: VIRTIO_BAR_SIZE })?;
+        for &(kind, offset, length) in &[
+            (CAP_COMMON_CFG, COMMON_OFFSET, COMMON_SIZE),
+            (CAP_ISR_CFG, ISR_OFFSET, 1),
+            (CAP_DEVICE_CFG, DEVICE_OFFSET, DEVICE_SIZE),
+        ] {
+            function.add_vendor_capability(&virtio_cap(kind, offset, length))?;
+        }
+        let mut notify = virtio_cap(CAP_NOTIFY_CFG, NOTIFY_OFFSET, self.queues.len() as u64 * NOTIFY_MULTIPLIER as u64);
+        notify.extend_from_slice(&NOTIFY_MULTIPLIER.to_le_bytes());
+        function.add_vendor_capability(&notify)?;
+        function.add_msix(self.queues.len() as u16 + 1, VIRTIO_BAR, MSIX_TABLE_OFFSET, VIRTIO_BAR, MSIX_PBA_OFFSET)?;
+        function.set_interrupt_pin(1);
+        Ok(function)
+    }
+
+    /// Read at an offset into the transport BAR
+    pub fn mmio_read(&mut self, offset: u64, size: usize) -> u64 {
+        match offset {
+            COMMON_OFFSET..=0x0FFF => self.common_read(offset - COMMON_OFFSET, size),
+            ISR_OFFSET => {
+                // Reading the ISR acknowledges the interrupt
+                let isr = self.isr;
+                self.isr = 0;
+                isr as u64
+            }
+            DEVICE_OFFSET..=0x2FFF => {
+                let mut data = [0u8; 8];
+                let size = size.min(8);
+                self.device.read_config((offset - DEVICE_OFFSET) as usize, &mut data[..size]);
+                u64::from_le_bytes(data)
+            }
+            _ => 0,
+        }
+    }
+
+    /// Write at an offset into the transport BAR
+    pub fn mmio_write(&mut self, offset: u64, value: u64, size: usize) {
+        match offset {
+            COMMON_OFFSET..=0x0FFF => self.common_write(offset - COMMON_OFFSET, value, size),
+            DEVICE_OFFSET..=0x2FFF => {
+                let size = size.min(8);
+                self.device.write_config((offset - DEVICE_OFFSET) as usize, &value.to_le_bytes()[..size]);
+            }
+            NOTIFY_OFFSET..=0x3FFF => {
+                let queue = ((offset - NOTIFY_OFFSET) / NOTIFY_MULTIPLIER as u64) as usize;
+                self.process_queue(queue);
+            }
+            _ => {}
+        }
+    }
+
+    /// Let the device handle a queue, after a notification or when it has
+    /// something new to put in it
+    pub fn process_queue(&mut self, index: usize) {
+        if !self.driver_ok() || self.status & VIRTIO_STATUS_NEEDS_RESET != 0 {
+            return;
+        }
+        let queue = match self.queues.get_mut(index) {
+            Some(queue) if queue.ready => queue,
+            _ => return,
+        };
+        match self.device.process_queue(index, queue, self.memory.as_mut()) {
+            Ok(true) => {
+                let vector = queue.msix_vector;
+                match queue.wants_interrupt(self.memory.as_ref()) {
+                    Ok(true) => self.signal(ISR_QUEUE, vector),
+                    Ok(false) => {}
+                    Err(e) => self.device_error(e),
+                }
+            }
+            Ok(false) => {}
+            Err(e) => self.device_error(e),
+        }
+    }
+
+    /// Tell the driver the device configuration changed
+    pub fn config_changed(&mut self) {
+        self.config_generation = self.config_generation.wrapping_add(1);
+        if self.driver_ok() {
+            self.signal(ISR_CONFIG, self.msix_config);
+        }
+    }
+
+    /// MSI-X vectors signalled since the last call; `VIRTIO_MSI_NO_VECTOR`
+    /// entries only raise the ISR
+    pub fn take_vectors(&mut self) -> Vec<u16> {
+        core::mem::take(&mut self.pending_vectors)
+    }
+
+    /// New level of the legacy interrupt line, if it changed
+    pub fn take_intx_change(&mut self) -> Option<bool> {
+        let level = self.isr != 0;
+        if level == self.intx_reported {
+            return None;
+        }
+        self.intx_reported = level;
+        Some(level)
+    }
+
+    fn signal(&mut self, isr: u8, vector: u16) {
+        self.isr |= isr;
+        self.pending_vectors.push(vector);
+    }
+
+    /// The driver broke the protocol: stop the device until it resets
+    fn device_error(&mut self, error: HypervisorError) {
+        warn!("virtio device type {}: {}, device needs reset", self.device.device_type(), error);
+        self.status |= VIRTIO_STATUS_NEEDS_RESET;
+        self.config_changed();
+    }
+
+    fn reset(&mut self) {
+        self.device.reset();
+        for queue in self.queues.iter_mut() {
+            queue.reset();
+        }
+        self.device_feature_select = 0;
+        self.driver_feature_select = 0;
+        self.driver_features = 0;
+        self.status = 0;
+        self.queue_select = 0;
+        self.msix_config = VIRTIO_MSI_NO_VECTOR;
+        self.isr = 0;
+        self.pending_vectors.clear();
+    }
+
+    fn offered_features(&self) -> u64 {
+        self.device.device_features() | VIRTIO_F_VERSION_1
+    }
+
+    fn selected_queue(&self) -> Option<&VirtQueue> {
+        self.queues.get(self.queue_select as usize)
+    }
+
+    fn common_read(&self, register: u64, size: usize) -> u64 {
+        let queue = self.selected_queue();
+        let value = match register {
+            COMMON_DEVICE_FEATURE_SELECT => self.device_feature_select as u64,
+            COMMON_DEVICE_FEATURE => match self.device_feature_select {
+                0 => self.offered_features() & 0xFFFF_FFFF,
+                1 => self.offered_features() >> 32,
+                _ => 0,
+            },
+            COMMON_DRIVER_FEATURE_SELECT => self.driver_feature_select as u64,
+            COMMON_DRIVER_FEATURE => match self.driver_feature_select {
+                0 => self.driver_features & 0xFFFF_FFFF,
+                1 => self.driver_features >> 32,
+                _ => 0,
+            },
+            COMMON_MSIX_CONFIG => self.msix_config as u64,
+            COMMON_NUM_QUEUES => self.queues.len() as u64,
+            COMMON_DEVICE_STATUS => self.status as u64,
+            COMMON_CONFIG_GENERATION => self.config_generation as u64,
+            COMMON_QUEUE_SELECT => self.queue_select as u64,
+            COMMON_QUEUE_SIZE => queue.map_or(0, |queue| queue.size as u64),
+            COMMON_QUEUE_MSIX_VECTOR => queue.map_or(VIRTIO_MSI_NO_VECTOR as u64, |queue| queue.msix_vector as u64),
+            COMMON_QUEUE_ENABLE => queue.map_or(0, |queue| queue.ready as u64),
+            COMMON_QUEUE_NOTIFY_OFF => self.queue_select as u64,
+            COMMON_QUEUE_DESC => queue.map_or(0, |queue| queue.desc_addr),
+            COMMON_QUEUE_DRIVER => queue.map_or(0, |queue| queue.driver_addr),
+            COMMON_QUEUE_DEVICE => queue.map_or(0, |queue| queue.device_addr),
+            // High halves of the queue addresses
+            0x24 | 0x2C | 0x34 => return self.common_read(register - 4, 8) >> 32,
+            _ => 0,
+        };
+        match size {
+            1 => value & 0xFF,
+            2 => value & 0xFFFF,
+            4 => value & 0xFFFF_FFFF,
+            _ => value,
+        }
+    }
+
+    fn common_write(&mut self, register: u64, value: u64, size: usize) {
+        let msix_vectors = self.queues.len() as u16 + 1;
+        match register {
+            COMMON_DEVICE_FEATURE_SELECT => self.device_feature_select = value as u32,
+            COMMON_DRIVER_FEATURE_SELECT => self.driver_feature_select = value as u32,
+            COMMON_DRIVER_FEATURE if self.status & VIRTIO_STATUS_FEATURES_OK == 0 => {
+                let value = value & 0xFFFF_FFFF;
+                match self.driver_feature_select {
+                    0 => self.driver_features = (self.driver_features & !0xFFFF_FFFF) | value,
+                    1 => self.driver_features = (self.driver_features & 0xFFFF_FFFF) | value << 32,
+                    _ => {}
+                }
+            }
+            COMMON_MSIX_CONFIG => {
+                self.msix_config = if (value as u16) < msix_vectors { value as u16 } else { VIRTIO_MSI_NO_VECTOR };
+            }
+            COMMON_DEVICE_STATUS => self.write_status(value as u8),
+            COMMON_QUEUE_SELECT => self.queue_select = value as u16,
+            _ => {
+                let driver_ok = self.driver_ok();
+                let queue = match self.queues.get_mut(self.queue_select as usize) {
+                    Some(queue) => queue,
+                    None => return,
+                };
+                // Queue layout is fixed once the queue is enabled
+                let configurable = !queue.ready && !driver_ok;
+                match register {
+                    COMMON_QUEUE_SIZE if configurable => {
+                        let requested = value as u16;
+                        if requested.is_power_of_two() && requested <= queue.max_size {
+                            queue.size = requested;
+                        }
+                    }
+                    COMMON_QUEUE_MSIX_VECTOR => {
+                        queue.msix_vector = if (value as u16) < msix_vectors { value as u16 } else { VIRTIO_MSI_NO_VECTOR };
+                    }
+                    COMMON_QUEUE_ENABLE if value & 1 != 0 => queue.ready = true,
+                    COMMON_QUEUE_DESC if configurable => set_address(&mut queue.desc_addr, 0, value, size),
+                    COMMON_QUEUE_DRIVER if configurable => set_address(&mut queue.driver_addr, 0, value, size),
+                    COMMON_QUEUE_DEVICE if configurable => set_address(&mut queue.device_addr, 0, value, size),
+                    0x24 if configurable => set_address(&mut queue.desc_addr, 32, value, size),
+                    0x2C if configurable => set_address(&mut queue.driver_addr, 32, value, size),
+                    0x34 if configurable => set_address(&mut queue.device_addr, 32, value, size),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    fn write_status(&mut self, status: u8) {
+        if status == 0 {
+            self.reset();
+            return;
+        }
+        let mut status = status;
+        if status & VIRTIO_STATUS_FEATURES_OK != 0 && self.status & VIRTIO_STATUS_FEATURES_OK == 0 {
+            let unsupported = self.driver_features & !self.offered_features();
+            if unsupported != 0 || self.driver_features & VIRTIO_F_VERSION_1 == 0 {
+                warn!("virtio device type {}: rejecting features {:#x}", self.device.device_type(), self.driver_features);
+                status &= !VIRTIO_STATUS_FEATURES_OK;
+            }
+        }
+        let starting = status & VIRTIO_STATUS_DRIVER_OK != 0 && !self.driver_ok();
+        self.status = status | (self.status & VIRTIO_STATUS_NEEDS_RESET);
+        if starting {
+            // Buffers made available before DRIVER_OK were not processed
+            for index in 0..self.queues.len() {
+                self.process_queue(index);
+            }
+        }
+    }
+}
+
+/// Write all or half of a 64-bit queue address
+fn set_address(address: &mut u64, shift: u32, value: u64, size: usize) {
+    if size == 8 && shift == 0 {
+        *address = value;
+    } else {
+        let mask = 0xFFFF_FFFFu64 << shift;
+        *address = (*address & !mask) | ((value & 0xFFFF_FFFF) << shift);
+    }
+}
+
+/// Body of a virtio vendor capability, after its length byte
+fn virtio_cap(kind: u8, offset: u64, length: u64) -> Vec<u8> {
+    let mut body = vec![kind, VIRTIO_BAR as u8, 0, 0, 0];
+    body.extend_from_slice(&(offset as u32).to_le_bytes());
+    body.extend_from_slice(&(length as u32).to_le_bytes());
+    body
+}
+
+// Saved state fields; queue N is saved under STATE_QUEUE + N
+const STATE_FEATURES: u16 = 1;
+const STATE_STATUS: u16 = 2;
+const STATE_SELECTORS: u16 = 3;
+const STATE_DEVICE: u16 = 4;
+const STATE_QUEUE: u16 = 0x100;
+
+impl<D: VirtioDevice + DeviceStateSerialize> DeviceStateSerialize for VirtioPci<D> {
+    fn state_version(&self) -> u16 {
+        1
+    }
+
+    fn save_state(&self, writer: &mut DeviceStateWriter) {
+        writer.put_u64(STATE_FEATURES, self.driver_features);
+        writer.put_bytes(STATE_STATUS, &[self.status, self.isr, self.config_generation]);
+        writer.put_u64s(STATE_SELECTORS, &[
+            self.device_feature_select as u64, self.driver_feature_select as u64,
+            self.queue_select as u64, self.msix_config as u64,
+        ]);
+        writer.put_nested(STATE_DEVICE, &self.device);
+        for (index, queue) in self.queues.iter().enumerate() {
+            writer.put_u64s(STATE_QUEUE + index as u16, &[
+                queue.size as u64, queue.ready as u64, queue.msix_vector as u64,
+                queue.desc_addr, queue.driver_addr, queue.device_addr,
+                queue.last_avail as u64, queue.used_index as u64,
+            ]);
+        }
+    }
+
+    fn restore_state(&mut self, reader: &DeviceStateReader) -> Result<(), HypervisorError> {
+        if let Some(features) = reader.u64(STATE_FEATURES) {
+            self.driver_features = features;
+        }
+        if let Some(status) = reader.bytes(STATE_STATUS).filter(|status| status.len() == 3) {
+            self.status = status[0];
+            self.isr = status[1];
+            self.config_generation = status[2];
+        }
+        if let Some(selectors) = reader.u64s(STATE_SELECTORS).filter(|selectors| selectors.len() == 4) {
+            self.device_feature_select = selectors[0] as u32;
+            self.driver_feature_select = selectors[1] as u32;
+            self.queue_select = selectors[2] as u16;
+            self.msix_config = selectors[3] as u16;
+        }
+        reader.restore_nested(STATE_DEVICE, &mut self.device)?;
+        for (index, queue) in self.queues.iter_mut().enumerate() {
+            let saved = match reader.u64s(STATE_QUEUE + index as u16).filter(|saved| saved.len() == 8) {
+                Some(saved) => saved,
+                None => continue,
+            };
+            if saved[0] as u16 > queue.max_size {
+                return Err(ConfigError::new("virtqueue.size", ConfigErrorReason::Mismatch).value(saved[0]).into());
+            }
+            queue.size = saved[0] as u16;
+            queue.ready = saved[1] != 0;
+            queue.msix_vector = saved[2] as u16;
+            queue.desc_addr = saved[3];
+            queue.driver_addr = saved[4];
+            queue.device_addr = saved[5];
+            queue.last_avail = saved[6] as u16;
+            queue.used_index = saved[7] as u16;
+        }
+        Ok(())
+    }
+}
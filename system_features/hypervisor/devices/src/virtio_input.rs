@@ -0,0 +1,410 @@
+//! Virtio Input Devices
+//!
+//! A keyboard and an absolute pointer (tablet) that report Linux evdev
+//! events through virtio-input. The host injects input with `press_key`
+//! and `move_pointer`; events wait in the device until the driver posts
+//! buffers for them. An absolute pointer is used rather than a relative
+//! mouse so host coordinates map onto the guest screen without acceleration
+//! getting in the way.
+
+use crate::HypervisorError;
+use super::{
+    DeviceCapability, DeviceConfig, DeviceState, DeviceStateReader, DeviceStateSerialize,
+    DeviceStateWriter, DeviceStats, DeviceType, GuestMemoryAccess, VirtQueue, VirtioDevice, VirtualDevice,
+};
+
+use alloc::collections::VecDeque;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+pub const VIRTIO_ID_INPUT: u16 = 18;
+/// PCI class: other input controller
+const VIRTIO_INPUT_PCI_CLASS: u32 = 0x09_80_00;
+const EVENT_QUEUE: usize = 0;
+const STATUS_QUEUE: usize = 1;
+const QUEUE_SIZE: u16 = 64;
+/// Events kept while the driver has no buffers posted
+const MAX_PENDING_EVENTS: usize = 256;
+/// Absolute axes span 0 to this value
+pub const VIRTIO_INPUT_ABS_MAX: u32 = 0x7FFF;
+
+// Config selectors
+const CFG_UNSET: u8 = 0x00;
+const CFG_ID_NAME: u8 = 0x01;
+const CFG_ID_SERIAL: u8 = 0x02;
+const CFG_ID_DEVIDS: u8 = 0x03;
+const CFG_PROP_BITS: u8 = 0x10;
+const CFG_EV_BITS: u8 = 0x11;
+const CFG_ABS_INFO: u8 = 0x12;
+/// Config layout: select, subsel, size, 5 reserved bytes, then data
+const CFG_DATA_OFFSET: usize = 8;
+const CFG_DATA_SIZE: usize = 128;
+
+// evdev event types and codes
+const EV_SYN: u16 = 0x00;
+const EV_KEY: u16 = 0x01;
+const EV_REL: u16 = 0x02;
+const EV_ABS: u16 = 0x03;
+const EV_LED: u16 = 0x11;
+const SYN_REPORT: u16 = 0;
+const ABS_X: u16 = 0x00;
+const ABS_Y: u16 = 0x01;
+const REL_WHEEL: u16 = 0x08;
+const BTN_LEFT: u16 = 0x110;
+const BTN_RIGHT: u16 = 0x111;
+const BTN_MIDDLE: u16 = 0x112;
+/// Highest key code the keyboard reports
+const KEY_MAX_REPORTED: u16 = 127;
+const BUS_VIRTUAL: u16 = 0x06;
+const INPUT_VENDOR: u16 = 0x0627;
+
+/// Pointer button bits, as in the RFB protocol
+pub const POINTER_BUTTON_LEFT: u8 = 1 << 0;
+pub const POINTER_BUTTON_MIDDLE: u8 = 1 << 1;
+pub const POINTER_BUTTON_RIGHT: u8 = 1 << 2;
+pub const POINTER_WHEEL_UP: u8 = 1 << 3;
+pub const POINTER_WHEEL_DOWN: u8 = 1 << 4;
+
+/// What a virtio-input device reports
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VirtioInputKind {
+    Keyboard,
+    /// Absolute pointer with three buttons and a wheel
+    Tablet,
+}
+
+/// One evdev event as the driver receives it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputEvent {
+    pub event_type: u16,
+    pub code: u16,
+    pub value: u32,
+}
+
+impl InputEvent {
+    fn encode(&self) -> [u8; 8] {
+        let mut bytes = [0u8; 8];
+        bytes[0..2].copy_from_slice(&self.event_type.to_le_bytes());
+        bytes[2..4].copy_from_slice(&self.code.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.value.to_le_bytes());
+        bytes
+    }
+}
+
+/// A virtio-input keyboard or tablet
+#[derive(Debug, Clone)]
+pub struct VirtioInput {
+    kind: VirtioInputKind,
+    select: u8,
+    subsel: u8,
+    pending: VecDeque<InputEvent>,
+    buttons: u8,
+    /// LED state the driver last set, bit 0 num lock to bit 2 scroll lock
+    leds: u8,
+    dropped: u64,
+}
+
+impl VirtioInput {
+    pub fn new(kind: VirtioInputKind) -> Self {
+        VirtioInput {
+            kind,
+            select: CFG_UNSET,
+            subsel: 0,
+            pending: VecDeque::new(),
+            buttons: 0,
+            leds: 0,
+            dropped: 0,
+        }
+    }
+
+    pub fn kind(&self) -> VirtioInputKind {
+        self.kind
+    }
+
+    pub fn leds(&self) -> u8 {
+        self.leds
+    }
+
+    /// Events lost because the driver did not take them in time
+    pub fn dropped_events(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Queue a key press or release by evdev key code
+    pub fn press_key(&mut self, keycode: u16, pressed: bool) -> Result<(), HypervisorError> {
+        if self.kind != VirtioInputKind::Keyboard || keycode == 0 || keycode > KEY_MAX_REPORTED {
+            return Err(HypervisorError::InvalidParameter);
+        }
+        self.queue_events(&[
+            InputEvent { event_type: EV_KEY, code: keycode, value: pressed as u32 },
+            InputEvent { event_type: EV_SYN, code: SYN_REPORT, value: 0 },
+        ]);
+        Ok(())
+    }
+
+    /// Queue a pointer position, with axes scaled to `VIRTIO_INPUT_ABS_MAX`,
+    /// and button state; button changes and wheel bits become events
+    pub fn move_pointer(&mut self, x: u32, y: u32, buttons: u8) -> Result<(), HypervisorError> {
+        if self.kind != VirtioInputKind::Tablet {
+            return Err(HypervisorError::InvalidParameter);
+        }
+        let mut events = vec![
+            InputEvent { event_type: EV_ABS, code: ABS_X, value: x.min(VIRTIO_INPUT_ABS_MAX) },
+            InputEvent { event_type: EV_ABS, code: ABS_Y, value: y.min(VIRTIO_INPUT_ABS_MAX) },
+        ];
+        for &(bit, code) in &[(POINTER_BUTTON_LEFT, BTN_LEFT), (POINTER_BUTTON_MIDDLE, BTN_MIDDLE), (POINTER_BUTTON_RIGHT, BTN_RIGHT)] {
+            if (buttons ^ self.buttons) & bit != 0 {
+                events.push(InputEvent { event_type: EV_KEY, code, value: (buttons & bit != 0) as u32 });
+            }
+        }
+        // Wheel bits are a click each time they are set, not a state
+        if buttons & POINTER_WHEEL_UP != 0 && self.buttons & POINTER_WHEEL_UP == 0 {
+            events.push(InputEvent { event_type: EV_REL, code: REL_WHEEL, value: 1 });
+        }
+        if buttons & POINTER_WHEEL_DOWN != 0 && self.buttons & POINTER_WHEEL_DOWN == 0 {
+            events.push(InputEvent { event_type: EV_REL, code: REL_WHEEL, value: (-1i32) as u32 });
+        }
+        events.push(InputEvent { event_type: EV_SYN, code: SYN_REPORT, value: 0 });
+        self.buttons = buttons;
+        self.queue_events(&events);
+        Ok(())
+    }
+
+    /// Device description for the framework's device list
+    pub fn build_virtual_device(&self) -> VirtualDevice {
+        let (device_type, name) = match self.kind {
+            VirtioInputKind::Keyboard => (DeviceType::KeyboardController, "Virtio Keyboard"),
+            VirtioInputKind::Tablet => (DeviceType::MouseController, "Virtio Tablet"),
+        };
+        let mut custom_config = BTreeMap::new();
+        custom_config.insert(String::from("virtio_type"), format!("{}", VIRTIO_ID_INPUT));
+
+        VirtualDevice {
+            device_type,
+            device_id: String::new(),
+            name: String::from(name),
+            state: DeviceState::Uninitialized,
+            config: DeviceConfig {
+                enabled: true,
+                address: 0,
+                interrupt_line: None,
+                dma_channels: Vec::new(),
+                custom_config,
+            },
+            mmio_regions: Vec::new(),
+            io_ports: Vec::new(),
+            interrupt: None,
+            registers: Vec::new(),
+            capabilities: vec![
+                DeviceCapability {
+                    name: String::from("virtio_input"),
+                    description: String::from("Paravirtual input device"),
+                    value: String::from(name),
+                },
+            ],
+            stats: DeviceStats {
+                read_count: 0,
+                write_count: 0,
+                interrupt_count: 0,
+                error_count: 0,
+                last_access_time: 0,
+            },
+        }
+    }
+
+    /// Queue a group of events, dropping whole old groups on overflow so
+    /// the driver never sees half a report
+    fn queue_events(&mut self, events: &[InputEvent]) {
+        while self.pending.len() + events.len() > MAX_PENDING_EVENTS {
+            let report_end = self.pending.iter().position(|event| event.event_type == EV_SYN);
+            let count = report_end.map_or(self.pending.len(), |end| end + 1);
+            self.pending.drain(..count);
+            if self.dropped == 0 {
+                warn!("virtio-input: driver is not taking events, dropping the oldest");
+            }
+            self.dropped += count as u64;
+        }
+        self.pending.extend(events.iter().copied());
+    }
+
+    /// Data the current config selection reads
+    fn config_data(&self) -> Vec<u8> {
+        match (self.select, self.kind) {
+            (CFG_ID_NAME, VirtioInputKind::Keyboard) => b"MultiOS Virtio Keyboard".to_vec(),
+            (CFG_ID_NAME, VirtioInputKind::Tablet) => b"MultiOS Virtio Tablet".to_vec(),
+            (CFG_ID_SERIAL, _) => b"0".to_vec(),
+            (CFG_ID_DEVIDS, kind) => {
+                let product: u16 = if kind == VirtioInputKind::Keyboard { 1 } else { 3 };
+                [BUS_VIRTUAL, INPUT_VENDOR, product, 1].iter().flat_map(|value| value.to_le_bytes()).collect()
+            }
+            (CFG_PROP_BITS, _) => Vec::new(),
+            (CFG_EV_BITS, kind) => self.event_bits(kind, self.subsel as u16),
+            (CFG_ABS_INFO, VirtioInputKind::Tablet) if matches!(self.subsel as u16, ABS_X | ABS_Y) => {
+                // Minimum, maximum, fuzz, flat and resolution
+                [0, VIRTIO_INPUT_ABS_MAX, 0, 0, 0].iter().flat_map(|value: &u32| value.to_le_bytes()).collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Bitmap of the codes reported for an event type
+    fn event_bits(&self, kind: VirtioInputKind, event_type: u16) -> Vec<u8> {
+        let codes: Vec<u16> = match (kind, event_type) {
+            (VirtioInputKind::Keyboard, EV_KEY) => (1..=KEY_MAX_REPORTED).collect(),
+            (VirtioInputKind::Keyboard, EV_LED) => vec![0, 1, 2],
+            (VirtioInputKind::Tablet, EV_KEY) => vec![BTN_LEFT, BTN_RIGHT, BTN_MIDDLE],
+            (VirtioInputKind::Tablet, EV_REL) => vec![REL_WHEEL],
+            (VirtioInputKind::Tablet, EV_ABS) => vec![ABS_X, ABS_Y],
+            _ => Vec::new(),
+        };
+        let mut bitmap = vec![0u8; codes.iter().max().map_or(0, |&max| max as usize / 8 + 1)];
+        for code in codes {
+            bitmap[code as usize / 8] |= 1 << (code % 8);
+        }
+        bitmap
+    }
+}
+
+impl VirtioDevice for VirtioInput {
+    fn device_type(&self) -> u16 {
+        VIRTIO_ID_INPUT
+    }
+
+    fn pci_class(&self) -> u32 {
+        VIRTIO_INPUT_PCI_CLASS
+    }
+
+    fn device_features(&self) -> u64 {
+        0
+    }
+
+    fn queue_max_sizes(&self) -> &[u16] {
+        &[QUEUE_SIZE, QUEUE_SIZE]
+    }
+
+    fn read_config(&self, offset: usize, data: &mut [u8]) {
+        let payload = self.config_data();
+        for (index, byte) in data.iter_mut().enumerate() {
+            *byte = match offset + index {
+                0 => self.select,
+                1 => self.subsel,
+                2 => payload.len().min(CFG_DATA_SIZE) as u8,
+                position if position >= CFG_DATA_OFFSET => payload.get(position - CFG_DATA_OFFSET).copied().unwrap_or(0),
+                _ => 0,
+            };
+        }
+    }
+
+    fn write_config(&mut self, offset: usize, data: &[u8]) {
+        for (index, &byte) in data.iter().enumerate() {
+            match offset + index {
+                0 => self.select = byte,
+                1 => self.subsel = byte,
+                _ => {}
+            }
+        }
+    }
+
+    fn process_queue(&mut self, index: usize, queue: &mut VirtQueue, memory: &mut dyn GuestMemoryAccess)
+        -> Result<bool, HypervisorError> {
+        let mut used = false;
+        match index {
+            EVENT_QUEUE => {
+                while let Some(event) = self.pending.front().copied() {
+                    let chain = match queue.pop(memory)? {
+                        Some(chain) => chain,
+                        None => break,
+                    };
+                    let written = chain.write(memory, &event.encode())?;
+                    queue.push_used(memory, chain.head, written)?;
+                    self.pending.pop_front();
+                    used = true;
+                }
+            }
+            STATUS_QUEUE => {
+                while let Some(chain) = queue.pop(memory)? {
+                    let data = chain.read_all(memory)?;
+                    if data.len() >= 8 && u16::from_le_bytes([data[0], data[1]]) == EV_LED {
+                        let led = u16::from_le_bytes([data[2], data[3]]);
+                        let on = u32::from_le_bytes([data[4], data[5], data[6], data[7]]) != 0;
+                        if led < 8 {
+                            self.leds = if on { self.leds | 1 << led } else { self.leds & !(1 << led) };
+                        }
+                    }
+                    queue.push_used(memory, chain.head, 0)?;
+                    used = true;
+                }
+            }
+            _ => {}
+        }
+        Ok(used)
+    }
+
+    fn reset(&mut self) {
+        self.select = CFG_UNSET;
+        self.subsel = 0;
+        self.pending.clear();
+        self.buttons = 0;
+        self.leds = 0;
+    }
+}
+
+/// Pending events are host input not yet seen by the guest; they are not
+/// replayed after a restore
+impl DeviceStateSerialize for VirtioInput {
+    fn state_version(&self) -> u16 {
+        1
+    }
+
+    fn save_state(&self, writer: &mut DeviceStateWriter) {
+        writer.put_bytes(1, &[self.select, self.subsel, self.buttons, self.leds]);
+    }
+
+    fn restore_state(&mut self, reader: &DeviceStateReader) -> Result<(), HypervisorError> {
+        if let Some(state) = reader.bytes(1).filter(|state| state.len() == 4) {
+            self.select = state[0];
+            self.subsel = state[1];
+            self.buttons = state[2];
+            self.leds = state[3];
+        }
+        self.pending.clear();
+        Ok(())
+    }
+}
+
+/// Translate a PC set 1 scancode to an evdev key code and whether it is a
+/// press. Extended scancodes carry the 0xE0 prefix in the high byte; bit 7
+/// of the low byte marks a release.
+pub fn scancode_to_keycode(scancode: u16) -> Option<(u16, bool)> {
+    let code = (scancode & 0x7F) as u8;
+    let pressed = scancode & 0x80 == 0;
+    let keycode = match scancode >> 8 {
+        // The main block's scancodes are its key codes
+        0x00 if (0x01..=0x58).contains(&code) => code as u16,
+        0xE0 => match code {
+            0x1C => 96,  // keypad enter
+            0x1D => 97,  // right ctrl
+            0x35 => 98,  // keypad slash
+            0x38 => 100, // right alt
+            0x47 => 102, // home
+            0x48 => 103, // up
+            0x49 => 104, // page up
+            0x4B => 105, // left
+            0x4D => 106, // right
+            0x4F => 107, // end
+            0x50 => 108, // down
+            0x51 => 109, // page down
+            0x52 => 110, // insert
+            0x53 => 111, // delete
+            0x5B => 125, // left meta
+            0x5C => 126, // right meta
+            0x5D => 127, // menu
+            _ => return None,
+        },
+        _ => return None,
+    };
+    Some((keycode, pressed))
+}
@@ -0,0 +1,482 @@
+//! Automated Grading for Instructors
+//!
+//! Each tutorial can carry a rubric of graded checkpoints. A student's
+//! submission is graded against the rubric using evidence collected from the
+//! hypervisor (VM statistics snapshots) together with console excerpts and
+//! lab events reported by the lab environment, e.g. a restored snapshot.
+//! Graded reports can be exported per student as JSON or CSV.
+
+use crate::{VmId, HypervisorError, ConfigError, ConfigErrorReason};
+use crate::core::{Hypervisor, VmState};
+use super::{EducationalExample, LearnerProgress, StepStatus};
+
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Condition a checkpoint checks against the collected evidence
+#[derive(Debug, Clone, PartialEq)]
+pub enum CheckpointCriterion {
+    /// The N-th VM of the submission (1-based) is in the given state
+    VmState { vm: usize, state: VmState },
+    /// The N-th VM of the submission runs with exactly this many vCPUs
+    VcpuCount { vm: usize, count: usize },
+    /// The N-th VM of the submission has at least this much memory
+    MemoryAtLeast { vm: usize, memory_mb: u64 },
+    /// A tutorial step was passed in the learner's progress
+    StepPassed(usize),
+    /// The tutorial is marked as completed in the learner's progress
+    TutorialCompleted,
+    /// Some console excerpt contains the text
+    ConsoleContains(String),
+    /// The lab reported the named event, e.g. `snapshot_restored`
+    EventRecorded(String),
+}
+
+/// A graded checkpoint of a tutorial rubric
+#[derive(Debug, Clone)]
+pub struct GradedCheckpoint {
+    pub id: String,
+    pub description: String,
+    pub points: u32,
+    pub criterion: CheckpointCriterion,
+}
+
+impl GradedCheckpoint {
+    pub fn new(id: &str, description: &str, points: u32, criterion: CheckpointCriterion) -> Self {
+        GradedCheckpoint {
+            id: id.to_string(),
+            description: description.to_string(),
+            points,
+            criterion,
+        }
+    }
+}
+
+/// Statistics of one VM captured at grading time
+#[derive(Debug, Clone)]
+pub struct VmEvidence {
+    /// Position of the VM in the submission (1-based)
+    pub vm: usize,
+    pub vm_id: VmId,
+    pub state: VmState,
+    pub vcpu_count: usize,
+    pub memory_mb: u64,
+    pub uptime_ms: u64,
+    pub vm_exits: u64,
+    pub page_faults: u64,
+}
+
+/// Console output captured from a VM
+#[derive(Debug, Clone)]
+pub struct ConsoleExcerpt {
+    pub vm: usize,
+    pub text: String,
+}
+
+/// Evidence a submission is graded on
+#[derive(Debug, Clone, Default)]
+pub struct Evidence {
+    pub vms: Vec<VmEvidence>,
+    pub console: Vec<ConsoleExcerpt>,
+    pub events: Vec<String>,
+}
+
+impl Evidence {
+    /// Capture statistics of the submission's VMs, in submission order
+    pub fn collect(hypervisor: &Hypervisor, vm_ids: &[VmId]) -> Result<Self, HypervisorError> {
+        let mut evidence = Evidence::default();
+        for (index, &vm_id) in vm_ids.iter().enumerate() {
+            let info = hypervisor.get_vm_info(vm_id)?;
+            let stats = hypervisor.get_vm_stats(vm_id)?;
+            evidence.vms.push(VmEvidence {
+                vm: index + 1,
+                vm_id,
+                state: info.state,
+                vcpu_count: info.vcpu_count,
+                memory_mb: info.memory_mb,
+                uptime_ms: info.uptime_ms,
+                vm_exits: stats.vcpu_stats.iter().map(|cpu| cpu.vm_exit_count).sum(),
+                page_faults: stats.memory_stats.page_faults,
+            });
+        }
+        Ok(evidence)
+    }
+
+    /// Add console output captured from the N-th VM
+    pub fn add_console_excerpt(&mut self, vm: usize, text: &str) {
+        self.console.push(ConsoleExcerpt { vm, text: text.to_string() });
+    }
+
+    /// Record a lab event such as `snapshot_restored`
+    pub fn record_event(&mut self, event: &str) {
+        self.events.push(event.to_string());
+    }
+
+    fn vm(&self, vm: usize) -> Option<&VmEvidence> {
+        self.vms.iter().find(|evidence| evidence.vm == vm)
+    }
+}
+
+/// Grading result of one checkpoint
+#[derive(Debug, Clone)]
+pub struct CheckpointResult {
+    pub checkpoint_id: String,
+    pub description: String,
+    pub passed: bool,
+    pub awarded: u32,
+    pub max_points: u32,
+    /// What the grader observed, for the instructor
+    pub observed: String,
+}
+
+/// Graded report of one student for one tutorial
+#[derive(Debug, Clone)]
+pub struct StudentReport {
+    pub student_id: String,
+    pub tutorial: EducationalExample,
+    pub results: Vec<CheckpointResult>,
+    pub score: u32,
+    pub max_score: u32,
+}
+
+impl StudentReport {
+    /// Score as a percentage of the maximum
+    pub fn percentage(&self) -> f32 {
+        if self.max_score > 0 {
+            (self.score as f32 / self.max_score as f32) * 100.0
+        } else {
+            0.0
+        }
+    }
+
+    /// Export as a JSON object
+    pub fn to_json(&self) -> String {
+        let checkpoints: Vec<String> = self
+            .results
+            .iter()
+            .map(|result| {
+                format!(
+                    "{{\"id\":{},\"description\":{},\"passed\":{},\"awarded\":{},\"max_points\":{},\"observed\":{}}}",
+                    json_string(&result.checkpoint_id),
+                    json_string(&result.description),
+                    result.passed,
+                    result.awarded,
+                    result.max_points,
+                    json_string(&result.observed)
+                )
+            })
+            .collect();
+
+        format!(
+            "{{\"student\":{},\"tutorial\":{},\"score\":{},\"max_score\":{},\"percentage\":{:.1},\"checkpoints\":[{}]}}",
+            json_string(&self.student_id),
+            json_string(self.tutorial.name()),
+            self.score,
+            self.max_score,
+            self.percentage(),
+            checkpoints.join(",")
+        )
+    }
+
+    /// Header line matching [`StudentReport::to_csv_rows`]
+    pub fn csv_header() -> &'static str {
+        "student,tutorial,checkpoint,passed,awarded,max_points,observed"
+    }
+
+    /// Export as CSV rows, one per checkpoint
+    pub fn to_csv_rows(&self) -> String {
+        let mut rows = String::new();
+        for result in &self.results {
+            rows.push_str(&format!(
+                "{},{},{},{},{},{},{}\n",
+                csv_field(&self.student_id),
+                csv_field(self.tutorial.name()),
+                csv_field(&result.checkpoint_id),
+                result.passed,
+                result.awarded,
+                result.max_points,
+                csv_field(&result.observed)
+            ));
+        }
+        rows
+    }
+}
+
+/// Submission of one student in a lab
+#[derive(Debug, Clone)]
+pub struct LabSubmission {
+    pub student_id: String,
+    /// The student's VMs, in the order of the tutorial's VM configurations
+    pub vm_ids: Vec<VmId>,
+    pub console: Vec<ConsoleExcerpt>,
+    pub events: Vec<String>,
+    pub progress: Option<LearnerProgress>,
+}
+
+impl LabSubmission {
+    pub fn new(student_id: &str, vm_ids: Vec<VmId>) -> Self {
+        LabSubmission {
+            student_id: student_id.to_string(),
+            vm_ids,
+            console: Vec::new(),
+            events: Vec::new(),
+            progress: None,
+        }
+    }
+}
+
+/// Rubrics per tutorial and the reports graded so far
+pub struct Assessment {
+    rubrics: BTreeMap<EducationalExample, Vec<GradedCheckpoint>>,
+    reports: Vec<StudentReport>,
+}
+
+impl Assessment {
+    /// Create an assessment without rubrics
+    pub fn new() -> Self {
+        Assessment {
+            rubrics: BTreeMap::new(),
+            reports: Vec::new(),
+        }
+    }
+
+    /// Add the rubrics for the standard tutorials
+    pub fn with_standard_rubrics(mut self) -> Self {
+        self.set_rubric(EducationalExample::SimpleBoot, vec![
+            GradedCheckpoint::new("vm_created", "VM created", 20, CheckpointCriterion::StepPassed(1)),
+            GradedCheckpoint::new(
+                "vm_booted",
+                "VM booted and running",
+                40,
+                CheckpointCriterion::VmState { vm: 1, state: VmState::Running },
+            ),
+            GradedCheckpoint::new(
+                "boot_console",
+                "Kernel output seen on the serial console",
+                40,
+                CheckpointCriterion::ConsoleContains(String::from("Booting")),
+            ),
+        ]);
+        self.set_rubric(EducationalExample::TeachingLab, vec![
+            GradedCheckpoint::new(
+                "vm_booted",
+                "VM booted with 1 vCPU",
+                30,
+                CheckpointCriterion::VcpuCount { vm: 1, count: 1 },
+            ),
+            GradedCheckpoint::new(
+                "vm_running",
+                "Student VM running",
+                20,
+                CheckpointCriterion::VmState { vm: 1, state: VmState::Running },
+            ),
+            GradedCheckpoint::new(
+                "memory",
+                "Student VM has 1024 MB of memory",
+                10,
+                CheckpointCriterion::MemoryAtLeast { vm: 1, memory_mb: 1024 },
+            ),
+            GradedCheckpoint::new(
+                "snapshot_restored",
+                "Snapshot restored",
+                40,
+                CheckpointCriterion::EventRecorded(String::from("snapshot_restored")),
+            ),
+        ]);
+        self
+    }
+
+    /// Replace the rubric of a tutorial
+    pub fn set_rubric(&mut self, tutorial: EducationalExample, checkpoints: Vec<GradedCheckpoint>) {
+        self.rubrics.insert(tutorial, checkpoints);
+    }
+
+    /// Add a checkpoint to the rubric of a tutorial
+    pub fn add_checkpoint(&mut self, tutorial: EducationalExample, checkpoint: GradedCheckpoint) {
+        self.rubrics.entry(tutorial).or_default().push(checkpoint);
+    }
+
+    /// Rubric of a tutorial
+    pub fn rubric(&self, tutorial: EducationalExample) -> Option<&[GradedCheckpoint]> {
+        self.rubrics.get(&tutorial).map(|checkpoints| checkpoints.as_slice())
+    }
+
+    /// Grade one student's evidence against a tutorial's rubric.
+    ///
+    /// Checkpoints are all-or-nothing. The report is kept for export.
+    pub fn grade(
+        &mut self,
+        student_id: &str,
+        tutorial: EducationalExample,
+        evidence: &Evidence,
+        progress: Option<&LearnerProgress>,
+    ) -> Result<&StudentReport, HypervisorError> {
+        let rubric = self.rubrics.get(&tutorial)
+            .ok_or_else(|| HypervisorError::from(ConfigError::new("rubric", ConfigErrorReason::NotFound)))?;
+
+        let results: Vec<CheckpointResult> = rubric
+            .iter()
+            .map(|checkpoint| {
+                let (passed, observed) = evaluate(&checkpoint.criterion, tutorial, evidence, progress);
+                CheckpointResult {
+                    checkpoint_id: checkpoint.id.clone(),
+                    description: checkpoint.description.clone(),
+                    passed,
+                    awarded: if passed { checkpoint.points } else { 0 },
+                    max_points: checkpoint.points,
+                    observed,
+                }
+            })
+            .collect();
+
+        let report = StudentReport {
+            student_id: student_id.to_string(),
+            tutorial,
+            score: results.iter().map(|result| result.awarded).sum(),
+            max_score: results.iter().map(|result| result.max_points).sum(),
+            results,
+        };
+        info!("Graded {} on {}: {}/{}", report.student_id, tutorial.name(), report.score, report.max_score);
+
+        // A regrade replaces the earlier report
+        self.reports.retain(|existing| !(existing.student_id == student_id && existing.tutorial == tutorial));
+        self.reports.push(report);
+        Ok(self.reports.last().unwrap())
+    }
+
+    /// Collect evidence for every lab submission and grade it against the
+    /// Teaching Lab rubric
+    pub fn grade_teaching_lab(
+        &mut self,
+        hypervisor: &Hypervisor,
+        submissions: &[LabSubmission],
+    ) -> Result<Vec<StudentReport>, HypervisorError> {
+        let mut reports = Vec::with_capacity(submissions.len());
+        for submission in submissions {
+            let mut evidence = Evidence::collect(hypervisor, &submission.vm_ids)?;
+            evidence.console.extend(submission.console.iter().cloned());
+            evidence.events.extend(submission.events.iter().cloned());
+            let report = self.grade(
+                &submission.student_id,
+                EducationalExample::TeachingLab,
+                &evidence,
+                submission.progress.as_ref(),
+            )?;
+            reports.push(report.clone());
+        }
+        Ok(reports)
+    }
+
+    /// Graded reports
+    pub fn reports(&self) -> &[StudentReport] {
+        &self.reports
+    }
+
+    /// Reports of one student
+    pub fn student_reports(&self, student_id: &str) -> Vec<&StudentReport> {
+        self.reports.iter().filter(|report| report.student_id == student_id).collect()
+    }
+
+    /// Export all reports as a JSON array
+    pub fn export_json(&self) -> String {
+        let reports: Vec<String> = self.reports.iter().map(|report| report.to_json()).collect();
+        format!("[{}]", reports.join(","))
+    }
+
+    /// Export all reports as CSV with a header line
+    pub fn export_csv(&self) -> String {
+        let mut csv = String::from(StudentReport::csv_header());
+        csv.push('\n');
+        for report in &self.reports {
+            csv.push_str(&report.to_csv_rows());
+        }
+        csv
+    }
+}
+
+/// Check a criterion, returning whether it holds and what was observed
+fn evaluate(
+    criterion: &CheckpointCriterion,
+    tutorial: EducationalExample,
+    evidence: &Evidence,
+    progress: Option<&LearnerProgress>,
+) -> (bool, String) {
+    let missing_vm = |vm: usize| (false, format!("VM {} not submitted", vm));
+
+    match criterion {
+        CheckpointCriterion::VmState { vm, state } => match evidence.vm(*vm) {
+            Some(found) => (found.state == *state, format!("state={:?}", found.state)),
+            None => missing_vm(*vm),
+        },
+        CheckpointCriterion::VcpuCount { vm, count } => match evidence.vm(*vm) {
+            // Counting vCPUs only makes sense for a VM that actually booted
+            Some(found) => (
+                found.vcpu_count == *count && found.state == VmState::Running,
+                format!("vcpus={} state={:?}", found.vcpu_count, found.state),
+            ),
+            None => missing_vm(*vm),
+        },
+        CheckpointCriterion::MemoryAtLeast { vm, memory_mb } => match evidence.vm(*vm) {
+            Some(found) => (found.memory_mb >= *memory_mb, format!("memory={}MB", found.memory_mb)),
+            None => missing_vm(*vm),
+        },
+        CheckpointCriterion::StepPassed(step_number) => match progress {
+            Some(progress) => {
+                let status = progress.step_status(tutorial, *step_number);
+                (status == StepStatus::Passed, format!("step {} {:?}", step_number, status))
+            }
+            None => (false, String::from("no learner progress")),
+        },
+        CheckpointCriterion::TutorialCompleted => match progress {
+            Some(progress) => {
+                let completed = progress.completed.contains(&tutorial);
+                (completed, format!("completed={}", completed))
+            }
+            None => (false, String::from("no learner progress")),
+        },
+        CheckpointCriterion::ConsoleContains(text) => {
+            match evidence.console.iter().find(|excerpt| excerpt.text.contains(text.as_str())) {
+                Some(excerpt) => (true, format!("VM {}: {}", excerpt.vm, excerpt_line(&excerpt.text, text))),
+                None => (false, format!("'{}' not in console output", text)),
+            }
+        }
+        CheckpointCriterion::EventRecorded(event) => {
+            let recorded = evidence.events.iter().any(|recorded| recorded == event);
+            (recorded, format!("{} {}", event, if recorded { "recorded" } else { "missing" }))
+        }
+    }
+}
+
+/// The console line containing the matched text
+fn excerpt_line<'a>(text: &'a str, needle: &str) -> &'a str {
+    text.lines().find(|line| line.contains(needle)).unwrap_or(text).trim()
+}
+
+fn json_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains(|c| matches!(c, ',' | '"' | '\n' | '\r')) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
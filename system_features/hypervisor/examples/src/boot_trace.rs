@@ -0,0 +1,492 @@
+//! Guided x86 Boot Sequence Tracing
+//!
+//! In trace boot mode the SimpleBoot guest's boot code is stepped one
+//! instruction, or one basic block, at a time through the real mode →
+//! protected mode → long mode transitions. Architectural state is tracked the
+//! way the processor tracks it (CR0, CR3, CR4, EFER, GDTR, CS), and a
+//! checkpoint is emitted the first time each milestone is reached so tutorial
+//! steps can assert on them. Steps the processor would reject, such as
+//! enabling long-mode paging without PAE, stop the trace with a fault.
+
+use crate::HypervisorError;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+const CR0_PE: u64 = 1 << 0;
+const CR0_PG: u64 = 1 << 31;
+const CR4_PAE: u64 = 1 << 5;
+const MSR_EFER: u32 = 0xC000_0080;
+const EFER_LME: u64 = 1 << 8;
+const EFER_LMA: u64 = 1 << 10;
+/// Value of CR0 after reset
+const CR0_RESET: u64 = 0x6000_0010;
+/// System control port A; bit 1 gates A20
+const PORT_FAST_A20: u16 = 0x92;
+const PORT_KBC_COMMAND: u16 = 0x64;
+const PORT_KBC_DATA: u16 = 0x60;
+/// Keyboard controller command: write output port
+const KBC_WRITE_OUTPUT: u8 = 0xD1;
+/// Code segment descriptor bits
+const DESC_PRESENT: u64 = 1 << 47;
+const DESC_CODE: u64 = 1 << 43;
+const DESC_LONG: u64 = 1 << 53;
+const DESC_DEFAULT_32: u64 = 1 << 54;
+
+/// Instructions of the traced boot code
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BootInstruction {
+    Cli,
+    OutAl { port: u16, value: u8 },
+    Lgdt { base: u64, limit: u16 },
+    Lidt { base: u64, limit: u16 },
+    /// `mov eax, cr0; or eax, bits; mov cr0, eax`
+    SetCr0Bits(u64),
+    /// `mov eax, cr4; or eax, bits; mov cr4, eax`
+    SetCr4Bits(u64),
+    MovCr3(u64),
+    /// `mov ecx, msr; rdmsr; or eax, bits; wrmsr`
+    SetMsrBits { msr: u32, bits: u64 },
+    /// Far jump to the following instruction, reloading CS
+    FarJump { selector: u16 },
+    /// Load the data segment registers
+    LoadDataSegments { selector: u16 },
+    Hlt,
+}
+
+impl BootInstruction {
+    /// Encoded length in bytes, for RIP tracking
+    pub fn length(&self) -> u64 {
+        match self {
+            BootInstruction::Cli | BootInstruction::Hlt => 1,
+            BootInstruction::OutAl { .. } => 4,
+            BootInstruction::Lgdt { .. } | BootInstruction::Lidt { .. } => 5,
+            BootInstruction::SetCr0Bits(_) | BootInstruction::SetCr4Bits(_) => 11,
+            BootInstruction::MovCr3(_) => 8,
+            BootInstruction::SetMsrBits { .. } => 12,
+            BootInstruction::FarJump { .. } => 7,
+            BootInstruction::LoadDataSegments { .. } => 12,
+        }
+    }
+
+    /// Whether the instruction ends a basic block
+    pub fn ends_block(&self) -> bool {
+        matches!(self, BootInstruction::FarJump { .. } | BootInstruction::Hlt)
+    }
+}
+
+/// Boot code laid out from its load address
+#[derive(Debug, Clone)]
+pub struct BootProgram {
+    pub origin: u64,
+    pub instructions: Vec<BootInstruction>,
+    /// Descriptors of the GDT the code loads with `lgdt`
+    pub gdt: Vec<u64>,
+}
+
+impl BootProgram {
+    /// Boot code of the SimpleBoot guest: enable A20, load a GDT, enter
+    /// protected mode, set up identity paging and enter long mode
+    pub fn simple_boot() -> Self {
+        // Access bytes 0x9A (present, code, readable) and 0x92 (present, data, writable)
+        let code32 = (0x9A << 40) | DESC_DEFAULT_32 | (0xF << 48) | 0xFFFF;
+        let data = (0x92 << 40) | DESC_DEFAULT_32 | (0xF << 48) | 0xFFFF;
+        let code64 = (0x9A << 40) | DESC_LONG;
+
+        BootProgram {
+            origin: 0x7c00,
+            instructions: vec![
+                BootInstruction::Cli,
+                BootInstruction::OutAl { port: PORT_FAST_A20, value: 0x02 },
+                BootInstruction::Lgdt { base: 0x7e00, limit: 4 * 8 - 1 },
+                BootInstruction::SetCr0Bits(CR0_PE),
+                BootInstruction::FarJump { selector: 0x08 },
+                BootInstruction::LoadDataSegments { selector: 0x10 },
+                BootInstruction::SetCr4Bits(CR4_PAE),
+                BootInstruction::MovCr3(0x1000),
+                BootInstruction::SetMsrBits { msr: MSR_EFER, bits: EFER_LME },
+                BootInstruction::SetCr0Bits(CR0_PG),
+                BootInstruction::FarJump { selector: 0x18 },
+                BootInstruction::Lidt { base: 0x8000, limit: 256 * 16 - 1 },
+                BootInstruction::Hlt,
+            ],
+            gdt: vec![0, code32, data, code64],
+        }
+    }
+}
+
+/// Processor operating mode
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CpuMode {
+    Real,
+    Protected,
+    /// IA-32e mode running 32-bit code
+    Compatibility,
+    Long,
+}
+
+/// Boot milestones, each reported once
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BootCheckpoint {
+    InterruptsDisabled,
+    A20Enabled,
+    GdtLoaded,
+    ProtectedModeEnabled,
+    /// CS reloaded with a 32-bit code segment
+    ProtectedModeEntered,
+    PaeEnabled,
+    PageTablesLoaded,
+    LongModeEnabled,
+    PagingEnabled,
+    /// EFER.LMA set by the processor
+    LongModeActive,
+    /// CS reloaded with a 64-bit code segment
+    LongModeEntered,
+    IdtLoaded,
+    Halted,
+}
+
+impl BootCheckpoint {
+    /// Name used in trace output
+    pub fn name(&self) -> &'static str {
+        match self {
+            BootCheckpoint::InterruptsDisabled => "interrupts-disabled",
+            BootCheckpoint::A20Enabled => "a20-enabled",
+            BootCheckpoint::GdtLoaded => "gdt-loaded",
+            BootCheckpoint::ProtectedModeEnabled => "protected-mode-enabled",
+            BootCheckpoint::ProtectedModeEntered => "protected-mode-entered",
+            BootCheckpoint::PaeEnabled => "pae-enabled",
+            BootCheckpoint::PageTablesLoaded => "page-tables-loaded",
+            BootCheckpoint::LongModeEnabled => "long-mode-enabled",
+            BootCheckpoint::PagingEnabled => "paging-enabled",
+            BootCheckpoint::LongModeActive => "long-mode-active",
+            BootCheckpoint::LongModeEntered => "long-mode-entered",
+            BootCheckpoint::IdtLoaded => "idt-loaded",
+            BootCheckpoint::Halted => "halted",
+        }
+    }
+}
+
+/// A checkpoint as it was reached
+#[derive(Debug, Clone, PartialEq)]
+pub struct CheckpointEvent {
+    pub checkpoint: BootCheckpoint,
+    pub rip: u64,
+    /// Instructions retired up to and including the one that reached it
+    pub instruction_count: u64,
+    pub mode: CpuMode,
+}
+
+/// What a single step did
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceStep {
+    pub rip: u64,
+    pub instruction: BootInstruction,
+    pub mode: CpuMode,
+    pub checkpoints: Vec<BootCheckpoint>,
+}
+
+/// Step granularity of the tracer
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TraceGranularity {
+    Instruction,
+    BasicBlock,
+}
+
+/// Steps boot code and tracks the architectural state it changes
+pub struct BootTracer {
+    program: BootProgram,
+    next: usize,
+    rip: u64,
+    cr0: u64,
+    cr3: u64,
+    cr4: u64,
+    efer: u64,
+    gdt_entries: usize,
+    cs_selector: u16,
+    cs_descriptor: u64,
+    a20: bool,
+    kbc_expect_output: bool,
+    halted: bool,
+    fault: Option<String>,
+    instruction_count: u64,
+    checkpoints: Vec<CheckpointEvent>,
+}
+
+impl BootTracer {
+    /// Set up a processor in its reset state at the program's origin
+    pub fn new(program: BootProgram) -> Self {
+        BootTracer {
+            rip: program.origin,
+            program,
+            next: 0,
+            cr0: CR0_RESET,
+            cr3: 0,
+            cr4: 0,
+            efer: 0,
+            gdt_entries: 0,
+            cs_selector: 0,
+            cs_descriptor: 0,
+            a20: false,
+            kbc_expect_output: false,
+            halted: false,
+            fault: None,
+            instruction_count: 0,
+            checkpoints: Vec::new(),
+        }
+    }
+
+    /// Current operating mode
+    pub fn mode(&self) -> CpuMode {
+        if self.cr0 & CR0_PE == 0 {
+            CpuMode::Real
+        } else if self.efer & EFER_LMA == 0 {
+            CpuMode::Protected
+        } else if self.cs_descriptor & DESC_LONG != 0 {
+            CpuMode::Long
+        } else {
+            CpuMode::Compatibility
+        }
+    }
+
+    /// Checkpoints reached so far, in order
+    pub fn checkpoints(&self) -> &[CheckpointEvent] {
+        &self.checkpoints
+    }
+
+    /// Whether a checkpoint was reached
+    pub fn reached(&self, checkpoint: BootCheckpoint) -> bool {
+        self.checkpoints.iter().any(|event| event.checkpoint == checkpoint)
+    }
+
+    /// Why the trace stopped early, if it did
+    pub fn fault(&self) -> Option<&str> {
+        self.fault.as_deref()
+    }
+
+    /// Whether the trace can continue
+    pub fn is_finished(&self) -> bool {
+        self.halted || self.fault.is_some() || self.next >= self.program.instructions.len()
+    }
+
+    /// Execute one instruction
+    pub fn step(&mut self) -> Result<TraceStep, HypervisorError> {
+        if self.is_finished() {
+            return Err(HypervisorError::InvalidVcpuState);
+        }
+
+        let instruction = self.program.instructions[self.next];
+        let rip = self.rip;
+        let reached_before = self.checkpoints.len();
+        self.instruction_count += 1;
+
+        if let Err(reason) = self.execute(instruction) {
+            warn!("Boot trace fault at 0x{:x}: {}", rip, reason);
+            self.fault = Some(format!("{} at rip=0x{:x}", reason, rip));
+        } else {
+            self.next += 1;
+            self.rip += instruction.length();
+        }
+
+        Ok(TraceStep {
+            rip,
+            instruction,
+            mode: self.mode(),
+            checkpoints: self.checkpoints[reached_before..].iter().map(|event| event.checkpoint).collect(),
+        })
+    }
+
+    /// Execute up to and including the next control transfer
+    pub fn step_block(&mut self) -> Result<Vec<TraceStep>, HypervisorError> {
+        let mut steps = Vec::new();
+        loop {
+            let step = self.step()?;
+            let ends_block = step.instruction.ends_block();
+            steps.push(step);
+            if ends_block || self.is_finished() {
+                return Ok(steps);
+            }
+        }
+    }
+
+    /// Run to completion, or until `max_instructions` have been executed
+    pub fn run(&mut self, granularity: TraceGranularity, max_instructions: u64) -> Result<Vec<TraceStep>, HypervisorError> {
+        let mut steps = Vec::new();
+        while !self.is_finished() && self.instruction_count < max_instructions {
+            match granularity {
+                TraceGranularity::Instruction => steps.push(self.step()?),
+                TraceGranularity::BasicBlock => steps.extend(self.step_block()?),
+            }
+        }
+        Ok(steps)
+    }
+
+    /// Render the checkpoints reached, and any fault, one per line
+    pub fn render(&self) -> String {
+        let mut output = String::new();
+        for event in &self.checkpoints {
+            output.push_str(&format!(
+                "checkpoint {} at rip=0x{:x} after {} instructions ({:?} mode)\n",
+                event.checkpoint.name(),
+                event.rip,
+                event.instruction_count,
+                event.mode
+            ));
+        }
+        if let Some(fault) = &self.fault {
+            output.push_str(&format!("fault: {}\n", fault));
+        }
+        output.push_str(&format!("final mode: {:?}\n", self.mode()));
+        output
+    }
+
+    fn execute(&mut self, instruction: BootInstruction) -> Result<(), String> {
+        match instruction {
+            BootInstruction::Cli => self.checkpoint(BootCheckpoint::InterruptsDisabled),
+            BootInstruction::OutAl { port, value } => self.port_write(port, value),
+            BootInstruction::Lgdt { limit, .. } => {
+                self.gdt_entries = ((limit as usize + 1) / 8).min(self.program.gdt.len());
+                self.checkpoint(BootCheckpoint::GdtLoaded);
+            }
+            BootInstruction::Lidt { .. } => self.checkpoint(BootCheckpoint::IdtLoaded),
+            BootInstruction::SetCr0Bits(bits) => self.write_cr0(self.cr0 | bits)?,
+            BootInstruction::SetCr4Bits(bits) => {
+                self.cr4 |= bits;
+                if self.cr4 & CR4_PAE != 0 {
+                    self.checkpoint(BootCheckpoint::PaeEnabled);
+                }
+            }
+            BootInstruction::MovCr3(value) => {
+                self.cr3 = value;
+                self.checkpoint(BootCheckpoint::PageTablesLoaded);
+            }
+            BootInstruction::SetMsrBits { msr, bits } => {
+                if msr != MSR_EFER {
+                    return Err(format!("#GP: unsupported MSR 0x{:x}", msr));
+                }
+                if bits & EFER_LME != 0 && self.cr0 & CR0_PG != 0 {
+                    return Err(String::from("#GP: EFER.LME changed while paging is enabled"));
+                }
+                // LMA is read-only; the processor sets it
+                self.efer |= bits & !EFER_LMA;
+                if self.efer & EFER_LME != 0 {
+                    self.checkpoint(BootCheckpoint::LongModeEnabled);
+                }
+            }
+            BootInstruction::FarJump { selector } => self.load_cs(selector)?,
+            BootInstruction::LoadDataSegments { selector } => {
+                if self.mode() != CpuMode::Real {
+                    self.descriptor(selector)?;
+                }
+            }
+            BootInstruction::Hlt => {
+                self.halted = true;
+                self.checkpoint(BootCheckpoint::Halted);
+            }
+        }
+        Ok(())
+    }
+
+    fn port_write(&mut self, port: u16, value: u8) {
+        let enable_a20 = match port {
+            PORT_FAST_A20 => value & 0x02 != 0,
+            PORT_KBC_COMMAND => {
+                self.kbc_expect_output = value == KBC_WRITE_OUTPUT;
+                false
+            }
+            PORT_KBC_DATA if self.kbc_expect_output => {
+                self.kbc_expect_output = false;
+                value & 0x02 != 0
+            }
+            _ => false,
+        };
+        if enable_a20 && !self.a20 {
+            self.a20 = true;
+            self.checkpoint(BootCheckpoint::A20Enabled);
+        }
+    }
+
+    fn write_cr0(&mut self, value: u64) -> Result<(), String> {
+        if value & CR0_PG != 0 && value & CR0_PE == 0 {
+            return Err(String::from("#GP: CR0.PG set without CR0.PE"));
+        }
+        let enabling_paging = value & CR0_PG != 0 && self.cr0 & CR0_PG == 0;
+        if enabling_paging && self.efer & EFER_LME != 0 && self.cr4 & CR4_PAE == 0 {
+            return Err(String::from("#GP: long mode paging requires CR4.PAE"));
+        }
+
+        let enabling_protection = value & CR0_PE != 0 && self.cr0 & CR0_PE == 0;
+        self.cr0 = value;
+        if enabling_protection {
+            self.checkpoint(BootCheckpoint::ProtectedModeEnabled);
+        }
+        if enabling_paging {
+            self.checkpoint(BootCheckpoint::PagingEnabled);
+            if self.efer & EFER_LME != 0 {
+                self.efer |= EFER_LMA;
+                self.checkpoint(BootCheckpoint::LongModeActive);
+            }
+        }
+        Ok(())
+    }
+
+    fn load_cs(&mut self, selector: u16) -> Result<(), String> {
+        if self.mode() == CpuMode::Real {
+            // Real mode far jumps load CS as a paragraph
+            self.cs_selector = selector;
+            self.cs_descriptor = 0;
+            return Ok(());
+        }
+
+        let descriptor = self.descriptor(selector)?;
+        if descriptor & DESC_CODE == 0 {
+            return Err(format!("#GP: selector 0x{:x} is not a code segment", selector));
+        }
+        let long = descriptor & DESC_LONG != 0;
+        if long && self.efer & EFER_LMA == 0 {
+            return Err(format!("#GP: 64-bit code segment 0x{:x} outside IA-32e mode", selector));
+        }
+
+        self.cs_selector = selector;
+        self.cs_descriptor = descriptor;
+        if long {
+            self.checkpoint(BootCheckpoint::LongModeEntered);
+        } else if descriptor & DESC_DEFAULT_32 != 0 {
+            self.checkpoint(BootCheckpoint::ProtectedModeEntered);
+        }
+        Ok(())
+    }
+
+    fn descriptor(&self, selector: u16) -> Result<u64, String> {
+        let index = (selector >> 3) as usize;
+        if index == 0 || index >= self.gdt_entries {
+            return Err(format!("#GP: selector 0x{:x} outside the loaded GDT", selector));
+        }
+        let descriptor = self.program.gdt[index];
+        if descriptor & DESC_PRESENT == 0 {
+            return Err(format!("#NP: selector 0x{:x} not present", selector));
+        }
+        Ok(descriptor)
+    }
+
+    fn checkpoint(&mut self, checkpoint: BootCheckpoint) {
+        if self.reached(checkpoint) {
+            return;
+        }
+        info!("Boot checkpoint {} at 0x{:x}", checkpoint.name(), self.rip);
+        self.checkpoints.push(CheckpointEvent {
+            checkpoint,
+            rip: self.rip,
+            instruction_count: self.instruction_count,
+            mode: self.mode(),
+        });
+    }
+}
+
+impl Default for BootTracer {
+    fn default() -> Self {
+        BootTracer::new(BootProgram::simple_boot())
+    }
+}
@@ -0,0 +1,91 @@
+//! Step-by-Step VM Exit Visualizer
+//!
+//! Renders pages of the monitoring exit feed as text for the CLI and keeps
+//! the reader's cursor, so a learner can step through the exits of a VM in
+//! teaching mode one page at a time.
+
+use crate::{VmId, HypervisorError};
+use crate::monitoring::{AnnotatedExit, ExitFeedPage, PerformanceMonitor};
+
+use alloc::format;
+use alloc::string::String;
+
+/// Cursor-keeping reader over the exit feed of one VM
+pub struct ExitVisualizer {
+    vm_id: VmId,
+    cursor: u64,
+    page_size: usize,
+    /// Whether to print the explanation of every exit
+    pub verbose: bool,
+}
+
+impl ExitVisualizer {
+    /// Start reading the feed of a VM from the oldest buffered exit
+    pub fn new(vm_id: VmId, page_size: usize) -> Self {
+        ExitVisualizer {
+            vm_id,
+            cursor: 0,
+            page_size,
+            verbose: true,
+        }
+    }
+
+    /// Put the VM into teaching mode so its exits are decoded
+    pub fn attach(&self, monitor: &mut PerformanceMonitor) {
+        monitor.exit_feed_mut().enable_teaching_mode(self.vm_id);
+    }
+
+    /// Take the VM out of teaching mode
+    pub fn detach(&self, monitor: &mut PerformanceMonitor) {
+        monitor.exit_feed_mut().disable_teaching_mode(self.vm_id);
+    }
+
+    /// Fetch the next page and advance the cursor
+    pub fn next_page(&mut self, monitor: &PerformanceMonitor) -> Result<ExitFeedPage, HypervisorError> {
+        let page = monitor.exit_feed().page(self.vm_id, self.cursor, self.page_size)?;
+        self.cursor = page.next_cursor;
+        Ok(page)
+    }
+
+    /// Render a page as text, one block per exit
+    pub fn render_page(&self, page: &ExitFeedPage) -> String {
+        let mut output = String::new();
+        if page.missed > 0 {
+            output.push_str(&format!("... {} exit(s) dropped from the buffer ...\n", page.missed));
+        }
+        for entry in &page.entries {
+            output.push_str(&self.render_exit(entry));
+        }
+        if page.entries.is_empty() {
+            output.push_str("No new VM exits\n");
+        } else if page.has_more {
+            output.push_str("-- more --\n");
+        }
+        output
+    }
+
+    /// Render a single exit
+    pub fn render_exit(&self, entry: &AnnotatedExit) -> String {
+        let exit = &entry.exit;
+        let mut output = format!(
+            "#{} vcpu{} rip=0x{:x} {:?}: {}\n",
+            entry.sequence, entry.vcpu_id.0, exit.guest_rip, exit.reason, exit.summary
+        );
+        for (name, value) in &exit.fields {
+            output.push_str(&format!("    {:<24}{}\n", name, value));
+        }
+        if let Some(register) = &exit.affected_register {
+            output.push_str(&format!(
+                "    {:<24}{} = 0x{:x} ({})\n",
+                "affected register",
+                register.name,
+                register.value,
+                if register.written { "written" } else { "read" }
+            ));
+        }
+        if self.verbose {
+            output.push_str(&format!("    {}\n", exit.explanation));
+        }
+        output
+    }
+}
@@ -0,0 +1,370 @@
+//! Scenario-Based Fault Injection
+//!
+//! A [`FaultInjector`] holds a scenario of faults, each aimed at one VM and
+//! active according to a schedule. Every [`FaultInjector::tick`] applies the
+//! faults that are active for a VM to that VM's memory manager, device
+//! framework and performance monitor, so the failures show up in the regular
+//! monitoring tools where students can diagnose them.
+
+use crate::{VmId, HypervisorError};
+use crate::memory::MemoryManager;
+use crate::devices::{DeviceFramework, DeviceState};
+use crate::monitoring::{PerformanceMonitor, PerformanceSample, MetricType};
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+/// Guest page size used to spread EPT violations
+const STORM_STRIDE: u64 = 0x1000;
+
+/// Kind of fault to inject
+#[derive(Debug, Clone, PartialEq)]
+pub enum FaultKind {
+    /// Raise EPT violations on consecutive guest pages every tick
+    EptViolationStorm { base_address: u64, violations_per_tick: u32 },
+    /// Put a device into the error state
+    DeviceError { device_id: String },
+    /// Drop a share of the interrupts on an interrupt line
+    DroppedInterrupts { interrupt_line: u8, drop_percent: u8 },
+    /// Take guest memory away as if the host were short of memory
+    MemoryPressure { memory_mb: u64 },
+    /// Add latency to every VM exit
+    ExitLatency { latency_us: u64 },
+}
+
+impl FaultKind {
+    /// Short name for logs and reports
+    pub fn name(&self) -> &'static str {
+        match self {
+            FaultKind::EptViolationStorm { .. } => "ept-violation-storm",
+            FaultKind::DeviceError { .. } => "device-error",
+            FaultKind::DroppedInterrupts { .. } => "dropped-interrupts",
+            FaultKind::MemoryPressure { .. } => "memory-pressure",
+            FaultKind::ExitLatency { .. } => "exit-latency",
+        }
+    }
+}
+
+/// When a fault is active, in milliseconds since the scenario started
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FaultSchedule {
+    /// Active from `at_ms` until the scenario is cleared
+    From { at_ms: u64 },
+    /// Active once for `duration_ms`
+    Window { at_ms: u64, duration_ms: u64 },
+    /// Active for `duration_ms` at the start of every period
+    Periodic { start_ms: u64, period_ms: u64, duration_ms: u64 },
+}
+
+impl FaultSchedule {
+    /// Whether the schedule is active at `now_ms`
+    pub fn is_active(&self, now_ms: u64) -> bool {
+        match *self {
+            FaultSchedule::From { at_ms } => now_ms >= at_ms,
+            FaultSchedule::Window { at_ms, duration_ms } => now_ms >= at_ms && now_ms - at_ms < duration_ms,
+            FaultSchedule::Periodic { start_ms, period_ms, duration_ms } => {
+                now_ms >= start_ms && period_ms > 0 && (now_ms - start_ms) % period_ms < duration_ms
+            }
+        }
+    }
+}
+
+/// A scheduled fault aimed at one VM
+#[derive(Debug, Clone)]
+pub struct ScheduledFault {
+    pub id: u32,
+    pub vm_id: VmId,
+    pub kind: FaultKind,
+    pub schedule: FaultSchedule,
+    /// Whether the fault was applied on the last tick
+    pub active: bool,
+    /// Interrupts seen while dropping, for the drop ratio
+    interrupts_seen: u64,
+    interrupts_dropped: u64,
+    /// Memory actually reserved for a memory pressure fault
+    reserved_mb: u64,
+}
+
+/// Something the injector did, for the scenario log
+#[derive(Debug, Clone, PartialEq)]
+pub enum FaultEventKind {
+    Activated,
+    Deactivated,
+    EptViolations(u32),
+    InterruptDropped(u8),
+    MemoryReserved(u64),
+    ExitLatencyAdded(u64),
+    /// The fault could not be applied, e.g. because its target is missing
+    Skipped(String),
+}
+
+/// Scenario log entry
+#[derive(Debug, Clone, PartialEq)]
+pub struct FaultEvent {
+    pub timestamp_ms: u64,
+    pub fault_id: u32,
+    pub vm_id: VmId,
+    pub kind: FaultEventKind,
+}
+
+/// Subsystems of one VM that faults are applied to; missing ones are skipped
+#[derive(Default)]
+pub struct FaultTargets<'a> {
+    pub memory: Option<&'a mut MemoryManager>,
+    pub devices: Option<&'a mut DeviceFramework>,
+    pub monitor: Option<&'a mut PerformanceMonitor>,
+}
+
+/// Injects scheduled faults into VMs
+pub struct FaultInjector {
+    faults: Vec<ScheduledFault>,
+    events: Vec<FaultEvent>,
+    next_id: u32,
+}
+
+impl FaultInjector {
+    /// Create an injector with an empty scenario
+    pub fn new() -> Self {
+        FaultInjector {
+            faults: Vec::new(),
+            events: Vec::new(),
+            next_id: 1,
+        }
+    }
+
+    /// Schedule a fault and return its ID
+    pub fn schedule(&mut self, vm_id: VmId, kind: FaultKind, schedule: FaultSchedule) -> Result<u32, HypervisorError> {
+        match &kind {
+            FaultKind::DroppedInterrupts { drop_percent, .. } if *drop_percent == 0 || *drop_percent > 100 => {
+                return Err(HypervisorError::InvalidParameter);
+            }
+            FaultKind::EptViolationStorm { violations_per_tick: 0, .. } => return Err(HypervisorError::InvalidParameter),
+            _ => {}
+        }
+        if let FaultSchedule::Periodic { period_ms, duration_ms, .. } = schedule {
+            if period_ms == 0 || duration_ms == 0 || duration_ms > period_ms {
+                return Err(HypervisorError::InvalidParameter);
+            }
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+        info!("Scheduled fault {} ({}) for VM {}", id, kind.name(), vm_id.0);
+        self.faults.push(ScheduledFault {
+            id,
+            vm_id,
+            kind,
+            schedule,
+            active: false,
+            interrupts_seen: 0,
+            interrupts_dropped: 0,
+            reserved_mb: 0,
+        });
+        Ok(id)
+    }
+
+    /// Scheduled faults
+    pub fn faults(&self) -> &[ScheduledFault] {
+        &self.faults
+    }
+
+    /// Faults of a VM that were applied on the last tick
+    pub fn active_faults(&self, vm_id: VmId) -> Vec<&ScheduledFault> {
+        self.faults.iter().filter(|fault| fault.vm_id == vm_id && fault.active).collect()
+    }
+
+    /// Scenario log
+    pub fn events(&self) -> &[FaultEvent] {
+        &self.events
+    }
+
+    /// Apply the faults of a VM that are active at `now_ms`.
+    ///
+    /// Faults that became inactive since the last tick are undone: devices
+    /// leave the error state and reserved memory is released.
+    pub fn tick(&mut self, vm_id: VmId, now_ms: u64, targets: &mut FaultTargets) -> Vec<FaultEvent> {
+        let mut events = Vec::new();
+
+        for fault in self.faults.iter_mut().filter(|fault| fault.vm_id == vm_id) {
+            let active = fault.schedule.is_active(now_ms);
+            if active != fault.active {
+                let kind = if active { FaultEventKind::Activated } else { FaultEventKind::Deactivated };
+                events.push(FaultEvent { timestamp_ms: now_ms, fault_id: fault.id, vm_id, kind });
+                if !active {
+                    Self::undo(fault, targets);
+                }
+                fault.active = active;
+            }
+            if active {
+                if let Some(kind) = Self::apply(fault, now_ms, targets) {
+                    events.push(FaultEvent { timestamp_ms: now_ms, fault_id: fault.id, vm_id, kind });
+                }
+            }
+        }
+
+        self.events.extend(events.iter().cloned());
+        events
+    }
+
+    /// Whether an interrupt on `interrupt_line` should be dropped.
+    ///
+    /// Interrupt delivery calls this for every interrupt; the drop ratio is
+    /// applied deterministically so scenarios are repeatable.
+    pub fn should_drop_interrupt(&mut self, vm_id: VmId, interrupt_line: u8, now_ms: u64) -> bool {
+        let fault = self.faults.iter_mut().find(|fault| {
+            fault.vm_id == vm_id
+                && fault.active
+                && matches!(fault.kind, FaultKind::DroppedInterrupts { interrupt_line: line, .. } if line == interrupt_line)
+        });
+        let fault = match fault {
+            Some(fault) => fault,
+            None => return false,
+        };
+        let drop_percent = match fault.kind {
+            FaultKind::DroppedInterrupts { drop_percent, .. } => drop_percent as u64,
+            _ => return false,
+        };
+
+        fault.interrupts_seen += 1;
+        // Drop while the observed ratio is below the requested one
+        let drop = fault.interrupts_dropped * 100 < fault.interrupts_seen * drop_percent;
+        if drop {
+            fault.interrupts_dropped += 1;
+            self.events.push(FaultEvent {
+                timestamp_ms: now_ms,
+                fault_id: fault.id,
+                vm_id,
+                kind: FaultEventKind::InterruptDropped(interrupt_line),
+            });
+        }
+        drop
+    }
+
+    /// Extra latency to add to each VM exit of a VM, in microseconds
+    pub fn exit_latency_us(&self, vm_id: VmId) -> u64 {
+        self.faults
+            .iter()
+            .filter(|fault| fault.vm_id == vm_id && fault.active)
+            .map(|fault| match fault.kind {
+                FaultKind::ExitLatency { latency_us } => latency_us,
+                _ => 0,
+            })
+            .sum()
+    }
+
+    /// Undo every active fault of a VM and drop the VM's scenario
+    pub fn clear_vm(&mut self, vm_id: VmId, targets: &mut FaultTargets) {
+        for fault in self.faults.iter_mut().filter(|fault| fault.vm_id == vm_id && fault.active) {
+            Self::undo(fault, targets);
+        }
+        self.faults.retain(|fault| fault.vm_id != vm_id);
+        info!("Cleared fault scenario of VM {}", vm_id.0);
+    }
+
+    /// Apply one tick of an active fault
+    fn apply(fault: &mut ScheduledFault, now_ms: u64, targets: &mut FaultTargets) -> Option<FaultEventKind> {
+        let missing = |target: &str| Some(FaultEventKind::Skipped(format!("no {} target", target)));
+
+        match &fault.kind {
+            FaultKind::EptViolationStorm { base_address, violations_per_tick } => {
+                let memory = match targets.memory.as_deref_mut() {
+                    Some(memory) => memory,
+                    None => return missing("memory"),
+                };
+                for page in 0..*violations_per_tick as u64 {
+                    if let Err(error) = memory.handle_ept_violation(base_address + page * STORM_STRIDE) {
+                        return Some(FaultEventKind::Skipped(format!("{:?}", error)));
+                    }
+                }
+                Some(FaultEventKind::EptViolations(*violations_per_tick))
+            }
+            FaultKind::DeviceError { device_id } => {
+                let devices = match targets.devices.as_deref_mut() {
+                    Some(devices) => devices,
+                    None => return missing("device"),
+                };
+                let device = match devices.devices.get(device_id) {
+                    Some(device) => device,
+                    None => return Some(FaultEventKind::Skipped(format!("device {} not found", device_id))),
+                };
+                let mut device = device.write();
+                if device.state != DeviceState::Error {
+                    device.state = DeviceState::Error;
+                    device.stats.error_count += 1;
+                    warn!("Injected error into device {}", device_id);
+                }
+                None
+            }
+            FaultKind::DroppedInterrupts { interrupt_line, .. } => {
+                // Latched interrupts on the line are lost as well
+                let devices = targets.devices.as_deref_mut()?;
+                let mut dropped = false;
+                for device in devices.devices.values() {
+                    let mut device = device.write();
+                    if let Some(interrupt) = device.interrupt.as_mut() {
+                        if interrupt.interrupt_line == *interrupt_line && interrupt.active {
+                            interrupt.active = false;
+                            dropped = true;
+                        }
+                    }
+                }
+                dropped.then_some(FaultEventKind::InterruptDropped(*interrupt_line))
+            }
+            FaultKind::MemoryPressure { memory_mb } => {
+                if fault.reserved_mb > 0 {
+                    return None;
+                }
+                let memory = match targets.memory.as_deref_mut() {
+                    Some(memory) => memory,
+                    None => return missing("memory"),
+                };
+                // Take what is there rather than failing outright
+                let reserve_mb = (*memory_mb).min(memory.available_mb());
+                if memory.reserve_memory(reserve_mb).is_err() || reserve_mb == 0 {
+                    return Some(FaultEventKind::Skipped(String::from("no memory available")));
+                }
+                fault.reserved_mb = reserve_mb;
+                Some(FaultEventKind::MemoryReserved(reserve_mb))
+            }
+            FaultKind::ExitLatency { latency_us } => {
+                let monitor = targets.monitor.as_deref_mut()?;
+                let sample = PerformanceSample {
+                    timestamp_ms: now_ms,
+                    vm_id: Some(fault.vm_id),
+                    vcpu_id: None,
+                    metric_type: MetricType::HypervisorOverhead,
+                    value: *latency_us as f64,
+                    unit: "us".to_string(),
+                };
+                match monitor.collect_sample(sample) {
+                    Ok(()) => Some(FaultEventKind::ExitLatencyAdded(*latency_us)),
+                    Err(error) => Some(FaultEventKind::Skipped(format!("{:?}", error))),
+                }
+            }
+        }
+    }
+
+    /// Revert the lasting effects of a fault
+    fn undo(fault: &mut ScheduledFault, targets: &mut FaultTargets) {
+        match &fault.kind {
+            FaultKind::DeviceError { device_id } => {
+                if let Some(device) = targets.devices.as_deref_mut().and_then(|devices| devices.devices.get(device_id)) {
+                    let mut device = device.write();
+                    if device.state == DeviceState::Error {
+                        device.state = DeviceState::Running;
+                    }
+                }
+            }
+            FaultKind::MemoryPressure { .. } => {
+                if let Some(memory) = targets.memory.as_deref_mut() {
+                    memory.release_memory(fault.reserved_mb);
+                    fault.reserved_mb = 0;
+                }
+            }
+            _ => {}
+        }
+        fault.interrupts_seen = 0;
+        fault.interrupts_dropped = 0;
+    }
+}
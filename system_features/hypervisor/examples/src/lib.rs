@@ -3,11 +3,24 @@
 //! Provides comprehensive examples and tutorials for learning virtualization
 //! concepts using the MultiOS hypervisor system.
 
-use crate::{VmId, VmConfig, VmFeatures, HypervisorError};
+use crate::{VmId, VmConfig, VmFeatures, HypervisorError, ConfigError, ConfigErrorReason};
 use crate::core::{Hypervisor, vm_config::{VmArchitecture, BootConfig, DeviceConfig, NetworkConfig, StorageConfig, SecurityConfig}};
+use crate::cpu::CpuModel;
+
+mod assessment;
+mod boot_trace;
+mod exit_visualizer;
+mod fault_injection;
+mod tutorial_runner;
+
+pub use assessment::*;
+pub use boot_trace::*;
+pub use exit_visualizer::*;
+pub use fault_injection::*;
+pub use tutorial_runner::*;
 
 /// Educational example identifier
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum EducationalExample {
     /// Simple boot example
     SimpleBoot,
@@ -31,6 +44,43 @@ pub enum EducationalExample {
     TeachingLab,
 }
 
+impl EducationalExample {
+    /// All educational examples
+    pub const ALL: [EducationalExample; 10] = [
+        EducationalExample::SimpleBoot,
+        EducationalExample::MultiOSComparison,
+        EducationalExample::NestedVirtualization,
+        EducationalExample::KernelDevelopment,
+        EducationalExample::DeviceDriverExample,
+        EducationalExample::MemoryManagement,
+        EducationalExample::NetworkVirtualization,
+        EducationalExample::SecurityIsolation,
+        EducationalExample::PerformanceAnalysis,
+        EducationalExample::TeachingLab,
+    ];
+    
+    /// Stable name used in persisted records
+    pub fn name(&self) -> &'static str {
+        match self {
+            EducationalExample::SimpleBoot => "SimpleBoot",
+            EducationalExample::MultiOSComparison => "MultiOSComparison",
+            EducationalExample::NestedVirtualization => "NestedVirtualization",
+            EducationalExample::KernelDevelopment => "KernelDevelopment",
+            EducationalExample::DeviceDriverExample => "DeviceDriverExample",
+            EducationalExample::MemoryManagement => "MemoryManagement",
+            EducationalExample::NetworkVirtualization => "NetworkVirtualization",
+            EducationalExample::SecurityIsolation => "SecurityIsolation",
+            EducationalExample::PerformanceAnalysis => "PerformanceAnalysis",
+            EducationalExample::TeachingLab => "TeachingLab",
+        }
+    }
+    
+    /// Parse a name produced by [`EducationalExample::name`]
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|example| example.name() == name)
+    }
+}
+
 /// Difficulty level for educational examples
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum DifficultyLevel {
@@ -111,6 +161,7 @@ impl EducationalManager {
         self.create_kernel_development_example()?;
         self.create_device_driver_example()?;
         self.create_memory_management_example()?;
+        self.create_performance_analysis_example()?;
         self.create_teaching_lab_example()?;
         
         info!("Initialized {} educational examples", self.tutorials.len());
@@ -124,6 +175,8 @@ impl EducationalManager {
             vcpu_count: 1,
             memory_mb: 512,
             arch: VmArchitecture::X86_64,
+            cpu_model: CpuModel::edu_baseline(),
+            numa: None,
             boot: BootConfig {
                 boot_order: crate::core::vm_config::BootOrder::DiskFirst,
                 kernel_path: Some(String::from("simple_kernel.bin")),
@@ -179,6 +232,18 @@ impl EducationalManager {
                         String::from("Verify boot configuration"),
                     ],
                 },
+                TutorialStep {
+                    step_number: 3,
+                    title: String::from("Trace the Boot Sequence"),
+                    description: String::from("Step the boot code one basic block at a time from real mode through protected mode into long mode"),
+                    code_example: Some(String::from("hypervisor trace-boot --vm 1 --blocks")),
+                    expected_output: Some(String::from("Checkpoints for A20, GDT load, protected mode, paging and long mode, in that order")),
+                    verification_commands: Vec::new(),
+                    troubleshooting_tips: vec![
+                        String::from("Drop --blocks to step one instruction at a time"),
+                        String::from("A fault line names the check the processor would have failed"),
+                    ],
+                },
             ],
             resources: vec![
                 TutorialResource {
@@ -203,6 +268,8 @@ impl EducationalManager {
                 vcpu_count: 2,
                 memory_mb: 2048,
                 arch: VmArchitecture::X86_64,
+                cpu_model: CpuModel::edu_baseline(),
+                numa: None,
                 boot: BootConfig {
                     boot_order: crate::core::vm_config::BootOrder::DiskFirst,
                     kernel_path: Some(String::from("linux_kernel.bin")),
@@ -222,6 +289,8 @@ impl EducationalManager {
                 vcpu_count: 2,
                 memory_mb: 2048,
                 arch: VmArchitecture::X86_64,
+                cpu_model: CpuModel::edu_baseline(),
+                numa: None,
                 boot: BootConfig {
                     boot_order: crate::core::vm_config::BootOrder::CdromFirst,
                     kernel_path: None,
@@ -241,6 +310,8 @@ impl EducationalManager {
                 vcpu_count: 2,
                 memory_mb: 2048,
                 arch: VmArchitecture::X86_64,
+                cpu_model: CpuModel::edu_baseline(),
+                numa: None,
                 boot: BootConfig {
                     boot_order: crate::core::vm_config::BootOrder::DiskFirst,
                     kernel_path: Some(String::from("bsd_kernel.bin")),
@@ -254,6 +325,27 @@ impl EducationalManager {
                 storage: StorageConfig::default(),
                 security: SecurityConfig::default(),
             },
+            // RISC-V VM, run on the H-extension backend
+            VmConfig {
+                name: String::from("RISC-V Comparison"),
+                vcpu_count: 2,
+                memory_mb: 1024,
+                arch: VmArchitecture::RiscV64,
+                cpu_model: CpuModel::host(),
+                numa: None,
+                boot: BootConfig {
+                    boot_order: crate::core::vm_config::BootOrder::DiskFirst,
+                    kernel_path: Some(String::from("riscv_kernel.bin")),
+                    initrd_path: Some(String::from("riscv_initrd.img")),
+                    kernel_args: String::from("console=hvc0 earlycon=sbi root=/dev/vda1"),
+                    timeout_sec: 30,
+                },
+                devices: DeviceConfig::default(),
+                features: VmFeatures::EDUCATIONAL,
+                network: NetworkConfig::default(),
+                storage: StorageConfig::default(),
+                security: SecurityConfig::default(),
+            },
         ];
         
         let tutorial = EducationalTutorial {
@@ -267,6 +359,7 @@ impl EducationalManager {
                 String::from("Learn VM configuration differences"),
                 String::from("Practice multi-VM management"),
                 String::from("Analyze performance differences"),
+                String::from("Compare x86 and RISC-V guests on the same hypervisor"),
             ],
             prerequisites: vec![
                 String::from("Basic virtualization concepts"),
@@ -286,6 +379,18 @@ impl EducationalManager {
                         String::from("Check storage requirements"),
                     ],
                 },
+                TutorialStep {
+                    step_number: 2,
+                    title: String::from("Boot a RISC-V Guest"),
+                    description: String::from("Run the RISC-V VM in VS-mode and watch its SBI calls and G-stage faults next to the x86 VM exits"),
+                    code_example: Some(String::from("hypervisor start riscv_vm && hypervisor stats riscv_vm")),
+                    expected_output: Some(String::from("RISC-V VM running with 2 harts")),
+                    verification_commands: vec![String::from("hypervisor info riscv_vm")],
+                    troubleshooting_tips: vec![
+                        String::from("The host must implement the RISC-V H extension"),
+                        String::from("Secondary harts stay stopped until the guest starts them through SBI HSM"),
+                    ],
+                },
             ],
             resources: vec![
                 TutorialResource {
@@ -308,6 +413,8 @@ impl EducationalManager {
             vcpu_count: 4,
             memory_mb: 4096,
             arch: VmArchitecture::X86_64,
+            cpu_model: CpuModel::host().with_nested(),
+            numa: None,
             boot: BootConfig {
                 boot_order: crate::core::vm_config::BootOrder::DiskFirst,
                 kernel_path: Some(String::from("linux_kernel.bin")),
@@ -327,6 +434,8 @@ impl EducationalManager {
             vcpu_count: 2,
             memory_mb: 1024,
             arch: VmArchitecture::X86_64,
+            cpu_model: CpuModel::edu_baseline(),
+            numa: None,
             boot: BootConfig {
                 boot_order: crate::core::vm_config::BootOrder::DiskFirst,
                 kernel_path: Some(String::from("linux_kernel.bin")),
@@ -371,6 +480,18 @@ impl EducationalManager {
                         String::from("Check CPU virtualization extensions"),
                     ],
                 },
+                TutorialStep {
+                    step_number: 2,
+                    title: String::from("Analyze Nested Overhead"),
+                    description: String::from("Run a workload in the nested guest and compare where each level's exit time goes: L0 handling, the guest hypervisor, and shadow VMCS sync"),
+                    code_example: Some(String::from("hypervisor nested-report")),
+                    expected_output: Some(String::from("Per-level overhead with L0, guest-hypervisor and vmcs-sync shares")),
+                    verification_commands: vec![String::from("hypervisor nested-report")],
+                    troubleshooting_tips: vec![
+                        String::from("Exits that are never reflected are handled entirely by L0"),
+                        String::from("A high vmcs-sync share means VMCS shadowing is not in use"),
+                    ],
+                },
             ],
             resources: vec![
                 TutorialResource {
@@ -393,6 +514,8 @@ impl EducationalManager {
             vcpu_count: 2,
             memory_mb: 2048,
             arch: VmArchitecture::X86_64,
+            cpu_model: CpuModel::host(),
+            numa: None,
             boot: BootConfig {
                 boot_order: crate::core::vm_config::BootOrder::DiskFirst,
                 kernel_path: Some(String::from("custom_kernel.bin")),
@@ -415,6 +538,7 @@ impl EducationalManager {
                 serial_console: crate::core::vm_config::SerialConfig::enabled(),
                 audio: crate::core::vm_config::AudioConfig::disabled(),
                 usb: crate::core::vm_config::UsbConfig::disabled(),
+                clock: crate::core::vm_config::ClockConfig::default(),
             },
             features: VmFeatures::KERNEL_DEBUG | VmFeatures::EDUCATIONAL,
             network: NetworkConfig::disabled(),
@@ -453,6 +577,20 @@ impl EducationalManager {
                         String::from("Check serial console configuration"),
                     ],
                 },
+                TutorialStep {
+                    step_number: 2,
+                    title: String::from("Step Through VM Exits"),
+                    description: String::from("Put the VM in teaching mode and read each VM exit your kernel causes, with the decoded qualification and affected register"),
+                    code_example: Some(String::from(
+                        "let mut visualizer = ExitVisualizer::new(vm_id, 20); visualizer.attach(&mut monitor); visualizer.render_page(&visualizer.next_page(&monitor)?)",
+                    )),
+                    expected_output: Some(String::from("Serial console writes show up as OUT to port 0x3f8 with rax as the affected register")),
+                    verification_commands: vec![String::from("hypervisor status --vm 1")],
+                    troubleshooting_tips: vec![
+                        String::from("Only exits recorded after attach appear in the feed"),
+                        String::from("A '... dropped from the buffer' line means the page size is too small for the exit rate"),
+                    ],
+                },
             ],
             resources: vec![
                 TutorialResource {
@@ -562,6 +700,92 @@ impl EducationalManager {
         Ok(())
     }
     
+    /// Create performance analysis example
+    fn create_performance_analysis_example(&mut self) -> Result<(), HypervisorError> {
+        let tutorial = EducationalTutorial {
+            id: EducationalExample::PerformanceAnalysis,
+            title: String::from("Performance Analysis of Virtual Machines"),
+            description: String::from("Use historical VM statistics to explain virtualization overhead"),
+            difficulty: DifficultyLevel::Intermediate,
+            estimated_duration_minutes: 60,
+            learning_objectives: vec![
+                String::from("Relate VM exit rates to guest CPU utilization"),
+                String::from("Read downsampled time series at different resolutions"),
+                String::from("Compare I/O-heavy and CPU-heavy workloads"),
+            ],
+            prerequisites: vec![
+                String::from("Simple Boot Example"),
+                String::from("Basic statistics (mean, min, max)"),
+            ],
+            vm_configs: vec![VmConfig::educational()],
+            steps: vec![
+                TutorialStep {
+                    step_number: 1,
+                    title: String::from("Collect a Baseline"),
+                    description: String::from("Run an idle guest for five minutes while the monitor records statistics"),
+                    code_example: Some(String::from("monitor.collect_vm_metrics(vm_id, &vm_stats, hypervisor.get_stats());")),
+                    expected_output: Some(String::from("CPU utilization below 5%, few VM exits per second")),
+                    verification_commands: vec![String::from("hypervisor stats --vm 1")],
+                    troubleshooting_tips: vec![
+                        String::from("Rates need two snapshots; wait for the second sample interval"),
+                    ],
+                },
+                TutorialStep {
+                    step_number: 2,
+                    title: String::from("Query the Time Series"),
+                    description: String::from("Query VM exits per second in 10 second buckets and compare with CPU utilization"),
+                    code_example: Some(String::from(
+                        "monitor.query_history(vm_id, HistoryMetric::VmExitRate, TimeRange { start_ms, end_ms }, 10_000)",
+                    )),
+                    expected_output: Some(String::from("One aligned bucket per 10 seconds with average, min and max")),
+                    verification_commands: vec![String::from("hypervisor stats --vm 1 --metric exits --resolution 10s")],
+                    troubleshooting_tips: vec![
+                        String::from("Empty buckets mean no snapshot was recorded in that interval"),
+                        String::from("Older ranges are served at a coarser resolution"),
+                    ],
+                },
+                TutorialStep {
+                    step_number: 3,
+                    title: String::from("Add an I/O Workload"),
+                    description: String::from("Start a disk benchmark in the guest and watch I/O and exit rates rise together"),
+                    code_example: Some(String::from(
+                        "monitor.query_history(vm_id, HistoryMetric::IoOperationRate, TimeRange { start_ms, end_ms }, 10_000)",
+                    )),
+                    expected_output: Some(String::from("I/O operations and VM exits increase in the same buckets")),
+                    verification_commands: vec![String::from("hypervisor stats --vm 1 --metric io --resolution 10s")],
+                    troubleshooting_tips: vec![
+                        String::from("Check that device I/O counters are reported with record_vm_io"),
+                    ],
+                },
+                TutorialStep {
+                    step_number: 4,
+                    title: String::from("Diagnose an Injected Fault"),
+                    description: String::from("Let the instructor inject an unknown fault and find it from the monitoring data alone"),
+                    code_example: Some(String::from(
+                        "injector.schedule(vm_id, FaultKind::EptViolationStorm { base_address: 0x100000, violations_per_tick: 500 }, FaultSchedule::Window { at_ms: 30_000, duration_ms: 20_000 })",
+                    )),
+                    expected_output: Some(String::from("Page fault rate spikes for 20 seconds while CPU utilization stays flat")),
+                    verification_commands: vec![String::from("hypervisor stats --vm 1 --metric faults --resolution 10s")],
+                    troubleshooting_tips: vec![
+                        String::from("Compare page fault, exit and I/O rates over the same range"),
+                        String::from("A rising hypervisor overhead with a steady exit rate points to exit latency"),
+                    ],
+                },
+            ],
+            resources: vec![
+                TutorialResource {
+                    title: String::from("Monitoring and Stats History"),
+                    resource_type: ResourceType::Documentation,
+                    url: Some(String::from("/docs/HYPERVISOR_IMPLEMENTATION.md")),
+                    description: String::from("How VM statistics are sampled, downsampled and retained"),
+                },
+            ],
+        };
+        
+        self.tutorials.push(tutorial);
+        Ok(())
+    }
+    
     /// Create teaching lab example
     fn create_teaching_lab_example(&mut self) -> Result<(), HypervisorError> {
         let mut vm_configs = Vec::new();
@@ -573,6 +797,8 @@ impl EducationalManager {
                 vcpu_count: 1,
                 memory_mb: 1024,
                 arch: VmArchitecture::X86_64,
+                cpu_model: CpuModel::edu_baseline(),
+                numa: None,
                 boot: BootConfig::default(),
                 devices: DeviceConfig::educational(),
                 features: VmFeatures::EDUCATIONAL | VmFeatures::SNAPSHOT_SUPPORT,
@@ -613,6 +839,20 @@ impl EducationalManager {
                         String::from("Check network isolation settings"),
                     ],
                 },
+                TutorialStep {
+                    step_number: 2,
+                    title: String::from("Grade Lab Submissions"),
+                    description: String::from("Collect evidence from every student VM, grade it against the lab rubric and export the reports"),
+                    code_example: Some(String::from(
+                        "let reports = assessment.grade_teaching_lab(&hypervisor, &submissions)?; assessment.export_csv()",
+                    )),
+                    expected_output: Some(String::from("One graded report per student with a score for each checkpoint")),
+                    verification_commands: vec![String::from("hypervisor list --filter students")],
+                    troubleshooting_tips: vec![
+                        String::from("Report snapshot restores as the snapshot_restored lab event"),
+                        String::from("Attach console excerpts to the submission before grading"),
+                    ],
+                },
             ],
             resources: vec![
                 TutorialResource {
@@ -650,14 +890,14 @@ impl EducationalManager {
             info!("Started tutorial: {:?}", id);
             Ok(())
         } else {
-            Err(HypervisorError::ConfigurationError(String::from("Tutorial not found")))
+            Err(ConfigError::new("tutorial", ConfigErrorReason::NotFound).into())
         }
     }
     
     /// Complete a tutorial
     pub fn complete_tutorial(&mut self, id: EducationalExample) -> Result<(), HypervisorError> {
         if let Some(index) = self.completed_tutorials.iter().position(|&t| t == id) {
-            return Err(HypervisorError::ConfigurationError(String::from("Tutorial already completed")));
+            return Err(ConfigError::new("tutorial", ConfigErrorReason::AlreadyExists).into());
         }
         
         self.completed_tutorials.push(id);
@@ -665,6 +905,11 @@ impl EducationalManager {
         Ok(())
     }
     
+    /// Whether a tutorial has been completed
+    pub fn is_completed(&self, id: EducationalExample) -> bool {
+        self.completed_tutorials.contains(&id)
+    }
+    
     /// Get current tutorial
     pub fn get_current_tutorial(&self) -> Option<EducationalExample> {
         self.current_tutorial
@@ -0,0 +1,611 @@
+//! Interactive Tutorial Runner
+//!
+//! Executes tutorial steps against a live hypervisor. When a step's code
+//! example is a `hypervisor` CLI command it is run as the step's action; the
+//! step's verification commands are run next and their combined output is
+//! checked with the step's matchers. Commands address VMs as `--vm N`, where
+//! N is the N-th VM the runner created from the tutorial's configurations.
+//!
+//! Learner progress is kept in a [`ProgressStore`] so it survives beyond the
+//! in-memory completion list of [`EducationalManager`].
+
+use crate::{VmId, HypervisorError, ConfigError, ConfigErrorReason, ErrorResponse};
+use crate::core::{Hypervisor, vm_config::VmArchitecture};
+use super::{EducationalExample, EducationalManager, EducationalTutorial, TutorialStep};
+use super::{BootTracer, TraceGranularity};
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Hypervisor CLI commands the runner can execute
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HypervisorCommand {
+    List,
+    Status { vm: u32 },
+    Stats { vm: u32 },
+    Start { vm: u32 },
+    Stop { vm: u32, force: bool },
+    Pause { vm: u32 },
+    Resume { vm: u32 },
+    Delete { vm: u32 },
+    /// Trace the VM's boot code through the x86 mode transitions
+    TraceBoot { vm: u32, blocks: bool },
+}
+
+/// Instructions a boot trace may execute before it is cut off
+const TRACE_BOOT_INSTRUCTION_LIMIT: u64 = 100_000;
+
+impl HypervisorCommand {
+    /// Parse a `hypervisor <verb> [--vm N] [--force] [--blocks]` command line.
+    ///
+    /// Flags the runner does not understand are ignored; anything that is not
+    /// a supported hypervisor command yields `None`.
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut tokens = line.split_whitespace();
+        if tokens.next()? != "hypervisor" {
+            return None;
+        }
+        let verb = tokens.next()?;
+
+        let mut vm = None;
+        let mut force = false;
+        let mut blocks = false;
+        while let Some(token) = tokens.next() {
+            match token {
+                "--vm" => vm = tokens.next().and_then(|id| id.parse().ok()),
+                "--force" => force = true,
+                "--blocks" => blocks = true,
+                _ => {}
+            }
+        }
+
+        match verb {
+            "list" => Some(HypervisorCommand::List),
+            "status" => vm.map(|vm| HypervisorCommand::Status { vm }),
+            "stats" => vm.map(|vm| HypervisorCommand::Stats { vm }),
+            "start" => vm.map(|vm| HypervisorCommand::Start { vm }),
+            "stop" => vm.map(|vm| HypervisorCommand::Stop { vm, force }),
+            "pause" => vm.map(|vm| HypervisorCommand::Pause { vm }),
+            "resume" => vm.map(|vm| HypervisorCommand::Resume { vm }),
+            "delete" => vm.map(|vm| HypervisorCommand::Delete { vm }),
+            "trace-boot" => vm.map(|vm| HypervisorCommand::TraceBoot { vm, blocks }),
+            _ => None,
+        }
+    }
+}
+
+/// Check applied to command output
+#[derive(Debug, Clone, PartialEq)]
+pub enum OutputMatcher {
+    /// The commands ran without error
+    Succeeds,
+    /// Output contains the text, ignoring ASCII case
+    Contains(String),
+    /// Output equals the text after trimming surrounding whitespace
+    Equals(String),
+    /// Output contains every fragment, ignoring ASCII case
+    ContainsAll(Vec<String>),
+    /// The inner matcher must not match
+    Not(Box<OutputMatcher>),
+}
+
+impl OutputMatcher {
+    /// Derive a matcher from a step's `expected_output`.
+    ///
+    /// `contains:`, `equals:` and `not-contains:` prefixes select a matcher;
+    /// plain prose is shown to the learner and only requires the commands to
+    /// succeed.
+    pub fn from_expected(expected: &str) -> Self {
+        if let Some(text) = expected.strip_prefix("contains:") {
+            OutputMatcher::Contains(text.trim().to_string())
+        } else if let Some(text) = expected.strip_prefix("equals:") {
+            OutputMatcher::Equals(text.trim().to_string())
+        } else if let Some(text) = expected.strip_prefix("not-contains:") {
+            OutputMatcher::Not(Box::new(OutputMatcher::Contains(text.trim().to_string())))
+        } else {
+            OutputMatcher::Succeeds
+        }
+    }
+
+    /// Whether the output satisfies the matcher
+    pub fn matches(&self, output: &str) -> bool {
+        match self {
+            OutputMatcher::Succeeds => true,
+            OutputMatcher::Contains(text) => contains_ignore_case(output, text),
+            OutputMatcher::Equals(text) => output.trim() == text.trim(),
+            OutputMatcher::ContainsAll(fragments) => fragments.iter().all(|text| contains_ignore_case(output, text)),
+            OutputMatcher::Not(inner) => !inner.matches(output),
+        }
+    }
+
+    /// Human-readable description for failure messages
+    pub fn describe(&self) -> String {
+        match self {
+            OutputMatcher::Succeeds => String::from("commands succeed"),
+            OutputMatcher::Contains(text) => format!("output contains \"{}\"", text),
+            OutputMatcher::Equals(text) => format!("output equals \"{}\"", text),
+            OutputMatcher::ContainsAll(fragments) => format!("output contains all of {:?}", fragments),
+            OutputMatcher::Not(inner) => format!("not ({})", inner.describe()),
+        }
+    }
+}
+
+/// Outcome of a single command
+#[derive(Debug, Clone, PartialEq)]
+pub enum CommandOutcome {
+    Succeeded,
+    Failed(HypervisorError),
+    /// Not a hypervisor command; left for the learner to run by hand
+    Unsupported,
+}
+
+/// Output of a single command
+#[derive(Debug, Clone)]
+pub struct CommandResult {
+    pub command: String,
+    pub outcome: CommandOutcome,
+    pub output: String,
+    /// Exit status of the command, 0 on success
+    pub exit_code: u8,
+}
+
+/// Step status as tracked in learner progress
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepStatus {
+    NotRun,
+    Passed,
+    Failed,
+    /// Nothing in the step could be executed automatically
+    Skipped,
+}
+
+impl StepStatus {
+    fn name(&self) -> &'static str {
+        match self {
+            StepStatus::NotRun => "NotRun",
+            StepStatus::Passed => "Passed",
+            StepStatus::Failed => "Failed",
+            StepStatus::Skipped => "Skipped",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "NotRun" => Some(StepStatus::NotRun),
+            "Passed" => Some(StepStatus::Passed),
+            "Failed" => Some(StepStatus::Failed),
+            "Skipped" => Some(StepStatus::Skipped),
+            _ => None,
+        }
+    }
+}
+
+/// Result of running one tutorial step
+#[derive(Debug, Clone)]
+pub struct StepResult {
+    pub step_number: usize,
+    pub status: StepStatus,
+    pub commands: Vec<CommandResult>,
+    /// Matchers that did not match the step output
+    pub failed_checks: Vec<String>,
+}
+
+/// Progress of one step
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StepProgress {
+    pub status: StepStatus,
+    pub attempts: u32,
+}
+
+/// Persisted progress of one learner
+#[derive(Debug, Clone, PartialEq)]
+pub struct LearnerProgress {
+    pub learner_id: String,
+    pub steps: BTreeMap<(EducationalExample, usize), StepProgress>,
+    pub completed: Vec<EducationalExample>,
+}
+
+impl LearnerProgress {
+    /// Empty progress for a learner
+    pub fn new(learner_id: &str) -> Self {
+        LearnerProgress {
+            learner_id: learner_id.to_string(),
+            steps: BTreeMap::new(),
+            completed: Vec::new(),
+        }
+    }
+
+    /// Status of a step, `NotRun` if never attempted
+    pub fn step_status(&self, example: EducationalExample, step_number: usize) -> StepStatus {
+        self.steps
+            .get(&(example, step_number))
+            .map_or(StepStatus::NotRun, |progress| progress.status)
+    }
+
+    /// Encode as tab-separated records, one per line
+    pub fn encode(&self) -> String {
+        let mut record = format!("learner\t{}\n", self.learner_id);
+        for ((example, step_number), progress) in &self.steps {
+            record.push_str(&format!(
+                "step\t{}\t{}\t{}\t{}\n",
+                example.name(),
+                step_number,
+                progress.status.name(),
+                progress.attempts
+            ));
+        }
+        for example in &self.completed {
+            record.push_str(&format!("completed\t{}\n", example.name()));
+        }
+        record
+    }
+
+    /// Decode a record produced by [`LearnerProgress::encode`]
+    pub fn decode(record: &str) -> Result<Self, HypervisorError> {
+        let invalid = || HypervisorError::from(ConfigError::new("progress_record", ConfigErrorReason::Malformed));
+        let mut progress: Option<LearnerProgress> = None;
+
+        for line in record.lines().filter(|line| !line.is_empty()) {
+            let fields: Vec<&str> = line.split('\t').collect();
+            match fields.as_slice() {
+                ["learner", learner_id] => progress = Some(LearnerProgress::new(learner_id)),
+                ["step", example, step_number, status, attempts] => {
+                    let progress = progress.as_mut().ok_or_else(invalid)?;
+                    let example = EducationalExample::from_name(example).ok_or_else(invalid)?;
+                    let step_number = step_number.parse().map_err(|_| invalid())?;
+                    progress.steps.insert(
+                        (example, step_number),
+                        StepProgress {
+                            status: StepStatus::from_name(status).ok_or_else(invalid)?,
+                            attempts: attempts.parse().map_err(|_| invalid())?,
+                        },
+                    );
+                }
+                ["completed", example] => {
+                    let progress = progress.as_mut().ok_or_else(invalid)?;
+                    progress.completed.push(EducationalExample::from_name(example).ok_or_else(invalid)?);
+                }
+                _ => return Err(invalid()),
+            }
+        }
+
+        progress.ok_or_else(invalid)
+    }
+}
+
+/// Storage for learner progress
+pub trait ProgressStore {
+    fn load(&self, learner_id: &str) -> Result<Option<LearnerProgress>, HypervisorError>;
+    fn save(&mut self, progress: &LearnerProgress) -> Result<(), HypervisorError>;
+}
+
+/// Progress store keeping encoded records in memory, e.g. for tests or a
+/// host that flushes [`MemoryProgressStore::records`] to disk itself
+#[derive(Debug, Default)]
+pub struct MemoryProgressStore {
+    records: BTreeMap<String, String>,
+}
+
+impl MemoryProgressStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Encoded records by learner
+    pub fn records(&self) -> &BTreeMap<String, String> {
+        &self.records
+    }
+}
+
+impl ProgressStore for MemoryProgressStore {
+    fn load(&self, learner_id: &str) -> Result<Option<LearnerProgress>, HypervisorError> {
+        self.records.get(learner_id).map(|record| LearnerProgress::decode(record)).transpose()
+    }
+
+    fn save(&mut self, progress: &LearnerProgress) -> Result<(), HypervisorError> {
+        self.records.insert(progress.learner_id.clone(), progress.encode());
+        Ok(())
+    }
+}
+
+/// Tutorial being run
+#[derive(Debug)]
+struct TutorialSession {
+    example: EducationalExample,
+    /// VMs created for the tutorial; `--vm N` refers to `vms[N - 1]`
+    vms: Vec<VmId>,
+}
+
+/// Runs tutorials step by step against a hypervisor
+pub struct TutorialRunner<S: ProgressStore> {
+    store: S,
+    progress: LearnerProgress,
+    matchers: BTreeMap<(EducationalExample, usize), Vec<OutputMatcher>>,
+    session: Option<TutorialSession>,
+}
+
+impl<S: ProgressStore> TutorialRunner<S> {
+    /// Create a runner for a learner, loading any saved progress
+    pub fn new(learner_id: &str, store: S) -> Result<Self, HypervisorError> {
+        let progress = store.load(learner_id)?.unwrap_or_else(|| LearnerProgress::new(learner_id));
+
+        Ok(TutorialRunner {
+            store,
+            progress,
+            matchers: BTreeMap::new(),
+            session: None,
+        })
+    }
+
+    /// Register output checks for the standard tutorials
+    pub fn with_standard_matchers(mut self) -> Self {
+        self.register_matcher(EducationalExample::SimpleBoot, 1, OutputMatcher::Contains(String::from("Simple Boot Demo")));
+        self.register_matcher(EducationalExample::SimpleBoot, 2, OutputMatcher::Contains(String::from("state=Running")));
+        self.register_matcher(EducationalExample::SimpleBoot, 3, OutputMatcher::ContainsAll(vec![
+            String::from("checkpoint a20-enabled"),
+            String::from("checkpoint gdt-loaded"),
+            String::from("checkpoint protected-mode-entered"),
+            String::from("checkpoint paging-enabled"),
+            String::from("checkpoint long-mode-entered"),
+        ]));
+        self.register_matcher(EducationalExample::SimpleBoot, 3, OutputMatcher::Not(Box::new(OutputMatcher::Contains(String::from("fault:")))));
+        self
+    }
+
+    /// Add a matcher for a step; registered matchers replace the one derived from `expected_output`
+    pub fn register_matcher(&mut self, example: EducationalExample, step_number: usize, matcher: OutputMatcher) {
+        self.matchers.entry((example, step_number)).or_default().push(matcher);
+    }
+
+    /// Learner progress
+    pub fn progress(&self) -> &LearnerProgress {
+        &self.progress
+    }
+
+    /// Copy persisted completions into an educational manager
+    pub fn restore_into(&self, manager: &mut EducationalManager) -> Result<(), HypervisorError> {
+        for &example in &self.progress.completed {
+            if !manager.is_completed(example) {
+                manager.complete_tutorial(example)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Start a tutorial, creating its VMs
+    pub fn start(&mut self, hypervisor: &mut Hypervisor, tutorial: &EducationalTutorial) -> Result<(), HypervisorError> {
+        if self.session.is_some() {
+            return Err(ConfigError::new("tutorial_session", ConfigErrorReason::AlreadyExists).into());
+        }
+
+        let mut vms = Vec::new();
+        for config in &tutorial.vm_configs {
+            match hypervisor.create_vm(config.clone()) {
+                Ok(vm_id) => vms.push(vm_id),
+                Err(error) => {
+                    Self::destroy_vms(hypervisor, &vms);
+                    return Err(error);
+                }
+            }
+        }
+
+        info!("Started tutorial {:?} for {} with {} VMs", tutorial.id, self.progress.learner_id, vms.len());
+        self.session = Some(TutorialSession { example: tutorial.id, vms });
+        Ok(())
+    }
+
+    /// Run one step and record its result
+    pub fn run_step(&mut self, hypervisor: &mut Hypervisor, tutorial: &EducationalTutorial, step_number: usize) -> Result<StepResult, HypervisorError> {
+        let session = self
+            .session
+            .as_ref()
+            .filter(|session| session.example == tutorial.id)
+            .ok_or_else(|| HypervisorError::from(ConfigError::new("tutorial_session", ConfigErrorReason::NotFound)))?;
+        let step = tutorial
+            .steps
+            .iter()
+            .find(|step| step.step_number == step_number)
+            .ok_or(HypervisorError::InvalidParameter)?;
+
+        let mut commands = Vec::new();
+        if let Some(action) = step.code_example.as_deref().filter(|code| HypervisorCommand::parse(code).is_some()) {
+            commands.push(Self::run_command(hypervisor, &session.vms, action));
+        }
+        for command in &step.verification_commands {
+            commands.push(Self::run_command(hypervisor, &session.vms, command));
+        }
+
+        let result = self.evaluate(tutorial.id, step, commands);
+
+        let progress = self.progress.steps.entry((tutorial.id, step_number)).or_insert(StepProgress {
+            status: StepStatus::NotRun,
+            attempts: 0,
+        });
+        progress.status = result.status;
+        progress.attempts += 1;
+        self.store.save(&self.progress)?;
+
+        Ok(result)
+    }
+
+    /// Run every step in order, stopping at the first failure
+    pub fn run_all(&mut self, hypervisor: &mut Hypervisor, tutorial: &EducationalTutorial) -> Result<Vec<StepResult>, HypervisorError> {
+        let mut results = Vec::new();
+        for step in &tutorial.steps {
+            let result = self.run_step(hypervisor, tutorial, step.step_number)?;
+            let failed = result.status == StepStatus::Failed;
+            results.push(result);
+            if failed {
+                break;
+            }
+        }
+        Ok(results)
+    }
+
+    /// Finish the running tutorial and tear down its VMs.
+    ///
+    /// The tutorial counts as completed when no step failed or was left
+    /// unrun; returns whether it was completed.
+    pub fn finish(&mut self, hypervisor: &mut Hypervisor, tutorial: &EducationalTutorial, manager: &mut EducationalManager) -> Result<bool, HypervisorError> {
+        let session = self
+            .session
+            .take()
+            .ok_or_else(|| HypervisorError::from(ConfigError::new("tutorial_session", ConfigErrorReason::NotFound)))?;
+        Self::destroy_vms(hypervisor, &session.vms);
+
+        let completed = tutorial.steps.iter().all(|step| {
+            matches!(
+                self.progress.step_status(tutorial.id, step.step_number),
+                StepStatus::Passed | StepStatus::Skipped
+            )
+        });
+
+        if completed && !self.progress.completed.contains(&tutorial.id) {
+            self.progress.completed.push(tutorial.id);
+        }
+        if completed && !manager.is_completed(tutorial.id) {
+            manager.complete_tutorial(tutorial.id)?;
+        }
+        self.store.save(&self.progress)?;
+
+        Ok(completed)
+    }
+
+    /// Apply the step's matchers to the output of its commands
+    fn evaluate(&self, example: EducationalExample, step: &TutorialStep, commands: Vec<CommandResult>) -> StepResult {
+        let executed: Vec<&CommandResult> = commands
+            .iter()
+            .filter(|result| result.outcome != CommandOutcome::Unsupported)
+            .collect();
+
+        if executed.is_empty() {
+            return StepResult {
+                step_number: step.step_number,
+                status: StepStatus::Skipped,
+                commands,
+                failed_checks: Vec::new(),
+            };
+        }
+
+        let mut failed_checks: Vec<String> = executed
+            .iter()
+            .filter_map(|result| match &result.outcome {
+                CommandOutcome::Failed(_) => Some(format!("{} exited with {}: {}", result.command, result.exit_code, result.output)),
+                _ => None,
+            })
+            .collect();
+
+        let output: String = executed.iter().map(|result| result.output.as_str()).collect::<Vec<_>>().join("\n");
+        let derived;
+        let matchers = match self.matchers.get(&(example, step.step_number)) {
+            Some(matchers) => matchers.as_slice(),
+            None => {
+                derived = vec![step.expected_output.as_deref().map_or(OutputMatcher::Succeeds, OutputMatcher::from_expected)];
+                derived.as_slice()
+            }
+        };
+        failed_checks.extend(
+            matchers
+                .iter()
+                .filter(|matcher| !matcher.matches(&output))
+                .map(|matcher| format!("expected {}", matcher.describe())),
+        );
+
+        StepResult {
+            step_number: step.step_number,
+            status: if failed_checks.is_empty() { StepStatus::Passed } else { StepStatus::Failed },
+            commands,
+            failed_checks,
+        }
+    }
+
+    /// Execute one command line against the hypervisor
+    fn run_command(hypervisor: &mut Hypervisor, vms: &[VmId], line: &str) -> CommandResult {
+        let Some(command) = HypervisorCommand::parse(line) else {
+            return CommandResult {
+                command: line.to_string(),
+                outcome: CommandOutcome::Unsupported,
+                output: String::new(),
+                exit_code: 0,
+            };
+        };
+
+        // `--vm N` names the N-th tutorial VM, falling back to the raw ID
+        let resolve = |vm: u32| vms.get((vm as usize).wrapping_sub(1)).copied().unwrap_or(VmId(vm));
+
+        let output = match command {
+            HypervisorCommand::List => hypervisor.list_vms().map(|vms| {
+                vms.iter()
+                    .map(|info| format!("VM {}: {} state={:?} vcpus={} memory={}MB", info.id.0, info.name, info.state, info.vcpu_count, info.memory_mb))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            }),
+            HypervisorCommand::Status { vm } => hypervisor.get_vm_info(resolve(vm)).map(|info| {
+                format!("VM {}: {} state={:?} uptime={}ms", info.id.0, info.name, info.state, info.uptime_ms)
+            }),
+            HypervisorCommand::Stats { vm } => hypervisor.get_vm_stats(resolve(vm)).map(|stats| {
+                format!(
+                    "vcpus={} vm_exits={} instructions={} memory_used={}MB page_faults={}",
+                    stats.vcpu_stats.len(),
+                    stats.vcpu_stats.iter().map(|cpu| cpu.vm_exit_count).sum::<u64>(),
+                    stats.vcpu_stats.iter().map(|cpu| cpu.instruction_count).sum::<u64>(),
+                    stats.memory_stats.used_mb,
+                    stats.memory_stats.page_faults
+                )
+            }),
+            HypervisorCommand::Start { vm } => hypervisor.start_vm(resolve(vm)).map(|_| format!("VM {} started", vm)),
+            HypervisorCommand::Stop { vm, force } => hypervisor.stop_vm(resolve(vm), force).map(|_| format!("VM {} stopped", vm)),
+            HypervisorCommand::Pause { vm } => hypervisor.pause_vm(resolve(vm)).map(|_| format!("VM {} paused", vm)),
+            HypervisorCommand::Resume { vm } => hypervisor.resume_vm(resolve(vm)).map(|_| format!("VM {} resumed", vm)),
+            HypervisorCommand::Delete { vm } => hypervisor.delete_vm(resolve(vm)).map(|_| format!("VM {} deleted", vm)),
+            HypervisorCommand::TraceBoot { vm, blocks } => Self::trace_boot(hypervisor, resolve(vm), blocks),
+        };
+
+        match output {
+            Ok(output) => CommandResult {
+                command: line.to_string(),
+                outcome: CommandOutcome::Succeeded,
+                output,
+                exit_code: 0,
+            },
+            Err(error) => CommandResult {
+                command: line.to_string(),
+                output: ErrorResponse::from(&error).to_string(),
+                exit_code: error.exit_code(),
+                outcome: CommandOutcome::Failed(error),
+            },
+        }
+    }
+
+    /// Run the boot trace of an x86 VM and render its checkpoints
+    fn trace_boot(hypervisor: &Hypervisor, vm_id: VmId, blocks: bool) -> Result<String, HypervisorError> {
+        let config = hypervisor.get_vm_config(vm_id)?;
+        if !matches!(config.arch, VmArchitecture::X86_64 | VmArchitecture::AMD64) {
+            return Err(HypervisorError::FeatureNotSupported);
+        }
+
+        let granularity = if blocks { TraceGranularity::BasicBlock } else { TraceGranularity::Instruction };
+        let mut tracer = BootTracer::default();
+        let steps = tracer.run(granularity, TRACE_BOOT_INSTRUCTION_LIMIT)?;
+        info!("Traced {} boot instructions of VM {}", steps.len(), vm_id.0);
+        Ok(tracer.render())
+    }
+
+    /// Stop and delete tutorial VMs, ignoring VMs the learner already removed
+    fn destroy_vms(hypervisor: &mut Hypervisor, vms: &[VmId]) {
+        for &vm_id in vms {
+            let _ = hypervisor.stop_vm(vm_id, true);
+            if let Err(error) = hypervisor.delete_vm(vm_id) {
+                warn!("Failed to delete tutorial VM {}: {:?}", vm_id.0, error);
+            }
+        }
+    }
+}
+
+fn contains_ignore_case(haystack: &str, needle: &str) -> bool {
+    haystack.to_ascii_lowercase().contains(&needle.to_ascii_lowercase())
+}
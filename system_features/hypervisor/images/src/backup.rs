@@ -0,0 +1,617 @@
+//! Block-Level Disk Backups
+//!
+//! A full backup stores every non-zero block of a virtual disk; an
+//! incremental one only the blocks the changed block tracker saw written
+//! since the previous backup of the chain. Each backup consists of a data
+//! stream of checksummed block records and a text manifest. In a backup
+//! directory they are two files per backup; on a single stream the manifest
+//! follows the records. Restores replay a chain onto a raw image and read
+//! every block back to verify it against the manifests.
+
+use crate::{HypervisorError, VmId, ConfigError, ConfigErrorReason, IoError, IoErrorKind};
+use crate::devices::crc32;
+use super::{BlockBitmap, ChangedBlockTracker, DiskImage, ImageFile, ImageOpener, TrackingCheckpoint, VirtualDiskId};
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+const MANIFEST_MAGIC: &str = "multios-backup 1";
+const DATA_MAGIC: [u8; 8] = *b"MOSBKUP1";
+/// Magic, block size and backup kind
+const DATA_HEADER_LEN: usize = 17;
+/// Block index, record kind, length and CRC
+const RECORD_HEADER_LEN: usize = 17;
+
+/// Largest block size accepted from a backup stream
+const MAX_BLOCK_SIZE: u64 = 16 * 1024 * 1024;
+
+const RECORD_END: u8 = 0;
+const RECORD_DATA: u8 = 1;
+const RECORD_ZERO: u8 = 2;
+
+fn corrupt(source: &'static str) -> HypervisorError {
+    IoError::new(source, IoErrorKind::Corrupt).into()
+}
+
+fn corrupt_block(source: &'static str, block: u64) -> HypervisorError {
+    IoError::new(source, IoErrorKind::Corrupt).at(block).into()
+}
+
+/// Whether a backup stands alone or builds on the previous one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackupKind {
+    Full,
+    Incremental,
+}
+
+impl BackupKind {
+    fn name(&self) -> &'static str {
+        match self {
+            BackupKind::Full => "full",
+            BackupKind::Incremental => "incremental",
+        }
+    }
+
+    fn code(&self) -> u8 {
+        match self {
+            BackupKind::Full => 0,
+            BackupKind::Incremental => 1,
+        }
+    }
+}
+
+/// Content of a block stored in a backup
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockContent {
+    /// Data with its CRC-32
+    Data { crc: u32 },
+    /// The block was zeroed
+    Zero,
+}
+
+/// Description of one backup
+#[derive(Debug, Clone, PartialEq)]
+pub struct BackupManifest {
+    pub disk: VirtualDiskId,
+    pub kind: BackupKind,
+    /// Position in the chain; the full backup is 0
+    pub sequence: u32,
+    pub virtual_size: u64,
+    pub block_size: u64,
+    /// Tracking checkpoint the stored changes are relative to
+    pub base: TrackingCheckpoint,
+    /// Tracking checkpoint collecting the writes made after this backup
+    pub tracking: TrackingCheckpoint,
+    pub created_at: u64,
+    /// Stored blocks, ascending; a full backup leaves out zero blocks
+    pub blocks: Vec<(u64, BlockContent)>,
+}
+
+impl BackupManifest {
+    /// Whether this backup directly follows `previous` in a chain
+    pub fn continues(&self, previous: &BackupManifest) -> bool {
+        self.kind == BackupKind::Incremental
+            && self.disk == previous.disk
+            && self.sequence == previous.sequence + 1
+            && self.base == previous.tracking
+    }
+
+    /// Bytes of block data stored
+    pub fn data_bytes(&self) -> u64 {
+        let blocks = self.blocks.iter().filter(|(_, content)| *content != BlockContent::Zero).count() as u64;
+        blocks * self.block_size
+    }
+
+    /// Text form, ending with a checksum line
+    pub fn encode(&self) -> String {
+        let mut text = String::new();
+        let _ = writeln!(text, "{}", MANIFEST_MAGIC);
+        let _ = writeln!(text, "vm {}", self.disk.vm_id.0);
+        let _ = writeln!(text, "disk {}", self.disk.index);
+        let _ = writeln!(text, "kind {}", self.kind.name());
+        let _ = writeln!(text, "sequence {}", self.sequence);
+        let _ = writeln!(text, "virtual_size {}", self.virtual_size);
+        let _ = writeln!(text, "block_size {}", self.block_size);
+        let _ = writeln!(text, "base {} {}", self.base.epoch, self.base.checkpoint);
+        let _ = writeln!(text, "tracking {} {}", self.tracking.epoch, self.tracking.checkpoint);
+        let _ = writeln!(text, "created_at {}", self.created_at);
+        for (block, content) in &self.blocks {
+            let _ = match content {
+                BlockContent::Data { crc } => writeln!(text, "block {} data {:08x}", block, crc),
+                BlockContent::Zero => writeln!(text, "block {} zero", block),
+            };
+        }
+        let crc = crc32(text.as_bytes());
+        let _ = writeln!(text, "end {:08x}", crc);
+        text
+    }
+
+    /// Parse and checksum-verify the text form
+    pub fn parse(text: &str) -> Result<Self, HypervisorError> {
+        let end = text.rfind("end ").ok_or_else(|| corrupt("backup.manifest"))?;
+        let expected = u32::from_str_radix(text[end + 4..].trim(), 16)
+            .map_err(|_| corrupt("backup.manifest"))?;
+        if crc32(text[..end].as_bytes()) != expected {
+            return Err(corrupt("backup.manifest"));
+        }
+
+        let mut lines = text[..end].lines();
+        if lines.next() != Some(MANIFEST_MAGIC) {
+            return Err(corrupt("backup.manifest"));
+        }
+        let mut fields: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+        let mut blocks = Vec::new();
+        for line in lines {
+            let mut words = line.split_whitespace();
+            let Some(key) = words.next() else { continue };
+            let values: Vec<&str> = words.collect();
+            if key == "block" {
+                let block = values.first().and_then(|value| value.parse().ok())
+                    .ok_or_else(|| corrupt("backup.manifest"))?;
+                let content = match values.get(1..) {
+                    Some(["zero"]) => BlockContent::Zero,
+                    Some(["data", crc]) => BlockContent::Data {
+                        crc: u32::from_str_radix(crc, 16).map_err(|_| corrupt("backup.manifest"))?,
+                    },
+                    _ => return Err(corrupt("backup.manifest")),
+                };
+                blocks.push((block, content));
+            } else {
+                fields.insert(key, values);
+            }
+        }
+
+        let number = |key: &str, index: usize| -> Result<u64, HypervisorError> {
+            fields.get(key).and_then(|values| values.get(index)).and_then(|value| value.parse().ok())
+                .ok_or_else(|| corrupt("backup.manifest"))
+        };
+        let kind = match fields.get("kind").and_then(|values| values.first()) {
+            Some(&"full") => BackupKind::Full,
+            Some(&"incremental") => BackupKind::Incremental,
+            _ => return Err(corrupt("backup.manifest")),
+        };
+
+        Ok(BackupManifest {
+            disk: VirtualDiskId { vm_id: VmId(number("vm", 0)? as u32), index: number("disk", 0)? as u32 },
+            kind,
+            sequence: number("sequence", 0)? as u32,
+            virtual_size: number("virtual_size", 0)?,
+            block_size: number("block_size", 0)?,
+            base: TrackingCheckpoint { epoch: number("base", 0)?, checkpoint: number("base", 1)? },
+            tracking: TrackingCheckpoint { epoch: number("tracking", 0)?, checkpoint: number("tracking", 1)? },
+            created_at: number("created_at", 0)?,
+            blocks,
+        })
+    }
+}
+
+/// Sequential sink a backup is written to
+pub trait BackupStream {
+    fn write(&mut self, data: &[u8]) -> Result<(), HypervisorError>;
+}
+
+/// Sequential source a backup is restored from
+pub trait BackupSource {
+    /// Fill `buf` completely
+    fn read(&mut self, buf: &mut [u8]) -> Result<(), HypervisorError>;
+}
+
+/// Sequential access to a file in a backup directory
+pub struct FileStream {
+    file: Box<dyn ImageFile>,
+    offset: u64,
+}
+
+impl FileStream {
+    pub fn new(file: Box<dyn ImageFile>) -> Self {
+        FileStream { file, offset: 0 }
+    }
+}
+
+impl BackupStream for FileStream {
+    fn write(&mut self, data: &[u8]) -> Result<(), HypervisorError> {
+        self.file.write_at(self.offset, data)?;
+        self.offset += data.len() as u64;
+        Ok(())
+    }
+}
+
+impl BackupSource for FileStream {
+    fn read(&mut self, buf: &mut [u8]) -> Result<(), HypervisorError> {
+        if self.offset + buf.len() as u64 > self.file.len() {
+            return Err(corrupt("backup.data"));
+        }
+        self.file.read_at(self.offset, buf)?;
+        self.offset += buf.len() as u64;
+        Ok(())
+    }
+}
+
+/// Where a backup goes
+pub enum BackupTarget<'a> {
+    /// A data file and a manifest file per backup in this directory
+    Directory(&'a str),
+    /// Records followed by the manifest on one stream
+    Stream(&'a mut dyn BackupStream),
+}
+
+/// Path of a backup file: `<directory>/vm<id>-disk<index>.<sequence>.<extension>`
+pub fn backup_file_path(directory: &str, disk: VirtualDiskId, sequence: u32, extension: &str) -> String {
+    format!("{}/vm{}-disk{}.{}.{}", directory.trim_end_matches('/'), disk.vm_id.0, disk.index, sequence, extension)
+}
+
+fn write_record(stream: &mut dyn BackupStream, block: u64, kind: u8, data: &[u8]) -> Result<(), HypervisorError> {
+    let mut header = [0u8; RECORD_HEADER_LEN];
+    header[..8].copy_from_slice(&block.to_le_bytes());
+    header[8] = kind;
+    header[9..13].copy_from_slice(&(data.len() as u32).to_le_bytes());
+    header[13..].copy_from_slice(&crc32(data).to_le_bytes());
+    stream.write(&header)?;
+    stream.write(data)
+}
+
+/// Write the data stream of a backup, filling in the manifest's blocks
+fn export_blocks(
+    image: &mut DiskImage,
+    manifest: &mut BackupManifest,
+    blocks: impl Iterator<Item = u64>,
+    stream: &mut dyn BackupStream,
+) -> Result<(), HypervisorError> {
+    let mut header = [0u8; DATA_HEADER_LEN];
+    header[..8].copy_from_slice(&DATA_MAGIC);
+    header[8..16].copy_from_slice(&manifest.block_size.to_le_bytes());
+    header[16] = manifest.kind.code();
+    stream.write(&header)?;
+
+    let mut buf = vec![0u8; manifest.block_size as usize];
+    for block in blocks {
+        let offset = block * manifest.block_size;
+        if offset >= manifest.virtual_size {
+            break;
+        }
+        let len = manifest.block_size.min(manifest.virtual_size - offset) as usize;
+        image.read_at(offset, &mut buf[..len])?;
+
+        if buf[..len].iter().all(|&byte| byte == 0) {
+            // A fresh full restore is zero already
+            if manifest.kind == BackupKind::Incremental {
+                write_record(stream, block, RECORD_ZERO, &[])?;
+                manifest.blocks.push((block, BlockContent::Zero));
+            }
+        } else {
+            write_record(stream, block, RECORD_DATA, &buf[..len])?;
+            manifest.blocks.push((block, BlockContent::Data { crc: crc32(&buf[..len]) }));
+        }
+    }
+    write_record(stream, 0, RECORD_END, &[])
+}
+
+/// Back up one disk image. Without `previous` the backup is full and
+/// starts tracking the disk if needed; with it the backup is incremental
+/// and fails if tracking does not continue from `previous`.
+pub fn backup_disk(
+    tracker: &mut ChangedBlockTracker,
+    opener: &mut dyn ImageOpener,
+    disk: VirtualDiskId,
+    image_path: &str,
+    previous: Option<&BackupManifest>,
+    target: BackupTarget<'_>,
+    created_at: u64,
+) -> Result<BackupManifest, HypervisorError> {
+    let mut image = DiskImage::open(opener, image_path)?;
+    let virtual_size = image.virtual_size();
+
+    match previous {
+        Some(previous) if previous.disk != disk => return Err(HypervisorError::InvalidParameter),
+        Some(previous) if tracker.current(disk) != Some(previous.tracking) => {
+            // A full backup is required
+            return Err(ConfigError::new("backup.tracking", ConfigErrorReason::Mismatch)
+                .vm(disk.vm_id).value(previous.sequence as u64).into());
+        }
+        Some(_) => {}
+        None if !tracker.is_tracking(disk) => {
+            tracker.enable(disk, virtual_size);
+        }
+        None => {}
+    }
+
+    // Writes from here on belong to the next backup
+    let (base, changes) = tracker.take_changes(disk)?;
+    let mut manifest = BackupManifest {
+        disk,
+        kind: if previous.is_some() { BackupKind::Incremental } else { BackupKind::Full },
+        sequence: previous.map_or(0, |previous| previous.sequence + 1),
+        virtual_size,
+        block_size: tracker.block_size(),
+        base,
+        tracking: TrackingCheckpoint { epoch: base.epoch, checkpoint: base.checkpoint + 1 },
+        created_at,
+        blocks: Vec::new(),
+    };
+
+    let result = write_backup(opener, &mut image, &mut manifest, &changes, target);
+    if let Err(error) = result {
+        tracker.return_changes(disk, base, &changes);
+        return Err(error);
+    }
+
+    info!(
+        "{} backup {} of VM {} disk {}: {} blocks, {} bytes",
+        manifest.kind.name(), manifest.sequence, disk.vm_id.0, disk.index, manifest.blocks.len(), manifest.data_bytes(),
+    );
+    Ok(manifest)
+}
+
+fn write_backup(
+    opener: &mut dyn ImageOpener,
+    image: &mut DiskImage,
+    manifest: &mut BackupManifest,
+    changes: &BlockBitmap,
+    target: BackupTarget<'_>,
+) -> Result<(), HypervisorError> {
+    let block_count = manifest.virtual_size.div_ceil(manifest.block_size);
+    let blocks: Box<dyn Iterator<Item = u64> + '_> = match manifest.kind {
+        BackupKind::Full => Box::new(0..block_count),
+        BackupKind::Incremental => Box::new(changes.blocks()),
+    };
+
+    match target {
+        BackupTarget::Directory(directory) => {
+            let data_path = backup_file_path(directory, manifest.disk, manifest.sequence, "data");
+            let mut data = FileStream::new(opener.create(&data_path)?);
+            export_blocks(image, manifest, blocks, &mut data)?;
+
+            // The manifest is written last so a partial backup is never
+            // picked up as part of the chain
+            let text = manifest.encode();
+            let manifest_path = backup_file_path(directory, manifest.disk, manifest.sequence, "manifest");
+            opener.create(&manifest_path)?.write_at(0, text.as_bytes())
+        }
+        BackupTarget::Stream(stream) => {
+            export_blocks(image, manifest, blocks, stream)?;
+            let text = manifest.encode();
+            stream.write(&(text.len() as u32).to_le_bytes())?;
+            stream.write(text.as_bytes())
+        }
+    }
+}
+
+fn parse_manifest_file(file: &mut dyn ImageFile, path: &str) -> Result<BackupManifest, HypervisorError> {
+    let mut text = vec![0u8; file.len() as usize];
+    file.read_at(0, &mut text)?;
+    BackupManifest::parse(core::str::from_utf8(&text).map_err(|_| corrupt("backup.manifest"))?)
+}
+
+/// Read a manifest file
+pub fn read_manifest(opener: &mut dyn ImageOpener, path: &str) -> Result<BackupManifest, HypervisorError> {
+    parse_manifest_file(opener.open(path, false)?.as_mut(), path)
+}
+
+/// The current backup chain of a disk in a backup directory, full backup
+/// first. Leftovers of an older chain are ignored.
+pub fn backup_chain(
+    opener: &mut dyn ImageOpener,
+    directory: &str,
+    disk: VirtualDiskId,
+) -> Result<Vec<BackupManifest>, HypervisorError> {
+    let mut chain: Vec<BackupManifest> = Vec::new();
+    loop {
+        let path = backup_file_path(directory, disk, chain.len() as u32, "manifest");
+        // A missing file ends the chain
+        let Ok(mut file) = opener.open(&path, false) else { break };
+        let manifest = parse_manifest_file(file.as_mut(), &path)?;
+
+        let continues = match chain.last() {
+            None => manifest.kind == BackupKind::Full && manifest.disk == disk,
+            Some(previous) => manifest.continues(previous),
+        };
+        if !continues {
+            break;
+        }
+        chain.push(manifest);
+    }
+    Ok(chain)
+}
+
+/// Back up a set of disks into one directory. Each disk continues its chain
+/// incrementally when tracking allows it and starts a new full chain
+/// otherwise; a failure on one disk does not stop the others.
+pub fn backup_lab(
+    tracker: &mut ChangedBlockTracker,
+    opener: &mut dyn ImageOpener,
+    disks: &[(VirtualDiskId, &str)],
+    directory: &str,
+    created_at: u64,
+) -> Vec<(VirtualDiskId, Result<BackupManifest, HypervisorError>)> {
+    disks.iter().map(|&(disk, image_path)| {
+        let result = backup_chain(opener, directory, disk).and_then(|chain| {
+            let previous = chain.last().filter(|last| tracker.current(disk) == Some(last.tracking));
+            backup_disk(tracker, opener, disk, image_path, previous, BackupTarget::Directory(directory), created_at)
+        });
+        if let Err(error) = &result {
+            warn!("Backup of VM {} disk {} failed: {}", disk.vm_id.0, disk.index, error);
+        }
+        (disk, result)
+    }).collect()
+}
+
+/// Outcome of a verified restore
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RestoreReport {
+    pub backups_applied: u32,
+    pub blocks_written: u64,
+    /// Blocks read back and matched against the manifests
+    pub blocks_verified: u64,
+    pub virtual_size: u64,
+}
+
+/// Replays a backup chain onto a raw image
+pub struct DiskRestore<'a> {
+    destination: &'a mut dyn ImageFile,
+    last: Option<BackupManifest>,
+    /// Expected content of every block not known to be zero
+    expected: BTreeMap<u64, BlockContent>,
+    report: RestoreReport,
+}
+
+impl<'a> DiskRestore<'a> {
+    pub fn new(destination: &'a mut dyn ImageFile) -> Self {
+        DiskRestore { destination, last: None, expected: BTreeMap::new(), report: RestoreReport::default() }
+    }
+
+    /// Apply the next backup of the chain. `manifest` is the backup's
+    /// manifest file; for stream backups pass `None` and it is read from
+    /// the end of the stream.
+    pub fn apply(&mut self, source: &mut dyn BackupSource, manifest: Option<BackupManifest>) -> Result<(), HypervisorError> {
+        let mut header = [0u8; DATA_HEADER_LEN];
+        source.read(&mut header)?;
+        if header[..8] != DATA_MAGIC {
+            return Err(corrupt("backup.data"));
+        }
+        let block_size = u64::from_le_bytes(header[8..16].try_into().unwrap());
+        if !block_size.is_power_of_two() || !(512..=MAX_BLOCK_SIZE).contains(&block_size) {
+            return Err(corrupt("backup.data"));
+        }
+        let full = header[16] == BackupKind::Full.code();
+        if !full && self.last.is_none() {
+            return Err(corrupt("backup.chain"));
+        }
+        if full {
+            self.destination.set_len(0)?;
+        }
+
+        let mut records = Vec::new();
+        let mut buf = vec![0u8; block_size as usize];
+        loop {
+            let mut record = [0u8; RECORD_HEADER_LEN];
+            source.read(&mut record)?;
+            let block = u64::from_le_bytes(record[..8].try_into().unwrap());
+            let len = u32::from_le_bytes(record[9..13].try_into().unwrap()) as usize;
+            let crc = u32::from_le_bytes(record[13..].try_into().unwrap());
+
+            match record[8] {
+                RECORD_END => break,
+                RECORD_DATA if len <= buf.len() => {
+                    source.read(&mut buf[..len])?;
+                    if crc32(&buf[..len]) != crc {
+                        return Err(corrupt_block("backup.data", block));
+                    }
+                    self.destination.write_at(block * block_size, &buf[..len])?;
+                    records.push((block, BlockContent::Data { crc }));
+                }
+                RECORD_ZERO => {
+                    buf.fill(0);
+                    let offset = block * block_size;
+                    let len = block_size.min(self.destination.len().saturating_sub(offset)) as usize;
+                    self.destination.write_at(offset, &buf[..len])?;
+                    records.push((block, BlockContent::Zero));
+                }
+                _ => return Err(corrupt_block("backup.data", block)),
+            }
+            self.report.blocks_written += 1;
+        }
+
+        let manifest = match manifest {
+            Some(manifest) => manifest,
+            None => {
+                let mut len = [0u8; 4];
+                source.read(&mut len)?;
+                let mut text = vec![0u8; u32::from_le_bytes(len) as usize];
+                source.read(&mut text)?;
+                BackupManifest::parse(core::str::from_utf8(&text).map_err(|_| corrupt("backup.manifest"))?)?
+            }
+        };
+
+        let in_chain = match &self.last {
+            None => manifest.kind == BackupKind::Full,
+            Some(previous) => manifest.kind == BackupKind::Full || manifest.continues(previous),
+        };
+        if !in_chain || full != (manifest.kind == BackupKind::Full) || manifest.block_size != block_size {
+            return Err(corrupt("backup.chain"));
+        }
+        if records != manifest.blocks {
+            return Err(corrupt("backup.chain"));
+        }
+
+        if full {
+            self.expected.clear();
+        }
+        for &(block, content) in &manifest.blocks {
+            match content {
+                BlockContent::Data { .. } => self.expected.insert(block, content),
+                BlockContent::Zero => self.expected.remove(&block),
+            };
+        }
+        self.destination.set_len(manifest.virtual_size)?;
+        self.report.virtual_size = manifest.virtual_size;
+        self.report.backups_applied += 1;
+        self.last = Some(manifest);
+        Ok(())
+    }
+
+    /// Read the restored image back and check every block
+    pub fn verify(mut self) -> Result<RestoreReport, HypervisorError> {
+        let mut report = self.report;
+        let Some(last) = &self.last else {
+            return Err(HypervisorError::InvalidParameter);
+        };
+        let block_size = last.block_size;
+        let mut buf = vec![0u8; block_size as usize];
+
+        for block in 0..report.virtual_size.div_ceil(block_size) {
+            let offset = block * block_size;
+            let len = block_size.min(report.virtual_size - offset) as usize;
+            self.destination.read_at(offset, &mut buf[..len])?;
+            let matches = match self.expected.get(&block) {
+                Some(BlockContent::Data { crc }) => crc32(&buf[..len]) == *crc,
+                _ => buf[..len].iter().all(|&byte| byte == 0),
+            };
+            if !matches {
+                return Err(corrupt_block("restore", block));
+            }
+            report.blocks_verified += 1;
+        }
+        Ok(report)
+    }
+}
+
+/// Restore a disk from a backup directory into a new raw image, up to and
+/// including backup `upto` (the latest when `None`), and verify it
+pub fn restore_disk(
+    opener: &mut dyn ImageOpener,
+    directory: &str,
+    disk: VirtualDiskId,
+    upto: Option<u32>,
+    destination: &str,
+) -> Result<RestoreReport, HypervisorError> {
+    let mut chain = backup_chain(opener, directory, disk)?;
+    if let Some(upto) = upto {
+        if upto as usize >= chain.len() {
+            return Err(HypervisorError::InvalidParameter);
+        }
+        chain.truncate(upto as usize + 1);
+    }
+    if chain.is_empty() {
+        return Err(ConfigError::new("backup", ConfigErrorReason::NotFound).vm(disk.vm_id).value(disk.index as u64).into());
+    }
+
+    let mut file = opener.create(destination)?;
+    let mut restore = DiskRestore::new(file.as_mut());
+    for manifest in chain {
+        let data_path = backup_file_path(directory, disk, manifest.sequence, "data");
+        let mut source = FileStream::new(opener.open(&data_path, false)?);
+        restore.apply(&mut source, Some(manifest))?;
+    }
+    let report = restore.verify()?;
+
+    info!(
+        "Restored VM {} disk {} to {}: {} backups, {} blocks verified",
+        disk.vm_id.0, disk.index, destination, report.backups_applied, report.blocks_verified,
+    );
+    Ok(report)
+}
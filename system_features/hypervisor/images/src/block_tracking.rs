@@ -0,0 +1,194 @@
+//! Changed Block Tracking
+//!
+//! Records which blocks of each virtual disk the guest wrote since the last
+//! backup, so incremental backups copy only those blocks. Every time
+//! tracking is started a new epoch begins; a backup chain taken in an older
+//! epoch may have missed writes and cannot be continued incrementally.
+
+use crate::{HypervisorError, VmId};
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+/// Tracking granularity unless configured otherwise
+pub const DEFAULT_BLOCK_SIZE: u64 = 64 * 1024;
+
+/// A virtual disk of a VM
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct VirtualDiskId {
+    pub vm_id: VmId,
+    /// Position of the disk in the VM's storage configuration
+    pub index: u32,
+}
+
+/// Bitmap with one bit per disk block
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BlockBitmap {
+    words: Vec<u64>,
+}
+
+impl BlockBitmap {
+    pub fn new() -> Self {
+        BlockBitmap { words: Vec::new() }
+    }
+
+    pub fn mark(&mut self, block: u64) {
+        let word = (block / 64) as usize;
+        if word >= self.words.len() {
+            self.words.resize(word + 1, 0);
+        }
+        self.words[word] |= 1 << (block % 64);
+    }
+
+    pub fn contains(&self, block: u64) -> bool {
+        self.words
+            .get((block / 64) as usize)
+            .map_or(false, |word| word & (1 << (block % 64)) != 0)
+    }
+
+    /// Number of marked blocks
+    pub fn count(&self) -> u64 {
+        self.words.iter().map(|word| word.count_ones() as u64).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.iter().all(|&word| word == 0)
+    }
+
+    /// Marked blocks, ascending
+    pub fn blocks(&self) -> impl Iterator<Item = u64> + '_ {
+        self.words.iter().enumerate().flat_map(|(index, &word)| {
+            (0..64u64)
+                .filter(move |bit| word & (1 << bit) != 0)
+                .map(move |bit| index as u64 * 64 + bit)
+        })
+    }
+
+    /// Add every block marked in `other`
+    pub fn merge(&mut self, other: &BlockBitmap) {
+        if other.words.len() > self.words.len() {
+            self.words.resize(other.words.len(), 0);
+        }
+        for (word, &bits) in self.words.iter_mut().zip(&other.words) {
+            *word |= bits;
+        }
+    }
+}
+
+/// Point in a disk's tracking history that a backup was taken at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TrackingCheckpoint {
+    pub epoch: u64,
+    pub checkpoint: u64,
+}
+
+struct DiskTracking {
+    size: u64,
+    epoch: u64,
+    checkpoint: u64,
+    changed: BlockBitmap,
+}
+
+/// Changed block tracking for all virtual disks
+pub struct ChangedBlockTracker {
+    block_size: u64,
+    disks: BTreeMap<VirtualDiskId, DiskTracking>,
+    /// Seeds epochs so they differ across hypervisor restarts
+    clock: fn() -> u64,
+    last_epoch: u64,
+}
+
+impl ChangedBlockTracker {
+    /// Create a tracker; `block_size` must be a power of two of at least 512
+    pub fn new(block_size: u64, clock: fn() -> u64) -> Result<Self, HypervisorError> {
+        if block_size < 512 || !block_size.is_power_of_two() {
+            return Err(HypervisorError::InvalidParameter);
+        }
+        Ok(ChangedBlockTracker {
+            block_size,
+            disks: BTreeMap::new(),
+            clock,
+            last_epoch: 0,
+        })
+    }
+
+    pub fn block_size(&self) -> u64 {
+        self.block_size
+    }
+
+    /// Start tracking a disk of `size` bytes in a new epoch. Nothing counts
+    /// as changed until the first write, so the next backup must be full.
+    pub fn enable(&mut self, disk: VirtualDiskId, size: u64) -> TrackingCheckpoint {
+        let epoch = (self.clock)().max(self.last_epoch + 1);
+        self.last_epoch = epoch;
+        self.disks.insert(disk, DiskTracking { size, epoch, checkpoint: 0, changed: BlockBitmap::new() });
+        info!("Changed block tracking enabled for VM {} disk {}", disk.vm_id.0, disk.index);
+        TrackingCheckpoint { epoch, checkpoint: 0 }
+    }
+
+    pub fn disable(&mut self, disk: VirtualDiskId) {
+        self.disks.remove(&disk);
+    }
+
+    pub fn is_tracking(&self, disk: VirtualDiskId) -> bool {
+        self.disks.contains_key(&disk)
+    }
+
+    /// Where a disk's tracking currently stands
+    pub fn current(&self, disk: VirtualDiskId) -> Option<TrackingCheckpoint> {
+        self.disks.get(&disk).map(|state| TrackingCheckpoint { epoch: state.epoch, checkpoint: state.checkpoint })
+    }
+
+    /// Record a guest write of `len` bytes at `offset`
+    pub fn record_write(&mut self, disk: VirtualDiskId, offset: u64, len: u64) {
+        let block_size = self.block_size;
+        if let Some(state) = self.disks.get_mut(&disk) {
+            if len == 0 || offset >= state.size {
+                return;
+            }
+            let end = offset.saturating_add(len).min(state.size);
+            for block in offset / block_size..end.div_ceil(block_size) {
+                state.changed.mark(block);
+            }
+        }
+    }
+
+    /// Follow a disk resize; blocks past a shrunk end are forgotten
+    pub fn resize(&mut self, disk: VirtualDiskId, size: u64) {
+        let block_size = self.block_size;
+        if let Some(state) = self.disks.get_mut(&disk) {
+            if size < state.size {
+                let mut kept = BlockBitmap::new();
+                for block in state.changed.blocks().take_while(|&block| block * block_size < size) {
+                    kept.mark(block);
+                }
+                state.changed = kept;
+            }
+            state.size = size;
+        }
+    }
+
+    /// Blocks written since the last checkpoint
+    pub fn changed_blocks(&self, disk: VirtualDiskId) -> Option<&BlockBitmap> {
+        self.disks.get(&disk).map(|state| &state.changed)
+    }
+
+    /// Hand out the blocks changed since the last checkpoint and start a
+    /// new one. Returns the checkpoint the changes are relative to.
+    pub fn take_changes(&mut self, disk: VirtualDiskId) -> Result<(TrackingCheckpoint, BlockBitmap), HypervisorError> {
+        let state = self.disks.get_mut(&disk).ok_or(HypervisorError::InvalidParameter)?;
+        let base = TrackingCheckpoint { epoch: state.epoch, checkpoint: state.checkpoint };
+        state.checkpoint += 1;
+        Ok((base, core::mem::take(&mut state.changed)))
+    }
+
+    /// Undo `take_changes` after a failed backup so its blocks are not lost
+    pub fn return_changes(&mut self, disk: VirtualDiskId, base: TrackingCheckpoint, changes: &BlockBitmap) {
+        if let Some(state) = self.disks.get_mut(&disk) {
+            if state.epoch == base.epoch && state.checkpoint == base.checkpoint + 1 {
+                state.checkpoint = base.checkpoint;
+            }
+            state.changed.merge(changes);
+        }
+    }
+}
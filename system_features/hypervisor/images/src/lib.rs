@@ -0,0 +1,370 @@
+//! Disk Image Utilities
+//!
+//! Programmatic inspection, conversion, resizing and sparsification of raw
+//! and QCOW2 disk images, so lab administration and the CLI do not depend
+//! on an external qemu-img, plus changed block tracking and block-level
+//! backups. Host files are reached through an [`ImageOpener`] supplied by
+//! the caller.
+
+use crate::{HypervisorError, ConfigError, ConfigErrorReason};
+use crate::core::StorageDeviceType;
+use crate::memory::HostFile;
+
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+mod qcow2;
+mod block_tracking;
+mod backup;
+
+pub use qcow2::*;
+pub use block_tracking::*;
+pub use backup::*;
+
+/// Longest backing chain followed before giving up
+pub const MAX_BACKING_CHAIN: usize = 16;
+/// Granularity of raw image scans
+const RAW_CHUNK_SIZE: u64 = 64 * 1024;
+
+/// Host file holding a disk image
+pub trait ImageFile: HostFile {
+    /// Truncate or extend the file; extension reads back as zeros
+    fn set_len(&mut self, len: u64) -> Result<(), HypervisorError>;
+    /// Hand a range back to the host filesystem, e.g. by punching a hole.
+    /// Only called on ranges that no longer hold data; the default keeps
+    /// the space allocated.
+    fn discard(&mut self, _offset: u64, _len: u64) -> Result<(), HypervisorError> {
+        Ok(())
+    }
+}
+
+/// Access to image files by path
+pub trait ImageOpener {
+    /// Open an existing image file
+    fn open(&mut self, path: &str, writable: bool) -> Result<Box<dyn ImageFile>, HypervisorError>;
+    /// Create an image file, truncating any existing one
+    fn create(&mut self, path: &str) -> Result<Box<dyn ImageFile>, HypervisorError>;
+}
+
+/// On-disk image format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Raw,
+    Qcow2,
+}
+
+impl ImageFormat {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ImageFormat::Raw => "raw",
+            ImageFormat::Qcow2 => "qcow2",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "raw" => Some(ImageFormat::Raw),
+            "qcow2" => Some(ImageFormat::Qcow2),
+            _ => None,
+        }
+    }
+
+    /// Storage device type a VM configuration uses for this format
+    pub fn device_type(&self) -> StorageDeviceType {
+        match self {
+            ImageFormat::Raw => StorageDeviceType::Raw,
+            ImageFormat::Qcow2 => StorageDeviceType::Qcow2,
+        }
+    }
+}
+
+/// Detect an image's format from its header
+pub fn probe(file: &mut dyn ImageFile) -> Result<ImageFormat, HypervisorError> {
+    if file.len() < QCOW2_MAGIC.len() as u64 {
+        return Ok(ImageFormat::Raw);
+    }
+    let mut magic = [0u8; 4];
+    file.read_at(0, &mut magic)?;
+    Ok(if magic == QCOW2_MAGIC { ImageFormat::Qcow2 } else { ImageFormat::Raw })
+}
+
+/// What `inspect` reports about one image
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageInfo {
+    pub path: String,
+    pub format: ImageFormat,
+    /// Size of the disk as the guest sees it
+    pub virtual_size: u64,
+    /// Size of the image file on the host
+    pub file_size: u64,
+    /// Bytes of guest data stored in this image; unknown for raw images
+    pub allocated_size: Option<u64>,
+    pub cluster_size: Option<u64>,
+    /// Backing file name as stored in the image
+    pub backing_file: Option<String>,
+}
+
+/// Resolve a backing file name relative to the image referring to it
+pub fn resolve_backing_path(image_path: &str, backing_file: &str) -> String {
+    match image_path.rfind('/') {
+        Some(slash) if !backing_file.starts_with('/') => format!("{}{}", &image_path[..=slash], backing_file),
+        _ => String::from(backing_file),
+    }
+}
+
+enum Layer {
+    Raw(Box<dyn ImageFile>),
+    Qcow2(Qcow2Image),
+}
+
+impl Layer {
+    fn open(opener: &mut dyn ImageOpener, path: &str, writable: bool) -> Result<Self, HypervisorError> {
+        let mut file = opener.open(path, writable)?;
+        match probe(file.as_mut())? {
+            ImageFormat::Raw => Ok(Layer::Raw(file)),
+            ImageFormat::Qcow2 => Ok(Layer::Qcow2(Qcow2Image::open(file)?)),
+        }
+    }
+
+    fn info(&mut self, path: &str) -> Result<ImageInfo, HypervisorError> {
+        Ok(match self {
+            Layer::Raw(file) => ImageInfo {
+                path: String::from(path),
+                format: ImageFormat::Raw,
+                virtual_size: file.len(),
+                file_size: file.len(),
+                allocated_size: None,
+                cluster_size: None,
+                backing_file: None,
+            },
+            Layer::Qcow2(image) => ImageInfo {
+                path: String::from(path),
+                format: ImageFormat::Qcow2,
+                virtual_size: image.virtual_size(),
+                file_size: image.file_size(),
+                allocated_size: Some(image.allocated_bytes()?),
+                cluster_size: Some(image.cluster_size()),
+                backing_file: image.backing_file().map(String::from),
+            },
+        })
+    }
+}
+
+/// Describe a single image without following its backing file
+pub fn inspect(opener: &mut dyn ImageOpener, path: &str) -> Result<ImageInfo, HypervisorError> {
+    Layer::open(opener, path, false)?.info(path)
+}
+
+/// Describe an image and every image in its backing chain, top first
+pub fn backing_chain(opener: &mut dyn ImageOpener, path: &str) -> Result<Vec<ImageInfo>, HypervisorError> {
+    let mut chain: Vec<ImageInfo> = Vec::new();
+    let mut next = Some(String::from(path));
+
+    while let Some(path) = next.take() {
+        if chain.iter().any(|info| info.path == path) {
+            warn!("Backing chain of {} loops at {}", chain[0].path, path);
+            return Err(ConfigError::new("backing_file", ConfigErrorReason::Malformed).into());
+        }
+        if chain.len() == MAX_BACKING_CHAIN {
+            return Err(ConfigError::new("backing_file", ConfigErrorReason::Exhausted).value(MAX_BACKING_CHAIN as u64).into());
+        }
+        let info = inspect(opener, &path)?;
+        next = info.backing_file.as_deref().map(|backing| resolve_backing_path(&path, backing));
+        chain.push(info);
+    }
+    Ok(chain)
+}
+
+/// Read-only view of an image with its backing chain
+pub struct DiskImage {
+    layer: Layer,
+    virtual_size: u64,
+    backing: Option<Box<DiskImage>>,
+}
+
+impl DiskImage {
+    /// Open an image and its backing chain
+    pub fn open(opener: &mut dyn ImageOpener, path: &str) -> Result<Self, HypervisorError> {
+        // Validates the chain before anything is kept open
+        let chain = backing_chain(opener, path)?;
+
+        let mut disk: Option<Box<DiskImage>> = None;
+        for info in chain.iter().rev() {
+            let layer = Layer::open(opener, &info.path, false)?;
+            disk = Some(Box::new(DiskImage { layer, virtual_size: info.virtual_size, backing: disk }));
+        }
+        Ok(*disk.unwrap())
+    }
+
+    pub fn virtual_size(&self) -> u64 {
+        self.virtual_size
+    }
+
+    /// Read guest data; bytes past the end of the disk read as zeros
+    pub fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<(), HypervisorError> {
+        let end = offset.saturating_add(buf.len() as u64).min(self.virtual_size);
+        if offset >= end {
+            buf.fill(0);
+            return Ok(());
+        }
+        let (data, tail) = buf.split_at_mut((end - offset) as usize);
+        tail.fill(0);
+
+        match &mut self.layer {
+            Layer::Raw(file) => file.read_at(offset, data),
+            Layer::Qcow2(image) => {
+                let cluster_size = image.cluster_size();
+                let mut done = 0;
+                while done < data.len() {
+                    let position = offset + done as u64;
+                    let within = position % cluster_size;
+                    let len = ((cluster_size - within) as usize).min(data.len() - done);
+                    let chunk = &mut data[done..done + len];
+
+                    match image.map(position / cluster_size)? {
+                        ClusterMapping::Data(host_offset) => image.read_host(host_offset + within, chunk)?,
+                        ClusterMapping::Zero => chunk.fill(0),
+                        ClusterMapping::Unallocated => match &mut self.backing {
+                            Some(backing) => backing.read_at(position, chunk)?,
+                            None => chunk.fill(0),
+                        },
+                        ClusterMapping::Compressed => return Err(HypervisorError::FeatureNotSupported),
+                    }
+                    done += len;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Output settings for `convert`
+#[derive(Debug, Clone, Copy)]
+pub struct ConvertOptions {
+    pub format: ImageFormat,
+    /// QCOW2 cluster size as a power of two
+    pub cluster_bits: u32,
+}
+
+impl Default for ConvertOptions {
+    fn default() -> Self {
+        ConvertOptions {
+            format: ImageFormat::Qcow2,
+            cluster_bits: DEFAULT_CLUSTER_BITS,
+        }
+    }
+}
+
+fn is_zero(buf: &[u8]) -> bool {
+    buf.iter().all(|&byte| byte == 0)
+}
+
+/// Copy an image into a new file of the requested format, flattening its
+/// backing chain. Zero ranges are left unallocated in the output.
+pub fn convert(
+    opener: &mut dyn ImageOpener,
+    source: &str,
+    destination: &str,
+    options: &ConvertOptions,
+) -> Result<ImageInfo, HypervisorError> {
+    if source == destination {
+        return Err(HypervisorError::InvalidParameter);
+    }
+    let mut disk = DiskImage::open(opener, source)?;
+    let size = disk.virtual_size();
+
+    match options.format {
+        ImageFormat::Raw => {
+            let mut file = opener.create(destination)?;
+            file.set_len(size)?;
+            let mut buf = vec![0u8; RAW_CHUNK_SIZE as usize];
+            let mut offset = 0;
+            while offset < size {
+                let len = RAW_CHUNK_SIZE.min(size - offset) as usize;
+                disk.read_at(offset, &mut buf[..len])?;
+                if !is_zero(&buf[..len]) {
+                    file.write_at(offset, &buf[..len])?;
+                }
+                offset += len as u64;
+            }
+        }
+        ImageFormat::Qcow2 => {
+            let mut image = Qcow2Image::create(opener.create(destination)?, size, options.cluster_bits, None)?;
+            let cluster_size = image.cluster_size();
+            let mut buf = vec![0u8; cluster_size as usize];
+            for cluster in 0..image.guest_clusters() {
+                disk.read_at(cluster * cluster_size, &mut buf)?;
+                if !is_zero(&buf) {
+                    image.write_cluster(cluster, &buf)?;
+                }
+            }
+        }
+    }
+
+    info!("Converted {} to {} ({} bytes, {})", source, destination, size, options.format.name());
+    inspect(opener, destination)
+}
+
+/// Change an image's virtual size. Raw images may shrink when
+/// `allow_shrink` is set; QCOW2 images can only grow.
+pub fn resize(
+    opener: &mut dyn ImageOpener,
+    path: &str,
+    new_size: u64,
+    allow_shrink: bool,
+) -> Result<(), HypervisorError> {
+    match Layer::open(opener, path, true)? {
+        Layer::Raw(mut file) => {
+            if new_size < file.len() && !allow_shrink {
+                return Err(HypervisorError::InvalidParameter);
+            }
+            file.set_len(new_size)
+        }
+        Layer::Qcow2(mut image) => image.grow(new_size),
+    }
+}
+
+/// Release the host storage of zero-filled ranges. Returns the bytes
+/// handed back to the host.
+pub fn sparsify(opener: &mut dyn ImageOpener, path: &str) -> Result<u64, HypervisorError> {
+    let mut released = 0;
+    match Layer::open(opener, path, true)? {
+        Layer::Raw(mut file) => {
+            let size = file.len();
+            let mut buf = vec![0u8; RAW_CHUNK_SIZE as usize];
+            let mut offset = 0;
+            while offset < size {
+                let len = RAW_CHUNK_SIZE.min(size - offset);
+                file.read_at(offset, &mut buf[..len as usize])?;
+                if is_zero(&buf[..len as usize]) {
+                    file.discard(offset, len)?;
+                    released += len;
+                }
+                offset += len;
+            }
+        }
+        Layer::Qcow2(mut image) => {
+            // Over a backing file a zeroed cluster must keep reading as
+            // zeros, which needs the version 3 zero flag
+            let has_backing = image.backing_file().is_some();
+            if has_backing && image.header().version < 3 {
+                return Err(HypervisorError::FeatureNotSupported);
+            }
+            let mut buf = vec![0u8; image.cluster_size() as usize];
+            for cluster in 0..image.guest_clusters() {
+                if let ClusterMapping::Data(host_offset) = image.map(cluster)? {
+                    image.read_host(host_offset, &mut buf)?;
+                    if is_zero(&buf) {
+                        released += image.discard_cluster(cluster, has_backing)?;
+                    }
+                }
+            }
+        }
+    }
+
+    info!("Sparsified {}: {} bytes released", path, released);
+    Ok(released)
+}
@@ -0,0 +1,725 @@
+//! QCOW2 Image Format
+//!
+//! Reader and writer for version 2 and 3 QCOW2 images: guest clusters are
+//! mapped through a two-level L1/L2 table, host clusters are reference
+//! counted with 16-bit refcounts, and unallocated clusters fall through to
+//! the backing file. New clusters are always appended to the end of the
+//! file. Compressed clusters, encryption and external data files are not
+//! supported.
+
+use crate::{HypervisorError, ConfigError, ConfigErrorReason};
+use super::ImageFile;
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// "QFI\xfb"
+pub const QCOW2_MAGIC: [u8; 4] = [b'Q', b'F', b'I', 0xfb];
+/// 64K clusters, the usual default
+pub const DEFAULT_CLUSTER_BITS: u32 = 16;
+const MIN_CLUSTER_BITS: u32 = 9;
+const MAX_CLUSTER_BITS: u32 = 21;
+
+const V2_HEADER_LENGTH: usize = 72;
+const V3_HEADER_LENGTH: usize = 104;
+const MAX_BACKING_FILE_NAME: usize = 1023;
+
+// Header field offsets that are updated in place
+const HEADER_SIZE_OFFSET: u64 = 24;
+const HEADER_L1_SIZE_OFFSET: u64 = 36;
+const HEADER_L1_TABLE_OFFSET: u64 = 40;
+const HEADER_AUTOCLEAR_OFFSET: u64 = 88;
+
+// L1 and L2 entry layout
+const ENTRY_OFFSET_MASK: u64 = 0x00ff_ffff_ffff_fe00;
+const OFLAG_COPIED: u64 = 1 << 63;
+const OFLAG_COMPRESSED: u64 = 1 << 62;
+const OFLAG_ZERO: u64 = 1;
+
+const INCOMPAT_DIRTY: u64 = 1 << 0;
+/// 16-bit refcounts, the only width this implementation handles
+const REFCOUNT_ORDER: u32 = 4;
+/// Largest L1 or refcount table loaded on open, as in QEMU
+const MAX_TABLE_BYTES: u64 = 32 * 1024 * 1024;
+
+fn be_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+fn be_u64(buf: &[u8], offset: usize) -> u64 {
+    u64::from_be_bytes(buf[offset..offset + 8].try_into().unwrap())
+}
+
+fn invalid(field: &'static str) -> HypervisorError {
+    ConfigError::new(field, ConfigErrorReason::Malformed).into()
+}
+
+/// QCOW2 file header
+#[derive(Debug, Clone, PartialEq)]
+pub struct Qcow2Header {
+    pub version: u32,
+    pub backing_file_offset: u64,
+    pub backing_file_size: u32,
+    pub cluster_bits: u32,
+    /// Virtual disk size in bytes
+    pub size: u64,
+    pub crypt_method: u32,
+    pub l1_size: u32,
+    pub l1_table_offset: u64,
+    pub refcount_table_offset: u64,
+    pub refcount_table_clusters: u32,
+    pub nb_snapshots: u32,
+    pub snapshots_offset: u64,
+    pub incompatible_features: u64,
+    pub compatible_features: u64,
+    pub autoclear_features: u64,
+    pub refcount_order: u32,
+    pub header_length: u32,
+}
+
+impl Qcow2Header {
+    /// Parse and validate a header
+    pub fn parse(buf: &[u8]) -> Result<Self, HypervisorError> {
+        if buf.len() < V2_HEADER_LENGTH || buf[..4] != QCOW2_MAGIC {
+            return Err(invalid("qcow2.magic"));
+        }
+        let version = be_u32(buf, 4);
+        let mut header = Qcow2Header {
+            version,
+            backing_file_offset: be_u64(buf, 8),
+            backing_file_size: be_u32(buf, 16),
+            cluster_bits: be_u32(buf, 20),
+            size: be_u64(buf, 24),
+            crypt_method: be_u32(buf, 32),
+            l1_size: be_u32(buf, 36),
+            l1_table_offset: be_u64(buf, 40),
+            refcount_table_offset: be_u64(buf, 48),
+            refcount_table_clusters: be_u32(buf, 56),
+            nb_snapshots: be_u32(buf, 60),
+            snapshots_offset: be_u64(buf, 64),
+            incompatible_features: 0,
+            compatible_features: 0,
+            autoclear_features: 0,
+            refcount_order: REFCOUNT_ORDER,
+            header_length: V2_HEADER_LENGTH as u32,
+        };
+
+        match version {
+            2 => {}
+            3 => {
+                if buf.len() < V3_HEADER_LENGTH {
+                    return Err(invalid("qcow2.header_length"));
+                }
+                header.incompatible_features = be_u64(buf, 72);
+                header.compatible_features = be_u64(buf, 80);
+                header.autoclear_features = be_u64(buf, 88);
+                header.refcount_order = be_u32(buf, 96);
+                header.header_length = be_u32(buf, 100);
+            }
+            _ => return Err(HypervisorError::FeatureNotSupported),
+        }
+
+        if !(MIN_CLUSTER_BITS..=MAX_CLUSTER_BITS).contains(&header.cluster_bits) {
+            return Err(ConfigError::new("qcow2.cluster_bits", ConfigErrorReason::OutOfRange).value(header.cluster_bits as u64).into());
+        }
+        if header.crypt_method != 0
+            || header.incompatible_features & !INCOMPAT_DIRTY != 0
+            || header.refcount_order != REFCOUNT_ORDER
+        {
+            return Err(HypervisorError::FeatureNotSupported);
+        }
+        Ok(header)
+    }
+
+    /// Encode as a version 3 header
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(V3_HEADER_LENGTH);
+        buf.extend_from_slice(&QCOW2_MAGIC);
+        buf.extend_from_slice(&self.version.to_be_bytes());
+        buf.extend_from_slice(&self.backing_file_offset.to_be_bytes());
+        buf.extend_from_slice(&self.backing_file_size.to_be_bytes());
+        buf.extend_from_slice(&self.cluster_bits.to_be_bytes());
+        buf.extend_from_slice(&self.size.to_be_bytes());
+        buf.extend_from_slice(&self.crypt_method.to_be_bytes());
+        buf.extend_from_slice(&self.l1_size.to_be_bytes());
+        buf.extend_from_slice(&self.l1_table_offset.to_be_bytes());
+        buf.extend_from_slice(&self.refcount_table_offset.to_be_bytes());
+        buf.extend_from_slice(&self.refcount_table_clusters.to_be_bytes());
+        buf.extend_from_slice(&self.nb_snapshots.to_be_bytes());
+        buf.extend_from_slice(&self.snapshots_offset.to_be_bytes());
+        buf.extend_from_slice(&self.incompatible_features.to_be_bytes());
+        buf.extend_from_slice(&self.compatible_features.to_be_bytes());
+        buf.extend_from_slice(&self.autoclear_features.to_be_bytes());
+        buf.extend_from_slice(&self.refcount_order.to_be_bytes());
+        buf.extend_from_slice(&self.header_length.to_be_bytes());
+        buf
+    }
+
+    pub fn cluster_size(&self) -> u64 {
+        1 << self.cluster_bits
+    }
+}
+
+/// Where a guest cluster's data lives
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClusterMapping {
+    /// Not allocated in this image; read from the backing file
+    Unallocated,
+    /// Reads as zeros without consulting the backing file
+    Zero,
+    /// Host offset of the cluster in this image
+    Data(u64),
+    /// Compressed cluster, not supported
+    Compressed,
+}
+
+/// Open QCOW2 image
+pub struct Qcow2Image {
+    file: Box<dyn ImageFile>,
+    header: Qcow2Header,
+    backing_file: Option<String>,
+    l1: Vec<u64>,
+    /// Clusters reserved for the L1 table
+    l1_clusters: u64,
+    refcount_table: Vec<u64>,
+    /// Most recently used L2 table and its host offset
+    l2_cache: Option<(u64, Vec<u64>)>,
+    /// First cluster past the end of the file, where allocation happens
+    end_cluster: u64,
+    modified: bool,
+}
+
+impl Qcow2Image {
+    /// Open an existing image
+    pub fn open(mut file: Box<dyn ImageFile>) -> Result<Self, HypervisorError> {
+        let header_len = (V3_HEADER_LENGTH as u64).min(file.len()) as usize;
+        let mut buf = vec![0u8; header_len];
+        file.read_at(0, &mut buf)?;
+        let header = Qcow2Header::parse(&buf)?;
+        let cluster_size = header.cluster_size();
+
+        let backing_file = if header.backing_file_offset != 0 {
+            let len = header.backing_file_size as usize;
+            if len > MAX_BACKING_FILE_NAME {
+                return Err(ConfigError::new("qcow2.backing_file", ConfigErrorReason::OutOfRange).value(len as u64).into());
+            }
+            let mut name = vec![0u8; len];
+            file.read_at(header.backing_file_offset, &mut name)?;
+            Some(String::from_utf8(name).map_err(|_| invalid("qcow2.backing_file"))?)
+        } else {
+            None
+        };
+
+        let l2_entries = cluster_size / 8;
+        let required_l1 = header.size.div_ceil(cluster_size).div_ceil(l2_entries);
+        if (header.l1_size as u64) < required_l1 {
+            return Err(invalid("qcow2.l1_size"));
+        }
+        let refcount_entries = header.refcount_table_clusters as u64 * cluster_size / 8;
+        check_table(file.as_ref(), "qcow2.l1_size", header.l1_table_offset, header.l1_size as u64)?;
+        check_table(file.as_ref(), "qcow2.refcount_table_clusters", header.refcount_table_offset, refcount_entries)?;
+        let l1 = read_table(file.as_mut(), header.l1_table_offset, header.l1_size as u64)?;
+        let refcount_table = read_table(file.as_mut(), header.refcount_table_offset, refcount_entries)?;
+
+        let end_cluster = file.len().div_ceil(cluster_size);
+        Ok(Qcow2Image {
+            file,
+            l1_clusters: (header.l1_size as u64 * 8).div_ceil(cluster_size),
+            header,
+            backing_file,
+            l1,
+            refcount_table,
+            l2_cache: None,
+            end_cluster,
+            modified: false,
+        })
+    }
+
+    /// Create an empty version 3 image of `size` bytes
+    pub fn create(
+        mut file: Box<dyn ImageFile>,
+        size: u64,
+        cluster_bits: u32,
+        backing_file: Option<&str>,
+    ) -> Result<Self, HypervisorError> {
+        if !(MIN_CLUSTER_BITS..=MAX_CLUSTER_BITS).contains(&cluster_bits) {
+            return Err(HypervisorError::InvalidParameter);
+        }
+        if backing_file.map_or(false, |name| name.len() > MAX_BACKING_FILE_NAME) {
+            return Err(HypervisorError::InvalidParameter);
+        }
+        let cluster_size = 1u64 << cluster_bits;
+        let l1_size = size.div_ceil(cluster_size).div_ceil(cluster_size / 8);
+        let l1_clusters = (l1_size * 8).div_ceil(cluster_size).max(1);
+        let refcount_table_clusters = refcount_table_clusters(size, cluster_bits, l1_clusters);
+
+        // Header and backing file name share cluster 0; the extension area
+        // holds only the end marker
+        let backing_file_offset = V3_HEADER_LENGTH as u64 + 8;
+        let header = Qcow2Header {
+            version: 3,
+            backing_file_offset: if backing_file.is_some() { backing_file_offset } else { 0 },
+            backing_file_size: backing_file.map_or(0, |name| name.len() as u32),
+            cluster_bits,
+            size,
+            crypt_method: 0,
+            l1_size: l1_size as u32,
+            l1_table_offset: (1 + refcount_table_clusters) * cluster_size,
+            refcount_table_offset: cluster_size,
+            refcount_table_clusters: refcount_table_clusters as u32,
+            nb_snapshots: 0,
+            snapshots_offset: 0,
+            incompatible_features: 0,
+            compatible_features: 0,
+            autoclear_features: 0,
+            refcount_order: REFCOUNT_ORDER,
+            header_length: V3_HEADER_LENGTH as u32,
+        };
+
+        let mut cluster0 = header.encode();
+        cluster0.extend_from_slice(&[0u8; 8]);
+        if let Some(name) = backing_file {
+            cluster0.extend_from_slice(name.as_bytes());
+        }
+        cluster0.resize(cluster_size as usize, 0);
+        file.set_len(0)?;
+        file.write_at(0, &cluster0)?;
+
+        let zeros = vec![0u8; cluster_size as usize];
+        let metadata_clusters = 1 + refcount_table_clusters + l1_clusters;
+        for cluster in 1..metadata_clusters {
+            file.write_at(cluster * cluster_size, &zeros)?;
+        }
+
+        let mut image = Qcow2Image {
+            file,
+            backing_file: backing_file.map(String::from),
+            l1: vec![0; l1_size as usize],
+            l1_clusters,
+            refcount_table: vec![0; (refcount_table_clusters * cluster_size / 8) as usize],
+            header,
+            l2_cache: None,
+            end_cluster: metadata_clusters,
+            modified: true,
+        };
+        for cluster in 0..metadata_clusters {
+            image.set_refcount(cluster, 1)?;
+        }
+        Ok(image)
+    }
+
+    pub fn header(&self) -> &Qcow2Header {
+        &self.header
+    }
+
+    pub fn backing_file(&self) -> Option<&str> {
+        self.backing_file.as_deref()
+    }
+
+    pub fn virtual_size(&self) -> u64 {
+        self.header.size
+    }
+
+    pub fn cluster_size(&self) -> u64 {
+        self.header.cluster_size()
+    }
+
+    /// Size of the underlying file
+    pub fn file_size(&self) -> u64 {
+        self.file.len()
+    }
+
+    fn l2_entries(&self) -> u64 {
+        self.cluster_size() / 8
+    }
+
+    fn refcount_block_entries(&self) -> u64 {
+        self.cluster_size() / 2
+    }
+
+    /// Number of guest clusters
+    pub fn guest_clusters(&self) -> u64 {
+        self.header.size.div_ceil(self.cluster_size())
+    }
+
+    fn load_l2(&mut self, l2_offset: u64) -> Result<&mut Vec<u64>, HypervisorError> {
+        if self.l2_cache.as_ref().map(|(offset, _)| *offset) != Some(l2_offset) {
+            let entries = self.l2_entries();
+            let table = read_table(self.file.as_mut(), l2_offset, entries)?;
+            self.l2_cache = Some((l2_offset, table));
+        }
+        Ok(&mut self.l2_cache.as_mut().unwrap().1)
+    }
+
+    fn raw_l2_entry(&mut self, guest_cluster: u64) -> Result<u64, HypervisorError> {
+        let l2_entries = self.l2_entries();
+        let l1_entry = self.l1[(guest_cluster / l2_entries) as usize];
+        let l2_offset = l1_entry & ENTRY_OFFSET_MASK;
+        if l2_offset == 0 {
+            return Ok(0);
+        }
+        Ok(self.load_l2(l2_offset)?[(guest_cluster % l2_entries) as usize])
+    }
+
+    /// Look up where a guest cluster is stored
+    pub fn map(&mut self, guest_cluster: u64) -> Result<ClusterMapping, HypervisorError> {
+        if guest_cluster >= self.guest_clusters() {
+            return Err(HypervisorError::InvalidParameter);
+        }
+        let entry = self.raw_l2_entry(guest_cluster)?;
+        if entry & OFLAG_COMPRESSED != 0 {
+            return Ok(ClusterMapping::Compressed);
+        }
+        if self.header.version >= 3 && entry & OFLAG_ZERO != 0 {
+            return Ok(ClusterMapping::Zero);
+        }
+        match entry & ENTRY_OFFSET_MASK {
+            0 => Ok(ClusterMapping::Unallocated),
+            offset => Ok(ClusterMapping::Data(offset)),
+        }
+    }
+
+    /// Read from the image file at a host offset returned by `map`
+    pub fn read_host(&mut self, host_offset: u64, buf: &mut [u8]) -> Result<(), HypervisorError> {
+        self.file.read_at(host_offset, buf)
+    }
+
+    /// Bytes of host data clusters referenced by the L2 tables
+    pub fn allocated_bytes(&mut self) -> Result<u64, HypervisorError> {
+        let mut clusters = 0;
+        for l1_index in 0..self.l1.len() {
+            let l2_offset = self.l1[l1_index] & ENTRY_OFFSET_MASK;
+            if l2_offset == 0 {
+                continue;
+            }
+            clusters += self.load_l2(l2_offset)?.iter()
+                .filter(|&&entry| entry & OFLAG_COMPRESSED != 0 || entry & ENTRY_OFFSET_MASK != 0)
+                .count() as u64;
+        }
+        Ok(clusters * self.cluster_size())
+    }
+
+    /// Write a full cluster of guest data, allocating it if needed
+    pub fn write_cluster(&mut self, guest_cluster: u64, data: &[u8]) -> Result<(), HypervisorError> {
+        if data.len() as u64 != self.cluster_size() {
+            return Err(HypervisorError::InvalidParameter);
+        }
+        let mapping = self.map(guest_cluster)?;
+        let entry = self.raw_l2_entry(guest_cluster)?;
+        let host_offset = match mapping {
+            ClusterMapping::Data(offset) if entry & OFLAG_COPIED != 0 => offset,
+            // Shared with a snapshot; copy-on-write is not implemented
+            ClusterMapping::Data(_) | ClusterMapping::Compressed => return Err(HypervisorError::FeatureNotSupported),
+            ClusterMapping::Unallocated | ClusterMapping::Zero => {
+                let offset = self.allocate_clusters(1)?;
+                self.file.write_at(offset, data)?;
+                self.set_l2_entry(guest_cluster, offset | OFLAG_COPIED)?;
+                return Ok(());
+            }
+        };
+        self.mark_modified()?;
+        self.file.write_at(host_offset, data)
+    }
+
+    /// Drop a guest cluster's data. With `keep_zero` the cluster reads as
+    /// zeros afterwards, otherwise it falls through to the backing file.
+    /// Returns the host bytes released.
+    pub fn discard_cluster(&mut self, guest_cluster: u64, keep_zero: bool) -> Result<u64, HypervisorError> {
+        let previous = self.map(guest_cluster)?;
+        if keep_zero && self.header.version < 3 {
+            // Version 2 has no zero flag
+            return Err(HypervisorError::FeatureNotSupported);
+        }
+        if previous == ClusterMapping::Compressed {
+            return Err(HypervisorError::FeatureNotSupported);
+        }
+        self.set_l2_entry(guest_cluster, if keep_zero { OFLAG_ZERO } else { 0 })?;
+
+        if let ClusterMapping::Data(offset) = previous {
+            let cluster = offset / self.cluster_size();
+            let refcount = self.refcount(cluster)?;
+            self.set_refcount(cluster, refcount.saturating_sub(1))?;
+            if refcount <= 1 {
+                self.file.discard(offset, self.cluster_size())?;
+                return Ok(self.cluster_size());
+            }
+        }
+        Ok(0)
+    }
+
+    /// Grow the virtual disk; shrinking is not supported
+    pub fn grow(&mut self, new_size: u64) -> Result<(), HypervisorError> {
+        if new_size < self.header.size {
+            return Err(HypervisorError::FeatureNotSupported);
+        }
+        let cluster_size = self.cluster_size();
+        let l1_size = new_size.div_ceil(cluster_size).div_ceil(self.l2_entries());
+        self.mark_modified()?;
+
+        if l1_size * 8 > self.l1_clusters * cluster_size {
+            // Move the L1 table to the end of the file; the header is only
+            // switched over once the new table is complete
+            let clusters = (l1_size * 8).div_ceil(cluster_size);
+            let offset = self.allocate_clusters(clusters)?;
+            let mut table = self.l1.clone();
+            table.resize(l1_size as usize, 0);
+            write_table(self.file.as_mut(), offset, &table)?;
+            let padding = vec![0u8; (clusters * cluster_size - l1_size * 8) as usize];
+            self.file.write_at(offset + l1_size * 8, &padding)?;
+
+            self.file.write_at(HEADER_L1_TABLE_OFFSET, &offset.to_be_bytes())?;
+            let old_offset = self.header.l1_table_offset;
+            for cluster in 0..self.l1_clusters {
+                self.set_refcount(old_offset / cluster_size + cluster, 0)?;
+            }
+            self.file.discard(old_offset, self.l1_clusters * cluster_size)?;
+            self.header.l1_table_offset = offset;
+            self.l1_clusters = clusters;
+        }
+
+        self.l1.resize(l1_size as usize, 0);
+        self.header.l1_size = l1_size as u32;
+        self.header.size = new_size;
+        self.file.write_at(HEADER_L1_SIZE_OFFSET, &(l1_size as u32).to_be_bytes())?;
+        self.file.write_at(HEADER_SIZE_OFFSET, &new_size.to_be_bytes())
+    }
+
+    /// Point a guest cluster's L2 entry somewhere, allocating the L2 table
+    fn set_l2_entry(&mut self, guest_cluster: u64, entry: u64) -> Result<(), HypervisorError> {
+        self.mark_modified()?;
+        let l2_entries = self.l2_entries();
+        let l1_index = (guest_cluster / l2_entries) as usize;
+        let mut l2_offset = self.l1[l1_index] & ENTRY_OFFSET_MASK;
+        if l2_offset == 0 {
+            if entry == 0 {
+                return Ok(());
+            }
+            l2_offset = self.allocate_clusters(1)?;
+            self.file.write_at(l2_offset, &vec![0u8; self.cluster_size() as usize])?;
+            self.l1[l1_index] = l2_offset | OFLAG_COPIED;
+            let l1_entry_offset = self.header.l1_table_offset + l1_index as u64 * 8;
+            self.file.write_at(l1_entry_offset, &self.l1[l1_index].to_be_bytes())?;
+        }
+
+        let l2_index = guest_cluster % l2_entries;
+        self.load_l2(l2_offset)?[l2_index as usize] = entry;
+        self.file.write_at(l2_offset + l2_index * 8, &entry.to_be_bytes())
+    }
+
+    /// Reserve `count` contiguous clusters at the end of the file
+    fn allocate_clusters(&mut self, count: u64) -> Result<u64, HypervisorError> {
+        loop {
+            let start = self.end_cluster;
+            // Refcount blocks covering the range may themselves need
+            // allocating, which moves the end of the file
+            for cluster in start..start + count {
+                self.ensure_refcount_block(cluster)?;
+            }
+            if self.end_cluster == start {
+                self.end_cluster += count;
+                for cluster in start..start + count {
+                    self.set_refcount(cluster, 1)?;
+                }
+                return Ok(start * self.cluster_size());
+            }
+        }
+    }
+
+    fn ensure_refcount_block(&mut self, cluster: u64) -> Result<(), HypervisorError> {
+        let block = (cluster / self.refcount_block_entries()) as usize;
+        if block >= self.refcount_table.len() {
+            // Growing the refcount table is not implemented
+            return Err(ConfigError::new("qcow2.refcount_table", ConfigErrorReason::Exhausted).into());
+        }
+        if self.refcount_table[block] != 0 {
+            return Ok(());
+        }
+
+        let cluster_size = self.cluster_size();
+        let block_cluster = self.end_cluster;
+        self.end_cluster += 1;
+        let offset = block_cluster * cluster_size;
+        self.file.write_at(offset, &vec![0u8; cluster_size as usize])?;
+        self.refcount_table[block] = offset;
+        let entry_offset = self.header.refcount_table_offset + block as u64 * 8;
+        self.file.write_at(entry_offset, &offset.to_be_bytes())?;
+        self.set_refcount(block_cluster, 1)
+    }
+
+    /// Reference count of a host cluster
+    pub fn refcount(&mut self, cluster: u64) -> Result<u16, HypervisorError> {
+        let entries = self.refcount_block_entries();
+        let block_offset = match self.refcount_table.get((cluster / entries) as usize) {
+            Some(&offset) if offset != 0 => offset,
+            _ => return Ok(0),
+        };
+        let mut buf = [0u8; 2];
+        self.file.read_at(block_offset + (cluster % entries) * 2, &mut buf)?;
+        Ok(u16::from_be_bytes(buf))
+    }
+
+    fn set_refcount(&mut self, cluster: u64, refcount: u16) -> Result<(), HypervisorError> {
+        self.ensure_refcount_block(cluster)?;
+        let entries = self.refcount_block_entries();
+        let block_offset = self.refcount_table[(cluster / entries) as usize];
+        self.file.write_at(block_offset + (cluster % entries) * 2, &refcount.to_be_bytes())
+    }
+
+    /// Clear autoclear feature bits before the first modification; they
+    /// describe extensions this implementation does not keep up to date
+    fn mark_modified(&mut self) -> Result<(), HypervisorError> {
+        if self.modified {
+            return Ok(());
+        }
+        self.modified = true;
+        if self.header.version >= 3 && self.header.autoclear_features != 0 {
+            self.header.autoclear_features = 0;
+            self.file.write_at(HEADER_AUTOCLEAR_OFFSET, &0u64.to_be_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Give the underlying file back
+    pub fn into_file(self) -> Box<dyn ImageFile> {
+        self.file
+    }
+}
+
+/// Clusters needed for a refcount table that can describe a fully
+/// allocated image of `size` bytes
+fn refcount_table_clusters(size: u64, cluster_bits: u32, l1_clusters: u64) -> u64 {
+    let cluster_size = 1u64 << cluster_bits;
+    let data_clusters = size.div_ceil(cluster_size);
+    let l2_tables = data_clusters.div_ceil(cluster_size / 8);
+    let fixed = 1 + l1_clusters + l2_tables + data_clusters;
+
+    let mut table_clusters = 1;
+    loop {
+        let blocks = (fixed + table_clusters).div_ceil(cluster_size / 2);
+        // Refcount blocks describe themselves as well
+        let blocks = (fixed + table_clusters + blocks).div_ceil(cluster_size / 2);
+        let needed = blocks.div_ceil(cluster_size / 8).max(1);
+        if needed <= table_clusters {
+            return table_clusters;
+        }
+        table_clusters = needed;
+    }
+}
+
+/// Reject a table from the header that is oversized or lies past the end
+/// of the file before anything is allocated for it
+fn check_table(file: &dyn ImageFile, field: &'static str, offset: u64, entries: u64) -> Result<(), HypervisorError> {
+    let bytes = entries.saturating_mul(8);
+    if bytes > MAX_TABLE_BYTES {
+        return Err(ConfigError::new(field, ConfigErrorReason::OutOfRange).value(entries).into());
+    }
+    match offset.checked_add(bytes) {
+        Some(end) if end <= file.len() => Ok(()),
+        _ => Err(invalid(field)),
+    }
+}
+
+fn read_table(file: &mut dyn ImageFile, offset: u64, entries: u64) -> Result<Vec<u64>, HypervisorError> {
+    let mut buf = vec![0u8; (entries * 8) as usize];
+    file.read_at(offset, &mut buf)?;
+    Ok(buf.chunks_exact(8).map(|entry| u64::from_be_bytes(entry.try_into().unwrap())).collect())
+}
+
+fn write_table(file: &mut dyn ImageFile, offset: u64, table: &[u64]) -> Result<(), HypervisorError> {
+    let buf: Vec<u8> = table.iter().flat_map(|entry| entry.to_be_bytes()).collect();
+    file.write_at(offset, &buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::HostFile;
+
+    struct MemoryFile(Vec<u8>);
+
+    impl HostFile for MemoryFile {
+        fn len(&self) -> u64 {
+            self.0.len() as u64
+        }
+
+        fn write_at(&mut self, offset: u64, data: &[u8]) -> Result<(), HypervisorError> {
+            let end = offset as usize + data.len();
+            if self.0.len() < end {
+                self.0.resize(end, 0);
+            }
+            self.0[offset as usize..end].copy_from_slice(data);
+            Ok(())
+        }
+
+        fn read_at(&mut self, offset: u64, data: &mut [u8]) -> Result<(), HypervisorError> {
+            let source = self.0.get(offset as usize..offset as usize + data.len()).ok_or(HypervisorError::InvalidParameter)?;
+            data.copy_from_slice(source);
+            Ok(())
+        }
+    }
+
+    impl ImageFile for MemoryFile {
+        fn set_len(&mut self, len: u64) -> Result<(), HypervisorError> {
+            self.0.resize(len as usize, 0);
+            Ok(())
+        }
+    }
+
+    /// Four clusters of 64K: header, refcount table, L1 table, refcount block
+    fn image_with(l1_size: u32, refcount_table_clusters: u32) -> Box<dyn ImageFile> {
+        let header = Qcow2Header {
+            version: 3,
+            backing_file_offset: 0,
+            backing_file_size: 0,
+            cluster_bits: DEFAULT_CLUSTER_BITS,
+            // One L2 table covers the whole disk
+            size: 1 << 29,
+            crypt_method: 0,
+            l1_size,
+            l1_table_offset: 2 << DEFAULT_CLUSTER_BITS,
+            refcount_table_offset: 1 << DEFAULT_CLUSTER_BITS,
+            refcount_table_clusters,
+            nb_snapshots: 0,
+            snapshots_offset: 0,
+            incompatible_features: 0,
+            compatible_features: 0,
+            autoclear_features: 0,
+            refcount_order: REFCOUNT_ORDER,
+            header_length: V3_HEADER_LENGTH as u32,
+        };
+        let mut data = header.encode();
+        data.resize(4 << DEFAULT_CLUSTER_BITS, 0);
+        Box::new(MemoryFile(data))
+    }
+
+    #[test]
+    fn test_open_accepts_tables_inside_the_file() {
+        let image = Qcow2Image::open(image_with(1, 1)).unwrap();
+        assert_eq!(image.header().l1_size, 1);
+    }
+
+    #[test]
+    fn test_open_rejects_oversized_l1_table() {
+        let error = Qcow2Image::open(image_with(u32::MAX, 1)).err().unwrap();
+        assert_eq!(error, HypervisorError::from(ConfigError::new("qcow2.l1_size", ConfigErrorReason::OutOfRange).value(u32::MAX as u64)));
+    }
+
+    #[test]
+    fn test_open_rejects_tables_past_the_end_of_the_file() {
+        // 256K of entries fit under the cap but not in the 128K after the L1 offset
+        let error = Qcow2Image::open(image_with(32 * 1024, 1)).err().unwrap();
+        assert_eq!(error, invalid("qcow2.l1_size"));
+
+        let error = Qcow2Image::open(image_with(1, 4)).err().unwrap();
+        assert_eq!(error, invalid("qcow2.refcount_table_clusters"));
+    }
+
+    #[test]
+    fn test_open_rejects_oversized_refcount_table() {
+        let error = Qcow2Image::open(image_with(1, u32::MAX)).err().unwrap();
+        assert!(matches!(error, HypervisorError::Config(ConfigError { reason: ConfigErrorReason::OutOfRange, .. })));
+    }
+}
+
@@ -3,18 +3,25 @@
 //! Manages the complete lifecycle of virtual machines including creation,
 //! initialization, startup, shutdown, pause, resume, and cleanup operations.
 
-use crate::{VmId, VmConfig, VmInfo, VmState, HypervisorError, VmFeatures};
+use crate::{VmId, VmConfig, VmInfo, VmState, HypervisorError, ConfigError, ConfigErrorReason, VmFeatures};
 use crate::core::{VmManager, Vcpu, VmStats, HypervisorStats, CpuStats};
-use crate::cpu::CpuVirtualization;
+use crate::cpu::{CpuVirtualization, host_cpuid};
 use crate::memory::MemoryManager;
 use crate::devices::DeviceFramework;
 
+use alloc::format;
 use alloc::vec::Vec;
 use alloc::collections::BTreeMap;
 use alloc::sync::Arc;
 use spin::RwLock;
 use core::time::Duration;
 
+mod reconciler;
+mod schedule;
+
+pub use reconciler::*;
+pub use schedule::*;
+
 /// VM lifecycle state machine
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum VmLifecycleState {
@@ -79,6 +86,10 @@ pub struct LifecycleManager {
     operation_callbacks: OperationCallbacks,
     /// Manager initialization time
     init_time_ms: u64,
+    /// Saved device state per VM and snapshot name
+    device_snapshots: BTreeMap<(VmId, String), Vec<u8>>,
+    /// Timed operations
+    schedules: OperationScheduler,
 }
 
 /// Lifecycle operation callbacks
@@ -101,6 +112,8 @@ impl LifecycleManager {
             vm_contexts: BTreeMap::new(),
             operation_callbacks: OperationCallbacks::default(),
             init_time_ms: 0, // Would use actual timestamp
+            device_snapshots: BTreeMap::new(),
+            schedules: OperationScheduler::new(),
         }
     }
     
@@ -110,7 +123,7 @@ impl LifecycleManager {
         
         // Check if VM already exists
         if self.vm_contexts.contains_key(&vm_id) {
-            return Err(HypervisorError::ConfigurationError(format!("VM {} already exists", vm_id.0)));
+            return Err(ConfigError::new("vm", ConfigErrorReason::AlreadyExists).vm(vm_id).into());
         }
         
         // Create lifecycle context
@@ -172,9 +185,9 @@ impl LifecycleManager {
     /// Start a VM
     pub fn start_vm(&mut self, vm_id: VmId) -> Result<(), HypervisorError> {
         let context = self.vm_contexts.get_mut(&vm_id)
-            .ok_or(HypervisorError::VmNotFound)?;
+            .ok_or(HypervisorError::VmNotFound(vm_id))?;
         
-        if context.state != VmLifecycleState::Initializing {
+        if !matches!(context.state, VmLifecycleState::Initializing | VmLifecycleState::ShuttingDown) {
             return Err(HypervisorError::InvalidVmState);
         }
         
@@ -201,7 +214,7 @@ impl LifecycleManager {
     /// Pause a VM
     pub fn pause_vm(&mut self, vm_id: VmId) -> Result<(), HypervisorError> {
         let context = self.vm_contexts.get_mut(&vm_id)
-            .ok_or(HypervisorError::VmNotFound)?;
+            .ok_or(HypervisorError::VmNotFound(vm_id))?;
         
         if context.state != VmLifecycleState::Running {
             return Err(HypervisorError::InvalidVmState);
@@ -225,7 +238,7 @@ impl LifecycleManager {
     /// Resume a VM
     pub fn resume_vm(&mut self, vm_id: VmId) -> Result<(), HypervisorError> {
         let context = self.vm_contexts.get_mut(&vm_id)
-            .ok_or(HypervisorError::VmNotFound)?;
+            .ok_or(HypervisorError::VmNotFound(vm_id))?;
         
         if context.state != VmLifecycleState::Paused {
             return Err(HypervisorError::InvalidVmState);
@@ -249,7 +262,7 @@ impl LifecycleManager {
     /// Stop a VM
     pub fn stop_vm(&mut self, vm_id: VmId, force: bool) -> Result<(), HypervisorError> {
         let context = self.vm_contexts.get_mut(&vm_id)
-            .ok_or(HypervisorError::VmNotFound)?;
+            .ok_or(HypervisorError::VmNotFound(vm_id))?;
         
         if !matches!(context.state, VmLifecycleState::Running | VmLifecycleState::Paused) {
             return Err(HypervisorError::InvalidVmState);
@@ -267,6 +280,7 @@ impl LifecycleManager {
         if force {
             context.state = VmLifecycleState::Destroyed;
             self.vm_contexts.remove(&vm_id);
+            self.schedules.remove_vm(vm_id);
         } else {
             context.state = VmLifecycleState::ShuttingDown;
             context.last_state_change_ms = self.get_current_time_ms();
@@ -279,7 +293,7 @@ impl LifecycleManager {
     /// Shutdown a VM gracefully
     pub fn shutdown_vm(&mut self, vm_id: VmId) -> Result<(), HypervisorError> {
         let context = self.vm_contexts.get_mut(&vm_id)
-            .ok_or(HypervisorError::VmNotFound)?;
+            .ok_or(HypervisorError::VmNotFound(vm_id))?;
         
         if !matches!(context.state, VmLifecycleState::Running | VmLifecycleState::Paused) {
             return Err(HypervisorError::InvalidVmState);
@@ -312,32 +326,45 @@ impl LifecycleManager {
     }
     
     /// Create VM snapshot
-    pub fn create_snapshot(&mut self, vm_id: VmId, snapshot_name: String) -> Result<(), HypervisorError> {
+    pub fn create_snapshot(&mut self, vm_id: VmId, snapshot_name: String, devices: &DeviceFramework) -> Result<(), HypervisorError> {
         let context = self.vm_contexts.get(&vm_id)
-            .ok_or(HypervisorError::VmNotFound)?;
+            .ok_or(HypervisorError::VmNotFound(vm_id))?;
         
         // Perform snapshot operation
+        let mut device_state = Vec::new();
         self.perform_operation(vm_id, &context.config, LifecycleOperation::Snapshot, |vm_id, config| {
             // Save VM state
             // Save memory contents
-            // Save device states
+            device_state = devices.save_all(vm_id)?;
             Ok(())
         })?;
+        self.device_snapshots.insert((vm_id, snapshot_name.clone()), device_state);
         
         info!("Created snapshot '{}' for VM {}", snapshot_name, vm_id.0);
         Ok(())
     }
     
     /// Restore VM from snapshot
-    pub fn restore_snapshot(&mut self, vm_id: VmId, snapshot_name: String) -> Result<(), HypervisorError> {
+    pub fn restore_snapshot(&mut self, vm_id: VmId, snapshot_name: String, devices: &mut DeviceFramework) -> Result<(), HypervisorError> {
         let context = self.vm_contexts.get(&vm_id)
-            .ok_or(HypervisorError::VmNotFound)?;
+            .ok_or(HypervisorError::VmNotFound(vm_id))?;
+        let device_state = self.device_snapshots.get(&(vm_id, snapshot_name.clone()))
+            .cloned()
+            .ok_or_else(|| HypervisorError::from(ConfigError::new("snapshot", ConfigErrorReason::NotFound).vm(vm_id)))?;
         
         // Perform restore operation
         self.perform_operation(vm_id, &context.config, LifecycleOperation::Restore, |vm_id, config| {
+            // The guest keeps the CPU features it was snapshotted with, so
+            // this host must provide all of them
+            let missing = config.cpu_model.missing_on_host(host_cpuid);
+            if !missing.is_empty() {
+                warn!("CPU model '{}' needs {:?}, which this host lacks", config.cpu_model.name, missing);
+                return Err(ConfigError::new("cpu_model", ConfigErrorReason::Mismatch).vm(vm_id).into());
+            }
+            
             // Load VM state
             // Load memory contents
-            // Load device states
+            devices.restore_all(vm_id, &device_state)?;
             Ok(())
         })?;
         
@@ -345,6 +372,68 @@ impl LifecycleManager {
         Ok(())
     }
     
+    /// Schedule an operation on a VM; `now_ms` is wall-clock time
+    pub fn add_schedule(&mut self, vm_id: VmId, cron: &str, action: ScheduledAction, catch_up: CatchUpPolicy, now_ms: u64) -> Result<u32, HypervisorError> {
+        if !self.vm_contexts.contains_key(&vm_id) {
+            return Err(HypervisorError::VmNotFound(vm_id));
+        }
+        let id = self.schedules.add(vm_id, cron, action, catch_up, now_ms)?;
+        info!("Scheduled {:?} for VM {} at '{}'", self.schedules.get(id).map(|schedule| &schedule.action), vm_id.0, cron);
+        Ok(id)
+    }
+    
+    /// Remove a schedule
+    pub fn remove_schedule(&mut self, schedule_id: u32) -> Result<(), HypervisorError> {
+        self.schedules.remove(schedule_id)?;
+        Ok(())
+    }
+    
+    /// Enable or disable a schedule
+    pub fn set_schedule_enabled(&mut self, schedule_id: u32, enabled: bool, now_ms: u64) -> Result<(), HypervisorError> {
+        self.schedules.set_enabled(schedule_id, enabled, now_ms)
+    }
+    
+    /// Get the schedule table
+    pub fn schedules(&self) -> &OperationScheduler {
+        &self.schedules
+    }
+    
+    /// Text form of the schedules, for persisting across restarts
+    pub fn save_schedules(&self) -> String {
+        self.schedules.encode()
+    }
+    
+    /// Replace the schedules with persisted ones
+    pub fn load_schedules(&mut self, text: &str) -> Result<(), HypervisorError> {
+        self.schedules = OperationScheduler::decode(text)?;
+        Ok(())
+    }
+    
+    /// Run the scheduled operations due at `now_ms`; call it at least once
+    /// a minute or at `schedules().next_deadline()`
+    pub fn run_due_schedules(&mut self, now_ms: u64, devices: &DeviceFramework) -> Vec<ScheduledRun> {
+        let mut runs = Vec::new();
+        for due in self.schedules.take_due(now_ms) {
+            let vm_id = due.vm_id;
+            let result = match &due.action {
+                ScheduledAction::Start => self.start_vm(vm_id),
+                ScheduledAction::Stop { force } => self.stop_vm(vm_id, *force),
+                ScheduledAction::Shutdown => self.shutdown_vm(vm_id),
+                ScheduledAction::Pause => self.pause_vm(vm_id),
+                ScheduledAction::Resume => self.resume_vm(vm_id),
+                ScheduledAction::Snapshot { prefix } => {
+                    let name = format!("{}-{}", prefix, format_timestamp(due.scheduled_ms));
+                    self.create_snapshot(vm_id, name, devices)
+                }
+            };
+            if let Err(ref e) = result {
+                warn!("Scheduled {:?} for VM {} failed: {}", due.action, vm_id.0, e);
+            }
+            runs.push(ScheduledRun { due, result });
+        }
+        runs
+    }
+    
     /// Perform lifecycle operation
     fn perform_operation<F>(&mut self, vm_id: VmId, config: &VmConfig, operation: LifecycleOperation, operation_fn: F) -> Result<LifecycleResult, HypervisorError>
     where
@@ -515,6 +604,13 @@ impl LifecycleManager {
     }
 }
 
+/// Outcome of a scheduled operation
+#[derive(Debug, Clone)]
+pub struct ScheduledRun {
+    pub due: DueRun,
+    pub result: Result<(), HypervisorError>,
+}
+
 /// Lifecycle statistics
 #[derive(Debug, Clone)]
 pub struct LifecycleStats {
@@ -0,0 +1,367 @@
+//! Desired-State Reconciliation
+//!
+//! Keeps a fleet of VMs in line with a declarative spec. Every pass creates
+//! the VMs the spec lists but the hypervisor lacks, drives each VM toward
+//! its desired state, brings back crashed VMs as their restart policy
+//! allows and deletes VMs that were dropped from the spec. Only VMs the
+//! reconciler created are touched; they are matched to the spec by name.
+
+use crate::{VmId, VmConfig, VmState, HypervisorError, ConfigError, ConfigErrorReason};
+use crate::core::Hypervisor;
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// State a VM of the fleet is kept in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DesiredState {
+    Running,
+    Paused,
+    Stopped,
+}
+
+/// Whether a VM that should be running is brought back
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestartPolicy {
+    /// Leave crashed and exited VMs alone
+    Never,
+    /// Recreate VMs that entered the error state
+    OnFailure,
+    /// Also start VMs again that stopped on their own
+    Always,
+}
+
+/// One VM of the fleet
+#[derive(Debug, Clone)]
+pub struct VmSpec {
+    /// Configuration; its name identifies the VM within the fleet
+    pub config: VmConfig,
+    pub state: DesiredState,
+    pub restart: RestartPolicy,
+    /// Bump to have the VM recreated, e.g. after changing its config
+    pub revision: u64,
+}
+
+impl VmSpec {
+    pub fn new(config: VmConfig, state: DesiredState) -> Self {
+        VmSpec { config, state, restart: RestartPolicy::OnFailure, revision: 0 }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.config.name
+    }
+}
+
+/// Declarative description of a fleet of VMs
+#[derive(Debug, Clone)]
+pub struct FleetSpec {
+    pub vms: Vec<VmSpec>,
+    /// Restarts of a VM allowed within `restart_window_ms` before the
+    /// reconciler gives up on it
+    pub max_restarts: u32,
+    pub restart_window_ms: u64,
+    /// Wait after a restart before the next one; doubles with every
+    /// further restart in the window
+    pub restart_backoff_ms: u64,
+}
+
+impl FleetSpec {
+    pub fn new(vms: Vec<VmSpec>) -> Self {
+        FleetSpec { vms, max_restarts: 5, restart_window_ms: 10 * 60 * 1000, restart_backoff_ms: 1000 }
+    }
+
+    fn validate(&self) -> Result<(), HypervisorError> {
+        for (index, vm) in self.vms.iter().enumerate() {
+            if vm.name().is_empty() {
+                return Err(ConfigError::new("fleet.vms.name", ConfigErrorReason::NotFound).value(index as u64).into());
+            }
+            if self.vms[..index].iter().any(|other| other.name() == vm.name()) {
+                return Err(ConfigError::new("fleet.vms.name", ConfigErrorReason::AlreadyExists).value(index as u64).into());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A change made by a reconciliation pass
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReconcileAction {
+    Created { name: String, vm_id: VmId },
+    Started { name: String, vm_id: VmId },
+    Paused { name: String, vm_id: VmId },
+    Resumed { name: String, vm_id: VmId },
+    Stopped { name: String, vm_id: VmId },
+    /// A crashed or exited VM was brought back; a crashed VM is
+    /// recreated under a new ID
+    Restarted { name: String, vm_id: VmId, restarts: u32 },
+    /// Recreated because the spec revision changed
+    Recreated { name: String, vm_id: VmId },
+    Deleted { name: String, vm_id: VmId },
+    Failed { name: String, error: HypervisorError },
+}
+
+/// Outcome of a reconciliation pass
+#[derive(Debug, Clone, Default)]
+pub struct ReconcileReport {
+    pub actions: Vec<ReconcileAction>,
+    /// VMs waiting out a restart backoff
+    pub backing_off: Vec<String>,
+    /// VMs left down: their policy forbids a restart or the restart
+    /// limit was hit
+    pub held: Vec<String>,
+    /// Every VM was in its desired state at the end of the pass
+    pub converged: bool,
+}
+
+#[derive(Debug, Clone)]
+struct ManagedVm {
+    vm_id: VmId,
+    revision: u64,
+    /// Set when the reconciler stopped the VM, so that being stopped is
+    /// not mistaken for the guest exiting
+    stopped_by_reconciler: bool,
+    /// Times of the restarts within the window
+    restarts: Vec<u64>,
+    next_restart_ms: u64,
+}
+
+impl ManagedVm {
+    fn new(vm_id: VmId, revision: u64) -> Self {
+        ManagedVm { vm_id, revision, stopped_by_reconciler: false, restarts: Vec::new(), next_restart_ms: 0 }
+    }
+}
+
+enum RestartGate {
+    Now,
+    Wait,
+    GiveUp,
+}
+
+/// Drives the hypervisor toward a fleet spec
+pub struct Reconciler {
+    spec: FleetSpec,
+    managed: BTreeMap<String, ManagedVm>,
+    clock: fn() -> u64,
+}
+
+impl Reconciler {
+    pub fn new(spec: FleetSpec, clock: fn() -> u64) -> Result<Self, HypervisorError> {
+        spec.validate()?;
+        Ok(Reconciler { spec, managed: BTreeMap::new(), clock })
+    }
+
+    pub fn spec(&self) -> &FleetSpec {
+        &self.spec
+    }
+
+    /// Replace the spec; it takes effect on the next pass. VMs given up
+    /// on get a fresh restart budget.
+    pub fn apply(&mut self, spec: FleetSpec) -> Result<(), HypervisorError> {
+        spec.validate()?;
+        self.spec = spec;
+        for vm in self.managed.values_mut() {
+            vm.restarts.clear();
+            vm.next_restart_ms = 0;
+        }
+        Ok(())
+    }
+
+    /// ID of a managed VM
+    pub fn vm_id(&self, name: &str) -> Option<VmId> {
+        self.managed.get(name).map(|vm| vm.vm_id)
+    }
+
+    /// Managed VMs by name
+    pub fn managed(&self) -> impl Iterator<Item = (&str, VmId)> {
+        self.managed.iter().map(|(name, vm)| (name.as_str(), vm.vm_id))
+    }
+
+    /// Run one pass; call it periodically to keep the fleet converged
+    pub fn reconcile(&mut self, hypervisor: &mut Hypervisor) -> ReconcileReport {
+        let now = (self.clock)();
+        let mut report = ReconcileReport { converged: true, ..ReconcileReport::default() };
+
+        for spec in self.spec.vms.clone() {
+            match self.reconcile_vm(hypervisor, &spec, now, &mut report) {
+                Ok(true) => {}
+                Ok(false) => report.converged = false,
+                Err(error) => {
+                    warn!("Reconciling VM '{}' failed: {}", spec.name(), error);
+                    report.actions.push(ReconcileAction::Failed { name: spec.config.name.clone(), error });
+                    report.converged = false;
+                }
+            }
+        }
+        self.collect_garbage(hypervisor, &mut report);
+        report
+    }
+
+    /// Delete every managed VM, e.g. when a lab ends
+    pub fn teardown(&mut self, hypervisor: &mut Hypervisor) -> ReconcileReport {
+        self.spec.vms.clear();
+        self.reconcile(hypervisor)
+    }
+
+    /// Returns whether the VM ended up in its desired state
+    fn reconcile_vm(&mut self, hypervisor: &mut Hypervisor, spec: &VmSpec, now: u64, report: &mut ReconcileReport)
+        -> Result<bool, HypervisorError> {
+        let name = &spec.config.name;
+
+        let mut state = None;
+        let mut revised = false;
+        if let Some(vm) = self.managed.get(name) {
+            match hypervisor.get_vm_info(vm.vm_id) {
+                Ok(info) if vm.revision != spec.revision => {
+                    remove_vm(hypervisor, vm.vm_id, info.state)?;
+                    self.managed.remove(name);
+                    revised = true;
+                }
+                Ok(info) => state = Some(info.state),
+                // Deleted behind our back
+                Err(HypervisorError::VmNotFound(_)) => {
+                    self.managed.remove(name);
+                }
+                Err(error) => return Err(error),
+            }
+        }
+        let mut state = match state {
+            Some(state) => state,
+            None => {
+                let vm_id = hypervisor.create_vm(spec.config.clone())?;
+                self.managed.insert(name.clone(), ManagedVm::new(vm_id, spec.revision));
+                report.actions.push(if revised {
+                    ReconcileAction::Recreated { name: name.clone(), vm_id }
+                } else {
+                    ReconcileAction::Created { name: name.clone(), vm_id }
+                });
+                VmState::Created
+            }
+        };
+
+        let crashed = state == VmState::Error;
+        let exited = spec.state != DesiredState::Stopped
+            && state == VmState::Stopped
+            && !self.managed[name].stopped_by_reconciler;
+        if crashed || exited {
+            let allowed = match spec.restart {
+                RestartPolicy::Never => false,
+                RestartPolicy::OnFailure => crashed,
+                RestartPolicy::Always => true,
+            };
+            if !allowed {
+                report.held.push(name.clone());
+                return Ok(false);
+            }
+            let vm = self.managed.get_mut(name).unwrap();
+            match restart_gate(vm, &self.spec, now) {
+                RestartGate::GiveUp => {
+                    report.held.push(name.clone());
+                    return Ok(false);
+                }
+                RestartGate::Wait => {
+                    report.backing_off.push(name.clone());
+                    return Ok(false);
+                }
+                RestartGate::Now => {}
+            }
+
+            let mut restarted = vm.clone();
+            if crashed {
+                remove_vm(hypervisor, vm.vm_id, state)?;
+                self.managed.remove(name);
+                restarted.vm_id = hypervisor.create_vm(spec.config.clone())?;
+                state = VmState::Created;
+            }
+            restarted.restarts.push(now);
+            let doublings = (restarted.restarts.len() as u32 - 1).min(16);
+            restarted.next_restart_ms = now + self.spec.restart_backoff_ms.saturating_mul(1 << doublings);
+            info!("Restarting VM '{}' ({} restarts in window)", name, restarted.restarts.len());
+            report.actions.push(ReconcileAction::Restarted {
+                name: name.clone(),
+                vm_id: restarted.vm_id,
+                restarts: restarted.restarts.len() as u32,
+            });
+            self.managed.insert(name.clone(), restarted);
+        }
+
+        let vm = self.managed.get_mut(name).unwrap();
+        let vm_id = vm.vm_id;
+        match (spec.state, state) {
+            (DesiredState::Running, VmState::Running)
+            | (DesiredState::Paused, VmState::Paused)
+            | (DesiredState::Stopped, VmState::NotCreated | VmState::Created | VmState::Stopped) => {}
+            (DesiredState::Running | DesiredState::Paused, VmState::NotCreated | VmState::Created | VmState::Stopped) => {
+                hypervisor.start_vm(vm_id)?;
+                vm.stopped_by_reconciler = false;
+                report.actions.push(ReconcileAction::Started { name: name.clone(), vm_id });
+                if spec.state == DesiredState::Paused {
+                    hypervisor.pause_vm(vm_id)?;
+                    report.actions.push(ReconcileAction::Paused { name: name.clone(), vm_id });
+                }
+            }
+            (DesiredState::Running, VmState::Paused) => {
+                hypervisor.resume_vm(vm_id)?;
+                report.actions.push(ReconcileAction::Resumed { name: name.clone(), vm_id });
+            }
+            (DesiredState::Paused, VmState::Running) => {
+                hypervisor.pause_vm(vm_id)?;
+                report.actions.push(ReconcileAction::Paused { name: name.clone(), vm_id });
+            }
+            (DesiredState::Stopped, VmState::Running | VmState::Paused) => {
+                hypervisor.stop_vm(vm_id, false)?;
+                vm.stopped_by_reconciler = true;
+                report.actions.push(ReconcileAction::Stopped { name: name.clone(), vm_id });
+            }
+            // Handled above
+            (_, VmState::Error) => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Delete managed VMs that are no longer in the spec
+    fn collect_garbage(&mut self, hypervisor: &mut Hypervisor, report: &mut ReconcileReport) {
+        let removed: Vec<String> = self.managed.keys()
+            .filter(|name| !self.spec.vms.iter().any(|spec| spec.name() == name.as_str()))
+            .cloned()
+            .collect();
+        for name in removed {
+            let vm_id = self.managed[&name].vm_id;
+            let result = match hypervisor.get_vm_info(vm_id) {
+                Ok(info) => remove_vm(hypervisor, vm_id, info.state),
+                Err(HypervisorError::VmNotFound(_)) => Ok(()),
+                Err(error) => Err(error),
+            };
+            match result {
+                Ok(()) => {
+                    self.managed.remove(&name);
+                    report.actions.push(ReconcileAction::Deleted { name, vm_id });
+                }
+                Err(error) => {
+                    warn!("Deleting VM '{}' failed: {}", name, error);
+                    report.actions.push(ReconcileAction::Failed { name, error });
+                    report.converged = false;
+                }
+            }
+        }
+    }
+}
+
+fn restart_gate(vm: &mut ManagedVm, spec: &FleetSpec, now: u64) -> RestartGate {
+    vm.restarts.retain(|&time| now.saturating_sub(time) < spec.restart_window_ms);
+    if vm.restarts.len() >= spec.max_restarts as usize {
+        RestartGate::GiveUp
+    } else if now < vm.next_restart_ms {
+        RestartGate::Wait
+    } else {
+        RestartGate::Now
+    }
+}
+
+/// Stop a VM if needed and delete it
+fn remove_vm(hypervisor: &mut Hypervisor, vm_id: VmId, state: VmState) -> Result<(), HypervisorError> {
+    if matches!(state, VmState::Running | VmState::Paused) {
+        hypervisor.stop_vm(vm_id, true)?;
+    }
+    hypervisor.delete_vm(vm_id)
+}
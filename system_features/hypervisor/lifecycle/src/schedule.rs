@@ -0,0 +1,472 @@
+//! Scheduled Lifecycle Operations
+//!
+//! Runs lifecycle operations at times given by cron expressions, e.g. a
+//! nightly snapshot, stopping student VMs when a lab ends or starting them
+//! before class. Expressions use the five-field cron syntax (minute, hour,
+//! day of month, month, day of week) and are evaluated in UTC. Times are
+//! wall-clock milliseconds since the Unix epoch. Schedules are persisted in
+//! a line-based text form so occurrences missed while the host was down are
+//! handled on the next run according to each schedule's catch-up policy.
+
+use crate::{VmId, HypervisorError, ConfigError, ConfigErrorReason};
+
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+const SCHEDULES_MAGIC: &str = "multios-schedules 1";
+
+const MS_PER_MINUTE: u64 = 60 * 1000;
+const MS_PER_DAY: u64 = 24 * 60 * MS_PER_MINUTE;
+
+/// How late an occurrence may run before it counts as missed
+pub const MISSED_GRACE_MS: u64 = MS_PER_MINUTE;
+
+/// Missed occurrences run at most per schedule with `CatchUpPolicy::RunAll`
+pub const MAX_CATCH_UP_RUNS: usize = 24;
+
+/// Days searched for the next occurrence; covers Feb 29 between leap years
+const MAX_SEARCH_DAYS: u64 = 8 * 366;
+
+const MONTH_NAMES: [&str; 12] = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+const WEEKDAY_NAMES: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+fn malformed(field: &'static str) -> HypervisorError {
+    ConfigError::new(field, ConfigErrorReason::Malformed).into()
+}
+
+/// Parsed cron expression
+#[derive(Debug, Clone, PartialEq)]
+pub struct CronExpr {
+    source: String,
+    minutes: u64,
+    hours: u32,
+    /// Bits 1-31
+    days: u32,
+    /// Bits 1-12
+    months: u16,
+    /// Bits 0-6, Sunday first
+    weekdays: u8,
+    /// Day of month or day of week was `*`; when both are restricted a day
+    /// matching either runs
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl CronExpr {
+    /// Parse `minute hour day month weekday` or one of `@yearly`,
+    /// `@monthly`, `@weekly`, `@daily` and `@hourly`.
+    ///
+    /// Fields take `*`, numbers, `a-b` ranges and comma lists, each
+    /// optionally followed by `/step`; months and weekdays also take
+    /// three-letter names and weekday 7 is Sunday.
+    pub fn parse(text: &str) -> Result<Self, HypervisorError> {
+        let text = text.trim();
+        let expanded = match text {
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            "@monthly" => "0 0 1 * *",
+            "@weekly" => "0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@hourly" => "0 * * * *",
+            _ => text,
+        };
+        let fields: Vec<&str> = expanded.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(malformed("schedule.cron"));
+        };
+
+        let mut weekdays = parse_field(weekday, 0, 7, &WEEKDAY_NAMES, 0).ok_or_else(|| malformed("schedule.cron.weekday"))?;
+        if weekdays & (1 << 7) != 0 {
+            weekdays = (weekdays | 1) & !(1 << 7);
+        }
+        let expr = CronExpr {
+            source: String::from(text),
+            minutes: parse_field(minute, 0, 59, &[], 0).ok_or_else(|| malformed("schedule.cron.minute"))?,
+            hours: parse_field(hour, 0, 23, &[], 0).ok_or_else(|| malformed("schedule.cron.hour"))? as u32,
+            days: parse_field(day, 1, 31, &[], 0).ok_or_else(|| malformed("schedule.cron.day"))? as u32,
+            months: parse_field(month, 1, 12, &MONTH_NAMES, 1).ok_or_else(|| malformed("schedule.cron.month"))? as u16,
+            weekdays: weekdays as u8,
+            any_day: day.starts_with('*'),
+            any_weekday: weekday.starts_with('*'),
+        };
+        Ok(expr)
+    }
+
+    /// The expression as written
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    /// First occurrence strictly after `after_ms`, or `None` if the
+    /// expression never fires (e.g. February 30)
+    pub fn next_after(&self, after_ms: u64) -> Option<u64> {
+        let first_minute = after_ms / MS_PER_MINUTE + 1;
+        let mut day = first_minute * MS_PER_MINUTE / MS_PER_DAY;
+        let mut minute_of_day = (first_minute * MS_PER_MINUTE % MS_PER_DAY / MS_PER_MINUTE) as u32;
+
+        for _ in 0..MAX_SEARCH_DAYS {
+            if self.matches_day(day) {
+                for hour in minute_of_day / 60..24 {
+                    if self.hours & (1 << hour) == 0 {
+                        continue;
+                    }
+                    let first = if hour == minute_of_day / 60 { minute_of_day % 60 } else { 0 };
+                    if let Some(minute) = (first..60).find(|&minute| self.minutes & (1 << minute) != 0) {
+                        return Some(day * MS_PER_DAY + (hour as u64 * 60 + minute as u64) * MS_PER_MINUTE);
+                    }
+                }
+            }
+            day += 1;
+            minute_of_day = 0;
+        }
+        None
+    }
+
+    fn matches_day(&self, day: u64) -> bool {
+        let (_, month, day_of_month) = civil_from_days(day);
+        if self.months & (1 << month) == 0 {
+            return false;
+        }
+        // 1970-01-01 was a Thursday
+        let weekday = (day + 4) % 7;
+        let day_matches = self.days & (1 << day_of_month) != 0;
+        let weekday_matches = self.weekdays & (1 << weekday) != 0;
+        match (self.any_day, self.any_weekday) {
+            (false, false) => day_matches || weekday_matches,
+            _ => day_matches && weekday_matches,
+        }
+    }
+}
+
+/// Bitmask of the values a cron field selects; `names[i]` stands for
+/// `first_name + i`
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str], first_name: u32) -> Option<u64> {
+    let value = |text: &str| -> Option<u32> {
+        let value = match names.iter().position(|name| name.eq_ignore_ascii_case(text)) {
+            Some(index) => index as u32 + first_name,
+            None => text.parse().ok()?,
+        };
+        (min..=max).contains(&value).then_some(value)
+    };
+
+    let mut mask = 0u64;
+    for item in field.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().ok().filter(|&step| step > 0)?),
+            None => (item, 1),
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((start, end)) => (value(start)?, value(end)?),
+                // `5/15` runs from 5 to the end of the range
+                None if item.contains('/') => (value(range)?, max),
+                None => (value(range)?, value(range)?),
+            },
+        };
+        if start > end {
+            return None;
+        }
+        for value in (start..=end).step_by(step as usize) {
+            mask |= 1 << value;
+        }
+    }
+    Some(mask)
+}
+
+/// (year, month, day) of a day count since 1970-01-01
+fn civil_from_days(days: u64) -> (u64, u32, u32) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// `YYYYMMDD-HHMM` in UTC
+pub fn format_timestamp(time_ms: u64) -> String {
+    let (year, month, day) = civil_from_days(time_ms / MS_PER_DAY);
+    let minute_of_day = time_ms % MS_PER_DAY / MS_PER_MINUTE;
+    format!("{:04}{:02}{:02}-{:02}{:02}", year, month, day, minute_of_day / 60, minute_of_day % 60)
+}
+
+/// Operation a schedule runs
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScheduledAction {
+    Start,
+    Stop { force: bool },
+    /// Graceful guest shutdown
+    Shutdown,
+    Pause,
+    Resume,
+    /// Snapshot named `<prefix>-YYYYMMDD-HHMM` after the scheduled time
+    Snapshot { prefix: String },
+}
+
+impl ScheduledAction {
+    fn encode(&self) -> String {
+        match self {
+            ScheduledAction::Start => String::from("start"),
+            ScheduledAction::Stop { force: false } => String::from("stop"),
+            ScheduledAction::Stop { force: true } => String::from("force-stop"),
+            ScheduledAction::Shutdown => String::from("shutdown"),
+            ScheduledAction::Pause => String::from("pause"),
+            ScheduledAction::Resume => String::from("resume"),
+            ScheduledAction::Snapshot { prefix } => format!("snapshot:{}", prefix),
+        }
+    }
+
+    fn decode(text: &str) -> Option<Self> {
+        Some(match text {
+            "start" => ScheduledAction::Start,
+            "stop" => ScheduledAction::Stop { force: false },
+            "force-stop" => ScheduledAction::Stop { force: true },
+            "shutdown" => ScheduledAction::Shutdown,
+            "pause" => ScheduledAction::Pause,
+            "resume" => ScheduledAction::Resume,
+            _ => ScheduledAction::Snapshot { prefix: String::from(text.strip_prefix("snapshot:")?) },
+        })
+    }
+}
+
+/// What to do with occurrences missed while the host was down
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CatchUpPolicy {
+    /// Drop them; only an occurrence within `MISSED_GRACE_MS` runs
+    Skip,
+    /// Run once for all of them
+    RunOnce,
+    /// Run each of them, up to `MAX_CATCH_UP_RUNS`
+    RunAll,
+}
+
+impl CatchUpPolicy {
+    fn name(self) -> &'static str {
+        match self {
+            CatchUpPolicy::Skip => "skip",
+            CatchUpPolicy::RunOnce => "run-once",
+            CatchUpPolicy::RunAll => "run-all",
+        }
+    }
+}
+
+/// A timed operation on a VM
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schedule {
+    pub id: u32,
+    pub vm_id: VmId,
+    pub expr: CronExpr,
+    pub action: ScheduledAction,
+    pub catch_up: CatchUpPolicy,
+    pub enabled: bool,
+    /// Next occurrence not yet handled
+    pub next_run_ms: Option<u64>,
+    pub last_run_ms: Option<u64>,
+}
+
+/// An occurrence that is due
+#[derive(Debug, Clone, PartialEq)]
+pub struct DueRun {
+    pub schedule_id: u32,
+    pub vm_id: VmId,
+    pub action: ScheduledAction,
+    /// Occurrence being run
+    pub scheduled_ms: u64,
+    /// Run later than `MISSED_GRACE_MS` after its occurrence
+    pub late: bool,
+}
+
+/// Table of schedules
+#[derive(Debug, Clone, Default)]
+pub struct OperationScheduler {
+    schedules: BTreeMap<u32, Schedule>,
+    next_id: u32,
+}
+
+impl OperationScheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a schedule whose first occurrence is after `now_ms`
+    pub fn add(&mut self, vm_id: VmId, cron: &str, action: ScheduledAction, catch_up: CatchUpPolicy, now_ms: u64)
+        -> Result<u32, HypervisorError> {
+        let expr = CronExpr::parse(cron)?;
+        if let ScheduledAction::Snapshot { prefix } = &action {
+            if prefix.is_empty() || prefix.contains(char::is_whitespace) {
+                return Err(malformed("schedule.snapshot_prefix"));
+            }
+        }
+        let next_run_ms = expr.next_after(now_ms);
+        if next_run_ms.is_none() {
+            return Err(ConfigError::new("schedule.cron", ConfigErrorReason::OutOfRange).vm(vm_id).into());
+        }
+
+        self.next_id += 1;
+        let id = self.next_id;
+        self.schedules.insert(id, Schedule {
+            id,
+            vm_id,
+            expr,
+            action,
+            catch_up,
+            enabled: true,
+            next_run_ms,
+            last_run_ms: None,
+        });
+        Ok(id)
+    }
+
+    pub fn remove(&mut self, id: u32) -> Result<Schedule, HypervisorError> {
+        self.schedules.remove(&id).ok_or_else(|| not_found(id))
+    }
+
+    /// Remove every schedule of a VM
+    pub fn remove_vm(&mut self, vm_id: VmId) {
+        self.schedules.retain(|_, schedule| schedule.vm_id != vm_id);
+    }
+
+    /// Enable or disable a schedule. Occurrences while it was disabled are
+    /// not caught up.
+    pub fn set_enabled(&mut self, id: u32, enabled: bool, now_ms: u64) -> Result<(), HypervisorError> {
+        let schedule = self.schedules.get_mut(&id).ok_or_else(|| not_found(id))?;
+        if enabled && !schedule.enabled {
+            schedule.next_run_ms = schedule.expr.next_after(now_ms);
+        }
+        schedule.enabled = enabled;
+        Ok(())
+    }
+
+    pub fn get(&self, id: u32) -> Option<&Schedule> {
+        self.schedules.get(&id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Schedule> {
+        self.schedules.values()
+    }
+
+    /// Earliest next occurrence of an enabled schedule
+    pub fn next_deadline(&self) -> Option<u64> {
+        self.schedules.values().filter(|schedule| schedule.enabled).filter_map(|schedule| schedule.next_run_ms).min()
+    }
+
+    /// Take the occurrences due at `now_ms`, applying the catch-up policy
+    /// to missed ones, and advance the schedules past them
+    pub fn take_due(&mut self, now_ms: u64) -> Vec<DueRun> {
+        let mut due = Vec::new();
+        for schedule in self.schedules.values_mut().filter(|schedule| schedule.enabled) {
+            let mut occurrences = Vec::new();
+            let mut missed = 0u64;
+            while let Some(time) = schedule.next_run_ms.filter(|&time| time <= now_ms) {
+                if now_ms - time >= MISSED_GRACE_MS {
+                    missed += 1;
+                }
+                occurrences.push(time);
+                if occurrences.len() > MAX_CATCH_UP_RUNS {
+                    occurrences.remove(0);
+                }
+                schedule.next_run_ms = schedule.expr.next_after(time);
+            }
+            let Some(&latest) = occurrences.last() else { continue };
+
+            let runs: Vec<u64> = match schedule.catch_up {
+                CatchUpPolicy::Skip if now_ms - latest >= MISSED_GRACE_MS => Vec::new(),
+                CatchUpPolicy::Skip | CatchUpPolicy::RunOnce => vec![latest],
+                CatchUpPolicy::RunAll => occurrences,
+            };
+            if missed > 0 {
+                warn!("Schedule {} for VM {} missed {} run(s), catch-up policy {}",
+                      schedule.id, schedule.vm_id.0, missed, schedule.catch_up.name());
+            }
+            for scheduled_ms in runs {
+                due.push(DueRun {
+                    schedule_id: schedule.id,
+                    vm_id: schedule.vm_id,
+                    action: schedule.action.clone(),
+                    scheduled_ms,
+                    late: now_ms - scheduled_ms >= MISSED_GRACE_MS,
+                });
+                schedule.last_run_ms = Some(now_ms);
+            }
+        }
+        due
+    }
+
+    /// Text form, one schedule per line
+    pub fn encode(&self) -> String {
+        let mut text = String::new();
+        let _ = writeln!(text, "{}", SCHEDULES_MAGIC);
+        let _ = writeln!(text, "next_id {}", self.next_id);
+        let time = |time: Option<u64>| time.map_or(String::from("-"), |time| format!("{}", time));
+        for schedule in self.schedules.values() {
+            let _ = writeln!(text, "schedule {} {} {} {} {} {} {} {}",
+                             schedule.id, schedule.vm_id.0, schedule.enabled as u8, schedule.catch_up.name(),
+                             time(schedule.next_run_ms), time(schedule.last_run_ms),
+                             schedule.action.encode(), schedule.expr.as_str());
+        }
+        text
+    }
+
+    /// Parse the text form. Occurrences that passed since it was written
+    /// are caught up by the next `take_due`.
+    pub fn decode(text: &str) -> Result<Self, HypervisorError> {
+        let invalid = || malformed("schedule_table");
+        let mut lines = text.lines();
+        if lines.next() != Some(SCHEDULES_MAGIC) {
+            return Err(invalid());
+        }
+
+        let mut table = OperationScheduler::new();
+        for line in lines {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words[..] {
+                [] => {}
+                ["next_id", id] => table.next_id = id.parse().map_err(|_| invalid())?,
+                ["schedule", id, vm, enabled, catch_up, next_run, last_run, action, ref cron @ ..] if !cron.is_empty() => {
+                    let time = |text: &str| -> Result<Option<u64>, HypervisorError> {
+                        match text {
+                            "-" => Ok(None),
+                            _ => text.parse().map(Some).map_err(|_| invalid()),
+                        }
+                    };
+                    let catch_up = match catch_up {
+                        "skip" => CatchUpPolicy::Skip,
+                        "run-once" => CatchUpPolicy::RunOnce,
+                        "run-all" => CatchUpPolicy::RunAll,
+                        _ => return Err(invalid()),
+                    };
+                    let schedule = Schedule {
+                        id: id.parse().map_err(|_| invalid())?,
+                        vm_id: VmId(vm.parse().map_err(|_| invalid())?),
+                        expr: CronExpr::parse(&cron.join(" "))?,
+                        action: ScheduledAction::decode(action).ok_or_else(invalid)?,
+                        catch_up,
+                        enabled: match enabled {
+                            "0" => false,
+                            "1" => true,
+                            _ => return Err(invalid()),
+                        },
+                        next_run_ms: time(next_run)?,
+                        last_run_ms: time(last_run)?,
+                    };
+                    table.next_id = table.next_id.max(schedule.id);
+                    table.schedules.insert(schedule.id, schedule);
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(table)
+    }
+}
+
+fn not_found(id: u32) -> HypervisorError {
+    ConfigError::new("schedule", ConfigErrorReason::NotFound).value(id as u64).into()
+}
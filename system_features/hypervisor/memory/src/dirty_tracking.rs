@@ -0,0 +1,226 @@
+//! Dirty Page Tracking
+//!
+//! Live migration, snapshots and monitoring need to know which guest pages
+//! were written since the last look. With Intel Page Modification Logging
+//! the processor logs the GPA of every page whose EPT dirty bit it sets, and
+//! a full log causes a VM exit that drains it. Without PML the tracker falls
+//! back to write protection: every page starts read-only, the first write to
+//! a page faults, the page is marked dirty and made writable again, and it is
+//! write-protected once more when the bitmap is collected.
+
+use crate::{HypervisorError, ConfigError, ConfigErrorReason, VmId};
+use super::PAGE_SIZE_4K;
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+/// Entries in a PML log buffer
+pub const PML_LOG_ENTRIES: usize = 512;
+
+/// How dirty pages of a VM are detected
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DirtyTrackingMode {
+    /// Intel Page Modification Logging
+    Pml,
+    /// Write-protect pages and catch the first write
+    WriteProtect,
+}
+
+/// Bitmap of dirty guest pages, one bit per 4K page
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DirtyBitmap {
+    words: Vec<u64>,
+}
+
+impl DirtyBitmap {
+    pub fn new() -> Self {
+        DirtyBitmap { words: Vec::new() }
+    }
+
+    /// Mark the page containing `guest_addr`; returns whether it was clean
+    pub fn mark(&mut self, guest_addr: u64) -> bool {
+        let page = guest_addr / PAGE_SIZE_4K;
+        let (word, bit) = ((page / 64) as usize, page % 64);
+        if word >= self.words.len() {
+            self.words.resize(word + 1, 0);
+        }
+        let was_clean = self.words[word] & (1 << bit) == 0;
+        self.words[word] |= 1 << bit;
+        was_clean
+    }
+
+    /// Whether the page containing `guest_addr` is dirty
+    pub fn is_dirty(&self, guest_addr: u64) -> bool {
+        let page = guest_addr / PAGE_SIZE_4K;
+        self.words
+            .get((page / 64) as usize)
+            .map_or(false, |word| word & (1 << (page % 64)) != 0)
+    }
+
+    /// Number of dirty pages
+    pub fn count(&self) -> u64 {
+        self.words.iter().map(|word| word.count_ones() as u64).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.iter().all(|&word| word == 0)
+    }
+
+    /// Guest addresses of the dirty pages, ascending
+    pub fn dirty_pages(&self) -> impl Iterator<Item = u64> + '_ {
+        self.words.iter().enumerate().flat_map(|(index, &word)| {
+            (0..64u64)
+                .filter(move |bit| word & (1 << bit) != 0)
+                .map(move |bit| (index as u64 * 64 + bit) * PAGE_SIZE_4K)
+        })
+    }
+
+    /// The raw bitmap words, bit N of word W covering page W * 64 + N
+    pub fn words(&self) -> &[u64] {
+        &self.words
+    }
+}
+
+/// Dirty rate statistics of a VM
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DirtyRateStats {
+    pub collections: u64,
+    /// Pages reported dirty over all collections
+    pub total_dirty_pages: u64,
+    /// Pages dirty in the last collection
+    pub last_dirty_pages: u64,
+    /// Pages dirtied per second between the last two collections
+    pub dirty_pages_per_second: f64,
+    pub peak_dirty_pages_per_second: f64,
+    pub pml_full_exits: u64,
+    pub write_protect_faults: u64,
+}
+
+struct VmDirtyState {
+    mode: DirtyTrackingMode,
+    bitmap: DirtyBitmap,
+    last_collection_ms: u64,
+    stats: DirtyRateStats,
+}
+
+/// Tracks dirty guest pages per VM
+pub struct DirtyTracker {
+    pml_available: bool,
+    vms: BTreeMap<VmId, VmDirtyState>,
+    /// Monotonic milliseconds, for rates
+    clock: fn() -> u64,
+}
+
+impl DirtyTracker {
+    /// Create a tracker; PML is used for every VM when the processor supports it
+    pub fn new(pml_available: bool, clock: fn() -> u64) -> Self {
+        DirtyTracker {
+            pml_available,
+            vms: BTreeMap::new(),
+            clock,
+        }
+    }
+
+    /// Start tracking a VM and return the mode in use.
+    ///
+    /// In `WriteProtect` mode the caller must write-protect the VM's memory
+    /// (`MemoryManager::write_protect_all`) before the VM runs again.
+    pub fn start_tracking(&mut self, vm_id: VmId) -> Result<DirtyTrackingMode, HypervisorError> {
+        if self.vms.contains_key(&vm_id) {
+            return Err(ConfigError::new("dirty_tracking", ConfigErrorReason::AlreadyEnabled).vm(vm_id).into());
+        }
+
+        let mode = if self.pml_available { DirtyTrackingMode::Pml } else { DirtyTrackingMode::WriteProtect };
+        self.vms.insert(vm_id, VmDirtyState {
+            mode,
+            bitmap: DirtyBitmap::new(),
+            last_collection_ms: (self.clock)(),
+            stats: DirtyRateStats::default(),
+        });
+        info!("Dirty tracking started for VM {} using {:?}", vm_id.0, mode);
+        Ok(mode)
+    }
+
+    /// Stop tracking a VM, returning its final statistics
+    pub fn stop_tracking(&mut self, vm_id: VmId) -> Result<DirtyRateStats, HypervisorError> {
+        let state = self.vms.remove(&vm_id).ok_or(HypervisorError::VmNotFound(vm_id))?;
+        info!("Dirty tracking stopped for VM {}", vm_id.0);
+        Ok(state.stats)
+    }
+
+    /// Whether a VM is tracked
+    pub fn is_tracking(&self, vm_id: VmId) -> bool {
+        self.vms.contains_key(&vm_id)
+    }
+
+    /// Tracking mode of a VM
+    pub fn mode(&self, vm_id: VmId) -> Option<DirtyTrackingMode> {
+        self.vms.get(&vm_id).map(|state| state.mode)
+    }
+
+    /// Drain a PML log, on a PML-full exit or before collecting
+    pub fn drain_pml_log(&mut self, vm_id: VmId, log: &[u64], log_full: bool) -> Result<(), HypervisorError> {
+        let state = self.vms.get_mut(&vm_id).ok_or(HypervisorError::VmNotFound(vm_id))?;
+        if state.mode != DirtyTrackingMode::Pml {
+            return Err(HypervisorError::InvalidVmState);
+        }
+        if log.len() > PML_LOG_ENTRIES {
+            return Err(HypervisorError::InvalidParameter);
+        }
+
+        for &guest_addr in log {
+            state.bitmap.mark(guest_addr);
+        }
+        if log_full {
+            state.stats.pml_full_exits += 1;
+        }
+        Ok(())
+    }
+
+    /// Handle a write to a write-protected page.
+    ///
+    /// Returns true if the fault was caused by dirty tracking, in which case
+    /// the caller makes the page writable and resumes the guest.
+    pub fn handle_write_fault(&mut self, vm_id: VmId, guest_addr: u64) -> bool {
+        match self.vms.get_mut(&vm_id) {
+            Some(state) if state.mode == DirtyTrackingMode::WriteProtect => {
+                state.bitmap.mark(guest_addr);
+                state.stats.write_protect_faults += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Take the pages dirtied since the last collection and reset the bitmap.
+    ///
+    /// In `WriteProtect` mode the caller write-protects the returned pages
+    /// again so later writes are caught.
+    pub fn collect_dirty_bitmap(&mut self, vm_id: VmId) -> Result<DirtyBitmap, HypervisorError> {
+        let now_ms = (self.clock)();
+        let state = self.vms.get_mut(&vm_id).ok_or(HypervisorError::VmNotFound(vm_id))?;
+
+        let bitmap = core::mem::take(&mut state.bitmap);
+        let dirty = bitmap.count();
+        let elapsed_ms = now_ms.saturating_sub(state.last_collection_ms);
+
+        let stats = &mut state.stats;
+        stats.collections += 1;
+        stats.total_dirty_pages += dirty;
+        stats.last_dirty_pages = dirty;
+        if elapsed_ms > 0 {
+            stats.dirty_pages_per_second = dirty as f64 * 1000.0 / elapsed_ms as f64;
+            if stats.dirty_pages_per_second > stats.peak_dirty_pages_per_second {
+                stats.peak_dirty_pages_per_second = stats.dirty_pages_per_second;
+            }
+        }
+        state.last_collection_ms = now_ms;
+
+        Ok(bitmap)
+    }
+
+    /// Dirty rate statistics of a VM
+    pub fn stats(&self, vm_id: VmId) -> Option<DirtyRateStats> {
+        self.vms.get(&vm_id).map(|state| state.stats)
+    }
+}
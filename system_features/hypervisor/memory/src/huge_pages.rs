@@ -0,0 +1,327 @@
+//! Transparent Huge Pages
+//!
+//! Guest memory is usually mapped with 4K EPT entries. The promoter scans
+//! 2MB-aligned guest regions in the background and collapses regions whose
+//! 512 pages are all mapped with the same permissions into one 2MB mapping,
+//! migrating the pages into a 2MB host block when their frames are not
+//! already contiguous. Ballooning a page out of a huge mapping splits it
+//! back into 4K mappings.
+//!
+//! Host frames come from a buddy allocator, which keeps free memory in
+//! power-of-two blocks and merges freed buddies so 2MB blocks stay available.
+
+use crate::HypervisorError;
+use super::{PAGE_SIZE_2M, PAGE_SIZE_4K, MemoryFlags};
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Largest block order handed out (order 10 is 4MB)
+pub const MAX_BUDDY_ORDER: usize = 10;
+/// Order of a 2MB block
+pub const HUGE_PAGE_ORDER: usize = 9;
+/// 4K pages in a 2MB region
+const PAGES_PER_HUGE_PAGE: usize = 512;
+
+/// Buddy allocator statistics
+#[derive(Debug, Clone, Default)]
+pub struct BuddyStats {
+    pub total_frames: u64,
+    pub free_frames: u64,
+    /// Free blocks per order
+    pub free_blocks: Vec<usize>,
+    pub allocations: u64,
+    pub failed_allocations: u64,
+    pub merges: u64,
+    pub splits: u64,
+}
+
+impl BuddyStats {
+    /// Share of free memory that cannot satisfy an allocation of `order`,
+    /// from 0.0 (none) to 1.0 (all of it)
+    pub fn fragmentation(&self, order: usize) -> f64 {
+        if self.free_frames == 0 {
+            return 0.0;
+        }
+        let usable: u64 = self
+            .free_blocks
+            .iter()
+            .enumerate()
+            .filter(|&(block_order, _)| block_order >= order)
+            .map(|(block_order, &count)| (count as u64) << block_order)
+            .sum();
+        1.0 - usable as f64 / self.free_frames as f64
+    }
+}
+
+/// Buddy allocator over a contiguous range of host physical memory
+pub struct BuddyAllocator {
+    base: u64,
+    total_frames: u64,
+    /// Free block addresses per order
+    free_lists: Vec<BTreeSet<u64>>,
+    stats: BuddyStats,
+}
+
+impl BuddyAllocator {
+    /// Manage `size` bytes of host memory starting at the 4K-aligned `base`
+    pub fn new(base: u64, size: u64) -> Result<Self, HypervisorError> {
+        if base & (PAGE_SIZE_4K - 1) != 0 || size < PAGE_SIZE_4K {
+            return Err(HypervisorError::InvalidParameter);
+        }
+
+        let mut allocator = BuddyAllocator {
+            base,
+            total_frames: size / PAGE_SIZE_4K,
+            free_lists: vec![BTreeSet::new(); MAX_BUDDY_ORDER + 1],
+            stats: BuddyStats::default(),
+        };
+
+        // Carve the range into the largest naturally aligned blocks
+        let mut address = base;
+        let end = base + allocator.total_frames * PAGE_SIZE_4K;
+        while address < end {
+            let mut order = MAX_BUDDY_ORDER;
+            while order > 0 && (Self::block_size(order) > end - address || (address - base) % Self::block_size(order) != 0) {
+                order -= 1;
+            }
+            allocator.free_lists[order].insert(address);
+            address += Self::block_size(order);
+        }
+        Ok(allocator)
+    }
+
+    fn block_size(order: usize) -> u64 {
+        PAGE_SIZE_4K << order
+    }
+
+    /// Allocate a block of 2^order frames
+    pub fn allocate(&mut self, order: usize) -> Option<u64> {
+        if order > MAX_BUDDY_ORDER {
+            return None;
+        }
+
+        let found = (order..=MAX_BUDDY_ORDER).find(|&candidate| !self.free_lists[candidate].is_empty());
+        let mut current = match found {
+            Some(current) => current,
+            None => {
+                self.stats.failed_allocations += 1;
+                return None;
+            }
+        };
+        let block = *self.free_lists[current].iter().next()?;
+        self.free_lists[current].remove(&block);
+
+        // Split down to the requested order, returning the upper halves
+        while current > order {
+            current -= 1;
+            self.free_lists[current].insert(block + Self::block_size(current));
+            self.stats.splits += 1;
+        }
+
+        self.stats.allocations += 1;
+        Some(block)
+    }
+
+    /// Free a block allocated with the given order, merging it with free buddies
+    pub fn free(&mut self, address: u64, order: usize) {
+        let mut block = address;
+        let mut order = order;
+        while order < MAX_BUDDY_ORDER {
+            let buddy = self.base + ((block - self.base) ^ Self::block_size(order));
+            if !self.free_lists[order].remove(&buddy) {
+                break;
+            }
+            block = block.min(buddy);
+            order += 1;
+            self.stats.merges += 1;
+        }
+        self.free_lists[order].insert(block);
+    }
+
+    pub fn stats(&self) -> BuddyStats {
+        let free_blocks: Vec<usize> = self.free_lists.iter().map(|list| list.len()).collect();
+        BuddyStats {
+            total_frames: self.total_frames,
+            free_frames: free_blocks.iter().enumerate().map(|(order, &count)| (count as u64) << order).sum(),
+            free_blocks,
+            ..self.stats.clone()
+        }
+    }
+}
+
+/// Huge page promotion statistics
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HugePageStats {
+    /// Regions currently mapped with a 2MB page
+    pub huge_pages: u64,
+    pub promotions: u64,
+    /// Promotions that had to copy pages into a new 2MB block
+    pub migrations: u64,
+    pub demotions: u64,
+    pub failed_promotions: u64,
+}
+
+/// Mapping of one 2MB-aligned guest region
+#[derive(Debug, Clone)]
+enum RegionMapping {
+    /// 4K mappings: host frame and flags per page index
+    Small(BTreeMap<usize, (u64, MemoryFlags)>),
+    Huge { host_base: u64, flags: MemoryFlags },
+}
+
+/// Change the EPT must apply after a promoter operation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HugePageChange {
+    /// Map the region with one 2MB entry
+    Promoted { guest_base: u64, host_base: u64 },
+    /// Map the region with 4K entries starting at `host_base`
+    Demoted { guest_base: u64, host_base: u64 },
+}
+
+/// Detects fully mapped 2MB regions and collapses them into huge pages
+pub struct HugePagePromoter {
+    regions: BTreeMap<u64, RegionMapping>,
+    /// Region the next scan starts at
+    scan_cursor: u64,
+    stats: HugePageStats,
+}
+
+impl HugePagePromoter {
+    pub fn new() -> Self {
+        HugePagePromoter {
+            regions: BTreeMap::new(),
+            scan_cursor: 0,
+            stats: HugePageStats::default(),
+        }
+    }
+
+    /// Record a 4K mapping
+    pub fn page_mapped(&mut self, guest_page: u64, host_frame: u64, flags: MemoryFlags) {
+        let (base, index) = Self::split_address(guest_page);
+        let region = self.regions.entry(base).or_insert_with(|| RegionMapping::Small(BTreeMap::new()));
+        if let RegionMapping::Small(pages) = region {
+            pages.insert(index, (host_frame, flags));
+        }
+    }
+
+    /// Record a 4K unmapping; a huge region must be split first
+    pub fn page_unmapped(&mut self, guest_page: u64) {
+        let (base, index) = Self::split_address(guest_page);
+        if let Some(RegionMapping::Small(pages)) = self.regions.get_mut(&base) {
+            pages.remove(&index);
+            if pages.is_empty() {
+                self.regions.remove(&base);
+            }
+        }
+    }
+
+    /// Whether a guest address is mapped by a huge page
+    pub fn is_huge(&self, guest_addr: u64) -> bool {
+        let (base, _) = Self::split_address(guest_addr);
+        matches!(self.regions.get(&base), Some(RegionMapping::Huge { .. }))
+    }
+
+    /// Scan up to `max_regions` regions and promote the eligible ones.
+    ///
+    /// Regions whose frames are already contiguous and 2MB aligned are
+    /// collapsed in place; others are copied into a 2MB block from the
+    /// allocator and their old frames freed.
+    pub fn scan(&mut self, allocator: &mut BuddyAllocator, max_regions: usize) -> Vec<HugePageChange> {
+        let candidates: Vec<u64> = self
+            .regions
+            .range(self.scan_cursor..)
+            .chain(self.regions.range(..self.scan_cursor))
+            .take(max_regions)
+            .filter(|(_, mapping)| Self::is_promotable(mapping))
+            .map(|(&base, _)| base)
+            .collect();
+        self.scan_cursor = self
+            .regions
+            .range(self.scan_cursor..)
+            .chain(self.regions.range(..self.scan_cursor))
+            .nth(max_regions)
+            .map_or(0, |(&base, _)| base);
+
+        let mut changes = Vec::new();
+        for guest_base in candidates {
+            match self.promote(guest_base, allocator) {
+                Some(change) => changes.push(change),
+                None => self.stats.failed_promotions += 1,
+            }
+        }
+        changes
+    }
+
+    /// Split the huge page covering `guest_addr` into 4K mappings, e.g.
+    /// before ballooning a page out of it
+    pub fn split(&mut self, guest_addr: u64) -> Option<HugePageChange> {
+        let (guest_base, _) = Self::split_address(guest_addr);
+        let (host_base, flags) = match self.regions.get(&guest_base) {
+            Some(RegionMapping::Huge { host_base, flags }) => (*host_base, *flags),
+            _ => return None,
+        };
+
+        let pages = (0..PAGES_PER_HUGE_PAGE)
+            .map(|index| (index, (host_base + index as u64 * PAGE_SIZE_4K, flags)))
+            .collect();
+        self.regions.insert(guest_base, RegionMapping::Small(pages));
+        self.stats.huge_pages -= 1;
+        self.stats.demotions += 1;
+        Some(HugePageChange::Demoted { guest_base, host_base })
+    }
+
+    pub fn stats(&self) -> HugePageStats {
+        self.stats
+    }
+
+    fn promote(&mut self, guest_base: u64, allocator: &mut BuddyAllocator) -> Option<HugePageChange> {
+        let pages = match self.regions.get(&guest_base) {
+            Some(RegionMapping::Small(pages)) => pages.clone(),
+            _ => return None,
+        };
+        let (first_frame, flags) = pages[&0];
+
+        let contiguous = first_frame & (PAGE_SIZE_2M - 1) == 0
+            && pages.iter().all(|(&index, &(frame, _))| frame == first_frame + index as u64 * PAGE_SIZE_4K);
+        let host_base = if contiguous {
+            first_frame
+        } else {
+            let block = allocator.allocate(HUGE_PAGE_ORDER)?;
+            for (&index, &(frame, _)) in &pages {
+                // Guest frames are mapped into the hypervisor's address space
+                unsafe {
+                    core::ptr::copy_nonoverlapping(
+                        frame as *const u8,
+                        (block + index as u64 * PAGE_SIZE_4K) as *mut u8,
+                        PAGE_SIZE_4K as usize,
+                    );
+                }
+                allocator.free(frame, 0);
+            }
+            self.stats.migrations += 1;
+            block
+        };
+
+        self.regions.insert(guest_base, RegionMapping::Huge { host_base, flags });
+        self.stats.huge_pages += 1;
+        self.stats.promotions += 1;
+        Some(HugePageChange::Promoted { guest_base, host_base })
+    }
+
+    fn is_promotable(mapping: &RegionMapping) -> bool {
+        match mapping {
+            RegionMapping::Small(pages) => {
+                pages.len() == PAGES_PER_HUGE_PAGE
+                    && pages.values().all(|(_, flags)| flags.bits() == pages[&0].1.bits())
+            }
+            RegionMapping::Huge { .. } => false,
+        }
+    }
+
+    fn split_address(guest_addr: u64) -> (u64, usize) {
+        let base = guest_addr & !(PAGE_SIZE_2M - 1);
+        (base, ((guest_addr - base) / PAGE_SIZE_4K) as usize)
+    }
+}
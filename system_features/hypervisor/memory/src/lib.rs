@@ -3,12 +3,24 @@
 //! Implements memory virtualization using Extended Page Tables (EPT) for Intel VT-x
 //! and Nested Page Tables (NPT) for AMD-V, providing efficient nested paging support.
 
-use crate::{HypervisorError, VmId, VcpuId};
-use crate::core::{VmExitReason, MemoryStats};
+use crate::{HypervisorError, ConfigError, ConfigErrorReason, VmId, VcpuId};
+use crate::core::{VmExitReason, MemoryStats, VnumaConfig};
+use multios_memory_manager::NumaManager;
 
 use bitflags::bitflags;
+use alloc::boxed::Box;
 use alloc::vec::Vec;
 
+mod dirty_tracking;
+mod huge_pages;
+mod swap;
+mod vnuma;
+
+pub use dirty_tracking::*;
+pub use huge_pages::*;
+pub use swap::*;
+pub use vnuma::*;
+
 /// Page size constants
 pub const PAGE_SIZE_4K: u64 = 0x1000;
 pub const PAGE_SIZE_2M: u64 = 0x200000;
@@ -157,6 +169,14 @@ pub struct MemoryManager {
     tlb_hit_count: u64,
     /// TLB miss count
     tlb_miss_count: u64,
+    /// Swap state when the VM is overcommitted
+    swap: Option<GuestSwap>,
+    /// Transparent huge page promoter
+    promoter: Option<HugePagePromoter>,
+    /// Host frames backing guest memory when huge pages are enabled
+    frame_allocator: Option<BuddyAllocator>,
+    /// Host frames backing each virtual NUMA node
+    numa_backing: Vec<VnumaBacking>,
 }
 
 impl MemoryManager {
@@ -176,6 +196,10 @@ impl MemoryManager {
             page_fault_count: 0,
             tlb_hit_count: 0,
             tlb_miss_count: 0,
+            swap: None,
+            promoter: None,
+            frame_allocator: None,
+            numa_backing: Vec::new(),
         };
         
         info!("Memory Manager created with {} MB", memory_mb);
@@ -265,6 +289,17 @@ impl MemoryManager {
         // Track memory region
         self.add_memory_region(guest_addr, guest_addr + align_size, flags)?;
         
+        if let Some(ref mut swap) = self.swap {
+            for offset in (0..align_size).step_by(PAGE_SIZE_4K as usize) {
+                swap.track_page(guest_addr + offset, host_addr + offset);
+            }
+        }
+        if let Some(ref mut promoter) = self.promoter {
+            for offset in (0..align_size).step_by(PAGE_SIZE_4K as usize) {
+                promoter.page_mapped(guest_addr + offset, host_addr + offset, flags);
+            }
+        }
+        
         self.used_memory_mb += align_size / (1024 * 1024);
         
         info!("Mapped guest address 0x{:016x} to host 0x{:016x} ({} bytes)", 
@@ -427,6 +462,16 @@ impl MemoryManager {
     pub fn handle_ept_violation(&mut self, guest_addr: u64) -> Result<VmExitReason, HypervisorError> {
         self.page_fault_count += 1;
         
+        if let Some(ref mut swap) = self.swap {
+            match swap.handle_fault(guest_addr) {
+                SwapFaultOutcome::NotSwapped => {},
+                SwapFaultOutcome::PageInQueued | SwapFaultOutcome::PageInPending => {
+                    // The vCPU waits until process_page_ins brings the page back
+                    return Ok(VmExitReason::EPTViolation);
+                },
+            }
+        }
+        
         // In real implementation, would handle the EPT violation
         // by allocating missing page, updating EPT, etc.
         
@@ -481,7 +526,342 @@ impl MemoryManager {
             allocated_mb: self.total_memory_mb,
             used_mb: self.used_memory_mb,
             page_faults: self.page_fault_count,
+            swap: self.swap.as_ref().map(|swap| swap.stats()).unwrap_or_default(),
+        }
+    }
+    
+    /// Memory not yet in use, in MB
+    pub fn available_mb(&self) -> u64 {
+        self.total_memory_mb.saturating_sub(self.used_memory_mb)
+    }
+
+    /// Mark memory as in use without mapping it, e.g. to simulate host pressure
+    pub fn reserve_memory(&mut self, memory_mb: u64) -> Result<(), HypervisorError> {
+        if memory_mb > self.available_mb() {
+            return Err(HypervisorError::MemoryAllocationFailed);
+        }
+
+        self.used_memory_mb += memory_mb;
+        Ok(())
+    }
+
+    /// Give back memory taken with `reserve_memory`
+    pub fn release_memory(&mut self, memory_mb: u64) {
+        self.used_memory_mb = self.used_memory_mb.saturating_sub(memory_mb);
+    }
+
+    /// Enable swapping of cold guest pages to a swap backend.
+    ///
+    /// Pages mapped so far are tracked as resident; later mappings are
+    /// tracked as they are made.
+    pub fn enable_swap(&mut self, config: SwapConfig, backend: Box<dyn SwapBackend>) -> Result<(), HypervisorError> {
+        if self.swap.is_some() {
+            return Err(ConfigError::new("swap", ConfigErrorReason::AlreadyEnabled).into());
+        }
+        
+        let mut swap = GuestSwap::new(config, backend)?;
+        let regions: Vec<MemoryRegion> = match (&self.ept_table, &self.npt_table) {
+            (Some(ept), _) => ept.regions.clone(),
+            (None, Some(npt)) => npt.regions.clone(),
+            (None, None) => Vec::new(),
+        };
+        for region in regions.iter().filter(|region| region.region_type != MemoryRegionType::Mmio) {
+            for guest_page in (region.start_address..region.end_address).step_by(PAGE_SIZE_4K as usize) {
+                swap.track_page(guest_page, region.host_address + (guest_page - region.start_address));
+            }
+        }
+        
+        info!("Swap enabled for VM {} with a {} MB resident limit", self.vm_id.0, swap.config().resident_limit_mb);
+        self.swap = Some(swap);
+        Ok(())
+    }
+    
+    /// Swap state, if swapping is enabled
+    pub fn swap(&self) -> Option<&GuestSwap> {
+        self.swap.as_ref()
+    }
+    
+    /// Note a guest access to a page for the eviction policy
+    pub fn record_page_access(&mut self, guest_addr: u64) {
+        if let Some(ref mut swap) = self.swap {
+            swap.record_access(guest_addr);
+        }
+    }
+    
+    /// Evict cold pages while the VM is above its resident limit.
+    ///
+    /// Called periodically and whenever the host is under memory pressure;
+    /// returns the number of pages written to swap.
+    pub fn balance_swap(&mut self) -> Result<usize, HypervisorError> {
+        let evicted = match self.swap {
+            Some(ref mut swap) => swap.balance()?,
+            None => return Ok(0),
+        };
+        for &guest_page in &evicted {
+            self.set_page_present(guest_page, false);
+            self.invalidate_tlb(guest_page);
+        }
+        Ok(evicted.len())
+    }
+    
+    /// Service queued page-ins and map the pages again.
+    ///
+    /// Returns the guest pages brought back so waiting vCPUs can resume.
+    pub fn process_page_ins(&mut self) -> Result<Vec<u64>, HypervisorError> {
+        let completed = match self.swap {
+            Some(ref mut swap) => swap.process_page_ins()?,
+            None => return Ok(Vec::new()),
+        };
+        for &guest_page in &completed {
+            self.set_page_present(guest_page, true);
+        }
+        Ok(completed)
+    }
+    
+    /// Set the present bit of the 4K EPT/NPT entry of a guest page
+    fn set_page_present(&mut self, guest_page: u64, present: bool) {
+        // One page table per 2MB of guest memory
+        let (table, index) = ((guest_page >> 21) as usize, ((guest_page >> 12) & 0x1FF) as usize);
+        if let Some(ref mut ept) = self.ept_table {
+            if let Some(entry) = ept.pts.get_mut(table).map(|pt| &mut pt[index]) {
+                entry.present = present;
+            }
+        }
+        if let Some(ref mut npt) = self.npt_table {
+            if let Some(entry) = npt.pts.get_mut(table).map(|pt| &mut pt[index]) {
+                entry.present = present;
+            }
+        }
+    }
+    
+    /// Clear the write bit of every writable 4K entry so the next write to
+    /// each page faults; used by write-protect dirty tracking.
+    ///
+    /// Returns the number of pages protected.
+    pub fn write_protect_all(&mut self) -> usize {
+        let mut protected = 0;
+        if let Some(ref mut ept) = self.ept_table {
+            for entry in ept.pts.iter_mut().flat_map(|pt| pt.iter_mut()) {
+                if entry.present && entry.write {
+                    entry.write = false;
+                    protected += 1;
+                }
+            }
+        }
+        if let Some(ref mut npt) = self.npt_table {
+            for entry in npt.pts.iter_mut().flat_map(|pt| pt.iter_mut()) {
+                if entry.present && entry.write {
+                    entry.write = false;
+                    protected += 1;
+                }
+            }
+        }
+        self.flush_tlb();
+        protected
+    }
+    
+    /// Set the write bit of the 4K EPT/NPT entry of a guest page.
+    ///
+    /// Pages of read-only regions are never made writable.
+    pub fn set_page_writable(&mut self, guest_addr: u64, writable: bool) {
+        let guest_page = guest_addr & !(PAGE_SIZE_4K - 1);
+        let writable = writable && self.region_flags(guest_page).map_or(false, |flags| flags.contains(MemoryFlags::WRITE));
+        let (table, index) = ((guest_page >> 21) as usize, ((guest_page >> 12) & 0x1FF) as usize);
+        if let Some(ref mut ept) = self.ept_table {
+            if let Some(entry) = ept.pts.get_mut(table).map(|pt| &mut pt[index]) {
+                entry.write = writable;
+            }
+        }
+        if let Some(ref mut npt) = self.npt_table {
+            if let Some(entry) = npt.pts.get_mut(table).map(|pt| &mut pt[index]) {
+                entry.write = writable;
+            }
         }
+        self.invalidate_tlb(guest_page);
+    }
+    
+    /// Clear the EPT dirty bits of the pages in a collected bitmap so PML
+    /// logs their next write again
+    pub fn clear_dirty_bits(&mut self, bitmap: &DirtyBitmap) {
+        for guest_page in bitmap.dirty_pages() {
+            let (table, index) = ((guest_page >> 21) as usize, ((guest_page >> 12) & 0x1FF) as usize);
+            if let Some(entry) = self.ept_table.as_mut().and_then(|ept| ept.pts.get_mut(table)).map(|pt| &mut pt[index]) {
+                entry.dirty = false;
+            }
+            if let Some(entry) = self.npt_table.as_mut().and_then(|npt| npt.pts.get_mut(table)).map(|pt| &mut pt[index]) {
+                entry.dirty = false;
+            }
+        }
+    }
+    
+    /// Flags of the memory region containing a guest address
+    fn region_flags(&self, guest_addr: u64) -> Option<MemoryFlags> {
+        let regions = match (&self.ept_table, &self.npt_table) {
+            (Some(ept), _) => &ept.regions,
+            (None, Some(npt)) => &npt.regions,
+            (None, None) => return None,
+        };
+        regions
+            .iter()
+            .find(|region| guest_addr >= region.start_address && guest_addr < region.end_address)
+            .map(|region| region.flags)
+    }
+    
+    /// Switch a 2MB guest region between one huge mapping and 4K mappings
+    fn apply_huge_page_change(&mut self, change: HugePageChange) {
+        let (guest_base, host_base, huge) = match change {
+            HugePageChange::Promoted { guest_base, host_base } => (guest_base, host_base, true),
+            HugePageChange::Demoted { guest_base, host_base } => (guest_base, host_base, false),
+        };
+        // One page directory per 1GB of guest memory
+        let (directory, index) = ((guest_base >> 30) as usize, ((guest_base >> 21) & 0x1FF) as usize);
+        let table = (guest_base >> 21) as usize;
+        
+        if let Some(ref mut ept) = self.ept_table {
+            if let Some(entry) = ept.pds.get_mut(directory).map(|pd| &mut pd[index]) {
+                entry.present = huge;
+                entry.address = host_base;
+            }
+            if let Some(pt) = ept.pts.get_mut(table) {
+                for (page, entry) in pt.iter_mut().enumerate() {
+                    entry.present = !huge;
+                    entry.address = host_base + page as u64 * PAGE_SIZE_4K;
+                }
+            }
+        }
+        if let Some(ref mut npt) = self.npt_table {
+            if let Some(entry) = npt.pds.get_mut(directory).map(|pd| &mut pd[index]) {
+                entry.present = huge;
+                entry.address = host_base;
+            }
+            if let Some(pt) = npt.pts.get_mut(table) {
+                for (page, entry) in pt.iter_mut().enumerate() {
+                    entry.present = !huge;
+                    entry.address = host_base + page as u64 * PAGE_SIZE_4K;
+                }
+            }
+        }
+        
+        for page in 0..512 {
+            self.invalidate_tlb(guest_base + page * PAGE_SIZE_4K);
+        }
+    }
+    
+    /// Enable transparent huge pages, backing guest memory with frames from `allocator`.
+    ///
+    /// Pages mapped so far are handed to the promoter; `run_huge_page_promoter`
+    /// then collapses fully mapped 2MB regions.
+    pub fn enable_huge_pages(&mut self, allocator: BuddyAllocator) -> Result<(), HypervisorError> {
+        if self.promoter.is_some() {
+            return Err(ConfigError::new("huge_pages", ConfigErrorReason::AlreadyEnabled).into());
+        }
+        
+        let mut promoter = HugePagePromoter::new();
+        let regions: Vec<MemoryRegion> = match (&self.ept_table, &self.npt_table) {
+            (Some(ept), _) => ept.regions.clone(),
+            (None, Some(npt)) => npt.regions.clone(),
+            (None, None) => Vec::new(),
+        };
+        for region in regions.iter().filter(|region| region.region_type != MemoryRegionType::Mmio) {
+            for guest_page in (region.start_address..region.end_address).step_by(PAGE_SIZE_4K as usize) {
+                promoter.page_mapped(guest_page, region.host_address + (guest_page - region.start_address), region.flags);
+            }
+        }
+        
+        self.promoter = Some(promoter);
+        self.frame_allocator = Some(allocator);
+        info!("Transparent huge pages enabled for VM {}", self.vm_id.0);
+        Ok(())
+    }
+    
+    /// Background promoter pass over up to `max_regions` 2MB regions; returns the number promoted
+    pub fn run_huge_page_promoter(&mut self, max_regions: usize) -> usize {
+        let changes = match (&mut self.promoter, &mut self.frame_allocator) {
+            (Some(promoter), Some(allocator)) => promoter.scan(allocator, max_regions),
+            _ => return 0,
+        };
+        for &change in &changes {
+            self.apply_huge_page_change(change);
+        }
+        changes.len()
+    }
+    
+    /// Back each virtual NUMA node with frames from its host node and map
+    /// them at the node's guest physical range
+    pub fn back_numa_nodes(&mut self, config: &VnumaConfig, layout: &VnumaLayout, numa: &mut NumaManager) -> Result<(), HypervisorError> {
+        if !self.numa_backing.is_empty() {
+            return Err(ConfigError::new("vnuma", ConfigErrorReason::AlreadyEnabled).into());
+        }
+        
+        for range in &layout.ranges {
+            let host_node = config.nodes[range.node].host_node;
+            let frames = numa.allocate_from_node(host_node, (range.size / PAGE_SIZE_4K) as usize)
+                .map_err(|error| {
+                    warn!("Host node {} cannot back vNUMA node {} of VM {}: {:?}", host_node, range.node, self.vm_id.0, error);
+                    HypervisorError::MemoryAllocationFailed
+                })?;
+            let host_frames: Vec<u64> = frames.iter().map(|frame| frame.0).collect();
+            
+            // Map each physically contiguous run of frames with one call
+            let mut run_start = 0;
+            for index in 1..=host_frames.len() {
+                let contiguous = index < host_frames.len() && host_frames[index] == host_frames[index - 1] + PAGE_SIZE_4K;
+                if !contiguous {
+                    self.map_guest_virtual_address(
+                        range.guest_base + run_start as u64 * PAGE_SIZE_4K,
+                        host_frames[run_start],
+                        (index - run_start) as u64 * PAGE_SIZE_4K,
+                        MemoryFlags::READ | MemoryFlags::WRITE | MemoryFlags::EXECUTE,
+                    )?;
+                    run_start = index;
+                }
+            }
+            
+            self.numa_backing.push(VnumaBacking {
+                node: range.node,
+                host_node,
+                guest_base: range.guest_base,
+                host_frames,
+            });
+        }
+        
+        info!("VM {} memory backed by {} host NUMA nodes", self.vm_id.0, self.numa_backing.len());
+        Ok(())
+    }
+    
+    /// Host frames backing each virtual NUMA node
+    pub fn numa_backing(&self) -> &[VnumaBacking] {
+        &self.numa_backing
+    }
+    
+    /// Take a guest page away for the balloon driver, splitting a huge page around it first
+    pub fn balloon_out_page(&mut self, guest_addr: u64) -> Result<(), HypervisorError> {
+        let guest_page = guest_addr & !(PAGE_SIZE_4K - 1);
+        
+        let split = self.promoter.as_mut().and_then(|promoter| promoter.split(guest_page));
+        if let Some(change) = split {
+            self.apply_huge_page_change(change);
+        }
+        if let Some(ref mut promoter) = self.promoter {
+            promoter.page_unmapped(guest_page);
+        }
+        if let Some(ref mut swap) = self.swap {
+            swap.untrack_page(guest_page);
+        }
+        
+        self.set_page_present(guest_page, false);
+        self.invalidate_tlb(guest_page);
+        Ok(())
+    }
+    
+    /// Huge page promotion counters
+    pub fn huge_page_stats(&self) -> HugePageStats {
+        self.promoter.as_ref().map(|promoter| promoter.stats()).unwrap_or_default()
+    }
+    
+    /// Host frame allocator statistics, if huge pages are enabled
+    pub fn frame_allocator_stats(&self) -> Option<BuddyStats> {
+        self.frame_allocator.as_ref().map(|allocator| allocator.stats())
     }
     
     /// Invalidate TLB entry
@@ -570,4 +950,5 @@ pub struct MemoryStats {
     pub allocated_mb: u64,
     pub used_mb: u64,
     pub page_faults: u64,
+    pub swap: SwapStats,
 }
\ No newline at end of file
@@ -0,0 +1,362 @@
+//! Guest Memory Swap
+//!
+//! Lets a VM be overcommitted: the host backs only a resident budget of the
+//! guest's pages and writes cold pages to a swap backend, usually a host swap
+//! file. Victims are chosen by LRU or by a clock (second chance) sweep. A
+//! guest access to a swapped page faults through the EPT; the page-in is
+//! queued and serviced asynchronously while the faulting vCPU waits.
+
+use crate::HypervisorError;
+use super::PAGE_SIZE_4K;
+
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::vec::Vec;
+
+/// Storage that swapped-out guest pages are written to
+pub trait SwapBackend: Send {
+    /// Number of page-sized slots
+    fn slot_count(&self) -> u64;
+    /// Copy the 4K host frame at `host_address` into a slot
+    fn page_out(&mut self, slot: u64, host_address: u64) -> Result<(), HypervisorError>;
+    /// Copy a slot back into the 4K host frame at `host_address`
+    fn page_in(&mut self, slot: u64, host_address: u64) -> Result<(), HypervisorError>;
+}
+
+/// Host file a [`SwapFile`] writes to
+pub trait HostFile: Send {
+    fn len(&self) -> u64;
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> Result<(), HypervisorError>;
+    fn read_at(&mut self, offset: u64, data: &mut [u8]) -> Result<(), HypervisorError>;
+}
+
+/// Swap backend storing one page per 4K slot of a host file
+pub struct SwapFile<F: HostFile> {
+    file: F,
+}
+
+impl<F: HostFile> SwapFile<F> {
+    pub fn new(file: F) -> Self {
+        SwapFile { file }
+    }
+}
+
+impl<F: HostFile> SwapBackend for SwapFile<F> {
+    fn slot_count(&self) -> u64 {
+        self.file.len() / PAGE_SIZE_4K
+    }
+
+    fn page_out(&mut self, slot: u64, host_address: u64) -> Result<(), HypervisorError> {
+        // Guest frames are mapped into the hypervisor's address space
+        let frame = unsafe { core::slice::from_raw_parts(host_address as *const u8, PAGE_SIZE_4K as usize) };
+        self.file.write_at(slot * PAGE_SIZE_4K, frame)
+    }
+
+    fn page_in(&mut self, slot: u64, host_address: u64) -> Result<(), HypervisorError> {
+        let frame = unsafe { core::slice::from_raw_parts_mut(host_address as *mut u8, PAGE_SIZE_4K as usize) };
+        self.file.read_at(slot * PAGE_SIZE_4K, frame)
+    }
+}
+
+/// How eviction victims are picked
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EvictionPolicy {
+    /// Least recently accessed page first
+    Lru,
+    /// Second chance sweep over the referenced bits
+    Clock,
+}
+
+/// Swap configuration of a VM
+#[derive(Debug, Clone)]
+pub struct SwapConfig {
+    pub policy: EvictionPolicy,
+    /// Host memory budget of the VM; guest pages beyond it are swapped
+    pub resident_limit_mb: u64,
+    /// Start evicting when residency exceeds this share of the budget
+    pub high_watermark_percent: u8,
+    /// Evict until residency is down to this share of the budget
+    pub low_watermark_percent: u8,
+    /// Page-ins serviced per call to `process_page_ins`
+    pub page_ins_per_pass: usize,
+}
+
+impl SwapConfig {
+    pub fn new(resident_limit_mb: u64) -> Self {
+        SwapConfig {
+            policy: EvictionPolicy::Clock,
+            resident_limit_mb,
+            high_watermark_percent: 95,
+            low_watermark_percent: 85,
+            page_ins_per_pass: 32,
+        }
+    }
+}
+
+/// Swap statistics of a VM
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SwapStats {
+    pub resident_pages: u64,
+    pub swapped_pages: u64,
+    pub swap_outs: u64,
+    pub swap_ins: u64,
+    pub pending_page_ins: u64,
+    /// EPT faults on swapped pages
+    pub major_faults: u64,
+}
+
+/// Result of an EPT fault on a swap-tracked VM
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SwapFaultOutcome {
+    /// The page is not swapped; handle the fault normally
+    NotSwapped,
+    /// A page-in was queued; the vCPU must wait for it
+    PageInQueued,
+    /// A page-in for the page is already queued
+    PageInPending,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ResidentPage {
+    host_address: u64,
+    last_access: u64,
+    referenced: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct SwappedPage {
+    host_address: u64,
+    slot: u64,
+}
+
+/// Swap state of one VM
+pub struct GuestSwap {
+    config: SwapConfig,
+    backend: Box<dyn SwapBackend>,
+    resident: BTreeMap<u64, ResidentPage>,
+    /// Clock ring of resident guest pages
+    clock: VecDeque<u64>,
+    swapped: BTreeMap<u64, SwappedPage>,
+    free_slots: Vec<u64>,
+    next_slot: u64,
+    pending: VecDeque<u64>,
+    access_clock: u64,
+    stats: SwapStats,
+}
+
+impl GuestSwap {
+    pub fn new(config: SwapConfig, backend: Box<dyn SwapBackend>) -> Result<Self, HypervisorError> {
+        if config.resident_limit_mb == 0
+            || config.low_watermark_percent >= config.high_watermark_percent
+            || config.high_watermark_percent > 100
+        {
+            return Err(HypervisorError::InvalidParameter);
+        }
+
+        Ok(GuestSwap {
+            config,
+            backend,
+            resident: BTreeMap::new(),
+            clock: VecDeque::new(),
+            swapped: BTreeMap::new(),
+            free_slots: Vec::new(),
+            next_slot: 0,
+            pending: VecDeque::new(),
+            access_clock: 0,
+            stats: SwapStats::default(),
+        })
+    }
+
+    pub fn config(&self) -> &SwapConfig {
+        &self.config
+    }
+
+    /// Start tracking a resident guest page
+    pub fn track_page(&mut self, guest_page: u64, host_address: u64) {
+        let guest_page = guest_page & !(PAGE_SIZE_4K - 1);
+        if self.resident.contains_key(&guest_page) || self.swapped.contains_key(&guest_page) {
+            return;
+        }
+        self.access_clock += 1;
+        self.resident.insert(guest_page, ResidentPage {
+            host_address,
+            last_access: self.access_clock,
+            referenced: true,
+        });
+        self.clock.push_back(guest_page);
+    }
+
+    /// Stop tracking a page that was unmapped, freeing its swap slot
+    pub fn untrack_page(&mut self, guest_page: u64) {
+        let guest_page = guest_page & !(PAGE_SIZE_4K - 1);
+        self.resident.remove(&guest_page);
+        self.clock.retain(|&page| page != guest_page);
+        self.pending.retain(|&page| page != guest_page);
+        if let Some(swapped) = self.swapped.remove(&guest_page) {
+            self.free_slots.push(swapped.slot);
+        }
+    }
+
+    /// Note a guest access, e.g. from the EPT accessed bits
+    pub fn record_access(&mut self, guest_addr: u64) {
+        self.access_clock += 1;
+        let clock = self.access_clock;
+        if let Some(page) = self.resident.get_mut(&(guest_addr & !(PAGE_SIZE_4K - 1))) {
+            page.last_access = clock;
+            page.referenced = true;
+        }
+    }
+
+    /// Whether a guest page is currently swapped out
+    pub fn is_swapped(&self, guest_addr: u64) -> bool {
+        self.swapped.contains_key(&(guest_addr & !(PAGE_SIZE_4K - 1)))
+    }
+
+    /// Whether a page-in of the page is waiting to be serviced
+    pub fn is_page_in_pending(&self, guest_addr: u64) -> bool {
+        self.pending.contains(&(guest_addr & !(PAGE_SIZE_4K - 1)))
+    }
+
+    /// Evict pages while residency is above the high watermark.
+    ///
+    /// Returns the evicted guest pages, whose EPT entries must be made
+    /// non-present.
+    pub fn balance(&mut self) -> Result<Vec<u64>, HypervisorError> {
+        let limit_pages = self.config.resident_limit_mb * (1024 * 1024 / PAGE_SIZE_4K);
+        let high = limit_pages * self.config.high_watermark_percent as u64 / 100;
+        let resident = self.resident.len() as u64;
+        if resident <= high {
+            return Ok(Vec::new());
+        }
+
+        let low = limit_pages * self.config.low_watermark_percent as u64 / 100;
+        self.evict((resident - low) as usize)
+    }
+
+    /// Evict up to `count` pages chosen by the eviction policy
+    pub fn evict(&mut self, count: usize) -> Result<Vec<u64>, HypervisorError> {
+        let mut evicted = Vec::new();
+        while evicted.len() < count {
+            let victim = match self.config.policy {
+                EvictionPolicy::Lru => self.lru_victim(),
+                EvictionPolicy::Clock => self.clock_victim(),
+            };
+            let guest_page = match victim {
+                Some(page) => page,
+                None => break,
+            };
+            let slot = match self.allocate_slot() {
+                Some(slot) => slot,
+                None => {
+                    warn!("Swap backend full, {} pages evicted", evicted.len());
+                    break;
+                }
+            };
+
+            let page = self.resident[&guest_page];
+            if let Err(error) = self.backend.page_out(slot, page.host_address) {
+                self.free_slots.push(slot);
+                return Err(error);
+            }
+            self.resident.remove(&guest_page);
+            self.clock.retain(|&resident| resident != guest_page);
+            self.swapped.insert(guest_page, SwappedPage { host_address: page.host_address, slot });
+            self.stats.swap_outs += 1;
+            evicted.push(guest_page);
+        }
+        Ok(evicted)
+    }
+
+    /// Handle an EPT fault, queueing a page-in if the page is swapped
+    pub fn handle_fault(&mut self, guest_addr: u64) -> SwapFaultOutcome {
+        let guest_page = guest_addr & !(PAGE_SIZE_4K - 1);
+        if !self.swapped.contains_key(&guest_page) {
+            return SwapFaultOutcome::NotSwapped;
+        }
+        if self.pending.contains(&guest_page) {
+            return SwapFaultOutcome::PageInPending;
+        }
+        self.stats.major_faults += 1;
+        self.pending.push_back(guest_page);
+        SwapFaultOutcome::PageInQueued
+    }
+
+    /// Service queued page-ins, up to `page_ins_per_pass`.
+    ///
+    /// Returns the guest pages that are resident again; their EPT entries
+    /// can be made present and the vCPUs waiting on them resumed.
+    pub fn process_page_ins(&mut self) -> Result<Vec<u64>, HypervisorError> {
+        let mut completed = Vec::new();
+        while completed.len() < self.config.page_ins_per_pass {
+            let guest_page = match self.pending.pop_front() {
+                Some(page) => page,
+                None => break,
+            };
+            let swapped = match self.swapped.get(&guest_page) {
+                Some(swapped) => *swapped,
+                None => continue,
+            };
+            if let Err(error) = self.backend.page_in(swapped.slot, swapped.host_address) {
+                // Retry on the next pass
+                self.pending.push_front(guest_page);
+                return Err(error);
+            }
+
+            self.swapped.remove(&guest_page);
+            self.free_slots.push(swapped.slot);
+            self.access_clock += 1;
+            self.resident.insert(guest_page, ResidentPage {
+                host_address: swapped.host_address,
+                last_access: self.access_clock,
+                referenced: true,
+            });
+            self.clock.push_back(guest_page);
+            self.stats.swap_ins += 1;
+            completed.push(guest_page);
+        }
+        Ok(completed)
+    }
+
+    pub fn stats(&self) -> SwapStats {
+        SwapStats {
+            resident_pages: self.resident.len() as u64,
+            swapped_pages: self.swapped.len() as u64,
+            pending_page_ins: self.pending.len() as u64,
+            ..self.stats
+        }
+    }
+
+    fn lru_victim(&self) -> Option<u64> {
+        self.resident
+            .iter()
+            .min_by_key(|(_, resident)| resident.last_access)
+            .map(|(&page, _)| page)
+    }
+
+    fn clock_victim(&mut self) -> Option<u64> {
+        // Two sweeps clear every referenced bit, so a victim is found if any page is resident
+        for _ in 0..self.clock.len() * 2 {
+            let guest_page = self.clock.pop_front()?;
+            self.clock.push_back(guest_page);
+            if let Some(page) = self.resident.get_mut(&guest_page) {
+                if page.referenced {
+                    page.referenced = false;
+                } else {
+                    return Some(guest_page);
+                }
+            }
+        }
+        None
+    }
+
+    fn allocate_slot(&mut self) -> Option<u64> {
+        if let Some(slot) = self.free_slots.pop() {
+            return Some(slot);
+        }
+        if self.next_slot < self.backend.slot_count() {
+            self.next_slot += 1;
+            return Some(self.next_slot - 1);
+        }
+        None
+    }
+}
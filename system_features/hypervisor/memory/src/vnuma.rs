@@ -0,0 +1,122 @@
+//! Virtual NUMA Presentation
+//!
+//! Lays the nodes of a `VnumaConfig` out in guest physical memory and
+//! builds the ACPI tables that describe them: the SRAT assigns each VCPU
+//! (by APIC ID) and each memory range to a proximity domain, the SLIT gives
+//! the distances between domains. Each node is backed by frames from the
+//! host NUMA node configured for it.
+
+use crate::core::{VnumaConfig, acpi_header, finish_acpi_table};
+
+use alloc::vec::Vec;
+
+const SRAT_REVISION: u8 = 3;
+const SLIT_REVISION: u8 = 1;
+/// SRAT structure types
+const SRAT_LOCAL_APIC_AFFINITY: u8 = 0;
+const SRAT_MEMORY_AFFINITY: u8 = 1;
+const SRAT_X2APIC_AFFINITY: u8 = 2;
+const SRAT_FLAG_ENABLED: u32 = 1;
+
+/// Guest physical range of one virtual node
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VnumaRange {
+    pub node: usize,
+    pub guest_base: u64,
+    pub size: u64,
+}
+
+/// Placement of the virtual nodes in guest physical memory
+#[derive(Debug, Clone, PartialEq)]
+pub struct VnumaLayout {
+    pub ranges: Vec<VnumaRange>,
+}
+
+impl VnumaLayout {
+    /// Place the nodes one after another starting at `ram_base`
+    pub fn new(config: &VnumaConfig, ram_base: u64) -> Self {
+        let mut guest_base = ram_base;
+        let ranges = config
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(node, vnode)| {
+                let range = VnumaRange { node, guest_base, size: vnode.memory_mb * 1024 * 1024 };
+                guest_base += range.size;
+                range
+            })
+            .collect();
+        VnumaLayout { ranges }
+    }
+
+    /// Node owning a guest physical address
+    pub fn node_of(&self, guest_addr: u64) -> Option<usize> {
+        self.ranges
+            .iter()
+            .find(|range| guest_addr >= range.guest_base && guest_addr - range.guest_base < range.size)
+            .map(|range| range.node)
+    }
+}
+
+/// Host frames backing one virtual node
+#[derive(Debug, Clone)]
+pub struct VnumaBacking {
+    pub node: usize,
+    pub host_node: usize,
+    pub guest_base: u64,
+    /// Host frames in guest address order
+    pub host_frames: Vec<u64>,
+}
+
+/// Build the SRAT for a topology; VCPU N has APIC ID N
+pub fn build_srat(config: &VnumaConfig, layout: &VnumaLayout) -> Vec<u8> {
+    let mut table = acpi_header(b"SRAT", SRAT_REVISION);
+    // Reserved field that must read 1, then 8 reserved bytes
+    table.extend_from_slice(&1u32.to_le_bytes());
+    table.extend_from_slice(&[0; 8]);
+
+    for (node, vnode) in config.nodes.iter().enumerate() {
+        let domain = node as u32;
+        for &vcpu in &vnode.vcpus {
+            let apic_id = vcpu as u32;
+            if apic_id < 0xFF {
+                table.extend_from_slice(&[SRAT_LOCAL_APIC_AFFINITY, 16, domain as u8, apic_id as u8]);
+                table.extend_from_slice(&SRAT_FLAG_ENABLED.to_le_bytes());
+                // Local SAPIC EID, proximity domain bits 31:8, clock domain
+                table.push(0);
+                table.extend_from_slice(&domain.to_le_bytes()[1..4]);
+                table.extend_from_slice(&0u32.to_le_bytes());
+            } else {
+                table.extend_from_slice(&[SRAT_X2APIC_AFFINITY, 24, 0, 0]);
+                table.extend_from_slice(&domain.to_le_bytes());
+                table.extend_from_slice(&apic_id.to_le_bytes());
+                table.extend_from_slice(&SRAT_FLAG_ENABLED.to_le_bytes());
+                table.extend_from_slice(&0u32.to_le_bytes());
+                table.extend_from_slice(&[0; 4]);
+            }
+        }
+    }
+
+    for range in &layout.ranges {
+        table.extend_from_slice(&[SRAT_MEMORY_AFFINITY, 40]);
+        table.extend_from_slice(&(range.node as u32).to_le_bytes());
+        table.extend_from_slice(&[0; 2]);
+        table.extend_from_slice(&range.guest_base.to_le_bytes());
+        table.extend_from_slice(&range.size.to_le_bytes());
+        table.extend_from_slice(&[0; 4]);
+        table.extend_from_slice(&SRAT_FLAG_ENABLED.to_le_bytes());
+        table.extend_from_slice(&[0; 8]);
+    }
+
+    finish_acpi_table(table)
+}
+
+/// Build the SLIT distance matrix for a topology
+pub fn build_slit(config: &VnumaConfig) -> Vec<u8> {
+    let mut table = acpi_header(b"SLIT", SLIT_REVISION);
+    table.extend_from_slice(&(config.nodes.len() as u64).to_le_bytes());
+    for vnode in &config.nodes {
+        table.extend_from_slice(&vnode.distances);
+    }
+    finish_acpi_table(table)
+}
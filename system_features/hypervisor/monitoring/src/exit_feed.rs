@@ -0,0 +1,148 @@
+//! VM Exit Feed for Teaching Mode
+//!
+//! For VMs in teaching mode every VM exit is decoded and kept in a bounded
+//! per-VM buffer. Front-ends and the CLI page through the buffer with a
+//! cursor: each page hands back the sequence number to continue after, so a
+//! reader that falls behind the buffer only loses the oldest exits and is
+//! told how many it missed.
+
+use crate::{VmId, VcpuId, HypervisorError, ConfigError, ConfigErrorReason};
+use crate::core::VcpuRegs;
+use crate::cpu::{DecodedExit, RawVmExit, decode_vm_exit};
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::vec::Vec;
+
+/// Largest page handed out at once
+pub const MAX_EXIT_PAGE_SIZE: usize = 256;
+
+/// A decoded VM exit in the feed
+#[derive(Debug, Clone)]
+pub struct AnnotatedExit {
+    /// Per-VM sequence number, starting at 1
+    pub sequence: u64,
+    pub timestamp_ns: u64,
+    pub vm_id: VmId,
+    pub vcpu_id: VcpuId,
+    pub exit: DecodedExit,
+}
+
+/// One page of the feed
+#[derive(Debug, Clone)]
+pub struct ExitFeedPage {
+    pub entries: Vec<AnnotatedExit>,
+    /// Cursor for the next page; pass it back to continue
+    pub next_cursor: u64,
+    /// Exits that fell out of the buffer before the reader got to them
+    pub missed: u64,
+    /// Whether more exits are buffered past this page
+    pub has_more: bool,
+}
+
+struct VmExitBuffer {
+    entries: VecDeque<AnnotatedExit>,
+    next_sequence: u64,
+}
+
+/// Buffered, decoded VM exits of the VMs in teaching mode
+pub struct ExitFeed {
+    capacity_per_vm: usize,
+    buffers: BTreeMap<VmId, VmExitBuffer>,
+}
+
+impl ExitFeed {
+    /// Create a feed keeping up to `capacity_per_vm` exits per VM
+    pub fn new(capacity_per_vm: usize) -> Self {
+        ExitFeed {
+            capacity_per_vm: capacity_per_vm.max(1),
+            buffers: BTreeMap::new(),
+        }
+    }
+
+    /// Start annotating the exits of a VM
+    pub fn enable_teaching_mode(&mut self, vm_id: VmId) {
+        self.buffers.entry(vm_id).or_insert_with(|| VmExitBuffer {
+            entries: VecDeque::new(),
+            next_sequence: 1,
+        });
+        info!("Teaching mode enabled for VM {}", vm_id.0);
+    }
+
+    /// Stop annotating the exits of a VM and drop its buffer
+    pub fn disable_teaching_mode(&mut self, vm_id: VmId) {
+        if self.buffers.remove(&vm_id).is_some() {
+            info!("Teaching mode disabled for VM {}", vm_id.0);
+        }
+    }
+
+    /// Whether a VM is in teaching mode
+    pub fn is_teaching_mode(&self, vm_id: VmId) -> bool {
+        self.buffers.contains_key(&vm_id)
+    }
+
+    /// Decode and buffer an exit. Returns its sequence number, or `None` if
+    /// the VM is not in teaching mode.
+    pub fn record(&mut self, vm_id: VmId, vcpu_id: VcpuId, timestamp_ns: u64, exit: &RawVmExit, regs: &VcpuRegs) -> Option<u64> {
+        let buffer = self.buffers.get_mut(&vm_id)?;
+
+        let sequence = buffer.next_sequence;
+        buffer.next_sequence += 1;
+        if buffer.entries.len() == self.capacity_per_vm {
+            buffer.entries.pop_front();
+        }
+        buffer.entries.push_back(AnnotatedExit {
+            sequence,
+            timestamp_ns,
+            vm_id,
+            vcpu_id,
+            exit: decode_vm_exit(exit, regs),
+        });
+        Some(sequence)
+    }
+
+    /// Page through the exits of a VM.
+    ///
+    /// `cursor` is 0 for the oldest buffered exit, or the `next_cursor` of
+    /// the previous page.
+    pub fn page(&self, vm_id: VmId, cursor: u64, limit: usize) -> Result<ExitFeedPage, HypervisorError> {
+        let buffer = self.buffers.get(&vm_id)
+            .ok_or_else(|| HypervisorError::from(ConfigError::new("teaching_mode", ConfigErrorReason::NotEnabled).vm(vm_id)))?;
+        if limit == 0 || limit > MAX_EXIT_PAGE_SIZE {
+            return Err(HypervisorError::InvalidParameter);
+        }
+
+        let oldest = buffer.entries.front().map_or(buffer.next_sequence, |entry| entry.sequence);
+        let first = (cursor + 1).max(oldest);
+        let missed = if cursor > 0 { first - (cursor + 1) } else { 0 };
+
+        let entries: Vec<AnnotatedExit> = buffer
+            .entries
+            .iter()
+            .skip((first - oldest) as usize)
+            .take(limit)
+            .cloned()
+            .collect();
+        let next_cursor = entries.last().map_or(first - 1, |entry| entry.sequence);
+
+        Ok(ExitFeedPage {
+            has_more: next_cursor + 1 < buffer.next_sequence,
+            entries,
+            next_cursor,
+            missed,
+        })
+    }
+
+    /// Most recent exits of a VM, newest last
+    pub fn latest(&self, vm_id: VmId, count: usize) -> Vec<&AnnotatedExit> {
+        match self.buffers.get(&vm_id) {
+            Some(buffer) => buffer.entries.iter().skip(buffer.entries.len().saturating_sub(count)).collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+impl Default for ExitFeed {
+    fn default() -> Self {
+        ExitFeed::new(1024)
+    }
+}
@@ -0,0 +1,186 @@
+//! VM Exit Reason Breakdown
+//!
+//! Counts VM exits and the time spent handling them per exit reason and
+//! per VCPU. Ranking the reasons by handling time shows where a slow guest
+//! loses its time: port I/O, MSR accesses or EPT faults.
+
+use crate::{VmId, VcpuId};
+use crate::core::VmExitReason;
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+/// Reasons shown by `top_exit_reasons` and the performance report
+pub const TOP_EXIT_REASONS: usize = 5;
+/// Share of the handling time a category needs to characterise a VM
+const DOMINANT_SHARE: f64 = 0.5;
+
+/// Exits of one reason
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ExitReasonStats {
+    pub count: u64,
+    /// Cumulative handling time
+    pub total_ns: u64,
+    pub max_ns: u64,
+}
+
+impl ExitReasonStats {
+    pub fn mean_ns(&self) -> u64 {
+        if self.count == 0 { 0 } else { self.total_ns / self.count }
+    }
+
+    fn add(&mut self, handling_ns: u64) {
+        self.count += 1;
+        self.total_ns += handling_ns;
+        self.max_ns = self.max_ns.max(handling_ns);
+    }
+
+    fn merge(&mut self, other: &ExitReasonStats) {
+        self.count += other.count;
+        self.total_ns += other.total_ns;
+        self.max_ns = self.max_ns.max(other.max_ns);
+    }
+}
+
+/// An exit reason's part of a VM's exits
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ExitReasonShare {
+    pub reason: VmExitReason,
+    pub stats: ExitReasonStats,
+    /// Fraction of all exits, 0.0 to 1.0
+    pub count_share: f64,
+    /// Fraction of all exit handling time, 0.0 to 1.0
+    pub time_share: f64,
+}
+
+/// What dominates a VM's exit handling time
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ExitProfile {
+    IoBound,
+    MsrHeavy,
+    EptFaultHeavy,
+    /// No category takes most of the time
+    Mixed,
+}
+
+impl ExitProfile {
+    pub fn description(&self) -> &'static str {
+        match self {
+            ExitProfile::IoBound => "I/O-bound",
+            ExitProfile::MsrHeavy => "MSR-heavy",
+            ExitProfile::EptFaultHeavy => "EPT-fault heavy",
+            ExitProfile::Mixed => "mixed",
+        }
+    }
+
+    fn of(reason: VmExitReason) -> Option<ExitProfile> {
+        match reason {
+            VmExitReason::IoInstruction => Some(ExitProfile::IoBound),
+            VmExitReason::MsrRead
+            | VmExitReason::MsrWrite
+            | VmExitReason::RdmsrInstruction
+            | VmExitReason::WrmsrInstruction => Some(ExitProfile::MsrHeavy),
+            VmExitReason::EnableEptViolation => Some(ExitProfile::EptFaultHeavy),
+            _ => None,
+        }
+    }
+}
+
+/// Per-VM, per-VCPU exit reason accounting
+#[derive(Debug, Clone, Default)]
+pub struct ExitHistogram {
+    vms: BTreeMap<VmId, BTreeMap<VcpuId, BTreeMap<VmExitReason, ExitReasonStats>>>,
+}
+
+impl ExitHistogram {
+    /// Account one handled exit
+    pub fn record(&mut self, vm_id: VmId, vcpu_id: VcpuId, reason: VmExitReason, handling_ns: u64) {
+        self.vms
+            .entry(vm_id)
+            .or_default()
+            .entry(vcpu_id)
+            .or_default()
+            .entry(reason)
+            .or_default()
+            .add(handling_ns);
+    }
+
+    /// Exit reasons of one VCPU
+    pub fn vcpu_breakdown(&self, vm_id: VmId, vcpu_id: VcpuId) -> Vec<(VmExitReason, ExitReasonStats)> {
+        self.vms
+            .get(&vm_id)
+            .and_then(|vcpus| vcpus.get(&vcpu_id))
+            .map(|reasons| reasons.iter().map(|(&reason, &stats)| (reason, stats)).collect())
+            .unwrap_or_default()
+    }
+
+    /// Exit reasons of a VM, summed over its VCPUs
+    pub fn vm_breakdown(&self, vm_id: VmId) -> BTreeMap<VmExitReason, ExitReasonStats> {
+        let mut totals: BTreeMap<VmExitReason, ExitReasonStats> = BTreeMap::new();
+        if let Some(vcpus) = self.vms.get(&vm_id) {
+            for reasons in vcpus.values() {
+                for (&reason, stats) in reasons {
+                    totals.entry(reason).or_default().merge(stats);
+                }
+            }
+        }
+        totals
+    }
+
+    /// Exit reasons of a VM ordered by handling time, then count
+    pub fn top(&self, vm_id: VmId, limit: usize) -> Vec<ExitReasonShare> {
+        let totals = self.vm_breakdown(vm_id);
+        let all_count: u64 = totals.values().map(|stats| stats.count).sum();
+        let all_ns: u64 = totals.values().map(|stats| stats.total_ns).sum();
+
+        let mut shares: Vec<ExitReasonShare> = totals
+            .into_iter()
+            .map(|(reason, stats)| ExitReasonShare {
+                reason,
+                stats,
+                count_share: if all_count == 0 { 0.0 } else { stats.count as f64 / all_count as f64 },
+                time_share: if all_ns == 0 { 0.0 } else { stats.total_ns as f64 / all_ns as f64 },
+            })
+            .collect();
+        shares.sort_by(|a, b| {
+            b.stats.total_ns.cmp(&a.stats.total_ns).then(b.stats.count.cmp(&a.stats.count))
+        });
+        shares.truncate(limit);
+        shares
+    }
+
+    /// Category taking most of a VM's exit handling time; by exit count
+    /// if no handling times were recorded. `None` without exits.
+    pub fn profile(&self, vm_id: VmId) -> Option<ExitProfile> {
+        let totals = self.vm_breakdown(vm_id);
+        let all_count: u64 = totals.values().map(|stats| stats.count).sum();
+        if all_count == 0 {
+            return None;
+        }
+        let all_ns: u64 = totals.values().map(|stats| stats.total_ns).sum();
+
+        let mut categories: BTreeMap<ExitProfile, u64> = BTreeMap::new();
+        for (reason, stats) in &totals {
+            if let Some(profile) = ExitProfile::of(*reason) {
+                let weight = if all_ns == 0 { stats.count } else { stats.total_ns };
+                *categories.entry(profile).or_insert(0) += weight;
+            }
+        }
+        let total = if all_ns == 0 { all_count } else { all_ns };
+        let dominant = categories
+            .into_iter()
+            .find(|&(_, weight)| weight as f64 / total as f64 >= DOMINANT_SHARE)
+            .map(|(profile, _)| profile);
+        Some(dominant.unwrap_or(ExitProfile::Mixed))
+    }
+
+    /// VMs with recorded exits
+    pub fn vm_ids(&self) -> Vec<VmId> {
+        self.vms.keys().copied().collect()
+    }
+
+    /// Forget the exits of a VM
+    pub fn reset_vm(&mut self, vm_id: VmId) {
+        self.vms.remove(&vm_id);
+    }
+}
@@ -3,16 +3,31 @@
 //! Provides comprehensive performance monitoring, debugging tools, and analysis
 //! for virtualized environments and educational purposes.
 
-use crate::{VmId, VcpuId, HypervisorError};
-use crate::core::{VmState, VmStats, CpuStats, HypervisorStats, MemoryStats};
-use crate::cpu::{VmExitReason, VmcsRegion, VmcbRegion};
+use crate::{VmId, VcpuId, HypervisorError, ConfigError, ConfigErrorReason};
+use crate::core::{VmState, VmStats, CpuStats, HypervisorStats, MemoryStats, VcpuRegs};
+use crate::cpu::{VmExitReason, VmcsRegion, VmcbRegion, RawVmExit};
 use crate::memory::{MemoryManager, PerformanceCounters};
 
+use alloc::boxed::Box;
 use alloc::vec::Vec;
 use alloc::collections::BTreeMap;
 use spin::RwLock;
 use core::time::Duration;
 
+mod exit_feed;
+mod stats_history;
+mod exit_histogram;
+mod slo;
+mod time_travel;
+mod sinks;
+
+pub use exit_feed::*;
+pub use stats_history::*;
+pub use exit_histogram::*;
+pub use slo::*;
+pub use time_travel::*;
+pub use sinks::*;
+
 /// Performance metric types
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum MetricType {
@@ -25,6 +40,7 @@ pub enum MetricType {
     ContextSwitchRate,
     PageFaultRate,
     HypervisorOverhead,
+    VMExitLatency,
 }
 
 /// Performance sample structure
@@ -156,6 +172,18 @@ pub struct PerformanceMonitor {
     start_time_ms: u64,
     /// Total samples collected
     total_samples_collected: u64,
+    /// Downsampled per-VM time series
+    history: StatsHistory,
+    /// Latest cumulative device I/O counters per VM (operations, bytes)
+    vm_io_counters: BTreeMap<VmId, (u64, u64)>,
+    /// Decoded VM exits of VMs in teaching mode
+    exit_feed: ExitFeed,
+    /// Exit counts and handling time per exit reason
+    exit_histogram: ExitHistogram,
+    /// Service level objectives and their burn rates
+    slos: SloTracker,
+    /// Sinks samples and alerts are streamed to
+    exporter: MetricExporter,
 }
 
 impl PerformanceMonitor {
@@ -170,13 +198,19 @@ impl PerformanceMonitor {
             profiling_sessions: BTreeMap::new(),
             start_time_ms: 0, // Would use actual timestamp
             total_samples_collected: 0,
+            history: StatsHistory::default(),
+            vm_io_counters: BTreeMap::new(),
+            exit_feed: ExitFeed::default(),
+            exit_histogram: ExitHistogram::default(),
+            slos: SloTracker::default(),
+            exporter: MetricExporter::new(),
         }
     }
     
     /// Start monitoring
     pub fn start_monitoring(&mut self) -> Result<(), HypervisorError> {
         if self.config.enabled {
-            return Err(HypervisorError::ConfigurationError(String::from("Monitoring already enabled")));
+            return Err(ConfigError::new("monitoring", ConfigErrorReason::AlreadyEnabled).into());
         }
         
         self.config.enabled = true;
@@ -189,7 +223,7 @@ impl PerformanceMonitor {
     /// Stop monitoring
     pub fn stop_monitoring(&mut self) -> Result<(), HypervisorError> {
         if !self.config.enabled {
-            return Err(HypervisorError::ConfigurationError(String::from("Monitoring already disabled")));
+            return Err(ConfigError::new("monitoring", ConfigErrorReason::NotEnabled).into());
         }
         
         self.config.enabled = false;
@@ -201,7 +235,7 @@ impl PerformanceMonitor {
     /// Collect performance sample
     pub fn collect_sample(&mut self, sample: PerformanceSample) -> Result<(), HypervisorError> {
         if !self.config.enabled {
-            return Err(HypervisorError::ConfigurationError(String::from("Monitoring not enabled")));
+            return Err(ConfigError::new("monitoring", ConfigErrorReason::NotEnabled).into());
         }
         
         // Store sample if retention period allows
@@ -218,7 +252,16 @@ impl PerformanceMonitor {
         }
         
         // Check for alerts
+        let first_new_alert = self.alerts.len();
         self.check_alerts(&sample)?;
+        let slo_alerts = self.slos.record_sample(&sample);
+        self.raise_slo_alerts(slo_alerts, sample.timestamp_ms);
+        
+        // Stream to sinks
+        self.exporter.publish_sample(&sample);
+        for alert in self.alerts.iter().skip(first_new_alert) {
+            self.exporter.publish_alert(alert);
+        }
         
         // Add trace if enabled
         if self.config.enable_tracing {
@@ -228,6 +271,27 @@ impl PerformanceMonitor {
         Ok(())
     }
     
+    /// Stream samples and alerts to a sink, buffering up to `capacity_bytes`
+    pub fn add_metric_sink(&mut self, sink: Box<dyn MetricSink>, capacity_bytes: usize, policy: OverflowPolicy) -> SinkId {
+        self.exporter.add_sink(sink, capacity_bytes, policy)
+    }
+    
+    /// Remove a sink, returning it and its final statistics
+    pub fn remove_metric_sink(&mut self, id: SinkId) -> Result<(Box<dyn MetricSink>, SinkStats), HypervisorError> {
+        self.exporter.remove_sink(id)
+    }
+    
+    /// Delivery and drop counters of a sink
+    pub fn metric_sink_stats(&self, id: SinkId) -> Option<SinkStats> {
+        self.exporter.stats(id)
+    }
+    
+    /// Drain buffered records into the sinks; call it from the monitoring
+    /// loop. Returns the records delivered.
+    pub fn pump_metric_sinks(&mut self) -> usize {
+        self.exporter.pump()
+    }
+    
     /// Collect VM performance metrics
     pub fn collect_vm_metrics(&mut self, vm_id: VmId, vm_stats: &VmStats, hypervisor_stats: &HypervisorStats) -> Result<(), HypervisorError> {
         let timestamp = self.get_current_time_ms();
@@ -289,9 +353,118 @@ impl PerformanceMonitor {
             unit: String::from("percent"),
         })?;
         
+        // Keep the time series for historical queries
+        let (io_operations, io_bytes) = self.vm_io_counters.get(&vm_id).copied().unwrap_or((0, 0));
+        self.history.record_vm_stats(vm_id, timestamp, vm_stats, io_operations, io_bytes);
+        
         Ok(())
     }
     
+    /// Update the cumulative device I/O counters of a VM, picked up by the next `collect_vm_metrics`
+    pub fn record_vm_io(&mut self, vm_id: VmId, io_operations: u64, io_bytes: u64) {
+        self.vm_io_counters.insert(vm_id, (io_operations, io_bytes));
+    }
+    
+    /// Query the downsampled history of a VM metric
+    pub fn query_history(&self, vm_id: VmId, metric: HistoryMetric, range: TimeRange, resolution_ms: u64) -> Result<StatsSeries, HypervisorError> {
+        self.history.query(vm_id, metric, range, resolution_ms)
+    }
+    
+    /// Per-VM stats history
+    pub fn stats_history(&self) -> &StatsHistory {
+        &self.history
+    }
+    
+    /// Replace the stats history, e.g. to use custom retention tiers
+    pub fn set_stats_history(&mut self, history: StatsHistory) {
+        self.history = history;
+        self.vm_io_counters.clear();
+    }
+    
+    /// Record a VM exit: VMs in teaching mode get it decoded into the exit
+    /// feed, and it is traced when tracing is enabled
+    pub fn record_vm_exit(&mut self, vm_id: VmId, vcpu_id: VcpuId, exit: &RawVmExit, regs: &VcpuRegs) -> Option<u64> {
+        let timestamp_ns = self.get_current_time_ms() * 1_000_000;
+        
+        if self.config.enable_tracing {
+            self.traces.push(DebugTraceEntry {
+                timestamp_ns,
+                trace_type: TraceType::VMExit,
+                vm_id: Some(vm_id),
+                vcpu_id: Some(vcpu_id),
+                data: TraceData::VMExitReason(exit.reason),
+            });
+        }
+        
+        self.exit_feed.record(vm_id, vcpu_id, timestamp_ns, exit, regs)
+    }
+    
+    /// Account the time spent handling a VM exit
+    pub fn record_exit_handled(&mut self, vm_id: VmId, vcpu_id: VcpuId, reason: VmExitReason, handling_ns: u64) {
+        self.exit_histogram.record(vm_id, vcpu_id, reason, handling_ns);
+        
+        let timestamp_ms = self.get_current_time_ms();
+        let slo_alerts = self.slos.record_exit(vm_id, timestamp_ms, handling_ns);
+        self.raise_slo_alerts(slo_alerts, timestamp_ms);
+    }
+    
+    /// Track a service level objective
+    pub fn add_slo(&mut self, slo: Slo) -> Result<SloId, HypervisorError> {
+        self.slos.add(slo)
+    }
+    
+    /// Stop tracking a service level objective
+    pub fn remove_slo(&mut self, id: SloId) -> Result<(), HypervisorError> {
+        self.slos.remove(id)
+    }
+    
+    /// Compliance, remaining error budget and burn rates of an SLO
+    pub fn slo_status(&self, id: SloId) -> Result<SloStatus, HypervisorError> {
+        self.slos.status(id)
+    }
+    
+    /// Status of every SLO
+    pub fn slo_statuses(&self) -> Vec<SloStatus> {
+        self.slos.statuses()
+    }
+    
+    /// Turn SLO burn-rate alerts into performance alerts
+    fn raise_slo_alerts(&mut self, slo_alerts: Vec<SloAlert>, timestamp_ms: u64) {
+        for slo_alert in slo_alerts {
+            warn!("Performance alert: {}", slo_alert.message);
+            self.alerts.push(PerformanceAlert {
+                id: format!("slo_{}_{}", slo_alert.slo, timestamp_ms),
+                severity: slo_alert.severity,
+                metric_type: slo_alert.metric_type,
+                current_value: slo_alert.burn_rate,
+                threshold_value: slo_alert.threshold,
+                message: slo_alert.message,
+                timestamp_ms,
+                vm_id: slo_alert.vm_id,
+            });
+        }
+    }
+    
+    /// Exit reasons of a VM that took the most handling time
+    pub fn top_exit_reasons(&self, vm_id: VmId) -> Vec<ExitReasonShare> {
+        self.exit_histogram.top(vm_id, TOP_EXIT_REASONS)
+    }
+    
+    /// Per-VCPU exit reason accounting
+    pub fn exit_histogram(&self) -> &ExitHistogram {
+        &self.exit_histogram
+    }
+    
+    /// Decoded VM exit feed
+    pub fn exit_feed(&self) -> &ExitFeed {
+        &self.exit_feed
+    }
+    
+    /// Decoded VM exit feed, e.g. to switch teaching mode on or off
+    pub fn exit_feed_mut(&mut self) -> &mut ExitFeed {
+        &mut self.exit_feed
+    }
+    
     /// Add debug trace entry
     pub fn add_trace_entry(&mut self, sample: PerformanceSample) -> Result<(), HypervisorError> {
         if !self.config.enable_tracing {
@@ -419,6 +592,7 @@ impl PerformanceMonitor {
             MetricType::ContextSwitchRate => "Context Switch Rate",
             MetricType::PageFaultRate => "Page Fault Rate",
             MetricType::HypervisorOverhead => "Hypervisor Overhead",
+            MetricType::VMExitLatency => "VM Exit Latency",
         }
     }
     
@@ -433,7 +607,7 @@ impl PerformanceMonitor {
     /// Start profiling session
     pub fn start_profiling(&mut self, session_id: String, vm_id: VmId, profile_type: ProfileType) -> Result<(), HypervisorError> {
         if self.profiling_sessions.contains_key(&session_id) {
-            return Err(HypervisorError::ConfigurationError(String::from("Profiling session already exists")));
+            return Err(ConfigError::new("profiling_session", ConfigErrorReason::AlreadyExists).vm(vm_id).into());
         }
         
         let profiling_data = ProfilingData {
@@ -460,7 +634,7 @@ impl PerformanceMonitor {
     /// Stop profiling session
     pub fn stop_profiling(&mut self, session_id: String) -> Result<ProfilingData, HypervisorError> {
         let mut profiling_data = self.profiling_sessions.remove(&session_id)
-            .ok_or(HypervisorError::ConfigurationError(format!("Profiling session '{}' not found", session_id)))?;
+            .ok_or(HypervisorError::from(ConfigError::new("profiling_session", ConfigErrorReason::NotFound)))?;
         
         // Calculate summary statistics
         profiling_data.summary = self.calculate_profile_summary(&profiling_data.samples);
@@ -617,6 +791,41 @@ impl PerformanceMonitor {
                                   sample.unit));
         }
         
+        // Exit reason breakdown
+        let exit_vms = self.exit_histogram.vm_ids();
+        if !exit_vms.is_empty() {
+            report.push_str("\nVM Exit Breakdown:\n");
+            for vm_id in exit_vms {
+                let profile = self.exit_histogram.profile(vm_id).unwrap_or(ExitProfile::Mixed);
+                report.push_str(&format!("  VM{} ({}):\n", vm_id.0, profile.description()));
+                for share in self.top_exit_reasons(vm_id) {
+                    report.push_str(&format!("    {:?}: {} exits ({:.1}%), {} us handling ({:.1}%), mean {} ns\n",
+                                          share.reason,
+                                          share.stats.count,
+                                          share.count_share * 100.0,
+                                          share.stats.total_ns / 1000,
+                                          share.time_share * 100.0,
+                                          share.stats.mean_ns()));
+                }
+            }
+        }
+        
+        // Service level objectives
+        let slo_statuses = self.slo_statuses();
+        if !slo_statuses.is_empty() {
+            report.push_str("\nService Level Objectives:\n");
+            for status in slo_statuses {
+                let firing = status.burn_rates.iter().filter(|rate| rate.firing).count();
+                report.push_str(&format!("  {}: {:.3}% good ({} of {}), {:.1}% budget left{}\n",
+                                      status.name,
+                                      status.compliance * 100.0,
+                                      status.good_events,
+                                      status.total_events,
+                                      status.budget_remaining * 100.0,
+                                      if firing > 0 { " [BURNING]" } else { "" }));
+            }
+        }
+        
         // Profiling sessions
         if !self.profiling_sessions.is_empty() {
             report.push_str("\nActive Profiling Sessions:\n");
@@ -0,0 +1,504 @@
+//! Metric Sinks
+//!
+//! Streams samples and alerts out of the hypervisor: to a serial console,
+//! to rotating log files when built with `std`, or to a collector over UDP
+//! or TCP. Each sink has a bounded buffer in front of it. A sink that
+//! cannot keep up pushes back by taking fewer bytes; once its buffer is
+//! full, records are dropped according to its overflow policy and every
+//! drop is counted, so monitoring never blocks the hypervisor.
+
+use crate::{HypervisorError, IoError, IoErrorKind};
+use super::{PerformanceSample, PerformanceAlert};
+
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Default buffer size of a sink
+pub const DEFAULT_SINK_BUFFER_BYTES: usize = 64 * 1024;
+
+/// Largest UDP payload that avoids IPv4 fragmentation on Ethernet
+pub const MAX_UDP_PAYLOAD: usize = 1472;
+
+/// Destination for encoded monitoring records
+pub trait MetricSink: Send {
+    fn name(&self) -> &str;
+
+    /// Offer (the rest of) one record and return how many bytes were
+    /// taken; 0 means the sink is full for now. Record-oriented sinks take
+    /// a record whole or not at all. After an error the record is offered
+    /// again from its start.
+    fn write(&mut self, record: &[u8]) -> Result<usize, HypervisorError>;
+
+    fn flush(&mut self) -> Result<(), HypervisorError> {
+        Ok(())
+    }
+
+    /// Longer records are dropped instead of buffered
+    fn max_record_len(&self) -> usize {
+        usize::MAX
+    }
+}
+
+/// Text line for a sample:
+/// `sample <timestamp_ms> <metric> <value> <unit> vm=<id> vcpu=<id>`
+pub fn encode_sample(sample: &PerformanceSample) -> Vec<u8> {
+    let unit = if sample.unit.is_empty() { String::from("-") } else { sample.unit.replace(' ', "_") };
+    format!("sample {} {:?} {} {} vm={} vcpu={}\n",
+            sample.timestamp_ms, sample.metric_type, sample.value, unit,
+            optional_id(sample.vm_id.map(|vm_id| vm_id.0)),
+            optional_id(sample.vcpu_id.map(|vcpu_id| vcpu_id.0)))
+        .into_bytes()
+}
+
+/// Text line for an alert:
+/// `alert <timestamp_ms> <severity> <metric> <value> <threshold> vm=<id> <message>`
+pub fn encode_alert(alert: &PerformanceAlert) -> Vec<u8> {
+    format!("alert {} {:?} {:?} {} {} vm={} {}\n",
+            alert.timestamp_ms, alert.severity, alert.metric_type, alert.current_value, alert.threshold_value,
+            optional_id(alert.vm_id.map(|vm_id| vm_id.0)), alert.message.replace('\n', " "))
+        .into_bytes()
+}
+
+fn optional_id(id: Option<u32>) -> String {
+    id.map_or(String::from("-"), |id| format!("{}", id))
+}
+
+/// What a full sink buffer gives up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Keep the buffered records and drop the new one
+    DropNewest,
+    /// Drop the oldest records to make room
+    DropOldest,
+}
+
+/// Delivery and drop counters of a sink
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SinkStats {
+    pub records_queued: u64,
+    pub records_delivered: u64,
+    pub records_dropped: u64,
+    pub bytes_dropped: u64,
+    pub write_errors: u64,
+    /// Bytes waiting in the buffer
+    pub queued_bytes: usize,
+    pub peak_queued_bytes: usize,
+}
+
+/// Identifier of a sink in a `MetricExporter`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SinkId(pub u32);
+
+struct BufferedSink {
+    sink: Box<dyn MetricSink>,
+    queue: VecDeque<Vec<u8>>,
+    /// Bytes of the front record the sink already took
+    front_offset: usize,
+    capacity_bytes: usize,
+    policy: OverflowPolicy,
+    stats: SinkStats,
+}
+
+impl BufferedSink {
+    fn enqueue(&mut self, record: &[u8]) {
+        if record.len() > self.capacity_bytes || record.len() > self.sink.max_record_len() {
+            self.drop_record(record.len());
+            return;
+        }
+        while self.stats.queued_bytes + record.len() > self.capacity_bytes {
+            // A partly written record has to be finished to keep the
+            // stream intact
+            let oldest = if self.front_offset > 0 { 1 } else { 0 };
+            if self.policy == OverflowPolicy::DropNewest || oldest >= self.queue.len() {
+                self.drop_record(record.len());
+                return;
+            }
+            if let Some(dropped) = self.queue.remove(oldest) {
+                self.stats.queued_bytes -= dropped.len();
+                self.drop_record(dropped.len());
+            }
+        }
+
+        self.queue.push_back(record.to_vec());
+        self.stats.records_queued += 1;
+        self.stats.queued_bytes += record.len();
+        self.stats.peak_queued_bytes = self.stats.peak_queued_bytes.max(self.stats.queued_bytes);
+    }
+
+    fn drop_record(&mut self, len: usize) {
+        self.stats.records_dropped += 1;
+        self.stats.bytes_dropped += len as u64;
+    }
+
+    /// Hand buffered records to the sink until it pushes back
+    fn pump(&mut self) -> usize {
+        let mut delivered = 0;
+        while let Some(record) = self.queue.front() {
+            match self.sink.write(&record[self.front_offset..]) {
+                Ok(0) => break,
+                Ok(taken) => {
+                    let taken = taken.min(record.len() - self.front_offset);
+                    self.front_offset += taken;
+                    self.stats.queued_bytes -= taken;
+                    if self.front_offset == record.len() {
+                        self.queue.pop_front();
+                        self.front_offset = 0;
+                        self.stats.records_delivered += 1;
+                        delivered += 1;
+                    }
+                }
+                Err(e) => {
+                    warn!("Metric sink {} failed: {}", self.sink.name(), e);
+                    self.stats.write_errors += 1;
+                    self.stats.queued_bytes += self.front_offset;
+                    self.front_offset = 0;
+                    break;
+                }
+            }
+        }
+        if delivered > 0 {
+            if let Err(e) = self.sink.flush() {
+                warn!("Flushing metric sink {} failed: {}", self.sink.name(), e);
+                self.stats.write_errors += 1;
+            }
+        }
+        delivered
+    }
+}
+
+/// Fans monitoring records out to the registered sinks
+#[derive(Default)]
+pub struct MetricExporter {
+    sinks: BTreeMap<SinkId, BufferedSink>,
+    next_id: u32,
+}
+
+impl MetricExporter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a sink buffering up to `capacity_bytes`
+    pub fn add_sink(&mut self, sink: Box<dyn MetricSink>, capacity_bytes: usize, policy: OverflowPolicy) -> SinkId {
+        self.next_id += 1;
+        let id = SinkId(self.next_id);
+        info!("Added metric sink {} with a {} byte buffer", sink.name(), capacity_bytes);
+        self.sinks.insert(id, BufferedSink {
+            sink,
+            queue: VecDeque::new(),
+            front_offset: 0,
+            capacity_bytes,
+            policy,
+            stats: SinkStats::default(),
+        });
+        id
+    }
+
+    /// Remove a sink after giving it one last chance to drain; records
+    /// still buffered are counted as dropped in the returned stats
+    pub fn remove_sink(&mut self, id: SinkId) -> Result<(Box<dyn MetricSink>, SinkStats), HypervisorError> {
+        let mut buffered = self.sinks.remove(&id)
+            .ok_or_else(|| HypervisorError::from(IoError::new("metric_sink", IoErrorKind::NoDevice)))?;
+        buffered.pump();
+        for record in core::mem::take(&mut buffered.queue) {
+            buffered.drop_record(record.len());
+        }
+        buffered.stats.queued_bytes = 0;
+        Ok((buffered.sink, buffered.stats))
+    }
+
+    pub fn stats(&self, id: SinkId) -> Option<SinkStats> {
+        self.sinks.get(&id).map(|buffered| buffered.stats)
+    }
+
+    pub fn sink_ids(&self) -> impl Iterator<Item = SinkId> + '_ {
+        self.sinks.keys().copied()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sinks.is_empty()
+    }
+
+    /// Buffer an encoded record for every sink
+    pub fn publish(&mut self, record: &[u8]) {
+        for buffered in self.sinks.values_mut() {
+            buffered.enqueue(record);
+        }
+    }
+
+    pub fn publish_sample(&mut self, sample: &PerformanceSample) {
+        if !self.sinks.is_empty() {
+            self.publish(&encode_sample(sample));
+        }
+    }
+
+    pub fn publish_alert(&mut self, alert: &PerformanceAlert) {
+        if !self.sinks.is_empty() {
+            self.publish(&encode_alert(alert));
+        }
+    }
+
+    /// Move buffered records into the sinks; returns the records delivered
+    pub fn pump(&mut self) -> usize {
+        self.sinks.values_mut().map(|buffered| buffered.pump()).sum()
+    }
+}
+
+/// Transmit side of a serial port
+pub trait SerialTx: Send {
+    /// Queue bytes for transmission and return how many fit in the FIFO
+    fn try_write(&mut self, data: &[u8]) -> usize;
+}
+
+/// Streams records to a serial console
+pub struct SerialSink<T: SerialTx> {
+    port: T,
+}
+
+impl<T: SerialTx> SerialSink<T> {
+    pub fn new(port: T) -> Self {
+        SerialSink { port }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.port
+    }
+}
+
+impl<T: SerialTx> MetricSink for SerialSink<T> {
+    fn name(&self) -> &str {
+        "serial"
+    }
+
+    fn write(&mut self, record: &[u8]) -> Result<usize, HypervisorError> {
+        Ok(self.port.try_write(record))
+    }
+}
+
+/// Connected datagram socket
+pub trait DatagramSocket: Send {
+    /// Send one datagram; `Ok(false)` if the socket would block
+    fn send(&mut self, datagram: &[u8]) -> Result<bool, HypervisorError>;
+}
+
+/// Connected stream socket
+pub trait StreamSocket: Send {
+    /// Send bytes and return how many were taken; `Ok(0)` if the socket
+    /// would block
+    fn send(&mut self, data: &[u8]) -> Result<usize, HypervisorError>;
+
+    /// Set up a new connection after an error
+    fn reconnect(&mut self) -> Result<(), HypervisorError>;
+}
+
+/// Exports records to a collector, one UDP datagram per record
+pub struct UdpExporter<S: DatagramSocket> {
+    socket: S,
+}
+
+impl<S: DatagramSocket> UdpExporter<S> {
+    pub fn new(socket: S) -> Self {
+        UdpExporter { socket }
+    }
+}
+
+impl<S: DatagramSocket> MetricSink for UdpExporter<S> {
+    fn name(&self) -> &str {
+        "udp"
+    }
+
+    fn write(&mut self, record: &[u8]) -> Result<usize, HypervisorError> {
+        Ok(if self.socket.send(record)? { record.len() } else { 0 })
+    }
+
+    fn max_record_len(&self) -> usize {
+        MAX_UDP_PAYLOAD
+    }
+}
+
+/// Exports records to a collector over a TCP stream, reconnecting after
+/// the connection fails
+pub struct TcpExporter<S: StreamSocket> {
+    socket: S,
+    broken: bool,
+}
+
+impl<S: StreamSocket> TcpExporter<S> {
+    pub fn new(socket: S) -> Self {
+        TcpExporter { socket, broken: false }
+    }
+}
+
+impl<S: StreamSocket> MetricSink for TcpExporter<S> {
+    fn name(&self) -> &str {
+        "tcp"
+    }
+
+    fn write(&mut self, record: &[u8]) -> Result<usize, HypervisorError> {
+        if self.broken {
+            self.socket.reconnect()?;
+            self.broken = false;
+            info!("Metric exporter reconnected");
+        }
+        self.socket.send(record).map_err(|e| {
+            self.broken = true;
+            e
+        })
+    }
+}
+
+#[cfg(feature = "std")]
+mod host {
+    use super::{DatagramSocket, MetricSink, StreamSocket};
+    use crate::{HypervisorError, IoError, IoErrorKind};
+
+    use std::format;
+    use std::fs::{self, File, OpenOptions};
+    use std::io::{self, Write};
+    use std::net::{SocketAddr, TcpStream, UdpSocket};
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    const CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+    fn backend(source: &'static str) -> impl Fn(io::Error) -> HypervisorError {
+        move |e| {
+            warn!("{}: {}", source, e);
+            IoError::new(source, IoErrorKind::Backend).into()
+        }
+    }
+
+    impl DatagramSocket for UdpSocket {
+        fn send(&mut self, datagram: &[u8]) -> Result<bool, HypervisorError> {
+            match UdpSocket::send(self, datagram) {
+                Ok(_) => Ok(true),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+                Err(e) => Err(backend("metric_sink.udp")(e)),
+            }
+        }
+    }
+
+    /// Non-blocking TCP connection to a collector
+    pub struct TcpCollector {
+        address: SocketAddr,
+        stream: Option<TcpStream>,
+    }
+
+    impl TcpCollector {
+        pub fn connect(address: SocketAddr) -> Result<Self, HypervisorError> {
+            let mut collector = TcpCollector { address, stream: None };
+            collector.reconnect()?;
+            Ok(collector)
+        }
+    }
+
+    impl StreamSocket for TcpCollector {
+        fn send(&mut self, data: &[u8]) -> Result<usize, HypervisorError> {
+            let stream = self.stream.as_mut()
+                .ok_or_else(|| HypervisorError::from(IoError::new("metric_sink.tcp", IoErrorKind::Closed)))?;
+            match stream.write(data) {
+                Ok(0) => {
+                    self.stream = None;
+                    Err(IoError::new("metric_sink.tcp", IoErrorKind::Closed).into())
+                }
+                Ok(written) => Ok(written),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(0),
+                Err(e) => {
+                    self.stream = None;
+                    Err(backend("metric_sink.tcp")(e))
+                }
+            }
+        }
+
+        fn reconnect(&mut self) -> Result<(), HypervisorError> {
+            self.stream = None;
+            let stream = TcpStream::connect_timeout(&self.address, CONNECT_TIMEOUT).map_err(backend("metric_sink.tcp"))?;
+            stream.set_nonblocking(true).map_err(backend("metric_sink.tcp"))?;
+            stream.set_nodelay(true).map_err(backend("metric_sink.tcp"))?;
+            self.stream = Some(stream);
+            Ok(())
+        }
+    }
+
+    /// Appends records to a log file, rotating it to `<path>.1` ...
+    /// `<path>.<keep>` once it reaches `max_bytes`
+    pub struct RotatingFileSink {
+        path: PathBuf,
+        max_bytes: u64,
+        keep: u32,
+        file: Option<File>,
+        written: u64,
+    }
+
+    impl RotatingFileSink {
+        pub fn new(path: impl Into<PathBuf>, max_bytes: u64, keep: u32) -> Self {
+            RotatingFileSink { path: path.into(), max_bytes, keep, file: None, written: 0 }
+        }
+
+        fn rotated_path(&self, generation: u32) -> PathBuf {
+            let mut path = self.path.clone().into_os_string();
+            path.push(format!(".{}", generation));
+            path.into()
+        }
+
+        fn rotate(&mut self) -> io::Result<()> {
+            self.file = None;
+            if self.keep == 0 {
+                fs::remove_file(&self.path)?;
+            } else {
+                for generation in (1..self.keep).rev() {
+                    let from = self.rotated_path(generation);
+                    if from.exists() {
+                        fs::rename(&from, self.rotated_path(generation + 1))?;
+                    }
+                }
+                fs::rename(&self.path, self.rotated_path(1))?;
+            }
+            self.written = 0;
+            Ok(())
+        }
+
+        fn open(&mut self) -> io::Result<&mut File> {
+            if self.file.is_none() {
+                let file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+                self.written = file.metadata()?.len();
+                self.file = Some(file);
+            }
+            Ok(self.file.as_mut().unwrap())
+        }
+    }
+
+    impl MetricSink for RotatingFileSink {
+        fn name(&self) -> &str {
+            "file"
+        }
+
+        fn write(&mut self, record: &[u8]) -> Result<usize, HypervisorError> {
+            self.open().map_err(backend("metric_sink.file"))?;
+            if self.written > 0 && self.written + record.len() as u64 > self.max_bytes {
+                self.rotate().map_err(backend("metric_sink.file"))?;
+            }
+            self.open()
+                .and_then(|file| file.write_all(record))
+                .map_err(|e| {
+                    self.file = None;
+                    backend("metric_sink.file")(e)
+                })?;
+            self.written += record.len() as u64;
+            Ok(record.len())
+        }
+
+        fn flush(&mut self) -> Result<(), HypervisorError> {
+            match self.file.as_mut() {
+                Some(file) => file.flush().map_err(backend("metric_sink.file")),
+                None => Ok(()),
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+pub use host::*;
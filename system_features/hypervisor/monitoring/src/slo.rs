@@ -0,0 +1,333 @@
+//! Service Level Objectives
+//!
+//! An SLO states the fraction of events that must be good over a rolling
+//! window, e.g. "99% of VM exits handled in under 20 µs over 1 h". Events
+//! are counted in small time buckets as monitoring data arrives. Alerts use
+//! multi-window burn rates: the rate at which the error budget is being
+//! spent must exceed a rule's threshold over both a long window (the budget
+//! is really going) and a short one (it is still going now), so a rule fires
+//! quickly on a sharp regression and clears soon after it recovers.
+
+use crate::{VmId, HypervisorError, ConfigError, ConfigErrorReason};
+use super::{AlertSeverity, MetricType, PerformanceSample};
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Buckets per SLO window; sets the time resolution of the burn rates
+const BUCKETS_PER_WINDOW: u64 = 720;
+
+/// SLO identifier
+pub type SloId = u32;
+
+/// What makes an event good
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SloIndicator {
+    /// A VM exit handled in less than `threshold_ns`
+    ExitLatencyBelow { threshold_ns: u64 },
+    /// A monitoring sample of `metric` below `threshold`
+    MetricBelow { metric: MetricType, threshold: f64 },
+}
+
+impl SloIndicator {
+    fn metric(&self) -> MetricType {
+        match self {
+            SloIndicator::ExitLatencyBelow { .. } => MetricType::VMExitLatency,
+            SloIndicator::MetricBelow { metric, .. } => *metric,
+        }
+    }
+}
+
+/// Burn rate that has to be exceeded over both windows for an alert
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BurnRateRule {
+    pub long_window_ms: u64,
+    pub short_window_ms: u64,
+    /// Multiple of the sustainable error rate, 1.0 spends exactly the budget
+    pub burn_rate: f64,
+    pub severity: AlertSeverity,
+}
+
+/// A service level objective
+#[derive(Debug, Clone)]
+pub struct Slo {
+    pub name: String,
+    /// VM whose events count, or every VM
+    pub vm_id: Option<VmId>,
+    pub indicator: SloIndicator,
+    /// Fraction of good events required, e.g. 0.99
+    pub target: f64,
+    pub window_ms: u64,
+    pub rules: Vec<BurnRateRule>,
+}
+
+impl Slo {
+    /// An SLO with the default burn-rate rules for its window: critical
+    /// when a twelfth of the window burns half the budget, warning when a
+    /// quarter of the window does
+    pub fn new(name: &str, vm_id: Option<VmId>, indicator: SloIndicator, target: f64, window_ms: u64) -> Self {
+        Slo {
+            name: String::from(name),
+            vm_id,
+            indicator,
+            target,
+            window_ms,
+            rules: alloc::vec![
+                BurnRateRule {
+                    long_window_ms: window_ms / 12,
+                    short_window_ms: window_ms / 144,
+                    burn_rate: 6.0,
+                    severity: AlertSeverity::Critical,
+                },
+                BurnRateRule {
+                    long_window_ms: window_ms / 4,
+                    short_window_ms: window_ms / 48,
+                    burn_rate: 2.0,
+                    severity: AlertSeverity::Warning,
+                },
+            ],
+        }
+    }
+
+    /// "99% of VM exits handled < 20 µs over 1 h"
+    pub fn exit_latency(name: &str, vm_id: Option<VmId>, threshold_ns: u64, target: f64, window_ms: u64) -> Self {
+        Slo::new(name, vm_id, SloIndicator::ExitLatencyBelow { threshold_ns }, target, window_ms)
+    }
+
+    fn validate(&self) -> Result<(), HypervisorError> {
+        let out_of_range = |field| {
+            let error = ConfigError::new(field, ConfigErrorReason::OutOfRange);
+            HypervisorError::from(match self.vm_id {
+                Some(vm_id) => error.vm(vm_id),
+                None => error,
+            })
+        };
+        if !(self.target > 0.0 && self.target < 1.0) {
+            return Err(out_of_range("slo.target"));
+        }
+        if self.window_ms < BUCKETS_PER_WINDOW {
+            return Err(out_of_range("slo.window_ms"));
+        }
+        for rule in &self.rules {
+            if rule.short_window_ms == 0 || rule.short_window_ms > rule.long_window_ms || rule.long_window_ms > self.window_ms {
+                return Err(out_of_range("slo.burn_rate_windows"));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Burn rates of one rule
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BurnRateStatus {
+    pub rule: BurnRateRule,
+    pub long_burn_rate: f64,
+    pub short_burn_rate: f64,
+    pub firing: bool,
+}
+
+/// Current state of an SLO
+#[derive(Debug, Clone)]
+pub struct SloStatus {
+    pub id: SloId,
+    pub name: String,
+    pub good_events: u64,
+    pub total_events: u64,
+    /// Fraction of good events over the window; 1.0 without events
+    pub compliance: f64,
+    /// Fraction of the error budget left, negative once overspent
+    pub budget_remaining: f64,
+    pub burn_rates: Vec<BurnRateStatus>,
+}
+
+/// Burn-rate alert raised by an SLO
+#[derive(Debug, Clone)]
+pub struct SloAlert {
+    pub slo: SloId,
+    pub metric_type: MetricType,
+    pub severity: AlertSeverity,
+    pub burn_rate: f64,
+    pub threshold: f64,
+    pub message: String,
+    pub vm_id: Option<VmId>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    start_ms: u64,
+    good: u64,
+    total: u64,
+}
+
+#[derive(Debug, Clone)]
+struct TrackedSlo {
+    slo: Slo,
+    bucket_ms: u64,
+    buckets: VecDeque<Bucket>,
+    /// Rules currently firing, so each raises one alert per episode
+    firing: Vec<bool>,
+}
+
+impl TrackedSlo {
+    fn add(&mut self, timestamp_ms: u64, good: bool) {
+        let start_ms = timestamp_ms - timestamp_ms % self.bucket_ms;
+        match self.buckets.back_mut() {
+            // Late events land in the newest bucket
+            Some(bucket) if bucket.start_ms >= start_ms => {
+                bucket.total += 1;
+                bucket.good += good as u64;
+            }
+            _ => self.buckets.push_back(Bucket { start_ms, good: good as u64, total: 1 }),
+        }
+
+        let horizon = start_ms.saturating_sub(self.slo.window_ms);
+        while self.buckets.front().map_or(false, |bucket| bucket.start_ms < horizon) {
+            self.buckets.pop_front();
+        }
+    }
+
+    /// Good and total events within `window_ms` of the newest bucket
+    fn counts(&self, window_ms: u64) -> (u64, u64) {
+        let newest = match self.buckets.back() {
+            Some(bucket) => bucket.start_ms + self.bucket_ms,
+            None => return (0, 0),
+        };
+        let since = newest.saturating_sub(window_ms);
+        self.buckets
+            .iter()
+            .rev()
+            .take_while(|bucket| bucket.start_ms >= since)
+            .fold((0, 0), |(good, total), bucket| (good + bucket.good, total + bucket.total))
+    }
+
+    fn burn_rate(&self, window_ms: u64) -> f64 {
+        let (good, total) = self.counts(window_ms);
+        if total == 0 {
+            return 0.0;
+        }
+        let error_rate = (total - good) as f64 / total as f64;
+        error_rate / (1.0 - self.slo.target)
+    }
+
+    fn burn_rates(&self) -> Vec<BurnRateStatus> {
+        self.slo
+            .rules
+            .iter()
+            .zip(&self.firing)
+            .map(|(rule, &firing)| BurnRateStatus {
+                rule: *rule,
+                long_burn_rate: self.burn_rate(rule.long_window_ms),
+                short_burn_rate: self.burn_rate(rule.short_window_ms),
+                firing,
+            })
+            .collect()
+    }
+}
+
+/// Evaluates SLOs as events arrive
+#[derive(Debug, Clone, Default)]
+pub struct SloTracker {
+    slos: BTreeMap<SloId, TrackedSlo>,
+    next_id: SloId,
+}
+
+impl SloTracker {
+    pub fn add(&mut self, slo: Slo) -> Result<SloId, HypervisorError> {
+        slo.validate()?;
+        self.next_id += 1;
+        let id = self.next_id;
+        info!("SLO {} '{}': {:.3}% over {} ms", id, slo.name, slo.target * 100.0, slo.window_ms);
+        self.slos.insert(id, TrackedSlo {
+            bucket_ms: slo.window_ms / BUCKETS_PER_WINDOW,
+            buckets: VecDeque::new(),
+            firing: alloc::vec![false; slo.rules.len()],
+            slo,
+        });
+        Ok(id)
+    }
+
+    pub fn remove(&mut self, id: SloId) -> Result<(), HypervisorError> {
+        self.slos.remove(&id).map(|_| ()).ok_or(HypervisorError::InvalidParameter)
+    }
+
+    /// Count a handled VM exit
+    pub fn record_exit(&mut self, vm_id: VmId, timestamp_ms: u64, handling_ns: u64) -> Vec<SloAlert> {
+        self.record(Some(vm_id), timestamp_ms, |indicator| match indicator {
+            SloIndicator::ExitLatencyBelow { threshold_ns } => Some(handling_ns < threshold_ns),
+            _ => None,
+        })
+    }
+
+    /// Count a monitoring sample
+    pub fn record_sample(&mut self, sample: &PerformanceSample) -> Vec<SloAlert> {
+        self.record(sample.vm_id, sample.timestamp_ms, |indicator| match indicator {
+            SloIndicator::MetricBelow { metric, threshold } if metric == sample.metric_type => Some(sample.value < threshold),
+            _ => None,
+        })
+    }
+
+    pub fn status(&self, id: SloId) -> Result<SloStatus, HypervisorError> {
+        let tracked = self.slos.get(&id).ok_or(HypervisorError::InvalidParameter)?;
+        let (good, total) = tracked.counts(tracked.slo.window_ms);
+        let compliance = if total == 0 { 1.0 } else { good as f64 / total as f64 };
+        Ok(SloStatus {
+            id,
+            name: tracked.slo.name.clone(),
+            good_events: good,
+            total_events: total,
+            compliance,
+            budget_remaining: 1.0 - (1.0 - compliance) / (1.0 - tracked.slo.target),
+            burn_rates: tracked.burn_rates(),
+        })
+    }
+
+    pub fn statuses(&self) -> Vec<SloStatus> {
+        self.slos.keys().filter_map(|&id| self.status(id).ok()).collect()
+    }
+
+    /// Add an event to every SLO it applies to and re-evaluate their rules
+    fn record<F>(&mut self, vm_id: Option<VmId>, timestamp_ms: u64, classify: F) -> Vec<SloAlert>
+    where
+        F: Fn(SloIndicator) -> Option<bool>,
+    {
+        let mut alerts = Vec::new();
+        for (&id, tracked) in self.slos.iter_mut() {
+            if tracked.slo.vm_id.is_some() && tracked.slo.vm_id != vm_id {
+                continue;
+            }
+            let good = match classify(tracked.slo.indicator) {
+                Some(good) => good,
+                None => continue,
+            };
+            tracked.add(timestamp_ms, good);
+
+            for (index, status) in tracked.burn_rates().into_iter().enumerate() {
+                let firing = status.long_burn_rate > status.rule.burn_rate && status.short_burn_rate > status.rule.burn_rate;
+                if firing && !tracked.firing[index] {
+                    alerts.push(SloAlert {
+                        slo: id,
+                        metric_type: tracked.slo.indicator.metric(),
+                        severity: status.rule.severity,
+                        burn_rate: status.long_burn_rate,
+                        threshold: status.rule.burn_rate,
+                        message: format!(
+                            "SLO '{}' burning error budget at {:.1}x over {} ms ({:.1}x over {} ms)",
+                            tracked.slo.name,
+                            status.long_burn_rate,
+                            status.rule.long_window_ms,
+                            status.short_burn_rate,
+                            status.rule.short_window_ms
+                        ),
+                        vm_id: tracked.slo.vm_id,
+                    });
+                } else if !firing && tracked.firing[index] {
+                    info!("SLO '{}' burn rate back under {:.1}x", tracked.slo.name, status.rule.burn_rate);
+                }
+                tracked.firing[index] = firing;
+            }
+        }
+        alerts
+    }
+}
@@ -0,0 +1,376 @@
+//! Per-VM Statistics History
+//!
+//! `VmStats` and `HypervisorStats` describe a single moment. `StatsHistory`
+//! turns periodic snapshots into per-VM time series that dashboards and the
+//! performance analysis tutorial can query over a time range. Samples are
+//! folded into fixed-width buckets at several resolutions, each kept for its
+//! own retention window, so recent data stays fine-grained while older data
+//! is kept only in downsampled form.
+
+use crate::{VmId, HypervisorError, ConfigError, ConfigErrorReason};
+use crate::core::VmStats;
+
+use alloc::collections::BTreeMap;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Metrics tracked per VM
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum HistoryMetric {
+    /// Guest CPU time as a percentage of the VM's vCPU capacity
+    CpuUtilization,
+    /// Guest memory in use, in MB
+    MemoryUsedMb,
+    /// VM exits per second, across all vCPUs
+    VmExitRate,
+    /// Page faults per second
+    PageFaultRate,
+    /// Device I/O operations per second
+    IoOperationRate,
+    /// Device I/O bytes per second
+    IoByteRate,
+}
+
+impl HistoryMetric {
+    /// All tracked metrics
+    pub const ALL: [HistoryMetric; 6] = [
+        HistoryMetric::CpuUtilization,
+        HistoryMetric::MemoryUsedMb,
+        HistoryMetric::VmExitRate,
+        HistoryMetric::PageFaultRate,
+        HistoryMetric::IoOperationRate,
+        HistoryMetric::IoByteRate,
+    ];
+
+    /// Display unit of the metric
+    pub fn unit(&self) -> &'static str {
+        match self {
+            HistoryMetric::CpuUtilization => "percent",
+            HistoryMetric::MemoryUsedMb => "MB",
+            HistoryMetric::VmExitRate => "exits/second",
+            HistoryMetric::PageFaultRate => "faults/second",
+            HistoryMetric::IoOperationRate => "operations/second",
+            HistoryMetric::IoByteRate => "bytes/second",
+        }
+    }
+}
+
+/// One downsampling tier
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetentionTier {
+    /// Bucket width
+    pub resolution_ms: u64,
+    /// How far back buckets are kept
+    pub retention_ms: u64,
+}
+
+/// Stats history configuration
+#[derive(Debug, Clone)]
+pub struct StatsHistoryConfig {
+    /// Tiers ordered from finest to coarsest resolution
+    pub tiers: Vec<RetentionTier>,
+}
+
+impl Default for StatsHistoryConfig {
+    fn default() -> Self {
+        StatsHistoryConfig {
+            tiers: vec![
+                // 1 s buckets for the last hour
+                RetentionTier { resolution_ms: 1_000, retention_ms: 60 * 60 * 1_000 },
+                // 1 min buckets for the last day
+                RetentionTier { resolution_ms: 60 * 1_000, retention_ms: 24 * 60 * 60 * 1_000 },
+                // 1 h buckets for the last 30 days
+                RetentionTier { resolution_ms: 60 * 60 * 1_000, retention_ms: 30 * 24 * 60 * 60 * 1_000 },
+            ],
+        }
+    }
+}
+
+/// Cumulative counters and gauges of one VM at a point in time
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StatsSnapshot {
+    pub timestamp_ms: u64,
+    pub vcpu_count: usize,
+    /// Sum of guest CPU time over all vCPUs
+    pub cpu_time_ms: u64,
+    pub vm_exits: u64,
+    pub memory_used_mb: u64,
+    pub page_faults: u64,
+    pub io_operations: u64,
+    pub io_bytes: u64,
+}
+
+impl StatsSnapshot {
+    /// Build a snapshot from VM statistics; device I/O counters come from the device layer
+    pub fn from_vm_stats(timestamp_ms: u64, stats: &VmStats, io_operations: u64, io_bytes: u64) -> Self {
+        StatsSnapshot {
+            timestamp_ms,
+            vcpu_count: stats.vcpu_stats.len(),
+            cpu_time_ms: stats.vcpu_stats.iter().map(|cpu| cpu.total_time_ms).sum(),
+            vm_exits: stats.vcpu_stats.iter().map(|cpu| cpu.vm_exit_count).sum(),
+            memory_used_mb: stats.memory_stats.used_mb,
+            page_faults: stats.memory_stats.page_faults,
+            io_operations,
+            io_bytes,
+        }
+    }
+}
+
+/// Requested time range, `[start_ms, end_ms)`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimeRange {
+    pub start_ms: u64,
+    pub end_ms: u64,
+}
+
+/// One aligned bucket of a query result
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatsBucket {
+    /// Bucket start, a multiple of the effective resolution
+    pub start_ms: u64,
+    /// Mean of the samples in the bucket, `None` if there were none
+    pub average: Option<f64>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub sample_count: u64,
+}
+
+/// Result of a history query
+#[derive(Debug, Clone)]
+pub struct StatsSeries {
+    pub vm_id: VmId,
+    pub metric: HistoryMetric,
+    /// Resolution actually used; coarser than requested when the range is
+    /// only covered by a downsampled tier
+    pub resolution_ms: u64,
+    pub buckets: Vec<StatsBucket>,
+}
+
+/// Running aggregate of one bucket
+#[derive(Debug, Clone, Copy)]
+struct Aggregate {
+    sum: f64,
+    min: f64,
+    max: f64,
+    count: u64,
+}
+
+impl Aggregate {
+    fn new(value: f64) -> Self {
+        Aggregate { sum: value, min: value, max: value, count: 1 }
+    }
+
+    fn merge(&mut self, other: &Aggregate) {
+        self.sum += other.sum;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.count += other.count;
+    }
+}
+
+/// Buckets of one metric at one tier
+type TierSeries = BTreeMap<u64, Aggregate>;
+
+/// History of a single VM
+#[derive(Debug, Default)]
+struct VmHistory {
+    last_snapshot: Option<StatsSnapshot>,
+    /// Series per metric, one per configured tier
+    series: BTreeMap<HistoryMetric, Vec<TierSeries>>,
+    latest_ms: u64,
+}
+
+/// Retains downsampled per-VM time series
+pub struct StatsHistory {
+    config: StatsHistoryConfig,
+    vms: BTreeMap<VmId, VmHistory>,
+}
+
+impl StatsHistory {
+    /// Create a new stats history
+    pub fn new(config: StatsHistoryConfig) -> Result<Self, HypervisorError> {
+        if config.tiers.is_empty() {
+            return Err(ConfigError::new("stats_history.tiers", ConfigErrorReason::NotFound).into());
+        }
+        let ordered = config.tiers.windows(2).all(|pair| {
+            pair[0].resolution_ms < pair[1].resolution_ms && pair[1].resolution_ms % pair[0].resolution_ms == 0
+        });
+        if config.tiers.iter().any(|tier| tier.resolution_ms == 0) || !ordered {
+            // Resolutions must increase, each a multiple of the previous
+            return Err(ConfigError::new("stats_history.tiers", ConfigErrorReason::OutOfRange).into());
+        }
+
+        Ok(StatsHistory {
+            config,
+            vms: BTreeMap::new(),
+        })
+    }
+
+    /// Record a snapshot of a VM.
+    ///
+    /// Rates are derived from the counter deltas to the previous snapshot, so
+    /// the first snapshot of a VM only contributes its memory gauge.
+    pub fn record(&mut self, vm_id: VmId, snapshot: StatsSnapshot) {
+        let tier_count = self.config.tiers.len();
+        let history = self.vms.entry(vm_id).or_default();
+
+        let mut values = vec![(HistoryMetric::MemoryUsedMb, snapshot.memory_used_mb as f64)];
+        if let Some(previous) = history.last_snapshot {
+            if snapshot.timestamp_ms <= previous.timestamp_ms {
+                // Out-of-order or duplicate snapshot; rates would be meaningless
+                return;
+            }
+
+            let elapsed_ms = (snapshot.timestamp_ms - previous.timestamp_ms) as f64;
+            let per_second = |current: u64, previous: u64| current.saturating_sub(previous) as f64 * 1000.0 / elapsed_ms;
+            let capacity_ms = elapsed_ms * snapshot.vcpu_count.max(1) as f64;
+
+            values.push((
+                HistoryMetric::CpuUtilization,
+                (snapshot.cpu_time_ms.saturating_sub(previous.cpu_time_ms) as f64 / capacity_ms * 100.0).min(100.0),
+            ));
+            values.push((HistoryMetric::VmExitRate, per_second(snapshot.vm_exits, previous.vm_exits)));
+            values.push((HistoryMetric::PageFaultRate, per_second(snapshot.page_faults, previous.page_faults)));
+            values.push((HistoryMetric::IoOperationRate, per_second(snapshot.io_operations, previous.io_operations)));
+            values.push((HistoryMetric::IoByteRate, per_second(snapshot.io_bytes, previous.io_bytes)));
+        }
+
+        for (metric, value) in values {
+            let series = history.series.entry(metric).or_insert_with(|| vec![TierSeries::new(); tier_count]);
+            for (tier, buckets) in self.config.tiers.iter().zip(series.iter_mut()) {
+                let bucket_start = align_down(snapshot.timestamp_ms, tier.resolution_ms);
+                buckets
+                    .entry(bucket_start)
+                    .and_modify(|aggregate| aggregate.merge(&Aggregate::new(value)))
+                    .or_insert_with(|| Aggregate::new(value));
+            }
+        }
+
+        history.last_snapshot = Some(snapshot);
+        history.latest_ms = history.latest_ms.max(snapshot.timestamp_ms);
+        Self::prune(&self.config, history);
+    }
+
+    /// Record the current statistics of a VM
+    pub fn record_vm_stats(&mut self, vm_id: VmId, timestamp_ms: u64, stats: &VmStats, io_operations: u64, io_bytes: u64) {
+        self.record(vm_id, StatsSnapshot::from_vm_stats(timestamp_ms, stats, io_operations, io_bytes));
+    }
+
+    /// Query a metric of a VM over `range`, in buckets of `resolution_ms`.
+    ///
+    /// Buckets are aligned to multiples of the effective resolution and cover
+    /// the whole range, including buckets without samples, so series of
+    /// different VMs and metrics line up. The finest tier that still covers the
+    /// start of the range is used; the requested resolution is rounded up to a
+    /// multiple of that tier's resolution.
+    pub fn query(&self, vm_id: VmId, metric: HistoryMetric, range: TimeRange, resolution_ms: u64) -> Result<StatsSeries, HypervisorError> {
+        if range.end_ms <= range.start_ms || resolution_ms == 0 {
+            return Err(HypervisorError::InvalidParameter);
+        }
+        let history = self.vms.get(&vm_id).ok_or(HypervisorError::VmNotFound(vm_id))?;
+
+        let tier_index = self.select_tier(history.latest_ms, range.start_ms, resolution_ms);
+        let tier = self.config.tiers[tier_index];
+        let resolution_ms = align_up(resolution_ms.max(tier.resolution_ms), tier.resolution_ms);
+
+        let first = align_down(range.start_ms, resolution_ms);
+        let mut aggregates: BTreeMap<u64, Aggregate> = BTreeMap::new();
+        if let Some(series) = history.series.get(&metric) {
+            for (&start, aggregate) in series[tier_index].range(first..range.end_ms) {
+                aggregates
+                    .entry(align_down(start, resolution_ms))
+                    .and_modify(|merged| merged.merge(aggregate))
+                    .or_insert(*aggregate);
+            }
+        }
+
+        let mut buckets = Vec::new();
+        let mut start = first;
+        while start < range.end_ms {
+            let aggregate = aggregates.get(&start);
+            buckets.push(StatsBucket {
+                start_ms: start,
+                average: aggregate.map(|a| a.sum / a.count as f64),
+                min: aggregate.map(|a| a.min),
+                max: aggregate.map(|a| a.max),
+                sample_count: aggregate.map_or(0, |a| a.count),
+            });
+            start += resolution_ms;
+        }
+
+        Ok(StatsSeries {
+            vm_id,
+            metric,
+            resolution_ms,
+            buckets,
+        })
+    }
+
+    /// Latest value of every metric of a VM, from the finest tier
+    pub fn latest(&self, vm_id: VmId) -> BTreeMap<HistoryMetric, f64> {
+        self.vms
+            .get(&vm_id)
+            .map(|history| {
+                history
+                    .series
+                    .iter()
+                    .filter_map(|(metric, tiers)| {
+                        tiers[0].values().next_back().map(|a| (*metric, a.sum / a.count as f64))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// VMs with recorded history
+    pub fn tracked_vms(&self) -> Vec<VmId> {
+        self.vms.keys().copied().collect()
+    }
+
+    /// Drop the history of a deleted VM
+    pub fn remove_vm(&mut self, vm_id: VmId) -> bool {
+        self.vms.remove(&vm_id).is_some()
+    }
+
+    /// Coarsest tier no coarser than the requested resolution whose retention reaches back to `start_ms`
+    fn select_tier(&self, latest_ms: u64, start_ms: u64, resolution_ms: u64) -> usize {
+        let age_ms = latest_ms.saturating_sub(start_ms);
+        let first_candidate = self
+            .config
+            .tiers
+            .iter()
+            .rposition(|tier| tier.resolution_ms <= resolution_ms)
+            .unwrap_or(0);
+
+        (first_candidate..self.config.tiers.len())
+            .find(|&index| self.config.tiers[index].retention_ms >= age_ms)
+            .unwrap_or(self.config.tiers.len() - 1)
+    }
+
+    /// Drop buckets that fell out of each tier's retention window
+    fn prune(config: &StatsHistoryConfig, history: &mut VmHistory) {
+        for series in history.series.values_mut() {
+            for (tier, buckets) in config.tiers.iter().zip(series.iter_mut()) {
+                let cutoff = align_down(history.latest_ms.saturating_sub(tier.retention_ms), tier.resolution_ms);
+                *buckets = buckets.split_off(&cutoff);
+            }
+        }
+    }
+}
+
+impl Default for StatsHistory {
+    fn default() -> Self {
+        StatsHistory {
+            config: StatsHistoryConfig::default(),
+            vms: BTreeMap::new(),
+        }
+    }
+}
+
+fn align_down(value: u64, step: u64) -> u64 {
+    value - value % step
+}
+
+fn align_up(value: u64, step: u64) -> u64 {
+    align_down(value + step - 1, step)
+}
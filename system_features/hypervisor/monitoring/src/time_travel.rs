@@ -0,0 +1,303 @@
+//! Time-Travel Debugging
+//!
+//! While time travel is on, a VM's non-deterministic inputs are recorded
+//! and a checkpoint is taken every `interval_ms`: VCPU registers, device
+//! state, and the guest pages written since the previous checkpoint. The
+//! oldest checkpoint holds a full memory image, and checkpoints that fall
+//! out of the window are folded into the next one, so memory use follows
+//! how much the guest writes rather than its size.
+//!
+//! [`TimeTravel::rewind`] puts the VM back to the newest checkpoint at
+//! least the requested number of seconds old, replays the recorded inputs
+//! from there with every VM exit traced in the exit feed, and leaves the
+//! VM paused so the user can resume it. Rewinding again while replaying
+//! goes back from the point last rewound to. Once replay has finished,
+//! call [`TimeTravel::enable`] again to record a fresh timeline.
+//!
+//! Pages that device models write directly, without going through the
+//! dirty tracker, are not captured by the incremental checkpoints.
+
+use crate::{VmId, HypervisorError};
+use crate::core::{VmManager, VmState, VcpuCheckpoint, ReplayLog, ReplayMode};
+use crate::devices::{DeviceFramework, GuestMemoryAccess};
+use crate::memory::{DirtyTracker, PAGE_SIZE_4K};
+use super::ExitFeed;
+
+use alloc::collections::{BTreeMap, BTreeSet, VecDeque};
+use alloc::vec;
+use alloc::vec::Vec;
+
+const NS_PER_MS: u64 = 1_000_000;
+const NS_PER_SEC: u64 = 1_000_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimeTravelConfig {
+    /// Time between checkpoints
+    pub interval_ms: u64,
+    /// How far back checkpoints are kept
+    pub window_secs: u64,
+}
+
+impl Default for TimeTravelConfig {
+    fn default() -> Self {
+        TimeTravelConfig { interval_ms: 1000, window_secs: 60 }
+    }
+}
+
+/// What time travel works on for one VM
+pub struct TimeTravelContext<'a> {
+    pub vms: &'a mut VmManager,
+    pub devices: &'a mut DeviceFramework,
+    pub dirty: &'a mut DirtyTracker,
+    pub memory: &'a mut dyn GuestMemoryAccess,
+}
+
+/// Summary of a checkpoint
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CheckpointInfo {
+    pub time_ns: u64,
+    /// Recorded inputs before the checkpoint
+    pub log_position: usize,
+    /// Guest pages stored with it
+    pub pages: usize,
+}
+
+struct Checkpoint {
+    time_ns: u64,
+    vcpus: Vec<VcpuCheckpoint>,
+    devices: Vec<u8>,
+    log_position: usize,
+    /// Page contents by guest address; empty for a zero page
+    pages: BTreeMap<u64, Vec<u8>>,
+}
+
+impl Checkpoint {
+    fn info(&self) -> CheckpointInfo {
+        CheckpointInfo { time_ns: self.time_ns, log_position: self.log_position, pages: self.pages.len() }
+    }
+}
+
+struct Timeline {
+    checkpoints: VecDeque<Checkpoint>,
+    /// The recording, once a rewind stopped it
+    log: Option<ReplayLog>,
+    /// Time of the checkpoint last rewound to, while replaying
+    rewound_to_ns: Option<u64>,
+}
+
+/// Periodic checkpoints and rewinding for VMs
+pub struct TimeTravel {
+    config: TimeTravelConfig,
+    /// Monotonic nanoseconds
+    clock: fn() -> u64,
+    timelines: BTreeMap<VmId, Timeline>,
+}
+
+impl TimeTravel {
+    pub fn new(config: TimeTravelConfig, clock: fn() -> u64) -> Self {
+        TimeTravel { config, clock, timelines: BTreeMap::new() }
+    }
+
+    /// Start recording a VM and take its first, full checkpoint. The VM
+    /// has to be paused or not yet started.
+    pub fn enable(&mut self, vm_id: VmId, ctx: &mut TimeTravelContext) -> Result<(), HypervisorError> {
+        if self.timelines.contains_key(&vm_id) {
+            self.disable(vm_id, ctx)?;
+        }
+        if ctx.vms.get_vm_info(vm_id)?.state == VmState::Running {
+            return Err(HypervisorError::InvalidVmState);
+        }
+        if self.config.interval_ms == 0 || self.config.window_secs == 0 {
+            return Err(HypervisorError::InvalidParameter);
+        }
+
+        if !ctx.dirty.is_tracking(vm_id) {
+            ctx.dirty.start_tracking(vm_id)?;
+        }
+        ctx.vms.start_recording(vm_id)?;
+        let mut timeline = Timeline { checkpoints: VecDeque::new(), log: None, rewound_to_ns: None };
+        let checkpoint = self.capture(vm_id, ctx, true);
+        match checkpoint {
+            Ok(checkpoint) => timeline.checkpoints.push_back(checkpoint),
+            Err(err) => {
+                ctx.vms.stop_recording(vm_id)?;
+                return Err(err);
+            }
+        }
+        self.timelines.insert(vm_id, timeline);
+
+        info!("Time travel enabled for VM {}, checkpoint every {} ms for {} s",
+              vm_id.0, self.config.interval_ms, self.config.window_secs);
+        Ok(())
+    }
+
+    /// Stop recording or replaying and drop the checkpoints
+    pub fn disable(&mut self, vm_id: VmId, ctx: &mut TimeTravelContext) -> Result<(), HypervisorError> {
+        self.timelines.remove(&vm_id).ok_or(HypervisorError::VmNotFound(vm_id))?;
+        match ctx.vms.record_replay_status(vm_id)? {
+            Some((ReplayMode::Recording, _)) => {
+                ctx.vms.stop_recording(vm_id)?;
+            }
+            Some(_) => ctx.vms.stop_replay(vm_id)?,
+            None => {}
+        }
+        ctx.dirty.stop_tracking(vm_id)?;
+        Ok(())
+    }
+
+    pub fn is_enabled(&self, vm_id: VmId) -> bool {
+        self.timelines.contains_key(&vm_id)
+    }
+
+    /// Take a checkpoint if one is due; returns whether it did. Called
+    /// periodically by the VMM.
+    pub fn tick(&mut self, vm_id: VmId, ctx: &mut TimeTravelContext) -> Result<bool, HypervisorError> {
+        let now = (self.clock)();
+        let Some(timeline) = self.timelines.get(&vm_id) else {
+            return Ok(false);
+        };
+        let last = timeline.checkpoints.back().map_or(0, |checkpoint| checkpoint.time_ns);
+        if timeline.log.is_some() || now.saturating_sub(last) < self.config.interval_ms * NS_PER_MS {
+            return Ok(false);
+        }
+        self.checkpoint(vm_id, ctx)?;
+        Ok(true)
+    }
+
+    /// Take a checkpoint now, pausing the VM while it is captured
+    pub fn checkpoint(&mut self, vm_id: VmId, ctx: &mut TimeTravelContext) -> Result<CheckpointInfo, HypervisorError> {
+        let timeline = self.timelines.get(&vm_id).ok_or(HypervisorError::VmNotFound(vm_id))?;
+        if timeline.log.is_some() {
+            // Replaying; the recorded timeline already has its checkpoints
+            return Err(HypervisorError::InvalidVmState);
+        }
+
+        let running = ctx.vms.get_vm_info(vm_id)?.state == VmState::Running;
+        if running {
+            ctx.vms.pause_vm(vm_id)?;
+        }
+        let checkpoint = self.capture(vm_id, ctx, false);
+        if running {
+            ctx.vms.resume_vm(vm_id)?;
+        }
+        let checkpoint = checkpoint?;
+        let info = checkpoint.info();
+
+        let window_ns = self.config.window_secs * NS_PER_SEC;
+        let timeline = self.timelines.get_mut(&vm_id).ok_or(HypervisorError::VmNotFound(vm_id))?;
+        timeline.checkpoints.push_back(checkpoint);
+        // Fold checkpoints that left the window into the next one, which
+        // then holds the full memory image
+        while timeline.checkpoints.len() > 1
+            && info.time_ns.saturating_sub(timeline.checkpoints[1].time_ns) >= window_ns {
+            if let Some(oldest) = timeline.checkpoints.pop_front() {
+                let next = &mut timeline.checkpoints[0];
+                for (gpa, page) in oldest.pages {
+                    next.pages.entry(gpa).or_insert(page);
+                }
+            }
+        }
+        Ok(info)
+    }
+
+    /// Checkpoints of a VM, oldest first
+    pub fn checkpoints(&self, vm_id: VmId) -> Vec<CheckpointInfo> {
+        self.timelines.get(&vm_id)
+            .map(|timeline| timeline.checkpoints.iter().map(Checkpoint::info).collect())
+            .unwrap_or_default()
+    }
+
+    /// Put the VM back to the newest checkpoint at least `seconds` before
+    /// the present, or the oldest one, and replay forward from there with
+    /// VM exits traced into `trace`. The VM is left paused.
+    pub fn rewind(&mut self, vm_id: VmId, seconds: u64, ctx: &mut TimeTravelContext, trace: &mut ExitFeed)
+        -> Result<CheckpointInfo, HypervisorError> {
+        let now = (self.clock)();
+        let timeline = self.timelines.get_mut(&vm_id).ok_or(HypervisorError::VmNotFound(vm_id))?;
+        let present = timeline.rewound_to_ns.unwrap_or(now);
+        let target = present.saturating_sub(seconds.saturating_mul(NS_PER_SEC));
+        let index = timeline.checkpoints.iter().rposition(|checkpoint| checkpoint.time_ns <= target).unwrap_or(0);
+        if timeline.checkpoints[index].time_ns > target {
+            warn!("VM {}: only {} s of history, rewinding to the oldest checkpoint",
+                  vm_id.0, (present - timeline.checkpoints[index].time_ns) / NS_PER_SEC);
+        }
+
+        if ctx.vms.get_vm_info(vm_id)?.state == VmState::Running {
+            ctx.vms.pause_vm(vm_id)?;
+        }
+        match ctx.vms.record_replay_status(vm_id)? {
+            Some((ReplayMode::Recording, _)) => timeline.log = Some(ctx.vms.stop_recording(vm_id)?),
+            Some(_) => ctx.vms.stop_replay(vm_id)?,
+            None => {}
+        }
+        let log = timeline.log.as_ref().ok_or(HypervisorError::InvalidVmState)?;
+
+        // Every page written after the checkpoint goes back to its content
+        // there: the newest copy at or before it, or zero
+        let mut written: BTreeSet<u64> = ctx.dirty.collect_dirty_bitmap(vm_id)?.dirty_pages().collect();
+        for later in timeline.checkpoints.iter().skip(index + 1) {
+            written.extend(later.pages.keys().copied());
+        }
+        let zero = vec![0u8; PAGE_SIZE_4K as usize];
+        for gpa in &written {
+            let page = timeline.checkpoints.iter().take(index + 1).rev()
+                .find_map(|checkpoint| checkpoint.pages.get(gpa))
+                .filter(|page| !page.is_empty())
+                .unwrap_or(&zero);
+            ctx.memory.write_guest(*gpa, page)?;
+        }
+        ctx.dirty.collect_dirty_bitmap(vm_id)?;
+
+        let checkpoint = &timeline.checkpoints[index];
+        ctx.devices.restore_all(vm_id, &checkpoint.devices)?;
+        ctx.vms.restore_vcpu_checkpoints(vm_id, &checkpoint.vcpus)?;
+
+        let mut tail = ReplayLog::new(vm_id, log.vcpu_count);
+        tail.entries.extend_from_slice(&log.entries[checkpoint.log_position.min(log.entries.len())..]);
+        ctx.vms.start_replay(vm_id, tail)?;
+        trace.enable_teaching_mode(vm_id);
+        timeline.rewound_to_ns = Some(checkpoint.time_ns);
+
+        info!("Rewound VM {} by {} s to checkpoint at {} ms, {} pages restored",
+              vm_id.0, (present - checkpoint.time_ns) / NS_PER_SEC, checkpoint.time_ns / NS_PER_MS, written.len());
+        Ok(checkpoint.info())
+    }
+
+    /// Capture the VM; `full` copies all of guest memory instead of the
+    /// pages written since the last checkpoint
+    fn capture(&self, vm_id: VmId, ctx: &mut TimeTravelContext, full: bool) -> Result<Checkpoint, HypervisorError> {
+        let dirty = ctx.dirty.collect_dirty_bitmap(vm_id)?;
+        let gpas: Vec<u64> = if full {
+            let memory_bytes = ctx.vms.get_vm_config(vm_id)?.memory_mb * 1024 * 1024;
+            (0..memory_bytes).step_by(PAGE_SIZE_4K as usize).collect()
+        } else {
+            dirty.dirty_pages().collect()
+        };
+
+        let mut pages = BTreeMap::new();
+        let mut page = vec![0u8; PAGE_SIZE_4K as usize];
+        for gpa in gpas {
+            ctx.memory.read_guest(gpa, &mut page)?;
+            if page.iter().all(|&byte| byte == 0) {
+                // A zero page needs no copy, and none at all in a full image
+                if !full {
+                    pages.insert(gpa, Vec::new());
+                }
+            } else {
+                pages.insert(gpa, page.clone());
+            }
+        }
+
+        let log_position = match ctx.vms.record_replay_status(vm_id)? {
+            Some((ReplayMode::Recording, position)) => position,
+            _ => return Err(HypervisorError::InvalidVmState),
+        };
+        Ok(Checkpoint {
+            time_ns: (self.clock)(),
+            vcpus: ctx.vms.vcpu_checkpoints(vm_id)?,
+            devices: ctx.devices.save_all(vm_id)?,
+            log_position,
+            pages,
+        })
+    }
+}
@@ -0,0 +1,279 @@
+//! Nested Exit Accounting
+//!
+//! Measures where the time of nested VM exits goes. An exit of a nested
+//! guest always reaches L0 first. L0 either handles it itself or reflects
+//! it to the guest hypervisor one level up: it syncs the shadow VMCS, enters
+//! the guest hypervisor and regains control when that executes VMRESUME,
+//! syncing the VMCS back before the nested guest runs again. Exits are
+//! timestamped at each of these points, which splits their cost into L0
+//! handling, guest hypervisor handling and VMCS sync, summed per VM and per
+//! nesting level.
+
+use crate::{VmId, VcpuId, HypervisorError};
+use crate::core::VmExitReason;
+use super::{NestingLevel, NestedPerformanceMetrics};
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+/// Who is handling an exit at the moment
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitHandler {
+    L0,
+    /// The hypervisor running in the parent VM
+    GuestHypervisor,
+}
+
+/// Cost of one nested exit, from the hardware exit to resuming the guest
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NestedExitRecord {
+    pub vm_id: VmId,
+    pub vcpu_id: VcpuId,
+    pub reason: VmExitReason,
+    /// Level of the VM that exited
+    pub level: NestingLevel,
+    /// Whether the exit was reflected to the guest hypervisor
+    pub reflected: bool,
+    pub total_ns: u64,
+    /// L0 time, without VMCS sync
+    pub l0_ns: u64,
+    pub guest_hypervisor_ns: u64,
+    pub vmcs_sync_ns: u64,
+    pub vmcs_syncs: u32,
+    pub vmcs_fields_synced: u32,
+}
+
+/// Summed cost of nested exits
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ExitCostBreakdown {
+    pub exits: u64,
+    pub reflected_exits: u64,
+    pub total_ns: u64,
+    pub l0_ns: u64,
+    pub guest_hypervisor_ns: u64,
+    pub vmcs_sync_ns: u64,
+    pub vmcs_syncs: u64,
+    pub vmcs_fields_synced: u64,
+    pub max_exit_ns: u64,
+    /// Time spent on EPT violations
+    pub memory_ns: u64,
+    /// Time spent on I/O instruction exits
+    pub io_ns: u64,
+}
+
+impl ExitCostBreakdown {
+    fn add(&mut self, record: &NestedExitRecord) {
+        self.exits += 1;
+        self.reflected_exits += record.reflected as u64;
+        self.total_ns += record.total_ns;
+        self.l0_ns += record.l0_ns;
+        self.guest_hypervisor_ns += record.guest_hypervisor_ns;
+        self.vmcs_sync_ns += record.vmcs_sync_ns;
+        self.vmcs_syncs += record.vmcs_syncs as u64;
+        self.vmcs_fields_synced += record.vmcs_fields_synced as u64;
+        self.max_exit_ns = self.max_exit_ns.max(record.total_ns);
+        match record.reason {
+            VmExitReason::EnableEptViolation => self.memory_ns += record.total_ns,
+            VmExitReason::IoInstruction => self.io_ns += record.total_ns,
+            _ => {}
+        }
+    }
+
+    pub fn avg_exit_ns(&self) -> u64 {
+        if self.exits > 0 { self.total_ns / self.exits } else { 0 }
+    }
+
+    /// Share of the exit time spent in L0, in the guest hypervisor and on
+    /// VMCS sync, in percent
+    pub fn shares(&self) -> (f64, f64, f64) {
+        if self.total_ns == 0 {
+            return (0.0, 0.0, 0.0);
+        }
+        let total = self.total_ns as f64;
+        (
+            self.l0_ns as f64 * 100.0 / total,
+            self.guest_hypervisor_ns as f64 * 100.0 / total,
+            self.vmcs_sync_ns as f64 * 100.0 / total,
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct InFlightExit {
+    reason: VmExitReason,
+    level: NestingLevel,
+    started_ns: u64,
+    phase_started_ns: u64,
+    handler: ExitHandler,
+    reflected: bool,
+    l0_ns: u64,
+    guest_hypervisor_ns: u64,
+    vmcs_sync_ns: u64,
+    vmcs_syncs: u32,
+    vmcs_fields_synced: u32,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct VmNestedCost {
+    exits: ExitCostBreakdown,
+    guest_run_ns: u64,
+    guest_instructions: u64,
+}
+
+/// Per-VM and per-level accounting of nested exits
+pub struct NestedAccounting {
+    /// Monotonic nanoseconds
+    clock: fn() -> u64,
+    in_flight: BTreeMap<(VmId, VcpuId), InFlightExit>,
+    vms: BTreeMap<VmId, VmNestedCost>,
+    levels: BTreeMap<NestingLevel, ExitCostBreakdown>,
+}
+
+impl NestedAccounting {
+    pub fn new(clock: fn() -> u64) -> Self {
+        NestedAccounting {
+            clock,
+            in_flight: BTreeMap::new(),
+            vms: BTreeMap::new(),
+            levels: BTreeMap::new(),
+        }
+    }
+
+    /// A nested guest exited to L0
+    pub fn begin_exit(&mut self, vm_id: VmId, vcpu_id: VcpuId, reason: VmExitReason, level: NestingLevel) -> Result<(), HypervisorError> {
+        if self.in_flight.contains_key(&(vm_id, vcpu_id)) {
+            return Err(HypervisorError::InvalidVcpuState);
+        }
+        let now = (self.clock)();
+        self.in_flight.insert((vm_id, vcpu_id), InFlightExit {
+            reason,
+            level,
+            started_ns: now,
+            phase_started_ns: now,
+            handler: ExitHandler::L0,
+            reflected: false,
+            l0_ns: 0,
+            guest_hypervisor_ns: 0,
+            vmcs_sync_ns: 0,
+            vmcs_syncs: 0,
+            vmcs_fields_synced: 0,
+        });
+        Ok(())
+    }
+
+    /// L0 enters the guest hypervisor to handle the exit
+    pub fn reflect_exit(&mut self, vm_id: VmId, vcpu_id: VcpuId) -> Result<(), HypervisorError> {
+        self.switch_handler(vm_id, vcpu_id, ExitHandler::L0, ExitHandler::GuestHypervisor)?;
+        if let Some(exit) = self.in_flight.get_mut(&(vm_id, vcpu_id)) {
+            exit.reflected = true;
+        }
+        Ok(())
+    }
+
+    /// The guest hypervisor executed VMRESUME and L0 has control again
+    pub fn guest_hypervisor_resumed(&mut self, vm_id: VmId, vcpu_id: VcpuId) -> Result<(), HypervisorError> {
+        self.switch_handler(vm_id, vcpu_id, ExitHandler::GuestHypervisor, ExitHandler::L0)
+    }
+
+    /// L0 copied `fields` VMCS fields between the shadow VMCS and the VMCS
+    /// it runs the nested guest with, taking `duration_ns`
+    pub fn record_vmcs_sync(&mut self, vm_id: VmId, vcpu_id: VcpuId, fields: u32, duration_ns: u64) -> Result<(), HypervisorError> {
+        let exit = self.in_flight.get_mut(&(vm_id, vcpu_id)).ok_or(HypervisorError::InvalidVcpuState)?;
+        if exit.handler != ExitHandler::L0 {
+            return Err(HypervisorError::InvalidVcpuState);
+        }
+        exit.vmcs_sync_ns += duration_ns;
+        exit.vmcs_syncs += 1;
+        exit.vmcs_fields_synced += fields;
+        Ok(())
+    }
+
+    /// The nested guest is resumed
+    pub fn end_exit(&mut self, vm_id: VmId, vcpu_id: VcpuId) -> Result<NestedExitRecord, HypervisorError> {
+        let exit = *self.in_flight.get(&(vm_id, vcpu_id)).ok_or(HypervisorError::InvalidVcpuState)?;
+        if exit.handler != ExitHandler::L0 {
+            return Err(HypervisorError::InvalidVcpuState);
+        }
+        self.in_flight.remove(&(vm_id, vcpu_id));
+
+        let now = (self.clock)();
+        let l0_ns = exit.l0_ns + now.saturating_sub(exit.phase_started_ns);
+        let record = NestedExitRecord {
+            vm_id,
+            vcpu_id,
+            reason: exit.reason,
+            level: exit.level,
+            reflected: exit.reflected,
+            total_ns: now.saturating_sub(exit.started_ns),
+            l0_ns: l0_ns.saturating_sub(exit.vmcs_sync_ns),
+            guest_hypervisor_ns: exit.guest_hypervisor_ns,
+            vmcs_sync_ns: exit.vmcs_sync_ns.min(l0_ns),
+            vmcs_syncs: exit.vmcs_syncs,
+            vmcs_fields_synced: exit.vmcs_fields_synced,
+        };
+        self.vms.entry(vm_id).or_default().exits.add(&record);
+        self.levels.entry(record.level).or_default().add(&record);
+        Ok(record)
+    }
+
+    /// Time a nested guest ran and the instructions it retired, for
+    /// relating exit overhead to useful work
+    pub fn record_guest_run(&mut self, vm_id: VmId, run_ns: u64, instructions: u64) {
+        let cost = self.vms.entry(vm_id).or_default();
+        cost.guest_run_ns += run_ns;
+        cost.guest_instructions += instructions;
+    }
+
+    /// Forget a VM, including exits still in flight
+    pub fn remove_vm(&mut self, vm_id: VmId) {
+        self.in_flight.retain(|&(exit_vm, _), _| exit_vm != vm_id);
+        self.vms.remove(&vm_id);
+    }
+
+    pub fn vm_breakdown(&self, vm_id: VmId) -> Option<ExitCostBreakdown> {
+        self.vms.get(&vm_id).map(|cost| cost.exits)
+    }
+
+    /// Exit costs by the nesting level of the exiting VM
+    pub fn level_breakdowns(&self) -> Vec<(NestingLevel, ExitCostBreakdown)> {
+        self.levels.iter().map(|(&level, &breakdown)| (level, breakdown)).collect()
+    }
+
+    /// Performance metrics of a VM from its measured exits
+    pub fn performance_metrics(&self, vm_id: VmId) -> NestedPerformanceMetrics {
+        let cost = self.vms.get(&vm_id).copied().unwrap_or_default();
+        let exits = cost.exits;
+        NestedPerformanceMetrics {
+            avg_instruction_overhead_ns: if cost.guest_instructions > 0 {
+                exits.total_ns / cost.guest_instructions
+            } else {
+                0
+            },
+            memory_overhead_ns: exits.memory_ns,
+            io_overhead_ns: exits.io_ns,
+            context_switch_overhead_ns: exits.vmcs_sync_ns,
+            total_overhead_ns: exits.total_ns,
+            efficiency: if cost.guest_run_ns + exits.total_ns > 0 {
+                cost.guest_run_ns as f32 / (cost.guest_run_ns + exits.total_ns) as f32
+            } else {
+                1.0
+            },
+        }
+    }
+
+    fn switch_handler(&mut self, vm_id: VmId, vcpu_id: VcpuId, from: ExitHandler, to: ExitHandler) -> Result<(), HypervisorError> {
+        let now = (self.clock)();
+        let exit = self.in_flight.get_mut(&(vm_id, vcpu_id)).ok_or(HypervisorError::InvalidVcpuState)?;
+        if exit.handler != from {
+            return Err(HypervisorError::InvalidVcpuState);
+        }
+        let elapsed = now.saturating_sub(exit.phase_started_ns);
+        match from {
+            ExitHandler::L0 => exit.l0_ns += elapsed,
+            ExitHandler::GuestHypervisor => exit.guest_hypervisor_ns += elapsed,
+        }
+        exit.handler = to;
+        exit.phase_started_ns = now;
+        Ok(())
+    }
+}
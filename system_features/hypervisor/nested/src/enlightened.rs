@@ -0,0 +1,266 @@
+//! Enlightened L1 Interface
+//!
+//! Paravirtual interface a MultiOS hypervisor running as L1 can use instead
+//! of having L0 emulate its VMX instructions one exit at a time. L1 finds
+//! the interface through CPUID leaves 0x4000_0000 and 0x4000_0001,
+//! negotiates the features it wants, and then issues VMCALLs to flush TLBs
+//! directly, write many VMCS fields in one exit, and forward interrupts to
+//! its L2 vCPUs as posted interrupts.
+//!
+//! Calling convention: RAX holds the call number and RBX, RCX, RDX and RSI
+//! the arguments; the status comes back in RAX and a result in RBX.
+
+use crate::{VmId, HypervisorError, ConfigError, ConfigErrorReason};
+use crate::core::VcpuRegs;
+use crate::cpu::CpuidResult;
+use crate::devices::GuestMemoryAccess;
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use bitflags::bitflags;
+
+/// Vendor leaf; EBX, ECX and EDX spell the signature
+pub const PV_CPUID_LEAF_VENDOR: u32 = 0x4000_0000;
+/// Feature leaf: EAX version, EBX offered features, ECX largest VMCS batch
+pub const PV_CPUID_LEAF_FEATURES: u32 = 0x4000_0001;
+pub const PV_SIGNATURE: &[u8; 12] = b"MultiOSNstHv";
+pub const PV_INTERFACE_VERSION: u32 = 1;
+
+/// RBX: wanted features; returns the granted ones in RBX
+pub const HC_NEGOTIATE: u64 = 0x1001;
+/// RBX: scope, RCX: EPTP or VPID, RDX: first address, RSI: pages
+pub const HC_FLUSH_TLB: u64 = 0x1002;
+/// RBX: guest-physical address of the VMCS12, RCX: address of an array of
+/// (encoding, value) u64 pairs, RDX: pairs
+pub const HC_VMCS_BATCH_WRITE: u64 = 0x1003;
+/// RBX: L2 vCPU, RCX: vector
+pub const HC_POST_INTERRUPT: u64 = 0x1004;
+
+pub const PV_STATUS_SUCCESS: u64 = 0;
+pub const PV_STATUS_UNKNOWN_CALL: u64 = 1;
+pub const PV_STATUS_NOT_NEGOTIATED: u64 = 2;
+pub const PV_STATUS_INVALID_PARAMETER: u64 = 3;
+pub const PV_STATUS_ACCESS_FAULT: u64 = 4;
+
+/// Most VMCS fields one batch may write
+pub const MAX_VMCS_BATCH: u32 = 64;
+/// Larger address flushes are widened to the whole context
+pub const MAX_FLUSH_PAGES: u64 = 512;
+
+const FLUSH_SCOPE_ALL: u64 = 0;
+const FLUSH_SCOPE_CONTEXT: u64 = 1;
+const FLUSH_SCOPE_ADDRESSES: u64 = 2;
+
+bitflags! {
+    /// Paravirtual nested features
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct PvNestedFeatures: u32 {
+        const DIRECT_TLB_FLUSH = 1 << 0;
+        const VMCS_BATCH_WRITE = 1 << 1;
+        const POSTED_INTERRUPT_FORWARDING = 1 << 2;
+    }
+}
+
+/// TLB entries a flush request covers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TlbFlushScope {
+    /// Every context of the L1
+    All,
+    /// One EPTP or VPID
+    Context(u64),
+    /// `pages` pages from `address` in one context
+    Addresses { context: u64, address: u64, pages: u64 },
+}
+
+/// Work L0 does on behalf of a hypercall
+#[derive(Debug, Clone, PartialEq)]
+pub enum EnlightenedAction {
+    FlushTlb(TlbFlushScope),
+    /// Write fields of the VMCS12 at `vmcs12` and the VMCS02 shadowing it
+    WriteVmcsFields { vmcs12: u64, fields: Vec<(u32, u64)> },
+    /// Post `vector` to an L2 vCPU's posted-interrupt descriptor
+    PostInterrupt { l2_vcpu: u32, vector: u8 },
+}
+
+/// Hypercall counters of an L1
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EnlightenedStats {
+    pub tlb_flushes: u64,
+    pub vmcs_batches: u64,
+    pub vmcs_fields_written: u64,
+    pub posted_interrupts: u64,
+    pub failed_calls: u64,
+    /// Exits the L1 would have taken emulating the same work
+    pub exits_avoided: u64,
+}
+
+#[derive(Debug, Clone)]
+struct EnlightenedL1 {
+    offered: PvNestedFeatures,
+    negotiated: PvNestedFeatures,
+    stats: EnlightenedStats,
+}
+
+/// Paravirtual nested interface of the L1 hypervisors that enabled it
+#[derive(Debug, Clone, Default)]
+pub struct EnlightenedInterface {
+    vms: BTreeMap<VmId, EnlightenedL1>,
+}
+
+impl EnlightenedInterface {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Offer the interface to an L1 VM
+    pub fn enable(&mut self, vm_id: VmId, offered: PvNestedFeatures) -> Result<(), HypervisorError> {
+        if self.vms.contains_key(&vm_id) {
+            return Err(ConfigError::new("nested.enlightened", ConfigErrorReason::AlreadyEnabled).vm(vm_id).into());
+        }
+        self.vms.insert(vm_id, EnlightenedL1 {
+            offered,
+            negotiated: PvNestedFeatures::empty(),
+            stats: EnlightenedStats::default(),
+        });
+        info!("Offered enlightened nested interface to VM {}: {:?}", vm_id.0, offered);
+        Ok(())
+    }
+
+    pub fn disable(&mut self, vm_id: VmId) {
+        self.vms.remove(&vm_id);
+    }
+
+    pub fn is_enabled(&self, vm_id: VmId) -> bool {
+        self.vms.contains_key(&vm_id)
+    }
+
+    /// Features the L1 negotiated
+    pub fn negotiated(&self, vm_id: VmId) -> Option<PvNestedFeatures> {
+        self.vms.get(&vm_id).map(|l1| l1.negotiated)
+    }
+
+    pub fn stats(&self, vm_id: VmId) -> Option<EnlightenedStats> {
+        self.vms.get(&vm_id).map(|l1| l1.stats)
+    }
+
+    /// Answer for the interface's CPUID leaves, `None` for other leaves or
+    /// VMs without the interface
+    pub fn cpuid(&self, vm_id: VmId, leaf: u32) -> Option<CpuidResult> {
+        let l1 = self.vms.get(&vm_id)?;
+        let word = |offset: usize| u32::from_le_bytes([
+            PV_SIGNATURE[offset], PV_SIGNATURE[offset + 1], PV_SIGNATURE[offset + 2], PV_SIGNATURE[offset + 3],
+        ]);
+        match leaf {
+            PV_CPUID_LEAF_VENDOR => Some(CpuidResult {
+                eax: PV_CPUID_LEAF_FEATURES,
+                ebx: word(0),
+                ecx: word(4),
+                edx: word(8),
+            }),
+            PV_CPUID_LEAF_FEATURES => Some(CpuidResult {
+                eax: PV_INTERFACE_VERSION,
+                ebx: l1.offered.bits(),
+                ecx: MAX_VMCS_BATCH,
+                edx: 0,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Handle a VMCALL from an L1. VMs without the interface get `Ok(None)`
+    /// and untouched registers, so the call can go to the regular hypercall
+    /// handler; otherwise the status is in RAX and L0 carries out the
+    /// returned action.
+    pub fn handle_hypercall(&mut self, vm_id: VmId, regs: &mut VcpuRegs, memory: &dyn GuestMemoryAccess)
+        -> Result<Option<EnlightenedAction>, HypervisorError> {
+        let Some(l1) = self.vms.get_mut(&vm_id) else {
+            return Ok(None);
+        };
+
+        let (status, action) = match regs.rax {
+            HC_NEGOTIATE => {
+                l1.negotiated = PvNestedFeatures::from_bits_truncate(regs.rbx as u32) & l1.offered;
+                regs.rbx = l1.negotiated.bits() as u64;
+                info!("VM {} negotiated enlightened nested features {:?}", vm_id.0, l1.negotiated);
+                (PV_STATUS_SUCCESS, None)
+            }
+            HC_FLUSH_TLB if l1.negotiated.contains(PvNestedFeatures::DIRECT_TLB_FLUSH) => {
+                match flush_scope(regs) {
+                    Some(scope) => {
+                        l1.stats.tlb_flushes += 1;
+                        l1.stats.exits_avoided += 1;
+                        (PV_STATUS_SUCCESS, Some(EnlightenedAction::FlushTlb(scope)))
+                    }
+                    None => (PV_STATUS_INVALID_PARAMETER, None),
+                }
+            }
+            HC_VMCS_BATCH_WRITE if l1.negotiated.contains(PvNestedFeatures::VMCS_BATCH_WRITE) => {
+                match read_vmcs_batch(regs, memory) {
+                    Ok(fields) => {
+                        l1.stats.vmcs_batches += 1;
+                        l1.stats.vmcs_fields_written += fields.len() as u64;
+                        // One VMWRITE exit per field, less the VMCALL
+                        l1.stats.exits_avoided += (fields.len() as u64).saturating_sub(1);
+                        (PV_STATUS_SUCCESS, Some(EnlightenedAction::WriteVmcsFields { vmcs12: regs.rbx, fields }))
+                    }
+                    Err(status) => (status, None),
+                }
+            }
+            HC_POST_INTERRUPT if l1.negotiated.contains(PvNestedFeatures::POSTED_INTERRUPT_FORWARDING) => {
+                // Vectors below 32 are exceptions, not interrupts
+                match u8::try_from(regs.rcx) {
+                    Ok(vector) if vector >= 32 => {
+                        l1.stats.posted_interrupts += 1;
+                        // The IPI to L2 and its delivery would each exit
+                        l1.stats.exits_avoided += 1;
+                        (PV_STATUS_SUCCESS, Some(EnlightenedAction::PostInterrupt { l2_vcpu: regs.rbx as u32, vector }))
+                    }
+                    _ => (PV_STATUS_INVALID_PARAMETER, None),
+                }
+            }
+            HC_FLUSH_TLB | HC_VMCS_BATCH_WRITE | HC_POST_INTERRUPT => (PV_STATUS_NOT_NEGOTIATED, None),
+            _ => (PV_STATUS_UNKNOWN_CALL, None),
+        };
+
+        if status != PV_STATUS_SUCCESS {
+            l1.stats.failed_calls += 1;
+        }
+        regs.rax = status;
+        Ok(action)
+    }
+}
+
+fn flush_scope(regs: &VcpuRegs) -> Option<TlbFlushScope> {
+    match regs.rbx {
+        FLUSH_SCOPE_ALL => Some(TlbFlushScope::All),
+        FLUSH_SCOPE_CONTEXT => Some(TlbFlushScope::Context(regs.rcx)),
+        FLUSH_SCOPE_ADDRESSES if regs.rsi == 0 || regs.rdx & 0xFFF != 0 => None,
+        FLUSH_SCOPE_ADDRESSES if regs.rsi > MAX_FLUSH_PAGES => Some(TlbFlushScope::Context(regs.rcx)),
+        FLUSH_SCOPE_ADDRESSES => Some(TlbFlushScope::Addresses { context: regs.rcx, address: regs.rdx, pages: regs.rsi }),
+        _ => None,
+    }
+}
+
+/// Read and check the (encoding, value) pairs of a batch write
+fn read_vmcs_batch(regs: &VcpuRegs, memory: &dyn GuestMemoryAccess) -> Result<Vec<(u32, u64)>, u64> {
+    let count = regs.rdx;
+    if count == 0 || count > MAX_VMCS_BATCH as u64 || regs.rbx & 0xFFF != 0 {
+        return Err(PV_STATUS_INVALID_PARAMETER);
+    }
+
+    let mut raw = [0u8; MAX_VMCS_BATCH as usize * 16];
+    let raw = &mut raw[..count as usize * 16];
+    memory.read_guest(regs.rcx, raw).map_err(|_| PV_STATUS_ACCESS_FAULT)?;
+
+    let mut fields = Vec::with_capacity(count as usize);
+    for pair in raw.chunks_exact(16) {
+        let encoding = u64::from_le_bytes(pair[..8].try_into().unwrap());
+        let value = u64::from_le_bytes(pair[8..].try_into().unwrap());
+        // Bits 11:10 = 1 are the read-only VM-exit information fields
+        if encoding > 0x7FFF || (encoding >> 10) & 3 == 1 {
+            return Err(PV_STATUS_INVALID_PARAMETER);
+        }
+        fields.push((encoding as u32, value));
+    }
+    Ok(fields)
+}
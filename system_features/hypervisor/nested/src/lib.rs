@@ -3,15 +3,24 @@
 //! Provides support for running virtual machines inside virtual machines,
 //! enabling OS research and nested virtualization experiments.
 
-use crate::{VmId, HypervisorError, VmConfig, VmFeatures, HypervisorCapabilities};
+use crate::{VmId, VcpuId, HypervisorError, ConfigError, ConfigErrorReason, VmConfig, VmFeatures, HypervisorCapabilities};
 use crate::core::{VmState, Vcpu, VcpuStateType, VmExitReason};
 use crate::cpu::{CpuVirtualization, VmcsRegion, VmcbRegion, SvmExitCode};
 use crate::memory::{MemoryManager, VirtualizationType, EptPageTable, NptPageTable};
+use crate::core::VcpuRegs;
+use crate::cpu::CpuidResult;
+use crate::devices::GuestMemoryAccess;
 
 use alloc::vec::Vec;
 use alloc::collections::BTreeMap;
 use bitflags::bitflags;
 
+mod accounting;
+mod enlightened;
+
+pub use accounting::*;
+pub use enlightened::*;
+
 /// Nested virtualization level
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum NestingLevel {
@@ -176,16 +185,23 @@ pub struct NestedVirtualizationManager {
     capabilities: HypervisorCapabilities,
     /// Manager statistics
     stats: NestedStats,
+    /// Measured cost of nested exits
+    accounting: NestedAccounting,
+    /// Paravirtual interface of enlightened L1 hypervisors
+    enlightened: EnlightenedInterface,
 }
 
 impl NestedVirtualizationManager {
-    /// Create a new nested virtualization manager
-    pub fn new(capabilities: HypervisorCapabilities) -> Self {
+    /// Create a new nested virtualization manager; `clock` returns
+    /// monotonic nanoseconds for exit accounting
+    pub fn new(capabilities: HypervisorCapabilities, clock: fn() -> u64) -> Self {
         NestedVirtualizationManager {
             nested_vms: BTreeMap::new(),
             parent_child_map: BTreeMap::new(),
             capabilities,
             stats: NestedStats::default(),
+            accounting: NestedAccounting::new(clock),
+            enlightened: EnlightenedInterface::new(),
         }
     }
     
@@ -196,7 +212,7 @@ impl NestedVirtualizationManager {
         }
         
         if !config.features.contains(VmFeatures::NESTED) {
-            return Err(HypervisorError::ConfigurationError(String::from("Nested virtualization not enabled in VM config")));
+            return Err(ConfigError::new("features.nested", ConfigErrorReason::NotEnabled).vm(vm_id).into());
         }
         
         // Determine nesting level
@@ -235,6 +251,9 @@ impl NestedVirtualizationManager {
     /// Disable nested virtualization for a VM
     pub fn disable_nested_virtualization(&mut self, vm_id: VmId) -> Result<(), HypervisorError> {
         if let Some(nested_vm) = self.nested_vms.remove(&vm_id) {
+            self.accounting.remove_vm(vm_id);
+            self.enlightened.disable(vm_id);
+            
             // Remove from parent-child relationships
             if let Some(parent_id) = nested_vm.parent_vm_id {
                 if let Some(children) = self.parent_child_map.get_mut(&parent_id) {
@@ -250,7 +269,7 @@ impl NestedVirtualizationManager {
             info!("Disabled nested virtualization for VM {}", vm_id.0);
             Ok(())
         } else {
-            Err(HypervisorError::VmNotFound)
+            Err(HypervisorError::VmNotFound(vm_id))
         }
     }
     
@@ -265,7 +284,7 @@ impl NestedVirtualizationManager {
             current_vm_id = parent_id;
             
             if level >= 3 {
-                return Err(HypervisorError::ConfigurationError(String::from("Maximum nesting level reached")));
+                return Err(ConfigError::new("nesting_level", ConfigErrorReason::Exhausted).vm(vm_id).value(level as u64).into());
             }
         }
         
@@ -274,7 +293,7 @@ impl NestedVirtualizationManager {
             1 => Ok(NestingLevel::Level1),
             2 => Ok(NestingLevel::Level2),
             3 => Ok(NestingLevel::Level3),
-            _ => Err(HypervisorError::ConfigurationError(String::from("Invalid nesting level"))),
+            _ => Err(ConfigError::new("nesting_level", ConfigErrorReason::OutOfRange).vm(vm_id).value(level as u64).into()),
         }
     }
     
@@ -364,7 +383,7 @@ impl NestedVirtualizationManager {
             info!("Configured nested features for VM {}: {:?}", vm_id.0, features);
             Ok(())
         } else {
-            Err(HypervisorError::VmNotFound)
+            Err(HypervisorError::VmNotFound(vm_id))
         }
     }
     
@@ -418,9 +437,7 @@ impl NestedVirtualizationManager {
     
     /// Handle nested VM exit
     pub fn handle_nested_vm_exit(&mut self, vm_id: VmId, exit_reason: VmExitReason) -> Result<(), HypervisorError> {
-        if let Some(nested_vm) = self.nested_vms.get_mut(&vm_id) {
-            nested_vm.performance_metrics.total_overhead_ns += self.calculate_exit_overhead(exit_reason);
-            
+        if self.nested_vms.contains_key(&vm_id) {
             match exit_reason {
                 VmExitReason::EPTViolation => {
                     self.handle_nested_ept_violation(vm_id)?;
@@ -441,7 +458,7 @@ impl NestedVirtualizationManager {
             info!("Handled nested VM exit {:?} for VM {}", exit_reason, vm_id.0);
             Ok(())
         } else {
-            Err(HypervisorError::VmNotFound)
+            Err(HypervisorError::VmNotFound(vm_id))
         }
     }
     
@@ -478,15 +495,96 @@ impl NestedVirtualizationManager {
         Ok(())
     }
     
-    /// Calculate overhead for VM exit
-    fn calculate_exit_overhead(&self, exit_reason: VmExitReason) -> u64 {
-        // Simulate overhead calculation based on exit type
-        match exit_reason {
-            VmExitReason::EPTViolation => 1000,    // 1µs
-            VmExitReason::MsrRead | VmExitReason::MsrWrite => 500, // 0.5µs
-            VmExitReason::CpuidInstruction => 300, // 0.3µs
-            _ => 100, // 0.1µs
+    /// Start timing an exit of a nested VM when it reaches L0
+    pub fn begin_nested_exit(&mut self, vm_id: VmId, vcpu_id: VcpuId, exit_reason: VmExitReason) -> Result<(), HypervisorError> {
+        let level = self.nested_vms.get(&vm_id)
+            .map(|nested_vm| nested_vm.nesting_level)
+            .ok_or(HypervisorError::VmNotFound(vm_id))?;
+        self.accounting.begin_exit(vm_id, vcpu_id, exit_reason, level)
+    }
+    
+    /// Mark the exit as handed to the guest hypervisor
+    pub fn reflect_nested_exit(&mut self, vm_id: VmId, vcpu_id: VcpuId) -> Result<(), HypervisorError> {
+        self.accounting.reflect_exit(vm_id, vcpu_id)
+    }
+    
+    /// Mark the guest hypervisor's VMRESUME returning control to L0
+    pub fn guest_hypervisor_resumed(&mut self, vm_id: VmId, vcpu_id: VcpuId) -> Result<(), HypervisorError> {
+        self.accounting.guest_hypervisor_resumed(vm_id, vcpu_id)
+    }
+    
+    /// Record a shadow VMCS sync done while handling the exit
+    pub fn record_vmcs_sync(&mut self, vm_id: VmId, vcpu_id: VcpuId, fields: u32, duration_ns: u64) -> Result<(), HypervisorError> {
+        self.accounting.record_vmcs_sync(vm_id, vcpu_id, fields, duration_ns)
+    }
+    
+    /// Finish timing the exit as the nested VM resumes
+    pub fn end_nested_exit(&mut self, vm_id: VmId, vcpu_id: VcpuId) -> Result<NestedExitRecord, HypervisorError> {
+        let record = self.accounting.end_exit(vm_id, vcpu_id)?;
+        self.stats.total_overhead_ns += record.total_ns;
+        if let Some(nested_vm) = self.nested_vms.get_mut(&vm_id) {
+            nested_vm.performance_metrics = self.accounting.performance_metrics(vm_id);
+        }
+        Ok(record)
+    }
+    
+    /// Record guest run time and retired instructions of a nested VM
+    pub fn record_nested_guest_run(&mut self, vm_id: VmId, run_ns: u64, instructions: u64) {
+        self.accounting.record_guest_run(vm_id, run_ns, instructions);
+        if let Some(nested_vm) = self.nested_vms.get_mut(&vm_id) {
+            nested_vm.performance_metrics = self.accounting.performance_metrics(vm_id);
+        }
+    }
+    
+    /// Measured exit cost of a nested VM
+    pub fn get_exit_breakdown(&self, vm_id: VmId) -> Option<ExitCostBreakdown> {
+        self.accounting.vm_breakdown(vm_id)
+    }
+    
+    /// Measured exit cost per nesting level
+    pub fn get_level_breakdowns(&self) -> Vec<(NestingLevel, ExitCostBreakdown)> {
+        self.accounting.level_breakdowns()
+    }
+    
+    /// Render the per-level overhead breakdown
+    pub fn generate_overhead_breakdown(&self) -> String {
+        let mut report = String::from("Per-level overhead:\n");
+        for (level, breakdown) in self.accounting.level_breakdowns() {
+            let (l0, guest, sync) = breakdown.shares();
+            report.push_str(&format!("  {:?}: exits={} reflected={} avg={} ns max={} ns L0={:.1}% guest-hypervisor={:.1}% vmcs-sync={:.1}% ({} syncs, {} fields)\n",
+                                  level, breakdown.exits, breakdown.reflected_exits, breakdown.avg_exit_ns(), breakdown.max_exit_ns,
+                                  l0, guest, sync, breakdown.vmcs_syncs, breakdown.vmcs_fields_synced));
+        }
+        report
+    }
+    
+    /// Offer the paravirtual nested interface to a VM running a MultiOS
+    /// hypervisor
+    pub fn enable_enlightenments(&mut self, vm_id: VmId, features: PvNestedFeatures) -> Result<(), HypervisorError> {
+        if !self.nested_vms.contains_key(&vm_id) {
+            return Err(HypervisorError::VmNotFound(vm_id));
         }
+        self.enlightened.enable(vm_id, features)
+    }
+    
+    pub fn disable_enlightenments(&mut self, vm_id: VmId) {
+        self.enlightened.disable(vm_id);
+    }
+    
+    /// CPUID leaves advertising the paravirtual nested interface
+    pub fn enlightened_cpuid(&self, vm_id: VmId, leaf: u32) -> Option<CpuidResult> {
+        self.enlightened.cpuid(vm_id, leaf)
+    }
+    
+    /// Handle a paravirtual nested hypercall; `Ok(None)` for VMs without
+    /// the interface or calls needing no further work from L0
+    pub fn handle_enlightened_hypercall(&mut self, vm_id: VmId, regs: &mut VcpuRegs, memory: &dyn GuestMemoryAccess)
+        -> Result<Option<EnlightenedAction>, HypervisorError> {
+        self.enlightened.handle_hypercall(vm_id, regs, memory)
+    }
+    
+    pub fn get_enlightened_stats(&self, vm_id: VmId) -> Option<EnlightenedStats> {
+        self.enlightened.stats(vm_id)
     }
     
     /// Get nested VM information
@@ -531,6 +629,9 @@ impl NestedVirtualizationManager {
             report.push_str(&format!("  VM {} -> {:?}\n", parent_id.0, children));
         }
         
+        report.push('\n');
+        report.push_str(&self.generate_overhead_breakdown());
+        
         report
     }
     
@@ -0,0 +1,550 @@
+//! Per-VM Firewall
+//!
+//! Every packet a VM sends passes its egress rules and every packet it
+//! receives its ingress rules. Rules match on protocol, remote IPv4 network
+//! and destination port and are evaluated in order; the first match
+//! decides, otherwise the profile's default applies. Accepted TCP, UDP and
+//! ICMP flows are entered into a connection table, and later packets of a
+//! tracked flow, in either direction, are accepted without consulting the
+//! rules. That is what lets a default-deny profile allow replies.
+
+use crate::{HypervisorError, ConfigError, ConfigErrorReason};
+use super::PacketInfo;
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+/// Connections tracked per VM before new flows are dropped
+pub const MAX_TRACKED_CONNECTIONS: usize = 4096;
+
+const NS_PER_SEC: u64 = 1_000_000_000;
+const TCP_ESTABLISHED_TIMEOUT_NS: u64 = 3600 * NS_PER_SEC;
+const TCP_CLOSING_TIMEOUT_NS: u64 = 120 * NS_PER_SEC;
+const TCP_SYN_TIMEOUT_NS: u64 = 60 * NS_PER_SEC;
+const UDP_TIMEOUT_NS: u64 = 30 * NS_PER_SEC;
+/// UDP flows that saw a reply, e.g. long-lived DNS or game traffic
+const UDP_STREAM_TIMEOUT_NS: u64 = 180 * NS_PER_SEC;
+const ICMP_TIMEOUT_NS: u64 = 30 * NS_PER_SEC;
+
+pub const IP_PROTO_ICMP: u8 = 1;
+pub const IP_PROTO_TCP: u8 = 6;
+pub const IP_PROTO_UDP: u8 = 17;
+
+const TCP_FIN: u8 = 0x01;
+const TCP_SYN: u8 = 0x02;
+const TCP_RST: u8 = 0x04;
+const TCP_ACK: u8 = 0x10;
+
+/// Which way a packet crosses the VM's virtual NIC
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Towards the VM
+    Ingress,
+    /// Sent by the VM
+    Egress,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Any,
+    Tcp,
+    Udp,
+    Icmp,
+}
+
+impl Protocol {
+    fn matches(&self, ip_protocol: u8) -> bool {
+        match self {
+            Protocol::Any => true,
+            Protocol::Tcp => ip_protocol == IP_PROTO_TCP,
+            Protocol::Udp => ip_protocol == IP_PROTO_UDP,
+            Protocol::Icmp => ip_protocol == IP_PROTO_ICMP,
+        }
+    }
+}
+
+/// IPv4 network in CIDR notation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ipv4Cidr {
+    pub address: [u8; 4],
+    pub prefix_len: u8,
+}
+
+impl Ipv4Cidr {
+    pub fn new(address: [u8; 4], prefix_len: u8) -> Result<Self, HypervisorError> {
+        if prefix_len > 32 {
+            return Err(HypervisorError::InvalidParameter);
+        }
+        Ok(Ipv4Cidr { address, prefix_len })
+    }
+
+    /// Parse `a.b.c.d/len`; a bare address is a /32
+    pub fn parse(text: &str) -> Result<Self, HypervisorError> {
+        let invalid = || HypervisorError::from(ConfigError::new("firewall.cidr", ConfigErrorReason::Malformed));
+        let (address, prefix_len) = match text.split_once('/') {
+            Some((address, prefix_len)) => (address, prefix_len.parse().map_err(|_| invalid())?),
+            None => (text, 32),
+        };
+        let mut octets = [0u8; 4];
+        let mut parts = address.split('.');
+        for octet in &mut octets {
+            *octet = parts.next().and_then(|part| part.parse().ok()).ok_or_else(invalid)?;
+        }
+        if parts.next().is_some() {
+            return Err(invalid());
+        }
+        Ipv4Cidr::new(octets, prefix_len).map_err(|_| invalid())
+    }
+
+    fn mask(&self) -> u32 {
+        if self.prefix_len == 0 { 0 } else { u32::MAX << (32 - self.prefix_len) }
+    }
+
+    pub fn contains(&self, address: [u8; 4]) -> bool {
+        (u32::from_be_bytes(address) ^ u32::from_be_bytes(self.address)) & self.mask() == 0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FirewallAction {
+    Accept,
+    Drop,
+}
+
+/// A firewall rule
+#[derive(Debug, Clone, PartialEq)]
+pub struct FirewallRule {
+    pub direction: Direction,
+    pub protocol: Protocol,
+    /// Remote network: the source of ingress and the destination of egress
+    /// packets; `None` matches any address
+    pub remote: Option<Ipv4Cidr>,
+    /// Inclusive destination port range; `None` matches any port
+    pub ports: Option<(u16, u16)>,
+    pub action: FirewallAction,
+}
+
+impl FirewallRule {
+    /// Accept `protocol` traffic in `direction` to a single port
+    pub fn allow_port(direction: Direction, protocol: Protocol, port: u16) -> Self {
+        FirewallRule { direction, protocol, remote: None, ports: Some((port, port)), action: FirewallAction::Accept }
+    }
+
+    fn matches(&self, direction: Direction, packet: &PacketInfo) -> bool {
+        let Some(ip) = &packet.ip else { return false };
+        let remote = match direction {
+            Direction::Ingress => ip.source,
+            Direction::Egress => ip.destination,
+        };
+        self.direction == direction
+            && self.protocol.matches(ip.protocol)
+            && self.remote.map_or(true, |cidr| cidr.contains(remote))
+            && self.ports.map_or(true, |(first, last)| {
+                // ICMP has no ports
+                ip.protocol != IP_PROTO_ICMP && (first..=last).contains(&ip.destination_port)
+            })
+    }
+}
+
+/// Packets and bytes that hit a rule or default policy
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RuleCounters {
+    pub packets: u64,
+    pub bytes: u64,
+}
+
+impl RuleCounters {
+    fn hit(&mut self, bytes: usize) {
+        self.packets += 1;
+        self.bytes += bytes as u64;
+    }
+}
+
+/// Starting point of a VM's firewall
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FirewallProfile {
+    /// Accept everything unless a rule drops it
+    Open,
+    /// Drop unsolicited ingress, accept egress
+    Outbound,
+    /// Drop everything in both directions except DHCP and DNS from the VM
+    /// and replies to accepted traffic; the instructor opens what a lab
+    /// needs with rules
+    LabDefaultDeny,
+    /// Drop all traffic, including ARP
+    Isolated,
+}
+
+impl FirewallProfile {
+    /// Default action when no rule matches
+    pub fn default_action(&self, direction: Direction) -> FirewallAction {
+        match (self, direction) {
+            (FirewallProfile::Open, _) | (FirewallProfile::Outbound, Direction::Egress) => FirewallAction::Accept,
+            _ => FirewallAction::Drop,
+        }
+    }
+
+    /// Rules installed with the profile, ahead of user rules
+    pub fn builtin_rules(&self) -> Vec<FirewallRule> {
+        match self {
+            FirewallProfile::LabDefaultDeny => alloc::vec![
+                FirewallRule::allow_port(Direction::Egress, Protocol::Udp, 67),
+                // DHCP offers are broadcast and do not match the request's flow
+                FirewallRule::allow_port(Direction::Ingress, Protocol::Udp, 68),
+                FirewallRule::allow_port(Direction::Egress, Protocol::Udp, 53),
+                FirewallRule::allow_port(Direction::Egress, Protocol::Tcp, 53),
+            ],
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Packet verdict
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// Part of a tracked connection
+    Established,
+    /// Accepted by the rule with this ID
+    AcceptedByRule(u32),
+    /// Dropped by the rule with this ID
+    DroppedByRule(u32),
+    /// No rule matched
+    AcceptedByDefault,
+    DroppedByDefault,
+    /// New flow dropped because the connection table is full
+    TableFull,
+}
+
+impl Verdict {
+    pub fn accepted(&self) -> bool {
+        matches!(self, Verdict::Established | Verdict::AcceptedByRule(_) | Verdict::AcceptedByDefault)
+    }
+}
+
+/// Flow identity in the direction of its first packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FlowKey {
+    pub protocol: u8,
+    pub source: [u8; 4],
+    pub source_port: u16,
+    pub destination: [u8; 4],
+    pub destination_port: u16,
+}
+
+impl FlowKey {
+    fn reversed(&self) -> Self {
+        FlowKey {
+            protocol: self.protocol,
+            source: self.destination,
+            source_port: self.destination_port,
+            destination: self.source,
+            destination_port: self.source_port,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// Only the initiator has sent packets
+    New,
+    /// Both sides have sent packets
+    Established,
+    /// A FIN or RST was seen
+    Closing,
+}
+
+/// Tracked connection
+#[derive(Debug, Clone, Copy)]
+pub struct Connection {
+    /// Direction of the first packet, relative to the VM
+    pub direction: Direction,
+    pub state: ConnectionState,
+    pub last_seen_ns: u64,
+    pub packets: u64,
+    pub bytes: u64,
+}
+
+impl Connection {
+    fn timeout_ns(&self, protocol: u8) -> u64 {
+        match (protocol, self.state) {
+            (IP_PROTO_TCP, ConnectionState::New) => TCP_SYN_TIMEOUT_NS,
+            (IP_PROTO_TCP, ConnectionState::Established) => TCP_ESTABLISHED_TIMEOUT_NS,
+            (IP_PROTO_TCP, ConnectionState::Closing) => TCP_CLOSING_TIMEOUT_NS,
+            (IP_PROTO_UDP, ConnectionState::New) => UDP_TIMEOUT_NS,
+            (IP_PROTO_UDP, _) => UDP_STREAM_TIMEOUT_NS,
+            _ => ICMP_TIMEOUT_NS,
+        }
+    }
+}
+
+/// Firewall statistics of a VM
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FirewallStats {
+    pub established: RuleCounters,
+    pub default_accepted: RuleCounters,
+    pub default_dropped: RuleCounters,
+    pub table_full_drops: u64,
+    pub expired_connections: u64,
+}
+
+/// Firewall of one VM
+#[derive(Debug, Clone)]
+pub struct VmFirewall {
+    profile: FirewallProfile,
+    /// Rules by ID, evaluated in insertion order
+    rules: Vec<(u32, FirewallRule, RuleCounters)>,
+    /// IDs of the rules the profile contributed
+    builtin_ids: Vec<u32>,
+    next_rule_id: u32,
+    connections: BTreeMap<FlowKey, Connection>,
+    stats: FirewallStats,
+}
+
+impl VmFirewall {
+    pub fn new(profile: FirewallProfile) -> Self {
+        let mut firewall = VmFirewall {
+            profile,
+            rules: Vec::new(),
+            builtin_ids: Vec::new(),
+            next_rule_id: 1,
+            connections: BTreeMap::new(),
+            stats: FirewallStats::default(),
+        };
+        firewall.set_profile(profile);
+        firewall
+    }
+
+    pub fn profile(&self) -> FirewallProfile {
+        self.profile
+    }
+
+    /// Switch profiles; user rules and tracked connections are kept
+    pub fn set_profile(&mut self, profile: FirewallProfile) {
+        let old_builtins = core::mem::take(&mut self.builtin_ids);
+        self.rules.retain(|(id, _, _)| !old_builtins.contains(id));
+        self.profile = profile;
+
+        let builtins: Vec<_> = profile.builtin_rules().into_iter().map(|rule| {
+            let id = self.next_rule_id;
+            self.next_rule_id += 1;
+            self.builtin_ids.push(id);
+            (id, rule, RuleCounters::default())
+        }).collect();
+        self.rules.splice(0..0, builtins);
+    }
+
+    /// Append a rule; returns its ID
+    pub fn add_rule(&mut self, rule: FirewallRule) -> u32 {
+        let id = self.next_rule_id;
+        self.next_rule_id += 1;
+        self.rules.push((id, rule, RuleCounters::default()));
+        id
+    }
+
+    pub fn remove_rule(&mut self, id: u32) -> Result<FirewallRule, HypervisorError> {
+        let index = self.rules.iter().position(|(rule_id, _, _)| *rule_id == id)
+            .ok_or(HypervisorError::InvalidParameter)?;
+        Ok(self.rules.remove(index).1)
+    }
+
+    /// Rules in evaluation order with their IDs and counters
+    pub fn rules(&self) -> &[(u32, FirewallRule, RuleCounters)] {
+        &self.rules
+    }
+
+    pub fn stats(&self) -> FirewallStats {
+        self.stats
+    }
+
+    pub fn connections(&self) -> impl Iterator<Item = (&FlowKey, &Connection)> {
+        self.connections.iter()
+    }
+
+    /// Forget all tracked connections, e.g. after tightening the rules
+    pub fn flush_connections(&mut self) {
+        self.connections.clear();
+    }
+
+    /// Decide a packet crossing the VM's NIC in `direction`
+    pub fn filter(&mut self, direction: Direction, packet: &PacketInfo, now_ns: u64) -> Verdict {
+        let Some(ip) = &packet.ip else {
+            // ARP is needed by every profile that passes any IP traffic
+            let action = if packet.is_arp() && self.profile != FirewallProfile::Isolated {
+                FirewallAction::Accept
+            } else {
+                self.profile.default_action(direction)
+            };
+            return self.apply_default(action, packet.length);
+        };
+
+        let key = FlowKey {
+            protocol: ip.protocol,
+            source: ip.source,
+            source_port: ip.source_port,
+            destination: ip.destination,
+            destination_port: ip.destination_port,
+        };
+        // A flow's own packets travel the way it was opened and replies the
+        // other way; anything else reusing the tuple is not part of it
+        let reverse_direction = match direction {
+            Direction::Ingress => Direction::Egress,
+            Direction::Egress => Direction::Ingress,
+        };
+        let tracked = [(key, direction), (key.reversed(), reverse_direction)]
+            .into_iter()
+            .find(|(key, opened)| self.connections.get(key).is_some_and(|connection| connection.direction == *opened))
+            .map(|(key, _)| key);
+        if let Some(tracked_key) = tracked {
+            let connection = self.connections.get_mut(&tracked_key).unwrap();
+            if now_ns.saturating_sub(connection.last_seen_ns) <= connection.timeout_ns(ip.protocol) {
+                let reply = tracked_key != key;
+                update_connection(connection, ip.protocol, ip.tcp_flags, reply, packet.length, now_ns);
+                self.stats.established.hit(packet.length);
+                return Verdict::Established;
+            }
+            self.connections.remove(&tracked_key);
+            self.stats.expired_connections += 1;
+        }
+
+        let matched = self.rules.iter_mut().find(|(_, rule, _)| rule.matches(direction, packet));
+        let verdict = match matched {
+            Some((id, rule, counters)) => {
+                counters.hit(packet.length);
+                match rule.action {
+                    FirewallAction::Accept => Verdict::AcceptedByRule(*id),
+                    FirewallAction::Drop => return Verdict::DroppedByRule(*id),
+                }
+            }
+            None => match self.apply_default(self.profile.default_action(direction), packet.length) {
+                verdict if verdict.accepted() => verdict,
+                verdict => return verdict,
+            },
+        };
+
+        // A TCP flow is only tracked from its opening SYN; mid-stream
+        // packets are judged by the rules every time
+        let opens_flow = ip.protocol != IP_PROTO_TCP || ip.tcp_flags & (TCP_SYN | TCP_ACK) == TCP_SYN;
+        let trackable = matches!(ip.protocol, IP_PROTO_TCP | IP_PROTO_UDP | IP_PROTO_ICMP);
+        if trackable && opens_flow && self.profile != FirewallProfile::Open {
+            if self.connections.len() >= MAX_TRACKED_CONNECTIONS {
+                self.expire(now_ns);
+            }
+            if self.connections.len() >= MAX_TRACKED_CONNECTIONS {
+                self.stats.table_full_drops += 1;
+                return Verdict::TableFull;
+            }
+            let mut connection = Connection {
+                direction,
+                state: ConnectionState::New,
+                last_seen_ns: now_ns,
+                packets: 0,
+                bytes: 0,
+            };
+            update_connection(&mut connection, ip.protocol, ip.tcp_flags, false, packet.length, now_ns);
+            // Never let a packet in the wrong direction replace a tracked flow
+            self.connections.entry(key).or_insert(connection);
+        }
+        verdict
+    }
+
+    fn apply_default(&mut self, action: FirewallAction, length: usize) -> Verdict {
+        match action {
+            FirewallAction::Accept => {
+                self.stats.default_accepted.hit(length);
+                Verdict::AcceptedByDefault
+            }
+            FirewallAction::Drop => {
+                self.stats.default_dropped.hit(length);
+                Verdict::DroppedByDefault
+            }
+        }
+    }
+
+    /// Drop connections that timed out; returns how many
+    pub fn expire(&mut self, now_ns: u64) -> usize {
+        let before = self.connections.len();
+        self.connections.retain(|key, connection| {
+            now_ns.saturating_sub(connection.last_seen_ns) <= connection.timeout_ns(key.protocol)
+        });
+        let expired = before - self.connections.len();
+        self.stats.expired_connections += expired as u64;
+        expired
+    }
+}
+
+fn update_connection(connection: &mut Connection, protocol: u8, tcp_flags: u8, reply: bool, length: usize, now_ns: u64) {
+    connection.last_seen_ns = now_ns;
+    connection.packets += 1;
+    connection.bytes += length as u64;
+    if protocol == IP_PROTO_TCP && tcp_flags & (TCP_FIN | TCP_RST) != 0 {
+        connection.state = ConnectionState::Closing;
+    } else if reply && connection.state == ConnectionState::New {
+        connection.state = ConnectionState::Established;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::Ipv4Info;
+
+    const VM: [u8; 4] = [10, 0, 0, 2];
+    const RESOLVER: [u8; 4] = [8, 8, 8, 8];
+
+    fn udp(source: ([u8; 4], u16), destination: ([u8; 4], u16)) -> PacketInfo {
+        PacketInfo {
+            destination_mac: [0; 6],
+            source_mac: [0; 6],
+            ethertype: 0x0800,
+            vlan: None,
+            ip: Some(Ipv4Info {
+                protocol: IP_PROTO_UDP,
+                source: source.0,
+                destination: destination.0,
+                source_port: source.1,
+                destination_port: destination.1,
+                tcp_flags: 0,
+            }),
+            length: 64,
+        }
+    }
+
+    #[test]
+    fn test_reply_to_tracked_flow_is_established() {
+        let mut firewall = VmFirewall::new(FirewallProfile::Outbound);
+        let query = udp((VM, 40000), (RESOLVER, 53));
+        assert_eq!(firewall.filter(Direction::Egress, &query, 0), Verdict::AcceptedByDefault);
+        assert_eq!(firewall.filter(Direction::Egress, &query, 1), Verdict::Established);
+
+        let reply = udp((RESOLVER, 53), (VM, 40000));
+        assert_eq!(firewall.filter(Direction::Ingress, &reply, 2), Verdict::Established);
+        let (_, connection) = firewall.connections().next().unwrap();
+        assert_eq!(connection.state, ConnectionState::Established);
+    }
+
+    #[test]
+    fn test_spoofed_ingress_with_egress_tuple_is_rejected() {
+        let mut firewall = VmFirewall::new(FirewallProfile::Outbound);
+        let query = udp((VM, 40000), (RESOLVER, 53));
+        assert_eq!(firewall.filter(Direction::Egress, &query, 0), Verdict::AcceptedByDefault);
+
+        // Arrives from outside but claims to come from the VM
+        assert_eq!(firewall.filter(Direction::Ingress, &query, 1), Verdict::DroppedByDefault);
+        let (_, connection) = firewall.connections().next().unwrap();
+        assert_eq!(connection.direction, Direction::Egress);
+        assert_eq!(connection.state, ConnectionState::New);
+    }
+
+    #[test]
+    fn test_reply_tuple_sent_by_the_vm_is_not_established() {
+        let mut firewall = VmFirewall::new(FirewallProfile::LabDefaultDeny);
+        firewall.add_rule(FirewallRule::allow_port(Direction::Ingress, Protocol::Udp, 5000));
+        let inbound = udp((RESOLVER, 6000), (VM, 5000));
+        assert!(firewall.filter(Direction::Ingress, &inbound, 0).accepted());
+
+        // The reversed tuple is only a reply when the VM sends it
+        let reply = udp((VM, 5000), (RESOLVER, 6000));
+        assert_eq!(firewall.filter(Direction::Ingress, &reply, 1), Verdict::DroppedByDefault);
+        assert_eq!(firewall.filter(Direction::Egress, &reply, 2), Verdict::Established);
+    }
+}
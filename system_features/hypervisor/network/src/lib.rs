@@ -0,0 +1,367 @@
+//! Virtual Networking
+//!
+//! A learning virtual switch connecting the virtual NICs of VMs with each
+//! other and with host uplinks. Frames sent by a VM pass the egress rules
+//! of its firewall, and frames delivered to a VM its ingress rules. Frames
+//! sent with `transmit` first pass the link emulation of their NIC.
+
+use crate::{HypervisorError, VmId};
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+mod firewall;
+mod shaping;
+
+pub use firewall::*;
+pub use shaping::*;
+
+pub type MacAddress = [u8; 6];
+pub type PortId = u32;
+
+const ETHERNET_HEADER_LEN: usize = 14;
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_ARP: u16 = 0x0806;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+
+/// IPv4 and transport fields of a packet
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ipv4Info {
+    pub protocol: u8,
+    pub source: [u8; 4],
+    pub destination: [u8; 4],
+    /// Transport ports; the echo identifier for ICMP echo, 0 when absent
+    /// or when the packet is a non-first fragment
+    pub source_port: u16,
+    pub destination_port: u16,
+    pub tcp_flags: u8,
+}
+
+/// Parsed headers of an Ethernet frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PacketInfo {
+    pub destination_mac: MacAddress,
+    pub source_mac: MacAddress,
+    pub ethertype: u16,
+    pub vlan: Option<u16>,
+    pub ip: Option<Ipv4Info>,
+    /// Frame length in bytes
+    pub length: usize,
+}
+
+impl PacketInfo {
+    pub fn parse(frame: &[u8]) -> Result<Self, HypervisorError> {
+        if frame.len() < ETHERNET_HEADER_LEN {
+            return Err(HypervisorError::InvalidParameter);
+        }
+        let mut ethertype = u16::from_be_bytes([frame[12], frame[13]]);
+        let mut payload = &frame[ETHERNET_HEADER_LEN..];
+        let mut vlan = None;
+        if ethertype == ETHERTYPE_VLAN {
+            if payload.len() < 4 {
+                return Err(HypervisorError::InvalidParameter);
+            }
+            vlan = Some(u16::from_be_bytes([payload[0], payload[1]]) & 0x0fff);
+            ethertype = u16::from_be_bytes([payload[2], payload[3]]);
+            payload = &payload[4..];
+        }
+
+        Ok(PacketInfo {
+            destination_mac: frame[0..6].try_into().unwrap(),
+            source_mac: frame[6..12].try_into().unwrap(),
+            ethertype,
+            vlan,
+            ip: if ethertype == ETHERTYPE_IPV4 { parse_ipv4(payload) } else { None },
+            length: frame.len(),
+        })
+    }
+
+    pub fn is_arp(&self) -> bool {
+        self.ethertype == ETHERTYPE_ARP
+    }
+
+    /// Broadcast or multicast destination
+    pub fn is_multicast(&self) -> bool {
+        self.destination_mac[0] & 1 != 0
+    }
+}
+
+fn parse_ipv4(packet: &[u8]) -> Option<Ipv4Info> {
+    if packet.len() < 20 || packet[0] >> 4 != 4 {
+        return None;
+    }
+    let header_len = ((packet[0] & 0x0f) as usize) * 4;
+    if header_len < 20 || packet.len() < header_len {
+        return None;
+    }
+    let mut info = Ipv4Info {
+        protocol: packet[9],
+        source: packet[12..16].try_into().unwrap(),
+        destination: packet[16..20].try_into().unwrap(),
+        source_port: 0,
+        destination_port: 0,
+        tcp_flags: 0,
+    };
+
+    // Only the first fragment carries the transport header
+    let fragment_offset = u16::from_be_bytes([packet[6], packet[7]]) & 0x1fff;
+    if fragment_offset != 0 {
+        return Some(info);
+    }
+    let transport = &packet[header_len..];
+    match info.protocol {
+        IP_PROTO_TCP | IP_PROTO_UDP if transport.len() >= 4 => {
+            info.source_port = u16::from_be_bytes([transport[0], transport[1]]);
+            info.destination_port = u16::from_be_bytes([transport[2], transport[3]]);
+            if info.protocol == IP_PROTO_TCP && transport.len() >= 14 {
+                info.tcp_flags = transport[13];
+            }
+        }
+        // Echo reply and request share the identifier
+        IP_PROTO_ICMP if transport.len() >= 6 && matches!(transport[0], 0 | 8) => {
+            let identifier = u16::from_be_bytes([transport[4], transport[5]]);
+            info.source_port = identifier;
+            info.destination_port = identifier;
+        }
+        _ => {}
+    }
+    Some(info)
+}
+
+/// Traffic counters of a switch port, from the attached NIC's view
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PortStats {
+    pub tx_packets: u64,
+    pub tx_bytes: u64,
+    pub rx_packets: u64,
+    pub rx_bytes: u64,
+    /// Frames to or from this port dropped by a firewall
+    pub dropped: u64,
+}
+
+/// A switch port
+#[derive(Debug, Clone)]
+pub struct SwitchPort {
+    /// VM owning the NIC on this port; `None` for a host uplink
+    pub vm_id: Option<VmId>,
+    pub mac: Option<MacAddress>,
+    pub stats: PortStats,
+}
+
+/// A frame leaving link emulation and the ports it reached
+#[derive(Debug, Clone)]
+pub struct Delivery {
+    pub source: PortId,
+    pub frame: Vec<u8>,
+    pub ports: Vec<PortId>,
+}
+
+/// Learning Ethernet switch with per-VM firewalls
+#[derive(Debug)]
+pub struct VirtualSwitch {
+    ports: BTreeMap<PortId, SwitchPort>,
+    /// Link emulation of the ports whose conditions are not ideal
+    shapers: BTreeMap<PortId, NicShaper>,
+    mac_table: BTreeMap<MacAddress, PortId>,
+    firewalls: BTreeMap<VmId, VmFirewall>,
+    /// Profile given to the firewall of a newly attached VM
+    default_profile: FirewallProfile,
+    next_port: PortId,
+}
+
+impl VirtualSwitch {
+    pub fn new(default_profile: FirewallProfile) -> Self {
+        VirtualSwitch {
+            ports: BTreeMap::new(),
+            shapers: BTreeMap::new(),
+            mac_table: BTreeMap::new(),
+            firewalls: BTreeMap::new(),
+            default_profile,
+            next_port: 1,
+        }
+    }
+
+    pub fn default_profile(&self) -> FirewallProfile {
+        self.default_profile
+    }
+
+    /// Profile for VMs attached from now on
+    pub fn set_default_profile(&mut self, profile: FirewallProfile) {
+        self.default_profile = profile;
+    }
+
+    fn add_port(&mut self, vm_id: Option<VmId>, mac: Option<MacAddress>) -> PortId {
+        let port = self.next_port;
+        self.next_port += 1;
+        self.ports.insert(port, SwitchPort { vm_id, mac, stats: PortStats::default() });
+        port
+    }
+
+    /// Connect a VM's NIC; the VM gets a firewall on its first NIC
+    pub fn attach_vm(&mut self, vm_id: VmId, mac: MacAddress) -> PortId {
+        let profile = self.default_profile;
+        self.firewalls.entry(vm_id).or_insert_with(|| VmFirewall::new(profile));
+        let port = self.add_port(Some(vm_id), Some(mac));
+        self.mac_table.insert(mac, port);
+        port
+    }
+
+    /// Connect a host uplink; its traffic is only filtered by the VMs'
+    /// firewalls
+    pub fn attach_uplink(&mut self) -> PortId {
+        self.add_port(None, None)
+    }
+
+    pub fn detach(&mut self, port: PortId) -> Result<(), HypervisorError> {
+        self.ports.remove(&port).ok_or(HypervisorError::InvalidParameter)?;
+        self.shapers.remove(&port);
+        self.mac_table.retain(|_, learned| *learned != port);
+        Ok(())
+    }
+
+    /// Detach all NICs of a VM and drop its firewall
+    pub fn remove_vm(&mut self, vm_id: VmId) {
+        let ports: Vec<PortId> = self.ports.iter()
+            .filter(|(_, port)| port.vm_id == Some(vm_id))
+            .map(|(&id, _)| id)
+            .collect();
+        for port in ports {
+            let _ = self.detach(port);
+        }
+        self.firewalls.remove(&vm_id);
+    }
+
+    pub fn port(&self, port: PortId) -> Option<&SwitchPort> {
+        self.ports.get(&port)
+    }
+
+    pub fn firewall(&self, vm_id: VmId) -> Option<&VmFirewall> {
+        self.firewalls.get(&vm_id)
+    }
+
+    pub fn firewall_mut(&mut self, vm_id: VmId) -> Option<&mut VmFirewall> {
+        self.firewalls.get_mut(&vm_id)
+    }
+
+    /// Switch a frame sent on `port`; returns the ports it is delivered to
+    pub fn forward(&mut self, port: PortId, frame: &[u8], now_ns: u64) -> Result<Vec<PortId>, HypervisorError> {
+        let packet = PacketInfo::parse(frame)?;
+        let source = self.ports.get_mut(&port).ok_or(HypervisorError::InvalidParameter)?;
+        let source_vm = source.vm_id;
+
+        if let Some(firewall) = source_vm.and_then(|vm_id| self.firewalls.get_mut(&vm_id)) {
+            if !firewall.filter(Direction::Egress, &packet, now_ns).accepted() {
+                source.stats.dropped += 1;
+                return Ok(Vec::new());
+            }
+        }
+        source.stats.tx_packets += 1;
+        source.stats.tx_bytes += frame.len() as u64;
+
+        if !packet.is_multicast() {
+            self.mac_table.insert(packet.source_mac, port);
+        }
+        let targets: Vec<PortId> = match self.mac_table.get(&packet.destination_mac) {
+            Some(&target) if !packet.is_multicast() => if target == port { Vec::new() } else { alloc::vec![target] },
+            // Broadcast, multicast and unknown unicast are flooded
+            _ => self.ports.keys().copied().filter(|&target| target != port).collect(),
+        };
+
+        let mut delivered = Vec::with_capacity(targets.len());
+        for target in targets {
+            let Some(target_port) = self.ports.get_mut(&target) else { continue };
+            if let Some(firewall) = target_port.vm_id.and_then(|vm_id| self.firewalls.get_mut(&vm_id)) {
+                if !firewall.filter(Direction::Ingress, &packet, now_ns).accepted() {
+                    target_port.stats.dropped += 1;
+                    continue;
+                }
+            }
+            target_port.stats.rx_packets += 1;
+            target_port.stats.rx_bytes += frame.len() as u64;
+            delivered.push(target);
+        }
+        Ok(delivered)
+    }
+
+    /// Set the emulated link of a port. Ideal conditions remove the
+    /// emulation once no frames are queued.
+    pub fn set_link_conditions(&mut self, port: PortId, conditions: LinkConditions) -> Result<(), HypervisorError> {
+        if !self.ports.contains_key(&port) {
+            return Err(HypervisorError::InvalidParameter);
+        }
+        match self.shapers.get_mut(&port) {
+            Some(shaper) => shaper.set_conditions(conditions)?,
+            None if conditions.is_ideal() => {}
+            None => {
+                // Seed by port so runs are repeatable
+                let shaper = NicShaper::new(conditions, 0x9e37_79b9_7f4a_7c15 ^ port as u64)?;
+                self.shapers.insert(port, shaper);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn link_conditions(&self, port: PortId) -> Option<LinkConditions> {
+        if !self.ports.contains_key(&port) {
+            return None;
+        }
+        Some(self.shapers.get(&port).map_or(LinkConditions::ideal(), |shaper| shaper.conditions()))
+    }
+
+    /// Link emulation of a port, if its conditions are not ideal
+    pub fn shaper(&self, port: PortId) -> Option<&NicShaper> {
+        self.shapers.get(&port)
+    }
+
+    pub fn shaper_mut(&mut self, port: PortId) -> Option<&mut NicShaper> {
+        self.shapers.get_mut(&port)
+    }
+
+    /// Send a frame on `port` through its link emulation. Frames of ports
+    /// with ideal links are switched at once; the rest are handed out by
+    /// `poll` when due.
+    pub fn transmit(&mut self, port: PortId, frame: Vec<u8>, now_ns: u64) -> Result<Option<Delivery>, HypervisorError> {
+        PacketInfo::parse(&frame)?;
+        match self.shapers.get_mut(&port) {
+            Some(shaper) => {
+                if !shaper.enqueue(frame, now_ns) {
+                    if let Some(source) = self.ports.get_mut(&port) {
+                        source.stats.dropped += 1;
+                    }
+                }
+                Ok(None)
+            }
+            None => {
+                let ports = self.forward(port, &frame, now_ns)?;
+                Ok(Some(Delivery { source: port, frame, ports }))
+            }
+        }
+    }
+
+    /// Switch the emulated frames due at `now_ns`, in release order
+    pub fn poll(&mut self, now_ns: u64) -> Vec<Delivery> {
+        let mut due = Vec::new();
+        for (&port, shaper) in self.shapers.iter_mut() {
+            due.extend(shaper.release(now_ns).into_iter().map(|(release, frame)| (release, port, frame)));
+        }
+        self.shapers.retain(|_, shaper| shaper.queued() > 0 || !shaper.conditions().is_ideal());
+        due.sort_by_key(|&(release, port, _)| (release, port));
+
+        due.into_iter()
+            .filter_map(|(_, port, frame)| {
+                let ports = self.forward(port, &frame, now_ns).ok()?;
+                Some(Delivery { source: port, frame, ports })
+            })
+            .collect()
+    }
+
+    /// When `poll` next has frames to hand out
+    pub fn next_deadline(&self) -> Option<u64> {
+        self.shapers.values().filter_map(|shaper| shaper.next_release()).min()
+    }
+
+    /// Expire timed-out connections of every VM's firewall
+    pub fn expire_connections(&mut self, now_ns: u64) -> usize {
+        self.firewalls.values_mut().map(|firewall| firewall.expire(now_ns)).sum()
+    }
+}
@@ -0,0 +1,273 @@
+//! Link Emulation
+//!
+//! Traffic shaping and netem-style impairments for frames sent by a virtual
+//! NIC. A token bucket limits the rate with a configurable burst, then
+//! frames are lost, delayed with jitter or reordered before they reach the
+//! switch. Conditions can be changed while frames are queued.
+
+use crate::HypervisorError;
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+/// Frames a NIC may have queued before new ones are dropped
+pub const MAX_QUEUED_FRAMES: usize = 1000;
+
+/// Denominator of the loss and reorder probabilities
+pub const PPM: u32 = 1_000_000;
+
+/// Emulated link properties of a virtual NIC
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LinkConditions {
+    /// Rate limit in bits per second; 0 for unlimited
+    pub rate_bps: u64,
+    /// Bytes that may be sent back to back at line rate
+    pub burst_bytes: u64,
+    /// Fixed one-way delay
+    pub delay_ns: u64,
+    /// Delay varies uniformly by up to this much in either direction.
+    /// Like netem, large jitter reorders frames.
+    pub jitter_ns: u64,
+    /// Probability of losing a frame, in parts per million
+    pub loss_ppm: u32,
+    /// Probability of a frame skipping the delay and overtaking earlier
+    /// frames, in parts per million
+    pub reorder_ppm: u32,
+}
+
+impl LinkConditions {
+    /// A perfect link
+    pub fn ideal() -> Self {
+        LinkConditions::default()
+    }
+
+    pub fn is_ideal(&self) -> bool {
+        *self == LinkConditions::ideal()
+    }
+
+    fn validate(&self) -> Result<(), HypervisorError> {
+        if self.loss_ppm > PPM || self.reorder_ppm > PPM {
+            return Err(HypervisorError::InvalidParameter);
+        }
+        Ok(())
+    }
+
+    /// Time to serialize `bytes` at the rate limit
+    fn transmit_ns(&self, bytes: u64) -> u64 {
+        if self.rate_bps == 0 {
+            return 0;
+        }
+        (bytes as u128 * 8 * 1_000_000_000 / self.rate_bps as u128) as u64
+    }
+}
+
+/// Counters of a NIC's link emulation
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ShapingStats {
+    pub sent_packets: u64,
+    pub sent_bytes: u64,
+    pub lost: u64,
+    /// Frames dropped because the queue was full
+    pub overflows: u64,
+    pub reordered: u64,
+    /// Sum of the time sent frames spent queued, for the mean latency
+    pub total_latency_ns: u64,
+}
+
+#[derive(Debug, Clone)]
+struct QueuedFrame {
+    frame: Vec<u8>,
+    enqueued_ns: u64,
+}
+
+/// Link emulation for one virtual NIC
+#[derive(Debug, Clone)]
+pub struct NicShaper {
+    conditions: LinkConditions,
+    /// Theoretical arrival time of the token bucket, as in GCRA
+    bucket_time_ns: u64,
+    /// Frames by release time and sequence number
+    queue: BTreeMap<(u64, u64), QueuedFrame>,
+    sequence: u64,
+    random_state: u64,
+    stats: ShapingStats,
+}
+
+impl NicShaper {
+    pub fn new(conditions: LinkConditions, seed: u64) -> Result<Self, HypervisorError> {
+        conditions.validate()?;
+        Ok(NicShaper {
+            conditions,
+            bucket_time_ns: 0,
+            queue: BTreeMap::new(),
+            sequence: 0,
+            random_state: seed,
+            stats: ShapingStats::default(),
+        })
+    }
+
+    pub fn conditions(&self) -> LinkConditions {
+        self.conditions
+    }
+
+    /// Change the conditions; queued frames keep their release times
+    pub fn set_conditions(&mut self, conditions: LinkConditions) -> Result<(), HypervisorError> {
+        conditions.validate()?;
+        self.conditions = conditions;
+        Ok(())
+    }
+
+    pub fn stats(&self) -> ShapingStats {
+        self.stats
+    }
+
+    pub fn queued(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Restart the random sequence so an experiment can be repeated
+    pub fn reseed(&mut self, seed: u64) {
+        self.random_state = seed;
+    }
+
+    fn next_random(&mut self) -> u64 {
+        self.random_state = self.random_state.wrapping_mul(6364136223846793005).wrapping_add(1);
+        self.random_state >> 33
+    }
+
+    fn chance(&mut self, ppm: u32) -> bool {
+        ppm != 0 && self.next_random() % (PPM as u64) < ppm as u64
+    }
+
+    /// Queue a frame sent at `now_ns`; returns false if it was dropped
+    pub fn enqueue(&mut self, frame: Vec<u8>, now_ns: u64) -> bool {
+        if self.queue.len() >= MAX_QUEUED_FRAMES {
+            self.stats.overflows += 1;
+            return false;
+        }
+        let conditions = self.conditions;
+        if self.chance(conditions.loss_ppm) {
+            self.stats.lost += 1;
+            return false;
+        }
+
+        // Conforming frames leave at once, others wait for tokens
+        let burst_ns = conditions.transmit_ns(conditions.burst_bytes);
+        let departure = now_ns.max(self.bucket_time_ns.saturating_sub(burst_ns));
+        self.bucket_time_ns = self.bucket_time_ns.max(now_ns) + conditions.transmit_ns(frame.len() as u64);
+
+        let release = if self.chance(conditions.reorder_ppm) {
+            self.stats.reordered += 1;
+            departure
+        } else {
+            let jitter = if conditions.jitter_ns == 0 {
+                0
+            } else {
+                self.next_random() % (2 * conditions.jitter_ns + 1)
+            };
+            (departure + conditions.delay_ns + jitter).saturating_sub(conditions.jitter_ns).max(departure)
+        };
+
+        self.sequence += 1;
+        self.queue.insert((release, self.sequence), QueuedFrame { frame, enqueued_ns: now_ns });
+        true
+    }
+
+    /// When the next frame is due
+    pub fn next_release(&self) -> Option<u64> {
+        self.queue.keys().next().map(|&(release, _)| release)
+    }
+
+    /// Take the frames due at `now_ns` with their release times, in order
+    pub fn release(&mut self, now_ns: u64) -> Vec<(u64, Vec<u8>)> {
+        let mut due = Vec::new();
+        while let Some(entry) = self.queue.first_entry() {
+            let release = entry.key().0;
+            if release > now_ns {
+                break;
+            }
+            let queued = entry.remove();
+            self.stats.sent_packets += 1;
+            self.stats.sent_bytes += queued.frame.len() as u64;
+            self.stats.total_latency_ns += release - queued.enqueued_ns;
+            due.push((release, queued.frame));
+        }
+        due
+    }
+
+    /// Drop all queued frames
+    pub fn flush(&mut self) {
+        self.queue.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn test_frames_are_released_after_the_delay_in_order() {
+        let conditions = LinkConditions { delay_ns: 1_000, ..LinkConditions::ideal() };
+        let mut shaper = NicShaper::new(conditions, 1).unwrap();
+        assert!(shaper.enqueue(vec![1; 10], 0));
+        assert!(shaper.enqueue(vec![2; 20], 100));
+        assert_eq!(shaper.next_release(), Some(1_000));
+
+        assert!(shaper.release(999).is_empty());
+        assert_eq!(shaper.release(1_000), vec![(1_000, vec![1; 10])]);
+        assert_eq!(shaper.release(5_000), vec![(1_100, vec![2; 20])]);
+        assert_eq!(shaper.queued(), 0);
+
+        let stats = shaper.stats();
+        assert_eq!(stats.sent_packets, 2);
+        assert_eq!(stats.sent_bytes, 30);
+        assert_eq!(stats.total_latency_ns, 2_000);
+    }
+
+    #[test]
+    fn test_rate_limit_spaces_frames_beyond_the_burst() {
+        // 8 Mbit/s sends a 1000 byte frame every millisecond
+        let conditions = LinkConditions { rate_bps: 8_000_000, burst_bytes: 1_000, ..LinkConditions::ideal() };
+        let mut shaper = NicShaper::new(conditions, 1).unwrap();
+        for _ in 0..3 {
+            assert!(shaper.enqueue(vec![0; 1_000], 0));
+        }
+        let releases: Vec<u64> = shaper.release(u64::MAX).into_iter().map(|(release, _)| release).collect();
+        assert_eq!(releases, [0, 0, 1_000_000]);
+    }
+
+    #[test]
+    fn test_full_queue_overflows() {
+        let conditions = LinkConditions { delay_ns: 1_000, ..LinkConditions::ideal() };
+        let mut shaper = NicShaper::new(conditions, 1).unwrap();
+        for _ in 0..MAX_QUEUED_FRAMES {
+            assert!(shaper.enqueue(vec![0], 0));
+        }
+        assert!(!shaper.enqueue(vec![0], 0));
+        assert_eq!(shaper.stats().overflows, 1);
+    }
+
+    #[test]
+    fn test_loss_probability() {
+        let mut lossless = NicShaper::new(LinkConditions::ideal(), 7).unwrap();
+        let mut lossy = NicShaper::new(LinkConditions { loss_ppm: PPM, ..LinkConditions::ideal() }, 7).unwrap();
+        let mut half = NicShaper::new(LinkConditions { loss_ppm: PPM / 2, ..LinkConditions::ideal() }, 7).unwrap();
+        for now in 0..800 {
+            assert!(lossless.enqueue(vec![0], now));
+            assert!(!lossy.enqueue(vec![0], now));
+            half.enqueue(vec![0], now);
+            half.release(now);
+        }
+        assert_eq!(lossy.stats().lost, 800);
+        let lost = half.stats().lost;
+        assert!((300..500).contains(&lost), "lost {} of 800", lost);
+        assert_eq!(lost + half.stats().sent_packets, 800);
+    }
+
+    #[test]
+    fn test_invalid_probability_is_rejected() {
+        let conditions = LinkConditions { loss_ppm: PPM + 1, ..LinkConditions::ideal() };
+        assert_eq!(NicShaper::new(conditions, 1).unwrap_err(), HypervisorError::InvalidParameter);
+    }
+}
@@ -0,0 +1,483 @@
+//! Alert Delivery Module
+//!
+//! Delivers regression alerts over SMTP email, Slack incoming webhooks and
+//! generic HMAC-signed webhooks, retrying transient failures with exponential
+//! backoff and escalating unacknowledged alerts according to the configured
+//! `EscalationRules`.
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use lettre::message::header::ContentType;
+use lettre::message::{Attachment, Mailbox, MultiPart, SinglePart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use log::{debug, info, warn};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::future::Future;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+use crate::reporter::render::severity_color;
+use crate::reporter::ReportAttachment;
+use crate::{AlertConfig, DetectedRegression, RegressionSeverity, Uuid};
+
+/// Generic webhook endpoint
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookConfig {
+    pub url: String,
+    /// Shared secret for the `X-MultiOS-Signature` HMAC-SHA256 header
+    pub secret: Option<String>,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+}
+
+/// Retry schedule for alert delivery
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    pub multiplier: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff_ms: 500,
+            max_backoff_ms: 30_000,
+            multiplier: 2.0,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before retry number `attempt` (1-based)
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = self.multiplier.max(1.0).powi(attempt.saturating_sub(1) as i32);
+        let millis = (self.initial_backoff_ms as f64 * factor).min(self.max_backoff_ms as f64);
+        Duration::from_millis(millis as u64)
+    }
+}
+
+/// Failure of a single delivery attempt
+#[derive(Debug, thiserror::Error)]
+#[error("{message}")]
+struct DeliveryError {
+    message: String,
+    retryable: bool,
+}
+
+impl DeliveryError {
+    fn transient(message: impl Into<String>) -> Self {
+        Self { message: message.into(), retryable: true }
+    }
+
+    fn permanent(message: impl Into<String>) -> Self {
+        Self { message: message.into(), retryable: false }
+    }
+}
+
+/// Why an alert is being sent
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertEvent {
+    RegressionDetected,
+    RegressionEscalated,
+}
+
+/// Outcome of delivering one alert to every channel
+#[derive(Debug, Clone, Default)]
+pub struct AlertDeliveryReport {
+    pub delivered: Vec<String>,
+    pub failed: Vec<(String, String)>,
+}
+
+/// Alert awaiting acknowledgement
+#[derive(Debug, Clone)]
+struct OpenAlert {
+    regression: DetectedRegression,
+    raised_at: DateTime<Utc>,
+    escalated: bool,
+}
+
+/// Dispatcher delivering alerts to all configured channels
+pub struct AlertDispatcher {
+    config: AlertConfig,
+    http_client: Client,
+    open_alerts: Mutex<HashMap<Uuid, OpenAlert>>,
+}
+
+impl AlertDispatcher {
+    /// Create new alert dispatcher
+    pub fn new(config: AlertConfig) -> Self {
+        let http_client = Client::builder()
+            .timeout(Duration::from_secs(30))
+            .build()
+            .unwrap_or_default();
+
+        Self {
+            config,
+            http_client,
+            open_alerts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Deliver an alert for a newly detected regression and track it for escalation
+    pub async fn dispatch(
+        &self,
+        regression: &DetectedRegression,
+        attachments: &[ReportAttachment],
+    ) -> AlertDeliveryReport {
+        let recipients = self.config.email_notifications.to_addresses.clone();
+        let report = self
+            .deliver(AlertEvent::RegressionDetected, regression, &recipients, attachments)
+            .await;
+
+        self.open_alerts.lock().await.insert(
+            regression.id,
+            OpenAlert {
+                regression: regression.clone(),
+                raised_at: Utc::now(),
+                escalated: false,
+            },
+        );
+
+        report
+    }
+
+    /// Stop escalating an alert; returns whether it was open
+    pub async fn acknowledge(&self, regression_id: Uuid) -> bool {
+        self.open_alerts.lock().await.remove(&regression_id).is_some()
+    }
+
+    /// Escalate every open alert whose severity delay has elapsed; returns how many were escalated
+    pub async fn process_escalations(&self) -> usize {
+        let now = Utc::now();
+        let due: Vec<OpenAlert> = {
+            let mut open = self.open_alerts.lock().await;
+            open.values_mut()
+                .filter(|alert| !alert.escalated)
+                .filter(|alert| now - alert.raised_at >= self.escalation_delay(&alert.regression.severity))
+                .map(|alert| {
+                    alert.escalated = true;
+                    alert.clone()
+                })
+                .collect()
+        };
+
+        for alert in &due {
+            let contacts = self.escalation_contacts(&alert.regression.severity);
+            if contacts.is_empty() {
+                debug!("No escalation contacts for {:?} alerts", alert.regression.severity);
+                continue;
+            }
+
+            info!(
+                "Escalating unacknowledged {:?} regression in {} to {} contacts",
+                alert.regression.severity,
+                alert.regression.component,
+                contacts.len()
+            );
+            self.deliver(AlertEvent::RegressionEscalated, &alert.regression, &contacts, &[])
+                .await;
+        }
+
+        due.len()
+    }
+
+    /// Time an alert may stay unacknowledged before escalation
+    fn escalation_delay(&self, severity: &RegressionSeverity) -> chrono::Duration {
+        let rules = &self.config.escalation_rules;
+        let minutes = match severity {
+            RegressionSeverity::Minor => rules.minor_delay_minutes,
+            RegressionSeverity::Major => rules.major_delay_minutes,
+            RegressionSeverity::Critical | RegressionSeverity::Blocker => rules.critical_delay_minutes,
+        };
+        chrono::Duration::minutes(minutes as i64)
+    }
+
+    /// Contacts listed under the severity's lowercase name
+    fn escalation_contacts(&self, severity: &RegressionSeverity) -> Vec<String> {
+        let key = format!("{:?}", severity).to_lowercase();
+        self.config
+            .escalation_rules
+            .escalation_contacts
+            .get(&key)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Send one alert over every configured channel
+    async fn deliver(
+        &self,
+        event: AlertEvent,
+        regression: &DetectedRegression,
+        email_recipients: &[String],
+        attachments: &[ReportAttachment],
+    ) -> AlertDeliveryReport {
+        let mut report = AlertDeliveryReport::default();
+        let mut record = |channel: String, result: Result<(), DeliveryError>| match result {
+            Ok(()) => report.delivered.push(channel),
+            Err(e) => {
+                warn!("Alert delivery to {} failed: {}", channel, e);
+                report.failed.push((channel, e.message));
+            }
+        };
+
+        if !email_recipients.is_empty() && !self.config.email_notifications.smtp_server.is_empty() {
+            let result = self
+                .with_retry("email", || self.send_email(event, regression, email_recipients, attachments))
+                .await;
+            record("email".to_string(), result);
+        }
+
+        if let Some(url) = &self.config.slack_webhook {
+            let payload = slack_payload(event, regression);
+            let result = self
+                .with_retry("slack", || self.post_json(url, &payload, None, &HashMap::new()))
+                .await;
+            record("slack".to_string(), result);
+        }
+
+        for webhook in &self.config.webhooks {
+            let payload = serde_json::json!({
+                "event": event,
+                "sent_at": Utc::now(),
+                "regression": regression,
+            });
+            let result = self
+                .with_retry(&webhook.url, || {
+                    self.post_json(&webhook.url, &payload, webhook.secret.as_deref(), &webhook.headers)
+                })
+                .await;
+            record(format!("webhook:{}", webhook.url), result);
+        }
+
+        report
+    }
+
+    /// Run `attempt` until it succeeds, fails permanently or retries are exhausted
+    async fn with_retry<F, Fut>(&self, channel: &str, mut attempt: F) -> Result<(), DeliveryError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<(), DeliveryError>>,
+    {
+        let policy = &self.config.retry;
+        let max_attempts = policy.max_attempts.max(1);
+        let mut tries = 0;
+
+        loop {
+            tries += 1;
+            match attempt().await {
+                Ok(()) => return Ok(()),
+                Err(e) if e.retryable && tries < max_attempts => {
+                    let delay = policy.backoff(tries);
+                    debug!("Retrying {} in {:?} after attempt {} failed: {}", channel, delay, tries, e);
+                    tokio::time::sleep(delay).await;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// POST a JSON payload, signing it when a secret is configured
+    async fn post_json(
+        &self,
+        url: &str,
+        payload: &serde_json::Value,
+        secret: Option<&str>,
+        headers: &HashMap<String, String>,
+    ) -> Result<(), DeliveryError> {
+        let body = serde_json::to_vec(payload).map_err(|e| DeliveryError::permanent(e.to_string()))?;
+        let mut request = self
+            .http_client
+            .post(url)
+            .header("Content-Type", "application/json");
+
+        for (name, value) in headers {
+            request = request.header(name.as_str(), value.as_str());
+        }
+
+        if let Some(secret) = secret {
+            let timestamp = Utc::now().timestamp().to_string();
+            request = request
+                .header("X-MultiOS-Timestamp", timestamp.as_str())
+                .header("X-MultiOS-Signature", sign_payload(secret, &timestamp, &body));
+        }
+
+        let response = request
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| DeliveryError::transient(e.to_string()))?;
+
+        let status = response.status();
+        if status.is_success() {
+            Ok(())
+        } else if status.is_server_error() || status.as_u16() == 429 {
+            Err(DeliveryError::transient(format!("HTTP {}", status)))
+        } else {
+            Err(DeliveryError::permanent(format!("HTTP {}", status)))
+        }
+    }
+
+    /// Send the alert email, attaching any report files
+    async fn send_email(
+        &self,
+        event: AlertEvent,
+        regression: &DetectedRegression,
+        recipients: &[String],
+        attachments: &[ReportAttachment],
+    ) -> Result<(), DeliveryError> {
+        let email = &self.config.email_notifications;
+        let from: Mailbox = email
+            .from_address
+            .parse()
+            .map_err(|e| DeliveryError::permanent(format!("Invalid from address: {}", e)))?;
+
+        let mut builder = Message::builder().from(from).subject(alert_subject(event, regression));
+        for recipient in recipients {
+            match recipient.parse::<Mailbox>() {
+                Ok(mailbox) => builder = builder.to(mailbox),
+                Err(e) => warn!("Skipping invalid alert recipient {}: {}", recipient, e),
+            }
+        }
+
+        let mut body = MultiPart::mixed().singlepart(SinglePart::plain(alert_text(event, regression)));
+        for attachment in attachments {
+            let content_type = ContentType::parse(&attachment.content_type).unwrap_or(ContentType::TEXT_PLAIN);
+            body = body.singlepart(Attachment::new(attachment.filename.clone()).body(attachment.data.clone(), content_type));
+        }
+
+        let message = builder
+            .multipart(body)
+            .map_err(|e| DeliveryError::permanent(format!("Failed to build email: {}", e)))?;
+
+        let mut transport = AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&email.smtp_server)
+            .map_err(|e| DeliveryError::permanent(format!("Invalid SMTP relay: {}", e)))?
+            .port(email.smtp_port);
+        if !email.username.is_empty() {
+            transport = transport.credentials(Credentials::new(email.username.clone(), email.password.clone()));
+        }
+
+        transport.build().send(message).await.map(|_| ()).map_err(|e| DeliveryError {
+            retryable: e.is_transient() || e.is_timeout(),
+            message: e.to_string(),
+        })
+    }
+}
+
+/// `sha256=<hex>` HMAC over `"{timestamp}.{body}"`
+pub fn sign_payload(secret: &str, timestamp: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(timestamp.as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+fn alert_subject(event: AlertEvent, regression: &DetectedRegression) -> String {
+    let prefix = match event {
+        AlertEvent::RegressionDetected => "Regression",
+        AlertEvent::RegressionEscalated => "ESCALATED regression",
+    };
+    format!(
+        "[MultiOS] {} {:?}: {} {:+.1}% in {}",
+        prefix, regression.severity, regression.test_name, regression.regression_percentage, regression.component
+    )
+}
+
+fn alert_text(event: AlertEvent, regression: &DetectedRegression) -> String {
+    let mut text = String::new();
+    if event == AlertEvent::RegressionEscalated {
+        text.push_str("This regression has not been acknowledged within its escalation window.\n\n");
+    }
+    text.push_str(&format!(
+        "Component:   {}\nTest:        {}\nType:        {:?}\nSeverity:    {:?}\nBaseline:    {:.3}\nCurrent:     {:.3}\nChange:      {:+.1}%\nConfidence:  {:.1}%\nAlgorithm:   {}\nDetected at: {}\nRegression:  {}\n",
+        regression.component,
+        regression.test_name,
+        regression.regression_type,
+        regression.severity,
+        regression.baseline_value,
+        regression.current_value,
+        regression.regression_percentage,
+        regression.confidence_score,
+        regression.detection_algorithm,
+        regression.timestamp.to_rfc3339(),
+        regression.id
+    ));
+    text
+}
+
+/// Slack message with a severity-coloured attachment of Block Kit fields
+fn slack_payload(event: AlertEvent, regression: &DetectedRegression) -> serde_json::Value {
+    let field = |label: &str, value: String| {
+        serde_json::json!({ "type": "mrkdwn", "text": format!("*{}*\n{}", label, value) })
+    };
+
+    serde_json::json!({
+        "text": alert_subject(event, regression),
+        "attachments": [{
+            "color": severity_color(&regression.severity),
+            "blocks": [
+                {
+                    "type": "header",
+                    "text": { "type": "plain_text", "text": alert_subject(event, regression) }
+                },
+                {
+                    "type": "section",
+                    "fields": [
+                        field("Component", regression.component.clone()),
+                        field("Test", format!("`{}`", regression.test_name)),
+                        field("Severity", format!("{:?}", regression.severity)),
+                        field("Type", format!("{:?}", regression.regression_type)),
+                        field("Baseline → Current", format!("{:.3} → {:.3}", regression.baseline_value, regression.current_value)),
+                        field("Change", format!("{:+.1}% ({:.0}% confidence)", regression.regression_percentage, regression.confidence_score)),
+                    ]
+                },
+                {
+                    "type": "context",
+                    "elements": [{
+                        "type": "mrkdwn",
+                        "text": format!("Detected by `{}` at {} · id `{}`", regression.detection_algorithm, regression.timestamp.to_rfc3339(), regression.id)
+                    }]
+                }
+            ]
+        }]
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_is_exponential_and_capped() {
+        let policy = RetryPolicy {
+            max_attempts: 6,
+            initial_backoff_ms: 100,
+            max_backoff_ms: 1_000,
+            multiplier: 2.0,
+        };
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff(4), Duration::from_millis(800));
+        assert_eq!(policy.backoff(5), Duration::from_millis(1_000));
+    }
+
+    #[test]
+    fn test_sign_payload() {
+        // HMAC-SHA256("key", "1.{}")
+        let signature = sign_payload("key", "1", b"{}");
+        assert!(signature.starts_with("sha256="));
+        assert_eq!(signature.len(), "sha256=".len() + 64);
+        assert_eq!(signature, sign_payload("key", "1", b"{}"));
+        assert_ne!(signature, sign_payload("other", "1", b"{}"));
+    }
+}
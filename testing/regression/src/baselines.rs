@@ -0,0 +1,426 @@
+//! Baseline Management Module
+//!
+//! Curates performance baselines through an approval workflow: a baseline is
+//! first proposed from the measurements of a test run, becomes active only
+//! once a reviewer approves it, and every approval is kept as a numbered
+//! revision so an earlier baseline can be restored. Baselines are keyed per
+//! test environment so results from different hardware are never compared.
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+use crate::database::PerformanceBaseline;
+use crate::{PerformanceMeasurement, Uuid};
+
+/// Baseline approval configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BaselineApprovalConfig {
+    /// Whether the proposer may approve their own baseline
+    pub allow_self_approval: bool,
+    /// Measurements required before a baseline can be proposed
+    pub min_samples: usize,
+}
+
+impl Default for BaselineApprovalConfig {
+    fn default() -> Self {
+        Self {
+            allow_self_approval: false,
+            min_samples: 5,
+        }
+    }
+}
+
+/// Identifies one baseline series
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct BaselineKey {
+    pub test_name: String,
+    pub component: String,
+    pub metric_type: String,
+    /// `TestEnvironment.environment_hash` the baseline applies to
+    pub environment_hash: String,
+}
+
+impl BaselineKey {
+    /// Key of an existing baseline
+    pub fn of(baseline: &PerformanceBaseline) -> Self {
+        Self {
+            test_name: baseline.test_name.clone(),
+            component: baseline.component.clone(),
+            metric_type: baseline.metric_type.clone(),
+            environment_hash: baseline.test_environment_hash.clone(),
+        }
+    }
+
+    fn of_measurement(measurement: &PerformanceMeasurement) -> Self {
+        Self {
+            test_name: measurement.test_name.clone(),
+            component: measurement.component.clone(),
+            metric_type: measurement.metric_type.clone(),
+            environment_hash: measurement.environment.environment_hash.clone(),
+        }
+    }
+}
+
+/// Review state of a proposal
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ProposalStatus {
+    Pending,
+    Approved,
+    Rejected,
+    /// Replaced by a newer proposal for the same key before review
+    Superseded,
+}
+
+/// Candidate baseline awaiting review
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BaselineProposal {
+    pub id: Uuid,
+    pub key: BaselineKey,
+    pub baseline: PerformanceBaseline,
+    pub source_test_run_id: String,
+    pub proposed_by: String,
+    pub proposed_at: DateTime<Utc>,
+    pub status: ProposalStatus,
+    pub reviewed_by: Option<String>,
+    pub reviewed_at: Option<DateTime<Utc>>,
+    pub review_comment: Option<String>,
+}
+
+/// Approved baseline in a key's history
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BaselineRevision {
+    pub key: BaselineKey,
+    /// 1-based, increasing with every approval or rollback
+    pub revision: u32,
+    pub baseline: PerformanceBaseline,
+    pub approved_by: String,
+    pub approved_at: DateTime<Utc>,
+    /// Proposal the revision came from; `None` for rollbacks
+    pub proposal_id: Option<Uuid>,
+    /// Revision restored by a rollback
+    pub rolled_back_to: Option<u32>,
+}
+
+/// In-memory state of the approval workflow
+#[derive(Debug, Clone, Default)]
+pub struct BaselineManager {
+    config: BaselineApprovalConfig,
+    proposals: HashMap<Uuid, BaselineProposal>,
+    history: BTreeMap<BaselineKey, Vec<BaselineRevision>>,
+}
+
+impl BaselineManager {
+    /// Create new baseline manager
+    pub fn new(config: BaselineApprovalConfig) -> Self {
+        Self {
+            config,
+            proposals: HashMap::new(),
+            history: BTreeMap::new(),
+        }
+    }
+
+    /// Restore previously persisted proposals and revisions
+    pub fn restore(&mut self, proposals: Vec<BaselineProposal>, revisions: Vec<BaselineRevision>) {
+        for proposal in proposals {
+            self.proposals.insert(proposal.id, proposal);
+        }
+        for revision in revisions {
+            self.history.entry(revision.key.clone()).or_default().push(revision);
+        }
+        for revisions in self.history.values_mut() {
+            revisions.sort_by_key(|revision| revision.revision);
+        }
+    }
+
+    /// Propose one baseline per test, metric and environment from a run's measurements.
+    ///
+    /// Any pending proposal for the same key is superseded.
+    pub fn propose_from_run(
+        &mut self,
+        test_run_id: &str,
+        measurements: &[PerformanceMeasurement],
+        proposed_by: &str,
+    ) -> Result<Vec<BaselineProposal>> {
+        let mut groups: BTreeMap<BaselineKey, Vec<&PerformanceMeasurement>> = BTreeMap::new();
+        for measurement in measurements.iter().filter(|m| m.test_run_id == test_run_id) {
+            groups.entry(BaselineKey::of_measurement(measurement)).or_default().push(measurement);
+        }
+
+        if groups.is_empty() {
+            return Err(anyhow!("No measurements found for test run {}", test_run_id));
+        }
+
+        let now = Utc::now();
+        let mut proposals = Vec::new();
+
+        for (key, samples) in groups {
+            if samples.len() < self.config.min_samples {
+                info!(
+                    "Not proposing baseline for {}/{}: {} of {} required samples",
+                    key.component, key.test_name, samples.len(), self.config.min_samples
+                );
+                continue;
+            }
+
+            for pending in self.proposals.values_mut() {
+                if pending.key == key && pending.status == ProposalStatus::Pending {
+                    pending.status = ProposalStatus::Superseded;
+                }
+            }
+
+            let values: Vec<f64> = samples.iter().map(|m| m.value).collect();
+            let n = values.len() as f64;
+            let mean = values.iter().sum::<f64>() / n;
+            let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1.0).max(1.0);
+
+            let mut metadata = HashMap::new();
+            metadata.insert("source_test_run_id".to_string(), serde_json::json!(test_run_id));
+            metadata.insert("environment_name".to_string(), serde_json::json!(samples[0].environment.name));
+
+            let proposal = BaselineProposal {
+                id: Uuid::new_v4(),
+                baseline: PerformanceBaseline {
+                    test_name: key.test_name.clone(),
+                    component: key.component.clone(),
+                    metric_type: key.metric_type.clone(),
+                    baseline_value: mean,
+                    // 95% confidence interval half-width of the mean
+                    confidence_interval: Some(1.96 * (variance / n).sqrt()),
+                    sample_count: samples.len() as i32,
+                    measurement_unit: samples[0].unit.clone(),
+                    test_environment_hash: key.environment_hash.clone(),
+                    created_at: now,
+                    updated_at: now,
+                    metadata,
+                    is_active: false,
+                },
+                key,
+                source_test_run_id: test_run_id.to_string(),
+                proposed_by: proposed_by.to_string(),
+                proposed_at: now,
+                status: ProposalStatus::Pending,
+                reviewed_by: None,
+                reviewed_at: None,
+                review_comment: None,
+            };
+
+            self.proposals.insert(proposal.id, proposal.clone());
+            proposals.push(proposal);
+        }
+
+        Ok(proposals)
+    }
+
+    /// Approve a pending proposal, making it the active baseline for its key
+    pub fn approve(&mut self, proposal_id: Uuid, approver: &str, comment: Option<String>) -> Result<BaselineRevision> {
+        let allow_self_approval = self.config.allow_self_approval;
+        let proposal = self.pending_proposal_mut(proposal_id)?;
+        if !allow_self_approval && proposal.proposed_by == approver {
+            return Err(anyhow!("Baseline proposal {} cannot be approved by its proposer", proposal_id));
+        }
+
+        let now = Utc::now();
+        proposal.status = ProposalStatus::Approved;
+        proposal.reviewed_by = Some(approver.to_string());
+        proposal.reviewed_at = Some(now);
+        proposal.review_comment = comment;
+
+        let mut baseline = proposal.baseline.clone();
+        baseline.is_active = true;
+        baseline.updated_at = now;
+        baseline.metadata.insert("approved_by".to_string(), serde_json::json!(approver));
+
+        let key = proposal.key.clone();
+        Ok(self.push_revision(key, baseline, approver, Some(proposal_id), None))
+    }
+
+    /// Reject a pending proposal
+    pub fn reject(&mut self, proposal_id: Uuid, reviewer: &str, reason: &str) -> Result<BaselineProposal> {
+        let proposal = self.pending_proposal_mut(proposal_id)?;
+        proposal.status = ProposalStatus::Rejected;
+        proposal.reviewed_by = Some(reviewer.to_string());
+        proposal.reviewed_at = Some(Utc::now());
+        proposal.review_comment = Some(reason.to_string());
+        Ok(proposal.clone())
+    }
+
+    /// Restore an earlier revision, or the one before the active revision when `to_revision` is `None`.
+    ///
+    /// The restored baseline is recorded as a new revision so history is never rewritten.
+    pub fn rollback(&mut self, key: &BaselineKey, to_revision: Option<u32>, requested_by: &str) -> Result<BaselineRevision> {
+        let revisions = self
+            .history
+            .get(key)
+            .ok_or_else(|| anyhow!("No baseline history for {}/{}", key.component, key.test_name))?;
+
+        let active = revisions.last().map(|revision| revision.revision).unwrap_or(0);
+        let target_revision = match to_revision {
+            Some(revision) if revision >= active => {
+                return Err(anyhow!("Revision {} is not older than the active revision {}", revision, active));
+            }
+            Some(revision) => revision,
+            None if revisions.len() >= 2 => revisions[revisions.len() - 2].revision,
+            None => return Err(anyhow!("No earlier baseline revision to roll back to")),
+        };
+
+        let target = revisions
+            .iter()
+            .find(|revision| revision.revision == target_revision)
+            .ok_or_else(|| anyhow!("Baseline revision {} not found", target_revision))?;
+
+        let mut baseline = target.baseline.clone();
+        baseline.is_active = true;
+        baseline.updated_at = Utc::now();
+
+        info!(
+            "Rolling back baseline {}/{} to revision {} at the request of {}",
+            key.component, key.test_name, target_revision, requested_by
+        );
+        Ok(self.push_revision(key.clone(), baseline, requested_by, None, Some(target_revision)))
+    }
+
+    /// Active baseline for a key
+    pub fn active_baseline(&self, key: &BaselineKey) -> Option<&PerformanceBaseline> {
+        self.history.get(key).and_then(|revisions| revisions.last()).map(|revision| &revision.baseline)
+    }
+
+    /// Active baselines for every key
+    pub fn active_baselines(&self) -> Vec<PerformanceBaseline> {
+        self.history
+            .values()
+            .filter_map(|revisions| revisions.last())
+            .map(|revision| revision.baseline.clone())
+            .collect()
+    }
+
+    /// Revision history of a key, oldest first
+    pub fn history(&self, key: &BaselineKey) -> &[BaselineRevision] {
+        self.history.get(key).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    /// Proposals awaiting review, oldest first
+    pub fn pending_proposals(&self) -> Vec<&BaselineProposal> {
+        let mut pending: Vec<_> = self
+            .proposals
+            .values()
+            .filter(|proposal| proposal.status == ProposalStatus::Pending)
+            .collect();
+        pending.sort_by_key(|proposal| proposal.proposed_at);
+        pending
+    }
+
+    /// Look up a proposal
+    pub fn proposal(&self, proposal_id: Uuid) -> Option<&BaselineProposal> {
+        self.proposals.get(&proposal_id)
+    }
+
+    fn pending_proposal_mut(&mut self, proposal_id: Uuid) -> Result<&mut BaselineProposal> {
+        let proposal = self
+            .proposals
+            .get_mut(&proposal_id)
+            .ok_or_else(|| anyhow!("Baseline proposal {} not found", proposal_id))?;
+        if proposal.status != ProposalStatus::Pending {
+            return Err(anyhow!("Baseline proposal {} is {:?}, not pending", proposal_id, proposal.status));
+        }
+        Ok(proposal)
+    }
+
+    fn push_revision(
+        &mut self,
+        key: BaselineKey,
+        baseline: PerformanceBaseline,
+        approved_by: &str,
+        proposal_id: Option<Uuid>,
+        rolled_back_to: Option<u32>,
+    ) -> BaselineRevision {
+        let revisions = self.history.entry(key.clone()).or_default();
+        let revision = BaselineRevision {
+            key,
+            revision: revisions.last().map_or(1, |last| last.revision + 1),
+            baseline,
+            approved_by: approved_by.to_string(),
+            approved_at: Utc::now(),
+            proposal_id,
+            rolled_back_to,
+        };
+        revisions.push(revision.clone());
+        revision
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TestEnvironment;
+
+    fn measurements(run: &str, environment_hash: &str, values: &[f64]) -> Vec<PerformanceMeasurement> {
+        values
+            .iter()
+            .map(|value| PerformanceMeasurement {
+                id: Uuid::new_v4(),
+                test_name: "vm_exit_latency".to_string(),
+                component: "hypervisor".to_string(),
+                metric_type: "latency".to_string(),
+                value: *value,
+                unit: "ns".to_string(),
+                test_run_id: run.to_string(),
+                timestamp: Utc::now(),
+                environment: TestEnvironment {
+                    name: environment_hash.to_string(),
+                    hardware_config: HashMap::new(),
+                    software_config: HashMap::new(),
+                    environment_hash: environment_hash.to_string(),
+                },
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_approval_requires_second_reviewer() {
+        let mut manager = BaselineManager::new(BaselineApprovalConfig::default());
+        let samples = measurements("run-1", "x86", &[100.0, 102.0, 98.0, 101.0, 99.0]);
+        let proposal = manager.propose_from_run("run-1", &samples, "alice").unwrap().remove(0);
+
+        assert!(manager.approve(proposal.id, "alice", None).is_err());
+        let revision = manager.approve(proposal.id, "bob", None).unwrap();
+        assert_eq!(revision.revision, 1);
+        assert_eq!(revision.approved_by, "bob");
+        assert_eq!(manager.active_baseline(&proposal.key).unwrap().baseline_value, 100.0);
+        assert!(manager.approve(proposal.id, "bob", None).is_err());
+    }
+
+    #[test]
+    fn test_rollback_restores_previous_revision() {
+        let mut manager = BaselineManager::new(BaselineApprovalConfig::default());
+        for (run, value) in [("run-1", 100.0), ("run-2", 150.0)] {
+            let samples = measurements(run, "x86", &[value; 5]);
+            let proposal = manager.propose_from_run(run, &samples, "alice").unwrap().remove(0);
+            manager.approve(proposal.id, "bob", None).unwrap();
+        }
+
+        let key = BaselineKey::of(&manager.active_baselines()[0]);
+        let revision = manager.rollback(&key, None, "carol").unwrap();
+        assert_eq!(revision.revision, 3);
+        assert_eq!(revision.rolled_back_to, Some(1));
+        assert_eq!(manager.active_baseline(&key).unwrap().baseline_value, 100.0);
+        assert_eq!(manager.history(&key).len(), 3);
+    }
+
+    #[test]
+    fn test_baselines_are_per_environment() {
+        let mut manager = BaselineManager::new(BaselineApprovalConfig::default());
+        let mut samples = measurements("run-1", "x86", &[100.0; 5]);
+        samples.extend(measurements("run-1", "arm", &[200.0; 5]));
+
+        let proposals = manager.propose_from_run("run-1", &samples, "alice").unwrap();
+        assert_eq!(proposals.len(), 2);
+        for proposal in &proposals {
+            manager.approve(proposal.id, "bob", None).unwrap();
+        }
+        assert_eq!(manager.active_baselines().len(), 2);
+    }
+}
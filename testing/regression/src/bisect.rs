@@ -0,0 +1,469 @@
+//! Bisection Module
+//!
+//! Automated root cause analysis by bisecting a commit range. Candidate
+//! commits are checked out and built through a pluggable [`CommitBuilder`],
+//! the affected benchmark is rerun, and each commit is classified by whether
+//! its result sits closer to the regression's baseline or current value.
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokio::process::Command;
+
+use crate::integration::multios::{MultiOsBenchHarness, MultiOsBenchmark};
+use crate::{CauseType, CodeChange, DetectedRegression, RootCauseAnalysis};
+
+/// Checks out, builds and benchmarks individual commits for the bisector
+#[async_trait]
+pub trait CommitBuilder: Send + Sync {
+    /// Check out and build `commit` so it can be benchmarked
+    async fn prepare(&self, commit: &str) -> Result<()>;
+
+    /// Rerun the benchmark affected by `regression` and return its samples
+    async fn measure(&self, commit: &str, regression: &DetectedRegression) -> Result<Vec<f64>>;
+
+    /// Return the tree to its state before bisection started
+    async fn restore(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Bisection limits
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BisectConfig {
+    /// Upper bound on commits built, including the two endpoints
+    pub max_steps: usize,
+    /// Stop once this many commits in a row fail to build or measure
+    pub max_consecutive_skips: usize,
+}
+
+impl Default for BisectConfig {
+    fn default() -> Self {
+        Self {
+            max_steps: 16,
+            max_consecutive_skips: 4,
+        }
+    }
+}
+
+/// Classification of a single tested commit
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum CommitVerdict {
+    Good,
+    Bad,
+    Skipped,
+}
+
+/// Result of testing one commit
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BisectStep {
+    pub commit_hash: String,
+    pub verdict: CommitVerdict,
+    pub mean_value: Option<f64>,
+}
+
+/// Automated regression bisector
+pub struct Bisector {
+    config: BisectConfig,
+    builder: Arc<dyn CommitBuilder>,
+}
+
+impl Bisector {
+    /// Create new bisector
+    pub fn new(config: BisectConfig, builder: Arc<dyn CommitBuilder>) -> Self {
+        Self { config, builder }
+    }
+
+    /// Bisect `changes` for the commit that introduced `regression`.
+    ///
+    /// The oldest change is expected to be good and the newest bad; both are
+    /// verified before narrowing. Returns `None` when the endpoints do not
+    /// reproduce the regression.
+    pub async fn bisect(
+        &self,
+        regression: &DetectedRegression,
+        changes: &[CodeChange],
+    ) -> Result<Option<RootCauseAnalysis>> {
+        let mut commits: Vec<&CodeChange> = changes.iter().collect();
+        commits.sort_by_key(|change| change.timestamp);
+        if commits.len() < 2 {
+            debug!("Need at least two commits to bisect, got {}", commits.len());
+            return Ok(None);
+        }
+
+        info!(
+            "Bisecting {} commits for regression in {}/{}",
+            commits.len(),
+            regression.component,
+            regression.test_name
+        );
+
+        let result = self.run(regression, &commits).await;
+        if let Err(e) = self.builder.restore().await {
+            warn!("Failed to restore tree after bisection: {}", e);
+        }
+        result
+    }
+
+    async fn run(
+        &self,
+        regression: &DetectedRegression,
+        commits: &[&CodeChange],
+    ) -> Result<Option<RootCauseAnalysis>> {
+        let mut steps = Vec::new();
+        let mut verdicts: HashMap<usize, CommitVerdict> = HashMap::new();
+
+        let last = commits.len() - 1;
+        for index in [0, last] {
+            let step = self.test_commit(regression, commits[index]).await;
+            verdicts.insert(index, step.verdict);
+            steps.push(step);
+        }
+
+        if verdicts[&0] != CommitVerdict::Good || verdicts[&last] != CommitVerdict::Bad {
+            warn!(
+                "Bisection endpoints did not reproduce the regression (oldest {:?}, newest {:?})",
+                verdicts[&0], verdicts[&last]
+            );
+            return Ok(None);
+        }
+
+        // Invariant: `good` is known good, `bad` is known bad, `good < bad`
+        let (mut good, mut bad) = (0, last);
+        let mut consecutive_skips = 0;
+
+        while bad - good > 1 && steps.len() < self.config.max_steps {
+            let candidate = match pick_midpoint(good, bad, &verdicts) {
+                Some(candidate) => candidate,
+                None => break,
+            };
+
+            let step = self.test_commit(regression, commits[candidate]).await;
+            verdicts.insert(candidate, step.verdict);
+            match step.verdict {
+                CommitVerdict::Good => {
+                    good = candidate;
+                    consecutive_skips = 0;
+                }
+                CommitVerdict::Bad => {
+                    bad = candidate;
+                    consecutive_skips = 0;
+                }
+                CommitVerdict::Skipped => consecutive_skips += 1,
+            }
+            steps.push(step);
+
+            if consecutive_skips >= self.config.max_consecutive_skips {
+                warn!("Giving up after {} consecutive skipped commits", consecutive_skips);
+                break;
+            }
+        }
+
+        // Every untested or skipped commit after `good` up to `bad` may be the culprit
+        let suspects: Vec<&CodeChange> = commits[good + 1..=bad].to_vec();
+        Ok(Some(build_analysis(regression, commits[bad], &suspects, &steps)))
+    }
+
+    /// Build, measure and classify one commit
+    async fn test_commit(&self, regression: &DetectedRegression, change: &CodeChange) -> BisectStep {
+        let commit = change.commit_hash.as_str();
+
+        let samples = match self.builder.prepare(commit).await {
+            Ok(()) => self.builder.measure(commit, regression).await,
+            Err(e) => Err(e),
+        };
+
+        let samples = match samples {
+            Ok(samples) if !samples.is_empty() => samples,
+            Ok(_) => {
+                warn!("No samples measured at {}, skipping", commit);
+                return BisectStep { commit_hash: commit.to_string(), verdict: CommitVerdict::Skipped, mean_value: None };
+            }
+            Err(e) => {
+                warn!("Could not test {}, skipping: {}", commit, e);
+                return BisectStep { commit_hash: commit.to_string(), verdict: CommitVerdict::Skipped, mean_value: None };
+            }
+        };
+
+        let mean = samples.iter().sum::<f64>() / samples.len() as f64;
+        let verdict = classify(mean, regression);
+        debug!("Commit {} measured {:.3} -> {:?}", commit, mean, verdict);
+
+        BisectStep { commit_hash: commit.to_string(), verdict, mean_value: Some(mean) }
+    }
+}
+
+/// A commit is bad when its result sits closer to the regressed value than to the baseline
+fn classify(value: f64, regression: &DetectedRegression) -> CommitVerdict {
+    if (value - regression.current_value).abs() < (value - regression.baseline_value).abs() {
+        CommitVerdict::Bad
+    } else {
+        CommitVerdict::Good
+    }
+}
+
+/// Pick the untested commit strictly between `good` and `bad` closest to the middle
+fn pick_midpoint(good: usize, bad: usize, verdicts: &HashMap<usize, CommitVerdict>) -> Option<usize> {
+    let middle = (good + bad) as f64 / 2.0;
+    (good + 1..bad)
+        .filter(|index| !verdicts.contains_key(index))
+        .min_by(|a, b| {
+            let da = (*a as f64 - middle).abs();
+            let db = (*b as f64 - middle).abs();
+            da.partial_cmp(&db).unwrap_or(std::cmp::Ordering::Equal)
+        })
+}
+
+/// Turn the bisection outcome into a root cause analysis record
+fn build_analysis(
+    regression: &DetectedRegression,
+    first_bad: &CodeChange,
+    suspects: &[&CodeChange],
+    steps: &[BisectStep],
+) -> RootCauseAnalysis {
+    let exact = suspects.len() == 1;
+
+    let root_cause = if exact {
+        format!(
+            "Commit {} by {}: {}",
+            first_bad.commit_hash, first_bad.author, first_bad.commit_message
+        )
+    } else {
+        format!(
+            "One of {} commits up to {} (bisection could not narrow further)",
+            suspects.len(),
+            first_bad.commit_hash
+        )
+    };
+
+    let mut contributing_factors: Vec<String> = suspects
+        .iter()
+        .flat_map(|change| change.files_changed.iter().cloned())
+        .collect();
+    contributing_factors.sort();
+    contributing_factors.dedup();
+
+    let mut recommendations = vec![format!(
+        "Review {} for changes affecting {}",
+        first_bad.commit_hash, regression.component
+    )];
+    if !exact {
+        recommendations.push(format!(
+            "Manually test the remaining suspects: {}",
+            suspects.iter().map(|c| c.commit_hash.as_str()).collect::<Vec<_>>().join(", ")
+        ));
+    }
+
+    let mut metadata = HashMap::new();
+    metadata.insert("first_bad_commit".to_string(), serde_json::json!(first_bad.commit_hash));
+    metadata.insert(
+        "suspect_commits".to_string(),
+        serde_json::json!(suspects.iter().map(|c| &c.commit_hash).collect::<Vec<_>>()),
+    );
+    metadata.insert("steps".to_string(), serde_json::to_value(steps).unwrap_or_default());
+
+    RootCauseAnalysis {
+        regression_id: regression.id,
+        cause_type: CauseType::CodeChange,
+        root_cause,
+        contributing_factors,
+        probability_score: 1.0 / suspects.len() as f64,
+        analysis_method: "automated_bisection".to_string(),
+        recommendations,
+        metadata,
+    }
+}
+
+/// Builder that checks commits out in a local git tree and benchmarks them with
+/// the MultiOS benchmark harness
+pub struct GitCommitBuilder {
+    repo_root: PathBuf,
+    /// Optional build step run after checkout; benchmark commands may build on their own
+    build_command: Option<Vec<String>>,
+    harness: MultiOsBenchHarness,
+    /// Ref checked out before the first `prepare`
+    original_ref: Mutex<Option<String>>,
+}
+
+impl GitCommitBuilder {
+    /// Create new git commit builder
+    pub fn new(repo_root: PathBuf, build_command: Option<Vec<String>>, harness: MultiOsBenchHarness) -> Self {
+        Self {
+            repo_root,
+            build_command,
+            harness,
+            original_ref: Mutex::new(None),
+        }
+    }
+
+    async fn git(&self, args: &[&str]) -> Result<String> {
+        let output = Command::new("git")
+            .args(args)
+            .current_dir(&self.repo_root)
+            .output()
+            .await
+            .context("Failed to run git")?;
+
+        if !output.status.success() {
+            return Err(anyhow::anyhow!(
+                "git {} failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+}
+
+#[async_trait]
+impl CommitBuilder for GitCommitBuilder {
+    async fn prepare(&self, commit: &str) -> Result<()> {
+        let needs_original = self.original_ref.lock().map(|r| r.is_none()).unwrap_or(false);
+        if needs_original {
+            // Prefer the branch name so restoring does not leave a detached HEAD
+            let branch = self.git(&["rev-parse", "--abbrev-ref", "HEAD"]).await?;
+            let original = if branch == "HEAD" {
+                self.git(&["rev-parse", "HEAD"]).await?
+            } else {
+                branch
+            };
+            if let Ok(mut slot) = self.original_ref.lock() {
+                *slot = Some(original);
+            }
+        }
+
+        self.git(&["checkout", "--quiet", "--detach", commit]).await?;
+
+        if let Some((program, args)) = self.build_command.as_ref().and_then(|c| c.split_first()) {
+            let status = Command::new(program)
+                .args(args)
+                .current_dir(&self.repo_root)
+                .status()
+                .await
+                .context("Failed to launch build")?;
+            if !status.success() {
+                return Err(anyhow::anyhow!("Build of {} failed with {}", commit, status));
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn measure(&self, commit: &str, regression: &DetectedRegression) -> Result<Vec<f64>> {
+        let benchmark = MultiOsBenchmark::from_name(&regression.test_name).ok_or_else(|| {
+            anyhow::anyhow!("No MultiOS benchmark named {}", regression.test_name)
+        })?;
+
+        let measurements = self.harness.run_selected(&[benchmark]).await?;
+        debug!("Measured {} samples of {} at {}", measurements.len(), benchmark.name(), commit);
+        Ok(measurements.into_iter().map(|m| m.value).collect())
+    }
+
+    async fn restore(&self) -> Result<()> {
+        let original = self.original_ref.lock().ok().and_then(|mut slot| slot.take());
+        if let Some(original) = original {
+            self.git(&["checkout", "--quiet", &original]).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, Utc};
+
+    /// Builder where every commit from `first_bad` onwards measures 150, earlier ones 100
+    struct StepBuilder {
+        commits: Vec<String>,
+        first_bad: usize,
+        broken: Vec<String>,
+    }
+
+    #[async_trait]
+    impl CommitBuilder for StepBuilder {
+        async fn prepare(&self, commit: &str) -> Result<()> {
+            if self.broken.iter().any(|c| c == commit) {
+                return Err(anyhow::anyhow!("build failed"));
+            }
+            Ok(())
+        }
+
+        async fn measure(&self, commit: &str, _regression: &DetectedRegression) -> Result<Vec<f64>> {
+            let index = self.commits.iter().position(|c| c == commit).unwrap();
+            Ok(vec![if index >= self.first_bad { 150.0 } else { 100.0 }])
+        }
+    }
+
+    fn changes(count: usize) -> Vec<CodeChange> {
+        let start = Utc::now() - Duration::hours(count as i64);
+        (0..count)
+            .map(|i| CodeChange {
+                commit_hash: format!("c{}", i),
+                commit_message: format!("change {}", i),
+                author: "dev@multios.org".to_string(),
+                files_changed: vec![format!("src/file{}.rs", i)],
+                timestamp: start + Duration::hours(i as i64),
+                change_type: "feature".to_string(),
+                changed_functions: HashMap::new(),
+            })
+            .collect()
+    }
+
+    fn regression() -> DetectedRegression {
+        DetectedRegression {
+            id: crate::Uuid::new_v4(),
+            regression_type: crate::RegressionType::PerformanceLatency,
+            severity: crate::RegressionSeverity::Major,
+            component: "hypervisor".to_string(),
+            test_name: "vm_exit_latency".to_string(),
+            current_value: 150.0,
+            baseline_value: 100.0,
+            regression_percentage: 50.0,
+            detection_algorithm: "statistical_analysis".to_string(),
+            confidence_score: 95.0,
+            test_run_id: "run".to_string(),
+            timestamp: Utc::now(),
+            metadata: HashMap::new(),
+        }
+    }
+
+    fn builder(count: usize, first_bad: usize, broken: &[&str]) -> Arc<dyn CommitBuilder> {
+        Arc::new(StepBuilder {
+            commits: (0..count).map(|i| format!("c{}", i)).collect(),
+            first_bad,
+            broken: broken.iter().map(|s| s.to_string()).collect(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_bisect_finds_first_bad_commit() {
+        let bisector = Bisector::new(BisectConfig::default(), builder(12, 7, &[]));
+        let rca = bisector.bisect(&regression(), &changes(12)).await.unwrap().unwrap();
+
+        assert_eq!(rca.metadata["first_bad_commit"], serde_json::json!("c7"));
+        assert_eq!(rca.probability_score, 1.0);
+        assert!(matches!(rca.cause_type, CauseType::CodeChange));
+    }
+
+    #[tokio::test]
+    async fn test_bisect_reports_suspects_around_skipped_commit() {
+        let bisector = Bisector::new(BisectConfig::default(), builder(8, 4, &["c3"]));
+        let rca = bisector.bisect(&regression(), &changes(8)).await.unwrap().unwrap();
+
+        assert_eq!(rca.metadata["suspect_commits"], serde_json::json!(["c3", "c4"]));
+        assert_eq!(rca.probability_score, 0.5);
+    }
+
+    #[tokio::test]
+    async fn test_bisect_requires_reproducing_endpoints() {
+        let bisector = Bisector::new(BisectConfig::default(), builder(6, 0, &[]));
+        assert!(bisector.bisect(&regression(), &changes(6)).await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,396 @@
+//! Change-Point Detection Module
+//!
+//! Statistical change-point algorithms used by the performance detector as
+//! alternatives to fixed percentage thresholds: tabular CUSUM, PELT with a
+//! Gaussian mean-shift cost, and split-point Mann-Whitney U tests with
+//! multiple-comparison correction.
+
+use serde::{Deserialize, Serialize};
+
+/// Regression detection algorithm used by the performance detector
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+pub enum DetectionAlgorithm {
+    /// Percentage deviation of the recent mean from the stored baseline
+    #[default]
+    Threshold,
+    /// Two-sided tabular CUSUM against the leading reference segment
+    Cusum,
+    /// Pruned Exact Linear Time segmentation on mean shifts
+    Pelt,
+    /// Mann-Whitney U at every split point, corrected across all tests
+    MannWhitney,
+}
+
+impl DetectionAlgorithm {
+    /// Name recorded as the regression's `detection_algorithm`
+    pub fn name(&self) -> &'static str {
+        match self {
+            DetectionAlgorithm::Threshold => "statistical_analysis",
+            DetectionAlgorithm::Cusum => "cusum",
+            DetectionAlgorithm::Pelt => "pelt",
+            DetectionAlgorithm::MannWhitney => "mann_whitney_u",
+        }
+    }
+}
+
+/// Multiple-comparison correction applied to Mann-Whitney p-values
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum CorrectionMethod {
+    Bonferroni,
+    Holm,
+    BenjaminiHochberg,
+}
+
+/// Tuning parameters for the change-point algorithms
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChangePointConfig {
+    /// Minimum samples on either side of a change point
+    pub min_segment_length: usize,
+    /// CUSUM allowance, in reference standard deviations
+    pub cusum_drift: f64,
+    /// CUSUM decision interval, in reference standard deviations
+    pub cusum_threshold: f64,
+    /// PELT penalty per change point; `None` uses 2·ln(n)
+    pub pelt_penalty: Option<f64>,
+    /// Family-wise (or false discovery) significance level
+    pub significance_level: f64,
+    pub correction: CorrectionMethod,
+}
+
+impl Default for ChangePointConfig {
+    fn default() -> Self {
+        Self {
+            min_segment_length: 5,
+            cusum_drift: 0.5,
+            cusum_threshold: 5.0,
+            pelt_penalty: None,
+            significance_level: 0.05,
+            correction: CorrectionMethod::BenjaminiHochberg,
+        }
+    }
+}
+
+/// Outcome of a two-sample Mann-Whitney U test
+#[derive(Debug, Clone, Copy)]
+pub struct MannWhitneyResult {
+    pub u: f64,
+    pub z: f64,
+    pub p_value: f64,
+}
+
+/// Locate the first sustained mean shift with a two-sided tabular CUSUM.
+///
+/// The first `reference_len` samples define the in-control mean and spread.
+/// Returns the index of the first sample after the change, estimated as the
+/// point where the alarming cumulative sum last left zero.
+pub fn cusum(values: &[f64], reference_len: usize, drift: f64, threshold: f64) -> Option<usize> {
+    if reference_len < 2 || values.len() <= reference_len {
+        return None;
+    }
+
+    let reference = &values[..reference_len];
+    let mean = mean(reference);
+    let sd = std_dev(reference, mean);
+    let scale = if sd > 0.0 { sd } else { fallback_scale(mean) };
+
+    let (mut upper, mut lower) = (0.0_f64, 0.0_f64);
+    let (mut upper_start, mut lower_start) = (reference_len, reference_len);
+
+    for (i, &value) in values.iter().enumerate().skip(reference_len) {
+        let z = (value - mean) / scale;
+
+        if upper == 0.0 {
+            upper_start = i;
+        }
+        if lower == 0.0 {
+            lower_start = i;
+        }
+
+        upper = (upper + z - drift).max(0.0);
+        lower = (lower - z - drift).max(0.0);
+
+        if upper > threshold {
+            return Some(upper_start);
+        }
+        if lower > threshold {
+            return Some(lower_start);
+        }
+    }
+
+    None
+}
+
+/// Segment `values` into constant-mean pieces using PELT.
+///
+/// Returns the start index of every segment after the first, in ascending
+/// order. The cost is the Gaussian negative log-likelihood with a variance
+/// estimated robustly from first differences.
+pub fn pelt(values: &[f64], min_segment_length: usize, penalty: Option<f64>) -> Vec<usize> {
+    let n = values.len();
+    let min_len = min_segment_length.max(1);
+    if n < 2 * min_len {
+        return Vec::new();
+    }
+
+    let variance = robust_variance(values);
+    let beta = penalty.unwrap_or(2.0 * (n as f64).ln());
+
+    // Prefix sums give O(1) segment costs
+    let mut sum = vec![0.0; n + 1];
+    let mut sum_sq = vec![0.0; n + 1];
+    for (i, &value) in values.iter().enumerate() {
+        sum[i + 1] = sum[i] + value;
+        sum_sq[i + 1] = sum_sq[i] + value * value;
+    }
+    let cost = |start: usize, end: usize| -> f64 {
+        let len = (end - start) as f64;
+        let s = sum[end] - sum[start];
+        let sq = sum_sq[end] - sum_sq[start];
+        (sq - s * s / len) / variance
+    };
+
+    let mut best = vec![f64::INFINITY; n + 1];
+    let mut previous = vec![0usize; n + 1];
+    best[0] = -beta;
+    let mut candidates: Vec<usize> = vec![0];
+
+    for end in min_len..=n {
+        let mut best_cost = f64::INFINITY;
+        let mut best_start = 0;
+        for &start in &candidates {
+            if end - start < min_len {
+                continue;
+            }
+            let total = best[start] + cost(start, end) + beta;
+            if total < best_cost {
+                best_cost = total;
+                best_start = start;
+            }
+        }
+        best[end] = best_cost;
+        previous[end] = best_start;
+
+        // Prune starts that can never be optimal again; keep ones still too close to test
+        candidates.retain(|&start| end - start < min_len || best[start] + cost(start, end) <= best_cost);
+        if end + min_len <= n {
+            candidates.push(end);
+        }
+    }
+
+    let mut change_points = Vec::new();
+    let mut end = n;
+    while end > 0 {
+        let start = previous[end];
+        if start > 0 {
+            change_points.push(start);
+        }
+        end = start;
+    }
+    change_points.reverse();
+    change_points
+}
+
+/// Two-sided Mann-Whitney U test using the normal approximation with tie
+/// and continuity correction.
+pub fn mann_whitney_u(before: &[f64], after: &[f64]) -> Option<MannWhitneyResult> {
+    let (n1, n2) = (before.len(), after.len());
+    if n1 == 0 || n2 == 0 {
+        return None;
+    }
+
+    let mut pooled: Vec<(f64, bool)> = before
+        .iter()
+        .map(|&v| (v, true))
+        .chain(after.iter().map(|&v| (v, false)))
+        .collect();
+    pooled.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+
+    // Assign average ranks to ties and accumulate the tie correction term
+    let n = pooled.len();
+    let mut rank_sum_before = 0.0;
+    let mut tie_term = 0.0;
+    let mut i = 0;
+    while i < n {
+        let mut j = i;
+        while j + 1 < n && pooled[j + 1].0 == pooled[i].0 {
+            j += 1;
+        }
+        let average_rank = (i + j) as f64 / 2.0 + 1.0;
+        let tied = (j - i + 1) as f64;
+        tie_term += tied.powi(3) - tied;
+        rank_sum_before += pooled[i..=j].iter().filter(|(_, first)| *first).count() as f64 * average_rank;
+        i = j + 1;
+    }
+
+    let (n1f, n2f, nf) = (n1 as f64, n2 as f64, n as f64);
+    let u = rank_sum_before - n1f * (n1f + 1.0) / 2.0;
+    let mean_u = n1f * n2f / 2.0;
+    let variance = n1f * n2f / 12.0 * ((nf + 1.0) - tie_term / (nf * (nf - 1.0)));
+    if variance <= 0.0 {
+        return Some(MannWhitneyResult { u, z: 0.0, p_value: 1.0 });
+    }
+
+    let diff = u - mean_u;
+    let corrected = if diff > 0.0 { diff - 0.5 } else if diff < 0.0 { diff + 0.5 } else { 0.0 };
+    let z = corrected / variance.sqrt();
+    let p_value = (2.0 * (1.0 - normal_cdf(z.abs()))).clamp(0.0, 1.0);
+
+    Some(MannWhitneyResult { u, z, p_value })
+}
+
+/// Adjust raw p-values for multiple comparisons, preserving input order
+pub fn adjust_p_values(p_values: &[f64], method: CorrectionMethod) -> Vec<f64> {
+    let m = p_values.len();
+    if m == 0 {
+        return Vec::new();
+    }
+
+    let mut order: Vec<usize> = (0..m).collect();
+    order.sort_by(|&a, &b| p_values[a].partial_cmp(&p_values[b]).unwrap_or(std::cmp::Ordering::Equal));
+    let mut adjusted = vec![1.0; m];
+
+    match method {
+        CorrectionMethod::Bonferroni => {
+            for (i, &p) in p_values.iter().enumerate() {
+                adjusted[i] = (p * m as f64).min(1.0);
+            }
+        }
+        CorrectionMethod::Holm => {
+            let mut running_max = 0.0_f64;
+            for (rank, &index) in order.iter().enumerate() {
+                let value = (p_values[index] * (m - rank) as f64).min(1.0);
+                running_max = running_max.max(value);
+                adjusted[index] = running_max;
+            }
+        }
+        CorrectionMethod::BenjaminiHochberg => {
+            let mut running_min = 1.0_f64;
+            for (rank, &index) in order.iter().enumerate().rev() {
+                let value = (p_values[index] * m as f64 / (rank + 1) as f64).min(1.0);
+                running_min = running_min.min(value);
+                adjusted[index] = running_min;
+            }
+        }
+    }
+
+    adjusted
+}
+
+/// Arithmetic mean of a slice
+pub fn mean(values: &[f64]) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+/// Sample standard deviation around a precomputed mean
+fn std_dev(values: &[f64], mean: f64) -> f64 {
+    if values.len() < 2 {
+        return 0.0;
+    }
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (values.len() - 1) as f64;
+    variance.sqrt()
+}
+
+/// Scale used when a reference segment has no spread at all
+fn fallback_scale(mean: f64) -> f64 {
+    if mean != 0.0 {
+        mean.abs() * 1e-3
+    } else {
+        1.0
+    }
+}
+
+/// Noise variance estimated from the MAD of first differences, so level
+/// shifts do not inflate it
+fn robust_variance(values: &[f64]) -> f64 {
+    let mut diffs: Vec<f64> = values.windows(2).map(|w| (w[1] - w[0]).abs()).collect();
+    diffs.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+
+    let median = if diffs.is_empty() { 0.0 } else { diffs[diffs.len() / 2] };
+    // MAD -> sigma for a normal difference of two samples
+    let sigma = median / (0.6745 * std::f64::consts::SQRT_2);
+    if sigma > 0.0 {
+        sigma * sigma
+    } else {
+        let m = mean(values);
+        let sd = std_dev(values, m);
+        if sd > 0.0 {
+            sd * sd
+        } else {
+            fallback_scale(m).powi(2)
+        }
+    }
+}
+
+/// Standard normal CDF via the Abramowitz-Stegun erf approximation
+fn normal_cdf(x: f64) -> f64 {
+    let t = 1.0 / (1.0 + 0.3275911 * x.abs() / std::f64::consts::SQRT_2);
+    let poly = t * (0.254829592 + t * (-0.284496736 + t * (1.421413741 + t * (-1.453152027 + t * 1.061405429))));
+    let erf = 1.0 - poly * (-(x * x) / 2.0).exp();
+    if x >= 0.0 {
+        0.5 * (1.0 + erf)
+    } else {
+        0.5 * (1.0 - erf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step_series() -> Vec<f64> {
+        let mut values: Vec<f64> = (0..20).map(|i| 100.0 + (i % 3) as f64).collect();
+        values.extend((0..20).map(|i| 130.0 + (i % 3) as f64));
+        values
+    }
+
+    #[test]
+    fn test_cusum_locates_step() {
+        let values = step_series();
+        let change = cusum(&values, 10, 0.5, 5.0).unwrap();
+        assert_eq!(change, 20);
+    }
+
+    #[test]
+    fn test_cusum_ignores_stable_series() {
+        let values: Vec<f64> = (0..40).map(|i| 100.0 + (i % 3) as f64).collect();
+        assert_eq!(cusum(&values, 10, 0.5, 5.0), None);
+    }
+
+    #[test]
+    fn test_pelt_locates_step() {
+        let values = step_series();
+        assert_eq!(pelt(&values, 5, None), vec![20]);
+    }
+
+    #[test]
+    fn test_mann_whitney_separates_shifted_samples() {
+        let values = step_series();
+        let result = mann_whitney_u(&values[..20], &values[20..]).unwrap();
+        assert!(result.p_value < 0.001);
+
+        let same = mann_whitney_u(&values[..10], &values[10..20]).unwrap();
+        assert!(same.p_value > 0.05);
+    }
+
+    #[test]
+    fn test_adjust_p_values() {
+        let p = [0.01, 0.04, 0.03, 0.20];
+        let bonferroni = adjust_p_values(&p, CorrectionMethod::Bonferroni);
+        assert!((bonferroni[0] - 0.04).abs() < 1e-12);
+        assert_eq!(bonferroni[3], 0.8);
+
+        let bh = adjust_p_values(&p, CorrectionMethod::BenjaminiHochberg);
+        assert!((bh[0] - 0.04).abs() < 1e-12);
+        assert!((bh[1] - 0.04 * 4.0 / 3.0).abs() < 1e-12);
+        assert!((bh[2] - 0.04 * 4.0 / 3.0).abs() < 1e-12);
+
+        let holm = adjust_p_values(&p, CorrectionMethod::Holm);
+        assert!((holm[0] - 0.04).abs() < 1e-12);
+        assert!((holm[2] - 0.09).abs() < 1e-12);
+        assert!((holm[1] - 0.09).abs() < 1e-12);
+    }
+}
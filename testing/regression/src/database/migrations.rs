@@ -0,0 +1,221 @@
+//! Versioned schema migrations
+//!
+//! Each migration is a list of statements shared by every SQL dialect; the
+//! only dialect-specific piece is the auto-increment key, written as `{id}`.
+//! Applied versions are recorded in `schema_migrations`, so running the
+//! migrator against an up-to-date database is a no-op.
+//!
+//! Timestamps are stored as fixed-width RFC 3339 UTC text and structured
+//! fields as JSON text, which keeps rows portable between backends and lets
+//! exports round-trip without conversion.
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use log::info;
+use sqlx::AnyPool;
+
+use super::sql::{encode_timestamp, SqlDialect};
+
+/// A single schema migration
+#[derive(Debug, Clone, Copy)]
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub statements: &'static [&'static str],
+}
+
+/// All migrations, in application order
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial_schema",
+        statements: &[
+            "CREATE TABLE IF NOT EXISTS test_environments (
+                environment_hash TEXT PRIMARY KEY,
+                env_name TEXT NOT NULL,
+                hardware_config TEXT NOT NULL,
+                software_config TEXT NOT NULL,
+                created_at TEXT NOT NULL
+            )",
+            "CREATE TABLE IF NOT EXISTS performance_baselines (
+                id {id},
+                test_name TEXT NOT NULL,
+                component TEXT NOT NULL,
+                metric_type TEXT NOT NULL,
+                baseline_value DOUBLE PRECISION NOT NULL,
+                confidence_interval DOUBLE PRECISION,
+                sample_count BIGINT NOT NULL,
+                measurement_unit TEXT NOT NULL,
+                test_environment_hash TEXT NOT NULL,
+                created_at TEXT NOT NULL,
+                updated_at TEXT NOT NULL,
+                metadata TEXT NOT NULL,
+                is_active BOOLEAN NOT NULL,
+                UNIQUE (test_name, component, metric_type, test_environment_hash)
+            )",
+            "CREATE TABLE IF NOT EXISTS performance_measurements (
+                id TEXT PRIMARY KEY,
+                test_name TEXT NOT NULL,
+                component TEXT NOT NULL,
+                metric_type TEXT NOT NULL,
+                measured_value DOUBLE PRECISION NOT NULL,
+                measurement_unit TEXT NOT NULL,
+                test_environment_hash TEXT NOT NULL,
+                test_run_id TEXT NOT NULL,
+                timestamp TEXT NOT NULL
+            )",
+            "CREATE INDEX IF NOT EXISTS idx_measurements_metric
+                ON performance_measurements (component, metric_type, timestamp)",
+            "CREATE INDEX IF NOT EXISTS idx_measurements_run
+                ON performance_measurements (test_run_id)",
+            "CREATE TABLE IF NOT EXISTS functional_test_results (
+                id TEXT PRIMARY KEY,
+                test_name TEXT NOT NULL,
+                component TEXT NOT NULL,
+                test_type TEXT NOT NULL,
+                execution_status TEXT NOT NULL,
+                execution_time_ms BIGINT NOT NULL,
+                environment_hash TEXT NOT NULL,
+                timestamp TEXT NOT NULL,
+                metrics TEXT NOT NULL,
+                metadata TEXT NOT NULL
+            )",
+            "CREATE INDEX IF NOT EXISTS idx_test_results_component
+                ON functional_test_results (component, timestamp)",
+            "CREATE TABLE IF NOT EXISTS detected_regressions (
+                id TEXT PRIMARY KEY,
+                regression_type TEXT NOT NULL,
+                severity TEXT NOT NULL,
+                component TEXT NOT NULL,
+                test_name TEXT NOT NULL,
+                current_value DOUBLE PRECISION NOT NULL,
+                baseline_value DOUBLE PRECISION NOT NULL,
+                regression_percentage DOUBLE PRECISION NOT NULL,
+                detection_algorithm TEXT NOT NULL,
+                confidence_score DOUBLE PRECISION NOT NULL,
+                test_run_id TEXT NOT NULL,
+                detected_at TEXT NOT NULL,
+                resolved BOOLEAN NOT NULL DEFAULT FALSE,
+                metadata TEXT NOT NULL
+            )",
+            "CREATE TABLE IF NOT EXISTS regression_root_causes (
+                id {id},
+                regression_id TEXT NOT NULL,
+                cause_type TEXT NOT NULL,
+                probability_score DOUBLE PRECISION NOT NULL,
+                analysis_method TEXT NOT NULL,
+                analysis TEXT NOT NULL,
+                created_at TEXT NOT NULL,
+                UNIQUE (regression_id, analysis_method)
+            )",
+            "CREATE TABLE IF NOT EXISTS test_trends (
+                id {id},
+                metric_type TEXT NOT NULL,
+                component TEXT NOT NULL,
+                period_start TEXT NOT NULL,
+                period_end TEXT NOT NULL,
+                trend_direction TEXT NOT NULL,
+                trend TEXT NOT NULL,
+                UNIQUE (component, metric_type, period_start)
+            )",
+            "CREATE TABLE IF NOT EXISTS alert_history (
+                id {id},
+                regression_id TEXT NOT NULL,
+                channel TEXT NOT NULL,
+                triggered_at TEXT NOT NULL,
+                details TEXT NOT NULL
+            )",
+        ],
+    },
+    Migration {
+        version: 2,
+        name: "baseline_approval",
+        statements: &[
+            "CREATE TABLE IF NOT EXISTS baseline_proposals (
+                id TEXT PRIMARY KEY,
+                test_name TEXT NOT NULL,
+                component TEXT NOT NULL,
+                metric_type TEXT NOT NULL,
+                environment_hash TEXT NOT NULL,
+                status TEXT NOT NULL,
+                proposal TEXT NOT NULL,
+                created_at TEXT NOT NULL,
+                updated_at TEXT NOT NULL
+            )",
+            "CREATE TABLE IF NOT EXISTS baseline_revisions (
+                id {id},
+                test_name TEXT NOT NULL,
+                component TEXT NOT NULL,
+                metric_type TEXT NOT NULL,
+                environment_hash TEXT NOT NULL,
+                revision BIGINT NOT NULL,
+                approved_by TEXT NOT NULL,
+                revision_data TEXT NOT NULL,
+                UNIQUE (test_name, component, metric_type, environment_hash, revision)
+            )",
+        ],
+    },
+];
+
+/// Apply all migrations newer than the database's current version.
+///
+/// Returns the versions that were applied.
+pub async fn run(pool: &AnyPool, dialect: SqlDialect) -> Result<Vec<i64>> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS schema_migrations (
+            version BIGINT PRIMARY KEY,
+            name TEXT NOT NULL,
+            applied_at TEXT NOT NULL
+        )",
+    )
+    .execute(pool)
+    .await
+    .context("Failed to create schema_migrations table")?;
+
+    let current: Option<i64> = sqlx::query_scalar("SELECT MAX(version) FROM schema_migrations")
+        .fetch_one(pool)
+        .await
+        .context("Failed to read schema version")?;
+    let current = current.unwrap_or(0);
+
+    let mut applied = Vec::new();
+    for migration in MIGRATIONS.iter().filter(|migration| migration.version > current) {
+        info!("Applying migration {} ({})", migration.version, migration.name);
+
+        let mut tx = pool.begin().await?;
+        for statement in migration.statements {
+            let statement = statement.replace("{id}", dialect.serial_primary_key());
+            sqlx::query(&statement)
+                .execute(&mut *tx)
+                .await
+                .with_context(|| format!("Migration {} ({}) failed", migration.version, migration.name))?;
+        }
+        sqlx::query("INSERT INTO schema_migrations (version, name, applied_at) VALUES ($1, $2, $3)")
+            .bind(migration.version)
+            .bind(migration.name)
+            .bind(encode_timestamp(&Utc::now()))
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        applied.push(migration.version);
+    }
+
+    Ok(applied)
+}
+
+/// Latest schema version known to this build
+pub fn latest_version() -> i64 {
+    MIGRATIONS.last().map_or(0, |migration| migration.version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migrations_are_strictly_ordered() {
+        assert!(MIGRATIONS.windows(2).all(|pair| pair[0].version < pair[1].version));
+        assert_eq!(latest_version(), MIGRATIONS.len() as i64);
+    }
+}
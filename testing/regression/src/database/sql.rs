@@ -0,0 +1,853 @@
+//! SQL storage backend
+//!
+//! A single implementation serves both supported databases through sqlx's
+//! `Any` driver: embedded SQLite (`sqlite://regression.db`) for local runs and
+//! PostgreSQL (`postgres://...`) for shared CI servers. Queries stick to the
+//! SQL both dialects accept; see `migrations` for the column conventions.
+
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, SecondsFormat, Utc};
+use log::{debug, info};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sqlx::any::{AnyPoolOptions, AnyRow};
+use sqlx::{AnyPool, Row};
+use std::collections::HashMap;
+
+use super::{migrations, Dataset, PerformanceBaseline, RegressionRecord, RegressionStats, StorageBackend, DATASET_FORMAT_VERSION};
+use crate::baselines::{BaselineProposal, BaselineRevision, ProposalStatus};
+use crate::{
+    DetectedRegression, PerformanceMeasurement, RootCauseAnalysis, TestEnvironment, TestResult, TrendData, Uuid,
+};
+
+/// Measurement columns joined with their environment
+const MEASUREMENT_COLUMNS: &str = "pm.id, pm.test_name, pm.component, pm.metric_type, pm.measured_value, \
+     pm.measurement_unit, pm.test_environment_hash, pm.test_run_id, pm.timestamp, \
+     te.env_name, te.hardware_config, te.software_config";
+
+/// Test result columns joined with their environment
+const TEST_RESULT_COLUMNS: &str = "ftr.id, ftr.test_name, ftr.component, ftr.test_type, ftr.execution_status, \
+     ftr.execution_time_ms, ftr.environment_hash, ftr.timestamp, ftr.metrics, ftr.metadata, \
+     te.env_name, te.hardware_config, te.software_config";
+
+const REGRESSION_COLUMNS: &str = "id, regression_type, severity, component, test_name, current_value, \
+     baseline_value, regression_percentage, detection_algorithm, confidence_score, test_run_id, \
+     detected_at, resolved, metadata";
+
+const BASELINE_COLUMNS: &str = "test_name, component, metric_type, baseline_value, confidence_interval, \
+     sample_count, measurement_unit, test_environment_hash, created_at, updated_at, metadata, is_active";
+
+/// SQL dialects supported by [`SqlBackend`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SqlDialect {
+    Sqlite,
+    Postgres,
+}
+
+impl SqlDialect {
+    /// Determine the dialect from a database URL
+    pub fn from_url(database_url: &str) -> Result<Self> {
+        if database_url.starts_with("sqlite:") {
+            Ok(Self::Sqlite)
+        } else if database_url.starts_with("postgres://") || database_url.starts_with("postgresql://") {
+            Ok(Self::Postgres)
+        } else {
+            bail!("Unsupported database URL '{}': expected sqlite:// or postgres://", database_url)
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Sqlite => "sqlite",
+            Self::Postgres => "postgres",
+        }
+    }
+
+    /// Column definition for an auto-incrementing integer key
+    pub(crate) fn serial_primary_key(self) -> &'static str {
+        match self {
+            Self::Sqlite => "INTEGER PRIMARY KEY AUTOINCREMENT",
+            Self::Postgres => "BIGSERIAL PRIMARY KEY",
+        }
+    }
+}
+
+/// Storage backend for SQLite and PostgreSQL databases
+pub struct SqlBackend {
+    pool: AnyPool,
+    dialect: SqlDialect,
+}
+
+impl SqlBackend {
+    /// Connect to the database named by `database_url`; SQLite files are created if missing
+    pub async fn connect(database_url: &str) -> Result<Self> {
+        let dialect = SqlDialect::from_url(database_url)?;
+        sqlx::any::install_default_drivers();
+
+        let (url, max_connections) = match dialect {
+            // SQLite has a single writer, and one connection keeps `:memory:` databases shared
+            SqlDialect::Sqlite => (sqlite_url(database_url), 1),
+            SqlDialect::Postgres => (database_url.to_string(), 5),
+        };
+
+        info!("Connecting to {} regression database", dialect.name());
+        let pool = AnyPoolOptions::new()
+            .max_connections(max_connections)
+            .connect(&url)
+            .await
+            .context("Failed to connect to database")?;
+
+        Ok(Self { pool, dialect })
+    }
+
+    pub fn dialect(&self) -> SqlDialect {
+        self.dialect
+    }
+
+    /// Record the environment a measurement or result ran in, once per hash
+    async fn store_environment(&self, environment: &TestEnvironment) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO test_environments
+             (environment_hash, env_name, hardware_config, software_config, created_at)
+             VALUES ($1, $2, $3, $4, $5)
+             ON CONFLICT (environment_hash) DO NOTHING",
+        )
+        .bind(&environment.environment_hash)
+        .bind(&environment.name)
+        .bind(encode_json(&environment.hardware_config)?)
+        .bind(encode_json(&environment.software_config)?)
+        .bind(encode_timestamp(&Utc::now()))
+        .execute(&self.pool)
+        .await
+        .context("Failed to store test environment")?;
+
+        Ok(())
+    }
+
+    async fn upsert_baseline_proposal(&self, proposal: &BaselineProposal) -> Result<()> {
+        let now = encode_timestamp(&Utc::now());
+        sqlx::query(
+            "INSERT INTO baseline_proposals
+             (id, test_name, component, metric_type, environment_hash, status, proposal, created_at, updated_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+             ON CONFLICT (id) DO UPDATE SET
+                status = EXCLUDED.status,
+                proposal = EXCLUDED.proposal,
+                updated_at = EXCLUDED.updated_at",
+        )
+        .bind(proposal.id.to_string())
+        .bind(&proposal.key.test_name)
+        .bind(&proposal.key.component)
+        .bind(&proposal.key.metric_type)
+        .bind(&proposal.key.environment_hash)
+        .bind(format!("{:?}", proposal.status))
+        .bind(encode_json(proposal)?)
+        .bind(encode_timestamp(&proposal.proposed_at))
+        .bind(now)
+        .execute(&self.pool)
+        .await
+        .context("Failed to store baseline proposal")?;
+
+        Ok(())
+    }
+
+    async fn fetch_proposals(&self, pending_only: bool) -> Result<Vec<BaselineProposal>> {
+        let query = if pending_only {
+            "SELECT proposal FROM baseline_proposals WHERE status = 'Pending' ORDER BY created_at ASC"
+        } else {
+            "SELECT proposal FROM baseline_proposals ORDER BY created_at ASC"
+        };
+
+        sqlx::query(query)
+            .fetch_all(&self.pool)
+            .await
+            .context("Failed to fetch baseline proposals")?
+            .iter()
+            .map(|row| decode_json(row, "proposal"))
+            .collect()
+    }
+}
+
+#[async_trait]
+impl StorageBackend for SqlBackend {
+    fn name(&self) -> &'static str {
+        self.dialect.name()
+    }
+
+    async fn migrate(&self) -> Result<Vec<i64>> {
+        migrations::run(&self.pool, self.dialect).await
+    }
+
+    // ==========================================
+    // PERFORMANCE BASELINES OPERATIONS
+    // ==========================================
+
+    async fn store_performance_baseline(&self, baseline: &PerformanceBaseline) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO performance_baselines
+             (test_name, component, metric_type, baseline_value, confidence_interval, sample_count,
+              measurement_unit, test_environment_hash, created_at, updated_at, metadata, is_active)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
+             ON CONFLICT (test_name, component, metric_type, test_environment_hash) DO UPDATE SET
+                baseline_value = EXCLUDED.baseline_value,
+                confidence_interval = EXCLUDED.confidence_interval,
+                sample_count = EXCLUDED.sample_count,
+                measurement_unit = EXCLUDED.measurement_unit,
+                updated_at = EXCLUDED.updated_at,
+                metadata = EXCLUDED.metadata,
+                is_active = EXCLUDED.is_active",
+        )
+        .bind(&baseline.test_name)
+        .bind(&baseline.component)
+        .bind(&baseline.metric_type)
+        .bind(baseline.baseline_value)
+        .bind(baseline.confidence_interval)
+        .bind(baseline.sample_count as i64)
+        .bind(&baseline.measurement_unit)
+        .bind(&baseline.test_environment_hash)
+        .bind(encode_timestamp(&baseline.created_at))
+        .bind(encode_timestamp(&baseline.updated_at))
+        .bind(encode_json(&baseline.metadata)?)
+        .bind(baseline.is_active)
+        .execute(&self.pool)
+        .await
+        .context("Failed to store performance baseline")?;
+
+        Ok(())
+    }
+
+    async fn get_performance_baselines(&self, component: &str, metric_type: &str) -> Result<Vec<PerformanceBaseline>> {
+        sqlx::query(&format!(
+            "SELECT {} FROM performance_baselines
+             WHERE component = $1 AND metric_type = $2 AND is_active = TRUE
+             ORDER BY created_at DESC",
+            BASELINE_COLUMNS
+        ))
+        .bind(component)
+        .bind(metric_type)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to fetch performance baselines")?
+        .iter()
+        .map(baseline_from_row)
+        .collect()
+    }
+
+    // ==========================================
+    // BASELINE APPROVAL OPERATIONS
+    // ==========================================
+
+    async fn store_baseline_proposal(&self, proposal: &BaselineProposal) -> Result<()> {
+        self.upsert_baseline_proposal(proposal).await?;
+
+        if proposal.status == ProposalStatus::Pending {
+            let older = sqlx::query(
+                "SELECT proposal FROM baseline_proposals
+                 WHERE test_name = $1 AND component = $2 AND metric_type = $3
+                   AND environment_hash = $4 AND status = 'Pending' AND id <> $5",
+            )
+            .bind(&proposal.key.test_name)
+            .bind(&proposal.key.component)
+            .bind(&proposal.key.metric_type)
+            .bind(&proposal.key.environment_hash)
+            .bind(proposal.id.to_string())
+            .fetch_all(&self.pool)
+            .await
+            .context("Failed to fetch older baseline proposals")?;
+
+            for row in &older {
+                let mut superseded: BaselineProposal = decode_json(row, "proposal")?;
+                superseded.status = ProposalStatus::Superseded;
+                self.upsert_baseline_proposal(&superseded).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn get_pending_baseline_proposals(&self) -> Result<Vec<BaselineProposal>> {
+        self.fetch_proposals(true).await
+    }
+
+    async fn store_baseline_revision(&self, revision: &BaselineRevision) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO baseline_revisions
+             (test_name, component, metric_type, environment_hash, revision, approved_by, revision_data)
+             VALUES ($1, $2, $3, $4, $5, $6, $7)
+             ON CONFLICT (test_name, component, metric_type, environment_hash, revision) DO NOTHING",
+        )
+        .bind(&revision.key.test_name)
+        .bind(&revision.key.component)
+        .bind(&revision.key.metric_type)
+        .bind(&revision.key.environment_hash)
+        .bind(revision.revision as i64)
+        .bind(&revision.approved_by)
+        .bind(encode_json(revision)?)
+        .execute(&self.pool)
+        .await
+        .context("Failed to store baseline revision")?;
+
+        Ok(())
+    }
+
+    async fn get_baseline_revisions(&self) -> Result<Vec<BaselineRevision>> {
+        sqlx::query(
+            "SELECT revision_data FROM baseline_revisions
+             ORDER BY test_name, component, metric_type, environment_hash, revision ASC",
+        )
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to fetch baseline revisions")?
+        .iter()
+        .map(|row| decode_json(row, "revision_data"))
+        .collect()
+    }
+
+    // ==========================================
+    // PERFORMANCE MEASUREMENTS OPERATIONS
+    // ==========================================
+
+    async fn store_performance_measurement(&self, measurement: &PerformanceMeasurement) -> Result<()> {
+        self.store_environment(&measurement.environment).await?;
+
+        sqlx::query(
+            "INSERT INTO performance_measurements
+             (id, test_name, component, metric_type, measured_value, measurement_unit,
+              test_environment_hash, test_run_id, timestamp)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+             ON CONFLICT (id) DO NOTHING",
+        )
+        .bind(measurement.id.to_string())
+        .bind(&measurement.test_name)
+        .bind(&measurement.component)
+        .bind(&measurement.metric_type)
+        .bind(measurement.value)
+        .bind(&measurement.unit)
+        .bind(&measurement.environment.environment_hash)
+        .bind(&measurement.test_run_id)
+        .bind(encode_timestamp(&measurement.timestamp))
+        .execute(&self.pool)
+        .await
+        .context("Failed to store performance measurement")?;
+
+        Ok(())
+    }
+
+    async fn get_performance_measurements(
+        &self,
+        component: &str,
+        metric_type: &str,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+    ) -> Result<Vec<PerformanceMeasurement>> {
+        sqlx::query(&format!(
+            "SELECT {} FROM performance_measurements pm
+             JOIN test_environments te ON pm.test_environment_hash = te.environment_hash
+             WHERE pm.component = $1 AND pm.metric_type = $2
+               AND pm.timestamp BETWEEN $3 AND $4
+             ORDER BY pm.timestamp ASC",
+            MEASUREMENT_COLUMNS
+        ))
+        .bind(component)
+        .bind(metric_type)
+        .bind(encode_timestamp(&start_time))
+        .bind(encode_timestamp(&end_time))
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to fetch performance measurements")?
+        .iter()
+        .map(measurement_from_row)
+        .collect()
+    }
+
+    async fn get_measurements_for_run(&self, test_run_id: &str) -> Result<Vec<PerformanceMeasurement>> {
+        sqlx::query(&format!(
+            "SELECT {} FROM performance_measurements pm
+             JOIN test_environments te ON pm.test_environment_hash = te.environment_hash
+             WHERE pm.test_run_id = $1
+             ORDER BY pm.timestamp ASC",
+            MEASUREMENT_COLUMNS
+        ))
+        .bind(test_run_id)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to fetch measurements for test run")?
+        .iter()
+        .map(measurement_from_row)
+        .collect()
+    }
+
+    // ==========================================
+    // FUNCTIONAL TEST RESULTS OPERATIONS
+    // ==========================================
+
+    async fn store_test_result(&self, test_result: &TestResult) -> Result<()> {
+        self.store_environment(&test_result.environment).await?;
+
+        sqlx::query(
+            "INSERT INTO functional_test_results
+             (id, test_name, component, test_type, execution_status, execution_time_ms,
+              environment_hash, timestamp, metrics, metadata)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+             ON CONFLICT (id) DO NOTHING",
+        )
+        .bind(test_result.id.to_string())
+        .bind(&test_result.test_name)
+        .bind(&test_result.component)
+        .bind(format!("{:?}", test_result.test_type))
+        .bind(format!("{:?}", test_result.status))
+        .bind(test_result.execution_time_ms as i64)
+        .bind(&test_result.environment.environment_hash)
+        .bind(encode_timestamp(&test_result.timestamp))
+        .bind(encode_json(&test_result.metrics)?)
+        .bind(encode_json(&test_result.metadata)?)
+        .execute(&self.pool)
+        .await
+        .context("Failed to store test result")?;
+
+        Ok(())
+    }
+
+    async fn get_test_results(
+        &self,
+        component: &str,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+    ) -> Result<Vec<TestResult>> {
+        sqlx::query(&format!(
+            "SELECT {} FROM functional_test_results ftr
+             JOIN test_environments te ON ftr.environment_hash = te.environment_hash
+             WHERE ftr.component = $1
+               AND ftr.timestamp BETWEEN $2 AND $3
+             ORDER BY ftr.timestamp ASC",
+            TEST_RESULT_COLUMNS
+        ))
+        .bind(component)
+        .bind(encode_timestamp(&start_time))
+        .bind(encode_timestamp(&end_time))
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to fetch test results")?
+        .iter()
+        .map(test_result_from_row)
+        .collect()
+    }
+
+    // ==========================================
+    // REGRESSION DETECTION OPERATIONS
+    // ==========================================
+
+    async fn store_regression(&self, regression: &DetectedRegression) -> Result<()> {
+        sqlx::query(&format!(
+            "INSERT INTO detected_regressions ({})
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, FALSE, $13)
+             ON CONFLICT (id) DO NOTHING",
+            REGRESSION_COLUMNS
+        ))
+        .bind(regression.id.to_string())
+        .bind(format!("{:?}", regression.regression_type))
+        .bind(format!("{:?}", regression.severity))
+        .bind(&regression.component)
+        .bind(&regression.test_name)
+        .bind(regression.current_value)
+        .bind(regression.baseline_value)
+        .bind(regression.regression_percentage)
+        .bind(&regression.detection_algorithm)
+        .bind(regression.confidence_score)
+        .bind(&regression.test_run_id)
+        .bind(encode_timestamp(&regression.timestamp))
+        .bind(encode_json(&regression.metadata)?)
+        .execute(&self.pool)
+        .await
+        .context("Failed to store regression")?;
+
+        Ok(())
+    }
+
+    async fn resolve_regression(&self, regression_id: Uuid) -> Result<bool> {
+        let result = sqlx::query("UPDATE detected_regressions SET resolved = TRUE WHERE id = $1 AND resolved = FALSE")
+            .bind(regression_id.to_string())
+            .execute(&self.pool)
+            .await
+            .context("Failed to resolve regression")?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_unresolved_regressions(&self) -> Result<Vec<DetectedRegression>> {
+        sqlx::query(&format!(
+            "SELECT {} FROM detected_regressions WHERE resolved = FALSE ORDER BY detected_at DESC",
+            REGRESSION_COLUMNS
+        ))
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to fetch unresolved regressions")?
+        .iter()
+        .map(|row| regression_from_row(row).map(|record| record.regression))
+        .collect()
+    }
+
+    async fn get_regression_stats(&self, since: DateTime<Utc>) -> Result<RegressionStats> {
+        let rows = sqlx::query(
+            "SELECT severity, regression_type, resolved, COUNT(*) AS regression_count
+             FROM detected_regressions
+             WHERE detected_at >= $1
+             GROUP BY severity, regression_type, resolved",
+        )
+        .bind(encode_timestamp(&since))
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to get regression statistics")?;
+
+        let mut stats = RegressionStats {
+            total_regressions: 0,
+            resolved_regressions: 0,
+            unresolved_regressions: 0,
+            regressions_by_severity: HashMap::new(),
+            regressions_by_type: HashMap::new(),
+            avg_detection_time_hours: 0.0, // Would need additional query
+            avg_resolution_time_hours: 0.0, // Would need additional query
+        };
+
+        for row in &rows {
+            let count = row.try_get::<i64, _>("regression_count")? as u32;
+            stats.total_regressions += count;
+            if row.try_get::<bool, _>("resolved")? {
+                stats.resolved_regressions += count;
+            } else {
+                stats.unresolved_regressions += count;
+            }
+            *stats.regressions_by_severity.entry(row.try_get("severity")?).or_insert(0) += count;
+            *stats.regressions_by_type.entry(row.try_get("regression_type")?).or_insert(0) += count;
+        }
+
+        Ok(stats)
+    }
+
+    // ==========================================
+    // ROOT CAUSE ANALYSIS OPERATIONS
+    // ==========================================
+
+    async fn store_root_cause_analysis(&self, rca: &RootCauseAnalysis) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO regression_root_causes
+             (regression_id, cause_type, probability_score, analysis_method, analysis, created_at)
+             VALUES ($1, $2, $3, $4, $5, $6)
+             ON CONFLICT (regression_id, analysis_method) DO UPDATE SET
+                cause_type = EXCLUDED.cause_type,
+                probability_score = EXCLUDED.probability_score,
+                analysis = EXCLUDED.analysis",
+        )
+        .bind(rca.regression_id.to_string())
+        .bind(format!("{:?}", rca.cause_type))
+        .bind(rca.probability_score)
+        .bind(&rca.analysis_method)
+        .bind(encode_json(rca)?)
+        .bind(encode_timestamp(&Utc::now()))
+        .execute(&self.pool)
+        .await
+        .context("Failed to store root cause analysis")?;
+
+        Ok(())
+    }
+
+    // ==========================================
+    // TREND ANALYSIS OPERATIONS
+    // ==========================================
+
+    async fn store_trend_data(&self, trend_data: &TrendData) -> Result<()> {
+        let period_start = trend_data.time_series.first().map_or_else(Utc::now, |(t, _)| *t);
+        let period_end = trend_data.time_series.last().map_or(period_start, |(t, _)| *t);
+
+        sqlx::query(
+            "INSERT INTO test_trends
+             (metric_type, component, period_start, period_end, trend_direction, trend)
+             VALUES ($1, $2, $3, $4, $5, $6)
+             ON CONFLICT (component, metric_type, period_start) DO UPDATE SET
+                period_end = EXCLUDED.period_end,
+                trend_direction = EXCLUDED.trend_direction,
+                trend = EXCLUDED.trend",
+        )
+        .bind(&trend_data.metric_name)
+        .bind(&trend_data.component)
+        .bind(encode_timestamp(&period_start))
+        .bind(encode_timestamp(&period_end))
+        .bind(format!("{:?}", trend_data.trend_direction))
+        .bind(encode_json(trend_data)?)
+        .execute(&self.pool)
+        .await
+        .context("Failed to store trend data")?;
+
+        Ok(())
+    }
+
+    async fn get_component_trends(
+        &self,
+        component: &str,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+    ) -> Result<Vec<TrendData>> {
+        sqlx::query(
+            "SELECT trend FROM test_trends
+             WHERE component = $1 AND period_start BETWEEN $2 AND $3
+             ORDER BY period_start ASC",
+        )
+        .bind(component)
+        .bind(encode_timestamp(&start_time))
+        .bind(encode_timestamp(&end_time))
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to fetch component trends")?
+        .iter()
+        .map(|row| decode_json(row, "trend"))
+        .collect()
+    }
+
+    // ==========================================
+    // RETENTION AND EXPORT
+    // ==========================================
+
+    async fn prune(&self, cutoff: DateTime<Utc>) -> Result<u64> {
+        let cutoff = encode_timestamp(&cutoff);
+        let statements = [
+            "DELETE FROM performance_measurements WHERE timestamp < $1",
+            "DELETE FROM functional_test_results WHERE timestamp < $1",
+            "DELETE FROM alert_history WHERE triggered_at < $1",
+            "DELETE FROM regression_root_causes WHERE regression_id IN
+                (SELECT id FROM detected_regressions WHERE resolved = TRUE AND detected_at < $1)",
+            "DELETE FROM detected_regressions WHERE resolved = TRUE AND detected_at < $1",
+        ];
+
+        let mut tx = self.pool.begin().await?;
+        let mut deleted = 0;
+        for statement in statements {
+            deleted += sqlx::query(statement)
+                .bind(cutoff.as_str())
+                .execute(&mut *tx)
+                .await
+                .context("Failed to prune old records")?
+                .rows_affected();
+        }
+        tx.commit().await?;
+
+        debug!("Pruned {} records older than {}", deleted, cutoff);
+        Ok(deleted)
+    }
+
+    async fn export_dataset(&self) -> Result<Dataset> {
+        let baselines = sqlx::query(&format!(
+            "SELECT {} FROM performance_baselines ORDER BY created_at ASC",
+            BASELINE_COLUMNS
+        ))
+        .fetch_all(&self.pool)
+        .await?
+        .iter()
+        .map(baseline_from_row)
+        .collect::<Result<_>>()?;
+
+        let measurements = sqlx::query(&format!(
+            "SELECT {} FROM performance_measurements pm
+             JOIN test_environments te ON pm.test_environment_hash = te.environment_hash
+             ORDER BY pm.timestamp ASC",
+            MEASUREMENT_COLUMNS
+        ))
+        .fetch_all(&self.pool)
+        .await?
+        .iter()
+        .map(measurement_from_row)
+        .collect::<Result<_>>()?;
+
+        let test_results = sqlx::query(&format!(
+            "SELECT {} FROM functional_test_results ftr
+             JOIN test_environments te ON ftr.environment_hash = te.environment_hash
+             ORDER BY ftr.timestamp ASC",
+            TEST_RESULT_COLUMNS
+        ))
+        .fetch_all(&self.pool)
+        .await?
+        .iter()
+        .map(test_result_from_row)
+        .collect::<Result<_>>()?;
+
+        let regressions = sqlx::query(&format!(
+            "SELECT {} FROM detected_regressions ORDER BY detected_at ASC",
+            REGRESSION_COLUMNS
+        ))
+        .fetch_all(&self.pool)
+        .await?
+        .iter()
+        .map(regression_from_row)
+        .collect::<Result<_>>()?;
+
+        let root_cause_analyses = sqlx::query("SELECT analysis FROM regression_root_causes ORDER BY id ASC")
+            .fetch_all(&self.pool)
+            .await?
+            .iter()
+            .map(|row| decode_json(row, "analysis"))
+            .collect::<Result<_>>()?;
+
+        let trends = sqlx::query("SELECT trend FROM test_trends ORDER BY period_start ASC")
+            .fetch_all(&self.pool)
+            .await?
+            .iter()
+            .map(|row| decode_json(row, "trend"))
+            .collect::<Result<_>>()?;
+
+        Ok(Dataset {
+            format_version: DATASET_FORMAT_VERSION,
+            exported_at: Utc::now(),
+            source_backend: self.name().to_string(),
+            schema_version: migrations::latest_version(),
+            baselines,
+            baseline_proposals: self.fetch_proposals(false).await?,
+            baseline_revisions: self.get_baseline_revisions().await?,
+            measurements,
+            test_results,
+            regressions,
+            root_cause_analyses,
+            trends,
+        })
+    }
+}
+
+/// Add `mode=rwc` so SQLite creates the database file on first use
+fn sqlite_url(database_url: &str) -> String {
+    if database_url.contains(":memory:") || database_url.contains("mode=") {
+        database_url.to_string()
+    } else if database_url.contains('?') {
+        format!("{}&mode=rwc", database_url)
+    } else {
+        format!("{}?mode=rwc", database_url)
+    }
+}
+
+/// Fixed-width RFC 3339 UTC text, so timestamps compare correctly as strings
+pub(crate) fn encode_timestamp(timestamp: &DateTime<Utc>) -> String {
+    timestamp.to_rfc3339_opts(SecondsFormat::Micros, true)
+}
+
+fn decode_timestamp(row: &AnyRow, column: &str) -> Result<DateTime<Utc>> {
+    let raw: String = row.try_get(column)?;
+    DateTime::parse_from_rfc3339(&raw)
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+        .with_context(|| format!("Invalid timestamp in column {}: {}", column, raw))
+}
+
+fn encode_json<T: Serialize + ?Sized>(value: &T) -> Result<String> {
+    serde_json::to_string(value).context("Failed to encode JSON column")
+}
+
+fn decode_json<T: DeserializeOwned>(row: &AnyRow, column: &str) -> Result<T> {
+    let raw: String = row.try_get(column)?;
+    serde_json::from_str(&raw).with_context(|| format!("Invalid JSON in column {}", column))
+}
+
+/// Decode a unit enum variant stored by its `Debug` name
+fn decode_enum<T: DeserializeOwned>(row: &AnyRow, column: &str) -> Result<T> {
+    let raw: String = row.try_get(column)?;
+    serde_json::from_value(serde_json::Value::String(raw.clone()))
+        .with_context(|| format!("Unknown value in column {}: {}", column, raw))
+}
+
+fn decode_uuid(row: &AnyRow, column: &str) -> Result<Uuid> {
+    let raw: String = row.try_get(column)?;
+    Uuid::parse_str(&raw).with_context(|| format!("Invalid UUID in column {}: {}", column, raw))
+}
+
+fn environment_from_row(row: &AnyRow, hash_column: &str) -> Result<TestEnvironment> {
+    Ok(TestEnvironment {
+        name: row.try_get("env_name")?,
+        hardware_config: decode_json(row, "hardware_config")?,
+        software_config: decode_json(row, "software_config")?,
+        environment_hash: row.try_get(hash_column)?,
+    })
+}
+
+fn baseline_from_row(row: &AnyRow) -> Result<PerformanceBaseline> {
+    Ok(PerformanceBaseline {
+        test_name: row.try_get("test_name")?,
+        component: row.try_get("component")?,
+        metric_type: row.try_get("metric_type")?,
+        baseline_value: row.try_get("baseline_value")?,
+        confidence_interval: row.try_get("confidence_interval")?,
+        sample_count: row.try_get::<i64, _>("sample_count")? as i32,
+        measurement_unit: row.try_get("measurement_unit")?,
+        test_environment_hash: row.try_get("test_environment_hash")?,
+        created_at: decode_timestamp(row, "created_at")?,
+        updated_at: decode_timestamp(row, "updated_at")?,
+        metadata: decode_json(row, "metadata")?,
+        is_active: row.try_get("is_active")?,
+    })
+}
+
+fn measurement_from_row(row: &AnyRow) -> Result<PerformanceMeasurement> {
+    Ok(PerformanceMeasurement {
+        id: decode_uuid(row, "id")?,
+        test_name: row.try_get("test_name")?,
+        component: row.try_get("component")?,
+        metric_type: row.try_get("metric_type")?,
+        value: row.try_get("measured_value")?,
+        unit: row.try_get("measurement_unit")?,
+        test_run_id: row.try_get("test_run_id")?,
+        timestamp: decode_timestamp(row, "timestamp")?,
+        environment: environment_from_row(row, "test_environment_hash")?,
+    })
+}
+
+fn test_result_from_row(row: &AnyRow) -> Result<TestResult> {
+    Ok(TestResult {
+        id: decode_uuid(row, "id")?,
+        test_name: row.try_get("test_name")?,
+        component: row.try_get("component")?,
+        test_type: decode_enum(row, "test_type")?,
+        status: decode_enum(row, "execution_status")?,
+        execution_time_ms: row.try_get::<i64, _>("execution_time_ms")? as u64,
+        timestamp: decode_timestamp(row, "timestamp")?,
+        environment: environment_from_row(row, "environment_hash")?,
+        metrics: decode_json(row, "metrics")?,
+        metadata: decode_json(row, "metadata")?,
+    })
+}
+
+fn regression_from_row(row: &AnyRow) -> Result<RegressionRecord> {
+    Ok(RegressionRecord {
+        regression: DetectedRegression {
+            id: decode_uuid(row, "id")?,
+            regression_type: decode_enum(row, "regression_type")?,
+            severity: decode_enum(row, "severity")?,
+            component: row.try_get("component")?,
+            test_name: row.try_get("test_name")?,
+            current_value: row.try_get("current_value")?,
+            baseline_value: row.try_get("baseline_value")?,
+            regression_percentage: row.try_get("regression_percentage")?,
+            detection_algorithm: row.try_get("detection_algorithm")?,
+            confidence_score: row.try_get("confidence_score")?,
+            test_run_id: row.try_get("test_run_id")?,
+            timestamp: decode_timestamp(row, "detected_at")?,
+            metadata: decode_json(row, "metadata")?,
+        },
+        resolved: row.try_get("resolved")?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dialect_from_url() {
+        assert_eq!(SqlDialect::from_url("sqlite://regression.db").unwrap(), SqlDialect::Sqlite);
+        assert_eq!(SqlDialect::from_url("postgresql://ci@db/regressions").unwrap(), SqlDialect::Postgres);
+        assert!(SqlDialect::from_url("mysql://localhost/regressions").is_err());
+        assert_eq!(sqlite_url("sqlite://regression.db"), "sqlite://regression.db?mode=rwc");
+    }
+
+    #[test]
+    fn test_timestamps_sort_as_text() {
+        let earlier = DateTime::parse_from_rfc3339("2024-03-01T09:00:00Z").unwrap().with_timezone(&Utc);
+        let later = earlier + chrono::Duration::microseconds(1500);
+        assert!(encode_timestamp(&earlier) < encode_timestamp(&later));
+        assert_eq!(encode_timestamp(&earlier).len(), encode_timestamp(&later).len());
+    }
+}
@@ -10,6 +10,7 @@ use serde::{Deserialize, Serialize};
 use simple_statistics::standard_deviation;
 use std::collections::HashMap;
 
+use crate::changepoint::{self, DetectionAlgorithm};
 use crate::{
     DetectedRegression, PerformanceBaseline, PerformanceMeasurement, RegressionSeverity, 
     RegressionType, TestResult, TestStatus, TestType, Uuid,
@@ -35,6 +36,12 @@ impl PerformanceDetector {
     ) -> Result<Vec<DetectedRegression>> {
         info!("Analyzing {} measurements for performance regressions", measurements.len());
         
+        if self.uses_change_point() {
+            let regressions = self.detect_change_point_regressions(measurements);
+            info!("Detected {} performance regressions via {}", regressions.len(), self.thresholds.detection_algorithm.name());
+            return Ok(regressions);
+        }
+        
         let mut regressions = Vec::new();
         
         // Group measurements by component and metric type
@@ -55,6 +62,142 @@ impl PerformanceDetector {
         Ok(regressions)
     }
 
+    /// Whether a change-point algorithm is selected instead of baseline thresholds
+    pub fn uses_change_point(&self) -> bool {
+        self.thresholds.detection_algorithm != DetectionAlgorithm::Threshold
+    }
+
+    /// Detect regressions as mean shifts within each component/metric series.
+    ///
+    /// Every candidate change is scored with a Mann-Whitney U test between the
+    /// segments either side of it, and all p-values from one call are corrected
+    /// together before any regression is reported.
+    fn detect_change_point_regressions(&self, measurements: &[PerformanceMeasurement]) -> Vec<DetectedRegression> {
+        let config = &self.thresholds.change_point;
+        let min_len = config.min_segment_length.max(2);
+        let mut series_by_key = Vec::new();
+        let mut candidates: Vec<(usize, usize, f64)> = Vec::new(); // (series, change index, raw p)
+        
+        for (key, mut group) in self.group_measurements_by_component_metric(measurements) {
+            group.sort_by_key(|m| m.timestamp);
+            let values: Vec<f64> = group.iter().map(|m| m.value).collect();
+            if values.len() < 2 * min_len {
+                debug!("Insufficient samples for change-point analysis of {}/{}: {}", key.0, key.1, values.len());
+                continue;
+            }
+            
+            let change_indices = match self.thresholds.detection_algorithm {
+                DetectionAlgorithm::Cusum => changepoint::cusum(&values, min_len, config.cusum_drift, config.cusum_threshold)
+                    .into_iter()
+                    .collect(),
+                DetectionAlgorithm::Pelt => changepoint::pelt(&values, min_len, config.pelt_penalty)
+                    .last()
+                    .copied()
+                    .into_iter()
+                    .collect(),
+                DetectionAlgorithm::MannWhitney => (min_len..=values.len() - min_len).collect(),
+                DetectionAlgorithm::Threshold => Vec::new(),
+            };
+            
+            let series = series_by_key.len();
+            for index in change_indices {
+                if let Some(test) = changepoint::mann_whitney_u(&values[..index], &values[index..]) {
+                    candidates.push((series, index, test.p_value));
+                }
+            }
+            series_by_key.push((key, group));
+        }
+        
+        let raw: Vec<f64> = candidates.iter().map(|c| c.2).collect();
+        let adjusted = changepoint::adjust_p_values(&raw, config.correction);
+        
+        // Keep the most significant change per series
+        let mut best: HashMap<usize, (usize, f64, f64)> = HashMap::new();
+        for ((series, index, p_value), adjusted_p) in candidates.into_iter().zip(adjusted) {
+            if adjusted_p > config.significance_level {
+                continue;
+            }
+            let entry = best.entry(series).or_insert((index, p_value, adjusted_p));
+            if adjusted_p < entry.2 {
+                *entry = (index, p_value, adjusted_p);
+            }
+        }
+        
+        let mut regressions = Vec::new();
+        for (series, (index, p_value, adjusted_p)) in best {
+            let ((component, metric_type), group) = &series_by_key[series];
+            let values: Vec<f64> = group.iter().map(|m| m.value).collect();
+            let before = changepoint::mean(&values[..index]);
+            let after = changepoint::mean(&values[index..]);
+            if before <= 0.0 {
+                continue;
+            }
+            
+            let regression_type = self.get_regression_type_for_metric(metric_type);
+            let (regression_percentage, threshold) = match regression_type {
+                // Higher throughput is better, so only a drop is a regression
+                RegressionType::PerformanceThroughput => (
+                    (before - after) / before * 100.0,
+                    self.thresholds.throughput_regression_pct,
+                ),
+                RegressionType::PerformanceMemory | RegressionType::MemoryLeak => (
+                    (after - before) / before * 100.0,
+                    self.thresholds.memory_regression_pct,
+                ),
+                RegressionType::PerformanceCpu | RegressionType::ResourceExhaustion => (
+                    (after - before) / before * 100.0,
+                    self.thresholds.cpu_regression_pct,
+                ),
+                _ => (
+                    (after - before) / before * 100.0,
+                    self.thresholds.latency_regression_pct,
+                ),
+            };
+            
+            // A significant shift still has to be material and in the worse direction
+            if regression_percentage <= threshold {
+                continue;
+            }
+            
+            let severity = if regression_percentage > threshold * 2.0 {
+                RegressionSeverity::Critical
+            } else if regression_percentage > threshold * 1.5 {
+                RegressionSeverity::Major
+            } else {
+                RegressionSeverity::Minor
+            };
+            
+            let change = group[index];
+            let latest = group[group.len() - 1];
+            let mut metadata = HashMap::new();
+            metadata.insert("change_timestamp".to_string(), serde_json::json!(change.timestamp.to_rfc3339()));
+            metadata.insert("change_index".to_string(), serde_json::json!(index));
+            metadata.insert("samples_before".to_string(), serde_json::json!(index));
+            metadata.insert("samples_after".to_string(), serde_json::json!(group.len() - index));
+            metadata.insert("p_value".to_string(), serde_json::json!(p_value));
+            metadata.insert("adjusted_p_value".to_string(), serde_json::json!(adjusted_p));
+            metadata.insert("correction".to_string(), serde_json::json!(format!("{:?}", config.correction)));
+            
+            regressions.push(DetectedRegression {
+                id: Uuid::new_v4(),
+                regression_type,
+                severity,
+                component: component.clone(),
+                test_name: latest.test_name.clone(),
+                current_value: after,
+                baseline_value: before,
+                regression_percentage,
+                detection_algorithm: self.thresholds.detection_algorithm.name().to_string(),
+                confidence_score: (1.0 - adjusted_p) * 100.0,
+                test_run_id: latest.test_run_id.clone(),
+                timestamp: Utc::now(),
+                metadata,
+            });
+        }
+        
+        regressions
+    }
+
     /// Group measurements by component and metric type
     fn group_measurements_by_component_metric(
         &self,
@@ -78,8 +221,9 @@ impl PerformanceDetector {
         measurements: &[&PerformanceMeasurement],
         baselines: &[PerformanceBaseline],
     ) -> Result<Option<Vec<DetectedRegression>>> {
-        // Find matching baseline
-        let baseline = self.find_matching_baseline(baselines, component, metric_type)?;
+        // Find matching baseline, preferring one recorded in the same environment
+        let environment_hash = measurements.last().map(|m| m.environment.environment_hash.as_str());
+        let baseline = self.find_matching_baseline(baselines, component, metric_type, environment_hash)?;
         
         if let Some(baseline) = baseline {
             let recent_measurements = self.get_recent_measurements(measurements);
@@ -109,7 +253,21 @@ impl PerformanceDetector {
         baselines: &[PerformanceBaseline],
         component: &str,
         metric_type: &str,
+        environment_hash: Option<&str>,
     ) -> Result<Option<PerformanceBaseline>> {
+        // Per-environment baselines take precedence
+        if let Some(environment_hash) = environment_hash {
+            for baseline in baselines {
+                if baseline.component == component
+                    && baseline.metric_type == metric_type
+                    && baseline.test_environment_hash == environment_hash
+                    && baseline.is_active
+                {
+                    return Ok(Some(baseline.clone()));
+                }
+            }
+        }
+        
         // Look for exact match first
         for baseline in baselines {
             if baseline.component == component && baseline.metric_type == metric_type && baseline.is_active {
@@ -498,6 +656,11 @@ impl FunctionalDetector {
         self.run_mock_functional_test("single_test").await
     }
 
+    /// Run a functional test by name
+    pub async fn run_named_test(&self, test_name: &str) -> Result<TestResult> {
+        self.run_mock_functional_test(test_name).await
+    }
+
     /// Mock functional test execution
     async fn run_mock_functional_test(&self, test_name: &str) -> Result<TestResult> {
         // Simulate test execution
@@ -0,0 +1,530 @@
+//! Distributed Test Execution Module
+//!
+//! Runs functional and targeted tests on a pool of workers instead of one at a
+//! time in-process. The local machine contributes a configurable number of
+//! slots; remote workers reached through the CI/CD system's API contribute
+//! their own. Every test gets a timeout and bounded retries, and each result is
+//! tagged with the worker and environment it ran on.
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use chrono::Utc;
+use log::{debug, info, warn};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::process::Command;
+use tokio::sync::Mutex;
+use tokio::task::JoinSet;
+
+use crate::detectors::FunctionalDetector;
+use crate::{CICDConfig, TestEnvironment, TestResult, TestStatus, TestType, Uuid};
+
+/// Worker pool configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExecutorConfig {
+    /// Tests run concurrently on this machine
+    pub parallelism: usize,
+    pub test_timeout_secs: u64,
+    /// Extra attempts after an error or timeout
+    pub max_retries: u32,
+    /// Also retry tests that ran to completion and failed
+    pub retry_failed: bool,
+    pub retry_backoff_ms: u64,
+    /// Workers reached through the CI/CD system API
+    pub remote_workers: Vec<RemoteWorkerConfig>,
+}
+
+impl Default for ExecutorConfig {
+    fn default() -> Self {
+        Self {
+            parallelism: std::thread::available_parallelism().map(|n| n.get()).unwrap_or(4),
+            test_timeout_secs: 600,
+            max_retries: 1,
+            retry_failed: false,
+            retry_backoff_ms: 1_000,
+            remote_workers: Vec::new(),
+        }
+    }
+}
+
+/// Remote worker registered with the CI/CD system
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemoteWorkerConfig {
+    pub name: String,
+    /// Tests the worker runs concurrently
+    #[serde(default = "default_remote_slots")]
+    pub slots: usize,
+}
+
+fn default_remote_slots() -> usize {
+    1
+}
+
+/// A test to run on some worker
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TestInvocation {
+    pub test_name: String,
+    pub component: String,
+    pub test_type: TestType,
+    /// Program and arguments; tests without a command run through the functional detector
+    pub command: Option<Vec<String>>,
+}
+
+impl TestInvocation {
+    /// Functional test run through the functional detector
+    pub fn functional(test_name: &str, component: &str) -> Self {
+        Self {
+            test_name: test_name.to_string(),
+            component: component.to_string(),
+            test_type: TestType::Functional,
+            command: None,
+        }
+    }
+}
+
+/// Something that can run tests
+#[async_trait]
+pub trait TestWorker: Send + Sync {
+    /// Name recorded on every result the worker produces
+    fn name(&self) -> &str;
+
+    /// Number of tests the worker runs concurrently
+    fn slots(&self) -> usize;
+
+    /// Environment results are tagged with
+    fn environment(&self) -> TestEnvironment;
+
+    /// Run one test to completion
+    async fn run(&self, test: &TestInvocation) -> Result<TestResult>;
+}
+
+/// Worker running tests on this machine
+pub struct LocalWorker {
+    slots: usize,
+    detector: FunctionalDetector,
+    environment: TestEnvironment,
+}
+
+impl LocalWorker {
+    /// Create a local worker with the given number of slots
+    pub fn new(slots: usize) -> Self {
+        Self {
+            slots: slots.max(1),
+            detector: FunctionalDetector::new(),
+            environment: local_environment(),
+        }
+    }
+}
+
+#[async_trait]
+impl TestWorker for LocalWorker {
+    fn name(&self) -> &str {
+        "local"
+    }
+
+    fn slots(&self) -> usize {
+        self.slots
+    }
+
+    fn environment(&self) -> TestEnvironment {
+        self.environment.clone()
+    }
+
+    async fn run(&self, test: &TestInvocation) -> Result<TestResult> {
+        let command = match &test.command {
+            Some(command) => command,
+            None => {
+                let mut result = self.detector.run_named_test(&test.test_name).await?;
+                result.component = test.component.clone();
+                result.environment = self.environment.clone();
+                return Ok(result);
+            }
+        };
+
+        let (program, args) = command
+            .split_first()
+            .ok_or_else(|| anyhow::anyhow!("Empty command for test {}", test.test_name))?;
+
+        let started = Instant::now();
+        let output = Command::new(program)
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .output()
+            .await
+            .with_context(|| format!("Failed to launch test {}", test.test_name))?;
+
+        let mut metadata = HashMap::new();
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let tail: String = stderr.chars().rev().take(4096).collect::<Vec<_>>().into_iter().rev().collect();
+            metadata.insert("stderr".to_string(), serde_json::json!(tail));
+            metadata.insert("exit_status".to_string(), serde_json::json!(output.status.to_string()));
+        }
+
+        Ok(TestResult {
+            id: Uuid::new_v4(),
+            test_name: test.test_name.clone(),
+            component: test.component.clone(),
+            test_type: test.test_type.clone(),
+            status: if output.status.success() { TestStatus::Passed } else { TestStatus::Failed },
+            execution_time_ms: started.elapsed().as_millis() as u64,
+            timestamp: Utc::now(),
+            environment: self.environment.clone(),
+            metrics: HashMap::new(),
+            metadata,
+        })
+    }
+}
+
+/// Worker reached through the CI/CD system's API.
+///
+/// Tests are posted to `{api_url}/workers/{name}/run`, which is expected to run
+/// the invocation and answer with a JSON `TestResult`.
+pub struct RemoteWorker {
+    config: RemoteWorkerConfig,
+    api_url: String,
+    auth_token: String,
+    http_client: Client,
+}
+
+impl RemoteWorker {
+    /// Create a remote worker using the CI/CD system's endpoint and credentials
+    pub fn new(config: RemoteWorkerConfig, cicd: &CICDConfig) -> Self {
+        Self {
+            config,
+            api_url: cicd.api_url.trim_end_matches('/').to_string(),
+            auth_token: cicd.auth_token.clone(),
+            http_client: Client::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl TestWorker for RemoteWorker {
+    fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    fn slots(&self) -> usize {
+        self.config.slots.max(1)
+    }
+
+    fn environment(&self) -> TestEnvironment {
+        TestEnvironment {
+            name: self.config.name.clone(),
+            hardware_config: HashMap::new(),
+            software_config: HashMap::new(),
+            environment_hash: format!("remote:{}", self.config.name),
+        }
+    }
+
+    async fn run(&self, test: &TestInvocation) -> Result<TestResult> {
+        let url = format!("{}/workers/{}/run", self.api_url, self.config.name);
+        let response = self
+            .http_client
+            .post(&url)
+            .bearer_auth(&self.auth_token)
+            .json(test)
+            .send()
+            .await
+            .with_context(|| format!("Failed to reach worker {}", self.config.name))?;
+
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!(
+                "Worker {} rejected {}: HTTP {}",
+                self.config.name,
+                test.test_name,
+                response.status()
+            ));
+        }
+
+        let mut result = response
+            .json::<TestResult>()
+            .await
+            .with_context(|| format!("Invalid result from worker {}", self.config.name))?;
+
+        // Keep the worker's own description of its environment when it sends one
+        if result.environment.environment_hash.is_empty() {
+            result.environment = self.environment();
+        }
+        Ok(result)
+    }
+}
+
+/// Results of one executor run
+#[derive(Debug, Clone, Default)]
+pub struct ExecutionSummary {
+    /// One result per invocation, in submission order
+    pub results: Vec<TestResult>,
+    /// Tests completed by each worker
+    pub tests_per_worker: HashMap<String, usize>,
+    /// Attempts beyond the first, across all tests
+    pub retries: usize,
+    pub wall_time_ms: u64,
+}
+
+/// Executor distributing tests across a worker pool
+#[derive(Clone)]
+pub struct DistributedExecutor {
+    config: ExecutorConfig,
+    workers: Vec<Arc<dyn TestWorker>>,
+}
+
+impl DistributedExecutor {
+    /// Create an executor with a local worker and any configured remote workers
+    pub fn new(config: ExecutorConfig, cicd: Option<&CICDConfig>) -> Self {
+        let mut workers: Vec<Arc<dyn TestWorker>> = vec![Arc::new(LocalWorker::new(config.parallelism))];
+
+        match cicd {
+            Some(cicd) => {
+                for remote in &config.remote_workers {
+                    workers.push(Arc::new(RemoteWorker::new(remote.clone(), cicd)));
+                }
+            }
+            None if !config.remote_workers.is_empty() => {
+                warn!("Remote workers configured without a CI/CD system; running locally only");
+            }
+            None => {}
+        }
+
+        Self { config, workers }
+    }
+
+    /// Create an executor over an explicit set of workers
+    pub fn with_workers(config: ExecutorConfig, workers: Vec<Arc<dyn TestWorker>>) -> Self {
+        Self { config, workers }
+    }
+
+    /// Total concurrent slots across all workers
+    pub fn capacity(&self) -> usize {
+        self.workers.iter().map(|worker| worker.slots()).sum()
+    }
+
+    /// Run every invocation and return results in submission order
+    pub async fn execute(&self, tests: Vec<TestInvocation>) -> ExecutionSummary {
+        let started = Instant::now();
+        let total = tests.len();
+        info!("Executing {} tests on {} workers ({} slots)", total, self.workers.len(), self.capacity());
+
+        let queue = Arc::new(Mutex::new(tests.into_iter().enumerate().collect::<VecDeque<_>>()));
+        let mut slots = JoinSet::new();
+
+        for worker in &self.workers {
+            for _ in 0..worker.slots() {
+                let worker = worker.clone();
+                let queue = queue.clone();
+                let config = self.config.clone();
+
+                slots.spawn(async move {
+                    let mut completed = Vec::new();
+                    loop {
+                        let next = queue.lock().await.pop_front();
+                        let (index, test) = match next {
+                            Some(next) => next,
+                            None => break,
+                        };
+                        let (result, attempts) = run_with_retries(worker.as_ref(), &test, &config).await;
+                        completed.push((index, result, attempts));
+                    }
+                    completed
+                });
+            }
+        }
+
+        let mut summary = ExecutionSummary::default();
+        let mut indexed = Vec::with_capacity(total);
+        while let Some(joined) = slots.join_next().await {
+            match joined {
+                Ok(completed) => {
+                    for (index, result, attempts) in completed {
+                        let worker = result
+                            .metadata
+                            .get("worker")
+                            .and_then(|worker| worker.as_str())
+                            .unwrap_or("unknown")
+                            .to_string();
+                        *summary.tests_per_worker.entry(worker).or_insert(0) += 1;
+                        summary.retries += attempts.saturating_sub(1) as usize;
+                        indexed.push((index, result));
+                    }
+                }
+                Err(e) => warn!("Test worker slot panicked: {}", e),
+            }
+        }
+
+        indexed.sort_by_key(|(index, _)| *index);
+        summary.results = indexed.into_iter().map(|(_, result)| result).collect();
+        summary.wall_time_ms = started.elapsed().as_millis() as u64;
+
+        info!(
+            "Executed {} tests in {} ms with {} retries",
+            summary.results.len(),
+            summary.wall_time_ms,
+            summary.retries
+        );
+        summary
+    }
+}
+
+/// Run one test with the configured timeout and retries, tagging the result with the worker
+async fn run_with_retries(worker: &dyn TestWorker, test: &TestInvocation, config: &ExecutorConfig) -> (TestResult, u32) {
+    let timeout = Duration::from_secs(config.test_timeout_secs);
+    let mut attempt = 0;
+
+    loop {
+        attempt += 1;
+        let started = Instant::now();
+        let mut result = match tokio::time::timeout(timeout, worker.run(test)).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(e)) => synthetic_result(worker, test, TestStatus::Error, started, Some(e.to_string())),
+            Err(_) => synthetic_result(worker, test, TestStatus::Timeout, started, None),
+        };
+
+        let retryable = match result.status {
+            TestStatus::Error | TestStatus::Timeout => true,
+            TestStatus::Failed => config.retry_failed,
+            TestStatus::Passed | TestStatus::Skipped => false,
+        };
+
+        if retryable && attempt <= config.max_retries {
+            debug!("Retrying {} on {} after {:?} (attempt {})", test.test_name, worker.name(), result.status, attempt);
+            tokio::time::sleep(Duration::from_millis(config.retry_backoff_ms * attempt as u64)).await;
+            continue;
+        }
+
+        result.metadata.insert("worker".to_string(), serde_json::json!(worker.name()));
+        result.metadata.insert("attempts".to_string(), serde_json::json!(attempt));
+        result.metadata.insert("environment".to_string(), serde_json::json!(result.environment.name));
+        return (result, attempt);
+    }
+}
+
+/// Result recorded when a test could not produce one itself
+fn synthetic_result(
+    worker: &dyn TestWorker,
+    test: &TestInvocation,
+    status: TestStatus,
+    started: Instant,
+    error: Option<String>,
+) -> TestResult {
+    let mut metadata = HashMap::new();
+    if let Some(error) = error {
+        metadata.insert("error".to_string(), serde_json::json!(error));
+    }
+
+    TestResult {
+        id: Uuid::new_v4(),
+        test_name: test.test_name.clone(),
+        component: test.component.clone(),
+        test_type: test.test_type.clone(),
+        status,
+        execution_time_ms: started.elapsed().as_millis() as u64,
+        timestamp: Utc::now(),
+        environment: worker.environment(),
+        metrics: HashMap::new(),
+        metadata,
+    }
+}
+
+/// Environment describing this machine
+fn local_environment() -> TestEnvironment {
+    let mut hardware_config = HashMap::new();
+    hardware_config.insert("arch".to_string(), std::env::consts::ARCH.to_string());
+    hardware_config.insert("os".to_string(), std::env::consts::OS.to_string());
+    if let Ok(cpus) = std::thread::available_parallelism() {
+        hardware_config.insert("cpus".to_string(), cpus.get().to_string());
+    }
+
+    let mut hasher = DefaultHasher::new();
+    let mut hardware: Vec<_> = hardware_config.iter().collect();
+    hardware.sort();
+    hardware.hash(&mut hasher);
+
+    TestEnvironment {
+        name: "local".to_string(),
+        hardware_config,
+        software_config: HashMap::new(),
+        environment_hash: format!("{:016x}", hasher.finish()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    /// Worker whose first attempt at `flaky` errors and which never finishes `hang`
+    struct FakeWorker {
+        name: String,
+        flaked: AtomicBool,
+    }
+
+    #[async_trait]
+    impl TestWorker for FakeWorker {
+        fn name(&self) -> &str {
+            &self.name
+        }
+
+        fn slots(&self) -> usize {
+            2
+        }
+
+        fn environment(&self) -> TestEnvironment {
+            TestEnvironment {
+                name: self.name.clone(),
+                hardware_config: HashMap::new(),
+                software_config: HashMap::new(),
+                environment_hash: self.name.clone(),
+            }
+        }
+
+        async fn run(&self, test: &TestInvocation) -> Result<TestResult> {
+            if test.test_name == "flaky" && !self.flaked.swap(true, Ordering::SeqCst) {
+                return Err(anyhow::anyhow!("worker lost"));
+            }
+            if test.test_name == "hang" {
+                tokio::time::sleep(Duration::from_secs(60)).await;
+            }
+            Ok(synthetic_result(self, test, TestStatus::Passed, Instant::now(), None))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_results_are_ordered_retried_and_tagged() {
+        let config = ExecutorConfig {
+            parallelism: 1,
+            test_timeout_secs: 1,
+            max_retries: 1,
+            retry_failed: false,
+            retry_backoff_ms: 0,
+            remote_workers: Vec::new(),
+        };
+        let worker = Arc::new(FakeWorker { name: "fake".to_string(), flaked: AtomicBool::new(false) });
+        let executor = DistributedExecutor::with_workers(config, vec![worker]);
+
+        let tests = ["flaky", "a", "hang", "b"]
+            .iter()
+            .map(|name| TestInvocation::functional(name, "kernel"))
+            .collect();
+        let summary = executor.execute(tests).await;
+
+        let names: Vec<_> = summary.results.iter().map(|r| r.test_name.as_str()).collect();
+        assert_eq!(names, ["flaky", "a", "hang", "b"]);
+        assert_eq!(summary.results[0].status, TestStatus::Passed);
+        assert_eq!(summary.results[2].status, TestStatus::Timeout);
+        assert_eq!(summary.results[1].environment.name, "fake");
+        assert_eq!(summary.tests_per_worker["fake"], 4);
+        // One retry for the lost worker, one for the timeout
+        assert_eq!(summary.retries, 2);
+    }
+}
@@ -0,0 +1,258 @@
+//! Flaky Test Detection Module
+//!
+//! Tracks pass/fail history per test and environment, scores how often each
+//! test flips between outcomes under otherwise identical conditions, and
+//! quarantines tests whose score crosses the configured threshold. Quarantined
+//! tests keep running, but their failures no longer gate the suite.
+
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+
+use crate::{TestResult, TestStatus};
+
+/// Flaky test detection configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FlakinessConfig {
+    pub enabled: bool,
+    /// Outcomes kept per test and environment
+    pub history_window: usize,
+    /// Runs required before a test can be quarantined
+    pub min_runs: usize,
+    /// Score at or above which a test is quarantined
+    pub quarantine_threshold: f64,
+    /// Score at or below which a quarantined test is released
+    pub release_threshold: f64,
+}
+
+impl Default for FlakinessConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            history_window: 50,
+            min_runs: 10,
+            quarantine_threshold: 0.2,
+            release_threshold: 0.05,
+        }
+    }
+}
+
+/// Quarantine state of a single test, as reported in suite results
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuarantineStatus {
+    pub test_name: String,
+    pub component: String,
+    pub flakiness_score: f64,
+    pub total_runs: usize,
+    pub failure_rate: f64,
+    pub quarantined_since: DateTime<Utc>,
+    /// Failures of this test in the current run that were not gated
+    pub suppressed_failures: usize,
+}
+
+/// Outcome history of one test
+#[derive(Debug, Clone, Default)]
+struct TestHistory {
+    component: String,
+    /// Pass (`true`) / fail (`false`) outcomes keyed by environment hash
+    outcomes: HashMap<String, VecDeque<bool>>,
+    quarantined_since: Option<DateTime<Utc>>,
+}
+
+impl TestHistory {
+    fn total_runs(&self) -> usize {
+        self.outcomes.values().map(|runs| runs.len()).sum()
+    }
+
+    fn failure_rate(&self) -> f64 {
+        let total = self.total_runs();
+        if total == 0 {
+            return 0.0;
+        }
+        let failures: usize = self.outcomes.values().map(|runs| runs.iter().filter(|passed| !**passed).count()).sum();
+        failures as f64 / total as f64
+    }
+
+    /// Fraction of consecutive runs in the same environment whose outcome flipped.
+    ///
+    /// Scoring per environment keeps a test that always fails on one machine
+    /// and always passes on another from being mistaken for a flaky one.
+    fn flakiness_score(&self) -> f64 {
+        let (flips, pairs) = self.outcomes.values().fold((0usize, 0usize), |(flips, pairs), runs| {
+            let env_flips = runs.iter().zip(runs.iter().skip(1)).filter(|(a, b)| a != b).count();
+            (flips + env_flips, pairs + runs.len().saturating_sub(1))
+        });
+
+        if pairs == 0 {
+            0.0
+        } else {
+            flips as f64 / pairs as f64
+        }
+    }
+}
+
+/// Tracks test outcomes and maintains the quarantine list
+#[derive(Debug, Clone)]
+pub struct FlakinessAnalyzer {
+    config: FlakinessConfig,
+    histories: HashMap<String, TestHistory>,
+}
+
+impl FlakinessAnalyzer {
+    /// Create new flakiness analyzer
+    pub fn new(config: FlakinessConfig) -> Self {
+        Self {
+            config,
+            histories: HashMap::new(),
+        }
+    }
+
+    /// Seed history from previously stored results, oldest first
+    pub fn load_history(&mut self, results: &[TestResult]) {
+        for result in results {
+            self.record(result);
+        }
+    }
+
+    /// Record a test outcome and return whether the test is quarantined afterwards.
+    ///
+    /// Skipped tests carry no pass/fail signal and are ignored.
+    pub fn record(&mut self, result: &TestResult) -> bool {
+        let passed = match result.status {
+            TestStatus::Passed => true,
+            TestStatus::Failed | TestStatus::Error | TestStatus::Timeout => false,
+            TestStatus::Skipped => return self.is_quarantined(&result.test_name),
+        };
+
+        let window = self.config.history_window.max(2);
+        let history = self.histories.entry(result.test_name.clone()).or_default();
+        history.component = result.component.clone();
+
+        let runs = history.outcomes.entry(result.environment.environment_hash.clone()).or_default();
+        runs.push_back(passed);
+        while runs.len() > window {
+            runs.pop_front();
+        }
+
+        if !self.config.enabled {
+            return false;
+        }
+
+        let score = history.flakiness_score();
+        match history.quarantined_since {
+            None if history.total_runs() >= self.config.min_runs && score >= self.config.quarantine_threshold => {
+                warn!("Quarantining flaky test {} (score {:.2})", result.test_name, score);
+                history.quarantined_since = Some(result.timestamp);
+            }
+            Some(_) if score <= self.config.release_threshold => {
+                info!("Releasing {} from quarantine (score {:.2})", result.test_name, score);
+                history.quarantined_since = None;
+            }
+            _ => {}
+        }
+
+        history.quarantined_since.is_some()
+    }
+
+    /// Whether failures of this test are excluded from regression gating
+    pub fn is_quarantined(&self, test_name: &str) -> bool {
+        self.config.enabled
+            && self
+                .histories
+                .get(test_name)
+                .is_some_and(|history| history.quarantined_since.is_some())
+    }
+
+    /// Current flakiness score of a test, if it has any history
+    pub fn score(&self, test_name: &str) -> Option<f64> {
+        self.histories.get(test_name).map(|history| history.flakiness_score())
+    }
+
+    /// Manually release a test from quarantine
+    pub fn release(&mut self, test_name: &str) -> bool {
+        self.histories
+            .get_mut(test_name)
+            .and_then(|history| history.quarantined_since.take())
+            .is_some()
+    }
+
+    /// All quarantined tests, most flaky first
+    pub fn quarantined_tests(&self) -> Vec<QuarantineStatus> {
+        let mut quarantined: Vec<QuarantineStatus> = self
+            .histories
+            .iter()
+            .filter_map(|(test_name, history)| {
+                history.quarantined_since.map(|since| QuarantineStatus {
+                    test_name: test_name.clone(),
+                    component: history.component.clone(),
+                    flakiness_score: history.flakiness_score(),
+                    total_runs: history.total_runs(),
+                    failure_rate: history.failure_rate(),
+                    quarantined_since: since,
+                    suppressed_failures: 0,
+                })
+            })
+            .collect();
+
+        quarantined.sort_by(|a, b| b.flakiness_score.total_cmp(&a.flakiness_score));
+        quarantined
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{TestEnvironment, TestType, Uuid};
+
+    fn result(test_name: &str, environment_hash: &str, status: TestStatus) -> TestResult {
+        TestResult {
+            id: Uuid::new_v4(),
+            test_name: test_name.to_string(),
+            component: "scheduler".to_string(),
+            test_type: TestType::Functional,
+            status,
+            execution_time_ms: 10,
+            timestamp: Utc::now(),
+            environment: TestEnvironment {
+                name: environment_hash.to_string(),
+                hardware_config: HashMap::new(),
+                software_config: HashMap::new(),
+                environment_hash: environment_hash.to_string(),
+            },
+            metrics: HashMap::new(),
+            metadata: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_alternating_outcomes_are_quarantined() {
+        let mut analyzer = FlakinessAnalyzer::new(FlakinessConfig::default());
+        for i in 0..12 {
+            let status = if i % 3 == 0 { TestStatus::Failed } else { TestStatus::Passed };
+            analyzer.record(&result("flaky", "ci", status));
+        }
+
+        assert!(analyzer.is_quarantined("flaky"));
+        assert_eq!(analyzer.quarantined_tests()[0].test_name, "flaky");
+
+        // A long run of passes brings the score back down
+        for _ in 0..50 {
+            analyzer.record(&result("flaky", "ci", TestStatus::Passed));
+        }
+        assert!(!analyzer.is_quarantined("flaky"));
+    }
+
+    #[test]
+    fn test_environment_specific_failures_are_not_flaky() {
+        let mut analyzer = FlakinessAnalyzer::new(FlakinessConfig::default());
+        for _ in 0..10 {
+            analyzer.record(&result("arm_only", "x86", TestStatus::Passed));
+            analyzer.record(&result("arm_only", "arm", TestStatus::Failed));
+        }
+
+        assert_eq!(analyzer.score("arm_only"), Some(0.0));
+        assert!(!analyzer.is_quarantined("arm_only"));
+    }
+}
@@ -4,15 +4,17 @@
 //! CI/CD systems, monitoring tools, and external testing platforms for
 //! comprehensive regression testing ecosystem integration.
 
-use anyhow::{Result};
+use anyhow::{Context, Result};
 use chrono::{DateTime, Utc};
 use log::{info, debug, warn};
 use reqwest::{Client, header};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
+pub mod multios;
+
 use crate::{
-    PerformanceMeasurement, TestSuiteConfig, TestSuiteResult, CodeChange,
+    PerformanceMeasurement, TestEnvironment, TestSuiteConfig, TestSuiteResult, CodeChange,
     BenchmarkResult, Uuid,
 };
 
@@ -146,6 +148,15 @@ pub struct MonitoringConfig {
     pub alert_thresholds: AlertThresholds,
 }
 
+/// A single instant query scraped into a performance measurement
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScrapeQuery {
+    pub component: String,
+    pub metric_type: String,
+    pub query: String,
+    pub unit: String,
+}
+
 /// Supported monitoring systems
 #[derive(Debug, Clone, Serialize, Deserialize)]
 enum MonitoringSystemType {
@@ -677,6 +688,7 @@ impl CICDIntegrator {
             failed_tests: 5,
             skipped_tests: 0,
             regressions_detected: Vec::new(),
+            quarantined_tests: Vec::new(),
             summary: HashMap::new(),
         })
     }
@@ -780,6 +792,96 @@ impl MonitoringIntegrator {
         Ok(())
     }
 
+    /// Scrape the given instant queries into performance measurements
+    pub async fn scrape_measurements(&self, queries: &[ScrapeQuery]) -> Result<Vec<PerformanceMeasurement>> {
+        let config = match &self.config {
+            Some(config) => config,
+            None => return Ok(Vec::new()),
+        };
+        
+        if !matches!(config.system_type, MonitoringSystemType::Prometheus) {
+            debug!("Measurement scraping only supported for Prometheus, skipping");
+            return Ok(Vec::new());
+        }
+        
+        let client = self.http_client();
+        let url = format!("{}/api/v1/query", config.api_url);
+        let mut measurements = Vec::new();
+        
+        for query in queries {
+            let response = client
+                .get(&url)
+                .header("Authorization", format!("Bearer {}", config.api_key))
+                .query(&[("query", query.query.as_str())])
+                .send()
+                .await
+                .context("Failed to send Prometheus query")?;
+            
+            if !response.status().is_success() {
+                warn!("Prometheus query '{}' failed with status: {}", query.query, response.status());
+                continue;
+            }
+            
+            let body: serde_json::Value = response
+                .json()
+                .await
+                .context("Failed to parse Prometheus response")?;
+            
+            measurements.extend(Self::parse_prometheus_vector(query, &body));
+        }
+        
+        debug!("Scraped {} measurements from Prometheus", measurements.len());
+        Ok(measurements)
+    }
+
+    /// Convert an instant-vector query response into measurements
+    fn parse_prometheus_vector(query: &ScrapeQuery, body: &serde_json::Value) -> Vec<PerformanceMeasurement> {
+        let results = match body["data"]["result"].as_array() {
+            Some(results) => results,
+            None => return Vec::new(),
+        };
+        
+        results
+            .iter()
+            .filter_map(|sample| {
+                // Prometheus encodes each sample as [unix_seconds, "value"]
+                let timestamp = sample["value"][0].as_f64()?;
+                let value = sample["value"][1].as_str()?.parse::<f64>().ok()?;
+                if !value.is_finite() {
+                    return None;
+                }
+                
+                let labels: HashMap<String, String> = sample["metric"]
+                    .as_object()
+                    .map(|labels| {
+                        labels
+                            .iter()
+                            .filter_map(|(k, v)| v.as_str().map(|v| (k.clone(), v.to_string())))
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                let instance = labels.get("instance").cloned().unwrap_or_else(|| "prometheus".to_string());
+                
+                Some(PerformanceMeasurement {
+                    id: Uuid::new_v4(),
+                    test_name: format!("{}_{}_monitor", query.component, query.metric_type),
+                    component: query.component.clone(),
+                    metric_type: query.metric_type.clone(),
+                    value,
+                    unit: query.unit.clone(),
+                    test_run_id: "continuous_monitoring".to_string(),
+                    timestamp: DateTime::<Utc>::from_timestamp(timestamp as i64, 0).unwrap_or_else(Utc::now),
+                    environment: TestEnvironment {
+                        name: instance.clone(),
+                        hardware_config: HashMap::new(),
+                        software_config: labels,
+                        environment_hash: instance,
+                    },
+                })
+            })
+            .collect()
+    }
+
     /// Collect metrics from Grafana
     async fn collect_grafana_metrics(&self) -> Result<()> {
         // This would query Grafana dashboards for metrics
@@ -907,6 +1009,7 @@ impl GitIntegration {
                 files_changed: vec!["kernel/scheduler.rs".to_string()],
                 timestamp: Utc::now(),
                 change_type: "bugfix".to_string(),
+                changed_functions: HashMap::new(),
             }
         ])
     }
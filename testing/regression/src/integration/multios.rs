@@ -0,0 +1,349 @@
+//! MultiOS Benchmark Adapter
+//!
+//! First-party harness that launches the in-tree hypervisor and scheduler
+//! micro-benchmarks and ingests their output as performance measurements, so
+//! regressions in this repository are tracked without an external
+//! benchmarking service.
+//!
+//! Each benchmark command is expected to print JSON lines on stdout of the form
+//! `{"benchmark": "vm_exit_latency", "value": 812.0, "unit": "ns"}`; any other
+//! output is ignored.
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::process::Command;
+
+use crate::{PerformanceMeasurement, TestEnvironment, Uuid};
+
+/// Micro-benchmarks provided by the MultiOS tree
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum MultiOsBenchmark {
+    /// Time from VM creation to the guest's first instruction
+    VmBootTime,
+    /// Round-trip cost of a VM exit and re-entry
+    VmExitLatency,
+    /// Scheduler context-switch latency between two runnable threads
+    ContextSwitchLatency,
+    /// CPUID exit handling against the hypervisor's guest stub
+    CpuidExitLatency,
+    /// EPT violation handling against the hypervisor's guest stub
+    EptFaultLatency,
+    /// Interrupt delivery until injection on VM entry
+    InterruptInjectionLatency,
+    /// Hypercall dispatch against the hypervisor's guest stub
+    HypercallLatency,
+}
+
+impl MultiOsBenchmark {
+    /// Name used on the command line and in the JSON output
+    pub fn name(&self) -> &'static str {
+        match self {
+            MultiOsBenchmark::VmBootTime => "vm_boot_time",
+            MultiOsBenchmark::VmExitLatency => "vm_exit_latency",
+            MultiOsBenchmark::ContextSwitchLatency => "context_switch_latency",
+            MultiOsBenchmark::CpuidExitLatency => "cpuid_exit_latency",
+            MultiOsBenchmark::EptFaultLatency => "ept_fault_latency",
+            MultiOsBenchmark::InterruptInjectionLatency => "interrupt_injection_latency",
+            MultiOsBenchmark::HypercallLatency => "hypercall_latency",
+        }
+    }
+
+    /// Component the measurement is attributed to
+    pub fn component(&self) -> &'static str {
+        match self {
+            MultiOsBenchmark::ContextSwitchLatency => "scheduler",
+            _ => "hypervisor",
+        }
+    }
+
+    /// Metric type understood by the performance detector
+    pub fn metric_type(&self) -> &'static str {
+        match self {
+            MultiOsBenchmark::VmBootTime => "execution_time",
+            _ => "latency",
+        }
+    }
+
+    /// Unit assumed when the benchmark output omits one
+    pub fn default_unit(&self) -> &'static str {
+        match self {
+            MultiOsBenchmark::VmBootTime => "ms",
+            MultiOsBenchmark::VmExitLatency | MultiOsBenchmark::ContextSwitchLatency => "ns",
+            // The guest stub benchmarks time with the TSC by default
+            _ => "cycles",
+        }
+    }
+
+    /// Command that runs the benchmark from the repository root
+    pub fn default_command(&self) -> Vec<String> {
+        let manifest = match self.component() {
+            "hypervisor" => "system_features/hypervisor/Cargo.toml",
+            _ => "perf/scheduler_profiler/Cargo.toml",
+        };
+
+        [
+            "cargo", "run", "--release", "--quiet", "--manifest-path", manifest, "--", "bench",
+            self.name(), "--json",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect()
+    }
+
+    /// Look a benchmark up by its command-line name
+    pub fn from_name(name: &str) -> Option<Self> {
+        [
+            MultiOsBenchmark::VmBootTime,
+            MultiOsBenchmark::VmExitLatency,
+            MultiOsBenchmark::ContextSwitchLatency,
+            MultiOsBenchmark::CpuidExitLatency,
+            MultiOsBenchmark::EptFaultLatency,
+            MultiOsBenchmark::InterruptInjectionLatency,
+            MultiOsBenchmark::HypercallLatency,
+        ]
+        .into_iter()
+        .find(|benchmark| benchmark.name() == name)
+    }
+}
+
+/// Override for how a single benchmark is launched
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MultiOsBenchmarkSpec {
+    pub benchmark: MultiOsBenchmark,
+    /// Program and arguments; defaults to [`MultiOsBenchmark::default_command`]
+    pub command: Option<Vec<String>>,
+}
+
+/// Configuration for the first-party benchmark harness
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MultiOsBenchConfig {
+    /// Repository root the benchmark commands run from
+    pub repo_root: PathBuf,
+    pub benchmarks: Vec<MultiOsBenchmarkSpec>,
+    /// Times each benchmark command is launched per collection
+    pub iterations: usize,
+    pub timeout_secs: u64,
+    pub environment_name: String,
+}
+
+impl Default for MultiOsBenchConfig {
+    fn default() -> Self {
+        Self {
+            repo_root: PathBuf::from("."),
+            benchmarks: vec![
+                MultiOsBenchmarkSpec { benchmark: MultiOsBenchmark::VmBootTime, command: None },
+                MultiOsBenchmarkSpec { benchmark: MultiOsBenchmark::VmExitLatency, command: None },
+                MultiOsBenchmarkSpec { benchmark: MultiOsBenchmark::ContextSwitchLatency, command: None },
+                MultiOsBenchmarkSpec { benchmark: MultiOsBenchmark::CpuidExitLatency, command: None },
+                MultiOsBenchmarkSpec { benchmark: MultiOsBenchmark::EptFaultLatency, command: None },
+                MultiOsBenchmarkSpec { benchmark: MultiOsBenchmark::InterruptInjectionLatency, command: None },
+                MultiOsBenchmarkSpec { benchmark: MultiOsBenchmark::HypercallLatency, command: None },
+            ],
+            iterations: 1,
+            timeout_secs: 600,
+            environment_name: "local".to_string(),
+        }
+    }
+}
+
+/// One line of benchmark output
+#[derive(Debug, Clone, Deserialize)]
+struct MicrobenchSample {
+    benchmark: String,
+    value: f64,
+    #[serde(default)]
+    unit: Option<String>,
+}
+
+/// Harness running MultiOS micro-benchmarks
+#[derive(Debug, Clone)]
+pub struct MultiOsBenchHarness {
+    config: MultiOsBenchConfig,
+}
+
+impl MultiOsBenchHarness {
+    /// Create new benchmark harness
+    pub fn new(config: MultiOsBenchConfig) -> Self {
+        Self { config }
+    }
+
+    /// Configured benchmarks
+    pub fn benchmarks(&self) -> Vec<MultiOsBenchmark> {
+        self.config.benchmarks.iter().map(|spec| spec.benchmark).collect()
+    }
+
+    /// Run every configured benchmark
+    pub async fn run_all(&self) -> Result<Vec<PerformanceMeasurement>> {
+        self.run_selected(&self.benchmarks()).await
+    }
+
+    /// Run the given benchmarks, skipping ones that fail to launch
+    pub async fn run_selected(&self, benchmarks: &[MultiOsBenchmark]) -> Result<Vec<PerformanceMeasurement>> {
+        let environment = self.describe_environment().await;
+        let test_run_id = Uuid::new_v4().to_string();
+        let mut measurements = Vec::new();
+
+        for spec in self.config.benchmarks.iter().filter(|spec| benchmarks.contains(&spec.benchmark)) {
+            for iteration in 0..self.config.iterations.max(1) {
+                match self.run_benchmark(spec).await {
+                    Ok(output) => {
+                        let parsed = parse_output(spec.benchmark, &output, &environment, &test_run_id);
+                        if parsed.is_empty() {
+                            warn!("Benchmark {} produced no samples", spec.benchmark.name());
+                        }
+                        measurements.extend(parsed);
+                    }
+                    Err(e) => {
+                        warn!("Benchmark {} iteration {} failed: {}", spec.benchmark.name(), iteration, e);
+                        break;
+                    }
+                }
+            }
+        }
+
+        info!("Collected {} MultiOS benchmark measurements", measurements.len());
+        Ok(measurements)
+    }
+
+    /// Launch one benchmark command and return its stdout
+    async fn run_benchmark(&self, spec: &MultiOsBenchmarkSpec) -> Result<String> {
+        let command = spec.command.clone().unwrap_or_else(|| spec.benchmark.default_command());
+        let (program, args) = command
+            .split_first()
+            .ok_or_else(|| anyhow::anyhow!("Empty command for benchmark {}", spec.benchmark.name()))?;
+
+        debug!("Running benchmark {}: {:?}", spec.benchmark.name(), command);
+
+        let child = Command::new(program)
+            .args(args)
+            .current_dir(&self.config.repo_root)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("Failed to launch benchmark {}", spec.benchmark.name()))?;
+
+        let output = tokio::time::timeout(Duration::from_secs(self.config.timeout_secs), child.wait_with_output())
+            .await
+            .map_err(|_| anyhow::anyhow!("Benchmark {} timed out", spec.benchmark.name()))?
+            .context("Failed to collect benchmark output")?;
+
+        if !output.status.success() {
+            return Err(anyhow::anyhow!(
+                "Benchmark exited with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    /// Describe the host and source revision the benchmarks ran against
+    async fn describe_environment(&self) -> TestEnvironment {
+        let mut hardware_config = HashMap::new();
+        hardware_config.insert("arch".to_string(), std::env::consts::ARCH.to_string());
+        hardware_config.insert("os".to_string(), std::env::consts::OS.to_string());
+        if let Ok(cpus) = std::thread::available_parallelism() {
+            hardware_config.insert("cpus".to_string(), cpus.get().to_string());
+        }
+
+        let mut software_config = HashMap::new();
+        let revision = Command::new("git")
+            .args(["rev-parse", "HEAD"])
+            .current_dir(&self.config.repo_root)
+            .output()
+            .await;
+        if let Ok(output) = revision {
+            if output.status.success() {
+                software_config.insert(
+                    "commit".to_string(),
+                    String::from_utf8_lossy(&output.stdout).trim().to_string(),
+                );
+            }
+        }
+
+        // The commit is excluded so results stay comparable across revisions
+        let mut hasher = DefaultHasher::new();
+        self.config.environment_name.hash(&mut hasher);
+        let mut hardware: Vec<_> = hardware_config.iter().collect();
+        hardware.sort();
+        hardware.hash(&mut hasher);
+
+        TestEnvironment {
+            name: self.config.environment_name.clone(),
+            hardware_config,
+            software_config,
+            environment_hash: format!("{:016x}", hasher.finish()),
+        }
+    }
+}
+
+/// Convert JSON-lines benchmark output into measurements for one benchmark
+fn parse_output(
+    benchmark: MultiOsBenchmark,
+    output: &str,
+    environment: &TestEnvironment,
+    test_run_id: &str,
+) -> Vec<PerformanceMeasurement> {
+    output
+        .lines()
+        .filter_map(|line| serde_json::from_str::<MicrobenchSample>(line.trim()).ok())
+        .filter(|sample| sample.benchmark == benchmark.name() && sample.value.is_finite())
+        .map(|sample| PerformanceMeasurement {
+            id: Uuid::new_v4(),
+            test_name: benchmark.name().to_string(),
+            component: benchmark.component().to_string(),
+            metric_type: benchmark.metric_type().to_string(),
+            value: sample.value,
+            unit: sample.unit.unwrap_or_else(|| benchmark.default_unit().to_string()),
+            test_run_id: test_run_id.to_string(),
+            timestamp: Utc::now(),
+            environment: environment.clone(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_output_filters_lines() {
+        let environment = TestEnvironment {
+            name: "ci".to_string(),
+            hardware_config: HashMap::new(),
+            software_config: HashMap::new(),
+            environment_hash: "ci".to_string(),
+        };
+        let output = "warming up\n\
+            {\"benchmark\":\"vm_exit_latency\",\"value\":812.5,\"unit\":\"ns\"}\n\
+            {\"benchmark\":\"vm_boot_time\",\"value\":41.0}\n\
+            {\"benchmark\":\"vm_exit_latency\",\"value\":790.0}\n";
+
+        let measurements = parse_output(MultiOsBenchmark::VmExitLatency, output, &environment, "run");
+        assert_eq!(measurements.len(), 2);
+        assert_eq!(measurements[0].value, 812.5);
+        assert_eq!(measurements[0].component, "hypervisor");
+        assert_eq!(measurements[0].metric_type, "latency");
+        assert_eq!(measurements[1].unit, "ns");
+    }
+
+    #[test]
+    fn test_benchmark_names_round_trip() {
+        for benchmark in MultiOsBenchConfig::default().benchmarks.iter().map(|spec| spec.benchmark) {
+            assert_eq!(MultiOsBenchmark::from_name(benchmark.name()), Some(benchmark));
+        }
+        assert_eq!(MultiOsBenchmark::from_name("unknown"), None);
+    }
+}
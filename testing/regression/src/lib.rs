@@ -14,13 +14,21 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::path::Path;
+use std::sync::Arc;
 use uuid::Uuid;
 
+pub mod alerts;
 pub mod analyzer;
+pub mod baselines;
+pub mod bisect;
+pub mod changepoint;
 pub mod database;
 pub mod detectors;
+pub mod executor;
+pub mod flakiness;
 pub mod generator;
 pub mod integration;
+pub mod monitor;
 pub mod reporter;
 pub mod scheduler;
 pub mod selector;
@@ -28,12 +36,20 @@ pub mod storage;
 pub mod trending;
 pub mod utils;
 
+use alerts::{AlertDispatcher, RetryPolicy, WebhookConfig};
 use analyzer::PerformanceAnalyzer;
-use database::DatabaseManager;
-use detectors::{FunctionalDetector, PerformanceDetector};
+use baselines::{BaselineApprovalConfig, BaselineKey, BaselineManager, BaselineProposal, BaselineRevision};
+use bisect::{BisectConfig, Bisector, CommitBuilder, GitCommitBuilder};
+use changepoint::{ChangePointConfig, DetectionAlgorithm};
+use database::{DatabaseManager, RetentionConfig};
+use detectors::PerformanceDetector;
+use executor::{DistributedExecutor, ExecutorConfig, TestInvocation};
+use flakiness::{FlakinessAnalyzer, FlakinessConfig, QuarantineStatus};
 use generator::TestCaseGenerator;
-use integration::BenchmarkIntegrator;
-use reporter::ReportGenerator;
+use integration::multios::{MultiOsBenchConfig, MultiOsBenchHarness, MultiOsBenchmark};
+use integration::{BenchmarkIntegrator, MonitoringIntegrator};
+use monitor::{ContinuousMonitor, MonitorHandle, MonitorStatsSnapshot, MonitoringLoopConfig};
+use reporter::{ReportAttachment, ReportGenerator, WrittenReport};
 use scheduler::TestScheduler;
 use selector::ChangeBasedSelector;
 use storage::{BaselineStore, MeasurementStore};
@@ -60,6 +76,10 @@ pub struct PerformanceThresholds {
     pub confidence_threshold: f64,          // Default: 80.0%
     pub sample_size_minimum: usize,         // Default: 10
     pub outlier_detection_sigma: f64,       // Default: 2.0
+    #[serde(default)]
+    pub detection_algorithm: DetectionAlgorithm, // Default: Threshold
+    #[serde(default)]
+    pub change_point: ChangePointConfig,
 }
 
 /// Alert configuration
@@ -69,6 +89,10 @@ pub struct AlertConfig {
     pub slack_webhook: Option<String>,
     pub escalation_rules: EscalationRules,
     pub quiet_hours: QuietHours,
+    #[serde(default)]
+    pub webhooks: Vec<WebhookConfig>,
+    #[serde(default)]
+    pub retry: RetryPolicy,
 }
 
 /// Email notification settings
@@ -107,6 +131,12 @@ pub struct SchedulingConfig {
     pub scheduled_test_intervals: HashMap<String, String>, // test_name -> cron expression
     pub regression_check_interval: String, // cron expression
     pub trend_analysis_interval: String,   // cron expression
+    #[serde(default)]
+    pub monitoring_loop: MonitoringLoopConfig,
+    #[serde(default)]
+    pub executor: ExecutorConfig,
+    #[serde(default)]
+    pub retention: RetentionConfig,
 }
 
 /// Integration configurations
@@ -115,6 +145,8 @@ pub struct IntegrationConfigs {
     pub benchmarking_system: Option<BenchmarkConfig>,
     pub ci_cd_system: Option<CICDConfig>,
     pub monitoring_system: Option<MonitoringConfig>,
+    #[serde(default)]
+    pub multios_benchmarks: Option<MultiOsBenchConfig>,
 }
 
 /// Benchmarking system integration
@@ -149,6 +181,10 @@ pub struct TestingStrategies {
     pub change_based_testing: ChangeBasedTestingConfig,
     pub automated_test_generation: AutomatedTestGenConfig,
     pub priority_based_testing: PriorityBasedConfig,
+    #[serde(default)]
+    pub flakiness: FlakinessConfig,
+    #[serde(default)]
+    pub baseline_approval: BaselineApprovalConfig,
 }
 
 /// Change-based testing configuration
@@ -158,6 +194,10 @@ pub struct ChangeBasedTestingConfig {
     pub impact_analysis_depth: usize,
     pub max_tests_per_change: usize,
     pub test_selection_algorithm: String, // risk_based, coverage_based, history_based
+    #[serde(default)]
+    pub coverage_map_path: Option<String>,
+    #[serde(default)]
+    pub max_selection_budget_ms: Option<u64>,
 }
 
 /// Automated test generation configuration
@@ -334,18 +374,29 @@ pub struct TrendPrediction {
 /// Main regression testing system controller
 pub struct RegressionTestingSystem {
     config: RegressionConfig,
-    db: DatabaseManager,
+    db: Arc<DatabaseManager>,
     performance_detector: PerformanceDetector,
-    functional_detector: FunctionalDetector,
+    executor: DistributedExecutor,
+    flakiness_analyzer: FlakinessAnalyzer,
+    suppressed_failures: HashMap<String, usize>,
     performance_analyzer: PerformanceAnalyzer,
     trend_analyzer: TrendAnalyzer,
     baseline_store: BaselineStore,
+    baseline_manager: BaselineManager,
     measurement_store: MeasurementStore,
     test_generator: TestCaseGenerator,
     change_selector: ChangeBasedSelector,
     scheduler: TestScheduler,
     benchmark_integrator: BenchmarkIntegrator,
+    multios_harness: Option<MultiOsBenchHarness>,
+    bisector: Option<Bisector>,
+    recent_code_changes: Vec<CodeChange>,
     report_generator: ReportGenerator,
+    last_report: Option<WrittenReport>,
+    alert_dispatcher: AlertDispatcher,
+    pending_alerts: Vec<DetectedRegression>,
+    monitor_handle: Option<MonitorHandle>,
+    retention_job: Option<tokio::task::JoinHandle<()>>,
 }
 
 impl RegressionTestingSystem {
@@ -358,12 +409,18 @@ impl RegressionTestingSystem {
         
         Ok(Self {
             config: config.clone(),
-            db,
+            db: Arc::new(db),
             performance_detector: PerformanceDetector::new(config.performance_thresholds.clone()),
-            functional_detector: FunctionalDetector::new(),
+            executor: DistributedExecutor::new(
+                config.scheduling_config.executor.clone(),
+                config.integration_configs.ci_cd_system.as_ref(),
+            ),
+            flakiness_analyzer: FlakinessAnalyzer::new(config.testing_strategies.flakiness.clone()),
+            suppressed_failures: HashMap::new(),
             performance_analyzer: PerformanceAnalyzer::new(),
             trend_analyzer: TrendAnalyzer::new(),
             baseline_store: BaselineStore::new(),
+            baseline_manager: BaselineManager::new(config.testing_strategies.baseline_approval.clone()),
             measurement_store: MeasurementStore::new(),
             test_generator: TestCaseGenerator::new(config.testing_strategies.automated_test_generation.clone()),
             change_selector: ChangeBasedSelector::new(config.testing_strategies.change_based_testing.clone()),
@@ -371,7 +428,22 @@ impl RegressionTestingSystem {
             benchmark_integrator: BenchmarkIntegrator::new(
                 config.integration_configs.benchmarking_system.clone()
             ),
+            multios_harness: config.integration_configs.multios_benchmarks.clone().map(MultiOsBenchHarness::new),
+            bisector: config.integration_configs.multios_benchmarks.clone().map(|bench_config| {
+                let builder = GitCommitBuilder::new(
+                    bench_config.repo_root.clone(),
+                    None,
+                    MultiOsBenchHarness::new(bench_config),
+                );
+                Bisector::new(BisectConfig::default(), Arc::new(builder))
+            }),
+            recent_code_changes: Vec::new(),
             report_generator: ReportGenerator::new(),
+            last_report: None,
+            alert_dispatcher: AlertDispatcher::new(config.alert_rules.clone()),
+            pending_alerts: Vec::new(),
+            monitor_handle: None,
+            retention_job: None,
         })
     }
 
@@ -387,6 +459,11 @@ impl RegressionTestingSystem {
         Ok(config)
     }
 
+    /// Use a custom commit builder for bisection-based root cause analysis
+    pub fn set_commit_builder(&mut self, config: BisectConfig, builder: Arc<dyn CommitBuilder>) {
+        self.bisector = Some(Bisector::new(config, builder));
+    }
+
     /// Initialize the regression testing system
     pub async fn initialize(&mut self) -> Result<()> {
         log::info!("Initializing MultiOS Regression Testing System");
@@ -395,12 +472,26 @@ impl RegressionTestingSystem {
         self.db.initialize_schema().await
             .context("Failed to initialize database schema")?;
         
-        // Start background services
-        self.start_background_services().await?;
-        
-        // Load existing baselines
+        // Load existing baselines before monitoring starts comparing against them
         self.baseline_store.load_from_database(&self.db).await
             .context("Failed to load performance baselines")?;
+        self.baseline_manager.restore(
+            self.db.get_pending_baseline_proposals().await
+                .context("Failed to load baseline proposals")?,
+            self.db.get_baseline_revisions().await
+                .context("Failed to load baseline history")?,
+        );
+        
+        // Load coverage data for change-based test selection
+        if let Some(path) = &self.config.testing_strategies.change_based_testing.coverage_map_path {
+            match selector::coverage::CoverageMap::load(Path::new(path), "unknown").await {
+                Ok(coverage) => self.change_selector.load_coverage(coverage),
+                Err(e) => log::warn!("Failed to load coverage map {}: {}", path, e),
+            }
+        }
+        
+        // Start background services
+        self.start_background_services().await?;
         
         log::info!("Regression testing system initialized successfully");
         Ok(())
@@ -417,20 +508,126 @@ impl RegressionTestingSystem {
             self.start_continuous_monitoring().await?;
         }
         
+        // Prune raw data past the retention window
+        if self.retention_job.is_none() {
+            self.retention_job = self.db.spawn_retention_job(self.config.scheduling_config.retention.clone());
+        }
+        
         Ok(())
     }
 
     /// Start continuous monitoring of system performance
     async fn start_continuous_monitoring(&mut self) -> Result<()> {
+        if self.monitor_handle.is_some() {
+            log::debug!("Continuous monitoring already running");
+            return Ok(());
+        }
+        
         log::info!("Starting continuous monitoring");
         
-        // TODO: Implement continuous monitoring logic
-        // This would involve periodic performance measurements,
-        // baseline comparisons, and regression detection
+        let monitor = ContinuousMonitor::new(
+            self.config.scheduling_config.monitoring_loop.clone(),
+            MonitoringIntegrator::new(self.config.integration_configs.monitoring_system.clone()),
+            self.benchmark_integrator.clone(),
+            self.performance_detector.clone(),
+            self.baseline_store.get_baselines(),
+        );
+        
+        self.monitor_handle = Some(monitor.spawn(self.db.clone()));
+        Ok(())
+    }
+
+    /// Stop continuous monitoring, flushing queued measurements and regressions
+    pub async fn stop_continuous_monitoring(&mut self) -> Result<()> {
+        if let Some(handle) = self.monitor_handle.take() {
+            handle.stop().await
+                .context("Failed to stop continuous monitoring")?;
+        }
         
         Ok(())
     }
 
+    /// Counters for the continuous monitoring loop, if running
+    pub fn monitoring_stats(&self) -> Option<MonitorStatsSnapshot> {
+        self.monitor_handle.as_ref().map(|handle| handle.stats())
+    }
+
+    /// Export the full regression dataset to a JSON file
+    pub async fn export_dataset(&self, path: &Path) -> Result<usize> {
+        self.db.export_to_file(path).await
+    }
+
+    /// Import a dataset exported from this or another storage backend
+    pub async fn import_dataset(&mut self, path: &Path) -> Result<usize> {
+        let imported = self.db.import_from_file(path).await?;
+        
+        // Imported baselines and approvals must be visible without a restart
+        self.baseline_store.load_from_database(&self.db).await
+            .context("Failed to reload performance baselines")?;
+        self.baseline_manager.restore(
+            self.db.get_pending_baseline_proposals().await?,
+            self.db.get_baseline_revisions().await?,
+        );
+        
+        Ok(imported)
+    }
+
+    /// Propose new baselines from the measurements of a test run; they take effect once approved
+    pub async fn propose_baselines_from_run(&mut self, test_run_id: &str, proposed_by: &str) -> Result<Vec<BaselineProposal>> {
+        let measurements = self.db.get_measurements_for_run(test_run_id).await?;
+        let proposals = self.baseline_manager.propose_from_run(test_run_id, &measurements, proposed_by)?;
+        
+        for proposal in &proposals {
+            self.db.store_baseline_proposal(proposal).await?;
+        }
+        
+        log::info!("{} proposed {} baselines from run {}", proposed_by, proposals.len(), test_run_id);
+        Ok(proposals)
+    }
+
+    /// Baseline proposals awaiting review
+    pub fn pending_baseline_proposals(&self) -> Vec<BaselineProposal> {
+        self.baseline_manager.pending_proposals().into_iter().cloned().collect()
+    }
+
+    /// Approve a baseline proposal, activating it for its environment
+    pub async fn approve_baseline(&mut self, proposal_id: Uuid, approver: &str, comment: Option<String>) -> Result<BaselineRevision> {
+        let revision = self.baseline_manager.approve(proposal_id, approver, comment)?;
+        
+        if let Some(proposal) = self.baseline_manager.proposal(proposal_id) {
+            self.db.store_baseline_proposal(proposal).await?;
+        }
+        self.activate_baseline_revision(&revision).await?;
+        
+        log::info!("Baseline {}/{} revision {} approved by {}", 
+                  revision.key.component, revision.key.test_name, revision.revision, approver);
+        Ok(revision)
+    }
+
+    /// Reject a baseline proposal
+    pub async fn reject_baseline(&mut self, proposal_id: Uuid, reviewer: &str, reason: &str) -> Result<()> {
+        let proposal = self.baseline_manager.reject(proposal_id, reviewer, reason)?;
+        self.db.store_baseline_proposal(&proposal).await
+    }
+
+    /// Roll a baseline back to an earlier revision, or the previous one when `to_revision` is `None`
+    pub async fn rollback_baseline(&mut self, key: &BaselineKey, to_revision: Option<u32>, requested_by: &str) -> Result<BaselineRevision> {
+        let revision = self.baseline_manager.rollback(key, to_revision, requested_by)?;
+        self.activate_baseline_revision(&revision).await?;
+        Ok(revision)
+    }
+
+    /// Revision history of a baseline, oldest first
+    pub fn baseline_history(&self, key: &BaselineKey) -> Vec<BaselineRevision> {
+        self.baseline_manager.history(key).to_vec()
+    }
+
+    /// Record a revision and make its baseline the one detection compares against
+    async fn activate_baseline_revision(&mut self, revision: &BaselineRevision) -> Result<()> {
+        self.db.store_baseline_revision(revision).await?;
+        self.baseline_store.store_baseline(&self.db, revision.baseline.clone()).await
+    }
+
     /// Run a complete regression test suite
     pub async fn run_regression_suite(&mut self, suite_config: &TestSuiteConfig) -> Result<TestSuiteResult> {
         log::info!("Running regression test suite: {}", suite_config.name);
@@ -438,6 +635,10 @@ impl RegressionTestingSystem {
         let start_time = Utc::now();
         let test_run_id = Uuid::new_v4().to_string();
         
+        // Remember the change range so detected regressions can be bisected
+        self.recent_code_changes = suite_config.recent_code_changes.clone();
+        self.suppressed_failures.clear();
+        
         // Initialize test suite result
         let mut suite_result = TestSuiteResult {
             id: Uuid::new_v4(),
@@ -450,6 +651,7 @@ impl RegressionTestingSystem {
             failed_tests: 0,
             skipped_tests: 0,
             regressions_detected: Vec::new(),
+            quarantined_tests: Vec::new(),
             summary: HashMap::new(),
         };
         
@@ -473,9 +675,44 @@ impl RegressionTestingSystem {
         
         suite_result.end_time = Utc::now();
         
+        // Report quarantine status, including failures that were not gated
+        suite_result.quarantined_tests = self.flakiness_analyzer.quarantined_tests();
+        for status in &mut suite_result.quarantined_tests {
+            status.suppressed_failures = self.suppressed_failures.get(&status.test_name).copied().unwrap_or(0);
+        }
+        
         // Generate and store test suite report
         self.report_generator.generate_suite_report(&suite_result).await?;
         
+        // Write HTML/Markdown reports with trend charts for the affected components
+        let mut trends = Vec::new();
+        let mut components: Vec<&str> = suite_result.regressions_detected
+            .iter()
+            .map(|r| r.component.as_str())
+            .collect();
+        components.sort();
+        components.dedup();
+        for component in components {
+            match self.db.get_component_trends(component, start_time - chrono::Duration::days(30), suite_result.end_time).await {
+                Ok(component_trends) => trends.extend(component_trends),
+                Err(e) => log::warn!("Failed to load trends for {}: {}", component, e),
+            }
+        }
+        let report = self.report_generator.write_suite_report(&suite_result, &trends).await?;
+        
+        // Alerts are sent once the report exists so it can be attached
+        let attachments = match report.attachments().await {
+            Ok(attachments) => attachments,
+            Err(e) => {
+                log::warn!("Failed to load report attachments: {}", e);
+                Vec::new()
+            }
+        };
+        for regression in std::mem::take(&mut self.pending_alerts) {
+            self.trigger_alert(&regression, &attachments).await?;
+        }
+        self.last_report = Some(report);
+        
         log::info!("Regression test suite completed: {} passed, {} failed", 
                   suite_result.passed_tests, suite_result.failed_tests);
         
@@ -518,16 +755,21 @@ impl RegressionTestingSystem {
         
         let mut result = TestSuiteResult::new_functional("Functional Tests");
         
-        // Execute functional tests
-        let test_results = self.functional_detector.run_functional_tests(config).await?;
+        // Execute functional tests across the worker pool
+        let invocations = config.functional_test_suites
+            .iter()
+            .map(|suite| TestInvocation::functional(suite, "functional"))
+            .collect();
+        let execution = self.executor.execute(invocations).await;
+        Self::record_execution_summary(&mut result, &execution);
         
         // Analyze results for regressions
-        for test_result in test_results {
+        for test_result in execution.results {
             self.handle_test_result(&test_result).await?;
             
             match test_result.status {
                 TestStatus::Passed => result.passed_tests += 1,
-                TestStatus::Failed | TestStatus::Error => result.failed_tests += 1,
+                TestStatus::Failed | TestStatus::Error | TestStatus::Timeout => result.failed_tests += 1,
                 TestStatus::Skipped => result.skipped_tests += 1,
                 _ => {}
             }
@@ -537,6 +779,16 @@ impl RegressionTestingSystem {
         Ok(result)
     }
 
+    /// Add worker pool statistics to a suite result
+    fn record_execution_summary(result: &mut TestSuiteResult, execution: &executor::ExecutionSummary) {
+        result.total_tests += execution.results.len();
+        result.summary.insert("execution_wall_time_ms".to_string(), execution.wall_time_ms as f64);
+        result.summary.insert("execution_retries".to_string(), execution.retries as f64);
+        for (worker, count) in &execution.tests_per_worker {
+            result.summary.insert(format!("tests_on_worker_{}", worker), *count as f64);
+        }
+    }
+
     /// Run targeted tests based on code changes
     async fn run_targeted_tests(&mut self, config: &TestSuiteConfig) -> Result<TestSuiteResult> {
         log::info!("Running targeted tests based on code changes");
@@ -548,14 +800,20 @@ impl RegressionTestingSystem {
         let mut result = TestSuiteResult::new_targeted("Targeted Tests");
         result.summary.insert("selected_tests_count".to_string(), selected_tests.len() as f64);
         
-        // Execute selected tests
-        for test_config in selected_tests {
-            let test_result = self.functional_detector.run_single_test(&test_config).await?;
+        // Execute selected tests across the worker pool
+        let invocations = selected_tests
+            .iter()
+            .map(|test| TestInvocation::functional(&test.test_name, &test.component))
+            .collect();
+        let execution = self.executor.execute(invocations).await;
+        Self::record_execution_summary(&mut result, &execution);
+        
+        for test_result in execution.results {
             self.handle_test_result(&test_result).await?;
             
             match test_result.status {
                 TestStatus::Passed => result.passed_tests += 1,
-                TestStatus::Failed | TestStatus::Error => result.failed_tests += 1,
+                TestStatus::Failed | TestStatus::Error | TestStatus::Timeout => result.failed_tests += 1,
                 _ => {}
             }
         }
@@ -566,9 +824,26 @@ impl RegressionTestingSystem {
 
     /// Collect performance measurements
     async fn collect_performance_measurements(&self, config: &TestSuiteConfig) -> Result<Vec<PerformanceMeasurement>> {
-        // TODO: Implement performance measurement collection
-        // This would integrate with the existing benchmarking framework
-        Ok(Vec::new())
+        let harness = match &self.multios_harness {
+            Some(harness) => harness,
+            None => {
+                log::debug!("No MultiOS benchmark harness configured");
+                return Ok(Vec::new());
+            }
+        };
+        
+        // An empty benchmark list runs every configured MultiOS benchmark
+        let selected: Vec<MultiOsBenchmark> = if config.performance_benchmarks.is_empty() {
+            harness.benchmarks()
+        } else {
+            config.performance_benchmarks
+                .iter()
+                .filter_map(|name| MultiOsBenchmark::from_name(name))
+                .collect()
+        };
+        
+        harness.run_selected(&selected).await
+            .context("Failed to run MultiOS benchmarks")
     }
 
     /// Handle detected regression
@@ -579,9 +854,9 @@ impl RegressionTestingSystem {
         // Store regression in database
         self.db.store_regression(&regression).await?;
         
-        // Trigger alert if configured
+        // Queue alert if configured; delivered with the suite report
         if self.should_trigger_alert(&regression) {
-            self.trigger_alert(&regression).await?;
+            self.pending_alerts.push(regression.clone());
         }
         
         // Perform root cause analysis
@@ -620,22 +895,43 @@ impl RegressionTestingSystem {
     }
 
     /// Trigger alert for regression
-    async fn trigger_alert(&self, regression: &DetectedRegression) -> Result<()> {
-        // TODO: Implement alert triggering
-        // Email, Slack, webhook notifications
-        
+    async fn trigger_alert(&self, regression: &DetectedRegression, attachments: &[ReportAttachment]) -> Result<()> {
         log::info!("Alert triggered for regression: {} in {}", 
                   regression.component, regression.test_name);
         
+        let delivery = self.alert_dispatcher.dispatch(regression, attachments).await;
+        if !delivery.failed.is_empty() {
+            log::error!("Alert for regression {} failed on {} of {} channels", 
+                       regression.id, delivery.failed.len(), delivery.failed.len() + delivery.delivered.len());
+        }
+        
         Ok(())
     }
 
+    /// Escalate unacknowledged alerts whose escalation delay has elapsed
+    pub async fn process_alert_escalations(&self) -> usize {
+        self.alert_dispatcher.process_escalations().await
+    }
+
+    /// Acknowledge a regression alert, stopping its escalation
+    pub async fn acknowledge_regression_alert(&self, regression_id: Uuid) -> bool {
+        self.alert_dispatcher.acknowledge(regression_id).await
+    }
+
     /// Perform root cause analysis for regression
     async fn perform_root_cause_analysis(&self, regression: &DetectedRegression) -> Result<Option<RootCauseAnalysis>> {
-        // TODO: Implement root cause analysis logic
-        // This would analyze code changes, dependencies, configuration, etc.
+        let bisector = match &self.bisector {
+            Some(bisector) => bisector,
+            None => return Ok(None),
+        };
+        
+        if self.recent_code_changes.len() < 2 {
+            log::debug!("No commit range available to bisect regression {}", regression.id);
+            return Ok(None);
+        }
         
-        Ok(None)
+        bisector.bisect(regression, &self.recent_code_changes).await
+            .context("Failed to bisect regression")
     }
 
     /// Handle test result
@@ -643,6 +939,14 @@ impl RegressionTestingSystem {
         // Store test result in database
         self.db.store_test_result(test_result).await?;
         
+        // Quarantined tests still run, but their failures do not gate the suite
+        let quarantined = self.flakiness_analyzer.record(test_result);
+        if quarantined && test_result.status == TestStatus::Failed {
+            log::info!("Ignoring failure of quarantined flaky test {}", test_result.test_name);
+            *self.suppressed_failures.entry(test_result.test_name.clone()).or_insert(0) += 1;
+            return Ok(());
+        }
+        
         // Check for functional regressions
         if test_result.status == TestStatus::Failed {
             let regression = DetectedRegression {
@@ -706,6 +1010,8 @@ pub struct TestSuiteResult {
     pub failed_tests: usize,
     pub skipped_tests: usize,
     pub regressions_detected: Vec<DetectedRegression>,
+    #[serde(default)]
+    pub quarantined_tests: Vec<QuarantineStatus>,
     pub summary: HashMap<String, f64>,
 }
 
@@ -722,6 +1028,7 @@ impl TestSuiteResult {
             failed_tests: 0,
             skipped_tests: 0,
             regressions_detected: Vec::new(),
+            quarantined_tests: Vec::new(),
             summary: HashMap::new(),
         }
     }
@@ -740,6 +1047,7 @@ impl TestSuiteResult {
         self.failed_tests += other.failed_tests;
         self.skipped_tests += other.skipped_tests;
         self.regressions_detected.extend(other.regressions_detected);
+        self.quarantined_tests.extend(other.quarantined_tests);
         
         for (key, value) in other.summary {
             self.summary.insert(key, value);
@@ -756,6 +1064,9 @@ pub struct CodeChange {
     pub files_changed: Vec<String>,
     pub timestamp: DateTime<Utc>,
     pub change_type: String,
+    /// Changed functions by file, when known; narrows coverage-based selection
+    #[serde(default)]
+    pub changed_functions: HashMap<String, Vec<String>>,
 }
 
 /// Performance measurement
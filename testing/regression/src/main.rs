@@ -109,6 +109,27 @@ enum Commands {
     
     /// Run the scheduler
     Schedule,
+    
+    /// Export the full dataset to a JSON file
+    Export {
+        /// Output file path
+        #[arg(short, long)]
+        output: String,
+    },
+    
+    /// Import a dataset exported from any storage backend
+    Import {
+        /// Input file path
+        #[arg(short, long)]
+        input: String,
+    },
+    
+    /// Delete raw data older than the retention window
+    Prune {
+        /// Retention window in days
+        #[arg(short, long, default_value_t = 90)]
+        days: u32,
+    },
 }
 
 #[tokio::main]
@@ -206,6 +227,23 @@ async fn main() -> Result<()> {
             let scheduler = TestScheduler::new(&config).await?;
             scheduler.start().await?;
         }
+        
+        Commands::Export { output } => {
+            db_manager.initialize_schema().await?;
+            let records = db_manager.export_to_file(std::path::Path::new(&output)).await?;
+            info!("Exported {} records from {} to {}", records, db_manager.backend_name(), output);
+        }
+        
+        Commands::Import { input } => {
+            db_manager.initialize_schema().await?;
+            let records = db_manager.import_from_file(std::path::Path::new(&input)).await?;
+            info!("Imported {} records into {}", records, db_manager.backend_name());
+        }
+        
+        Commands::Prune { days } => {
+            let deleted = db_manager.cleanup_old_data(days).await?;
+            info!("Pruned {} records older than {} days", deleted, days);
+        }
     }
     
     info!("MultiOS Automated Regression Testing System completed");
@@ -240,6 +278,8 @@ async fn load_config(cli: &Cli) -> Result<RegressionConfig> {
                 end_hour: 7,
                 timezone: "UTC".to_string(),
             },
+            webhooks: Vec::new(),
+            retry: regression_testing::alerts::RetryPolicy::default(),
         },
         performance_thresholds: PerformanceThresholds {
             latency_regression_pct: 10.0,
@@ -249,6 +289,8 @@ async fn load_config(cli: &Cli) -> Result<RegressionConfig> {
             confidence_threshold: 80.0,
             sample_size_minimum: 10,
             outlier_detection_sigma: 2.0,
+            detection_algorithm: regression_testing::changepoint::DetectionAlgorithm::Threshold,
+            change_point: regression_testing::changepoint::ChangePointConfig::default(),
         },
         scheduling_config: regression_testing::SchedulingConfig {
             test_frequency_hours: 4,
@@ -0,0 +1,548 @@
+//! Continuous Monitoring Module
+//!
+//! Runs the background collection loop behind continuous monitoring. Each tick
+//! scrapes the configured monitoring and benchmark integrations, compares a
+//! sliding window of recent samples against the stored (or rolling) baseline,
+//! and hands measurements and detected regressions to a bounded persistence
+//! queue so a slow database never stalls collection.
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+
+use crate::{
+    database::{DatabaseManager, PerformanceBaseline},
+    detectors::PerformanceDetector,
+    integration::{BenchmarkIntegrator, BenchmarkResult, MonitoringIntegrator, ScrapeQuery},
+    DetectedRegression, PerformanceMeasurement, TestEnvironment, Uuid,
+};
+
+/// Continuous monitoring loop configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MonitoringLoopConfig {
+    /// Seconds between collection ticks
+    pub collection_interval_secs: u64,
+    /// Components polled from the benchmark API each tick
+    pub components: Vec<String>,
+    /// Instant queries scraped from the monitoring system each tick
+    pub scrape_queries: Vec<ScrapeQuery>,
+    /// Samples retained per component/metric; the older half forms the rolling baseline
+    pub window_size: usize,
+    /// Jobs the persistence queue holds before collection starts shedding measurements
+    pub persist_queue_capacity: usize,
+    /// Regressions held back while the persistence queue is full
+    pub pending_regression_limit: usize,
+}
+
+impl Default for MonitoringLoopConfig {
+    fn default() -> Self {
+        Self {
+            collection_interval_secs: 60,
+            components: Vec::new(),
+            scrape_queries: Vec::new(),
+            window_size: 40,
+            persist_queue_capacity: 64,
+            pending_regression_limit: 256,
+        }
+    }
+}
+
+/// Counters describing the monitoring loop
+#[derive(Debug, Default)]
+pub struct MonitorStats {
+    collections: AtomicU64,
+    collection_errors: AtomicU64,
+    measurements_collected: AtomicU64,
+    measurements_dropped: AtomicU64,
+    regressions_detected: AtomicU64,
+    regressions_dropped: AtomicU64,
+    persist_failures: AtomicU64,
+}
+
+/// Point-in-time copy of the monitoring loop counters
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MonitorStatsSnapshot {
+    pub collections: u64,
+    pub collection_errors: u64,
+    pub measurements_collected: u64,
+    pub measurements_dropped: u64,
+    pub regressions_detected: u64,
+    pub regressions_dropped: u64,
+    pub persist_failures: u64,
+}
+
+impl MonitorStats {
+    /// Take a snapshot of the current counters
+    pub fn snapshot(&self) -> MonitorStatsSnapshot {
+        MonitorStatsSnapshot {
+            collections: self.collections.load(Ordering::Relaxed),
+            collection_errors: self.collection_errors.load(Ordering::Relaxed),
+            measurements_collected: self.measurements_collected.load(Ordering::Relaxed),
+            measurements_dropped: self.measurements_dropped.load(Ordering::Relaxed),
+            regressions_detected: self.regressions_detected.load(Ordering::Relaxed),
+            regressions_dropped: self.regressions_dropped.load(Ordering::Relaxed),
+            persist_failures: self.persist_failures.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Work handed from the collector to the database writer
+#[derive(Debug)]
+enum PersistJob {
+    Measurements(Vec<PerformanceMeasurement>),
+    Regression(DetectedRegression),
+}
+
+/// Bounded window of recent samples for one component/metric pair
+#[derive(Debug)]
+struct SlidingWindow {
+    samples: VecDeque<PerformanceMeasurement>,
+    capacity: usize,
+}
+
+impl SlidingWindow {
+    fn new(capacity: usize) -> Self {
+        Self {
+            samples: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    fn push(&mut self, measurement: PerformanceMeasurement) {
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(measurement);
+    }
+
+    fn is_full(&self) -> bool {
+        self.samples.len() >= self.capacity
+    }
+
+    /// Split into the older reference half and the recent half
+    fn halves(&self) -> (Vec<&PerformanceMeasurement>, Vec<PerformanceMeasurement>) {
+        let split = self.samples.len() / 2;
+        let reference = self.samples.iter().take(split).collect();
+        let recent = self.samples.iter().skip(split).cloned().collect();
+        (reference, recent)
+    }
+
+    fn clear(&mut self) {
+        self.samples.clear();
+    }
+}
+
+/// Continuous monitor driving periodic collection and regression detection
+pub struct ContinuousMonitor {
+    config: MonitoringLoopConfig,
+    monitoring: MonitoringIntegrator,
+    benchmark: BenchmarkIntegrator,
+    detector: PerformanceDetector,
+    /// Approved baselines; take precedence over the rolling window baseline
+    stored_baselines: Vec<PerformanceBaseline>,
+    windows: HashMap<(String, String), SlidingWindow>,
+    /// Regressions waiting for room in the persistence queue
+    pending_regressions: VecDeque<DetectedRegression>,
+    last_collection: DateTime<Utc>,
+    stats: Arc<MonitorStats>,
+}
+
+/// Handle to a running monitoring loop
+pub struct MonitorHandle {
+    shutdown: watch::Sender<bool>,
+    collector: JoinHandle<()>,
+    writer: JoinHandle<()>,
+    stats: Arc<MonitorStats>,
+}
+
+impl MonitorHandle {
+    /// Current loop counters
+    pub fn stats(&self) -> MonitorStatsSnapshot {
+        self.stats.snapshot()
+    }
+
+    /// Stop collection and wait for queued writes to drain
+    pub async fn stop(self) -> Result<()> {
+        let _ = self.shutdown.send(true);
+        self.collector.await?;
+        // The writer exits once the collector drops its sender
+        self.writer.await?;
+        info!("Continuous monitoring stopped");
+        Ok(())
+    }
+}
+
+impl ContinuousMonitor {
+    /// Create new continuous monitor
+    pub fn new(
+        config: MonitoringLoopConfig,
+        monitoring: MonitoringIntegrator,
+        benchmark: BenchmarkIntegrator,
+        detector: PerformanceDetector,
+        stored_baselines: Vec<PerformanceBaseline>,
+    ) -> Self {
+        Self {
+            config,
+            monitoring,
+            benchmark,
+            detector,
+            stored_baselines,
+            windows: HashMap::new(),
+            pending_regressions: VecDeque::new(),
+            last_collection: Utc::now(),
+            stats: Arc::new(MonitorStats::default()),
+        }
+    }
+
+    /// Spawn the collector and database writer tasks
+    pub fn spawn(self, db: Arc<DatabaseManager>) -> MonitorHandle {
+        let (tx, rx) = mpsc::channel(self.config.persist_queue_capacity.max(1));
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let stats = self.stats.clone();
+
+        let writer = tokio::spawn(persist_loop(db, rx, stats.clone()));
+        let collector = tokio::spawn(self.run(tx, shutdown_rx));
+
+        MonitorHandle {
+            shutdown: shutdown_tx,
+            collector,
+            writer,
+            stats,
+        }
+    }
+
+    /// Collection loop; runs until shutdown is signalled
+    async fn run(mut self, tx: mpsc::Sender<PersistJob>, mut shutdown: watch::Receiver<bool>) {
+        info!(
+            "Continuous monitoring running every {}s over {} queries and {} components",
+            self.config.collection_interval_secs,
+            self.config.scrape_queries.len(),
+            self.config.components.len()
+        );
+
+        let mut ticker = tokio::time::interval(Duration::from_secs(self.config.collection_interval_secs.max(1)));
+        // A slow tick should not be followed by a burst of catch-up scrapes
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {
+                    if let Err(e) = self.tick(&tx).await {
+                        self.stats.collection_errors.fetch_add(1, Ordering::Relaxed);
+                        warn!("Continuous monitoring tick failed: {}", e);
+                    }
+                }
+                _ = shutdown.changed() => break,
+            }
+        }
+
+        // Hand any held-back regressions to the writer before exiting
+        while let Some(regression) = self.pending_regressions.pop_front() {
+            if tx.send(PersistJob::Regression(regression)).await.is_err() {
+                break;
+            }
+        }
+    }
+
+    /// Run a single collect/compare/enqueue pass
+    async fn tick(&mut self, tx: &mpsc::Sender<PersistJob>) -> Result<()> {
+        let measurements = self.collect().await;
+        self.stats.collections.fetch_add(1, Ordering::Relaxed);
+        self.stats
+            .measurements_collected
+            .fetch_add(measurements.len() as u64, Ordering::Relaxed);
+
+        let regressions = self.evaluate(&measurements).await?;
+        self.stats
+            .regressions_detected
+            .fetch_add(regressions.len() as u64, Ordering::Relaxed);
+
+        self.enqueue(tx, measurements, regressions);
+        Ok(())
+    }
+
+    /// Collect measurements from every configured integration
+    async fn collect(&mut self) -> Vec<PerformanceMeasurement> {
+        let now = Utc::now();
+        let time_range = (self.last_collection, now);
+        self.last_collection = now;
+
+        let mut measurements = Vec::new();
+
+        if !self.config.scrape_queries.is_empty() {
+            match self.monitoring.scrape_measurements(&self.config.scrape_queries).await {
+                Ok(scraped) => measurements.extend(scraped),
+                Err(e) => {
+                    self.stats.collection_errors.fetch_add(1, Ordering::Relaxed);
+                    warn!("Monitoring scrape failed: {}", e);
+                }
+            }
+        }
+
+        for component in &self.config.components {
+            match self.benchmark.fetch_benchmark_results(component, time_range).await {
+                Ok(results) => measurements.extend(results.into_iter().map(benchmark_to_measurement)),
+                Err(e) => {
+                    self.stats.collection_errors.fetch_add(1, Ordering::Relaxed);
+                    warn!("Benchmark fetch failed for {}: {}", component, e);
+                }
+            }
+        }
+
+        // Each tick queries a fresh time range, so cached entries are never hit again
+        self.benchmark.clear_cache();
+
+        debug!("Collected {} measurements", measurements.len());
+        measurements
+    }
+
+    /// Feed measurements into the sliding windows and compare full windows against their baseline
+    async fn evaluate(&mut self, measurements: &[PerformanceMeasurement]) -> Result<Vec<DetectedRegression>> {
+        let mut touched = HashSet::new();
+
+        for measurement in measurements {
+            let key = (measurement.component.clone(), measurement.metric_type.clone());
+            let window_size = self.config.window_size.max(2);
+            self.windows
+                .entry(key.clone())
+                .or_insert_with(|| SlidingWindow::new(window_size))
+                .push(measurement.clone());
+            touched.insert(key);
+        }
+
+        let mut regressions = Vec::new();
+
+        for key in touched {
+            let window = match self.windows.get_mut(&key) {
+                Some(window) if window.is_full() => window,
+                _ => continue,
+            };
+
+            let (mut found, baseline_source, samples) = if self.detector.uses_change_point() {
+                // Change-point algorithms locate the shift within the whole window themselves
+                let samples: Vec<PerformanceMeasurement> = window.samples.iter().cloned().collect();
+                (self.detector.detect_regressions(&samples, &[]).await?, "change_point", samples.len())
+            } else {
+                let (reference, recent) = window.halves();
+                let (baseline, baseline_source) = match find_stored_baseline(&self.stored_baselines, &key.0, &key.1) {
+                    Some(baseline) => (baseline.clone(), "stored"),
+                    None => (rolling_baseline(&key.0, &key.1, &reference), "rolling_window"),
+                };
+                (self.detector.detect_regressions(&recent, &[baseline]).await?, baseline_source, recent.len())
+            };
+            if found.is_empty() {
+                continue;
+            }
+
+            for regression in &mut found {
+                regression.test_run_id = "continuous_monitoring".to_string();
+                regression
+                    .metadata
+                    .insert("source".to_string(), serde_json::json!("continuous_sliding_window"));
+                regression
+                    .metadata
+                    .insert("baseline_source".to_string(), serde_json::json!(baseline_source));
+                regression
+                    .metadata
+                    .insert("window_size".to_string(), serde_json::json!(samples));
+            }
+
+            // Start a fresh window so one shift is reported once rather than every tick
+            window.clear();
+            regressions.extend(found);
+        }
+
+        Ok(regressions)
+    }
+
+    /// Hand results to the writer without ever waiting on it
+    fn enqueue(
+        &mut self,
+        tx: &mpsc::Sender<PersistJob>,
+        measurements: Vec<PerformanceMeasurement>,
+        regressions: Vec<DetectedRegression>,
+    ) {
+        // Regressions go first and are held back rather than dropped when the queue is full
+        self.pending_regressions.extend(regressions);
+        while let Some(regression) = self.pending_regressions.pop_front() {
+            match tx.try_send(PersistJob::Regression(regression)) {
+                Ok(()) => {}
+                Err(TrySendError::Full(PersistJob::Regression(regression))) => {
+                    self.pending_regressions.push_front(regression);
+                    break;
+                }
+                Err(TrySendError::Full(_)) => break,
+                Err(TrySendError::Closed(_)) => {
+                    error!("Persistence queue closed, discarding regressions");
+                    break;
+                }
+            }
+        }
+
+        while self.pending_regressions.len() > self.config.pending_regression_limit {
+            if let Some(dropped) = self.pending_regressions.pop_front() {
+                self.stats.regressions_dropped.fetch_add(1, Ordering::Relaxed);
+                warn!("Dropping held regression for {} after persistence backlog", dropped.component);
+            }
+        }
+
+        if measurements.is_empty() {
+            return;
+        }
+
+        let count = measurements.len() as u64;
+        match tx.try_send(PersistJob::Measurements(measurements)) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                self.stats.measurements_dropped.fetch_add(count, Ordering::Relaxed);
+                warn!("Persistence queue full, shedding {} measurements", count);
+            }
+            Err(TrySendError::Closed(_)) => {
+                self.stats.measurements_dropped.fetch_add(count, Ordering::Relaxed);
+                error!("Persistence queue closed, discarding {} measurements", count);
+            }
+        }
+    }
+}
+
+/// Drain the persistence queue into the database
+async fn persist_loop(db: Arc<DatabaseManager>, mut rx: mpsc::Receiver<PersistJob>, stats: Arc<MonitorStats>) {
+    while let Some(job) = rx.recv().await {
+        if let Err(e) = persist_job(&db, &job).await {
+            stats.persist_failures.fetch_add(1, Ordering::Relaxed);
+            warn!("Failed to persist monitoring data: {}", e);
+        }
+    }
+
+    debug!("Monitoring persistence queue drained");
+}
+
+/// Write a single job to the database
+async fn persist_job(db: &DatabaseManager, job: &PersistJob) -> Result<()> {
+    match job {
+        PersistJob::Measurements(measurements) => {
+            for measurement in measurements {
+                db.store_performance_measurement(measurement).await?;
+            }
+        }
+        PersistJob::Regression(regression) => {
+            db.store_regression(regression).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Find an active stored baseline for exactly this component and metric
+fn find_stored_baseline<'a>(
+    baselines: &'a [PerformanceBaseline],
+    component: &str,
+    metric_type: &str,
+) -> Option<&'a PerformanceBaseline> {
+    baselines
+        .iter()
+        .find(|b| b.is_active && b.component == component && b.metric_type == metric_type)
+}
+
+/// Build a baseline from the older half of a sliding window
+fn rolling_baseline(component: &str, metric_type: &str, reference: &[&PerformanceMeasurement]) -> PerformanceBaseline {
+    let count = reference.len().max(1) as f64;
+    let mean = reference.iter().map(|m| m.value).sum::<f64>() / count;
+    let variance = reference.iter().map(|m| (m.value - mean).powi(2)).sum::<f64>() / count;
+    let first = reference.first();
+    let now = Utc::now();
+
+    PerformanceBaseline {
+        test_name: first.map(|m| m.test_name.clone()).unwrap_or_default(),
+        component: component.to_string(),
+        metric_type: metric_type.to_string(),
+        baseline_value: mean,
+        confidence_interval: Some(variance.sqrt()),
+        sample_count: reference.len() as i32,
+        measurement_unit: first.map(|m| m.unit.clone()).unwrap_or_default(),
+        test_environment_hash: first.map(|m| m.environment.environment_hash.clone()).unwrap_or_default(),
+        created_at: first.map(|m| m.timestamp).unwrap_or(now),
+        updated_at: now,
+        metadata: HashMap::new(),
+        is_active: true,
+    }
+}
+
+/// Convert an external benchmark result into a performance measurement
+fn benchmark_to_measurement(result: BenchmarkResult) -> PerformanceMeasurement {
+    PerformanceMeasurement {
+        id: Uuid::new_v4(),
+        test_name: result.test_name,
+        component: result.component,
+        metric_type: result.metric_type,
+        value: result.value,
+        unit: result.unit,
+        test_run_id: result.id,
+        timestamp: result.timestamp,
+        environment: TestEnvironment {
+            name: result.environment.clone(),
+            hardware_config: HashMap::new(),
+            software_config: HashMap::new(),
+            environment_hash: result.environment,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn measurement(value: f64) -> PerformanceMeasurement {
+        PerformanceMeasurement {
+            id: Uuid::new_v4(),
+            test_name: "sched_latency".to_string(),
+            component: "scheduler".to_string(),
+            metric_type: "latency".to_string(),
+            value,
+            unit: "us".to_string(),
+            test_run_id: "test".to_string(),
+            timestamp: Utc::now(),
+            environment: TestEnvironment {
+                name: "ci".to_string(),
+                hardware_config: HashMap::new(),
+                software_config: HashMap::new(),
+                environment_hash: "ci".to_string(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_sliding_window_evicts_oldest() {
+        let mut window = SlidingWindow::new(4);
+        for value in 0..6 {
+            window.push(measurement(value as f64));
+        }
+
+        assert!(window.is_full());
+        let (reference, recent) = window.halves();
+        assert_eq!(reference.iter().map(|m| m.value).collect::<Vec<_>>(), vec![2.0, 3.0]);
+        assert_eq!(recent.iter().map(|m| m.value).collect::<Vec<_>>(), vec![4.0, 5.0]);
+    }
+
+    #[test]
+    fn test_rolling_baseline_uses_reference_mean() {
+        let samples = vec![measurement(10.0), measurement(20.0), measurement(30.0)];
+        let reference: Vec<&PerformanceMeasurement> = samples.iter().collect();
+        let baseline = rolling_baseline("scheduler", "latency", &reference);
+
+        assert_eq!(baseline.baseline_value, 20.0);
+        assert_eq!(baseline.sample_count, 3);
+        assert_eq!(baseline.measurement_unit, "us");
+        assert!(baseline.is_active);
+    }
+}
@@ -3,17 +3,22 @@
 //! Provides comprehensive reporting capabilities for regression testing results,
 //! including trend analysis, performance reports, and executive summaries.
 
-use anyhow::{Result};
+use anyhow::{Context, Result};
 use chrono::{DateTime, Duration, Utc};
 use log::{info, debug};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 
 use crate::{
     DatabaseManager, DetectedRegression, RegressionSeverity, RegressionType, TestSuiteResult,
-    TrendAnalysisResult, Uuid,
+    TrendAnalysisResult, TrendData, Uuid,
 };
 
+pub mod render;
+
+use render::{RenderPalette, ReportView};
+
 /// Report generator for creating comprehensive test reports
 #[derive(Debug, Clone)]
 pub struct ReportGenerator {
@@ -33,6 +38,55 @@ pub struct ReportConfig {
     pub executive_summary_enabled: bool,
     pub detail_level: DetailLevel,
     pub branding: ReportBranding,
+    #[serde(default = "default_output_directory")]
+    pub output_directory: PathBuf,
+}
+
+fn default_output_directory() -> PathBuf {
+    PathBuf::from("reports")
+}
+
+/// Report files written for one suite run
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WrittenReport {
+    pub html_path: Option<PathBuf>,
+    pub markdown_path: Option<PathBuf>,
+    pub chart_paths: Vec<PathBuf>,
+}
+
+/// A report file prepared for attaching to an email
+#[derive(Debug, Clone)]
+pub struct ReportAttachment {
+    pub filename: String,
+    pub content_type: String,
+    pub data: Vec<u8>,
+}
+
+impl WrittenReport {
+    /// Load the written report files as email attachments
+    pub async fn attachments(&self) -> Result<Vec<ReportAttachment>> {
+        let mut attachments = Vec::new();
+        
+        let files = [
+            (self.html_path.as_ref(), "text/html; charset=utf-8"),
+            (self.markdown_path.as_ref(), "text/markdown; charset=utf-8"),
+        ];
+        for (path, content_type) in files {
+            if let Some(path) = path {
+                attachments.push(ReportAttachment {
+                    filename: path
+                        .file_name()
+                        .map(|name| name.to_string_lossy().into_owned())
+                        .unwrap_or_else(|| "report".to_string()),
+                    content_type: content_type.to_string(),
+                    data: tokio::fs::read(path).await
+                        .with_context(|| format!("Failed to read report {}", path.display()))?,
+                });
+            }
+        }
+        
+        Ok(attachments)
+    }
 }
 
 /// Output formats for reports
@@ -509,7 +563,7 @@ impl ReportGenerator {
     /// Create new report generator
     pub fn new() -> Self {
         let config = ReportConfig {
-            output_formats: vec![OutputFormat::HTML, OutputFormat::PDF],
+            output_formats: vec![OutputFormat::HTML, OutputFormat::Markdown, OutputFormat::PDF],
             include_charts: true,
             include_trends: true,
             include_recommendations: true,
@@ -527,6 +581,7 @@ impl ReportGenerator {
                 },
                 contact_info: "regression-testing@multios.com".to_string(),
             },
+            output_directory: default_output_directory(),
         };
         
         Self {
@@ -624,6 +679,86 @@ impl ReportGenerator {
         self.format_report(&report_content)
     }
 
+    /// Override the `suite_html` or `suite_markdown` page template
+    pub fn register_template(&mut self, name: &str, template: String) {
+        self.template_engine.custom_templates.insert(name.to_string(), template);
+    }
+
+    /// Render the suite report as HTML and/or Markdown with SVG trend charts
+    /// and write it to the configured output directory
+    pub async fn write_suite_report(
+        &self,
+        suite_result: &TestSuiteResult,
+        trends: &[TrendData],
+    ) -> Result<WrittenReport> {
+        let generated_at = Utc::now();
+        let view = ReportView {
+            title: format!("{} Regression Report: {}", self.config.branding.company_name, suite_result.suite_name),
+            company: self.config.branding.company_name.clone(),
+            generated_at,
+            suite: suite_result,
+            trends: if self.config.include_charts { trends } else { &[] },
+        };
+        let colors = &self.config.branding.color_scheme;
+        let palette = RenderPalette {
+            primary: colors.secondary_color.clone(),
+            accent: colors.accent_color.clone(),
+            text: colors.text_color.clone(),
+            background: colors.background_color.clone(),
+        };
+        let charts = render::render_charts(&view, &palette);
+        
+        let stem = crate::utils::sanitize_filename(&format!(
+            "{}_{}",
+            suite_result.suite_name.replace(' ', "_"),
+            generated_at.format("%Y%m%dT%H%M%SZ")
+        ));
+        let directory = self.config.output_directory.as_path();
+        tokio::fs::create_dir_all(directory).await
+            .with_context(|| format!("Failed to create report directory {}", directory.display()))?;
+        
+        let mut written = WrittenReport {
+            html_path: None,
+            markdown_path: None,
+            chart_paths: Vec::new(),
+        };
+        
+        if self.config.output_formats.iter().any(|f| matches!(f, OutputFormat::HTML)) {
+            let template = self.template("suite_html", render::HTML_TEMPLATE);
+            let html = render::render_html(&view, &charts, template, &self.generate_css_styles());
+            written.html_path = Some(write_report_file(directory, &format!("{}.html", stem), html.as_bytes()).await?);
+        }
+        
+        if self.config.output_formats.iter().any(|f| matches!(f, OutputFormat::Markdown)) {
+            // Markdown links charts as standalone SVG files next to the report
+            let chart_dir = format!("{}_charts", stem);
+            for chart in &charts {
+                let path = write_report_file(
+                    &directory.join(&chart_dir),
+                    &format!("{}.svg", chart.name),
+                    chart.svg.as_bytes(),
+                ).await?;
+                written.chart_paths.push(path);
+            }
+            
+            let template = self.template("suite_markdown", render::MARKDOWN_TEMPLATE);
+            let markdown = render::render_markdown(&view, &charts, &chart_dir, template);
+            written.markdown_path = Some(write_report_file(directory, &format!("{}.md", stem), markdown.as_bytes()).await?);
+        }
+        
+        info!("Wrote suite report for {} to {}", suite_result.suite_name, directory.display());
+        Ok(written)
+    }
+
+    /// Custom template registered under `name`, or the built-in default
+    fn template<'a>(&'a self, name: &str, default: &'a str) -> &'a str {
+        self.template_engine
+            .custom_templates
+            .get(name)
+            .map(String::as_str)
+            .unwrap_or(default)
+    }
+
     /// Generate daily summary report
     pub async fn generate_daily_summary(
         &self,
@@ -1207,6 +1342,31 @@ impl ReportGenerator {
             padding: 5px 0;
             border-bottom: 1px solid #eee;
         }}
+        table {{
+            border-collapse: collapse;
+            margin-bottom: 20px;
+        }}
+        th, td {{
+            padding: 6px 10px;
+            border-bottom: 1px solid #eee;
+            text-align: left;
+        }}
+        .severity {{
+            color: #ffffff;
+            padding: 2px 8px;
+            border-radius: 4px;
+        }}
+        .bar {{
+            height: 8px;
+            margin: 2px 0;
+            border-radius: 2px;
+        }}
+        .bar.baseline {{
+            background-color: #9ca3af;
+        }}
+        .chart {{
+            margin: 0 0 20px 0;
+        }}
         "#,
             text_color = self.config.branding.color_scheme.text_color,
             bg_color = self.config.branding.color_scheme.background_color,
@@ -1462,4 +1622,17 @@ struct KeyMetricsCollection {
     test_success_rate: f64,
     avg_response_time_ms: u64,
     system_availability: f64,
-}
\ No newline at end of file
+}
+
+/// Write one report file, creating its directory if needed
+async fn write_report_file(directory: &Path, filename: &str, contents: &[u8]) -> Result<PathBuf> {
+    tokio::fs::create_dir_all(directory).await
+        .with_context(|| format!("Failed to create directory {}", directory.display()))?;
+    
+    let path = directory.join(filename);
+    tokio::fs::write(&path, contents).await
+        .with_context(|| format!("Failed to write report {}", path.display()))?;
+    
+    debug!("Wrote report file {}", path.display());
+    Ok(path)
+}
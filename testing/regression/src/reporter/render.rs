@@ -0,0 +1,517 @@
+//! Report Rendering
+//!
+//! Templated HTML and Markdown rendering for suite reports: SVG trend charts,
+//! per-component regression tables with severity colour coding, and
+//! baseline-versus-current comparisons.
+
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+
+use crate::{DetectedRegression, RegressionSeverity, TestSuiteResult, TrendData};
+
+/// Default HTML page template; `{{name}}` placeholders are substituted
+pub const HTML_TEMPLATE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>{{title}}</title>
+<style>
+{{styles}}
+</style>
+</head>
+<body>
+<div class="report-header">
+<h1>{{title}}</h1>
+<p>Generated at {{generated_at}} by {{company}}</p>
+</div>
+<div class="executive-summary">
+<h2>Summary</h2>
+{{summary}}
+</div>
+<h2>Trends</h2>
+{{charts}}
+<h2>Regressions by Component</h2>
+{{tables}}
+<h2>Baseline vs Current</h2>
+{{comparisons}}
+</body>
+</html>
+"#;
+
+/// Default Markdown template; `{{name}}` placeholders are substituted
+pub const MARKDOWN_TEMPLATE: &str = "# {{title}}
+
+_Generated at {{generated_at}} by {{company}}_
+
+## Summary
+
+{{summary}}
+
+## Trends
+
+{{charts}}
+
+## Regressions by Component
+
+{{tables}}
+
+## Baseline vs Current
+
+{{comparisons}}
+";
+
+const CHART_WIDTH: f64 = 640.0;
+const CHART_HEIGHT: f64 = 220.0;
+const CHART_PADDING: f64 = 40.0;
+
+/// Colours used by the rendered report
+#[derive(Debug, Clone)]
+pub struct RenderPalette {
+    pub primary: String,
+    pub accent: String,
+    pub text: String,
+    pub background: String,
+}
+
+/// A rendered SVG trend chart
+#[derive(Debug, Clone)]
+pub struct RenderedChart {
+    /// File stem, unique within one report
+    pub name: String,
+    pub title: String,
+    pub svg: String,
+}
+
+/// Everything a report page is rendered from
+pub struct ReportView<'a> {
+    pub title: String,
+    pub company: String,
+    pub generated_at: DateTime<Utc>,
+    pub suite: &'a TestSuiteResult,
+    pub trends: &'a [TrendData],
+}
+
+/// Substitute `{{key}}` placeholders in `template`
+pub fn fill_template(template: &str, values: &[(&str, &str)]) -> String {
+    values.iter().fold(template.to_string(), |acc, (key, value)| {
+        acc.replace(&format!("{{{{{}}}}}", key), value)
+    })
+}
+
+/// Escape text for inclusion in HTML or SVG
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Escape pipes so cell text cannot break a Markdown table
+fn escape_markdown_cell(text: &str) -> String {
+    text.replace('|', "\\|").replace('\n', " ")
+}
+
+/// Colour associated with a regression severity
+pub fn severity_color(severity: &RegressionSeverity) -> &'static str {
+    match severity {
+        RegressionSeverity::Minor => "#eab308",
+        RegressionSeverity::Major => "#f97316",
+        RegressionSeverity::Critical => "#dc2626",
+        RegressionSeverity::Blocker => "#7f1d1d",
+    }
+}
+
+/// Marker used for severities in Markdown, where colour is unavailable
+fn severity_marker(severity: &RegressionSeverity) -> &'static str {
+    match severity {
+        RegressionSeverity::Minor => "🟡 Minor",
+        RegressionSeverity::Major => "🟠 Major",
+        RegressionSeverity::Critical => "🔴 Critical",
+        RegressionSeverity::Blocker => "⛔ Blocker",
+    }
+}
+
+/// Group regressions by component, most severe first within each group
+fn regressions_by_component(regressions: &[DetectedRegression]) -> BTreeMap<&str, Vec<&DetectedRegression>> {
+    let mut grouped: BTreeMap<&str, Vec<&DetectedRegression>> = BTreeMap::new();
+    for regression in regressions {
+        grouped.entry(regression.component.as_str()).or_default().push(regression);
+    }
+    for group in grouped.values_mut() {
+        group.sort_by(|a, b| b.severity.partial_cmp(&a.severity).unwrap_or(std::cmp::Ordering::Equal));
+    }
+    grouped
+}
+
+/// Render a line chart of a trend's time series, with the baseline dashed when known
+pub fn render_trend_chart(trend: &TrendData, baseline: Option<f64>, palette: &RenderPalette) -> Option<RenderedChart> {
+    if trend.time_series.len() < 2 {
+        return None;
+    }
+
+    let values = trend.time_series.iter().map(|(_, v)| *v).chain(baseline);
+    let (mut min, mut max) = values.fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), v| (lo.min(v), hi.max(v)));
+    if (max - min).abs() < f64::EPSILON {
+        min -= 1.0;
+        max += 1.0;
+    }
+
+    let first_time = trend.time_series[0].0;
+    let last_time = trend.time_series[trend.time_series.len() - 1].0;
+    let span = (last_time - first_time).num_seconds().max(1) as f64;
+    let plot_width = CHART_WIDTH - 2.0 * CHART_PADDING;
+    let plot_height = CHART_HEIGHT - 2.0 * CHART_PADDING;
+
+    let x = |time: DateTime<Utc>| CHART_PADDING + (time - first_time).num_seconds() as f64 / span * plot_width;
+    let y = |value: f64| CHART_HEIGHT - CHART_PADDING - (value - min) / (max - min) * plot_height;
+
+    let points: Vec<String> = trend
+        .time_series
+        .iter()
+        .map(|(time, value)| format!("{:.1},{:.1}", x(*time), y(*value)))
+        .collect();
+
+    let title = format!("{} / {}", trend.component, trend.metric_name);
+    let mut svg = String::new();
+    svg.push_str(&format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\" role=\"img\" aria-label=\"{t}\">\n",
+        w = CHART_WIDTH,
+        h = CHART_HEIGHT,
+        t = escape_html(&title)
+    ));
+    svg.push_str(&format!(
+        "<rect width=\"100%\" height=\"100%\" fill=\"{}\"/>\n",
+        escape_html(&palette.background)
+    ));
+    svg.push_str(&format!(
+        "<text x=\"{}\" y=\"20\" font-family=\"Arial\" font-size=\"14\" fill=\"{}\">{}</text>\n",
+        CHART_PADDING,
+        escape_html(&palette.text),
+        escape_html(&title)
+    ));
+    // Axes
+    svg.push_str(&format!(
+        "<path d=\"M{p},{top} V{bottom} H{right}\" stroke=\"#9ca3af\" fill=\"none\"/>\n",
+        p = CHART_PADDING,
+        top = CHART_PADDING,
+        bottom = CHART_HEIGHT - CHART_PADDING,
+        right = CHART_WIDTH - CHART_PADDING
+    ));
+    for (value, anchor_y) in [(max, y(max)), (min, y(min))] {
+        svg.push_str(&format!(
+            "<text x=\"{:.1}\" y=\"{:.1}\" font-family=\"Arial\" font-size=\"10\" text-anchor=\"end\" fill=\"{}\">{:.2}</text>\n",
+            CHART_PADDING - 4.0,
+            anchor_y + 3.0,
+            escape_html(&palette.text),
+            value
+        ));
+    }
+    for (time, anchor) in [(first_time, "start"), (last_time, "end")] {
+        svg.push_str(&format!(
+            "<text x=\"{:.1}\" y=\"{:.1}\" font-family=\"Arial\" font-size=\"10\" text-anchor=\"{}\" fill=\"{}\">{}</text>\n",
+            x(time),
+            CHART_HEIGHT - CHART_PADDING + 14.0,
+            anchor,
+            escape_html(&palette.text),
+            time.format("%Y-%m-%d")
+        ));
+    }
+    if let Some(baseline) = baseline {
+        svg.push_str(&format!(
+            "<line x1=\"{l}\" x2=\"{r}\" y1=\"{y:.1}\" y2=\"{y:.1}\" stroke=\"{c}\" stroke-dasharray=\"6 4\"><title>baseline {b:.2}</title></line>\n",
+            l = CHART_PADDING,
+            r = CHART_WIDTH - CHART_PADDING,
+            y = y(baseline),
+            c = escape_html(&palette.accent),
+            b = baseline
+        ));
+    }
+    svg.push_str(&format!(
+        "<polyline points=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"2\"/>\n",
+        points.join(" "),
+        escape_html(&palette.primary)
+    ));
+    svg.push_str("</svg>\n");
+
+    let name = crate::utils::sanitize_filename(&format!("{}_{}", trend.component, trend.metric_name)).replace(' ', "_");
+    Some(RenderedChart { name, title, svg })
+}
+
+/// Baseline for a trend taken from the suite's regressions, if one matches
+fn baseline_for(trend: &TrendData, regressions: &[DetectedRegression]) -> Option<f64> {
+    regressions
+        .iter()
+        .find(|r| r.component == trend.component && r.test_name.contains(&trend.metric_name))
+        .or_else(|| regressions.iter().find(|r| r.component == trend.component))
+        .map(|r| r.baseline_value)
+}
+
+/// Render every chart for a view
+pub fn render_charts(view: &ReportView<'_>, palette: &RenderPalette) -> Vec<RenderedChart> {
+    view.trends
+        .iter()
+        .filter_map(|trend| render_trend_chart(trend, baseline_for(trend, &view.suite.regressions_detected), palette))
+        .collect()
+}
+
+/// Render the HTML report
+pub fn render_html(
+    view: &ReportView<'_>,
+    charts: &[RenderedChart],
+    template: &str,
+    styles: &str,
+) -> String {
+    let suite = view.suite;
+
+    let summary = format!(
+        "<ul>\n<li>Suite: {}</li>\n<li>Tests: {} run, {} passed, {} failed, {} skipped</li>\n<li>Regressions detected: {}</li>\n<li>Duration: {}s</li>\n</ul>",
+        escape_html(&suite.suite_name),
+        suite.total_tests,
+        suite.passed_tests,
+        suite.failed_tests,
+        suite.skipped_tests,
+        suite.regressions_detected.len(),
+        (suite.end_time - suite.start_time).num_seconds()
+    );
+    let summary = if suite.quarantined_tests.is_empty() {
+        summary
+    } else {
+        let mut html = format!("{}\n<p>Quarantined flaky tests (failures not gated):</p>\n<ul>\n", summary);
+        for status in &suite.quarantined_tests {
+            html.push_str(&format!(
+                "<li>{} ({}): flakiness {:.2}, {} suppressed failures</li>\n",
+                escape_html(&status.test_name),
+                escape_html(&status.component),
+                status.flakiness_score,
+                status.suppressed_failures
+            ));
+        }
+        html.push_str("</ul>");
+        html
+    };
+
+    let charts_html = if charts.is_empty() {
+        "<p>No trend data available.</p>".to_string()
+    } else {
+        charts
+            .iter()
+            .map(|chart| format!("<figure class=\"chart\">\n{}</figure>", chart.svg))
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+
+    let grouped = regressions_by_component(&suite.regressions_detected);
+    let tables = if grouped.is_empty() {
+        "<p>No regressions detected.</p>".to_string()
+    } else {
+        let mut html = String::new();
+        for (component, regressions) in &grouped {
+            html.push_str(&format!("<h3>{}</h3>\n<table class=\"regressions\">\n", escape_html(component)));
+            html.push_str("<tr><th>Severity</th><th>Test</th><th>Type</th><th>Change</th><th>Confidence</th><th>Algorithm</th></tr>\n");
+            for regression in regressions {
+                html.push_str(&format!(
+                    "<tr><td><span class=\"severity\" style=\"background-color:{}\">{:?}</span></td><td>{}</td><td>{:?}</td><td>{:+.1}%</td><td>{:.1}%</td><td>{}</td></tr>\n",
+                    severity_color(&regression.severity),
+                    regression.severity,
+                    escape_html(&regression.test_name),
+                    regression.regression_type,
+                    regression.regression_percentage,
+                    regression.confidence_score,
+                    escape_html(&regression.detection_algorithm)
+                ));
+            }
+            html.push_str("</table>\n");
+        }
+        html
+    };
+
+    let comparisons = if suite.regressions_detected.is_empty() {
+        "<p>All tracked metrics are within their baselines.</p>".to_string()
+    } else {
+        let mut html = String::from("<table class=\"comparison\">\n<tr><th>Component</th><th>Test</th><th>Baseline</th><th>Current</th><th></th></tr>\n");
+        for regression in &suite.regressions_detected {
+            let scale = regression.baseline_value.abs().max(regression.current_value.abs()).max(f64::EPSILON);
+            html.push_str(&format!(
+                "<tr><td>{}</td><td>{}</td><td>{:.3}</td><td>{:.3}</td><td><div class=\"bar baseline\" style=\"width:{:.0}px\"></div><div class=\"bar current\" style=\"width:{:.0}px;background-color:{}\"></div></td></tr>\n",
+                escape_html(&regression.component),
+                escape_html(&regression.test_name),
+                regression.baseline_value,
+                regression.current_value,
+                regression.baseline_value.abs() / scale * 160.0,
+                regression.current_value.abs() / scale * 160.0,
+                severity_color(&regression.severity)
+            ));
+        }
+        html.push_str("</table>\n");
+        html
+    };
+
+    fill_template(
+        template,
+        &[
+            ("title", &escape_html(&view.title)),
+            ("company", &escape_html(&view.company)),
+            ("generated_at", &view.generated_at.format("%Y-%m-%d %H:%M:%S UTC").to_string()),
+            ("styles", styles),
+            ("summary", &summary),
+            ("charts", &charts_html),
+            ("tables", &tables),
+            ("comparisons", &comparisons),
+        ],
+    )
+}
+
+/// Render the Markdown report; charts are linked from `chart_dir` rather than inlined
+pub fn render_markdown(view: &ReportView<'_>, charts: &[RenderedChart], chart_dir: &str, template: &str) -> String {
+    let suite = view.suite;
+
+    let summary = format!(
+        "| Suite | Run | Passed | Failed | Skipped | Regressions |\n|---|---|---|---|---|---|\n| {} | {} | {} | {} | {} | {} |",
+        escape_markdown_cell(&suite.suite_name),
+        suite.total_tests,
+        suite.passed_tests,
+        suite.failed_tests,
+        suite.skipped_tests,
+        suite.regressions_detected.len()
+    );
+    let summary = if suite.quarantined_tests.is_empty() {
+        summary
+    } else {
+        let mut md = format!("{}\n\nQuarantined flaky tests (failures not gated):\n", summary);
+        for status in &suite.quarantined_tests {
+            md.push_str(&format!(
+                "\n- `{}` ({}): flakiness {:.2}, {} suppressed failures",
+                status.test_name, status.component, status.flakiness_score, status.suppressed_failures
+            ));
+        }
+        md
+    };
+
+    let charts_md = if charts.is_empty() {
+        "No trend data available.".to_string()
+    } else {
+        charts
+            .iter()
+            .map(|chart| format!("![{}]({}/{}.svg)", chart.title, chart_dir, chart.name))
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    };
+
+    let grouped = regressions_by_component(&suite.regressions_detected);
+    let tables = if grouped.is_empty() {
+        "No regressions detected.".to_string()
+    } else {
+        let mut md = String::new();
+        for (component, regressions) in &grouped {
+            md.push_str(&format!("### {}\n\n", escape_markdown_cell(component)));
+            md.push_str("| Severity | Test | Type | Change | Confidence | Algorithm |\n|---|---|---|---|---|---|\n");
+            for regression in regressions {
+                md.push_str(&format!(
+                    "| {} | {} | {:?} | {:+.1}% | {:.1}% | {} |\n",
+                    severity_marker(&regression.severity),
+                    escape_markdown_cell(&regression.test_name),
+                    regression.regression_type,
+                    regression.regression_percentage,
+                    regression.confidence_score,
+                    escape_markdown_cell(&regression.detection_algorithm)
+                ));
+            }
+            md.push('\n');
+        }
+        md.trim_end().to_string()
+    };
+
+    let comparisons = if suite.regressions_detected.is_empty() {
+        "All tracked metrics are within their baselines.".to_string()
+    } else {
+        let mut md = String::from("| Component | Test | Baseline | Current | Change |\n|---|---|---|---|---|\n");
+        for regression in &suite.regressions_detected {
+            md.push_str(&format!(
+                "| {} | {} | {:.3} | {:.3} | {:+.1}% |\n",
+                escape_markdown_cell(&regression.component),
+                escape_markdown_cell(&regression.test_name),
+                regression.baseline_value,
+                regression.current_value,
+                regression.regression_percentage
+            ));
+        }
+        md.trim_end().to_string()
+    };
+
+    fill_template(
+        template,
+        &[
+            ("title", &view.title),
+            ("company", &view.company),
+            ("generated_at", &view.generated_at.format("%Y-%m-%d %H:%M:%S UTC").to_string()),
+            ("summary", &summary),
+            ("charts", &charts_md),
+            ("tables", &tables),
+            ("comparisons", &comparisons),
+        ],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{TrendDirection, TrendStatistics};
+
+    fn palette() -> RenderPalette {
+        RenderPalette {
+            primary: "#3b82f6".to_string(),
+            accent: "#10b981".to_string(),
+            text: "#1f2937".to_string(),
+            background: "#ffffff".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_fill_template() {
+        let filled = fill_template("<h1>{{title}}</h1>{{body}}{{title}}", &[("title", "T"), ("body", "B")]);
+        assert_eq!(filled, "<h1>T</h1>BT");
+    }
+
+    #[test]
+    fn test_escape_html() {
+        assert_eq!(escape_html("<a href=\"x\">&'"), "&lt;a href=&quot;x&quot;&gt;&amp;&#39;");
+    }
+
+    #[test]
+    fn test_render_trend_chart() {
+        let start = Utc::now();
+        let trend = TrendData {
+            metric_name: "latency".to_string(),
+            component: "scheduler".to_string(),
+            time_series: (0..5).map(|i| (start + chrono::Duration::hours(i), 100.0 + i as f64)).collect(),
+            statistics: TrendStatistics {
+                mean: 102.0,
+                standard_deviation: 1.4,
+                median: 102.0,
+                percentile_95: 104.0,
+                percentile_99: 104.0,
+                min_value: 100.0,
+                max_value: 104.0,
+            },
+            trend_direction: TrendDirection::Degrading,
+            predictions: Vec::new(),
+        };
+
+        let chart = render_trend_chart(&trend, Some(100.0), &palette()).unwrap();
+        assert_eq!(chart.name, "scheduler_latency");
+        assert!(chart.svg.starts_with("<svg"));
+        assert!(chart.svg.contains("<polyline"));
+        assert!(chart.svg.contains("stroke-dasharray"));
+
+        let short = TrendData { time_series: trend.time_series[..1].to_vec(), ..trend };
+        assert!(render_trend_chart(&short, None, &palette()).is_none());
+    }
+}
@@ -545,6 +545,7 @@ async fn execute_scheduled_test(test_name: &str) -> Result<TestSuiteResult> {
         failed_tests: 2,
         skipped_tests: 0,
         regressions_detected: Vec::new(),
+        quarantined_tests: Vec::new(),
         summary: HashMap::new(),
     })
 }
@@ -574,6 +575,7 @@ async fn execute_test_suite_async(suite_config: &TestSuiteConfig) -> Result<Test
         failed_tests,
         skipped_tests: 0,
         regressions_detected: Vec::new(),
+        quarantined_tests: Vec::new(),
         summary: HashMap::new(),
     })
 }
@@ -13,6 +13,10 @@ use git2::{Repository, Commit, Diff, DiffOptions};
 
 use crate::{CodeChange, TestSuiteConfig, Uuid};
 
+pub mod coverage;
+
+use coverage::CoverageMap;
+
 /// Change-based test selector
 #[derive(Debug, Clone)]
 pub struct ChangeBasedSelector {
@@ -22,6 +26,8 @@ pub struct ChangeBasedSelector {
     historical_data: HistoricalTestData,
     /// Test impact analyzer
     impact_analyzer: ImpactAnalyzer,
+    /// Which tests execute which files and functions
+    coverage: CoverageMap,
 }
 
 /// Configuration for change-based testing
@@ -33,6 +39,12 @@ pub struct ChangeBasedTestingConfig {
     pub test_selection_algorithm: String, // risk_based, coverage_based, history_based
     pub risk_threshold: f64,
     pub confidence_threshold: f64,
+    /// Coverage map (LCOV tracefile or JSON) used by `coverage_based` selection
+    #[serde(default)]
+    pub coverage_map_path: Option<String>,
+    /// Upper bound on the summed expected execution time of selected tests
+    #[serde(default)]
+    pub max_selection_budget_ms: Option<u64>,
 }
 
 /// Historical test effectiveness data
@@ -243,9 +255,21 @@ impl ChangeBasedSelector {
                 repository: None,
                 component_mapper: ComponentMapper::new(),
             },
+            coverage: CoverageMap::new(),
         }
     }
 
+    /// Replace the coverage map used by `coverage_based` selection
+    pub fn load_coverage(&mut self, coverage: CoverageMap) {
+        info!("Loaded coverage for {} tests", coverage.test_count());
+        self.coverage = coverage;
+    }
+
+    /// Current coverage map
+    pub fn coverage(&self) -> &CoverageMap {
+        &self.coverage
+    }
+
     /// Select tests based on code changes
     pub async fn select_tests_for_changes(
         &self,
@@ -258,6 +282,13 @@ impl ChangeBasedSelector {
             return Ok(Vec::new());
         }
         
+        // Coverage data, when available, pins selection to tests that execute the changed code
+        if self.config.test_selection_algorithm == "coverage_based" && !self.coverage.is_empty() {
+            let selected_tests = self.apply_budget(self.select_tests_from_coverage(code_changes));
+            info!("Selected {} tests from coverage data", selected_tests.len());
+            return Ok(selected_tests);
+        }
+        
         // Analyze impact of code changes
         let impact_analysis = self.analyze_code_change_impact(code_changes).await?;
         
@@ -276,8 +307,8 @@ impl ChangeBasedSelector {
             }
         };
         
-        // Apply max tests per change limit
-        let limited_tests = self.apply_test_limits(selected_tests);
+        // Apply max tests per change limit and execution budget
+        let limited_tests = self.apply_budget(self.apply_test_limits(selected_tests));
         
         info!("Selected {} tests for execution", limited_tests.len());
         Ok(limited_tests)
@@ -442,6 +473,98 @@ impl ChangeBasedSelector {
         Ok(selected_tests)
     }
 
+    /// Select tests whose recorded coverage includes the changed files or functions.
+    ///
+    /// Risk combines how much of the change a test executes with how often it
+    /// has caught failures before.
+    fn select_tests_from_coverage(&self, code_changes: &[CodeChange]) -> Vec<SelectedTest> {
+        let mut files: Vec<String> = Vec::new();
+        let mut changed_functions: HashMap<String, Vec<String>> = HashMap::new();
+        for change in code_changes {
+            files.extend(change.files_changed.iter().cloned());
+            for (file, functions) in &change.changed_functions {
+                changed_functions.entry(file.clone()).or_default().extend(functions.iter().cloned());
+            }
+        }
+        files.sort();
+        files.dedup();
+        
+        let hits = self.coverage.tests_for_changes(&files, &changed_functions);
+        let max_covered = hits.iter().map(|hit| hit.covered_changes).max().unwrap_or(1).max(1);
+        
+        let mut tests: Vec<SelectedTest> = hits
+            .into_iter()
+            .map(|hit| {
+                let history = self.find_test_effectiveness(&hit.test_name, &hit.component);
+                let coverage_score = hit.covered_changes as f64 / max_covered as f64;
+                let failure_rate = history.map_or(0.0, |eff| eff.failure_detection_rate);
+                let risk_score = (0.6 * coverage_score + 0.4 * failure_rate).min(1.0);
+                
+                let priority = match risk_score {
+                    r if r >= 0.7 => TestPriority::Critical,
+                    r if r >= 0.5 => TestPriority::High,
+                    r if r >= 0.3 => TestPriority::Medium,
+                    _ => TestPriority::Low,
+                };
+                
+                SelectedTest {
+                    selection_reason: format!(
+                        "Executes {} changed code location(s); historical failure rate {:.0}%",
+                        hit.covered_changes,
+                        failure_rate * 100.0
+                    ),
+                    test_name: hit.test_name,
+                    component: hit.component,
+                    test_type: SelectedTestType::Regression,
+                    priority,
+                    expected_execution_time_ms: history.map_or(200, |eff| eff.avg_execution_time_ms),
+                    risk_score,
+                }
+            })
+            .collect();
+        
+        tests.sort_by(|a, b| b.risk_score.total_cmp(&a.risk_score));
+        tests.truncate(self.config.max_tests_per_change);
+        tests
+    }
+
+    /// Historical effectiveness of a test, looked up under its component first
+    fn find_test_effectiveness(&self, test_name: &str, component: &str) -> Option<&TestEffectiveness> {
+        self.historical_data.test_effectiveness
+            .get(component)
+            .and_then(|tests| tests.get(test_name))
+            .or_else(|| {
+                self.historical_data.test_effectiveness
+                    .values()
+                    .find_map(|tests| tests.get(test_name))
+            })
+    }
+
+    /// Keep the highest-risk tests that fit within the execution budget
+    fn apply_budget(&self, tests: Vec<SelectedTest>) -> Vec<SelectedTest> {
+        let budget = match self.config.max_selection_budget_ms {
+            Some(budget) => budget,
+            None => return tests,
+        };
+        
+        let mut ranked = tests;
+        ranked.sort_by(|a, b| b.risk_score.total_cmp(&a.risk_score));
+        
+        let mut spent = 0u64;
+        let mut selected = Vec::new();
+        for test in ranked {
+            if spent + test.expected_execution_time_ms <= budget {
+                spent += test.expected_execution_time_ms;
+                selected.push(test);
+            } else {
+                debug!("Skipping {} ({} ms) to stay within the {} ms budget", 
+                       test.test_name, test.expected_execution_time_ms, budget);
+            }
+        }
+        
+        selected
+    }
+
     /// Select tests for a specific component and priority
     async fn select_tests_for_component(&self, component: &str, priority: TestPriority) -> Result<Vec<SelectedTest>> {
         let mut tests = Vec::new();
@@ -0,0 +1,240 @@
+//! Coverage Maps for Test Selection
+//!
+//! Records which tests execute which files and functions so change-based
+//! selection can pick the tests that actually run the modified code. Maps are
+//! ingested either from per-test LCOV tracefiles (one `TN:` section per test)
+//! or from a JSON document of the form
+//! `{"tests": {"<test>": {"component": "...", "files": {"<path>": ["<fn>", ...]}}}}`.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+
+/// Coverage of a single test, as stored in the JSON format
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TestCoverage {
+    #[serde(default)]
+    pub component: String,
+    /// Covered functions by file; an empty list means only file-level data is known
+    #[serde(default)]
+    pub files: BTreeMap<String, Vec<String>>,
+}
+
+/// JSON coverage document
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CoverageReport {
+    pub tests: BTreeMap<String, TestCoverage>,
+}
+
+/// Tests that cover a piece of code
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoverageHit {
+    pub test_name: String,
+    pub component: String,
+    /// Changed functions (or files, without function data) the test executes
+    pub covered_changes: usize,
+}
+
+/// Index from files and functions to the tests that execute them
+#[derive(Debug, Clone, Default)]
+pub struct CoverageMap {
+    file_tests: HashMap<String, HashSet<String>>,
+    function_tests: HashMap<(String, String), HashSet<String>>,
+    test_components: HashMap<String, String>,
+}
+
+impl CoverageMap {
+    /// Create an empty coverage map
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load a coverage map from disk; `.info`/`.lcov` files are parsed as LCOV, anything else as JSON
+    pub async fn load(path: &Path, default_component: &str) -> Result<Self> {
+        let content = tokio::fs::read_to_string(path)
+            .await
+            .with_context(|| format!("Failed to read coverage map {}", path.display()))?;
+
+        let mut map = Self::new();
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("info") | Some("lcov") => map.ingest_lcov(&content, default_component),
+            _ => map.ingest_json(&content)?,
+        }
+        Ok(map)
+    }
+
+    /// Whether any coverage has been ingested
+    pub fn is_empty(&self) -> bool {
+        self.file_tests.is_empty()
+    }
+
+    /// Number of tests with coverage data
+    pub fn test_count(&self) -> usize {
+        self.test_components.len()
+    }
+
+    /// Record that `test_name` executes `file`, and optionally `function` within it
+    pub fn record(&mut self, test_name: &str, component: &str, file: &str, function: Option<&str>) {
+        let file = normalize_path(file);
+        self.file_tests.entry(file.clone()).or_default().insert(test_name.to_string());
+        if let Some(function) = function {
+            self.function_tests
+                .entry((file, function.to_string()))
+                .or_default()
+                .insert(test_name.to_string());
+        }
+        self.test_components
+            .entry(test_name.to_string())
+            .or_insert_with(|| component.to_string());
+    }
+
+    /// Ingest a JSON coverage document
+    pub fn ingest_json(&mut self, json: &str) -> Result<()> {
+        let report: CoverageReport = serde_json::from_str(json).context("Invalid coverage map")?;
+        for (test_name, coverage) in &report.tests {
+            for (file, functions) in &coverage.files {
+                self.record(test_name, &coverage.component, file, None);
+                for function in functions {
+                    self.record(test_name, &coverage.component, file, Some(function));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Ingest LCOV tracefile data; each `TN:` section names the test it belongs to.
+    ///
+    /// Only lines and functions with a non-zero hit count are recorded.
+    pub fn ingest_lcov(&mut self, lcov: &str, component: &str) {
+        let mut test_name = String::new();
+        let mut file: Option<String> = None;
+        let mut file_hit = false;
+
+        for line in lcov.lines().map(str::trim) {
+            if let Some(name) = line.strip_prefix("TN:") {
+                test_name = name.to_string();
+            } else if let Some(path) = line.strip_prefix("SF:") {
+                file = Some(path.to_string());
+                file_hit = false;
+            } else if let Some(data) = line.strip_prefix("FNDA:") {
+                // FNDA:<hits>,<function>
+                if let (Some(file), Some((hits, function))) = (&file, data.split_once(',')) {
+                    if hits.parse::<u64>().unwrap_or(0) > 0 && !test_name.is_empty() {
+                        self.record(&test_name, component, file, Some(function));
+                        file_hit = true;
+                    }
+                }
+            } else if let Some(data) = line.strip_prefix("DA:") {
+                // DA:<line>,<hits>[,<checksum>]
+                let hits = data.split(',').nth(1).and_then(|hits| hits.parse::<u64>().ok()).unwrap_or(0);
+                file_hit |= hits > 0;
+            } else if line == "end_of_record" {
+                if let Some(file) = file.take() {
+                    if file_hit && !test_name.is_empty() {
+                        self.record(&test_name, component, &file, None);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Tests executing the changed code.
+    ///
+    /// `changed_functions` narrows a file to specific functions; files without
+    /// function information, or whose functions were never seen in coverage,
+    /// match every test that executes the file.
+    pub fn tests_for_changes(&self, files: &[String], changed_functions: &HashMap<String, Vec<String>>) -> Vec<CoverageHit> {
+        let mut hits: HashMap<&str, usize> = HashMap::new();
+
+        for file in files {
+            let normalized = normalize_path(file);
+            let functions = changed_functions.get(file).filter(|functions| !functions.is_empty());
+
+            let function_hits: Vec<&HashSet<String>> = functions
+                .into_iter()
+                .flatten()
+                .filter_map(|function| self.function_tests.get(&(normalized.clone(), function.clone())))
+                .collect();
+
+            if function_hits.is_empty() {
+                for test in self.file_tests.get(&normalized).into_iter().flatten() {
+                    *hits.entry(test.as_str()).or_insert(0) += 1;
+                }
+            } else {
+                for test in function_hits.into_iter().flatten() {
+                    *hits.entry(test.as_str()).or_insert(0) += 1;
+                }
+            }
+        }
+
+        let mut result: Vec<CoverageHit> = hits
+            .into_iter()
+            .map(|(test_name, covered_changes)| CoverageHit {
+                test_name: test_name.to_string(),
+                component: self.test_components.get(test_name).cloned().unwrap_or_default(),
+                covered_changes,
+            })
+            .collect();
+        result.sort_by(|a, b| b.covered_changes.cmp(&a.covered_changes).then_with(|| a.test_name.cmp(&b.test_name)));
+        result
+    }
+}
+
+/// Strip leading `./` so paths from git and coverage tools compare equal
+fn normalize_path(path: &str) -> String {
+    path.trim_start_matches("./").replace('\\', "/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LCOV: &str = "TN:sched_fairness\n\
+        SF:kernel/src/scheduler/mod.rs\n\
+        FN:10,pick_next\n\
+        FNDA:42,pick_next\n\
+        FNDA:0,rebalance\n\
+        DA:11,42\n\
+        end_of_record\n\
+        TN:vm_boot\n\
+        SF:./kernel/src/scheduler/mod.rs\n\
+        FNDA:3,rebalance\n\
+        DA:40,3\n\
+        end_of_record\n\
+        SF:hypervisor/src/vmx.rs\n\
+        DA:5,0\n\
+        end_of_record\n";
+
+    #[test]
+    fn test_lcov_function_level_selection() {
+        let mut map = CoverageMap::new();
+        map.ingest_lcov(LCOV, "kernel");
+        assert_eq!(map.test_count(), 2);
+
+        let files = vec!["kernel/src/scheduler/mod.rs".to_string()];
+        let mut functions = HashMap::new();
+        functions.insert(files[0].clone(), vec!["rebalance".to_string()]);
+
+        let hits = map.tests_for_changes(&files, &functions);
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].test_name, "vm_boot");
+
+        // Without function data every test touching the file is selected
+        assert_eq!(map.tests_for_changes(&files, &HashMap::new()).len(), 2);
+
+        // Zero-hit files are not recorded
+        assert!(map.tests_for_changes(&["hypervisor/src/vmx.rs".to_string()], &HashMap::new()).is_empty());
+    }
+
+    #[test]
+    fn test_json_ingestion() {
+        let mut map = CoverageMap::new();
+        map.ingest_json(r#"{"tests": {"net_loopback": {"component": "network", "files": {"net/src/tcp.rs": ["send"]}}}}"#)
+            .unwrap();
+
+        let hits = map.tests_for_changes(&["net/src/tcp.rs".to_string()], &HashMap::new());
+        assert_eq!(hits[0].component, "network");
+        assert_eq!(hits[0].covered_changes, 1);
+    }
+}
@@ -280,10 +280,14 @@ impl BaselineStore {
         let cache_key = self.generate_cache_key(component, metric_type);
         
         // Check cache first
-        if let Some(entry) = self.cache.entries.get(&cache_key) {
-            self.update_access_stats(&cache_key);
+        let prefix = format!("{}:", cache_key);
+        let cached = self.cache.entries.iter()
+            .find(|(key, _)| key.starts_with(&prefix))
+            .map(|(key, entry)| (key.clone(), entry.baseline.clone()));
+        if let Some((key, baseline)) = cached {
+            self.update_access_stats(&key);
             debug!("Baseline cache hit for {}/{}", component, metric_type);
-            return Ok(Some(entry.baseline.clone()));
+            return Ok(Some(baseline));
         }
         
         debug!("Baseline cache miss for {}/{}, loading from database", component, metric_type);
@@ -307,13 +311,29 @@ impl BaselineStore {
         self.cache.entries.values().map(|entry| entry.baseline.clone()).collect()
     }
 
+    /// Get the cached baseline for one environment
+    pub fn get_environment_baseline(
+        &self,
+        component: &str,
+        metric_type: &str,
+        environment_hash: &str,
+    ) -> Option<PerformanceBaseline> {
+        let cache_key = format!("{}:{}", self.generate_cache_key(component, metric_type), environment_hash);
+        self.cache.entries.get(&cache_key).map(|entry| entry.baseline.clone())
+    }
+
     /// Cache baseline entry
     fn cache_baseline(&mut self, baseline: PerformanceBaseline) -> Result<()> {
         if !self.config.enable_caching {
             return Ok(());
         }
         
-        let cache_key = self.generate_cache_key(&baseline.component, &baseline.metric_type);
+        // Baselines are cached per environment so they don't overwrite each other
+        let cache_key = format!(
+            "{}:{}",
+            self.generate_cache_key(&baseline.component, &baseline.metric_type),
+            baseline.test_environment_hash
+        );
         
         // Check cache size limit
         if self.cache.entries.len() >= self.config.cache_size_limit {
@@ -376,8 +396,10 @@ impl BaselineStore {
     /// Invalidate cache entry
     pub fn invalidate_cache(&mut self, component: &str, metric_type: &str) {
         let cache_key = self.generate_cache_key(component, metric_type);
-        self.cache.entries.remove(&cache_key);
-        self.cache.lru_order.retain(|key| key != &cache_key);
+        let prefix = format!("{}:", cache_key);
+        self.cache.entries.retain(|key, _| !key.starts_with(&prefix));
+        self.cache.lru_order.retain(|key| !key.starts_with(&prefix));
+        self.cache.current_size = self.cache.entries.len();
         debug!("Invalidated baseline cache for {}", cache_key);
     }
 